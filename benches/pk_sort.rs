@@ -0,0 +1,69 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Primary key sort of a 500k-item `current_table_items` batch, comparing the `(table_handle,
+//! key_hash)` strings as the processors used to, against `sort_by_pk` over precomputed keys.
+//! Handles repeat across items like in a table-heavy batch. Reports the p50 and p99 latency:
+//! `cargo bench -p aptos-indexer --bench pk_sort`
+
+use aptos_indexer::{
+    models::move_tables::CurrentTableItem,
+    util::sort_key::{sort_by_pk, SortKey},
+};
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+const ITEMS: usize = 500_000;
+const HANDLES: usize = 200;
+const RUNS: usize = 20;
+
+fn fixture() -> Vec<CurrentTableItem> {
+    (0..ITEMS)
+        .map(|i| {
+            // Spread the items over the handles and key hashes in no particular order
+            let mixed = (i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+            CurrentTableItem {
+                table_handle: format!("0x{:064x}", mixed % HANDLES as u64),
+                key_hash: format!("{:016x}{:048x}", mixed, i),
+                key: format!("0x{:x}", i),
+                decoded_key: serde_json::Value::Null,
+                decoded_value: None,
+                last_transaction_version: i as i64,
+                is_deleted: false,
+            }
+        })
+        .collect()
+}
+
+/// The p50 and p99 latency of sorting a fresh copy of `items`
+fn measure(
+    items: &[CurrentTableItem],
+    sort: impl Fn(&mut Vec<CurrentTableItem>),
+) -> (Duration, Duration) {
+    let mut latencies = (0..RUNS)
+        .map(|_| {
+            let mut batch = items.to_vec();
+            let started = Instant::now();
+            sort(&mut batch);
+            let elapsed = started.elapsed();
+            black_box(batch);
+            elapsed
+        })
+        .collect::<Vec<_>>();
+    latencies.sort();
+    (latencies[RUNS / 2], latencies[RUNS * 99 / 100])
+}
+
+fn main() {
+    let items = fixture();
+
+    let by_string = measure(&items, |batch| batch.sort_by(CurrentTableItem::cmp_pk));
+    let by_key = measure(&items, sort_by_pk);
+
+    println!("{} items per batch, {} runs", ITEMS, RUNS);
+    for (name, (p50, p99)) in [("strings", by_string), ("sort keys", by_key)] {
+        println!("{:<10} p50: {:>9.3?}  p99: {:>9.3?}", name, p50, p99);
+    }
+}
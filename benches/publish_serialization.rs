@@ -0,0 +1,108 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Serialization of a 10k-row batch for publishing, per message with `serde_json::to_string` as
+//! the publisher used to, against `SerializationPool`. Reports allocations and p50/p99 latency
+//! per batch. Nothing is produced, so no Kafka is needed:
+//! `cargo bench -p aptos-indexer --bench publish_serialization`
+
+use aptos_indexer::{
+    custom::driver::{config::SerializationConfig, serialization::SerializationPool},
+    models::events::EventModel,
+};
+use serde_json::json;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+const ROWS: usize = 10_000;
+const BATCHES: usize = 200;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn fixture() -> Vec<EventModel> {
+    (0..ROWS)
+        .map(|i| EventModel {
+            sequence_number: i as i64,
+            creation_number: 2,
+            account_address: format!("0x{:064x}", i % 97),
+            transaction_version: 1_000_000 + i as i64 / 4,
+            transaction_block_height: 50_000 + i as i64 / 40,
+            type_: "0x1::coin::DepositEvent".to_string(),
+            data: json!({ "amount": (i * 7919).to_string(), "memo": "x".repeat(i % 200) }),
+            event_index: Some((i % 4) as i64),
+        })
+        .collect()
+}
+
+/// Allocations per batch and the p50 and p99 batch latency
+fn measure(mut publish_batch: impl FnMut()) -> (u64, Duration, Duration) {
+    // Warm up, e.g. fill the buffer pool
+    for _ in 0..10 {
+        publish_batch();
+    }
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let mut latencies = (0..BATCHES)
+        .map(|_| {
+            let started = Instant::now();
+            publish_batch();
+            started.elapsed()
+        })
+        .collect::<Vec<_>>();
+    let allocations = (ALLOCATIONS.load(Ordering::Relaxed) - allocations_before) / BATCHES as u64;
+    latencies.sort();
+    (
+        allocations,
+        latencies[BATCHES / 2],
+        latencies[BATCHES * 99 / 100],
+    )
+}
+
+fn main() {
+    let rows = fixture();
+
+    let per_message = measure(|| {
+        for row in &rows {
+            black_box(serde_json::to_string(row).unwrap().into_bytes());
+        }
+    });
+
+    let pool = SerializationPool::new(&SerializationConfig::default());
+    let pooled = measure(|| {
+        pool.serialize_each("EventModel", &rows, |_, payload| {
+            black_box(payload.unwrap());
+        });
+    });
+
+    println!("{} rows per batch, {} batches", ROWS, BATCHES);
+    for (name, (allocations, p50, p99)) in [("per message", per_message), ("pooled", pooled)] {
+        println!(
+            "{:<12} allocations/batch: {:>7}  p50: {:>9.3?}  p99: {:>9.3?}",
+            name, allocations, p50, p99
+        );
+    }
+}
@@ -0,0 +1,22 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Generates the protobuf messages of `custom::driver::publisher::proto` from `proto/`. Only the
+//! `indexer` feature publishes, so builds without it skip this. `protoc` is the one in `PROTOC`
+//! if set, otherwise the vendored one of `protoc-bin-vendored`, so no system `protoc` is needed.
+
+use std::io;
+
+fn main() -> io::Result<()> {
+    println!("cargo:rerun-if-changed=proto/published.proto");
+    println!("cargo:rerun-if-env-changed=PROTOC");
+    if std::env::var_os("CARGO_FEATURE_INDEXER").is_none() {
+        return Ok(());
+    }
+    if std::env::var_os("PROTOC").is_none() {
+        let protoc = protoc_bin_vendored::protoc_bin_path()
+            .map_err(|e| io::Error::new(io::ErrorKind::NotFound, e.to_string()))?;
+        std::env::set_var("PROTOC", protoc);
+    }
+    prost_build::compile_protos(&["proto/published.proto"], &["proto/"])
+}
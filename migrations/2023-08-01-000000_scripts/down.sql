@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS ut_script_hash_index;
+ALTER TABLE user_transactions DROP COLUMN IF EXISTS script_hash;
+DROP TABLE IF EXISTS scripts;
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+-- Script payloads carry their bytecode in every transaction, so store each distinct
+-- script once, keyed by the sha3-256 of its bytecode
+CREATE TABLE IF NOT EXISTS scripts (
+  script_hash VARCHAR(66) PRIMARY KEY NOT NULL,
+  bytecode BYTEA NOT NULL,
+  abi JSONB,
+  first_seen_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+ALTER TABLE user_transactions
+ADD COLUMN IF NOT EXISTS script_hash VARCHAR(66);
+CREATE INDEX IF NOT EXISTS ut_script_hash_index ON user_transactions (script_hash);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS ds_pool_index;
+DROP INDEX IF EXISTS ds_sender_index;
+DROP INDEX IF EXISTS ds_insat_index;
+DROP TABLE IF EXISTS dex_swaps;
+DROP TABLE IF EXISTS dex_pools;
@@ -0,0 +1,30 @@
+-- Your SQL goes here
+-- Swaps of all configured DEX protocols normalized into one shape
+CREATE TABLE IF NOT EXISTS dex_swaps (
+  transaction_version BIGINT NOT NULL,
+  event_index BIGINT NOT NULL,
+  protocol VARCHAR(100) NOT NULL,
+  -- Pool resource type, e.g. <address>::liquidity_pool::LiquidityPool<X, Y, Curve>
+  pool TEXT NOT NULL,
+  coin_in TEXT NOT NULL,
+  coin_out TEXT NOT NULL,
+  amount_in NUMERIC NOT NULL,
+  amount_out NUMERIC NOT NULL,
+  sender VARCHAR(66) NOT NULL,
+  transaction_timestamp TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (transaction_version, event_index)
+);
+CREATE INDEX IF NOT EXISTS ds_pool_index ON dex_swaps (pool);
+CREATE INDEX IF NOT EXISTS ds_sender_index ON dex_swaps (sender);
+CREATE INDEX IF NOT EXISTS ds_insat_index ON dex_swaps (inserted_at);
+-- Pools referenced by dex_swaps, registered the first time one of their swaps is seen
+CREATE TABLE IF NOT EXISTS dex_pools (
+  pool TEXT PRIMARY KEY NOT NULL,
+  protocol VARCHAR(100) NOT NULL,
+  pool_address VARCHAR(66) NOT NULL,
+  coin_x TEXT NOT NULL,
+  coin_y TEXT NOT NULL,
+  first_seen_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
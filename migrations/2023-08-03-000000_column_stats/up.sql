@@ -0,0 +1,17 @@
+-- Your SQL goes here
+-- Per column statistics of sampled inserted rows, one row per column and flush window
+CREATE TABLE IF NOT EXISTS indexer_column_stats (
+  table_name VARCHAR(100) NOT NULL,
+  column_name VARCHAR(100) NOT NULL,
+  window_end TIMESTAMP NOT NULL,
+  window_start TIMESTAMP NOT NULL,
+  rows_seen BIGINT NOT NULL,
+  rows_sampled BIGINT NOT NULL,
+  null_ratio DOUBLE PRECISION NOT NULL,
+  -- HyperLogLog estimate over the sampled rows
+  distinct_estimate BIGINT NOT NULL,
+  -- [{"value": ..., "share": ...}] of the most frequent sampled values
+  top_values JSONB NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (table_name, column_name, window_end)
+);
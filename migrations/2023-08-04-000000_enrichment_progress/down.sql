@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS ctd_undecoded_properties_index;
+DROP TABLE IF EXISTS enrichment_progress;
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- Where each enricher (see custom::enrichment) is in its walk over its table, so a backfill
+-- resumes after a restart instead of starting over
+CREATE TABLE IF NOT EXISTS enrichment_progress (
+  enricher_name VARCHAR(50) PRIMARY KEY NOT NULL,
+  table_name VARCHAR(100) NOT NULL,
+  -- primary key of the last row looked at, NULL before the first batch
+  last_cursor JSONB,
+  rows_enriched BIGINT NOT NULL DEFAULT 0,
+  completed_at TIMESTAMP,
+  last_updated TIMESTAMP NOT NULL DEFAULT NOW()
+);
+-- Rows whose property map was stored before it could be decoded still have the raw
+-- {"map": {"data": [...]}} shape
+CREATE INDEX IF NOT EXISTS ctd_undecoded_properties_index ON current_token_datas (token_data_id_hash)
+WHERE default_properties ? 'map';
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS occ_config_type_version_index;
+DROP TABLE IF EXISTS onchain_config_changes;
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+-- Writes of the 0x1 config resources (gas schedule, feature flags, consensus and execution
+-- config) that changed their value, see custom_onchain_config_processor
+CREATE TABLE IF NOT EXISTS onchain_config_changes (
+  transaction_version BIGINT NOT NULL,
+  -- gas_schedule, features, consensus_config or execution_config
+  config_type VARCHAR(50) NOT NULL,
+  resource_type VARCHAR(512) NOT NULL,
+  -- decoded value after the write
+  value JSONB NOT NULL,
+  -- {"added": {...}, "removed": {...}, "changed": {path: {"old": .., "new": ..}}} against the
+  -- previous value, everything is added for the first indexed write
+  diff JSONB NOT NULL,
+  transaction_timestamp TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (transaction_version, config_type)
+);
+CREATE INDEX IF NOT EXISTS occ_config_type_version_index ON onchain_config_changes (config_type, transaction_version DESC);
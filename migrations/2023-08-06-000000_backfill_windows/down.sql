@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS bw_processor_expires_at_index;
+DROP TABLE IF EXISTS backfill_windows;
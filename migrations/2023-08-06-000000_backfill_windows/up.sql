@@ -0,0 +1,14 @@
+-- Your SQL goes here
+-- Version ranges a processor may overwrite existing rows in, see custom::driver::backfill_guard.
+-- Outside of an unexpired window conflicting rows are skipped instead of updated.
+CREATE TABLE IF NOT EXISTS backfill_windows (
+  id BIGSERIAL PRIMARY KEY,
+  processor VARCHAR(50) NOT NULL,
+  start_version BIGINT NOT NULL,
+  -- inclusive
+  end_version BIGINT NOT NULL,
+  created_by VARCHAR(100) NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  expires_at TIMESTAMP NOT NULL
+);
+CREATE INDEX IF NOT EXISTS bw_processor_expires_at_index ON backfill_windows (processor, expires_at);
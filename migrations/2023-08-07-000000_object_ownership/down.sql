@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS co_ultimate_owner_index;
+ALTER TABLE current_objects
+DROP COLUMN IF EXISTS ultimate_owner,
+DROP COLUMN IF EXISTS ownership_depth;
+DROP INDEX IF EXISTS ooe_owner_address_index;
+DROP TABLE IF EXISTS object_ownership_edges;
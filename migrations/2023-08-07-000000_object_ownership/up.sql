@@ -0,0 +1,17 @@
+-- Your SQL goes here
+-- Latest owner of every object, the edges walked to resolve the ultimate owner of nested objects
+CREATE TABLE IF NOT EXISTS object_ownership_edges (
+  object_address VARCHAR(66) PRIMARY KEY NOT NULL,
+  owner_address VARCHAR(66) NOT NULL,
+  last_transaction_version BIGINT NOT NULL,
+  is_deleted BOOLEAN NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+-- To find the objects owned by an object
+CREATE INDEX IF NOT EXISTS ooe_owner_address_index ON object_ownership_edges (owner_address);
+-- First owner up the chain that isn't an object, and the number of edges to it. NULL for
+-- deleted objects and chains with a cycle or longer than the max depth.
+ALTER TABLE current_objects
+ADD COLUMN IF NOT EXISTS ultimate_owner VARCHAR(66),
+ADD COLUMN IF NOT EXISTS ownership_depth BIGINT;
+CREATE INDEX IF NOT EXISTS co_ultimate_owner_index ON current_objects (ultimate_owner);
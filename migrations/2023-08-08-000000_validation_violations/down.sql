@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS vv_insat_index;
+DROP INDEX IF EXISTS vv_transaction_version_index;
+DROP TABLE IF EXISTS validation_violations;
@@ -0,0 +1,25 @@
+-- Your SQL goes here
+-- Rows a processor was about to commit that broke one of its validation rules, see
+-- custom::driver::validation. Keyed by batch so a retried batch doesn't record its violations twice.
+CREATE TABLE IF NOT EXISTS validation_violations (
+  processor VARCHAR(50) NOT NULL,
+  rule_name VARCHAR(100) NOT NULL,
+  start_version BIGINT NOT NULL,
+  end_version BIGINT NOT NULL,
+  violation_index BIGINT NOT NULL,
+  -- warn or fail
+  policy VARCHAR(10) NOT NULL,
+  transaction_version BIGINT,
+  message TEXT NOT NULL,
+  details JSONB NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (
+    processor,
+    rule_name,
+    start_version,
+    end_version,
+    violation_index
+  )
+);
+CREATE INDEX IF NOT EXISTS vv_transaction_version_index ON validation_violations (transaction_version);
+CREATE INDEX IF NOT EXISTS vv_insat_index ON validation_violations (inserted_at);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS ledger_resets;
+ALTER TABLE ledger_infos DROP COLUMN IF EXISTS genesis_hash;
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+-- Hash of the transaction at version 0 of the indexed ledger. A node with the same chain id but
+-- a different genesis was reset, see custom::driver::ledger_reset. NULL until first checked.
+ALTER TABLE ledger_infos
+ADD COLUMN IF NOT EXISTS genesis_hash VARCHAR(66);
+-- Audit log of detected ledger resets and what was done about them
+CREATE TABLE IF NOT EXISTS ledger_resets (
+  id BIGSERIAL PRIMARY KEY,
+  chain_id BIGINT NOT NULL,
+  previous_genesis_hash VARCHAR(66) NOT NULL,
+  new_genesis_hash VARCHAR(66) NOT NULL,
+  -- halt or wipe
+  action VARCHAR(10) NOT NULL,
+  -- names of the truncated tables
+  truncated_tables JSONB NOT NULL,
+  processor VARCHAR(50) NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS cf_insat_index;
+DROP TABLE IF EXISTS change_feed;
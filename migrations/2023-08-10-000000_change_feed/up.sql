@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- Append-only log of the rows processors write, for consumers that poll instead of reading
+-- Kafka, see custom::driver::change_feed. Ids are committed in order, so "everything after id X"
+-- never misses a row that becomes visible later.
+CREATE TABLE IF NOT EXISTS change_feed (
+  id BIGSERIAL PRIMARY KEY,
+  -- table the row was written to
+  model VARCHAR(100) NOT NULL,
+  transaction_version BIGINT,
+  -- primary key columns of the row
+  pk JSONB NOT NULL,
+  -- insert, upsert or update
+  operation VARCHAR(10) NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS cf_insat_index ON change_feed (inserted_at);
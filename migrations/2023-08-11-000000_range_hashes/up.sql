@@ -0,0 +1,15 @@
+-- Your SQL goes here
+-- Hashes of the rows of a table in a version range, to compare deployments indexing the same
+-- chain, see custom::driver::range_hash
+CREATE TABLE IF NOT EXISTS range_hashes (
+  processor VARCHAR(50) NOT NULL,
+  table_name VARCHAR(100) NOT NULL,
+  start_version BIGINT NOT NULL,
+  -- inclusive
+  end_version BIGINT NOT NULL,
+  row_count BIGINT NOT NULL,
+  -- xxh3 of the canonicalized rows in primary key order, hex
+  hash VARCHAR(16) NOT NULL,
+  computed_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (processor, table_name, start_version, end_version)
+);
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+-- How the query helpers filter their tables and how Postgres plans them, aggregated per helper
+-- and filter, see queries::index_advisor
+CREATE TABLE IF NOT EXISTS index_advisor_observations (
+  helper VARCHAR(100) NOT NULL,
+  table_name VARCHAR(100) NOT NULL,
+  -- comma separated, in the order the helper filters on them
+  filter_columns VARCHAR(300) NOT NULL,
+  calls BIGINT NOT NULL,
+  total_exec_ms DOUBLE PRECISION NOT NULL,
+  sampled_plans BIGINT NOT NULL,
+  -- sampled plans with a sequential scan of table_name
+  seq_scan_plans BIGINT NOT NULL,
+  -- largest row estimate of a sampled sequential scan
+  max_estimated_rows BIGINT NOT NULL,
+  -- the scans of the last sampled plan
+  last_plan JSONB NOT NULL,
+  last_seen TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (helper, table_name, filter_columns)
+);
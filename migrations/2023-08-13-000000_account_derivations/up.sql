@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- Addresses derived from another address rather than from a key: resource accounts and objects
+CREATE TABLE IF NOT EXISTS account_derivations (
+  derived_address VARCHAR(66) PRIMARY KEY NOT NULL,
+  -- resource_account or object
+  derivation_kind VARCHAR(50) NOT NULL,
+  -- the account or object the address was derived from, when known
+  source_address VARCHAR(66),
+  -- hex, when the transaction that derived the address shows it
+  seed TEXT,
+  -- first version the derivation was seen at
+  transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS ad_source_address_index ON account_derivations (source_address);
+CREATE INDEX IF NOT EXISTS ad_kind_index ON account_derivations (derivation_kind);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE processor_status
+DROP COLUMN IF EXISTS health_state,
+DROP COLUMN IF EXISTS health_state_since;
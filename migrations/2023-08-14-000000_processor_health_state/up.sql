@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- Health state of the processor as of its last transition, see custom::driver::circuit_breaker:
+-- healthy, degraded or critical
+ALTER TABLE processor_status
+ADD COLUMN IF NOT EXISTS health_state VARCHAR(10) NOT NULL DEFAULT 'healthy',
+ADD COLUMN IF NOT EXISTS health_state_since TIMESTAMP NOT NULL DEFAULT NOW();
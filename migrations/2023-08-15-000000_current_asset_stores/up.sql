@@ -0,0 +1,22 @@
+-- Your SQL goes here
+-- Every coin store and fungible store, live or deleted, for listings of the assets an account holds
+CREATE TABLE IF NOT EXISTS current_asset_stores (
+  -- the owner's address for a coin store, the store object's for a fungible store
+  store_address VARCHAR(66) NOT NULL,
+  -- coin type for a coin store, metadata address for a fungible store
+  asset_type VARCHAR(5000) NOT NULL,
+  owner_address VARCHAR(66) NOT NULL,
+  -- coin or fungible_asset
+  store_kind VARCHAR(50) NOT NULL,
+  is_frozen BOOLEAN NOT NULL,
+  -- version the store was last created at
+  created_transaction_version BIGINT NOT NULL,
+  -- NULL while the store exists
+  deleted_transaction_version BIGINT,
+  last_transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (store_address, asset_type)
+);
+CREATE INDEX IF NOT EXISTS cas_owner_address_index ON current_asset_stores (owner_address)
+WHERE deleted_transaction_version IS NULL;
+CREATE INDEX IF NOT EXISTS cas_insat_index ON current_asset_stores (inserted_at);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS ol_kind_emitted_at_index;
+DROP TABLE IF EXISTS operations_log;
@@ -0,0 +1,21 @@
+-- Your SQL goes here
+-- Lifecycle events of long-running driver tasks (backfills, enrichment, pruning), as published on
+-- the control topic, see custom::driver::operations
+CREATE TABLE IF NOT EXISTS operations_log (
+  operation_id VARCHAR(100) NOT NULL,
+  -- 0 for started, then one per event
+  event_index BIGINT NOT NULL,
+  kind VARCHAR(50) NOT NULL,
+  -- started, progress, completed or failed
+  event VARCHAR(20) NOT NULL,
+  actor VARCHAR(200) NOT NULL,
+  parameters JSONB NOT NULL,
+  done BIGINT NOT NULL,
+  total BIGINT,
+  error_code VARCHAR(50),
+  error TEXT,
+  emitted_at TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (operation_id, event_index)
+);
+CREATE INDEX IF NOT EXISTS ol_kind_emitted_at_index ON operations_log (kind, emitted_at DESC);
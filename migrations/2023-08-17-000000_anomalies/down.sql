@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS an_insat_index;
+DROP TABLE IF EXISTS anomalies;
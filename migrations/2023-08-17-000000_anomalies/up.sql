@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- Transactions that look wrong in a way no single row shows, e.g. a sequence number used twice,
+-- see custom::driver::duplicate_transactions. Keyed by version so a retried batch doesn't record
+-- its anomalies twice.
+CREATE TABLE IF NOT EXISTS anomalies (
+  kind VARCHAR(50) NOT NULL,
+  transaction_version BIGINT NOT NULL,
+  processor VARCHAR(50) NOT NULL,
+  -- what was done about it, e.g. keep_both, keep_lower or fail
+  policy VARCHAR(20) NOT NULL,
+  message TEXT NOT NULL,
+  details JSONB NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (kind, transaction_version)
+);
+CREATE INDEX IF NOT EXISTS an_insat_index ON anomalies (inserted_at);
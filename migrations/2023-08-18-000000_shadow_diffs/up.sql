@@ -0,0 +1,26 @@
+-- Your SQL goes here
+-- Rows where a shadow processor's output differs from the primary's, see custom::driver::shadow.
+-- Keyed by batch so a retried batch doesn't record its diffs twice.
+CREATE TABLE IF NOT EXISTS shadow_diffs (
+  processor VARCHAR(50) NOT NULL,
+  shadow VARCHAR(100) NOT NULL,
+  start_version BIGINT NOT NULL,
+  end_version BIGINT NOT NULL,
+  diff_index BIGINT NOT NULL,
+  table_name VARCHAR(100) NOT NULL,
+  -- primary key values of the row, as a JSON array
+  pk TEXT NOT NULL,
+  -- * for a row only one of the two has
+  column_name VARCHAR(100) NOT NULL,
+  primary_value JSONB,
+  shadow_value JSONB,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (
+    processor,
+    shadow,
+    start_version,
+    end_version,
+    diff_index
+  )
+);
+CREATE INDEX IF NOT EXISTS sd_insat_index ON shadow_diffs (inserted_at);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS efds_entry_function_date_index;
+DROP TABLE IF EXISTS entry_function_daily_stats;
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+-- Calls of every entry function per UTC day, see custom::driver::entry_function_stats. Batches
+-- add to the counts; last_transaction_version keeps a re-processed batch from adding twice.
+CREATE TABLE IF NOT EXISTS entry_function_daily_stats (
+  date DATE NOT NULL,
+  entry_function_id_str TEXT NOT NULL,
+  call_count BIGINT NOT NULL,
+  success_count BIGINT NOT NULL,
+  -- HyperLogLog registers of the senders, see util::hyperloglog
+  distinct_senders_sketch BYTEA NOT NULL,
+  distinct_senders BIGINT NOT NULL,
+  total_gas_used NUMERIC NOT NULL,
+  last_transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (date, entry_function_id_str)
+);
+CREATE INDEX IF NOT EXISTS efds_entry_function_date_index ON entry_function_daily_stats (entry_function_id_str, date);
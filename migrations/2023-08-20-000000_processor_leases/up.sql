@@ -0,0 +1,10 @@
+-- Your SQL goes here
+-- Which instance of a processor (or shard) writes and publishes, see custom::driver::standby.
+-- A lease is taken over once expires_at has passed.
+CREATE TABLE IF NOT EXISTS processor_leases (
+  processor VARCHAR(50) NOT NULL PRIMARY KEY,
+  holder VARCHAR(200) NOT NULL,
+  acquired_at TIMESTAMP NOT NULL,
+  expires_at TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
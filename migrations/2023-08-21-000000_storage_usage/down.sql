@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS account_storage_deposits;
+DROP INDEX IF EXISTS tsu_payer_version_index;
+DROP TABLE IF EXISTS transaction_storage_usage;
@@ -0,0 +1,29 @@
+-- Your SQL goes here
+-- Storage fees and write set sizes of user transactions, see custom::driver::storage_usage.
+-- Fees come from the 0x1::transaction_fee::FeeStatement event and are null before the chain
+-- emitted it.
+CREATE TABLE IF NOT EXISTS transaction_storage_usage (
+  transaction_version BIGINT NOT NULL,
+  -- The fee payer if the transaction has one, the sender otherwise
+  payer_address VARCHAR(66) NOT NULL,
+  storage_fee_octas NUMERIC,
+  storage_fee_refund_octas NUMERIC,
+  slots_written BIGINT NOT NULL,
+  slots_deleted BIGINT NOT NULL,
+  -- Bytes of the written table items (key and value) and modules, resources aren't sized by the API
+  bytes_written BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (transaction_version)
+);
+CREATE INDEX IF NOT EXISTS tsu_payer_version_index ON transaction_storage_usage (payer_address, transaction_version);
+-- Running storage deposits per payer: fees add, refunds subtract, never below zero.
+-- last_transaction_version keeps a re-processed batch from applying twice.
+CREATE TABLE IF NOT EXISTS account_storage_deposits (
+  account_address VARCHAR(66) NOT NULL,
+  total_deposited_octas NUMERIC NOT NULL,
+  total_refunded_octas NUMERIC NOT NULL,
+  deposit_balance_octas NUMERIC NOT NULL,
+  last_transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (account_address)
+);
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- Lets queries::stream_events page through events by version without scanning the table
+CREATE INDEX IF NOT EXISTS ev_version_index ON events (transaction_version);
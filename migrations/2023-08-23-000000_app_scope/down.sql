@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS app_scope_table_handles;
+DROP TABLE IF EXISTS app_scope_addresses;
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+-- The module addresses an app-scoped processor indexes, see custom::driver::app_scope. An address
+-- added since the processor last ran isn't backfilled until its backfill completes.
+CREATE TABLE IF NOT EXISTS app_scope_addresses (
+  processor VARCHAR(50) NOT NULL,
+  address VARCHAR(66) NOT NULL,
+  backfilled BOOLEAN NOT NULL,
+  added_at_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (processor, address)
+);
+-- Table handles reachable from the scoped apps' resources, whose items are in scope
+CREATE TABLE IF NOT EXISTS app_scope_table_handles (
+  handle VARCHAR(66) NOT NULL PRIMARY KEY,
+  discovered_from TEXT NOT NULL,
+  transaction_version BIGINT NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
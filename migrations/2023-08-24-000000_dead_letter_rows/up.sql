@@ -0,0 +1,15 @@
+-- Your SQL goes here
+-- Rows left out of an insert for a value over its column's size limit, see
+-- custom::driver::row_limits. Keyed by the hash of the row so a retried batch doesn't store it twice.
+CREATE TABLE IF NOT EXISTS dead_letter_rows (
+  table_name VARCHAR(100) NOT NULL,
+  transaction_version BIGINT NOT NULL,
+  row_hash VARCHAR(64) NOT NULL,
+  column_name VARCHAR(100) NOT NULL,
+  column_bytes BIGINT NOT NULL,
+  max_bytes BIGINT NOT NULL,
+  row_data JSONB NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (table_name, transaction_version, row_hash)
+);
+CREATE INDEX IF NOT EXISTS dlr_insat_index ON dead_letter_rows (inserted_at);
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- Lets queries::get_table_item_as_of find the last write of a key at or before a version. On the md5
+-- of the key, since keys can be longer than an index entry allows.
+CREATE INDEX IF NOT EXISTS ti_hand_keymd5_ver_index ON table_items (table_handle, md5(key), transaction_version DESC);
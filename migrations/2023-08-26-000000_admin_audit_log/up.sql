@@ -0,0 +1,15 @@
+-- Your SQL goes here
+-- Requests to the admin server that launch or cancel operations, with the caller that made them,
+-- see custom::driver::admin
+CREATE TABLE IF NOT EXISTS admin_audit_log (
+  id BIGSERIAL PRIMARY KEY,
+  caller VARCHAR(100) NOT NULL,
+  action VARCHAR(50) NOT NULL,
+  parameters JSONB NOT NULL,
+  operation_id VARCHAR(100),
+  -- accepted or rejected
+  outcome VARCHAR(20) NOT NULL,
+  error TEXT,
+  requested_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS aal_reqat_index ON admin_audit_log (requested_at);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS at_to_version_index;
+DROP INDEX IF EXISTS at_from_version_index;
+DROP TABLE IF EXISTS asset_transfers;
@@ -0,0 +1,29 @@
+-- Your SQL goes here
+-- Who sent what to whom, normalized across coin v1, fungible assets and tokens v1 and v2, see
+-- custom::driver::asset_transfers. Written by custom_coin_processor.
+CREATE TABLE IF NOT EXISTS asset_transfers (
+  transaction_version BIGINT NOT NULL,
+  -- Position of the transfer in the transaction, in the order of the events completing them
+  transfer_index BIGINT NOT NULL,
+  -- coin_v1, fungible_asset, token_v1 or token_v2
+  standard VARCHAR(20) NOT NULL,
+  -- Coin type, fungible asset metadata address, token v1 data id hash or token v2 object address
+  asset_id VARCHAR(5000) NOT NULL,
+  -- Property version of a token v1, 0 otherwise
+  property_version_v1 NUMERIC NOT NULL,
+  -- Null for mints and unmatched deposits
+  from_address VARCHAR(66),
+  -- Null for burns and unmatched withdrawals
+  to_address VARCHAR(66),
+  -- Amount of a fungible asset, number of tokens
+  amount NUMERIC NOT NULL,
+  -- direct, mint, burn, airdrop_claim, unmatched_deposit or unmatched_withdrawal
+  kind VARCHAR(30) NOT NULL,
+  -- Event completing the transfer
+  event_index BIGINT NOT NULL,
+  transaction_timestamp TIMESTAMP NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (transaction_version, transfer_index)
+);
+CREATE INDEX IF NOT EXISTS at_from_version_index ON asset_transfers (from_address, transaction_version);
+CREATE INDEX IF NOT EXISTS at_to_version_index ON asset_transfers (to_address, transaction_version);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+UPDATE signatures
+SET is_sender_primary = TRUE
+WHERE is_fee_payer;
+ALTER TABLE signatures DROP COLUMN IF EXISTS is_fee_payer;
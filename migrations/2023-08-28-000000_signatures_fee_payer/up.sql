@@ -0,0 +1,9 @@
+-- Your SQL goes here
+ALTER TABLE signatures
+ADD COLUMN IF NOT EXISTS is_fee_payer BOOLEAN NOT NULL DEFAULT FALSE;
+-- The fee payer was the only primary signer after the sender
+UPDATE signatures
+SET is_fee_payer = TRUE,
+  is_sender_primary = FALSE
+WHERE is_sender_primary
+  AND multi_agent_index > 0;
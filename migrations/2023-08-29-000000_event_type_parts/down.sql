@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE events DROP COLUMN IF EXISTS event_account_address,
+  DROP COLUMN IF EXISTS event_module,
+  DROP COLUMN IF EXISTS event_name,
+  DROP COLUMN IF EXISTS event_type_params;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE events
+ADD COLUMN IF NOT EXISTS event_account_address VARCHAR(66),
+  ADD COLUMN IF NOT EXISTS event_module TEXT,
+  ADD COLUMN IF NOT EXISTS event_name TEXT,
+  ADD COLUMN IF NOT EXISTS event_type_params JSONB;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS cmr_insat_index;
+DROP INDEX IF EXISTS cmr_addr_mod_name_index;
+DROP TABLE IF EXISTS current_move_resources;
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS current_move_resources (
+  address VARCHAR(66) NOT NULL,
+  type TEXT NOT NULL,
+  module TEXT NOT NULL,
+  name TEXT NOT NULL,
+  generic_type_params JSONB,
+  -- NULL once deleted
+  data JSONB,
+  state_key_hash VARCHAR(66) NOT NULL,
+  last_transaction_version BIGINT NOT NULL,
+  is_deleted BOOLEAN NOT NULL,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  -- Constraints
+  PRIMARY KEY (address, type)
+);
+CREATE INDEX IF NOT EXISTS cmr_addr_mod_name_index ON current_move_resources (address, module, name);
+CREATE INDEX IF NOT EXISTS cmr_insat_index ON current_move_resources (inserted_at);
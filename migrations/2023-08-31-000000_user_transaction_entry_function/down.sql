@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS ut_entry_function_module_name_index;
+ALTER TABLE user_transactions DROP COLUMN IF EXISTS entry_function_module,
+  DROP COLUMN IF EXISTS entry_function_name,
+  DROP COLUMN IF EXISTS entry_function_type_arguments,
+  DROP COLUMN IF EXISTS entry_function_arguments;
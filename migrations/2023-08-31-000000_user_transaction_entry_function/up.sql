@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE user_transactions
+ADD COLUMN IF NOT EXISTS entry_function_module TEXT,
+  ADD COLUMN IF NOT EXISTS entry_function_name TEXT,
+  ADD COLUMN IF NOT EXISTS entry_function_type_arguments JSONB NOT NULL DEFAULT '[]',
+  ADD COLUMN IF NOT EXISTS entry_function_arguments JSONB NOT NULL DEFAULT '[]';
+CREATE INDEX IF NOT EXISTS ut_entry_function_module_name_index ON user_transactions (entry_function_module, entry_function_name);
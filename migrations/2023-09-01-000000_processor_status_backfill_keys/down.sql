@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM processor_status
+WHERE LENGTH(processor) > 50;
+ALTER TABLE processor_status
+ALTER COLUMN processor TYPE VARCHAR(50);
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- Room for the progress of backfill ranges, <processor>@<start_version>-<end_version>, see
+-- custom::driver::backfill
+ALTER TABLE processor_status
+ALTER COLUMN processor TYPE VARCHAR(100);
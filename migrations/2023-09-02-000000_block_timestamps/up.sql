@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE transactions
+ADD COLUMN IF NOT EXISTS block_timestamp TIMESTAMP;
+ALTER TABLE events
+ADD COLUMN IF NOT EXISTS block_timestamp TIMESTAMP;
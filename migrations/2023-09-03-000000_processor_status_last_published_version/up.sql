@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE processor_status
+ADD COLUMN IF NOT EXISTS last_published_version BIGINT;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS mr_res_addr_mod_name_ver_index;
+DROP INDEX IF EXISTS cmr_res_addr_mod_name_index;
+ALTER TABLE move_resources DROP COLUMN IF EXISTS resource_address;
+ALTER TABLE current_move_resources DROP COLUMN IF EXISTS resource_address;
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+ALTER TABLE move_resources
+ADD COLUMN IF NOT EXISTS resource_address VARCHAR(66);
+ALTER TABLE current_move_resources
+ADD COLUMN IF NOT EXISTS resource_address VARCHAR(66);
+CREATE INDEX IF NOT EXISTS mr_res_addr_mod_name_ver_index ON move_resources (
+  resource_address,
+  module,
+  name,
+  transaction_version
+);
+CREATE INDEX IF NOT EXISTS cmr_res_addr_mod_name_index ON current_move_resources (resource_address, module, name);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS ans_ra_primary_index;
+ALTER TABLE current_ans_lookup DROP COLUMN IF EXISTS record_expiration_timestamp;
+ALTER TABLE current_ans_lookup DROP COLUMN IF EXISTS is_primary;
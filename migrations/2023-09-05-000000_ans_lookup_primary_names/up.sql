@@ -0,0 +1,15 @@
+-- Your SQL goes here
+ALTER TABLE current_ans_lookup
+ADD COLUMN IF NOT EXISTS is_primary BOOLEAN NOT NULL DEFAULT FALSE;
+-- the expiration in the name's own record, expiration_timestamp caps a subdomain's at its domain's
+ALTER TABLE current_ans_lookup
+ADD COLUMN IF NOT EXISTS record_expiration_timestamp TIMESTAMP;
+UPDATE current_ans_lookup
+SET record_expiration_timestamp = expiration_timestamp
+WHERE record_expiration_timestamp IS NULL;
+ALTER TABLE current_ans_lookup
+ALTER COLUMN record_expiration_timestamp
+SET NOT NULL;
+-- the primary name of an address
+CREATE INDEX IF NOT EXISTS ans_ra_primary_index ON current_ans_lookup (registered_address)
+WHERE is_primary;
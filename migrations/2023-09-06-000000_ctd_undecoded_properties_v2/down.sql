@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS ctd_undecoded_properties_v2_index;
+CREATE INDEX IF NOT EXISTS ctd_undecoded_properties_index ON current_token_datas (token_data_id_hash)
+WHERE default_properties ? 'map';
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- Rows with a property value that may still be BCS encoded, which token_properties_v2 (see
+-- custom::enrichment::token_properties) decodes again from the property map's table item
+DROP INDEX IF EXISTS ctd_undecoded_properties_index;
+CREATE INDEX IF NOT EXISTS ctd_undecoded_properties_v2_index ON current_token_datas (token_data_id_hash)
+WHERE (default_properties ? 'map' OR default_properties::text LIKE '%": "0x%');
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS ba_unhealed_index;
+DROP TABLE IF EXISTS balance_anomalies;
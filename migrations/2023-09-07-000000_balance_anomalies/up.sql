@@ -0,0 +1,25 @@
+-- Your SQL goes here
+-- Balances the indexer keeps from deltas that a delta would have taken below zero, see
+-- custom::driver::balance_guard. The balance isn't written negative; the account and asset are
+-- re-derived from their history once the batch is committed, and healed_at is set then.
+CREATE TABLE IF NOT EXISTS balance_anomalies (
+  -- Table of the balance, e.g. account_storage_deposits
+  balance_table VARCHAR(50) NOT NULL,
+  account_address VARCHAR(66) NOT NULL,
+  asset VARCHAR(5000) NOT NULL,
+  transaction_version BIGINT NOT NULL,
+  processor VARCHAR(50) NOT NULL,
+  expected_delta NUMERIC NOT NULL,
+  stored_balance NUMERIC NOT NULL,
+  rederived_balance NUMERIC,
+  healed_at TIMESTAMP,
+  inserted_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  PRIMARY KEY (
+    balance_table,
+    account_address,
+    asset,
+    transaction_version
+  )
+);
+CREATE INDEX IF NOT EXISTS ba_unhealed_index ON balance_anomalies (balance_table)
+WHERE healed_at IS NULL;
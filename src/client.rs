@@ -0,0 +1,223 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Everything a downstream service needs to decode what the indexer publishes. This module (and
+//! the model structs it re-exports) builds without the default `indexer` feature, i.e. without
+//! diesel, rdkafka or the fullnode runtime:
+//!
+//! ```toml
+//! aptos-indexer = { ..., default-features = false }
+//! ```
+
+pub use crate::models::{events::EventModel, transactions::TransactionModel};
+use anyhow::Context;
+pub use aptos_api_types::Transaction as APITransaction;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Header carrying the unsalted message key, see `custom::driver::salting`
+pub const LOGICAL_KEY_HEADER: &str = "logical_key";
+
+/// Header set to `true` on the priority lane's early copy of a transaction, see
+/// `custom::driver::priority`. The same version is published again by the main pipeline, so
+/// consumers should keep one message per version and prefer the one without this header.
+pub const PRIORITY_HEADER: &str = "priority";
+
+/// Header carrying the version of the payload's schema, see `custom::driver::payload_schema`.
+/// Set on every message but dead letters.
+pub const SCHEMA_VERSION_HEADER: &str = "schema_version";
+
+/// Header carrying the version of the order messages are published in within a batch, see
+/// `custom::driver::ordering`. Set on every message.
+pub const ORDERING_VERSION_HEADER: &str = "ordering_version";
+
+/// Header set to `PROTOBUF_FORMAT` on transactions, events and write set changes published as
+/// protobuf, see `proto/published.proto`, and to `AVRO_FORMAT` on those published as Avro. JSON
+/// messages don't have it.
+pub const FORMAT_HEADER: &str = "format";
+
+pub const PROTOBUF_FORMAT: &str = "protobuf";
+
+/// Avro in the Confluent wire format: a 0 byte, the big-endian 4 byte id of the schema in the
+/// schema registry, then the Avro datum
+pub const AVRO_FORMAT: &str = "avro";
+
+/// Header carrying the id of the chain the message comes from, see `custom::driver::envelope`.
+/// Set on every message.
+pub const CHAIN_ID_HEADER: &str = "chain_id";
+
+/// Header carrying the name of the processor that published the message. Control messages, which
+/// the processors share, don't have it.
+pub const PROCESSOR_NAME_HEADER: &str = "processor_name";
+
+/// Header carrying the version of the transaction the message is about. Messages that aren't
+/// about a single transaction, like control messages, don't have it.
+pub const TRANSACTION_VERSION_HEADER: &str = "transaction_version";
+
+/// Header carrying the height of the block of the transaction, on transactions, events and write
+/// set changes
+pub const BLOCK_HEIGHT_HEADER: &str = "block_height";
+
+/// Appended to a topic for the topic its dead letters go to, see `DeadLetterMessage`
+pub const DEAD_LETTER_TOPIC_SUFFIX: &str = ".dlq";
+
+/// Which `topics` config entry each published model goes to. `TransactionModel` messages carry
+/// the full API transaction (see `decode_transaction`), all others a single serialized model.
+pub const MODEL_TOPIC_KEYS: &[(&str, &str)] = &[
+    ("TransactionModel", "transaction_topic"),
+    ("CoinInfo", "coin_info_topic"),
+    ("CoinActivity", "coin_activity_topic"),
+    ("CoinBalance", "coin_balance_topic"),
+    ("CurrentCoinBalance", "current_coin_balance_topic"),
+    ("CoinSupply", "coin_supply_topic"),
+    ("CurrentTokenData", "current_token_data_topic"),
+    ("Token", "token_topic"),
+    ("TokenData", "token_data_topic"),
+    ("TokenOwnership", "token_ownership_topic"),
+    ("CurrentTokenOwnership", "current_token_ownership_topic"),
+    ("CurrentCollectionData", "current_collection_data_topic"),
+    ("TokenActivity", "token_activity_topic"),
+    ("OnchainConfigChange", "onchain_config_topic"),
+    ("CurrentObject", "current_object_topic"),
+    ("CurrentAssetStore", "asset_store_topic"),
+    ("HealthStateChange", "control_topic"),
+    ("OperationEvent", "control_topic"),
+    ("EntryFunctionDailyRollup", "entry_function_stats_topic"),
+    ("AssetTransfer", "asset_transfer_topic"),
+    ("EventModel", "event_topic"),
+    ("WriteSetChangeModel", "write_set_change_topic"),
+    ("CurrentMoveResource", "current_move_resource_topic"),
+    ("AccountTransaction", "account_transaction_topic"),
+    ("DelegatedStakingActivity", "staking_activity_topic"),
+    ("CurrentStakingPoolVoter", "staking_pool_voter_topic"),
+    ("CurrentDelegatorBalance", "delegator_balance_topic"),
+    ("CurrentAnsLookup", "ans_lookup_topic"),
+    ("BatchManifest", "batch_manifest_topic"),
+];
+
+pub fn topic_key_for_model(model: &str) -> Option<&'static str> {
+    MODEL_TOPIC_KEYS
+        .iter()
+        .find(|(name, _)| *name == model)
+        .map(|(_, topic_key)| *topic_key)
+}
+
+/// Payload of a JSON message on `transaction_topic`, see `FORMAT_HEADER`
+pub fn decode_transaction(payload: &[u8]) -> anyhow::Result<APITransaction> {
+    serde_json::from_slice(payload).context("Failed to decode published transaction")
+}
+
+/// Payload of a message on any model topic, e.g. `decode_model::<EventModel>(payload)`
+pub fn decode_model<T: DeserializeOwned>(payload: &[u8]) -> anyhow::Result<T> {
+    serde_json::from_slice(payload)
+        .with_context(|| format!("Failed to decode published {}", std::any::type_name::<T>()))
+}
+
+/// Payload of a message on `<topic>.dlq`, published in place of a message that couldn't be
+/// serialized or that Kafka rejected, with `publish_dead_letter` enabled. Decode with
+/// `decode_model::<DeadLetterMessage>(payload)`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DeadLetterMessage {
+    /// Model of the message, as in `MODEL_TOPIC_KEYS`
+    pub model: String,
+    /// Where the message should have gone
+    pub topic: String,
+    pub version: Option<u64>,
+    /// Of the transaction, for transactions
+    pub hash: Option<String>,
+    /// Unsalted key of the message
+    pub key: Option<String>,
+    pub error: String,
+    /// The message as JSON, unless it couldn't be serialized, was over `max_payload_bytes` or was
+    /// protobuf
+    pub payload: Option<String>,
+    /// Size of the serialized message, if it could be serialized
+    pub payload_bytes: Option<usize>,
+}
+
+/// Body of a POST of the HTTP sink, published instead of Kafka messages with `sink.backend` set
+/// to `http`. `records` are transactions for `TransactionModel`, decode the body with
+/// `decode_model::<HttpBatch<APITransaction>>(body)`, and the models themselves otherwise.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct HttpBatch<T> {
+    /// Model of the records, as in `MODEL_TOPIC_KEYS`
+    pub model: String,
+    pub chain_id: u8,
+    pub processor_name: String,
+    /// See `SCHEMA_VERSION_HEADER`
+    pub schema_version: Option<u32>,
+    /// In publishing order, see `ORDERING_VERSION_HEADER`
+    pub records: Vec<T>,
+}
+
+/// The topic the dead letters of `topic` go to
+pub fn dead_letter_topic(topic: &str) -> String {
+    format!("{}{}", topic, DEAD_LETTER_TOPIC_SUFFIX)
+}
+
+/// Unsalted key from a message's headers, falling back to the message key itself
+pub fn logical_key<'a>(
+    headers: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+    message_key: Option<&'a [u8]>,
+) -> Option<&'a [u8]> {
+    headers
+        .into_iter()
+        .find(|(name, _)| *name == LOGICAL_KEY_HEADER)
+        .map(|(_, value)| value)
+        .or(message_key)
+}
+
+/// Version of a message's payload schema, from its headers
+pub fn schema_version<'a>(headers: impl IntoIterator<Item = (&'a str, &'a [u8])>) -> Option<u32> {
+    parsed_header(headers, SCHEMA_VERSION_HEADER)
+}
+
+/// Version of the order a message was published in, from its headers
+pub fn ordering_version<'a>(
+    headers: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+) -> Option<u32> {
+    parsed_header(headers, ORDERING_VERSION_HEADER)
+}
+
+/// Id of the chain a message comes from, from its headers
+pub fn chain_id<'a>(headers: impl IntoIterator<Item = (&'a str, &'a [u8])>) -> Option<u8> {
+    parsed_header(headers, CHAIN_ID_HEADER)
+}
+
+/// Processor that published a message, from its headers
+pub fn processor_name<'a>(
+    headers: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+) -> Option<&'a str> {
+    headers
+        .into_iter()
+        .find(|(name, _)| *name == PROCESSOR_NAME_HEADER)
+        .and_then(|(_, value)| std::str::from_utf8(value).ok())
+}
+
+/// Version of the transaction a message is about, from its headers
+pub fn transaction_version<'a>(
+    headers: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+) -> Option<u64> {
+    parsed_header(headers, TRANSACTION_VERSION_HEADER)
+}
+
+/// Height of the block of the transaction a message is about, from its headers
+pub fn block_height<'a>(headers: impl IntoIterator<Item = (&'a str, &'a [u8])>) -> Option<u64> {
+    parsed_header(headers, BLOCK_HEIGHT_HEADER)
+}
+
+fn parsed_header<'a, T: std::str::FromStr>(
+    headers: impl IntoIterator<Item = (&'a str, &'a [u8])>,
+    header: &str,
+) -> Option<T> {
+    headers
+        .into_iter()
+        .find(|(name, _)| *name == header)
+        .and_then(|(_, value)| std::str::from_utf8(value).ok()?.parse().ok())
+}
+
+/// Whether a message is a priority lane copy that the main pipeline will publish again
+pub fn is_priority<'a>(headers: impl IntoIterator<Item = (&'a str, &'a [u8])>) -> bool {
+    headers
+        .into_iter()
+        .any(|(name, value)| name == PRIORITY_HEADER && value == b"true")
+}
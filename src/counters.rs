@@ -2,8 +2,9 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use aptos_metrics_core::{
-    register_int_counter, register_int_counter_vec, register_int_gauge_vec, IntCounter,
-    IntCounterVec, IntGaugeVec,
+    register_gauge, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, register_int_gauge_vec, Gauge, HistogramVec, IntCounter, IntCounterVec,
+    IntGauge, IntGaugeVec,
 };
 use once_cell::sync::Lazy;
 
@@ -73,6 +74,24 @@ pub static FETCHED_TRANSACTION: Lazy<IntCounter> = Lazy::new(|| {
     .unwrap()
 });
 
+/// Average bytes per version of the recent fetches, when fetches are sized by bytes
+pub static FETCH_BYTES_PER_VERSION: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "indexer_fetch_bytes_per_version",
+        "Average bytes per version of the recent fetches"
+    )
+    .unwrap()
+});
+
+/// Number of fetches over the hard cap that were fetched again in halves
+pub static FETCH_SPLITS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_fetch_splits_count",
+        "Number of fetches over the hard cap that were fetched again in halves"
+    )
+    .unwrap()
+});
+
 /// Max version processed
 pub static LATEST_PROCESSED_VERSION: Lazy<IntGaugeVec> = Lazy::new(|| {
     register_int_gauge_vec!(
@@ -82,3 +101,687 @@ pub static LATEST_PROCESSED_VERSION: Lazy<IntGaugeVec> = Lazy::new(|| {
     )
     .unwrap()
 });
+
+/// Number of current_* rows not applied because the stored row was already newer
+pub static CURRENT_ROW_REGRESSIONS_REJECTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_current_row_regressions_rejected_count",
+        "Number of current table upserts rejected because the stored row has a newer last_transaction_version",
+        &["table_name"]
+    )
+    .unwrap()
+});
+
+/// Number of messages published with a salted key, by unsalted key
+pub static PUBLISHER_SALTED_MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_publisher_salted_message_count",
+        "Number of messages published with a salted key, by configured hot key, auto for detected ones",
+        &["logical_key"]
+    )
+    .unwrap()
+});
+
+/// Number of keys currently detected as hot by the publisher
+pub static PUBLISHER_HOT_KEYS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "indexer_publisher_detected_hot_keys",
+        "Number of message keys currently over the hot key share threshold"
+    )
+    .unwrap()
+});
+
+/// Seconds from a transaction's block timestamp until it was published (priority lane) or
+/// committed (main pipeline)
+pub static INDEXING_LATENCY_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "indexer_indexing_latency_seconds",
+        "Seconds between a transaction's timestamp and it being indexed, by lane",
+        &["lane"],
+        vec![0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0, 900.0]
+    )
+    .unwrap()
+});
+
+/// Number of transactions sent through the priority lane
+pub static PRIORITY_LANE_TRANSACTIONS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_priority_lane_transaction_count",
+        "Number of transactions touching watched addresses published through the priority lane"
+    )
+    .unwrap()
+});
+
+/// Number of addresses currently watched by the priority lane
+pub static PRIORITY_LANE_WATCHED_ADDRESSES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "indexer_priority_lane_watched_addresses",
+        "Number of addresses currently watched by the priority lane"
+    )
+    .unwrap()
+});
+
+/// Latest flushed column stats, see `custom::driver::column_stats`
+pub static COLUMN_STATS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "indexer_column_stats",
+        "Sampled column statistics of inserted rows: distinct_estimate or null_ratio_ppm",
+        &["table_name", "column_name", "stat"]
+    )
+    .unwrap()
+});
+
+/// Memory reserved by the column stats sketches of the current window
+pub static COLUMN_STATS_MEMORY_BYTES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "indexer_column_stats_memory_bytes",
+        "Memory reserved by the column stats sketches of the current window"
+    )
+    .unwrap()
+});
+
+/// Rows changed by each enricher, see `custom::enrichment`
+pub static ENRICHMENT_ROWS_ENRICHED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_enrichment_rows_enriched_count",
+        "Number of stored rows updated by an enricher",
+        &["enricher"]
+    )
+    .unwrap()
+});
+
+/// Rows enriched per second over each enricher's last batch, rate limiting included
+pub static ENRICHMENT_ROWS_PER_SECOND: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "indexer_enrichment_rows_per_second",
+        "Rows enriched per second over the last batch",
+        &["enricher"]
+    )
+    .unwrap()
+});
+
+/// Estimated rows each enricher still has to go through
+pub static ENRICHMENT_BACKLOG_ROWS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "indexer_enrichment_backlog_rows",
+        "Estimated number of rows an enricher still has to enrich",
+        &["enricher"]
+    )
+    .unwrap()
+});
+
+/// Move data parses diffed against the parsed types, see `strictness`
+pub static API_FIELD_DRIFT_CHECKS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_api_field_drift_checks_count",
+        "Number of Move data parses checked for field drift"
+    )
+    .unwrap()
+});
+
+/// Unknown or missing fields found in Move data, by struct and field path
+pub static API_FIELD_DRIFT: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_api_field_drift_count",
+        "Number of checked parses where a field was unknown to or missing from the indexer's type",
+        &["data_type", "field_path", "kind"]
+    )
+    .unwrap()
+});
+
+/// 1 while a processor is paused, see `custom::driver::lifecycle`
+pub static PROCESSOR_PAUSED: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "indexer_processor_paused",
+        "1 while a processor is paused through its lifecycle control",
+        &["processor_name"]
+    )
+    .unwrap()
+});
+
+/// Number of times a processor was rebuilt through its lifecycle control
+pub static PROCESSOR_RELOADS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_processor_reload_count",
+        "Number of times a processor was rebuilt while the others kept running",
+        &["processor_name"]
+    )
+    .unwrap()
+});
+
+/// Alerts raised through `custom::driver::alerts`, by kind
+pub static ALERTS_FIRED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_alerts_fired_count",
+        "Number of operationally significant events alerted on",
+        &["kind"]
+    )
+    .unwrap()
+});
+
+/// Lag of the consumer group watched by `custom::driver::consumer_lag`, as of its last query
+pub static CONSUMER_LAG: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "indexer_consumer_lag_messages",
+        "Messages on the data topics not yet committed by the watched consumer group"
+    )
+    .unwrap()
+});
+
+/// Share of their unthrottled rate the processors run at because of consumer lag
+pub static CONSUMER_LAG_THROTTLE_FACTOR: Lazy<Gauge> = Lazy::new(|| {
+    register_gauge!(
+        "indexer_consumer_lag_throttle_factor",
+        "Share of the unthrottled rate the processors run at, 1 when not throttled"
+    )
+    .unwrap()
+});
+
+/// Conflicting rows left as they were because their batch was outside a backfill window
+pub static BACKFILL_GUARD_SKIPPED_ROWS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_backfill_guard_skipped_rows_count",
+        "Number of conflicting rows not overwritten outside of a backfill window",
+        &["table_name"]
+    )
+    .unwrap()
+});
+
+/// Stored objects whose ultimate owner was updated because an object up their chain changed
+pub static OBJECT_OWNERSHIP_PROPAGATED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_object_ownership_propagated_count",
+        "Number of descendant objects whose ultimate owner was re-resolved"
+    )
+    .unwrap()
+});
+
+/// Batches whose ownership changes had more descendants than `max_propagation`
+pub static OBJECT_OWNERSHIP_PROPAGATION_TRUNCATED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_object_ownership_propagation_truncated_count",
+        "Number of batches that left descendant objects with a stale ultimate owner"
+    )
+    .unwrap()
+});
+
+/// Objects whose ultimate owner couldn't be resolved, by reason (cycle, too_deep)
+pub static OBJECT_OWNERSHIP_UNRESOLVED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_object_ownership_unresolved_count",
+        "Number of objects whose ownership chain has a cycle or is longer than the max depth",
+        &["reason"]
+    )
+    .unwrap()
+});
+
+/// Rows breaking a processor's validation rules, see `custom::driver::validation`
+pub static VALIDATION_VIOLATIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_validation_violations_count",
+        "Number of rows a processor was about to commit that broke one of its validation rules",
+        &["processor_name", "rule", "policy"]
+    )
+    .unwrap()
+});
+
+/// Batches failed by a validation rule with the `fail` policy
+pub static VALIDATION_FAILED_BATCHES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_validation_failed_batches_count",
+        "Number of batches failed before commit by a validation rule",
+        &["processor_name", "rule"]
+    )
+    .unwrap()
+});
+
+/// Buffers allocated or grown to serialize published messages, see `custom::driver::serialization`
+pub static PUBLISH_SERIALIZATION_ALLOCATIONS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_publish_serialization_allocations_count",
+        "Number of message buffers allocated or grown because no pooled buffer was large enough"
+    )
+    .unwrap()
+});
+
+/// Seconds to serialize and enqueue one batch of a published model
+pub static PUBLISH_BATCH_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "indexer_publish_batch_seconds",
+        "Seconds to serialize and enqueue a batch of published messages, by model",
+        &["model"],
+        vec![0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]
+    )
+    .unwrap()
+});
+
+/// Ledger resets detected at startup, see `custom::driver::ledger_reset`
+pub static LEDGER_RESETS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_ledger_resets_count",
+        "Number of times the node's genesis differed from the indexed one, by the policy applied",
+        &["processor_name", "policy"]
+    )
+    .unwrap()
+});
+
+/// Entries appended to the change feed, see `custom::driver::change_feed`
+pub static CHANGE_FEED_ENTRIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_change_feed_entries_count",
+        "Number of change feed entries written, by table",
+        &["model"]
+    )
+    .unwrap()
+});
+
+/// Change feed entries deleted after the retention window
+pub static CHANGE_FEED_PRUNED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_change_feed_pruned_count",
+        "Number of change feed entries pruned after the retention window"
+    )
+    .unwrap()
+});
+
+/// Version ranges hashed, see `custom::driver::range_hash`
+pub static RANGE_HASHES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_range_hashes_count",
+        "Number of version ranges hashed, by processor and whether hashing succeeded",
+        &["processor_name", "result"]
+    )
+    .unwrap()
+});
+
+/// Retry budgets spent, by the class of the attempt that spent them, see
+/// `custom::driver::retry_budget`
+pub static RETRY_BUDGET_EXHAUSTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_retry_budget_exhausted_count",
+        "Number of retry budgets exhausted, by the class of the last attempt",
+        &["class"]
+    )
+    .unwrap()
+});
+
+/// Health state of each processor, 0 healthy, 1 degraded, 2 critical, see
+/// `custom::driver::circuit_breaker`
+pub static HEALTH_STATE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "indexer_processor_health_state",
+        "Health state of the processor: 0 healthy, 1 degraded, 2 critical",
+        &["processor_name"]
+    )
+    .unwrap()
+});
+
+/// Replication lag read by `custom::driver::replication_lag`, as of its last query
+pub static REPLICATION_LAG_SECONDS: Lazy<Gauge> = Lazy::new(|| {
+    register_gauge!(
+        "indexer_replication_lag_seconds",
+        "Replication lag of the Postgres replicas in seconds"
+    )
+    .unwrap()
+});
+
+/// Pauses of fetching and processing because of replication lag
+pub static REPLICATION_LAG_PAUSES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_replication_lag_pauses_count",
+        "Number of times fetching and processing paused because of replication lag"
+    )
+    .unwrap()
+});
+
+/// 1 while fetching and processing are paused because of replication lag
+pub static REPLICATION_LAG_PAUSED: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "indexer_replication_lag_paused",
+        "1 while fetching and processing are paused because of replication lag, else 0"
+    )
+    .unwrap()
+});
+
+/// Lifecycle events of long-running operations, see `custom::driver::operations`
+pub static OPERATION_EVENTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_operation_events_count",
+        "Number of lifecycle events of long-running operations, by kind and event",
+        &["kind", "event"]
+    )
+    .unwrap()
+});
+
+/// User transactions reusing a sender's sequence number, see
+/// `custom::driver::duplicate_transactions`
+pub static DUPLICATE_TRANSACTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_duplicate_transactions_count",
+        "Number of user transactions with the sender and sequence number of another version, by policy",
+        &["policy"]
+    )
+    .unwrap()
+});
+
+/// Batches a shadow processor ran on, see `custom::driver::shadow`
+pub static SHADOW_BATCHES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_shadow_batches_count",
+        "Number of batches a shadow processor ran on, by outcome: match, mismatch or error",
+        &["processor", "outcome"]
+    )
+    .unwrap()
+});
+
+/// Values where a shadow processor's output differs from the primary's
+pub static SHADOW_DIFFS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_shadow_diffs_count",
+        "Number of values where a shadow processor's output differs from the primary's, by table",
+        &["processor", "table"]
+    )
+    .unwrap()
+});
+
+/// Entry function calls left out of the daily stats, their day and function having been
+/// aggregated past them, see `custom::driver::entry_function_stats`
+pub static ENTRY_FUNCTION_STATS_SKIPPED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "indexer_entry_function_stats_skipped_count",
+        "Number of entry function calls at or below the last aggregated version of their day and function"
+    )
+    .unwrap()
+});
+
+/// Values replaced by a redaction marker, see `custom::driver::redaction`
+pub static REDACTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_redactions_count",
+        "Number of payload argument and event data values redacted, by rule",
+        &["rule"]
+    )
+    .unwrap()
+});
+
+/// Role of the process for each processor, see `custom::driver::standby`
+pub static STANDBY_ROLE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "indexer_standby_role",
+        "Role of the process for the processor, 1 if it leads and writes, 0 if it's a standby",
+        &["processor"]
+    )
+    .unwrap()
+});
+
+/// Leases acquired by a standby
+pub static STANDBY_TAKEOVERS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_standby_takeovers_count",
+        "Number of times a standby acquired the lease and became the leader",
+        &["processor"]
+    )
+    .unwrap()
+});
+
+/// Parsing stages that panicked on a standby
+pub static STANDBY_PARSE_PANICS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_standby_parse_panics_count",
+        "Number of parsing stages that panicked on a batch parsed by a standby",
+        &["processor"]
+    )
+    .unwrap()
+});
+
+/// Deltas that would have taken a balance below zero, see `custom::driver::balance_guard`
+pub static BALANCE_ANOMALIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_balance_anomalies_count",
+        "Number of deltas that would have taken a balance the indexer keeps below zero",
+        &["balance_table"]
+    )
+    .unwrap()
+});
+
+/// Balances derived again from their history after an anomaly, see
+/// `custom::driver::balance_guard`
+pub static BALANCE_ANOMALIES_HEALED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_balance_anomalies_healed_count",
+        "Number of accounts and assets whose balance was derived again from its history",
+        &["balance_table"]
+    )
+    .unwrap()
+});
+
+/// What an app-scoped processor leaves out, see `custom::driver::app_scope`
+pub static APP_SCOPE_SKIPPED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_app_scope_skipped_count",
+        "Number of transactions, events and write set changes outside of the app scope",
+        &["kind"]
+    )
+    .unwrap()
+});
+
+/// Values over their column's size limit, see `custom::driver::row_limits`
+pub static OVERSIZED_COLUMNS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_oversized_columns_count",
+        "Number of values over their column's size limit, by what was done about them",
+        &["table", "column", "action"]
+    )
+    .unwrap()
+});
+
+/// Requests to the admin server, see `custom::driver::admin`
+pub static ADMIN_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_admin_requests_count",
+        "Number of requests to the admin server, by action and status code",
+        &["action", "status"]
+    )
+    .unwrap()
+});
+
+/// Messages not produced again after a restart, see `custom::driver::replay_cache`
+pub static REPLAY_SUPPRESSED_MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_replay_suppressed_messages_count",
+        "Number of messages skipped because they were delivered before the last restart",
+        &["topic"]
+    )
+    .unwrap()
+});
+
+/// Replay caches started empty, see `custom::driver::replay_cache`
+pub static REPLAY_CACHE_RESETS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_replay_cache_resets_count",
+        "Number of replay caches that couldn't be loaded and were started empty, by reason",
+        &["reason"]
+    )
+    .unwrap()
+});
+
+/// Lookups of the read caches, see `database::read_cache`
+pub static READ_CACHE_LOOKUPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_read_cache_lookups_count",
+        "Number of lookups of a read cache, by cache and result (hit or miss)",
+        &["cache", "result"]
+    )
+    .unwrap()
+});
+
+/// Read cache entries dropped because their row was written, see `database::read_cache`
+pub static READ_CACHE_INVALIDATIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_read_cache_invalidations_count",
+        "Number of read cache entries invalidated by a write of their row, by cache",
+        &["cache"]
+    )
+    .unwrap()
+});
+
+/// Transactions left out of publishing by the publish filter, see `driver::publish_filter`
+pub static PUBLISH_FILTERED_TRANSACTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_publish_filtered_transactions_count",
+        "Number of transactions not published because they don't match the publish filter, by processor",
+        &["processor_name"]
+    )
+    .unwrap()
+});
+
+/// Retried produces and flushes, see `driver::publish_retry`
+pub static PUBLISH_RETRIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_publish_retries_count",
+        "Number of times producing to or flushing Kafka failed with a transient error and was retried, by topic or flush",
+        &["operation"]
+    )
+    .unwrap()
+});
+
+/// Versions processed, see `driver::metrics`
+pub static TRANSACTIONS_PROCESSED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_transactions_processed_total",
+        "Number of versions in the batches a processor processed successfully, by processor",
+        &["processor_name"]
+    )
+    .unwrap()
+});
+
+/// Seconds spent on a batch, see `driver::metrics`
+pub static BATCH_DURATION_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "indexer_batch_duration_seconds",
+        "Seconds a processor spent on a batch, by processor and stage (process, insert_to_db or publish)",
+        &["processor_name", "stage"],
+        vec![0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]
+    )
+    .unwrap()
+});
+
+/// Messages that couldn't be published, see `driver::publish_retry`
+pub static PUBLISHER_SEND_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_publisher_send_failures_total",
+        "Number of messages that couldn't be serialized or enqueued once retried, by model",
+        &["model"]
+    )
+    .unwrap()
+});
+
+/// Versions between the ledger and a processor's last batch, see `driver::metrics`
+pub static PROCESSOR_LAG_VERSIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "indexer_processor_lag_versions",
+        "Number of versions a processor was behind the ledger after its last round, by processor",
+        &["processor_name"]
+    )
+    .unwrap()
+});
+
+/// Rows the batches of a processor produced, see `indexer::processing_result::RowCounts`
+pub static PROCESSED_ROWS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_processed_rows_total",
+        "Number of rows the successful batches of a processor produced, by processor and entity",
+        &["processor_name", "entity"]
+    )
+    .unwrap()
+});
+
+/// Messages published to a dead letter topic instead, see `driver::publisher`
+pub static PUBLISHER_DEAD_LETTERED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_publisher_dead_lettered_total",
+        "Number of messages that couldn't be serialized or were rejected by Kafka and went to the dead letter topic, by model",
+        &["model"]
+    )
+    .unwrap()
+});
+
+/// Batches that didn't start right after the last one, see `driver::version_guard`
+pub static VERSION_GAPS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_version_gaps_total",
+        "Number of batches that skipped or repeated versions after the last batch of a processor, by processor",
+        &["processor_name"]
+    )
+    .unwrap()
+});
+
+/// Rounds processed again after a retryable error, see `driver::batch_retry`
+pub static BATCH_RETRIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_batch_retries_count",
+        "Number of rounds of batches that failed with a retryable error and were processed again, by processor",
+        &["processor_name"]
+    )
+    .unwrap()
+});
+
+/// Retried POSTs of the HTTP sink, see `driver::http_sink`
+pub static HTTP_SINK_RETRIES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_http_sink_retries_count",
+        "Number of times a POST to the HTTP sink failed with a server error or no response and was retried, by model",
+        &["model"]
+    )
+    .unwrap()
+});
+
+/// Batches a best effort sink failed to take, see `driver::multi_sink`
+pub static BEST_EFFORT_SINK_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_best_effort_sink_failures_count",
+        "Number of times a best effort sink failed to take a batch or to flush, which didn't fail the batch, by sink",
+        &["sink"]
+    )
+    .unwrap()
+});
+
+/// Transactions not published because they were published before, see `custom::driver::publish_dedupe`
+pub static PUBLISH_DEDUPE_SKIPPED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_publish_dedupe_skipped_count",
+        "Number of transactions, with their events and write set changes, not published again because a round that published them was recorded, by processor",
+        &["processor_name"]
+    )
+    .unwrap()
+});
+
+/// Messages the publisher's producer holds that Kafka didn't ack yet, see `driver::backpressure`
+pub static PUBLISHER_QUEUE_DEPTH: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "indexer_publisher_queue_depth",
+        "Number of messages the publisher's producer holds that Kafka didn't ack yet, as of the last message produced, by processor",
+        &["processor_name"]
+    )
+    .unwrap()
+});
+
+/// Times the publisher blocked on a full producer queue, see `driver::backpressure`
+pub static PUBLISHER_BACKPRESSURE_WAITS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_publisher_backpressure_waits_count",
+        "Number of times the publisher blocked until its producer's queue drained to the low watermark, by processor",
+        &["processor_name"]
+    )
+    .unwrap()
+});
+
+/// Time the processors were held back by `rate_limit`, see `driver::rate_limit`
+pub static RATE_LIMITED_MILLIS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "indexer_rate_limited_millis_count",
+        "Milliseconds processors waited after a round to stay within the versions per second of rate_limit, by processor",
+        &["processor_name"]
+    )
+    .unwrap()
+});
@@ -0,0 +1,702 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Admin HTTP server for operators to launch, poll and cancel operations (see `driver::operations`)
+//! without a shell in the pod:
+//! - `POST /operations/backfill`, `/operations/rewind`, `/operations/enrichment` and
+//!   `/operations/prune` launch an operation from a JSON body and answer with its id
+//! - `GET /operations` lists the running operations, `GET /operations/<id>` reports on one, from
+//!   `operations_log` once it's done
+//! - `POST /operations/<id>/cancel` asks a backfill, rewind or enrichment to stop
+//! - `GET /gaps/<from>/<to>` reports the versions missing from `transactions` in a range, see
+//!   `driver::version_guard::verify_gaps`
+//!
+//! Callers authenticate with `Authorization: Bearer <token>`; the config only holds the sha256 of
+//! each caller's token. Rewinds and prunes lose or redo work, so they need `"confirm": true`.
+//! Every launch and cancel, accepted or not, is recorded in `admin_audit_log` with the caller's
+//! name, which is also the operation's actor. Backfills and rewinds are carried out by the
+//! processor's lifecycle control, so the processor has to run in this process; operations have
+//! to be tracked (`operations.enabled`) for them to have an id.
+
+use crate::{
+    counters::ADMIN_REQUESTS,
+    custom::{
+        driver::{
+            change_feed,
+            config::{DriverConfig, EnrichmentConfig},
+            lifecycle::{BackfillCommand, Indexer},
+            operations::{self, Operation},
+            version_guard,
+        },
+        enrichment,
+    },
+    database::{execute_with_better_error, PgDbPool},
+    models::admin_audit_log::AdminAuditEntry,
+    schema::admin_audit_log,
+    util::hash_str,
+};
+use aptos_logger::{error, info, warn};
+use once_cell::sync::OnceCell;
+use poem::{
+    handler, http::StatusCode, listener::TcpListener, web::Data, Body, EndpointExt, Request,
+    Response, Server,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+/// Operations that stop early when cancelled
+const CANCELLABLE: &[&str] = &["backfill", "rewind", "enrichment"];
+
+/// Reprocesses versions below a processor's watermark, overwriting their rows
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackfillRequest {
+    pub processor: String,
+    pub start_version: u64,
+    /// Inclusive, the version before the watermark if unset
+    pub end_version: Option<u64>,
+    /// Lifetime of the backfill window, `backfill_guard.starting_version_window_hours` if unset
+    pub window_hours: Option<u64>,
+    /// Publishes the versions again even if they were published, see `driver::publish_dedupe`
+    #[serde(default)]
+    pub force_republish: bool,
+}
+
+/// Moves a processor back to `version` and reprocesses and republishes everything since,
+/// without overwriting the rows already indexed
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RewindRequest {
+    pub processor: String,
+    pub version: u64,
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+/// Runs an enricher to the end of its table, resuming where it stopped
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct EnrichmentRequest {
+    pub enricher: String,
+    /// `enrichment.batch_size` if unset
+    pub batch_size: Option<i64>,
+    /// `enrichment.max_rows_per_sec` if unset
+    pub max_rows_per_sec: Option<u64>,
+}
+
+/// Deletes the change feed entries older than `retention_hours`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PruneRequest {
+    /// `change_feed.retention_hours` if unset
+    pub retention_hours: Option<u64>,
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdminResponse {
+    pub status: u16,
+    pub body: Value,
+}
+
+/// A request turned down, with the status to answer with
+#[derive(Debug)]
+struct Rejection {
+    status: u16,
+    message: String,
+}
+
+impl Rejection {
+    fn new(status: u16, message: impl std::fmt::Display) -> Self {
+        Self {
+            status,
+            message: message.to_string(),
+        }
+    }
+}
+
+pub struct Admin {
+    driver_config: DriverConfig,
+    connection_pool: PgDbPool,
+    /// Caller names by the sha256 of their token
+    callers: HashMap<String, String>,
+}
+
+/// Spawns the server if it's enabled. Only the first call in a process has an effect, so every
+/// processor runtime can call it.
+pub fn init(driver_config: &DriverConfig, connection_pool: PgDbPool) {
+    static STARTED: OnceCell<()> = OnceCell::new();
+    let config = &driver_config.admin;
+    if !config.enabled || STARTED.set(()).is_err() {
+        return;
+    }
+    if !driver_config.operations.enabled {
+        warn!("Operations aren't tracked, the admin server can't launch any");
+    }
+    let address = config.listen_address.clone();
+    let admin = Arc::new(Admin::new(driver_config.clone(), connection_pool));
+    tokio::spawn(async move {
+        info!(listen_address = address, "Starting the admin server");
+        if let Err(e) = Server::new(TcpListener::bind(address.as_str()))
+            .run(serve.data(admin))
+            .await
+        {
+            error!(listen_address = address, error = ?e, "Admin server stopped");
+        }
+    });
+}
+
+#[handler]
+async fn serve(request: &Request, body: Body, admin: Data<&Arc<Admin>>) -> Response {
+    let response = match body.into_vec().await {
+        Ok(body) => {
+            let admin = admin.0.clone();
+            let method = request.method().as_str().to_string();
+            let path = request.uri().path().to_string();
+            let authorization = request
+                .headers()
+                .get("authorization")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            // The handlers use blocking db connections
+            tokio::task::spawn_blocking(move || {
+                admin.handle(&method, &path, authorization.as_deref(), &body)
+            })
+            .await
+            .unwrap_or_else(|e| AdminResponse {
+                status: 500,
+                body: json!({ "error": e.to_string() }),
+            })
+        },
+        Err(e) => AdminResponse {
+            status: 400,
+            body: json!({ "error": e.to_string() }),
+        },
+    };
+    Response::builder()
+        .status(StatusCode::from_u16(response.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR))
+        .content_type("application/json")
+        .body(response.body.to_string())
+}
+
+impl Admin {
+    pub fn new(driver_config: DriverConfig, connection_pool: PgDbPool) -> Self {
+        let callers = driver_config
+            .admin
+            .callers
+            .iter()
+            .map(|(name, token_sha256)| (token_sha256.to_lowercase(), name.clone()))
+            .collect();
+        Self {
+            driver_config,
+            connection_pool,
+            callers,
+        }
+    }
+
+    /// Answers a request to `path` with `body`. Blocks on the db.
+    pub fn handle(
+        &self,
+        method: &str,
+        path: &str,
+        authorization: Option<&str>,
+        body: &[u8],
+    ) -> AdminResponse {
+        let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+        let action = match (method, &segments[..]) {
+            ("POST", ["operations", _, "cancel"]) => "cancel",
+            ("POST", ["operations", kind]) => *kind,
+            ("GET", ["operations", ..]) => "status",
+            ("GET", ["gaps", ..]) => "gaps",
+            _ => "unknown",
+        };
+        let response = match authenticate(&self.callers, authorization) {
+            None => AdminResponse {
+                status: 401,
+                body: json!({ "error": "Missing or unknown bearer token" }),
+            },
+            Some(caller) => match (method, &segments[..]) {
+                ("GET", ["operations"]) => AdminResponse {
+                    status: 200,
+                    body: json!({ "operations": operations::status() }),
+                },
+                ("GET", ["operations", operation_id]) => self.report(operation_id),
+                ("GET", ["gaps", from, to]) => self.gaps(from, to),
+                ("POST", ["operations", operation_id, "cancel"]) => {
+                    let result = self.cancel(operation_id);
+                    let parameters = json!({ "operation_id": operation_id });
+                    self.audited(&caller, action, parameters, result)
+                },
+                ("POST", ["operations", kind]) => {
+                    let result = self.launch(&caller, kind, body);
+                    let parameters = serde_json::from_slice(body).unwrap_or(Value::Null);
+                    self.audited(&caller, action, parameters, result)
+                },
+                _ => AdminResponse {
+                    status: 404,
+                    body: json!({ "error": format!("No route for {} {}", method, path) }),
+                },
+            },
+        };
+        ADMIN_REQUESTS
+            .with_label_values(&[action, &response.status.to_string()])
+            .inc();
+        response
+    }
+
+    /// Records the launch or cancel `result` and answers with it
+    fn audited(
+        &self,
+        caller: &str,
+        action: &str,
+        parameters: Value,
+        result: Result<String, Rejection>,
+    ) -> AdminResponse {
+        let entry = AdminAuditEntry {
+            caller: caller.to_string(),
+            action: action.to_string(),
+            parameters,
+            operation_id: result.as_ref().ok().cloned(),
+            outcome: if result.is_ok() {
+                "accepted"
+            } else {
+                "rejected"
+            }
+            .to_string(),
+            error: result
+                .as_ref()
+                .err()
+                .map(|rejection| rejection.message.clone()),
+        };
+        let recorded = self
+            .connection_pool
+            .get()
+            .map_err(anyhow::Error::from)
+            .and_then(|mut conn| {
+                execute_with_better_error(
+                    &mut conn,
+                    diesel::insert_into(admin_audit_log::table).values(&entry),
+                    None,
+                )?;
+                Ok(())
+            });
+        if let Err(e) = recorded {
+            error!(caller = caller, action = action, error = ?e, "Failed to audit an admin request");
+        }
+        info!(
+            caller = caller,
+            action = action,
+            operation_id = entry.operation_id,
+            error = entry.error,
+            "Admin request {}",
+            entry.outcome
+        );
+        match result {
+            Ok(operation_id) => AdminResponse {
+                status: 202,
+                body: json!({ "operation_id": operation_id }),
+            },
+            Err(rejection) => AdminResponse {
+                status: rejection.status,
+                body: json!({ "error": rejection.message }),
+            },
+        }
+    }
+
+    fn report(&self, operation_id: &str) -> AdminResponse {
+        if let Some(running) = operations::get(operation_id) {
+            return AdminResponse {
+                status: 200,
+                body: json!({ "state": "running", "operation": running }),
+            };
+        }
+        let last_event = self
+            .connection_pool
+            .get()
+            .map_err(anyhow::Error::from)
+            .and_then(|mut conn| Ok(operations::last_event(&mut conn, operation_id)?));
+        match last_event {
+            Ok(Some(event)) => AdminResponse {
+                status: 200,
+                body: json!({ "state": event.event, "operation": event }),
+            },
+            Ok(None) => AdminResponse {
+                status: 404,
+                body: json!({ "error": format!("Unknown operation {}", operation_id) }),
+            },
+            Err(e) => AdminResponse {
+                status: 500,
+                body: json!({ "error": format!("{:#}", e) }),
+            },
+        }
+    }
+
+    fn gaps(&self, from: &str, to: &str) -> AdminResponse {
+        let (Ok(from), Ok(to)) = (from.parse::<i64>(), to.parse::<i64>()) else {
+            return AdminResponse {
+                status: 400,
+                body: json!({ "error": "Versions must be integers" }),
+            };
+        };
+        if from < 0 || to < from {
+            return AdminResponse {
+                status: 400,
+                body: json!({ "error": "Expected 0 <= from <= to" }),
+            };
+        }
+        let gaps = self
+            .connection_pool
+            .get()
+            .map_err(anyhow::Error::from)
+            .and_then(|mut conn| Ok(version_guard::verify_gaps(&mut conn, from, to)?));
+        match gaps {
+            Ok(gaps) => AdminResponse {
+                status: 200,
+                body: json!({
+                    "from": from,
+                    "to": to,
+                    "missing_versions": gaps
+                        .iter()
+                        .map(|gap| gap.end_version - gap.start_version + 1)
+                        .sum::<i64>(),
+                    "gaps": gaps,
+                }),
+            },
+            Err(e) => AdminResponse {
+                status: 500,
+                body: json!({ "error": format!("{:#}", e) }),
+            },
+        }
+    }
+
+    fn cancel(&self, operation_id: &str) -> Result<String, Rejection> {
+        let Some(running) = operations::get(operation_id) else {
+            return Err(Rejection::new(
+                404,
+                format!("Operation {} isn't running", operation_id),
+            ));
+        };
+        if !CANCELLABLE.contains(&running.kind) {
+            return Err(Rejection::new(
+                409,
+                format!("A {} can't be cancelled", running.kind),
+            ));
+        }
+        operations::cancel(operation_id).map_err(|e| Rejection::new(404, e))?;
+        Ok(operation_id.to_string())
+    }
+
+    /// Launches an operation of `kind`, returns its id
+    fn launch(&self, caller: &str, kind: &str, body: &[u8]) -> Result<String, Rejection> {
+        let actor = format!("admin:{}", caller);
+        match kind {
+            "backfill" => {
+                let request = parse::<BackfillRequest>(body)?;
+                if matches!(request.end_version, Some(end) if end < request.start_version) {
+                    return Err(Rejection::new(400, "end_version is below start_version"));
+                }
+                let window_hours = request.window_hours.unwrap_or(
+                    self.driver_config
+                        .backfill_guard
+                        .starting_version_window_hours,
+                );
+                let parameters = serde_json::to_value(&request).unwrap();
+                self.launch_backfill(
+                    &request.processor,
+                    &actor,
+                    "backfill",
+                    parameters,
+                    BackfillCommand {
+                        start_version: request.start_version,
+                        end_version: request.end_version,
+                        window: Some(Duration::from_secs(window_hours * 3600)),
+                        actor: actor.clone(),
+                        operation_id: None,
+                        force_republish: request.force_republish,
+                    },
+                )
+            },
+            "rewind" => {
+                let request = parse::<RewindRequest>(body)?;
+                confirmed(request.confirm, kind)?;
+                let parameters = serde_json::to_value(&request).unwrap();
+                self.launch_backfill(
+                    &request.processor,
+                    &actor,
+                    "rewind",
+                    parameters,
+                    BackfillCommand {
+                        start_version: request.version,
+                        end_version: None,
+                        window: None,
+                        actor: actor.clone(),
+                        operation_id: None,
+                        force_republish: true,
+                    },
+                )
+            },
+            "enrichment" => {
+                let request = parse::<EnrichmentRequest>(body)?;
+                if !enrichment::is_supported(&request.enricher) {
+                    return Err(Rejection::new(
+                        400,
+                        format!("Enricher {} is unsupported", request.enricher),
+                    ));
+                }
+                let configured = &self.driver_config.enrichment;
+                let config = EnrichmentConfig {
+                    enabled: true,
+                    enrichers: vec![request.enricher.clone()],
+                    batch_size: request.batch_size.unwrap_or(configured.batch_size),
+                    max_rows_per_sec: request
+                        .max_rows_per_sec
+                        .unwrap_or(configured.max_rows_per_sec),
+                };
+                if config.batch_size <= 0 {
+                    return Err(Rejection::new(400, "batch_size must be positive"));
+                }
+                let operation = start(
+                    "enrichment",
+                    &actor,
+                    serde_json::to_value(&request).unwrap(),
+                )?;
+                let operation_id = operation.id().unwrap().to_string();
+                enrichment::launch(
+                    &request.enricher,
+                    &config,
+                    self.connection_pool.clone(),
+                    operation,
+                )
+                .map_err(|e| Rejection::new(400, e))?;
+                Ok(operation_id)
+            },
+            "prune" => {
+                let request = parse::<PruneRequest>(body)?;
+                confirmed(request.confirm, kind)?;
+                let retention_hours = request
+                    .retention_hours
+                    .unwrap_or(self.driver_config.change_feed.retention_hours);
+                let operation = start(
+                    "prune",
+                    &actor,
+                    json!({ "table": "change_feed", "retention_hours": retention_hours }),
+                )?;
+                let operation_id = operation.id().unwrap().to_string();
+                let connection_pool = self.connection_pool.clone();
+                tokio::task::spawn_blocking(move || {
+                    let retention = chrono::Duration::hours(retention_hours as i64);
+                    match change_feed::prune(&connection_pool, retention) {
+                        Ok(pruned) => operation.complete(pruned as u64),
+                        Err(err) => {
+                            operation.fail(operations::error_code(&err), format!("{:#}", err))
+                        },
+                    }
+                });
+                Ok(operation_id)
+            },
+            _ => Err(Rejection::new(
+                404,
+                format!("Unknown operation kind {}", kind),
+            )),
+        }
+    }
+
+    /// Starts the operation and hands it to the processor's lifecycle control
+    fn launch_backfill(
+        &self,
+        processor: &str,
+        actor: &str,
+        kind: &'static str,
+        parameters: Value,
+        mut command: BackfillCommand,
+    ) -> Result<String, Rejection> {
+        if !Indexer::handle()
+            .processors()
+            .iter()
+            .any(|name| name == processor)
+        {
+            return Err(Rejection::new(
+                404,
+                format!("Processor {} isn't running in this process", processor),
+            ));
+        }
+        let operation = start(kind, actor, parameters)?;
+        let operation_id = operation.id().unwrap().to_string();
+        command.operation_id = Some(operation_id.clone());
+        if let Err(e) = Indexer::handle().backfill_processor(processor, command) {
+            operation.fail("invalid", &e);
+            return Err(Rejection::new(404, e));
+        }
+        Ok(operation_id)
+    }
+}
+
+/// The name of the caller whose token `authorization` bears
+fn authenticate(callers: &HashMap<String, String>, authorization: Option<&str>) -> Option<String> {
+    let token = authorization?.strip_prefix("Bearer ")?.trim();
+    if token.is_empty() {
+        return None;
+    }
+    callers.get(&hash_str(token)).cloned()
+}
+
+fn parse<T: DeserializeOwned>(body: &[u8]) -> Result<T, Rejection> {
+    serde_json::from_slice(body).map_err(|e| Rejection::new(400, format!("Invalid body: {}", e)))
+}
+
+fn confirmed(confirm: bool, kind: &str) -> Result<(), Rejection> {
+    if confirm {
+        Ok(())
+    } else {
+        Err(Rejection::new(
+            400,
+            format!("A {} needs \"confirm\": true", kind),
+        ))
+    }
+}
+
+/// Starts an operation whose work is set once it runs
+fn start(kind: &'static str, actor: &str, parameters: Value) -> Result<Operation, Rejection> {
+    let operation = operations::start(kind, actor, parameters, 0, None);
+    match operation.id() {
+        Some(_) => Ok(operation),
+        None => Err(Rejection::new(
+            503,
+            "Operations aren't tracked, enable operations to launch them",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::new_db_pool, indexer::tailer::MIGRATIONS};
+    use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+    use diesel_migrations::MigrationHarness;
+
+    const PROCESSOR: &str = "admin_test_processor";
+
+    fn callers() -> HashMap<String, String> {
+        HashMap::from([("ops".to_string(), hash_str("secret"))])
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let by_hash = callers()
+            .into_iter()
+            .map(|(name, hash)| (hash, name))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(
+            authenticate(&by_hash, Some("Bearer secret")),
+            Some("ops".to_string())
+        );
+        assert_eq!(authenticate(&by_hash, Some("Bearer wrong")), None);
+        assert_eq!(authenticate(&by_hash, Some("secret")), None);
+        assert_eq!(authenticate(&by_hash, Some("Bearer ")), None);
+        assert_eq!(authenticate(&by_hash, None), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_backfill() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(&database_url).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        let mut driver_config = DriverConfig::default();
+        driver_config.operations.enabled = true;
+        driver_config.admin.callers = callers();
+        operations::init(&driver_config, 4, conn_pool.clone());
+        let admin = Admin::new(driver_config, conn_pool.clone());
+        let mut control = Indexer::handle().register(PROCESSOR);
+        let auth = Some("Bearer secret");
+        let post =
+            |path: &str, body: Value| admin.handle("POST", path, auth, body.to_string().as_bytes());
+
+        // Not authenticated, not launched
+        let response = admin.handle(
+            "POST",
+            "/operations/backfill",
+            Some("Bearer wrong"),
+            json!({ "processor": PROCESSOR, "start_version": 10 })
+                .to_string()
+                .as_bytes(),
+        );
+        assert_eq!(response.status, 401);
+        // Invalid bodies and unconfirmed rewinds are rejected
+        let response = post(
+            "/operations/backfill",
+            json!({ "processor": PROCESSOR, "start": 10 }),
+        );
+        assert_eq!(response.status, 400);
+        let response = post(
+            "/operations/rewind",
+            json!({ "processor": PROCESSOR, "version": 10 }),
+        );
+        assert_eq!(response.status, 400);
+        let response = post(
+            "/operations/backfill",
+            json!({ "processor": "not_running", "start_version": 10 }),
+        );
+        assert_eq!(response.status, 404);
+        assert!(control.take_backfill().is_none());
+
+        // Launched, the processor gets the command
+        let response = post(
+            "/operations/backfill",
+            json!({ "processor": PROCESSOR, "start_version": 10, "end_version": 109 }),
+        );
+        assert_eq!(response.status, 202);
+        let operation_id = response.body["operation_id"].as_str().unwrap().to_string();
+        let command = control.take_backfill().unwrap();
+        assert_eq!(command.operation_id.as_deref(), Some(operation_id.as_str()));
+        assert_eq!(
+            (command.start_version, command.end_version),
+            (10, Some(109))
+        );
+        assert_eq!(command.window, Some(Duration::from_secs(24 * 3600)));
+        assert_eq!(command.actor, "admin:ops");
+        assert!(control.take_backfill().is_none());
+
+        // What the runtime does with it
+        let operation = operations::attach(command.operation_id);
+        operation.set_work(0, Some(100));
+        operation.progress(40);
+        let path = format!("/operations/{}", operation_id);
+        let response = admin.handle("GET", &path, auth, b"");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body["state"], "running");
+        assert_eq!(response.body["operation"]["done"], 40);
+        assert_eq!(response.body["operation"]["progress_percent"], 40.0);
+        assert_eq!(response.body["operation"]["actor"], "admin:ops");
+
+        // Cancelled, the runtime notices and ends it
+        let response = post(&format!("{}/cancel", path), Value::Null);
+        assert_eq!(response.status, 202);
+        assert!(operation.is_cancelled());
+        let response = admin.handle("GET", &path, auth, b"");
+        assert_eq!(response.body["operation"]["cancel_requested"], true);
+        operation.cancelled();
+        let response = admin.handle("GET", &path, auth, b"");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body["state"], "cancelled");
+        assert_eq!(response.body["operation"]["done"], 40);
+        let response = post(&format!("{}/cancel", path), Value::Null);
+        assert_eq!(response.status, 404);
+
+        let audited = admin_audit_log::table
+            .filter(admin_audit_log::operation_id.eq(&operation_id))
+            .select((admin_audit_log::caller, admin_audit_log::action))
+            .order(admin_audit_log::id)
+            .load::<(String, String)>(&mut conn)
+            .unwrap();
+        assert_eq!(audited, vec![
+            ("ops".to_string(), "backfill".to_string()),
+            ("ops".to_string(), "cancel".to_string()),
+        ]);
+    }
+}
@@ -0,0 +1,61 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Hook for events an operator should hear about right away, e.g. an on-chain config change.
+//! Every alert is logged and counted; with a `webhook_url` it's also POSTed there as JSON, in
+//! the background so a slow endpoint never holds up a batch.
+
+use crate::{counters::ALERTS_FIRED, custom::driver::config::AlertsConfig};
+use aptos_logger::{error, warn};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use std::time::Duration;
+
+static WEBHOOK: OnceCell<Webhook> = OnceCell::new();
+
+struct Webhook {
+    client: reqwest::Client,
+    url: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Alert {
+    /// Short stable name, the metric label, e.g. `onchain_config_change`
+    pub kind: &'static str,
+    pub summary: String,
+    pub details: serde_json::Value,
+}
+
+/// Sets up the webhook of the process. Only the first call has an effect.
+pub fn init(config: &AlertsConfig) {
+    let url = match &config.webhook_url {
+        Some(url) => url.clone(),
+        None => return,
+    };
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_millis(config.timeout_millis))
+        .build()
+        .expect("Failed to build the alert webhook client");
+    WEBHOOK.set(Webhook { client, url }).ok();
+}
+
+pub fn fire(alert: Alert) {
+    ALERTS_FIRED.with_label_values(&[alert.kind]).inc();
+    warn!(
+        kind = alert.kind,
+        details = alert.details.to_string(),
+        "Alert: {}",
+        alert.summary
+    );
+    let webhook = match WEBHOOK.get() {
+        Some(webhook) => webhook,
+        None => return,
+    };
+    let request = webhook.client.post(&webhook.url).json(&alert);
+    tokio::spawn(async move {
+        let result = request.send().await.and_then(|res| res.error_for_status());
+        if let Err(err) = result {
+            error!(kind = alert.kind, error = ?err, "Failed to deliver alert to the webhook");
+        }
+    });
+}
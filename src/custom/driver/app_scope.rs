@@ -0,0 +1,478 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! App-scoped mode. With `app_scope` enabled, the tailer narrows every fetched batch, after
+//! redaction and before the priority lane and the processor see it, to the apps published at the
+//! configured module addresses:
+//! - events whose type is declared at a scoped address
+//! - modules published at a scoped address
+//! - resources stored at a scoped address, or whose type or one of its type arguments is declared
+//!   at one, e.g. a `0x1::coin::CoinStore<0xa11ce::token::Token>` of any account
+//! - table items of a handle reachable from in-scope resources and table items, or whose key or
+//!   value type is declared at a scoped address
+//!
+//! A transaction is kept if anything in it is in scope or it calls an entry function of a scoped
+//! module, and dropped otherwise, which drops most block metadata and state checkpoint
+//! transactions. What's left out is counted in `indexer_app_scope_skipped_count` by kind. The
+//! write set of a kept transaction is narrowed too, so `write_set_change_index` counts the
+//! changes in scope only.
+//!
+//! Table handles are found in the data of in-scope resources and table items, as any
+//! `{"handle": "0x.."}` object, which covers `Table`, `TableWithLength` and the buckets of a
+//! `SmartTable`. A handle is in scope from the transaction it was found in onwards, so items
+//! written to it earlier in the batch are skipped. The handles go to `app_scope_table_handles`,
+//! which keeps them across restarts and is shared by every processor, and batches are narrowed in
+//! version order under the fetcher lock.
+//!
+//! Batches keep the versions they spanned before narrowing, so the watermark and
+//! `processor_statuses` move over the versions left out, and the `versions_contiguous` rule of
+//! the default processor is dropped.
+//!
+//! `app_scope_addresses` records the addresses each processor is scoped to. On start, addresses
+//! added since the processor last ran are backfilled from `backfill_from_version`: the processor
+//! starts there, which registers a backfill of the versions below the watermark, and the
+//! addresses are marked backfilled once it completes. The backfill reprocesses the whole scope,
+//! not only the new addresses. Addresses added by a reload are backfilled at the next start, and
+//! nothing is backfilled on the first start.
+
+use crate::{
+    counters::APP_SCOPE_SKIPPED,
+    custom::driver::config::AppScopeConfig,
+    database::{execute_with_better_error, get_chunks, write_transaction, PgDbPool},
+    models::app_scope::{AppScopeAddress, AppScopeTableHandle},
+    schema::{app_scope_addresses, app_scope_table_handles},
+    util::standardize_address,
+};
+use aptos_api_types::{
+    MultisigTransactionPayload, Transaction, TransactionPayload, WriteSetChange,
+};
+use diesel::{dsl::not, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use field_count::FieldCount;
+use serde_json::Value;
+use std::{
+    collections::{BTreeSet, HashSet},
+    sync::Mutex,
+};
+
+pub struct AppScope {
+    addresses: HashSet<String>,
+    conn_pool: PgDbPool,
+    /// Loaded from `app_scope_table_handles` with the first batch
+    handles: Mutex<Option<HashSet<String>>>,
+}
+
+impl AppScope {
+    pub fn new(config: &AppScopeConfig, conn_pool: PgDbPool) -> Self {
+        Self {
+            addresses: config
+                .addresses
+                .iter()
+                .map(|address| standardize_address(address))
+                .collect(),
+            conn_pool,
+            handles: Mutex::new(None),
+        }
+    }
+
+    /// Drops what's outside of the scope from the transactions, then the transactions with
+    /// nothing left in scope. The table handles found are stored before returning.
+    pub fn scope(&self, transactions: Vec<Transaction>) -> anyhow::Result<Vec<Transaction>> {
+        let mut guard = self.handles.lock().unwrap();
+        if guard.is_none() {
+            let mut conn = self.conn_pool.get()?;
+            let stored = app_scope_table_handles::table
+                .select(app_scope_table_handles::handle)
+                .load::<String>(&mut conn)?;
+            *guard = Some(stored.into_iter().collect());
+        }
+        let handles = guard.as_mut().unwrap();
+
+        let mut discovered = vec![];
+        let fetched = transactions.len();
+        let transactions = transactions
+            .into_iter()
+            .filter_map(|mut transaction| {
+                self.scope_transaction(&mut transaction, handles, &mut discovered)
+                    .then_some(transaction)
+            })
+            .collect::<Vec<_>>();
+        APP_SCOPE_SKIPPED
+            .with_label_values(&["transaction"])
+            .inc_by((fetched - transactions.len()) as u64);
+
+        if !discovered.is_empty() {
+            let inserted = self
+                .conn_pool
+                .get()
+                .map_err(anyhow::Error::from)
+                .and_then(|mut conn| Ok(insert_table_handles(&mut conn, &discovered)?));
+            if let Err(err) = inserted {
+                // Reloaded with the next batch, without the handles that weren't stored
+                *guard = None;
+                return Err(err.context("Failed to store the app scope's table handles"));
+            }
+        }
+        Ok(transactions)
+    }
+
+    /// Narrows the events and write set of the transaction, true if it's kept
+    fn scope_transaction(
+        &self,
+        transaction: &mut Transaction,
+        handles: &mut HashSet<String>,
+        discovered: &mut Vec<AppScopeTableHandle>,
+    ) -> bool {
+        let calls_scoped_function = self.calls_scoped_function(transaction);
+        let version = transaction.version().unwrap_or_default() as i64;
+        let (events, changes) = match transaction {
+            Transaction::UserTransaction(txn) => (&mut txn.events, &mut txn.info.changes),
+            Transaction::BlockMetadataTransaction(txn) => (&mut txn.events, &mut txn.info.changes),
+            Transaction::GenesisTransaction(txn) => (&mut txn.events, &mut txn.info.changes),
+            _ => return false,
+        };
+
+        let fetched_events = events.len();
+        events.retain(|event| {
+            type_address(&event.typ.to_string()).map_or(false, |address| self.contains(&address))
+        });
+        APP_SCOPE_SKIPPED
+            .with_label_values(&["event"])
+            .inc_by((fetched_events - events.len()) as u64);
+
+        // A handle found in a change can bring in changes already passed over
+        let mut keep = vec![false; changes.len()];
+        let mut found_handle = true;
+        while found_handle {
+            found_handle = false;
+            for (index, change) in changes.iter().enumerate() {
+                if keep[index] || !self.change_in_scope(change, handles) {
+                    continue;
+                }
+                keep[index] = true;
+                let (discovered_from, data) = match change {
+                    WriteSetChange::WriteResource(resource) => (
+                        resource.data.typ.to_string(),
+                        serde_json::to_value(&resource.data.data).ok(),
+                    ),
+                    WriteSetChange::WriteTableItem(item) => (
+                        standardize_address(&item.handle.to_string()),
+                        item.data.as_ref().map(|data| data.value.clone()),
+                    ),
+                    _ => continue,
+                };
+                let mut found = vec![];
+                if let Some(data) = &data {
+                    find_handles(data, &mut found);
+                }
+                for handle in found {
+                    if handles.insert(handle.clone()) {
+                        found_handle = true;
+                        discovered.push(AppScopeTableHandle {
+                            handle,
+                            discovered_from: discovered_from.clone(),
+                            transaction_version: version,
+                        });
+                    }
+                }
+            }
+        }
+        let mut keep = keep.into_iter();
+        let fetched_changes = changes.len();
+        changes.retain(|_| keep.next().unwrap());
+        APP_SCOPE_SKIPPED
+            .with_label_values(&["write_set_change"])
+            .inc_by((fetched_changes - changes.len()) as u64);
+
+        calls_scoped_function || !events.is_empty() || !changes.is_empty()
+    }
+
+    fn calls_scoped_function(&self, transaction: &Transaction) -> bool {
+        let Transaction::UserTransaction(txn) = transaction else {
+            return false;
+        };
+        let function = match &txn.request.payload {
+            TransactionPayload::EntryFunctionPayload(payload) => &payload.function,
+            TransactionPayload::MultisigPayload(payload) => match &payload.transaction_payload {
+                Some(MultisigTransactionPayload::EntryFunctionPayload(payload)) => {
+                    &payload.function
+                },
+                _ => return false,
+            },
+            _ => return false,
+        };
+        self.contains(&function.module.address.to_string())
+    }
+
+    fn change_in_scope(&self, change: &WriteSetChange, handles: &HashSet<String>) -> bool {
+        match change {
+            WriteSetChange::WriteModule(module) => self.contains(&module.address.to_string()),
+            WriteSetChange::DeleteModule(module) => self.contains(&module.address.to_string()),
+            WriteSetChange::WriteResource(resource) => {
+                self.contains(&resource.address.to_string())
+                    || self.type_in_scope(&resource.data.typ.to_string())
+            },
+            WriteSetChange::DeleteResource(resource) => {
+                self.contains(&resource.address.to_string())
+                    || self.type_in_scope(&resource.resource.to_string())
+            },
+            WriteSetChange::WriteTableItem(item) => {
+                handles.contains(&standardize_address(&item.handle.to_string()))
+                    || item.data.as_ref().map_or(false, |data| {
+                        self.type_in_scope(&data.key_type) || self.type_in_scope(&data.value_type)
+                    })
+            },
+            WriteSetChange::DeleteTableItem(item) => {
+                handles.contains(&standardize_address(&item.handle.to_string()))
+            },
+        }
+    }
+
+    fn contains(&self, address: &str) -> bool {
+        is_address(address) && self.addresses.contains(&standardize_address(address))
+    }
+
+    fn type_in_scope(&self, type_str: &str) -> bool {
+        type_in_scope(&self.addresses, type_str)
+    }
+}
+
+/// Addresses added to the scope of a processor since it last ran, which it backfills
+pub struct ScopeExpansion {
+    processor: String,
+    addresses: Vec<String>,
+    conn_pool: PgDbPool,
+}
+
+impl ScopeExpansion {
+    /// Records the scope of `processor`, and returns its addresses that weren't backfilled yet.
+    /// On the first start every address counts as backfilled. Addresses no longer configured are
+    /// forgotten, so they're backfilled again if they're added back.
+    pub fn detect(
+        processor: &str,
+        config: &AppScopeConfig,
+        watermark: u64,
+        conn_pool: PgDbPool,
+    ) -> anyhow::Result<Option<Self>> {
+        let addresses = config
+            .addresses
+            .iter()
+            .map(|address| standardize_address(address))
+            .collect::<BTreeSet<_>>();
+        let mut conn = conn_pool.get()?;
+        let pending = write_transaction(
+            &mut conn,
+            |conn| -> Result<Vec<String>, diesel::result::Error> {
+                let stored = app_scope_addresses::table
+                    .filter(app_scope_addresses::processor.eq(processor))
+                    .count()
+                    .get_result::<i64>(conn)?;
+                diesel::delete(
+                    app_scope_addresses::table
+                        .filter(app_scope_addresses::processor.eq(processor))
+                        .filter(not(app_scope_addresses::address.eq_any(&addresses))),
+                )
+                .execute(conn)?;
+                let rows = addresses
+                    .iter()
+                    .map(|address| AppScopeAddress {
+                        processor: processor.to_string(),
+                        address: address.clone(),
+                        backfilled: stored == 0,
+                        added_at_version: watermark as i64,
+                    })
+                    .collect::<Vec<_>>();
+                for (start_ind, end_ind) in get_chunks(rows.len(), AppScopeAddress::field_count()) {
+                    execute_with_better_error(
+                        conn,
+                        diesel::insert_into(app_scope_addresses::table)
+                            .values(&rows[start_ind..end_ind])
+                            .on_conflict((
+                                app_scope_addresses::processor,
+                                app_scope_addresses::address,
+                            ))
+                            .do_nothing(),
+                        None,
+                    )?;
+                }
+                app_scope_addresses::table
+                    .filter(app_scope_addresses::processor.eq(processor))
+                    .filter(app_scope_addresses::backfilled.eq(false))
+                    .select(app_scope_addresses::address)
+                    .order(app_scope_addresses::address)
+                    .load::<String>(conn)
+            },
+        )?;
+        Ok((!pending.is_empty()).then(|| Self {
+            processor: processor.to_string(),
+            addresses: pending,
+            conn_pool,
+        }))
+    }
+
+    pub fn addresses(&self) -> &[String] {
+        &self.addresses
+    }
+
+    /// Marks the addresses backfilled
+    pub fn complete(&self) -> anyhow::Result<()> {
+        let mut conn = self.conn_pool.get()?;
+        diesel::update(
+            app_scope_addresses::table
+                .filter(app_scope_addresses::processor.eq(&self.processor))
+                .filter(app_scope_addresses::address.eq_any(&self.addresses)),
+        )
+        .set(app_scope_addresses::backfilled.eq(true))
+        .execute(&mut conn)?;
+        Ok(())
+    }
+}
+
+fn insert_table_handles(
+    conn: &mut PgConnection,
+    items_to_insert: &[AppScopeTableHandle],
+) -> Result<(), diesel::result::Error> {
+    let chunks = get_chunks(items_to_insert.len(), AppScopeTableHandle::field_count());
+    for (start_ind, end_ind) in chunks {
+        execute_with_better_error(
+            conn,
+            diesel::insert_into(app_scope_table_handles::table)
+                .values(&items_to_insert[start_ind..end_ind])
+                .on_conflict(app_scope_table_handles::handle)
+                .do_nothing(),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+/// Whether the type or one of its type arguments is declared at one of the `addresses`
+pub(crate) fn type_in_scope(addresses: &HashSet<String>, type_str: &str) -> bool {
+    type_str
+        .split(|c: char| matches!(c, '<' | '>' | ',' | ' '))
+        .filter_map(type_address)
+        .any(|address| addresses.contains(&address))
+}
+
+/// The standardized address of `0x..::module::Name`
+fn type_address(type_str: &str) -> Option<String> {
+    let (address, _) = type_str.trim().split_once("::")?;
+    is_address(address).then(|| standardize_address(address))
+}
+
+pub(crate) fn is_address(value: &str) -> bool {
+    value.len() > 2
+        && value.len() <= 66
+        && value.starts_with("0x")
+        && value[2..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// The handles of every `{"handle": "0x.."}` object in `value`, however deep
+fn find_handles(value: &Value, handles: &mut Vec<String>) {
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::String(handle)) = object.get("handle") {
+                if is_address(handle) {
+                    handles.push(standardize_address(handle));
+                }
+            }
+            object
+                .values()
+                .for_each(|value| find_handles(value, handles));
+        },
+        Value::Array(values) => values.iter().for_each(|value| find_handles(value, handles)),
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::new_db_pool, indexer::tailer::MIGRATIONS};
+    use diesel_migrations::MigrationHarness;
+    use serde_json::json;
+
+    fn config(addresses: &[&str]) -> AppScopeConfig {
+        AppScopeConfig {
+            enabled: true,
+            addresses: addresses
+                .iter()
+                .map(|address| address.to_string())
+                .collect(),
+            backfill_from_version: 0,
+        }
+    }
+
+    #[test]
+    fn test_find_handles() {
+        let data = json!({
+            "players": { "handle": "0xab" },
+            "buckets": { "inner": { "handle": "0x00cd" }, "length": "2" },
+            "names": [{ "handle": "not a handle" }, { "handle": "0xef" }],
+        });
+        let mut handles = vec![];
+        find_handles(&data, &mut handles);
+        handles.sort();
+        assert_eq!(handles, vec![
+            standardize_address("0xab"),
+            standardize_address("0xcd"),
+            standardize_address("0xef"),
+        ]);
+    }
+
+    #[test]
+    fn test_type_in_scope() {
+        let addresses = HashSet::from([standardize_address("0xa11ce")]);
+        let in_scope = |type_str: &str| type_in_scope(&addresses, type_str);
+        assert!(in_scope("0xa11ce::game::Player"));
+        assert!(in_scope(&format!(
+            "{}::game::Player",
+            standardize_address("0xa11ce")
+        )));
+        assert!(in_scope("0x1::coin::CoinStore<0xa11ce::token::Token>"));
+        assert!(in_scope(
+            "0x1::table::Table<u64, vector<0xa11ce::game::Score>>"
+        ));
+        assert!(!in_scope(
+            "0x1::coin::CoinStore<0x1::aptos_coin::AptosCoin>"
+        ));
+        assert!(!in_scope("u64"));
+        assert_eq!(type_address("vector<u8>"), None);
+    }
+
+    #[test]
+    fn test_scope_expansion() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(&database_url).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        diesel::delete(
+            app_scope_addresses::table.filter(app_scope_addresses::processor.eq("app_scope_test")),
+        )
+        .execute(&mut conn)
+        .unwrap();
+
+        // Nothing to backfill on the first start
+        let detect = |addresses: &[&str]| {
+            ScopeExpansion::detect("app_scope_test", &config(addresses), 100, conn_pool.clone())
+                .unwrap()
+        };
+        assert!(detect(&["0xa"]).is_none());
+        assert!(detect(&["0xa"]).is_none());
+
+        // An added address stays pending until its backfill completes
+        let expansion = detect(&["0xa", "0xb"]).unwrap();
+        assert_eq!(expansion.addresses(), &[standardize_address("0xb")]);
+        assert!(detect(&["0xa", "0xb"]).is_some());
+        expansion.complete().unwrap();
+        assert!(detect(&["0xa", "0xb"]).is_none());
+
+        // A removed address is backfilled again once added back
+        assert!(detect(&["0xa"]).is_none());
+        assert_eq!(detect(&["0xa", "0xb"]).unwrap().addresses(), &[
+            standardize_address("0xb")
+        ]);
+    }
+}
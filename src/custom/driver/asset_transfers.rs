@@ -0,0 +1,111 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Transfers across asset standards. `custom_coin_processor` derives from each successful user
+//! transaction who sent what to whom, whether the asset is a coin, a fungible asset, a token v1 or
+//! a token v2, and writes it to `asset_transfers` and the `AssetTransfer` topic, one row per
+//! transfer numbered by `transfer_index` within the transaction. Failed transactions only pay gas,
+//! and gas fees aren't transfers, so neither gives a row.
+//!
+//! Withdrawals and deposits of the same asset are paired in event order, see
+//! `AssetTransfer::from_transaction`; a mint or burn has no sender or no recipient, and so does a
+//! withdrawal or deposit nothing in the transaction pairs with, e.g. an asset going into a
+//! contract's custody, which is then `unmatched_withdrawal` or `unmatched_deposit`. A coin paired
+//! with a fungible asset emits events for both, the coin's transfer is kept. A fungible asset
+//! store is only resolved to its owner if the transaction writes its object, a withdrawal or
+//! deposit from a store it doesn't write is left out.
+
+use crate::{
+    custom::driver::{config::AssetTransfersConfig, publisher::Publisher},
+    database::{execute_with_better_error, get_chunks, write_transaction, PgPoolConnection},
+    models::{
+        asset_transfers::{AssetTransfer, TransferLeg},
+        coin_models::coin_activities::CoinActivity,
+    },
+    schema,
+};
+use aptos_api_types::Transaction;
+use diesel::PgConnection;
+use field_count::FieldCount;
+use std::collections::HashMap;
+
+pub const ASSET_TRANSFER_MODEL: &str = "AssetTransfer";
+
+pub struct AssetTransfers {
+    enabled: bool,
+}
+
+impl AssetTransfers {
+    pub fn new(config: &AssetTransfersConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+        }
+    }
+
+    /// The transfers of the batch, given the coin activities the processor parsed from it
+    pub fn derive(
+        &self,
+        transactions: &[Transaction],
+        coin_activities: &[CoinActivity],
+    ) -> Vec<AssetTransfer> {
+        if !self.enabled {
+            return vec![];
+        }
+        let mut coin_legs: HashMap<i64, Vec<TransferLeg>> = HashMap::new();
+        for activity in coin_activities {
+            if let Some(leg) = TransferLeg::from_coin_activity(activity) {
+                coin_legs
+                    .entry(activity.transaction_version)
+                    .or_default()
+                    .push(leg);
+            }
+        }
+        transactions
+            .iter()
+            .flat_map(|transaction| {
+                let legs = transaction
+                    .version()
+                    .and_then(|version| coin_legs.remove(&(version as i64)))
+                    .unwrap_or_default();
+                AssetTransfer::from_transaction(transaction, legs)
+            })
+            .collect()
+    }
+
+    /// Writes the transfers and publishes them once written
+    pub fn record(
+        &self,
+        conn: &mut PgPoolConnection,
+        publisher: &Publisher,
+        transfers: &[AssetTransfer],
+    ) -> anyhow::Result<()> {
+        if transfers.is_empty() {
+            return Ok(());
+        }
+        write_transaction(conn, |pg_conn| insert_asset_transfers(pg_conn, transfers))?;
+        if publisher.publishes(ASSET_TRANSFER_MODEL) {
+            publisher.try_send(ASSET_TRANSFER_MODEL, transfers)?;
+        }
+        Ok(())
+    }
+}
+
+fn insert_asset_transfers(
+    conn: &mut PgConnection,
+    items_to_insert: &[AssetTransfer],
+) -> Result<(), diesel::result::Error> {
+    use schema::asset_transfers::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), AssetTransfer::field_count());
+    for (start_ind, end_ind) in chunks {
+        execute_with_better_error(
+            conn,
+            diesel::insert_into(schema::asset_transfers::table)
+                .values(&items_to_insert[start_ind..end_ind])
+                .on_conflict((transaction_version, transfer_index))
+                .do_nothing(),
+            None,
+        )?;
+    }
+    Ok(())
+}
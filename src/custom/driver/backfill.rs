@@ -0,0 +1,193 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Backfill mode: a process that runs one processor over a closed range of versions and exits,
+//! e.g. to republish a range after a parsing fix while another process keeps following the
+//! ledger. See `runtime::bootstrap_backfill`.
+//!
+//! The fetcher stops at `end_version` and batches are sized by the indexer's `batch_size` as
+//! usual. Progress is saved after every round in `processor_status` under
+//! `<processor>@<start_version>-<end_version>`, apart from the processor's own watermark, so a
+//! backfill that's killed resumes where it was when started again with the same range. A backfill
+//! window is registered for what's left of the range, so its rows overwrite the ones indexed
+//! before, see `driver::backfill_guard`.
+//!
+//! With `--dry-run` the processor runs over the range as usual, but its writes are made in a
+//! transaction that's rolled back (`database::new_rollback_db_pool`) and its messages are
+//! published to memory (`replay::in_memory_processor`), so nothing is kept, published or
+//! checkpointed. The rows and messages the batches produced are counted, and the batches the
+//! processor failed or panicked on.
+
+use crate::{
+    custom::processors::{custom_default_processor, processor_registry},
+    indexer::processing_result::ProcessingResult,
+};
+use anyhow::{bail, Result};
+use clap::Parser;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Flags of a backfill run, for the binary embedding the indexer to take
+#[derive(Clone, Debug, Parser)]
+pub struct BackfillArgs {
+    /// Processor to run over the range
+    #[clap(long, default_value = custom_default_processor::NAME)]
+    pub processor: String,
+    /// First version to process
+    #[clap(long)]
+    pub start_version: u64,
+    /// Last version to process, inclusive
+    #[clap(long)]
+    pub end_version: u64,
+    /// Process the range without keeping what's written, publishing or saving progress
+    #[clap(long)]
+    pub dry_run: bool,
+    /// Publish the range even where the processor published it already, see
+    /// `driver::publish_dedupe`
+    #[clap(long)]
+    pub force_republish: bool,
+}
+
+impl BackfillArgs {
+    /// Checked before anything is started
+    pub fn validate(&self) -> Result<()> {
+        if self.end_version < self.start_version {
+            bail!(
+                "end_version {} is below start_version {}",
+                self.end_version,
+                self.start_version
+            );
+        }
+        processor_registry::validate_names(std::slice::from_ref(&self.processor))
+    }
+
+    /// Key of the range's progress in `processor_status`
+    pub fn watermark_key(&self) -> String {
+        format!(
+            "{}@{}-{}",
+            self.processor, self.start_version, self.end_version
+        )
+    }
+
+    /// Version to start at given the range's saved progress, the version after the last one
+    /// processed. `None` if the whole range was processed already.
+    pub fn resume_version(&self, watermark: Option<u64>) -> Option<u64> {
+        let version = watermark.unwrap_or(0).max(self.start_version);
+        (version <= self.end_version).then_some(version)
+    }
+
+    /// Versions in the range
+    pub fn versions(&self) -> u64 {
+        self.end_version - self.start_version + 1
+    }
+}
+
+/// What a dry run derived from the range
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DryRunSummary {
+    pub batches: usize,
+    pub versions: u64,
+    /// As counted by the processor, see `RowCounts`
+    pub rows: BTreeMap<&'static str, usize>,
+    pub messages: usize,
+    /// Batches the processor returned an error or panicked on
+    pub failed_batches: usize,
+}
+
+impl DryRunSummary {
+    pub fn add(&mut self, versions: u64, result: &ProcessingResult, messages: usize) {
+        self.batches += 1;
+        self.versions += versions;
+        for (entity, rows) in result.counts.entries() {
+            *self.rows.entry(entity).or_default() += rows;
+        }
+        self.messages += messages;
+    }
+
+    pub fn add_failed(&mut self, versions: u64) {
+        self.batches += 1;
+        self.versions += versions;
+        self.failed_batches += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{custom::processors::custom_coin_processor, indexer::processing_result::RowCounts};
+
+    fn args(start_version: u64, end_version: u64) -> BackfillArgs {
+        BackfillArgs::parse_from([
+            "backfill",
+            "--start-version",
+            &start_version.to_string(),
+            "--end-version",
+            &end_version.to_string(),
+        ])
+    }
+
+    #[test]
+    fn test_validate() {
+        let range = args(120_000_000, 125_000_000);
+        assert_eq!(range.processor, custom_default_processor::NAME);
+        assert!(!range.dry_run);
+        range.validate().unwrap();
+        assert_eq!(range.versions(), 5_000_001);
+        args(7, 7).validate().unwrap();
+
+        assert_eq!(
+            args(10, 9).validate().unwrap_err().to_string(),
+            "end_version 9 is below start_version 10"
+        );
+        let unknown = BackfillArgs {
+            processor: "custom_processor_backfill".to_string(),
+            ..args(0, 9)
+        };
+        assert!(unknown
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .starts_with("Unknown processor \"custom_processor_backfill\""));
+    }
+
+    #[test]
+    fn test_resume_version() {
+        let range = BackfillArgs::parse_from([
+            "backfill",
+            "--processor",
+            custom_coin_processor::NAME,
+            "--start-version",
+            "100",
+            "--end-version",
+            "199",
+            "--dry-run",
+        ]);
+        assert!(range.dry_run);
+        assert_eq!(range.watermark_key(), "custom_coin_processor@100-199");
+        assert_eq!(range.resume_version(None), Some(100));
+        assert_eq!(range.resume_version(Some(150)), Some(150));
+        assert_eq!(range.resume_version(Some(199)), Some(199));
+        assert_eq!(range.resume_version(Some(200)), None);
+    }
+
+    #[test]
+    fn test_dry_run_summary() {
+        let result =
+            ProcessingResult::new(custom_default_processor::NAME, 10, 11).with_counts(RowCounts {
+                transactions: 2,
+                events: 3,
+                ..RowCounts::default()
+            });
+        let mut summary = DryRunSummary::default();
+        summary.add(2, &result, 5);
+        summary.add(2, &result, 5);
+        summary.add_failed(3);
+        assert_eq!(summary.batches, 3);
+        assert_eq!(summary.versions, 7);
+        assert_eq!(summary.rows["transactions"], 4);
+        assert_eq!(summary.rows["events"], 6);
+        assert_eq!(summary.rows["table_items"], 0);
+        assert_eq!(summary.messages, 10);
+        assert_eq!(summary.failed_batches, 1);
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Guardrail against reprocessing overwriting good rows. Upserts that update conflicting rows
+//! (`ON CONFLICT DO UPDATE`) only update them for batches inside a backfill window registered in
+//! `backfill_windows` for the processor. Processors take the `policy` of each batch and pass it
+//! to their upserts, `database::CurrentRowUpsert` and `database::GuardedUpsert`. Outside of a
+//! window, conflicting rows are skipped and counted in `indexer_backfill_guard_skipped_rows_count`;
+//! current tables still take rows that are strictly newer than the stored ones, which is what
+//! live processing writes, and tables keeping the earliest version of a row strictly earlier ones.
+//! Windows are re-read every `refresh_interval_secs` and checked against `expires_at` on every
+//! batch, so an expired window stops permitting overwrites without a restart.
+
+use crate::{
+    custom::driver::config::BackfillGuardConfig,
+    database::{execute_with_better_error, PgDbPool},
+    models::backfill_windows::{BackfillWindow, BackfillWindowQuery},
+    schema::backfill_windows,
+};
+use aptos_logger::{error, info};
+use once_cell::sync::OnceCell;
+use std::{sync::RwLock, time::Duration};
+
+static GUARD: OnceCell<BackfillGuard> = OnceCell::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Conflicting rows are updated as the upsert says
+    Overwrite,
+    /// Conflicting rows are left as they are, unless a current table row is strictly newer, or
+    /// strictly earlier for tables keeping the earliest version
+    Skip,
+}
+
+struct BackfillGuard {
+    allow_unguarded_overwrites: bool,
+    windows: RwLock<Vec<BackfillWindowQuery>>,
+}
+
+/// Loads the windows and spawns their refresh. Only the first call in a process has an effect,
+/// so every processor runtime can call it. Without it nothing is guarded.
+pub fn init(config: &BackfillGuardConfig, connection_pool: PgDbPool) {
+    if GUARD.get().is_some() {
+        return;
+    }
+    let guard = BackfillGuard {
+        allow_unguarded_overwrites: config.allow_unguarded_overwrites,
+        windows: RwLock::new(load_windows(&connection_pool).unwrap_or_default()),
+    };
+    if GUARD.set(guard).is_err() {
+        return;
+    }
+    if config.allow_unguarded_overwrites {
+        info!("Backfill guard disabled, upserts overwrite conflicting rows at any version");
+        return;
+    }
+    let interval = Duration::from_secs(config.refresh_interval_secs.max(1));
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            let pool = connection_pool.clone();
+            if let Ok(Some(windows)) =
+                tokio::task::spawn_blocking(move || load_windows(&pool)).await
+            {
+                *GUARD.get().unwrap().windows.write().unwrap() = windows;
+            }
+        }
+    });
+}
+
+fn load_windows(connection_pool: &PgDbPool) -> Option<Vec<BackfillWindowQuery>> {
+    let windows = connection_pool
+        .get()
+        .map_err(anyhow::Error::from)
+        .and_then(|mut conn| BackfillWindowQuery::get_unexpired(&mut conn).map_err(Into::into));
+    match windows {
+        Ok(windows) => Some(windows),
+        Err(err) => {
+            error!(error = ?err, "Failed to load backfill windows, keeping the loaded ones");
+            None
+        },
+    }
+}
+
+/// Permits `processor` to overwrite rows for versions `start_version..=end_version` for `ttl`
+pub fn register_window(
+    connection_pool: &PgDbPool,
+    processor: &str,
+    start_version: i64,
+    end_version: i64,
+    created_by: &str,
+    ttl: Duration,
+) -> anyhow::Result<()> {
+    let expires_at = chrono::Utc::now().naive_utc() + chrono::Duration::from_std(ttl)?;
+    let mut conn = connection_pool.get()?;
+    execute_with_better_error(
+        &mut conn,
+        diesel::insert_into(backfill_windows::table).values(&BackfillWindow {
+            processor: processor.to_string(),
+            start_version,
+            end_version,
+            created_by: created_by.to_string(),
+            expires_at,
+        }),
+        None,
+    )?;
+    info!(
+        processor_name = processor,
+        start_version = start_version,
+        end_version = end_version,
+        created_by = created_by,
+        expires_at = expires_at.to_string(),
+        "Registered backfill window"
+    );
+    if let (Some(guard), Some(windows)) = (GUARD.get(), load_windows(connection_pool)) {
+        *guard.windows.write().unwrap() = windows;
+    }
+    Ok(())
+}
+
+/// Policy for a batch of `processor`: overwrite if the whole batch is inside an unexpired window
+pub fn policy(processor: &str, start_version: u64, end_version: u64) -> OverwritePolicy {
+    let guard = match GUARD.get() {
+        Some(guard) if !guard.allow_unguarded_overwrites => guard,
+        _ => return OverwritePolicy::Overwrite,
+    };
+    let in_window = guard
+        .windows
+        .read()
+        .unwrap()
+        .iter()
+        .any(|window| window.contains(processor, start_version as i64, end_version as i64));
+    if in_window {
+        OverwritePolicy::Overwrite
+    } else {
+        OverwritePolicy::Skip
+    }
+}
@@ -0,0 +1,209 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Blocking the publisher while its producer's queue is full, instead of failing messages Kafka
+//! can't take yet. Before every message is produced, the messages the producer holds that Kafka
+//! didn't ack yet are counted. Above `high_watermark` the publisher blocks until they're down to
+//! `low_watermark`, so that a slow cluster slows the processor down rather than filling the queue
+//! and failing batches. A queue that doesn't drain within `max_wait_millis` fails the message
+//! with `PublishError::QueueFull`, which is retried like any transient error, see
+//! `driver::batch_retry`.
+//!
+//! The publisher is synchronous, so a blocked publisher blocks the processor's task. The wait
+//! moves the tokio worker's other tasks elsewhere first, see `serialization::in_place`.
+//!
+//! The depth is kept in `indexer_publisher_queue_depth` and every wait counted in
+//! `indexer_publisher_backpressure_waits_count`, by processor, or `control` for the health state
+//! changes and operation events, which the processors share.
+
+use crate::{
+    counters::{PUBLISHER_BACKPRESSURE_WAITS, PUBLISHER_QUEUE_DEPTH},
+    custom::driver::{
+        config::PublisherBackpressureConfig, publish_retry::PublishError, serialization,
+    },
+};
+use std::{
+    collections::HashMap,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often a blocked publisher counts the messages again
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+#[derive(Clone, Debug)]
+pub struct Backpressure {
+    enabled: bool,
+    high_watermark: usize,
+    low_watermark: usize,
+    max_wait: Duration,
+    /// Label of the depth metrics
+    processor_name: String,
+}
+
+impl Backpressure {
+    /// For a producer of `kafka`, failing if the watermarks are invalid
+    pub fn new(
+        config: &PublisherBackpressureConfig,
+        kafka: &HashMap<String, String>,
+        processor_name: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let (high_watermark, low_watermark) = config.watermarks(kafka)?;
+        Ok(Self {
+            enabled: config.enabled,
+            high_watermark,
+            low_watermark,
+            max_wait: Duration::from_millis(config.max_wait_millis),
+            processor_name: processor_name.unwrap_or("control").to_string(),
+        })
+    }
+
+    /// Returns once `depth`, the messages not acked yet, is below the high watermark, or was
+    /// brought down to the low one. Fails if it wasn't within `max_wait_millis`.
+    pub fn wait(&self, depth: impl Fn() -> usize) -> Result<(), PublishError> {
+        let gauge = PUBLISHER_QUEUE_DEPTH.with_label_values(&[&self.processor_name]);
+        let mut current = depth();
+        gauge.set(current as i64);
+        if !self.enabled || current < self.high_watermark {
+            return Ok(());
+        }
+        PUBLISHER_BACKPRESSURE_WAITS
+            .with_label_values(&[&self.processor_name])
+            .inc();
+        aptos_logger::debug!(
+            processor_name = self.processor_name,
+            depth = current,
+            low_watermark = self.low_watermark,
+            "The producer's queue is full, waiting for it to drain"
+        );
+        let started = Instant::now();
+        serialization::in_place(|| {
+            while current > self.low_watermark {
+                if started.elapsed() >= self.max_wait {
+                    return Err(PublishError::QueueFull {
+                        depth: current,
+                        waited: started.elapsed(),
+                    });
+                }
+                thread::sleep(POLL_INTERVAL);
+                current = depth();
+                gauge.set(current as i64);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        cell::Cell,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+    };
+
+    fn backpressure(max_wait_millis: u64) -> Backpressure {
+        let config = PublisherBackpressureConfig {
+            enabled: true,
+            high_watermark: Some(100),
+            low_watermark: Some(50),
+            max_wait_millis,
+        };
+        Backpressure::new(&config, &HashMap::new(), Some("test")).unwrap()
+    }
+
+    #[test]
+    fn test_waits_for_low_watermark() {
+        let backpressure = backpressure(10_000);
+        let polls = Cell::new(0);
+        backpressure
+            .wait(|| {
+                polls.set(polls.get() + 1);
+                99
+            })
+            .unwrap();
+        assert_eq!(polls.get(), 1);
+
+        // Drains by 10 every poll, from above the high watermark down to the low one
+        let depth = Cell::new(120);
+        backpressure
+            .wait(|| {
+                let current = depth.get();
+                depth.set(current - 10);
+                current
+            })
+            .unwrap();
+        assert_eq!(depth.get(), 40);
+    }
+
+    #[test]
+    fn test_fails_after_max_wait() {
+        let err = backpressure(20).wait(|| 100).unwrap_err();
+        match &err {
+            PublishError::QueueFull { depth, waited } => {
+                assert_eq!(*depth, 100);
+                assert!(*waited >= Duration::from_millis(20));
+            },
+            other => panic!("Unexpected error {}", other),
+        }
+        assert!(!err.is_poison());
+
+        let disabled = Backpressure {
+            enabled: false,
+            ..backpressure(20)
+        };
+        disabled.wait(|| 1_000).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn test_wait_leaves_the_worker_to_other_tasks() {
+        let drained = Arc::new(AtomicBool::new(false));
+        let waiter = {
+            let drained = drained.clone();
+            tokio::spawn(async move {
+                backpressure(2_000).wait(|| {
+                    if drained.load(Ordering::SeqCst) {
+                        0
+                    } else {
+                        100
+                    }
+                })
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        // Queued behind the waiter on the only worker, it runs only if the wait gave the worker up
+        tokio::spawn(async move { drained.store(true, Ordering::SeqCst) });
+        waiter.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_watermarks() {
+        let kafka = |size: &str| {
+            HashMap::from([("queue.buffering.max.messages".to_string(), size.to_string())])
+        };
+        let config = PublisherBackpressureConfig::default();
+        assert_eq!(
+            config.watermarks(&HashMap::new()).unwrap(),
+            (80_000, 50_000)
+        );
+        assert_eq!(
+            config.watermarks(&kafka("5000000")).unwrap(),
+            (4_000_000, 2_500_000)
+        );
+        assert!(config.watermarks(&kafka("many")).is_err());
+
+        let config = PublisherBackpressureConfig {
+            high_watermark: Some(1_000),
+            ..PublisherBackpressureConfig::default()
+        };
+        assert_eq!(config.watermarks(&HashMap::new()).unwrap(), (1_000, 625));
+        let config = PublisherBackpressureConfig {
+            low_watermark: Some(90_000),
+            ..PublisherBackpressureConfig::default()
+        };
+        assert!(config.watermarks(&HashMap::new()).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Guard for the balances the indexer keeps by applying deltas, rather than reading them from the
+//! chain like the coin balances, e.g. the running deposits of `storage_usage`. A delta that would
+//! take such a balance below zero means a delta was missed or counted twice. The balance isn't
+//! written negative: the caller keeps it at zero or above, and records the anomaly with `record` in the
+//! transaction writing the batch. Once the batch is committed, `heal` counts the anomalies in
+//! `indexer_balance_anomalies_count`, fires a `balance_anomaly` alert, and derives the balance of
+//! every account and asset with an unhealed anomaly again from its history, see `Rederive`. An
+//! account and asset that can't be derived again stay unhealed, and are tried again after the
+//! next batch.
+
+use crate::{
+    counters::{BALANCE_ANOMALIES, BALANCE_ANOMALIES_HEALED},
+    custom::driver::alerts::{self, Alert},
+    database::{execute_with_better_error, get_chunks, write_transaction},
+    models::balance_anomalies::BalanceAnomaly,
+    schema,
+};
+use aptos_logger::{error, info};
+use bigdecimal::BigDecimal;
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl};
+use field_count::FieldCount;
+use serde_json::json;
+
+/// Anomalies listed in the details of an alert, the rest are in `balance_anomalies`
+const ALERT_SAMPLE_SIZE: usize = 10;
+
+/// How a balance table is derived from its history
+pub trait Rederive: Send + Sync {
+    /// Table of the balances, the anomalies are recorded under
+    fn balance_table(&self) -> &'static str;
+
+    /// Derives the balance of `account_address` in `asset` from its history, writes it and
+    /// returns it. Runs in the transaction marking its anomalies healed.
+    fn rederive(
+        &self,
+        conn: &mut PgConnection,
+        account_address: &str,
+        asset: &str,
+    ) -> QueryResult<BigDecimal>;
+}
+
+pub struct BalanceGuard<R: Rederive> {
+    processor: &'static str,
+    rederive: R,
+}
+
+impl<R: Rederive> BalanceGuard<R> {
+    pub fn new(processor: &'static str, rederive: R) -> Self {
+        Self {
+            processor,
+            rederive,
+        }
+    }
+
+    /// An anomaly of the guarded table: `delta` applied at `transaction_version` would have taken
+    /// `stored_balance` below zero
+    pub fn anomaly(
+        &self,
+        account_address: &str,
+        asset: &str,
+        transaction_version: i64,
+        delta: BigDecimal,
+        stored_balance: BigDecimal,
+    ) -> BalanceAnomaly {
+        BalanceAnomaly {
+            balance_table: self.rederive.balance_table().to_string(),
+            account_address: account_address.to_string(),
+            asset: asset.to_string(),
+            transaction_version,
+            processor: self.processor.to_string(),
+            expected_delta: delta,
+            stored_balance,
+        }
+    }
+
+    /// Records `anomalies` once per account, asset and version, so a retried batch doesn't
+    /// record them twice. Call it in the transaction writing the batch.
+    pub fn record(
+        &self,
+        conn: &mut PgConnection,
+        anomalies: &[BalanceAnomaly],
+    ) -> Result<(), diesel::result::Error> {
+        use schema::balance_anomalies::dsl::*;
+
+        let chunks = get_chunks(anomalies.len(), BalanceAnomaly::field_count());
+        for (start_ind, end_ind) in chunks {
+            execute_with_better_error(
+                conn,
+                diesel::insert_into(schema::balance_anomalies::table)
+                    .values(&anomalies[start_ind..end_ind])
+                    .on_conflict((balance_table, account_address, asset, transaction_version))
+                    .do_nothing(),
+                None,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Once the batch that found `anomalies` is committed, alerts on them and derives every
+    /// unhealed account and asset of the table again, the batch's and those left by earlier
+    /// batches. Returns the number healed.
+    pub fn heal(&self, conn: &mut PgConnection, anomalies: &[BalanceAnomaly]) -> usize {
+        let table = self.rederive.balance_table();
+        if let Some(first) = anomalies.first() {
+            BALANCE_ANOMALIES
+                .with_label_values(&[table])
+                .inc_by(anomalies.len() as u64);
+            alerts::fire(Alert {
+                kind: "balance_anomaly",
+                summary: format!(
+                    "{} would have gone below zero {} times, first for {} at version {}",
+                    table,
+                    anomalies.len(),
+                    first.account_address,
+                    first.transaction_version
+                ),
+                details: json!({
+                    "processor": self.processor,
+                    "balance_table": table,
+                    "anomalies": &anomalies[..anomalies.len().min(ALERT_SAMPLE_SIZE)],
+                }),
+            });
+        }
+        let unhealed = match self.unhealed(conn) {
+            Ok(unhealed) => unhealed,
+            Err(err) => {
+                error!(
+                    processor_name = self.processor,
+                    balance_table = table,
+                    error = ?err,
+                    "Failed to look up the balances to derive again"
+                );
+                return 0;
+            },
+        };
+        let mut healed = 0;
+        for (account, asset_name) in unhealed {
+            match write_transaction(conn, |pg_conn| {
+                self.heal_one(pg_conn, &account, &asset_name)
+            }) {
+                Ok(balance) => {
+                    healed += 1;
+                    info!(
+                        processor_name = self.processor,
+                        balance_table = table,
+                        account_address = account,
+                        asset = asset_name,
+                        balance = balance.to_string(),
+                        "Derived the balance again from its history"
+                    );
+                },
+                Err(err) => error!(
+                    processor_name = self.processor,
+                    balance_table = table,
+                    account_address = account,
+                    asset = asset_name,
+                    error = ?err,
+                    "Failed to derive the balance again, retried after the next batch"
+                ),
+            }
+        }
+        BALANCE_ANOMALIES_HEALED
+            .with_label_values(&[table])
+            .inc_by(healed as u64);
+        healed
+    }
+
+    /// Accounts and assets of the table with an anomaly that isn't healed yet
+    fn unhealed(&self, conn: &mut PgConnection) -> QueryResult<Vec<(String, String)>> {
+        use schema::balance_anomalies::dsl::*;
+
+        balance_anomalies
+            .filter(balance_table.eq(self.rederive.balance_table()))
+            .filter(healed_at.is_null())
+            .select((account_address, asset))
+            .distinct()
+            .order((account_address, asset))
+            .load(conn)
+    }
+
+    fn heal_one(
+        &self,
+        conn: &mut PgConnection,
+        account: &str,
+        asset_name: &str,
+    ) -> QueryResult<BigDecimal> {
+        use schema::balance_anomalies::dsl::*;
+
+        let balance = self.rederive.rederive(conn, account, asset_name)?;
+        diesel::update(
+            balance_anomalies
+                .filter(balance_table.eq(self.rederive.balance_table()))
+                .filter(account_address.eq(account))
+                .filter(asset.eq(asset_name))
+                .filter(healed_at.is_null()),
+        )
+        .set((
+            rederived_balance.eq(&balance),
+            healed_at.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(conn)?;
+        Ok(balance)
+    }
+}
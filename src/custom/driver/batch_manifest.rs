@@ -0,0 +1,145 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! A manifest of every batch a processor commits, published on `batch_manifest_topic` when it's
+//! configured, so that a consumer knows a version range is complete on every topic without
+//! counting messages.
+//!
+//! Once the batches of a round are processed, the processor's publisher is flushed, so everything
+//! they produced is delivered, and only then is a `BatchManifest` per batch produced, by a
+//! producer of its own, and flushed in turn before the watermark moves. Seeing the manifest of a
+//! range therefore means every message of the range was delivered. A round whose messages or
+//! manifests can't be delivered in time is processed again, so a range can have more than one
+//! manifest, and the batches of a round processed again needn't have the same ranges.
+//!
+//! Manifests are keyed by processor name, unsalted, so that the manifests of a processor are
+//! read in order.
+
+use crate::{
+    custom::driver::{
+        config::DriverConfig,
+        envelope::Envelope,
+        publisher::{FlushHandle, Publisher},
+    },
+    indexer::processing_result::{ProcessingResult, RowCounts},
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Model name of the published manifests, see `client::MODEL_TOPIC_KEYS`
+pub const BATCH_MANIFEST: &str = "BatchManifest";
+pub const TOPIC_KEY: &str = "batch_manifest_topic";
+
+/// How long a round waits for its messages, then its manifests, to be delivered
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A batch a processor committed, every message of which was delivered
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct BatchManifest {
+    pub processor: String,
+    pub start_version: u64,
+    /// Inclusive
+    pub end_version: u64,
+    /// What the batch produced, all zero for processors that don't count it
+    pub counts: RowCounts,
+    /// Messages of the batch sent to a dead letter topic instead of their own
+    pub dead_lettered: usize,
+    pub chain_id: u8,
+    /// When the processor finished the batch
+    pub committed_at: chrono::NaiveDateTime,
+}
+
+pub struct BatchManifests {
+    publisher: Publisher,
+    chain_id: u8,
+}
+
+impl BatchManifests {
+    /// `None` without `batch_manifest_topic`
+    pub fn from_config(driver_config: &DriverConfig, envelope: Envelope) -> Option<Self> {
+        if !driver_config.topics.contains_key(TOPIC_KEY) {
+            return None;
+        }
+        Some(Self {
+            chain_id: envelope.chain_id,
+            publisher: Publisher::from_config(driver_config.clone(), envelope),
+        })
+    }
+
+    /// The manifest of a batch that just committed
+    pub fn manifest(&self, result: &ProcessingResult) -> BatchManifest {
+        BatchManifest {
+            processor: result.name.to_string(),
+            start_version: result.start_version,
+            end_version: result.end_version,
+            counts: result.counts,
+            dead_lettered: result.dead_lettered,
+            chain_id: self.chain_id,
+            committed_at: chrono::Utc::now().naive_utc(),
+        }
+    }
+
+    /// Produces `manifests` once what `data` produced is delivered, then waits for them to be
+    /// delivered as well
+    pub fn publish(
+        &self,
+        data: Option<&FlushHandle>,
+        manifests: &[BatchManifest],
+    ) -> anyhow::Result<()> {
+        if manifests.is_empty() {
+            return Ok(());
+        }
+        if let Some(data) = data {
+            data.flush(FLUSH_TIMEOUT)?;
+        }
+        self.publisher
+            .try_send_keyed(BATCH_MANIFEST, manifests, |manifest| {
+                manifest.processor.clone()
+            })?;
+        self.publisher.flush(FLUSH_TIMEOUT)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::decode_model;
+    use serde_json::json;
+
+    fn driver_config(topics: serde_json::Value) -> DriverConfig {
+        serde_json::from_value(json!({ "kafka": {}, "topics": topics })).unwrap()
+    }
+
+    #[test]
+    fn test_manifest() {
+        let envelope = Envelope::new(4, "custom_default_processor");
+        assert!(BatchManifests::from_config(&driver_config(json!({})), envelope.clone()).is_none());
+        let batch_manifests = BatchManifests::from_config(
+            &driver_config(json!({ "batch_manifest_topic": "manifests" })),
+            envelope,
+        )
+        .unwrap();
+
+        let counts = RowCounts {
+            transactions: 100,
+            user_transactions: 60,
+            events: 250,
+            write_set_changes: 400,
+            move_resources: 300,
+            table_items: 100,
+        };
+        let result = ProcessingResult::new("custom_default_processor", 100, 199)
+            .with_counts(counts)
+            .with_dead_lettered(2);
+        let manifest = batch_manifests.manifest(&result);
+        assert_eq!(manifest.processor, "custom_default_processor");
+        assert_eq!((manifest.start_version, manifest.end_version), (100, 199));
+        assert_eq!(manifest.counts, counts);
+        assert_eq!(manifest.dead_lettered, 2);
+        assert_eq!(manifest.chain_id, 4);
+
+        let payload = serde_json::to_vec(&manifest).unwrap();
+        assert_eq!(decode_model::<BatchManifest>(&payload).unwrap(), manifest);
+    }
+}
@@ -0,0 +1,135 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Retrying the rounds that fail on a retryable error, instead of stopping the processor. When a
+//! batch of a round fails, its error is classified (`TransactionProcessingError::kind`): a
+//! `Retryable` one, like a pool timeout, a Postgres serialization failure or a broker that's
+//! down, has the processor wait and process the round again from the last version committed,
+//! `base_delay_millis` doubled after every failed round in a row, at most `max_delay_millis`.
+//! After `max_attempts` rounds in a row failed, or right away on a `Parse` or `Fatal` error, the
+//! processor stops with the error and the batch's versions. Retries are counted in
+//! `indexer_batch_retries_count` by processor.
+
+use crate::{
+    counters::BATCH_RETRIES,
+    custom::driver::config::BatchRetryConfig,
+    indexer::errors::{ErrorKind, TransactionProcessingError},
+};
+use std::{
+    fmt::{self, Display},
+    time::Duration,
+};
+
+/// The failed rounds in a row of a processor
+#[derive(Clone, Debug)]
+pub struct BatchRetry {
+    config: BatchRetryConfig,
+    failures: u32,
+}
+
+/// Why a failed round isn't retried
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GiveUp {
+    /// The error isn't retryable
+    NotRetryable(ErrorKind),
+    /// Rounds failed this many times in a row
+    Exhausted(u32),
+}
+
+impl Display for GiveUp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GiveUp::NotRetryable(kind) => write!(f, "{} error, not retried", kind.as_str()),
+            GiveUp::Exhausted(failures) => {
+                write!(f, "still failing after {} attempts", failures)
+            },
+        }
+    }
+}
+
+impl BatchRetry {
+    pub fn new(config: &BatchRetryConfig) -> Self {
+        Self {
+            config: config.clone(),
+            failures: 0,
+        }
+    }
+
+    /// After a round failed with `error`, how long to wait before processing it again
+    pub fn on_failure(
+        &mut self,
+        processor_name: &str,
+        error: &TransactionProcessingError,
+    ) -> Result<Duration, GiveUp> {
+        let kind = error.kind();
+        if kind != ErrorKind::Retryable {
+            return Err(GiveUp::NotRetryable(kind));
+        }
+        self.failures += 1;
+        if self.failures >= self.config.max_attempts {
+            return Err(GiveUp::Exhausted(self.failures));
+        }
+        BATCH_RETRIES.with_label_values(&[processor_name]).inc();
+        Ok(self.delay())
+    }
+
+    /// After a round committed
+    pub fn on_success(&mut self) {
+        self.failures = 0;
+    }
+
+    fn delay(&self) -> Duration {
+        let backoff = self
+            .config
+            .base_delay_millis
+            .saturating_mul(1 << (self.failures - 1).min(32))
+            .min(self.config.max_delay_millis);
+        Duration::from_millis(backoff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+    fn error(err: anyhow::Error) -> TransactionProcessingError {
+        TransactionProcessingError::TransactionCommitError((err, 100, 199, "batch_retry_test"))
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut retry = BatchRetry::new(&BatchRetryConfig {
+            max_attempts: 5,
+            base_delay_millis: 100,
+            max_delay_millis: 300,
+        });
+        let timeout = error(anyhow::Error::new(DieselError::DatabaseError(
+            DatabaseErrorKind::SerializationFailure,
+            Box::new("could not serialize access".to_string()),
+        )));
+        let delays = (0..4)
+            .map(|_| retry.on_failure("batch_retry_test", &timeout).unwrap())
+            .map(|delay| delay.as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [100, 200, 300, 300]);
+        assert_eq!(
+            retry.on_failure("batch_retry_test", &timeout),
+            Err(GiveUp::Exhausted(5))
+        );
+
+        // Starts over once a round commits
+        retry.on_success();
+        assert_eq!(
+            retry.on_failure("batch_retry_test", &timeout),
+            Ok(Duration::from_millis(100))
+        );
+        assert_eq!(
+            retry.on_failure(
+                "batch_retry_test",
+                &error(anyhow::anyhow!("Schema mismatch"))
+            ),
+            Err(GiveUp::NotRetryable(ErrorKind::Fatal))
+        );
+    }
+}
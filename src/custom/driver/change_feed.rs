@@ -0,0 +1,189 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! A change feed in Postgres for consumers that would rather poll "what changed after id X" than
+//! run a Kafka consumer, see `queries::poll_change_feed`. Processors hand the rows of each table
+//! to `record` in the transaction that writes them, which appends one entry per row to
+//! `change_feed` (table, version, primary key and operation) with one multi-row insert. Entries
+//! older than `retention_hours` are pruned every `prune_interval_secs`, each pruning tracked as a
+//! `prune` operation.
+//!
+//! Ids come from a sequence, so two batches committing concurrently could become visible out of
+//! id order and a poller could skip past an entry. Writers take a transaction scoped advisory
+//! lock before appending, which serializes the end of their transactions and commits ids in
+//! order. The feed is off unless `enabled`, and built without the `change_feed` feature it
+//! compiles down to nothing for deployments that can't afford the lock.
+
+use crate::{
+    counters::{CHANGE_FEED_ENTRIES, CHANGE_FEED_PRUNED},
+    custom::driver::{config::ChangeFeedConfig, operations},
+    database::{execute_with_better_error, get_chunks, PgDbPool},
+    models::change_feed::{ChangeFeedRow, Operation},
+    schema::change_feed,
+};
+use aptos_logger::{error, info};
+use diesel::{
+    sql_query, sql_types::BigInt, ExpressionMethods, PgConnection, QueryResult, RunQueryDsl,
+};
+use field_count::FieldCount;
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::time::Duration;
+
+/// Held by every transaction appending to the feed until it commits
+const ADVISORY_LOCK_KEY: i64 = 0x6368_616e_6765;
+/// Fields a row's version is read from, the first one present wins
+const VERSION_FIELDS: &[&str] = &[
+    "transaction_version",
+    "last_transaction_version",
+    "version",
+    "first_transaction_version",
+    "first_seen_version",
+];
+
+static ENABLED: OnceCell<bool> = OnceCell::new();
+
+/// Turns the feed on and spawns the pruning. Only the first call in a process has an effect, so
+/// every processor runtime can call it.
+pub fn init(config: &ChangeFeedConfig, connection_pool: PgDbPool) {
+    if !cfg!(feature = "change_feed") || !config.enabled || ENABLED.set(true).is_err() {
+        return;
+    }
+    info!(
+        retention_hours = config.retention_hours,
+        "Recording written rows to the change feed"
+    );
+    let retention = chrono::Duration::hours(config.retention_hours as i64);
+    let interval = Duration::from_secs(config.prune_interval_secs.max(1));
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            let operation = operations::start(
+                "prune",
+                "change_feed.retention_hours",
+                serde_json::json!({
+                    "table": "change_feed",
+                    "retention_hours": retention.num_hours(),
+                }),
+                0,
+                None,
+            );
+            let pool = connection_pool.clone();
+            let result = tokio::task::spawn_blocking(move || prune(&pool, retention))
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|result| result);
+            match result {
+                Ok(pruned) => operation.complete(pruned as u64),
+                Err(err) => {
+                    error!(error = ?err, "Failed to prune the change feed");
+                    operation.fail(operations::error_code(&err), format!("{:#}", err));
+                },
+            }
+        }
+    });
+}
+
+/// Appends an entry for each of `rows`, written to `model` with `operation`, keyed by the
+/// `pk_columns` of their serialization. A no-op unless the feed is enabled. Call it after the
+/// batch's other writes, so nothing waits for row locks while holding the feed's lock.
+pub fn record<T: Serialize>(
+    conn: &mut PgConnection,
+    model: &'static str,
+    pk_columns: &[&str],
+    operation: Operation,
+    rows: &[T],
+) -> QueryResult<()> {
+    if !cfg!(feature = "change_feed") || ENABLED.get().is_none() || rows.is_empty() {
+        return Ok(());
+    }
+    let entries = rows
+        .iter()
+        .map(|row| entry(model, pk_columns, operation, row))
+        .collect::<Vec<_>>();
+    sql_query("SELECT pg_advisory_xact_lock($1)")
+        .bind::<BigInt, _>(ADVISORY_LOCK_KEY)
+        .execute(conn)?;
+    for (start_ind, end_ind) in get_chunks(entries.len(), ChangeFeedRow::field_count()) {
+        execute_with_better_error(
+            conn,
+            diesel::insert_into(change_feed::table).values(&entries[start_ind..end_ind]),
+            None,
+        )?;
+    }
+    CHANGE_FEED_ENTRIES
+        .with_label_values(&[model])
+        .inc_by(entries.len() as u64);
+    Ok(())
+}
+
+fn entry<T: Serialize>(
+    model: &str,
+    pk_columns: &[&str],
+    operation: Operation,
+    row: &T,
+) -> ChangeFeedRow {
+    let value = serde_json::to_value(row).unwrap_or_default();
+    let pk = pk_columns
+        .iter()
+        .map(|column| {
+            (
+                column.to_string(),
+                value.get(column).cloned().unwrap_or_default(),
+            )
+        })
+        .collect::<Map<_, _>>();
+    let transaction_version = VERSION_FIELDS
+        .iter()
+        .find_map(|field| value.get(field).and_then(Value::as_i64));
+    ChangeFeedRow {
+        model: model.to_string(),
+        transaction_version,
+        pk: Value::Object(pk),
+        operation: operation.as_str().to_string(),
+    }
+}
+
+/// Deletes the entries older than `retention`, returns how many
+pub fn prune(connection_pool: &PgDbPool, retention: chrono::Duration) -> anyhow::Result<usize> {
+    let mut conn = connection_pool.get()?;
+    let cutoff = chrono::Utc::now().naive_utc() - retention;
+    let pruned = diesel::delete(change_feed::table)
+        .filter(change_feed::inserted_at.lt(cutoff))
+        .execute(&mut conn)?;
+    CHANGE_FEED_PRUNED.inc_by(pruned as u64);
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_entry_keys_and_version() {
+        let row = json!({
+            "transaction_version": 10,
+            "write_set_change_index": 2,
+            "owner_address": "0x1",
+        });
+        let object = entry(
+            "objects",
+            &["transaction_version", "write_set_change_index"],
+            Operation::Insert,
+            &row,
+        );
+        assert_eq!(object.transaction_version, Some(10));
+        assert_eq!(
+            object.pk,
+            json!({"transaction_version": 10, "write_set_change_index": 2})
+        );
+        assert_eq!(object.operation, "insert");
+
+        let row = json!({"pool": "0xa", "first_transaction_version": 3});
+        let pool = entry("dex_pools", &["pool"], Operation::Upsert, &row);
+        assert_eq!(pool.transaction_version, Some(3));
+        assert_eq!(pool.pk, json!({"pool": "0xa"}));
+    }
+}
@@ -0,0 +1,479 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Health state of each processor, a signal downstream teams can act on without a human in the
+//! loop. After every round the processor's lag (versions between the ledger and what it
+//! committed) and the process' error rate (fetch and connection errors per minute over
+//! `error_window_secs`) are compared with the thresholds of `degraded` and `critical`.
+//!
+//! A processor moves to a worse state once that state's thresholds were crossed for
+//! `escalate_after_secs`, and back to a better one only once it stayed under the better state's
+//! thresholds, lowered by `recovery_ratio`, for `recover_after_secs`. Every transition is
+//! persisted in `processor_status`, published as a `HealthStateChange` on `control_topic` when
+//! that topic is configured, and fired as a `health_state_change` alert. A restarted processor
+//! picks up the state it was in.
+
+use crate::{
+    counters::{HEALTH_STATE, UNABLE_TO_FETCH_TRANSACTION, UNABLE_TO_GET_CONNECTION},
+    custom::driver::{
+        alerts::{self, Alert},
+        config::{CircuitBreakerConfig, DriverConfig},
+        envelope::Envelope,
+        publisher::Publisher,
+    },
+    database::PgDbPool,
+    models::processor_status::ProcessorStatusV2Query,
+    schema::processor_status,
+};
+use aptos_logger::{error, info, warn};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Model name of the published transitions, see `client::MODEL_TOPIC_KEYS`
+pub const HEALTH_STATE_CHANGE: &str = "HealthStateChange";
+
+static BREAKER: OnceCell<CircuitBreaker> = OnceCell::new();
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthState {
+    Healthy,
+    Degraded,
+    Critical,
+}
+
+impl HealthState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HealthState::Healthy => "healthy",
+            HealthState::Degraded => "degraded",
+            HealthState::Critical => "critical",
+        }
+    }
+
+    fn parse(state: &str) -> Option<Self> {
+        match state {
+            "healthy" => Some(HealthState::Healthy),
+            "degraded" => Some(HealthState::Degraded),
+            "critical" => Some(HealthState::Critical),
+            _ => None,
+        }
+    }
+}
+
+/// A transition, as persisted, published and alerted
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct HealthStateChange {
+    pub processor: String,
+    pub from: HealthState,
+    pub to: HealthState,
+    /// Versions behind the ledger
+    pub lag: u64,
+    pub errors_per_minute: f64,
+    pub changed_at: chrono::NaiveDateTime,
+}
+
+/// Snapshot of a processor's breaker, meant for status reporting
+#[derive(Clone, Debug, Serialize)]
+pub struct ProcessorHealth {
+    pub processor: String,
+    pub state: HealthState,
+    pub lag: u64,
+    pub errors_per_minute: f64,
+}
+
+struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    connection_pool: PgDbPool,
+    /// Only with `control_topic` configured
+    publisher: Option<Publisher>,
+    errors: Mutex<ErrorRate>,
+    processors: Mutex<HashMap<String, Breaker>>,
+}
+
+/// Turns the breaker on. Only the first call in a process has an effect, so every processor
+/// runtime can call it.
+pub fn init(driver_config: &DriverConfig, chain_id: u8, connection_pool: PgDbPool) {
+    let config = &driver_config.circuit_breaker;
+    if !config.enabled || BREAKER.get().is_some() {
+        return;
+    }
+    if let Err(err) = config.validate() {
+        panic!("Invalid circuit_breaker config: {:#}", err);
+    }
+    let publisher = driver_config
+        .topics
+        .contains_key("control_topic")
+        .then(|| Publisher::from_config(driver_config.clone(), Envelope::control(chain_id)));
+    info!(
+        publishes = publisher.is_some(),
+        "Tracking the health state of the processors"
+    );
+    let _ = BREAKER.set(CircuitBreaker {
+        config: config.clone(),
+        connection_pool,
+        publisher,
+        errors: Mutex::new(ErrorRate::new(Duration::from_secs(
+            config.error_window_secs,
+        ))),
+        processors: Mutex::new(HashMap::new()),
+    });
+}
+
+pub fn enabled() -> bool {
+    BREAKER.get().is_some()
+}
+
+/// Called after every round of `processor`, `lag` versions behind the ledger
+pub fn on_round(processor: &str, lag: u64) {
+    let Some(breaker) = BREAKER.get() else {
+        return;
+    };
+    let now = Instant::now();
+    let errors_per_minute = breaker.errors.lock().unwrap().observe(
+        UNABLE_TO_FETCH_TRANSACTION.get() + UNABLE_TO_GET_CONNECTION.get(),
+        now,
+    );
+    let (state, transition) = {
+        let mut processors = breaker.processors.lock().unwrap();
+        let state = processors
+            .entry(processor.to_string())
+            .or_insert_with(|| Breaker::new(breaker.persisted_state(processor)));
+        let transition = state.observe(&breaker.config, lag, errors_per_minute, now);
+        (state.state, transition)
+    };
+    HEALTH_STATE
+        .with_label_values(&[processor])
+        .set(state as i64);
+    if let Some((from, to)) = transition {
+        breaker.transition(HealthStateChange {
+            processor: processor.to_string(),
+            from,
+            to,
+            lag,
+            errors_per_minute,
+            changed_at: chrono::Utc::now().naive_utc(),
+        });
+    }
+}
+
+/// Every processor seen so far
+pub fn status() -> Vec<ProcessorHealth> {
+    let Some(breaker) = BREAKER.get() else {
+        return vec![];
+    };
+    let mut status = breaker
+        .processors
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(processor, state)| ProcessorHealth {
+            processor: processor.clone(),
+            state: state.state,
+            lag: state.lag,
+            errors_per_minute: state.errors_per_minute,
+        })
+        .collect::<Vec<_>>();
+    status.sort_by(|a, b| a.processor.cmp(&b.processor));
+    status
+}
+
+/// Status code of the worst state of the processors, for a health endpoint to answer with
+pub fn http_status() -> u16 {
+    let Some(breaker) = BREAKER.get() else {
+        return 200;
+    };
+    let worst = status()
+        .iter()
+        .map(|health| health.state)
+        .max()
+        .unwrap_or(HealthState::Healthy);
+    breaker.config.status_codes[worst.as_str()]
+}
+
+impl CircuitBreaker {
+    /// The state `processor` was left in, healthy if unknown
+    fn persisted_state(&self, processor: &str) -> HealthState {
+        let persisted = self
+            .connection_pool
+            .get()
+            .map_err(anyhow::Error::from)
+            .and_then(|mut conn| {
+                Ok(ProcessorStatusV2Query::get_by_processor(
+                    &processor.to_string(),
+                    &mut conn,
+                )?)
+            });
+        match persisted {
+            Ok(status) => status
+                .and_then(|status| HealthState::parse(&status.health_state))
+                .unwrap_or(HealthState::Healthy),
+            Err(err) => {
+                warn!(
+                    processor_name = processor,
+                    error = ?err,
+                    "Failed to read the persisted health state, starting healthy"
+                );
+                HealthState::Healthy
+            },
+        }
+    }
+
+    fn transition(&self, change: HealthStateChange) {
+        info!(
+            processor_name = change.processor,
+            from = change.from.as_str(),
+            to = change.to.as_str(),
+            lag = change.lag,
+            errors_per_minute = change.errors_per_minute,
+            "Health state changed"
+        );
+        let persisted = self
+            .connection_pool
+            .get()
+            .map_err(anyhow::Error::from)
+            .and_then(|mut conn| {
+                diesel::update(
+                    processor_status::table
+                        .filter(processor_status::processor.eq(&change.processor)),
+                )
+                .set((
+                    processor_status::health_state.eq(change.to.as_str()),
+                    processor_status::health_state_since.eq(change.changed_at),
+                ))
+                .execute(&mut conn)?;
+                Ok(())
+            });
+        if let Err(err) = persisted {
+            error!(
+                processor_name = change.processor,
+                error = ?err,
+                "Failed to persist the health state"
+            );
+        }
+        // Most likely while Kafka is down, the alert goes out anyway
+        if let Some(publisher) = &self.publisher {
+            if let Err(err) = publisher.try_send(HEALTH_STATE_CHANGE, &[change.clone()]) {
+                error!(
+                    processor_name = change.processor,
+                    error = ?err,
+                    "Failed to publish the health state change"
+                );
+            }
+        }
+        alerts::fire(Alert {
+            kind: "health_state_change",
+            summary: format!(
+                "{} is {}, was {}",
+                change.processor,
+                change.to.as_str(),
+                change.from.as_str()
+            ),
+            details: serde_json::to_value(&change).unwrap_or_default(),
+        });
+    }
+}
+
+/// Errors per minute over a sliding window, from a counter's samples
+struct ErrorRate {
+    window: Duration,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl ErrorRate {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Records the counter's value at `now`, returns the rate over the window
+    fn observe(&mut self, total: u64, now: Instant) -> f64 {
+        self.samples.push_back((now, total));
+        // Keeps the last sample before the window, the baseline of the errors in it
+        while self.samples.len() > 1 && now.duration_since(self.samples[1].0) >= self.window {
+            self.samples.pop_front();
+        }
+        let (_, oldest) = self.samples.front().unwrap();
+        (total - oldest) as f64 / (self.window.as_secs_f64() / 60.0)
+    }
+}
+
+/// State machine of one processor
+struct Breaker {
+    state: HealthState,
+    /// The state the conditions point to and since when they have, while it isn't `state`
+    pending: Option<(HealthState, Instant)>,
+    lag: u64,
+    errors_per_minute: f64,
+}
+
+impl Breaker {
+    fn new(state: HealthState) -> Self {
+        Self {
+            state,
+            pending: None,
+            lag: 0,
+            errors_per_minute: 0.0,
+        }
+    }
+
+    /// The transition the observation completes, if any
+    fn observe(
+        &mut self,
+        config: &CircuitBreakerConfig,
+        lag: u64,
+        errors_per_minute: f64,
+        now: Instant,
+    ) -> Option<(HealthState, HealthState)> {
+        self.lag = lag;
+        self.errors_per_minute = errors_per_minute;
+        let crossed = classify(config, lag, errors_per_minute, 1.0);
+        let target = if crossed > self.state {
+            Some(crossed)
+        } else {
+            Some(classify(
+                config,
+                lag,
+                errors_per_minute,
+                config.recovery_ratio,
+            ))
+            .filter(|recovered| *recovered < self.state)
+        };
+        let Some(target) = target else {
+            self.pending = None;
+            return None;
+        };
+        // A pending move keeps its start while the conditions point the same way, e.g. a lag
+        // between the degraded and critical thresholds still escalates a healthy processor
+        let since = match self.pending {
+            Some((pending, since)) if (pending > self.state) == (target > self.state) => since,
+            _ => now,
+        };
+        self.pending = Some((target, since));
+        let sustain = if target > self.state {
+            config.escalate_after_secs
+        } else {
+            config.recover_after_secs
+        };
+        if now.duration_since(since) < Duration::from_secs(sustain) {
+            return None;
+        }
+        let from = self.state;
+        self.state = target;
+        self.pending = None;
+        Some((from, target))
+    }
+}
+
+/// The worst state whose thresholds, scaled by `ratio`, the lag or error rate reach
+fn classify(
+    config: &CircuitBreakerConfig,
+    lag: u64,
+    errors_per_minute: f64,
+    ratio: f64,
+) -> HealthState {
+    let reaches = |lag_threshold: u64, rate_threshold: f64| {
+        lag as f64 >= lag_threshold as f64 * ratio || errors_per_minute >= rate_threshold * ratio
+    };
+    if reaches(config.critical_lag, config.critical_errors_per_minute) {
+        HealthState::Critical
+    } else if reaches(config.degraded_lag, config.degraded_errors_per_minute) {
+        HealthState::Degraded
+    } else {
+        HealthState::Healthy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> CircuitBreakerConfig {
+        CircuitBreakerConfig {
+            enabled: true,
+            degraded_lag: 100,
+            critical_lag: 1000,
+            escalate_after_secs: 60,
+            recover_after_secs: 120,
+            recovery_ratio: 0.5,
+            ..CircuitBreakerConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_escalates_after_sustained_lag() {
+        let config = config();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut breaker = Breaker::new(HealthState::Healthy);
+        assert_eq!(breaker.observe(&config, 2000, 0.0, at(0)), None);
+        // Dropping to degraded levels keeps the escalation pending
+        assert_eq!(breaker.observe(&config, 500, 0.0, at(30)), None);
+        assert_eq!(
+            breaker.observe(&config, 500, 0.0, at(60)),
+            Some((HealthState::Healthy, HealthState::Degraded))
+        );
+        // A blip under the threshold restarts the clock
+        assert_eq!(breaker.observe(&config, 2000, 0.0, at(70)), None);
+        assert_eq!(breaker.observe(&config, 500, 0.0, at(80)), None);
+        assert_eq!(breaker.observe(&config, 2000, 0.0, at(90)), None);
+        assert_eq!(
+            breaker.observe(&config, 2000, 0.0, at(150)),
+            Some((HealthState::Degraded, HealthState::Critical))
+        );
+        // An error rate at degraded levels only recovers to degraded
+        assert_eq!(breaker.observe(&config, 0, 20.0, at(200)), None);
+        assert_eq!(
+            breaker.observe(&config, 0, 20.0, at(320)),
+            Some((HealthState::Critical, HealthState::Degraded))
+        );
+    }
+
+    #[test]
+    fn test_recovers_with_hysteresis() {
+        let config = config();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut breaker = Breaker::new(HealthState::Degraded);
+        // Under the threshold but not under half of it
+        assert_eq!(breaker.observe(&config, 80, 0.0, at(0)), None);
+        assert_eq!(breaker.observe(&config, 80, 0.0, at(500)), None);
+        assert_eq!(breaker.observe(&config, 40, 0.0, at(600)), None);
+        assert_eq!(breaker.observe(&config, 40, 0.0, at(700)), None);
+        assert_eq!(
+            breaker.observe(&config, 10, 0.0, at(720)),
+            Some((HealthState::Degraded, HealthState::Healthy))
+        );
+    }
+
+    #[test]
+    fn test_error_rate() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut rate = ErrorRate::new(Duration::from_secs(120));
+        assert_eq!(rate.observe(5, at(0)), 0.0);
+        assert_eq!(rate.observe(15, at(60)), 5.0);
+        // The sample at 0 is the baseline until the one at 60 leaves the window
+        assert_eq!(rate.observe(15, at(150)), 5.0);
+        assert_eq!(rate.observe(15, at(180)), 0.0);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(config().validate().is_ok());
+        let mut invalid = config();
+        invalid.critical_lag = 50;
+        assert!(invalid.validate().is_err());
+        let mut invalid = config();
+        invalid.status_codes.remove("degraded");
+        assert!(invalid.validate().is_err());
+    }
+}
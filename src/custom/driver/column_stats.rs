@@ -0,0 +1,452 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Column statistics of inserted rows, to answer "what's the cardinality of X" without querying
+//! the tables. Processors hand their rows to `observe` right before inserting them; for the
+//! configured tables a share of the rows is sampled into per column sketches (HyperLogLog for
+//! distinct values, null counts, and a reservoir of values for the top K). Every
+//! `flush_interval_secs` the sketches are written to `indexer_column_stats`, exported as the
+//! `indexer_column_stats` gauges and reset.
+
+use crate::{
+    counters::{COLUMN_STATS, COLUMN_STATS_MEMORY_BYTES},
+    custom::driver::config::ColumnStatsConfig,
+    database::{execute_with_better_error, get_chunks, PgDbPool},
+    models::column_stats::ColumnStat,
+    schema,
+};
+use aptos_logger::{error, info, warn};
+use field_count::FieldCount;
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use std::{
+    collections::{
+        hash_map::{DefaultHasher, RandomState},
+        BTreeMap, HashMap,
+    },
+    hash::{BuildHasher, Hash, Hasher},
+    sync::Mutex,
+    time::Duration,
+};
+
+/// HyperLogLog precision: 2^12 one byte registers per column, ~1.6% standard error
+const HLL_PRECISION: u32 = 12;
+const HLL_REGISTERS: usize = 1 << HLL_PRECISION;
+/// Reservoir values are truncated to this many bytes
+const MAX_VALUE_LEN: usize = 64;
+
+static COLLECTOR: OnceCell<ColumnStatsCollector> = OnceCell::new();
+
+/// Starts collecting for the configured tables and spawns the flush task. Only the first call
+/// in a process has an effect, so every processor runtime can call it.
+pub fn init(config: &ColumnStatsConfig, connection_pool: PgDbPool) {
+    if !config.enabled || COLLECTOR.get().is_some() {
+        return;
+    }
+    if COLLECTOR
+        .set(ColumnStatsCollector::new(config.clone()))
+        .is_err()
+    {
+        return;
+    }
+    info!(
+        tables = format!("{:?}", config.tables.keys().collect::<Vec<_>>()),
+        "Collecting column stats"
+    );
+    let interval = Duration::from_secs(config.flush_interval_secs.max(1));
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            let stats = COLLECTOR.get().unwrap().flush();
+            if stats.is_empty() {
+                continue;
+            }
+            let pool = connection_pool.clone();
+            // Low priority: keep the db write off the runtime's worker threads
+            let result = tokio::task::spawn_blocking(move || insert_column_stats(&pool, &stats))
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|result| result);
+            if let Err(err) = result {
+                error!(error = ?err, "Failed to write column stats");
+            }
+        }
+    });
+}
+
+/// Samples `rows` of `table_name` if stats are collected for it. Cheap for all other tables.
+pub fn observe<T: Serialize>(table_name: &str, rows: &[T]) {
+    if let Some(collector) = COLLECTOR.get() {
+        collector.observe(table_name, rows);
+    }
+}
+
+fn insert_column_stats(pool: &PgDbPool, stats: &[ColumnStat]) -> anyhow::Result<()> {
+    use schema::indexer_column_stats::dsl::*;
+
+    let mut conn = pool.get()?;
+    for (start_ind, end_ind) in get_chunks(stats.len(), ColumnStat::field_count()) {
+        execute_with_better_error(
+            &mut conn,
+            diesel::insert_into(schema::indexer_column_stats::table)
+                .values(&stats[start_ind..end_ind])
+                .on_conflict((table_name, column_name, window_end))
+                .do_nothing(),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+pub struct ColumnStatsCollector {
+    config: ColumnStatsConfig,
+    random_state: RandomState,
+    state: Mutex<CollectorState>,
+}
+
+struct CollectorState {
+    window_start: chrono::NaiveDateTime,
+    tables: HashMap<String, TableSketch>,
+    draws: u64,
+    memory_bytes: usize,
+}
+
+#[derive(Default)]
+struct TableSketch {
+    rows_seen: u64,
+    rows_sampled: u64,
+    columns: BTreeMap<String, ColumnSketch>,
+}
+
+struct ColumnSketch {
+    registers: Vec<u8>,
+    nulls: u64,
+    reservoir: Vec<String>,
+    /// Non null values offered to the reservoir
+    values_seen: u64,
+}
+
+impl ColumnStatsCollector {
+    pub fn new(config: ColumnStatsConfig) -> Self {
+        Self {
+            config,
+            random_state: RandomState::new(),
+            state: Mutex::new(CollectorState {
+                window_start: chrono::Utc::now().naive_utc(),
+                tables: HashMap::new(),
+                draws: 0,
+                memory_bytes: 0,
+            }),
+        }
+    }
+
+    pub fn observe<T: Serialize>(&self, table_name: &str, rows: &[T]) {
+        let sample_rate = match self.config.tables.get(table_name) {
+            Some(sample_rate) if *sample_rate > 0.0 && !rows.is_empty() => *sample_rate,
+            _ => return,
+        };
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        let table = state.tables.entry(table_name.to_string()).or_default();
+        table.rows_seen += rows.len() as u64;
+        for row in rows {
+            state.draws += 1;
+            if self.uniform(state.draws) >= sample_rate {
+                continue;
+            }
+            let row = match serde_json::to_value(row) {
+                Ok(serde_json::Value::Object(row)) => row,
+                _ => continue,
+            };
+            table.rows_sampled += 1;
+            for (column_name, value) in row {
+                if !table.columns.contains_key(&column_name) {
+                    if state.memory_bytes + ColumnSketch::max_bytes(&self.config)
+                        > self.config.max_memory_bytes
+                    {
+                        warn!(
+                            table_name = table_name,
+                            column_name = column_name,
+                            max_memory_bytes = self.config.max_memory_bytes,
+                            "Column stats memory limit reached, not tracking column"
+                        );
+                        continue;
+                    }
+                    state.memory_bytes += ColumnSketch::max_bytes(&self.config);
+                    table
+                        .columns
+                        .insert(column_name.clone(), ColumnSketch::new());
+                }
+                state.draws += 1;
+                let draw = self.random(state.draws);
+                if let Some(column) = table.columns.get_mut(&column_name) {
+                    column.observe(&value, self.config.reservoir_size, draw);
+                }
+            }
+        }
+        COLUMN_STATS_MEMORY_BYTES.set(state.memory_bytes as i64);
+    }
+
+    /// Takes the stats of the window since the last flush and starts a new one
+    pub fn flush(&self) -> Vec<ColumnStat> {
+        let (window_start, tables) = {
+            let mut state = self.state.lock().unwrap();
+            let window_start =
+                std::mem::replace(&mut state.window_start, chrono::Utc::now().naive_utc());
+            state.memory_bytes = 0;
+            COLUMN_STATS_MEMORY_BYTES.set(0);
+            (window_start, std::mem::take(&mut state.tables))
+        };
+        let window_end = chrono::Utc::now().naive_utc();
+        let mut stats = vec![];
+        for (table_name, table) in tables {
+            for (column_name, column) in table.columns {
+                let stat = column.finish(
+                    &table_name,
+                    &column_name,
+                    &table,
+                    self.config.top_k,
+                    window_start,
+                    window_end,
+                );
+                COLUMN_STATS
+                    .with_label_values(&[&table_name, &column_name, "distinct_estimate"])
+                    .set(stat.distinct_estimate);
+                COLUMN_STATS
+                    .with_label_values(&[&table_name, &column_name, "null_ratio_ppm"])
+                    .set((stat.null_ratio * 1_000_000.0) as i64);
+                stats.push(stat);
+            }
+        }
+        stats
+    }
+
+    /// Random u64 without pulling in an RNG: the process' random hasher keys over a counter
+    fn random(&self, draw: u64) -> u64 {
+        let mut hasher = self.random_state.build_hasher();
+        hasher.write_u64(draw);
+        hasher.finish()
+    }
+
+    /// Uniform in [0, 1)
+    fn uniform(&self, draw: u64) -> f64 {
+        (self.random(draw) >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl ColumnSketch {
+    fn new() -> Self {
+        Self {
+            registers: vec![0; HLL_REGISTERS],
+            nulls: 0,
+            reservoir: vec![],
+            values_seen: 0,
+        }
+    }
+
+    /// Upper bound of the memory a column takes, which is what the memory limit is checked
+    /// against
+    fn max_bytes(config: &ColumnStatsConfig) -> usize {
+        HLL_REGISTERS + config.reservoir_size * (MAX_VALUE_LEN + std::mem::size_of::<String>())
+    }
+
+    fn observe(&mut self, value: &serde_json::Value, reservoir_size: usize, draw: u64) {
+        let value = match value {
+            serde_json::Value::Null => {
+                self.nulls += 1;
+                return;
+            },
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+
+        // HyperLogLog over the full value
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let index = (hash >> (64 - HLL_PRECISION)) as usize;
+        let rank = ((hash << HLL_PRECISION).leading_zeros() + 1).min(64 - HLL_PRECISION + 1) as u8;
+        self.registers[index] = self.registers[index].max(rank);
+
+        // Reservoir (algorithm R) for the top K
+        self.values_seen += 1;
+        if reservoir_size == 0 {
+            return;
+        }
+        let value = truncate(value);
+        if self.reservoir.len() < reservoir_size {
+            self.reservoir.push(value);
+        } else {
+            let slot = (draw % self.values_seen) as usize;
+            if slot < reservoir_size {
+                self.reservoir[slot] = value;
+            }
+        }
+    }
+
+    fn distinct_estimate(&self) -> f64 {
+        let m = HLL_REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|register| 2f64.powi(-(*register as i32)))
+            .sum();
+        let estimate = alpha * m * m / sum;
+        let zeros = self
+            .registers
+            .iter()
+            .filter(|register| **register == 0)
+            .count();
+        if estimate <= 2.5 * m && zeros > 0 {
+            // Linear counting is more accurate for small cardinalities
+            m * (m / zeros as f64).ln()
+        } else {
+            estimate
+        }
+    }
+
+    fn finish(
+        self,
+        table_name: &str,
+        column_name: &str,
+        table: &TableSketch,
+        top_k: usize,
+        window_start: chrono::NaiveDateTime,
+        window_end: chrono::NaiveDateTime,
+    ) -> ColumnStat {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for value in &self.reservoir {
+            *counts.entry(value.as_str()).or_default() += 1;
+        }
+        let mut top_values = counts.into_iter().collect::<Vec<_>>();
+        top_values.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        top_values.truncate(top_k);
+        let top_values = top_values
+            .into_iter()
+            .map(|(value, count)| {
+                serde_json::json!({
+                    "value": value,
+                    "share": count as f64 / self.reservoir.len() as f64,
+                })
+            })
+            .collect::<Vec<_>>();
+        let observed = self.nulls + self.values_seen;
+        ColumnStat {
+            table_name: table_name.to_string(),
+            column_name: column_name.to_string(),
+            window_end,
+            window_start,
+            rows_seen: table.rows_seen as i64,
+            rows_sampled: table.rows_sampled as i64,
+            null_ratio: if observed == 0 {
+                0.0
+            } else {
+                self.nulls as f64 / observed as f64
+            },
+            distinct_estimate: if self.values_seen == 0 {
+                0
+            } else {
+                self.distinct_estimate().round() as i64
+            },
+            top_values: serde_json::Value::Array(top_values),
+        }
+    }
+}
+
+fn truncate(mut value: String) -> String {
+    if value.len() > MAX_VALUE_LEN {
+        let mut end = MAX_VALUE_LEN;
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        value.truncate(end);
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sketch_of(values: impl Iterator<Item = String>) -> ColumnSketch {
+        let mut sketch = ColumnSketch::new();
+        for (draw, value) in values.enumerate() {
+            sketch.observe(&serde_json::Value::String(value), 0, draw as u64);
+        }
+        sketch
+    }
+
+    #[test]
+    fn test_distinct_estimate() {
+        // Small enough for linear counting, every value seen 10 times
+        let sketch = sketch_of((0..1000).map(|i| format!("0x1::type::T{}", i % 100)));
+        assert!((sketch.distinct_estimate() - 100.0).abs() < 5.0);
+
+        let sketch = sketch_of((0..50_000).map(|i| format!("0x1::type::T{}", i)));
+        let error = (sketch.distinct_estimate() - 50_000.0).abs() / 50_000.0;
+        assert!(error < 0.05, "error {}", error);
+    }
+
+    #[test]
+    fn test_top_values() {
+        let collector = ColumnStatsCollector::new(ColumnStatsConfig {
+            enabled: true,
+            top_k: 2,
+            tables: HashMap::from([("events".to_string(), 1.0)]),
+            ..ColumnStatsConfig::default()
+        });
+        // Fewer values than the reservoir holds, so all of them are counted
+        let rows = (0..1000)
+            .map(|i| {
+                let event_type = match i % 10 {
+                    0..=5 => "0x1::coin::DepositEvent",
+                    6..=8 => "0x1::coin::WithdrawEvent",
+                    _ => "0x1::account::CoinRegisterEvent",
+                };
+                serde_json::json!({ "type": event_type, "data": null })
+            })
+            .collect::<Vec<_>>();
+        collector.observe("events", &rows);
+        collector.observe("transactions", &rows);
+
+        let stats = collector.flush();
+        assert_eq!(stats.len(), 2);
+        let event_type = stats
+            .iter()
+            .find(|stat| stat.column_name == "type")
+            .unwrap();
+        assert_eq!(event_type.table_name, "events");
+        assert_eq!(
+            (event_type.rows_seen, event_type.rows_sampled),
+            (1000, 1000)
+        );
+        assert_eq!(event_type.distinct_estimate, 3);
+        assert_eq!(event_type.null_ratio, 0.0);
+        assert_eq!(
+            event_type.top_values,
+            serde_json::json!([
+                { "value": "0x1::coin::DepositEvent", "share": 0.6 },
+                { "value": "0x1::coin::WithdrawEvent", "share": 0.3 },
+            ])
+        );
+        let data = stats
+            .iter()
+            .find(|stat| stat.column_name == "data")
+            .unwrap();
+        assert_eq!((data.null_ratio, data.distinct_estimate), (1.0, 0));
+        assert!(collector.flush().is_empty());
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(
+            truncate("0x1::coin::CoinStore".to_string()),
+            "0x1::coin::CoinStore"
+        );
+        assert_eq!(truncate("a".repeat(100)), "a".repeat(MAX_VALUE_LEN));
+        // 'é' is two bytes, the 64th byte is in the middle of one
+        let value = format!("a{}", "é".repeat(40));
+        assert_eq!(truncate(value), format!("a{}", "é".repeat(31)));
+    }
+}
@@ -3,10 +3,1652 @@ use std::fs;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+use crate::{
+    custom::{
+        driver::{
+            duplicate_transactions::DuplicatePolicy, ledger_reset::ResetPolicy,
+            redaction::RedactionRules, validation::Policy,
+        },
+        processors::{custom_default_processor, processor_registry},
+    },
+    models::dex_models::protocols::DexProtocol,
+    strictness::Strictness,
+};
+
+/// Where the driver looks for its config, relative to the aptos-core checkout.
+pub const DEFAULT_CONFIG_PATH: &str = "crates/indexer/config.json";
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct DriverConfig {
     pub kafka: HashMap<String, String>,
     pub topics: HashMap<String, String>,
+    /// Run side by side, each with its own watermark. See `processors::processor_registry`.
+    #[serde(default = "default_processors")]
+    pub processors: Vec<String>,
+    /// The chain the node must be on, checked at startup. See `driver::envelope`.
+    #[serde(default)]
+    pub chain_id: Option<u8>,
+    #[serde(default)]
+    pub preflight: PreflightConfig,
+    #[serde(default)]
+    pub key_salting: KeySaltingConfig,
+    #[serde(default)]
+    pub dex: DexConfig,
+    #[serde(default)]
+    pub ans: AnsConfig,
+    #[serde(default)]
+    pub priority_lane: PriorityLaneConfig,
+    #[serde(default)]
+    pub fetcher_recording: FetcherRecordingConfig,
+    #[serde(default)]
+    pub column_stats: ColumnStatsConfig,
+    #[serde(default)]
+    pub enrichment: EnrichmentConfig,
+    #[serde(default)]
+    pub api_strictness: ApiStrictnessConfig,
+    #[serde(default)]
+    pub alerts: AlertsConfig,
+    #[serde(default)]
+    pub consumer_lag: ConsumerLagConfig,
+    #[serde(default)]
+    pub backfill_guard: BackfillGuardConfig,
+    #[serde(default)]
+    pub object_ownership: ObjectOwnershipConfig,
+    #[serde(default)]
+    pub validation: ValidationConfig,
+    #[serde(default)]
+    pub publisher_serialization: SerializationConfig,
+    #[serde(default)]
+    pub ledger_reset: LedgerResetConfig,
+    #[serde(default)]
+    pub change_feed: ChangeFeedConfig,
+    #[serde(default)]
+    pub range_hash: RangeHashConfig,
+    #[serde(default)]
+    pub index_advisor: IndexAdvisorConfig,
+    #[serde(default)]
+    pub fetch_budget: FetchBudgetConfig,
+    #[serde(default)]
+    pub retry_budget: RetryBudgetConfig,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+    #[serde(default)]
+    pub sharding: ShardingConfig,
+    #[serde(default)]
+    pub replication_lag: ReplicationLagConfig,
+    #[serde(default)]
+    pub operations: OperationsConfig,
+    #[serde(default)]
+    pub payload_schemas: PayloadSchemaConfig,
+    #[serde(default)]
+    pub duplicate_transactions: DuplicateTransactionsConfig,
+    #[serde(default)]
+    pub shadow: ShadowConfig,
+    #[serde(default)]
+    pub entry_function_stats: EntryFunctionStatsConfig,
+    #[serde(default)]
+    pub redaction: RedactionConfig,
+    #[serde(default)]
+    pub standby: StandbyConfig,
+    #[serde(default)]
+    pub storage_usage: StorageUsageConfig,
+    #[serde(default)]
+    pub app_scope: AppScopeConfig,
+    #[serde(default)]
+    pub row_limits: RowLimitsConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub replay_cache: ReplayCacheConfig,
+    #[serde(default)]
+    pub asset_transfers: AssetTransfersConfig,
+    #[serde(default)]
+    pub sink: SinkConfig,
+    #[serde(default)]
+    pub read_cache: ReadCacheConfig,
+    #[serde(default)]
+    pub publish_filter: PublishFilterConfig,
+    #[serde(default)]
+    pub publish_retry: PublishRetryConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub publish_dead_letter: PublishDeadLetterConfig,
+    #[serde(default)]
+    pub partition_key: PartitionKeyConfig,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    #[serde(default)]
+    pub version_guard: VersionGuardConfig,
+    #[serde(default)]
+    pub ordered_commit: OrderedCommitConfig,
+    #[serde(default)]
+    pub batch_retry: BatchRetryConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub publish_dedupe: PublishDedupeConfig,
+    #[serde(default)]
+    pub db_pools: DbPoolsConfig,
+    #[serde(default)]
+    pub publisher_backpressure: PublisherBackpressureConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+}
+
+fn default_processors() -> Vec<String> {
+    vec![custom_default_processor::NAME.to_string()]
+}
+
+/// Startup checks run before the watermark is loaded. See `driver::preflight`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct PreflightConfig {
+    pub enabled: bool,
+    /// Topic set aside for the canary, none is produced without one. The configured topics are
+    /// only checked for on the broker.
+    pub canary_topic: Option<String>,
+    /// Read the canary back to verify consume permissions as well.
+    pub consume_canary: bool,
+    pub timeout_millis: u64,
+}
+
+impl Default for PreflightConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            canary_topic: None,
+            consume_canary: false,
+            timeout_millis: 10_000,
+        }
+    }
+}
+
+/// Salting of hot message keys. See `driver::salting`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct KeySaltingConfig {
+    pub enabled: bool,
+    /// Keys that are always salted, e.g. "0x1"
+    pub hot_keys: Vec<String>,
+    /// Number of partitions a hot key is spread over
+    pub salt_factor: u64,
+    /// Also salt any key above this share of the last `window_size` messages
+    pub auto_share_threshold: Option<f64>,
+    pub window_size: usize,
+}
+
+impl Default for KeySaltingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            hot_keys: vec![],
+            salt_factor: 8,
+            auto_share_threshold: None,
+            window_size: 100_000,
+        }
+    }
+}
+
+/// Early publishing of transactions touching watched addresses. See `driver::priority`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct PriorityLaneConfig {
+    pub enabled: bool,
+    pub watched_addresses: Vec<String>,
+    /// One address per line, re-read every `reload_interval_secs`
+    pub watched_addresses_file: Option<String>,
+    pub reload_interval_secs: u64,
+}
+
+impl Default for PriorityLaneConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            watched_addresses: vec![],
+            watched_addresses_file: None,
+            reload_interval_secs: 10,
+        }
+    }
+}
+
+/// Record fetched batches to `path`, or replay them from it instead of the fullnode. See
+/// `indexer::recording`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct FetcherRecordingConfig {
+    pub mode: RecordingMode,
+    pub path: String,
+    /// Simulated fetch latency per replayed batch
+    pub replay_latency_millis: u64,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingMode {
+    Off,
+    Record,
+    Replay,
+}
+
+impl Default for FetcherRecordingConfig {
+    fn default() -> Self {
+        Self {
+            mode: RecordingMode::Off,
+            path: "crates/indexer/recording.aptrec".to_string(),
+            replay_latency_millis: 0,
+        }
+    }
+}
+
+/// Sampled statistics of the inserted rows. See `driver::column_stats`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ColumnStatsConfig {
+    pub enabled: bool,
+    pub flush_interval_secs: u64,
+    /// Values kept per column to compute the top values from
+    pub reservoir_size: usize,
+    pub top_k: usize,
+    /// Columns beyond this aren't tracked until the next flush
+    pub max_memory_bytes: usize,
+    /// Table name to the share of its rows that are sampled, e.g. `{"coin_activities": 0.01}`
+    pub tables: HashMap<String, f64>,
+}
+
+impl Default for ColumnStatsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            flush_interval_secs: 300,
+            reservoir_size: 1000,
+            top_k: 10,
+            max_memory_bytes: 64 * 1024 * 1024,
+            tables: HashMap::new(),
+        }
+    }
+}
+
+/// Backfills of already indexed rows. See `custom::enrichment`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct EnrichmentConfig {
+    pub enabled: bool,
+    /// Names of the enrichers to run, e.g. `token_properties_v2`
+    pub enrichers: Vec<String>,
+    pub batch_size: i64,
+    /// Rows looked at per second and enricher, 0 for no limit
+    pub max_rows_per_sec: u64,
+}
+
+impl Default for EnrichmentConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            enrichers: vec![],
+            batch_size: 1000,
+            max_rows_per_sec: 2000,
+        }
+    }
+}
+
+impl EnrichmentConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for name in &self.enrichers {
+            if !crate::custom::enrichment::is_supported(name) {
+                anyhow::bail!("Enricher {} is unsupported", name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks of the Move data from the node against the indexer's types. See `strictness`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ApiStrictnessConfig {
+    pub level: Strictness,
+    /// With `warn`, check one in this many parses of each type
+    pub sample_every: u64,
+    /// `data_type.field_path` entries that aren't reported, e.g. known drift
+    pub ignored_paths: Vec<String>,
+}
+
+impl Default for ApiStrictnessConfig {
+    fn default() -> Self {
+        Self {
+            level: Strictness::Lenient,
+            sample_every: 100,
+            ignored_paths: vec![],
+        }
+    }
+}
+
+/// Where alerts go besides the logs and metrics. See `driver::alerts`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct AlertsConfig {
+    /// Alerts are POSTed here as JSON if set
+    pub webhook_url: Option<String>,
+    pub timeout_millis: u64,
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        Self {
+            webhook_url: None,
+            timeout_millis: 5000,
+        }
+    }
+}
+
+/// Throttling on the lag of a downstream consumer group. See `driver::consumer_lag`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ConsumerLagConfig {
+    pub enabled: bool,
+    pub consumer_group: String,
+    /// Keys of `topics` whose lag counts, all of them if empty
+    pub topics: Vec<String>,
+    pub poll_interval_secs: u64,
+    pub timeout_millis: u64,
+    /// Lag up to which the processors aren't throttled
+    pub lag_threshold: i64,
+    /// Lag from which the processors run at `min_throttle_factor`
+    pub max_lag: i64,
+    pub min_throttle_factor: f64,
+}
+
+impl Default for ConsumerLagConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            consumer_group: String::new(),
+            topics: vec![],
+            poll_interval_secs: 30,
+            timeout_millis: 10000,
+            lag_threshold: 100_000,
+            max_lag: 1_000_000,
+            min_throttle_factor: 0.1,
+        }
+    }
+}
+
+/// Which batches may overwrite existing rows. See `driver::backfill_guard`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct BackfillGuardConfig {
+    /// Overwrite conflicting rows at any version, as before the guard
+    pub allow_unguarded_overwrites: bool,
+    pub refresh_interval_secs: u64,
+    /// Lifetime of the window registered for a `starting_version` below the watermark
+    pub starting_version_window_hours: u64,
+}
+
+impl Default for BackfillGuardConfig {
+    fn default() -> Self {
+        Self {
+            allow_unguarded_overwrites: false,
+            refresh_interval_secs: 30,
+            starting_version_window_hours: 24,
+        }
+    }
+}
+
+/// Pool serializing published messages. See `driver::serialization`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct SerializationConfig {
+    pub threads: usize,
+    /// Messages serialized before they're produced, and so buffers in use at once
+    pub chunk_size: usize,
+    /// Idle buffers kept for reuse, the rest are freed
+    pub max_pooled_buffers: usize,
+    /// Encoding of transactions, events and write set changes, other models are always JSON
+    pub format: SerializationFormat,
+    /// Where the schemas of the `avro` format are registered
+    pub schema_registry: SchemaRegistryConfig,
+}
+
+impl Default for SerializationConfig {
+    fn default() -> Self {
+        Self {
+            threads: 2,
+            chunk_size: 256,
+            max_pooled_buffers: 512,
+            format: SerializationFormat::Json,
+            schema_registry: SchemaRegistryConfig::default(),
+        }
+    }
+}
+
+impl SerializationConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.format == SerializationFormat::Avro {
+            if !cfg!(feature = "avro") {
+                anyhow::bail!("The avro format needs the avro feature");
+            }
+            if self.schema_registry.url.is_empty() {
+                anyhow::bail!("The avro format needs a schema_registry url");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SerializationFormat {
+    #[default]
+    Json,
+    /// The messages of `proto/published.proto`, see `driver::publisher::proto`
+    Protobuf,
+    /// The same messages as Avro records, needs the `avro` feature. See `driver::schema_registry`.
+    Avro,
+}
+
+/// A Confluent compatible schema registry. See `driver::schema_registry`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct SchemaRegistryConfig {
+    pub url: String,
+    /// Basic auth, e.g. the API key and secret of a managed registry
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Registers the schemas the registry doesn't have yet, otherwise they're only looked up
+    pub auto_register: bool,
+    pub timeout_millis: u64,
+}
+
+impl Default for SchemaRegistryConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            username: None,
+            password: None,
+            auto_register: true,
+            timeout_millis: 10_000,
+        }
+    }
+}
+
+/// Checks of the processors' output before commit. See `driver::validation`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ValidationConfig {
+    pub enabled: bool,
+    /// Policy by rule name, overriding the rule's own
+    pub policies: HashMap<String, Policy>,
+    pub disabled_rules: Vec<String>,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            policies: HashMap::new(),
+            disabled_rules: vec![],
+        }
+    }
+}
+
+/// Bounds of `custom_object_processor`'s owner resolution. See `models::object_ownership`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ObjectOwnershipConfig {
+    /// Chains with more edges than this are left unresolved
+    pub max_depth: i64,
+    /// Descendants re-resolved per batch, the rest keep a stale ultimate owner
+    pub max_propagation: usize,
+}
+
+impl Default for ObjectOwnershipConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: 8,
+            max_propagation: 10_000,
+        }
+    }
+}
+
+/// Protocols indexed by `custom_dex_processor`. See `models::dex_models::protocols`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct DexConfig {
+    /// Index the protocols shipped with the indexer (liquidswap, pancakeswap)
+    pub include_builtin: bool,
+    pub protocols: Vec<DexProtocol>,
+}
+
+impl Default for DexConfig {
+    fn default() -> Self {
+        Self {
+            include_builtin: true,
+            protocols: vec![],
+        }
+    }
+}
+
+impl DexConfig {
+    pub fn protocols(&self) -> Vec<DexProtocol> {
+        let mut protocols = if self.include_builtin {
+            DexProtocol::builtin()
+        } else {
+            vec![]
+        };
+        protocols.extend(self.protocols.iter().cloned());
+        protocols
+    }
+}
+
+/// The Aptos Names contract of `custom_ans_processor`. See `models::token_models::ans_lookup`.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct AnsConfig {
+    /// Address the contract is published at, which differs across networks. Falls back to the
+    /// node config's `ans_contract_address`; without either, nothing is indexed.
+    pub contract_address: Option<String>,
+}
+
+/// What to do when the node's genesis differs from the indexed one. See `driver::ledger_reset`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct LedgerResetConfig {
+    pub enabled: bool,
+    pub policy: ResetPolicy,
+    /// Required by the `wipe` policy, which otherwise halts like `halt`
+    pub allow_auto_reset: bool,
+    /// Tables kept on a wipe besides the indexer's own bookkeeping
+    pub preserved_tables: Vec<String>,
+}
+
+impl Default for LedgerResetConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            policy: ResetPolicy::Halt,
+            allow_auto_reset: false,
+            preserved_tables: vec![],
+        }
+    }
+}
+
+/// Entries of written rows for polling consumers. See `driver::change_feed`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ChangeFeedConfig {
+    pub enabled: bool,
+    pub retention_hours: u64,
+    pub prune_interval_secs: u64,
+}
+
+impl Default for ChangeFeedConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retention_hours: 72,
+            prune_interval_secs: 300,
+        }
+    }
+}
+
+/// Hashes of completed version ranges. See `driver::range_hash`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct RangeHashConfig {
+    pub enabled: bool,
+    pub range_size: u64,
+    /// Tables hashed once each processor committed a range, by processor name
+    pub tables: HashMap<String, Vec<String>>,
+    /// Manifests kept for `range_hash::status`
+    pub keep_recent: usize,
+}
+
+impl Default for RangeHashConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            range_size: 100_000,
+            tables: HashMap::new(),
+            keep_recent: 16,
+        }
+    }
+}
+
+/// Sampling of the query helpers' plans. See `queries::index_advisor`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct IndexAdvisorConfig {
+    /// One helper call in this many is planned with `EXPLAIN`, 0 turns recording off
+    pub sample_every: u64,
+}
+
+impl Default for IndexAdvisorConfig {
+    fn default() -> Self {
+        Self { sample_every: 100 }
+    }
+}
+
+/// Fetch sizes by bytes rather than versions. See `indexer::fetcher::FetchBudget`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct FetchBudgetConfig {
+    pub enabled: bool,
+    pub target_bytes: u64,
+    pub hard_cap_bytes: u64,
+    pub window: usize,
+}
+
+impl Default for FetchBudgetConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            target_bytes: 16 * 1024 * 1024,
+            hard_cap_bytes: 64 * 1024 * 1024,
+            window: 20,
+        }
+    }
+}
+
+/// Limits on the retries of a batch. See `driver::retry_budget`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct RetryBudgetConfig {
+    pub enabled: bool,
+    /// Retries of any class
+    pub max_attempts: u32,
+    /// Since the batch started, after which nothing is retried
+    pub max_wall_clock_secs: u64,
+    /// By error class: `fetch`, `db_connection`, `db_query` or `publish`
+    pub max_attempts_per_class: HashMap<String, u32>,
+}
+
+impl Default for RetryBudgetConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_attempts: 20,
+            max_wall_clock_secs: 120,
+            max_attempts_per_class: HashMap::from([
+                ("fetch".to_string(), 3),
+                ("db_connection".to_string(), 5),
+                ("db_query".to_string(), 10),
+                ("publish".to_string(), 10),
+            ]),
+        }
+    }
+}
+
+/// Health states of the processors by lag and error rate. See `driver::circuit_breaker`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    pub enabled: bool,
+    /// Versions behind the ledger from which a processor is degraded
+    pub degraded_lag: u64,
+    /// Versions behind the ledger from which a processor is critical
+    pub critical_lag: u64,
+    /// Fetch and connection errors per minute from which the processors are degraded
+    pub degraded_errors_per_minute: f64,
+    /// Fetch and connection errors per minute from which the processors are critical
+    pub critical_errors_per_minute: f64,
+    /// Window the error rate is measured over
+    pub error_window_secs: u64,
+    /// How long a worse state's thresholds must be crossed before moving to it
+    pub escalate_after_secs: u64,
+    /// How long a better state's thresholds must hold before moving back to it
+    pub recover_after_secs: u64,
+    /// Share of the thresholds the lag and error rate must drop under to recover, so that a
+    /// processor hovering at a threshold doesn't flap
+    pub recovery_ratio: f64,
+    /// Status code of each state, for a health endpoint to answer with
+    pub status_codes: HashMap<String, u16>,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            degraded_lag: 100_000,
+            critical_lag: 1_000_000,
+            degraded_errors_per_minute: 10.0,
+            critical_errors_per_minute: 60.0,
+            error_window_secs: 300,
+            escalate_after_secs: 300,
+            recover_after_secs: 600,
+            recovery_ratio: 0.8,
+            status_codes: HashMap::from([
+                ("healthy".to_string(), 200),
+                ("degraded".to_string(), 207),
+                ("critical".to_string(), 503),
+            ]),
+        }
+    }
+}
+
+impl CircuitBreakerConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.degraded_lag == 0 || self.degraded_lag >= self.critical_lag {
+            anyhow::bail!("degraded_lag must be positive and below critical_lag");
+        }
+        if !(self.degraded_errors_per_minute > 0.0
+            && self.degraded_errors_per_minute < self.critical_errors_per_minute)
+        {
+            anyhow::bail!(
+                "degraded_errors_per_minute must be positive and below critical_errors_per_minute"
+            );
+        }
+        if self.error_window_secs == 0 {
+            anyhow::bail!("error_window_secs must be positive");
+        }
+        if !(self.recovery_ratio > 0.0 && self.recovery_ratio <= 1.0) {
+            anyhow::bail!("recovery_ratio must be in (0, 1]");
+        }
+        for state in ["healthy", "degraded", "critical"] {
+            match self.status_codes.get(state) {
+                Some(code) if (100..600).contains(code) => {},
+                Some(code) => anyhow::bail!("status code {} of {} isn't valid", code, state),
+                None => anyhow::bail!("status_codes has no code for {}", state),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The share of the versions this instance processes. See `driver::sharding`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ShardingConfig {
+    pub enabled: bool,
+    pub shard_count: u64,
+    /// From 0 to `shard_count - 1`, different on every instance
+    pub shard_index: u64,
+    /// Versions per slice, the unit shards take turns on
+    pub slice_size: u64,
+    /// Stop fetching at this version, to change the shard count
+    pub drain_at_version: Option<u64>,
+}
+
+impl Default for ShardingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            shard_count: 1,
+            shard_index: 0,
+            slice_size: 10_000,
+            drain_at_version: None,
+        }
+    }
+}
+
+impl ShardingConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.shard_count == 0 || self.shard_index >= self.shard_count {
+            anyhow::bail!("shard_index must be below shard_count");
+        }
+        if self.slice_size == 0 {
+            anyhow::bail!("slice_size must be positive");
+        }
+        Ok(())
+    }
+}
+
+/// Pausing while the Postgres replicas lag behind. See `driver::replication_lag`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ReplicationLagConfig {
+    pub enabled: bool,
+    /// Statement run on the indexer's database returning the lag as a `lag_secs` float8 column
+    pub source_sql: Option<String>,
+    /// Endpoint answering a GET with the lag in seconds as a bare number
+    pub source_url: Option<String>,
+    pub poll_interval_secs: u64,
+    pub timeout_millis: u64,
+    /// Lag above which fetching and processing pause
+    pub pause_threshold_secs: f64,
+    /// Lag below which they resume
+    pub resume_threshold_secs: f64,
+    /// Failed queries in a row after which a pause is lifted
+    pub max_consecutive_errors: u64,
+}
+
+impl Default for ReplicationLagConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            source_sql: None,
+            source_url: None,
+            poll_interval_secs: 10,
+            timeout_millis: 5000,
+            pause_threshold_secs: 60.0,
+            resume_threshold_secs: 15.0,
+            max_consecutive_errors: 3,
+        }
+    }
+}
+
+impl ReplicationLagConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.source_sql.is_some() == self.source_url.is_some() {
+            anyhow::bail!("exactly one of source_sql and source_url must be set");
+        }
+        if self.resume_threshold_secs >= self.pause_threshold_secs {
+            anyhow::bail!("resume_threshold_secs must be below pause_threshold_secs");
+        }
+        if self.max_consecutive_errors == 0 {
+            anyhow::bail!("max_consecutive_errors must be positive");
+        }
+        Ok(())
+    }
+}
+
+/// Lifecycle events of long-running tasks. See `driver::operations`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct OperationsConfig {
+    pub enabled: bool,
+    /// Emit a progress event every time an operation gets this much further
+    pub progress_step_percent: u64,
+}
+
+impl Default for OperationsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            progress_step_percent: 10,
+        }
+    }
+}
+
+/// Versions of the published payloads per topic. See `driver::payload_schema`.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct PayloadSchemaConfig {
+    /// Version to publish by topic key, the models' current one if unset
+    pub pinned_versions: HashMap<String, u32>,
+    /// Topic keys that also publish the other of the current and previous version, to
+    /// `<topic>.v<version>`
+    pub dual_publish: Vec<String>,
+}
+
+/// Detection of a sender's sequence number used at two versions. See
+/// `driver::duplicate_transactions`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct DuplicateTransactionsConfig {
+    pub enabled: bool,
+    pub policy: DuplicatePolicy,
+    /// `(sender, sequence_number)` pairs remembered before looking them up in `user_transactions`
+    pub cache_size: usize,
+}
+
+impl Default for DuplicateTransactionsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            policy: DuplicatePolicy::KeepBoth,
+            cache_size: 1_000_000,
+        }
+    }
+}
+
+/// Shadow processors diffed against the primary ones. See `driver::shadow`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ShadowConfig {
+    pub enabled: bool,
+    /// Run the shadow on every Nth batch only, bounding its CPU
+    pub sample_every: u64,
+    /// Rows of `shadow_diffs` per processor past which differences are only counted
+    pub max_stored_diffs: i64,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sample_every: 10,
+            max_stored_diffs: 10_000,
+        }
+    }
+}
+
+/// Daily calls per entry function. See `driver::entry_function_stats`.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct EntryFunctionStatsConfig {
+    pub enabled: bool,
+}
+
+/// Redaction of payload arguments and event data before they're stored or published. See
+/// `driver::redaction`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct RedactionConfig {
+    pub enabled: bool,
+    pub rules: RedactionRules,
+    /// More rules in the same shape as `rules`, re-read every `reload_interval_secs`
+    pub rules_file: Option<String>,
+    pub reload_interval_secs: u64,
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rules: RedactionRules::default(),
+            rules_file: None,
+            reload_interval_secs: 10,
+        }
+    }
+}
+
+/// Leader lease with warm standby instances. See `driver::standby`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct StandbyConfig {
+    pub enabled: bool,
+    /// Name of this instance in the lease, the host name and process id if unset
+    pub instance_id: Option<String>,
+    /// Longer than the slowest round of batches, or a standby takes over a busy leader
+    pub lease_ttl_secs: u64,
+    /// How often a standby tries to acquire the lease
+    pub poll_interval_millis: u64,
+    /// How far a standby fetches past the leader's watermark, and how far behind it it may fall
+    /// before restarting its fetcher at the watermark
+    pub max_lead_versions: u64,
+}
+
+impl Default for StandbyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            instance_id: None,
+            lease_ttl_secs: 30,
+            poll_interval_millis: 500,
+            max_lead_versions: 100_000,
+        }
+    }
+}
+
+/// Storage fees per user transaction and running storage deposits per account. See
+/// `driver::storage_usage`.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct StorageUsageConfig {
+    pub enabled: bool,
+}
+
+/// Indexing only the data of the apps published at `addresses`. See `driver::app_scope`.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct AppScopeConfig {
+    pub enabled: bool,
+    /// Module addresses of the apps, e.g. `0x1` or the full 32 bytes.
+    pub addresses: Vec<String>,
+    /// Addresses added since the processor last ran are backfilled from this version.
+    pub backfill_from_version: u64,
+}
+
+/// Size limits of indexed columns, checked before rows are inserted. See `driver::row_limits`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct RowLimitsConfig {
+    pub enabled: bool,
+    /// Per `table.column`, replaces the limit registered with the table's model.
+    pub overrides: HashMap<String, ColumnLimitOverride>,
+}
+
+impl Default for RowLimitsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            overrides: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct ColumnLimitOverride {
+    pub max_bytes: Option<usize>,
+    pub action: Option<OversizeAction>,
+}
+
+/// What happens to a row with a value over its column's limit
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizeAction {
+    /// Cut the value to fit, for display-only columns
+    Truncate,
+    /// Leave the row out, storing it in `dead_letter_rows`
+    DeadLetter,
+    /// Fail the batch
+    Fail,
+}
+
+/// HTTP server launching, polling and cancelling operations. See `driver::admin`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct AdminConfig {
+    pub enabled: bool,
+    pub listen_address: String,
+    /// sha256 of each caller's bearer token, hex, by the caller's name
+    pub callers: HashMap<String, String>,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_address: "127.0.0.1:8090".to_string(),
+            callers: HashMap::new(),
+        }
+    }
+}
+
+/// Fingerprints of the messages delivered for the last versions, skipped when produced again after
+/// a restart. See `driver::replay_cache`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ReplayCacheConfig {
+    pub enabled: bool,
+    /// Directory of the cache files, one per processor or shard
+    pub path: String,
+    /// Fingerprints are kept for this many versions below the last batch
+    pub window_versions: u64,
+    /// Fingerprints kept at most, the oldest are dropped first
+    pub max_entries: usize,
+    /// How long a batch waits for its messages to be delivered before its fingerprints are kept
+    pub flush_timeout_millis: u64,
+}
+
+impl Default for ReplayCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: "crates/indexer/replay_cache".to_string(),
+            window_versions: 10_000,
+            max_entries: 200_000,
+            flush_timeout_millis: 10_000,
+        }
+    }
+}
+
+/// Transfers normalized across coin, fungible asset and token standards. See
+/// `driver::asset_transfers`.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct AssetTransfersConfig {
+    pub enabled: bool,
+}
+
+/// Where `custom_default_processor` and `custom_coin_processor` write the rows they parse: Kafka,
+/// Postgres or both.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct SinkConfig {
+    pub mode: SinkMode,
+    /// Where what's published goes. See `driver::sink`.
+    pub backend: SinkBackend,
+    pub http: HttpSinkConfig,
+    /// Publishes to each of these in order instead of `backend`, if any. See `driver::multi_sink`.
+    pub fan_out: Vec<FanOutSinkConfig>,
+    pub object_store: ObjectStoreSinkConfig,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkBackend {
+    #[default]
+    Kafka,
+    Http,
+    /// Needs the `object_store_sink` feature
+    ObjectStore,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct FanOutSinkConfig {
+    pub backend: SinkBackend,
+    #[serde(default)]
+    pub policy: SinkPolicy,
+}
+
+/// What a sink failing to take a batch does to the batch
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkPolicy {
+    /// Fails it
+    #[default]
+    Required,
+    /// Nothing, the failure is logged and counted
+    BestEffort,
+}
+
+impl SinkConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut backends = self
+            .fan_out
+            .iter()
+            .map(|sink| sink.backend)
+            .collect::<Vec<_>>();
+        if backends.is_empty() {
+            backends.push(self.backend);
+        } else if !self
+            .fan_out
+            .iter()
+            .any(|sink| sink.policy == SinkPolicy::Required)
+        {
+            anyhow::bail!("fan_out needs at least one required sink");
+        }
+        for (i, backend) in backends.iter().enumerate() {
+            if backends[..i].contains(backend) {
+                anyhow::bail!("fan_out lists {} more than once", backend.as_str());
+            }
+        }
+        if backends.contains(&SinkBackend::Http) {
+            self.http.validate()?;
+        }
+        if backends.contains(&SinkBackend::ObjectStore) {
+            if !cfg!(feature = "object_store_sink") {
+                anyhow::bail!("The object_store backend needs the object_store_sink feature");
+            }
+            self.object_store.validate()?;
+        }
+        Ok(())
+    }
+}
+
+impl SinkBackend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SinkBackend::Kafka => "kafka",
+            SinkBackend::Http => "http",
+            SinkBackend::ObjectStore => "object_store",
+        }
+    }
+}
+
+/// Batches written as newline-delimited JSON objects to a bucket. See
+/// `driver::object_store_sink`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ObjectStoreSinkConfig {
+    pub provider: ObjectStoreProvider,
+    pub bucket: String,
+    /// Of every key, e.g. `mainnet`
+    pub prefix: String,
+    pub compression: ObjectCompression,
+    /// Of the uncompressed JSON of an object, larger batches are split
+    pub max_object_bytes: usize,
+}
+
+/// Credentials are read from the environment, as the provider's SDKs do
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ObjectStoreProvider {
+    #[default]
+    S3,
+    Gcs,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ObjectCompression {
+    None,
+    #[default]
+    Gzip,
+}
+
+impl Default for ObjectStoreSinkConfig {
+    fn default() -> Self {
+        Self {
+            provider: ObjectStoreProvider::S3,
+            bucket: String::new(),
+            prefix: String::new(),
+            compression: ObjectCompression::Gzip,
+            max_object_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl ObjectStoreSinkConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.bucket.is_empty() {
+            anyhow::bail!("bucket must be set");
+        }
+        if self.max_object_bytes == 0 {
+            anyhow::bail!("max_object_bytes must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// POSTs of the published batches to an HTTP endpoint. See `driver::http_sink`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct HttpSinkConfig {
+    pub url: String,
+    /// Sent as `Authorization: Bearer <token>`
+    pub bearer_token: Option<String>,
+    /// Records per request
+    pub batch_size: usize,
+    pub timeout_millis: u64,
+    /// Including the first, 1 to not retry
+    pub max_attempts: u32,
+    /// Before the first retry, doubled before every next one
+    pub base_delay_millis: u64,
+    pub max_delay_millis: u64,
+    /// Also POST the events and write set changes of every batch
+    pub events: bool,
+}
+
+impl Default for HttpSinkConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            bearer_token: None,
+            batch_size: 500,
+            timeout_millis: 10_000,
+            max_attempts: 5,
+            base_delay_millis: 500,
+            max_delay_millis: 10_000,
+            events: false,
+        }
+    }
+}
+
+impl HttpSinkConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Err(err) = url::Url::parse(&self.url) {
+            anyhow::bail!("url {:?} isn't a valid URL: {}", self.url, err);
+        }
+        if self.batch_size == 0 {
+            anyhow::bail!("batch_size must be at least 1");
+        }
+        if self.max_attempts == 0 {
+            anyhow::bail!("max_attempts must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkMode {
+    #[default]
+    PublishOnly,
+    DbOnly,
+    /// The batch is committed to Postgres before it's published
+    Both,
+}
+
+impl SinkMode {
+    pub fn writes_db(self) -> bool {
+        matches!(self, SinkMode::DbOnly | SinkMode::Both)
+    }
+
+    pub fn publishes(self) -> bool {
+        matches!(self, SinkMode::PublishOnly | SinkMode::Both)
+    }
+}
+
+/// Caches of hot current rows read from Postgres. See `database::read_cache`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ReadCacheConfig {
+    pub enabled: bool,
+    /// How long an entry is kept, which bounds how stale writes of other processes can be read
+    pub ttl_millis: u64,
+    /// Entries kept at most per cache, the least recently used are dropped first
+    pub max_entries: usize,
+}
+
+impl Default for ReadCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ttl_millis: 5_000,
+            max_entries: 10_000,
+        }
+    }
+}
+
+/// Which transactions `custom_default_processor` publishes, empty for all of them. See
+/// `driver::publish_filter`.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct PublishFilterConfig {
+    /// Module addresses, e.g. `0x1` or the full 32 bytes.
+    pub module_addresses: Vec<String>,
+    /// Fully qualified entry functions, e.g. `0x1::coin::transfer`.
+    pub entry_functions: Vec<String>,
+}
+
+/// Retries of producing to and flushing Kafka. See `driver::publish_retry`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct PublishRetryConfig {
+    /// Including the first, 1 to not retry
+    pub max_attempts: u32,
+    /// Before the first retry, doubled before every next one
+    pub base_delay_millis: u64,
+    pub max_delay_millis: u64,
+}
+
+impl Default for PublishRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay_millis: 200,
+            max_delay_millis: 5_000,
+        }
+    }
+}
+
+/// HTTP server exposing the metrics to Prometheus. See `driver::metrics`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub listen_address: String,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_address: "0.0.0.0:9105".to_string(),
+        }
+    }
+}
+
+/// Dead lettering messages that can't be published instead of failing their batch. See
+/// `driver::publisher`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct PublishDeadLetterConfig {
+    pub enabled: bool,
+    /// Larger messages are dead lettered without their payload
+    pub max_payload_bytes: usize,
+}
+
+impl Default for PublishDeadLetterConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_payload_bytes: 65_536,
+        }
+    }
+}
+
+/// What transaction messages are keyed by, which decides their partition. See
+/// `driver::publisher`.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct PartitionKeyConfig {
+    pub transactions: PartitionKeyStrategy,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PartitionKeyStrategy {
+    /// The sender for user transactions, the version for the others
+    #[default]
+    SenderAddress,
+    Version,
+    TransactionHash,
+}
+
+/// Draining and flushing on SIGINT and SIGTERM. See `driver::shutdown`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    pub enabled: bool,
+    /// For the batches in flight to finish once a signal is received
+    pub drain_timeout_secs: u64,
+    /// For Kafka to ack everything published
+    pub flush_timeout_secs: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            drain_timeout_secs: 20,
+            flush_timeout_secs: 5,
+        }
+    }
+}
+
+/// Checking that every batch starts right after the last one. See `driver::version_guard`.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct VersionGuardConfig {
+    pub mode: VersionGuardMode,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionGuardMode {
+    Off,
+    /// A gap stops the processor before its watermark moves past it
+    #[default]
+    Strict,
+    /// A gap is logged and counted, and processing carries on
+    Tolerant,
+}
+
+/// Processing a round's batches in parallel and committing them in version order. See
+/// `driver::ordered_commit`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct OrderedCommitConfig {
+    pub enabled: bool,
+    /// Batches processed at once, instead of the indexer's `processor_tasks`
+    pub concurrency: u8,
+}
+
+impl Default for OrderedCommitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            concurrency: 8,
+        }
+    }
+}
+
+impl OrderedCommitConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.concurrency == 0 {
+            anyhow::bail!("concurrency must be positive");
+        }
+        Ok(())
+    }
+
+    /// Batches per round, `processor_tasks` unless enabled
+    pub fn round_tasks(&self, processor_tasks: u8) -> u8 {
+        if self.enabled {
+            self.concurrency
+        } else {
+            processor_tasks
+        }
+    }
+}
+
+/// Processing a round again after a retryable error. See `driver::batch_retry`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct BatchRetryConfig {
+    /// Failed rounds in a row after which the processor stops, 1 to never retry
+    pub max_attempts: u32,
+    pub base_delay_millis: u64,
+    pub max_delay_millis: u64,
+}
+
+impl Default for BatchRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay_millis: 1_000,
+            max_delay_millis: 30_000,
+        }
+    }
+}
+
+/// HTTP liveness, readiness and status endpoints. See `driver::health`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct HealthConfig {
+    pub enabled: bool,
+    pub listen_address: String,
+    /// `/healthz` fails once a processor hasn't finished a round for this long
+    pub stale_after_secs: u64,
+    /// How often the ledger version `/status` reports is read from the node
+    pub ledger_refresh_secs: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_address: "0.0.0.0:9106".to_string(),
+            stale_after_secs: 120,
+            ledger_refresh_secs: 10,
+        }
+    }
+}
+
+/// Skipping the versions a processor published already. See `driver::publish_dedupe`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct PublishDedupeConfig {
+    pub enabled: bool,
+    /// Publishes every version again while the last published one is still recorded
+    pub force_republish: bool,
+    /// How long a round waits for its messages to be delivered before its last version is recorded
+    pub flush_timeout_millis: u64,
+}
+
+impl Default for PublishDedupeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            force_republish: false,
+            flush_timeout_millis: 10_000,
+        }
+    }
+}
+
+/// Connection pools of the processors. See `driver::db_pools`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct DbPoolsConfig {
+    /// The processors without settings of their own share one pool instead of a pool each
+    pub shared: bool,
+    pub max_size: u32,
+    /// How long a processor waits for a connection before the attempt fails and is retried
+    pub connection_timeout_millis: u64,
+    /// Postgres `statement_timeout` of every connection, none if 0
+    pub statement_timeout_millis: u64,
+    /// By processor name, replaces the settings above for a pool of the processor's own
+    pub processors: HashMap<String, PoolOverride>,
+}
+
+impl Default for DbPoolsConfig {
+    fn default() -> Self {
+        // r2d2's defaults
+        Self {
+            shared: false,
+            max_size: 10,
+            connection_timeout_millis: 30_000,
+            statement_timeout_millis: 0,
+            processors: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct PoolOverride {
+    pub max_size: Option<u32>,
+    pub connection_timeout_millis: Option<u64>,
+    pub statement_timeout_millis: Option<u64>,
+}
+
+impl DbPoolsConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.max_size == 0 {
+            anyhow::bail!("db_pools.max_size must be at least 1");
+        }
+        for (processor, pool) in &self.processors {
+            if !processor_registry::NAMES.contains(&processor.as_str()) {
+                anyhow::bail!("db_pools.processors has unknown processor {:?}", processor);
+            }
+            if pool.max_size == Some(0) {
+                anyhow::bail!("db_pools.processors.{}.max_size must be at least 1", processor);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Blocking the publisher while its producer's queue is full. See `driver::backpressure`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct PublisherBackpressureConfig {
+    pub enabled: bool,
+    /// Messages not acked yet above which producing blocks, 80% of the producer's
+    /// `queue.buffering.max.messages` if unset
+    pub high_watermark: Option<usize>,
+    /// Messages not acked yet that producing waits for, 5/8 of the high watermark if unset, so
+    /// half the producer's queue by default
+    pub low_watermark: Option<usize>,
+    /// How long producing blocks before it fails as if the queue were full
+    pub max_wait_millis: u64,
+}
+
+impl Default for PublisherBackpressureConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            high_watermark: None,
+            low_watermark: None,
+            max_wait_millis: 60_000,
+        }
+    }
+}
+
+impl PublisherBackpressureConfig {
+    /// The high and low watermarks for a producer of `kafka`
+    pub fn watermarks(&self, kafka: &HashMap<String, String>) -> anyhow::Result<(usize, usize)> {
+        // librdkafka's default
+        let queue_size = match kafka.get("queue.buffering.max.messages") {
+            Some(size) => size
+                .parse::<usize>()
+                .map_err(|e| anyhow::anyhow!("Invalid queue.buffering.max.messages {:?}: {}", size, e))?,
+            None => 100_000,
+        };
+        let high = self.high_watermark.unwrap_or(queue_size / 5 * 4);
+        let low = self.low_watermark.unwrap_or(high / 8 * 5);
+        if high == 0 {
+            anyhow::bail!("publisher_backpressure.high_watermark must be at least 1");
+        }
+        if low > high {
+            anyhow::bail!("publisher_backpressure.low_watermark must be at most the high watermark, {}", high);
+        }
+        Ok((high, low))
+    }
+}
+
+/// Caps on the versions processed per second. See `driver::rate_limit`.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// By every processor of the process together, none if 0
+    pub max_versions_per_second: u64,
+    /// By backfills together, within the cap above, none if 0
+    pub backfill_max_versions_per_second: u64,
 }
 
 impl DriverConfig {
@@ -16,4 +1658,14 @@ impl DriverConfig {
         let res: DriverConfig = serde_json::from_str(&data).expect("Error when parsing config file content");
         return res;
     }
+
+    /// Like `read_from`, with an error instead of a panic if the file can't be read or parsed,
+    /// for a running process to re-read it
+    pub fn try_read_from(config_path: &str) -> anyhow::Result<DriverConfig> {
+        let data = fs::read_to_string(config_path)
+            .map_err(|e| anyhow::anyhow!("Unable to read config file {}: {}", config_path, e))?;
+        serde_json::from_str(&data)
+            .map_err(|e| anyhow::anyhow!("Error when parsing config file {}: {}", config_path, e))
+    }
 }
+
@@ -0,0 +1,297 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Backpressure from a downstream consumer group. Every `poll_interval_secs` the lag of the
+//! configured group on the data topics is read from the broker (committed offsets against the
+//! high watermarks). Above `lag_threshold` the processors are throttled: after each round of
+//! batches they wait so that they run at `throttle_factor` of their unthrottled rate, the factor
+//! going down linearly to `min_throttle_factor` at `max_lag` and back up as the lag shrinks. If
+//! the lag can't be read the controller fails open and doesn't throttle.
+
+use crate::{
+    counters::{CONSUMER_LAG, CONSUMER_LAG_THROTTLE_FACTOR},
+    custom::driver::{
+        config::{ConsumerLagConfig, DriverConfig},
+        producer::Producer,
+    },
+};
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use aptos_logger::{info, warn};
+use once_cell::sync::OnceCell;
+use rdkafka::{
+    consumer::{BaseConsumer, Consumer},
+    ClientConfig, Offset, TopicPartitionList,
+};
+use serde::Serialize;
+use std::{sync::Mutex, time::Duration};
+
+/// Longest wait after a round, so that a throttled processor still checks its lifecycle
+/// control regularly
+const MAX_PACING_WAIT: Duration = Duration::from_secs(60);
+
+static CONTROLLER: OnceCell<Controller> = OnceCell::new();
+
+/// Snapshot of the controller, meant for status reporting
+#[derive(Clone, Debug, Serialize)]
+pub struct ConsumerLagStatus {
+    pub consumer_group: String,
+    /// Sum over the partitions of the data topics, `None` until the first successful query
+    pub observed_lag: Option<i64>,
+    /// Share of the unthrottled rate the processors run at, 1 when not throttled
+    pub throttle_factor: f64,
+    /// Error of the last query, while failing open
+    pub last_error: Option<String>,
+}
+
+impl ConsumerLagStatus {
+    /// Throttles on a queried lag, or fails open on an error
+    fn observe(&mut self, lag: Result<i64>, config: &ConsumerLagConfig) {
+        match lag {
+            Ok(lag) => {
+                let factor = throttle_factor(
+                    lag,
+                    config.lag_threshold,
+                    config.max_lag,
+                    config.min_throttle_factor,
+                );
+                if factor != self.throttle_factor {
+                    info!(
+                        consumer_group = config.consumer_group,
+                        lag = lag,
+                        throttle_factor = factor,
+                        "Consumer lag throttle changed"
+                    );
+                }
+                self.observed_lag = Some(lag);
+                self.throttle_factor = factor;
+                self.last_error = None;
+            },
+            Err(err) => {
+                warn!(
+                    consumer_group = config.consumer_group,
+                    error = ?err,
+                    "Failed to query consumer lag, not throttling"
+                );
+                self.throttle_factor = 1.0;
+                self.last_error = Some(format!("{:#}", err));
+            },
+        }
+    }
+}
+
+struct Controller {
+    status: Mutex<ConsumerLagStatus>,
+}
+
+/// Starts polling the lag of the configured consumer group. Only the first call in a process
+/// has an effect, so every processor runtime can call it.
+pub fn init(driver_config: &DriverConfig) {
+    let config = driver_config.consumer_lag.clone();
+    if !config.enabled || CONTROLLER.get().is_some() {
+        return;
+    }
+    let topics = if config.topics.is_empty() {
+        driver_config.topics.values().cloned().collect::<Vec<_>>()
+    } else {
+        config
+            .topics
+            .iter()
+            .map(|topic_key| {
+                driver_config
+                    .topics
+                    .get(topic_key)
+                    .cloned()
+                    .unwrap_or_else(|| panic!("consumer_lag topic {} isn't configured", topic_key))
+            })
+            .collect()
+    };
+    let controller = Controller {
+        status: Mutex::new(ConsumerLagStatus {
+            consumer_group: config.consumer_group.clone(),
+            observed_lag: None,
+            throttle_factor: 1.0,
+            last_error: None,
+        }),
+    };
+    if CONTROLLER.set(controller).is_err() {
+        return;
+    }
+    info!(
+        consumer_group = config.consumer_group,
+        topics = format!("{:?}", topics),
+        "Watching consumer lag"
+    );
+    CONSUMER_LAG_THROTTLE_FACTOR.set(1.0);
+
+    let mut client_config = Producer::new(driver_config.kafka.clone()).client_config();
+    client_config
+        .set("group.id", &config.consumer_group)
+        .set("enable.auto.commit", "false");
+    let interval = Duration::from_secs(config.poll_interval_secs.max(1));
+    let timeout = Duration::from_millis(config.timeout_millis);
+    tokio::spawn(async move {
+        loop {
+            let query_config = client_config.clone();
+            let query_topics = topics.clone();
+            let lag = tokio::task::spawn_blocking(move || {
+                query_lag(&query_config, &query_topics, timeout)
+            })
+            .await
+            .map_err(|e| anyhow!("Lag query panicked: {}", e))
+            .and_then(|lag| lag);
+
+            let mut status = CONTROLLER.get().unwrap().status.lock().unwrap();
+            if let Ok(lag) = &lag {
+                CONSUMER_LAG.set(*lag);
+            }
+            status.observe(lag, &config);
+            CONSUMER_LAG_THROTTLE_FACTOR.set(status.throttle_factor);
+            drop(status);
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
+pub fn status() -> Option<ConsumerLagStatus> {
+    CONTROLLER
+        .get()
+        .map(|controller| controller.status.lock().unwrap().clone())
+}
+
+/// Waits after a round of batches that took `round_duration`, so that the processor runs at the
+/// throttle factor of its rate
+pub async fn pace(round_duration: Duration) {
+    let factor = match status() {
+        Some(status) if status.throttle_factor < 1.0 => status.throttle_factor,
+        _ => return,
+    };
+    let wait = round_duration.mul_f64(1.0 / factor - 1.0);
+    tokio::time::sleep(wait.min(MAX_PACING_WAIT)).await;
+}
+
+/// 1 up to `lag_threshold`, then linearly down to `min_factor` at `max_lag`
+fn throttle_factor(lag: i64, lag_threshold: i64, max_lag: i64, min_factor: f64) -> f64 {
+    let min_factor = min_factor.clamp(0.01, 1.0);
+    if lag <= lag_threshold {
+        return 1.0;
+    }
+    if lag >= max_lag || max_lag <= lag_threshold {
+        return min_factor;
+    }
+    let over = (lag - lag_threshold) as f64 / (max_lag - lag_threshold) as f64;
+    1.0 - over * (1.0 - min_factor)
+}
+
+/// Messages on the topics not yet committed by the client config's group. Partitions the group
+/// never committed count from the low watermark.
+fn query_lag(client_config: &ClientConfig, topics: &[String], timeout: Duration) -> Result<i64> {
+    let consumer: BaseConsumer = client_config
+        .create()
+        .context("Failed to create consumer")?;
+    let mut partitions = TopicPartitionList::new();
+    for topic in topics {
+        let metadata = consumer
+            .fetch_metadata(Some(topic), timeout)
+            .with_context(|| format!("Failed to fetch metadata of {}", topic))?;
+        for metadata_topic in metadata.topics() {
+            for partition in metadata_topic.partitions() {
+                partitions.add_partition(topic, partition.id());
+            }
+        }
+    }
+    let committed = consumer
+        .committed_offsets(partitions, timeout)
+        .context("Failed to fetch committed offsets")?;
+    let mut lag = 0;
+    for element in committed.elements() {
+        let (low, high) = consumer
+            .fetch_watermarks(element.topic(), element.partition(), timeout)
+            .with_context(|| {
+                format!(
+                    "Failed to fetch watermarks of {}:{}",
+                    element.topic(),
+                    element.partition()
+                )
+            })?;
+        let position = match element.offset() {
+            Offset::Offset(offset) => offset,
+            _ => low,
+        };
+        lag += (high - position).max(0);
+    }
+    Ok(lag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ConsumerLagConfig {
+        ConsumerLagConfig {
+            enabled: true,
+            consumer_group: "downstream".to_string(),
+            lag_threshold: 100,
+            max_lag: 1100,
+            min_throttle_factor: 0.2,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_throttle_factor() {
+        // Not throttled up to the threshold, then linearly down to the minimum at the max lag
+        assert_eq!(throttle_factor(0, 100, 1100, 0.2), 1.0);
+        assert_eq!(throttle_factor(100, 100, 1100, 0.2), 1.0);
+        assert!((throttle_factor(600, 100, 1100, 0.2) - 0.6).abs() < 1e-9);
+        assert_eq!(throttle_factor(1100, 100, 1100, 0.2), 0.2);
+        assert_eq!(throttle_factor(5000, 100, 1100, 0.2), 0.2);
+        // A max lag at or below the threshold throttles fully right above it
+        assert_eq!(throttle_factor(101, 100, 100, 0.2), 0.2);
+        assert_eq!(throttle_factor(101, 100, 50, 0.2), 0.2);
+        // The minimum is clamped, so that the processors never stop or speed up
+        assert_eq!(throttle_factor(5000, 100, 1100, 0.0), 0.01);
+        assert_eq!(throttle_factor(5000, 100, 1100, 2.0), 1.0);
+    }
+
+    #[test]
+    fn test_fails_open() {
+        let config = config();
+        let mut status = ConsumerLagStatus {
+            consumer_group: config.consumer_group.clone(),
+            observed_lag: None,
+            throttle_factor: 1.0,
+            last_error: None,
+        };
+        status.observe(Ok(1100), &config);
+        assert_eq!(status.observed_lag, Some(1100));
+        assert_eq!(status.throttle_factor, 0.2);
+
+        // Not throttled while the lag can't be read, keeping the last one observed
+        status.observe(Err(anyhow!("Failed to fetch committed offsets")), &config);
+        assert_eq!(status.throttle_factor, 1.0);
+        assert_eq!(status.observed_lag, Some(1100));
+        assert_eq!(
+            status.last_error.as_deref(),
+            Some("Failed to fetch committed offsets")
+        );
+
+        status.observe(Ok(600), &config);
+        assert!((status.throttle_factor - 0.6).abs() < 1e-9);
+        assert_eq!(status.last_error, None);
+    }
+
+    #[test]
+    fn test_query_fails_without_broker() {
+        let mut client_config = ClientConfig::new();
+        client_config
+            .set("bootstrap.servers", "127.0.0.1:1")
+            .set("group.id", "downstream");
+        let err = query_lag(
+            &client_config,
+            &["transactions".to_string()],
+            Duration::from_millis(100),
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", err).contains("Failed to fetch metadata of transactions"));
+    }
+}
@@ -0,0 +1,147 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Connection pools of the processors, so that a processor doing heavy upserts, e.g.
+//! `custom_token_processor`, can't starve the others of connections.
+//!
+//! Every processor gets a pool of its own, sized and timed out by `db_pools`, r2d2's defaults
+//! unless set. With `shared`, the processors without an entry under `processors` share one pool of
+//! the process instead, while the listed ones keep a pool of their own with their settings.
+//! `statement_timeout_millis` sets Postgres' `statement_timeout` on every connection as it's
+//! opened, so a statement stuck behind a lock fails its batch, which is retried, rather than
+//! holding the connection.
+//!
+//! A processor that gets no connection within `connection_timeout_millis` logs an error naming
+//! it and its pool's size, and retries within its batch's retry budget.
+//!
+//! The other components of the process, e.g. `driver::admin` or `driver::health`, use the pool of
+//! the first processor started.
+
+use crate::{
+    custom::driver::config::DbPoolsConfig,
+    database::{new_db_pool_with, PgDbPool},
+};
+use aptos_logger::info;
+use diesel::r2d2::PoolError;
+use once_cell::sync::OnceCell;
+use std::time::Duration;
+
+static SHARED: OnceCell<PgDbPool> = OnceCell::new();
+
+/// The settings of a processor's pool
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoolSettings {
+    pub max_size: u32,
+    pub connection_timeout: Duration,
+    pub statement_timeout: Option<Duration>,
+    /// Whether the processor shares the process' pool
+    pub shared: bool,
+}
+
+impl PoolSettings {
+    pub fn for_processor(config: &DbPoolsConfig, processor: &str) -> Self {
+        let own = config.processors.get(processor);
+        let statement_timeout_millis = own
+            .and_then(|own| own.statement_timeout_millis)
+            .unwrap_or(config.statement_timeout_millis);
+        Self {
+            max_size: own.and_then(|own| own.max_size).unwrap_or(config.max_size),
+            connection_timeout: Duration::from_millis(
+                own.and_then(|own| own.connection_timeout_millis)
+                    .unwrap_or(config.connection_timeout_millis),
+            ),
+            statement_timeout: (statement_timeout_millis > 0)
+                .then(|| Duration::from_millis(statement_timeout_millis)),
+            shared: config.shared && own.is_none(),
+        }
+    }
+}
+
+/// The pool of `processor`, the process' shared pool if it shares it
+pub fn connection_pool(
+    config: &DbPoolsConfig,
+    processor: &str,
+    database_url: &str,
+) -> Result<PgDbPool, PoolError> {
+    let settings = PoolSettings::for_processor(config, processor);
+    let build = || {
+        info!(
+            processor_name = processor,
+            max_size = settings.max_size,
+            connection_timeout = ?settings.connection_timeout,
+            statement_timeout = ?settings.statement_timeout,
+            shared = settings.shared,
+            "Creating connection pool..."
+        );
+        new_db_pool_with(
+            database_url,
+            settings.max_size,
+            settings.connection_timeout,
+            settings.statement_timeout,
+        )
+    };
+    if settings.shared {
+        SHARED.get_or_try_init(build).cloned()
+    } else {
+        build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom::driver::config::PoolOverride;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_settings() {
+        let mut config = DbPoolsConfig::default();
+        let defaults = PoolSettings {
+            max_size: 10,
+            connection_timeout: Duration::from_secs(30),
+            statement_timeout: None,
+            shared: false,
+        };
+        assert_eq!(
+            PoolSettings::for_processor(&config, "custom_default_processor"),
+            defaults
+        );
+
+        config.shared = true;
+        config.statement_timeout_millis = 5_000;
+        config.processors = HashMap::from([("custom_token_processor".to_string(), PoolOverride {
+            max_size: Some(20),
+            statement_timeout_millis: Some(60_000),
+            ..PoolOverride::default()
+        })]);
+        config.validate().unwrap();
+        assert_eq!(
+            PoolSettings::for_processor(&config, "custom_default_processor"),
+            PoolSettings {
+                statement_timeout: Some(Duration::from_secs(5)),
+                shared: true,
+                ..defaults.clone()
+            }
+        );
+        // Listed, so with a pool of its own
+        assert_eq!(
+            PoolSettings::for_processor(&config, "custom_token_processor"),
+            PoolSettings {
+                max_size: 20,
+                connection_timeout: Duration::from_secs(30),
+                statement_timeout: Some(Duration::from_secs(60)),
+                shared: false,
+            }
+        );
+
+        config
+            .processors
+            .get_mut("custom_token_processor")
+            .unwrap()
+            .max_size = Some(0);
+        assert!(config.validate().is_err());
+        config.processors =
+            HashMap::from([("token_processor".to_string(), PoolOverride::default())]);
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,537 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Replays a single version through every processor's parsing stage without writing to the db or
+//! producing to Kafka. Meant for reproducing what the indexer derives for a row a consumer reports
+//! as suspicious, and for investigating poison-pill transactions: panics are caught per stage and
+//! reported with a backtrace instead of aborting. `derive_batch` does the same for a batch fetched
+//! elsewhere, which is how a standby parses, see `driver::standby`.
+//!
+//! In a node, `runtime::bootstrap_debug` fetches the version from the node's db; outside of one,
+//! `aptos-indexer debug --version <version> --input <file>` finds it in files of transactions like
+//! replays read, see `driver::replay`. Panics are captured by a hook wrapping the process' own
+//! once, and only on the thread deriving while it derives: every other panic still reaches the
+//! hook that was installed before, e.g. the node's crash handler.
+
+use crate::{
+    custom::driver::{
+        config::{DriverConfig, PartitionKeyStrategy},
+        publisher::Publisher,
+        replay,
+        salting::{KeySalter, LOGICAL_KEY_HEADER},
+    },
+    indexer::fetcher::fetch_nexts,
+    models::{
+        coin_models::{account_transactions::AccountTransaction, coin_activities::CoinActivity},
+        scripts::Script,
+        stake_models::{
+            delegator_activities::DelegatedStakingActivity, delegator_pools::DelegatorPool,
+            proposal_votes::ProposalVote, staking_pool_voter::CurrentStakingPoolVoter,
+        },
+        transactions::{TransactionDetail, TransactionModel},
+        write_set_changes::WriteSetChangeDetail,
+    },
+};
+use anyhow::{anyhow, bail, Context as AnyhowContext, Result};
+use aptos_api::context::Context;
+use aptos_api_types::{HashValue, Transaction};
+use clap::Parser;
+use futures::FutureExt;
+use serde::Serialize;
+use std::{
+    backtrace::Backtrace,
+    cell::RefCell,
+    collections::BTreeMap,
+    future::Future,
+    panic::AssertUnwindSafe,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex, Once},
+};
+
+pub enum VersionOrHash {
+    Version(u64),
+    Hash(String),
+}
+
+/// Flags of a debug run, for the `debug` command and the binary embedding the indexer to take
+#[derive(Clone, Debug, Parser)]
+pub struct DebugArgs {
+    /// Version of the transaction
+    #[clap(long, required_unless_present = "hash", conflicts_with = "hash")]
+    pub version: Option<u64>,
+    /// Hash of the transaction, instead of its version
+    #[clap(long)]
+    pub hash: Option<String>,
+    /// Files of transactions to find it in, JSON arrays or fetcher recordings. Needed outside of a
+    /// node, a node reads its db without them.
+    #[clap(long)]
+    pub input: Vec<PathBuf>,
+    /// Driver config, the default config path if it exists, otherwise the defaults
+    #[clap(long)]
+    pub config: Option<PathBuf>,
+    /// Print the report as JSON
+    #[clap(long)]
+    pub json: bool,
+}
+
+impl DebugArgs {
+    pub fn target(&self) -> Result<VersionOrHash> {
+        match (self.version, &self.hash) {
+            (Some(version), None) => Ok(VersionOrHash::Version(version)),
+            (None, Some(hash)) => Ok(VersionOrHash::Hash(hash.clone())),
+            _ => bail!("Either a version or a hash is needed"),
+        }
+    }
+
+    pub fn driver_config(&self) -> Result<DriverConfig> {
+        replay::read_driver_config(self.config.as_deref())
+    }
+
+    pub fn print(&self, report: &DebugReport) -> Result<()> {
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(report)?);
+        } else {
+            report.print();
+        }
+        Ok(())
+    }
+}
+
+/// Derives the transaction of `args` from its `input` files and prints what the processors would
+/// derive from it
+pub fn run(args: &DebugArgs) -> Result<()> {
+    if args.input.is_empty() {
+        bail!("Outside of a node the transaction is read from files, set --input");
+    }
+    let target = args.target()?;
+    let driver_config = args.driver_config()?;
+    let transactions = replay::read_transactions(&args.input)?;
+    let report = derive_transaction(&driver_config, &transactions, &target)?;
+    args.print(&report)
+}
+
+/// Derives everything the processors would from the transaction `target` among `transactions`
+pub fn derive_transaction(
+    config: &DriverConfig,
+    transactions: &[Transaction],
+    target: &VersionOrHash,
+) -> Result<DebugReport> {
+    let txn = match target {
+        VersionOrHash::Version(version) => transactions
+            .iter()
+            .find(|txn| txn.version() == Some(*version))
+            .with_context(|| format!("Transaction {} not found", version))?,
+        VersionOrHash::Hash(hash_str) => {
+            let hash = HashValue::from_str(hash_str).context("Invalid transaction hash")?;
+            transactions
+                .iter()
+                .find(|txn| {
+                    txn.transaction_info()
+                        .map_or(false, |info| info.hash == hash)
+                })
+                .with_context(|| format!("Transaction {} not found", hash_str))?
+        },
+    };
+    Ok(derive_batch(config, std::slice::from_ref(txn)))
+}
+
+/// A message that would have been produced for this version
+#[derive(Debug, Serialize)]
+pub struct PlannedMessage {
+    pub processor: &'static str,
+    pub topic: String,
+    pub key: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub payload: serde_json::Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StagePanic {
+    pub stage: &'static str,
+    pub message: String,
+    pub backtrace: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DebugReport {
+    pub version: u64,
+    /// Derived rows grouped by table name
+    pub tables: BTreeMap<&'static str, Vec<serde_json::Value>>,
+    pub messages: Vec<PlannedMessage>,
+    pub panics: Vec<StagePanic>,
+}
+
+impl DebugReport {
+    fn add_rows<T: Serialize>(&mut self, table: &'static str, rows: &[T]) {
+        let entry = self.tables.entry(table).or_default();
+        for row in rows {
+            entry
+                .push(serde_json::to_value(row).unwrap_or_else(
+                    |e| serde_json::json!({ "unserializable_row": e.to_string() }),
+                ));
+        }
+    }
+
+    pub fn print(&self) {
+        println!("==== Derived rows for version {} ====", self.version);
+        for (table, rows) in &self.tables {
+            println!("---- {} ({} rows)", table, rows.len());
+            for row in rows {
+                println!("{}", serde_json::to_string_pretty(row).unwrap());
+            }
+        }
+        println!("==== Would publish {} messages ====", self.messages.len());
+        for message in &self.messages {
+            println!(
+                "---- [{}] topic={} key={:?} headers={:?}",
+                message.processor, message.topic, message.key, message.headers
+            );
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&message.payload).unwrap()
+            );
+        }
+        for panic in &self.panics {
+            println!("==== PANIC in stage '{}' ====", panic.stage);
+            println!("{}\n{}", panic.message, panic.backtrace);
+        }
+    }
+}
+
+/// Fetches a single version (by version or hash) and derives everything the processors would
+pub async fn debug_version(
+    context: Arc<Context>,
+    config: &DriverConfig,
+    target: VersionOrHash,
+) -> Result<DebugReport> {
+    let ledger_version = context
+        .get_latest_ledger_info_wrapped()
+        .map_err(|e| anyhow!("Failed to get ledger info: {}", e))?
+        .ledger_version
+        .0;
+    let version = match target {
+        VersionOrHash::Version(version) => version,
+        VersionOrHash::Hash(hash_str) => {
+            let hash = HashValue::from_str(&hash_str).context("Invalid transaction hash")?;
+            context
+                .db
+                .get_transaction_by_hash(hash.into(), ledger_version, false)?
+                .with_context(|| format!("Transaction {} not found", hash_str))?
+                .version
+        },
+    };
+
+    // Records the panics and their backtraces instead of crashing the node
+    let captured = CapturedPanic::default();
+    Ok(derive_report(context, config, version, ledger_version, &captured).await)
+}
+
+async fn derive_report(
+    context: Arc<Context>,
+    config: &DriverConfig,
+    version: u64,
+    ledger_version: u64,
+    captured: &CapturedPanic,
+) -> DebugReport {
+    let mut report = DebugReport {
+        version,
+        ..DebugReport::default()
+    };
+    // Captured on whichever thread the fetch is polled on
+    let mut fetch =
+        Box::pin(AssertUnwindSafe(fetch_nexts(context, version, ledger_version, 1)).catch_unwind());
+    let fetched = futures::future::poll_fn(|cx| captured.scope(|| fetch.as_mut().poll(cx))).await;
+    let transactions = match fetched {
+        Ok(transactions) => transactions,
+        Err(_) => {
+            report.panics.push(take_panic(captured, "fetch"));
+            return report;
+        },
+    };
+
+    derive_stages(&mut report, config, &transactions, captured);
+    report
+}
+
+/// Derives everything the processors would from an already fetched batch, e.g. a standby's
+pub fn derive_batch(config: &DriverConfig, transactions: &[Transaction]) -> DebugReport {
+    let mut report = DebugReport {
+        version: transactions
+            .first()
+            .and_then(|txn| txn.version())
+            .unwrap_or_default(),
+        ..DebugReport::default()
+    };
+    derive_stages(&mut report, config, transactions, &CapturedPanic::default());
+    report
+}
+
+fn derive_stages(
+    report: &mut DebugReport,
+    config: &DriverConfig,
+    transactions: &[Transaction],
+    captured: &CapturedPanic,
+) {
+    run_stage(report, captured, "transaction_model", |report| {
+        let (txns, txn_details, events, wscs, wsc_details) =
+            TransactionModel::from_transactions(transactions);
+        report.add_rows("transactions", &txns);
+        report.add_rows("scripts", &Script::from_transactions(transactions));
+        for detail in &txn_details {
+            match detail {
+                TransactionDetail::User(user_txn, sigs) => {
+                    report.add_rows("user_transactions", std::slice::from_ref(user_txn));
+                    report.add_rows("signatures", sigs);
+                },
+                TransactionDetail::BlockMetadata(bmt) => {
+                    report.add_rows("block_metadata_transactions", std::slice::from_ref(bmt))
+                },
+                TransactionDetail::Genesis => {},
+            }
+        }
+        report.add_rows("events", &events);
+        report.add_rows("write_set_changes", &wscs);
+        for detail in &wsc_details {
+            match detail {
+                WriteSetChangeDetail::Module(module) => {
+                    report.add_rows("move_modules", std::slice::from_ref(module))
+                },
+                WriteSetChangeDetail::Resource(resource) => {
+                    report.add_rows("move_resources", std::slice::from_ref(resource))
+                },
+                WriteSetChangeDetail::Table(item, current_item, metadata, _) => {
+                    report.add_rows("table_items", std::slice::from_ref(item));
+                    report.add_rows("current_table_items", std::slice::from_ref(current_item));
+                    if let Some(meta) = metadata {
+                        report.add_rows("table_metadatas", std::slice::from_ref(meta));
+                    }
+                },
+            }
+        }
+    });
+
+    run_stage(report, captured, "coin", |report| {
+        for txn in transactions {
+            // The aptos coin supply lookup needs the db, which this tool never touches
+            let (coin_activities, coin_balances, coin_infos, current_coin_balances, coin_supply) =
+                CoinActivity::from_transaction(txn, &None);
+            report.add_rows("coin_activities", &coin_activities);
+            report.add_rows("coin_balances", &coin_balances);
+            report.add_rows("coin_infos", &coin_infos.into_values().collect::<Vec<_>>());
+            report.add_rows(
+                "current_coin_balances",
+                &current_coin_balances.into_values().collect::<Vec<_>>(),
+            );
+            report.add_rows("coin_supply", &coin_supply);
+            let account_transactions = AccountTransaction::from_transaction(txn).unwrap();
+            report.add_rows(
+                "account_transactions",
+                &account_transactions.into_values().collect::<Vec<_>>(),
+            );
+        }
+    });
+
+    run_stage(report, captured, "stake", |report| {
+        for txn in transactions {
+            let voters = CurrentStakingPoolVoter::from_transaction(txn).unwrap();
+            report.add_rows(
+                "current_staking_pool_voter",
+                &voters.into_values().collect::<Vec<_>>(),
+            );
+            report.add_rows(
+                "proposal_votes",
+                &ProposalVote::from_transaction(txn).unwrap(),
+            );
+            report.add_rows(
+                "delegated_staking_activities",
+                &DelegatedStakingActivity::from_transaction(txn).unwrap(),
+            );
+            let (pools, pool_balances, current_pool_balances) =
+                DelegatorPool::from_transaction(txn).unwrap();
+            report.add_rows(
+                "delegated_staking_pools",
+                &pools.into_values().collect::<Vec<_>>(),
+            );
+            report.add_rows("delegated_staking_pool_balances", &pool_balances);
+            report.add_rows(
+                "current_delegated_staking_pool_balances",
+                &current_pool_balances.into_values().collect::<Vec<_>>(),
+            );
+        }
+    });
+
+    run_stage(report, captured, "publish", |report| {
+        let topic = config
+            .topics
+            .get("transaction_topic")
+            .cloned()
+            .unwrap_or_default();
+        // Only the configured hot keys apply here, auto detection needs live traffic
+        let mut salter = KeySalter::new(&config.key_salting);
+        for txn in transactions {
+            report.messages.push(planned_transaction_message(
+                &topic,
+                txn,
+                config.partition_key.transactions,
+                &mut salter,
+            ));
+        }
+    });
+}
+
+/// Mirrors what `Publisher::send_transaction` produces for the default processor
+fn planned_transaction_message(
+    topic: &str,
+    txn: &Transaction,
+    partition_key: PartitionKeyStrategy,
+    salter: &mut KeySalter,
+) -> PlannedMessage {
+    let (key, headers) = match Publisher::transaction_key(partition_key, txn) {
+        Some((logical_key, version)) => (Some(salter.salt(&logical_key, version)), vec![(
+            LOGICAL_KEY_HEADER.to_string(),
+            logical_key,
+        )]),
+        None => (None, vec![]),
+    };
+    PlannedMessage {
+        processor: crate::custom::processors::custom_default_processor::NAME,
+        topic: topic.to_string(),
+        key,
+        headers,
+        payload: serde_json::to_value(txn)
+            .unwrap_or_else(|e| serde_json::json!({ "unserializable_transaction": e.to_string() })),
+    }
+}
+
+static PANIC_HOOK: Once = Once::new();
+
+thread_local! {
+    /// Where a panic on this thread is recorded instead of handed to the process' hook
+    static CAPTURING: RefCell<Option<CapturedPanic>> = RefCell::new(None);
+}
+
+/// The last panic on a thread while it was capturing, with its backtrace
+#[derive(Clone, Default)]
+struct CapturedPanic(Arc<Mutex<Option<(String, String)>>>);
+
+impl CapturedPanic {
+    /// Runs `f` with the panics of this thread captured
+    fn scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        PANIC_HOOK.call_once(|| {
+            let previous_hook = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                // None as well on a thread being torn down
+                let capturing = CAPTURING.try_with(|capturing| capturing.borrow().clone());
+                match capturing.ok().flatten() {
+                    Some(captured) => {
+                        *captured.0.lock().unwrap() =
+                            Some((info.to_string(), Backtrace::force_capture().to_string()))
+                    },
+                    None => previous_hook(info),
+                }
+            }));
+        });
+
+        /// Stops capturing even if `f` unwinds
+        struct Restore(Option<CapturedPanic>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                CAPTURING.with(|capturing| *capturing.borrow_mut() = self.0.take());
+            }
+        }
+        let _restore = Restore(CAPTURING.with(|capturing| capturing.replace(Some(self.clone()))));
+        f()
+    }
+}
+
+fn take_panic(captured: &CapturedPanic, stage: &'static str) -> StagePanic {
+    let (message, backtrace) = captured.0.lock().unwrap().take().unwrap_or_default();
+    StagePanic {
+        stage,
+        message,
+        backtrace,
+    }
+}
+
+/// Runs one processor's parsing in isolation; a panic drops only this stage's rows
+fn run_stage<F>(report: &mut DebugReport, captured: &CapturedPanic, stage: &'static str, f: F)
+where
+    F: FnOnce(&mut DebugReport),
+{
+    let mut staged = DebugReport::default();
+    match captured.scope(|| std::panic::catch_unwind(AssertUnwindSafe(|| f(&mut staged)))) {
+        Ok(_) => {
+            for (table, mut rows) in staged.tables {
+                report.tables.entry(table).or_default().append(&mut rows);
+            }
+            report.messages.append(&mut staged.messages);
+        },
+        Err(_) => report.panics.push(take_panic(captured, stage)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom::test_utils;
+
+    #[test]
+    fn test_captures_only_its_own_panics() {
+        let captured = CapturedPanic::default();
+        let result = captured.scope(|| std::panic::catch_unwind(|| panic!("poison pill")));
+        assert!(result.is_err());
+        let panic = take_panic(&captured, "coin");
+        assert!(panic.message.contains("poison pill"));
+        assert!(!panic.backtrace.is_empty());
+
+        // Neither after the scope nor on other threads while it runs
+        assert!(std::panic::catch_unwind(|| panic!("after")).is_err());
+        captured.scope(|| {
+            assert!(std::thread::spawn(|| panic!("elsewhere")).join().is_err());
+        });
+        assert!(captured.0.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_derive_transaction() {
+        let transactions = test_utils::fixture("batch1.json");
+        let config = test_utils::driver_config(serde_json::json!({
+            "transaction_topic": "transactions",
+        }));
+
+        let report =
+            derive_transaction(&config, &transactions, &VersionOrHash::Version(691595)).unwrap();
+        assert_eq!(report.version, 691595);
+        assert_eq!(report.tables["transactions"].len(), 1);
+        assert_eq!(report.tables["user_transactions"].len(), 1);
+        assert!(report.panics.is_empty());
+        assert_eq!(report.messages.len(), 1);
+        assert_eq!(report.messages[0].topic, "transactions");
+
+        let hash = "0xefd4c865e00c240da0c426a37ceeda10d9b030d0e8a4fb4fb7ff452ad63401fb";
+        let by_hash = derive_transaction(
+            &config,
+            &transactions,
+            &VersionOrHash::Hash(hash.to_string()),
+        )
+        .unwrap();
+        assert_eq!(by_hash.version, 691595);
+
+        assert!(derive_transaction(&config, &transactions, &VersionOrHash::Version(1)).is_err());
+        let invalid = VersionOrHash::Hash("0xnot_a_hash".to_string());
+        assert!(derive_transaction(&config, &transactions, &invalid).is_err());
+    }
+
+    #[test]
+    fn test_args() {
+        let args = DebugArgs::parse_from(["debug", "--version", "7", "--input", "batch.json"]);
+        assert!(matches!(args.target().unwrap(), VersionOrHash::Version(7)));
+        assert!(DebugArgs::try_parse_from(["debug", "--input", "batch.json"]).is_err());
+        assert!(DebugArgs::try_parse_from(["debug", "--version", "7", "--hash", "0x1"]).is_err());
+
+        // Outside of a node there's nothing to read the transaction from without files
+        let args = DebugArgs::parse_from(["debug", "--hash", "0x1"]);
+        assert!(run(&args).is_err());
+    }
+}
@@ -0,0 +1,398 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Detection of a sender's sequence number used at two versions, as seen when a node serves a
+//! stale fork of pending data. Before a batch is published, the `(sender, sequence_number)` of
+//! every user transaction is checked against the versions seen so far: a bounded cache of the
+//! recent pairs, backed by `user_transactions` through its `(sender, sequence_number)` index for
+//! the pairs the cache doesn't have. Duplicates are counted in
+//! `indexer_duplicate_transactions_count` and recorded in `anomalies`, then the policy applies:
+//! `keep_both` publishes both, `keep_lower` drops the higher version if it's in the batch, and
+//! `fail` fails the batch.
+//!
+//! Transactions with nonce-based replay protection have no sequence number of their own, the API
+//! reports them with `u64::MAX`, and are skipped.
+
+use crate::{
+    counters::DUPLICATE_TRANSACTIONS,
+    custom::driver::config::DuplicateTransactionsConfig,
+    database::{execute_with_better_error, get_chunks, PgPoolConnection},
+    models::anomalies::Anomaly,
+    schema,
+    util::standardize_address,
+};
+use anyhow::bail;
+use aptos_api_types::Transaction;
+use aptos_logger::{error, warn};
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Mutex,
+};
+
+/// Kind of the recorded anomalies
+pub const DUPLICATE_SEQUENCE_NUMBER: &str = "duplicate_sequence_number";
+
+/// Sequence number the API reports for transactions with nonce-based replay protection
+const NONCE_SEQUENCE_NUMBER: u64 = u64::MAX;
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicatePolicy {
+    /// Duplicates are logged, counted, recorded and published
+    KeepBoth,
+    /// As `KeepBoth`, but the higher version is dropped from the batch. One already published
+    /// stays published.
+    KeepLower,
+    /// As `KeepBoth`, and the batch fails before anything is published
+    Fail,
+}
+
+impl DuplicatePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DuplicatePolicy::KeepBoth => "keep_both",
+            DuplicatePolicy::KeepLower => "keep_lower",
+            DuplicatePolicy::Fail => "fail",
+        }
+    }
+}
+
+type SequenceKey = (String, i64);
+
+/// A user transaction's sequence number, at its position in the batch
+#[derive(Debug)]
+struct Submission {
+    index: usize,
+    sender: String,
+    sequence_number: i64,
+    version: i64,
+}
+
+#[derive(Debug, PartialEq)]
+struct Duplicate {
+    index: usize,
+    sender: String,
+    sequence_number: i64,
+    version: i64,
+    /// The version the sequence number was seen at before
+    other_version: i64,
+}
+
+/// Lowest version seen per `(sender, sequence_number)`, forgetting the oldest pairs past
+/// `capacity`
+struct SequenceCache {
+    capacity: usize,
+    versions: HashMap<SequenceKey, i64>,
+    order: VecDeque<SequenceKey>,
+}
+
+pub struct DuplicateDetector {
+    processor: &'static str,
+    enabled: bool,
+    policy: DuplicatePolicy,
+    cache: Mutex<SequenceCache>,
+}
+
+impl DuplicateDetector {
+    pub fn new(processor: &'static str, config: &DuplicateTransactionsConfig) -> Self {
+        Self {
+            processor,
+            enabled: config.enabled,
+            policy: config.policy,
+            cache: Mutex::new(SequenceCache::new(config.cache_size.max(1))),
+        }
+    }
+
+    /// Checks the user transactions of the batch and returns the transactions to publish. Errors
+    /// with the `fail` policy if there are duplicates, or if the lookup fails.
+    pub fn check(
+        &self,
+        conn: &mut PgPoolConnection,
+        transactions: Vec<Transaction>,
+        start_version: u64,
+        end_version: u64,
+    ) -> anyhow::Result<Vec<Transaction>> {
+        if !self.enabled {
+            return Ok(transactions);
+        }
+        let submissions = submissions(&transactions);
+        let duplicates = {
+            let mut cache = self.cache.lock().unwrap();
+            let misses = submissions
+                .iter()
+                .map(Submission::key)
+                .filter(|key| !cache.versions.contains_key(key))
+                .collect::<HashSet<_>>();
+            for (key, version) in lookup(conn, &misses)? {
+                cache.insert(key, version);
+            }
+            detect(&mut cache, &submissions)
+        };
+        if duplicates.is_empty() {
+            return Ok(transactions);
+        }
+
+        let policy = self.policy.as_str();
+        DUPLICATE_TRANSACTIONS
+            .with_label_values(&[policy])
+            .inc_by(duplicates.len() as u64);
+        warn!(
+            processor_name = self.processor,
+            policy = policy,
+            start_version = start_version,
+            end_version = end_version,
+            duplicates = duplicates.len(),
+            first_version = duplicates[0].version,
+            first_other_version = duplicates[0].other_version,
+            "Sequence numbers used at two versions"
+        );
+        let rows = duplicates
+            .iter()
+            .map(|duplicate| duplicate.anomaly(self.processor, policy))
+            .collect::<Vec<_>>();
+        // Recording is best effort, it never holds up the batch
+        if let Err(err) = insert_anomalies(conn, &rows) {
+            error!(
+                processor_name = self.processor,
+                error = ?err,
+                "Failed to record duplicate transactions"
+            );
+        }
+
+        match self.policy {
+            DuplicatePolicy::KeepBoth => Ok(transactions),
+            DuplicatePolicy::KeepLower => {
+                let dropped = dropped(&duplicates);
+                Ok(transactions
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(index, _)| !dropped.contains(index))
+                    .map(|(_, txn)| txn)
+                    .collect())
+            },
+            DuplicatePolicy::Fail => bail!(
+                "[{}] Versions {} to {} reuse the sequence numbers of {} other versions",
+                self.processor,
+                start_version,
+                end_version,
+                duplicates.len()
+            ),
+        }
+    }
+}
+
+impl Submission {
+    fn key(&self) -> SequenceKey {
+        (self.sender.clone(), self.sequence_number)
+    }
+}
+
+impl Duplicate {
+    fn anomaly(&self, processor: &str, policy: &str) -> Anomaly {
+        Anomaly {
+            kind: DUPLICATE_SEQUENCE_NUMBER.to_string(),
+            transaction_version: self.version,
+            processor: processor.to_string(),
+            policy: policy.to_string(),
+            message: format!(
+                "Sequence number {} of {} was already used at version {}",
+                self.sequence_number, self.sender, self.other_version
+            ),
+            details: json!({
+                "sender": self.sender,
+                "sequence_number": self.sequence_number,
+                "version": self.version,
+                "other_version": self.other_version,
+            }),
+        }
+    }
+}
+
+impl SequenceCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            versions: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Keeps the lower of `version` and the one already cached
+    fn insert(&mut self, key: SequenceKey, version: i64) {
+        if let Some(cached) = self.versions.get_mut(&key) {
+            *cached = (*cached).min(version);
+            return;
+        }
+        self.versions.insert(key.clone(), version);
+        self.order.push_back(key);
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.versions.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// `None` for nonce-based replay protection
+fn sequence_number(sequence_number: u64) -> Option<i64> {
+    (sequence_number != NONCE_SEQUENCE_NUMBER).then_some(sequence_number as i64)
+}
+
+fn submissions(transactions: &[Transaction]) -> Vec<Submission> {
+    transactions
+        .iter()
+        .enumerate()
+        .filter_map(|(index, txn)| match txn {
+            Transaction::UserTransaction(user_txn) => Some(Submission {
+                index,
+                sender: standardize_address(&user_txn.request.sender.inner().to_hex_literal()),
+                sequence_number: sequence_number(user_txn.request.sequence_number.0)?,
+                version: user_txn.info.version.0 as i64,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Finds the submissions whose sequence number the cache has at another version, adding them
+/// to the cache in batch order
+fn detect(cache: &mut SequenceCache, submissions: &[Submission]) -> Vec<Duplicate> {
+    let mut duplicates = vec![];
+    for submission in submissions {
+        let key = submission.key();
+        if let Some(&other_version) = cache.versions.get(&key) {
+            if other_version != submission.version {
+                duplicates.push(Duplicate {
+                    index: submission.index,
+                    sender: submission.sender.clone(),
+                    sequence_number: submission.sequence_number,
+                    version: submission.version,
+                    other_version,
+                });
+            }
+        }
+        cache.insert(key, submission.version);
+    }
+    duplicates
+}
+
+/// Positions in the batch of the duplicates above the version they conflict with. A duplicate
+/// below it is kept, the higher version having been published already.
+fn dropped(duplicates: &[Duplicate]) -> HashSet<usize> {
+    duplicates
+        .iter()
+        .filter(|duplicate| duplicate.version > duplicate.other_version)
+        .map(|duplicate| duplicate.index)
+        .collect()
+}
+
+/// The lowest stored version of each of `keys`. Filtering on both columns uses the
+/// `(sender, sequence_number)` index; pairs that only match across keys are dropped.
+fn lookup(
+    conn: &mut PgPoolConnection,
+    keys: &HashSet<SequenceKey>,
+) -> Result<Vec<(SequenceKey, i64)>, diesel::result::Error> {
+    use schema::user_transactions::dsl::*;
+
+    if keys.is_empty() {
+        return Ok(vec![]);
+    }
+    let senders = keys.iter().map(|(s, _)| s.clone()).collect::<HashSet<_>>();
+    let sequence_numbers = keys.iter().map(|(_, n)| *n).collect::<HashSet<_>>();
+    let rows = user_transactions
+        .select((sender, sequence_number, version))
+        .filter(sender.eq_any(senders))
+        .filter(sequence_number.eq_any(sequence_numbers))
+        .load::<(String, i64, i64)>(conn)?;
+    Ok(rows
+        .into_iter()
+        .map(|(s, n, v)| ((s, n), v))
+        .filter(|(key, _)| keys.contains(key))
+        .collect())
+}
+
+/// Records anomalies, once per kind and version
+pub fn insert_anomalies(
+    conn: &mut PgConnection,
+    items_to_insert: &[Anomaly],
+) -> Result<(), diesel::result::Error> {
+    use schema::anomalies::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), Anomaly::field_count());
+    for (start_ind, end_ind) in chunks {
+        execute_with_better_error(
+            conn,
+            diesel::insert_into(schema::anomalies::table)
+                .values(&items_to_insert[start_ind..end_ind])
+                .on_conflict((kind, transaction_version))
+                .do_nothing(),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn submission(index: usize, sender: &str, sequence_number: i64, version: i64) -> Submission {
+        Submission {
+            index,
+            sender: sender.to_string(),
+            sequence_number,
+            version,
+        }
+    }
+
+    #[test]
+    fn test_detect() {
+        let mut cache = SequenceCache::new(100);
+        // Stored at version 5 by an earlier batch
+        cache.insert(("0x1".to_string(), 7), 5);
+        let duplicates = detect(&mut cache, &[
+            submission(0, "0x1", 7, 10),
+            submission(1, "0x2", 7, 11),
+            submission(2, "0x2", 7, 12),
+            submission(3, "0x2", 8, 13),
+        ]);
+        assert_eq!(
+            duplicates
+                .iter()
+                .map(|d| (d.index, d.version, d.other_version))
+                .collect::<Vec<_>>(),
+            vec![(0, 10, 5), (2, 12, 11)]
+        );
+        // A retried batch sees its own versions again, which aren't duplicates
+        assert!(detect(&mut cache, &[submission(0, "0x2", 8, 13)]).is_empty());
+        // Seen below the cached version, the lower version is kept from then on
+        let duplicates = detect(&mut cache, &[submission(0, "0x3", 1, 20)]);
+        assert!(duplicates.is_empty());
+        let duplicates = detect(&mut cache, &[submission(0, "0x3", 1, 15)]);
+        assert_eq!(duplicates[0].other_version, 20);
+        assert!(dropped(&duplicates).is_empty());
+        assert_eq!(cache.versions[&("0x3".to_string(), 1)], 15);
+    }
+
+    #[test]
+    fn test_cache_is_bounded() {
+        let mut cache = SequenceCache::new(2);
+        cache.insert(("0x1".to_string(), 0), 1);
+        cache.insert(("0x1".to_string(), 1), 2);
+        cache.insert(("0x1".to_string(), 0), 0);
+        cache.insert(("0x1".to_string(), 2), 3);
+        assert_eq!(cache.versions.len(), 2);
+        assert!(!cache.versions.contains_key(&("0x1".to_string(), 0)));
+        assert_eq!(cache.order.len(), 2);
+    }
+
+    #[test]
+    fn test_nonce_is_skipped() {
+        assert_eq!(sequence_number(42), Some(42));
+        assert_eq!(sequence_number(u64::MAX), None);
+    }
+}
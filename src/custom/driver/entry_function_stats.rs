@@ -0,0 +1,215 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Daily usage of every entry function, for analytics that shouldn't scan the raw tables.
+//! `custom_default_processor` adds every batch's calls to `entry_function_daily_stats`: calls,
+//! successes, gas used and a HyperLogLog sketch of the senders per day and function. The counts
+//! are added up in the upsert, and each bucket keeps the last version aggregated into it, so a
+//! re-processed batch doesn't count twice; calls at or below that version are left out and
+//! counted in `indexer_entry_function_stats_skipped_count`. Batches are expected in version order
+//! per bucket, which one processor task guarantees; with more, a batch landing after a later one
+//! of the same day loses its calls to the guard, which `queries::rederive_entry_function_stats`
+//! shows.
+//!
+//! Days are UTC only: a call's day is the UTC date of its block timestamp, there is no other time
+//! zone. A day closes once a batch has a block of a later day, and its rows are then published
+//! on `entry_function_stats_topic`, if configured, as `EntryFunctionDailyRollup`s.
+
+use crate::{
+    counters::ENTRY_FUNCTION_STATS_SKIPPED,
+    custom::driver::{config::EntryFunctionStatsConfig, publisher::Publisher},
+    database::{execute_with_better_error, get_chunks, write_transaction, PgPoolConnection},
+    models::entry_function_daily_stats::{
+        EntryFunctionCall, EntryFunctionDailyRollup, EntryFunctionDailyStat,
+        EntryFunctionDailyStatQuery,
+    },
+    schema,
+    util::{hyperloglog::HyperLogLog, parse_timestamp},
+};
+use aptos_api_types::Transaction;
+use aptos_logger::info;
+use chrono::NaiveDate;
+use diesel::{pg::upsert::excluded, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use field_count::FieldCount;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+/// Model name of the published rollups, see `client::MODEL_TOPIC_KEYS`
+pub const ENTRY_FUNCTION_DAILY_ROLLUP: &str = "EntryFunctionDailyRollup";
+
+pub struct EntryFunctionStats {
+    enabled: bool,
+    /// UTC day of the latest block seen
+    latest_date: Mutex<Option<NaiveDate>>,
+}
+
+impl EntryFunctionStats {
+    pub fn new(config: &EntryFunctionStatsConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            latest_date: Mutex::new(None),
+        }
+    }
+
+    /// Adds the calls of the batch to the stats. Returns the days the batch closed.
+    pub fn record(
+        &self,
+        conn: &mut PgPoolConnection,
+        transactions: &[Transaction],
+    ) -> anyhow::Result<Vec<NaiveDate>> {
+        if !self.enabled {
+            return Ok(vec![]);
+        }
+        let calls = transactions
+            .iter()
+            .filter_map(EntryFunctionCall::from_transaction)
+            .collect::<Vec<_>>();
+        if !calls.is_empty() {
+            let skipped = write_transaction(conn, |pg_conn| upsert_stats(pg_conn, &calls))?;
+            if skipped > 0 {
+                ENTRY_FUNCTION_STATS_SKIPPED.inc_by(skipped as u64);
+                info!(
+                    skipped = skipped,
+                    "Left out entry function calls already aggregated"
+                );
+            }
+        }
+        let batch_date = transactions
+            .iter()
+            .rev()
+            .find(|txn| txn.timestamp() > 0)
+            .map(|txn| {
+                parse_timestamp(txn.timestamp(), txn.version().unwrap_or_default() as i64).date()
+            });
+        Ok(batch_date.map_or_else(Vec::new, |batch_date| {
+            closed_days(&mut self.latest_date.lock().unwrap(), batch_date)
+        }))
+    }
+
+    /// Publishes the stats of `days`, keyed by day and entry function
+    pub fn publish_rollups(
+        &self,
+        conn: &mut PgPoolConnection,
+        publisher: &Publisher,
+        days: &[NaiveDate],
+    ) -> anyhow::Result<()> {
+        use schema::entry_function_daily_stats::dsl::*;
+
+        if days.is_empty() || !publisher.publishes(ENTRY_FUNCTION_DAILY_ROLLUP) {
+            return Ok(());
+        }
+        let rollups = entry_function_daily_stats
+            .filter(date.eq_any(days))
+            .order((date, entry_function_id_str))
+            .load::<EntryFunctionDailyStatQuery>(conn)?
+            .into_iter()
+            .map(EntryFunctionDailyRollup::from)
+            .collect::<Vec<_>>();
+        publisher.try_send_keyed(ENTRY_FUNCTION_DAILY_ROLLUP, &rollups, |rollup| {
+            format!("{}/{}", rollup.date, rollup.entry_function_id_str)
+        })?;
+        info!(
+            days = format!("{:?}", days),
+            rollups = rollups.len(),
+            "Published daily entry function stats"
+        );
+        Ok(())
+    }
+}
+
+/// Days from the latest one seen up to `batch_date`, which closed with the batch
+fn closed_days(latest_date: &mut Option<NaiveDate>, batch_date: NaiveDate) -> Vec<NaiveDate> {
+    let Some(latest) = *latest_date else {
+        *latest_date = Some(batch_date);
+        return vec![];
+    };
+    if batch_date <= latest {
+        return vec![];
+    }
+    *latest_date = Some(batch_date);
+    latest
+        .iter_days()
+        .take_while(|day| *day < batch_date)
+        .collect()
+}
+
+/// Adds the calls to their buckets, locked so that concurrent batches add up. Returns the number
+/// of calls left out.
+fn upsert_stats(
+    conn: &mut PgConnection,
+    calls: &[EntryFunctionCall],
+) -> Result<usize, diesel::result::Error> {
+    use schema::entry_function_daily_stats::dsl::*;
+
+    let dates = calls.iter().map(|call| call.date).collect::<HashSet<_>>();
+    let functions = calls
+        .iter()
+        .map(|call| call.entry_function_id_str.clone())
+        .collect::<HashSet<_>>();
+    let stored = entry_function_daily_stats
+        .filter(date.eq_any(dates))
+        .filter(entry_function_id_str.eq_any(functions))
+        .order((date, entry_function_id_str))
+        .for_update()
+        .load::<EntryFunctionDailyStatQuery>(conn)?
+        .into_iter()
+        .map(|row| {
+            let sketch = HyperLogLog::from_bytes(&row.distinct_senders_sketch).unwrap_or_default();
+            (
+                (row.date, row.entry_function_id_str),
+                (sketch, row.last_transaction_version),
+            )
+        })
+        .collect::<HashMap<_, _>>();
+    let (stats, skipped) = EntryFunctionDailyStat::aggregate(calls, &stored);
+
+    let chunks = get_chunks(stats.len(), EntryFunctionDailyStat::field_count());
+    for (start_ind, end_ind) in chunks {
+        execute_with_better_error(
+            conn,
+            diesel::insert_into(schema::entry_function_daily_stats::table)
+                .values(&stats[start_ind..end_ind])
+                .on_conflict((date, entry_function_id_str))
+                .do_update()
+                .set((
+                    call_count.eq(call_count + excluded(call_count)),
+                    success_count.eq(success_count + excluded(success_count)),
+                    distinct_senders_sketch.eq(excluded(distinct_senders_sketch)),
+                    distinct_senders.eq(excluded(distinct_senders)),
+                    total_gas_used.eq(total_gas_used + excluded(total_gas_used)),
+                    last_transaction_version.eq(excluded(last_transaction_version)),
+                    inserted_at.eq(excluded(inserted_at)),
+                )),
+            None,
+        )?;
+    }
+    Ok(skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 8, day).unwrap()
+    }
+
+    #[test]
+    fn test_closed_days() {
+        let mut latest_date = None;
+        // Nothing closes on the first batch, even after a restart mid-day
+        assert!(closed_days(&mut latest_date, day(1)).is_empty());
+        assert!(closed_days(&mut latest_date, day(1)).is_empty());
+        assert_eq!(closed_days(&mut latest_date, day(2)), vec![day(1)]);
+        // A batch behind the latest day doesn't reopen or close anything
+        assert!(closed_days(&mut latest_date, day(1)).is_empty());
+        assert_eq!(closed_days(&mut latest_date, day(5)), vec![
+            day(2),
+            day(3),
+            day(4)
+        ]);
+        assert_eq!(latest_date, Some(day(5)));
+    }
+}
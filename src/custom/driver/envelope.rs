@@ -0,0 +1,168 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! What every published message says about where it comes from, in its Kafka headers, so that a
+//! consumer reading several chains or processors off shared topics can tell them apart without
+//! decoding the payload. Next to `schema_version` and `ordering_version`, the publisher stamps:
+//! - `chain_id`, on every message,
+//! - `processor_name`, on the messages of a processor, but not on the control messages the
+//!   processors share (health state changes and operation events),
+//! - `transaction_version`, on the messages about one transaction, i.e. every row and the
+//!   transaction itself,
+//! - `block_height`, on transactions, events and write set changes, the messages that carry it.
+//!
+//! The chain id is the node's, read once at startup. With `chain_id` set in the driver config the
+//! indexer refuses to start against a node of another chain. See `client::chain_id` and the other
+//! header readers for consumers.
+
+use crate::{
+    client::{
+        BLOCK_HEIGHT_HEADER, CHAIN_ID_HEADER, ORDERING_VERSION_HEADER, PROCESSOR_NAME_HEADER,
+        SCHEMA_VERSION_HEADER, TRANSACTION_VERSION_HEADER,
+    },
+    custom::driver::ordering::ORDERING_VERSION,
+};
+use rdkafka::message::{Header, OwnedHeaders};
+use std::fmt::{self, Display};
+
+/// What the messages of a publisher are stamped with
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Envelope {
+    pub chain_id: u8,
+    pub processor_name: Option<String>,
+}
+
+/// The transaction a message is about
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Position {
+    pub transaction_version: Option<u64>,
+    pub block_height: Option<u64>,
+}
+
+/// A configured chain id the node doesn't report
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChainIdMismatch {
+    pub configured: u8,
+    pub node: u8,
+}
+
+impl Envelope {
+    pub fn new(chain_id: u8, processor_name: &str) -> Self {
+        Self {
+            chain_id,
+            processor_name: Some(processor_name.to_string()),
+        }
+    }
+
+    /// Of the messages published on behalf of every processor, e.g. on `control_topic`
+    pub fn control(chain_id: u8) -> Self {
+        Self {
+            chain_id,
+            processor_name: None,
+        }
+    }
+
+    /// Headers of a message at `position`, of a model at `schema_version` if it has one, with
+    /// the ordering version
+    pub fn headers(&self, schema_version: Option<u32>, position: Position) -> OwnedHeaders {
+        let numbers = [
+            (ORDERING_VERSION_HEADER, Some(ORDERING_VERSION as u64)),
+            (SCHEMA_VERSION_HEADER, schema_version.map(u64::from)),
+            (CHAIN_ID_HEADER, Some(self.chain_id as u64)),
+            (TRANSACTION_VERSION_HEADER, position.transaction_version),
+            (BLOCK_HEIGHT_HEADER, position.block_height),
+        ];
+        let mut headers = OwnedHeaders::new();
+        for (key, value) in numbers {
+            if let Some(value) = value {
+                headers = headers.insert(Header {
+                    key,
+                    value: Some(value.to_string().as_str()),
+                });
+            }
+        }
+        if let Some(processor_name) = &self.processor_name {
+            headers = headers.insert(Header {
+                key: PROCESSOR_NAME_HEADER,
+                value: Some(processor_name.as_str()),
+            });
+        }
+        headers
+    }
+}
+
+/// The chain id to stamp messages with, the one the node reports, unless `configured` is another
+pub fn chain_id(configured: Option<u8>, node: u8) -> Result<u8, ChainIdMismatch> {
+    match configured {
+        Some(configured) if configured != node => Err(ChainIdMismatch { configured, node }),
+        _ => Ok(node),
+    }
+}
+
+impl Display for ChainIdMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "The driver config's chain_id is {} but the node is on chain {}",
+            self.configured, self.node
+        )
+    }
+}
+
+impl std::error::Error for ChainIdMismatch {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client;
+    use rdkafka::message::Headers;
+
+    fn pairs(headers: &OwnedHeaders) -> Vec<(&str, &[u8])> {
+        headers
+            .iter()
+            .map(|header| (header.key, header.value.unwrap_or_default()))
+            .collect()
+    }
+
+    #[test]
+    fn test_headers() {
+        let envelope = Envelope::new(1, "custom_default_processor");
+        let headers = envelope.headers(Some(2), Position {
+            transaction_version: Some(1_000),
+            block_height: Some(40),
+        });
+        let headers = pairs(&headers);
+        assert_eq!(client::chain_id(headers.clone()), Some(1));
+        assert_eq!(
+            client::processor_name(headers.clone()),
+            Some("custom_default_processor")
+        );
+        assert_eq!(client::transaction_version(headers.clone()), Some(1_000));
+        assert_eq!(client::block_height(headers.clone()), Some(40));
+        assert_eq!(client::schema_version(headers.clone()), Some(2));
+        assert_eq!(client::ordering_version(headers), Some(ORDERING_VERSION));
+
+        // A control message
+        let headers = Envelope::control(4).headers(Some(1), Position::default());
+        let headers = pairs(&headers);
+        assert_eq!(client::chain_id(headers.clone()), Some(4));
+        assert_eq!(client::processor_name(headers.clone()), None);
+        assert_eq!(client::transaction_version(headers.clone()), None);
+        assert_eq!(client::block_height(headers), None);
+    }
+
+    #[test]
+    fn test_chain_id() {
+        assert_eq!(chain_id(None, 1), Ok(1));
+        assert_eq!(chain_id(Some(2), 2), Ok(2));
+        let mismatch = chain_id(Some(1), 2).unwrap_err();
+        assert_eq!(mismatch, ChainIdMismatch {
+            configured: 1,
+            node: 2,
+        });
+        assert_eq!(
+            mismatch.to_string(),
+            "The driver config's chain_id is 1 but the node is on chain 2"
+        );
+    }
+}
@@ -0,0 +1,480 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! HTTP probes for the orchestrator, and a quick look at how far behind the indexer is:
+//! - `GET /healthz` answers 200 while every running processor of the process finished a round
+//!   within `stale_after_secs`, empty or not, and 503 once one didn't, e.g. because its loop is
+//!   stuck. Paused processors and standbys don't run rounds and are never stale.
+//! - `GET /readyz` answers 200 once the database pool hands out a connection and every processor
+//!   committed its first batch, which it published, so the publisher is connected as well
+//! - `GET /status` answers with each processor's last processed version and lag, why it's paused
+//!   and its standby role if any, the ledger version and the uptime, as JSON
+//! - `GET /data_dictionary` answers with the documentation of every indexed table, see
+//!   `queries::data_dictionary`, for data catalogs to import
+//!
+//! The ledger version is read from the node every `ledger_refresh_secs` in the background, so
+//! `/status` doesn't reach the node. The lag is `null` until both versions are known.
+
+use crate::{
+    custom::driver::{config::HealthConfig, standby::Role},
+    database::PgDbPool,
+    queries::data_dictionary_json,
+};
+use aptos_api::context::Context;
+use aptos_logger::{error, info, warn};
+use once_cell::sync::OnceCell;
+use poem::{get, handler, http::StatusCode, listener::TcpListener, Response, Route, Server};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// How long `/readyz` waits for a connection from the pool
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(2);
+
+static HEALTH: OnceCell<Health> = OnceCell::new();
+
+struct Health {
+    connection_pool: PgDbPool,
+    probes: Probes,
+}
+
+/// What the endpoints answer from
+struct Probes {
+    stale_after: Duration,
+    started_at: Instant,
+    ledger_version: Mutex<Option<u64>>,
+    processors: Mutex<HashMap<String, Progress>>,
+}
+
+struct Progress {
+    /// Or when the processor last resumed or took the lead
+    last_round: Instant,
+    /// `None` until the processor commits its first batch
+    last_processed_version: Option<u64>,
+    paused: Option<PauseReason>,
+    /// `None` without `standby`
+    role: Option<Role>,
+}
+
+impl Progress {
+    fn new(now: Instant) -> Self {
+        Self {
+            last_round: now,
+            last_processed_version: None,
+            paused: None,
+            role: None,
+        }
+    }
+
+    /// Whether the processor runs rounds, which is what `/healthz` checks
+    fn running(&self) -> bool {
+        self.paused.is_none() && self.role != Some(Role::Standby)
+    }
+}
+
+/// Why a processor doesn't run rounds for now
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PauseReason {
+    /// Paused through `lifecycle::Indexer::pause_processor`
+    Lifecycle,
+    /// Waiting for the read replica to catch up, see `driver::replication_lag`
+    ReplicationLag,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Status {
+    pub uptime_secs: u64,
+    /// `None` until first read from the node
+    pub ledger_version: Option<u64>,
+    pub processors: Vec<ProcessorStatus>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ProcessorStatus {
+    pub processor: String,
+    pub last_processed_version: Option<u64>,
+    /// Versions between the ledger and the last processed one
+    pub lag: Option<u64>,
+    pub secs_since_last_round: u64,
+    pub paused: Option<PauseReason>,
+    pub role: Option<Role>,
+}
+
+/// Spawns the server and the ledger version refresh if they're enabled. Only the first call in
+/// a process has an effect, so every processor runtime can call it.
+pub fn init(config: &HealthConfig, connection_pool: PgDbPool, context: Arc<Context>) {
+    if !config.enabled {
+        return;
+    }
+    let health = Health {
+        connection_pool,
+        probes: Probes::new(Duration::from_secs(config.stale_after_secs)),
+    };
+    if HEALTH.set(health).is_err() {
+        return;
+    }
+    let address = config.listen_address.clone();
+    tokio::spawn(async move {
+        info!(listen_address = address, "Starting the health server");
+        let routes = Route::new()
+            .at("/healthz", get(healthz))
+            .at("/readyz", get(readyz))
+            .at("/status", get(status))
+            .at("/data_dictionary", get(data_dictionary));
+        if let Err(e) = Server::new(TcpListener::bind(address.as_str()))
+            .run(routes)
+            .await
+        {
+            error!(listen_address = address, error = ?e, "Health server stopped");
+        }
+    });
+    let refresh = Duration::from_secs(config.ledger_refresh_secs.max(1));
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(refresh);
+        loop {
+            interval.tick().await;
+            match context.get_latest_ledger_info_wrapped() {
+                Ok(ledger_info) => HEALTH
+                    .get()
+                    .unwrap()
+                    .probes
+                    .on_ledger_version(ledger_info.ledger_version.0),
+                Err(e) => warn!(error = ?e, "Failed to read the ledger version"),
+            }
+        }
+    });
+}
+
+/// Starts tracking `processor`, as if it had just finished a round
+pub fn register(processor: &str) {
+    if let Some(health) = HEALTH.get() {
+        health.probes.register(processor, Instant::now());
+    }
+}
+
+/// Called after every round of `processor`, with the end of the round if it committed any batch
+pub fn on_round(processor: &str, committed_version: Option<u64>) {
+    if let Some(health) = HEALTH.get() {
+        health
+            .probes
+            .on_round(processor, committed_version, Instant::now());
+    }
+}
+
+/// Called when `processor` stops running rounds for `reason`, until its next round or resume
+pub fn on_paused(processor: &str, reason: PauseReason) {
+    if let Some(health) = HEALTH.get() {
+        health.probes.on_paused(processor, reason);
+    }
+}
+
+/// Called when `processor` resumes, which restarts the clock of `/healthz`
+pub fn on_resumed(processor: &str) {
+    if let Some(health) = HEALTH.get() {
+        health.probes.on_resumed(processor, Instant::now());
+    }
+}
+
+/// Called when the process takes the lead of `processor` or becomes its standby
+pub fn on_role(processor: &str, role: Role) {
+    if let Some(health) = HEALTH.get() {
+        health.probes.on_role(processor, role, Instant::now());
+    }
+}
+
+impl Probes {
+    fn new(stale_after: Duration) -> Self {
+        Self {
+            stale_after,
+            started_at: Instant::now(),
+            ledger_version: Mutex::new(None),
+            processors: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn register(&self, processor: &str, now: Instant) {
+        self.processors
+            .lock()
+            .unwrap()
+            .entry(processor.to_string())
+            .or_insert_with(|| Progress::new(now));
+    }
+
+    fn on_round(&self, processor: &str, committed_version: Option<u64>, now: Instant) {
+        let mut processors = self.processors.lock().unwrap();
+        let progress = processors
+            .entry(processor.to_string())
+            .or_insert_with(|| Progress::new(now));
+        progress.last_round = now;
+        progress.paused = None;
+        if committed_version.is_some() {
+            progress.last_processed_version = committed_version;
+        }
+    }
+
+    fn on_paused(&self, processor: &str, reason: PauseReason) {
+        let mut processors = self.processors.lock().unwrap();
+        if let Some(progress) = processors.get_mut(processor) {
+            progress.paused = Some(reason);
+        }
+    }
+
+    fn on_resumed(&self, processor: &str, now: Instant) {
+        let mut processors = self.processors.lock().unwrap();
+        if let Some(progress) = processors.get_mut(processor) {
+            if progress.paused.take().is_some() {
+                progress.last_round = now;
+            }
+        }
+    }
+
+    fn on_role(&self, processor: &str, role: Role, now: Instant) {
+        let mut processors = self.processors.lock().unwrap();
+        let progress = processors
+            .entry(processor.to_string())
+            .or_insert_with(|| Progress::new(now));
+        if progress.role != Some(role) {
+            progress.role = Some(role);
+            progress.last_round = now;
+        }
+    }
+
+    fn on_ledger_version(&self, version: u64) {
+        *self.ledger_version.lock().unwrap() = Some(version);
+    }
+
+    /// The running processors that didn't finish a round within `stale_after`
+    fn stale(&self, now: Instant) -> Vec<String> {
+        let mut stale = self
+            .processors
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, progress)| {
+                progress.running() && now.duration_since(progress.last_round) > self.stale_after
+            })
+            .map(|(processor, _)| processor.clone())
+            .collect::<Vec<_>>();
+        stale.sort();
+        stale
+    }
+
+    /// The processors that didn't commit a batch yet
+    fn starting(&self) -> Vec<String> {
+        let mut starting = self
+            .processors
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, progress)| progress.last_processed_version.is_none())
+            .map(|(processor, _)| processor.clone())
+            .collect::<Vec<_>>();
+        starting.sort();
+        starting
+    }
+
+    fn status(&self, now: Instant) -> Status {
+        let ledger_version = *self.ledger_version.lock().unwrap();
+        let mut processors = self
+            .processors
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(processor, progress)| ProcessorStatus {
+                processor: processor.clone(),
+                last_processed_version: progress.last_processed_version,
+                lag: ledger_version
+                    .zip(progress.last_processed_version)
+                    .map(|(ledger, processed)| ledger.saturating_sub(processed)),
+                secs_since_last_round: now.duration_since(progress.last_round).as_secs(),
+                paused: progress.paused,
+                role: progress.role,
+            })
+            .collect::<Vec<_>>();
+        processors.sort_by(|a, b| a.processor.cmp(&b.processor));
+        Status {
+            uptime_secs: now.duration_since(self.started_at).as_secs(),
+            ledger_version,
+            processors,
+        }
+    }
+}
+
+fn json_response(status: StatusCode, body: serde_json::Value) -> Response {
+    Response::builder()
+        .status(status)
+        .content_type("application/json")
+        .body(body.to_string())
+}
+
+#[handler]
+fn healthz() -> Response {
+    let stale = HEALTH.get().unwrap().probes.stale(Instant::now());
+    if stale.is_empty() {
+        json_response(StatusCode::OK, serde_json::json!({ "status": "ok" }))
+    } else {
+        json_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            serde_json::json!({ "status": "stale", "processors": stale }),
+        )
+    }
+}
+
+#[handler]
+async fn readyz() -> Response {
+    let health = HEALTH.get().unwrap();
+    let connection_pool = health.connection_pool.clone();
+    // The pool blocks until it has a connection
+    let database = tokio::task::spawn_blocking(move || {
+        connection_pool
+            .get_timeout(CONNECTION_TIMEOUT)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .unwrap_or_else(|e| Err(e.to_string()));
+    let starting = health.probes.starting();
+    match database {
+        Ok(()) if starting.is_empty() => {
+            json_response(StatusCode::OK, serde_json::json!({ "status": "ready" }))
+        },
+        Ok(()) => json_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            serde_json::json!({ "status": "starting", "processors": starting }),
+        ),
+        Err(e) => json_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            serde_json::json!({ "status": "database_unavailable", "error": e }),
+        ),
+    }
+}
+
+#[handler]
+fn status() -> Response {
+    let status = HEALTH.get().unwrap().probes.status(Instant::now());
+    json_response(StatusCode::OK, serde_json::json!(status))
+}
+
+#[handler]
+fn data_dictionary() -> Response {
+    json_response(StatusCode::OK, data_dictionary_json())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use poem::{Endpoint, Request};
+
+    #[test]
+    fn test_liveness_and_readiness() {
+        let probes = Probes::new(Duration::from_secs(60));
+        let start = Instant::now();
+        probes.register("a", start);
+        probes.register("b", start);
+        assert!(probes.stale(start).is_empty());
+        assert_eq!(probes.starting(), ["a", "b"]);
+
+        // An empty round is progress, but commits nothing
+        let later = start + Duration::from_secs(61);
+        probes.on_round("a", Some(100), later);
+        probes.on_round("b", None, later);
+        assert!(probes.stale(later).is_empty());
+        assert_eq!(probes.starting(), ["b"]);
+        probes.on_round("b", Some(90), later);
+        assert!(probes.starting().is_empty());
+
+        let stuck = later + Duration::from_secs(30);
+        probes.on_round("a", None, stuck);
+        assert!(probes.stale(stuck).is_empty());
+        assert_eq!(probes.stale(stuck + Duration::from_secs(31)), ["b"]);
+    }
+
+    #[test]
+    fn test_status() {
+        let probes = Probes::new(Duration::from_secs(60));
+        let now = probes.started_at + Duration::from_secs(5);
+        probes.register("a", probes.started_at);
+        probes.on_round("b", Some(100), now);
+        assert_eq!(probes.status(now).processors[1].lag, None);
+
+        probes.on_ledger_version(150);
+        assert_eq!(probes.status(now), Status {
+            uptime_secs: 5,
+            ledger_version: Some(150),
+            processors: vec![
+                ProcessorStatus {
+                    processor: "a".to_string(),
+                    last_processed_version: None,
+                    lag: None,
+                    secs_since_last_round: 5,
+                    paused: None,
+                    role: None,
+                },
+                ProcessorStatus {
+                    processor: "b".to_string(),
+                    last_processed_version: Some(100),
+                    lag: Some(50),
+                    secs_since_last_round: 0,
+                    paused: None,
+                    role: None,
+                },
+            ],
+        });
+    }
+
+    #[test]
+    fn test_paused_and_standby_are_live() {
+        let probes = Probes::new(Duration::from_secs(60));
+        let start = Instant::now();
+        probes.register("a", start);
+        probes.register("b", start);
+        probes.register("c", start);
+        probes.on_paused("a", PauseReason::Lifecycle);
+        probes.on_paused("b", PauseReason::ReplicationLag);
+        probes.on_role("c", Role::Standby, start);
+
+        let later = start + Duration::from_secs(600);
+        assert!(probes.stale(later).is_empty());
+        let status = probes.status(later);
+        assert_eq!(status.processors[0].paused, Some(PauseReason::Lifecycle));
+        assert_eq!(
+            status.processors[1].paused,
+            Some(PauseReason::ReplicationLag)
+        );
+        assert_eq!(status.processors[2].role, Some(Role::Standby));
+
+        // Resuming or taking the lead restarts the clock rather than counting the pause
+        probes.on_resumed("a", later);
+        probes.on_role("c", Role::Leader, later);
+        assert!(probes.stale(later + Duration::from_secs(30)).is_empty());
+        assert_eq!(probes.stale(later + Duration::from_secs(61)), ["a", "c"]);
+        // The next round ends a replication lag pause
+        probes.on_round("b", None, later);
+        assert_eq!(probes.status(later).processors[1].paused, None);
+    }
+
+    #[tokio::test]
+    async fn test_data_dictionary() {
+        let response = data_dictionary.call(Request::default()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "application/json"
+        );
+        let body = response
+            .into_body()
+            .into_json::<serde_json::Value>()
+            .await
+            .unwrap();
+        assert_eq!(body, data_dictionary_json());
+        assert!(body["tables"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|table| table["table"] == "transactions"));
+    }
+}
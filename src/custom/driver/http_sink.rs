@@ -0,0 +1,380 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Publishing to an HTTP endpoint instead of Kafka, with `sink.backend` set to `http`. Every
+//! batch's transactions, and with `events` its events and write set changes first, are POSTed to
+//! `url` as JSON `client::HttpBatch`es of at most `batch_size` records, in publishing order, with
+//! the `bearer_token` if one is configured.
+//!
+//! A request that times out, gets no response, or gets a 5xx or a 429 is attempted again up to
+//! `max_attempts` times, waiting `base_delay_millis` doubled after every failed attempt, at most
+//! `max_delay_millis`, with jitter. Every retry draws a `publish` attempt from the batch's retry
+//! budget, see `driver::retry_budget`, and is counted in `indexer_http_sink_retries_count` by
+//! model. Any other status, or a retryable one after the last attempt, fails the batch with an
+//! `HttpSinkError`, so the watermark doesn't move past it: a retryable failure has the driver
+//! process the batch again, see `driver::batch_retry`, and any other stops the processor. Requests
+//! that went through before the failing one are sent again then, so the endpoint has to take
+//! duplicates, by transaction version.
+
+use crate::{
+    client::HttpBatch,
+    counters::HTTP_SINK_RETRIES,
+    custom::driver::{
+        config::HttpSinkConfig,
+        envelope::Envelope,
+        ordering::{self, Ordered},
+        payload_schema, publish_retry,
+        retry_budget::{self, ErrorClass},
+        sink::Sink,
+    },
+    models::{events::EventModel, write_set_changes::WriteSetChangeModel},
+};
+use aptos_api_types::Transaction;
+use async_trait::async_trait;
+use serde::Serialize;
+use std::{
+    fmt::{self, Display},
+    time::Duration,
+};
+
+/// Of an error response body, in `HttpSinkError`
+const MAX_ERROR_BODY_CHARS: usize = 200;
+
+pub struct HttpSink {
+    client: reqwest::Client,
+    config: HttpSinkConfig,
+    envelope: Envelope,
+}
+
+/// A POST that failed for good
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpSinkError {
+    pub model: String,
+    pub attempts: u32,
+    /// `None` if there was no response, e.g. a timeout
+    pub status: Option<u16>,
+    pub error: String,
+}
+
+impl HttpSink {
+    /// Stamps every batch with `envelope`'s chain id and processor name
+    pub fn new(config: &HttpSinkConfig, envelope: Envelope) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_millis))
+            .build()
+            .expect("Failed to build the HTTP sink client");
+        Self {
+            client,
+            config: config.clone(),
+            envelope,
+        }
+    }
+
+    /// POSTs `records` in publishing order, `batch_size` at a time, stopping at the first request
+    /// that fails for good
+    async fn post<T: Serialize + Ordered>(&self, model: &str, records: &[T]) -> anyhow::Result<()> {
+        let records = ordering::sort(records);
+        for chunk in records.chunks(self.config.batch_size) {
+            let batch = HttpBatch {
+                model: model.to_string(),
+                chain_id: self.envelope.chain_id,
+                processor_name: self.envelope.processor_name.clone().unwrap_or_default(),
+                schema_version: payload_schema::current_version(model),
+                records: chunk.to_vec(),
+            };
+            self.post_batch(model, &batch).await?;
+        }
+        Ok(())
+    }
+
+    async fn post_batch<T: Serialize>(
+        &self,
+        model: &str,
+        batch: &HttpBatch<T>,
+    ) -> anyhow::Result<()> {
+        let mut attempt = 1;
+        loop {
+            let mut request = self.client.post(&self.config.url).json(batch);
+            if let Some(token) = &self.config.bearer_token {
+                request = request.bearer_auth(token);
+            }
+            let (status, error) = match request.send().await {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) => {
+                    let status = response.status();
+                    let body = response.text().await.unwrap_or_default();
+                    let body = body.chars().take(MAX_ERROR_BODY_CHARS).collect::<String>();
+                    let error = format!("{} {}", status, body.trim());
+                    (Some(status.as_u16()), error.trim_end().to_string())
+                },
+                Err(err) => (None, err.to_string()),
+            };
+            let error = HttpSinkError {
+                model: model.to_string(),
+                attempts: attempt,
+                status,
+                error,
+            };
+            if !error.is_transient() || attempt >= self.config.max_attempts {
+                return Err(error.into());
+            }
+            retry_budget::retry(ErrorClass::Publish, model, &error)?;
+            let delay = self.delay(attempt, publish_retry::jitter());
+            aptos_logger::warn!(
+                model = model,
+                attempt = attempt,
+                delay_millis = delay.as_millis() as u64,
+                error = ?error,
+                "Failed to post to the HTTP sink, will retry"
+            );
+            HTTP_SINK_RETRIES.with_label_values(&[model]).inc();
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// How long to wait after the `attempt`th attempt failed, `jitter` in `0.0..1.0` taking off
+    /// up to half of it
+    fn delay(&self, attempt: u32, jitter: f64) -> Duration {
+        let backoff = self
+            .config
+            .base_delay_millis
+            .saturating_mul(1 << (attempt - 1).min(32))
+            .min(self.config.max_delay_millis);
+        Duration::from_millis(backoff - (backoff as f64 * jitter / 2.0) as u64)
+    }
+}
+
+#[async_trait]
+impl Sink for HttpSink {
+    fn publishes(&self, model: &str) -> bool {
+        match model {
+            "TransactionModel" => true,
+            "EventModel" | "WriteSetChangeModel" => self.config.events,
+            _ => false,
+        }
+    }
+
+    /// Nothing is dead lettered, a batch the endpoint doesn't take fails
+    async fn send_txs(&self, txns: &[Transaction]) -> anyhow::Result<usize> {
+        self.post("TransactionModel", txns).await?;
+        Ok(0)
+    }
+
+    async fn send_events(
+        &self,
+        events: &[EventModel],
+        wscs: &[WriteSetChangeModel],
+    ) -> anyhow::Result<usize> {
+        if self.config.events {
+            self.post("EventModel", events).await?;
+            self.post("WriteSetChangeModel", wscs).await?;
+        }
+        Ok(0)
+    }
+
+    /// Every POST is answered before the batch goes on, there's nothing to wait for
+    async fn flush(&self, _timeout: Duration) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+impl HttpSinkError {
+    /// A timeout, no response, a server error or a 429, which may pass when sent again
+    pub fn is_transient(&self) -> bool {
+        match self.status {
+            None => true,
+            Some(status) => status >= 500 || status == 429,
+        }
+    }
+}
+
+impl Display for HttpSinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Failed to post {} to the HTTP sink after {} attempt(s): {}",
+            self.model, self.attempts, self.error
+        )
+    }
+}
+
+impl std::error::Error for HttpSinkError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::errors::{classify, ErrorKind};
+    use poem::{
+        handler,
+        http::StatusCode,
+        listener::{Acceptor, Listener, TcpListener},
+        web::Data,
+        Body, EndpointExt, Request, Server,
+    };
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+
+    /// Answers the requests with `statuses` in turn, then with 200
+    #[derive(Default)]
+    struct Endpoint {
+        statuses: Vec<u16>,
+        /// The authorization header and body of every request
+        requests: Mutex<Vec<(Option<String>, HttpBatch<Value>)>>,
+    }
+
+    #[handler]
+    async fn receive(request: &Request, body: Body, endpoint: Data<&Arc<Endpoint>>) -> StatusCode {
+        let authorization = request
+            .headers()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let body = serde_json::from_slice(&body.into_vec().await.unwrap()).unwrap();
+        let mut requests = endpoint.requests.lock().unwrap();
+        let status = endpoint
+            .statuses
+            .get(requests.len())
+            .copied()
+            .unwrap_or(200);
+        requests.push((authorization, body));
+        StatusCode::from_u16(status).unwrap()
+    }
+
+    /// Serves `statuses` on a free port, the URL to post to
+    async fn serve(statuses: &[u16]) -> (String, Arc<Endpoint>) {
+        let endpoint = Arc::new(Endpoint {
+            statuses: statuses.to_vec(),
+            ..Endpoint::default()
+        });
+        let acceptor = TcpListener::bind("127.0.0.1:0")
+            .into_acceptor()
+            .await
+            .unwrap();
+        let address = *acceptor.local_addr()[0].as_socket_addr().unwrap();
+        let app = receive.data(endpoint.clone());
+        tokio::spawn(Server::new_with_acceptor(acceptor).run(app));
+        (format!("http://{}/batches", address), endpoint)
+    }
+
+    fn sink(url: String, batch_size: usize) -> HttpSink {
+        let config = HttpSinkConfig {
+            url,
+            bearer_token: Some("secret".to_string()),
+            batch_size,
+            max_attempts: 3,
+            base_delay_millis: 1,
+            max_delay_millis: 10,
+            ..HttpSinkConfig::default()
+        };
+        config.validate().unwrap();
+        HttpSink::new(&config, Envelope::new(4, "http_sink_test"))
+    }
+
+    fn transaction(version: u64) -> Transaction {
+        serde_json::from_value(json!({
+            "type": "block_metadata_transaction",
+            "version": version.to_string(),
+            "block_height": "100",
+            "epoch": "1",
+            "hash": format!("0x{:064x}", version),
+            "state_change_hash": format!("0x{:064x}", 0),
+            "event_root_hash": format!("0x{:064x}", 0),
+            "gas_used": "0",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": format!("0x{:064x}", 0),
+            "id": format!("0x{:064x}", 1),
+            "round": "57600",
+            "failed_proposer_indices": [],
+            "previous_block_votes_bitvec": [],
+            "proposer": "0x68f04222bd9f8846cda028ea5ba3846a806b04a47e1f1a4f0939f350d713b2eb",
+            "timestamp": "1649395495746947",
+            "events": [],
+            "changes": []
+        }))
+        .unwrap()
+    }
+
+    fn versions(batch: &HttpBatch<Value>) -> Vec<u64> {
+        batch
+            .records
+            .iter()
+            .map(|record| record["version"].as_str().unwrap().parse().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_retries_server_errors() {
+        // Flakes on the first request
+        let (url, endpoint) = serve(&[503]).await;
+        let sink = sink(url, 2);
+        let txns = [transaction(12), transaction(10), transaction(11)];
+        assert_eq!(sink.send_txs(&txns).await.unwrap(), 0);
+
+        let requests = endpoint.requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests
+            .iter()
+            .all(|(authorization, _)| authorization.as_deref() == Some("Bearer secret")));
+        // The first chunk again, then the rest, in version order
+        let batches = requests.iter().map(|(_, batch)| batch).collect::<Vec<_>>();
+        assert_eq!(versions(batches[0]), [10, 11]);
+        assert_eq!(versions(batches[1]), [10, 11]);
+        assert_eq!(versions(batches[2]), [12]);
+        assert_eq!(batches[2].model, "TransactionModel");
+        assert_eq!(batches[2].chain_id, 4);
+        assert_eq!(batches[2].processor_name, "http_sink_test");
+        assert_eq!(
+            batches[2].schema_version,
+            payload_schema::current_version("TransactionModel")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fails_the_batch() {
+        // Still failing after the last attempt
+        let (url, endpoint) = serve(&[500, 502, 500]).await;
+        let error = sink(url, 10)
+            .send_txs(&[transaction(10)])
+            .await
+            .unwrap_err();
+        assert_eq!(endpoint.requests.lock().unwrap().len(), 3);
+        let failure = error.downcast_ref::<HttpSinkError>().unwrap();
+        assert_eq!(failure.attempts, 3);
+        assert_eq!(failure.status, Some(500));
+        assert_eq!(classify(&error), ErrorKind::Retryable);
+
+        // Not retried, and needs a look
+        let (url, endpoint) = serve(&[400]).await;
+        let error = sink(url, 10)
+            .send_txs(&[transaction(10)])
+            .await
+            .unwrap_err();
+        assert_eq!(endpoint.requests.lock().unwrap().len(), 1);
+        assert_eq!(
+            error.to_string(),
+            "Failed to post TransactionModel to the HTTP sink after 1 attempt(s): 400 Bad Request"
+        );
+        assert_eq!(classify(&error), ErrorKind::Fatal);
+
+        // Nothing listening
+        let error = sink("http://127.0.0.1:1/batches".to_string(), 10)
+            .send_txs(&[transaction(10)])
+            .await
+            .unwrap_err();
+        assert_eq!(error.downcast_ref::<HttpSinkError>().unwrap().status, None);
+        assert_eq!(classify(&error), ErrorKind::Retryable);
+    }
+
+    #[test]
+    fn test_publishes() {
+        let mut sink = sink("http://localhost/batches".to_string(), 10);
+        assert!(sink.publishes("TransactionModel"));
+        assert!(!sink.publishes("EventModel"));
+        assert!(!sink.publishes("CurrentMoveResource"));
+        assert!(sink.publisher().is_none());
+        sink.config.events = true;
+        assert!(sink.publishes("WriteSetChangeModel"));
+    }
+}
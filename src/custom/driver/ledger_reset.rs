@@ -0,0 +1,388 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Detection of a ledger that was reset under the indexer. Localnets and devnets restart from a
+//! fresh genesis with the same chain id, so the chain id check passes while the stored watermark
+//! points past the new ledger. The hash of the transaction at version 0 is stored in
+//! `ledger_infos` and compared with the node's at startup, before the watermark is loaded.
+//!
+//! On a mismatch the `halt` policy stops the processor with an error. The `wipe` policy, which
+//! also needs `allow_auto_reset`, truncates the indexer's own tables, the ones of the diesel
+//! schema, except `PRESERVED_TABLES` and `preserved_tables`, which drops the watermarks with the
+//! data, and records the reset in `ledger_resets`. Anything else in the schema is left alone. The
+//! wipe runs in one transaction under an advisory lock, so of several processors starting together
+//! only the first wipes and the others find the new hash stored.
+//!
+//! Running processors hold the same lock shared for every round of batches, see `Fence`, so a wipe
+//! waits for the rounds in flight, in this process or any other, and no round starts until it's
+//! committed. The next round of every processor then finds the new hash and restarts it from its
+//! truncated watermark.
+
+use crate::{
+    counters::LEDGER_RESETS,
+    custom::driver::config::LedgerResetConfig,
+    database::{execute_with_better_error, PgDbPool, PgPoolConnection},
+    models::{ledger_info::LedgerInfo, ledger_resets::LedgerReset},
+    queries::indexed_tables,
+    schema::{ledger_infos, ledger_resets},
+};
+use anyhow::{bail, Context as _};
+use aptos_api::context::Context;
+use aptos_logger::{info, warn};
+use diesel::{
+    pg::upsert::excluded,
+    sql_query,
+    sql_types::{BigInt, Text},
+    Connection, ExpressionMethods, RunQueryDsl,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+/// Never truncated: the migrations, the ledger and reset bookkeeping, and operator state
+pub const PRESERVED_TABLES: &[&str] = &[
+    "__diesel_schema_migrations",
+    "ledger_infos",
+    "ledger_resets",
+    "backfill_windows",
+    "indexer_column_stats",
+];
+
+/// Serializes the check of concurrently starting processors and fences the running ones, any
+/// constant shared by all of them
+const ADVISORY_LOCK_KEY: i64 = 0x6c65_6467_6572;
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResetPolicy {
+    /// Stop with an error and leave the data to the operator
+    Halt,
+    /// Truncate the indexed tables and index the new ledger from version 0
+    Wipe,
+}
+
+impl ResetPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResetPolicy::Halt => "halt",
+            ResetPolicy::Wipe => "wipe",
+        }
+    }
+}
+
+#[derive(Debug, QueryableByName)]
+struct TableName {
+    #[diesel(sql_type = Text)]
+    tablename: String,
+}
+
+/// Compares the stored genesis hash with the node's and applies the configured policy on a
+/// mismatch. Stores the chain id and hash if none is stored yet.
+pub fn check(
+    processor_name: &str,
+    config: &LedgerResetConfig,
+    connection_pool: &PgDbPool,
+    context: Arc<Context>,
+) -> anyhow::Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+    let Some((chain_id, node_hash)) = node_genesis_hash(&context)? else {
+        warn!(
+            processor_name = processor_name,
+            "Genesis is pruned on the node, skipping the ledger reset check"
+        );
+        return Ok(());
+    };
+    let mut conn = connection_pool.get()?;
+    conn.transaction::<_, anyhow::Error, _>(|conn| {
+        sql_query("SELECT pg_advisory_xact_lock($1)")
+            .bind::<BigInt, _>(ADVISORY_LOCK_KEY)
+            .execute(conn)?;
+        // Read under the lock, another processor may have just handled the reset
+        let ledger_info = match LedgerInfo::get(conn)? {
+            Some(ledger_info) if ledger_info.chain_id == chain_id => ledger_info,
+            // A different chain altogether, which the chain id check reports
+            Some(_) => return Ok(()),
+            None => LedgerInfo {
+                chain_id,
+                genesis_hash: None,
+            },
+        };
+        match ledger_info.genesis_hash {
+            None => {
+                info!(
+                    processor_name = processor_name,
+                    genesis_hash = node_hash,
+                    "Storing the genesis hash of the indexed ledger"
+                );
+                store_genesis_hash(conn, ledger_info.chain_id, &node_hash)
+            },
+            Some(stored_hash) if stored_hash == node_hash => Ok(()),
+            Some(stored_hash) => {
+                LEDGER_RESETS
+                    .with_label_values(&[processor_name, config.policy.as_str()])
+                    .inc();
+                if config.policy == ResetPolicy::Halt || !config.allow_auto_reset {
+                    bail!(
+                        "Ledger reset detected! Chain {} now has genesis {} but existing data is for genesis {}. \
+                        Wipe the database, or set ledger_reset.policy to \"wipe\" and \
+                        ledger_reset.allow_auto_reset to true to let the indexer do it.",
+                        chain_id,
+                        node_hash,
+                        stored_hash
+                    );
+                }
+                wipe(
+                    conn,
+                    processor_name,
+                    chain_id,
+                    &stored_hash,
+                    &node_hash,
+                    &config.preserved_tables,
+                )
+            },
+        }
+    })
+}
+
+/// Keeps a running processor's rounds of batches out of a wipe. Holds a connection of its own,
+/// since the shared lock belongs to the session that takes it.
+pub struct Fence {
+    processor_name: String,
+    /// The hash the processor started indexing under
+    genesis_hash: String,
+    conn: Mutex<PgPoolConnection>,
+}
+
+/// A round of batches in flight, which a wipe waits for. Releases the lock when dropped.
+pub struct FencedRound<'a> {
+    fence: &'a Fence,
+}
+
+impl Fence {
+    /// `None` with the check disabled or no hash stored, e.g. because the node pruned its genesis
+    pub fn new(
+        processor_name: &str,
+        config: &LedgerResetConfig,
+        connection_pool: &PgDbPool,
+    ) -> anyhow::Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let mut conn = connection_pool.get()?;
+        let Some(genesis_hash) = LedgerInfo::get(&mut conn)?.and_then(|info| info.genesis_hash)
+        else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            processor_name: processor_name.to_string(),
+            genesis_hash,
+            conn: Mutex::new(conn),
+        }))
+    }
+
+    /// Called before a round of batches. Waits while a wipe is running, and returns `None` if the
+    /// ledger was reset since the processor started, in which case it must be restarted from its
+    /// watermark.
+    pub fn enter(&self) -> anyhow::Result<Option<FencedRound<'_>>> {
+        let stored_hash = {
+            let mut conn = self.conn.lock().unwrap();
+            sql_query("SELECT pg_advisory_lock_shared($1)")
+                .bind::<BigInt, _>(ADVISORY_LOCK_KEY)
+                .execute(&mut *conn)?;
+            LedgerInfo::get(&mut conn)
+        };
+        // Released when dropped, also on an error
+        let round = FencedRound { fence: self };
+        let stored_hash = stored_hash?.and_then(|info| info.genesis_hash);
+        if stored_hash.as_deref() == Some(self.genesis_hash.as_str()) {
+            return Ok(Some(round));
+        }
+        warn!(
+            processor_name = self.processor_name,
+            genesis_hash = self.genesis_hash,
+            stored_genesis_hash = stored_hash,
+            "The ledger was reset under the processor, restarting it"
+        );
+        Ok(None)
+    }
+}
+
+impl Drop for FencedRound<'_> {
+    fn drop(&mut self) {
+        let mut conn = self.fence.conn.lock().unwrap();
+        if let Err(e) = sql_query("SELECT pg_advisory_unlock_shared($1)")
+            .bind::<BigInt, _>(ADVISORY_LOCK_KEY)
+            .execute(&mut *conn)
+        {
+            warn!(
+                processor_name = self.fence.processor_name,
+                error = ?e,
+                "Failed to release the ledger reset fence"
+            );
+        }
+    }
+}
+
+/// Chain id and hash of the node's transaction at version 0, `None` if it was pruned
+fn node_genesis_hash(context: &Context) -> anyhow::Result<Option<(i64, String)>> {
+    let ledger_info = context
+        .get_latest_ledger_info_wrapped()
+        .map_err(|e| anyhow::anyhow!("Failed to get ledger info: {}", e))?;
+    if ledger_info.oldest_ledger_version.0 > 0 {
+        return Ok(None);
+    }
+    let genesis = context
+        .get_transactions(0, 1, ledger_info.ledger_version.0)
+        .context("Failed to fetch the genesis transaction")?;
+    Ok(genesis.first().map(|txn| {
+        (
+            ledger_info.chain_id as i64,
+            txn.info.transaction_hash().to_hex_literal(),
+        )
+    }))
+}
+
+fn store_genesis_hash(
+    conn: &mut PgPoolConnection,
+    chain_id: i64,
+    genesis_hash: &str,
+) -> anyhow::Result<()> {
+    execute_with_better_error(
+        conn,
+        diesel::insert_into(ledger_infos::table)
+            .values(LedgerInfo {
+                chain_id,
+                genesis_hash: Some(genesis_hash.to_string()),
+            })
+            .on_conflict(ledger_infos::chain_id)
+            .do_update()
+            .set(ledger_infos::genesis_hash.eq(excluded(ledger_infos::genesis_hash))),
+        None,
+    )?;
+    Ok(())
+}
+
+fn wipe(
+    conn: &mut PgPoolConnection,
+    processor_name: &str,
+    chain_id: i64,
+    previous_genesis_hash: &str,
+    new_genesis_hash: &str,
+    preserved_tables: &[String],
+) -> anyhow::Result<()> {
+    // The indexer's own tables, of those in the schema this connection resolves unqualified names
+    // in, which is where the migrations created them. Tables of a migration not run yet are left
+    // out, since truncating them would fail.
+    let indexed_tables = indexed_tables();
+    let tables = sql_query("SELECT tablename FROM pg_tables WHERE schemaname = current_schema()")
+        .load::<TableName>(conn)?
+        .into_iter()
+        .map(|table| table.tablename)
+        .filter(|table| {
+            indexed_tables.contains(table)
+                && !PRESERVED_TABLES.contains(&table.as_str())
+                && !preserved_tables.contains(table)
+        })
+        .collect::<Vec<_>>();
+    warn!(
+        processor_name = processor_name,
+        chain_id = chain_id,
+        previous_genesis_hash = previous_genesis_hash,
+        new_genesis_hash = new_genesis_hash,
+        tables = tables.len(),
+        "Ledger reset detected, wiping the indexed tables"
+    );
+    if !tables.is_empty() {
+        let quoted = tables
+            .iter()
+            .map(|table| format!("\"{}\"", table.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(", ");
+        sql_query(format!("TRUNCATE TABLE {} RESTART IDENTITY", quoted)).execute(conn)?;
+    }
+    store_genesis_hash(conn, chain_id, new_genesis_hash)?;
+    execute_with_better_error(
+        conn,
+        diesel::insert_into(ledger_resets::table).values(&LedgerReset {
+            chain_id,
+            previous_genesis_hash: previous_genesis_hash.to_string(),
+            new_genesis_hash: new_genesis_hash.to_string(),
+            action: ResetPolicy::Wipe.as_str().to_string(),
+            truncated_tables: serde_json::to_value(&tables)?,
+            processor: processor_name.to_string(),
+        }),
+        None,
+    )?;
+    info!(
+        processor_name = processor_name,
+        "Wiped the indexed tables, indexing the new ledger from version 0"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        custom::test_utils, models::processor_status::ProcessorStatusV2, schema::processor_status,
+    };
+    use diesel::{sql_types::Integer, QueryDsl};
+
+    #[derive(QueryableByName)]
+    struct Count {
+        #[diesel(sql_type = BigInt)]
+        count: i64,
+    }
+
+    #[test]
+    fn test_wipe_keeps_foreign_tables() {
+        let Some(conn_pool) = test_utils::test_pool() else {
+            return;
+        };
+        let mut conn = conn_pool.get().unwrap();
+        // Rolled back, truncation included
+        conn.begin_test_transaction().unwrap();
+        sql_query("CREATE TABLE ledger_reset_foreign (id INT PRIMARY KEY)")
+            .execute(&mut conn)
+            .unwrap();
+        sql_query("INSERT INTO ledger_reset_foreign VALUES ($1)")
+            .bind::<Integer, _>(1)
+            .execute(&mut conn)
+            .unwrap();
+        diesel::insert_into(processor_status::table)
+            .values(ProcessorStatusV2 {
+                processor: "ledger_reset_test".to_string(),
+                last_success_version: 100,
+            })
+            .execute(&mut conn)
+            .unwrap();
+
+        wipe(&mut conn, "ledger_reset_test", 4, "0xaa", "0xbb", &[]).unwrap();
+
+        let foreign = sql_query("SELECT COUNT(*) AS count FROM ledger_reset_foreign")
+            .get_result::<Count>(&mut conn)
+            .unwrap();
+        assert_eq!(foreign.count, 1);
+        let watermarks = processor_status::table
+            .count()
+            .get_result::<i64>(&mut conn)
+            .unwrap();
+        assert_eq!(watermarks, 0);
+        let reset = ledger_resets::table
+            .select(ledger_resets::truncated_tables)
+            .order(ledger_resets::id.desc())
+            .first::<serde_json::Value>(&mut conn)
+            .unwrap();
+        let truncated = serde_json::from_value::<Vec<String>>(reset).unwrap();
+        assert!(truncated.contains(&"processor_status".to_string()));
+        assert!(!truncated.contains(&"ledger_reset_foreign".to_string()));
+        assert!(!truncated.contains(&"ledger_infos".to_string()));
+        let genesis_hash = ledger_infos::table
+            .find(4)
+            .select(ledger_infos::genesis_hash)
+            .first::<Option<String>>(&mut conn)
+            .unwrap();
+        assert_eq!(genesis_hash.as_deref(), Some("0xbb"));
+    }
+}
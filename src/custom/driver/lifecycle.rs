@@ -0,0 +1,281 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Pausing, resuming, reloading, demoting and backfilling single processors while the others keep
+//! running. Every processor runs its own pipeline (fetcher, batches, watermark in
+//! `processor_status`), so a paused processor just falls behind and catches up from its watermark
+//! once resumed; no versions are skipped. Commands take effect between two rounds of batches,
+//! i.e. once the in-flight batches are committed and the watermark is saved.
+
+use crate::{
+    counters::{PROCESSOR_PAUSED, PROCESSOR_RELOADS},
+    custom::driver::{
+        config::{DriverConfig, DEFAULT_CONFIG_PATH},
+        health::{self, PauseReason},
+    },
+};
+use anyhow::{bail, Result};
+use aptos_logger::info;
+use once_cell::sync::Lazy;
+use std::{collections::HashMap, sync::RwLock, time::Duration};
+use tokio::sync::watch;
+
+static INDEXER: Lazy<Indexer> = Lazy::new(|| Indexer {
+    controls: RwLock::new(HashMap::new()),
+});
+
+#[derive(Clone, Debug, Default)]
+struct ControlState {
+    paused: bool,
+    /// Rebuild the processor with this config at the next checkpoint
+    reload: Option<Box<DriverConfig>>,
+    /// Hand the lease over to a standby at the next checkpoint
+    demote: bool,
+    /// Restart the processor below its watermark at the next checkpoint
+    backfill: Option<BackfillCommand>,
+}
+
+/// Reprocessing of versions below a processor's watermark, as the operation `operation_id`
+#[derive(Clone, Debug)]
+pub struct BackfillCommand {
+    pub start_version: u64,
+    /// Inclusive, the version before the watermark if `None` or above it
+    pub end_version: Option<u64>,
+    /// Lifetime of the backfill window registered for the versions. Without one, rows already
+    /// indexed aren't overwritten, see `driver::backfill_guard`.
+    pub window: Option<Duration>,
+    pub actor: String,
+    pub operation_id: Option<String>,
+    /// Publishes the versions again even if they were published, see `driver::publish_dedupe`
+    pub force_republish: bool,
+}
+
+/// Lifecycle control of the processors running in this process
+pub struct Indexer {
+    controls: RwLock<HashMap<String, watch::Sender<ControlState>>>,
+}
+
+impl Indexer {
+    pub fn handle() -> &'static Indexer {
+        &INDEXER
+    }
+
+    /// Names of the processors that can be controlled
+    pub fn processors(&self) -> Vec<String> {
+        let mut names = self
+            .controls
+            .read()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Stops the processor after its in-flight batches until it's resumed or reloaded
+    pub fn pause_processor(&self, name: &str) -> Result<()> {
+        self.update(name, |state| state.paused = true)
+    }
+
+    pub fn resume_processor(&self, name: &str) -> Result<()> {
+        self.update(name, |state| state.paused = false)
+    }
+
+    /// Rebuilds the processor once its in-flight batches are committed and resumes it from its
+    /// watermark. The new processor is built from `driver_config`, or the config file re-read if
+    /// `None`; a file that can't be read or parsed fails the reload and leaves the processor as it
+    /// is. Also resumes a paused processor.
+    pub fn reload_processor(&self, name: &str, driver_config: Option<DriverConfig>) -> Result<()> {
+        let driver_config = match driver_config {
+            Some(driver_config) => driver_config,
+            None => DriverConfig::try_read_from(DEFAULT_CONFIG_PATH)?,
+        };
+        self.update(name, |state| {
+            state.paused = false;
+            state.reload = Some(Box::new(driver_config));
+        })
+    }
+
+    /// Controlled failover: flushes the publisher once the in-flight batches are committed,
+    /// releases the lease for a standby to take over and turns this process into a standby.
+    /// Only has an effect with `standby` enabled.
+    pub fn demote_processor(&self, name: &str) -> Result<()> {
+        self.update(name, |state| state.demote = true)
+    }
+
+    /// Restarts the processor at `command.start_version` once its in-flight batches are committed,
+    /// replacing the backfill it's running if any. Once it's back at its watermark it carries on
+    /// as before.
+    pub fn backfill_processor(&self, name: &str, command: BackfillCommand) -> Result<()> {
+        self.update(name, |state| state.backfill = Some(command))
+    }
+
+    /// Registers a processor when its pipeline starts
+    pub(crate) fn register(&self, name: &str) -> ProcessorControl {
+        let (sender, receiver) = watch::channel(ControlState::default());
+        self.controls
+            .write()
+            .unwrap()
+            .insert(name.to_string(), sender);
+        ProcessorControl {
+            name: name.to_string(),
+            receiver,
+        }
+    }
+
+    fn update(&self, name: &str, update: impl FnOnce(&mut ControlState)) -> Result<()> {
+        let controls = self.controls.read().unwrap();
+        let sender = match controls.get(name) {
+            Some(sender) => sender,
+            None => bail!("Processor {} isn't running in this process", name),
+        };
+        sender.send_modify(update);
+        Ok(())
+    }
+}
+
+/// The processor's end of its lifecycle control
+pub struct ProcessorControl {
+    name: String,
+    receiver: watch::Receiver<ControlState>,
+}
+
+impl ProcessorControl {
+    /// Whether a demotion was requested, which the caller carries out
+    pub fn take_demotion(&mut self) -> bool {
+        let demote = self.receiver.borrow().demote;
+        if demote {
+            INDEXER.update(&self.name, |state| state.demote = false).ok();
+        }
+        demote
+    }
+
+    /// The backfill requested, which the caller carries out
+    pub fn take_backfill(&mut self) -> Option<BackfillCommand> {
+        let command = self.receiver.borrow().backfill.clone();
+        if command.is_some() {
+            INDEXER
+                .update(&self.name, |state| state.backfill = None)
+                .ok();
+        }
+        command
+    }
+
+    /// Called between rounds of batches. Waits while the processor is paused and returns the
+    /// config to rebuild it with if a reload was requested.
+    pub async fn checkpoint(&mut self) -> Option<DriverConfig> {
+        let mut was_paused = false;
+        loop {
+            let state = self.receiver.borrow_and_update().clone();
+            if let Some(driver_config) = state.reload {
+                INDEXER.update(&self.name, |state| state.reload = None).ok();
+                PROCESSOR_PAUSED.with_label_values(&[&self.name]).set(0);
+                PROCESSOR_RELOADS.with_label_values(&[&self.name]).inc();
+                health::on_resumed(&self.name);
+                info!(processor_name = self.name, "Reloading processor...");
+                return Some(*driver_config);
+            }
+            if !state.paused {
+                if was_paused {
+                    PROCESSOR_PAUSED.with_label_values(&[&self.name]).set(0);
+                    health::on_resumed(&self.name);
+                    info!(processor_name = self.name, "Processor resumed");
+                }
+                return None;
+            }
+            if !was_paused {
+                PROCESSOR_PAUSED.with_label_values(&[&self.name]).set(1);
+                health::on_paused(&self.name, PauseReason::Lifecycle);
+                info!(processor_name = self.name, "Processor paused");
+                was_paused = true;
+            }
+            if self.receiver.changed().await.is_err() {
+                // The control was replaced, keep running
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pause_and_resume() {
+        const NAME: &str = "lifecycle_pause_test";
+        let indexer = Indexer::handle();
+        let mut control = indexer.register(NAME);
+        assert!(indexer.pause_processor("not_running").is_err());
+
+        // Runs on while it isn't paused
+        assert!(control.checkpoint().await.is_none());
+        indexer.pause_processor(NAME).unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), control.checkpoint())
+                .await
+                .is_err()
+        );
+        assert_eq!(PROCESSOR_PAUSED.with_label_values(&[NAME]).get(), 1);
+
+        // Waits at its checkpoint until it's resumed
+        let resume = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            indexer.resume_processor(NAME).unwrap();
+        };
+        let (reload, ()) = tokio::join!(control.checkpoint(), resume);
+        assert!(reload.is_none());
+        assert_eq!(PROCESSOR_PAUSED.with_label_values(&[NAME]).get(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        const NAME: &str = "lifecycle_reload_test";
+        let indexer = Indexer::handle();
+        let mut control = indexer.register(NAME);
+        indexer.pause_processor(NAME).unwrap();
+
+        // The default path is relative to the aptos-core checkout, which tests don't run in
+        assert!(indexer.reload_processor(NAME, None).is_err());
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), control.checkpoint())
+                .await
+                .is_err()
+        );
+
+        let mut driver_config = DriverConfig::default();
+        driver_config.chain_id = Some(2);
+        indexer.reload_processor(NAME, Some(driver_config)).unwrap();
+        let reloaded = control.checkpoint().await.unwrap();
+        assert_eq!(reloaded.chain_id, Some(2));
+        // Taken once, and resumed
+        assert!(control.checkpoint().await.is_none());
+        assert_eq!(PROCESSOR_RELOADS.with_label_values(&[NAME]).get(), 1);
+    }
+
+    #[test]
+    fn test_take_demotion_and_backfill() {
+        const NAME: &str = "lifecycle_take_test";
+        let indexer = Indexer::handle();
+        let mut control = indexer.register(NAME);
+        assert!(!control.take_demotion());
+        assert!(control.take_backfill().is_none());
+
+        indexer.demote_processor(NAME).unwrap();
+        indexer
+            .backfill_processor(NAME, BackfillCommand {
+                start_version: 10,
+                end_version: None,
+                window: None,
+                actor: "test".to_string(),
+                operation_id: None,
+                force_republish: false,
+            })
+            .unwrap();
+        assert!(control.take_demotion());
+        assert!(!control.take_demotion());
+        assert_eq!(control.take_backfill().unwrap().start_version, 10);
+        assert!(control.take_backfill().is_none());
+    }
+}
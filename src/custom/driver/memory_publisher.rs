@@ -0,0 +1,252 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! A `Publisher` that records its messages in memory instead of producing them to Kafka, for
+//! replays (see `driver::replay`) and for tests to assert on what a processor published. Build one
+//! with `Publisher::in_memory`: it serializes, keys, salts, orders and routes every message as
+//! the Kafka one does, with the same headers, and every send succeeds and is delivered at once,
+//! unless a test made the next sends fail with `RecordedMessages::fail_next`.
+//!
+//! `RecordingSink` is the same for the `Sink` of `custom_default_processor`, see `driver::sink`,
+//! keeping the transactions, events and write set changes it's sent as they are.
+
+use crate::{
+    custom::driver::sink::Sink,
+    models::{events::EventModel, write_set_changes::WriteSetChangeModel},
+};
+use aptos_api_types::Transaction;
+use async_trait::async_trait;
+use rdkafka::{
+    message::{Headers, OwnedHeaders},
+    producer::BaseRecord,
+    types::RDKafkaErrorCode,
+};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// A message as it would have been produced
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedMessage {
+    pub topic: String,
+    pub key: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub payload: Vec<u8>,
+}
+
+/// What a `RecordedMessage` prints as, with the payload as JSON, or hex if it isn't JSON, e.g.
+/// protobuf
+#[derive(Debug, Serialize)]
+pub struct PrintedMessage<'a> {
+    pub topic: &'a str,
+    pub key: Option<&'a str>,
+    pub headers: &'a [(String, String)],
+    pub payload: serde_json::Value,
+}
+
+impl RecordedMessage {
+    fn from_record(record: &BaseRecord<str, [u8]>) -> Self {
+        Self {
+            topic: record.topic.to_string(),
+            key: record.key.map(str::to_string),
+            headers: record
+                .headers
+                .as_ref()
+                .map(header_pairs)
+                .unwrap_or_default(),
+            payload: record.payload.map(<[u8]>::to_vec).unwrap_or_default(),
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn printed(&self) -> PrintedMessage<'_> {
+        PrintedMessage {
+            topic: &self.topic,
+            key: self.key.as_deref(),
+            headers: &self.headers,
+            payload: serde_json::from_slice(&self.payload).unwrap_or_else(|_| {
+                serde_json::Value::String(format!("0x{}", hex::encode(&self.payload)))
+            }),
+        }
+    }
+}
+
+/// The messages of an in-memory publisher, in the order they were sent, shared with its clones
+#[derive(Clone, Debug, Default)]
+pub struct RecordedMessages {
+    messages: Arc<Mutex<Vec<RecordedMessage>>>,
+    /// Errors the next sends fail with, in order
+    failures: Arc<Mutex<VecDeque<RDKafkaErrorCode>>>,
+}
+
+impl RecordedMessages {
+    /// Fails the next `count` sends with `code`, as the producer would, before recording again
+    pub fn fail_next(&self, count: usize, code: RDKafkaErrorCode) {
+        self.failures
+            .lock()
+            .unwrap()
+            .extend(std::iter::repeat(code).take(count));
+    }
+
+    pub(crate) fn next_failure(&self) -> Option<RDKafkaErrorCode> {
+        self.failures.lock().unwrap().pop_front()
+    }
+
+    pub(crate) fn record(&self, record: &BaseRecord<str, [u8]>) {
+        self.messages
+            .lock()
+            .unwrap()
+            .push(RecordedMessage::from_record(record));
+    }
+
+    pub fn all(&self) -> Vec<RecordedMessage> {
+        self.messages.lock().unwrap().clone()
+    }
+
+    pub fn on_topic(&self, topic: &str) -> Vec<RecordedMessage> {
+        self.messages
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|message| message.topic == topic)
+            .cloned()
+            .collect()
+    }
+
+    /// The messages recorded so far, leaving none
+    pub fn take(&self) -> Vec<RecordedMessage> {
+        std::mem::take(&mut *self.messages.lock().unwrap())
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn header_pairs(headers: &OwnedHeaders) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|header| {
+            let value = header.value.unwrap_or_default();
+            (
+                header.key.to_string(),
+                String::from_utf8_lossy(value).into_owned(),
+            )
+        })
+        .collect()
+}
+
+/// What a `RecordingSink` was sent by one call
+#[derive(Clone, Debug)]
+pub enum SentBatch {
+    Transactions(Vec<Transaction>),
+    Events {
+        events: Vec<EventModel>,
+        write_set_changes: Vec<WriteSetChangeModel>,
+    },
+}
+
+/// A `Sink` that keeps what it's sent, shared with its clones, and takes transactions, events and
+/// write set changes. Every send succeeds and nothing is dead lettered.
+#[derive(Clone, Debug, Default)]
+pub struct RecordingSink {
+    sent: Arc<Mutex<Vec<SentBatch>>>,
+    flushes: Arc<Mutex<usize>>,
+}
+
+impl RecordingSink {
+    /// Every call, in order
+    pub fn sent(&self) -> Vec<SentBatch> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    pub fn transactions(&self) -> Vec<Transaction> {
+        self.sent
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|batch| match batch {
+                SentBatch::Transactions(txns) => txns.clone(),
+                SentBatch::Events { .. } => vec![],
+            })
+            .collect()
+    }
+
+    pub fn events(&self) -> Vec<EventModel> {
+        self.sent
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|batch| match batch {
+                SentBatch::Events { events, .. } => events.clone(),
+                SentBatch::Transactions(_) => vec![],
+            })
+            .collect()
+    }
+
+    pub fn write_set_changes(&self) -> Vec<WriteSetChangeModel> {
+        self.sent
+            .lock()
+            .unwrap()
+            .iter()
+            .flat_map(|batch| match batch {
+                SentBatch::Events {
+                    write_set_changes, ..
+                } => write_set_changes.clone(),
+                SentBatch::Transactions(_) => vec![],
+            })
+            .collect()
+    }
+
+    pub fn flushes(&self) -> usize {
+        *self.flushes.lock().unwrap()
+    }
+}
+
+#[async_trait]
+impl Sink for RecordingSink {
+    fn publishes(&self, model: &str) -> bool {
+        matches!(
+            model,
+            "TransactionModel" | "EventModel" | "WriteSetChangeModel"
+        )
+    }
+
+    async fn send_txs(&self, txns: &[Transaction]) -> anyhow::Result<usize> {
+        self.sent
+            .lock()
+            .unwrap()
+            .push(SentBatch::Transactions(txns.to_vec()));
+        Ok(0)
+    }
+
+    async fn send_events(
+        &self,
+        events: &[EventModel],
+        wscs: &[WriteSetChangeModel],
+    ) -> anyhow::Result<usize> {
+        self.sent.lock().unwrap().push(SentBatch::Events {
+            events: events.to_vec(),
+            write_set_changes: wscs.to_vec(),
+        });
+        Ok(0)
+    }
+
+    async fn flush(&self, _timeout: Duration) -> anyhow::Result<()> {
+        *self.flushes.lock().unwrap() += 1;
+        Ok(())
+    }
+}
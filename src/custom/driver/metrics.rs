@@ -0,0 +1,88 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! HTTP server exposing every metric of the process (see `counters`) on `GET /metrics`, in the
+//! Prometheus text format, for a scraper to follow throughput and lag:
+//! - `indexer_transactions_processed_total` counts the versions of the successful batches, by
+//!   processor, so its rate is versions per second
+//! - `indexer_batch_duration_seconds` times each batch by processor and stage: `process` for the
+//!   whole batch, `insert_to_db` and `publish` for those of `custom_default_processor`
+//! - `indexer_publish_batch_seconds` times serializing and enqueueing the messages of a batch, by
+//!   model, and `indexer_publisher_send_failures_total` counts the messages that couldn't be
+//!   published once retried, by model
+//! - `indexer_processor_latest_version` is the last version each processor fully consumed, and
+//!   `indexer_processor_lag_versions` how many versions it's behind the ledger, as of its last
+//!   round. Tracking it fetches the ledger info once a round, like the circuit breaker does.
+
+use crate::{counters::PROCESSOR_LAG_VERSIONS, custom::driver::config::MetricsConfig};
+use aptos_logger::{error, info};
+use aptos_metrics_core::{gather, Encoder, TextEncoder};
+use once_cell::sync::OnceCell;
+use poem::{get, handler, http::StatusCode, listener::TcpListener, Response, Route, Server};
+
+static STARTED: OnceCell<()> = OnceCell::new();
+
+/// Spawns the server if it's enabled. Only the first call in a process has an effect, so every
+/// processor runtime can call it.
+pub fn init(config: &MetricsConfig) {
+    if !config.enabled || STARTED.set(()).is_err() {
+        return;
+    }
+    let address = config.listen_address.clone();
+    tokio::spawn(async move {
+        info!(listen_address = address, "Starting the metrics server");
+        if let Err(e) = Server::new(TcpListener::bind(address.as_str()))
+            .run(Route::new().at("/metrics", get(serve)))
+            .await
+        {
+            error!(listen_address = address, error = ?e, "Metrics server stopped");
+        }
+    });
+}
+
+pub fn enabled() -> bool {
+    STARTED.get().is_some()
+}
+
+/// Called after every round of `processor`, `lag` versions behind the ledger
+pub fn on_round(processor: &str, lag: u64) {
+    PROCESSOR_LAG_VERSIONS
+        .with_label_values(&[processor])
+        .set(lag as i64);
+}
+
+#[handler]
+fn serve() -> Response {
+    match render() {
+        Ok((content_type, body)) => Response::builder().content_type(content_type).body(body),
+        Err(e) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(e.to_string()),
+    }
+}
+
+/// Every registered metric, with the content type to serve it as
+pub fn render() -> anyhow::Result<(String, Vec<u8>)> {
+    let encoder = TextEncoder::new();
+    let mut body = vec![];
+    encoder.encode(&gather(), &mut body)?;
+    Ok((encoder.format_type().to_string(), body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counters::TRANSACTIONS_PROCESSED;
+
+    #[test]
+    fn test_render() {
+        TRANSACTIONS_PROCESSED
+            .with_label_values(&["metrics_test"])
+            .inc_by(100);
+        let (content_type, body) = render().unwrap();
+        assert!(content_type.starts_with("text/plain"));
+        let body = String::from_utf8(body).unwrap();
+        assert!(body.lines().any(|line| line
+            .starts_with("indexer_transactions_processed_total{processor_name=\"metrics_test\"}")));
+    }
+}
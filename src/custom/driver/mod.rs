@@ -1,3 +1,60 @@
 pub mod publisher;
 pub mod producer;
 pub mod config;
+pub mod preflight;
+pub mod debug;
+pub mod salting;
+pub mod priority;
+pub mod column_stats;
+pub mod lifecycle;
+pub mod alerts;
+pub mod consumer_lag;
+pub mod backfill_guard;
+pub mod validation;
+pub mod serialization;
+pub mod ledger_reset;
+pub mod change_feed;
+pub mod range_hash;
+pub mod retry_budget;
+pub mod circuit_breaker;
+pub mod sharding;
+pub mod replication_lag;
+pub mod operations;
+pub mod payload_schema;
+pub mod duplicate_transactions;
+pub mod shadow;
+pub mod entry_function_stats;
+pub mod redaction;
+pub mod standby;
+pub mod storage_usage;
+pub mod app_scope;
+pub mod row_limits;
+pub mod admin;
+pub mod replay_cache;
+pub mod asset_transfers;
+pub mod ordering;
+pub mod publish_filter;
+pub mod publish_retry;
+pub mod metrics;
+pub mod shutdown;
+pub mod version_guard;
+pub mod backfill;
+pub mod ordered_commit;
+pub mod batch_retry;
+pub mod envelope;
+pub mod sink;
+pub mod http_sink;
+pub mod multi_sink;
+#[cfg(feature = "object_store_sink")]
+pub mod object_store_sink;
+#[cfg(feature = "avro")]
+pub mod schema_registry;
+pub mod health;
+pub mod publish_dedupe;
+pub mod db_pools;
+pub mod batch_manifest;
+pub mod backpressure;
+pub mod rate_limit;
+pub mod memory_publisher;
+pub mod replay;
+pub mod balance_guard;
@@ -0,0 +1,263 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Publishing the same batch to several sinks, with `sink.fan_out` listing them, e.g. Kafka and an
+//! HTTP endpoint. Every batch goes to the sinks in their order, each with a policy: a `required`
+//! sink that fails fails the batch right away, so the sinks after it don't get it and the
+//! watermark doesn't move past it, while a `best_effort` sink that fails is logged, counted in
+//! `indexer_best_effort_sink_failures_count` by sink, and skipped for that batch. A batch that
+//! failed is processed again whole, so the sinks before the failing one get it twice.
+//!
+//! Flushing flushes every sink, even after one failed, and fails if a required one did.
+
+use crate::{
+    counters::BEST_EFFORT_SINK_FAILURES,
+    custom::driver::{config::SinkPolicy, publisher::Publisher, sink::Sink},
+    models::{events::EventModel, write_set_changes::WriteSetChangeModel},
+};
+use aptos_api_types::Transaction;
+use async_trait::async_trait;
+use std::time::Duration;
+
+/// Sinks in the order they're published to
+#[derive(Default)]
+pub struct MultiSink {
+    sinks: Vec<Child>,
+}
+
+struct Child {
+    /// In logs and metrics, e.g. `kafka`
+    name: String,
+    policy: SinkPolicy,
+    sink: Box<dyn Sink>,
+}
+
+impl MultiSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Publishes to `sink` after the sinks added before it
+    pub fn with(mut self, name: &str, policy: SinkPolicy, sink: Box<dyn Sink>) -> Self {
+        self.sinks.push(Child {
+            name: name.to_string(),
+            policy,
+            sink,
+        });
+        self
+    }
+}
+
+impl Child {
+    /// The error of a required sink, `None` for a best effort one's
+    fn settle<T>(&self, operation: &str, result: anyhow::Result<T>) -> anyhow::Result<Option<T>> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(err) if self.policy == SinkPolicy::BestEffort => {
+                aptos_logger::warn!(
+                    sink = self.name,
+                    operation = operation,
+                    error = format!("{:#}", err),
+                    "Best effort sink failed, the batch goes on without it"
+                );
+                BEST_EFFORT_SINK_FAILURES
+                    .with_label_values(&[self.name.as_str()])
+                    .inc();
+                Ok(None)
+            },
+            Err(err) => Err(err.context(format!("Required sink {} failed", self.name))),
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for MultiSink {
+    fn publishes(&self, model: &str) -> bool {
+        self.sinks.iter().any(|child| child.sink.publishes(model))
+    }
+
+    /// The transactions dead lettered by any of the sinks
+    async fn send_txs(&self, txns: &[Transaction]) -> anyhow::Result<usize> {
+        let mut dead_lettered = 0;
+        for child in &self.sinks {
+            let result = child.sink.send_txs(txns).await;
+            dead_lettered += child.settle("send_txs", result)?.unwrap_or_default();
+        }
+        Ok(dead_lettered)
+    }
+
+    async fn send_events(
+        &self,
+        events: &[EventModel],
+        wscs: &[WriteSetChangeModel],
+    ) -> anyhow::Result<usize> {
+        let mut dead_lettered = 0;
+        for child in &self.sinks {
+            if child.sink.publishes("EventModel") || child.sink.publishes("WriteSetChangeModel") {
+                let result = child.sink.send_events(events, wscs).await;
+                dead_lettered += child.settle("send_events", result)?.unwrap_or_default();
+            }
+        }
+        Ok(dead_lettered)
+    }
+
+    async fn flush(&self, timeout: Duration) -> anyhow::Result<()> {
+        let mut first_error = None;
+        for child in &self.sinks {
+            let result = child.sink.flush(timeout).await;
+            if let Err(err) = child.settle("flush", result) {
+                first_error.get_or_insert(err);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// The first of the sinks that's Kafka
+    fn publisher(&self) -> Option<&Publisher> {
+        self.sinks.iter().find_map(|child| child.sink.publisher())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    /// Counts what it's sent, and fails every call if `fails`
+    #[derive(Clone, Default)]
+    struct FakeSink {
+        fails: bool,
+        sent: Arc<AtomicUsize>,
+        flushed: Arc<AtomicUsize>,
+    }
+
+    impl FakeSink {
+        fn failing() -> Self {
+            Self {
+                fails: true,
+                ..Self::default()
+            }
+        }
+
+        fn result(&self, counter: &AtomicUsize) -> anyhow::Result<()> {
+            counter.fetch_add(1, Ordering::SeqCst);
+            if self.fails {
+                anyhow::bail!("Sink is down");
+            }
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl Sink for FakeSink {
+        fn publishes(&self, model: &str) -> bool {
+            model == "TransactionModel"
+        }
+
+        async fn send_txs(&self, txns: &[Transaction]) -> anyhow::Result<usize> {
+            self.result(&self.sent)?;
+            Ok(txns.len())
+        }
+
+        async fn send_events(
+            &self,
+            _events: &[EventModel],
+            _wscs: &[WriteSetChangeModel],
+        ) -> anyhow::Result<usize> {
+            self.result(&self.sent)?;
+            Ok(0)
+        }
+
+        async fn flush(&self, _timeout: Duration) -> anyhow::Result<()> {
+            self.result(&self.flushed)
+        }
+    }
+
+    fn transaction() -> Transaction {
+        serde_json::from_value(json!({
+            "type": "block_metadata_transaction",
+            "version": "10",
+            "block_height": "100",
+            "epoch": "1",
+            "hash": format!("0x{:064x}", 10),
+            "state_change_hash": format!("0x{:064x}", 0),
+            "event_root_hash": format!("0x{:064x}", 0),
+            "gas_used": "0",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": format!("0x{:064x}", 0),
+            "id": format!("0x{:064x}", 1),
+            "round": "57600",
+            "failed_proposer_indices": [],
+            "previous_block_votes_bitvec": [],
+            "proposer": "0x68f04222bd9f8846cda028ea5ba3846a806b04a47e1f1a4f0939f350d713b2eb",
+            "timestamp": "1649395495746947",
+            "events": [],
+            "changes": []
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_best_effort_failure() {
+        let (required, best_effort) = (FakeSink::default(), FakeSink::failing());
+        let sink = MultiSink::new()
+            .with(
+                "audit",
+                SinkPolicy::BestEffort,
+                Box::new(best_effort.clone()),
+            )
+            .with("kafka", SinkPolicy::Required, Box::new(required.clone()));
+        let failures = || {
+            BEST_EFFORT_SINK_FAILURES
+                .with_label_values(&["audit"])
+                .get()
+        };
+        let failures_before = failures();
+
+        assert_eq!(sink.send_txs(&[transaction()]).await.unwrap(), 1);
+        assert_eq!(required.sent.load(Ordering::SeqCst), 1);
+        assert_eq!(best_effort.sent.load(Ordering::SeqCst), 1);
+        assert_eq!(failures(), failures_before + 1);
+        // Only to the sinks that publish them
+        assert_eq!(sink.send_events(&[], &[]).await.unwrap(), 0);
+        assert_eq!(required.sent.load(Ordering::SeqCst), 1);
+
+        sink.flush(Duration::from_secs(1)).await.unwrap();
+        assert_eq!(required.flushed.load(Ordering::SeqCst), 1);
+        assert_eq!(best_effort.flushed.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_required_failure() {
+        let (first, required, last) = (
+            FakeSink::default(),
+            FakeSink::failing(),
+            FakeSink::default(),
+        );
+        let sink = MultiSink::new()
+            .with("kafka", SinkPolicy::Required, Box::new(first.clone()))
+            .with("audit", SinkPolicy::Required, Box::new(required.clone()))
+            .with("http", SinkPolicy::BestEffort, Box::new(last.clone()));
+
+        let error = sink.send_txs(&[transaction()]).await.unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "Required sink audit failed: Sink is down"
+        );
+        assert_eq!(first.sent.load(Ordering::SeqCst), 1);
+        // Stops at the failing sink
+        assert_eq!(last.sent.load(Ordering::SeqCst), 0);
+
+        // Flushes them all anyway
+        assert!(sink.flush(Duration::from_secs(1)).await.is_err());
+        for child in [first, required, last] {
+            assert_eq!(child.flushed.load(Ordering::SeqCst), 1);
+        }
+        assert!(sink.publisher().is_none());
+    }
+}
@@ -0,0 +1,388 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Writing every batch's transactions to object storage, S3 or GCS, for disaster recovery, with
+//! the `object_store` sink backend, usually as a `fan_out` sink next to Kafka. A batch is written
+//! as newline-delimited JSON, gzipped unless `compression` is `none`, to
+//! `{prefix}/transactions/{first_version}-{last_version}.json.gz`. A batch over
+//! `max_object_bytes` of JSON is split into objects of consecutive versions, each keyed by its own
+//! versions.
+//!
+//! Keys only depend on the transactions, so a version range processed again overwrites its
+//! objects rather than adding to them, and the gzip output has no timestamp. Objects of a range
+//! processed again with other batch boundaries do overlap, and `replay`, which reads a range back
+//! for reprocessing without the fullnode, keeps one copy of every version.
+//!
+//! Only built with the `object_store_sink` feature. Credentials come from the environment, e.g.
+//! `AWS_ACCESS_KEY_ID` and `AWS_REGION` or `GOOGLE_SERVICE_ACCOUNT`, and requests are retried by
+//! the `object_store` client.
+
+use crate::{
+    custom::driver::{
+        config::{ObjectCompression, ObjectStoreProvider, ObjectStoreSinkConfig},
+        ordering,
+        sink::Sink,
+    },
+    models::{events::EventModel, write_set_changes::WriteSetChangeModel},
+};
+use anyhow::Context;
+use aptos_api_types::Transaction;
+use async_trait::async_trait;
+use flate2::{read::GzDecoder, write::GzEncoder};
+use futures::TryStreamExt;
+use object_store::{
+    aws::AmazonS3Builder, gcp::GoogleCloudStorageBuilder, path::Path, ObjectMeta, ObjectStore,
+};
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    sync::Arc,
+    time::Duration,
+};
+
+pub struct ObjectStoreSink {
+    store: Arc<dyn ObjectStore>,
+    config: ObjectStoreSinkConfig,
+}
+
+/// An object of a batch, before it's compressed
+#[derive(Debug, PartialEq, Eq)]
+struct Chunk {
+    first_version: u64,
+    last_version: u64,
+    json: Vec<u8>,
+}
+
+impl ObjectStoreSink {
+    pub fn new(store: Arc<dyn ObjectStore>, config: &ObjectStoreSinkConfig) -> Self {
+        Self {
+            store,
+            config: config.clone(),
+        }
+    }
+
+    /// Writing to the bucket of `config`
+    pub fn from_config(config: &ObjectStoreSinkConfig) -> anyhow::Result<Self> {
+        Ok(Self::new(store(config)?, config))
+    }
+
+    /// The key of the object of `first_version` to `last_version`
+    fn key(&self, first_version: u64, last_version: u64) -> Path {
+        let extension = match self.config.compression {
+            ObjectCompression::None => "json",
+            ObjectCompression::Gzip => "json.gz",
+        };
+        Path::from(format!(
+            "{}/{}-{}.{}",
+            transactions_prefix(&self.config.prefix),
+            first_version,
+            last_version,
+            extension
+        ))
+    }
+
+    /// `txns` in version order, as JSON lines split into chunks of at most `max_object_bytes`,
+    /// unless a single transaction is larger
+    fn chunks(&self, txns: &[Transaction]) -> anyhow::Result<Vec<Chunk>> {
+        let mut chunks: Vec<Chunk> = vec![];
+        for txn in ordering::sort(txns) {
+            let version = txn.version().context("Transaction without a version")?;
+            let mut line = serde_json::to_vec(txn)?;
+            line.push(b'\n');
+            match chunks.last_mut() {
+                Some(chunk) if chunk.json.len() + line.len() <= self.config.max_object_bytes => {
+                    chunk.last_version = version;
+                    chunk.json.extend(line);
+                },
+                _ => chunks.push(Chunk {
+                    first_version: version,
+                    last_version: version,
+                    json: line,
+                }),
+            }
+        }
+        Ok(chunks)
+    }
+
+    fn compress(&self, json: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        match self.config.compression {
+            ObjectCompression::None => Ok(json),
+            ObjectCompression::Gzip => {
+                let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+                encoder.write_all(&json)?;
+                Ok(encoder.finish()?)
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for ObjectStoreSink {
+    fn publishes(&self, model: &str) -> bool {
+        model == "TransactionModel"
+    }
+
+    /// Stops at the first object that can't be written, nothing is dead lettered
+    async fn send_txs(&self, txns: &[Transaction]) -> anyhow::Result<usize> {
+        for chunk in self.chunks(txns)? {
+            let key = self.key(chunk.first_version, chunk.last_version);
+            let body = self.compress(chunk.json)?;
+            self.store
+                .put(&key, body.into())
+                .await
+                .with_context(|| format!("Failed to write {} to the object store", key))?;
+        }
+        Ok(0)
+    }
+
+    async fn send_events(
+        &self,
+        _events: &[EventModel],
+        _wscs: &[WriteSetChangeModel],
+    ) -> anyhow::Result<usize> {
+        Ok(0)
+    }
+
+    /// Every object is written before the batch goes on, there's nothing to wait for
+    async fn flush(&self, _timeout: Duration) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// The bucket of `config`
+pub fn store(config: &ObjectStoreSinkConfig) -> anyhow::Result<Arc<dyn ObjectStore>> {
+    let store: Arc<dyn ObjectStore> = match config.provider {
+        ObjectStoreProvider::S3 => Arc::new(
+            AmazonS3Builder::from_env()
+                .with_bucket_name(&config.bucket)
+                .build()?,
+        ),
+        ObjectStoreProvider::Gcs => Arc::new(
+            GoogleCloudStorageBuilder::from_env()
+                .with_bucket_name(&config.bucket)
+                .build()?,
+        ),
+    };
+    Ok(store)
+}
+
+/// The transactions from `start_version` to `end_version` written to `store` under `prefix`, in
+/// version order and each once, to reprocess a range without fetching it from the fullnode.
+/// Versions that were never written are missing, it's up to the caller to check for gaps.
+pub async fn replay(
+    store: &dyn ObjectStore,
+    prefix: &str,
+    start_version: u64,
+    end_version: u64,
+) -> anyhow::Result<Vec<Transaction>> {
+    let objects: Vec<ObjectMeta> = store
+        .list(Some(&transactions_prefix(prefix)))
+        .await?
+        .try_collect()
+        .await?;
+    let mut transactions = BTreeMap::new();
+    for object in objects {
+        let Some((first_version, last_version, compression)) =
+            object.location.filename().and_then(parse_filename)
+        else {
+            continue;
+        };
+        if last_version < start_version || first_version > end_version {
+            continue;
+        }
+        let body = store.get(&object.location).await?.bytes().await?;
+        let json = match compression {
+            ObjectCompression::None => body.to_vec(),
+            ObjectCompression::Gzip => {
+                let mut json = vec![];
+                GzDecoder::new(&body[..]).read_to_end(&mut json)?;
+                json
+            },
+        };
+        for line in json.split(|byte| *byte == b'\n') {
+            if line.is_empty() {
+                continue;
+            }
+            let txn: Transaction = serde_json::from_slice(line)
+                .with_context(|| format!("Failed to read a transaction of {}", object.location))?;
+            match txn.version() {
+                Some(version) if (start_version..=end_version).contains(&version) => {
+                    transactions.insert(version, txn);
+                },
+                _ => {},
+            }
+        }
+    }
+    Ok(transactions.into_values().collect())
+}
+
+fn transactions_prefix(prefix: &str) -> Path {
+    match prefix.trim_matches('/') {
+        "" => Path::from("transactions"),
+        prefix => Path::from(format!("{}/transactions", prefix)),
+    }
+}
+
+/// The versions and compression of an object, from its name, e.g. `100-199.json.gz`
+fn parse_filename(filename: &str) -> Option<(u64, u64, ObjectCompression)> {
+    let (versions, compression) = if let Some(versions) = filename.strip_suffix(".json.gz") {
+        (versions, ObjectCompression::Gzip)
+    } else {
+        (filename.strip_suffix(".json")?, ObjectCompression::None)
+    };
+    let (first_version, last_version) = versions.split_once('-')?;
+    Some((
+        first_version.parse().ok()?,
+        last_version.parse().ok()?,
+        compression,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+    use serde_json::json;
+
+    fn transaction(version: u64) -> Transaction {
+        serde_json::from_value(json!({
+            "type": "block_metadata_transaction",
+            "version": version.to_string(),
+            "block_height": "100",
+            "epoch": "1",
+            "hash": format!("0x{:064x}", version),
+            "state_change_hash": format!("0x{:064x}", 0),
+            "event_root_hash": format!("0x{:064x}", 0),
+            "gas_used": "0",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": format!("0x{:064x}", 0),
+            "id": format!("0x{:064x}", 1),
+            "round": "57600",
+            "failed_proposer_indices": [],
+            "previous_block_votes_bitvec": [],
+            "proposer": "0x68f04222bd9f8846cda028ea5ba3846a806b04a47e1f1a4f0939f350d713b2eb",
+            "timestamp": "1649395495746947",
+            "events": [],
+            "changes": []
+        }))
+        .unwrap()
+    }
+
+    fn sink(
+        store: &Arc<InMemory>,
+        compression: ObjectCompression,
+        max_object_bytes: usize,
+    ) -> ObjectStoreSink {
+        ObjectStoreSink::new(store.clone(), &ObjectStoreSinkConfig {
+            bucket: "indexer-backups".to_string(),
+            prefix: "testnet/".to_string(),
+            compression,
+            max_object_bytes,
+            ..ObjectStoreSinkConfig::default()
+        })
+    }
+
+    async fn keys(store: &InMemory) -> Vec<String> {
+        let objects: Vec<ObjectMeta> = store.list(None).await.unwrap().try_collect().await.unwrap();
+        let mut keys = objects
+            .into_iter()
+            .map(|object| object.location.to_string())
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    fn versions(txns: &[Transaction]) -> Vec<u64> {
+        txns.iter().map(|txn| txn.version().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_write_and_replay() {
+        let store = Arc::new(InMemory::new());
+        let sink = sink(&store, ObjectCompression::Gzip, 1024 * 1024);
+        let batch = (100..110).rev().map(transaction).collect::<Vec<_>>();
+        assert_eq!(sink.send_txs(&batch).await.unwrap(), 0);
+        assert_eq!(keys(&store).await, ["testnet/transactions/100-109.json.gz"]);
+
+        // Processing the range again overwrites it
+        let written = store
+            .get(&sink.key(100, 109))
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        sink.send_txs(&batch).await.unwrap();
+        assert_eq!(keys(&store).await, ["testnet/transactions/100-109.json.gz"]);
+        let rewritten = store
+            .get(&sink.key(100, 109))
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(written, rewritten);
+
+        let replayed = replay(store.as_ref(), "testnet", 100, 109).await.unwrap();
+        assert_eq!(versions(&replayed), (100..110).collect::<Vec<_>>());
+        assert_eq!(
+            serde_json::to_value(&replayed[0]).unwrap(),
+            serde_json::to_value(transaction(100)).unwrap()
+        );
+        let replayed = replay(store.as_ref(), "testnet", 105, 200).await.unwrap();
+        assert_eq!(versions(&replayed), (105..110).collect::<Vec<_>>());
+        assert!(replay(store.as_ref(), "mainnet", 100, 109)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_split_batches() {
+        let store = Arc::new(InMemory::new());
+        let line_bytes = serde_json::to_vec(&transaction(100)).unwrap().len() + 1;
+        // Three transactions per object
+        let split = sink(&store, ObjectCompression::None, line_bytes * 3 + 10);
+        let batch = (100..108).map(transaction).collect::<Vec<_>>();
+        split.send_txs(&batch).await.unwrap();
+        assert_eq!(keys(&store).await, [
+            "testnet/transactions/100-102.json",
+            "testnet/transactions/103-105.json",
+            "testnet/transactions/106-107.json",
+        ]);
+        let body = store
+            .get(&split.key(106, 107))
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(
+            body.split(|byte| *byte == b'\n')
+                .filter(|line| !line.is_empty())
+                .count(),
+            2
+        );
+
+        // Written again in other batches, read back once
+        let whole = sink(&store, ObjectCompression::Gzip, 1024 * 1024);
+        whole.send_txs(&batch[2..]).await.unwrap();
+        let replayed = replay(store.as_ref(), "testnet/", 101, 107).await.unwrap();
+        assert_eq!(versions(&replayed), (101..108).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_parse_filename() {
+        assert_eq!(
+            parse_filename("100-199.json.gz"),
+            Some((100, 199, ObjectCompression::Gzip))
+        );
+        assert_eq!(
+            parse_filename("7-7.json"),
+            Some((7, 7, ObjectCompression::None))
+        );
+        assert_eq!(parse_filename("100-199.csv"), None);
+        assert_eq!(parse_filename("latest.json"), None);
+    }
+}
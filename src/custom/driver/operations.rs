@@ -0,0 +1,498 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! One view of the long-running driver tasks: backfills, enrichment runs and pruning. Each run is
+//! an operation with a generated id, the actor that started it and its parameters. Its lifecycle
+//! events (`started`, `progress` every `progress_step_percent` of its total, `completed`, and
+//! `failed` with an error code) are written to `operations_log` and, when `topics` has a
+//! `control_topic`, published there as `OperationEvent`s. `status()` lists the running
+//! operations with their progress and an ETA from the throughput since they started.
+//!
+//! Tasks start operations whether or not tracking is on; without it the handles do nothing.
+//! `cancel` asks a running operation to stop; the tasks that can stop early check
+//! `Operation::is_cancelled` and end it with a `cancelled` event.
+
+use crate::{
+    counters::OPERATION_EVENTS,
+    custom::driver::{config::DriverConfig, envelope::Envelope, publisher::Publisher},
+    database::{execute_with_better_error, PgDbPool},
+    models::operations_log::OperationEvent,
+    schema::operations_log,
+};
+use anyhow::bail;
+use aptos_logger::{error, info, warn};
+use diesel::{
+    r2d2::PoolError, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, QueryResult,
+    RunQueryDsl,
+};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+/// Model name of the published events, see `client::MODEL_TOPIC_KEYS`
+pub const OPERATION_EVENT: &str = "OperationEvent";
+
+static TRACKER: OnceCell<OperationTracker> = OnceCell::new();
+
+/// Snapshot of a running operation, meant for status reporting
+#[derive(Clone, Debug, Serialize)]
+pub struct RunningOperation {
+    pub operation_id: String,
+    pub kind: &'static str,
+    pub actor: String,
+    pub parameters: serde_json::Value,
+    pub done: u64,
+    pub total: Option<u64>,
+    pub progress_percent: Option<f64>,
+    pub started_at: chrono::NaiveDateTime,
+    /// From the throughput since the operation started, `None` without a total or any progress
+    pub eta_secs: Option<u64>,
+    pub cancel_requested: bool,
+}
+
+struct OperationTracker {
+    progress_step_percent: u64,
+    connection_pool: PgDbPool,
+    /// Only with `control_topic` configured
+    publisher: Option<Publisher>,
+    sequence: AtomicU64,
+    running: Mutex<HashMap<String, Tracked>>,
+}
+
+struct Tracked {
+    kind: &'static str,
+    actor: String,
+    parameters: serde_json::Value,
+    total: Option<u64>,
+    started: Instant,
+    started_at: chrono::NaiveDateTime,
+    /// Done when the operation started, e.g. by an earlier run it resumes
+    started_done: u64,
+    done: u64,
+    /// Index of the next event
+    event_index: i64,
+    /// Progress steps already emitted
+    steps: u64,
+    cancel_requested: bool,
+}
+
+/// Handle of a started operation. A no-op when tracking is off.
+#[derive(Debug)]
+pub struct Operation {
+    id: Option<String>,
+}
+
+/// Turns tracking on. Only the first call in a process has an effect, so every processor runtime
+/// can call it.
+pub fn init(driver_config: &DriverConfig, chain_id: u8, connection_pool: PgDbPool) {
+    let config = &driver_config.operations;
+    if !config.enabled || TRACKER.get().is_some() {
+        return;
+    }
+    let publisher = driver_config
+        .topics
+        .contains_key("control_topic")
+        .then(|| Publisher::from_config(driver_config.clone(), Envelope::control(chain_id)));
+    info!(
+        publishes = publisher.is_some(),
+        "Tracking long-running operations"
+    );
+    let _ = TRACKER.set(OperationTracker {
+        progress_step_percent: config.progress_step_percent.clamp(1, 100),
+        connection_pool,
+        publisher,
+        sequence: AtomicU64::new(0),
+        running: Mutex::new(HashMap::new()),
+    });
+}
+
+/// Starts an operation of `kind` with `done` of its `total` units of work, if known, already done
+pub fn start(
+    kind: &'static str,
+    actor: &str,
+    parameters: serde_json::Value,
+    done: u64,
+    total: Option<u64>,
+) -> Operation {
+    let Some(tracker) = TRACKER.get() else {
+        return Operation { id: None };
+    };
+    let started_at = chrono::Utc::now().naive_utc();
+    let operation_id = format!(
+        "{}-{:x}-{}",
+        kind,
+        started_at.timestamp_micros(),
+        tracker.sequence.fetch_add(1, Ordering::Relaxed)
+    );
+    let mut tracked = Tracked {
+        kind,
+        actor: actor.to_string(),
+        parameters,
+        total,
+        started: Instant::now(),
+        started_at,
+        started_done: done,
+        done,
+        event_index: 0,
+        steps: 0,
+        cancel_requested: false,
+    };
+    tracked.steps = tracked.steps(tracker.progress_step_percent);
+    let event = tracked.event(&operation_id, "started", None);
+    tracker
+        .running
+        .lock()
+        .unwrap()
+        .insert(operation_id.clone(), tracked);
+    tracker.emit(event);
+    Operation {
+        id: Some(operation_id),
+    }
+}
+
+/// Operations started and not completed or failed yet, oldest first
+pub fn status() -> Vec<RunningOperation> {
+    let Some(tracker) = TRACKER.get() else {
+        return vec![];
+    };
+    let mut running = tracker
+        .running
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(operation_id, tracked)| tracked.snapshot(operation_id))
+        .collect::<Vec<_>>();
+    running.sort_by(|a, b| (a.started_at, &a.operation_id).cmp(&(b.started_at, &b.operation_id)));
+    running
+}
+
+/// Handle of an operation started elsewhere, e.g. by the admin server for a task to run
+pub fn attach(operation_id: Option<String>) -> Operation {
+    Operation { id: operation_id }
+}
+
+/// The running operation `operation_id`
+pub fn get(operation_id: &str) -> Option<RunningOperation> {
+    let tracker = TRACKER.get()?;
+    let running = tracker.running.lock().unwrap();
+    running
+        .get(operation_id)
+        .map(|tracked| tracked.snapshot(operation_id))
+}
+
+/// Asks the running operation `operation_id` to stop. It's up to its task to notice, so it keeps
+/// running until it does.
+pub fn cancel(operation_id: &str) -> anyhow::Result<()> {
+    let Some(tracker) = TRACKER.get() else {
+        bail!("Operations aren't tracked");
+    };
+    match tracker.running.lock().unwrap().get_mut(operation_id) {
+        Some(tracked) => tracked.cancel_requested = true,
+        None => bail!("Operation {} isn't running", operation_id),
+    }
+    Ok(())
+}
+
+/// The last event logged for `operation_id`, e.g. to report on one that isn't running anymore
+pub fn last_event(
+    conn: &mut PgConnection,
+    operation_id: &str,
+) -> QueryResult<Option<OperationEvent>> {
+    operations_log::table
+        .filter(operations_log::operation_id.eq(operation_id))
+        .order(operations_log::event_index.desc())
+        .select((
+            operations_log::operation_id,
+            operations_log::event_index,
+            operations_log::kind,
+            operations_log::event,
+            operations_log::actor,
+            operations_log::parameters,
+            operations_log::done,
+            operations_log::total,
+            operations_log::error_code,
+            operations_log::error,
+            operations_log::emitted_at,
+        ))
+        .first::<OperationEvent>(conn)
+        .optional()
+}
+
+/// Class of an error for a `failed` event: `database`, `connection` or `internal`
+pub fn error_code(err: &anyhow::Error) -> &'static str {
+    if err.downcast_ref::<diesel::result::Error>().is_some() {
+        "database"
+    } else if err.downcast_ref::<PoolError>().is_some() {
+        "connection"
+    } else {
+        "internal"
+    }
+}
+
+impl Operation {
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// Whether `cancel` was called for the operation
+    pub fn is_cancelled(&self) -> bool {
+        let (Some(tracker), Some(id)) = (TRACKER.get(), &self.id) else {
+            return false;
+        };
+        let running = tracker.running.lock().unwrap();
+        running
+            .get(id)
+            .map_or(false, |tracked| tracked.cancel_requested)
+    }
+
+    /// Sets the work of an operation started before it was known, `done` counting as done when
+    /// it started
+    pub fn set_work(&self, done: u64, total: Option<u64>) {
+        let (Some(tracker), Some(id)) = (TRACKER.get(), &self.id) else {
+            return;
+        };
+        if let Some(tracked) = tracker.running.lock().unwrap().get_mut(id) {
+            tracked.started_done = done;
+            tracked.done = done;
+            tracked.total = total;
+            tracked.steps = tracked.steps(tracker.progress_step_percent);
+        }
+    }
+
+    /// Records `done` units of work, emitting `progress` when it crosses a step
+    pub fn progress(&self, done: u64) {
+        let (Some(tracker), Some(id)) = (TRACKER.get(), &self.id) else {
+            return;
+        };
+        let event = {
+            let mut running = tracker.running.lock().unwrap();
+            let Some(tracked) = running.get_mut(id) else {
+                return;
+            };
+            tracked.done = done;
+            let steps = tracked.steps(tracker.progress_step_percent);
+            if steps <= tracked.steps {
+                return;
+            }
+            tracked.steps = steps;
+            tracked.event(id, "progress", None)
+        };
+        tracker.emit(event);
+    }
+
+    pub fn complete(self, done: u64) {
+        self.finish(Some(done), "completed", None);
+    }
+
+    /// Ends the operation stopped on `cancel`, at its last recorded progress
+    pub fn cancelled(self) {
+        self.finish(None, "cancelled", None);
+    }
+
+    /// Ends the operation at its last recorded progress
+    pub fn fail(self, error_code: &str, error: impl std::fmt::Display) {
+        self.finish(
+            None,
+            "failed",
+            Some((error_code.to_string(), error.to_string())),
+        );
+    }
+
+    fn finish(self, done: Option<u64>, event: &'static str, error: Option<(String, String)>) {
+        let (Some(tracker), Some(id)) = (TRACKER.get(), &self.id) else {
+            return;
+        };
+        let Some(mut tracked) = tracker.running.lock().unwrap().remove(id) else {
+            return;
+        };
+        if let Some(done) = done {
+            tracked.done = done;
+        }
+        tracker.emit(tracked.event(id, event, error));
+    }
+}
+
+impl Tracked {
+    fn steps(&self, step_percent: u64) -> u64 {
+        match self.total {
+            Some(total) if total > 0 => self.done.min(total) * 100 / total / step_percent,
+            _ => 0,
+        }
+    }
+
+    fn event(
+        &mut self,
+        operation_id: &str,
+        event: &str,
+        error: Option<(String, String)>,
+    ) -> OperationEvent {
+        let (error_code, error) = match error {
+            Some((error_code, error)) => (Some(error_code), Some(error)),
+            None => (None, None),
+        };
+        let event = OperationEvent {
+            operation_id: operation_id.to_string(),
+            event_index: self.event_index,
+            kind: self.kind.to_string(),
+            event: event.to_string(),
+            actor: self.actor.clone(),
+            parameters: self.parameters.clone(),
+            done: self.done as i64,
+            total: self.total.map(|total| total as i64),
+            error_code,
+            error,
+            emitted_at: chrono::Utc::now().naive_utc(),
+        };
+        self.event_index += 1;
+        event
+    }
+
+    fn snapshot(&self, operation_id: &str) -> RunningOperation {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = (self.done.saturating_sub(self.started_done)) as f64 / elapsed;
+        RunningOperation {
+            operation_id: operation_id.to_string(),
+            kind: self.kind,
+            actor: self.actor.clone(),
+            parameters: self.parameters.clone(),
+            done: self.done,
+            total: self.total,
+            progress_percent: self
+                .total
+                .filter(|total| *total > 0)
+                .map(|total| self.done.min(total) as f64 * 100.0 / total as f64),
+            started_at: self.started_at,
+            eta_secs: self
+                .total
+                .filter(|_| rate > 0.0)
+                .map(|total| (total.saturating_sub(self.done) as f64 / rate).ceil() as u64),
+            cancel_requested: self.cancel_requested,
+        }
+    }
+}
+
+impl OperationTracker {
+    fn emit(&self, event: OperationEvent) {
+        OPERATION_EVENTS
+            .with_label_values(&[&event.kind, &event.event])
+            .inc();
+        match event.event.as_str() {
+            "failed" => warn!(
+                operation_id = event.operation_id,
+                kind = event.kind,
+                error_code = event.error_code,
+                error = event.error,
+                "Operation failed"
+            ),
+            _ => info!(
+                operation_id = event.operation_id,
+                kind = event.kind,
+                event = event.event,
+                done = event.done,
+                total = event.total,
+                "Operation {}",
+                event.event
+            ),
+        }
+        let logged = self
+            .connection_pool
+            .get()
+            .map_err(anyhow::Error::from)
+            .and_then(|mut conn| {
+                execute_with_better_error(
+                    &mut conn,
+                    diesel::insert_into(operations_log::table)
+                        .values(&event)
+                        .on_conflict_do_nothing(),
+                    None,
+                )?;
+                Ok(())
+            });
+        if let Err(err) = logged {
+            error!(
+                operation_id = event.operation_id,
+                error = ?err,
+                "Failed to log the operation event"
+            );
+        }
+        // The `operations_log` row is the durable record, the event is only a notification
+        if let Some(publisher) = &self.publisher {
+            if let Err(err) = publisher.try_send(OPERATION_EVENT, std::slice::from_ref(&event)) {
+                error!(
+                    operation_id = event.operation_id,
+                    error = ?err,
+                    "Failed to publish the operation event"
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracked(done: u64, total: Option<u64>) -> Tracked {
+        Tracked {
+            kind: "backfill",
+            actor: "test".to_string(),
+            parameters: serde_json::json!({}),
+            total,
+            started: Instant::now() - std::time::Duration::from_secs(10),
+            started_at: chrono::Utc::now().naive_utc(),
+            started_done: 0,
+            done,
+            event_index: 0,
+            steps: 0,
+            cancel_requested: false,
+        }
+    }
+
+    #[test]
+    fn test_steps() {
+        assert_eq!(tracked(0, Some(1000)).steps(10), 0);
+        assert_eq!(tracked(99, Some(1000)).steps(10), 0);
+        assert_eq!(tracked(100, Some(1000)).steps(10), 1);
+        assert_eq!(tracked(999, Some(1000)).steps(10), 9);
+        assert_eq!(tracked(2000, Some(1000)).steps(10), 10);
+        assert_eq!(tracked(500, None).steps(10), 0);
+        assert_eq!(tracked(500, Some(0)).steps(10), 0);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        // 250 units in 10 seconds, 750 to go
+        let snapshot = tracked(250, Some(1000)).snapshot("backfill-1-0");
+        assert_eq!(snapshot.progress_percent, Some(25.0));
+        let eta = snapshot.eta_secs.unwrap();
+        assert!((29..=31).contains(&eta), "{}", eta);
+        assert_eq!(tracked(0, Some(1000)).snapshot("x").eta_secs, None);
+        assert_eq!(tracked(250, None).snapshot("x").eta_secs, None);
+    }
+
+    #[test]
+    fn test_events() {
+        let mut tracked = tracked(100, Some(1000));
+        let started = tracked.event("backfill-1-0", "started", None);
+        let failed = tracked.event(
+            "backfill-1-0",
+            "failed",
+            Some(("database".to_string(), "deadlock".to_string())),
+        );
+        assert_eq!((started.event_index, failed.event_index), (0, 1));
+        assert_eq!(failed.error_code.as_deref(), Some("database"));
+        assert_eq!(failed.done, 100);
+        assert_eq!(
+            error_code(&anyhow::Error::from(diesel::result::Error::NotFound)),
+            "database"
+        );
+        assert_eq!(error_code(&anyhow::anyhow!("boom")), "internal");
+    }
+}
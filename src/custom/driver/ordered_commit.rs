@@ -0,0 +1,270 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Processing the batches of a round in parallel while committing them in version order. With
+//! `ordered_commit` enabled, a round fetches `concurrency` consecutive batches and processes them
+//! at once, and every batch gets a `Ticket` when it's fetched, in fetch order. A processor parses
+//! its batch right away and waits for the batch's `turn` before it writes or publishes anything,
+//! and the turn only comes once every batch fetched before it committed, so rows and messages land
+//! in the same order as with one batch at a time. Processors that don't split their work that way
+//! (`TransactionProcessor::takes_commit_turn`) wait for their turn before processing at all.
+//!
+//! A batch that fails, or whose task is gone, takes the turn away from every batch after it: they
+//! fail with `EarlierBatchFailed` instead of committing, and the round stops the processor as
+//! usual. What a processor does before its turn, like the default processor's duplicate checks,
+//! sees the batches in whatever order they get there, as it does with `processor_tasks`.
+
+use std::{
+    fmt::{self, Display},
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::watch;
+
+tokio::task_local! {
+    static TURN: Turn;
+}
+
+/// Hands out the turns of a processor's batches, in the order they were fetched
+#[derive(Debug)]
+pub struct CommitOrder {
+    /// Sequence number of the next batch fetched
+    issued: AtomicU64,
+    progress: watch::Sender<Progress>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Progress {
+    /// Sequence number of the batch whose turn it is
+    next: u64,
+    /// Sequence number and start version of the first batch that failed
+    failed: Option<(u64, u64)>,
+}
+
+/// Where a batch is in the commit order, for `turn` to wait on
+#[derive(Clone, Debug)]
+pub struct Turn {
+    order: Arc<CommitOrder>,
+    seq: u64,
+    start_version: u64,
+    end_version: u64,
+}
+
+/// A fetched batch's place in the commit order. The next batch's turn comes once it's finished,
+/// and a ticket dropped without being finished counts as a failed batch.
+#[derive(Debug)]
+pub struct Ticket {
+    turn: Turn,
+    finished: bool,
+}
+
+/// A batch that isn't committed because one fetched before it failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EarlierBatchFailed {
+    pub failed_version: u64,
+    pub start_version: u64,
+    pub end_version: u64,
+}
+
+impl CommitOrder {
+    pub fn new() -> Self {
+        Self {
+            issued: AtomicU64::new(0),
+            progress: watch::channel(Progress::default()).0,
+        }
+    }
+
+    /// The ticket of the batch `start_version..=end_version`. Has to be called in fetch order,
+    /// i.e. under the fetcher's lock.
+    pub fn ticket(self: &Arc<Self>, start_version: u64, end_version: u64) -> Ticket {
+        Ticket {
+            turn: Turn {
+                order: self.clone(),
+                seq: self.issued.fetch_add(1, Ordering::SeqCst),
+                start_version,
+                end_version,
+            },
+            finished: false,
+        }
+    }
+
+    fn fail(&self, seq: u64, start_version: u64) {
+        self.progress.send_modify(|progress| {
+            if progress.failed.map_or(true, |(failed, _)| seq < failed) {
+                progress.failed = Some((seq, start_version));
+            }
+        });
+    }
+}
+
+impl Default for CommitOrder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Turn {
+    /// Resolves once every batch fetched before this one committed
+    pub async fn wait(&self) -> Result<(), EarlierBatchFailed> {
+        let mut progress = self.order.progress.subscribe();
+        loop {
+            let current = *progress.borrow_and_update();
+            if let Some((failed, failed_version)) = current.failed {
+                if failed < self.seq {
+                    return Err(EarlierBatchFailed {
+                        failed_version,
+                        start_version: self.start_version,
+                        end_version: self.end_version,
+                    });
+                }
+            }
+            if current.next >= self.seq {
+                return Ok(());
+            }
+            // The sender lives as long as the order, which this turn holds
+            let _ = progress.changed().await;
+        }
+    }
+}
+
+impl Ticket {
+    pub fn turn(&self) -> Turn {
+        self.turn.clone()
+    }
+
+    /// Ends the batch. If it `succeeded` the next batch's turn comes, once this one's did,
+    /// otherwise no later batch gets a turn.
+    pub async fn finish(mut self, succeeded: bool) {
+        self.finished = true;
+        let Turn { order, seq, .. } = &self.turn;
+        if !succeeded {
+            order.fail(*seq, self.turn.start_version);
+        } else if self.turn.wait().await.is_ok() {
+            order
+                .progress
+                .send_modify(|progress| progress.next = progress.next.max(seq + 1));
+        }
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.turn.order.fail(self.turn.seq, self.turn.start_version);
+        }
+    }
+}
+
+/// Runs `f` as the processing of the batch of `turn`, see `turn`
+pub async fn scope<F: Future>(turn: Turn, f: F) -> F::Output {
+    TURN.scope(turn, f).await
+}
+
+/// Waits for the turn of the batch being processed to commit. Right away outside of ordered
+/// commits.
+pub async fn turn() -> Result<(), EarlierBatchFailed> {
+    match TURN.try_with(Turn::clone) {
+        Ok(turn) => turn.wait().await,
+        Err(_) => Ok(()),
+    }
+}
+
+impl Display for EarlierBatchFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Batch {}-{} isn't committed, the batch at version {} before it failed",
+            self.start_version, self.end_version, self.failed_version
+        )
+    }
+}
+
+impl std::error::Error for EarlierBatchFailed {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Mutex, time::Duration};
+
+    /// Processes the batches of `tickets` at once, each parsing for its delay and failing if it's
+    /// in `failing`, and returns what each returned and the batches in the order they committed
+    async fn process(
+        tickets: Vec<Ticket>,
+        delays_millis: &[u64],
+        failing: &[u64],
+    ) -> (Vec<Result<u64, String>>, Vec<u64>) {
+        let committed = Arc::new(Mutex::new(vec![]));
+        let tasks = tickets
+            .into_iter()
+            .zip(delays_millis)
+            .map(|(ticket, &delay)| {
+                let committed = committed.clone();
+                let fails = failing.contains(&ticket.turn.start_version);
+                tokio::spawn(async move {
+                    let start_version = ticket.turn.start_version;
+                    let result = scope(ticket.turn(), async {
+                        tokio::time::sleep(Duration::from_millis(delay)).await;
+                        if fails {
+                            return Err("failed".to_string());
+                        }
+                        turn().await.map_err(|e| e.to_string())?;
+                        committed.lock().unwrap().push(start_version);
+                        Ok(start_version)
+                    })
+                    .await;
+                    ticket.finish(result.is_ok()).await;
+                    result
+                })
+            });
+        let results = futures::future::try_join_all(tasks).await.unwrap();
+        let committed = committed.lock().unwrap().clone();
+        (results, committed)
+    }
+
+    fn tickets(order: &Arc<CommitOrder>, count: u64) -> Vec<Ticket> {
+        (0..count)
+            .map(|i| order.ticket(i * 100, i * 100 + 99))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_commits_in_order() {
+        let order = Arc::new(CommitOrder::new());
+        // The last batches fetched finish parsing first
+        let (results, committed) = process(tickets(&order, 5), &[50, 40, 0, 30, 10], &[]).await;
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(committed, [0, 100, 200, 300, 400]);
+
+        // Carries on with the next round
+        let (_, committed) = process(tickets(&order, 3), &[20, 0, 10], &[]).await;
+        assert_eq!(committed, [500, 600, 700]);
+        assert_eq!(turn().await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_failure_stops_later_commits() {
+        let order = Arc::new(CommitOrder::new());
+        let (results, committed) = process(tickets(&order, 5), &[30, 0, 40, 10, 20], &[200]).await;
+        assert_eq!(committed, [0, 100]);
+        assert_eq!(results[2], Err("failed".to_string()));
+        assert_eq!(
+            results[3],
+            Err(
+                "Batch 300-399 isn't committed, the batch at version 200 before it failed"
+                    .to_string()
+            )
+        );
+        assert!(results[4].is_err());
+
+        // A batch whose ticket is dropped, e.g. its task panicked, fails the ones after it too
+        let order = Arc::new(CommitOrder::new());
+        let mut tickets = tickets(&order, 3);
+        drop(tickets.remove(0));
+        let (results, committed) = process(tickets, &[0, 0], &[]).await;
+        assert!(committed.is_empty());
+        assert!(results.iter().all(Result::is_err));
+    }
+}
@@ -0,0 +1,317 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! The order the publisher produces a batch in, so that publishing the same batch again gives
+//! the same messages byte for byte, whichever order the processor parsed or aggregated it in.
+//!
+//! Every batch is sorted by
+//! 1. version: the transaction version of the row, `last_transaction_version` for current rows
+//!    and `transaction_version_created` for coin infos,
+//! 2. index within the version: `event_index` for events and the activities parsed from them,
+//!    `index` for write set changes and `transfer_index` for asset transfers, 0 for models
+//!    without one,
+//! 3. the serialized payload, compared byte by byte, for rows with the same version and index,
+//!    e.g. the current rows a transaction writes.
+//!
+//! Control messages that aren't about a version (health state changes, operation events and
+//! entry function rollups) have version 0, and batch manifests have their start version. Messages are stamped with `ORDERING_VERSION` in the
+//! `ordering_version` header, see `client::ordering_version`, which is bumped whenever this order
+//! changes. Only the messages of a batch are ordered, batches processed in parallel are still
+//! published in the order they finish.
+
+use crate::{
+    custom::driver::{batch_manifest::BatchManifest, circuit_breaker::HealthStateChange},
+    models::{
+        asset_stores::CurrentAssetStore,
+        asset_transfers::AssetTransfer,
+        coin_models::{
+            account_transactions::AccountTransaction,
+            coin_activities::CoinActivity,
+            coin_balances::{CoinBalance, CurrentCoinBalance},
+            coin_infos::CoinInfo,
+            coin_supply::CoinSupply,
+        },
+        entry_function_daily_stats::EntryFunctionDailyRollup,
+        events::EventModel,
+        move_resources::CurrentMoveResource,
+        onchain_config_changes::OnchainConfigChange,
+        operations_log::OperationEvent,
+        stake_models::{
+            delegator_activities::DelegatedStakingActivity,
+            delegator_balances::CurrentDelegatorBalance,
+            staking_pool_voter::CurrentStakingPoolVoter,
+        },
+        token_models::{
+            ans_lookup::CurrentAnsLookup,
+            collection_datas::CurrentCollectionData,
+            token_activities::TokenActivity,
+            token_datas::{CurrentTokenData, TokenData},
+            token_ownerships::{CurrentTokenOwnership, TokenOwnership},
+            tokens::Token,
+        },
+        v2_objects::CurrentObject,
+        write_set_changes::WriteSetChangeModel,
+    },
+};
+use aptos_api_types::Transaction;
+use serde::Serialize;
+
+/// Version of the order below, sent in the `ordering_version` header
+pub const ORDERING_VERSION: u32 = 1;
+
+/// Where a published model goes in its batch
+pub trait Ordered {
+    fn version(&self) -> i64;
+
+    fn index(&self) -> i64 {
+        0
+    }
+
+    /// Of the transaction the model is about, for the `transaction_version` header, see
+    /// `driver::envelope`. `None` for control messages.
+    fn transaction_version(&self) -> Option<i64> {
+        Some(self.version())
+    }
+}
+
+/// The index of a model within its version, for the models whose index column is nullable
+trait IndexColumn {
+    fn or_zero(self) -> i64;
+}
+
+impl IndexColumn for i64 {
+    fn or_zero(self) -> i64 {
+        self
+    }
+}
+
+impl IndexColumn for Option<i64> {
+    fn or_zero(self) -> i64 {
+        self.unwrap_or(0)
+    }
+}
+
+macro_rules! ordered {
+    ($($model:ident => $version:ident $(/ $index:ident)?),* $(,)?) => {
+        $(
+            impl Ordered for $model {
+                fn version(&self) -> i64 {
+                    self.$version
+                }
+
+                $(
+                    fn index(&self) -> i64 {
+                        self.$index.or_zero()
+                    }
+                )?
+            }
+        )*
+    };
+}
+
+ordered! {
+    Token => transaction_version,
+    TokenData => transaction_version,
+    TokenOwnership => transaction_version,
+    CurrentTokenOwnership => last_transaction_version,
+    CurrentTokenData => last_transaction_version,
+    CurrentCollectionData => last_transaction_version,
+    TokenActivity => transaction_version / event_index,
+    OnchainConfigChange => transaction_version,
+    CoinActivity => transaction_version / event_index,
+    CoinInfo => transaction_version_created,
+    CoinBalance => transaction_version,
+    CurrentCoinBalance => last_transaction_version,
+    CoinSupply => transaction_version,
+    CurrentAssetStore => last_transaction_version,
+    CurrentObject => last_transaction_version,
+    CurrentMoveResource => last_transaction_version,
+    AssetTransfer => transaction_version / transfer_index,
+    EventModel => transaction_version / event_index,
+    WriteSetChangeModel => transaction_version / index,
+    AccountTransaction => transaction_version,
+    DelegatedStakingActivity => transaction_version / event_index,
+    CurrentStakingPoolVoter => last_transaction_version,
+    CurrentDelegatorBalance => last_transaction_version,
+    CurrentAnsLookup => last_transaction_version,
+}
+
+impl Ordered for Transaction {
+    fn version(&self) -> i64 {
+        Transaction::version(self).map_or(0, |version| version as i64)
+    }
+}
+
+impl Ordered for OperationEvent {
+    fn version(&self) -> i64 {
+        0
+    }
+
+    fn index(&self) -> i64 {
+        self.event_index
+    }
+
+    fn transaction_version(&self) -> Option<i64> {
+        None
+    }
+}
+
+impl Ordered for HealthStateChange {
+    fn version(&self) -> i64 {
+        0
+    }
+
+    fn transaction_version(&self) -> Option<i64> {
+        None
+    }
+}
+
+impl Ordered for BatchManifest {
+    fn version(&self) -> i64 {
+        self.start_version as i64
+    }
+
+    fn transaction_version(&self) -> Option<i64> {
+        None
+    }
+}
+
+impl Ordered for EntryFunctionDailyRollup {
+    fn version(&self) -> i64 {
+        0
+    }
+
+    fn transaction_version(&self) -> Option<i64> {
+        None
+    }
+}
+
+/// `items` in publishing order. Only rows tied on version and index are serialized, to compare
+/// their payloads.
+pub fn sort<T: Ordered + Serialize>(items: &[T]) -> Vec<&T> {
+    let mut sorted: Vec<&T> = items.iter().collect();
+    sorted.sort_by_key(|item| (item.version(), item.index()));
+    let mut start = 0;
+    while start < sorted.len() {
+        let key = (sorted[start].version(), sorted[start].index());
+        let end = start
+            + sorted[start..]
+                .iter()
+                .take_while(|item| (item.version(), item.index()) == key)
+                .count();
+        if end - start > 1 {
+            // A payload that can't be serialized fails to publish anyway
+            sorted[start..end]
+                .sort_by_cached_key(|item| serde_json::to_vec(item).unwrap_or_default());
+        }
+        start = end;
+    }
+    sorted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bigdecimal::BigDecimal;
+
+    /// Deterministic xorshift, so failures reproduce
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn shuffle<T>(&mut self, items: &mut [T]) {
+            for i in (1..items.len()).rev() {
+                items.swap(i, (self.next() % (i as u64 + 1)) as usize);
+            }
+        }
+    }
+
+    /// Few versions and indices, so that rows tie on both
+    fn events(rng: &mut Rng, count: usize) -> Vec<EventModel> {
+        (0..count)
+            .map(|position| EventModel {
+                sequence_number: position as i64,
+                creation_number: (rng.next() % 3) as i64,
+                account_address: format!("0x{:x}", rng.next() % 4),
+                transaction_version: (rng.next() % 5) as i64,
+                transaction_block_height: 0,
+                type_: "0x1::coin::DepositEvent".to_string(),
+                data: serde_json::json!({ "amount": (rng.next() % 100).to_string() }),
+                event_index: (rng.next() % 3 > 0).then(|| (rng.next() % 3) as i64),
+                event_account_address: None,
+                event_module: None,
+                event_name: None,
+                event_type_params: None,
+                block_timestamp: None,
+            })
+            .collect()
+    }
+
+    fn balances(rng: &mut Rng, count: usize) -> Vec<CurrentCoinBalance> {
+        (0..count)
+            .map(|position| CurrentCoinBalance {
+                owner_address: format!("0x{:x}", position),
+                coin_type_hash: String::new(),
+                coin_type: "0x1::aptos_coin::AptosCoin".to_string(),
+                amount: BigDecimal::from(rng.next() % 1000),
+                last_transaction_version: (rng.next() % 3) as i64,
+                last_transaction_timestamp: chrono::NaiveDateTime::from_timestamp_opt(0, 0)
+                    .unwrap(),
+            })
+            .collect()
+    }
+
+    fn published<T: Ordered + Serialize>(items: &[T]) -> Vec<Vec<u8>> {
+        sort(items)
+            .into_iter()
+            .map(|item| serde_json::to_vec(item).unwrap())
+            .collect()
+    }
+
+    fn assert_reproducible<T: Ordered + Serialize>(rng: &mut Rng, mut items: Vec<T>) {
+        rng.shuffle(&mut items);
+        let first = published(&items);
+        rng.shuffle(&mut items);
+        assert_eq!(published(&items), first);
+        let keys = sort(&items)
+            .into_iter()
+            .map(|item| (item.version(), item.index()))
+            .collect::<Vec<_>>();
+        assert!(keys.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn test_sort_is_reproducible() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for count in [0, 1, 2, 10, 100, 500] {
+            let events = events(&mut rng, count);
+            assert_reproducible(&mut rng, events);
+            let balances = balances(&mut rng, count);
+            assert_reproducible(&mut rng, balances);
+        }
+    }
+
+    #[test]
+    fn test_sort_tiebreak() {
+        let mut rng = Rng(7);
+        let mut balances = balances(&mut rng, 3);
+        for balance in &mut balances {
+            balance.last_transaction_version = 1;
+        }
+        balances[0].owner_address = "0xc".to_string();
+        balances[1].owner_address = "0xa".to_string();
+        balances[2].owner_address = "0xb".to_string();
+        let owners = sort(&balances)
+            .into_iter()
+            .map(|balance| balance.owner_address.as_str())
+            .collect::<Vec<_>>();
+        // Same version, so by payload, which starts with the owner
+        assert_eq!(owners, ["0xa", "0xb", "0xc"]);
+    }
+}
@@ -0,0 +1,576 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Versions of the published payloads, so that a model can evolve while its consumers upgrade
+//! on their own schedules. Every published model has a `PayloadSchema` version, stamped on each
+//! message in the `schema_version` header. When a model changes, its version is bumped and its
+//! previous payload is kept as a struct of its own, e.g. `CurrentObjectV1`, converting to and
+//! from the current one through `Evolved`. A topic can then be pinned to the previous version
+//! under `pinned_versions`, or, under `dual_publish`, keep its version while the other of the
+//! current and previous version goes to `<topic>.v<version>` for the migration window.
+//!
+//! `payload_schemas!` is the registry of the current versions. It destructures every model
+//! without `..`, so adding, removing or renaming a field fails the build until the model's entry
+//! is updated, which is where the version gets bumped and the previous payload added.
+
+use crate::{
+    client::MODEL_TOPIC_KEYS,
+    custom::driver::{
+        batch_manifest::BatchManifest, circuit_breaker::HealthStateChange,
+        config::PayloadSchemaConfig,
+    },
+    models::{
+        asset_stores::CurrentAssetStore,
+        asset_transfers::AssetTransfer,
+        coin_models::{
+            account_transactions::AccountTransaction,
+            coin_activities::CoinActivity,
+            coin_balances::{CoinBalance, CurrentCoinBalance},
+            coin_infos::CoinInfo,
+            coin_supply::CoinSupply,
+        },
+        entry_function_daily_stats::EntryFunctionDailyRollup,
+        events::EventModel,
+        move_resources::CurrentMoveResource,
+        move_utils::StructTag,
+        onchain_config_changes::OnchainConfigChange,
+        operations_log::OperationEvent,
+        stake_models::{
+            delegator_activities::DelegatedStakingActivity,
+            delegator_balances::CurrentDelegatorBalance,
+            staking_pool_voter::CurrentStakingPoolVoter,
+        },
+        token_models::{
+            ans_lookup::CurrentAnsLookup,
+            collection_datas::CurrentCollectionData,
+            token_activities::TokenActivity,
+            token_datas::{CurrentTokenData, TokenData},
+            token_ownerships::{CurrentTokenOwnership, TokenOwnership},
+            tokens::Token,
+        },
+        v2_objects::CurrentObject,
+        write_set_changes::WriteSetChangeModel,
+    },
+};
+use anyhow::Context;
+use bigdecimal::BigDecimal;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Converts a payload of the current version into the previous one
+pub type Convert = fn(&[u8]) -> anyhow::Result<Vec<u8>>;
+
+/// A version of a model's payload
+pub trait PayloadSchema: Serialize + DeserializeOwned {
+    /// Model name, as in `client::MODEL_TOPIC_KEYS`
+    const MODEL: &'static str;
+    const VERSION: u32;
+}
+
+/// A payload with a previous version it converts to and from
+pub trait Evolved: PayloadSchema {
+    type Previous: PayloadSchema;
+
+    fn to_previous(&self) -> Self::Previous;
+
+    /// Fields the previous version doesn't have are left empty
+    fn from_previous(previous: Self::Previous) -> Self;
+}
+
+macro_rules! payload_schemas {
+    ($($model:ident = $version:literal { $($field:ident),* $(,)? }),* $(,)?) => {
+        $(
+            impl PayloadSchema for $model {
+                const MODEL: &'static str = stringify!($model);
+                const VERSION: u32 = $version;
+            }
+
+            // Fails to build when a field is added, removed or renamed: bump the version
+            const _: fn(&$model) = |&$model { $($field: _),* }| {};
+        )*
+
+        /// Current version of every registered model
+        const CURRENT: &[(&str, u32)] = &[$((stringify!($model), $version)),*];
+    };
+}
+
+payload_schemas! {
+    CoinInfo = 1 {
+        coin_type_hash, coin_type, transaction_version_created, creator_address, name, symbol,
+        decimals, transaction_created_timestamp, supply_aggregator_table_handle,
+        supply_aggregator_table_key,
+    },
+    CoinActivity = 1 {
+        transaction_version, event_account_address, event_creation_number,
+        event_sequence_number, owner_address, coin_type, amount, activity_type, is_gas_fee,
+        is_transaction_success, entry_function_id_str, block_height, transaction_timestamp,
+        event_index,
+    },
+    CoinBalance = 1 {
+        transaction_version, owner_address, coin_type_hash, coin_type, amount,
+        transaction_timestamp,
+    },
+    CurrentCoinBalance = 1 {
+        owner_address, coin_type_hash, coin_type, amount, last_transaction_version,
+        last_transaction_timestamp,
+    },
+    CoinSupply = 1 {
+        transaction_version, coin_type_hash, coin_type, supply, transaction_timestamp,
+        transaction_epoch,
+    },
+    CurrentTokenData = 1 {
+        token_data_id_hash, creator_address, collection_name, name, maximum, supply,
+        largest_property_version, metadata_uri, payee_address, royalty_points_numerator,
+        royalty_points_denominator, maximum_mutable, uri_mutable, description_mutable,
+        properties_mutable, royalty_mutable, default_properties, last_transaction_version,
+        collection_data_id_hash, last_transaction_timestamp, description,
+    },
+    Token = 1 {
+        token_data_id_hash, property_version, transaction_version, creator_address,
+        collection_name, name, token_properties, collection_data_id_hash, transaction_timestamp,
+    },
+    TokenData = 1 {
+        token_data_id_hash, transaction_version, creator_address, collection_name, name, maximum,
+        supply, largest_property_version, metadata_uri, payee_address, royalty_points_numerator,
+        royalty_points_denominator, maximum_mutable, uri_mutable, description_mutable,
+        properties_mutable, royalty_mutable, default_properties, collection_data_id_hash,
+        transaction_timestamp, description,
+    },
+    TokenOwnership = 1 {
+        token_data_id_hash, property_version, transaction_version, table_handle, creator_address,
+        collection_name, name, owner_address, amount, table_type, collection_data_id_hash,
+        transaction_timestamp,
+    },
+    CurrentTokenOwnership = 1 {
+        token_data_id_hash, property_version, owner_address, creator_address, collection_name,
+        name, amount, token_properties, last_transaction_version, collection_data_id_hash,
+        table_type, last_transaction_timestamp,
+    },
+    CurrentCollectionData = 1 {
+        collection_data_id_hash, creator_address, collection_name, description, metadata_uri,
+        supply, maximum, maximum_mutable, uri_mutable, description_mutable,
+        last_transaction_version, table_handle, last_transaction_timestamp,
+    },
+    TokenActivity = 1 {
+        transaction_version, event_account_address, event_creation_number,
+        event_sequence_number, token_data_id_hash, property_version, creator_address,
+        collection_name, name, transfer_type, from_address, to_address, token_amount, coin_type,
+        coin_amount, collection_data_id_hash, transaction_timestamp, event_index,
+    },
+    OnchainConfigChange = 1 {
+        transaction_version, config_type, resource_type, value, diff, transaction_timestamp,
+    },
+    // 2 added the resolved owner
+    CurrentObject = 2 {
+        object_address, owner_address, state_key_hash, allow_ungated_transfer,
+        last_guid_creation_num, last_transaction_version, is_deleted, ultimate_owner,
+        ownership_depth,
+    },
+    CurrentAssetStore = 1 {
+        store_address, asset_type, owner_address, store_kind, is_frozen,
+        created_transaction_version, deleted_transaction_version, last_transaction_version,
+    },
+    HealthStateChange = 1 { processor, from, to, lag, errors_per_minute, changed_at },
+    OperationEvent = 1 {
+        operation_id, event_index, kind, event, actor, parameters, done, total, error_code,
+        error, emitted_at,
+    },
+    EntryFunctionDailyRollup = 1 {
+        date, entry_function_id_str, call_count, success_count, distinct_senders, total_gas_used,
+    },
+    AccountTransaction = 1 { transaction_version, account_address },
+    AssetTransfer = 1 {
+        transaction_version, transfer_index, standard, asset_id, property_version_v1,
+        from_address, to_address, amount, kind, event_index, transaction_timestamp,
+    },
+    // Of the JSON payloads, protobuf ones evolve through their field numbers. 2 added the block
+    // timestamp
+    EventModel = 2 {
+        sequence_number, creation_number, account_address, transaction_version,
+        transaction_block_height, type_, data, event_index, event_account_address, event_module,
+        event_name, event_type_params, block_timestamp,
+    },
+    WriteSetChangeModel = 1 {
+        transaction_version, index, hash, transaction_block_height, type_, address,
+    },
+    // 2 added the address the type is declared at
+    CurrentMoveResource = 2 {
+        address, type_, module, name, generic_type_params, data, state_key_hash,
+        last_transaction_version, is_deleted, resource_address,
+    },
+    BatchManifest = 1 {
+        processor, start_version, end_version, counts, dead_lettered, chain_id, committed_at,
+    },
+    DelegatedStakingActivity = 1 {
+        transaction_version, event_index, delegator_address, pool_address, event_type, amount,
+    },
+    CurrentStakingPoolVoter = 1 {
+        staking_pool_address, voter_address, last_transaction_version, operator_address,
+    },
+    CurrentDelegatorBalance = 1 {
+        delegator_address, pool_address, pool_type, table_handle, last_transaction_version,
+        shares, parent_table_handle,
+    },
+    CurrentAnsLookup = 1 {
+        domain, subdomain, registered_address, last_transaction_version, expiration_timestamp,
+        token_name, is_primary, record_expiration_timestamp,
+    },
+}
+
+/// `TransactionModel` messages carry the API transaction, which isn't ours to version
+const TRANSACTION_VERSION: u32 = 1;
+
+/// Previous version of each model that has one, with the conversion from the current version
+const PREVIOUS: &[(&str, u32, Convert)] = &[
+    previous::<CurrentObject>(),
+    previous::<EventModel>(),
+    previous::<CurrentMoveResource>(),
+];
+
+/// `CurrentObject` before the resolved owner
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CurrentObjectV1 {
+    pub object_address: String,
+    pub owner_address: String,
+    pub state_key_hash: String,
+    pub allow_ungated_transfer: bool,
+    pub last_guid_creation_num: BigDecimal,
+    pub last_transaction_version: i64,
+    pub is_deleted: bool,
+}
+
+impl PayloadSchema for CurrentObjectV1 {
+    const MODEL: &'static str = "CurrentObject";
+    const VERSION: u32 = 1;
+}
+
+impl Evolved for CurrentObject {
+    type Previous = CurrentObjectV1;
+
+    fn to_previous(&self) -> CurrentObjectV1 {
+        CurrentObjectV1 {
+            object_address: self.object_address.clone(),
+            owner_address: self.owner_address.clone(),
+            state_key_hash: self.state_key_hash.clone(),
+            allow_ungated_transfer: self.allow_ungated_transfer,
+            last_guid_creation_num: self.last_guid_creation_num.clone(),
+            last_transaction_version: self.last_transaction_version,
+            is_deleted: self.is_deleted,
+        }
+    }
+
+    fn from_previous(previous: CurrentObjectV1) -> Self {
+        Self {
+            object_address: previous.object_address,
+            owner_address: previous.owner_address,
+            state_key_hash: previous.state_key_hash,
+            allow_ungated_transfer: previous.allow_ungated_transfer,
+            last_guid_creation_num: previous.last_guid_creation_num,
+            last_transaction_version: previous.last_transaction_version,
+            is_deleted: previous.is_deleted,
+            ultimate_owner: None,
+            ownership_depth: None,
+        }
+    }
+}
+
+/// `EventModel` before the block timestamp
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct EventModelV1 {
+    pub sequence_number: i64,
+    pub creation_number: i64,
+    pub account_address: String,
+    pub transaction_version: i64,
+    pub transaction_block_height: i64,
+    pub type_: String,
+    pub data: serde_json::Value,
+    pub event_index: Option<i64>,
+    pub event_account_address: Option<String>,
+    pub event_module: Option<String>,
+    pub event_name: Option<String>,
+    pub event_type_params: Option<serde_json::Value>,
+}
+
+impl PayloadSchema for EventModelV1 {
+    const MODEL: &'static str = "EventModel";
+    const VERSION: u32 = 1;
+}
+
+impl Evolved for EventModel {
+    type Previous = EventModelV1;
+
+    fn to_previous(&self) -> EventModelV1 {
+        EventModelV1 {
+            sequence_number: self.sequence_number,
+            creation_number: self.creation_number,
+            account_address: self.account_address.clone(),
+            transaction_version: self.transaction_version,
+            transaction_block_height: self.transaction_block_height,
+            type_: self.type_.clone(),
+            data: self.data.clone(),
+            event_index: self.event_index,
+            event_account_address: self.event_account_address.clone(),
+            event_module: self.event_module.clone(),
+            event_name: self.event_name.clone(),
+            event_type_params: self.event_type_params.clone(),
+        }
+    }
+
+    fn from_previous(previous: EventModelV1) -> Self {
+        Self {
+            sequence_number: previous.sequence_number,
+            creation_number: previous.creation_number,
+            account_address: previous.account_address,
+            transaction_version: previous.transaction_version,
+            transaction_block_height: previous.transaction_block_height,
+            type_: previous.type_,
+            data: previous.data,
+            event_index: previous.event_index,
+            event_account_address: previous.event_account_address,
+            event_module: previous.event_module,
+            event_name: previous.event_name,
+            event_type_params: previous.event_type_params,
+            block_timestamp: None,
+        }
+    }
+}
+
+/// `CurrentMoveResource` before the address the type is declared at
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CurrentMoveResourceV1 {
+    pub address: String,
+    pub type_: String,
+    pub module: String,
+    pub name: String,
+    pub generic_type_params: Option<serde_json::Value>,
+    pub data: Option<serde_json::Value>,
+    pub state_key_hash: String,
+    pub last_transaction_version: i64,
+    pub is_deleted: bool,
+}
+
+impl PayloadSchema for CurrentMoveResourceV1 {
+    const MODEL: &'static str = "CurrentMoveResource";
+    const VERSION: u32 = 1;
+}
+
+impl Evolved for CurrentMoveResource {
+    type Previous = CurrentMoveResourceV1;
+
+    fn to_previous(&self) -> CurrentMoveResourceV1 {
+        CurrentMoveResourceV1 {
+            address: self.address.clone(),
+            type_: self.type_.clone(),
+            module: self.module.clone(),
+            name: self.name.clone(),
+            generic_type_params: self.generic_type_params.clone(),
+            data: self.data.clone(),
+            state_key_hash: self.state_key_hash.clone(),
+            last_transaction_version: self.last_transaction_version,
+            is_deleted: self.is_deleted,
+        }
+    }
+
+    /// The declaring address is still in the type
+    fn from_previous(previous: CurrentMoveResourceV1) -> Self {
+        Self {
+            resource_address: StructTag::parse(&previous.type_)
+                .map(|tag| tag.address)
+                .unwrap_or_default(),
+            address: previous.address,
+            type_: previous.type_,
+            module: previous.module,
+            name: previous.name,
+            generic_type_params: previous.generic_type_params,
+            data: previous.data,
+            state_key_hash: previous.state_key_hash,
+            last_transaction_version: previous.last_transaction_version,
+            is_deleted: previous.is_deleted,
+        }
+    }
+}
+
+const fn previous<T: Evolved>() -> (&'static str, u32, Convert) {
+    (T::MODEL, T::Previous::VERSION, convert::<T>)
+}
+
+fn convert<T: Evolved>(payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let current: T = serde_json::from_slice(payload)
+        .with_context(|| format!("Failed to read a {} payload", T::MODEL))?;
+    Ok(serde_json::to_vec(&current.to_previous())?)
+}
+
+/// Where a message goes and as which version
+pub struct Route {
+    pub topic: String,
+    /// `None` for models without a registered version
+    pub version: Option<u32>,
+    /// Set when the version is the previous one
+    pub convert: Option<Convert>,
+}
+
+pub fn current_version(model: &str) -> Option<u32> {
+    if model == "TransactionModel" {
+        return Some(TRANSACTION_VERSION);
+    }
+    CURRENT
+        .iter()
+        .find(|(name, _)| *name == model)
+        .map(|(_, version)| *version)
+}
+
+fn previous_version(model: &str) -> Option<(u32, Convert)> {
+    PREVIOUS
+        .iter()
+        .find(|(name, _, _)| *name == model)
+        .map(|(_, version, convert)| (*version, *convert))
+}
+
+/// The messages of `model` to produce, on `topic` of `topic_key`: its pinned or current
+/// version, and with dual publishing the other version to the suffixed topic
+pub fn routes(
+    config: &PayloadSchemaConfig,
+    model: &str,
+    topic_key: &str,
+    topic: &str,
+) -> Vec<Route> {
+    let current = current_version(model);
+    let previous = previous_version(model);
+    let route = |topic: String, version: Option<u32>| Route {
+        topic,
+        version,
+        convert: previous
+            .filter(|(previous, _)| Some(*previous) == version)
+            .map(|(_, convert)| convert),
+    };
+    let pinned = config.pinned_versions.get(topic_key).copied().or(current);
+    let mut routes = vec![route(topic.to_string(), pinned)];
+    if let (Some(current), Some((previous, _))) = (current, previous) {
+        if config.dual_publish.iter().any(|key| key == topic_key) {
+            let other = if pinned == Some(current) {
+                previous
+            } else {
+                current
+            };
+            routes.push(route(format!("{}.v{}", topic, other), Some(other)));
+        }
+    }
+    routes
+}
+
+impl PayloadSchemaConfig {
+    /// Every model on a pinned topic must have the pinned version as its current or previous one
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (topic_key, version) in &self.pinned_versions {
+            for (model, _) in MODEL_TOPIC_KEYS
+                .iter()
+                .filter(|(_, key)| *key == topic_key.as_str())
+            {
+                let current = current_version(model);
+                let previous = previous_version(model).map(|(previous, _)| previous);
+                if current != Some(*version) && previous != Some(*version) {
+                    anyhow::bail!(
+                        "{} is pinned to version {}, but {} has {:?} and previously {:?}",
+                        topic_key,
+                        version,
+                        model,
+                        current,
+                        previous
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn object() -> CurrentObject {
+        CurrentObject {
+            object_address: "0xa".to_string(),
+            owner_address: "0xb".to_string(),
+            state_key_hash: "0xc".to_string(),
+            allow_ungated_transfer: true,
+            last_guid_creation_num: BigDecimal::from(3),
+            last_transaction_version: 10,
+            is_deleted: false,
+            ultimate_owner: Some("0xd".to_string()),
+            ownership_depth: Some(2),
+        }
+    }
+
+    #[test]
+    fn test_current_object_conversion() {
+        let previous = object().to_previous();
+        assert_eq!(previous.object_address, "0xa");
+        let back = CurrentObject::from_previous(previous.clone());
+        assert_eq!(back.ultimate_owner, None);
+        assert_eq!(back.ownership_depth, None);
+        assert_eq!(back.to_previous(), previous);
+
+        // The converted payload reads as the previous version and has none of the new fields
+        let payload = serde_json::to_vec(&object()).unwrap();
+        let converted = convert::<CurrentObject>(&payload).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&converted).unwrap();
+        assert!(value.get("ultimate_owner").is_none());
+        assert_eq!(
+            serde_json::from_value::<CurrentObjectV1>(value).unwrap(),
+            previous
+        );
+        // A consumer of the current version can read the previous payload
+        assert!(serde_json::from_slice::<CurrentObject>(&converted).is_ok());
+    }
+
+    #[test]
+    fn test_registry() {
+        for (model, _) in MODEL_TOPIC_KEYS {
+            assert!(
+                current_version(model).is_some(),
+                "{} isn't versioned",
+                model
+            );
+        }
+        for (model, version, _) in PREVIOUS {
+            assert_eq!(current_version(model), Some(version + 1));
+        }
+    }
+
+    #[test]
+    fn test_routes() {
+        let mut config = PayloadSchemaConfig::default();
+        let current = routes(&config, "CurrentObject", "current_object_topic", "objects");
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].version, Some(2));
+        assert!(current[0].convert.is_none());
+
+        config.pinned_versions = HashMap::from([("current_object_topic".to_string(), 1)]);
+        config.dual_publish = vec!["current_object_topic".to_string()];
+        config.validate().unwrap();
+        let pinned = routes(&config, "CurrentObject", "current_object_topic", "objects");
+        assert_eq!(pinned.len(), 2);
+        assert_eq!(
+            (pinned[0].topic.as_str(), pinned[0].version),
+            ("objects", Some(1))
+        );
+        assert!(pinned[0].convert.is_some());
+        assert_eq!(
+            (pinned[1].topic.as_str(), pinned[1].version),
+            ("objects.v2", Some(2))
+        );
+        assert!(pinned[1].convert.is_none());
+
+        // Models without a previous version aren't dual published
+        let coin = routes(&config, "CoinInfo", "current_object_topic", "objects");
+        assert_eq!(coin.len(), 1);
+        assert_eq!(
+            routes(&config, "CoinActivity", "coin_topic", "coins")[0].version,
+            None
+        );
+
+        config.pinned_versions = HashMap::from([("coin_info_topic".to_string(), 2)]);
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,382 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Startup self-checks. Every dependency the driver needs (Kafka, Postgres, the fullnode) is
+//! exercised once before the watermark is loaded so that a misconfiguration fails at boot with
+//! a precise error instead of minutes later on the first produce. Every configured topic must
+//! exist on the broker. A canary message is only produced to `canary_topic`, a topic set aside
+//! for it, so that consumers of the data topics never see one.
+
+use crate::{
+    custom::driver::{config::DriverConfig, producer::Producer},
+    database::PgDbPool,
+    models::{ledger_info::LedgerInfo, processor_status::ProcessorStatusV2},
+    schema::processor_status,
+};
+use anyhow::{anyhow, Context as AnyhowContext};
+use aptos_api::context::Context;
+use aptos_logger::info;
+use diesel::{result::Error, sql_query, RunQueryDsl};
+use rdkafka::{
+    consumer::{BaseConsumer, Consumer},
+    producer::{FutureProducer, FutureRecord},
+    ClientConfig, Message, Offset, TopicPartitionList,
+};
+use std::{collections::HashMap, fmt, sync::Arc, time::Duration};
+
+/// Row written (and rolled back) in `processor_status` to prove we can write to the db
+const WRITE_PROBE_PROCESSOR: &str = "__indexer_preflight_probe__";
+
+/// Which dependency and which operation on it failed
+#[derive(Debug)]
+pub struct PreflightError {
+    pub dependency: &'static str,
+    pub operation: String,
+    pub error: anyhow::Error,
+}
+
+impl PreflightError {
+    fn new(dependency: &'static str, operation: impl Into<String>, error: anyhow::Error) -> Self {
+        Self {
+            dependency,
+            operation: operation.into(),
+            error,
+        }
+    }
+}
+
+impl fmt::Display for PreflightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Preflight check failed for {} ({}): {:?}",
+            self.dependency, self.operation, self.error
+        )
+    }
+}
+
+impl std::error::Error for PreflightError {}
+
+pub struct Preflight {
+    processor_name: String,
+    kafka_conf: HashMap<String, String>,
+    /// Checked for on the broker
+    topics: Vec<String>,
+    canary_topic: Option<String>,
+    connection_pool: PgDbPool,
+    context: Arc<Context>,
+    skip: bool,
+    consume_canary: bool,
+    timeout: Duration,
+}
+
+impl Preflight {
+    pub fn new(
+        processor_name: &str,
+        config: &DriverConfig,
+        connection_pool: PgDbPool,
+        context: Arc<Context>,
+    ) -> Self {
+        Self {
+            processor_name: processor_name.to_string(),
+            kafka_conf: config.kafka.clone(),
+            topics: configured_topics(config),
+            canary_topic: config.preflight.canary_topic.clone(),
+            connection_pool,
+            context,
+            skip: !config.preflight.enabled,
+            consume_canary: config.preflight.consume_canary,
+            timeout: Duration::from_millis(config.preflight.timeout_millis),
+        }
+    }
+
+    /// Skip every check, e.g. in test environments without a broker
+    pub fn skip(mut self, skip: bool) -> Self {
+        self.skip = skip;
+        self
+    }
+
+    /// Also consume each canary back to verify consumer ACLs
+    pub fn consume_canary(mut self, consume_canary: bool) -> Self {
+        self.consume_canary = consume_canary;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub async fn run(&self) -> Result<(), PreflightError> {
+        if self.skip {
+            info!(
+                processor_name = self.processor_name,
+                "Skipping preflight checks"
+            );
+            return Ok(());
+        }
+        info!(
+            processor_name = self.processor_name,
+            "Running preflight checks..."
+        );
+        self.check_postgres()?;
+        self.check_fullnode()?;
+        self.check_kafka().await?;
+        info!(
+            processor_name = self.processor_name,
+            "Preflight checks passed"
+        );
+        Ok(())
+    }
+
+    /// `SELECT 1` plus an insert that is always rolled back
+    fn check_postgres(&self) -> Result<(), PreflightError> {
+        let mut conn = self
+            .connection_pool
+            .get()
+            .map_err(|e| PreflightError::new("postgres", "get connection", e.into()))?;
+        sql_query("SELECT 1")
+            .execute(&mut conn)
+            .map_err(|e| PreflightError::new("postgres", "SELECT 1", e.into()))?;
+
+        let res = conn
+            .build_transaction()
+            .read_write()
+            .run::<(), Error, _>(|pg_conn| {
+                diesel::insert_into(processor_status::table)
+                    .values(&ProcessorStatusV2 {
+                        processor: WRITE_PROBE_PROCESSOR.to_string(),
+                        last_success_version: 0,
+                    })
+                    .on_conflict_do_nothing()
+                    .execute(pg_conn)?;
+                Err(Error::RollbackTransaction)
+            });
+        match res {
+            Err(Error::RollbackTransaction) => Ok(()),
+            Err(e) => Err(PreflightError::new(
+                "postgres",
+                "write-rollback probe on processor_status",
+                e.into(),
+            )),
+            Ok(_) => unreachable!("Write probe transaction must always roll back"),
+        }
+    }
+
+    /// The node must answer, and must be on the chain we already have data for (if any)
+    fn check_fullnode(&self) -> Result<(), PreflightError> {
+        let node_chain_id = self
+            .context
+            .get_latest_ledger_info_wrapped()
+            .map_err(|e| PreflightError::new("fullnode", "get ledger info", anyhow!("{}", e)))?
+            .chain_id as i64;
+        let mut conn = self
+            .connection_pool
+            .get()
+            .map_err(|e| PreflightError::new("postgres", "get connection", e.into()))?;
+        let stored_chain_id = LedgerInfo::get(&mut conn)
+            .map_err(|e| PreflightError::new("postgres", "read ledger_infos", e.into()))?
+            .map(|li| li.chain_id);
+        match stored_chain_id {
+            Some(chain_id) if chain_id != node_chain_id => Err(PreflightError::new(
+                "fullnode",
+                "verify chain id",
+                anyhow!(
+                    "node reports chain {} but existing data is for chain {}",
+                    node_chain_id,
+                    chain_id
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// The configured topics must exist. The canary, if there's a canary topic, must be produced,
+    /// and consumed back if asked.
+    async fn check_kafka(&self) -> Result<(), PreflightError> {
+        let client_config = Producer::new(self.kafka_conf.clone()).client_config();
+        let metadata_config = client_config.clone();
+        let topics = self.topics.clone();
+        let timeout = self.timeout;
+        tokio::task::spawn_blocking(move || check_topics(&metadata_config, &topics, timeout))
+            .await
+            .map_err(|e| PreflightError::new("kafka", "join metadata task", e.into()))??;
+
+        let Some(topic) = &self.canary_topic else {
+            return Ok(());
+        };
+        let producer: FutureProducer = client_config
+            .create()
+            .map_err(|e| PreflightError::new("kafka", "create producer", e.into()))?;
+        let canary_id = format!(
+            "{}-{}-{}",
+            self.processor_name,
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos()
+        );
+        let payload = serde_json::json!({
+            "type": "indexer_canary",
+            "id": canary_id,
+            "processor_name": self.processor_name,
+        })
+        .to_string();
+        let (partition, offset) = producer
+            .send(
+                FutureRecord::to(topic).key(&canary_id).payload(&payload),
+                self.timeout,
+            )
+            .await
+            .map_err(|(e, _)| {
+                PreflightError::new("kafka", format!("produce canary to {}", topic), e.into())
+            })?;
+        if self.consume_canary {
+            let mut consumer_config = client_config.clone();
+            let topic = topic.clone();
+            let timeout = self.timeout;
+            tokio::task::spawn_blocking(move || {
+                consume_canary(
+                    &mut consumer_config,
+                    &topic,
+                    partition,
+                    offset,
+                    &canary_id,
+                    timeout,
+                )
+                .map_err(|e| {
+                    PreflightError::new("kafka", format!("consume canary from {}", topic), e)
+                })
+            })
+            .await
+            .map_err(|e| PreflightError::new("kafka", "join consumer task", e.into()))??;
+        }
+        Ok(())
+    }
+}
+
+/// The topics of the config, each once
+fn configured_topics(config: &DriverConfig) -> Vec<String> {
+    let mut topics = config.topics.values().cloned().collect::<Vec<_>>();
+    topics.sort();
+    topics.dedup();
+    topics
+}
+
+/// Fails on the first topic that the broker doesn't have, or can't be asked about
+fn check_topics(
+    client_config: &ClientConfig,
+    topics: &[String],
+    timeout: Duration,
+) -> Result<(), PreflightError> {
+    let consumer: BaseConsumer = client_config
+        .create()
+        .map_err(|e| PreflightError::new("kafka", "create metadata client", e.into()))?;
+    for topic in topics {
+        let operation = format!("fetch metadata of {}", topic);
+        let metadata = consumer
+            .fetch_metadata(Some(topic), timeout)
+            .map_err(|e| PreflightError::new("kafka", operation.clone(), e.into()))?;
+        let exists = metadata.topics().iter().any(|metadata_topic| {
+            metadata_topic.name() == topic
+                && metadata_topic.error().is_none()
+                && !metadata_topic.partitions().is_empty()
+        });
+        if !exists {
+            return Err(PreflightError::new(
+                "kafka",
+                operation,
+                anyhow!("topic {} doesn't exist", topic),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Reads the single message at (partition, offset) back and checks it is our canary
+fn consume_canary(
+    consumer_config: &mut ClientConfig,
+    topic: &str,
+    partition: i32,
+    offset: i64,
+    canary_id: &str,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let consumer: BaseConsumer = consumer_config
+        .set("group.id", format!("{}-group", canary_id))
+        .set("enable.auto.commit", "false")
+        .create()
+        .context("Failed to create consumer")?;
+    let mut assignment = TopicPartitionList::new();
+    assignment
+        .add_partition_offset(topic, partition, Offset::Offset(offset))
+        .context("Failed to build assignment")?;
+    consumer
+        .assign(&assignment)
+        .context("Failed to assign partition")?;
+
+    let deadline = std::time::Instant::now() + timeout;
+    while std::time::Instant::now() < deadline {
+        match consumer.poll(Duration::from_millis(100)) {
+            Some(Ok(message)) => {
+                if message.key() == Some(canary_id.as_bytes()) {
+                    return Ok(());
+                }
+            },
+            Some(Err(e)) => return Err(e).context("Failed to poll"),
+            None => {},
+        }
+    }
+    Err(anyhow!(
+        "canary {} not seen at {}:{}@{} within {:?}",
+        canary_id,
+        topic,
+        partition,
+        offset,
+        timeout
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom::{driver::config::PreflightConfig, test_utils};
+
+    #[test]
+    fn test_no_canary_by_default() {
+        assert_eq!(PreflightConfig::default().canary_topic, None);
+        let config = test_utils::driver_config(serde_json::json!({
+            "transaction_topic": "txns",
+            "event_topic": "events",
+            "coin_activity_topic": "txns",
+        }));
+        // Only checked for, a canary on them would reach their consumers
+        assert_eq!(configured_topics(&config), ["events", "txns"]);
+        assert_eq!(config.preflight.canary_topic, None);
+
+        let config: DriverConfig = serde_json::from_value(serde_json::json!({
+            "kafka": {},
+            "topics": { "transaction_topic": "txns" },
+            "preflight": { "canary_topic": "indexer-canary" },
+        }))
+        .unwrap();
+        assert_eq!(
+            config.preflight.canary_topic.as_deref(),
+            Some("indexer-canary")
+        );
+    }
+
+    #[test]
+    fn test_check_topics_without_broker() {
+        let mut client_config = ClientConfig::new();
+        client_config.set("bootstrap.servers", "127.0.0.1:1");
+        let error = check_topics(
+            &client_config,
+            &["txns".to_string()],
+            Duration::from_millis(100),
+        )
+        .unwrap_err();
+        assert_eq!(error.dependency, "kafka");
+        assert_eq!(error.operation, "fetch metadata of txns");
+        // Nothing to ask about
+        assert!(check_topics(&client_config, &[], Duration::from_millis(100)).is_ok());
+    }
+}
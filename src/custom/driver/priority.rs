@@ -0,0 +1,317 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Priority lane: the transactions touching a watched address (sender, event account or resource
+//! address) are published on their own, as soon as they're on chain. The lane tails the chain with
+//! a fetcher of its own, from the tip it finds on start, so it isn't behind by however far the
+//! processor is. Its batches are redacted like the tailer's. These early copies carry the
+//! `priority: true` header and are published again by the main pipeline, so consumers deduplicate
+//! by version (see `client::is_priority`).
+
+use crate::{
+    counters::{
+        INDEXING_LATENCY_SECONDS, PRIORITY_LANE_TRANSACTIONS, PRIORITY_LANE_WATCHED_ADDRESSES,
+    },
+    custom::driver::{config::PriorityLaneConfig, publisher::Publisher, redaction::Redactor},
+    indexer::fetcher::TransactionFetcherTrait,
+    util::standardize_address,
+};
+use aptos_api_types::{Transaction, WriteSetChange};
+use aptos_logger::{info, warn};
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+pub const PRIORITY_LANE: &str = "priority";
+pub const MAIN_LANE: &str = "main";
+/// How long the lane waits for a batch once it's caught up with the chain
+const TAIL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub struct PriorityLane {
+    publisher: Publisher,
+    config: PriorityLaneConfig,
+    watched: RwLock<HashSet<String>>,
+}
+
+impl PriorityLane {
+    pub fn new(config: PriorityLaneConfig, publisher: Publisher) -> Self {
+        let lane = Self {
+            publisher,
+            config,
+            watched: RwLock::new(HashSet::new()),
+        };
+        lane.reload();
+        lane
+    }
+
+    /// Re-reads the watched addresses: the ones in the config plus the ones in
+    /// `watched_addresses_file`, one per line. A file that can't be read keeps the previous set.
+    pub fn reload(&self) {
+        let mut watched = self
+            .config
+            .watched_addresses
+            .iter()
+            .map(|address| standardize_address(address.trim()))
+            .collect::<HashSet<_>>();
+        if let Some(path) = &self.config.watched_addresses_file {
+            match std::fs::read_to_string(path) {
+                Ok(content) => watched.extend(
+                    content
+                        .lines()
+                        .map(str::trim)
+                        .filter(|line| line.starts_with("0x"))
+                        .map(standardize_address),
+                ),
+                Err(err) => {
+                    warn!(
+                        path = path,
+                        error = ?err,
+                        "Failed to read priority lane watched addresses, keeping the previous set"
+                    );
+                    return;
+                },
+            }
+        }
+        let mut current = self.watched.write().unwrap();
+        if *current != watched {
+            info!(
+                watched_addresses = watched.len(),
+                "Reloaded priority lane watched addresses"
+            );
+            PRIORITY_LANE_WATCHED_ADDRESSES.set(watched.len() as i64);
+            *current = watched;
+        }
+    }
+
+    /// Reloads the watched set every `reload_interval_secs` for as long as the lane lives
+    pub fn spawn_reloader(lane: Arc<Self>) {
+        if lane.config.watched_addresses_file.is_none() {
+            return;
+        }
+        let interval = Duration::from_secs(lane.config.reload_interval_secs.max(1));
+        let lane = Arc::downgrade(&lane);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                match lane.upgrade() {
+                    Some(lane) => lane.reload(),
+                    // The tailer using the lane was dropped
+                    None => return,
+                }
+            }
+        });
+    }
+
+    /// Tails the chain with `fetcher` from its current tip, publishing the watched transactions
+    /// of every batch, for as long as the lane lives
+    pub fn spawn_tail<F: TransactionFetcherTrait + 'static>(
+        lane: Arc<Self>,
+        mut fetcher: F,
+        redactor: Option<Arc<Redactor>>,
+    ) {
+        let lane = Arc::downgrade(&lane);
+        tokio::spawn(async move {
+            let tip = fetcher.fetch_ledger_info().ledger_version.0;
+            info!(version = tip, "Priority lane tailing from the chain's tip");
+            fetcher.set_version(tip).await;
+            fetcher.start().await;
+            loop {
+                let mut transactions = fetcher.fetch_next_batch().await;
+                let Some(lane) = lane.upgrade() else {
+                    // The tailer using the lane was dropped, which stops the fetcher too
+                    return;
+                };
+                if transactions.is_empty() {
+                    drop(lane);
+                    tokio::time::sleep(TAIL_POLL_INTERVAL).await;
+                    continue;
+                }
+                if let Some(redactor) = &redactor {
+                    redactor.redact(&mut transactions);
+                }
+                lane.publish_watched(&transactions);
+            }
+        });
+    }
+
+    /// Publishes the watched transactions of a freshly fetched batch
+    pub fn publish_watched(&self, transactions: &[Transaction]) {
+        let watched_txns = {
+            let watched = self.watched.read().unwrap();
+            if watched.is_empty() {
+                return;
+            }
+            transactions
+                .iter()
+                .filter(|txn| touches_watched(txn, &watched))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        if watched_txns.is_empty() {
+            return;
+        }
+        // The regular lane publishes them again, and fails its batch if that fails too
+        if let Err(err) = self
+            .publisher
+            .send_priority_transaction("TransactionModel", &watched_txns)
+        {
+            warn!(error = ?err, "Failed to publish watched transactions");
+            return;
+        }
+        PRIORITY_LANE_TRANSACTIONS.inc_by(watched_txns.len() as u64);
+        for txn in &watched_txns {
+            observe_latency(PRIORITY_LANE, txn.timestamp());
+        }
+    }
+}
+
+/// Records how far behind the chain a transaction with this timestamp was indexed
+pub fn observe_latency(lane: &str, timestamp_micros: u64) {
+    // Genesis and pending transactions have no timestamp
+    if timestamp_micros == 0 {
+        return;
+    }
+    let now_micros = chrono::Utc::now().timestamp_micros() as u64;
+    INDEXING_LATENCY_SECONDS
+        .with_label_values(&[lane])
+        .observe(now_micros.saturating_sub(timestamp_micros) as f64 / 1_000_000.0);
+}
+
+fn touches_watched(txn: &Transaction, watched: &HashSet<String>) -> bool {
+    let (sender, events, changes) = match txn {
+        Transaction::UserTransaction(txn) => {
+            (Some(&txn.request.sender), &txn.events, &txn.info.changes)
+        },
+        Transaction::BlockMetadataTransaction(txn) => (None, &txn.events, &txn.info.changes),
+        Transaction::GenesisTransaction(txn) => (None, &txn.events, &txn.info.changes),
+        _ => return false,
+    };
+    let is_watched = |address: String| watched.contains(&standardize_address(&address));
+    sender.map_or(false, |sender| is_watched(sender.to_string()))
+        || events
+            .iter()
+            .any(|event| is_watched(event.guid.account_address.to_string()))
+        || changes.iter().any(|change| match change {
+            WriteSetChange::WriteResource(resource) => is_watched(resource.address.to_string()),
+            WriteSetChange::DeleteResource(resource) => is_watched(resource.address.to_string()),
+            _ => false,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom::{driver::envelope::Envelope, test_utils};
+    use aptos_api_types::{LedgerInfo, U64};
+    use serde_json::json;
+
+    const WATCHED: &str = "0x000000000000000000000000000000000000000000000000000000000000abcd";
+
+    fn user_transaction(version: u64) -> Transaction {
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": version.to_string(),
+            "hash": format!("0x{:064x}", version),
+            "state_change_hash": format!("0x{:064x}", 0),
+            "event_root_hash": format!("0x{:064x}", 0),
+            "gas_used": "1",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": format!("0x{:064x}", 0),
+            "sender": WATCHED,
+            "sequence_number": version.to_string(),
+            "max_gas_amount": "1000",
+            "gas_unit_price": "1",
+            "expiration_timestamp_secs": "1649713172",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0x1::aptos_account::transfer",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "events": [],
+            "timestamp": "1649713141723410",
+            "changes": []
+        }))
+        .unwrap()
+    }
+
+    /// A chain at version `tip`, serving the versions from the one it's set to
+    struct ChainFetcher {
+        tip: u64,
+        transactions: Vec<Transaction>,
+    }
+
+    #[async_trait::async_trait]
+    impl TransactionFetcherTrait for ChainFetcher {
+        async fn fetch_next_batch(&mut self) -> Vec<Transaction> {
+            std::mem::take(&mut self.transactions)
+        }
+
+        fn fetch_ledger_info(&mut self) -> LedgerInfo {
+            LedgerInfo {
+                chain_id: 4,
+                epoch: U64::from(1),
+                ledger_version: U64::from(self.tip),
+                ledger_timestamp: U64::from(0),
+                oldest_ledger_version: U64::from(0),
+                oldest_block_height: U64::from(0),
+                block_height: U64::from(0),
+            }
+        }
+
+        async fn set_version(&mut self, version: u64) {
+            self.transactions
+                .retain(|txn| txn.version().unwrap_or_default() >= version);
+        }
+
+        async fn start(&mut self) {}
+    }
+
+    #[tokio::test]
+    async fn test_tails_from_the_tip() {
+        let (publisher, recorded) = Publisher::in_memory(
+            test_utils::driver_config(json!({ "transaction_topic": "transaction_topic" })),
+            Envelope::new(4, "custom_default_processor"),
+        );
+        let lane = Arc::new(PriorityLane::new(
+            PriorityLaneConfig {
+                enabled: true,
+                watched_addresses: vec!["0xabcd".to_string()],
+                ..PriorityLaneConfig::default()
+            },
+            publisher,
+        ));
+        // However far behind the processor is, what was on chain before the lane started isn't
+        // published early
+        let fetcher = ChainFetcher {
+            tip: 10,
+            transactions: vec![
+                user_transaction(9),
+                user_transaction(10),
+                user_transaction(11),
+            ],
+        };
+        PriorityLane::spawn_tail(lane.clone(), fetcher, None);
+
+        let started = std::time::Instant::now();
+        while recorded.on_topic("transaction_topic").len() < 2 {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let versions = recorded
+            .on_topic("transaction_topic")
+            .iter()
+            .map(|message| {
+                assert_eq!(message.header(crate::client::PRIORITY_HEADER), Some("true"));
+                message
+                    .header(crate::client::TRANSACTION_VERSION_HEADER)
+                    .unwrap()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(versions, ["10", "11"]);
+    }
+}
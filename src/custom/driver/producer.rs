@@ -14,11 +14,17 @@ impl Producer {
     }
 
     pub fn create(&self) -> ThreadedProducer<DefaultProducerContext> {
+        self.client_config().create().expect("Invalid producer config")
+    }
+
+    /// The raw client config, for callers that need a different client type than the
+    /// publisher's `ThreadedProducer` (e.g. the preflight checks).
+    pub fn client_config(&self) -> ClientConfig {
         let mut config = ClientConfig::new();
         for (k, v) in self.kafka_conf.iter() {
             config.set(k, v);
         }
-        config.create().expect("Invalid producer config")
+        config
     }
 }
 
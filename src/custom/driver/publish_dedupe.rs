@@ -0,0 +1,165 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Skipping the versions a processor published already, so that a processor restarted after a
+//! crash or a backfill over published versions doesn't publish them again, for consumers that
+//! would rather not dedupe themselves.
+//!
+//! After every round, once the processor's producer is flushed and everything it produced is
+//! delivered, the round's last version is recorded as `last_published_version` in the processor's
+//! `processor_status` row, or its shard's, before the watermark moves. The recorded version only
+//! goes up. Before a batch is published, its transactions at or below the recorded version are
+//! dropped, and so are their events and write set changes, which come from them: a batch of
+//! 100..199 with 150 recorded publishes 151..199. The dropped transactions are counted in the
+//! batch's `ProcessingResult` and in `indexer_publish_dedupe_skipped_count`. Rows written to
+//! Postgres aren't affected.
+//!
+//! Only whole rounds are recorded, so a crash in the middle of a round publishes its batches
+//! again. A processor's first round isn't recorded either, since its row doesn't exist yet.
+//! Intentional backfills need `force_republish`, which publishes every version while still
+//! recording them.
+
+use crate::{
+    custom::driver::config::PublishDedupeConfig, database::PgDbPool, schema::processor_status,
+};
+use anyhow::Result;
+use aptos_api_types::Transaction;
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use std::time::Duration;
+
+pub struct PublishCheckpoint {
+    connection_pool: PgDbPool,
+    /// The `processor_status` row, the processor's or with sharding its shard's
+    key: String,
+    force_republish: bool,
+    flush_timeout: Duration,
+}
+
+impl PublishCheckpoint {
+    pub fn new(config: &PublishDedupeConfig, connection_pool: PgDbPool, key: &str) -> Self {
+        Self {
+            connection_pool,
+            key: key.to_string(),
+            force_republish: config.force_republish,
+            flush_timeout: Duration::from_millis(config.flush_timeout_millis),
+        }
+    }
+
+    pub fn flush_timeout(&self) -> Duration {
+        self.flush_timeout
+    }
+
+    /// `None` until a round is recorded
+    pub fn last_published_version(&self) -> Result<Option<u64>> {
+        let mut conn = self.connection_pool.get()?;
+        let version = processor_status::table
+            .filter(processor_status::processor.eq(&self.key))
+            .select(processor_status::last_published_version)
+            .first::<Option<i64>>(&mut conn)
+            .optional()?
+            .flatten();
+        Ok(version.map(|version| version as u64))
+    }
+
+    /// The transactions not published yet, all of them with `force_republish`, and the number of
+    /// the others
+    pub fn unpublished(&self, txns: Vec<Transaction>) -> Result<(Vec<Transaction>, usize)> {
+        if self.force_republish {
+            return Ok((txns, 0));
+        }
+        Ok(after(txns, self.last_published_version()?))
+    }
+
+    /// Records that everything up to `end_version` was delivered, unless a later version was
+    pub fn commit(&self, end_version: u64) -> Result<()> {
+        let mut conn = self.connection_pool.get()?;
+        diesel::update(
+            processor_status::table.filter(
+                processor_status::processor.eq(&self.key).and(
+                    processor_status::last_published_version
+                        .is_null()
+                        .or(processor_status::last_published_version.lt(end_version as i64)),
+                ),
+            ),
+        )
+        .set(processor_status::last_published_version.eq(end_version as i64))
+        .execute(&mut conn)?;
+        Ok(())
+    }
+}
+
+/// The transactions after `last_published_version`, with the number of the others. Pending
+/// transactions have no version and are kept.
+fn after(txns: Vec<Transaction>, last_published_version: Option<u64>) -> (Vec<Transaction>, usize) {
+    let Some(last_published_version) = last_published_version else {
+        return (txns, 0);
+    };
+    let total = txns.len();
+    let unpublished = txns
+        .into_iter()
+        .filter(|txn| {
+            txn.version()
+                .map_or(true, |version| version > last_published_version)
+        })
+        .collect::<Vec<_>>();
+    let skipped = total - unpublished.len();
+    (unpublished, skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn transaction(version: u64) -> Transaction {
+        serde_json::from_value(json!({
+            "type": "block_metadata_transaction",
+            "version": version.to_string(),
+            "block_height": "100",
+            "epoch": "1",
+            "hash": format!("0x{:064x}", version),
+            "state_change_hash": format!("0x{:064x}", 0),
+            "event_root_hash": format!("0x{:064x}", 0),
+            "gas_used": "0",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": format!("0x{:064x}", 0),
+            "id": format!("0x{:064x}", 1),
+            "round": "57600",
+            "failed_proposer_indices": [],
+            "previous_block_votes_bitvec": [],
+            "proposer": "0x68f04222bd9f8846cda028ea5ba3846a806b04a47e1f1a4f0939f350d713b2eb",
+            "timestamp": "1649395495746947",
+            "events": [],
+            "changes": []
+        }))
+        .unwrap()
+    }
+
+    fn versions(txns: &[Transaction]) -> Vec<u64> {
+        txns.iter().filter_map(|txn| txn.version()).collect()
+    }
+
+    #[test]
+    fn test_batch_boundaries() {
+        let batch = || (100..200).map(transaction).collect::<Vec<_>>();
+
+        let (unpublished, skipped) = after(batch(), Some(150));
+        assert_eq!(versions(&unpublished), (151..200).collect::<Vec<_>>());
+        assert_eq!(skipped, 51);
+
+        // Nothing recorded yet, or only versions before the batch
+        for last_published_version in [None, Some(99)] {
+            let (unpublished, skipped) = after(batch(), last_published_version);
+            assert_eq!(unpublished.len(), 100);
+            assert_eq!(skipped, 0);
+        }
+
+        let (unpublished, skipped) = after(batch(), Some(199));
+        assert!(unpublished.is_empty());
+        assert_eq!(skipped, 100);
+        let (unpublished, skipped) = after(batch(), Some(198));
+        assert_eq!(versions(&unpublished), [199]);
+        assert_eq!(skipped, 99);
+    }
+}
@@ -0,0 +1,219 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Publishing only the transactions of some apps. With `publish_filter` set,
+//! `custom_default_processor` publishes a transaction, with its events and write set changes, only
+//! if it:
+//! - calls one of the `entry_functions`, directly or through a multisig account,
+//! - calls an entry function of a module at one of the `module_addresses`, or
+//! - emits an event whose type, or one of its type arguments, is declared at one of them.
+//!
+//! An empty filter publishes every transaction. The filter only applies to publishing, what the
+//! processor writes to Postgres (see `sink`) and the watermark are the same either way.
+//! Transactions left out are counted in `indexer_publish_filtered_transactions_count`.
+
+use crate::{
+    counters::PUBLISH_FILTERED_TRANSACTIONS,
+    custom::driver::{
+        app_scope::{is_address, type_in_scope},
+        config::PublishFilterConfig,
+    },
+    util::standardize_address,
+};
+use aptos_api_types::{
+    EntryFunctionId, MultisigTransactionPayload, Transaction, TransactionPayload,
+};
+use std::collections::HashSet;
+
+pub struct PublishFilter {
+    processor: &'static str,
+    module_addresses: HashSet<String>,
+    /// `address::module::function`, with the address standardized
+    entry_functions: HashSet<String>,
+}
+
+impl PublishFilter {
+    pub fn new(processor: &'static str, config: &PublishFilterConfig) -> Self {
+        Self {
+            processor,
+            module_addresses: config
+                .module_addresses
+                .iter()
+                .map(|address| standardize_address(address))
+                .collect(),
+            entry_functions: config
+                .entry_functions
+                .iter()
+                .map(|function| standardize_function(function))
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.module_addresses.is_empty() && self.entry_functions.is_empty()
+    }
+
+    /// The transactions to publish, counting the others
+    pub fn retain(&self, transactions: Vec<Transaction>) -> Vec<Transaction> {
+        if self.is_empty() {
+            return transactions;
+        }
+        let total = transactions.len();
+        let kept = transactions
+            .into_iter()
+            .filter(|transaction| self.matches(transaction))
+            .collect::<Vec<_>>();
+        PUBLISH_FILTERED_TRANSACTIONS
+            .with_label_values(&[self.processor])
+            .inc_by((total - kept.len()) as u64);
+        kept
+    }
+
+    pub fn matches(&self, transaction: &Transaction) -> bool {
+        let events = match transaction {
+            Transaction::UserTransaction(txn) => {
+                if entry_function(&txn.request.payload)
+                    .map_or(false, |function| self.matches_function(function))
+                {
+                    return true;
+                }
+                &txn.events
+            },
+            Transaction::BlockMetadataTransaction(txn) => &txn.events,
+            Transaction::GenesisTransaction(txn) => &txn.events,
+            _ => return false,
+        };
+        events
+            .iter()
+            .any(|event| type_in_scope(&self.module_addresses, &event.typ.to_string()))
+    }
+
+    fn matches_function(&self, function: &EntryFunctionId) -> bool {
+        let address = standardize_address(&function.module.address.to_string());
+        self.module_addresses.contains(&address)
+            || self.entry_functions.contains(&format!(
+                "{}::{}::{}",
+                address, function.module.name, function.name
+            ))
+    }
+}
+
+fn entry_function(payload: &TransactionPayload) -> Option<&EntryFunctionId> {
+    match payload {
+        TransactionPayload::EntryFunctionPayload(payload) => Some(&payload.function),
+        TransactionPayload::MultisigPayload(payload) => match &payload.transaction_payload {
+            Some(MultisigTransactionPayload::EntryFunctionPayload(payload)) => {
+                Some(&payload.function)
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// `0x1::coin::transfer` with its address standardized, as configured if it has none
+fn standardize_function(function: &str) -> String {
+    match function.trim().split_once("::") {
+        Some((address, rest)) if is_address(address) => {
+            format!("{}::{}", standardize_address(address), rest)
+        },
+        _ => function.trim().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn filter(module_addresses: &[&str], entry_functions: &[&str]) -> PublishFilter {
+        PublishFilter::new("publish_filter_test", &PublishFilterConfig {
+            module_addresses: module_addresses.iter().map(|a| a.to_string()).collect(),
+            entry_functions: entry_functions.iter().map(|f| f.to_string()).collect(),
+        })
+    }
+
+    fn user_transaction(version: u64, function: &str, event_type: &str) -> Transaction {
+        let account = format!("0x{:064x}", 0xabcd);
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": version.to_string(),
+            "block_height": "100",
+            "epoch": "1",
+            "hash": format!("0x{:064x}", version),
+            "state_change_hash": format!("0x{:064x}", 0),
+            "event_root_hash": format!("0x{:064x}", 0),
+            "gas_used": "43",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": format!("0x{:064x}", 0),
+            "sender": account,
+            "sequence_number": "0",
+            "max_gas_amount": "1000",
+            "gas_unit_price": "1",
+            "expiration_timestamp_secs": "1649713172",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": function,
+                "type_arguments": [],
+                "arguments": []
+            },
+            "signature": {
+                "type": "ed25519_signature",
+                "public_key": "0x14ff6646855dad4a2dab30db773cdd4b22d6f9e6813f3e50142adf4f3efcf9f8",
+                "signature": "0x70781112e78cc8b54b86805c016cef2478bccdef21b721542af0323276ab906c989172adffed5bf2f475f2ec3a5b284a0ac46a6aef0d79f0dbb6b85bfca0080a"
+            },
+            "events": [{
+                "guid": { "account_address": account, "creation_number": "2" },
+                "sequence_number": "0",
+                "type": event_type,
+                "data": {}
+            }],
+            "timestamp": "1649713141723410",
+            "changes": []
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_matches() {
+        let transfer = user_transaction(1, "0x1::coin::transfer", "0x1::coin::DepositEvent");
+        let play = user_transaction(2, "0xa11ce::game::play", "0x1::coin::DepositEvent");
+        let settle = user_transaction(
+            3,
+            "0x1::aptos_account::transfer",
+            "0x1::event::Settled<0xa11ce::game::Round>",
+        );
+
+        let by_address = filter(&["0x0a11ce"], &[]);
+        assert!(!by_address.matches(&transfer));
+        assert!(by_address.matches(&play));
+        assert!(by_address.matches(&settle));
+
+        let by_function = filter(&[], &[&format!(
+            "{}::coin::transfer",
+            standardize_address("0x1")
+        )]);
+        assert!(by_function.matches(&transfer));
+        assert!(!by_function.matches(&play));
+        assert!(!by_function.matches(&settle));
+        assert!(!filter(&[], &["0x1::coin::mint"]).matches(&transfer));
+    }
+
+    #[test]
+    fn test_retain() {
+        let transactions = vec![
+            user_transaction(1, "0x1::coin::transfer", "0x1::coin::DepositEvent"),
+            user_transaction(2, "0xa11ce::game::play", "0x1::coin::DepositEvent"),
+        ];
+        // Empty publishes everything
+        assert_eq!(filter(&[], &[]).retain(transactions.clone()).len(), 2);
+        let kept = filter(&["0xa11ce"], &[]).retain(transactions);
+        assert_eq!(
+            kept.iter()
+                .map(|transaction| transaction.version())
+                .collect::<Vec<_>>(),
+            vec![Some(2)]
+        );
+    }
+}
@@ -0,0 +1,373 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Retrying what the publisher hands to Kafka through broker hiccups, e.g. a rolling restart,
+//! instead of failing the batch on the first error. Producing a message and flushing the producer
+//! are attempted up to `max_attempts` times, waiting `base_delay_millis` doubled after every
+//! failed attempt, at most `max_delay_millis`, with jitter so that processors sharing a cluster
+//! don't retry in lockstep.
+//!
+//! Only transient errors are retried: a full local queue, timeouts, and brokers or partition
+//! leaders that are unavailable. Anything else, and a transient error that's still there after
+//! the last attempt, fails with a `PublishError`. Every retry draws a `publish` attempt from the
+//! batch's retry budget, see `driver::retry_budget`, and is counted in
+//! `indexer_publish_retries_count` by topic, or `flush`. The wait blocks the processor's task, with
+//! the tokio worker's other tasks moved elsewhere first, see `serialization::in_place`.
+
+use crate::{
+    counters::PUBLISH_RETRIES,
+    custom::driver::{
+        config::PublishRetryConfig,
+        retry_budget::{self, ErrorClass, RetryBudgetExhausted},
+        serialization,
+    },
+};
+use rdkafka::{error::KafkaError, types::RDKafkaErrorCode};
+use std::{
+    collections::hash_map::RandomState,
+    fmt::{self, Display},
+    hash::{BuildHasher, Hasher},
+    thread,
+    time::Duration,
+};
+
+#[derive(Clone, Debug)]
+pub struct PublishRetry {
+    config: PublishRetryConfig,
+}
+
+impl PublishRetry {
+    pub fn new(config: &PublishRetryConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    /// Sends `record` with `send`, which hands the record back with the error when it fails, until
+    /// it's accepted or a retry isn't allowed. `operation` names what's retried in errors and
+    /// metrics, e.g. the topic.
+    pub fn send<R>(
+        &self,
+        operation: &str,
+        mut record: R,
+        mut send: impl FnMut(R) -> Result<(), (KafkaError, R)>,
+    ) -> Result<(), PublishError> {
+        let mut attempt = 1;
+        loop {
+            match send(record) {
+                Ok(()) => return Ok(()),
+                Err((error, returned)) => {
+                    self.before_retry(operation, attempt, error)?;
+                    record = returned;
+                    attempt += 1;
+                },
+            }
+        }
+    }
+
+    /// Runs `f` until it succeeds or a retry isn't allowed, for operations that can be repeated
+    /// as is, like flushing
+    pub fn run<T>(
+        &self,
+        operation: &str,
+        mut f: impl FnMut() -> Result<T, KafkaError>,
+    ) -> Result<T, PublishError> {
+        let mut attempt = 1;
+        loop {
+            match f() {
+                Ok(value) => return Ok(value),
+                Err(error) => {
+                    self.before_retry(operation, attempt, error)?;
+                    attempt += 1;
+                },
+            }
+        }
+    }
+
+    /// Waits before the next attempt, or the error to fail with if there's none
+    fn before_retry(
+        &self,
+        operation: &str,
+        attempt: u32,
+        error: KafkaError,
+    ) -> Result<(), PublishError> {
+        if !is_transient(&error) || attempt >= self.config.max_attempts {
+            return Err(PublishError::Failed {
+                operation: operation.to_string(),
+                attempts: attempt,
+                error,
+            });
+        }
+        if let Err(exhausted) = retry_budget::retry(ErrorClass::Publish, operation, &error) {
+            return Err(PublishError::BudgetExhausted(exhausted));
+        }
+        let delay = self.delay(attempt, jitter());
+        aptos_logger::warn!(
+            operation = operation,
+            attempt = attempt,
+            delay_millis = delay.as_millis() as u64,
+            error = ?error,
+            "Failed to publish, will retry"
+        );
+        PUBLISH_RETRIES.with_label_values(&[operation]).inc();
+        serialization::in_place(|| thread::sleep(delay));
+        Ok(())
+    }
+
+    /// How long to wait after the `attempt`th attempt failed, `jitter` in `0.0..1.0` taking off
+    /// up to half of it
+    fn delay(&self, attempt: u32, jitter: f64) -> Duration {
+        let backoff = self
+            .config
+            .base_delay_millis
+            .saturating_mul(1 << (attempt - 1).min(32))
+            .min(self.config.max_delay_millis);
+        Duration::from_millis(backoff - (backoff as f64 * jitter / 2.0) as u64)
+    }
+}
+
+/// Errors that go away on their own once the cluster recovers
+pub fn is_transient(error: &KafkaError) -> bool {
+    matches!(
+        error.rdkafka_error_code(),
+        Some(
+            RDKafkaErrorCode::QueueFull
+                | RDKafkaErrorCode::MessageTimedOut
+                | RDKafkaErrorCode::OperationTimedOut
+                | RDKafkaErrorCode::RequestTimedOut
+                | RDKafkaErrorCode::BrokerTransportFailure
+                | RDKafkaErrorCode::AllBrokersDown
+                | RDKafkaErrorCode::BrokerNotAvailable
+                | RDKafkaErrorCode::LeaderNotAvailable
+                | RDKafkaErrorCode::NotLeaderForPartition
+                | RDKafkaErrorCode::NetworkException
+                | RDKafkaErrorCode::NotEnoughReplicas
+                | RDKafkaErrorCode::NotEnoughReplicasAfterAppend
+        )
+    )
+}
+
+/// Errors of the message itself, which fail however often it's sent again
+fn is_poison(error: &KafkaError) -> bool {
+    matches!(
+        error.rdkafka_error_code(),
+        Some(
+            RDKafkaErrorCode::MessageSizeTooLarge
+                | RDKafkaErrorCode::InvalidMessageSize
+                | RDKafkaErrorCode::InvalidMessage
+                | RDKafkaErrorCode::InvalidRecord
+                | RDKafkaErrorCode::BadMessage
+        )
+    )
+}
+
+/// In `0.0..1.0`, from the randomly seeded std hasher so that no RNG is needed
+pub fn jitter() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Why a message or a flush couldn't be published
+#[derive(Debug)]
+pub enum PublishError {
+    /// Failed with an error that isn't transient, or still failing after the last attempt
+    Failed {
+        operation: String,
+        attempts: u32,
+        error: KafkaError,
+    },
+    /// The batch's retry budget ran out before the attempts did
+    BudgetExhausted(RetryBudgetExhausted),
+    /// The producer's queue didn't drain to the low watermark in time, see `driver::backpressure`
+    QueueFull { depth: usize, waited: Duration },
+    /// A message couldn't be serialized, which sending the batch again doesn't change
+    Serialization {
+        model: String,
+        version: Option<u64>,
+        error: String,
+    },
+}
+
+impl PublishError {
+    /// Whether the message was rejected for what it is, rather than because Kafka is unavailable
+    pub fn is_poison(&self) -> bool {
+        match self {
+            PublishError::Failed { error, .. } => is_poison(error),
+            PublishError::Serialization { .. } => true,
+            PublishError::BudgetExhausted(_) | PublishError::QueueFull { .. } => false,
+        }
+    }
+
+    /// Whether publishing the batch again may succeed: Kafka was unavailable, rather than the
+    /// message or the config being wrong
+    pub fn is_transient(&self) -> bool {
+        match self {
+            PublishError::Failed { error, .. } => is_transient(error),
+            PublishError::BudgetExhausted(_) | PublishError::QueueFull { .. } => true,
+            PublishError::Serialization { .. } => false,
+        }
+    }
+}
+
+impl Display for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PublishError::Failed {
+                operation,
+                attempts,
+                error,
+            } => write!(
+                f,
+                "Failed to publish to {} after {} attempt(s): {}",
+                operation, attempts, error
+            ),
+            PublishError::BudgetExhausted(exhausted) => exhausted.fmt(f),
+            PublishError::QueueFull { depth, waited } => write!(
+                f,
+                "The producer's queue still held {} messages after waiting {:?} for it to drain",
+                depth, waited
+            ),
+            PublishError::Serialization {
+                model,
+                version: Some(version),
+                error,
+            } => write!(
+                f,
+                "Failed to serialize {} of version {}: {}",
+                model, version, error
+            ),
+            PublishError::Serialization { model, error, .. } => {
+                write!(f, "Failed to serialize {}: {}", model, error)
+            },
+        }
+    }
+}
+
+impl std::error::Error for PublishError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn policy(max_attempts: u32) -> PublishRetry {
+        PublishRetry::new(&PublishRetryConfig {
+            max_attempts,
+            base_delay_millis: 1,
+            max_delay_millis: 4,
+        })
+    }
+
+    /// Rejects the first `failures` records it's sent with `error`
+    struct FakeProducer {
+        failures: u32,
+        error: RDKafkaErrorCode,
+        attempts: Cell<u32>,
+        sent: Cell<Option<&'static str>>,
+    }
+
+    impl FakeProducer {
+        fn new(failures: u32, error: RDKafkaErrorCode) -> Self {
+            Self {
+                failures,
+                error,
+                attempts: Cell::new(0),
+                sent: Cell::new(None),
+            }
+        }
+
+        fn send(&self, record: &'static str) -> Result<(), (KafkaError, &'static str)> {
+            self.attempts.set(self.attempts.get() + 1);
+            if self.attempts.get() <= self.failures {
+                return Err((KafkaError::MessageProduction(self.error), record));
+            }
+            self.sent.set(Some(record));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_retries_transient_errors() {
+        let producer = FakeProducer::new(3, RDKafkaErrorCode::QueueFull);
+        policy(5)
+            .send("transactions", "txn", |record| producer.send(record))
+            .unwrap();
+        assert_eq!(producer.attempts.get(), 4);
+        assert_eq!(producer.sent.get(), Some("txn"));
+
+        // Out of attempts
+        let producer = FakeProducer::new(3, RDKafkaErrorCode::AllBrokersDown);
+        let error = policy(3)
+            .send("transactions", "txn", |record| producer.send(record))
+            .unwrap_err();
+        assert_eq!(producer.attempts.get(), 3);
+        assert!(matches!(error, PublishError::Failed { attempts: 3, .. }));
+        assert_eq!(producer.sent.get(), None);
+    }
+
+    #[test]
+    fn test_permanent_errors_fail_right_away() {
+        let producer = FakeProducer::new(3, RDKafkaErrorCode::MessageSizeTooLarge);
+        let error = policy(5)
+            .send("transactions", "txn", |record| producer.send(record))
+            .unwrap_err();
+        assert_eq!(producer.attempts.get(), 1);
+        assert!(error
+            .to_string()
+            .starts_with("Failed to publish to transactions after 1 attempt(s): "));
+
+        let mut flushes = 0;
+        let result = policy(5).run("flush", || {
+            flushes += 1;
+            Err::<(), _>(KafkaError::Flush(RDKafkaErrorCode::OperationTimedOut))
+        });
+        assert!(matches!(
+            result,
+            Err(PublishError::Failed { attempts: 5, .. })
+        ));
+        assert_eq!(flushes, 5);
+    }
+
+    #[test]
+    fn test_is_poison() {
+        let error = |code| PublishError::Failed {
+            operation: "transactions".to_string(),
+            attempts: 1,
+            error: KafkaError::MessageProduction(code),
+        };
+        assert!(error(RDKafkaErrorCode::MessageSizeTooLarge).is_poison());
+        assert!(!error(RDKafkaErrorCode::QueueFull).is_poison());
+        assert!(!error(RDKafkaErrorCode::TopicAuthorizationFailed).is_poison());
+
+        assert!(error(RDKafkaErrorCode::QueueFull).is_transient());
+        assert!(!error(RDKafkaErrorCode::MessageSizeTooLarge).is_transient());
+        assert!(!error(RDKafkaErrorCode::TopicAuthorizationFailed).is_transient());
+        let unserializable = PublishError::Serialization {
+            model: "CurrentMoveResource".to_string(),
+            version: None,
+            error: "key must be a string".to_string(),
+        };
+        assert!(unserializable.is_poison());
+        assert!(!unserializable.is_transient());
+        assert_eq!(
+            unserializable.to_string(),
+            "Failed to serialize CurrentMoveResource: key must be a string"
+        );
+    }
+
+    #[test]
+    fn test_delay() {
+        let retry = PublishRetry::new(&PublishRetryConfig {
+            max_attempts: 10,
+            base_delay_millis: 100,
+            max_delay_millis: 1_000,
+        });
+        let delays = (1..=6)
+            .map(|attempt| retry.delay(attempt, 0.0).as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [100, 200, 400, 800, 1_000, 1_000]);
+        assert_eq!(retry.delay(2, 0.5).as_millis(), 150);
+        assert_eq!(retry.delay(64, 0.0).as_millis(), 1_000);
+        assert!((0..100).map(|_| jitter()).all(|j| (0.0..1.0).contains(&j)));
+    }
+}
@@ -1,72 +1,873 @@
+pub mod proto;
+#[cfg(feature = "avro")]
+pub mod avro;
+
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use serde::Serialize;
 use poem_openapi::types::ToJSON;
 
 use {
     rdkafka::{
-        producer::{BaseRecord, DefaultProducerContext, ThreadedProducer},
+        error::{KafkaError, KafkaResult},
+        message::Header,
+        producer::{BaseRecord, DefaultProducerContext, Producer as _, ThreadedProducer},
     },
 };
 
-use crate::custom::driver::config::DriverConfig;
+use crate::custom::driver::backpressure::Backpressure;
+use crate::custom::driver::config::{
+    DriverConfig, PartitionKeyStrategy, PayloadSchemaConfig, PublishDeadLetterConfig, SerializationFormat, DEFAULT_CONFIG_PATH,
+};
+use crate::custom::driver::envelope::{Envelope, Position};
+use crate::custom::driver::memory_publisher::RecordedMessages;
+use crate::custom::driver::payload_schema::{self, Route};
+use crate::custom::driver::ordering::{self, Ordered};
 use crate::custom::driver::producer::Producer;
+use crate::custom::driver::publish_dedupe::PublishCheckpoint;
+use crate::custom::driver::publish_retry::{PublishError, PublishRetry};
+use crate::custom::driver::replay_cache::ReplayCache;
+use crate::counters::{PUBLISHER_DEAD_LETTERED, PUBLISHER_SEND_FAILURES, REPLAY_SUPPRESSED_MESSAGES};
+use crate::client::{
+    dead_letter_topic, DeadLetterMessage, AVRO_FORMAT, FORMAT_HEADER, LOGICAL_KEY_HEADER, PROTOBUF_FORMAT, MODEL_TOPIC_KEYS,
+    PRIORITY_HEADER,
+};
+use crate::custom::driver::salting::{HotKeyStats, KeySalter};
+#[cfg(feature = "avro")]
+use crate::custom::driver::schema_registry;
+use crate::custom::driver::serialization::SerializationPool;
+use crate::models::{events::EventModel, write_set_changes::WriteSetChangeModel};
+use crate::util::standardize_address;
 use aptos_api_types::Transaction;
 
+/// How long a dropped publisher waits for what it still has queued to be delivered
+const DROP_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The messages of `proto`, which encode as Avro too with the `avro` feature
+#[cfg(feature = "avro")]
+pub trait PublishedMessage: prost::Message + avro::AvroMessage {}
+#[cfg(feature = "avro")]
+impl<P: prost::Message + avro::AvroMessage> PublishedMessage for P {}
+#[cfg(not(feature = "avro"))]
+pub trait PublishedMessage: prost::Message {}
+#[cfg(not(feature = "avro"))]
+impl<P: prost::Message> PublishedMessage for P {}
+
 pub struct Publisher {
-    producer: ThreadedProducer<DefaultProducerContext>,
+    producer: Destination,
     topics: HashMap<String, String>,
     model_to_topic: HashMap<&'static str, &'static str>,
+    salter: Mutex<KeySalter>,
+    serializer: Arc<SerializationPool>,
+    payload_schemas: PayloadSchemaConfig,
+    replay_cache: Option<Arc<ReplayCache>>,
+    publish_checkpoint: Option<Arc<PublishCheckpoint>>,
+    retry: PublishRetry,
+    backpressure: Backpressure,
+    dead_letter: PublishDeadLetterConfig,
+    partition_key: PartitionKeyStrategy,
+    /// Of transactions, events and write set changes
+    format: SerializationFormat,
+    /// By model, of the models published as Avro, see `driver::schema_registry`
+    schema_ids: HashMap<&'static str, u32>,
+    envelope: Envelope,
 }
 
 
 impl Publisher {
-    pub fn new() -> Self {
-        let conf_map: DriverConfig = DriverConfig::read_from("crates/indexer/config.json");
+    pub fn new(envelope: Envelope) -> Self {
+        Self::from_config(DriverConfig::read_from(DEFAULT_CONFIG_PATH), envelope)
+    }
+
+    /// Stamps every message with `envelope`, see `driver::envelope`
+    pub fn from_config(conf_map: DriverConfig, envelope: Envelope) -> Self {
+        let producer = Destination::Kafka(Arc::new(Producer::new(conf_map.kafka.clone()).create()));
+        Self::with_destination(conf_map, envelope, producer)
+    }
+
+    /// Records every message instead of producing it, see `driver::memory_publisher`
+    pub fn in_memory(conf_map: DriverConfig, envelope: Envelope) -> (Self, RecordedMessages) {
+        let recorded = RecordedMessages::default();
+        let publisher =
+            Self::with_destination(conf_map, envelope, Destination::Memory(recorded.clone()));
+        (publisher, recorded)
+    }
+
+    fn with_destination(conf_map: DriverConfig, envelope: Envelope, producer: Destination) -> Self {
+        if let Err(err) = conf_map.payload_schemas.validate() {
+            panic!("Invalid payload_schemas config: {:#}", err);
+        }
+        if let Err(err) = conf_map.publisher_serialization.validate() {
+            panic!("Invalid publisher_serialization config: {:#}", err);
+        }
+        #[cfg(feature = "avro")]
+        let schema_ids = schema_registry::schema_ids(&conf_map)
+            .unwrap_or_else(|err| panic!("Invalid publisher_serialization config: {:#}", err));
+        #[cfg(not(feature = "avro"))]
+        let schema_ids = HashMap::new();
+        let backpressure = Backpressure::new(
+            &conf_map.publisher_backpressure,
+            &conf_map.kafka,
+            envelope.processor_name.as_deref(),
+        )
+        .unwrap_or_else(|err| panic!("Invalid publisher_backpressure config: {:#}", err));
         Self {
-            producer: Producer::new(conf_map.kafka).create(),
+            payload_schemas: conf_map.payload_schemas,
+            salter: Mutex::new(KeySalter::new(&conf_map.key_salting)),
+            serializer: SerializationPool::shared(&conf_map.publisher_serialization),
+            format: conf_map.publisher_serialization.format,
+            schema_ids,
+            producer,
             topics: conf_map.topics,
-            model_to_topic: HashMap::from([
-                ("TransactionModel", "transaction_topic"),
-                ("CoinInfo", "coin_info_topic"),
-                ("CurrentTokenData", "current_token_data_topic"),
-                ("Token", "token_topic"),
-                ("CurrentTokenOwnership", "current_token_ownership_topic"),
-                ("CurrentCollectionData", "current_collection_data_topic"),
-                ("TokenActivity", "token_activity_topic")
-            ]),
+            model_to_topic: MODEL_TOPIC_KEYS.iter().copied().collect(),
+            replay_cache: None,
+            publish_checkpoint: None,
+            retry: PublishRetry::new(&conf_map.publish_retry),
+            backpressure,
+            dead_letter: conf_map.publish_dead_letter,
+            partition_key: conf_map.partition_key.transactions,
+            envelope,
         }
     }
 
-    pub fn send<T: Serialize>(&self, model: &str, list_objects: &[T]) {
-        let n = list_objects.len();
-        let topic = self.get_topic(model);
-        for i in 0..n {
-            let serialized_obj = serde_json::to_string(&list_objects[i]).unwrap();
-            self.producer.send(BaseRecord::<Vec<u8>, _>::to(&topic).payload(serialized_obj.as_bytes())).expect("Failed to send message");
+    /// Skip the messages delivered before a restart, see `driver::replay_cache`
+    pub fn with_replay_cache(mut self, replay_cache: Arc<ReplayCache>) -> Self {
+        self.replay_cache = Some(replay_cache);
+        self
+    }
+
+    /// Skip the versions published before, see `driver::publish_dedupe`
+    pub fn with_publish_checkpoint(mut self, publish_checkpoint: Arc<PublishCheckpoint>) -> Self {
+        self.publish_checkpoint = Some(publish_checkpoint);
+        self
+    }
+
+    /// The transactions of `txns` not published yet, and the number of the others. All of them
+    /// without a publish checkpoint.
+    pub fn unpublished(&self, txns: Vec<Transaction>) -> anyhow::Result<(Vec<Transaction>, usize)> {
+        match &self.publish_checkpoint {
+            Some(publish_checkpoint) => publish_checkpoint.unpublished(txns),
+            None => Ok((txns, 0)),
         }
     }
 
-    pub fn send_transaction(&self, model: &str, list_objects: &[Transaction]) {
-        let n = list_objects.len();
-        let topic = self.get_topic(model);
-        for i in 0..n {
-            let ic = i.clone();
-            match serde_json::to_string(&list_objects[i]) {
+    /// Produces `list_objects` in publishing order, see `driver::ordering`, stopping at the first
+    /// message that can't be serialized, or sent once retried. A batch fails with the error, which
+    /// `indexer::errors` classifies.
+    pub fn try_send<T: Serialize + Sync + Ordered>(&self, model: &str, list_objects: &[T]) -> Result<(), PublishError> {
+        self.send_routed(model, list_objects, |_| None)
+    }
+
+    /// `try_send` keyed by `key`, unsalted so that a compacted topic keeps the latest message of
+    /// every key
+    pub fn try_send_keyed<T: Serialize + Sync + Ordered>(
+        &self,
+        model: &str,
+        list_objects: &[T],
+        key: impl Fn(&T) -> String,
+    ) -> Result<(), PublishError> {
+        self.send_routed(model, list_objects, |obj| Some(key(obj)))
+    }
+
+    fn send_routed<T: Serialize + Sync + Ordered>(
+        &self,
+        model: &str,
+        list_objects: &[T],
+        key: impl Fn(&T) -> Option<String>,
+    ) -> Result<(), PublishError> {
+        let routes = self.routes(model);
+        let list_objects = ordering::sort(list_objects);
+        let mut result = Ok(());
+        self.serializer.serialize_each(model, &list_objects, |obj, serialized_obj| {
+            if result.is_err() {
+                return;
+            }
+            let position = Self::position(*obj);
+            result = match serialized_obj {
                 Ok(serialized_obj) => {
-                    self.producer.send(BaseRecord::<Vec<u8>, _>::to(&topic).payload(serialized_obj.as_bytes())).expect("Failed to send message");
+                    let key = key(obj);
+                    self.produce_routes(model, &routes, key.as_deref(), position, serialized_obj)
+                }
+                Err(err) => Err(PublishError::Serialization {
+                    model: model.to_string(),
+                    version: position.transaction_version,
+                    error: err.to_string(),
+                }),
+            };
+        });
+        if result.is_err() {
+            PUBLISHER_SEND_FAILURES.with_label_values(&[model]).inc();
+        }
+        result
+    }
+
+    /// What the messages are stamped with
+    pub fn envelope(&self) -> &Envelope {
+        &self.envelope
+    }
+
+    /// Whether a topic is configured for `model`, for the models whose topic is optional
+    pub fn publishes(&self, model: &str) -> bool {
+        self.model_to_topic
+            .get(model)
+            .map_or(false, |topic_key| self.topics.contains_key(*topic_key))
+    }
+
+    /// Stops at the first transaction that can't be published once retried, see
+    /// `driver::publish_retry`, unless Kafka rejected it and it could be dead lettered. The number
+    /// of dead lettered transactions otherwise.
+    pub fn send_transaction(&self, model: &str, list_objects: &[Transaction]) -> Result<usize, PublishError> {
+        self.send_transactions_with(model, list_objects, false)
+    }
+
+    /// Early copies of transactions from the priority lane, marked with the priority header
+    pub fn send_priority_transaction(&self, model: &str, list_objects: &[Transaction]) -> Result<usize, PublishError> {
+        self.send_transactions_with(model, list_objects, true)
+    }
+
+    fn send_transactions_with(&self, model: &str, list_objects: &[Transaction], priority: bool) -> Result<usize, PublishError> {
+        let topic = self.get_topic(model);
+        let schema_version = payload_schema::current_version(model);
+        let mut result = Ok(0);
+        let list_objects = ordering::sort(list_objects);
+        let mut produce = |txn: &Transaction, payload: &[u8]| {
+            let dead_lettered = match result {
+                Ok(dead_lettered) => dead_lettered,
+                Err(_) => return,
+            };
+            let key = Self::transaction_key(self.partition_key, txn);
+            let position = Position {
+                transaction_version: txn.version(),
+                block_height: txn.transaction_info().ok().and_then(|info| info.block_height).map(|height| height.0),
+            };
+            result = match self.try_produce(topic, key.clone(), payload, priority, schema_version, position) {
+                Ok(()) => Ok(dead_lettered),
+                Err(err) if err.is_poison() => {
+                    let message = DeadLetterMessage {
+                        model: model.to_string(),
+                        topic: topic.to_string(),
+                        version: txn.version(),
+                        hash: txn.transaction_info().ok().map(|info| info.hash.to_string()),
+                        key: key.map(|(logical_key, _)| logical_key),
+                        error: err.to_string(),
+                        payload: None,
+                        payload_bytes: None,
+                    };
+                    match self.dead_letter(message, Some(payload)) {
+                        Ok(true) => Ok(dead_lettered + 1),
+                        Ok(false) => Err(err),
+                        Err(dead_letter_err) => Err(dead_letter_err),
+                    }
                 }
-                Err(err) => {
-                    eprintln!("Error serializing object, use another method to serialize");
-                    let serialized_obj = &list_objects[ic].to_json_string();
-                    let log = serialized_obj.clone();
-                    println!("New serialized obj when serializing error: {}", log);
-                    self.producer.send(BaseRecord::<Vec<u8>, _>::to(&topic).payload(serialized_obj.as_bytes())).expect("Failed to send message");
+                Err(err) => Err(err),
+            };
+        };
+        match self.format {
+            SerializationFormat::Json => self.serializer.serialize_each(model, &list_objects, |txn, serialized_obj| {
+                match serialized_obj {
+                    Ok(serialized_obj) => produce(*txn, serialized_obj),
+                    Err(_) => {
+                        eprintln!("Error serializing object, use another method to serialize");
+                        let serialized_obj = txn.to_json_string();
+                        println!("New serialized obj when serializing error: {}", serialized_obj);
+                        produce(*txn, serialized_obj.as_bytes());
+                    }
+                }
+            }),
+            SerializationFormat::Protobuf | SerializationFormat::Avro => self.encode_each(
+                model,
+                &list_objects,
+                |txn| proto::Transaction::from(*txn),
+                |txn, encoded| produce(*txn, encoded),
+            ),
+        }
+        if result.is_err() {
+            PUBLISHER_SEND_FAILURES.with_label_values(&[model]).inc();
+        }
+        result
+    }
+
+    /// Events on `event_topic`, if configured, keyed by the account of their event handle. The
+    /// number of dead lettered events.
+    pub fn send_events(&self, events: &[EventModel]) -> Result<usize, PublishError> {
+        self.send_versioned(
+            "EventModel",
+            events,
+            |event| (event.account_address.clone(), event.transaction_version as u64),
+            |event| event.transaction_block_height as u64,
+            |event| proto::Event::from(event),
+        )
+    }
+
+    /// Write set changes on `write_set_change_topic`, if configured, keyed by the address they
+    /// change. The number of dead lettered write set changes.
+    pub fn send_write_set_changes(&self, wscs: &[WriteSetChangeModel]) -> Result<usize, PublishError> {
+        self.send_versioned(
+            "WriteSetChangeModel",
+            wscs,
+            |wsc| (wsc.address.clone(), wsc.transaction_version as u64),
+            |wsc| wsc.transaction_block_height as u64,
+            |wsc| proto::WriteSetChange::from(wsc),
+        )
+    }
+
+    /// Produces `list_objects` in publishing order, keyed and salted like transactions. Stops at
+    /// the first message that can't be serialized or enqueued, what was enqueued before it is
+    /// still sent, unless it could be dead lettered. The number of dead lettered messages
+    /// otherwise. A message Kafka took but couldn't enqueue fails with the error of its topic,
+    /// one that can't be serialized with `PublishError::Serialization`.
+    fn send_versioned<T: Serialize + Sync + Ordered, P: PublishedMessage>(
+        &self,
+        model: &str,
+        list_objects: &[T],
+        key: impl Fn(&T) -> (String, u64),
+        block_height: impl Fn(&T) -> u64,
+        to_proto: impl Fn(&T) -> P + Sync,
+    ) -> Result<usize, PublishError> {
+        if !self.publishes(model) {
+            return Ok(0);
+        }
+        let topic = self.get_topic(model);
+        let schema_version = payload_schema::current_version(model);
+        let mut result = Ok(0);
+        let list_objects = ordering::sort(list_objects);
+        let mut produce = |obj: &T, serialized_obj: Result<&[u8], &serde_json::Error>| {
+            let dead_lettered = match result {
+                Ok(dead_lettered) => dead_lettered,
+                Err(_) => return,
+            };
+            let (logical_key, version) = key(obj);
+            let position = Position {
+                transaction_version: Some(version),
+                block_height: Some(block_height(obj)),
+            };
+            let (error, payload) = match serialized_obj {
+                Ok(serialized_obj) => match self.try_produce(topic, Some((logical_key.clone(), version)), serialized_obj, false, schema_version, position) {
+                    Ok(()) => return,
+                    Err(err) if err.is_poison() => (err, Some(serialized_obj)),
+                    Err(err) => {
+                        result = Err(err);
+                        return;
+                    }
+                },
+                Err(err) => (
+                    PublishError::Serialization {
+                        model: model.to_string(),
+                        version: Some(version),
+                        error: err.to_string(),
+                    },
+                    None,
+                ),
+            };
+            let message = DeadLetterMessage {
+                model: model.to_string(),
+                topic: topic.to_string(),
+                version: Some(version),
+                hash: None,
+                key: Some(logical_key),
+                error: error.to_string(),
+                payload: None,
+                payload_bytes: None,
+            };
+            result = match self.dead_letter(message, payload) {
+                Ok(true) => Ok(dead_lettered + 1),
+                Ok(false) => Err(error),
+                Err(err) => Err(err),
+            };
+        };
+        match self.format {
+            SerializationFormat::Json => {
+                self.serializer.serialize_each(model, &list_objects, |obj, serialized_obj| produce(*obj, serialized_obj))
+            }
+            SerializationFormat::Protobuf | SerializationFormat::Avro => self.encode_each(
+                model,
+                &list_objects,
+                |obj| to_proto(*obj),
+                |obj, encoded| produce(*obj, Ok(encoded)),
+            ),
+        }
+        if result.is_err() {
+            PUBLISHER_SEND_FAILURES.with_label_values(&[model]).inc();
+        }
+        result
+    }
+
+    /// Encodes the `to_proto` message of every item as protobuf, or as framed Avro, see
+    /// `publisher::avro`, and hands them to `f` in order
+    fn encode_each<I: Sync, P: PublishedMessage>(
+        &self,
+        model: &str,
+        items: &[I],
+        to_proto: impl Fn(&I) -> P + Sync,
+        f: impl FnMut(&I, &[u8]),
+    ) {
+        match self.format {
+            SerializationFormat::Protobuf => self.serializer.encode_each(model, items, to_proto, f),
+            #[cfg(feature = "avro")]
+            SerializationFormat::Avro => {
+                let schema_id = self.schema_ids[model];
+                self.serializer.write_each(
+                    model,
+                    items,
+                    |item, buffer| avro::encode(schema_id, &to_proto(item), buffer),
+                    f,
+                )
+            },
+            #[cfg(not(feature = "avro"))]
+            SerializationFormat::Avro => {
+                unreachable!("SerializationConfig::validate rejects the avro format without its feature")
+            },
+            SerializationFormat::Json => unreachable!("JSON messages are serialized"),
+        }
+    }
+
+    /// Blocks until everything produced so far is delivered and acked, for at most `timeout`, see
+    /// `FlushHandle::flush`
+    pub fn flush(&self, timeout: Duration) -> Result<(), PublishError> {
+        self.retry.run("flush", || self.producer.flush(timeout))
+    }
+
+    /// Flushes and drops the publisher. Its producer only goes once no `FlushHandle` uses it.
+    pub fn close(self, timeout: Duration) -> Result<(), PublishError> {
+        self.flush(timeout)
+    }
+
+    /// For flushing what was produced from outside the processor owning the publisher
+    pub fn flush_handle(&self) -> FlushHandle {
+        FlushHandle {
+            producer: self.producer.clone(),
+            replay_cache: self.replay_cache.clone(),
+            publish_checkpoint: self.publish_checkpoint.clone(),
+            retry: self.retry.clone(),
+        }
+    }
+
+    pub fn hot_key_stats(&self) -> HotKeyStats {
+        self.salter.lock().unwrap().stats()
+    }
+
+    /// Key of a transaction under `strategy`, with the version used for salting. Transactions
+    /// without a version (pending ones) are unkeyed.
+    pub(crate) fn transaction_key(strategy: PartitionKeyStrategy, txn: &Transaction) -> Option<(String, u64)> {
+        let version = txn.version()?;
+        let key = match (strategy, txn) {
+            (PartitionKeyStrategy::SenderAddress, Transaction::UserTransaction(user_txn)) => {
+                standardize_address(&user_txn.request.sender.inner().to_hex_literal())
+            }
+            (PartitionKeyStrategy::TransactionHash, _) => match txn.transaction_info() {
+                Ok(info) => info.hash.to_string(),
+                Err(_) => version.to_string(),
+            },
+            _ => version.to_string(),
+        };
+        Some((key, version))
+    }
+
+    fn try_produce(
+        &self,
+        topic: &str,
+        key: Option<(String, u64)>,
+        payload: &[u8],
+        priority: bool,
+        schema_version: Option<u32>,
+        position: Position,
+    ) -> Result<(), PublishError> {
+        let fingerprint = self.replay_cache.as_ref().map(|_| {
+            ReplayCache::fingerprint(
+                topic,
+                key.as_ref().map(|(logical_key, _)| logical_key.as_str()),
+                key.as_ref().map(|(_, version)| *version),
+                payload,
+            )
+        });
+        if self.was_delivered(topic, fingerprint) {
+            return Ok(());
+        }
+        let salted_key;
+        let mut headers = self.envelope.headers(schema_version, position);
+        let mut record = BaseRecord::<str, [u8]>::to(topic).payload(payload);
+        if let Some((logical_key, version)) = &key {
+            salted_key = self.salter.lock().unwrap().salt(logical_key, *version);
+            record = record.key(salted_key.as_str());
+            headers = headers.insert(Header {
+                key: LOGICAL_KEY_HEADER,
+                value: Some(logical_key.as_str()),
+            });
+        }
+        if priority {
+            headers = headers.insert(Header {
+                key: PRIORITY_HEADER,
+                value: Some("true"),
+            });
+        }
+        let format = match self.format {
+            SerializationFormat::Json => None,
+            SerializationFormat::Protobuf => Some(PROTOBUF_FORMAT),
+            SerializationFormat::Avro => Some(AVRO_FORMAT),
+        };
+        if let Some(format) = format {
+            headers = headers.insert(Header {
+                key: FORMAT_HEADER,
+                value: Some(format),
+            });
+        }
+        record = record.headers(headers);
+        self.send_record(topic, record)?;
+        self.record(fingerprint);
+        Ok(())
+    }
+
+    /// Sends `message`, with `payload` if it's JSON and small enough, to the dead letter topic of
+    /// its topic, if dead lettering is enabled. Whether it was sent.
+    fn dead_letter(&self, mut message: DeadLetterMessage, payload: Option<&[u8]>) -> Result<bool, PublishError> {
+        if !self.dead_letter.enabled {
+            return Ok(false);
+        }
+        if let Some(payload) = payload {
+            message.payload_bytes = Some(payload.len());
+            if self.format == SerializationFormat::Json && payload.len() <= self.dead_letter.max_payload_bytes {
+                message.payload = Some(String::from_utf8_lossy(payload).into_owned());
+            }
+        }
+        let topic = dead_letter_topic(&message.topic);
+        // Strings and numbers only, which always serialize
+        let serialized = serde_json::to_vec(&message).expect("Failed to serialize dead letter");
+        let mut record = BaseRecord::<str, [u8]>::to(&topic)
+            .payload(serialized.as_slice())
+            .headers(self.envelope.headers(None, Position {
+                transaction_version: message.version,
+                block_height: None,
+            }));
+        if let Some(key) = &message.key {
+            record = record.key(key.as_str());
+        }
+        self.send_record(&topic, record)?;
+        PUBLISHER_DEAD_LETTERED.with_label_values(&[&message.model]).inc();
+        aptos_logger::warn!(
+            model = message.model,
+            topic = topic,
+            version = message.version,
+            error = message.error,
+            "Dead lettered a message that couldn't be published"
+        );
+        Ok(true)
+    }
+
+    /// Produces `payload`, of the model's current version, to every route, converted to the
+    /// route's version
+    fn produce_routes(&self, model: &str, routes: &[Route], key: Option<&str>, position: Position, payload: &[u8]) -> Result<(), PublishError> {
+        for route in routes {
+            let fingerprint = self
+                .replay_cache
+                .as_ref()
+                .map(|_| ReplayCache::fingerprint(&route.topic, key, None, payload));
+            if self.was_delivered(&route.topic, fingerprint) {
+                continue;
+            }
+            let converted;
+            let payload = match route.convert {
+                Some(convert) => {
+                    converted = convert(payload).map_err(|err| PublishError::Serialization {
+                        model: model.to_string(),
+                        version: position.transaction_version,
+                        error: format!("Failed to convert to version {}: {:#}", route.version.unwrap_or_default(), err),
+                    })?;
+                    converted.as_slice()
                 }
+                None => payload,
+            };
+            let mut record = BaseRecord::<str, [u8]>::to(&route.topic).payload(payload);
+            if let Some(key) = key {
+                record = record.key(key);
             }
+            record = record.headers(self.envelope.headers(route.version, position));
+            self.send_record(&route.topic, record)?;
+            self.record(fingerprint);
+        }
+        Ok(())
+    }
+
+    /// Produces `record` once the producer's queue has room, see `driver::backpressure`, retrying
+    /// transient errors, see `driver::publish_retry`
+    fn send_record(&self, topic: &str, record: BaseRecord<str, [u8]>) -> Result<(), PublishError> {
+        self.backpressure.wait(|| self.producer.in_flight_count().max(0) as usize)?;
+        self.retry.send(topic, record, |record| self.producer.send(record))
+    }
+
+    /// Whether the message was delivered before a restart, counted if so
+    fn was_delivered(&self, topic: &str, fingerprint: Option<u64>) -> bool {
+        match (&self.replay_cache, fingerprint) {
+            (Some(replay_cache), Some(fingerprint)) if replay_cache.is_delivered(fingerprint) => {
+                REPLAY_SUPPRESSED_MESSAGES.with_label_values(&[topic]).inc();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn record(&self, fingerprint: Option<u64>) {
+        if let (Some(replay_cache), Some(fingerprint)) = (&self.replay_cache, fingerprint) {
+            replay_cache.record(fingerprint);
+        }
+    }
+
+    /// Rows are about the transaction of their version, control messages about none
+    fn position<T: Ordered>(obj: &T) -> Position {
+        Position {
+            transaction_version: obj.transaction_version().map(|version| version as u64),
+            block_height: None,
         }
     }
 
+    fn routes(&self, model: &str) -> Vec<Route> {
+        let topic_key = self.model_to_topic[model];
+        payload_schema::routes(&self.payload_schemas, model, topic_key, &self.topics[topic_key])
+    }
+
     fn get_topic(&self, model: &str) -> &str {
         return &self.topics[self.model_to_topic[model]];
     }
 }
+
+impl Drop for Publisher {
+    fn drop(&mut self) {
+        flush_if_last(&self.producer);
+    }
+}
+
+/// Where a publisher's messages go
+#[derive(Clone)]
+enum Destination {
+    Kafka(Arc<ThreadedProducer<DefaultProducerContext>>),
+    /// Recorded instead, see `driver::memory_publisher`
+    Memory(RecordedMessages),
+}
+
+impl Destination {
+    fn send<'a>(&self, record: BaseRecord<'a, str, [u8]>) -> Result<(), (KafkaError, BaseRecord<'a, str, [u8]>)> {
+        match self {
+            Destination::Kafka(producer) => producer.send(record),
+            Destination::Memory(recorded) => match recorded.next_failure() {
+                Some(code) => Err((KafkaError::MessageProduction(code), record)),
+                None => {
+                    recorded.record(&record);
+                    Ok(())
+                }
+            },
+        }
+    }
+
+    fn flush(&self, timeout: Duration) -> KafkaResult<()> {
+        match self {
+            Destination::Kafka(producer) => producer.flush(timeout),
+            Destination::Memory(_) => Ok(()),
+        }
+    }
+
+    /// Messages not acked yet
+    fn in_flight_count(&self) -> i32 {
+        match self {
+            Destination::Kafka(producer) => producer.in_flight_count(),
+            Destination::Memory(_) => 0,
+        }
+    }
+}
+
+/// librdkafka drops what's still queued with the producer, so the last user of the producer
+/// delivers it first
+fn flush_if_last(producer: &Destination) {
+    let Destination::Kafka(producer) = producer else {
+        return;
+    };
+    if Arc::strong_count(producer) > 1 || producer.in_flight_count() == 0 {
+        return;
+    }
+    if let Err(e) = producer.flush(DROP_FLUSH_TIMEOUT) {
+        aptos_logger::warn!(
+            in_flight = producer.in_flight_count(),
+            error = ?e,
+            "Dropped a publisher with undelivered messages"
+        );
+    }
+}
+
+/// Flushes a publisher's producer, e.g. before the process hands its lease over
+#[derive(Clone)]
+pub struct FlushHandle {
+    producer: Destination,
+    replay_cache: Option<Arc<ReplayCache>>,
+    publish_checkpoint: Option<Arc<PublishCheckpoint>>,
+    retry: PublishRetry,
+}
+
+impl Drop for FlushHandle {
+    fn drop(&mut self) {
+        flush_if_last(&self.producer);
+    }
+}
+
+impl FlushHandle {
+    /// Waits up to `timeout` for everything produced so far to be delivered, again after a
+    /// transient error, see `driver::publish_retry`
+    pub fn flush(&self, timeout: Duration) -> Result<(), PublishError> {
+        self.retry.run("flush", || self.producer.flush(timeout))
+    }
+
+    /// Keeps the fingerprints of what was produced up to `end_version` in the replay cache, once
+    /// it's delivered
+    pub fn commit_replay_cache(&self, end_version: u64) -> anyhow::Result<()> {
+        if let Some(replay_cache) = &self.replay_cache {
+            self.flush(replay_cache.flush_timeout())?;
+            replay_cache.commit(end_version)?;
+        }
+        Ok(())
+    }
+
+    /// Forgets the fingerprints of `version` on, so that reprocessing them publishes them again
+    pub fn forget_replayed_from(&self, version: u64) -> anyhow::Result<()> {
+        if let Some(replay_cache) = &self.replay_cache {
+            replay_cache.forget_from(version)?;
+        }
+        Ok(())
+    }
+
+    /// Records `end_version` as the last version published, once everything produced so far is
+    /// delivered
+    pub fn commit_publish_checkpoint(&self, end_version: u64) -> anyhow::Result<()> {
+        if let Some(publish_checkpoint) = &self.publish_checkpoint {
+            self.flush(publish_checkpoint.flush_timeout())?;
+            publish_checkpoint.commit(end_version)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SENDER: &str = "0x000000000000000000000000000000000000000000000000000000000000abcd";
+
+    fn user_transaction(version: u64) -> Transaction {
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": version.to_string(),
+            "block_height": "100",
+            "epoch": "1",
+            "hash": format!("0x{:064x}", version),
+            "state_change_hash": format!("0x{:064x}", 0),
+            "event_root_hash": format!("0x{:064x}", 0),
+            "gas_used": "43",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": format!("0x{:064x}", 0),
+            "sender": "0xabcd",
+            "sequence_number": "0",
+            "max_gas_amount": "1000",
+            "gas_unit_price": "1",
+            "expiration_timestamp_secs": "1649713172",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0x1::coin::transfer",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "signature": {
+                "type": "ed25519_signature",
+                "public_key": "0x14ff6646855dad4a2dab30db773cdd4b22d6f9e6813f3e50142adf4f3efcf9f8",
+                "signature": "0x70781112e78cc8b54b86805c016cef2478bccdef21b721542af0323276ab906c989172adffed5bf2f475f2ec3a5b284a0ac46a6aef0d79f0dbb6b85bfca0080a"
+            },
+            "events": [],
+            "timestamp": "1649713141723410",
+            "changes": []
+        }))
+        .unwrap()
+    }
+
+    fn block_metadata_transaction(version: u64) -> Transaction {
+        serde_json::from_value(json!({
+            "type": "block_metadata_transaction",
+            "version": version.to_string(),
+            "block_height": "100",
+            "epoch": "1",
+            "hash": format!("0x{:064x}", version),
+            "state_change_hash": format!("0x{:064x}", 0),
+            "event_root_hash": format!("0x{:064x}", 0),
+            "gas_used": "0",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": format!("0x{:064x}", 0),
+            "id": format!("0x{:064x}", 1),
+            "round": "57600",
+            "failed_proposer_indices": [],
+            "previous_block_votes_bitvec": [],
+            "proposer": "0x68f04222bd9f8846cda028ea5ba3846a806b04a47e1f1a4f0939f350d713b2eb",
+            "timestamp": "1649395495746947",
+            "events": [],
+            "changes": []
+        }))
+        .unwrap()
+    }
+
+    fn keys(strategy: PartitionKeyStrategy, batch: &[Transaction]) -> Vec<Option<(Vec<u8>, u64)>> {
+        batch
+            .iter()
+            .map(|txn| Publisher::transaction_key(strategy, txn).map(|(key, version)| (key.into_bytes(), version)))
+            .collect()
+    }
+
+    #[test]
+    fn test_transaction_key() {
+        let batch = [block_metadata_transaction(41), user_transaction(42), user_transaction(43)];
+        let key = |key: &str, version| Some((key.as_bytes().to_vec(), version));
+
+        assert_eq!(keys(PartitionKeyStrategy::SenderAddress, &batch), vec![
+            key("41", 41),
+            key(SENDER, 42),
+            key(SENDER, 43),
+        ]);
+        assert_eq!(keys(PartitionKeyStrategy::Version, &batch), vec![
+            key("41", 41),
+            key("42", 42),
+            key("43", 43),
+        ]);
+        assert_eq!(keys(PartitionKeyStrategy::TransactionHash, &batch), vec![
+            key(&format!("0x{:064x}", 41), 41),
+            key(&format!("0x{:064x}", 42), 42),
+            key(&format!("0x{:064x}", 43), 43),
+        ]);
+    }
+
+    #[test]
+    fn test_in_memory() {
+        let config: DriverConfig = serde_json::from_value(json!({
+            "kafka": {},
+            "topics": { "transaction_topic": "txns" },
+        }))
+        .unwrap();
+        let (publisher, recorded) = Publisher::in_memory(config, Envelope::new(4, "custom_default_processor"));
+        let batch = [user_transaction(43), block_metadata_transaction(41), user_transaction(42)];
+        assert_eq!(publisher.send_transaction("TransactionModel", &batch).unwrap(), 0);
+        publisher.flush(Duration::from_secs(1)).unwrap();
+
+        let messages = recorded.on_topic("txns");
+        assert_eq!(messages.len(), 3);
+        assert!(recorded.on_topic("apscan.indexer.transaction").is_empty());
+        // In version order, keyed and stamped as on Kafka
+        let versions = messages
+            .iter()
+            .map(|message| message.header(crate::client::TRANSACTION_VERSION_HEADER).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(versions, ["41", "42", "43"]);
+        assert_eq!(messages[0].key.as_deref(), Some("41"));
+        assert_eq!(messages[1].key.as_deref(), Some(SENDER));
+        assert_eq!(messages[1].header(crate::client::PROCESSOR_NAME_HEADER), Some("custom_default_processor"));
+        let printed = messages[1].printed();
+        assert_eq!(printed.payload["version"], json!("42"));
+
+        assert_eq!(recorded.take().len(), 3);
+        assert!(recorded.is_empty());
+    }
+}
@@ -0,0 +1,455 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Avro encoding of published transactions, events and write set changes, when
+//! `publisher_serialization.format` is `avro`. The records have the fields of the messages of
+//! `proto/published.proto`, with the same names, and are mapped from them, so they carry the same
+//! thing as the protobuf messages. Optional fields are unions with `null`, unsigned ones `long`s.
+//!
+//! Every payload is framed as the Confluent serializers do: a 0 byte, the big-endian id of the
+//! schema in the schema registry, then the Avro datum. The ids are the ones registered at
+//! startup, see `driver::schema_registry`.
+
+use crate::custom::driver::publisher::proto;
+use anyhow::Context;
+use apache_avro::{to_avro_datum, types::Value, Schema};
+use once_cell::sync::Lazy;
+use serde_json::json;
+
+/// Of the records, the package of `proto/published.proto`
+pub const NAMESPACE: &str = "aptos_indexer.published.v1";
+/// First byte of a framed payload
+pub const MAGIC_BYTE: u8 = 0;
+/// Of the magic byte and schema id
+pub const HEADER_LEN: usize = 5;
+/// The models published as Avro
+pub const MODELS: [&str; 3] = ["TransactionModel", "EventModel", "WriteSetChangeModel"];
+
+static TRANSACTION_SCHEMA: Lazy<Schema> = Lazy::new(|| parse(transaction_schema()));
+static EVENT_SCHEMA: Lazy<Schema> = Lazy::new(|| parse(event_schema()));
+static WRITE_SET_CHANGE_SCHEMA: Lazy<Schema> = Lazy::new(|| parse(write_set_change_schema()));
+
+/// A message of `proto` with its Avro record
+pub trait AvroMessage {
+    fn schema() -> &'static Schema;
+
+    fn to_avro(&self) -> Value;
+}
+
+/// The schema of `model` as registered, if it's published as Avro
+pub fn schema_json(model: &str) -> Option<serde_json::Value> {
+    match model {
+        "TransactionModel" => Some(transaction_schema()),
+        "EventModel" => Some(event_schema()),
+        "WriteSetChangeModel" => Some(write_set_change_schema()),
+        _ => None,
+    }
+}
+
+/// Writes `message` framed with `schema_id` to `buffer`
+pub fn encode<P: AvroMessage>(schema_id: u32, message: &P, buffer: &mut Vec<u8>) {
+    // Only fails if the record doesn't match its schema, which the tests cover for every message
+    let datum =
+        to_avro_datum(P::schema(), message.to_avro()).expect("Failed to encode Avro message");
+    buffer.reserve(HEADER_LEN + datum.len());
+    buffer.push(MAGIC_BYTE);
+    buffer.extend_from_slice(&schema_id.to_be_bytes());
+    buffer.extend_from_slice(&datum);
+}
+
+/// The schema id and Avro datum of a framed payload
+pub fn unframe(payload: &[u8]) -> anyhow::Result<(u32, &[u8])> {
+    if payload.len() < HEADER_LEN || payload[0] != MAGIC_BYTE {
+        anyhow::bail!("Not a framed Avro payload");
+    }
+    let schema_id = u32::from_be_bytes(payload[1..HEADER_LEN].try_into().unwrap());
+    Ok((schema_id, &payload[HEADER_LEN..]))
+}
+
+fn parse(schema: serde_json::Value) -> Schema {
+    Schema::parse(&schema)
+        .with_context(|| format!("Invalid Avro schema {}", schema))
+        .unwrap()
+}
+
+fn transaction_schema() -> serde_json::Value {
+    json!({
+        "type": "record",
+        "name": "Transaction",
+        "namespace": NAMESPACE,
+        "doc": "A message on transaction_topic: the transactions row of a transaction, with its events and write set changes",
+        "fields": [
+            { "name": "version", "type": "long" },
+            { "name": "block_height", "type": "long" },
+            { "name": "hash", "type": "string" },
+            { "name": "type", "type": "string" },
+            { "name": "payload", "type": ["null", "string"], "default": null, "doc": "JSON, null for transactions without a payload" },
+            { "name": "state_change_hash", "type": "string" },
+            { "name": "event_root_hash", "type": "string" },
+            { "name": "state_checkpoint_hash", "type": ["null", "string"], "default": null },
+            { "name": "gas_used", "type": "string" },
+            { "name": "success", "type": "boolean" },
+            { "name": "vm_status", "type": "string" },
+            { "name": "accumulator_root_hash", "type": "string" },
+            { "name": "num_events", "type": "long" },
+            { "name": "num_write_set_changes", "type": "long" },
+            { "name": "epoch", "type": "long" },
+            { "name": "timestamp_micros", "type": "long", "doc": "Microseconds since the unix epoch, 0 for genesis" },
+            { "name": "user", "type": ["null", user_transaction_schema()], "default": null, "doc": "Set for user transactions only" },
+            { "name": "events", "type": { "type": "array", "items": event_schema() } },
+            { "name": "write_set_changes", "type": { "type": "array", "items": write_set_change_schema() } },
+        ],
+    })
+}
+
+fn user_transaction_schema() -> serde_json::Value {
+    json!({
+        "type": "record",
+        "name": "UserTransaction",
+        "namespace": NAMESPACE,
+        "doc": "The user_transactions row of a user transaction",
+        "fields": [
+            { "name": "sender", "type": "string" },
+            { "name": "sequence_number", "type": "long" },
+            { "name": "entry_function_id_str", "type": "string" },
+            { "name": "max_gas_amount", "type": "string" },
+            { "name": "gas_unit_price", "type": "string" },
+            { "name": "expiration_timestamp_secs", "type": "long" },
+            { "name": "script_hash", "type": ["null", "string"], "default": null },
+            { "name": "entry_function_module", "type": ["null", "string"], "default": null },
+            { "name": "entry_function_name", "type": ["null", "string"], "default": null },
+            { "name": "entry_function_type_arguments", "type": { "type": "array", "items": "string" } },
+            { "name": "entry_function_arguments", "type": "string", "doc": "JSON array" },
+        ],
+    })
+}
+
+fn event_schema() -> serde_json::Value {
+    json!({
+        "type": "record",
+        "name": "Event",
+        "namespace": NAMESPACE,
+        "doc": "A message on event_topic, and an event of a transaction",
+        "fields": [
+            { "name": "sequence_number", "type": "long" },
+            { "name": "creation_number", "type": "long" },
+            { "name": "account_address", "type": "string" },
+            { "name": "transaction_version", "type": "long" },
+            { "name": "transaction_block_height", "type": "long" },
+            { "name": "type", "type": "string" },
+            { "name": "data", "type": "string", "doc": "JSON" },
+            { "name": "event_index", "type": ["null", "long"], "default": null },
+            { "name": "event_account_address", "type": ["null", "string"], "default": null },
+            { "name": "event_module", "type": ["null", "string"], "default": null },
+            { "name": "event_name", "type": ["null", "string"], "default": null },
+            { "name": "event_type_params", "type": { "type": "array", "items": "string" } },
+            { "name": "block_timestamp_micros", "type": ["null", "long"], "default": null },
+        ],
+    })
+}
+
+fn write_set_change_schema() -> serde_json::Value {
+    json!({
+        "type": "record",
+        "name": "WriteSetChange",
+        "namespace": NAMESPACE,
+        "doc": "A message on write_set_change_topic, and a write set change of a transaction",
+        "fields": [
+            { "name": "transaction_version", "type": "long" },
+            { "name": "index", "type": "long" },
+            { "name": "hash", "type": "string" },
+            { "name": "transaction_block_height", "type": "long" },
+            { "name": "type", "type": "string" },
+            { "name": "address", "type": "string" },
+        ],
+    })
+}
+
+/// The fields in the order of the schema, which the encoding follows
+fn record(fields: Vec<(&str, Value)>) -> Value {
+    Value::Record(
+        fields
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect(),
+    )
+}
+
+/// The branch of a `["null", ...]` union
+fn optional<T>(value: Option<T>, to_avro: impl FnOnce(T) -> Value) -> Value {
+    match value {
+        Some(value) => Value::Union(1, Box::new(to_avro(value))),
+        None => Value::Union(0, Box::new(Value::Null)),
+    }
+}
+
+fn strings(values: &[String]) -> Value {
+    Value::Array(values.iter().cloned().map(Value::String).collect())
+}
+
+impl AvroMessage for proto::Transaction {
+    fn schema() -> &'static Schema {
+        &TRANSACTION_SCHEMA
+    }
+
+    fn to_avro(&self) -> Value {
+        record(vec![
+            ("version", Value::Long(self.version)),
+            ("block_height", Value::Long(self.block_height)),
+            ("hash", Value::String(self.hash.clone())),
+            ("type", Value::String(self.r#type.clone())),
+            ("payload", optional(self.payload.clone(), Value::String)),
+            (
+                "state_change_hash",
+                Value::String(self.state_change_hash.clone()),
+            ),
+            (
+                "event_root_hash",
+                Value::String(self.event_root_hash.clone()),
+            ),
+            (
+                "state_checkpoint_hash",
+                optional(self.state_checkpoint_hash.clone(), Value::String),
+            ),
+            ("gas_used", Value::String(self.gas_used.clone())),
+            ("success", Value::Boolean(self.success)),
+            ("vm_status", Value::String(self.vm_status.clone())),
+            (
+                "accumulator_root_hash",
+                Value::String(self.accumulator_root_hash.clone()),
+            ),
+            ("num_events", Value::Long(self.num_events)),
+            (
+                "num_write_set_changes",
+                Value::Long(self.num_write_set_changes),
+            ),
+            ("epoch", Value::Long(self.epoch)),
+            (
+                "timestamp_micros",
+                Value::Long(self.timestamp_micros as i64),
+            ),
+            ("user", optional(self.user.as_ref(), |user| user.to_avro())),
+            (
+                "events",
+                Value::Array(self.events.iter().map(AvroMessage::to_avro).collect()),
+            ),
+            (
+                "write_set_changes",
+                Value::Array(
+                    self.write_set_changes
+                        .iter()
+                        .map(AvroMessage::to_avro)
+                        .collect(),
+                ),
+            ),
+        ])
+    }
+}
+
+impl proto::UserTransaction {
+    fn to_avro(&self) -> Value {
+        record(vec![
+            ("sender", Value::String(self.sender.clone())),
+            ("sequence_number", Value::Long(self.sequence_number)),
+            (
+                "entry_function_id_str",
+                Value::String(self.entry_function_id_str.clone()),
+            ),
+            ("max_gas_amount", Value::String(self.max_gas_amount.clone())),
+            ("gas_unit_price", Value::String(self.gas_unit_price.clone())),
+            (
+                "expiration_timestamp_secs",
+                Value::Long(self.expiration_timestamp_secs),
+            ),
+            (
+                "script_hash",
+                optional(self.script_hash.clone(), Value::String),
+            ),
+            (
+                "entry_function_module",
+                optional(self.entry_function_module.clone(), Value::String),
+            ),
+            (
+                "entry_function_name",
+                optional(self.entry_function_name.clone(), Value::String),
+            ),
+            (
+                "entry_function_type_arguments",
+                strings(&self.entry_function_type_arguments),
+            ),
+            (
+                "entry_function_arguments",
+                Value::String(self.entry_function_arguments.clone()),
+            ),
+        ])
+    }
+}
+
+impl AvroMessage for proto::Event {
+    fn schema() -> &'static Schema {
+        &EVENT_SCHEMA
+    }
+
+    fn to_avro(&self) -> Value {
+        record(vec![
+            ("sequence_number", Value::Long(self.sequence_number)),
+            ("creation_number", Value::Long(self.creation_number)),
+            (
+                "account_address",
+                Value::String(self.account_address.clone()),
+            ),
+            ("transaction_version", Value::Long(self.transaction_version)),
+            (
+                "transaction_block_height",
+                Value::Long(self.transaction_block_height),
+            ),
+            ("type", Value::String(self.r#type.clone())),
+            ("data", Value::String(self.data.clone())),
+            ("event_index", optional(self.event_index, Value::Long)),
+            (
+                "event_account_address",
+                optional(self.event_account_address.clone(), Value::String),
+            ),
+            (
+                "event_module",
+                optional(self.event_module.clone(), Value::String),
+            ),
+            (
+                "event_name",
+                optional(self.event_name.clone(), Value::String),
+            ),
+            ("event_type_params", strings(&self.event_type_params)),
+            (
+                "block_timestamp_micros",
+                optional(self.block_timestamp_micros, Value::Long),
+            ),
+        ])
+    }
+}
+
+impl AvroMessage for proto::WriteSetChange {
+    fn schema() -> &'static Schema {
+        &WRITE_SET_CHANGE_SCHEMA
+    }
+
+    fn to_avro(&self) -> Value {
+        record(vec![
+            ("transaction_version", Value::Long(self.transaction_version)),
+            ("index", Value::Long(self.index)),
+            ("hash", Value::String(self.hash.clone())),
+            (
+                "transaction_block_height",
+                Value::Long(self.transaction_block_height),
+            ),
+            ("type", Value::String(self.r#type.clone())),
+            ("address", Value::String(self.address.clone())),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use apache_avro::from_avro_datum;
+
+    fn decode<P: AvroMessage>(payload: &[u8]) -> (u32, Value) {
+        let (schema_id, mut datum) = unframe(payload).unwrap();
+        let value = from_avro_datum(P::schema(), &mut datum, None).unwrap();
+        assert!(datum.is_empty());
+        (schema_id, value)
+    }
+
+    fn field<'a>(value: &'a Value, name: &str) -> &'a Value {
+        match value {
+            Value::Record(fields) => &fields.iter().find(|(field, _)| field == name).unwrap().1,
+            other => panic!("Not a record {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_encode_frames_the_datum() {
+        let event = proto::Event {
+            sequence_number: 3,
+            account_address: "0x1".to_string(),
+            transaction_version: 42,
+            r#type: "0x1::coin::DepositEvent".to_string(),
+            data: r#"{"amount":"100"}"#.to_string(),
+            event_index: Some(0),
+            event_type_params: vec!["0x1::aptos_coin::AptosCoin".to_string()],
+            ..proto::Event::default()
+        };
+        let mut buffer = vec![];
+        encode(0x0102_0304, &event, &mut buffer);
+        assert_eq!(buffer[..HEADER_LEN], [0, 1, 2, 3, 4]);
+
+        let (schema_id, value) = decode::<proto::Event>(&buffer);
+        assert_eq!(schema_id, 0x0102_0304);
+        assert_eq!(value, event.to_avro());
+        assert_eq!(field(&value, "transaction_version"), &Value::Long(42));
+        assert_eq!(
+            field(&value, "event_name"),
+            &Value::Union(0, Box::new(Value::Null))
+        );
+
+        assert!(unframe(&buffer[..4]).is_err());
+        assert!(unframe(&[1, 0, 0, 0, 1]).is_err());
+    }
+
+    #[test]
+    fn test_transaction_with_user_events_and_changes() {
+        let transaction = proto::Transaction {
+            version: 42,
+            hash: "0x2a".to_string(),
+            r#type: "user_transaction".to_string(),
+            payload: Some("{}".to_string()),
+            gas_used: "43".to_string(),
+            success: true,
+            num_events: 1,
+            num_write_set_changes: 1,
+            timestamp_micros: 1_649_713_141_723_410,
+            user: Some(proto::UserTransaction {
+                sender: "0xabcd".to_string(),
+                entry_function_module: Some("coin".to_string()),
+                entry_function_type_arguments: vec!["0x1::aptos_coin::AptosCoin".to_string()],
+                entry_function_arguments: "[]".to_string(),
+                ..proto::UserTransaction::default()
+            }),
+            events: vec![proto::Event {
+                transaction_version: 42,
+                ..proto::Event::default()
+            }],
+            write_set_changes: vec![proto::WriteSetChange {
+                transaction_version: 42,
+                r#type: "write_resource".to_string(),
+                ..proto::WriteSetChange::default()
+            }],
+            ..proto::Transaction::default()
+        };
+        let mut buffer = vec![];
+        encode(7, &transaction, &mut buffer);
+        let (schema_id, value) = decode::<proto::Transaction>(&buffer);
+        assert_eq!(schema_id, 7);
+        assert_eq!(value, transaction.to_avro());
+        assert_eq!(
+            field(&value, "timestamp_micros"),
+            &Value::Long(1_649_713_141_723_410)
+        );
+
+        let write_set_change = &transaction.write_set_changes[0];
+        let mut buffer = vec![];
+        encode(8, write_set_change, &mut buffer);
+        assert_eq!(
+            decode::<proto::WriteSetChange>(&buffer),
+            (8, write_set_change.to_avro())
+        );
+    }
+
+    #[test]
+    fn test_schemas() {
+        for model in MODELS {
+            let schema = Schema::parse(&schema_json(model).unwrap()).unwrap();
+            assert!(schema.canonical_form().contains(NAMESPACE));
+        }
+        assert!(schema_json("CurrentAnsLookup").is_none());
+    }
+}
@@ -0,0 +1,280 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Protobuf encoding of published transactions, events and write set changes, when
+//! `publisher_serialization.format` is `protobuf`. The messages are generated from
+//! `proto/published.proto` by `build.rs`. They're mapped from the same models the JSON messages
+//! are serialized from, so the processors publish the same thing whatever the format.
+
+use crate::models::{
+    events::EventModel,
+    transactions::{TransactionDetail, TransactionModel},
+    user_transactions::UserTransactionModel,
+    write_set_changes::WriteSetChangeModel,
+};
+use aptos_api_types::Transaction as APITransaction;
+
+include!(concat!(env!("OUT_DIR"), "/aptos_indexer.published.v1.rs"));
+
+impl From<&APITransaction> for Transaction {
+    fn from(txn: &APITransaction) -> Self {
+        let (model, detail, events, wscs, _) = TransactionModel::from_transaction(txn);
+        Self {
+            timestamp_micros: txn.timestamp(),
+            user: match detail {
+                Some(TransactionDetail::User(user_txn, _)) => Some((&user_txn).into()),
+                _ => None,
+            },
+            events: events.iter().map(Event::from).collect(),
+            write_set_changes: wscs.iter().map(WriteSetChange::from).collect(),
+            ..Transaction::from(&model)
+        }
+    }
+}
+
+/// The `transactions` row only, without the user transaction, events and write set changes
+impl From<&TransactionModel> for Transaction {
+    fn from(model: &TransactionModel) -> Self {
+        Self {
+            version: model.version,
+            block_height: model.block_height,
+            hash: model.hash.clone(),
+            r#type: model.type_.clone(),
+            payload: model.payload.as_ref().map(|payload| payload.to_string()),
+            state_change_hash: model.state_change_hash.clone(),
+            event_root_hash: model.event_root_hash.clone(),
+            state_checkpoint_hash: model.state_checkpoint_hash.clone(),
+            gas_used: model.gas_used.to_string(),
+            success: model.success,
+            vm_status: model.vm_status.clone(),
+            accumulator_root_hash: model.accumulator_root_hash.clone(),
+            num_events: model.num_events,
+            num_write_set_changes: model.num_write_set_changes,
+            epoch: model.epoch,
+            timestamp_micros: 0,
+            user: None,
+            events: vec![],
+            write_set_changes: vec![],
+        }
+    }
+}
+
+impl From<&UserTransactionModel> for UserTransaction {
+    fn from(model: &UserTransactionModel) -> Self {
+        Self {
+            sender: model.sender.clone(),
+            sequence_number: model.sequence_number,
+            entry_function_id_str: model.entry_function_id_str.clone(),
+            max_gas_amount: model.max_gas_amount.to_string(),
+            gas_unit_price: model.gas_unit_price.to_string(),
+            expiration_timestamp_secs: model.expiration_timestamp_secs.timestamp(),
+            script_hash: model.script_hash.clone(),
+            entry_function_module: model.entry_function_module.clone(),
+            entry_function_name: model.entry_function_name.clone(),
+            entry_function_type_arguments: string_array(&model.entry_function_type_arguments),
+            entry_function_arguments: model.entry_function_arguments.to_string(),
+        }
+    }
+}
+
+impl From<&EventModel> for Event {
+    fn from(model: &EventModel) -> Self {
+        Self {
+            sequence_number: model.sequence_number,
+            creation_number: model.creation_number,
+            account_address: model.account_address.clone(),
+            transaction_version: model.transaction_version,
+            transaction_block_height: model.transaction_block_height,
+            r#type: model.type_.clone(),
+            data: model.data.to_string(),
+            event_index: model.event_index,
+            event_account_address: model.event_account_address.clone(),
+            event_module: model.event_module.clone(),
+            event_name: model.event_name.clone(),
+            event_type_params: model
+                .event_type_params
+                .as_ref()
+                .map(string_array)
+                .unwrap_or_default(),
+            block_timestamp_micros: model
+                .block_timestamp
+                .map(|timestamp| timestamp.timestamp_micros()),
+        }
+    }
+}
+
+/// The strings of a JSON array, empty if it isn't one
+fn string_array(value: &serde_json::Value) -> Vec<String> {
+    value
+        .as_array()
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|item| item.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+impl From<&WriteSetChangeModel> for WriteSetChange {
+    fn from(model: &WriteSetChangeModel) -> Self {
+        Self {
+            transaction_version: model.transaction_version,
+            index: model.index,
+            hash: model.hash.clone(),
+            transaction_block_height: model.transaction_block_height,
+            r#type: model.type_.clone(),
+            address: model.address.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom::driver::{config::SerializationConfig, serialization::SerializationPool};
+    use prost::Message;
+    use serde_json::json;
+
+    fn user_transaction(version: u64) -> APITransaction {
+        let account = format!("0x{:064x}", 0xabcd);
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": version.to_string(),
+            "block_height": "100",
+            "epoch": "1",
+            "hash": format!("0x{:064x}", version),
+            "state_change_hash": format!("0x{:064x}", 0),
+            "event_root_hash": format!("0x{:064x}", 0),
+            "gas_used": "43",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": format!("0x{:064x}", 0),
+            "sender": account,
+            "sequence_number": "7",
+            "max_gas_amount": "1000",
+            "gas_unit_price": "100",
+            "expiration_timestamp_secs": "1649713172",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0x1::coin::transfer",
+                "type_arguments": ["0x1::aptos_coin::AptosCoin"],
+                "arguments": [account, "10"]
+            },
+            "signature": {
+                "type": "ed25519_signature",
+                "public_key": "0x14ff6646855dad4a2dab30db773cdd4b22d6f9e6813f3e50142adf4f3efcf9f8",
+                "signature": "0x70781112e78cc8b54b86805c016cef2478bccdef21b721542af0323276ab906c989172adffed5bf2f475f2ec3a5b284a0ac46a6aef0d79f0dbb6b85bfca0080a"
+            },
+            "events": [{
+                "guid": { "account_address": account, "creation_number": "2" },
+                "sequence_number": "3",
+                "type": "0x1::coin::DepositEvent",
+                "data": { "amount": "10" }
+            }],
+            "timestamp": "1649713141723410",
+            "changes": [{
+                "type": "write_resource",
+                "address": account,
+                "state_key_hash": format!("0x{:064x}", 1),
+                "data": {
+                    "type": "0x1::coin::CoinStore<0x1::aptos_coin::AptosCoin>",
+                    "data": { "coin": { "value": "10" } }
+                }
+            }]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_transaction_round_trip() {
+        let api_txn = user_transaction(42);
+        let txn = Transaction::from(&api_txn);
+        let decoded = Transaction::decode(txn.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded, txn);
+
+        assert_eq!(decoded.version, 42);
+        assert_eq!(decoded.hash, format!("0x{:064x}", 42));
+        assert_eq!(decoded.r#type, "user_transaction");
+        assert_eq!(decoded.gas_used, "43");
+        assert_eq!(decoded.timestamp_micros, 1_649_713_141_723_410);
+        let user = decoded.user.unwrap();
+        assert_eq!(user.sender, format!("0x{:064x}", 0xabcd));
+        assert_eq!(user.sequence_number, 7);
+        assert_eq!(user.entry_function_id_str, "0x1::coin::transfer");
+        assert_eq!(user.entry_function_module.as_deref(), Some("0x1::coin"));
+        assert_eq!(user.entry_function_name.as_deref(), Some("transfer"));
+        assert_eq!(user.entry_function_type_arguments, [
+            "0x1::aptos_coin::AptosCoin"
+        ]);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&user.entry_function_arguments).unwrap(),
+            json!([format!("0x{:064x}", 0xabcd), "10"])
+        );
+        assert_eq!(user.gas_unit_price, "100");
+
+        assert_eq!(decoded.events.len(), 1);
+        let event = &decoded.events[0];
+        assert_eq!(event.transaction_version, 42);
+        assert_eq!((event.creation_number, event.sequence_number), (2, 3));
+        assert_eq!(event.r#type, "0x1::coin::DepositEvent");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&event.data).unwrap(),
+            json!({ "amount": "10" })
+        );
+        assert_eq!(event.event_module.as_deref(), Some("coin"));
+        assert_eq!(event.block_timestamp_micros, Some(1_649_713_141_723_410));
+
+        assert_eq!(decoded.write_set_changes.len(), 1);
+        let wsc = &decoded.write_set_changes[0];
+        assert_eq!((wsc.transaction_version, wsc.index), (42, 0));
+        assert_eq!(wsc.r#type, "write_resource");
+        assert_eq!(wsc.address, format!("0x{:064x}", 0xabcd));
+    }
+
+    #[test]
+    fn test_encode_each_round_trip() {
+        let api_txns = [user_transaction(1), user_transaction(2)];
+        let (_, _, events, wscs, _) = TransactionModel::from_transactions(&api_txns);
+        let pool = SerializationPool::new(&SerializationConfig::default());
+
+        let mut decoded = vec![];
+        pool.encode_each(
+            "TransactionModel",
+            &api_txns,
+            |txn| Transaction::from(txn),
+            |_, payload| {
+                decoded.push(Transaction::decode(payload).unwrap());
+            },
+        );
+        assert_eq!(decoded.iter().map(|txn| txn.version).collect::<Vec<_>>(), [
+            1, 2
+        ]);
+
+        let mut decoded = vec![];
+        pool.encode_each(
+            "EventModel",
+            &events,
+            |event| Event::from(event),
+            |event, payload| {
+                decoded.push((event.transaction_version, Event::decode(payload).unwrap()));
+            },
+        );
+        assert_eq!(decoded.len(), 2);
+        assert!(decoded
+            .iter()
+            .all(|(version, event)| event.transaction_version == *version));
+
+        let mut decoded = vec![];
+        pool.encode_each(
+            "WriteSetChangeModel",
+            &wscs,
+            |wsc| WriteSetChange::from(wsc),
+            |wsc, payload| {
+                decoded.push(WriteSetChange::decode(payload).unwrap());
+                assert_eq!(decoded.last().unwrap(), &WriteSetChange::from(wsc));
+            },
+        );
+        assert_eq!(decoded.len(), 2);
+    }
+}
@@ -0,0 +1,279 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Continuous consistency hashes. Versions are cut into ranges of `range_size`; once a processor
+//! has committed past the end of a range, the configured tables of that processor are hashed for
+//! the range with `queries::hash_range` off the processing path, one range at a time whatever
+//! the number of processors. Each table's hash is stored in `range_hashes`, so two deployments
+//! can be compared with a join, and the last `keep_recent` manifests are kept in memory for
+//! `status`.
+//!
+//! Tables without an immutable version column, the `current_*` ones, aren't hashed: a row's
+//! `last_transaction_version` moves past its range when the row is updated, so the hash of a
+//! range would depend on when it was taken. They're logged once and skipped.
+
+use crate::{
+    counters::RANGE_HASHES,
+    custom::driver::config::RangeHashConfig,
+    database::{execute_with_better_error, PgDbPool},
+    models::range_hashes::RangeHash,
+    queries::hash_range::{hash_range, version_column, RangeManifest},
+    schema::range_hashes,
+};
+use aptos_logger::{error, info};
+use diesel::{pg::upsert::excluded, ExpressionMethods};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+static HASHER: OnceCell<RangeHasher> = OnceCell::new();
+
+/// A completed range of a processor, meant for status reporting
+#[derive(Clone, Debug, Serialize)]
+pub struct RangeHashStatus {
+    pub processor: String,
+    pub manifest: RangeManifest,
+}
+
+struct RangeHasher {
+    config: RangeHashConfig,
+    connection_pool: PgDbPool,
+    recent: Mutex<VecDeque<RangeHashStatus>>,
+    /// Whether each table has an immutable version column, once known
+    hashable: Mutex<HashMap<String, bool>>,
+    /// Ranges waiting for the worker, which hashes them in order
+    ranges: UnboundedSender<CompletedRange>,
+}
+
+struct CompletedRange {
+    processor: String,
+    tables: Vec<String>,
+    start_version: i64,
+    end_version: i64,
+}
+
+/// Turns the hashing on. Only the first call in a process has an effect, so every processor
+/// runtime can call it. Must be called within a tokio runtime, which the worker is spawned on.
+pub fn init(config: &RangeHashConfig, connection_pool: PgDbPool) {
+    if !config.enabled || HASHER.get().is_some() {
+        return;
+    }
+    info!(
+        range_size = config.range_size,
+        tables = format!("{:?}", config.tables),
+        "Hashing completed version ranges"
+    );
+    let (ranges, receiver) = mpsc::unbounded_channel();
+    if HASHER
+        .set(RangeHasher {
+            config: config.clone(),
+            connection_pool,
+            recent: Mutex::new(VecDeque::new()),
+            hashable: Mutex::new(HashMap::new()),
+            ranges,
+        })
+        .is_ok()
+    {
+        tokio::spawn(run_worker(receiver));
+    }
+}
+
+/// Hashes the ranges sent to it one after the other, so that hashing holds a single connection
+/// however many ranges complete at once
+async fn run_worker(mut receiver: UnboundedReceiver<CompletedRange>) {
+    let Some(hasher) = HASHER.get() else {
+        return;
+    };
+    while let Some(range) = receiver.recv().await {
+        let tables = range.tables.clone();
+        let hashed = tokio::task::spawn_blocking(move || {
+            let tables = hasher.hashable_tables(&range.tables);
+            if !tables.is_empty() {
+                hasher.hash(
+                    &range.processor,
+                    &tables,
+                    range.start_version,
+                    range.end_version,
+                );
+            }
+        })
+        .await;
+        if let Err(err) = hashed {
+            error!(tables = format!("{:?}", tables), error = ?err, "Range hashing panicked");
+        }
+    }
+}
+
+/// Called once `processor` committed `start_version..=end_version` and everything before it.
+/// Hashes the ranges ending in there in the background.
+pub fn on_progress(processor: &str, start_version: u64, end_version: u64) {
+    let Some(hasher) = HASHER.get() else {
+        return;
+    };
+    let Some(tables) = hasher.config.tables.get(processor) else {
+        return;
+    };
+    if tables.is_empty() || start_version > end_version {
+        return;
+    }
+    for (range_start, range_end) in
+        completed_ranges(hasher.config.range_size, start_version, end_version)
+    {
+        let _ = hasher.ranges.send(CompletedRange {
+            processor: processor.to_string(),
+            tables: tables.clone(),
+            start_version: range_start as i64,
+            end_version: range_end as i64,
+        });
+    }
+}
+
+/// The last `keep_recent` ranges hashed, oldest first
+pub fn status() -> Vec<RangeHashStatus> {
+    HASHER
+        .get()
+        .map(|hasher| hasher.recent.lock().unwrap().iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Ranges of `range_size` versions whose last version is in `start_version..=end_version`
+fn completed_ranges(range_size: u64, start_version: u64, end_version: u64) -> Vec<(u64, u64)> {
+    let range_size = range_size.max(1);
+    let mut ranges = vec![];
+    let mut range_start = start_version / range_size * range_size;
+    while range_start + range_size - 1 <= end_version {
+        ranges.push((range_start, range_start + range_size - 1));
+        range_start += range_size;
+    }
+    ranges
+}
+
+impl RangeHasher {
+    /// The tables of `tables` with an immutable version column. Each table is looked up once,
+    /// or again for the next range if its lookup failed.
+    fn hashable_tables(&self, tables: &[String]) -> Vec<String> {
+        let mut hashable = self.hashable.lock().unwrap();
+        for table in tables {
+            if hashable.contains_key(table) {
+                continue;
+            }
+            let column = self
+                .connection_pool
+                .get()
+                .map_err(anyhow::Error::from)
+                .and_then(|mut conn| version_column(&mut conn, table));
+            match column {
+                Ok(Some(_)) => {
+                    hashable.insert(table.clone(), true);
+                },
+                Ok(None) => {
+                    error!(
+                        table = table,
+                        "Not hashing a table without an immutable version column"
+                    );
+                    hashable.insert(table.clone(), false);
+                },
+                Err(err) => {
+                    error!(table = table, error = ?err, "Failed to look up a table to hash");
+                },
+            }
+        }
+        tables
+            .iter()
+            .filter(|table| hashable.get(*table) == Some(&true))
+            .cloned()
+            .collect()
+    }
+
+    fn hash(&self, processor: &str, tables: &[String], start_version: i64, end_version: i64) {
+        let result = self
+            .connection_pool
+            .get()
+            .map_err(anyhow::Error::from)
+            .and_then(|mut conn| {
+                let manifest = hash_range(&mut conn, tables, start_version, end_version)?;
+                let rows = manifest
+                    .tables
+                    .iter()
+                    .map(|(table, hash)| RangeHash {
+                        processor: processor.to_string(),
+                        table_name: table.clone(),
+                        start_version,
+                        end_version,
+                        row_count: hash.rows as i64,
+                        hash: hash.hash.clone(),
+                    })
+                    .collect::<Vec<_>>();
+                // Rehashing a range, e.g. after a backfill, replaces its hashes
+                execute_with_better_error(
+                    &mut conn,
+                    diesel::insert_into(range_hashes::table)
+                        .values(&rows)
+                        .on_conflict((
+                            range_hashes::processor,
+                            range_hashes::table_name,
+                            range_hashes::start_version,
+                            range_hashes::end_version,
+                        ))
+                        .do_update()
+                        .set((
+                            range_hashes::row_count.eq(excluded(range_hashes::row_count)),
+                            range_hashes::hash.eq(excluded(range_hashes::hash)),
+                            range_hashes::computed_at.eq(excluded(range_hashes::computed_at)),
+                        )),
+                    None,
+                )?;
+                Ok(manifest)
+            });
+        match result {
+            Ok(manifest) => {
+                RANGE_HASHES.with_label_values(&[processor, "ok"]).inc();
+                info!(
+                    processor_name = processor,
+                    start_version = start_version,
+                    end_version = end_version,
+                    tables = serde_json::to_string(&manifest.tables).unwrap_or_default(),
+                    "Hashed version range"
+                );
+                let mut recent = self.recent.lock().unwrap();
+                recent.push_back(RangeHashStatus {
+                    processor: processor.to_string(),
+                    manifest,
+                });
+                while recent.len() > self.config.keep_recent {
+                    recent.pop_front();
+                }
+            },
+            Err(err) => {
+                RANGE_HASHES.with_label_values(&[processor, "error"]).inc();
+                error!(
+                    processor_name = processor,
+                    start_version = start_version,
+                    end_version = end_version,
+                    error = ?err,
+                    "Failed to hash version range"
+                );
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completed_ranges() {
+        assert_eq!(completed_ranges(100, 0, 98), vec![]);
+        assert_eq!(completed_ranges(100, 0, 99), vec![(0, 99)]);
+        assert_eq!(
+            completed_ranges(100, 150, 420),
+            vec![(100, 199), (200, 299), (300, 399)]
+        );
+        assert_eq!(completed_ranges(100, 400, 420), vec![]);
+    }
+}
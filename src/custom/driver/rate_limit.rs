@@ -0,0 +1,133 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Caps on the versions the processors of a process go through per second, so that a backfill or
+//! a processor catching up doesn't flood Kafka and Postgres for the processors following the
+//! ledger. After every round, the versions it processed are charged to `max_versions_per_second`,
+//! shared by every processor of the process, and a backfill's rounds to
+//! `backfill_max_versions_per_second` as well. The processor then waits until the rounds charged
+//! so far fit in their caps. A round slower than its cap allows waits for nothing, and the time
+//! it took isn't saved up for later rounds beyond its own versions. Waits are counted in
+//! `indexer_rate_limited_millis_count` by processor.
+
+use crate::{counters::RATE_LIMITED_MILLIS, custom::driver::config::RateLimitConfig};
+use once_cell::sync::Lazy;
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+static LIMITS: Lazy<Mutex<Limits>> = Lazy::new(|| Mutex::new(Limits::default()));
+
+#[derive(Default)]
+struct Limits {
+    all: Bucket,
+    backfill: Bucket,
+}
+
+#[derive(Default)]
+struct Bucket {
+    /// None if 0
+    max_versions_per_second: u64,
+    /// When the versions charged so far fit in the cap, none until a round is charged
+    next: Option<Instant>,
+}
+
+/// Applies `rate_limit` of the config to every later round, keeping what was charged so far
+pub fn init(config: &RateLimitConfig) {
+    let mut limits = LIMITS.lock().unwrap();
+    limits.all.max_versions_per_second = config.max_versions_per_second;
+    limits.backfill.max_versions_per_second = config.backfill_max_versions_per_second;
+}
+
+/// Waits after a round of `processor_name` that processed `versions` versions, long enough to
+/// stay within the caps
+pub async fn pace(processor_name: &str, versions: u64, backfilling: bool) {
+    let wait = {
+        let mut limits = LIMITS.lock().unwrap();
+        let now = Instant::now();
+        let all = limits.all.charge(versions, now);
+        if backfilling {
+            all.max(limits.backfill.charge(versions, now))
+        } else {
+            all
+        }
+    };
+    if wait.is_zero() {
+        return;
+    }
+    RATE_LIMITED_MILLIS
+        .with_label_values(&[processor_name])
+        .inc_by(wait.as_millis() as u64);
+    tokio::time::sleep(wait).await;
+}
+
+impl Bucket {
+    /// Charges a round of `versions` that ended at `now`, and how long to wait before the next
+    fn charge(&mut self, versions: u64, now: Instant) -> Duration {
+        if self.max_versions_per_second == 0 || versions == 0 {
+            return Duration::ZERO;
+        }
+        let cost = Duration::from_secs_f64(versions as f64 / self.max_versions_per_second as f64);
+        // The round's own time counts towards its versions
+        let round_start = now.checked_sub(cost).unwrap_or(now);
+        let start = self.next.map_or(round_start, |next| next.max(round_start));
+        let next = start + cost;
+        self.next = Some(next);
+        next.saturating_duration_since(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(max_versions_per_second: u64) -> Bucket {
+        Bucket {
+            max_versions_per_second,
+            next: None,
+        }
+    }
+
+    #[test]
+    fn test_charge() {
+        let start = Instant::now();
+        let mut bucket = bucket(1_000);
+        // Rounds of 500 versions finishing at once wait 0.5s each, one after the other
+        assert_eq!(bucket.charge(500, start), Duration::ZERO);
+        assert_eq!(bucket.charge(500, start), Duration::from_millis(500));
+        assert_eq!(bucket.charge(500, start), Duration::from_secs(1));
+
+        // Rounds slower than the cap don't wait, and don't save up
+        let later = start + Duration::from_secs(10);
+        assert_eq!(bucket.charge(1_000, later), Duration::ZERO);
+        assert_eq!(
+            bucket.charge(1_000, later + Duration::from_secs(2)),
+            Duration::ZERO
+        );
+        assert_eq!(
+            bucket.charge(1_000, later + Duration::from_secs(2)),
+            Duration::from_secs(1)
+        );
+
+        // At most the cap on average, the first round having taken the time of its versions
+        let mut bucket = self::bucket(100);
+        let mut now = start;
+        for _ in 0..50 {
+            now += bucket.charge(20, now) + Duration::from_millis(10);
+        }
+        let rate = (1_000 - 20) as f64 / now.duration_since(start).as_secs_f64();
+        assert!(rate <= 100.0, "{} versions/s", rate);
+        assert!(rate > 99.0, "{} versions/s", rate);
+    }
+
+    #[test]
+    fn test_no_cap() {
+        let now = Instant::now();
+        let mut bucket = bucket(0);
+        for _ in 0..10 {
+            assert_eq!(bucket.charge(1_000_000, now), Duration::ZERO);
+        }
+        assert_eq!(self::bucket(10).charge(0, now), Duration::ZERO);
+    }
+}
@@ -0,0 +1,417 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Redaction of sensitive values in payload arguments and event data. The tailer redacts every
+//! fetched batch before anything else sees it, and the priority lane the batches of its own
+//! fetcher, so the processors, their inserts and everything published only ever get the redacted
+//! transactions. With `fetcher_recording` recording, the recording fetcher redacts the tailer's
+//! batches instead, before they're written to the recording.
+//!
+//! A rule is either a pattern, a regex whose matches in any string of the arguments or event
+//! data are replaced, however deep in vectors and structs, or a target, an argument of an entry
+//! function whose strings are replaced whole. A replaced value becomes
+//! `[REDACTED:<first 16 hex chars of its sha256>]`, so the same secret can still be correlated
+//! across transactions. Replacements are counted in `indexer_redactions_count` by rule name.
+//! Object keys and non-string values are left as they are.
+//!
+//! Rules come from the config and from `rules_file`, which is re-read every
+//! `reload_interval_secs`. A rules file that can't be read or has an invalid regex keeps the
+//! previous rules, except on startup, where it fails.
+
+use crate::{
+    counters::REDACTIONS,
+    custom::driver::config::RedactionConfig,
+    util::{hash_str, standardize_address},
+};
+use anyhow::Context;
+use aptos_api_types::{
+    EntryFunctionPayload, MultisigTransactionPayload, Transaction, TransactionPayload,
+};
+use aptos_logger::{info, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+pub const REDACTION_MARKER_PREFIX: &str = "[REDACTED:";
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct RedactionRules {
+    pub patterns: Vec<RedactionPattern>,
+    pub targets: Vec<RedactionTarget>,
+}
+
+/// Matches of `regex` in any string of the arguments or event data
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RedactionPattern {
+    pub name: String,
+    pub regex: String,
+}
+
+/// Every string of argument `arg_index` of `module::function`, e.g. module `0x1::coin` and
+/// function `transfer`
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RedactionTarget {
+    pub name: String,
+    pub module: String,
+    pub function: String,
+    pub arg_index: usize,
+}
+
+pub struct Redactor {
+    config: RedactionConfig,
+    rules: RwLock<Arc<CompiledRules>>,
+}
+
+impl Redactor {
+    /// Fails if a rule is invalid or the rules file can't be read
+    pub fn new(config: RedactionConfig) -> anyhow::Result<Self> {
+        let rules = load_rules(&config)?;
+        info!(
+            patterns = rules.patterns.len(),
+            targets = rules.source.targets.len(),
+            "Loaded redaction rules"
+        );
+        Ok(Self {
+            config,
+            rules: RwLock::new(Arc::new(rules)),
+        })
+    }
+
+    /// Re-reads the rules: the ones in the config plus the ones in `rules_file`. Rules that
+    /// can't be loaded keep the previous ones.
+    pub fn reload(&self) {
+        let rules = match load_rules(&self.config) {
+            Ok(rules) => rules,
+            Err(err) => {
+                warn!(
+                    error = ?err,
+                    "Failed to load redaction rules, keeping the previous ones"
+                );
+                return;
+            },
+        };
+        let mut current = self.rules.write().unwrap();
+        if current.source != rules.source {
+            info!(
+                patterns = rules.patterns.len(),
+                targets = rules.source.targets.len(),
+                "Reloaded redaction rules"
+            );
+            *current = Arc::new(rules);
+        }
+    }
+
+    /// Reloads the rules every `reload_interval_secs` for as long as the redactor lives
+    pub fn spawn_reloader(redactor: Arc<Self>) {
+        if redactor.config.rules_file.is_none() {
+            return;
+        }
+        let interval = Duration::from_secs(redactor.config.reload_interval_secs.max(1));
+        let redactor = Arc::downgrade(&redactor);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                match redactor.upgrade() {
+                    Some(redactor) => redactor.reload(),
+                    // The tailer using the redactor was dropped
+                    None => return,
+                }
+            }
+        });
+    }
+
+    /// Redacts the payload arguments and event data of a fetched batch in place
+    pub fn redact(&self, transactions: &mut [Transaction]) {
+        let rules = self.rules.read().unwrap().clone();
+        if rules.patterns.is_empty() && rules.targets.is_empty() {
+            return;
+        }
+        for txn in transactions {
+            rules.redact_transaction(txn);
+        }
+    }
+}
+
+fn load_rules(config: &RedactionConfig) -> anyhow::Result<CompiledRules> {
+    let mut rules = config.rules.clone();
+    if let Some(path) = &config.rules_file {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read redaction rules file {}", path))?;
+        let file_rules: RedactionRules = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid redaction rules file {}", path))?;
+        rules.patterns.extend(file_rules.patterns);
+        rules.targets.extend(file_rules.targets);
+    }
+    CompiledRules::compile(rules)
+}
+
+/// `0x1::coin::transfer` for module `0x1::coin` and function `transfer`, with the address
+/// standardized
+fn function_key(module: &str, function: &str) -> String {
+    match module.split_once("::") {
+        Some((address, name)) => {
+            format!("{}::{}::{}", standardize_address(address), name, function)
+        },
+        None => format!("{}::{}", module, function),
+    }
+}
+
+fn marker(value: &str) -> String {
+    format!("{}{}]", REDACTION_MARKER_PREFIX, &hash_str(value)[..16])
+}
+
+struct CompiledRules {
+    source: RedactionRules,
+    patterns: Vec<(String, Regex)>,
+    /// Function key to its targeted argument indices and rule names
+    targets: HashMap<String, Vec<(usize, String)>>,
+}
+
+impl CompiledRules {
+    fn compile(source: RedactionRules) -> anyhow::Result<Self> {
+        let patterns = source
+            .patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(&pattern.regex)
+                    .map(|regex| (pattern.name.clone(), regex))
+                    .with_context(|| format!("Invalid regex of redaction pattern {}", pattern.name))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut targets = HashMap::<String, Vec<(usize, String)>>::new();
+        for target in &source.targets {
+            targets
+                .entry(function_key(&target.module, &target.function))
+                .or_default()
+                .push((target.arg_index, target.name.clone()));
+        }
+        Ok(Self {
+            source,
+            patterns,
+            targets,
+        })
+    }
+
+    fn redact_transaction(&self, transaction: &mut Transaction) {
+        let events = match transaction {
+            Transaction::UserTransaction(txn) => {
+                match &mut txn.request.payload {
+                    TransactionPayload::EntryFunctionPayload(payload) => {
+                        self.redact_entry_function(payload)
+                    },
+                    TransactionPayload::ScriptPayload(payload) => {
+                        self.redact_arguments(&[], &mut payload.arguments)
+                    },
+                    TransactionPayload::MultisigPayload(payload) => {
+                        if let Some(MultisigTransactionPayload::EntryFunctionPayload(payload)) =
+                            &mut payload.transaction_payload
+                        {
+                            self.redact_entry_function(payload)
+                        }
+                    },
+                    _ => {},
+                }
+                &mut txn.events
+            },
+            Transaction::BlockMetadataTransaction(txn) => &mut txn.events,
+            Transaction::GenesisTransaction(txn) => &mut txn.events,
+            _ => return,
+        };
+        for event in events {
+            self.redact_value(&mut event.data);
+        }
+    }
+
+    fn redact_entry_function(&self, payload: &mut EntryFunctionPayload) {
+        let key = function_key(
+            &payload.function.module.to_string(),
+            &payload.function.name.to_string(),
+        );
+        let targets = self.targets.get(&key).map_or(&[][..], Vec::as_slice);
+        self.redact_arguments(targets, &mut payload.arguments);
+    }
+
+    /// Targeted arguments are replaced whole, the others only where a pattern matches
+    fn redact_arguments(&self, targets: &[(usize, String)], arguments: &mut [Value]) {
+        for (index, argument) in arguments.iter_mut().enumerate() {
+            match targets.iter().find(|(arg_index, _)| *arg_index == index) {
+                Some((_, name)) => redact_all(name, argument),
+                None => self.redact_value(argument),
+            }
+        }
+    }
+
+    fn redact_value(&self, value: &mut Value) {
+        match value {
+            Value::String(string) => {
+                if let Some(redacted) = self.redact_str(string) {
+                    *string = redacted;
+                }
+            },
+            Value::Array(values) => values.iter_mut().for_each(|value| self.redact_value(value)),
+            Value::Object(fields) => fields
+                .values_mut()
+                .for_each(|value| self.redact_value(value)),
+            _ => {},
+        }
+    }
+
+    /// Replaces the matches of all patterns, `None` if none matches. Overlapping matches go to
+    /// the earliest and then longest one, so no pattern ever sees another one's marker.
+    fn redact_str(&self, value: &str) -> Option<String> {
+        let mut matches = self
+            .patterns
+            .iter()
+            .flat_map(|(name, regex)| {
+                regex
+                    .find_iter(value)
+                    .filter(|found| !found.as_str().is_empty())
+                    .map(move |found| (found.start(), found.end(), name))
+            })
+            .collect::<Vec<_>>();
+        if matches.is_empty() {
+            return None;
+        }
+        matches.sort_by_key(|(start, end, _)| (*start, std::cmp::Reverse(*end)));
+        let mut redacted = String::with_capacity(value.len());
+        let mut position = 0;
+        for (start, end, name) in matches {
+            if start < position {
+                continue;
+            }
+            redacted.push_str(&value[position..start]);
+            redacted.push_str(&marker(&value[start..end]));
+            REDACTIONS.with_label_values(&[name]).inc();
+            position = end;
+        }
+        redacted.push_str(&value[position..]);
+        Some(redacted)
+    }
+}
+
+/// Replaces every string in `value` whole
+fn redact_all(name: &str, value: &mut Value) {
+    match value {
+        Value::String(string) => {
+            *string = marker(string);
+            REDACTIONS.with_label_values(&[name]).inc();
+        },
+        Value::Array(values) => values.iter_mut().for_each(|value| redact_all(name, value)),
+        Value::Object(fields) => fields
+            .values_mut()
+            .for_each(|value| redact_all(name, value)),
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rules(patterns: &[(&str, &str)], targets: Vec<RedactionTarget>) -> RedactionRules {
+        RedactionRules {
+            patterns: patterns
+                .iter()
+                .map(|(name, regex)| RedactionPattern {
+                    name: name.to_string(),
+                    regex: regex.to_string(),
+                })
+                .collect(),
+            targets,
+        }
+    }
+
+    #[test]
+    fn test_redact_nested_values() {
+        let compiled =
+            CompiledRules::compile(rules(&[("api_key", r"sk_live_[0-9a-z]+")], vec![])).unwrap();
+        let mut arguments = vec![
+            json!(["plain", "key=sk_live_abc123;other=sk_live_def456"]),
+            json!([["sk_live_abc123"], { "inner": ["x", { "deeper": "sk_live_abc123" }] }]),
+            json!("100"),
+            json!(true),
+        ];
+        compiled.redact_arguments(&[], &mut arguments);
+        let abc = marker("sk_live_abc123");
+        assert_eq!(arguments[0][0], json!("plain"));
+        assert_eq!(
+            arguments[0][1],
+            json!(format!("key={};other={}", abc, marker("sk_live_def456")))
+        );
+        assert_eq!(arguments[1][0][0], json!(abc));
+        assert_eq!(arguments[1][1]["inner"][1]["deeper"], json!(abc));
+        assert_eq!(arguments[2], json!("100"));
+        assert_eq!(arguments[3], json!(true));
+    }
+
+    #[test]
+    fn test_overlapping_patterns() {
+        // The second pattern would match inside the first one's marker if applied after it
+        let compiled = CompiledRules::compile(rules(
+            &[("token", r"tok_[A-Z]+"), ("caps", r"[A-Z]{4,}")],
+            vec![],
+        ))
+        .unwrap();
+        assert_eq!(
+            compiled.redact_str("a tok_SECRET and LOUD").unwrap(),
+            format!("a {} and {}", marker("tok_SECRET"), marker("LOUD"))
+        );
+        assert_eq!(compiled.redact_str("nothing here"), None);
+    }
+
+    #[test]
+    fn test_redact_targets() {
+        let compiled = CompiledRules::compile(rules(&[], vec![RedactionTarget {
+            name: "partner_key".to_string(),
+            module: "0x0000000000000000000000000000000000000000000000000000000000000abc::partner"
+                .to_string(),
+            function: "register".to_string(),
+            arg_index: 1,
+        }]))
+        .unwrap();
+        let targets = compiled
+            .targets
+            .get(&function_key("0xabc::partner", "register"))
+            .unwrap();
+        let mut arguments = vec![json!("name"), json!(["key-1", "key-2"]), json!("other")];
+        compiled.redact_arguments(targets, &mut arguments);
+        assert_eq!(arguments[0], json!("name"));
+        assert_eq!(arguments[1], json!([marker("key-1"), marker("key-2")]));
+        assert_eq!(arguments[2], json!("other"));
+    }
+
+    #[test]
+    fn test_reload() {
+        let path =
+            std::env::temp_dir().join(format!("redaction_rules_{}.json", std::process::id()));
+        let write = |rules: &RedactionRules| {
+            std::fs::write(&path, serde_json::to_string(rules).unwrap()).unwrap()
+        };
+        write(&rules(&[("first", "one")], vec![]));
+        let redactor = Redactor::new(RedactionConfig {
+            enabled: true,
+            rules_file: Some(path.to_string_lossy().to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        let redact = |value: &str| redactor.rules.read().unwrap().redact_str(value);
+        assert!(redact("one two").is_some());
+
+        write(&rules(&[("second", "two")], vec![]));
+        redactor.reload();
+        assert_eq!(redact("one two").unwrap(), format!("one {}", marker("two")));
+
+        // An invalid rule keeps the previous rules
+        write(&rules(&[("broken", "(")], vec![]));
+        redactor.reload();
+        assert_eq!(redact("one two").unwrap(), format!("one {}", marker("two")));
+        std::fs::remove_file(&path).ok();
+    }
+}
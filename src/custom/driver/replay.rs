@@ -0,0 +1,576 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Replay mode: runs a processor over transactions read from files instead of a fullnode, for
+//! iterating on parsing bugs, e.g.
+//! `cargo run -- replay --processor custom_coin_processor --input batch1.json --output stdout`.
+//!
+//! Every `--input` is a JSON array of API transactions, as the fullnode's REST API returns them,
+//! or a fetcher recording (see `indexer::recording`). Their transactions are put in version
+//! order, repeated versions dropped, and cut into batches of up to `--batch-size` consecutive
+//! versions, which the processor built by `processor_registry::build_processor` processes one
+//! after the other. The processors read and write Postgres as they do on a node, so replays need
+//! a database, `--database-url` or `INDEXER_DATABASE_URL`, migrated at start; point it at a
+//! scratch database. The one exception is `custom_default_processor` publishing only, the default
+//! sink mode, which `--output stdout` replays without a database when neither is set: its rules
+//! are checked but nothing is recorded, neither its statuses nor its stats, storage usage or
+//! duplicates. Nothing is produced to Kafka: the processor's publisher is an in-memory one,
+//! see `driver::memory_publisher`, which publishes every model, under its configured topic or
+//! named after its topic key. `--output stdout` prints each message as a line of JSON,
+//! `--output postgres` only the batches and the rows they wrote, and `replay` hands the messages
+//! back for tests to assert on. Without a schema registry, the `avro` format is replayed as
+//! `protobuf`, which carries the same fields.
+
+use crate::{
+    client::MODEL_TOPIC_KEYS,
+    custom::{
+        driver::{
+            config::{DriverConfig, SerializationFormat, SinkBackend, DEFAULT_CONFIG_PATH},
+            envelope::Envelope,
+            memory_publisher::RecordedMessages,
+            publisher::Publisher,
+            sink,
+        },
+        processors::{
+            custom_default_processor,
+            processor_registry::{self, ProcessorConfig},
+            ProcessorOptions,
+        },
+    },
+    database::{new_db_pool, PgDbPool},
+    indexer::{
+        processing_result::ProcessingResult,
+        recording::{self, RecordingFrame, RecordingReader, RECORDING_MAGIC},
+        tailer::MIGRATIONS,
+        transaction_processor::TransactionProcessor,
+    },
+};
+use anyhow::{bail, Context, Result};
+use aptos_api_types::Transaction;
+use aptos_config::config::IndexerConfig;
+use clap::Parser;
+use diesel_migrations::MigrationHarness;
+use std::{
+    fmt::{self, Display},
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+/// Flags of a replay, for the binary embedding the indexer to take
+#[derive(Clone, Debug, Parser)]
+pub struct ReplayArgs {
+    /// Processor to run over the transactions
+    #[clap(long, default_value = custom_default_processor::NAME)]
+    pub processor: String,
+    /// Files of transactions, JSON arrays or fetcher recordings
+    #[clap(long, required = true)]
+    pub input: Vec<PathBuf>,
+    /// `stdout` to print the messages published, `postgres` for the rows written only
+    #[clap(long, default_value = "stdout")]
+    pub output: ReplayOutput,
+    /// Postgres the processor reads and writes, `INDEXER_DATABASE_URL` if unset
+    #[clap(long)]
+    pub database_url: Option<String>,
+    /// Driver config, the default config path if it exists, otherwise the defaults
+    #[clap(long)]
+    pub config: Option<PathBuf>,
+    /// Most versions in a batch
+    #[clap(long, default_value = "500")]
+    pub batch_size: usize,
+    /// Chain id the messages are stamped with, the driver config's if unset
+    #[clap(long)]
+    pub chain_id: Option<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplayOutput {
+    Stdout,
+    Postgres,
+}
+
+impl FromStr for ReplayOutput {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "stdout" => Ok(ReplayOutput::Stdout),
+            "postgres" => Ok(ReplayOutput::Postgres),
+            other => bail!("Unknown output {:?}, expected stdout or postgres", other),
+        }
+    }
+}
+
+impl Display for ReplayOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ReplayOutput::Stdout => "stdout",
+            ReplayOutput::Postgres => "postgres",
+        })
+    }
+}
+
+/// What a replay did
+pub struct Replay {
+    /// One per batch, in version order
+    pub results: Vec<ProcessingResult>,
+    pub messages: RecordedMessages,
+}
+
+/// Replays the files of `args` and prints what they produced
+pub async fn run(args: &ReplayArgs) -> Result<()> {
+    processor_registry::validate_names(std::slice::from_ref(&args.processor))?;
+    if args.batch_size == 0 {
+        bail!("batch_size must be at least 1");
+    }
+    let driver_config = args.driver_config()?;
+    let transactions = read_transactions(&args.input)?;
+    let database_url = args
+        .database_url
+        .clone()
+        .or_else(|| std::env::var("INDEXER_DATABASE_URL").ok());
+    let conn_pool = match database_url {
+        Some(database_url) => {
+            let conn_pool = new_db_pool(&database_url)?;
+            conn_pool
+                .get()?
+                .run_pending_migrations(MIGRATIONS)
+                .map_err(|e| anyhow::anyhow!("Failed to run migrations: {}", e))?;
+            Some(conn_pool)
+        },
+        None if args.output == ReplayOutput::Stdout => None,
+        None => {
+            bail!("--output postgres needs a database, set --database-url or INDEXER_DATABASE_URL")
+        },
+    };
+    let chain_id = args.chain_id.or(driver_config.chain_id).unwrap_or_default();
+
+    let replay = replay(
+        &args.processor,
+        conn_pool,
+        &driver_config,
+        chain_id,
+        transactions,
+        args.batch_size,
+    )
+    .await?;
+    match args.output {
+        ReplayOutput::Stdout => {
+            for message in replay.messages.all() {
+                println!("{}", serde_json::to_string(&message.printed())?);
+            }
+        },
+        ReplayOutput::Postgres => {
+            for result in &replay.results {
+                println!(
+                    "{} {}-{} {}",
+                    result.name,
+                    result.start_version,
+                    result.end_version,
+                    serde_json::to_string(&result.counts)?
+                );
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Runs `processor_name` over `transactions` with an in-memory publisher, without a database if
+/// there's no `conn_pool`, see the module doc
+pub async fn replay(
+    processor_name: &str,
+    conn_pool: Option<PgDbPool>,
+    driver_config: &DriverConfig,
+    chain_id: u8,
+    transactions: Vec<Transaction>,
+    batch_size: usize,
+) -> Result<Replay> {
+    let (processor, messages) =
+        in_memory_processor(processor_name, conn_pool, driver_config, chain_id)?;
+    let mut results = vec![];
+    for batch in batches(transactions, batch_size) {
+        let start_version = batch.first().and_then(|txn| txn.version()).unwrap();
+        let end_version = batch.last().and_then(|txn| txn.version()).unwrap();
+        let result = processor
+            .process_versions_with_status(batch, start_version, end_version)
+            .await
+            .map_err(|tpe| {
+                let (err, ..) = tpe.inner();
+                anyhow::anyhow!(
+                    "Error in '{}' while processing batch {}-{}: {:?}",
+                    processor_name,
+                    start_version,
+                    end_version,
+                    err
+                )
+            })?;
+        results.push(result);
+    }
+    Ok(Replay { results, messages })
+}
+
+/// `processor_name` publishing every model to memory instead of Kafka, see `replay_config`.
+/// Without `conn_pool` only `custom_default_processor` publishing only can run.
+pub(crate) fn in_memory_processor(
+    processor_name: &str,
+    conn_pool: Option<PgDbPool>,
+    driver_config: &DriverConfig,
+    chain_id: u8,
+) -> Result<(Arc<dyn TransactionProcessor>, RecordedMessages)> {
+    let driver_config = replay_config(driver_config.clone());
+    let (publisher, messages) = Publisher::in_memory(
+        driver_config.clone(),
+        Envelope::new(chain_id, processor_name),
+    );
+    let config = ProcessorConfig {
+        indexer: &IndexerConfig::default(),
+        driver: &driver_config,
+        options: &ProcessorOptions::default(),
+    };
+    let processor: Arc<dyn TransactionProcessor> = match conn_pool {
+        Some(conn_pool) => {
+            processor_registry::build_processor(processor_name, conn_pool, publisher, &config)?
+        },
+        None if processor_name == custom_default_processor::NAME
+            && !driver_config.sink.mode.writes_db() =>
+        {
+            Arc::new(processor_registry::default_processor(
+                None,
+                sink::from_config(&driver_config.sink, publisher),
+                &config,
+            ))
+        },
+        None => bail!(
+            "{} needs a database to replay, set --database-url or INDEXER_DATABASE_URL",
+            processor_name
+        ),
+    };
+    Ok((processor, messages))
+}
+
+impl ReplayArgs {
+    fn driver_config(&self) -> Result<DriverConfig> {
+        read_driver_config(self.config.as_deref())
+    }
+}
+
+/// The driver config at `path`, or at the default config path if it exists, otherwise the
+/// defaults
+pub(crate) fn read_driver_config(path: Option<&Path>) -> Result<DriverConfig> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None if Path::new(DEFAULT_CONFIG_PATH).exists() => PathBuf::from(DEFAULT_CONFIG_PATH),
+        None => {
+            return Ok(serde_json::from_value(serde_json::json!({
+                "kafka": {},
+                "topics": {},
+            }))?)
+        },
+    };
+    let data =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&data).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// `driver_config` publishing every model, to Kafka so that the in-memory publisher gets it
+fn replay_config(mut driver_config: DriverConfig) -> DriverConfig {
+    for (_, topic_key) in MODEL_TOPIC_KEYS {
+        driver_config
+            .topics
+            .entry(topic_key.to_string())
+            .or_insert_with(|| topic_key.to_string());
+    }
+    driver_config.sink.backend = SinkBackend::Kafka;
+    driver_config.sink.fan_out.clear();
+    // Avro needs the schemas registered, replays print the same messages as protobuf instead
+    if driver_config.publisher_serialization.format == SerializationFormat::Avro {
+        driver_config.publisher_serialization.format = SerializationFormat::Protobuf;
+    }
+    driver_config
+}
+
+/// The transactions of every file, in version order and each version once
+pub fn read_transactions(paths: &[PathBuf]) -> Result<Vec<Transaction>> {
+    let mut transactions = vec![];
+    for path in paths {
+        transactions
+            .extend(read_file(path).with_context(|| format!("Failed to read {}", path.display()))?);
+    }
+    for txn in &transactions {
+        if txn.version().is_none() {
+            bail!("Pending transactions can't be replayed");
+        }
+    }
+    transactions.sort_by_key(|txn| txn.version());
+    transactions.dedup_by_key(|txn| txn.version());
+    Ok(transactions)
+}
+
+fn read_file(path: &Path) -> Result<Vec<Transaction>> {
+    let data = fs::read(path)?;
+    if data.starts_with(RECORDING_MAGIC) {
+        let mut reader = RecordingReader::open(path)?;
+        let mut transactions = vec![];
+        while let Some(frame) = reader.next_frame()? {
+            if let RecordingFrame::Batch {
+                transactions: batch,
+                ..
+            } = frame
+            {
+                transactions.extend(batch);
+            }
+        }
+        return Ok(transactions);
+    }
+    let raw_txns: Vec<serde_json::Value> = serde_json::from_slice(&data)?;
+    raw_txns
+        .iter()
+        .map(|raw_txn| {
+            let mut txn: Transaction = serde_json::from_value(raw_txn.clone())?;
+            recording::restore_block_info(&mut txn, raw_txn);
+            Ok(txn)
+        })
+        .collect()
+}
+
+/// Consecutive versions, at most `batch_size` of them, from transactions in version order
+fn batches(transactions: Vec<Transaction>, batch_size: usize) -> Vec<Vec<Transaction>> {
+    let mut batches: Vec<Vec<Transaction>> = vec![];
+    for txn in transactions {
+        match batches.last_mut() {
+            Some(batch)
+                if batch.len() < batch_size
+                    && batch.last().and_then(|last| last.version()).map(|v| v + 1)
+                        == txn.version() =>
+            {
+                batch.push(txn)
+            },
+            _ => batches.push(vec![txn]),
+        }
+    }
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom::{driver::config::SinkMode, test_utils};
+
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/batch1.json");
+
+    fn versions(transactions: &[Transaction]) -> Vec<u64> {
+        transactions
+            .iter()
+            .filter_map(|txn| txn.version())
+            .collect()
+    }
+
+    #[test]
+    fn test_read_transactions() {
+        let fixture = read_transactions(&[PathBuf::from(FIXTURE)]).unwrap();
+        assert_eq!(versions(&fixture), [260885, 691595, 691596]);
+        // Kept from the fetcher's JSON
+        assert!(fixture[0]
+            .transaction_info()
+            .unwrap()
+            .block_height
+            .is_some());
+
+        // Overlapping with the recording, which the fixture was taken from
+        let both = read_transactions(&[
+            PathBuf::from(recording::TAILER_FIXTURES_RECORDING),
+            PathBuf::from(FIXTURE),
+        ])
+        .unwrap();
+        assert_eq!(versions(&both), [0, 69158, 260885, 691595, 691596]);
+
+        let missing = read_transactions(&[PathBuf::from("missing.json")]).unwrap_err();
+        assert!(format!("{:#}", missing).contains("missing.json"));
+    }
+
+    #[test]
+    fn test_batches() {
+        let transactions = read_transactions(&[PathBuf::from(FIXTURE)]).unwrap();
+        let sizes = |batch_size| {
+            batches(transactions.clone(), batch_size)
+                .iter()
+                .map(|batch| versions(batch))
+                .collect::<Vec<_>>()
+        };
+        // Cut at the gap, and at the size
+        assert_eq!(sizes(500), vec![vec![260885], vec![691595, 691596]]);
+        assert_eq!(sizes(1), vec![vec![260885], vec![691595], vec![691596]]);
+    }
+
+    #[test]
+    fn test_args() {
+        let args = ReplayArgs::parse_from([
+            "replay",
+            "--processor",
+            "custom_coin_processor",
+            "--input",
+            "a.json",
+            "--input",
+            "b.aptrec",
+        ]);
+        assert_eq!(args.input, [
+            PathBuf::from("a.json"),
+            PathBuf::from("b.aptrec")
+        ]);
+        assert_eq!(args.output, ReplayOutput::Stdout);
+        assert_eq!(args.batch_size, 500);
+        let args = ReplayArgs::parse_from(["replay", "--input", "a.json", "--output", "postgres"]);
+        assert_eq!(args.processor, custom_default_processor::NAME);
+        assert_eq!(args.output, ReplayOutput::Postgres);
+        assert!(ReplayArgs::try_parse_from(["replay", "--output", "postgres"]).is_err());
+        assert!(
+            ReplayArgs::try_parse_from(["replay", "--input", "a.json", "--output", "kafka"])
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let Some(conn_pool) = test_utils::test_pool() else {
+            return;
+        };
+        let driver_config =
+            test_utils::driver_config(serde_json::json!({ "transaction_topic": "txns" }));
+        let replay = replay(
+            custom_default_processor::NAME,
+            Some(conn_pool),
+            &driver_config,
+            test_utils::CHAIN_ID,
+            test_utils::fixture("batch1.json"),
+            500,
+        )
+        .await
+        .unwrap();
+        let ranges = replay
+            .results
+            .iter()
+            .map(|result| (result.start_version, result.end_version))
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, [(260885, 260885), (691595, 691596)]);
+        // Under the configured topic, and the others under their keys
+        let transactions = replay.messages.on_topic("txns");
+        assert_eq!(transactions.len(), 3);
+        assert_eq!(transactions[0].printed().payload["version"], "260885");
+        assert!(replay
+            .messages
+            .all()
+            .iter()
+            .all(|message| message.topic == "txns"
+                || MODEL_TOPIC_KEYS
+                    .iter()
+                    .any(|(_, key)| message.topic == *key)));
+    }
+
+    #[tokio::test]
+    async fn test_replay_without_database() {
+        let driver_config =
+            test_utils::driver_config(serde_json::json!({ "transaction_topic": "txns" }));
+        let replay = replay(
+            custom_default_processor::NAME,
+            None,
+            &driver_config,
+            test_utils::CHAIN_ID,
+            test_utils::fixture("batch1.json"),
+            500,
+        )
+        .await
+        .unwrap();
+        assert_eq!(replay.results.len(), 2);
+        let versions = replay
+            .messages
+            .on_topic("txns")
+            .iter()
+            .map(|message| message.printed().payload["version"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(versions, ["260885", "691595", "691596"]);
+
+        // The other processors, and writing Postgres, need one
+        let error = in_memory_processor(
+            "custom_coin_processor",
+            None,
+            &driver_config,
+            test_utils::CHAIN_ID,
+        )
+        .err()
+        .unwrap();
+        assert!(error.to_string().contains("needs a database"));
+        let mut writing = driver_config.clone();
+        writing.sink.mode = SinkMode::Both;
+        assert!(in_memory_processor(
+            custom_default_processor::NAME,
+            None,
+            &writing,
+            test_utils::CHAIN_ID
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rolled_back_processor() {
+        use crate::{
+            database::{new_rollback_db_pool, write_transaction},
+            schema::processor_statuses::dsl,
+        };
+        use diesel::prelude::*;
+
+        let Some(conn_pool) = test_utils::test_pool() else {
+            return;
+        };
+        let rollback_pool = new_rollback_db_pool(
+            &std::env::var("INDEXER_DATABASE_URL").unwrap(),
+            std::time::Duration::from_secs(5),
+        )
+        .unwrap();
+        let mut driver_config = test_utils::driver_config(serde_json::json!({}));
+        driver_config.publish_dedupe.force_republish = true;
+        let (processor, messages) = in_memory_processor(
+            custom_default_processor::NAME,
+            Some(rollback_pool.clone()),
+            &driver_config,
+            test_utils::CHAIN_ID,
+        )
+        .unwrap();
+        // Its transactions are savepoints of the connection's
+        let transactions = test_utils::fixture("batch1.json").split_off(1);
+        let result = processor
+            .process_versions_with_status(transactions, 691595, 691596)
+            .await
+            .unwrap();
+        assert_eq!(result.counts.transactions, 2);
+        assert!(!messages.is_empty());
+
+        let name = "rolled_back_processor";
+        let statuses = |conn: &mut PgConnection| {
+            dsl::processor_statuses
+                .filter(dsl::name.eq(name))
+                .count()
+                .get_result::<i64>(conn)
+                .unwrap()
+        };
+        write_transaction::<_, diesel::result::Error, _>(
+            &mut rollback_pool.get().unwrap(),
+            |conn| {
+                diesel::insert_into(dsl::processor_statuses)
+                    .values((
+                        dsl::name.eq(name),
+                        dsl::version.eq(1),
+                        dsl::success.eq(true),
+                        dsl::last_updated.eq(chrono::Utc::now().naive_utc()),
+                    ))
+                    .execute(conn)
+            },
+        )
+        .unwrap();
+        assert_eq!(statuses(&mut rollback_pool.get().unwrap()), 1);
+        assert_eq!(statuses(&mut conn_pool.get().unwrap()), 0);
+        drop(processor);
+        drop(rollback_pool);
+        assert_eq!(statuses(&mut conn_pool.get().unwrap()), 0);
+    }
+}
@@ -0,0 +1,387 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Fingerprints of the messages a processor's publisher delivered for its last versions, kept in
+//! a local file so that a restarted processor doesn't produce them again.
+//!
+//! A fingerprint hashes a message's topic, key, version when the publisher knows it and payload.
+//! After each batch the publisher's producer is flushed, and only once everything is delivered
+//! are the batch's fingerprints added to the file, tagged with the batch's last version: a crash
+//! before that republishes the batch as usual, a crash after it and before the watermark commit
+//! skips it. A restarted processor skips the messages whose fingerprint is in the file until a
+//! batch reaches the last version in the file. Two identical messages (same topic, key and
+//! payload, without a version) within the replayed versions share a fingerprint, so if only the
+//! first was delivered before the crash the second is skipped too: that's the gap risk traded for
+//! fewer duplicates, and why the cache is off by default.
+//!
+//! The file holds at most `max_entries` fingerprints of the last `window_versions` versions. A
+//! file that can't be read or fails its checksum is ignored, and everything is published again.
+//!
+//! File format (all integers little endian): the magic `APTIDXPC`, the format version as u16, the
+//! number of entries as u64, the entries as fingerprint and version (u64 each), then the xxh3 of
+//! everything before it as u64.
+
+use crate::{counters::REPLAY_CACHE_RESETS, custom::driver::config::ReplayCacheConfig};
+use anyhow::{ensure, Context, Result};
+use aptos_logger::{info, warn};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+use xxhash_rust::xxh3::{xxh3_64, Xxh3};
+
+pub const REPLAY_CACHE_MAGIC: &[u8; 8] = b"APTIDXPC";
+pub const REPLAY_CACHE_FORMAT_VERSION: u16 = 1;
+
+const HEADER_LEN: usize = 8 + 2 + 8;
+const ENTRY_LEN: usize = 16;
+
+pub struct ReplayCache {
+    path: PathBuf,
+    window_versions: u64,
+    max_entries: usize,
+    flush_timeout: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Delivered fingerprints, with the last version of the batch they were delivered in
+    delivered: HashMap<u64, u64>,
+    /// Produced since the last batch, not known to be delivered yet
+    pending: HashSet<u64>,
+    /// Last version of the file loaded at start, messages are skipped until a batch reaches it
+    replaying_until: Option<u64>,
+}
+
+impl ReplayCache {
+    /// Loads the cache of `name`, a processor or shard, starting empty if there's no readable file
+    pub fn open(config: &ReplayCacheConfig, name: &str) -> Self {
+        let path = Path::new(&config.path).join(format!("{}.replay", name));
+        let mut state = State::default();
+        match load(&path) {
+            Ok(Some(entries)) => {
+                state.replaying_until = entries.iter().map(|(_, version)| *version).max();
+                state.delivered = entries.into_iter().collect();
+                info!(
+                    path = path.display().to_string(),
+                    fingerprints = state.delivered.len(),
+                    replaying_until = state.replaying_until,
+                    "Loaded the replay cache"
+                );
+            },
+            Ok(None) => {},
+            Err(e) => {
+                let reason = match e.downcast_ref::<std::io::Error>() {
+                    Some(_) => "unreadable",
+                    None => "corrupt",
+                };
+                REPLAY_CACHE_RESETS.with_label_values(&[reason]).inc();
+                warn!(
+                    path = path.display().to_string(),
+                    error = format!("{:#}", e),
+                    "Ignoring the replay cache, publishing everything again"
+                );
+            },
+        }
+        let cache = Self {
+            path,
+            window_versions: config.window_versions,
+            max_entries: config.max_entries,
+            flush_timeout: Duration::from_millis(config.flush_timeout_millis),
+            state: Mutex::new(state),
+        };
+        cache.evict(&mut cache.state.lock().unwrap(), u64::MAX);
+        cache
+    }
+
+    pub fn fingerprint(
+        topic: &str,
+        key: Option<&str>,
+        version: Option<u64>,
+        payload: &[u8],
+    ) -> u64 {
+        let mut hasher = Xxh3::new();
+        hasher.update(topic.as_bytes());
+        hasher.update(&[0]);
+        if let Some(key) = key {
+            hasher.update(key.as_bytes());
+        }
+        hasher.update(&[0]);
+        if let Some(version) = version {
+            hasher.update(&version.to_le_bytes());
+        }
+        hasher.update(&[0]);
+        hasher.update(payload);
+        hasher.digest()
+    }
+
+    /// Whether the message was delivered before the restart and shouldn't be produced again
+    pub fn is_delivered(&self, fingerprint: u64) -> bool {
+        let state = self.state.lock().unwrap();
+        state.replaying_until.is_some() && state.delivered.contains_key(&fingerprint)
+    }
+
+    /// Notes a produced message, kept once its batch is committed
+    pub fn record(&self, fingerprint: u64) {
+        self.state.lock().unwrap().pending.insert(fingerprint);
+    }
+
+    /// How long to wait for the producer to deliver before `commit`
+    pub fn flush_timeout(&self) -> Duration {
+        self.flush_timeout
+    }
+
+    /// Keeps what was produced so far, once it's delivered, as of `end_version`
+    pub fn commit(&self, end_version: u64) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        for fingerprint in std::mem::take(&mut state.pending) {
+            let version = state.delivered.entry(fingerprint).or_insert(end_version);
+            *version = (*version).max(end_version);
+        }
+        if state
+            .replaying_until
+            .map_or(false, |until| end_version >= until)
+        {
+            info!(
+                path = self.path.display().to_string(),
+                "Caught up with the replay cache"
+            );
+            state.replaying_until = None;
+        }
+        self.evict(&mut state, end_version);
+        self.persist(&state)
+    }
+
+    /// Forgets the fingerprints of `version` on, e.g. before reprocessing them to publish again
+    pub fn forget_from(&self, version: u64) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.pending.clear();
+        state.delivered.retain(|_, delivered| *delivered < version);
+        self.persist(&state)
+    }
+
+    /// Drops the fingerprints out of the window below `end_version`, then the oldest over the cap
+    fn evict(&self, state: &mut State, end_version: u64) {
+        let oldest = end_version.saturating_sub(self.window_versions);
+        if end_version != u64::MAX {
+            state.delivered.retain(|_, version| *version > oldest);
+        }
+        if state.delivered.len() > self.max_entries {
+            let mut versions = state.delivered.values().copied().collect::<Vec<_>>();
+            versions.sort_unstable();
+            let cutoff = versions[versions.len() - self.max_entries.max(1)];
+            state.delivered.retain(|_, version| *version > cutoff);
+        }
+    }
+
+    fn persist(&self, state: &State) -> Result<()> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + state.delivered.len() * ENTRY_LEN + 8);
+        bytes.extend_from_slice(REPLAY_CACHE_MAGIC);
+        bytes.extend_from_slice(&REPLAY_CACHE_FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(state.delivered.len() as u64).to_le_bytes());
+        for (fingerprint, version) in &state.delivered {
+            bytes.extend_from_slice(&fingerprint.to_le_bytes());
+            bytes.extend_from_slice(&version.to_le_bytes());
+        }
+        bytes.extend_from_slice(&xxh3_64(&bytes).to_le_bytes());
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Renamed over the previous file so that a crash leaves one or the other
+        let tmp_path = self.path.with_extension("replay.tmp");
+        let mut file = fs::File::create(&tmp_path)
+            .with_context(|| format!("Failed to create {}", tmp_path.display()))?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+/// The entries of the file at `path`, `None` if there's none
+fn load(path: &Path) -> Result<Option<Vec<(u64, u64)>>> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    ensure!(
+        bytes.len() >= HEADER_LEN + 8 && &bytes[..8] == REPLAY_CACHE_MAGIC,
+        "Not a replay cache"
+    );
+    let u64_at = |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+    let format_version = u16::from_le_bytes(bytes[8..10].try_into().unwrap());
+    ensure!(
+        format_version == REPLAY_CACHE_FORMAT_VERSION,
+        "Unknown format version {}",
+        format_version
+    );
+    let count = u64_at(10);
+    let body_len = bytes.len() - 8;
+    ensure!(
+        (body_len - HEADER_LEN) as u64 == count.saturating_mul(ENTRY_LEN as u64),
+        "Truncated"
+    );
+    ensure!(
+        xxh3_64(&bytes[..body_len]) == u64_at(body_len),
+        "Checksum mismatch"
+    );
+    Ok(Some(
+        (0..count as usize)
+            .map(|i| {
+                let offset = HEADER_LEN + i * ENTRY_LEN;
+                (u64_at(offset), u64_at(offset + 8))
+            })
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(test: &str) -> ReplayCacheConfig {
+        let path =
+            std::env::temp_dir().join(format!("replay_cache_{}_{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        ReplayCacheConfig {
+            enabled: true,
+            path: path.display().to_string(),
+            window_versions: 1000,
+            max_entries: 100,
+            flush_timeout_millis: 0,
+        }
+    }
+
+    /// Fingerprints of the messages of `versions`
+    fn batch(versions: std::ops::RangeInclusive<u64>) -> Vec<u64> {
+        versions
+            .map(|version| ReplayCache::fingerprint("topic", Some("0x1"), Some(version), b"{}"))
+            .collect()
+    }
+
+    fn publish(cache: &ReplayCache, fingerprints: &[u64]) -> usize {
+        fingerprints
+            .iter()
+            .filter(|fingerprint| {
+                let delivered = cache.is_delivered(**fingerprint);
+                if !delivered {
+                    cache.record(**fingerprint);
+                }
+                !delivered
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_crash_between_flush_and_watermark_commit() {
+        let config = config("flush_first");
+        let cache = ReplayCache::open(&config, "processor");
+        assert_eq!(publish(&cache, &batch(1..=10)), 10);
+        cache.commit(10).unwrap();
+        assert_eq!(publish(&cache, &batch(11..=20)), 10);
+        cache.commit(20).unwrap();
+        // The watermark is still at 10, versions 11 to 20 are processed again
+        drop(cache);
+        let cache = ReplayCache::open(&config, "processor");
+        assert_eq!(publish(&cache, &batch(11..=20)), 0);
+        cache.commit(20).unwrap();
+        // Caught up, identical messages are published again
+        assert_eq!(publish(&cache, &batch(11..=12)), 2);
+        assert_eq!(publish(&cache, &batch(21..=30)), 10);
+    }
+
+    #[test]
+    fn test_crash_between_watermark_commit_and_flush() {
+        let config = config("watermark_first");
+        let cache = ReplayCache::open(&config, "processor");
+        assert_eq!(publish(&cache, &batch(1..=10)), 10);
+        cache.commit(10).unwrap();
+        // Produced and committed to the watermark, never committed to the cache
+        assert_eq!(publish(&cache, &batch(11..=20)), 10);
+        drop(cache);
+        let cache = ReplayCache::open(&config, "processor");
+        // Resumes after the watermark, nothing of it was delivered before
+        assert_eq!(publish(&cache, &batch(21..=30)), 10);
+        cache.commit(30).unwrap();
+        drop(cache);
+        // Only what was committed to the cache is skipped
+        let cache = ReplayCache::open(&config, "processor");
+        assert_eq!(publish(&cache, &batch(21..=30)), 0);
+        assert_eq!(publish(&cache, &batch(11..=20)), 10);
+    }
+
+    #[test]
+    fn test_corrupt_file() {
+        let config = config("corrupt");
+        let cache = ReplayCache::open(&config, "processor");
+        publish(&cache, &batch(1..=10));
+        cache.commit(10).unwrap();
+        let path = cache.path.clone();
+        drop(cache);
+
+        let bytes = fs::read(&path).unwrap();
+        let mut flipped = bytes.clone();
+        flipped[HEADER_LEN + 3] ^= 1;
+        for corrupt in [
+            flipped,
+            bytes[..bytes.len() - 5].to_vec(),
+            b"garbage".to_vec(),
+            vec![],
+        ] {
+            fs::write(&path, corrupt).unwrap();
+            let cache = ReplayCache::open(&config, "processor");
+            assert_eq!(publish(&cache, &batch(1..=10)), 10);
+        }
+        fs::write(&path, bytes).unwrap();
+        let cache = ReplayCache::open(&config, "processor");
+        assert_eq!(publish(&cache, &batch(1..=10)), 0);
+    }
+
+    #[test]
+    fn test_bounded() {
+        let config = config("bounded");
+        let cache = ReplayCache::open(&config, "processor");
+        for end_version in (10..=2000).step_by(10) {
+            publish(&cache, &batch(end_version - 9..=end_version));
+            cache.commit(end_version).unwrap();
+            assert!(cache.state.lock().unwrap().delivered.len() <= config.max_entries);
+        }
+        drop(cache);
+        let cache = ReplayCache::open(&config, "processor");
+        // The newest fingerprints are kept
+        assert_eq!(publish(&cache, &batch(1991..=2000)), 0);
+        assert_eq!(publish(&cache, &batch(1..=10)), 10);
+
+        let cache = ReplayCache::open(
+            &ReplayCacheConfig {
+                window_versions: 15,
+                ..config.clone()
+            },
+            "processor",
+        );
+        cache.commit(2000).unwrap();
+        assert_eq!(cache.state.lock().unwrap().delivered.len(), 20);
+    }
+
+    #[test]
+    fn test_forget_from() {
+        let config = config("forget");
+        let cache = ReplayCache::open(&config, "processor");
+        publish(&cache, &batch(1..=10));
+        cache.commit(10).unwrap();
+        publish(&cache, &batch(11..=20));
+        cache.commit(20).unwrap();
+        cache.forget_from(11).unwrap();
+        drop(cache);
+        let cache = ReplayCache::open(&config, "processor");
+        assert_eq!(publish(&cache, &batch(1..=10)), 0);
+        assert_eq!(publish(&cache, &batch(11..=20)), 10);
+    }
+}
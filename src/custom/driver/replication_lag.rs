@@ -0,0 +1,328 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Backpressure from the Postgres replicas. Every `poll_interval_secs` the replication lag, in
+//! seconds, is read from the configured source: `source_sql` run on the indexer's own database,
+//! the primary, or a GET of `source_url` answering with the bare number. Above
+//! `pause_threshold_secs` fetching and processing pause between rounds, so that nothing is left
+//! half written, until the lag drops below `resume_threshold_secs`. Pausing doesn't hold up the
+//! publisher, which flushes what the last round produced. After `max_consecutive_errors` failed
+//! queries in a row the guard fails open and lets the processors run.
+
+use crate::{
+    counters::{REPLICATION_LAG_PAUSED, REPLICATION_LAG_PAUSES, REPLICATION_LAG_SECONDS},
+    custom::driver::config::ReplicationLagConfig,
+    database::PgDbPool,
+};
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use aptos_logger::{info, warn};
+use diesel::{sql_types::Double, QueryableByName, RunQueryDsl};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use std::{sync::Mutex, time::Duration};
+
+/// How often a paused processor checks whether it may go on
+const PAUSED_WAIT: Duration = Duration::from_secs(1);
+
+static GUARD: OnceCell<Guard> = OnceCell::new();
+
+/// Snapshot of the guard, meant for status reporting
+#[derive(Clone, Debug, Serialize)]
+pub struct ReplicationLagStatus {
+    /// `sql` or `http`
+    pub source: &'static str,
+    /// `None` until the first successful query
+    pub observed_lag_secs: Option<f64>,
+    pub paused: bool,
+    pub paused_since: Option<chrono::NaiveDateTime>,
+    /// Pauses since the process started
+    pub pauses: u64,
+    pub consecutive_errors: u64,
+    /// Error of the last query, while it keeps failing
+    pub last_error: Option<String>,
+}
+
+struct Guard {
+    status: Mutex<ReplicationLagStatus>,
+}
+
+enum LagSource {
+    Sql(String, PgDbPool),
+    Http(reqwest::Client, String),
+}
+
+#[derive(Debug, QueryableByName)]
+struct Lag {
+    #[diesel(sql_type = Double)]
+    lag_secs: f64,
+}
+
+/// What an observation did to the guard
+#[derive(Debug, PartialEq)]
+enum Transition {
+    Paused,
+    Resumed,
+    FailedOpen,
+}
+
+/// Starts polling the replication lag. Only the first call in a process has an effect, so every
+/// processor runtime can call it.
+pub fn init(config: &ReplicationLagConfig, connection_pool: PgDbPool) {
+    if !config.enabled || GUARD.get().is_some() {
+        return;
+    }
+    if let Err(err) = config.validate() {
+        panic!("Invalid replication_lag config: {:#}", err);
+    }
+    let timeout = Duration::from_millis(config.timeout_millis);
+    let source = match (&config.source_sql, &config.source_url) {
+        (Some(sql), _) => LagSource::Sql(sql.clone(), connection_pool),
+        (None, Some(url)) => LagSource::Http(
+            reqwest::Client::builder()
+                .timeout(timeout)
+                .build()
+                .expect("Failed to build the replication lag client"),
+            url.clone(),
+        ),
+        (None, None) => unreachable!("validated"),
+    };
+    let guard = Guard {
+        status: Mutex::new(ReplicationLagStatus {
+            source: source.name(),
+            observed_lag_secs: None,
+            paused: false,
+            paused_since: None,
+            pauses: 0,
+            consecutive_errors: 0,
+            last_error: None,
+        }),
+    };
+    if GUARD.set(guard).is_err() {
+        return;
+    }
+    info!(
+        source = source.name(),
+        pause_threshold_secs = config.pause_threshold_secs,
+        resume_threshold_secs = config.resume_threshold_secs,
+        "Watching replication lag"
+    );
+    REPLICATION_LAG_PAUSED.set(0);
+
+    let config = config.clone();
+    let interval = Duration::from_secs(config.poll_interval_secs.max(1));
+    tokio::spawn(async move {
+        loop {
+            let lag = source.query(timeout).await;
+            let mut status = GUARD.get().unwrap().status.lock().unwrap();
+            let transition = observe(&mut status, &config, lag);
+            match transition {
+                Some(Transition::Paused) => {
+                    REPLICATION_LAG_PAUSES.inc();
+                    warn!(
+                        lag_secs = status.observed_lag_secs,
+                        "Replication lag is high, pausing fetching and processing"
+                    );
+                },
+                Some(Transition::Resumed) => info!(
+                    lag_secs = status.observed_lag_secs,
+                    "Replication lag is back down, resuming"
+                ),
+                Some(Transition::FailedOpen) => warn!(
+                    consecutive_errors = status.consecutive_errors,
+                    error = status.last_error,
+                    "Failed to query replication lag, resuming"
+                ),
+                None => {
+                    if let Some(err) = &status.last_error {
+                        warn!(
+                            consecutive_errors = status.consecutive_errors,
+                            error = err,
+                            "Failed to query replication lag"
+                        );
+                    }
+                },
+            }
+            if let Some(lag) = status.observed_lag_secs {
+                REPLICATION_LAG_SECONDS.set(lag);
+            }
+            REPLICATION_LAG_PAUSED.set(status.paused as i64);
+            drop(status);
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
+pub fn status() -> Option<ReplicationLagStatus> {
+    GUARD
+        .get()
+        .map(|guard| guard.status.lock().unwrap().clone())
+}
+
+pub fn is_paused() -> bool {
+    GUARD
+        .get()
+        .map_or(false, |guard| guard.status.lock().unwrap().paused)
+}
+
+/// Waits a moment if the guard is paused, returning whether it was. Callers loop on it so that
+/// they still get to check their own lifecycle in between.
+pub async fn wait_if_paused() -> bool {
+    if !is_paused() {
+        return false;
+    }
+    tokio::time::sleep(PAUSED_WAIT).await;
+    true
+}
+
+/// Applies the outcome of a query to `status`: pauses above the pause threshold, resumes below
+/// the resume threshold and keeps the state in between; fails open once the errors in a row
+/// reach `max_consecutive_errors`, while fewer errors keep the state.
+fn observe(
+    status: &mut ReplicationLagStatus,
+    config: &ReplicationLagConfig,
+    lag: Result<f64>,
+) -> Option<Transition> {
+    match lag {
+        Ok(lag) => {
+            status.observed_lag_secs = Some(lag);
+            status.consecutive_errors = 0;
+            status.last_error = None;
+            if !status.paused && lag > config.pause_threshold_secs {
+                status.paused = true;
+                status.paused_since = Some(chrono::Utc::now().naive_utc());
+                status.pauses += 1;
+                return Some(Transition::Paused);
+            }
+            if status.paused && lag < config.resume_threshold_secs {
+                status.paused = false;
+                status.paused_since = None;
+                return Some(Transition::Resumed);
+            }
+            None
+        },
+        Err(err) => {
+            status.consecutive_errors += 1;
+            status.last_error = Some(format!("{:#}", err));
+            if status.paused && status.consecutive_errors >= config.max_consecutive_errors {
+                status.paused = false;
+                status.paused_since = None;
+                return Some(Transition::FailedOpen);
+            }
+            None
+        },
+    }
+}
+
+impl LagSource {
+    fn name(&self) -> &'static str {
+        match self {
+            LagSource::Sql(..) => "sql",
+            LagSource::Http(..) => "http",
+        }
+    }
+
+    async fn query(&self, timeout: Duration) -> Result<f64> {
+        match self {
+            LagSource::Sql(sql, connection_pool) => {
+                let sql = sql.clone();
+                let connection_pool = connection_pool.clone();
+                tokio::time::timeout(
+                    timeout,
+                    tokio::task::spawn_blocking(move || -> Result<f64> {
+                        let mut conn = connection_pool.get()?;
+                        let lag = diesel::sql_query(sql)
+                            .get_result::<Lag>(&mut conn)
+                            .context("Failed to run the lag query")?;
+                        Ok(lag.lag_secs)
+                    }),
+                )
+                .await
+                .map_err(|_| anyhow!("Lag query timed out"))?
+                .map_err(|e| anyhow!("Lag query panicked: {}", e))?
+            },
+            LagSource::Http(client, url) => {
+                let body = client
+                    .get(url)
+                    .send()
+                    .await
+                    .and_then(|res| res.error_for_status())
+                    .with_context(|| format!("Failed to GET {}", url))?
+                    .text()
+                    .await?;
+                body.trim()
+                    .parse()
+                    .with_context(|| format!("Lag endpoint answered {:?}", body))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status() -> ReplicationLagStatus {
+        ReplicationLagStatus {
+            source: "sql",
+            observed_lag_secs: None,
+            paused: false,
+            paused_since: None,
+            pauses: 0,
+            consecutive_errors: 0,
+            last_error: None,
+        }
+    }
+
+    fn config() -> ReplicationLagConfig {
+        ReplicationLagConfig {
+            enabled: true,
+            source_sql: Some("SELECT 0::float8 AS lag_secs".to_string()),
+            pause_threshold_secs: 30.0,
+            resume_threshold_secs: 10.0,
+            max_consecutive_errors: 3,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let (mut status, config) = (status(), config());
+        assert_eq!(observe(&mut status, &config, Ok(20.0)), None);
+        assert_eq!(
+            observe(&mut status, &config, Ok(31.0)),
+            Some(Transition::Paused)
+        );
+        assert!(status.paused_since.is_some());
+        // Between the thresholds the pause holds
+        assert_eq!(observe(&mut status, &config, Ok(20.0)), None);
+        assert!(status.paused);
+        assert_eq!(
+            observe(&mut status, &config, Ok(9.5)),
+            Some(Transition::Resumed)
+        );
+        assert!(!status.paused);
+        assert_eq!(status.pauses, 1);
+    }
+
+    #[test]
+    fn test_fail_open() {
+        let (mut status, config) = (status(), config());
+        observe(&mut status, &config, Ok(60.0));
+        assert_eq!(observe(&mut status, &config, Err(anyhow!("down"))), None);
+        assert_eq!(observe(&mut status, &config, Err(anyhow!("down"))), None);
+        assert!(status.paused);
+        assert_eq!(
+            observe(&mut status, &config, Err(anyhow!("down"))),
+            Some(Transition::FailedOpen)
+        );
+        assert!(!status.paused);
+        assert_eq!(status.last_error.as_deref(), Some("down"));
+        // A successful query clears the errors and can pause again
+        assert_eq!(
+            observe(&mut status, &config, Ok(60.0)),
+            Some(Transition::Paused)
+        );
+        assert_eq!(status.consecutive_errors, 0);
+        assert_eq!(status.pauses, 2);
+    }
+}
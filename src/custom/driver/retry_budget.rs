@@ -0,0 +1,333 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! One retry budget per batch instead of every layer retrying on its own, so that an outage
+//! doesn't multiply fetch, connection and query retries into a storm. The budget of the batch
+//! being processed is task local, like the backfill guard's policy; every retry site draws an
+//! attempt from it with `retry`, which fails once the batch made `max_attempts` retries in total,
+//! `max_attempts_per_class` of that class, or has run for `max_wall_clock_secs`. From then on
+//! nothing is retried and the batch fails with a single `RetryBudgetExhausted` listing every
+//! attempt it made.
+//!
+//! Fetches run ahead of the batches in the fetcher's task, so each fetch gets a budget of its own.
+//! Code running outside of a budget's task, e.g. on another thread, retries as it always did.
+
+use crate::{counters::RETRY_BUDGET_EXHAUSTED, custom::driver::config::RetryBudgetConfig};
+use once_cell::sync::OnceCell;
+use std::{
+    fmt::{self, Debug, Display},
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+static CONFIG: OnceCell<RetryBudgetConfig> = OnceCell::new();
+
+tokio::task_local! {
+    static BUDGET: Arc<RetryBudget>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Reading transactions from the node
+    Fetch,
+    /// Getting a connection from the pool
+    DbConnection,
+    /// Lookups of rows written by earlier batches
+    DbQuery,
+    /// Producing to or flushing Kafka
+    Publish,
+}
+
+impl ErrorClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorClass::Fetch => "fetch",
+            ErrorClass::DbConnection => "db_connection",
+            ErrorClass::DbQuery => "db_query",
+            ErrorClass::Publish => "publish",
+        }
+    }
+}
+
+/// A failed attempt that drew from a budget
+#[derive(Clone, Debug)]
+pub struct Attempt {
+    pub class: ErrorClass,
+    pub operation: String,
+    pub error: String,
+    /// Since the budget was created
+    pub at: Duration,
+}
+
+pub struct RetryBudget {
+    config: RetryBudgetConfig,
+    /// What the budget is for, e.g. "custom_token_processor batch 100..=199"
+    label: String,
+    started: Instant,
+    attempts: Mutex<Vec<Attempt>>,
+    exhausted: Mutex<Option<RetryBudgetExhausted>>,
+}
+
+/// Turns the budgets on. Only the first call in a process has an effect, so every processor
+/// runtime can call it.
+pub fn init(config: &RetryBudgetConfig) {
+    if config.enabled {
+        let _ = CONFIG.set(config.clone());
+    }
+}
+
+/// A budget for one batch of `processor`, if budgets are on
+pub fn for_batch(
+    processor: &str,
+    start_version: u64,
+    end_version: u64,
+) -> Option<Arc<RetryBudget>> {
+    CONFIG.get().map(|config| {
+        Arc::new(RetryBudget::new(
+            config,
+            format!("{} batch {}..={}", processor, start_version, end_version),
+        ))
+    })
+}
+
+/// A budget for one fetch, if budgets are on
+pub fn for_fetch(starting_version: u64, num_transactions: u16) -> Option<RetryBudget> {
+    CONFIG.get().map(|config| {
+        RetryBudget::new(
+            config,
+            format!(
+                "fetch of {} versions from {}",
+                num_transactions, starting_version
+            ),
+        )
+    })
+}
+
+/// Runs a batch's processing with `budget` for its retries
+pub async fn scope<F: Future>(budget: Option<Arc<RetryBudget>>, f: F) -> F::Output {
+    match budget {
+        Some(budget) => BUDGET.scope(budget, f).await,
+        None => f.await,
+    }
+}
+
+/// Draws an attempt to retry `operation`, which failed with `error`, from the budget of the batch
+/// being processed. Always `Ok` outside of a batch or with budgets off.
+pub fn retry(
+    class: ErrorClass,
+    operation: &str,
+    error: &dyn Debug,
+) -> Result<(), RetryBudgetExhausted> {
+    BUDGET
+        .try_with(|budget| budget.retry(class, operation, error))
+        .unwrap_or(Ok(()))
+}
+
+impl RetryBudget {
+    pub fn new(config: &RetryBudgetConfig, label: String) -> Self {
+        Self {
+            config: config.clone(),
+            label,
+            started: Instant::now(),
+            attempts: Mutex::new(vec![]),
+            exhausted: Mutex::new(None),
+        }
+    }
+
+    /// Records a failed attempt of `operation`, `Ok` if it may be retried
+    pub fn retry(
+        &self,
+        class: ErrorClass,
+        operation: &str,
+        error: &dyn Debug,
+    ) -> Result<(), RetryBudgetExhausted> {
+        let mut exhausted = self.exhausted.lock().unwrap();
+        let mut attempts = self.attempts.lock().unwrap();
+        attempts.push(Attempt {
+            class,
+            operation: operation.to_string(),
+            error: format!("{:?}", error),
+            at: self.started.elapsed(),
+        });
+        if let Some(exhausted) = exhausted.as_mut() {
+            // Spent already, the attempt is only recorded
+            exhausted.attempts = attempts.clone();
+            return Err(exhausted.clone());
+        }
+        let Some(reason) = self.exceeded(class, &attempts) else {
+            return Ok(());
+        };
+        RETRY_BUDGET_EXHAUSTED
+            .with_label_values(&[class.as_str()])
+            .inc();
+        let error = RetryBudgetExhausted {
+            label: self.label.clone(),
+            reason,
+            elapsed: self.started.elapsed(),
+            attempts: attempts.clone(),
+        };
+        *exhausted = Some(error.clone());
+        Err(error)
+    }
+
+    /// Why the budget is spent once `attempts` were made, if it is
+    fn exceeded(&self, class: ErrorClass, attempts: &[Attempt]) -> Option<String> {
+        let of_class = attempts
+            .iter()
+            .filter(|attempt| attempt.class == class)
+            .count() as u32;
+        let class_limit = self
+            .config
+            .max_attempts_per_class
+            .get(class.as_str())
+            .copied();
+        if let Some(limit) = class_limit.filter(|limit| of_class > *limit) {
+            return Some(format!(
+                "{} attempts of {} over {}",
+                of_class,
+                class.as_str(),
+                limit
+            ));
+        }
+        if attempts.len() as u32 > self.config.max_attempts {
+            return Some(format!(
+                "{} attempts over {}",
+                attempts.len(),
+                self.config.max_attempts
+            ));
+        }
+        let max_wall_clock = Duration::from_secs(self.config.max_wall_clock_secs);
+        if self.started.elapsed() > max_wall_clock {
+            return Some(format!(
+                "running for over {}s",
+                self.config.max_wall_clock_secs
+            ));
+        }
+        None
+    }
+
+    /// The budget's report once it's spent
+    pub fn exhausted(&self) -> Option<RetryBudgetExhausted> {
+        self.exhausted.lock().unwrap().clone()
+    }
+}
+
+/// Every attempt a budget paid for, the error a batch fails with once its budget is spent
+#[derive(Clone, Debug)]
+pub struct RetryBudgetExhausted {
+    pub label: String,
+    pub reason: String,
+    pub elapsed: Duration,
+    pub attempts: Vec<Attempt>,
+}
+
+impl Display for RetryBudgetExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Retry budget of {} exhausted after {} attempts in {:.1}s ({})",
+            self.label,
+            self.attempts.len(),
+            self.elapsed.as_secs_f64(),
+            self.reason
+        )?;
+        for (index, attempt) in self.attempts.iter().enumerate() {
+            write!(
+                f,
+                "\n  #{} at {:.1}s {} {}: {}",
+                index + 1,
+                attempt.at.as_secs_f64(),
+                attempt.class.as_str(),
+                attempt.operation,
+                attempt.error
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RetryBudgetExhausted {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn config(max_attempts: u32, per_class: &[(&str, u32)]) -> RetryBudgetConfig {
+        RetryBudgetConfig {
+            enabled: true,
+            max_attempts,
+            max_wall_clock_secs: 3600,
+            max_attempts_per_class: per_class
+                .iter()
+                .map(|(class, limit)| (class.to_string(), *limit))
+                .collect::<HashMap<_, _>>(),
+        }
+    }
+
+    #[test]
+    fn test_class_limit_and_report() {
+        let budget = RetryBudget::new(
+            &config(10, &[("db_query", 2)]),
+            "custom_token_processor batch 100..=199".to_string(),
+        );
+        assert!(budget
+            .retry(ErrorClass::DbQuery, "get collection creator", &"NotFound")
+            .is_ok());
+        assert!(budget
+            .retry(ErrorClass::DbConnection, "get connection", &"timed out")
+            .is_ok());
+        assert!(budget
+            .retry(ErrorClass::DbQuery, "get collection creator", &"NotFound")
+            .is_ok());
+        let exhausted = budget
+            .retry(ErrorClass::DbQuery, "get object owner", &"NotFound")
+            .unwrap_err();
+        let report = exhausted.to_string();
+        let lines = report.lines().collect::<Vec<_>>();
+        assert!(lines[0].starts_with(
+            "Retry budget of custom_token_processor batch 100..=199 exhausted after 3 attempts in"
+        ));
+        assert!(lines[0].ends_with("(3 attempts of db_query over 2)"));
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with("  #1 at "));
+        assert!(lines[1].ends_with(" db_query get collection creator: \"NotFound\""));
+        assert!(lines[2].ends_with(" db_connection get connection: \"timed out\""));
+        assert!(lines[3].ends_with(" db_query get object owner: \"NotFound\""));
+
+        // Spent, later attempts fail right away and show up in the report
+        let exhausted = budget
+            .retry(ErrorClass::DbConnection, "get connection", &"timed out")
+            .unwrap_err();
+        assert_eq!(exhausted.attempts.len(), 4);
+        assert_eq!(budget.exhausted().unwrap().attempts.len(), 4);
+    }
+
+    #[test]
+    fn test_total_limit() {
+        let budget = RetryBudget::new(&config(2, &[]), "fetch".to_string());
+        assert!(budget
+            .retry(ErrorClass::Fetch, "get_transactions", &"io")
+            .is_ok());
+        assert!(budget
+            .retry(ErrorClass::Fetch, "get_transactions", &"io")
+            .is_ok());
+        let exhausted = budget
+            .retry(ErrorClass::Fetch, "get_transactions", &"io")
+            .unwrap_err();
+        assert_eq!(exhausted.reason, "3 attempts over 2");
+    }
+
+    #[tokio::test]
+    async fn test_scope() {
+        assert!(retry(ErrorClass::DbQuery, "outside", &"error").is_ok());
+        let budget = Arc::new(RetryBudget::new(&config(0, &[]), "batch".to_string()));
+        let result = scope(Some(budget.clone()), async {
+            retry(ErrorClass::DbQuery, "inside", &"error")
+        })
+        .await;
+        assert!(result.is_err());
+        assert!(budget.exhausted().is_some());
+    }
+}
@@ -0,0 +1,423 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Size limits of indexed columns, checked before rows are inserted. A btree index entry can't be
+//! larger than about 2.7kB, so a longer value, e.g. an absurdly long event type or table key,
+//! fails its insert with "index row size exceeds maximum", and the whole batch with it, without
+//! saying which row it was. Each model registers the limits of its table's indexed text columns
+//! by implementing `RowLimits`, including the ones `index_advisor` may suggest indexing, and the
+//! processors hand their rows to `enforce` before inserting them. A `VARCHAR` column narrower than
+//! that whose values the parser doesn't cut to fit, like the ANS names, registers its width, as a
+//! longer value fails the insert just the same. It only compares lengths until a value is over its
+//! limit; what happens to the row then is the action of the column:
+//! - `truncate`, for display-only columns: the value is cut to fit and ends with
+//!   `[TRUNCATED:<original bytes>]`
+//! - `dead_letter`: the row is left out of the insert and stored whole in `dead_letter_rows`, in
+//!   the transaction of the batch
+//! - `fail`: the batch fails with an error naming the table, column and version
+//!
+//! A row with values over the limits of several columns gets the strictest of their actions. Every
+//! value over its limit is counted in `indexer_oversized_columns_count`. `row_limits.overrides`
+//! replaces the limit or the action of a `table.column`, and `enabled` turns the checks off.
+
+use crate::{
+    counters::OVERSIZED_COLUMNS,
+    custom::driver::config::{OversizeAction, RowLimitsConfig},
+    database::{execute_with_better_error, get_chunks},
+    models::dead_letter_rows::DeadLetterRow,
+    schema::dead_letter_rows,
+    util::hash_str,
+};
+use aptos_logger::warn;
+use diesel::{PgConnection, QueryResult};
+use field_count::FieldCount;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::{borrow::Cow, sync::RwLock};
+
+pub const TRUNCATION_MARKER_PREFIX: &str = "[TRUNCATED:";
+/// Leaves room in the index entry for the other columns of the index
+pub const INDEXED_COLUMN_MAX_BYTES: usize = 2000;
+
+static CONFIG: Lazy<RwLock<RowLimitsConfig>> =
+    Lazy::new(|| RwLock::new(RowLimitsConfig::default()));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ColumnLimit {
+    pub column: &'static str,
+    pub max_bytes: usize,
+    pub action: OversizeAction,
+}
+
+impl ColumnLimit {
+    pub const fn new(column: &'static str, max_bytes: usize, action: OversizeAction) -> Self {
+        Self {
+            column,
+            max_bytes,
+            action,
+        }
+    }
+}
+
+/// Size limits of the indexed text columns of a table, implemented next to its model
+pub trait RowLimits: Clone + Serialize {
+    const TABLE: &'static str;
+    const LIMITS: &'static [ColumnLimit];
+
+    fn transaction_version(&self) -> i64;
+
+    /// The value of the column at `index` in `LIMITS`
+    fn column(&self, index: usize) -> &str;
+
+    fn column_mut(&mut self, index: usize) -> &mut String;
+}
+
+/// Applies `row_limits` of the config to every later `enforce`
+pub fn init(config: &RowLimitsConfig) {
+    *CONFIG.write().unwrap() = config.clone();
+}
+
+/// The rows to insert: the rows themselves if no value is over its limit, otherwise the rows with
+/// their values truncated and without the dead-lettered ones, which are stored. Fails if a value
+/// of a `fail` column is over its limit.
+pub fn enforce<'a, T: RowLimits>(
+    conn: &mut PgConnection,
+    rows: &'a [T],
+) -> QueryResult<Cow<'a, [T]>> {
+    let limits = limits::<T>(&CONFIG.read().unwrap());
+    let checked = check(rows, &limits)?;
+    if let Some(first) = checked.dead_letters.first() {
+        warn!(
+            table = T::TABLE,
+            rows = checked.dead_letters.len(),
+            first_version = first.transaction_version,
+            first_column = first.column_name,
+            "Rows with values over their column's size limit, stored in dead_letter_rows"
+        );
+        insert_dead_letters(conn, &checked.dead_letters)?;
+    }
+    Ok(checked.rows)
+}
+
+struct Checked<'a, T: Clone> {
+    rows: Cow<'a, [T]>,
+    dead_letters: Vec<DeadLetterRow>,
+}
+
+/// The limits of `T`'s table, in the order of `T::LIMITS`, none if the checks are off
+fn limits<T: RowLimits>(config: &RowLimitsConfig) -> Vec<ColumnLimit> {
+    if !config.enabled {
+        return vec![];
+    }
+    T::LIMITS
+        .iter()
+        .map(|limit| {
+            let key = format!("{}.{}", T::TABLE, limit.column);
+            match config.overrides.get(&key) {
+                Some(limit_override) => ColumnLimit {
+                    max_bytes: limit_override.max_bytes.unwrap_or(limit.max_bytes),
+                    action: limit_override.action.unwrap_or(limit.action),
+                    ..*limit
+                },
+                None => *limit,
+            }
+        })
+        .collect()
+}
+
+fn check<'a, T: RowLimits>(rows: &'a [T], limits: &[ColumnLimit]) -> QueryResult<Checked<'a, T>> {
+    let is_over = |row: &T| {
+        limits
+            .iter()
+            .enumerate()
+            .any(|(index, limit)| row.column(index).len() > limit.max_bytes)
+    };
+    let Some(first) = rows.iter().position(is_over) else {
+        return Ok(Checked {
+            rows: Cow::Borrowed(rows),
+            dead_letters: vec![],
+        });
+    };
+
+    let mut kept = rows[..first].to_vec();
+    let mut dead_letters = vec![];
+    for row in &rows[first..] {
+        let over = limits
+            .iter()
+            .enumerate()
+            .filter(|(index, limit)| row.column(*index).len() > limit.max_bytes)
+            .collect::<Vec<_>>();
+        if over.is_empty() {
+            kept.push(row.clone());
+            continue;
+        }
+        for (_, limit) in &over {
+            OVERSIZED_COLUMNS
+                .with_label_values(&[T::TABLE, limit.column, action_label(limit.action)])
+                .inc();
+        }
+        let strictest = |action| over.iter().find(|(_, limit)| limit.action == action);
+        if let Some((index, limit)) = strictest(OversizeAction::Fail) {
+            return Err(diesel::result::Error::QueryBuilderError(
+                format!(
+                    "Value of {}.{} at version {} is {} bytes, over its limit of {}",
+                    T::TABLE,
+                    limit.column,
+                    row.transaction_version(),
+                    row.column(*index).len(),
+                    limit.max_bytes
+                )
+                .into(),
+            ));
+        }
+        if let Some((index, limit)) = strictest(OversizeAction::DeadLetter) {
+            let row_data = serde_json::to_value(row).unwrap_or_default();
+            dead_letters.push(DeadLetterRow {
+                table_name: T::TABLE.to_string(),
+                transaction_version: row.transaction_version(),
+                row_hash: hash_str(&row_data.to_string()),
+                column_name: limit.column.to_string(),
+                column_bytes: row.column(*index).len() as i64,
+                max_bytes: limit.max_bytes as i64,
+                row_data,
+            });
+            continue;
+        }
+        let mut row = row.clone();
+        for (index, limit) in over {
+            truncate_with_marker(row.column_mut(index), limit.max_bytes);
+        }
+        kept.push(row);
+    }
+    Ok(Checked {
+        rows: Cow::Owned(kept),
+        dead_letters,
+    })
+}
+
+/// Cuts `value` to `max_bytes` at a char boundary, marker included
+fn truncate_with_marker(value: &mut String, max_bytes: usize) {
+    let marker = format!("{}{}]", TRUNCATION_MARKER_PREFIX, value.len());
+    let mut end = max_bytes.saturating_sub(marker.len());
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    value.truncate(end);
+    value.push_str(&marker);
+}
+
+fn action_label(action: OversizeAction) -> &'static str {
+    match action {
+        OversizeAction::Truncate => "truncate",
+        OversizeAction::DeadLetter => "dead_letter",
+        OversizeAction::Fail => "fail",
+    }
+}
+
+fn insert_dead_letters(
+    conn: &mut PgConnection,
+    items_to_insert: &[DeadLetterRow],
+) -> QueryResult<()> {
+    let chunks = get_chunks(items_to_insert.len(), DeadLetterRow::field_count());
+    for (start_ind, end_ind) in chunks {
+        execute_with_better_error(
+            conn,
+            diesel::insert_into(dead_letter_rows::table)
+                .values(&items_to_insert[start_ind..end_ind])
+                .on_conflict((
+                    dead_letter_rows::table_name,
+                    dead_letter_rows::transaction_version,
+                    dead_letter_rows::row_hash,
+                ))
+                .do_nothing(),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom::driver::config::ColumnLimitOverride;
+
+    #[derive(Clone, Debug, PartialEq, Serialize)]
+    struct Row {
+        version: i64,
+        label: String,
+        key: String,
+        kind: String,
+    }
+
+    impl RowLimits for Row {
+        const LIMITS: &'static [ColumnLimit] = &[
+            ColumnLimit::new("label", 20, OversizeAction::Truncate),
+            ColumnLimit::new("key", 10, OversizeAction::DeadLetter),
+            ColumnLimit::new("kind", 10, OversizeAction::Fail),
+        ];
+        const TABLE: &'static str = "rows";
+
+        fn transaction_version(&self) -> i64 {
+            self.version
+        }
+
+        fn column(&self, index: usize) -> &str {
+            match index {
+                0 => &self.label,
+                1 => &self.key,
+                _ => &self.kind,
+            }
+        }
+
+        fn column_mut(&mut self, index: usize) -> &mut String {
+            match index {
+                0 => &mut self.label,
+                1 => &mut self.key,
+                _ => &mut self.kind,
+            }
+        }
+    }
+
+    fn row(version: i64, label: &str, key: &str, kind: &str) -> Row {
+        Row {
+            version,
+            label: label.to_string(),
+            key: key.to_string(),
+            kind: kind.to_string(),
+        }
+    }
+
+    fn check_rows(rows: &[Row]) -> QueryResult<Checked<Row>> {
+        check(rows, &limits::<Row>(&RowLimitsConfig::default()))
+    }
+
+    #[test]
+    fn test_within_limits() {
+        let rows = vec![row(1, "label", "key", "kind")];
+        let checked = check_rows(&rows).unwrap();
+        assert!(matches!(checked.rows, Cow::Borrowed(_)));
+        assert!(checked.dead_letters.is_empty());
+    }
+
+    #[test]
+    fn test_truncate() {
+        let rows = vec![
+            row(1, "label", "key", "kind"),
+            row(2, &"é".repeat(20), "key", "kind"),
+        ];
+        let checked = check_rows(&rows).unwrap();
+        assert_eq!(checked.rows[0], rows[0]);
+        // 40 bytes cut to 20 with the marker, at a char boundary
+        let label = &checked.rows[1].label;
+        assert_eq!(
+            label,
+            &format!("{}{}40]", "é".repeat(3), TRUNCATION_MARKER_PREFIX)
+        );
+        assert!(label.len() <= 20);
+        assert!(checked.dead_letters.is_empty());
+    }
+
+    #[test]
+    fn test_dead_letter() {
+        let rows = vec![
+            row(1, "label", &"k".repeat(11), "kind"),
+            row(2, &"l".repeat(21), "key", "kind"),
+        ];
+        let checked = check_rows(&rows).unwrap();
+        assert_eq!(checked.rows.len(), 1);
+        assert_eq!(checked.rows[0].version, 2);
+        assert_eq!(checked.dead_letters.len(), 1);
+        let dead_letter = &checked.dead_letters[0];
+        assert_eq!(
+            (
+                dead_letter.table_name.as_str(),
+                dead_letter.transaction_version,
+                dead_letter.column_name.as_str(),
+                dead_letter.column_bytes,
+                dead_letter.max_bytes
+            ),
+            ("rows", 1, "key", 11, 10)
+        );
+        assert_eq!(
+            dead_letter.row_data,
+            serde_json::to_value(&rows[0]).unwrap()
+        );
+
+        // Dead-lettering wins over truncating, the row is stored as it was
+        let rows = vec![row(3, &"l".repeat(21), &"k".repeat(11), "kind")];
+        let checked = check_rows(&rows).unwrap();
+        assert!(checked.rows.is_empty());
+        assert_eq!(checked.dead_letters[0].row_data["label"], "l".repeat(21));
+    }
+
+    #[test]
+    fn test_fail() {
+        let rows = vec![
+            row(1, "label", "key", "kind"),
+            row(2, "label", &"k".repeat(11), &"x".repeat(11)),
+        ];
+        let Err(err) = check_rows(&rows) else {
+            panic!("Expected the check to fail");
+        };
+        assert_eq!(
+            err.to_string(),
+            "Value of rows.kind at version 2 is 11 bytes, over its limit of 10"
+        );
+    }
+
+    #[test]
+    fn test_overrides() {
+        let mut config = RowLimitsConfig::default();
+        config
+            .overrides
+            .insert("rows.kind".to_string(), ColumnLimitOverride {
+                max_bytes: Some(20),
+                action: Some(OversizeAction::DeadLetter),
+            });
+        let limits = limits::<Row>(&config);
+        assert_eq!(
+            limits[2],
+            ColumnLimit::new("kind", 20, OversizeAction::DeadLetter)
+        );
+        assert_eq!(limits[0], Row::LIMITS[0]);
+
+        config.enabled = false;
+        let rows = vec![row(1, "label", "key", &"x".repeat(100))];
+        let checked = check(&rows, &limits::<Row>(&config)).unwrap();
+        assert!(matches!(checked.rows, Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_asset_store_limits() {
+        use crate::models::asset_stores::CurrentAssetStore;
+
+        let store = |version: i64, asset_type: String| CurrentAssetStore {
+            store_address: format!("0x{:064x}", version),
+            asset_type,
+            owner_address: format!("0x{:064x}", version),
+            store_kind: "coin".to_string(),
+            is_frozen: false,
+            created_transaction_version: version,
+            deleted_transaction_version: None,
+            last_transaction_version: version,
+        };
+        // A coin type nesting type arguments until it's over what the primary key can index
+        let nested = format!("0x1::coin::CoinStore<{}>", "0x1::pair::Pair<".repeat(200));
+        let rows = vec![
+            store(1, "0x1::aptos_coin::AptosCoin".to_string()),
+            store(2, nested),
+        ];
+        let config = RowLimitsConfig::default();
+        let checked = check(&rows, &limits::<CurrentAssetStore>(&config)).unwrap();
+        assert_eq!(checked.rows.len(), 1);
+        assert_eq!(checked.rows[0].last_transaction_version, 1);
+        assert_eq!(
+            (
+                checked.dead_letters[0].table_name.as_str(),
+                checked.dead_letters[0].column_name.as_str(),
+                checked.dead_letters[0].transaction_version
+            ),
+            ("current_asset_stores", "asset_type", 2)
+        );
+    }
+}
@@ -0,0 +1,250 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Spreads hot message keys (e.g. the framework account 0x1) over several partitions. A salted
+//! key is `<logical key>#<version % salt_factor>`; the unsalted key is always sent in the
+//! `logical_key` header. Consumers that need per-account ordering must group by that header, and
+//! for salted accounts ordering only holds within each salt bucket. Configured hot keys that are
+//! addresses are standardized like the keys they're matched against, so `0x1` salts the framework
+//! account's key. Salted messages are counted by configured hot key, and under `auto` for the
+//! detected ones, which are logged instead, to keep the metric's cardinality bounded.
+
+pub use crate::client::LOGICAL_KEY_HEADER;
+use crate::{
+    counters::{PUBLISHER_HOT_KEYS, PUBLISHER_SALTED_MESSAGES},
+    custom::driver::config::KeySaltingConfig,
+    util::standardize_address,
+};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Don't call anything hot before we've seen this many keys
+const MIN_WINDOW_SAMPLES: usize = 1_000;
+/// How often (in observed keys) detected hot keys are re-checked for cooling down
+const RECHECK_EVERY: u64 = 1_000;
+/// Label of the salted messages of detected hot keys
+const DETECTED_KEY_LABEL: &str = "auto";
+
+/// Snapshot of the hot key detection, meant for status reporting
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct HotKeyStats {
+    pub window_len: usize,
+    pub configured_hot_keys: Vec<String>,
+    /// Keys currently over the share threshold, with their share of the window
+    pub detected_hot_keys: Vec<(String, f64)>,
+    pub salted_messages: u64,
+}
+
+pub struct KeySalter {
+    enabled: bool,
+    salt_factor: u64,
+    hot_keys: HashSet<String>,
+    auto_share_threshold: Option<f64>,
+    window_size: usize,
+    window: VecDeque<String>,
+    counts: HashMap<String, usize>,
+    detected: HashSet<String>,
+    observed: u64,
+    salted_messages: u64,
+}
+
+impl KeySalter {
+    pub fn new(config: &KeySaltingConfig) -> Self {
+        Self {
+            enabled: config.enabled && config.salt_factor > 1,
+            salt_factor: config.salt_factor,
+            hot_keys: config.hot_keys.iter().map(|key| normalize(key)).collect(),
+            auto_share_threshold: config.auto_share_threshold,
+            window_size: config.window_size,
+            window: VecDeque::new(),
+            counts: HashMap::new(),
+            detected: HashSet::new(),
+            observed: 0,
+            salted_messages: 0,
+        }
+    }
+
+    /// Returns the key to produce with. Only differs from `logical_key` for hot keys.
+    pub fn salt(&mut self, logical_key: &str, version: u64) -> String {
+        if !self.enabled {
+            return logical_key.to_string();
+        }
+        if self.auto_share_threshold.is_some() {
+            self.observe(logical_key);
+        }
+        let label = if self.hot_keys.contains(logical_key) {
+            logical_key
+        } else if self.detected.contains(logical_key) {
+            DETECTED_KEY_LABEL
+        } else {
+            return logical_key.to_string();
+        };
+        self.salted_messages += 1;
+        PUBLISHER_SALTED_MESSAGES.with_label_values(&[label]).inc();
+        format!("{}#{}", logical_key, version % self.salt_factor)
+    }
+
+    fn observe(&mut self, logical_key: &str) {
+        self.window.push_back(logical_key.to_string());
+        *self.counts.entry(logical_key.to_string()).or_default() += 1;
+        if self.window.len() > self.window_size {
+            if let Some(evicted) = self.window.pop_front() {
+                if let Some(count) = self.counts.get_mut(&evicted) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.counts.remove(&evicted);
+                        self.detected.remove(&evicted);
+                    }
+                }
+            }
+        }
+        self.refresh_detected(logical_key);
+        self.observed += 1;
+        if self.observed % RECHECK_EVERY == 0 {
+            // A detected key that stops showing up isn't refreshed above, so re-check them here
+            let keys = self.detected.iter().cloned().collect::<Vec<_>>();
+            for key in keys {
+                self.refresh_detected(&key);
+            }
+        }
+    }
+
+    fn refresh_detected(&mut self, key: &str) {
+        let threshold = match self.auto_share_threshold {
+            Some(threshold) => threshold,
+            None => return,
+        };
+        if self.window.len() < MIN_WINDOW_SAMPLES.min(self.window_size) {
+            return;
+        }
+        let is_hot = self.share(key) > threshold;
+        let changed = if is_hot {
+            self.detected.insert(key.to_string())
+        } else {
+            self.detected.remove(key)
+        };
+        if changed {
+            PUBLISHER_HOT_KEYS.set(self.detected.len() as i64);
+            aptos_logger::info!(
+                key = key,
+                share = self.share(key),
+                is_hot = is_hot,
+                "Publisher hot key status changed"
+            );
+        }
+    }
+
+    fn share(&self, key: &str) -> f64 {
+        if self.window.is_empty() {
+            return 0.0;
+        }
+        *self.counts.get(key).unwrap_or(&0) as f64 / self.window.len() as f64
+    }
+
+    pub fn stats(&self) -> HotKeyStats {
+        let mut configured_hot_keys = self.hot_keys.iter().cloned().collect::<Vec<_>>();
+        configured_hot_keys.sort();
+        let mut detected_hot_keys = self
+            .detected
+            .iter()
+            .map(|key| (key.clone(), self.share(key)))
+            .collect::<Vec<_>>();
+        detected_hot_keys.sort_by(|a, b| a.0.cmp(&b.0));
+        HotKeyStats {
+            window_len: self.window.len(),
+            configured_hot_keys,
+            detected_hot_keys,
+            salted_messages: self.salted_messages,
+        }
+    }
+}
+
+/// Addresses as the partition keys have them, any other key as it is
+fn normalize(key: &str) -> String {
+    let is_address = key.strip_prefix("0x").map_or(false, |hex| {
+        !hex.is_empty() && hex.len() <= 64 && hex.chars().all(|c| c.is_ascii_hexdigit())
+    });
+    if is_address {
+        standardize_address(&key.to_lowercase())
+    } else {
+        key.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn salter(hot_keys: &[&str], auto_share_threshold: Option<f64>) -> KeySalter {
+        KeySalter::new(&KeySaltingConfig {
+            enabled: true,
+            hot_keys: hot_keys.iter().map(|key| key.to_string()).collect(),
+            salt_factor: 4,
+            auto_share_threshold,
+            window_size: 1_000,
+        })
+    }
+
+    #[test]
+    fn test_configured_hot_keys() {
+        let framework = standardize_address("0x1");
+        let mut salter = salter(&["0x1", "0xA", "12345"], None);
+        assert_eq!(salter.salt(&framework, 7), format!("{}#3", framework));
+        assert_eq!(
+            salter.salt(&standardize_address("0xa"), 8),
+            format!("{}#0", standardize_address("0xa"))
+        );
+        // Not an address, matched as it is
+        assert_eq!(salter.salt("12345", 9), "12345#1");
+        let other = standardize_address("0x2");
+        assert_eq!(salter.salt(&other, 7), other);
+        assert_eq!(salter.stats().salted_messages, 3);
+        assert_eq!(
+            PUBLISHER_SALTED_MESSAGES
+                .with_label_values(&[&framework])
+                .get(),
+            1
+        );
+
+        let mut disabled = KeySalter::new(&KeySaltingConfig {
+            hot_keys: vec!["0x1".to_string()],
+            ..KeySaltingConfig::default()
+        });
+        assert_eq!(disabled.salt(&framework, 7), framework);
+    }
+
+    #[test]
+    fn test_detected_hot_keys() {
+        let hot = standardize_address("0xcafe");
+        let mut salter = salter(&[], Some(0.5));
+        let before = PUBLISHER_SALTED_MESSAGES
+            .with_label_values(&[DETECTED_KEY_LABEL])
+            .get();
+        let mut salted = 0;
+        for version in 0..2_000u64 {
+            let key = if version % 4 == 0 {
+                standardize_address(&format!("0x{:x}", version))
+            } else {
+                hot.clone()
+            };
+            if salter.salt(&key, version) != key {
+                salted += 1;
+            }
+        }
+        assert!(salted > 0);
+        assert_eq!(salter.stats().detected_hot_keys.len(), 1);
+        assert_eq!(salter.stats().detected_hot_keys[0].0, hot);
+        // Counted under one label, whichever keys were detected
+        assert_eq!(
+            PUBLISHER_SALTED_MESSAGES
+                .with_label_values(&[DETECTED_KEY_LABEL])
+                .get()
+                - before,
+            salted
+        );
+        assert_eq!(
+            PUBLISHER_SALTED_MESSAGES.with_label_values(&[&hot]).get(),
+            0
+        );
+    }
+}
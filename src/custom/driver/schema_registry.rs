@@ -0,0 +1,540 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! The schema registry the Avro messages are published against, with
+//! `publisher_serialization.format` set to `avro`, see `driver::publisher::avro`. Only built with
+//! the `avro` feature.
+//!
+//! At startup, before any publisher is built, the schema of transactions, events and write set
+//! changes is sent to the registry at `schema_registry.url` for every one of them with a topic,
+//! under the subject `<topic>-value`. It's first checked against the latest version of the
+//! subject, a subject without versions taking any schema. A schema the registry finds
+//! incompatible stops the indexer with the registry's reasons, rather than failing the first
+//! send. It's then registered, which the registry answers with the id of the existing version if
+//! it's already there, or only looked up without `auto_register`, a schema that isn't registered
+//! stopping the indexer too. The ids are kept for the process, and publishers frame every payload
+//! with the id of its topic's subject.
+
+use crate::{
+    client::MODEL_TOPIC_KEYS,
+    custom::driver::{
+        config::{DriverConfig, SchemaRegistryConfig, SerializationFormat},
+        publisher::avro,
+    },
+};
+use anyhow::Context;
+use aptos_logger::info;
+use once_cell::sync::Lazy;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    sync::RwLock,
+    time::Duration,
+};
+
+const CONTENT_TYPE: &str = "application/vnd.schemaregistry.v1+json";
+/// Of the registry's error responses, for a subject that doesn't exist
+const SUBJECT_NOT_FOUND: u32 = 40401;
+/// For a subject without the version, or the schema, asked for
+const VERSION_NOT_FOUND: u32 = 40402;
+const SCHEMA_NOT_FOUND: u32 = 40403;
+
+/// Schema id by subject, of the schemas registered or looked up so far
+static SCHEMA_IDS: Lazy<RwLock<HashMap<String, u32>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+pub struct SchemaRegistry {
+    client: reqwest::Client,
+    config: SchemaRegistryConfig,
+}
+
+/// A request the registry answered with an error, or didn't answer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RegistryError {
+    /// `None` if there was no response, e.g. a timeout
+    pub status: Option<u16>,
+    pub error_code: Option<u32>,
+    pub message: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    error_code: u32,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct SchemaId {
+    id: u32,
+}
+
+#[derive(Deserialize)]
+struct Compatibility {
+    is_compatible: bool,
+    #[serde(default)]
+    messages: Vec<String>,
+}
+
+/// The subject the schema of the messages on `topic` is registered under
+pub fn subject(topic: &str) -> String {
+    format!("{}-value", topic)
+}
+
+/// Registers or looks up the schema of every model published as Avro, if the format is `avro`.
+/// Fails on an invalid config, a schema the registry finds incompatible, or doesn't have without
+/// `auto_register`, or a registry that can't be reached.
+pub async fn init(config: &DriverConfig) -> anyhow::Result<()> {
+    let serialization = &config.publisher_serialization;
+    if serialization.format != SerializationFormat::Avro {
+        return Ok(());
+    }
+    serialization
+        .validate()
+        .context("Invalid publisher_serialization config")?;
+    let registry = SchemaRegistry::new(&serialization.schema_registry)?;
+    for (model, subject) in subjects(config) {
+        let schema = avro::schema_json(model).unwrap().to_string();
+        let schema_id = registry.resolve(&subject, &schema).await.with_context(|| {
+            format!(
+                "The schema registry at {} didn't take the Avro schema of {} under {}",
+                serialization.schema_registry.url, model, subject
+            )
+        })?;
+        SCHEMA_IDS
+            .write()
+            .unwrap()
+            .insert(subject.clone(), schema_id);
+        info!(
+            model = model,
+            subject = subject,
+            schema_id = schema_id,
+            "Resolved the Avro schema"
+        );
+    }
+    Ok(())
+}
+
+/// The schema id by model of every model published as Avro, none if the format isn't `avro`.
+/// Fails if one wasn't resolved by `init`.
+pub fn schema_ids(config: &DriverConfig) -> anyhow::Result<HashMap<&'static str, u32>> {
+    if config.publisher_serialization.format != SerializationFormat::Avro {
+        return Ok(HashMap::new());
+    }
+    let resolved = SCHEMA_IDS.read().unwrap();
+    subjects(config)
+        .into_iter()
+        .map(|(model, subject)| match resolved.get(&subject) {
+            Some(schema_id) => Ok((model, *schema_id)),
+            None => Err(anyhow::anyhow!(
+                "The Avro schema of {} under {} wasn't registered at startup",
+                model,
+                subject
+            )),
+        })
+        .collect()
+}
+
+/// The models published as Avro with a topic, with their subject
+fn subjects(config: &DriverConfig) -> Vec<(&'static str, String)> {
+    avro::MODELS
+        .iter()
+        .filter_map(|model| {
+            let topic_key = MODEL_TOPIC_KEYS
+                .iter()
+                .find(|(name, _)| name == model)
+                .map(|(_, topic_key)| *topic_key)?;
+            let topic = config.topics.get(topic_key)?;
+            Some((*model, subject(topic)))
+        })
+        .collect()
+}
+
+impl SchemaRegistry {
+    pub fn new(config: &SchemaRegistryConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_millis))
+            .build()
+            .context("Failed to build the schema registry client")?;
+        Ok(Self {
+            client,
+            config: config.clone(),
+        })
+    }
+
+    /// The id of `schema` under `subject`, once checked for compatibility and registered, or
+    /// looked up without `auto_register`
+    pub async fn resolve(&self, subject: &str, schema: &str) -> anyhow::Result<u32> {
+        self.check_compatibility(subject, schema).await?;
+        if self.config.auto_register {
+            return Ok(self.register(subject, schema).await?);
+        }
+        match self.lookup(subject, schema).await {
+            Err(err) if matches!(err.error_code, Some(SUBJECT_NOT_FOUND | SCHEMA_NOT_FOUND)) => {
+                anyhow::bail!(
+                    "The schema isn't registered and auto_register is off: {}",
+                    err
+                )
+            },
+            result => Ok(result?),
+        }
+    }
+
+    /// Fails with the registry's reasons if `schema` is incompatible with the latest version of
+    /// `subject`. Any schema is compatible with a subject without versions.
+    pub async fn check_compatibility(&self, subject: &str, schema: &str) -> anyhow::Result<()> {
+        let path = format!(
+            "compatibility/subjects/{}/versions/latest?verbose=true",
+            subject
+        );
+        let compatibility: Compatibility = match self.post(&path, schema).await {
+            Ok(compatibility) => compatibility,
+            Err(err) if matches!(err.error_code, Some(SUBJECT_NOT_FOUND | VERSION_NOT_FOUND)) => {
+                return Ok(())
+            },
+            Err(err) => return Err(err.into()),
+        };
+        if !compatibility.is_compatible {
+            let reasons = if compatibility.messages.is_empty() {
+                "no reason given".to_string()
+            } else {
+                compatibility.messages.join("; ")
+            };
+            anyhow::bail!(
+                "The schema is incompatible with the latest version of {}: {}",
+                subject,
+                reasons
+            );
+        }
+        Ok(())
+    }
+
+    /// The id of `schema` under `subject`, registered as a new version if it isn't one yet
+    pub async fn register(&self, subject: &str, schema: &str) -> Result<u32, RegistryError> {
+        let path = format!("subjects/{}/versions", subject);
+        let registered: SchemaId = self.post(&path, schema).await?;
+        Ok(registered.id)
+    }
+
+    /// The id of `schema` if it's a version of `subject`
+    pub async fn lookup(&self, subject: &str, schema: &str) -> Result<u32, RegistryError> {
+        let path = format!("subjects/{}", subject);
+        let found: SchemaId = self.post(&path, schema).await?;
+        Ok(found.id)
+    }
+
+    /// POSTs the Avro `schema` to `path` of the registry
+    async fn post<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        schema: &str,
+    ) -> Result<T, RegistryError> {
+        let url = format!("{}/{}", self.config.url.trim_end_matches('/'), path);
+        let mut request = self
+            .client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, CONTENT_TYPE)
+            .json(&json!({ "schema": schema }));
+        if let Some(username) = &self.config.username {
+            request = request.basic_auth(username, self.config.password.as_ref());
+        }
+        let response = request.send().await.map_err(|err| RegistryError {
+            status: None,
+            error_code: None,
+            message: err.to_string(),
+        })?;
+        let status = response.status();
+        let body = response.bytes().await.map_err(|err| RegistryError {
+            status: Some(status.as_u16()),
+            error_code: None,
+            message: err.to_string(),
+        })?;
+        if !status.is_success() {
+            return Err(match serde_json::from_slice::<ErrorBody>(&body) {
+                Ok(error) => RegistryError {
+                    status: Some(status.as_u16()),
+                    error_code: Some(error.error_code),
+                    message: error.message,
+                },
+                Err(_) => RegistryError {
+                    status: Some(status.as_u16()),
+                    error_code: None,
+                    message: String::from_utf8_lossy(&body).into_owned(),
+                },
+            });
+        }
+        serde_json::from_slice(&body).map_err(|err| RegistryError {
+            status: Some(status.as_u16()),
+            error_code: None,
+            message: format!("Unexpected response: {}", err),
+        })
+    }
+}
+
+impl Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.status, self.error_code) {
+            (Some(status), Some(error_code)) => write!(
+                f,
+                "status {}, error {}: {}",
+                status, error_code, self.message
+            ),
+            (Some(status), None) => write!(f, "status {}: {}", status, self.message),
+            (None, _) => write!(f, "no response: {}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::{FORMAT_HEADER, TRANSACTION_VERSION_HEADER},
+        custom::{
+            driver::{envelope::Envelope, publisher::Publisher},
+            test_utils,
+        },
+    };
+    use apache_avro::{from_avro_datum, types::Value, Schema};
+    use poem::{
+        handler,
+        http::StatusCode,
+        listener::{Acceptor, Listener, TcpListener},
+        web::Data,
+        Body, EndpointExt, Request, Response, Server,
+    };
+    use std::sync::{Arc, Mutex};
+
+    /// Answers as a registry whose subjects have no versions until registered, each registered
+    /// schema getting the next id from `first_id`
+    #[derive(Default)]
+    struct Registry {
+        first_id: u32,
+        /// Compatibility checks fail with this reason
+        incompatible: Option<String>,
+        /// Schema id by subject
+        subjects: Mutex<HashMap<String, u32>>,
+        /// The path and schema of every request
+        requests: Mutex<Vec<(String, String)>>,
+    }
+
+    fn respond(status: StatusCode, body: serde_json::Value) -> Response {
+        Response::builder()
+            .status(status)
+            .content_type(CONTENT_TYPE)
+            .body(body.to_string())
+    }
+
+    fn not_found(error_code: u32) -> Response {
+        respond(
+            StatusCode::NOT_FOUND,
+            json!({ "error_code": error_code, "message": "Not found" }),
+        )
+    }
+
+    #[handler]
+    async fn answer(request: &Request, body: Body, registry: Data<&Arc<Registry>>) -> Response {
+        let body: serde_json::Value =
+            serde_json::from_slice(&body.into_vec().await.unwrap()).unwrap();
+        let schema = body["schema"].as_str().unwrap().to_string();
+        let path = request.uri().path().to_string();
+        registry
+            .requests
+            .lock()
+            .unwrap()
+            .push((path.clone(), schema));
+        let mut subjects = registry.subjects.lock().unwrap();
+        let segments = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+        match segments.as_slice() {
+            ["compatibility", "subjects", subject, "versions", "latest"] => {
+                if !subjects.contains_key(*subject) {
+                    return not_found(SUBJECT_NOT_FOUND);
+                }
+                match &registry.incompatible {
+                    Some(reason) => respond(
+                        StatusCode::OK,
+                        json!({ "is_compatible": false, "messages": [reason] }),
+                    ),
+                    None => respond(StatusCode::OK, json!({ "is_compatible": true })),
+                }
+            },
+            ["subjects", subject, "versions"] => {
+                let next_id = registry.first_id + subjects.len() as u32;
+                let id = *subjects.entry(subject.to_string()).or_insert(next_id);
+                respond(StatusCode::OK, json!({ "id": id }))
+            },
+            ["subjects", subject] => match subjects.get(*subject) {
+                Some(id) => respond(
+                    StatusCode::OK,
+                    json!({ "subject": subject, "id": id, "version": 1 }),
+                ),
+                None => not_found(SUBJECT_NOT_FOUND),
+            },
+            _ => not_found(0),
+        }
+    }
+
+    /// Serves `registry` on a free port, its URL
+    async fn serve(registry: Registry) -> (String, Arc<Registry>) {
+        let registry = Arc::new(registry);
+        let acceptor = TcpListener::bind("127.0.0.1:0")
+            .into_acceptor()
+            .await
+            .unwrap();
+        let address = *acceptor.local_addr()[0].as_socket_addr().unwrap();
+        let app = answer.data(registry.clone());
+        tokio::spawn(Server::new_with_acceptor(acceptor).run(app));
+        (format!("http://{}/", address), registry)
+    }
+
+    /// Every test has its own topics, since the ids are kept for the process
+    fn config(url: &str, prefix: &str, auto_register: bool) -> DriverConfig {
+        let mut config = test_utils::driver_config(json!({
+            "transaction_topic": format!("{}.transactions", prefix),
+            "event_topic": format!("{}.events", prefix),
+        }));
+        config.publisher_serialization.format = SerializationFormat::Avro;
+        config.publisher_serialization.schema_registry = SchemaRegistryConfig {
+            url: url.to_string(),
+            auto_register,
+            ..SchemaRegistryConfig::default()
+        };
+        config
+    }
+
+    #[tokio::test]
+    async fn test_registers_and_frames() {
+        let (url, registry) = serve(Registry {
+            first_id: 0x0100_0002,
+            ..Registry::default()
+        })
+        .await;
+        let config = config(&url, "registers", true);
+        init(&config).await.unwrap();
+
+        // Checked, then registered, for the models with a topic only
+        let requests = registry.requests.lock().unwrap().clone();
+        let paths = requests
+            .iter()
+            .map(|(path, _)| path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, [
+            "/compatibility/subjects/registers.transactions-value/versions/latest",
+            "/subjects/registers.transactions-value/versions",
+            "/compatibility/subjects/registers.events-value/versions/latest",
+            "/subjects/registers.events-value/versions",
+        ]);
+        let registered = Schema::parse_str(&requests[1].1).unwrap();
+        assert_eq!(
+            registered.canonical_form(),
+            Schema::parse(&avro::schema_json("TransactionModel").unwrap())
+                .unwrap()
+                .canonical_form()
+        );
+        assert_eq!(
+            schema_ids(&config).unwrap(),
+            HashMap::from([
+                ("TransactionModel", 0x0100_0002),
+                ("EventModel", 0x0100_0003)
+            ])
+        );
+
+        let (publisher, recorded) =
+            Publisher::in_memory(config.clone(), Envelope::new(4, "custom_default_processor"));
+        let transactions = test_utils::fixture("batch1.json");
+        publisher
+            .send_transaction("TransactionModel", &transactions[..1])
+            .unwrap();
+        let messages = recorded.on_topic("registers.transactions");
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].header(FORMAT_HEADER), Some("avro"));
+        // The magic byte, then the big-endian schema id
+        assert_eq!(messages[0].payload[..5], [0, 1, 0, 0, 2]);
+        let mut datum = &messages[0].payload[5..];
+        let value = from_avro_datum(
+            &Schema::parse(&avro::schema_json("TransactionModel").unwrap()).unwrap(),
+            &mut datum,
+            None,
+        )
+        .unwrap();
+        let version = messages[0].header(TRANSACTION_VERSION_HEADER).unwrap();
+        match value {
+            Value::Record(fields) => assert_eq!(
+                fields[0],
+                ("version".to_string(), Value::Long(version.parse().unwrap()))
+            ),
+            other => panic!("Not a record {:?}", other),
+        }
+
+        // Registered again at the next start, with the same ids
+        init(&config).await.unwrap();
+        assert_eq!(schema_ids(&config).unwrap()["EventModel"], 0x0100_0003);
+    }
+
+    #[tokio::test]
+    async fn test_incompatible_schema_fails_init() {
+        let (url, registry) = serve(Registry {
+            first_id: 1,
+            incompatible: Some("reader field 'version' has no default".to_string()),
+            subjects: Mutex::new(HashMap::from([(
+                "incompatible.transactions-value".to_string(),
+                1,
+            )])),
+            ..Registry::default()
+        })
+        .await;
+        let config = config(&url, "incompatible", true);
+        let err = format!("{:#}", init(&config).await.unwrap_err());
+        assert!(
+            err.contains("didn't take the Avro schema of TransactionModel"),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains("reader field 'version' has no default"),
+            "{}",
+            err
+        );
+        // Nothing registered
+        assert_eq!(registry.requests.lock().unwrap().len(), 1);
+        assert!(schema_ids(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_lookup_without_auto_register() {
+        let (url, registry) = serve(Registry::default()).await;
+        let config = config(&url, "lookup", false);
+        let err = format!("{:#}", init(&config).await.unwrap_err());
+        assert!(err.contains("auto_register is off"), "{}", err);
+
+        registry.subjects.lock().unwrap().extend([
+            ("lookup.transactions-value".to_string(), 11),
+            ("lookup.events-value".to_string(), 12),
+        ]);
+        init(&config).await.unwrap();
+        assert_eq!(
+            schema_ids(&config).unwrap(),
+            HashMap::from([("TransactionModel", 11), ("EventModel", 12)])
+        );
+        // Never registered
+        assert!(registry
+            .requests
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|(path, _)| !path.ends_with("/versions")));
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_registry() {
+        let config = config("http://127.0.0.1:1", "unreachable", true);
+        let err = format!("{:#}", init(&config).await.unwrap_err());
+        assert!(err.contains("no response"), "{}", err);
+
+        let mut config = config;
+        config.publisher_serialization.schema_registry.url = String::new();
+        assert!(init(&config).await.is_err());
+    }
+}
@@ -0,0 +1,337 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Serialization of published messages, off the tokio workers that run the parsing. A batch of
+//! models is serialized chunk by chunk on a small dedicated rayon pool into buffers taken from a
+//! `BufferPool`, and the publisher produces the borrowed bytes in order on its own thread.
+//! librdkafka copies the payload when a record is enqueued, so a buffer goes back to the pool as
+//! soon as its record is sent, there's nothing to wait for in the delivery callback.
+//!
+//! JSON messages leave without the null bytes of their strings, however deeply nested, like the
+//! rows written to Postgres after `clean_data_for_db`, so consumers storing them don't trip over
+//! them either.
+
+use crate::{
+    counters::{PUBLISH_BATCH_SECONDS, PUBLISH_SERIALIZATION_ALLOCATIONS},
+    custom::driver::config::SerializationConfig,
+    util::recurse_remove_null_bytes_from_json,
+};
+use once_cell::sync::OnceCell;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::{
+    ops::Deref,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+/// Initial capacity of a buffer before any message was seen
+const MIN_BUFFER_CAPACITY: usize = 1024;
+/// The largest recent message size loses 1/`DECAY` of its value per buffer returned, so a single
+/// huge message doesn't keep every buffer large
+const DECAY: usize = 1024;
+
+static SHARED: OnceCell<Arc<SerializationPool>> = OnceCell::new();
+
+/// Buffers sized to the largest recent message
+pub struct BufferPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    max_buffers: usize,
+    largest_recent: AtomicUsize,
+}
+
+impl BufferPool {
+    pub fn new(max_buffers: usize) -> Self {
+        Self {
+            buffers: Mutex::new(vec![]),
+            max_buffers,
+            largest_recent: AtomicUsize::new(MIN_BUFFER_CAPACITY),
+        }
+    }
+
+    pub fn take(self: &Arc<Self>) -> PooledBuffer {
+        let buffer = self.buffers.lock().unwrap().pop().unwrap_or_else(|| {
+            PUBLISH_SERIALIZATION_ALLOCATIONS.inc();
+            Vec::with_capacity(self.largest_recent.load(Ordering::Relaxed))
+        });
+        PooledBuffer {
+            buffer,
+            pool: self.clone(),
+        }
+    }
+
+    fn give_back(&self, mut buffer: Vec<u8>) {
+        let largest = self.observe(buffer.len());
+        // Buffers that grew for an outlier don't stay large
+        if buffer.capacity() > 2 * largest {
+            buffer.shrink_to(largest);
+        }
+        buffer.clear();
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers.len() < self.max_buffers {
+            buffers.push(buffer);
+        }
+    }
+
+    fn observe(&self, len: usize) -> usize {
+        let previous = self.largest_recent.load(Ordering::Relaxed);
+        let largest = (previous - previous / DECAY)
+            .max(len)
+            .max(MIN_BUFFER_CAPACITY);
+        self.largest_recent.store(largest, Ordering::Relaxed);
+        largest
+    }
+
+    pub fn largest_recent(&self) -> usize {
+        self.largest_recent.load(Ordering::Relaxed)
+    }
+}
+
+/// A serialized message, returned to its pool when dropped
+pub struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: Arc<BufferPool>,
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.give_back(std::mem::take(&mut self.buffer));
+    }
+}
+
+pub struct SerializationPool {
+    threads: rayon::ThreadPool,
+    buffers: Arc<BufferPool>,
+    chunk_size: usize,
+}
+
+impl SerializationPool {
+    pub fn new(config: &SerializationConfig) -> Self {
+        Self {
+            threads: rayon::ThreadPoolBuilder::new()
+                .num_threads(config.threads.max(1))
+                .thread_name(|index| format!("publish-serializer-{}", index))
+                .build()
+                .expect("Failed to build the publisher serialization pool"),
+            buffers: Arc::new(BufferPool::new(config.max_pooled_buffers)),
+            chunk_size: config.chunk_size.max(1),
+        }
+    }
+
+    /// The pool of the process. Only the first call's config has an effect, so publishers
+    /// rebuilt on a reload keep the same threads and buffers.
+    pub fn shared(config: &SerializationConfig) -> Arc<Self> {
+        SHARED.get_or_init(|| Arc::new(Self::new(config))).clone()
+    }
+
+    /// Serializes `items` as JSON and hands them to `f` in order, `chunk_size` at a time so
+    /// that only a chunk's buffers are out of the pool. Blocks the calling thread, but a tokio
+    /// worker hands its other tasks over while it waits.
+    pub fn serialize_each<T, F>(&self, model: &str, items: &[T], mut f: F)
+    where
+        T: Serialize + Sync,
+        F: FnMut(&T, Result<&[u8], &serde_json::Error>),
+    {
+        let started = Instant::now();
+        for chunk in items.chunks(self.chunk_size) {
+            let serialized = in_place(|| self.threads.install(|| self.serialize_chunk(chunk)));
+            for (item, payload) in chunk.iter().zip(serialized) {
+                // The buffer is back in the pool once the payload is sent
+                f(item, payload.as_deref());
+            }
+        }
+        PUBLISH_BATCH_SECONDS
+            .with_label_values(&[model])
+            .observe(started.elapsed().as_secs_f64());
+    }
+
+    /// Like `serialize_each`, encoding the `to_proto` message of each item as protobuf instead,
+    /// which can't fail
+    pub fn encode_each<T, P, F>(
+        &self,
+        model: &str,
+        items: &[T],
+        to_proto: impl Fn(&T) -> P + Sync,
+        f: F,
+    ) where
+        T: Sync,
+        P: prost::Message,
+        F: FnMut(&T, &[u8]),
+    {
+        self.write_each(
+            model,
+            items,
+            |item, buffer| {
+                // Only fails when the buffer can't grow, which a `Vec` always can
+                to_proto(item)
+                    .encode(buffer)
+                    .expect("Failed to encode protobuf message")
+            },
+            f,
+        )
+    }
+
+    /// Like `encode_each`, with each item written to its buffer by `write`, e.g. as Avro
+    pub fn write_each<T, F>(
+        &self,
+        model: &str,
+        items: &[T],
+        write: impl Fn(&T, &mut Vec<u8>) + Sync,
+        mut f: F,
+    ) where
+        T: Sync,
+        F: FnMut(&T, &[u8]),
+    {
+        let started = Instant::now();
+        for chunk in items.chunks(self.chunk_size) {
+            let encoded = in_place(|| self.threads.install(|| self.write_chunk(chunk, &write)));
+            for (item, payload) in chunk.iter().zip(encoded) {
+                f(item, &payload);
+            }
+        }
+        PUBLISH_BATCH_SECONDS
+            .with_label_values(&[model])
+            .observe(started.elapsed().as_secs_f64());
+    }
+
+    fn serialize_chunk<T: Serialize + Sync>(
+        &self,
+        chunk: &[T],
+    ) -> Vec<serde_json::Result<PooledBuffer>> {
+        chunk
+            .par_iter()
+            .map(|item| {
+                let mut buffer = self.buffers.take();
+                let capacity = buffer.buffer.capacity();
+                serde_json::to_writer(&mut buffer.buffer, item)?;
+                if has_null_bytes(&buffer.buffer) {
+                    remove_null_bytes(&mut buffer.buffer)?;
+                }
+                if buffer.buffer.capacity() > capacity {
+                    PUBLISH_SERIALIZATION_ALLOCATIONS.inc();
+                }
+                Ok(buffer)
+            })
+            .collect()
+    }
+
+    fn write_chunk<T: Sync>(
+        &self,
+        chunk: &[T],
+        write: &(impl Fn(&T, &mut Vec<u8>) + Sync),
+    ) -> Vec<PooledBuffer> {
+        chunk
+            .par_iter()
+            .map(|item| {
+                let mut buffer = self.buffers.take();
+                let capacity = buffer.buffer.capacity();
+                write(item, &mut buffer.buffer);
+                if buffer.buffer.capacity() > capacity {
+                    PUBLISH_SERIALIZATION_ALLOCATIONS.inc();
+                }
+                buffer
+            })
+            .collect()
+    }
+
+    pub fn buffers(&self) -> &BufferPool {
+        &self.buffers
+    }
+}
+
+/// Whether serialized JSON has a null byte, which serde_json always escapes as `\u0000`
+fn has_null_bytes(json: &[u8]) -> bool {
+    json.windows(6).any(|window| window == b"\\u0000")
+}
+
+/// Serializes `json` again from its value, without the null bytes
+fn remove_null_bytes(json: &mut Vec<u8>) -> serde_json::Result<()> {
+    let mut value: serde_json::Value = serde_json::from_slice(json)?;
+    recurse_remove_null_bytes_from_json(&mut value);
+    json.clear();
+    serde_json::to_writer(json, &value)
+}
+
+/// Runs `f` with the calling tokio worker's other tasks moved elsewhere, if there's anywhere. For
+/// the publisher's blocking work, which runs on the processors' tasks.
+pub(crate) fn in_place<R>(f: impl FnOnce() -> R) -> R {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(f)
+        },
+        _ => f(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_each_in_order_with_reused_buffers() {
+        let pool = SerializationPool::new(&SerializationConfig {
+            threads: 4,
+            chunk_size: 8,
+            max_pooled_buffers: 8,
+            ..SerializationConfig::default()
+        });
+        let items = (0..100).collect::<Vec<u64>>();
+        let mut serialized = vec![];
+        pool.serialize_each("test", &items, |item, payload| {
+            serialized.push((*item, String::from_utf8(payload.unwrap().to_vec()).unwrap()));
+        });
+        assert_eq!(
+            serialized,
+            items
+                .iter()
+                .map(|item| (*item, item.to_string()))
+                .collect::<Vec<_>>()
+        );
+        // Only a chunk's buffers were ever out of the pool
+        assert_eq!(pool.buffers.buffers.lock().unwrap().len(), 8);
+
+        // An outlier grows one buffer, which is shrunk once the outlier is no longer recent
+        let large = "x".repeat(100_000);
+        pool.serialize_each("test", &[large], |_, _| {});
+        assert!(pool.buffers().largest_recent() > 100_000);
+        for _ in 0..20 {
+            pool.serialize_each("test", &items, |_, _| {});
+        }
+        assert!(pool.buffers().largest_recent() < 100_000);
+        assert!(pool
+            .buffers
+            .buffers
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|buffer| buffer.capacity() < 100_000));
+    }
+
+    #[test]
+    fn test_serialize_each_removes_null_bytes() {
+        let pool = SerializationPool::new(&SerializationConfig::default());
+        let items = [
+            serde_json::json!({ "a": { "b": { "c\u{0000}": ["x\u{0000}y"] } } }),
+            serde_json::json!({ "a": "clean" }),
+        ];
+        let mut serialized = vec![];
+        pool.serialize_each("test", &items, |_, payload| {
+            serialized.push(String::from_utf8(payload.unwrap().to_vec()).unwrap());
+        });
+        assert_eq!(serialized, [
+            r#"{"a":{"b":{"c":["xy"]}}}"#,
+            r#"{"a":"clean"}"#
+        ]);
+    }
+}
@@ -0,0 +1,394 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Shadow processing: an alternate transform of a processor, e.g. a parsing refactor, runs over
+//! the same batches as the primary and its output is diffed against the primary's row by row, so
+//! a change can soak before it replaces the primary. A shadow is registered through
+//! `ProcessorOptions` for the processors with a typed output, built by whoever builds the options
+//! with different options or an alternate code path. It runs in a read-only transaction and
+//! isn't given a publisher, so it can read like the primary but can't write or publish.
+//!
+//! Only every `sample_every`th batch is shadowed, bounding the extra CPU. Differences are counted
+//! in `indexer_shadow_diffs_count` and stored in `shadow_diffs` with the row's primary key, the
+//! column and both values, up to `max_stored_diffs` rows per processor. A failing or panicking
+//! shadow is counted and logged, and never fails the batch. `set_enabled(false)` switches every
+//! shadow of the process off at runtime.
+
+use crate::{
+    counters::{SHADOW_BATCHES, SHADOW_DIFFS},
+    custom::driver::config::ShadowConfig,
+    database::{execute_with_better_error, get_chunks, read_only_transaction, PgPoolConnection},
+    models::shadow_diffs::ShadowDiff,
+    schema,
+};
+use anyhow::anyhow;
+use aptos_api_types::Transaction;
+use aptos_logger::{error, warn};
+use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use field_count::FieldCount;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Differences stored per batch, all of them are counted
+const MAX_STORED_DIFFS: usize = 100;
+
+/// Column of a difference in the rows themselves, one output having a row the other doesn't
+const ROW_COLUMN: &str = "*";
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// The rows of one table of an output, as JSON objects
+pub struct ShadowTable {
+    pub table: &'static str,
+    pub pk: &'static [&'static str],
+    pub rows: Vec<Value>,
+}
+
+impl ShadowTable {
+    pub fn new<T: Serialize>(table: &'static str, pk: &'static [&'static str], rows: &[T]) -> Self {
+        Self {
+            table,
+            pk,
+            rows: rows
+                .iter()
+                .map(|row| serde_json::to_value(row).unwrap_or(Value::Null))
+                .collect(),
+        }
+    }
+}
+
+/// A processor output that can be diffed against a shadow's
+pub trait ShadowOutput {
+    /// Every table of the output, in the same order for any output
+    fn tables(&self) -> Vec<ShadowTable>;
+}
+
+type Transform<O> =
+    Arc<dyn Fn(&mut PgConnection, &[Transaction]) -> anyhow::Result<O> + Send + Sync>;
+
+/// A named alternate transform of a processor's batches into its output `O`
+pub struct Shadow<O> {
+    pub name: String,
+    transform: Transform<O>,
+}
+
+impl<O> Shadow<O> {
+    pub fn new(
+        name: impl Into<String>,
+        transform: impl Fn(&mut PgConnection, &[Transaction]) -> anyhow::Result<O>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            transform: Arc::new(transform),
+        }
+    }
+}
+
+impl<O> Clone for Shadow<O> {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            transform: self.transform.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diff {
+    pub table: &'static str,
+    /// Primary key values of the row, as a JSON array
+    pub pk: String,
+    pub column: String,
+    /// `None` if the output doesn't have the row or column
+    pub primary: Option<Value>,
+    pub shadow: Option<Value>,
+}
+
+/// The shadow of one processor, if any, with the config applied
+pub struct ShadowRunner<O> {
+    processor: &'static str,
+    shadow: Option<Shadow<O>>,
+    sample_every: u64,
+    max_stored_diffs: i64,
+    batches: AtomicU64,
+    /// Rows in `shadow_diffs` for the processor, counted before the first store
+    stored_diffs: Mutex<Option<i64>>,
+}
+
+/// Switches every shadow of the process on or off, e.g. to free the CPU once a soak is over
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+impl<O: ShadowOutput> ShadowRunner<O> {
+    pub fn new(processor: &'static str, shadow: Option<Shadow<O>>, config: &ShadowConfig) -> Self {
+        Self {
+            processor,
+            shadow: shadow.filter(|_| config.enabled),
+            sample_every: config.sample_every.max(1),
+            max_stored_diffs: config.max_stored_diffs,
+            batches: AtomicU64::new(0),
+            stored_diffs: Mutex::new(None),
+        }
+    }
+
+    /// Runs the shadow on the sampled batches and records how its output differs from `primary`
+    pub fn run(
+        &self,
+        conn: &mut PgPoolConnection,
+        transactions: &[Transaction],
+        primary: &O,
+        start_version: u64,
+        end_version: u64,
+    ) {
+        let Some(shadow) = &self.shadow else {
+            return;
+        };
+        if !is_enabled() || self.batches.fetch_add(1, Ordering::Relaxed) % self.sample_every != 0 {
+            return;
+        }
+        let output = read_only_transaction(conn, |pg_conn| {
+            panic::catch_unwind(AssertUnwindSafe(|| {
+                (shadow.transform)(pg_conn, transactions)
+            }))
+            .unwrap_or_else(|_| Err(anyhow!("Shadow panicked")))
+        });
+        let output = match output {
+            Ok(output) => output,
+            Err(err) => {
+                SHADOW_BATCHES
+                    .with_label_values(&[self.processor, "error"])
+                    .inc();
+                warn!(
+                    processor_name = self.processor,
+                    shadow = shadow.name,
+                    start_version = start_version,
+                    end_version = end_version,
+                    error = ?err,
+                    "Shadow failed"
+                );
+                return;
+            },
+        };
+
+        let diffs = diff(&primary.tables(), &output.tables());
+        if diffs.is_empty() {
+            SHADOW_BATCHES
+                .with_label_values(&[self.processor, "match"])
+                .inc();
+            return;
+        }
+        SHADOW_BATCHES
+            .with_label_values(&[self.processor, "mismatch"])
+            .inc();
+        for diff in &diffs {
+            SHADOW_DIFFS
+                .with_label_values(&[self.processor, diff.table])
+                .inc();
+        }
+        warn!(
+            processor_name = self.processor,
+            shadow = shadow.name,
+            start_version = start_version,
+            end_version = end_version,
+            diffs = diffs.len(),
+            first_table = diffs[0].table,
+            first_pk = diffs[0].pk,
+            first_column = diffs[0].column,
+            "Shadow output differs"
+        );
+        if let Err(err) = self.store(conn, &shadow.name, &diffs, start_version, end_version) {
+            error!(
+                processor_name = self.processor,
+                error = ?err,
+                "Failed to record shadow diffs"
+            );
+        }
+    }
+
+    fn store(
+        &self,
+        conn: &mut PgPoolConnection,
+        shadow: &str,
+        diffs: &[Diff],
+        start_version: u64,
+        end_version: u64,
+    ) -> Result<(), diesel::result::Error> {
+        let mut stored_diffs = self.stored_diffs.lock().unwrap();
+        let stored = match *stored_diffs {
+            Some(stored) => stored,
+            None => schema::shadow_diffs::table
+                .filter(schema::shadow_diffs::processor.eq(self.processor))
+                .count()
+                .get_result::<i64>(conn)?,
+        };
+        let room = (self.max_stored_diffs - stored).clamp(0, MAX_STORED_DIFFS as i64) as usize;
+        let rows = diffs
+            .iter()
+            .take(room)
+            .enumerate()
+            .map(|(index, diff)| ShadowDiff {
+                processor: self.processor.to_string(),
+                shadow: shadow.to_string(),
+                start_version: start_version as i64,
+                end_version: end_version as i64,
+                diff_index: index as i64,
+                table_name: diff.table.to_string(),
+                pk: diff.pk.clone(),
+                column_name: diff.column.clone(),
+                primary_value: diff.primary.clone(),
+                shadow_value: diff.shadow.clone(),
+            })
+            .collect::<Vec<_>>();
+        insert_shadow_diffs(conn, &rows)?;
+        *stored_diffs = Some(stored + rows.len() as i64);
+        Ok(())
+    }
+}
+
+fn insert_shadow_diffs(
+    conn: &mut PgPoolConnection,
+    items_to_insert: &[ShadowDiff],
+) -> Result<(), diesel::result::Error> {
+    use schema::shadow_diffs::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), ShadowDiff::field_count());
+    for (start_ind, end_ind) in chunks {
+        execute_with_better_error(
+            conn,
+            diesel::insert_into(schema::shadow_diffs::table)
+                .values(&items_to_insert[start_ind..end_ind])
+                .on_conflict((processor, shadow, start_version, end_version, diff_index))
+                .do_nothing(),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+/// Differences between two outputs, row by row and column by column, in primary key order
+fn diff(primary: &[ShadowTable], shadow: &[ShadowTable]) -> Vec<Diff> {
+    let mut diffs = vec![];
+    for (primary, shadow) in primary.iter().zip(shadow) {
+        let primary_rows = by_pk(primary);
+        let shadow_rows = by_pk(shadow);
+        let pks = primary_rows
+            .keys()
+            .chain(shadow_rows.keys())
+            .collect::<BTreeSet<_>>();
+        for pk in pks {
+            match (primary_rows.get(pk), shadow_rows.get(pk)) {
+                (Some(primary_row), Some(shadow_row)) => {
+                    let columns = columns(primary_row)
+                        .chain(columns(shadow_row))
+                        .collect::<BTreeSet<_>>();
+                    diffs.extend(
+                        columns
+                            .into_iter()
+                            .filter(|column| primary_row.get(column) != shadow_row.get(column))
+                            .map(|column| Diff {
+                                table: primary.table,
+                                pk: pk.clone(),
+                                column: column.clone(),
+                                primary: primary_row.get(column).cloned(),
+                                shadow: shadow_row.get(column).cloned(),
+                            }),
+                    );
+                },
+                (primary_row, shadow_row) => diffs.push(Diff {
+                    table: primary.table,
+                    pk: pk.clone(),
+                    column: ROW_COLUMN.to_string(),
+                    primary: primary_row.cloned().cloned(),
+                    shadow: shadow_row.cloned().cloned(),
+                }),
+            }
+        }
+    }
+    diffs
+}
+
+fn by_pk(table: &ShadowTable) -> BTreeMap<String, &Value> {
+    table
+        .rows
+        .iter()
+        .map(|row| {
+            let pk = table
+                .pk
+                .iter()
+                .map(|column| row.get(column).cloned().unwrap_or(Value::Null))
+                .collect::<Vec<_>>();
+            (Value::Array(pk).to_string(), row)
+        })
+        .collect()
+}
+
+fn columns(row: &Value) -> impl Iterator<Item = &String> {
+    row.as_object().into_iter().flat_map(|object| object.keys())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn table(rows: Vec<Value>) -> ShadowTable {
+        ShadowTable {
+            table: "coin_balances",
+            pk: &["owner_address", "coin_type"],
+            rows,
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let primary = table(vec![
+            json!({ "owner_address": "0x1", "coin_type": "A", "amount": "10" }),
+            json!({ "owner_address": "0x1", "coin_type": "B", "amount": "5" }),
+            json!({ "owner_address": "0x2", "coin_type": "A", "amount": "1" }),
+        ]);
+        let shadow = table(vec![
+            json!({ "owner_address": "0x2", "coin_type": "A", "amount": "1" }),
+            json!({ "owner_address": "0x1", "coin_type": "A", "amount": "11" }),
+            json!({ "owner_address": "0x3", "coin_type": "A", "amount": "7" }),
+        ]);
+        let diffs = diff(&[primary], &[shadow]);
+        assert_eq!(
+            diffs
+                .iter()
+                .map(|d| (d.pk.as_str(), d.column.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (r#"["0x1","A"]"#, "amount"),
+                (r#"["0x1","B"]"#, "*"),
+                (r#"["0x3","A"]"#, "*"),
+            ]
+        );
+        assert_eq!(diffs[0].primary, Some(json!("10")));
+        assert_eq!(diffs[0].shadow, Some(json!("11")));
+        assert_eq!(diffs[1].shadow, None);
+        assert_eq!(diffs[2].primary, None);
+    }
+
+    #[test]
+    fn test_same_output() {
+        let rows = vec![json!({ "owner_address": "0x1", "coin_type": "A", "amount": "10" })];
+        assert!(diff(&[table(rows.clone())], &[table(rows)]).is_empty());
+        assert!(diff(&[table(vec![])], &[table(vec![])]).is_empty());
+    }
+}
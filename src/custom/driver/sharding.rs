@@ -0,0 +1,223 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Splits the versions of a processor across `shard_count` instances. Versions are cut into
+//! slices of `slice_size`, and slice `n` belongs to shard `n % shard_count`. The fetcher of each
+//! instance only fetches the versions of its own slices, so every version is processed exactly
+//! once and published with the usual keys; messages of a key stay in order within a shard.
+//!
+//! Each shard keeps its own watermark in `processor_status`, under `processor@index/count`. The
+//! processor's own row holds the combined low watermark, the last version up to which every shard
+//! committed, so that everything reading a processor's watermark sees a version all of whose
+//! predecessors are indexed. Current tables are upserted with the version guard of
+//! `CurrentRowUpsert`, so whichever shard writes a key last, the row of the highest version wins.
+//!
+//! To change the shard count, restart every shard with `drain_at_version` set above all of their
+//! watermarks. Each stops fetching at that version and the combined watermark reaches it once
+//! they're done; shards started with the new count then pick up from the combined watermark, as
+//! none of them has a row of its own yet.
+
+use crate::{
+    custom::driver::config::ShardingConfig, database::PgPoolConnection,
+    models::processor_status::ProcessorStatusV2Query,
+};
+use aptos_logger::info;
+use once_cell::sync::OnceCell;
+
+static SPEC: OnceCell<ShardSpec> = OnceCell::new();
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShardSpec {
+    pub shard_count: u64,
+    pub shard_index: u64,
+    pub slice_size: u64,
+    /// First version no shard of this count processes
+    pub drain_at_version: Option<u64>,
+}
+
+/// Sets the shard of the process. Only the first call in a process has an effect, so every
+/// processor runtime can call it, and a reload never moves a running processor to another shard.
+pub fn init(config: &ShardingConfig) {
+    if !config.enabled || SPEC.get().is_some() {
+        return;
+    }
+    if let Err(err) = config.validate() {
+        panic!("Invalid sharding config: {:#}", err);
+    }
+    let spec = ShardSpec {
+        shard_count: config.shard_count,
+        shard_index: config.shard_index,
+        slice_size: config.slice_size,
+        drain_at_version: config.drain_at_version,
+    };
+    info!(
+        shard_index = spec.shard_index,
+        shard_count = spec.shard_count,
+        slice_size = spec.slice_size,
+        drain_at_version = spec.drain_at_version,
+        "Processing a shard of the versions"
+    );
+    let _ = SPEC.set(spec);
+}
+
+/// The shard of the process, `None` if it processes every version
+pub fn spec() -> Option<ShardSpec> {
+    SPEC.get().copied()
+}
+
+/// Name of the `processor_status` row the process keeps its watermark in
+pub fn watermark_key(processor: &str) -> String {
+    match spec() {
+        Some(spec) => spec.watermark_key(processor),
+        None => processor.to_string(),
+    }
+}
+
+impl ShardSpec {
+    pub fn owns(&self, version: u64) -> bool {
+        (version / self.slice_size) % self.shard_count == self.shard_index
+    }
+
+    /// First version from `version` on that the shard owns
+    pub fn next_owned(&self, version: u64) -> u64 {
+        if self.owns(version) {
+            return version;
+        }
+        let slice = version / self.slice_size;
+        let ahead =
+            (self.shard_index + self.shard_count - slice % self.shard_count) % self.shard_count;
+        (slice + ahead) * self.slice_size
+    }
+
+    /// Last version of the slice `version` is in
+    pub fn slice_end(&self, version: u64) -> u64 {
+        (version / self.slice_size + 1) * self.slice_size - 1
+    }
+
+    /// Whether the shard has nothing left to process from `version` on
+    pub fn is_drained(&self, version: u64) -> bool {
+        self.drain_at_version.map_or(false, |drain_at_version| {
+            self.next_owned(version) >= drain_at_version
+        })
+    }
+
+    pub fn watermark_key(&self, processor: &str) -> String {
+        format!("{}@{}/{}", processor, self.shard_index, self.shard_count)
+    }
+
+    fn with_index(&self, shard_index: u64) -> Self {
+        Self {
+            shard_index,
+            ..*self
+        }
+    }
+}
+
+/// Last version up to which every shard of `spec`'s count committed, from the shards' watermarks
+/// (last committed version, by shard index) and the combined watermark the shards without one
+/// started from
+pub fn combined_low_watermark(
+    spec: &ShardSpec,
+    watermarks: &[Option<u64>],
+    base: Option<u64>,
+) -> Option<u64> {
+    let next = (0..spec.shard_count)
+        .map(|shard_index| {
+            let from = match watermarks.get(shard_index as usize).copied().flatten() {
+                Some(watermark) => watermark + 1,
+                None => base.map_or(0, |base| base + 1),
+            };
+            spec.with_index(shard_index).next_owned(from)
+        })
+        .min()?;
+    next.checked_sub(1)
+}
+
+/// The combined low watermark of `processor`, from the rows of every shard
+pub fn low_watermark(
+    conn: &mut PgPoolConnection,
+    spec: &ShardSpec,
+    processor: &str,
+) -> anyhow::Result<Option<u64>> {
+    let mut watermarks = vec![];
+    for shard_index in 0..spec.shard_count {
+        let key = spec.with_index(shard_index).watermark_key(processor);
+        let status = ProcessorStatusV2Query::get_by_processor(&key, conn)?;
+        watermarks.push(status.map(|status| status.last_success_version as u64));
+    }
+    let base = ProcessorStatusV2Query::get_by_processor(&processor.to_string(), conn)?
+        .map(|status| status.last_success_version as u64);
+    Ok(combined_low_watermark(spec, &watermarks, base))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shard(shard_index: u64) -> ShardSpec {
+        ShardSpec {
+            shard_count: 3,
+            shard_index,
+            slice_size: 10,
+            drain_at_version: None,
+        }
+    }
+
+    #[test]
+    fn test_slices() {
+        assert!(shard(0).owns(0));
+        assert!(shard(0).owns(9));
+        assert!(shard(1).owns(10));
+        assert!(shard(0).owns(30));
+        assert_eq!(shard(0).next_owned(5), 5);
+        assert_eq!(shard(0).next_owned(10), 30);
+        assert_eq!(shard(2).next_owned(5), 20);
+        assert_eq!(shard(1).next_owned(25), 40);
+        assert_eq!(shard(1).slice_end(12), 19);
+        // Every version belongs to exactly one shard
+        for version in 0..100 {
+            assert_eq!(
+                (0..3).filter(|index| shard(*index).owns(version)).count(),
+                1
+            );
+        }
+    }
+
+    #[test]
+    fn test_combined_low_watermark() {
+        // Nothing committed yet
+        assert_eq!(
+            combined_low_watermark(&shard(0), &[None, None, None], None),
+            None
+        );
+        // Shard 1 is done with 10..=19, shard 2 with 20..=29 and 50..=52, shard 0 hasn't started
+        assert_eq!(
+            combined_low_watermark(&shard(0), &[None, Some(19), Some(52)], None),
+            None
+        );
+        // Shard 0 is done with 0..=9, shard 1 with 10..=19, shard 2 halfway through 20..=29
+        assert_eq!(
+            combined_low_watermark(&shard(0), &[Some(9), Some(19), Some(24)], None),
+            Some(24)
+        );
+        // Shards of a new count without rows start from the combined watermark
+        assert_eq!(
+            combined_low_watermark(&shard(0), &[Some(39), None, None], Some(29)),
+            Some(39)
+        );
+    }
+
+    #[test]
+    fn test_drain() {
+        let spec = ShardSpec {
+            drain_at_version: Some(40),
+            ..shard(1)
+        };
+        assert!(!spec.is_drained(15));
+        assert!(spec.is_drained(20));
+        assert_eq!(
+            spec.watermark_key("custom_coin_processor"),
+            "custom_coin_processor@1/3"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Graceful shutdown on SIGINT or SIGTERM, e.g. when Kubernetes replaces the pod. Once a signal
+//! is received the processors start no new round of batches. The round in flight gets
+//! `drain_timeout_secs` to finish, then the publisher is flushed, waiting up to
+//! `flush_timeout_secs` for Kafka to ack every message, and only then is the round's watermark
+//! saved. A round that doesn't finish in time, or whose messages aren't all acked, leaves the
+//! watermark where it was and is processed again after the restart, so no version is skipped and
+//! only that round can be published twice.
+//!
+//! The process exits once every processor stopped, or once both timeouts passed, and right away
+//! on a second signal.
+
+use crate::custom::driver::config::ShutdownConfig;
+use aptos_logger::{info, warn};
+use once_cell::sync::{Lazy, OnceCell};
+use std::{collections::HashSet, future::Future, sync::Mutex, time::Duration};
+use tokio::{
+    signal::unix::{signal, Signal, SignalKind},
+    sync::watch,
+};
+
+static REQUESTED: Lazy<watch::Sender<bool>> = Lazy::new(|| watch::channel(false).0);
+static CONFIG: OnceCell<ShutdownConfig> = OnceCell::new();
+/// Processors that haven't stopped yet
+static RUNNING: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// Starts listening for the signals if enabled. Only the first call in a process has an effect,
+/// so every processor runtime can call it.
+pub fn init(config: &ShutdownConfig) {
+    if !config.enabled || CONFIG.set(config.clone()).is_err() {
+        return;
+    }
+    let deadline = Duration::from_secs(config.drain_timeout_secs + config.flush_timeout_secs);
+    let mut interrupt = signal(SignalKind::interrupt()).expect("Failed to listen for SIGINT");
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::spawn(async move {
+        let received = next_signal(&mut interrupt, &mut terminate).await;
+        info!(signal = received, "Shutting down...");
+        REQUESTED.send_replace(true);
+        tokio::select! {
+            received = next_signal(&mut interrupt, &mut terminate) => {
+                warn!(signal = received, "Signal received again, exiting right away");
+            },
+            _ = tokio::time::sleep(deadline) => {
+                warn!(
+                    running = ?RUNNING.lock().unwrap(),
+                    "Processors didn't stop in time, exiting"
+                );
+            },
+        }
+        std::process::exit(1);
+    });
+}
+
+async fn next_signal(interrupt: &mut Signal, terminate: &mut Signal) -> &'static str {
+    tokio::select! {
+        _ = interrupt.recv() => "SIGINT",
+        _ = terminate.recv() => "SIGTERM",
+    }
+}
+
+/// Called when a processor starts, the process only exits once it stopped
+pub fn register(processor: &str) {
+    RUNNING.lock().unwrap().insert(processor.to_string());
+}
+
+/// Called once a processor stopped because of a shutdown. The last one exits the process.
+pub fn stopped(processor: &str) {
+    let mut running = RUNNING.lock().unwrap();
+    running.remove(processor);
+    info!(processor_name = processor, "Processor stopped");
+    if running.is_empty() {
+        info!("Every processor stopped, exiting");
+        std::process::exit(0);
+    }
+}
+
+pub fn requested() -> bool {
+    *REQUESTED.borrow()
+}
+
+/// Resolves once a shutdown is requested
+pub async fn wait() {
+    wait_on(REQUESTED.subscribe()).await
+}
+
+/// `future`'s output, or `None` if a shutdown was requested and it didn't finish within the drain
+/// timeout
+pub async fn within_drain<T>(future: impl Future<Output = T>) -> Option<T> {
+    match CONFIG.get() {
+        Some(config) => {
+            race(
+                future,
+                REQUESTED.subscribe(),
+                Duration::from_secs(config.drain_timeout_secs),
+            )
+            .await
+        },
+        None => Some(future.await),
+    }
+}
+
+/// How long a stopping processor waits for what it published to be acked
+pub fn flush_timeout() -> Duration {
+    Duration::from_secs(CONFIG.get().map_or(0, |config| config.flush_timeout_secs))
+}
+
+async fn wait_on(mut requested: watch::Receiver<bool>) {
+    while !*requested.borrow_and_update() {
+        if requested.changed().await.is_err() {
+            // The sender is static, this never happens
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+async fn race<T>(
+    future: impl Future<Output = T>,
+    requested: watch::Receiver<bool>,
+    drain_timeout: Duration,
+) -> Option<T> {
+    tokio::select! {
+        output = future => Some(output),
+        _ = async {
+            wait_on(requested).await;
+            tokio::time::sleep(drain_timeout).await;
+        } => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_race() {
+        let drain_timeout = Duration::from_millis(50);
+        let (sender, receiver) = watch::channel(false);
+        // No shutdown, whatever it takes
+        let slow = tokio::time::sleep(Duration::from_millis(100));
+        assert_eq!(
+            race(
+                async move {
+                    slow.await;
+                    1
+                },
+                receiver.clone(),
+                drain_timeout
+            )
+            .await,
+            Some(1)
+        );
+
+        sender.send_replace(true);
+        // Finishes within the drain timeout
+        let quick = tokio::time::sleep(Duration::from_millis(10));
+        assert_eq!(
+            race(
+                async move {
+                    quick.await;
+                    2
+                },
+                receiver.clone(),
+                drain_timeout
+            )
+            .await,
+            Some(2)
+        );
+        // Doesn't
+        let stuck = std::future::pending::<u32>();
+        assert_eq!(race(stuck, receiver, drain_timeout).await, None);
+    }
+}
@@ -0,0 +1,119 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Where `custom_default_processor` publishes a batch: Kafka through the `Publisher` by default,
+//! or with `sink.backend` set to `http` an HTTP endpoint, see `driver::http_sink`. Either way a
+//! batch that can't be published fails, and is retried or stops the processor like any failed
+//! batch, so the watermark never moves past it.
+//!
+//! With the `object_store_sink` feature, `object_store` writes batches to S3 or GCS, see
+//! `driver::object_store_sink`. With `sink.fan_out` every batch goes to several of them, see
+//! `driver::multi_sink`.
+//!
+//! Only transactions, events and write set changes are sent through the sink. Current resources,
+//! account transactions and entry function rollups are Kafka topics of their own, and only
+//! published when the sink is the `Publisher`.
+
+use crate::{
+    custom::driver::{
+        config::{SinkBackend, SinkConfig},
+        http_sink::HttpSink,
+        multi_sink::MultiSink,
+        publisher::Publisher,
+    },
+    models::{events::EventModel, write_set_changes::WriteSetChangeModel},
+};
+use aptos_api_types::Transaction;
+use async_trait::async_trait;
+use std::time::Duration;
+
+#[async_trait]
+pub trait Sink: Send + Sync {
+    /// Whether the sink takes `model`, only the models it takes are parsed
+    fn publishes(&self, model: &str) -> bool;
+
+    /// The number of transactions that couldn't be sent but were dead lettered
+    async fn send_txs(&self, txns: &[Transaction]) -> anyhow::Result<usize>;
+
+    /// Sends the events and write set changes of a batch, before its transactions. The number
+    /// dead lettered.
+    async fn send_events(
+        &self,
+        events: &[EventModel],
+        wscs: &[WriteSetChangeModel],
+    ) -> anyhow::Result<usize>;
+
+    /// Waits up to `timeout` for what was sent to be delivered
+    async fn flush(&self, timeout: Duration) -> anyhow::Result<()>;
+
+    /// The publisher of the models only Kafka takes, if the sink is Kafka
+    fn publisher(&self) -> Option<&Publisher> {
+        None
+    }
+}
+
+/// The sink `config` sets up, `backend` or the `fan_out` sinks, publishing to Kafka with
+/// `publisher`. Panics if `config` is invalid.
+pub fn from_config(config: &SinkConfig, publisher: Publisher) -> Box<dyn Sink> {
+    if let Err(err) = config.validate() {
+        panic!("Invalid sink config: {:#}", err);
+    }
+    let envelope = publisher.envelope().clone();
+    // Kafka is listed once at most
+    let mut publisher = Some(publisher);
+    let mut build = |backend| -> Box<dyn Sink> {
+        match backend {
+            SinkBackend::Kafka => Box::new(publisher.take().unwrap()),
+            SinkBackend::Http => Box::new(HttpSink::new(&config.http, envelope.clone())),
+            SinkBackend::ObjectStore => object_store(config),
+        }
+    };
+    if config.fan_out.is_empty() {
+        return build(config.backend);
+    }
+    let sinks = config.fan_out.iter().fold(MultiSink::new(), |sinks, sink| {
+        sinks.with(sink.backend.as_str(), sink.policy, build(sink.backend))
+    });
+    Box::new(sinks)
+}
+
+#[cfg(feature = "object_store_sink")]
+fn object_store(config: &SinkConfig) -> Box<dyn Sink> {
+    use crate::custom::driver::object_store_sink::ObjectStoreSink;
+    match ObjectStoreSink::from_config(&config.object_store) {
+        Ok(sink) => Box::new(sink),
+        Err(err) => panic!("Invalid sink config: {:#}", err),
+    }
+}
+
+#[cfg(not(feature = "object_store_sink"))]
+fn object_store(_config: &SinkConfig) -> Box<dyn Sink> {
+    unreachable!("SinkConfig::validate rejects the object_store backend without its feature")
+}
+
+#[async_trait]
+impl Sink for Publisher {
+    fn publishes(&self, model: &str) -> bool {
+        model == "TransactionModel" || Publisher::publishes(self, model)
+    }
+
+    async fn send_txs(&self, txns: &[Transaction]) -> anyhow::Result<usize> {
+        Ok(self.send_transaction("TransactionModel", txns)?)
+    }
+
+    async fn send_events(
+        &self,
+        events: &[EventModel],
+        wscs: &[WriteSetChangeModel],
+    ) -> anyhow::Result<usize> {
+        Ok(Publisher::send_events(self, events)? + self.send_write_set_changes(wscs)?)
+    }
+
+    async fn flush(&self, timeout: Duration) -> anyhow::Result<()> {
+        Ok(Publisher::flush(self, timeout)?)
+    }
+
+    fn publisher(&self) -> Option<&Publisher> {
+        Some(self)
+    }
+}
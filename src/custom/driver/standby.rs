@@ -0,0 +1,302 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Warm standby. Every instance of a processor (or of a shard, see `driver::sharding`) with
+//! `standby` enabled contends for a lease in `processor_leases`; the one holding it is the leader
+//! and runs as usual, the others are standbys. A standby fetches and parses (`debug::derive_batch`)
+//! without writing, publishing or moving the watermark, staying within `max_lead_versions` of the
+//! leader's watermark, which keeps its fetcher and producer connections warm and shows parse
+//! failures in `indexer_standby_parse_panics_count` before it ever leads. It polls the lease every
+//! `poll_interval_millis` and takes it over once it expires, then starts a fresh processor at the
+//! authoritative watermark.
+//!
+//! The leader renews the lease between rounds of batches, so a leader that hangs loses it after
+//! `lease_ttl_secs`, which must be longer than the slowest round. A leader that finds the lease
+//! taken becomes a standby. `lifecycle::Indexer::demote_processor` hands the lease over without
+//! waiting for the ttl: once the in-flight batches are committed the publisher is flushed, the
+//! lease released and the process becomes a standby, which doesn't take the lease back for a ttl.
+//! The role of the process is in `status()`, the health server's `/status` and the
+//! `indexer_standby_role` gauge.
+
+use crate::{
+    counters::{STANDBY_PARSE_PANICS, STANDBY_ROLE, STANDBY_TAKEOVERS},
+    custom::driver::config::StandbyConfig,
+    database::PgDbPool,
+    models::processor_leases::ProcessorLease,
+    schema::processor_leases,
+};
+use anyhow::Context;
+use aptos_logger::{info, warn};
+use diesel::{
+    sql_query,
+    sql_types::{Double, Text},
+    ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl,
+};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+/// Takes the lease if it's free or expired, renews it if it's held already
+const ACQUIRE_SQL: &str = "
+INSERT INTO processor_leases (processor, holder, acquired_at, expires_at)
+VALUES ($1, $2, NOW(), NOW() + make_interval(secs => $3))
+ON CONFLICT (processor) DO UPDATE SET
+    holder = EXCLUDED.holder,
+    acquired_at = CASE WHEN processor_leases.holder = EXCLUDED.holder
+        THEN processor_leases.acquired_at ELSE EXCLUDED.acquired_at END,
+    expires_at = EXCLUDED.expires_at
+WHERE processor_leases.holder = EXCLUDED.holder OR processor_leases.expires_at <= NOW()";
+
+const RELEASE_SQL: &str =
+    "UPDATE processor_leases SET expires_at = NOW() WHERE processor = $1 AND holder = $2";
+
+static STATUS: Lazy<RwLock<BTreeMap<String, StandbyStatus>>> =
+    Lazy::new(|| RwLock::new(BTreeMap::new()));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Leader,
+    Standby,
+}
+
+/// Snapshot of a processor's role, meant for status reporting
+#[derive(Clone, Debug, Serialize)]
+pub struct StandbyStatus {
+    pub processor: String,
+    pub holder: String,
+    pub role: Role,
+    pub since: chrono::NaiveDateTime,
+    /// Last version parsed as a standby
+    pub parsed_version: Option<u64>,
+    /// The leader's watermark as last read by the standby
+    pub leader_watermark: Option<u64>,
+    /// Parsing stages that panicked as a standby
+    pub parse_panics: u64,
+}
+
+/// The role of every processor of the process that runs with `standby` enabled
+pub fn status() -> Vec<StandbyStatus> {
+    STATUS.read().unwrap().values().cloned().collect()
+}
+
+pub struct Lease {
+    processor: String,
+    holder: String,
+    ttl: Duration,
+    conn_pool: PgDbPool,
+    /// When the lease was last acquired or renewed, `None` while it isn't held
+    renewed_at: Mutex<Option<Instant>>,
+    /// No acquiring before this, after handing the lease over
+    hold_off_until: Mutex<Option<Instant>>,
+}
+
+impl Lease {
+    /// The lease of `processor`, the watermark key for a shard
+    pub fn new(processor: &str, config: &StandbyConfig, conn_pool: PgDbPool) -> Self {
+        let holder = config.instance_id.clone().unwrap_or_else(|| {
+            format!(
+                "{}-{}",
+                std::env::var("HOSTNAME").unwrap_or_else(|_| "unknown".to_string()),
+                std::process::id()
+            )
+        });
+        Self {
+            processor: processor.to_string(),
+            holder,
+            ttl: Duration::from_secs(config.lease_ttl_secs.max(1)),
+            conn_pool,
+            renewed_at: Mutex::new(None),
+            hold_off_until: Mutex::new(None),
+        }
+    }
+
+    pub fn holder(&self) -> &str {
+        &self.holder
+    }
+
+    /// Acquires the lease if it's free or expired, or renews it if it's held already. False if
+    /// another instance holds it.
+    pub fn try_acquire(&self) -> anyhow::Result<bool> {
+        if let Some(hold_off_until) = *self.hold_off_until.lock().unwrap() {
+            if Instant::now() < hold_off_until {
+                return Ok(false);
+            }
+        }
+        let mut conn = self.conn_pool.get()?;
+        let acquired = sql_query(ACQUIRE_SQL)
+            .bind::<Text, _>(&self.processor)
+            .bind::<Text, _>(&self.holder)
+            .bind::<Double, _>(self.ttl.as_secs_f64())
+            .execute(&mut conn)
+            .context("Failed to acquire the lease")?
+            == 1;
+        *self.renewed_at.lock().unwrap() = acquired.then(Instant::now);
+        Ok(acquired)
+    }
+
+    /// Called by the leader between rounds: renews the lease once a third of its ttl has passed.
+    /// False once the lease is lost; failing to renew only loses it after half the ttl, leaving
+    /// a margin before it expires in the db.
+    pub fn renew_if_due(&self) -> bool {
+        let renewed_at = *self.renewed_at.lock().unwrap();
+        let Some(renewed_at) = renewed_at else {
+            return false;
+        };
+        if renewed_at.elapsed() < self.ttl / 3 {
+            return true;
+        }
+        match self.try_acquire() {
+            Ok(held) => held,
+            Err(err) => {
+                warn!(
+                    processor_name = self.processor,
+                    error = ?err,
+                    "Failed to renew the lease"
+                );
+                renewed_at.elapsed() < self.ttl / 2
+            },
+        }
+    }
+
+    /// Expires the lease for a standby to take it over at its next poll, and keeps this process
+    /// from taking it back for a ttl
+    pub fn release(&self) -> anyhow::Result<()> {
+        *self.hold_off_until.lock().unwrap() = Some(Instant::now() + self.ttl);
+        *self.renewed_at.lock().unwrap() = None;
+        let mut conn = self.conn_pool.get()?;
+        sql_query(RELEASE_SQL)
+            .bind::<Text, _>(&self.processor)
+            .bind::<Text, _>(&self.holder)
+            .execute(&mut conn)
+            .context("Failed to release the lease")?;
+        Ok(())
+    }
+
+    /// The lease as stored, whoever holds it
+    pub fn current(&self) -> anyhow::Result<Option<ProcessorLease>> {
+        let mut conn = self.conn_pool.get()?;
+        Ok(processor_leases::table
+            .filter(processor_leases::processor.eq(&self.processor))
+            .first::<ProcessorLease>(&mut conn)
+            .optional()?)
+    }
+
+    /// Records the role the process took for the processor
+    pub fn set_role(&self, role: Role) {
+        let mut status = STATUS.write().unwrap();
+        if status
+            .get(&self.processor)
+            .map_or(false, |status| status.role == role)
+        {
+            return;
+        }
+        match role {
+            Role::Leader => {
+                // The first role of the process isn't a takeover
+                if status.contains_key(&self.processor) {
+                    STANDBY_TAKEOVERS
+                        .with_label_values(&[&self.processor])
+                        .inc();
+                }
+                info!(
+                    processor_name = self.processor,
+                    holder = self.holder,
+                    "Acquired the lease, leading"
+                );
+            },
+            Role::Standby => info!(
+                processor_name = self.processor,
+                holder = self.holder,
+                "Running as a standby"
+            ),
+        }
+        STANDBY_ROLE
+            .with_label_values(&[&self.processor])
+            .set((role == Role::Leader) as i64);
+        status.insert(self.processor.clone(), StandbyStatus {
+            processor: self.processor.clone(),
+            holder: self.holder.clone(),
+            role,
+            since: chrono::Utc::now().naive_utc(),
+            parsed_version: None,
+            leader_watermark: None,
+            parse_panics: 0,
+        });
+    }
+
+    /// Records a batch parsed as a standby
+    pub fn on_parsed(&self, parsed_version: u64, leader_watermark: u64, parse_panics: usize) {
+        if parse_panics > 0 {
+            STANDBY_PARSE_PANICS
+                .with_label_values(&[&self.processor])
+                .inc_by(parse_panics as u64);
+        }
+        if let Some(status) = STATUS.write().unwrap().get_mut(&self.processor) {
+            status.parsed_version = Some(parsed_version);
+            status.leader_watermark = Some(leader_watermark);
+            status.parse_panics += parse_panics as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::new_db_pool, indexer::tailer::MIGRATIONS};
+    use diesel_migrations::MigrationHarness;
+
+    fn lease(conn_pool: &PgDbPool, instance_id: &str, lease_ttl_secs: u64) -> Lease {
+        let config = StandbyConfig {
+            enabled: true,
+            instance_id: Some(instance_id.to_string()),
+            lease_ttl_secs,
+            ..Default::default()
+        };
+        Lease::new("standby_lease_test", &config, conn_pool.clone())
+    }
+
+    #[test]
+    fn test_lease_handover() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(&database_url).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        diesel::delete(
+            processor_leases::table.filter(processor_leases::processor.eq("standby_lease_test")),
+        )
+        .execute(&mut conn)
+        .unwrap();
+
+        let leader = lease(&conn_pool, "leader", 30);
+        let standby = lease(&conn_pool, "standby", 30);
+        assert!(leader.try_acquire().unwrap());
+        assert!(!standby.try_acquire().unwrap());
+        assert!(leader.try_acquire().unwrap());
+        assert!(leader.renew_if_due());
+        assert_eq!(leader.current().unwrap().unwrap().holder, "leader");
+
+        // A release hands the lease over right away, and the old leader stays off it
+        leader.release().unwrap();
+        assert!(!leader.renew_if_due());
+        assert!(standby.try_acquire().unwrap());
+        assert!(!leader.try_acquire().unwrap());
+        assert_eq!(standby.current().unwrap().unwrap().holder, "standby");
+
+        // An expired lease is taken over without a release
+        let standby = lease(&conn_pool, "standby", 1);
+        assert!(standby.try_acquire().unwrap());
+        let other = lease(&conn_pool, "other", 1);
+        assert!(!other.try_acquire().unwrap());
+        std::thread::sleep(Duration::from_millis(1_100));
+        assert!(other.try_acquire().unwrap());
+        assert!(!standby.try_acquire().unwrap());
+    }
+}
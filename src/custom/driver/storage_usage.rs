@@ -0,0 +1,326 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! State storage fees. `custom_default_processor` writes a row to `transaction_storage_usage`
+//! for every user transaction: the storage fee and refund of its
+//! `0x1::transaction_fee::FeeStatement` event, null before the chain emitted one, and the slots
+//! its write set writes and deletes. The API only gives the sizes of table items and modules, so
+//! `bytes_written` leaves resources out, and deletions aren't sized at all.
+//!
+//! The fees also go to `account_storage_deposits`, the running deposits of each payer (the fee
+//! payer if the transaction has one, the sender otherwise): a storage fee adds to the balance, a
+//! refund subtracts from it. The fee statement doesn't split the refundable part of the fee from
+//! the rest, so the balance is an upper bound of what the account can get back. A refund goes to
+//! whoever deletes the slot, not who paid for it, so it can exceed the payer's balance, as can a
+//! fee the indexer missed. The balance is then set to zero and the refund goes through
+//! `balance_guard`: it's recorded in `balance_anomalies`, and the payer's row derived again from
+//! its `transaction_storage_usage` history, see `StorageDepositHistory`. Each account keeps the
+//! last version applied to it, so a re-processed batch doesn't apply twice.
+
+use crate::{
+    custom::driver::{
+        balance_guard::{BalanceGuard, Rederive},
+        config::StorageUsageConfig,
+    },
+    database::{execute_with_better_error, get_chunks, write_transaction, PgPoolConnection},
+    models::{
+        balance_anomalies::BalanceAnomaly,
+        storage_usage::{
+            AccountStorageDeposit, AccountStorageDepositQuery, NegativeDeposit,
+            TransactionStorageUsage,
+        },
+    },
+    schema,
+};
+use aptos_api_types::Transaction;
+use aptos_logger::warn;
+use bigdecimal::{BigDecimal, Zero};
+use diesel::{
+    pg::upsert::excluded, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl,
+    QueryResult, RunQueryDsl,
+};
+use field_count::FieldCount;
+use std::collections::{HashMap, HashSet};
+
+/// Asset of the storage deposits in `balance_anomalies`
+pub const STORAGE_DEPOSIT_ASSET: &str = "0x1::aptos_coin::AptosCoin";
+
+pub struct StorageUsage {
+    processor: &'static str,
+    enabled: bool,
+    guard: BalanceGuard<StorageDepositHistory>,
+}
+
+impl StorageUsage {
+    pub fn new(processor: &'static str, config: &StorageUsageConfig) -> Self {
+        Self {
+            processor,
+            enabled: config.enabled,
+            guard: BalanceGuard::new(processor, StorageDepositHistory),
+        }
+    }
+
+    /// Writes the storage usage of the batch's user transactions and applies their fees to the
+    /// payers' deposits
+    pub fn record(
+        &self,
+        conn: &mut PgPoolConnection,
+        transactions: &[Transaction],
+    ) -> anyhow::Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let usages = transactions
+            .iter()
+            .filter_map(TransactionStorageUsage::from_transaction)
+            .collect::<Vec<_>>();
+        self.record_usages(conn, &usages)
+    }
+
+    fn record_usages(
+        &self,
+        conn: &mut PgConnection,
+        usages: &[TransactionStorageUsage],
+    ) -> anyhow::Result<()> {
+        if usages.is_empty() {
+            return Ok(());
+        }
+        let anomalies = write_transaction(conn, |pg_conn| self.insert(pg_conn, usages))?;
+        if let Some(first) = anomalies.first() {
+            warn!(
+                processor_name = self.processor,
+                refunds = anomalies.len(),
+                first_version = first.transaction_version,
+                first_account = first.account_address,
+                "Storage refunds larger than the deposit balance, deriving the balances again"
+            );
+        }
+        self.guard.heal(conn, &anomalies);
+        Ok(())
+    }
+
+    fn insert(
+        &self,
+        conn: &mut PgConnection,
+        usages: &[TransactionStorageUsage],
+    ) -> Result<Vec<BalanceAnomaly>, diesel::result::Error> {
+        insert_transaction_storage_usage(conn, usages)?;
+        let negatives = upsert_deposits(conn, usages)?;
+        let anomalies = negatives
+            .iter()
+            .map(|negative| self.anomaly(negative))
+            .collect::<Vec<_>>();
+        self.guard.record(conn, &anomalies)?;
+        Ok(anomalies)
+    }
+
+    fn anomaly(&self, negative: &NegativeDeposit) -> BalanceAnomaly {
+        self.guard.anomaly(
+            &negative.account_address,
+            STORAGE_DEPOSIT_ASSET,
+            negative.transaction_version,
+            &negative.deposit_octas - &negative.refund_octas,
+            negative.balance_octas.clone(),
+        )
+    }
+}
+
+/// Derives a payer's `account_storage_deposits` row again by applying all of its
+/// `transaction_storage_usage` rows from zero
+pub struct StorageDepositHistory;
+
+impl Rederive for StorageDepositHistory {
+    fn balance_table(&self) -> &'static str {
+        "account_storage_deposits"
+    }
+
+    fn rederive(
+        &self,
+        conn: &mut PgConnection,
+        payer: &str,
+        _asset: &str,
+    ) -> QueryResult<BigDecimal> {
+        // Locked like a batch would, so one applying fees meanwhile waits for the derived row
+        schema::account_storage_deposits::table
+            .find(payer)
+            .for_update()
+            .first::<AccountStorageDepositQuery>(conn)
+            .optional()?;
+        let usages = {
+            use schema::transaction_storage_usage::dsl::*;
+
+            transaction_storage_usage
+                .filter(payer_address.eq(payer))
+                .order(transaction_version)
+                .select((
+                    transaction_version,
+                    payer_address,
+                    storage_fee_octas,
+                    storage_fee_refund_octas,
+                    slots_written,
+                    slots_deleted,
+                    bytes_written,
+                ))
+                .load::<TransactionStorageUsage>(conn)?
+        };
+        // The refunds that still exceed the balance are legitimate, see the module doc
+        let (deposits, _, _) = AccountStorageDeposit::apply(&usages, &HashMap::new());
+        write_deposits(conn, &deposits)?;
+        Ok(deposits
+            .first()
+            .map(|deposit| deposit.deposit_balance_octas.clone())
+            .unwrap_or_else(BigDecimal::zero))
+    }
+}
+
+fn insert_transaction_storage_usage(
+    conn: &mut PgConnection,
+    items_to_insert: &[TransactionStorageUsage],
+) -> Result<(), diesel::result::Error> {
+    use schema::transaction_storage_usage::dsl::*;
+
+    let chunks = get_chunks(
+        items_to_insert.len(),
+        TransactionStorageUsage::field_count(),
+    );
+    for (start_ind, end_ind) in chunks {
+        execute_with_better_error(
+            conn,
+            diesel::insert_into(schema::transaction_storage_usage::table)
+                .values(&items_to_insert[start_ind..end_ind])
+                .on_conflict(transaction_version)
+                .do_nothing(),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+/// Applies the fees to the payers' deposits, locked so that concurrent batches add up. Returns
+/// the refunds kept from taking a balance below zero.
+fn upsert_deposits(
+    conn: &mut PgConnection,
+    usages: &[TransactionStorageUsage],
+) -> Result<Vec<NegativeDeposit>, diesel::result::Error> {
+    use schema::account_storage_deposits::dsl::*;
+
+    let payers = usages
+        .iter()
+        .map(|usage| usage.payer_address.clone())
+        .collect::<HashSet<_>>();
+    let stored = account_storage_deposits
+        .filter(account_address.eq_any(payers))
+        .order(account_address)
+        .for_update()
+        .load::<AccountStorageDepositQuery>(conn)?
+        .into_iter()
+        .map(|row| {
+            (
+                row.account_address.clone(),
+                AccountStorageDeposit::from(row),
+            )
+        })
+        .collect();
+    let (deposits, negatives, _) = AccountStorageDeposit::apply(usages, &stored);
+    write_deposits(conn, &deposits)?;
+    Ok(negatives)
+}
+
+fn write_deposits(
+    conn: &mut PgConnection,
+    deposits: &[AccountStorageDeposit],
+) -> Result<(), diesel::result::Error> {
+    use schema::account_storage_deposits::dsl::*;
+
+    let chunks = get_chunks(deposits.len(), AccountStorageDeposit::field_count());
+    for (start_ind, end_ind) in chunks {
+        execute_with_better_error(
+            conn,
+            diesel::insert_into(schema::account_storage_deposits::table)
+                .values(&deposits[start_ind..end_ind])
+                .on_conflict(account_address)
+                .do_update()
+                .set((
+                    total_deposited_octas.eq(excluded(total_deposited_octas)),
+                    total_refunded_octas.eq(excluded(total_refunded_octas)),
+                    deposit_balance_octas.eq(excluded(deposit_balance_octas)),
+                    last_transaction_version.eq(excluded(last_transaction_version)),
+                    inserted_at.eq(excluded(inserted_at)),
+                )),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{custom::test_utils, models::balance_anomalies::BalanceAnomalyQuery};
+    use diesel::Connection;
+
+    fn usage(payer: &str, version: i64, fee: u64, refund: u64) -> TransactionStorageUsage {
+        TransactionStorageUsage {
+            transaction_version: version,
+            payer_address: payer.to_string(),
+            storage_fee_octas: Some(BigDecimal::from(fee)),
+            storage_fee_refund_octas: Some(BigDecimal::from(refund)),
+            slots_written: 1,
+            slots_deleted: 0,
+            bytes_written: 0,
+        }
+    }
+
+    #[test]
+    fn test_dropped_deposit_heals() {
+        let Some(conn_pool) = test_utils::test_pool() else {
+            return;
+        };
+        let mut conn = conn_pool.get().unwrap();
+        // Rolled back, the anomaly and the healed row included
+        conn.begin_test_transaction().unwrap();
+        let payer = format!("0x{:064x}", 0x5d);
+        // Far past any ledger the tests index
+        let version = 1 << 50;
+        let storage_usage =
+            StorageUsage::new("storage_usage_test", &StorageUsageConfig { enabled: true });
+
+        // The deposit of `version` is in the history but never made it to the balance
+        insert_transaction_storage_usage(&mut conn, &[usage(&payer, version, 100, 0)]).unwrap();
+        write_deposits(&mut conn, &[AccountStorageDeposit {
+            account_address: payer.clone(),
+            total_deposited_octas: BigDecimal::zero(),
+            total_refunded_octas: BigDecimal::zero(),
+            deposit_balance_octas: BigDecimal::zero(),
+            last_transaction_version: version,
+        }])
+        .unwrap();
+
+        storage_usage
+            .record_usages(&mut conn, &[usage(&payer, version + 1, 0, 40)])
+            .unwrap();
+
+        let anomalies = schema::balance_anomalies::table
+            .filter(schema::balance_anomalies::account_address.eq(&payer))
+            .load::<BalanceAnomalyQuery>(&mut conn)
+            .unwrap();
+        assert_eq!(anomalies.len(), 1);
+        let anomaly = &anomalies[0];
+        assert_eq!(anomaly.balance_table, "account_storage_deposits");
+        assert_eq!(anomaly.asset, STORAGE_DEPOSIT_ASSET);
+        assert_eq!(anomaly.transaction_version, version + 1);
+        assert_eq!(anomaly.expected_delta, BigDecimal::from(-40));
+        assert_eq!(anomaly.stored_balance, BigDecimal::zero());
+        assert_eq!(anomaly.rederived_balance, Some(BigDecimal::from(60)));
+        assert!(anomaly.healed_at.is_some());
+
+        let deposit = schema::account_storage_deposits::table
+            .find(&payer)
+            .first::<AccountStorageDepositQuery>(&mut conn)
+            .unwrap();
+        assert_eq!(deposit.deposit_balance_octas, BigDecimal::from(60));
+        assert_eq!(deposit.total_deposited_octas, BigDecimal::from(100));
+        assert_eq!(deposit.total_refunded_octas, BigDecimal::from(40));
+        assert_eq!(deposit.last_transaction_version, version + 1);
+    }
+}
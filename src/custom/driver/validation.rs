@@ -0,0 +1,333 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Invariant checks on what a processor is about to commit. A processor hands the output of its
+//! parsing to its `Validator` before storing or publishing anything; every rule returns the
+//! violations it finds, which are counted in `indexer_validation_violations_count` and recorded in
+//! `validation_violations` with enough context to look into them. A rule with the `fail` policy
+//! fails the batch, which is then retried like any other failed batch, and raises a
+//! `validation_failed` alert, see `driver::alerts`; a `warn` rule doesn't.
+//!
+//! Each processor ships a default rule set next to its output type. Custom rules are registered
+//! through `ProcessorOptions` and the policy of any rule can be overridden, or the rule turned
+//! off, in the `validation` config.
+
+use crate::{
+    counters::{VALIDATION_FAILED_BATCHES, VALIDATION_VIOLATIONS},
+    custom::driver::{
+        alerts::{self, Alert},
+        config::ValidationConfig,
+    },
+    database::{execute_with_better_error, get_chunks, PgPoolConnection},
+    models::validation_violations::ValidationViolation,
+    schema,
+};
+use anyhow::bail;
+use aptos_logger::{error, warn};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// Violations of a rule stored per batch, all of them are counted
+const MAX_STORED_VIOLATIONS: usize = 100;
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    /// Violations are logged, counted and stored, the batch goes on
+    Warn,
+    /// As `Warn`, and the batch fails before anything is committed
+    Fail,
+}
+
+impl Policy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Policy::Warn => "warn",
+            Policy::Fail => "fail",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    pub transaction_version: Option<i64>,
+    pub message: String,
+    /// The offending row or whatever else helps to investigate
+    pub details: Value,
+}
+
+impl Violation {
+    pub fn new(transaction_version: i64, message: impl Into<String>, details: Value) -> Self {
+        Self {
+            transaction_version: Some(transaction_version),
+            message: message.into(),
+            details,
+        }
+    }
+}
+
+type Check<O> = Arc<dyn Fn(&O) -> Vec<Violation> + Send + Sync>;
+
+/// A named check of a processor's output `O`
+pub struct Rule<O> {
+    pub name: String,
+    pub policy: Policy,
+    check: Check<O>,
+}
+
+impl<O> Rule<O> {
+    pub fn new(
+        name: impl Into<String>,
+        policy: Policy,
+        check: impl Fn(&O) -> Vec<Violation> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            policy,
+            check: Arc::new(check),
+        }
+    }
+
+    pub fn check(&self, output: &O) -> Vec<Violation> {
+        (self.check)(output)
+    }
+}
+
+impl<O> Clone for Rule<O> {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            policy: self.policy,
+            check: self.check.clone(),
+        }
+    }
+}
+
+/// The rules of one processor, with the config's overrides applied
+pub struct Validator<O> {
+    processor: &'static str,
+    rules: Vec<Rule<O>>,
+}
+
+impl<O> Validator<O> {
+    pub fn new(processor: &'static str, rules: Vec<Rule<O>>, config: &ValidationConfig) -> Self {
+        let rules = if config.enabled {
+            rules
+                .into_iter()
+                .filter(|rule| !config.disabled_rules.contains(&rule.name))
+                .map(|mut rule| {
+                    if let Some(policy) = config.policies.get(&rule.name) {
+                        rule.policy = *policy;
+                    }
+                    rule
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+        Self { processor, rules }
+    }
+
+    /// Runs every rule on `output`, recording the violations in `conn`, if any. Errors if a
+    /// `fail` rule found violations.
+    pub fn validate(
+        &self,
+        conn: Option<&mut PgPoolConnection>,
+        output: &O,
+        start_version: u64,
+        end_version: u64,
+    ) -> anyhow::Result<()> {
+        let mut rows = vec![];
+        let mut failed = vec![];
+        for rule in &self.rules {
+            let violations = rule.check(output);
+            if violations.is_empty() {
+                continue;
+            }
+            VALIDATION_VIOLATIONS
+                .with_label_values(&[self.processor, &rule.name, rule.policy.as_str()])
+                .inc_by(violations.len() as u64);
+            warn!(
+                processor_name = self.processor,
+                rule = rule.name,
+                policy = rule.policy.as_str(),
+                start_version = start_version,
+                end_version = end_version,
+                violations = violations.len(),
+                first = violations[0].message,
+                "Validation rule violated"
+            );
+            if rule.policy == Policy::Fail {
+                VALIDATION_FAILED_BATCHES
+                    .with_label_values(&[self.processor, &rule.name])
+                    .inc();
+                failed.push(FailedRule {
+                    rule: rule.name.clone(),
+                    violations: violations.len(),
+                    first: violations[0].message.clone(),
+                });
+            }
+            rows.extend(
+                violations
+                    .into_iter()
+                    .take(MAX_STORED_VIOLATIONS)
+                    .enumerate()
+                    .map(|(index, violation)| ValidationViolation {
+                        processor: self.processor.to_string(),
+                        rule_name: rule.name.clone(),
+                        start_version: start_version as i64,
+                        end_version: end_version as i64,
+                        violation_index: index as i64,
+                        policy: rule.policy.as_str().to_string(),
+                        transaction_version: violation.transaction_version,
+                        message: violation.message,
+                        details: violation.details,
+                    }),
+            );
+        }
+        // Recording is best effort, a warn rule never holds up the batch
+        if let Some(conn) = conn {
+            if let Err(err) = insert_validation_violations(conn, &rows) {
+                error!(
+                    processor_name = self.processor,
+                    error = ?err,
+                    "Failed to record validation violations"
+                );
+            }
+        }
+        if !failed.is_empty() {
+            let alert = alert(self.processor, start_version, end_version, &failed);
+            let summary = alert.summary.clone();
+            alerts::fire(alert);
+            bail!(summary);
+        }
+        Ok(())
+    }
+}
+
+/// A `fail` rule that found violations in a batch
+#[derive(Debug, Serialize)]
+struct FailedRule {
+    rule: String,
+    violations: usize,
+    /// Message of the first violation
+    first: String,
+}
+
+fn alert(processor: &str, start_version: u64, end_version: u64, failed: &[FailedRule]) -> Alert {
+    Alert {
+        kind: "validation_failed",
+        summary: format!(
+            "[{}] Versions {} to {} failed validation rules {:?}",
+            processor,
+            start_version,
+            end_version,
+            failed.iter().map(|rule| &rule.rule).collect::<Vec<_>>()
+        ),
+        details: json!({
+            "processor": processor,
+            "start_version": start_version,
+            "end_version": end_version,
+            "rules": failed,
+        }),
+    }
+}
+
+fn insert_validation_violations(
+    conn: &mut PgPoolConnection,
+    items_to_insert: &[ValidationViolation],
+) -> Result<(), diesel::result::Error> {
+    use schema::validation_violations::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), ValidationViolation::field_count());
+    for (start_ind, end_ind) in chunks {
+        execute_with_better_error(
+            conn,
+            diesel::insert_into(schema::validation_violations::table)
+                .values(&items_to_insert[start_ind..end_ind])
+                .on_conflict((
+                    processor,
+                    rule_name,
+                    start_version,
+                    end_version,
+                    violation_index,
+                ))
+                .do_nothing(),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn negatives() -> Rule<Vec<i64>> {
+        Rule::new("non_negative", Policy::Fail, |output: &Vec<i64>| {
+            output
+                .iter()
+                .enumerate()
+                .filter(|(_, value)| **value < 0)
+                .map(|(version, value)| {
+                    Violation::new(version as i64, "Negative value", json!(value))
+                })
+                .collect()
+        })
+    }
+
+    fn odds() -> Rule<Vec<i64>> {
+        Rule::new("even", Policy::Warn, |output: &Vec<i64>| {
+            output
+                .iter()
+                .filter(|value| **value % 2 != 0)
+                .map(|value| Violation::new(*value, "Odd value", json!(value)))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn test_rules_and_overrides() {
+        let rule = negatives();
+        let violations = rule.check(&vec![1, -2, 3, -4]);
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[0].transaction_version, Some(1));
+
+        let config = ValidationConfig {
+            enabled: true,
+            policies: HashMap::from([("non_negative".to_string(), Policy::Warn)]),
+            disabled_rules: vec!["even".to_string()],
+        };
+        let validator = Validator::new("test_processor", vec![negatives(), odds()], &config);
+        assert_eq!(validator.rules.len(), 1);
+        assert_eq!(validator.rules[0].name, "non_negative");
+        assert_eq!(validator.rules[0].policy, Policy::Warn);
+
+        let config = ValidationConfig {
+            enabled: false,
+            ..ValidationConfig::default()
+        };
+        let validator = Validator::new("test_processor", vec![negatives(), odds()], &config);
+        assert!(validator.rules.is_empty());
+    }
+
+    #[test]
+    fn test_alert() {
+        let failed = [FailedRule {
+            rule: "non_negative".to_string(),
+            violations: 2,
+            first: "Negative value".to_string(),
+        }];
+        let alert = alert("test_processor", 100, 199, &failed);
+        assert_eq!(alert.kind, "validation_failed");
+        assert_eq!(
+            alert.summary,
+            "[test_processor] Versions 100 to 199 failed validation rules [\"non_negative\"]"
+        );
+        assert_eq!(alert.details["rules"][0]["violations"], 2);
+        assert_eq!(alert.details["rules"][0]["first"], "Negative value");
+    }
+}
@@ -0,0 +1,278 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Guards a processor's watermark against holes in what it processed. The runtime hands every
+//! round's batches to the processor's `VersionGuard` before the watermark moves, by start
+//! version since the batches of a round finish in any order, and each has to start right after
+//! the last one, or at the next version its shard owns (see `driver::sharding`).
+//!
+//! In `strict` mode a batch that skips or repeats versions stops the processor before its
+//! watermark gets past them. In `tolerant` mode, e.g. for setups processing ranges in parallel
+//! on purpose, the batch is logged and processing carries on. Either way it's counted in
+//! `indexer_version_gaps_total`.
+//!
+//! `verify_gaps` finds the versions missing from `transactions` afterwards, for the admin server
+//! to report.
+
+use crate::{
+    counters::VERSION_GAPS,
+    custom::driver::{
+        config::{VersionGuardConfig, VersionGuardMode},
+        sharding::ShardSpec,
+    },
+};
+use aptos_logger::warn;
+use diesel::{sql_query, sql_types::BigInt, PgConnection, QueryResult, RunQueryDsl};
+use serde::Serialize;
+use std::fmt::{self, Display};
+
+/// Where the next batch of a processor has to start
+#[derive(Clone, Debug)]
+pub struct VersionGuard {
+    processor_name: String,
+    mode: VersionGuardMode,
+    shard: Option<ShardSpec>,
+    next_version: u64,
+}
+
+/// A batch that didn't start at the version expected
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VersionGap {
+    pub expected_version: u64,
+    pub start_version: u64,
+    pub end_version: u64,
+}
+
+/// Inclusive range of versions
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, QueryableByName)]
+pub struct VersionRange {
+    #[diesel(sql_type = BigInt)]
+    pub start_version: i64,
+    #[diesel(sql_type = BigInt)]
+    pub end_version: i64,
+}
+
+impl VersionGuard {
+    /// For a processor whose next batch starts at `start_version`
+    pub fn new(
+        processor_name: &str,
+        config: &VersionGuardConfig,
+        start_version: u64,
+        shard: Option<ShardSpec>,
+    ) -> Self {
+        let mut guard = Self {
+            processor_name: processor_name.to_string(),
+            mode: config.mode,
+            shard,
+            next_version: 0,
+        };
+        guard.next_version = guard.owned_from(start_version);
+        guard
+    }
+
+    /// Checks the batches of a round, each `(start_version, end_version)`, in version order
+    pub fn accept_round(&mut self, batches: &mut [(u64, u64)]) -> Result<(), VersionGap> {
+        batches.sort_unstable();
+        for &(start_version, end_version) in batches.iter() {
+            self.accept(start_version, end_version)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the batch `start_version..=end_version` starts where the last one ended. Only
+    /// fails in `strict` mode, and then expects the same batch again.
+    pub fn accept(&mut self, start_version: u64, end_version: u64) -> Result<(), VersionGap> {
+        if self.mode == VersionGuardMode::Off {
+            return Ok(());
+        }
+        if start_version != self.next_version {
+            let gap = VersionGap {
+                expected_version: self.next_version,
+                start_version,
+                end_version,
+            };
+            VERSION_GAPS
+                .with_label_values(&[self.processor_name.as_str()])
+                .inc();
+            if self.mode == VersionGuardMode::Strict {
+                return Err(gap);
+            }
+            warn!(
+                processor_name = self.processor_name,
+                expected_version = gap.expected_version,
+                start_version = start_version,
+                end_version = end_version,
+                "{}",
+                gap
+            );
+        }
+        // An older batch in tolerant mode doesn't move it back
+        self.next_version = self.next_version.max(self.owned_from(end_version + 1));
+        Ok(())
+    }
+
+    /// Version the next batch has to start at
+    pub fn next_version(&self) -> u64 {
+        self.next_version
+    }
+
+    fn owned_from(&self, version: u64) -> u64 {
+        match &self.shard {
+            Some(shard) => shard.next_owned(version),
+            None => version,
+        }
+    }
+}
+
+impl VersionGap {
+    /// The versions skipped, `None` if the batch repeated versions instead
+    pub fn missing(&self) -> Option<(u64, u64)> {
+        (self.start_version > self.expected_version)
+            .then(|| (self.expected_version, self.start_version - 1))
+    }
+}
+
+impl Display for VersionGap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Batch {}-{} doesn't start at version {}: ",
+            self.start_version, self.end_version, self.expected_version
+        )?;
+        match self.missing() {
+            Some((first, last)) => write!(f, "versions {}-{} are missing", first, last),
+            None => write!(
+                f,
+                "versions {}-{} were already processed",
+                self.start_version,
+                self.expected_version - 1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VersionGap {}
+
+/// The ranges of versions from `from` to `to`, inclusive, that have no row in `transactions`
+pub fn verify_gaps(conn: &mut PgConnection, from: i64, to: i64) -> QueryResult<Vec<VersionRange>> {
+    // `from - 1` and the default of `LEAD` catch the gaps at either end
+    sql_query(
+        "
+        SELECT start_version, end_version
+        FROM (
+            SELECT
+                version + 1 AS start_version,
+                LEAD(version, 1, $2 + 1) OVER (ORDER BY version) - 1 AS end_version
+            FROM (
+                SELECT $1 - 1 AS version
+                UNION ALL
+                SELECT version FROM transactions WHERE version BETWEEN $1 AND $2
+            ) versions
+        ) gaps
+        WHERE start_version <= end_version
+        ORDER BY start_version
+        ",
+    )
+    .bind::<BigInt, _>(from)
+    .bind::<BigInt, _>(to)
+    .load(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::new_db_pool, indexer::tailer::MIGRATIONS};
+    use diesel_migrations::MigrationHarness;
+
+    fn guard(mode: VersionGuardMode, shard: Option<ShardSpec>) -> VersionGuard {
+        VersionGuard::new("version_guard_test", &VersionGuardConfig { mode }, 0, shard)
+    }
+
+    #[test]
+    fn test_strict() {
+        let mut guard = guard(VersionGuardMode::Strict, None);
+        guard.accept(0, 99).unwrap();
+        let gap = guard.accept(200, 299).unwrap_err();
+        assert_eq!(gap, VersionGap {
+            expected_version: 100,
+            start_version: 200,
+            end_version: 299,
+        });
+        assert_eq!(gap.missing(), Some((100, 199)));
+        assert_eq!(
+            gap.to_string(),
+            "Batch 200-299 doesn't start at version 100: versions 100-199 are missing"
+        );
+        // Still expects the versions missing
+        guard.accept(100, 199).unwrap();
+        guard.accept(200, 299).unwrap();
+
+        let gap = guard.accept(250, 349).unwrap_err();
+        assert_eq!(gap.missing(), None);
+        assert_eq!(
+            gap.to_string(),
+            "Batch 250-349 doesn't start at version 300: versions 250-299 were already processed"
+        );
+    }
+
+    #[test]
+    fn test_tolerant() {
+        let mut guard = guard(VersionGuardMode::Tolerant, None);
+        guard.accept(0, 99).unwrap();
+        guard.accept(200, 299).unwrap();
+        assert_eq!(guard.next_version(), 300);
+        guard.accept(100, 199).unwrap();
+        assert_eq!(guard.next_version(), 300);
+
+        let mut guard = self::guard(VersionGuardMode::Off, None);
+        guard.accept(200, 299).unwrap();
+    }
+
+    #[test]
+    fn test_round() {
+        let mut guard = guard(VersionGuardMode::Strict, None);
+        // Finished out of order
+        guard
+            .accept_round(&mut [(200, 299), (0, 99), (100, 199)])
+            .unwrap();
+        assert_eq!(guard.next_version(), 300);
+        let gap = guard
+            .accept_round(&mut [(500, 599), (300, 399)])
+            .unwrap_err();
+        assert_eq!(gap.missing(), Some((400, 499)));
+
+        // Shard 1 of 2 owns 100-199, 300-399...
+        let shard = ShardSpec {
+            shard_count: 2,
+            shard_index: 1,
+            slice_size: 100,
+            drain_at_version: None,
+        };
+        let mut guard = self::guard(VersionGuardMode::Strict, Some(shard));
+        assert_eq!(guard.next_version(), 100);
+        guard
+            .accept_round(&mut [(100, 149), (150, 199), (300, 399)])
+            .unwrap();
+        assert!(guard.accept(700, 799).is_err());
+    }
+
+    #[test]
+    fn test_verify_gaps() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(&database_url).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        // Far past any ledger the tests index
+        let from = 1 << 50;
+        assert_eq!(verify_gaps(&mut conn, from, from + 99).unwrap(), vec![
+            VersionRange {
+                start_version: from,
+                end_version: from + 99,
+            }
+        ]);
+        assert!(verify_gaps(&mut conn, from, from - 1).unwrap().is_empty());
+    }
+}
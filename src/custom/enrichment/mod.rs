@@ -0,0 +1,516 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Late enrichment: when the indexer learns to decode something new, the rows indexed before
+//! that stay undecoded. An `Enricher` finds those rows and computes their new values; the
+//! `EnrichmentDriver` walks the enricher's table in primary key order in bounded, rate limited
+//! batches. Each batch's updates are committed together with the enricher's cursor in
+//! `enrichment_progress`, so a restart resumes at the last committed batch. Every run is tracked
+//! as an `enrichment` operation, and stops after the batch it's cancelled in.
+
+pub mod token_properties;
+
+use crate::{
+    counters::{ENRICHMENT_BACKLOG_ROWS, ENRICHMENT_ROWS_ENRICHED, ENRICHMENT_ROWS_PER_SECOND},
+    custom::driver::{
+        config::EnrichmentConfig,
+        operations::{self, Operation},
+    },
+    database::{execute_with_better_error, PgDbPool, PgPoolConnection},
+    models::enrichment_progress::{EnrichmentProgress, EnrichmentProgressQuery},
+    schema::enrichment_progress,
+};
+use aptos_logger::{error, info};
+use diesel::{
+    pg::upsert::excluded, result::Error, sql_query, sql_types::Text, ExpressionMethods,
+    PgConnection, QueryResult, RunQueryDsl,
+};
+use once_cell::sync::OnceCell;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+pub trait Enricher: Send + Sync + 'static {
+    type Row: Send;
+    type Update: Send;
+
+    /// Key of the enricher's progress. Once an enricher has caught up it doesn't run again, so
+    /// give it a new name (e.g. with a `_v2` suffix) to walk the table again.
+    fn name(&self) -> &'static str;
+
+    fn table(&self) -> &'static str;
+
+    /// Up to `limit` rows that still need enriching and come after `cursor`, in primary key
+    /// order. `None` starts from the beginning of the table.
+    fn select_unenriched(
+        &self,
+        conn: &mut PgConnection,
+        cursor: Option<&serde_json::Value>,
+        limit: i64,
+    ) -> QueryResult<Vec<Self::Row>>;
+
+    /// Primary key of a row, stored as the cursor once the row's batch is committed
+    fn cursor(&self, row: &Self::Row) -> serde_json::Value;
+
+    /// New values of the rows. Rows that can't be enriched are left out and stay as they are.
+    fn enrich(&self, rows: &[Self::Row]) -> Vec<Self::Update>;
+
+    /// Writes the updates and returns the number of rows changed. Runs in the transaction that
+    /// also moves the cursor.
+    fn apply(&self, conn: &mut PgConnection, updates: &[Self::Update]) -> QueryResult<usize>;
+
+    /// Cheap estimate of the rows left to enrich, if the enricher has one
+    fn estimate_backlog(&self, _conn: &mut PgConnection) -> QueryResult<Option<i64>> {
+        Ok(None)
+    }
+}
+
+/// Starts the enrichers listed in the config, each walking its table until it has caught up.
+/// Only the first call in a process has an effect, so every processor runtime can call it.
+/// Fails, before starting any, if the config lists an enricher that doesn't exist.
+pub fn spawn_configured(
+    config: &EnrichmentConfig,
+    connection_pool: PgDbPool,
+) -> anyhow::Result<()> {
+    static STARTED: OnceCell<()> = OnceCell::new();
+    if !config.enabled {
+        return Ok(());
+    }
+    config.validate()?;
+    if STARTED.set(()).is_err() {
+        return Ok(());
+    }
+    for name in &config.enrichers {
+        match name.as_str() {
+            token_properties::NAME => spawn(EnrichmentDriver::new(
+                token_properties::TokenPropertiesEnricher,
+                connection_pool.clone(),
+                config,
+            )),
+            _ => unreachable!("Validated enricher {}", name),
+        }
+    }
+    Ok(())
+}
+
+/// Whether `name` is an enricher `launch` can run
+pub fn is_supported(name: &str) -> bool {
+    name == token_properties::NAME
+}
+
+/// Runs the enricher `name` once on a blocking thread, tracked as `operation`, e.g. to resume it
+/// from the admin server
+pub fn launch(
+    name: &str,
+    config: &EnrichmentConfig,
+    connection_pool: PgDbPool,
+    operation: Operation,
+) -> anyhow::Result<()> {
+    let driver = match name {
+        token_properties::NAME => EnrichmentDriver::new(
+            token_properties::TokenPropertiesEnricher,
+            connection_pool,
+            config,
+        ),
+        _ => anyhow::bail!("Enricher {} is unsupported", name),
+    };
+    tokio::task::spawn_blocking(move || {
+        if let Err(err) = driver.run_tracked(Some(operation)) {
+            error!(enricher = driver.enricher.name(), error = ?err, "Enrichment failed");
+        }
+    });
+    Ok(())
+}
+
+fn spawn<E: Enricher>(driver: EnrichmentDriver<E>) {
+    let driver = Arc::new(driver);
+    tokio::spawn(async move {
+        loop {
+            let task_driver = driver.clone();
+            match tokio::task::spawn_blocking(move || task_driver.run()).await {
+                Ok(Ok(())) => return,
+                Ok(Err(err)) => error!(
+                    enricher = driver.enricher.name(),
+                    error = ?err,
+                    "Enrichment failed, resuming from the last committed batch"
+                ),
+                Err(err) => {
+                    error!(enricher = driver.enricher.name(), error = ?err, "Enrichment panicked");
+                    return;
+                },
+            }
+            tokio::time::sleep(RETRY_DELAY).await;
+        }
+    });
+}
+
+pub struct EnrichmentDriver<E: Enricher> {
+    enricher: E,
+    connection_pool: PgDbPool,
+    batch_size: i64,
+    max_rows_per_sec: u64,
+}
+
+impl<E: Enricher> EnrichmentDriver<E> {
+    pub fn new(enricher: E, connection_pool: PgDbPool, config: &EnrichmentConfig) -> Self {
+        Self {
+            enricher,
+            connection_pool,
+            batch_size: config.batch_size.max(1),
+            max_rows_per_sec: config.max_rows_per_sec,
+        }
+    }
+
+    /// Enriches batches until the table has been walked to the end. Blocks, so run it on a
+    /// blocking thread.
+    pub fn run(&self) -> anyhow::Result<()> {
+        self.run_tracked(None)
+    }
+
+    /// Like `run`, tracked as `operation` if given instead of a new operation
+    pub fn run_tracked(&self, operation: Option<Operation>) -> anyhow::Result<()> {
+        let name = self.enricher.name();
+        let (mut conn, progress, backlog) = match self.load_progress() {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                if let Some(operation) = operation {
+                    operation.fail(operations::error_code(&err), format!("{:#}", err));
+                }
+                return Err(err);
+            },
+        };
+        if let Some(completed_at) = progress.as_ref().and_then(|p| p.completed_at) {
+            info!(
+                enricher = name,
+                completed_at = completed_at.to_string(),
+                "Enrichment already completed"
+            );
+            if let Some(operation) = operation {
+                operation.complete(progress.map_or(0, |p| p.rows_enriched as u64));
+            }
+            return Ok(());
+        }
+        let (cursor, rows_enriched) = progress
+            .map(|p| (p.last_cursor, p.rows_enriched))
+            .unwrap_or((None, 0));
+        info!(
+            enricher = name,
+            table_name = self.enricher.table(),
+            cursor = cursor.as_ref().map(|c| c.to_string()),
+            rows_enriched = rows_enriched,
+            "Starting enrichment"
+        );
+        let total = backlog.map(|backlog| (rows_enriched + backlog) as u64);
+        let operation = match operation {
+            Some(operation) => {
+                operation.set_work(rows_enriched as u64, total);
+                operation
+            },
+            None => operations::start(
+                "enrichment",
+                "enrichment.enrichers",
+                serde_json::json!({ "enricher": name, "table": self.enricher.table() }),
+                rows_enriched as u64,
+                total,
+            ),
+        };
+        match self.enrich_batches(&mut conn, &operation, cursor, rows_enriched) {
+            Ok((rows_enriched, true)) => {
+                operation.complete(rows_enriched as u64);
+                Ok(())
+            },
+            Ok((rows_enriched, false)) => {
+                info!(
+                    enricher = name,
+                    rows_enriched = rows_enriched,
+                    "Enrichment cancelled"
+                );
+                operation.cancelled();
+                Ok(())
+            },
+            Err(err) => {
+                operation.fail(operations::error_code(&err), format!("{:#}", err));
+                Err(err)
+            },
+        }
+    }
+
+    /// A connection, the enricher's progress and its backlog estimate
+    fn load_progress(
+        &self,
+    ) -> anyhow::Result<(
+        PgPoolConnection,
+        Option<EnrichmentProgressQuery>,
+        Option<i64>,
+    )> {
+        let mut conn = self.connection_pool.get()?;
+        let progress = EnrichmentProgressQuery::get_by_enricher(self.enricher.name(), &mut conn)?;
+        let backlog = self.enricher.estimate_backlog(&mut conn)?;
+        Ok((conn, progress, backlog))
+    }
+
+    /// Enriches the batches after `cursor` until the end of the table or until the operation is
+    /// cancelled. Returns the rows enriched in total and whether the end was reached.
+    fn enrich_batches(
+        &self,
+        conn: &mut PgConnection,
+        operation: &Operation,
+        mut cursor: Option<serde_json::Value>,
+        mut rows_enriched: i64,
+    ) -> anyhow::Result<(i64, bool)> {
+        let name = self.enricher.name();
+        loop {
+            let batch_start = Instant::now();
+            let rows = self
+                .enricher
+                .select_unenriched(conn, cursor.as_ref(), self.batch_size)?;
+            let completed = (rows.len() as i64) < self.batch_size;
+            let batch_cursor = rows.last().map(|row| self.enricher.cursor(row)).or(cursor);
+            let updates = self.enricher.enrich(&rows);
+            let enriched = conn
+                .build_transaction()
+                .read_write()
+                .run::<_, Error, _>(|pg_conn| {
+                    let enriched = self.enricher.apply(pg_conn, &updates)?;
+                    save_progress(pg_conn, &EnrichmentProgress {
+                        enricher_name: name.to_string(),
+                        table_name: self.enricher.table().to_string(),
+                        last_cursor: batch_cursor.clone(),
+                        rows_enriched: rows_enriched + enriched as i64,
+                        completed_at: completed.then(|| chrono::Utc::now().naive_utc()),
+                    })?;
+                    Ok(enriched)
+                })?;
+            cursor = batch_cursor;
+            rows_enriched += enriched as i64;
+            ENRICHMENT_ROWS_ENRICHED
+                .with_label_values(&[name])
+                .inc_by(enriched as u64);
+            operation.progress(rows_enriched as u64);
+            if let Some(backlog) = self.enricher.estimate_backlog(conn)? {
+                ENRICHMENT_BACKLOG_ROWS
+                    .with_label_values(&[name])
+                    .set(backlog);
+            }
+
+            if completed {
+                ENRICHMENT_ROWS_PER_SECOND.with_label_values(&[name]).set(0);
+                info!(
+                    enricher = name,
+                    rows_enriched = rows_enriched,
+                    "Enrichment completed"
+                );
+                return Ok((rows_enriched, true));
+            }
+            if operation.is_cancelled() {
+                ENRICHMENT_ROWS_PER_SECOND.with_label_values(&[name]).set(0);
+                return Ok((rows_enriched, false));
+            }
+
+            // Rate limit on the rows looked at, they're what costs the db
+            if self.max_rows_per_sec > 0 {
+                let min_duration =
+                    Duration::from_secs_f64(rows.len() as f64 / self.max_rows_per_sec as f64);
+                if let Some(wait) = min_duration.checked_sub(batch_start.elapsed()) {
+                    std::thread::sleep(wait);
+                }
+            }
+            ENRICHMENT_ROWS_PER_SECOND
+                .with_label_values(&[name])
+                .set((enriched as f64 / batch_start.elapsed().as_secs_f64()) as i64);
+        }
+    }
+}
+
+fn save_progress(conn: &mut PgConnection, progress: &EnrichmentProgress) -> QueryResult<usize> {
+    execute_with_better_error(
+        conn,
+        diesel::insert_into(enrichment_progress::table)
+            .values(progress)
+            .on_conflict(enrichment_progress::enricher_name)
+            .do_update()
+            .set((
+                enrichment_progress::last_cursor.eq(excluded(enrichment_progress::last_cursor)),
+                enrichment_progress::rows_enriched.eq(excluded(enrichment_progress::rows_enriched)),
+                enrichment_progress::completed_at.eq(excluded(enrichment_progress::completed_at)),
+                enrichment_progress::last_updated.eq(excluded(enrichment_progress::last_updated)),
+            )),
+        None,
+    )
+}
+
+#[derive(Debug, QueryableByName)]
+struct RelationEstimate {
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    estimate: i64,
+}
+
+/// Row count of a table or index as of its last vacuum/analyze. For a partial index over the
+/// unenriched rows this is the backlog, without counting it.
+pub fn estimate_relation_rows(
+    conn: &mut PgConnection,
+    relation_name: &str,
+) -> QueryResult<Option<i64>> {
+    let mut res: Vec<RelationEstimate> =
+        sql_query("SELECT reltuples::BIGINT AS estimate FROM pg_class WHERE relname = $1")
+            .bind::<Text, _>(relation_name)
+            .get_results(conn)?;
+    // Relations that were never analyzed report -1
+    Ok(res
+        .pop()
+        .map(|r| r.estimate)
+        .filter(|estimate| *estimate >= 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{custom::test_utils, schema::enrichment_progress::dsl};
+    use diesel::{sql_types::BigInt, QueryDsl};
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    };
+
+    const TEST_ENRICHER: &str = "enrichment_resume_test";
+
+    #[derive(Debug, QueryableByName)]
+    struct TestRow {
+        #[diesel(sql_type = BigInt)]
+        id: i64,
+    }
+
+    /// Enriches the rows of a table of its own, failing the batch of `fail_at` while `failing`
+    struct TestEnricher {
+        fail_at: i64,
+        failing: AtomicBool,
+        cursors: Mutex<Vec<Option<serde_json::Value>>>,
+    }
+
+    impl Enricher for TestEnricher {
+        type Row = TestRow;
+        type Update = i64;
+
+        fn name(&self) -> &'static str {
+            TEST_ENRICHER
+        }
+
+        fn table(&self) -> &'static str {
+            "enrichment_test_rows"
+        }
+
+        fn select_unenriched(
+            &self,
+            conn: &mut PgConnection,
+            cursor: Option<&serde_json::Value>,
+            limit: i64,
+        ) -> QueryResult<Vec<Self::Row>> {
+            self.cursors.lock().unwrap().push(cursor.cloned());
+            let after = cursor
+                .and_then(|cursor| cursor["id"].as_i64())
+                .unwrap_or(-1);
+            sql_query(
+                "SELECT id FROM enrichment_test_rows WHERE enriched IS NULL AND id > $1 \
+                ORDER BY id LIMIT $2",
+            )
+            .bind::<BigInt, _>(after)
+            .bind::<BigInt, _>(limit)
+            .load(conn)
+        }
+
+        fn cursor(&self, row: &Self::Row) -> serde_json::Value {
+            serde_json::json!({ "id": row.id })
+        }
+
+        fn enrich(&self, rows: &[Self::Row]) -> Vec<Self::Update> {
+            rows.iter().map(|row| row.id).collect()
+        }
+
+        fn apply(&self, conn: &mut PgConnection, updates: &[Self::Update]) -> QueryResult<usize> {
+            if self.failing.load(Ordering::SeqCst) && updates.contains(&self.fail_at) {
+                return Err(Error::RollbackTransaction);
+            }
+            let mut changed = 0;
+            for id in updates {
+                changed +=
+                    sql_query("UPDATE enrichment_test_rows SET enriched = id * 10 WHERE id = $1")
+                        .bind::<BigInt, _>(id)
+                        .execute(conn)?;
+            }
+            Ok(changed)
+        }
+    }
+
+    #[test]
+    fn test_resumes_after_last_committed_batch() {
+        let Some(conn_pool) = test_utils::test_pool() else {
+            return;
+        };
+        let mut conn = conn_pool.get().unwrap();
+        sql_query("DROP TABLE IF EXISTS enrichment_test_rows")
+            .execute(&mut conn)
+            .unwrap();
+        sql_query("CREATE TABLE enrichment_test_rows (id BIGINT PRIMARY KEY, enriched BIGINT)")
+            .execute(&mut conn)
+            .unwrap();
+        sql_query("INSERT INTO enrichment_test_rows (id) SELECT generate_series(0, 4)")
+            .execute(&mut conn)
+            .unwrap();
+        diesel::delete(dsl::enrichment_progress.filter(dsl::enricher_name.eq(TEST_ENRICHER)))
+            .execute(&mut conn)
+            .unwrap();
+        let config = EnrichmentConfig {
+            batch_size: 2,
+            max_rows_per_sec: 0,
+            ..EnrichmentConfig::default()
+        };
+        let driver = EnrichmentDriver::new(
+            TestEnricher {
+                fail_at: 3,
+                failing: AtomicBool::new(true),
+                cursors: Mutex::new(vec![]),
+            },
+            conn_pool.clone(),
+            &config,
+        );
+
+        // The second batch, 2 and 3, fails and only the first is committed
+        assert!(driver.run().is_err());
+        let progress = EnrichmentProgressQuery::get_by_enricher(TEST_ENRICHER, &mut conn)
+            .unwrap()
+            .unwrap();
+        assert_eq!(progress.last_cursor, Some(serde_json::json!({ "id": 1 })));
+        assert_eq!(progress.rows_enriched, 2);
+        assert!(progress.completed_at.is_none());
+
+        // A restart picks up after the committed cursor, not from the start
+        driver.enricher.failing.store(false, Ordering::SeqCst);
+        driver.enricher.cursors.lock().unwrap().clear();
+        driver.run().unwrap();
+        assert_eq!(
+            driver.enricher.cursors.lock().unwrap()[0],
+            Some(serde_json::json!({ "id": 1 }))
+        );
+        let progress = EnrichmentProgressQuery::get_by_enricher(TEST_ENRICHER, &mut conn)
+            .unwrap()
+            .unwrap();
+        assert_eq!(progress.rows_enriched, 5);
+        assert!(progress.completed_at.is_some());
+        let enriched = sql_query("SELECT id FROM enrichment_test_rows WHERE enriched = id * 10")
+            .load::<TestRow>(&mut conn)
+            .unwrap();
+        assert_eq!(enriched.len(), 5);
+
+        // Once completed it doesn't walk the table again
+        driver.enricher.cursors.lock().unwrap().clear();
+        driver.run().unwrap();
+        assert!(driver.enricher.cursors.lock().unwrap().is_empty());
+
+        sql_query("DROP TABLE enrichment_test_rows")
+            .execute(&mut conn)
+            .unwrap();
+    }
+}
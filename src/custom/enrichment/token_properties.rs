@@ -0,0 +1,242 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Decodes the v1 token property maps of `current_token_datas` that the parser left BCS encoded.
+//! Before `util::convert_bcs_hex` knew `u16`, `u32`, `u256`, `vector<u8>` and the `String`
+//! spelling of strings, values of those types were stored as their BCS hex, and the flattened
+//! map no longer says which type they had. The enricher reads the property map as written on
+//! chain, with its types, from the token data's `table_items` row at the row's
+//! `last_transaction_version`, and decodes it again. Maps still stored in their raw
+//! `{"map": {"data": [...]}}` form are decoded from the row itself. Rows whose `table_items`
+//! weren't indexed, e.g. by a publish only default processor, stay as they are.
+
+use super::{estimate_relation_rows, Enricher};
+use crate::{
+    database::execute_with_better_error,
+    models::token_models::token_utils::TokenDataIdType,
+    schema::{current_token_datas, table_items},
+    util,
+};
+use diesel::{
+    dsl::sql, sql_types::Bool, BoolExpressionMethods, ExpressionMethods, PgConnection,
+    PgJsonbExpressionMethods, QueryDsl, QueryResult, RunQueryDsl,
+};
+use serde_json::Value;
+
+pub const NAME: &str = "token_properties_v2";
+/// Partial index over the rows with a value that may be BCS encoded, see the migration that
+/// creates it
+const UNDECODED_INDEX: &str = "ctd_undecoded_properties_v2_index";
+/// A raw map, or a flattened one with a value in hex, which is how undecoded values look. Decoded
+/// addresses look the same, and decode to themselves again.
+const UNDECODED_FILTER: &str =
+    "(default_properties ? 'map' OR default_properties::text LIKE '%\": \"0x%')";
+
+pub struct TokenPropertiesEnricher;
+
+#[derive(Debug)]
+pub struct UndecodedTokenData {
+    pub token_data_id_hash: String,
+    pub default_properties: Value,
+    pub last_transaction_version: i64,
+    /// The property map as written on chain, `None` if its table item wasn't indexed
+    pub raw_properties: Option<Value>,
+}
+
+#[derive(Debug)]
+pub struct DecodedProperties {
+    pub token_data_id_hash: String,
+    pub last_transaction_version: i64,
+    pub default_properties: Value,
+}
+
+impl Enricher for TokenPropertiesEnricher {
+    type Row = UndecodedTokenData;
+    type Update = DecodedProperties;
+
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn table(&self) -> &'static str {
+        "current_token_datas"
+    }
+
+    fn select_unenriched(
+        &self,
+        conn: &mut PgConnection,
+        cursor: Option<&Value>,
+        limit: i64,
+    ) -> QueryResult<Vec<Self::Row>> {
+        use current_token_datas::dsl::*;
+
+        let mut query = current_token_datas
+            .select((
+                token_data_id_hash,
+                default_properties,
+                last_transaction_version,
+            ))
+            .filter(sql::<Bool>(UNDECODED_FILTER))
+            .order(token_data_id_hash.asc())
+            .limit(limit)
+            .into_boxed();
+        if let Some(after) = cursor
+            .and_then(|cursor| cursor.get("token_data_id_hash"))
+            .and_then(|hash| hash.as_str())
+        {
+            query = query.filter(token_data_id_hash.gt(after.to_string()));
+        }
+        let rows = query.load::<(String, Value, i64)>(conn)?;
+
+        // The token data's table item of the same write, found by its key
+        let versions = rows.iter().map(|row| row.2).collect::<Vec<_>>();
+        let items = table_items::table
+            .select((
+                table_items::transaction_version,
+                table_items::decoded_key,
+                table_items::decoded_value,
+            ))
+            .filter(table_items::transaction_version.eq_any(&versions))
+            .filter(
+                table_items::decoded_value
+                    .has_key("default_properties")
+                    .and(table_items::is_deleted.eq(false)),
+            )
+            .load::<(i64, Value, Option<Value>)>(conn)?;
+        Ok(rows
+            .into_iter()
+            .map(|(hash, properties, version)| {
+                let raw_properties = items
+                    .iter()
+                    .filter(|(item_version, _, _)| *item_version == version)
+                    .find(|(_, key, _)| {
+                        serde_json::from_value::<TokenDataIdType>(key.clone())
+                            .map_or(false, |key| key.to_hash() == hash)
+                    })
+                    .and_then(|(_, _, value)| value.as_ref()?.get("default_properties").cloned());
+                UndecodedTokenData {
+                    token_data_id_hash: hash,
+                    default_properties: properties,
+                    last_transaction_version: version,
+                    raw_properties,
+                }
+            })
+            .collect())
+    }
+
+    fn cursor(&self, row: &Self::Row) -> Value {
+        serde_json::json!({ "token_data_id_hash": row.token_data_id_hash })
+    }
+
+    fn enrich(&self, rows: &[Self::Row]) -> Vec<Self::Update> {
+        rows.iter()
+            .filter_map(|row| {
+                let raw = match &row.raw_properties {
+                    Some(raw) => raw,
+                    None if row.default_properties.get("map").is_some() => &row.default_properties,
+                    None => return None,
+                };
+                let decoded = util::convert_bcs_propertymap(raw.clone())?;
+                (decoded != row.default_properties).then(|| DecodedProperties {
+                    token_data_id_hash: row.token_data_id_hash.clone(),
+                    last_transaction_version: row.last_transaction_version,
+                    default_properties: decoded,
+                })
+            })
+            .collect()
+    }
+
+    fn apply(&self, conn: &mut PgConnection, updates: &[Self::Update]) -> QueryResult<usize> {
+        use current_token_datas::dsl::*;
+
+        let mut changed = 0;
+        for update in updates {
+            // A processor may have written a newer version of the row since it was selected,
+            // which is already decoded
+            changed += execute_with_better_error(
+                conn,
+                diesel::update(
+                    current_token_datas
+                        .filter(token_data_id_hash.eq(&update.token_data_id_hash))
+                        .filter(last_transaction_version.eq(update.last_transaction_version)),
+                )
+                .set(default_properties.eq(&update.default_properties)),
+                None,
+            )?;
+        }
+        Ok(changed)
+    }
+
+    fn estimate_backlog(&self, conn: &mut PgConnection) -> QueryResult<Option<i64>> {
+        estimate_relation_rows(conn, UNDECODED_INDEX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(default_properties: Value, raw_properties: Option<Value>) -> UndecodedTokenData {
+        UndecodedTokenData {
+            token_data_id_hash: "ab".to_string(),
+            default_properties,
+            last_transaction_version: 10,
+            raw_properties,
+        }
+    }
+
+    fn property(key: &str, typ: &str, value: &str) -> Value {
+        serde_json::json!({ "key": key, "value": { "type": typ, "value": value } })
+    }
+
+    #[test]
+    fn test_decodes_types_the_parser_left_encoded() {
+        let raw = serde_json::json!({ "map": { "data": [
+            property("level", "u16", "0x0a00"),
+            property("power", "u32", "0x40420f00"),
+            property("rarity", "String", "0x0465706963"),
+            property("seed", "vector<u8>", "0x02beef"),
+            property("score", "u64", "0x0500000000000000"),
+        ] } });
+        // Flattened by the old parser, which only decoded the u64
+        let stored = serde_json::json!({
+            "level": "0x0a00",
+            "power": "0x40420f00",
+            "rarity": "0x0465706963",
+            "seed": "0x02beef",
+            "score": "5",
+        });
+
+        let updates = TokenPropertiesEnricher.enrich(&[row(stored, Some(raw))]);
+        assert_eq!(updates.len(), 1);
+        assert_eq!(
+            updates[0].default_properties,
+            serde_json::json!({
+                "level": "10",
+                "power": "1000000",
+                "rarity": "epic",
+                "seed": "0xbeef",
+                "score": "5",
+            })
+        );
+    }
+
+    #[test]
+    fn test_leaves_decoded_and_unknown_rows() {
+        let raw = serde_json::json!({ "map": { "data": [
+            property("owner", "address", "0x0000000000000000000000000000000000000000000000000000000000000001"),
+        ] } });
+        let decoded = util::convert_bcs_propertymap(raw.clone()).unwrap();
+        // Decodes to what's stored already
+        assert!(TokenPropertiesEnricher
+            .enrich(&[row(decoded.clone(), Some(raw.clone()))])
+            .is_empty());
+        // Flattened, without the table item to read the types from
+        assert!(TokenPropertiesEnricher
+            .enrich(&[row(serde_json::json!({ "seed": "0x02beef" }), None)])
+            .is_empty());
+        // Still raw, decoded from the row itself
+        let updates = TokenPropertiesEnricher.enrich(&[row(raw, None)]);
+        assert_eq!(updates[0].default_properties, decoded);
+    }
+}
@@ -1,2 +1,5 @@
 pub mod processors;
 pub mod driver;
+pub mod enrichment;
+#[cfg(test)]
+pub(crate) mod test_utils;
@@ -0,0 +1,436 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    custom::driver::{
+        backfill_guard::{self, OverwritePolicy},
+        change_feed, column_stats,
+        publish_retry::PublishError,
+        publisher::Publisher,
+        row_limits,
+    },
+    database::{
+        clean_data_for_db, get_chunks, write_transaction, CurrentRowUpsert, PgDbPool,
+        PgPoolConnection,
+    },
+    indexer::{
+        errors::TransactionProcessingError, processing_result::ProcessingResult,
+        transaction_processor::TransactionProcessor,
+    },
+    models::{
+        change_feed::Operation,
+        token_models::ans_lookup::{
+            AnsChange, CurrentAnsLookup, CurrentAnsLookupMap, CurrentAnsLookupQuery,
+        },
+    },
+    schema,
+    util::standardize_address,
+};
+use anyhow::Context;
+use aptos_api_types::Transaction as APITransaction;
+use async_trait::async_trait;
+use diesel::{pg::upsert::excluded, result::Error, ExpressionMethods, PgConnection};
+use field_count::FieldCount;
+use std::{collections::BTreeSet, fmt::Debug};
+
+pub const NAME: &str = "custom_ans_processor";
+/// Model of the published names, see `client::MODEL_TOPIC_KEYS`
+const ANS_LOOKUP_MODEL: &str = "CurrentAnsLookup";
+
+pub struct CAnsTransactionProcessor {
+    connection_pool: PgDbPool,
+    /// Standardized, None if not configured
+    ans_contract_address: Option<String>,
+    publisher: Publisher,
+}
+
+impl CAnsTransactionProcessor {
+    pub fn new(
+        connection_pool: PgDbPool,
+        ans_contract_address: Option<String>,
+        publisher: Publisher,
+    ) -> Self {
+        let ans_contract_address =
+            ans_contract_address.map(|address| standardize_address(&address));
+        aptos_logger::info!(
+            ans_contract_address = ans_contract_address,
+            "init AnsTransactionProcessor"
+        );
+        if ans_contract_address.is_none() {
+            aptos_logger::warn!("No ANS contract address is configured, no names are indexed");
+        }
+        Self {
+            connection_pool,
+            ans_contract_address,
+            publisher,
+        }
+    }
+
+    /// The names changed by a committed batch, keyed by `<domain>:<subdomain>` without salting,
+    /// for a compacted topic
+    fn publish(&self, ans_lookups: &[CurrentAnsLookup]) -> Result<(), PublishError> {
+        if !ans_lookups.is_empty() && self.publisher.publishes(ANS_LOOKUP_MODEL) {
+            self.publisher
+                .try_send_keyed(ANS_LOOKUP_MODEL, ans_lookups, |ans_lookup| {
+                    format!("{}:{}", ans_lookup.domain, ans_lookup.subdomain)
+                })?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for CAnsTransactionProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = &self.connection_pool.state();
+        write!(
+            f,
+            "AnsTransactionProcessor {{ connections: {:?}  idle_connections: {:?}  \
+             max_size: {:?}  connection_timeout: {:?} }}",
+            state.connections,
+            state.idle_connections,
+            self.connection_pool.max_size(),
+            self.connection_pool.connection_timeout()
+        )
+    }
+}
+
+fn insert_to_db_impl(
+    conn: &mut PgConnection,
+    policy: OverwritePolicy,
+    ans_lookups: &[CurrentAnsLookup],
+) -> Result<(), diesel::result::Error> {
+    // Shadowed so the change feed records the rows that are inserted
+    let ans_lookups = row_limits::enforce(conn, ans_lookups)?;
+    let ans_lookups = ans_lookups.as_ref();
+    insert_current_ans_lookups(conn, policy, ans_lookups)?;
+
+    change_feed::record(
+        conn,
+        "current_ans_lookup",
+        &["domain", "subdomain"],
+        Operation::Upsert,
+        ans_lookups,
+    )?;
+    Ok(())
+}
+
+fn insert_to_db(
+    conn: &mut PgPoolConnection,
+    name: &'static str,
+    start_version: u64,
+    end_version: u64,
+    policy: OverwritePolicy,
+    ans_lookups: Vec<CurrentAnsLookup>,
+) -> Result<(), diesel::result::Error> {
+    aptos_logger::trace!(
+        name = name,
+        start_version = start_version,
+        end_version = end_version,
+        "Inserting to db",
+    );
+    column_stats::observe("current_ans_lookup", &ans_lookups);
+    match write_transaction::<_, Error, _>(conn, |pg_conn| {
+        insert_to_db_impl(pg_conn, policy, &ans_lookups)
+    }) {
+        Ok(_) => Ok(()),
+        Err(_) => write_transaction::<_, Error, _>(conn, |pg_conn| {
+            let ans_lookups = clean_data_for_db(ans_lookups, true);
+
+            insert_to_db_impl(pg_conn, policy, &ans_lookups)
+        }),
+    }
+}
+
+fn insert_current_ans_lookups(
+    conn: &mut PgConnection,
+    policy: OverwritePolicy,
+    item_to_insert: &[CurrentAnsLookup],
+) -> Result<(), diesel::result::Error> {
+    use schema::current_ans_lookup::dsl::*;
+
+    let chunks = get_chunks(item_to_insert.len(), CurrentAnsLookup::field_count());
+    for (start_ind, end_ind) in chunks {
+        CurrentRowUpsert::new("current_ans_lookup", policy).execute(
+            conn,
+            diesel::insert_into(schema::current_ans_lookup::table)
+                .values(&item_to_insert[start_ind..end_ind])
+                .on_conflict((domain, subdomain))
+                .do_update()
+                .set((
+                    registered_address.eq(excluded(registered_address)),
+                    expiration_timestamp.eq(excluded(expiration_timestamp)),
+                    last_transaction_version.eq(excluded(last_transaction_version)),
+                    inserted_at.eq(excluded(inserted_at)),
+                    token_name.eq(excluded(token_name)),
+                    is_primary.eq(excluded(is_primary)),
+                    record_expiration_timestamp.eq(excluded(record_expiration_timestamp)),
+                )),
+            &item_to_insert[start_ind..end_ind],
+        )?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl TransactionProcessor for CAnsTransactionProcessor {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn process_transactions(
+        &self,
+        transactions: Vec<APITransaction>,
+        start_version: u64,
+        end_version: u64,
+    ) -> Result<ProcessingResult, TransactionProcessingError> {
+        let ans_contract_address = match &self.ans_contract_address {
+            Some(ans_contract_address) => ans_contract_address,
+            None => {
+                return Ok(ProcessingResult::new(
+                    self.name(),
+                    start_version,
+                    end_version,
+                ))
+            },
+        };
+        let mut conn = self.get_conn().map_err(|err| {
+            TransactionProcessingError::ConnectionPoolError((
+                err,
+                start_version,
+                end_version,
+                self.name(),
+            ))
+        })?;
+
+        let mut all_changes = vec![];
+        for txn in &transactions {
+            let mut changes = AnsChange::from_transaction(txn, ans_contract_address).unwrap();
+            all_changes.append(&mut changes);
+        }
+
+        // The names of every changed domain as they are before the batch, so that its subdomains
+        // written in earlier batches follow its expiration
+        let domains = all_changes
+            .iter()
+            .map(|change| change.name().0.clone())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let mut current = CurrentAnsLookupMap::new();
+        if !domains.is_empty() {
+            let rows = CurrentAnsLookupQuery::get_by_domains(&mut conn, &domains)
+                .context("Failed to look up the current names of the batch's domains")
+                .map_err(|err| {
+                    TransactionProcessingError::TransactionCommitError((
+                        err,
+                        start_version,
+                        end_version,
+                        self.name(),
+                    ))
+                })?;
+            current.extend(rows.into_iter().map(|row| {
+                (
+                    (row.domain.clone(), row.subdomain.clone()),
+                    CurrentAnsLookup::from(row),
+                )
+            }));
+        }
+        // Sorted by PK in order to avoid postgres deadlock since we're doing multi threaded db writes
+        let all_ans_lookups = CurrentAnsLookup::apply_changes(current, &all_changes);
+
+        let tx_result = insert_to_db(
+            &mut conn,
+            self.name(),
+            start_version,
+            end_version,
+            backfill_guard::policy(self.name(), start_version, end_version),
+            all_ans_lookups.clone(),
+        );
+        // Published once the rows are written, a failure has the batch processed again
+        tx_result
+            .map_err(anyhow::Error::from)
+            .and_then(|_| self.publish(&all_ans_lookups).map_err(anyhow::Error::from))
+            .map(|_| ProcessingResult::new(self.name(), start_version, end_version))
+            .map_err(|err| {
+                TransactionProcessingError::TransactionCommitError((
+                    err,
+                    start_version,
+                    end_version,
+                    self.name(),
+                ))
+            })
+    }
+
+    fn connection_pool(&self) -> Option<&PgDbPool> {
+        Some(&self.connection_pool)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::decode_model,
+        custom::{
+            driver::{
+                config::{AnsConfig, DriverConfig},
+                replay::Replay,
+            },
+            test_utils,
+        },
+    };
+    use diesel::{QueryDsl, RunQueryDsl};
+    use serde_json::json;
+
+    const CONTRACT: &str = "0xa25";
+    const ALICE: &str = "0x00000000000000000000000000000000000000000000000000000000000000a1";
+    const BOB: &str = "0x00000000000000000000000000000000000000000000000000000000000000b2";
+    const VERSION: i64 = 5_300_000_001;
+    /// Expiration of `alice.apt`, and of `pay.alice.apt`'s record, a year later
+    const ALICE_EXPIRATION: i64 = 1_731_000_000;
+    const PAY_EXPIRATION: i64 = ALICE_EXPIRATION + 31_536_000;
+
+    /// (subdomain, target, is_primary, expiration, last version) of every published name
+    fn published(replay: &Replay) -> Vec<(String, Option<String>, bool, i64, i64)> {
+        replay
+            .messages
+            .on_topic("ans_lookup_topic")
+            .iter()
+            .map(|message| {
+                let row = decode_model::<CurrentAnsLookup>(&message.payload).unwrap();
+                assert_eq!(row.domain, "alice");
+                assert_eq!(
+                    message.key.as_deref(),
+                    Some(format!("alice:{}", row.subdomain).as_str())
+                );
+                (
+                    row.subdomain,
+                    row.registered_address,
+                    row.is_primary,
+                    row.expiration_timestamp.timestamp(),
+                    row.last_transaction_version,
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_register_set_primary_transfer() {
+        let Some(conn_pool) = test_utils::test_pool() else {
+            return;
+        };
+        let driver_config = test_utils::driver_config(json!({}));
+        let driver_config = DriverConfig {
+            ans: AnsConfig {
+                contract_address: Some(CONTRACT.to_string()),
+            },
+            ..driver_config
+        };
+        let alice = || Some(ALICE.to_string());
+        let bob = || Some(BOB.to_string());
+        // In one batch, and a batch per transaction
+        for batch_size in [usize::MAX, 1] {
+            diesel::delete(
+                schema::current_ans_lookup::table
+                    .filter(schema::current_ans_lookup::domain.eq("alice")),
+            )
+            .execute(&mut conn_pool.get().unwrap())
+            .unwrap();
+            let (subdomain, transactions): (Vec<_>, Vec<_>) = test_utils::fixture("ans_flow.json")
+                .into_iter()
+                .partition(|txn| txn.version() == Some(VERSION as u64 + 1));
+
+            // The subdomain first, before its domain was indexed
+            let replay = test_utils::run_processor_with_config(
+                NAME,
+                conn_pool.clone(),
+                &driver_config,
+                subdomain,
+                batch_size,
+            )
+            .await;
+            assert_eq!(published(&replay), [(
+                "pay".to_string(),
+                alice(),
+                false,
+                PAY_EXPIRATION,
+                VERSION + 1
+            )]);
+
+            // Register, set primary, the token transfer, which changes no name, and the new
+            // owner pointing the name to itself, clearing the previous owner's primary name
+            let replay = test_utils::run_processor_with_config(
+                NAME,
+                conn_pool.clone(),
+                &driver_config,
+                transactions,
+                batch_size,
+            )
+            .await;
+            let alice_history = published(&replay)
+                .into_iter()
+                .filter(|(subdomain, ..)| subdomain.is_empty())
+                .map(|(_, target, is_primary, _, version)| (target, is_primary, version))
+                .collect::<Vec<_>>();
+            // The gap left by the subdomain splits the batch after the registration
+            if batch_size == 1 {
+                assert_eq!(alice_history, [
+                    (alice(), false, VERSION),
+                    (alice(), true, VERSION + 2),
+                    (bob(), false, VERSION + 4),
+                ]);
+            } else {
+                assert_eq!(alice_history, [
+                    (alice(), false, VERSION),
+                    (bob(), false, VERSION + 4)
+                ]);
+            }
+            // The subdomain follows its domain's expiration once the domain is indexed
+            let pay = published(&replay)
+                .into_iter()
+                .filter(|(subdomain, ..)| subdomain == "pay")
+                .collect::<Vec<_>>();
+            assert_eq!(pay, [(
+                "pay".to_string(),
+                alice(),
+                false,
+                ALICE_EXPIRATION,
+                VERSION + 1
+            )]);
+
+            let mut stored = schema::current_ans_lookup::table
+                .filter(schema::current_ans_lookup::domain.eq("alice"))
+                .load::<CurrentAnsLookupQuery>(&mut conn_pool.get().unwrap())
+                .unwrap();
+            stored.sort_by(|a, b| a.subdomain.cmp(&b.subdomain));
+            let stored = stored
+                .into_iter()
+                .map(|row| {
+                    (
+                        row.token_name,
+                        row.registered_address,
+                        row.is_primary,
+                        row.expiration_timestamp.timestamp(),
+                        row.record_expiration_timestamp.timestamp(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(stored, [
+                (
+                    "alice.apt".to_string(),
+                    bob(),
+                    false,
+                    ALICE_EXPIRATION,
+                    ALICE_EXPIRATION
+                ),
+                (
+                    "pay.alice.apt".to_string(),
+                    alice(),
+                    false,
+                    ALICE_EXPIRATION,
+                    PAY_EXPIRATION
+                ),
+            ]);
+        }
+    }
+}
@@ -3,7 +3,8 @@
 
 use crate::{
     database::{
-        clean_data_for_db, execute_with_better_error, get_chunks, PgDbPool, PgPoolConnection,
+        clean_data_for_db, execute_with_better_error, get_chunks, read_cache, write_transaction,
+        CurrentRowUpsert, GuardedUpsert, PgDbPool, PgPoolConnection,
     },
     indexer::{
         errors::TransactionProcessingError, processing_result::ProcessingResult,
@@ -21,59 +22,266 @@ use crate::{
 use aptos_api_types::Transaction as APITransaction;
 use aptos_types::APTOS_COIN_TYPE;
 use async_trait::async_trait;
-use diesel::{pg::upsert::excluded, result::Error, ExpressionMethods, PgConnection};
+use bigdecimal::Signed;
+use diesel::{pg::upsert::excluded, ExpressionMethods, PgConnection};
 use field_count::FieldCount;
+use serde::Serialize;
+use serde_json::json;
 use std::{collections::HashMap, fmt::Debug};
-use crate::custom::driver::publisher::Publisher;
+use crate::custom::driver::{
+    asset_transfers::AssetTransfers,
+    backfill_guard::{self, OverwritePolicy},
+    column_stats,
+    config::SinkMode,
+    ordering::Ordered,
+    publish_retry::PublishError,
+    publisher::Publisher,
+    shadow::{ShadowOutput, ShadowRunner, ShadowTable},
+    validation::{Policy, Rule, Validator, Violation},
+};
 
 pub const NAME: &str = "custom_coin_processor";
 pub struct CCoinTransactionProcessor {
     connection_pool: PgDbPool,
     publisher: Publisher,
+    validator: Validator<CoinOutput>,
+    shadow: ShadowRunner<CoinOutput>,
+    asset_transfers: AssetTransfers,
+    /// Whether the coin tables are written to Postgres, published, or both, see `SinkConfig`
+    sink_mode: SinkMode,
 }
 
 impl CCoinTransactionProcessor {
-    pub fn new(connection_pool: PgDbPool, publisher: Publisher) -> Self {
+    pub fn new(
+        connection_pool: PgDbPool,
+        publisher: Publisher,
+        validator: Validator<CoinOutput>,
+        shadow: ShadowRunner<CoinOutput>,
+        asset_transfers: AssetTransfers,
+        sink_mode: SinkMode,
+    ) -> Self {
         Self {
             connection_pool,
-            publisher
+            publisher,
+            validator,
+            shadow,
+            asset_transfers,
+            sink_mode,
         }
     }
 }
 
+/// What a batch is about to commit, as checked by the validation rules
+pub struct CoinOutput {
+    pub coin_activities: Vec<CoinActivity>,
+    pub coin_infos: Vec<CoinInfo>,
+    pub coin_balances: Vec<CoinBalance>,
+    pub current_coin_balances: Vec<CurrentCoinBalance>,
+    pub coin_supply: Vec<CoinSupply>,
+    pub account_transactions: Vec<AccountTransaction>,
+}
+
+impl ShadowOutput for CoinOutput {
+    fn tables(&self) -> Vec<ShadowTable> {
+        vec![
+            ShadowTable::new(
+                "coin_activities",
+                &[
+                    "transaction_version",
+                    "event_account_address",
+                    "event_creation_number",
+                    "event_sequence_number",
+                ],
+                &self.coin_activities,
+            ),
+            ShadowTable::new("coin_infos", &["coin_type_hash"], &self.coin_infos),
+            ShadowTable::new(
+                "coin_balances",
+                &["transaction_version", "owner_address", "coin_type_hash"],
+                &self.coin_balances,
+            ),
+            ShadowTable::new(
+                "current_coin_balances",
+                &["owner_address", "coin_type_hash"],
+                &self.current_coin_balances,
+            ),
+            ShadowTable::new(
+                "coin_supply",
+                &["transaction_version", "coin_type_hash"],
+                &self.coin_supply,
+            ),
+            ShadowTable::new(
+                "account_transactions",
+                &["account_address", "transaction_version"],
+                &self.account_transactions,
+            ),
+        ]
+    }
+}
+
+pub fn default_rules() -> Vec<Rule<CoinOutput>> {
+    vec![
+        Rule::new(
+            "coin_activity_amount_non_negative",
+            Policy::Fail,
+            |output: &CoinOutput| {
+                output
+                    .coin_activities
+                    .iter()
+                    .filter(|activity| activity.amount.is_negative())
+                    .map(|activity| {
+                        Violation::new(
+                            activity.transaction_version,
+                            format!("Negative {} amount", activity.activity_type),
+                            json!(activity),
+                        )
+                    })
+                    .collect()
+            },
+        ),
+        Rule::new(
+            "coin_balance_non_negative",
+            Policy::Warn,
+            |output: &CoinOutput| {
+                output
+                    .current_coin_balances
+                    .iter()
+                    .filter(|balance| balance.amount.is_negative())
+                    .map(|balance| {
+                        Violation::new(
+                            balance.last_transaction_version,
+                            format!("Negative balance of {}", balance.owner_address),
+                            json!(balance),
+                        )
+                    })
+                    .collect()
+            },
+        ),
+    ]
+}
+
+/// Parses a batch into what it's about to commit
+pub fn transform(conn: &mut PgConnection, transactions: &[APITransaction]) -> CoinOutput {
+    // get aptos_coin info for supply tracking, cached across batches
+    let maybe_aptos_coin_info =
+        &read_cache::coin_info(conn, &APTOS_COIN_TYPE.to_string()).unwrap();
+    transform_with(transactions, maybe_aptos_coin_info)
+}
+
+/// `transform` with the aptos_coin info already read
+fn transform_with(
+    transactions: &[APITransaction],
+    maybe_aptos_coin_info: &Option<CoinInfoQuery>,
+) -> CoinOutput {
+    let mut all_coin_activities = vec![];
+    let mut all_coin_balances = vec![];
+    let mut all_coin_infos: HashMap<String, CoinInfo> = HashMap::new();
+    let mut all_current_coin_balances: HashMap<CurrentCoinBalancePK, CurrentCoinBalance> =
+        HashMap::new();
+    let mut all_coin_supply = vec![];
+
+    let mut account_transactions = HashMap::new();
+
+    for txn in transactions {
+        let (
+            mut coin_activities,
+            mut coin_balances,
+            coin_infos,
+            current_coin_balances,
+            mut coin_supply,
+        ) = CoinActivity::from_transaction(txn, maybe_aptos_coin_info);
+        all_coin_activities.append(&mut coin_activities);
+        all_coin_balances.append(&mut coin_balances);
+        all_coin_supply.append(&mut coin_supply);
+        // For coin infos, we only want to keep the first version, so insert only if key is not present already
+        for (key, value) in coin_infos {
+            all_coin_infos.entry(key).or_insert(value);
+        }
+        all_current_coin_balances.extend(current_coin_balances);
+
+        account_transactions.extend(AccountTransaction::from_transaction(txn).unwrap());
+    }
+    let mut all_coin_infos = all_coin_infos.into_values().collect::<Vec<CoinInfo>>();
+    let mut all_current_coin_balances = all_current_coin_balances
+        .into_values()
+        .collect::<Vec<CurrentCoinBalance>>();
+    let mut account_transactions = account_transactions
+        .into_values()
+        .collect::<Vec<AccountTransaction>>();
+
+    // Sort by PK
+    all_coin_infos.sort_by(|a, b| a.coin_type.cmp(&b.coin_type));
+    all_current_coin_balances.sort_by(|a, b| {
+        (&a.owner_address, &a.coin_type).cmp(&(&b.owner_address, &b.coin_type))
+    });
+    account_transactions.sort_by(|a, b| {
+        (&a.transaction_version, &a.account_address)
+            .cmp(&(&b.transaction_version, &b.account_address))
+    });
+
+    CoinOutput {
+        coin_activities: all_coin_activities,
+        coin_infos: all_coin_infos,
+        coin_balances: all_coin_balances,
+        current_coin_balances: all_current_coin_balances,
+        coin_supply: all_coin_supply,
+        account_transactions,
+    }
+}
+
 impl Debug for CCoinTransactionProcessor {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let state = &self.connection_pool.state();
         write!(
             f,
-            "CoinTransactionProcessor {{ connections: {:?}  idle_connections: {:?} }}",
-            state.connections, state.idle_connections
+            "CoinTransactionProcessor {{ connections: {:?}  idle_connections: {:?}  \
+             max_size: {:?}  connection_timeout: {:?} }}",
+            state.connections,
+            state.idle_connections,
+            self.connection_pool.max_size(),
+            self.connection_pool.connection_timeout()
         )
     }
 }
 
 fn insert_to_db_impl(
     publisher: &Publisher,
+    sink_mode: SinkMode,
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     coin_activities: &[CoinActivity],
     coin_infos: &[CoinInfo],
     coin_balances: &[CoinBalance],
     current_coin_balances: &[CurrentCoinBalance],
     coin_supply: &[CoinSupply],
     account_transactions: &[AccountTransaction],
-) -> Result<(), diesel::result::Error> {
-    // insert_coin_activities(publisher, coin_activities)?;
-    // insert_coin_infos(publisher, coin_infos)?;
-    // insert_coin_balances(publisher, coin_balances)?;
-    // insert_current_coin_balances(publisher, current_coin_balances)?;
-    // insert_coin_supply(publisher, coin_supply)?;
-    // insert_account_transactions(conn, account_transactions)?;
+) -> anyhow::Result<()> {
+    // Always kept in Postgres: `transform` reads the aptos_coin info back for supply tracking
+    insert_coin_infos(conn, policy, coin_infos)?;
+    if sink_mode.writes_db() {
+        insert_coin_activities(conn, coin_activities)?;
+        insert_coin_balances(conn, coin_balances)?;
+        insert_current_coin_balances(conn, policy, current_coin_balances)?;
+        insert_coin_supply(conn, coin_supply)?;
+        insert_account_transactions(conn, account_transactions)?;
+    }
+    // Each model goes to its topic if one is configured
+    if sink_mode.publishes() {
+        publish_if_configured(publisher, "CoinActivity", coin_activities)?;
+        publish_if_configured(publisher, "CoinInfo", coin_infos)?;
+        publish_if_configured(publisher, "CoinBalance", coin_balances)?;
+        publish_if_configured(publisher, "CurrentCoinBalance", current_coin_balances)?;
+        publish_if_configured(publisher, "CoinSupply", coin_supply)?;
+        publish_if_configured(publisher, "AccountTransaction", account_transactions)?;
+    }
     Ok(())
 }
 
 fn insert_to_db(
     publisher: &Publisher,
+    sink_mode: SinkMode,
     conn: &mut PgPoolConnection,
+    policy: OverwritePolicy,
     name: &'static str,
     start_version: u64,
     end_version: u64,
@@ -83,20 +291,49 @@ fn insert_to_db(
     current_coin_balances: Vec<CurrentCoinBalance>,
     coin_supply: Vec<CoinSupply>,
     account_transactions: Vec<AccountTransaction>,
-) -> Result<(), diesel::result::Error> {
+) -> anyhow::Result<()> {
     aptos_logger::trace!(
         name = name,
         start_version = start_version,
         end_version = end_version,
         "Inserting to db",
     );
-    match conn
-        .build_transaction()
-        .read_write()
-        .run::<_, Error, _>(|pg_conn| {
+    column_stats::observe("coin_activities", &coin_activities);
+    column_stats::observe("coin_infos", &coin_infos);
+    column_stats::observe("coin_balances", &coin_balances);
+    column_stats::observe("current_coin_balances", &current_coin_balances);
+    column_stats::observe("coin_supply", &coin_supply);
+    column_stats::observe("account_transactions", &account_transactions);
+    match write_transaction::<_, anyhow::Error, _>(conn, |pg_conn| {
+        insert_to_db_impl(
+            publisher,
+            sink_mode,
+            pg_conn,
+            policy,
+            &coin_activities,
+            &coin_infos,
+            &coin_balances,
+            &current_coin_balances,
+            &coin_supply,
+            &account_transactions,
+        )
+    }) {
+        Ok(_) => Ok(()),
+        // Cleaning the rows doesn't help a message that couldn't be published
+        Err(err) if err.is::<PublishError>() => Err(err),
+        Err(_) => write_transaction::<_, anyhow::Error, _>(conn, |pg_conn| {
+            let coin_activities = clean_data_for_db(coin_activities, true);
+            let coin_infos = clean_data_for_db(coin_infos, true);
+            let coin_balances = clean_data_for_db(coin_balances, true);
+            let current_coin_balances = clean_data_for_db(current_coin_balances, true);
+            let coin_supply = clean_data_for_db(coin_supply, true);
+            let account_transactions = clean_data_for_db(account_transactions, true);
+
             insert_to_db_impl(
                 publisher,
+                sink_mode,
                 pg_conn,
+                policy,
                 &coin_activities,
                 &coin_infos,
                 &coin_balances,
@@ -104,70 +341,169 @@ fn insert_to_db(
                 &coin_supply,
                 &account_transactions,
             )
-        }) {
-        Ok(_) => Ok(()),
-        Err(_) => conn
-            .build_transaction()
-            .read_write()
-            .run::<_, Error, _>(|pg_conn| {
-                let coin_activities = clean_data_for_db(coin_activities, true);
-                let coin_infos = clean_data_for_db(coin_infos, true);
-                let coin_balances = clean_data_for_db(coin_balances, true);
-                let current_coin_balances = clean_data_for_db(current_coin_balances, true);
-                let coin_supply = clean_data_for_db(coin_supply, true);
-                let account_transactions = clean_data_for_db(account_transactions, true);
-
-                insert_to_db_impl(
-                    publisher,
-                    pg_conn,
-                    &coin_activities,
-                    &coin_infos,
-                    &coin_balances,
-                    &current_coin_balances,
-                    &coin_supply,
-                    &account_transactions,
-                )
-            }),
+        }),
     }
 }
 
-fn insert_coin_activities(
+/// Sends `items` to the topic of `model`, if one is configured, failing the batch if they can't
+/// be published
+fn publish_if_configured<T: Serialize + Sync + Ordered>(
     publisher: &Publisher,
+    model: &str,
+    items: &[T],
+) -> Result<(), PublishError> {
+    if publisher.publishes(model) {
+        publisher.try_send(model, items)?;
+    }
+    Ok(())
+}
+
+fn insert_coin_activities(
+    conn: &mut PgConnection,
     item_to_insert: &[CoinActivity],
 ) -> Result<(), diesel::result::Error> {
-    publisher.send("CoinActivity", item_to_insert);
+    use schema::coin_activities::dsl::*;
+
+    let chunks = get_chunks(item_to_insert.len(), CoinActivity::field_count());
+    for (start_ind, end_ind) in chunks {
+        execute_with_better_error(
+            conn,
+            diesel::insert_into(schema::coin_activities::table)
+                .values(&item_to_insert[start_ind..end_ind])
+                .on_conflict((
+                    transaction_version,
+                    event_account_address,
+                    event_creation_number,
+                    event_sequence_number,
+                ))
+                .do_nothing(),
+            None,
+        )?;
+    }
     Ok(())
 }
 
 fn insert_coin_infos(
-    publisher: &Publisher,
+    conn: &mut PgConnection,
+    policy: OverwritePolicy,
     item_to_insert: &[CoinInfo],
 ) -> Result<(), diesel::result::Error> {
-    publisher.send("CoinInfo", item_to_insert);
+    use schema::coin_infos::dsl::*;
+
+    let chunks = get_chunks(item_to_insert.len(), CoinInfo::field_count());
+    for (start_ind, end_ind) in chunks {
+        GuardedUpsert::new("coin_infos", policy)
+            .skip_unless(
+                " WHERE coin_infos.transaction_version_created > EXCLUDED.transaction_version_created ",
+            )
+            .execute(
+                conn,
+                diesel::insert_into(schema::coin_infos::table)
+                    .values(&item_to_insert[start_ind..end_ind])
+                    .on_conflict(coin_type_hash)
+                    .do_update()
+                    .set((
+                        transaction_version_created.eq(excluded(transaction_version_created)),
+                        creator_address.eq(excluded(creator_address)),
+                        name.eq(excluded(name)),
+                        symbol.eq(excluded(symbol)),
+                        decimals.eq(excluded(decimals)),
+                        transaction_created_timestamp.eq(excluded(transaction_created_timestamp)),
+                        supply_aggregator_table_handle.eq(excluded(supply_aggregator_table_handle)),
+                        supply_aggregator_table_key.eq(excluded(supply_aggregator_table_key)),
+                        inserted_at.eq(excluded(inserted_at)),
+                    )),
+                Some(" WHERE coin_infos.transaction_version_created >= EXCLUDED.transaction_version_created "),
+                &item_to_insert[start_ind..end_ind],
+            )?;
+    }
     Ok(())
 }
 
 fn insert_coin_balances(
-    publisher: &Publisher,
+    conn: &mut PgConnection,
     item_to_insert: &[CoinBalance],
 ) -> Result<(), diesel::result::Error> {
-    publisher.send("CoinBalance", item_to_insert);
+    use schema::coin_balances::dsl::*;
+
+    let chunks = get_chunks(item_to_insert.len(), CoinBalance::field_count());
+    for (start_ind, end_ind) in chunks {
+        execute_with_better_error(
+            conn,
+            diesel::insert_into(schema::coin_balances::table)
+                .values(&item_to_insert[start_ind..end_ind])
+                .on_conflict((transaction_version, owner_address, coin_type_hash))
+                .do_nothing(),
+            None,
+        )?;
+    }
     Ok(())
 }
 
 fn insert_current_coin_balances(
-    publisher: &Publisher,
+    conn: &mut PgConnection,
+    policy: OverwritePolicy,
     item_to_insert: &[CurrentCoinBalance],
 ) -> Result<(), diesel::result::Error> {
-    publisher.send("CurrentCoinBalance", item_to_insert);
+    use schema::current_coin_balances::dsl::*;
+
+    let chunks = get_chunks(item_to_insert.len(), CurrentCoinBalance::field_count());
+    for (start_ind, end_ind) in chunks {
+        CurrentRowUpsert::new("current_coin_balances", policy).execute(
+            conn,
+            diesel::insert_into(schema::current_coin_balances::table)
+                .values(&item_to_insert[start_ind..end_ind])
+                .on_conflict((owner_address, coin_type_hash))
+                .do_update()
+                .set((
+                    amount.eq(excluded(amount)),
+                    last_transaction_version.eq(excluded(last_transaction_version)),
+                    last_transaction_timestamp.eq(excluded(last_transaction_timestamp)),
+                    inserted_at.eq(excluded(inserted_at)),
+                )),
+            &item_to_insert[start_ind..end_ind],
+        )?;
+    }
     Ok(())
 }
 
 fn insert_coin_supply(
-    publisher: &Publisher,
+    conn: &mut PgConnection,
     item_to_insert: &[CoinSupply],
 ) -> Result<(), diesel::result::Error> {
-    publisher.send("CoinSupply", item_to_insert);
+    use schema::coin_supply::dsl::*;
+
+    let chunks = get_chunks(item_to_insert.len(), CoinSupply::field_count());
+    for (start_ind, end_ind) in chunks {
+        execute_with_better_error(
+            conn,
+            diesel::insert_into(schema::coin_supply::table)
+                .values(&item_to_insert[start_ind..end_ind])
+                .on_conflict((transaction_version, coin_type_hash))
+                .do_nothing(),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_account_transactions(
+    conn: &mut PgConnection,
+    item_to_insert: &[AccountTransaction],
+) -> Result<(), diesel::result::Error> {
+    use schema::account_transactions::dsl::*;
+
+    let chunks = get_chunks(item_to_insert.len(), AccountTransaction::field_count());
+    for (start_ind, end_ind) in chunks {
+        execute_with_better_error(
+            conn,
+            diesel::insert_into(schema::account_transactions::table)
+                .values(&item_to_insert[start_ind..end_ind])
+                .on_conflict((transaction_version, account_address))
+                .do_nothing(),
+            None,
+        )?;
+    }
     Ok(())
 }
 
@@ -183,79 +519,60 @@ impl TransactionProcessor for CCoinTransactionProcessor {
         start_version: u64,
         end_version: u64,
     ) -> Result<ProcessingResult, TransactionProcessingError> {
-        let mut conn = self.get_conn();
-        // get aptos_coin info for supply tracking
-        // TODO: This only needs to be fetched once. Need to persist somehow
-        let maybe_aptos_coin_info =
-            &CoinInfoQuery::get_by_coin_type(APTOS_COIN_TYPE.to_string(), &mut conn).unwrap();
-
-        let mut all_coin_activities = vec![];
-        let mut all_coin_balances = vec![];
-        let mut all_coin_infos: HashMap<String, CoinInfo> = HashMap::new();
-        let mut all_current_coin_balances: HashMap<CurrentCoinBalancePK, CurrentCoinBalance> =
-            HashMap::new();
-        let mut all_coin_supply = vec![];
-
-        let mut account_transactions = HashMap::new();
-
-        for txn in &transactions {
-            let (
-                mut coin_activities,
-                mut coin_balances,
-                coin_infos,
-                current_coin_balances,
-                mut coin_supply,
-            ) = CoinActivity::from_transaction(txn, maybe_aptos_coin_info);
-            all_coin_activities.append(&mut coin_activities);
-            all_coin_balances.append(&mut coin_balances);
-            all_coin_supply.append(&mut coin_supply);
-            // For coin infos, we only want to keep the first version, so insert only if key is not present already
-            for (key, value) in coin_infos {
-                all_coin_infos.entry(key).or_insert(value);
-            }
-            all_current_coin_balances.extend(current_coin_balances);
-
-            account_transactions.extend(AccountTransaction::from_transaction(txn).unwrap());
-        }
-        let mut all_coin_infos = all_coin_infos.into_values().collect::<Vec<CoinInfo>>();
-        let mut all_current_coin_balances = all_current_coin_balances
-            .into_values()
-            .collect::<Vec<CurrentCoinBalance>>();
-        let mut account_transactions = account_transactions
-            .into_values()
-            .collect::<Vec<AccountTransaction>>();
-
-        // Sort by PK
-        all_coin_infos.sort_by(|a, b| a.coin_type.cmp(&b.coin_type));
-        all_current_coin_balances.sort_by(|a, b| {
-            (&a.owner_address, &a.coin_type).cmp(&(&b.owner_address, &b.coin_type))
-        });
-        account_transactions.sort_by(|a, b| {
-            (&a.transaction_version, &a.account_address)
-                .cmp(&(&b.transaction_version, &b.account_address))
-        });
+        let mut conn = self.get_conn().map_err(|err| {
+            TransactionProcessingError::ConnectionPoolError((
+                err,
+                start_version,
+                end_version,
+                self.name(),
+            ))
+        })?;
+        let output = transform(&mut conn, &transactions);
+        self.shadow.run(&mut conn, &transactions, &output, start_version, end_version);
+        self.validator
+            .validate(Some(&mut conn), &output, start_version, end_version)
+            .map_err(|err| {
+                TransactionProcessingError::TransactionCommitError((
+                    err,
+                    start_version,
+                    end_version,
+                    self.name(),
+                ))
+            })?;
+        let asset_transfers = self
+            .asset_transfers
+            .derive(&transactions, &output.coin_activities);
 
         let tx_result = insert_to_db(
             &self.publisher,
+            self.sink_mode,
             &mut conn,
+            backfill_guard::policy(self.name(), start_version, end_version),
             self.name(),
             start_version,
             end_version,
-            all_coin_activities,
-            all_coin_infos,
-            all_coin_balances,
-            all_current_coin_balances,
-            all_coin_supply,
-            account_transactions,
+            output.coin_activities,
+            output.coin_infos,
+            output.coin_balances,
+            output.current_coin_balances,
+            output.coin_supply,
+            output.account_transactions,
         );
         match tx_result {
-            Ok(_) => Ok(ProcessingResult::new(
-                self.name(),
-                start_version,
-                end_version,
-            )),
+            Ok(_) => self
+                .asset_transfers
+                .record(&mut conn, &self.publisher, &asset_transfers)
+                .map(|_| ProcessingResult::new(self.name(), start_version, end_version))
+                .map_err(|err| {
+                    TransactionProcessingError::TransactionCommitError((
+                        err,
+                        start_version,
+                        end_version,
+                        self.name(),
+                    ))
+                }),
             Err(err) => Err(TransactionProcessingError::TransactionCommitError((
-                anyhow::Error::from(err),
+                err,
                 start_version,
                 end_version,
                 self.name(),
@@ -263,7 +580,276 @@ impl TransactionProcessor for CCoinTransactionProcessor {
         }
     }
 
-    fn connection_pool(&self) -> &PgDbPool {
-        &self.connection_pool
+    fn connection_pool(&self) -> Option<&PgDbPool> {
+        Some(&self.connection_pool)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client::decode_model, custom::test_utils};
+    use bigdecimal::BigDecimal;
+    use diesel::{QueryDsl, RunQueryDsl};
+    use serde_json::Value;
+
+    const APT: &str = "0x1::aptos_coin::AptosCoin";
+    const GEM: &str = "0xcafe::gem::Gem";
+    const ALICE: &str = "0x3f9e0589ca0668a5273b86bfcb5f357164408a889bc733b309cf1901098c8ce5";
+    const BOB: &str = "0x9e9a2e6b1b3c4f0a7d8c1e2f3a4b5c6d7e8f9a0b1c2d3e4f5a6b7c8d9e0f1a2b";
+    const U128_MAX: &str = "340282366920938463463374607431768211455";
+
+    /// Hand-built in the shape the node's API serves, not captured, see `tests/fixtures/README.md`
+    fn user_transaction(
+        version: u64,
+        sender: &str,
+        gas_used: &str,
+        events: Vec<Value>,
+        changes: Vec<Value>,
+    ) -> APITransaction {
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": version.to_string(),
+            "block_height": (version / 2).to_string(),
+            "epoch": "2",
+            "hash": format!("0x{:064x}", version),
+            "state_change_hash": format!("0x{:064x}", 0),
+            "event_root_hash": format!("0x{:064x}", 0),
+            "state_checkpoint_hash": null,
+            "gas_used": gas_used,
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": format!("0x{:064x}", 0),
+            "sender": sender,
+            "sequence_number": "7",
+            "max_gas_amount": "200000",
+            "gas_unit_price": "100",
+            "expiration_timestamp_secs": "1692900608",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0x1::aptos_account::transfer",
+                "type_arguments": [],
+                "arguments": [BOB, "1000"]
+            },
+            "signature": {
+                "type": "ed25519_signature",
+                "public_key": "0x14ff6646855dad4a2dab30db773cdd4b22d6f9e6813f3e50142adf4f3efcf9f8",
+                "signature": "0x70781112e78cc8b54b86805c016cef2478bccdef21b721542af0323276ab906c989172adffed5bf2f475f2ec3a5b284a0ac46a6aef0d79f0dbb6b85bfca0080a"
+            },
+            "events": events,
+            "timestamp": "1692900578554839",
+            "changes": changes
+        }))
+        .unwrap()
+    }
+
+    fn coin_store(owner: &str, coin: &str, value: &str, deposit_events: u64) -> Value {
+        let handle = |creation_num: u64| {
+            json!({
+                "counter": "1",
+                "guid": { "id": { "addr": owner, "creation_num": creation_num.to_string() } }
+            })
+        };
+        json!({
+            "type": "write_resource",
+            "address": owner,
+            "state_key_hash": format!("0x{:064x}", deposit_events),
+            "data": {
+                "type": format!("0x1::coin::CoinStore<{}>", coin),
+                "data": {
+                    "coin": { "value": value },
+                    "deposit_events": handle(deposit_events),
+                    "withdraw_events": handle(deposit_events + 1),
+                    "frozen": false
+                }
+            }
+        })
+    }
+
+    fn coin_event(owner: &str, kind: &str, creation_number: u64, amount: &str) -> Value {
+        json!({
+            "guid": { "account_address": owner, "creation_number": creation_number.to_string() },
+            "sequence_number": "0",
+            "type": format!("0x1::coin::{}", kind),
+            "data": { "amount": amount }
+        })
+    }
+
+    fn activities(output: &CoinOutput) -> Vec<(i64, &str, &str, &str, String)> {
+        output
+            .coin_activities
+            .iter()
+            .map(|activity| {
+                (
+                    activity.transaction_version,
+                    activity.activity_type.as_str(),
+                    activity.owner_address.as_str(),
+                    activity.coin_type.as_str(),
+                    activity.amount.to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_transfer() {
+        let transaction = user_transaction(
+            100,
+            ALICE,
+            "6",
+            vec![
+                coin_event(ALICE, "WithdrawEvent", 3, "1000"),
+                coin_event(BOB, "DepositEvent", 2, "1000"),
+            ],
+            vec![
+                coin_store(ALICE, APT, "99998400", 2),
+                coin_store(BOB, APT, "1000", 2),
+            ],
+        );
+        let output = transform_with(&[transaction], &None);
+        assert_eq!(activities(&output), vec![
+            (
+                100,
+                "0x1::aptos_coin::GasFeeEvent",
+                ALICE,
+                APT,
+                "600".to_string()
+            ),
+            (
+                100,
+                "0x1::coin::WithdrawEvent",
+                ALICE,
+                APT,
+                "1000".to_string()
+            ),
+            (100, "0x1::coin::DepositEvent", BOB, APT, "1000".to_string()),
+        ]);
+        let balances = output
+            .current_coin_balances
+            .iter()
+            .map(|balance| (balance.owner_address.as_str(), balance.amount.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(balances, vec![
+            (ALICE, "99998400".to_string()),
+            (BOB, "1000".to_string()),
+        ]);
+        assert_eq!(output.coin_balances.len(), 2);
+
+        // Gas that overflows a u64 once priced
+        let transaction = user_transaction(101, ALICE, &u64::MAX.to_string(), vec![], vec![]);
+        let output = transform_with(&[transaction], &None);
+        assert_eq!(
+            output.coin_activities[0].amount,
+            BigDecimal::from(u64::MAX) * BigDecimal::from(100)
+        );
+    }
+
+    #[test]
+    fn test_coin_store_created_and_deleted() {
+        // Bob registers a coin store and receives more than an i64 holds, then withdraws it all
+        // and the store is destroyed, within the batch
+        let registered = user_transaction(
+            200,
+            BOB,
+            "5",
+            vec![coin_event(BOB, "DepositEvent", 4, U128_MAX)],
+            vec![coin_store(BOB, GEM, U128_MAX, 4)],
+        );
+        let deleted = user_transaction(
+            201,
+            BOB,
+            "5",
+            vec![coin_event(BOB, "WithdrawEvent", 5, U128_MAX)],
+            vec![json!({
+                "type": "delete_resource",
+                "address": BOB,
+                "state_key_hash": format!("0x{:064x}", 4),
+                "resource": format!("0x1::coin::CoinStore<{}>", GEM)
+            })],
+        );
+        let output = transform_with(&[registered, deleted], &None);
+
+        let gem_activities = activities(&output)
+            .into_iter()
+            .filter(|activity| activity.3 == GEM)
+            .collect::<Vec<_>>();
+        assert_eq!(gem_activities, vec![
+            (
+                200,
+                "0x1::coin::DepositEvent",
+                BOB,
+                GEM,
+                U128_MAX.to_string()
+            ),
+            (
+                201,
+                "0x1::coin::WithdrawEvent",
+                BOB,
+                GEM,
+                U128_MAX.to_string()
+            ),
+        ]);
+        let balances = output
+            .coin_balances
+            .iter()
+            .map(|balance| (balance.transaction_version, balance.amount.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(balances, vec![
+            (200, U128_MAX.to_string()),
+            (201, "0".to_string())
+        ]);
+        // Deduped on the primary key, the deletion wins
+        assert_eq!(output.current_coin_balances.len(), 1);
+        let current = &output.current_coin_balances[0];
+        assert_eq!(current.amount.to_string(), "0");
+        assert_eq!(current.last_transaction_version, 201);
+        assert_eq!(current.coin_type, GEM);
+    }
+
+    #[tokio::test]
+    async fn test_publishes_configured_topics() {
+        let Some(conn_pool) = test_utils::test_pool() else {
+            return;
+        };
+        let transaction = user_transaction(
+            100,
+            ALICE,
+            "6",
+            vec![
+                coin_event(ALICE, "WithdrawEvent", 3, "1000"),
+                coin_event(BOB, "DepositEvent", 2, "1000"),
+            ],
+            vec![
+                coin_store(ALICE, APT, "99998400", 2),
+                coin_store(BOB, APT, "1000", 2),
+            ],
+        );
+        let replay = test_utils::run_processor(NAME, conn_pool.clone(), vec![transaction], 1).await;
+
+        let mut activities = replay
+            .messages
+            .on_topic("coin_activity_topic")
+            .iter()
+            .map(|message| decode_model::<CoinActivity>(&message.payload).unwrap())
+            .map(|activity| (activity.activity_type, activity.amount.to_string()))
+            .collect::<Vec<_>>();
+        activities.sort();
+        assert_eq!(activities, [
+            ("0x1::aptos_coin::GasFeeEvent".to_string(), "600".to_string()),
+            ("0x1::coin::WithdrawEvent".to_string(), "1000".to_string()),
+            ("0x1::coin::DepositEvent".to_string(), "1000".to_string()),
+        ]);
+        assert_eq!(replay.messages.on_topic("coin_balance_topic").len(), 2);
+        assert_eq!(replay.messages.on_topic("current_coin_balance_topic").len(), 2);
+        assert_eq!(replay.messages.on_topic("account_transaction_topic").len(), 2);
+
+        // Publish only by default, so the published tables aren't written
+        let mut conn = conn_pool.get().unwrap();
+        let written = schema::coin_activities::table
+            .filter(schema::coin_activities::transaction_version.eq(100))
+            .count()
+            .get_result::<i64>(&mut conn)
+            .unwrap();
+        assert_eq!(written, 0);
     }
 }
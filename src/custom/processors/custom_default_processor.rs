@@ -2,19 +2,25 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
+    counters::{BATCH_DURATION_SECONDS, PUBLISH_DEDUPE_SKIPPED},
     database::{
-        clean_data_for_db, execute_with_better_error, get_chunks, PgDbPool, PgPoolConnection,
+        clean_data_for_db, execute_with_context, get_chunks, read_cache, write_transaction,
+        ChunkContext, CurrentRowUpsert, GuardedUpsert, InsertError, PgDbPool, PgPoolConnection,
     },
     indexer::{
-        errors::TransactionProcessingError, processing_result::ProcessingResult,
+        errors::TransactionProcessingError,
+        processing_result::{ProcessingResult, RowCounts},
         transaction_processor::TransactionProcessor,
     },
     models::{
         block_metadata_transactions::BlockMetadataTransactionModel,
+        change_feed::Operation,
+        coin_models::account_transactions::AccountTransaction,
         events::EventModel,
         move_modules::MoveModule,
-        move_resources::MoveResource,
+        move_resources::{CurrentMoveResource, MoveResource},
         move_tables::{CurrentTableItem, TableItem, TableMetadata},
+        scripts::{Script, SeenScripts, DEFAULT_SEEN_SCRIPTS_CAPACITY},
         signatures::Signature,
         transactions::{TransactionDetail, TransactionModel},
         user_transactions::UserTransactionModel,
@@ -22,76 +28,500 @@ use crate::{
         write_set_changes::{WriteSetChangeDetail, WriteSetChangeModel},
     },
     schema,
+    util::sort_key::sort_by_pk,
 };
 use aptos_api_types::{Transaction, WriteSetChange};
 use async_trait::async_trait;
-use diesel::{pg::upsert::excluded, result::Error, ExpressionMethods, PgConnection};
+use diesel::{pg::upsert::excluded, ExpressionMethods, PgConnection};
 use field_count::FieldCount;
-use std::{collections::HashMap, fmt::Debug};
-use crate::custom::driver::publisher::Publisher;
+use serde_json::json;
+use std::{collections::HashMap, fmt::Debug, sync::Mutex, time::Instant};
+use crate::custom::driver::{
+    backfill_guard::{self, OverwritePolicy},
+    change_feed, column_stats,
+    config::SinkMode,
+    duplicate_transactions::DuplicateDetector,
+    entry_function_stats::EntryFunctionStats,
+    ordered_commit,
+    publish_filter::PublishFilter,
+    row_limits,
+    sink::Sink,
+    storage_usage::StorageUsage,
+    validation::{Policy, Rule, Validator, Violation},
+};
 
 pub const NAME: &str = "custom_default_processor";
+/// Model of the published current resources, see `client::MODEL_TOPIC_KEYS`
+const CURRENT_MOVE_RESOURCE_MODEL: &str = "CurrentMoveResource";
+/// Model of the published account index rows, see `client::MODEL_TOPIC_KEYS`
+const ACCOUNT_TRANSACTION_MODEL: &str = "AccountTransaction";
+
 pub struct CDefaultTransactionProcessor {
-    connection_pool: PgDbPool,
-    publisher: Publisher,
+    /// `None` to publish without Postgres: violations, duplicates, stats and storage usage aren't
+    /// recorded, and the sink mode must be `publish_only`
+    connection_pool: Option<PgDbPool>,
+    /// Kafka, or with `sink.backend` set to `http` an `HttpSink`, see `driver::sink`
+    sink: Box<dyn Sink>,
+    validator: Validator<DefaultOutput>,
+    duplicates: DuplicateDetector,
+    entry_function_stats: EntryFunctionStats,
+    storage_usage: StorageUsage,
+    sink_mode: SinkMode,
+    publish_filter: PublishFilter,
+    /// Scripts already in the `scripts` table, so hot scripts aren't sent every batch
+    seen_scripts: Mutex<SeenScripts>,
 }
 
 impl CDefaultTransactionProcessor {
-    pub fn new(connection_pool: PgDbPool, publisher: Publisher) -> Self {
+    /// Panics without a connection pool if `sink_mode` writes Postgres
+    pub fn new(
+        connection_pool: Option<PgDbPool>,
+        sink: Box<dyn Sink>,
+        validator: Validator<DefaultOutput>,
+        duplicates: DuplicateDetector,
+        entry_function_stats: EntryFunctionStats,
+        storage_usage: StorageUsage,
+        sink_mode: SinkMode,
+        publish_filter: PublishFilter,
+    ) -> Self {
+        assert!(
+            connection_pool.is_some() || !sink_mode.writes_db(),
+            "{} can't write Postgres without a database",
+            NAME
+        );
         Self {
             connection_pool,
-            publisher
+            sink,
+            validator,
+            duplicates,
+            entry_function_stats,
+            storage_usage,
+            sink_mode,
+            publish_filter,
+            seen_scripts: Mutex::new(SeenScripts::new(DEFAULT_SEEN_SCRIPTS_CAPACITY)),
         }
     }
 }
 
+/// The transactions of the batch, which are published as they are
+pub type DefaultOutput = Vec<Transaction>;
+
+pub fn default_rules() -> Vec<Rule<DefaultOutput>> {
+    vec![
+        Rule::new(
+            "versions_contiguous",
+            Policy::Fail,
+            |output: &DefaultOutput| {
+                output
+                    .windows(2)
+                    .filter_map(|pair| {
+                        let (previous, next) = (pair[0].version()?, pair[1].version()?);
+                        (next != previous + 1).then(|| {
+                            Violation::new(
+                                next as i64,
+                                format!("Version {} follows version {}", next, previous),
+                                json!({ "previous_version": previous, "version": next }),
+                            )
+                        })
+                    })
+                    .collect()
+            },
+        ),
+        Rule::new(
+            "user_transaction_has_signature",
+            Policy::Warn,
+            |output: &DefaultOutput| {
+                output
+                    .iter()
+                    .filter_map(|txn| match txn {
+                        Transaction::UserTransaction(user_txn)
+                            if user_txn.request.signature.is_none() =>
+                        {
+                            Some(Violation::new(
+                                user_txn.info.version.0 as i64,
+                                "User transaction without a signature",
+                                json!({
+                                    "sender": user_txn.request.sender.to_string(),
+                                    "sequence_number": user_txn.request.sequence_number.0,
+                                    "hash": user_txn.info.hash.to_string(),
+                                }),
+                            ))
+                        },
+                        _ => None,
+                    })
+                    .collect()
+            },
+        ),
+    ]
+}
+
 impl Debug for CDefaultTransactionProcessor {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let state = &self.connection_pool.state();
+        let Some(connection_pool) = &self.connection_pool else {
+            return write!(f, "DefaultTransactionProcessor {{ without a database }}");
+        };
+        let state = &connection_pool.state();
         write!(
             f,
-            "DefaultTransactionProcessor {{ connections: {:?}  idle_connections: {:?} }}",
-            state.connections, state.idle_connections
+            "DefaultTransactionProcessor {{ connections: {:?}  idle_connections: {:?}  \
+             max_size: {:?}  connection_timeout: {:?} }}",
+            state.connections,
+            state.idle_connections,
+            connection_pool.max_size(),
+            connection_pool.connection_timeout()
         )
     }
 }
 
+/// The rows of a batch, written to Postgres when the sink mode includes it
+pub struct DefaultRows {
+    pub txns: Vec<TransactionModel>,
+    pub user_transactions: Vec<UserTransactionModel>,
+    pub signatures: Vec<Signature>,
+    pub block_metadata_transactions: Vec<BlockMetadataTransactionModel>,
+    /// The distinct script payloads of the batch, see `SeenScripts` for those left out
+    pub scripts: Vec<Script>,
+    pub events: Vec<EventModel>,
+    pub wscs: Vec<WriteSetChangeModel>,
+    pub move_modules: Vec<MoveModule>,
+    pub move_resources: Vec<MoveResource>,
+    pub current_move_resources: Vec<CurrentMoveResource>,
+    pub table_items: Vec<TableItem>,
+    pub current_table_items: Vec<CurrentTableItem>,
+    pub table_metadata: Vec<TableMetadata>,
+    pub objects: Vec<Object>,
+    pub current_objects: Vec<CurrentObject>,
+    /// The accounts each transaction touched, see `AccountTransaction::from_transaction`
+    pub account_transactions: Vec<AccountTransaction>,
+}
+
+impl DefaultRows {
+    pub fn counts(&self) -> RowCounts {
+        RowCounts {
+            transactions: self.txns.len(),
+            user_transactions: self.user_transactions.len(),
+            events: self.events.len(),
+            write_set_changes: self.wscs.len(),
+            move_resources: self.move_resources.len(),
+            table_items: self.table_items.len(),
+        }
+    }
+}
+
+/// What publishing `transactions` produces when the batch isn't written to Postgres, counted from
+/// the API transactions. Only user, block metadata and genesis transactions have events and
+/// changes to count.
+fn published_counts(transactions: &[Transaction]) -> RowCounts {
+    let mut counts = RowCounts {
+        transactions: transactions.len(),
+        ..RowCounts::default()
+    };
+    for txn in transactions {
+        let (events, changes) = match txn {
+            Transaction::UserTransaction(user_txn) => {
+                counts.user_transactions += 1;
+                (user_txn.events.as_slice(), user_txn.info.changes.as_slice())
+            },
+            Transaction::BlockMetadataTransaction(bmt_txn) => {
+                (bmt_txn.events.as_slice(), bmt_txn.info.changes.as_slice())
+            },
+            Transaction::GenesisTransaction(genesis_txn) => (
+                genesis_txn.events.as_slice(),
+                genesis_txn.info.changes.as_slice(),
+            ),
+            _ => continue,
+        };
+        counts.events += events.len();
+        counts.write_set_changes += changes.len();
+        for change in changes {
+            match change {
+                WriteSetChange::WriteResource(_) | WriteSetChange::DeleteResource(_) => {
+                    counts.move_resources += 1
+                },
+                WriteSetChange::WriteTableItem(_) | WriteSetChange::DeleteTableItem(_) => {
+                    counts.table_items += 1
+                },
+                _ => {},
+            }
+        }
+    }
+    counts
+}
+
+/// Parses a batch into its rows. The owner of an object deleted in the batch is looked up in
+/// `current_objects` when the batch didn't write it first.
+pub fn transform_rows(conn: &mut PgPoolConnection, transactions: &[Transaction]) -> DefaultRows {
+    let (txns, txn_details, events, wscs, wsc_details) =
+        TransactionModel::from_transactions(transactions);
+
+    let mut signatures = vec![];
+    let mut user_transactions = vec![];
+    let mut block_metadata_transactions = vec![];
+    for detail in txn_details {
+        match detail {
+            TransactionDetail::User(user_txn, mut sigs) => {
+                signatures.append(&mut sigs);
+                user_transactions.push(user_txn);
+            },
+            TransactionDetail::BlockMetadata(bmt) => block_metadata_transactions.push(bmt),
+            TransactionDetail::Genesis => {},
+        }
+    }
+    let mut move_modules = vec![];
+    let mut move_resources = vec![];
+    let mut table_items = vec![];
+    let mut current_table_items = HashMap::new();
+    let mut table_metadata = HashMap::new();
+    for detail in wsc_details {
+        match detail {
+            WriteSetChangeDetail::Module(module) => move_modules.push(module),
+            WriteSetChangeDetail::Resource(resource) => move_resources.push(resource),
+            WriteSetChangeDetail::Table(item, current_item, metadata, is_deleted) => {
+                debug_assert_eq!(current_item.is_deleted, is_deleted);
+                table_items.push(item);
+                // The latest change of a key wins, so a key deleted and written again in the
+                // batch ends up live, and one written then deleted a tombstone
+                let key = (
+                    current_item.table_handle.clone(),
+                    current_item.key_hash.clone(),
+                );
+                let is_latest = current_table_items.get(&key).map_or(true, |latest| {
+                    latest.last_transaction_version <= current_item.last_transaction_version
+                });
+                if is_latest {
+                    current_table_items.insert(key, current_item);
+                }
+                if let Some(meta) = metadata {
+                    table_metadata.insert(meta.handle.clone(), meta);
+                }
+            },
+        }
+    }
+
+    let mut objects = vec![];
+    let mut current_objects = HashMap::new();
+    for txn in transactions {
+        let (changes, txn_version) = match txn {
+            Transaction::UserTransaction(user_txn) => {
+                (&user_txn.info.changes, user_txn.info.version.0 as i64)
+            },
+            Transaction::BlockMetadataTransaction(bmt_txn) => {
+                (&bmt_txn.info.changes, bmt_txn.info.version.0 as i64)
+            },
+            _ => continue,
+        };
+        for (index, wsc) in changes.iter().enumerate() {
+            let index = index as i64;
+            let object = match wsc {
+                WriteSetChange::WriteResource(inner) => {
+                    Object::from_write_resource(inner, txn_version, index).unwrap()
+                },
+                WriteSetChange::DeleteResource(inner) => Object::from_delete_resource(
+                    inner,
+                    txn_version,
+                    index,
+                    &current_objects,
+                    conn,
+                )
+                .unwrap(),
+                _ => None,
+            };
+            if let Some((object, current_object)) = object {
+                current_objects.insert(object.object_address.clone(), current_object);
+                objects.push(object);
+            }
+        }
+    }
+
+    let current_move_resources = MoveResource::current_resources(&move_resources);
+    let account_transactions = AccountTransaction::from_transactions(transactions).unwrap();
+    // Sorted by PK to avoid deadlocks between concurrent batches
+    let mut current_table_items = current_table_items
+        .into_values()
+        .collect::<Vec<CurrentTableItem>>();
+    let mut table_metadata = table_metadata.into_values().collect::<Vec<TableMetadata>>();
+    let mut current_objects = current_objects.into_values().collect::<Vec<CurrentObject>>();
+    sort_by_pk(&mut current_table_items);
+    table_metadata.sort_by(|a, b| a.handle.cmp(&b.handle));
+    current_objects.sort_by(|a, b| a.object_address.cmp(&b.object_address));
+
+    DefaultRows {
+        txns,
+        user_transactions,
+        signatures,
+        block_metadata_transactions,
+        scripts: Script::from_transactions(transactions),
+        events,
+        wscs,
+        move_modules,
+        move_resources,
+        current_move_resources,
+        table_items,
+        current_table_items,
+        table_metadata,
+        objects,
+        current_objects,
+        account_transactions,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 fn insert_to_db_impl(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     txns: &[TransactionModel],
     txn_details: (
         &[UserTransactionModel],
         &[Signature],
         &[BlockMetadataTransactionModel],
+        &[Script],
     ),
     events: &[EventModel],
     wscs: &[WriteSetChangeModel],
     wsc_details: (
         &[MoveModule],
         &[MoveResource],
+        &[CurrentMoveResource],
         &[TableItem],
         &[CurrentTableItem],
         &[TableMetadata],
     ),
     object_core: (&[Object], &[CurrentObject]),
-) -> Result<(), diesel::result::Error> {
-    let (user_transactions, signatures, block_metadata_transactions) = txn_details;
-    let (move_modules, move_resources, table_items, current_table_items, table_metadata) =
-        wsc_details;
+    account_transactions: &[AccountTransaction],
+) -> Result<(), InsertError> {
+    let (user_transactions, signatures, block_metadata_transactions, scripts) = txn_details;
+    let (
+        move_modules,
+        move_resources,
+        current_move_resources,
+        table_items,
+        current_table_items,
+        table_metadata,
+    ) = wsc_details;
     let (objects, current_objects) = object_core;
+    // Shadowed so the change feed records the rows that are inserted
+    let user_transactions = row_limits::enforce(conn, user_transactions)?;
+    let user_transactions = user_transactions.as_ref();
+    let events = row_limits::enforce(conn, events)?;
+    let events = events.as_ref();
+    let move_resources = row_limits::enforce(conn, move_resources)?;
+    let move_resources = move_resources.as_ref();
+    let current_move_resources = row_limits::enforce(conn, current_move_resources)?;
+    let current_move_resources = current_move_resources.as_ref();
+    let table_items = row_limits::enforce(conn, table_items)?;
+    let table_items = table_items.as_ref();
+    let current_table_items = row_limits::enforce(conn, current_table_items)?;
+    let current_table_items = current_table_items.as_ref();
     insert_transactions(conn, txns)?;
+    insert_scripts(conn, scripts)?;
     insert_user_transactions(conn, user_transactions)?;
     insert_signatures(conn, signatures)?;
     insert_block_metadata_transactions(conn, block_metadata_transactions)?;
-    insert_events(conn, events)?;
+    insert_events(conn, policy, events)?;
     insert_write_set_changes(conn, wscs)?;
     insert_move_modules(conn, move_modules)?;
-    insert_move_resources(conn, move_resources)?;
+    insert_move_resources(conn, policy, move_resources)?;
+    insert_current_move_resources(conn, policy, current_move_resources)?;
     insert_table_items(conn, table_items)?;
-    insert_current_table_items(conn, current_table_items)?;
+    insert_current_table_items(conn, policy, current_table_items)?;
     insert_table_metadata(conn, table_metadata)?;
     insert_objects(conn, objects)?;
-    insert_current_objects(conn, current_objects)?;
+    insert_current_objects(conn, policy, current_objects)?;
+    insert_account_transactions(conn, account_transactions)?;
+
+    change_feed::record(conn, "transactions", &["version"], Operation::Insert, txns)?;
+    change_feed::record(
+        conn,
+        "user_transactions",
+        &["version"],
+        Operation::Insert,
+        user_transactions,
+    )?;
+    change_feed::record(
+        conn,
+        "signatures",
+        &[
+            "transaction_version",
+            "multi_agent_index",
+            "multi_sig_index",
+            "is_sender_primary",
+        ],
+        Operation::Insert,
+        signatures,
+    )?;
+    change_feed::record(
+        conn,
+        "block_metadata_transactions",
+        &["version"],
+        Operation::Insert,
+        block_metadata_transactions,
+    )?;
+    change_feed::record(
+        conn,
+        "events",
+        &["account_address", "creation_number", "sequence_number"],
+        Operation::Upsert,
+        events,
+    )?;
+    change_feed::record(
+        conn,
+        "write_set_changes",
+        &["transaction_version", "index"],
+        Operation::Insert,
+        wscs,
+    )?;
+    let by_wsc = &["transaction_version", "write_set_change_index"];
+    change_feed::record(
+        conn,
+        "move_modules",
+        by_wsc,
+        Operation::Insert,
+        move_modules,
+    )?;
+    change_feed::record(
+        conn,
+        "move_resources",
+        by_wsc,
+        Operation::Upsert,
+        move_resources,
+    )?;
+    change_feed::record(
+        conn,
+        "current_move_resources",
+        &["address", "type"],
+        Operation::Upsert,
+        current_move_resources,
+    )?;
+    change_feed::record(conn, "table_items", by_wsc, Operation::Insert, table_items)?;
+    change_feed::record(
+        conn,
+        "current_table_items",
+        &["table_handle", "key_hash"],
+        Operation::Upsert,
+        current_table_items,
+    )?;
+    change_feed::record(
+        conn,
+        "table_metadatas",
+        &["handle"],
+        Operation::Insert,
+        table_metadata,
+    )?;
+    change_feed::record(conn, "objects", by_wsc, Operation::Insert, objects)?;
+    change_feed::record(
+        conn,
+        "current_objects",
+        &["object_address"],
+        Operation::Upsert,
+        current_objects,
+    )?;
+    change_feed::record(
+        conn,
+        "account_transactions",
+        &["account_address", "transaction_version"],
+        Operation::Insert,
+        account_transactions,
+    )?;
     Ok(())
 }
 
@@ -100,96 +530,128 @@ fn insert_to_db(
     name: &'static str,
     start_version: u64,
     end_version: u64,
-    txns: Vec<TransactionModel>,
-    txn_details: (
-        Vec<UserTransactionModel>,
-        Vec<Signature>,
-        Vec<BlockMetadataTransactionModel>,
-    ),
-    events: Vec<EventModel>,
-    wscs: Vec<WriteSetChangeModel>,
-    wsc_details: (
-        Vec<MoveModule>,
-        Vec<MoveResource>,
-        Vec<TableItem>,
-        Vec<CurrentTableItem>,
-        Vec<TableMetadata>,
-    ),
-    object_core: (Vec<Object>, Vec<CurrentObject>),
-) -> Result<(), diesel::result::Error> {
+    policy: OverwritePolicy,
+    rows: DefaultRows,
+) -> Result<(), InsertError> {
     aptos_logger::trace!(
         name = name,
         start_version = start_version,
         end_version = end_version,
         "Inserting to db",
     );
-    let (user_transactions, signatures, block_metadata_transactions) = txn_details;
-    let (move_modules, move_resources, table_items, current_table_items, table_metadata) =
-        wsc_details;
-    let (objects, current_objects) = object_core;
-    match conn
-        .build_transaction()
-        .read_write()
-        .run::<_, Error, _>(|pg_conn| {
-            insert_to_db_impl(
-                pg_conn,
-                &txns,
-                (
-                    &user_transactions,
-                    &signatures,
-                    &block_metadata_transactions,
-                ),
-                &events,
-                &wscs,
-                (
-                    &move_modules,
-                    &move_resources,
-                    &table_items,
-                    &current_table_items,
-                    &table_metadata,
-                ),
-                (&objects, &current_objects),
-            )
-        }) {
+    let DefaultRows {
+        txns,
+        user_transactions,
+        signatures,
+        block_metadata_transactions,
+        scripts,
+        events,
+        wscs,
+        move_modules,
+        move_resources,
+        current_move_resources,
+        table_items,
+        current_table_items,
+        table_metadata,
+        objects,
+        current_objects,
+        account_transactions,
+    } = rows;
+    column_stats::observe("transactions", &txns);
+    column_stats::observe("user_transactions", &user_transactions);
+    column_stats::observe("signatures", &signatures);
+    column_stats::observe("block_metadata_transactions", &block_metadata_transactions);
+    column_stats::observe("scripts", &scripts);
+    column_stats::observe("events", &events);
+    column_stats::observe("write_set_changes", &wscs);
+    column_stats::observe("move_modules", &move_modules);
+    column_stats::observe("move_resources", &move_resources);
+    column_stats::observe("current_move_resources", &current_move_resources);
+    column_stats::observe("table_items", &table_items);
+    column_stats::observe("current_table_items", &current_table_items);
+    column_stats::observe("table_metadatas", &table_metadata);
+    column_stats::observe("objects", &objects);
+    column_stats::observe("current_objects", &current_objects);
+    column_stats::observe("account_transactions", &account_transactions);
+    match write_transaction::<_, InsertError, _>(conn, |pg_conn| {
+        insert_to_db_impl(
+            pg_conn,
+            policy,
+            &txns,
+            (
+                &user_transactions,
+                &signatures,
+                &block_metadata_transactions,
+                &scripts,
+            ),
+            &events,
+            &wscs,
+            (
+                &move_modules,
+                &move_resources,
+                &current_move_resources,
+                &table_items,
+                &current_table_items,
+                &table_metadata,
+            ),
+            (&objects, &current_objects),
+            &account_transactions,
+        )
+    }) {
         Ok(_) => Ok(()),
-        Err(_) => {
+        Err(error) => {
+            aptos_logger::warn!(
+                name = name,
+                start_version = start_version,
+                end_version = end_version,
+                table = error.context.as_ref().map(|context| context.table),
+                failed_versions = ?error.context.as_ref().and_then(|context| context.versions),
+                constraint = error.constraint.as_deref(),
+                error = error.to_string(),
+                "Failed to insert the batch, retrying without null bytes"
+            );
             let txns = clean_data_for_db(txns, true);
             let user_transactions = clean_data_for_db(user_transactions, true);
             let signatures = clean_data_for_db(signatures, true);
             let block_metadata_transactions = clean_data_for_db(block_metadata_transactions, true);
+            let scripts = clean_data_for_db(scripts, true);
             let events = clean_data_for_db(events, true);
             let wscs = clean_data_for_db(wscs, true);
             let move_modules = clean_data_for_db(move_modules, true);
             let move_resources = clean_data_for_db(move_resources, true);
+            let current_move_resources = clean_data_for_db(current_move_resources, true);
             let table_items = clean_data_for_db(table_items, true);
             let current_table_items = clean_data_for_db(current_table_items, true);
             let table_metadata = clean_data_for_db(table_metadata, true);
             let objects = clean_data_for_db(objects, true);
             let current_objects = clean_data_for_db(current_objects, true);
+            let account_transactions = clean_data_for_db(account_transactions, true);
 
-            conn.build_transaction()
-                .read_write()
-                .run::<_, Error, _>(|pg_conn| {
-                    insert_to_db_impl(
-                        pg_conn,
-                        &txns,
-                        (
-                            &user_transactions,
-                            &signatures,
-                            &block_metadata_transactions,
-                        ),
-                        &events,
-                        &wscs,
-                        (
-                            &move_modules,
-                            &move_resources,
-                            &table_items,
-                            &current_table_items,
-                            &table_metadata,
-                        ),
-                        (&objects, &current_objects),
-                    )
-                })
+            write_transaction::<_, InsertError, _>(conn, |pg_conn| {
+                insert_to_db_impl(
+                    pg_conn,
+                    policy,
+                    &txns,
+                    (
+                        &user_transactions,
+                        &signatures,
+                        &block_metadata_transactions,
+                        &scripts,
+                    ),
+                    &events,
+                    &wscs,
+                    (
+                        &move_modules,
+                        &move_resources,
+                        &current_move_resources,
+                        &table_items,
+                        &current_table_items,
+                        &table_metadata,
+                    ),
+                    (&objects, &current_objects),
+                    &account_transactions,
+                )
+            })
         },
     }
 }
@@ -197,17 +659,45 @@ fn insert_to_db(
 fn insert_transactions(
     conn: &mut PgConnection,
     items_to_insert: &[TransactionModel],
-) -> Result<(), diesel::result::Error> {
+) -> Result<(), InsertError> {
     use schema::transactions::dsl::*;
     let chunks = get_chunks(items_to_insert.len(), TransactionModel::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        execute_with_context(
             conn,
             diesel::insert_into(schema::transactions::table)
                 .values(&items_to_insert[start_ind..end_ind])
                 .on_conflict(version)
                 .do_nothing(),
             None,
+            ChunkContext::new(
+                "transactions",
+                items_to_insert[start_ind..end_ind]
+                    .iter()
+                    .map(|row| row.version),
+            ),
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_scripts(conn: &mut PgConnection, items_to_insert: &[Script]) -> Result<(), InsertError> {
+    use schema::scripts::dsl::*;
+    let chunks = get_chunks(items_to_insert.len(), Script::field_count());
+    for (start_ind, end_ind) in chunks {
+        execute_with_context(
+            conn,
+            diesel::insert_into(schema::scripts::table)
+                .values(&items_to_insert[start_ind..end_ind])
+                .on_conflict(script_hash)
+                .do_nothing(),
+            None,
+            ChunkContext::new(
+                "scripts",
+                items_to_insert[start_ind..end_ind]
+                    .iter()
+                    .map(|row| row.first_seen_version),
+            ),
         )?;
     }
     Ok(())
@@ -216,17 +706,23 @@ fn insert_transactions(
 fn insert_user_transactions(
     conn: &mut PgConnection,
     items_to_insert: &[UserTransactionModel],
-) -> Result<(), diesel::result::Error> {
+) -> Result<(), InsertError> {
     use schema::user_transactions::dsl::*;
     let chunks = get_chunks(items_to_insert.len(), UserTransactionModel::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        execute_with_context(
             conn,
             diesel::insert_into(schema::user_transactions::table)
                 .values(&items_to_insert[start_ind..end_ind])
                 .on_conflict(version)
                 .do_nothing(),
             None,
+            ChunkContext::new(
+                "user_transactions",
+                items_to_insert[start_ind..end_ind]
+                    .iter()
+                    .map(|row| row.version),
+            ),
         )?;
     }
     Ok(())
@@ -235,11 +731,11 @@ fn insert_user_transactions(
 fn insert_signatures(
     conn: &mut PgConnection,
     items_to_insert: &[Signature],
-) -> Result<(), diesel::result::Error> {
+) -> Result<(), InsertError> {
     use schema::signatures::dsl::*;
     let chunks = get_chunks(items_to_insert.len(), Signature::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        execute_with_context(
             conn,
             diesel::insert_into(schema::signatures::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -251,6 +747,12 @@ fn insert_signatures(
                 ))
                 .do_nothing(),
             None,
+            ChunkContext::new(
+                "signatures",
+                items_to_insert[start_ind..end_ind]
+                    .iter()
+                    .map(|row| row.transaction_version),
+            ),
         )?;
     }
     Ok(())
@@ -259,20 +761,26 @@ fn insert_signatures(
 fn insert_block_metadata_transactions(
     conn: &mut PgConnection,
     items_to_insert: &[BlockMetadataTransactionModel],
-) -> Result<(), diesel::result::Error> {
+) -> Result<(), InsertError> {
     use schema::block_metadata_transactions::dsl::*;
     let chunks = get_chunks(
         items_to_insert.len(),
         BlockMetadataTransactionModel::field_count(),
     );
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        execute_with_context(
             conn,
             diesel::insert_into(schema::block_metadata_transactions::table)
                 .values(&items_to_insert[start_ind..end_ind])
                 .on_conflict(version)
                 .do_nothing(),
             None,
+            ChunkContext::new(
+                "block_metadata_transactions",
+                items_to_insert[start_ind..end_ind]
+                    .iter()
+                    .map(|row| row.version),
+            ),
         )?;
     }
     Ok(())
@@ -280,12 +788,13 @@ fn insert_block_metadata_transactions(
 
 fn insert_events(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[EventModel],
-) -> Result<(), diesel::result::Error> {
+) -> Result<(), InsertError> {
     use schema::events::dsl::*;
     let chunks = get_chunks(items_to_insert.len(), EventModel::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        GuardedUpsert::new("events", policy).execute_with_context(
             conn,
             diesel::insert_into(schema::events::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -294,8 +803,17 @@ fn insert_events(
                 .set((
                     inserted_at.eq(excluded(inserted_at)),
                     event_index.eq(excluded(event_index)),
+                    event_account_address.eq(excluded(event_account_address)),
+                    event_module.eq(excluded(event_module)),
+                    event_name.eq(excluded(event_name)),
+                    event_type_params.eq(excluded(event_type_params)),
+                    block_timestamp.eq(excluded(block_timestamp)),
                 )),
             None,
+            &items_to_insert[start_ind..end_ind],
+            items_to_insert[start_ind..end_ind]
+                .iter()
+                .map(|row| row.transaction_version),
         )?;
     }
     Ok(())
@@ -304,17 +822,23 @@ fn insert_events(
 fn insert_write_set_changes(
     conn: &mut PgConnection,
     items_to_insert: &[WriteSetChangeModel],
-) -> Result<(), diesel::result::Error> {
+) -> Result<(), InsertError> {
     use schema::write_set_changes::dsl::*;
     let chunks = get_chunks(items_to_insert.len(), WriteSetChangeModel::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        execute_with_context(
             conn,
             diesel::insert_into(schema::write_set_changes::table)
                 .values(&items_to_insert[start_ind..end_ind])
                 .on_conflict((transaction_version, index))
                 .do_nothing(),
             None,
+            ChunkContext::new(
+                "write_set_changes",
+                items_to_insert[start_ind..end_ind]
+                    .iter()
+                    .map(|row| row.transaction_version),
+            ),
         )?;
     }
     Ok(())
@@ -323,17 +847,23 @@ fn insert_write_set_changes(
 fn insert_move_modules(
     conn: &mut PgConnection,
     items_to_insert: &[MoveModule],
-) -> Result<(), diesel::result::Error> {
+) -> Result<(), InsertError> {
     use schema::move_modules::dsl::*;
     let chunks = get_chunks(items_to_insert.len(), MoveModule::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        execute_with_context(
             conn,
             diesel::insert_into(schema::move_modules::table)
                 .values(&items_to_insert[start_ind..end_ind])
                 .on_conflict((transaction_version, write_set_change_index))
                 .do_nothing(),
             None,
+            ChunkContext::new(
+                "move_modules",
+                items_to_insert[start_ind..end_ind]
+                    .iter()
+                    .map(|row| row.transaction_version),
+            ),
         )?;
     }
     Ok(())
@@ -341,12 +871,13 @@ fn insert_move_modules(
 
 fn insert_move_resources(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[MoveResource],
-) -> Result<(), diesel::result::Error> {
+) -> Result<(), InsertError> {
     use schema::move_resources::dsl::*;
     let chunks = get_chunks(items_to_insert.len(), MoveResource::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        GuardedUpsert::new("move_resources", policy).execute_with_context(
             conn,
             diesel::insert_into(schema::move_resources::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -357,6 +888,44 @@ fn insert_move_resources(
                     state_key_hash.eq(excluded(state_key_hash)),
                 )),
             None,
+            &items_to_insert[start_ind..end_ind],
+            items_to_insert[start_ind..end_ind]
+                .iter()
+                .map(|row| row.transaction_version),
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_current_move_resources(
+    conn: &mut PgConnection,
+    policy: OverwritePolicy,
+    items_to_insert: &[CurrentMoveResource],
+) -> Result<(), InsertError> {
+    use schema::current_move_resources::dsl::*;
+    let chunks = get_chunks(items_to_insert.len(), CurrentMoveResource::field_count());
+    for (start_ind, end_ind) in chunks {
+        CurrentRowUpsert::new("current_move_resources", policy).execute_with_context(
+            conn,
+            diesel::insert_into(schema::current_move_resources::table)
+                .values(&items_to_insert[start_ind..end_ind])
+                .on_conflict((address, type_))
+                .do_update()
+                .set((
+                    module.eq(excluded(module)),
+                    name.eq(excluded(name)),
+                    generic_type_params.eq(excluded(generic_type_params)),
+                    data.eq(excluded(data)),
+                    state_key_hash.eq(excluded(state_key_hash)),
+                    last_transaction_version.eq(excluded(last_transaction_version)),
+                    is_deleted.eq(excluded(is_deleted)),
+                    inserted_at.eq(excluded(inserted_at)),
+                    resource_address.eq(excluded(resource_address)),
+                )),
+            &items_to_insert[start_ind..end_ind],
+            items_to_insert[start_ind..end_ind]
+                .iter()
+                .map(|row| row.last_transaction_version),
         )?;
     }
     Ok(())
@@ -365,17 +934,23 @@ fn insert_move_resources(
 fn insert_table_items(
     conn: &mut PgConnection,
     items_to_insert: &[TableItem],
-) -> Result<(), diesel::result::Error> {
+) -> Result<(), InsertError> {
     use schema::table_items::dsl::*;
     let chunks = get_chunks(items_to_insert.len(), TableItem::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        execute_with_context(
             conn,
             diesel::insert_into(schema::table_items::table)
                 .values(&items_to_insert[start_ind..end_ind])
                 .on_conflict((transaction_version, write_set_change_index))
                 .do_nothing(),
             None,
+            ChunkContext::new(
+                "table_items",
+                items_to_insert[start_ind..end_ind]
+                    .iter()
+                    .map(|row| row.transaction_version),
+            ),
         )?;
     }
     Ok(())
@@ -383,12 +958,13 @@ fn insert_table_items(
 
 fn insert_current_table_items(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentTableItem],
-) -> Result<(), diesel::result::Error> {
+) -> Result<(), InsertError> {
     use schema::current_table_items::dsl::*;
     let chunks = get_chunks(items_to_insert.len(), CurrentTableItem::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_table_items", policy).execute_with_context(
             conn,
             diesel::insert_into(schema::current_table_items::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -402,7 +978,10 @@ fn insert_current_table_items(
                     last_transaction_version.eq(excluded(last_transaction_version)),
                     inserted_at.eq(excluded(inserted_at)),
                 )),
-                Some(" WHERE current_table_items.last_transaction_version <= excluded.last_transaction_version "),
+            &items_to_insert[start_ind..end_ind],
+            items_to_insert[start_ind..end_ind]
+                .iter()
+                .map(|row| row.last_transaction_version),
         )?;
     }
     Ok(())
@@ -411,36 +990,40 @@ fn insert_current_table_items(
 fn insert_table_metadata(
     conn: &mut PgConnection,
     items_to_insert: &[TableMetadata],
-) -> Result<(), diesel::result::Error> {
+) -> Result<(), InsertError> {
     use schema::table_metadatas::dsl::*;
     let chunks = get_chunks(items_to_insert.len(), TableMetadata::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        execute_with_context(
             conn,
             diesel::insert_into(schema::table_metadatas::table)
                 .values(&items_to_insert[start_ind..end_ind])
                 .on_conflict(handle)
                 .do_nothing(),
             None,
+            ChunkContext::new("table_metadatas", std::iter::empty()),
         )?;
     }
     Ok(())
 }
 
-fn insert_objects(
-    conn: &mut PgConnection,
-    items_to_insert: &[Object],
-) -> Result<(), diesel::result::Error> {
+fn insert_objects(conn: &mut PgConnection, items_to_insert: &[Object]) -> Result<(), InsertError> {
     use schema::objects::dsl::*;
     let chunks = get_chunks(items_to_insert.len(), Object::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        execute_with_context(
             conn,
             diesel::insert_into(schema::objects::table)
                 .values(&items_to_insert[start_ind..end_ind])
                 .on_conflict((transaction_version, write_set_change_index))
                 .do_nothing(),
             None,
+            ChunkContext::new(
+                "objects",
+                items_to_insert[start_ind..end_ind]
+                    .iter()
+                    .map(|row| row.transaction_version),
+            ),
         )?;
     }
     Ok(())
@@ -448,12 +1031,13 @@ fn insert_objects(
 
 fn insert_current_objects(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentObject],
-) -> Result<(), diesel::result::Error> {
+) -> Result<(), InsertError> {
     use schema::current_objects::dsl::*;
     let chunks = get_chunks(items_to_insert.len(), CurrentObject::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_objects", policy).execute_with_context(
             conn,
             diesel::insert_into(schema::current_objects::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -468,27 +1052,122 @@ fn insert_current_objects(
                     is_deleted.eq(excluded(is_deleted)),
                     inserted_at.eq(excluded(inserted_at)),
                 )),
-                Some(" WHERE current_objects.last_transaction_version <= excluded.last_transaction_version "),
+            &items_to_insert[start_ind..end_ind],
+            items_to_insert[start_ind..end_ind]
+                .iter()
+                .map(|row| row.last_transaction_version),
         )?;
     }
     Ok(())
 }
 
-fn custom_insert_to_db(
-    publisher: &Publisher,
+fn insert_account_transactions(
+    conn: &mut PgConnection,
+    items_to_insert: &[AccountTransaction],
+) -> Result<(), InsertError> {
+    use schema::account_transactions::dsl::*;
+    let chunks = get_chunks(items_to_insert.len(), AccountTransaction::field_count());
+    for (start_ind, end_ind) in chunks {
+        execute_with_context(
+            conn,
+            diesel::insert_into(schema::account_transactions::table)
+                .values(&items_to_insert[start_ind..end_ind])
+                .on_conflict((account_address, transaction_version))
+                .do_nothing(),
+            None,
+            ChunkContext::new(
+                "account_transactions",
+                items_to_insert[start_ind..end_ind]
+                    .iter()
+                    .map(|row| row.transaction_version),
+            ),
+        )?;
+    }
+    Ok(())
+}
+
+/// The models a batch publishes, parsed before the batch's commit turn
+struct PublishedBatch {
+    events: Vec<EventModel>,
+    wscs: Vec<WriteSetChangeModel>,
+    current_move_resources: Vec<CurrentMoveResource>,
+    account_transactions: Vec<AccountTransaction>,
+    txns: Vec<Transaction>,
+}
+
+impl PublishedBatch {
+    /// Parses only the models `sink` publishes
+    fn parse(sink: &dyn Sink, txns: Vec<Transaction>) -> anyhow::Result<Self> {
+        let publishes_entities = sink.publishes("EventModel") || sink.publishes("WriteSetChangeModel");
+        let publishes_resources = sink.publishes(CURRENT_MOVE_RESOURCE_MODEL);
+        let mut batch = PublishedBatch {
+            events: vec![],
+            wscs: vec![],
+            current_move_resources: vec![],
+            account_transactions: vec![],
+            txns: vec![],
+        };
+        if publishes_entities || publishes_resources {
+            let (_, _, events, wscs, wsc_details) = TransactionModel::from_transactions(&txns);
+            if publishes_entities {
+                batch.events = events;
+                batch.wscs = wscs;
+            }
+            if publishes_resources {
+                let resources = wsc_details
+                    .into_iter()
+                    .filter_map(|detail| match detail {
+                        WriteSetChangeDetail::Resource(resource) => Some(resource),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                batch.current_move_resources = MoveResource::current_resources(&resources);
+            }
+        }
+        if sink.publishes(ACCOUNT_TRANSACTION_MODEL) {
+            batch.account_transactions = AccountTransaction::from_transactions(&txns)?;
+        }
+        batch.txns = txns;
+        Ok(batch)
+    }
+}
+
+async fn custom_insert_to_db(
+    sink: &dyn Sink,
     name: &'static str,
     start_version: u64,
     end_version: u64,
-    txns: Vec<Transaction>,
-) -> Result<(), diesel::result::Error> {
+    batch: PublishedBatch,
+) -> anyhow::Result<usize> {
     aptos_logger::trace!(
         name = name,
         start_version = start_version,
         end_version = end_version,
         "Inserting to db",
     );
-    publisher.send_transaction("TransactionModel", &txns);
-    Ok(())
+    // Entities go out before their transactions, a batch failing on one of them publishes no
+    // transaction and is retried whole, unless what failed could be dead lettered. A
+    // `PublishError` tells a Kafka outage, retried, from a message that can't be serialized,
+    // which isn't, see `indexer::errors`.
+    let mut dead_lettered = 0;
+    if sink.publishes("EventModel") || sink.publishes("WriteSetChangeModel") {
+        dead_lettered += sink.send_events(&batch.events, &batch.wscs).await?;
+    }
+    // Only ever parsed for Kafka, see `driver::sink`
+    if let Some(publisher) = sink.publisher() {
+        if publisher.publishes(CURRENT_MOVE_RESOURCE_MODEL) {
+            publisher.try_send_keyed(
+                CURRENT_MOVE_RESOURCE_MODEL,
+                &batch.current_move_resources,
+                CurrentMoveResource::topic_key,
+            )?;
+        }
+        if publisher.publishes(ACCOUNT_TRANSACTION_MODEL) {
+            publisher.try_send(ACCOUNT_TRANSACTION_MODEL, &batch.account_transactions)?;
+        }
+    }
+    dead_lettered += sink.send_txs(&batch.txns).await?;
+    Ok(dead_lettered)
 }
 
 #[async_trait]
@@ -503,21 +1182,192 @@ impl TransactionProcessor for CDefaultTransactionProcessor {
         start_version: u64,
         end_version: u64,
     ) -> Result<ProcessingResult, TransactionProcessingError> {
-        let tx_result = custom_insert_to_db(
-            &self.publisher,
-            self.name(),
-            start_version,
-            end_version,
-            transactions,
-        );
-        match tx_result {
-            Ok(_) => Ok(ProcessingResult::new(
+        let mut conn = self
+            .connection_pool
+            .is_some()
+            .then(|| self.get_conn())
+            .transpose()
+            .map_err(|err| {
+                TransactionProcessingError::ConnectionPoolError((
+                    err,
+                    start_version,
+                    end_version,
+                    self.name(),
+                ))
+            })?;
+        self.validator
+            .validate(conn.as_mut(), &transactions, start_version, end_version)
+            .map_err(|err| {
+                TransactionProcessingError::TransactionCommitError((
+                    err,
+                    start_version,
+                    end_version,
+                    self.name(),
+                ))
+            })?;
+        // Nothing is looked up or recorded without a database
+        let mut closed_days = vec![];
+        let transactions = match conn.as_mut() {
+            Some(conn) => {
+                let transactions = self
+                    .duplicates
+                    .check(conn, transactions, start_version, end_version)
+                    .map_err(|err| {
+                        TransactionProcessingError::TransactionCommitError((
+                            err,
+                            start_version,
+                            end_version,
+                            self.name(),
+                        ))
+                    })?;
+                closed_days = self
+                    .entry_function_stats
+                    .record(conn, &transactions)
+                    .map_err(|err| {
+                        TransactionProcessingError::TransactionCommitError((
+                            err,
+                            start_version,
+                            end_version,
+                            self.name(),
+                        ))
+                    })?;
+                self.storage_usage
+                    .record(conn, &transactions)
+                    .map_err(|err| {
+                        TransactionProcessingError::TransactionCommitError((
+                            err,
+                            start_version,
+                            end_version,
+                            self.name(),
+                        ))
+                    })?;
+                transactions
+            },
+            None => transactions,
+        };
+        let mut counts = RowCounts::default();
+        // Parsed while the batches before it commit, see `driver::ordered_commit`
+        let rows = conn.as_mut().filter(|_| self.sink_mode.writes_db()).map(|conn| {
+            let mut rows = transform_rows(conn, &transactions);
+            rows.scripts = self
+                .seen_scripts
+                .lock()
+                .unwrap()
+                .filter_unseen(rows.scripts);
+            rows
+        });
+        let mut republish_skipped = 0;
+        let published = self.sink_mode.publishes().then(|| {
+            let transactions = self.publish_filter.retain(transactions);
+            // Their events and write set changes are parsed from them, so they go too
+            let (transactions, skipped) = match self.sink.publisher() {
+                Some(publisher) => publisher.unpublished(transactions)?,
+                None => (transactions, 0),
+            };
+            republish_skipped = skipped;
+            if !self.sink_mode.writes_db() {
+                counts = published_counts(&transactions);
+            }
+            PublishedBatch::parse(self.sink.as_ref(), transactions)
+        });
+        ordered_commit::turn().await.map_err(|err| {
+            TransactionProcessingError::TransactionCommitError((
+                err.into(),
+                start_version,
+                end_version,
+                self.name(),
+            ))
+        })?;
+        // Committed before anything is published, so Kafka is never ahead of the database
+        if let (Some(rows), Some(conn)) = (rows, conn.as_mut()) {
+            counts = rows.counts();
+            let written_table_items = rows
+                .current_table_items
+                .iter()
+                .map(|item| (item.table_handle.clone(), item.key_hash.clone()))
+                .collect::<Vec<_>>();
+            let written_resources = rows
+                .move_resources
+                .iter()
+                .map(|resource| (resource.address.clone(), resource.type_.clone()))
+                .collect::<Vec<_>>();
+            let script_hashes = rows
+                .scripts
+                .iter()
+                .map(|script| script.script_hash.clone())
+                .collect::<Vec<_>>();
+            let started = Instant::now();
+            let policy = backfill_guard::policy(self.name(), start_version, end_version);
+            insert_to_db(
+                conn,
                 self.name(),
                 start_version,
                 end_version,
-            )),
+                policy,
+                rows,
+            )
+            .map_err(|err| {
+                TransactionProcessingError::TransactionCommitError((
+                    anyhow::Error::from(err),
+                    start_version,
+                    end_version,
+                    self.name(),
+                ))
+            })?;
+            BATCH_DURATION_SECONDS
+                .with_label_values(&[self.name(), "insert_to_db"])
+                .observe(started.elapsed().as_secs_f64());
+            read_cache::CURRENT_TABLE_ITEMS.invalidate(written_table_items);
+            read_cache::CURRENT_RESOURCES.invalidate(written_resources);
+            self.seen_scripts.lock().unwrap().mark_seen(script_hashes);
+        }
+
+        let tx_result = match published {
+            Some(Ok(published)) => {
+                let started = Instant::now();
+                let result = custom_insert_to_db(
+                    self.sink.as_ref(),
+                    self.name(),
+                    start_version,
+                    end_version,
+                    published,
+                )
+                .await;
+                BATCH_DURATION_SECONDS
+                    .with_label_values(&[self.name(), "publish"])
+                    .observe(started.elapsed().as_secs_f64());
+                result
+            },
+            Some(Err(err)) => Err(err),
+            None => Ok(0),
+        };
+        // Rollups are a Kafka topic of their own, not published to other sinks
+        if let (Some(publisher), Some(conn)) = (self.sink.publisher(), conn.as_mut()) {
+            if let Err(err) = self
+                .entry_function_stats
+                .publish_rollups(conn, publisher, &closed_days)
+            {
+                aptos_logger::warn!(
+                    days = format!("{:?}", closed_days),
+                    error = ?err,
+                    "Failed to publish daily entry function stats"
+                );
+            }
+        }
+        if republish_skipped > 0 {
+            PUBLISH_DEDUPE_SKIPPED
+                .with_label_values(&[self.name()])
+                .inc_by(republish_skipped as u64);
+        }
+        match tx_result {
+            Ok(dead_lettered) => Ok(
+                ProcessingResult::new(self.name(), start_version, end_version)
+                    .with_counts(counts)
+                    .with_dead_lettered(dead_lettered)
+                    .with_republish_skipped(republish_skipped),
+            ),
             Err(err) => Err(TransactionProcessingError::TransactionCommitError((
-                anyhow::Error::from(err),
+                err,
                 start_version,
                 end_version,
                 self.name(),
@@ -525,7 +1375,684 @@ impl TransactionProcessor for CDefaultTransactionProcessor {
         }
     }
 
-    fn connection_pool(&self) -> &PgDbPool {
-        &self.connection_pool
+    fn connection_pool(&self) -> Option<&PgDbPool> {
+        self.connection_pool.as_ref()
+    }
+
+    fn takes_commit_turn(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        custom::{
+            driver::{
+                config::{
+                    DuplicateTransactionsConfig, EntryFunctionStatsConfig, HttpSinkConfig,
+                    PublishFilterConfig, PublishRetryConfig, SinkPolicy, StorageUsageConfig,
+                    ValidationConfig,
+                },
+                envelope::Envelope,
+                http_sink::HttpSink,
+                multi_sink::MultiSink,
+                publisher::Publisher,
+            },
+            test_utils,
+        },
+        database::new_db_pool,
+        indexer::tailer::MIGRATIONS,
+    };
+    use diesel::{QueryDsl, RunQueryDsl};
+    use diesel_migrations::MigrationHarness;
+    use rdkafka::types::RDKafkaErrorCode;
+    use serde_json::Value;
+
+    const VERSION: i64 = 4_100_000_001;
+
+    fn user_transaction(version: i64) -> Transaction {
+        let account = format!("0x{:064x}", 0xabcd);
+        user_transaction_with_changes(
+            version,
+            json!([{
+                "type": "write_resource",
+                "address": account,
+                "state_key_hash": format!("0x{:064x}", 1),
+                "data": {
+                    "type": "0x1::account::Account",
+                    "data": { "sequence_number": "1" }
+                }
+            }]),
+        )
+    }
+
+    fn user_transaction_with_changes(version: i64, changes: Value) -> Transaction {
+        let account = format!("0x{:064x}", 0xabcd);
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": version.to_string(),
+            "block_height": "100",
+            "epoch": "1",
+            "hash": format!("0x{:064x}", version),
+            "state_change_hash": format!("0x{:064x}", 0),
+            "event_root_hash": format!("0x{:064x}", 0),
+            "gas_used": "43",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": format!("0x{:064x}", 0),
+            "sender": account,
+            "sequence_number": "0",
+            "max_gas_amount": "1000",
+            "gas_unit_price": "1",
+            "expiration_timestamp_secs": "1649713172",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0x1::aptos_account::transfer",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "signature": {
+                "type": "ed25519_signature",
+                "public_key": "0x14ff6646855dad4a2dab30db773cdd4b22d6f9e6813f3e50142adf4f3efcf9f8",
+                "signature": "0x70781112e78cc8b54b86805c016cef2478bccdef21b721542af0323276ab906c989172adffed5bf2f475f2ec3a5b284a0ac46a6aef0d79f0dbb6b85bfca0080a"
+            },
+            "events": [{
+                "guid": { "account_address": account, "creation_number": "2" },
+                "sequence_number": version.to_string(),
+                "type": "0x1::coin::DepositEvent",
+                "data": { "amount": "1" }
+            }],
+            "timestamp": "1649713141723410",
+            "changes": changes
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sink_mode() {
+        let modes = [
+            ("publish_only", SinkMode::PublishOnly, false, true),
+            ("db_only", SinkMode::DbOnly, true, false),
+            ("both", SinkMode::Both, true, true),
+        ];
+        for (name, mode, writes_db, publishes) in modes {
+            assert_eq!(
+                serde_json::from_value::<SinkMode>(Value::from(name)).unwrap(),
+                mode
+            );
+            assert_eq!(mode.writes_db(), writes_db);
+            assert_eq!(mode.publishes(), publishes);
+        }
+        assert_eq!(SinkMode::default(), SinkMode::PublishOnly);
+    }
+
+    #[test]
+    fn test_published_counts() {
+        let counts = published_counts(&[user_transaction(VERSION), user_transaction(VERSION + 1)]);
+        assert_eq!(counts, RowCounts {
+            transactions: 2,
+            user_transactions: 2,
+            events: 2,
+            write_set_changes: 2,
+            move_resources: 2,
+            table_items: 0,
+        });
+    }
+
+    #[test]
+    fn test_insert_rows() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(&database_url).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        for table in [
+            "transactions",
+            "user_transactions",
+            "events",
+            "move_resources",
+            "account_transactions",
+        ] {
+            let version_column = match table {
+                "transactions" | "user_transactions" => "version",
+                _ => "transaction_version",
+            };
+            diesel::sql_query(format!(
+                "DELETE FROM {} WHERE {} = {}",
+                table, version_column, VERSION
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        }
+
+        let transactions = vec![user_transaction(VERSION)];
+        let rows = transform_rows(&mut conn, &transactions);
+        assert_eq!(rows.txns.len(), 1);
+        assert_eq!(rows.user_transactions.len(), 1);
+        assert_eq!(rows.events.len(), 1);
+        assert_eq!(rows.move_resources.len(), 1);
+        // Sent by, and only touching, its sender
+        assert_eq!(rows.account_transactions.len(), 1);
+        assert_eq!(rows.counts(), RowCounts {
+            transactions: 1,
+            user_transactions: 1,
+            events: 1,
+            write_set_changes: 1,
+            move_resources: 1,
+            table_items: 0,
+        });
+        insert_to_db(
+            &mut conn,
+            NAME,
+            VERSION as u64,
+            VERSION as u64,
+            OverwritePolicy::Overwrite,
+            rows,
+        )
+        .unwrap();
+        // A retried batch is skipped, not failed
+        let rows = transform_rows(&mut conn, &transactions);
+        insert_to_db(
+            &mut conn,
+            NAME,
+            VERSION as u64,
+            VERSION as u64,
+            OverwritePolicy::Overwrite,
+            rows,
+        )
+        .unwrap();
+
+        let transaction_rows: i64 = schema::transactions::table
+            .filter(schema::transactions::version.eq(VERSION))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        let event_rows: i64 = schema::events::table
+            .filter(schema::events::transaction_version.eq(VERSION))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        let resource_rows: i64 = schema::move_resources::table
+            .filter(schema::move_resources::transaction_version.eq(VERSION))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        let account_rows: i64 = schema::account_transactions::table
+            .filter(schema::account_transactions::transaction_version.eq(VERSION))
+            .count()
+            .get_result(&mut conn)
+            .unwrap();
+        assert_eq!(
+            (transaction_rows, event_rows, resource_rows, account_rows),
+            (1, 1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn test_current_move_resource_deleted() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(&database_url).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        let (created, deleted) = (VERSION + 1, VERSION + 2);
+        let account = format!("0x{:064x}", 0xabcd);
+        let resource_type = "0x1::test_resources::Marker";
+        for table in ["transactions", "user_transactions"] {
+            diesel::sql_query(format!(
+                "DELETE FROM {} WHERE version IN ({}, {})",
+                table, created, deleted
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        }
+        for table in ["events", "move_resources"] {
+            diesel::sql_query(format!(
+                "DELETE FROM {} WHERE transaction_version IN ({}, {})",
+                table, created, deleted
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        }
+        diesel::delete(
+            schema::current_move_resources::table
+                .filter(schema::current_move_resources::address.eq(&account))
+                .filter(schema::current_move_resources::type_.eq(resource_type)),
+        )
+        .execute(&mut conn)
+        .unwrap();
+
+        let create = user_transaction_with_changes(
+            created,
+            json!([{
+                "type": "write_resource",
+                "address": account,
+                "state_key_hash": format!("0x{:064x}", 2),
+                "data": { "type": resource_type, "data": { "value": "1" } }
+            }]),
+        );
+        let delete = user_transaction_with_changes(
+            deleted,
+            json!([{
+                "type": "delete_resource",
+                "address": account,
+                "state_key_hash": format!("0x{:064x}", 2),
+                "resource": resource_type
+            }]),
+        );
+        // Each in its own batch, so the tombstone overwrites the stored row
+        for transaction in [create, delete] {
+            let rows = transform_rows(&mut conn, &[transaction]);
+            assert_eq!(rows.current_move_resources.len(), 1);
+            insert_to_db(
+                &mut conn,
+                NAME,
+                created as u64,
+                deleted as u64,
+                OverwritePolicy::Overwrite,
+                rows,
+            )
+            .unwrap();
+        }
+
+        let (version, is_deleted, data): (i64, bool, Option<Value>) =
+            schema::current_move_resources::table
+                .filter(schema::current_move_resources::address.eq(&account))
+                .filter(schema::current_move_resources::type_.eq(resource_type))
+                .select((
+                    schema::current_move_resources::last_transaction_version,
+                    schema::current_move_resources::is_deleted,
+                    schema::current_move_resources::data,
+                ))
+                .first(&mut conn)
+                .unwrap();
+        assert_eq!((version, is_deleted, data), (deleted, true, None));
+    }
+
+    #[test]
+    fn test_current_table_item_deleted() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(&database_url).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        let (inserted, deleted, reinserted) = (VERSION + 20, VERSION + 21, VERSION + 22);
+        let handle = format!("0x{:064x}", 0x7ab1e);
+        let versions = format!("{}, {}, {}", inserted, deleted, reinserted);
+        for table in ["transactions", "user_transactions"] {
+            diesel::sql_query(format!(
+                "DELETE FROM {} WHERE version IN ({})",
+                table, versions
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        }
+        for table in ["events", "table_items"] {
+            diesel::sql_query(format!(
+                "DELETE FROM {} WHERE transaction_version IN ({})",
+                table, versions
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        }
+        diesel::delete(
+            schema::current_table_items::table
+                .filter(schema::current_table_items::table_handle.eq(&handle)),
+        )
+        .execute(&mut conn)
+        .unwrap();
+
+        let write = |version| {
+            user_transaction_with_changes(
+                version,
+                json!([{
+                    "type": "write_table_item",
+                    "state_key_hash": format!("0x{:064x}", 3),
+                    "handle": handle,
+                    "key": "0x01",
+                    "value": "0x0100000000000000",
+                    "data": { "key": 1, "key_type": "u8", "value": "1", "value_type": "u64" }
+                }]),
+            )
+        };
+        let delete = user_transaction_with_changes(
+            deleted,
+            json!([{
+                "type": "delete_table_item",
+                "state_key_hash": format!("0x{:064x}", 3),
+                "handle": handle,
+                "key": "0x01",
+                "data": { "key": 1, "key_type": "u8" }
+            }]),
+        );
+        let current_item = |conn: &mut PgConnection| -> (i64, bool, Option<Value>) {
+            schema::current_table_items::table
+                .filter(schema::current_table_items::table_handle.eq(&handle))
+                .select((
+                    schema::current_table_items::last_transaction_version,
+                    schema::current_table_items::is_deleted,
+                    schema::current_table_items::decoded_value,
+                ))
+                .first(conn)
+                .unwrap()
+        };
+
+        // Written then deleted in a batch, a tombstone
+        let rows = transform_rows(&mut conn, &[write(inserted), delete.clone()]);
+        assert_eq!(rows.current_table_items.len(), 1);
+        assert!(rows.current_table_items[0].is_deleted);
+        insert_to_db(
+            &mut conn,
+            NAME,
+            inserted as u64,
+            deleted as u64,
+            OverwritePolicy::Overwrite,
+            rows,
+        )
+        .unwrap();
+        assert_eq!(current_item(&mut conn), (deleted, true, None));
+
+        // Deleted then written again, live
+        let rows = transform_rows(&mut conn, &[write(inserted), delete, write(reinserted)]);
+        assert_eq!(rows.current_table_items.len(), 1);
+        let is_deleted: Vec<bool> = rows
+            .table_items
+            .iter()
+            .map(|item| item.is_deleted)
+            .collect();
+        assert_eq!(is_deleted, vec![false, true, false]);
+        insert_to_db(
+            &mut conn,
+            NAME,
+            inserted as u64,
+            reinserted as u64,
+            OverwritePolicy::Overwrite,
+            rows,
+        )
+        .unwrap();
+        assert_eq!(
+            current_item(&mut conn),
+            (reinserted, false, Some(json!("1")))
+        );
+    }
+
+    /// Publishing only, to `sink`
+    fn processor(conn_pool: PgDbPool, sink: impl Sink + 'static) -> CDefaultTransactionProcessor {
+        CDefaultTransactionProcessor::new(
+            Some(conn_pool),
+            Box::new(sink),
+            Validator::new(NAME, vec![], &ValidationConfig::default()),
+            DuplicateDetector::new(NAME, &DuplicateTransactionsConfig::default()),
+            EntryFunctionStats::new(&EntryFunctionStatsConfig::default()),
+            StorageUsage::new(NAME, &StorageUsageConfig::default()),
+            SinkMode::PublishOnly,
+            PublishFilter::new(NAME, &PublishFilterConfig::default()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_sink_policies() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(&database_url).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        // Nothing listens there
+        let failing = || {
+            let config = HttpSinkConfig {
+                url: "http://127.0.0.1:1/batches".to_string(),
+                max_attempts: 1,
+                ..HttpSinkConfig::default()
+            };
+            Box::new(HttpSink::new(&config, Envelope::new(4, NAME)))
+        };
+        let succeeded = |conn: &mut PgPoolConnection, version: i64| -> bool {
+            schema::processor_statuses::table
+                .filter(schema::processor_statuses::name.eq(NAME))
+                .filter(schema::processor_statuses::version.eq(version))
+                .select(schema::processor_statuses::success)
+                .first(conn)
+                .unwrap()
+        };
+
+        // A best effort sink failing doesn't fail the batch
+        let version = VERSION + 10;
+        let processor = processor(
+            conn_pool.clone(),
+            MultiSink::new().with("http", SinkPolicy::BestEffort, failing()),
+        );
+        let result = processor
+            .process_versions_with_status(
+                vec![user_transaction(version)],
+                version as u64,
+                version as u64,
+            )
+            .await;
+        assert!(result.is_ok());
+        assert!(succeeded(&mut conn, version));
+
+        // A required one does, and the version isn't marked processed
+        let version = VERSION + 11;
+        let processor = processor(
+            conn_pool.clone(),
+            MultiSink::new().with("http", SinkPolicy::Required, failing()),
+        );
+        let error = processor
+            .process_versions_with_status(
+                vec![user_transaction(version)],
+                version as u64,
+                version as u64,
+            )
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", error.inner().0).contains("Required sink http failed"));
+        assert!(error.is_retryable());
+        assert!(!succeeded(&mut conn, version));
+    }
+
+    #[test]
+    fn test_insert_null_bytes() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(&database_url).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        let version = VERSION + 3;
+        for table in ["transactions", "user_transactions"] {
+            diesel::sql_query(format!("DELETE FROM {} WHERE version = {}", table, version))
+                .execute(&mut conn)
+                .unwrap();
+        }
+        for table in ["events", "move_resources"] {
+            diesel::sql_query(format!(
+                "DELETE FROM {} WHERE transaction_version = {}",
+                table, version
+            ))
+            .execute(&mut conn)
+            .unwrap();
+        }
+
+        // Three levels deep, in a key and in a value, which Postgres rejects in JSONB
+        let dirty = json!({ "a": { "b": { "c\u{0000}": "x\u{0000}y" } } });
+        let mut transaction = serde_json::to_value(user_transaction_with_changes(
+            version,
+            json!([{
+                "type": "write_resource",
+                "address": format!("0x{:064x}", 0xabcd),
+                "state_key_hash": format!("0x{:064x}", 1),
+                "data": { "type": "0x1::account::Account", "data": dirty }
+            }]),
+        ))
+        .unwrap();
+        transaction["events"][0]["data"] = dirty;
+        let transaction: Transaction = serde_json::from_value(transaction).unwrap();
+        let rows = transform_rows(&mut conn, &[transaction]);
+        insert_to_db(
+            &mut conn,
+            NAME,
+            version as u64,
+            version as u64,
+            OverwritePolicy::Overwrite,
+            rows,
+        )
+        .unwrap();
+
+        let clean = json!({ "a": { "b": { "c": "xy" } } });
+        let event_data: Value = schema::events::table
+            .filter(schema::events::transaction_version.eq(version))
+            .select(schema::events::data)
+            .first(&mut conn)
+            .unwrap();
+        assert_eq!(event_data, clean);
+        let resource_data: Option<Value> = schema::move_resources::table
+            .filter(schema::move_resources::transaction_version.eq(version))
+            .select(schema::move_resources::data)
+            .first(&mut conn)
+            .unwrap();
+        assert_eq!(resource_data, Some(clean));
+    }
+
+    #[tokio::test]
+    async fn test_scripts_stored_once() {
+        let Some(conn_pool) = test_utils::test_pool() else {
+            return;
+        };
+        let script_transaction = |version: i64| -> Transaction {
+            let mut transaction = serde_json::to_value(user_transaction(version)).unwrap();
+            transaction["payload"] = json!({
+                "type": "script_payload",
+                "code": { "bytecode": "0xa11ceb0b0600000001" },
+                "type_arguments": [],
+                "arguments": []
+            });
+            serde_json::from_value(transaction).unwrap()
+        };
+        let hash = Script::hash_bytecode(&hex::decode("a11ceb0b0600000001").unwrap());
+        let (first, second, later) = (VERSION + 20, VERSION + 21, VERSION + 30);
+        {
+            let mut conn = conn_pool.get().unwrap();
+            diesel::delete(schema::scripts::table.filter(schema::scripts::script_hash.eq(&hash)))
+                .execute(&mut conn)
+                .unwrap();
+            for table in ["transactions", "user_transactions"] {
+                diesel::sql_query(format!(
+                    "DELETE FROM {} WHERE version IN ({}, {}, {})",
+                    table, first, second, later
+                ))
+                .execute(&mut conn)
+                .unwrap();
+            }
+        }
+
+        // Twice in a batch, then again in a later one
+        test_utils::run_default_processor(Some(conn_pool.clone()), SinkMode::DbOnly, vec![
+            script_transaction(first),
+            script_transaction(second),
+        ])
+        .await;
+        test_utils::run_default_processor(Some(conn_pool.clone()), SinkMode::DbOnly, vec![
+            script_transaction(later),
+        ])
+        .await;
+
+        let mut conn = conn_pool.get().unwrap();
+        let first_seen: Vec<i64> = schema::scripts::table
+            .filter(schema::scripts::script_hash.eq(&hash))
+            .select(schema::scripts::first_seen_version)
+            .load(&mut conn)
+            .unwrap();
+        assert_eq!(first_seen, [first]);
+        let script_hashes: Vec<Option<String>> = schema::user_transactions::table
+            .filter(schema::user_transactions::version.eq_any([first, second, later]))
+            .select(schema::user_transactions::script_hash)
+            .load(&mut conn)
+            .unwrap();
+        assert_eq!(script_hashes, vec![Some(hash); 3]);
+    }
+
+    #[tokio::test]
+    async fn test_publish_fixture() {
+        // The consecutive versions of the fixture, published without a database
+        let transactions = test_utils::fixture("batch1.json")
+            .into_iter()
+            .filter(|txn| txn.version() >= Some(691595))
+            .collect();
+        let (result, sink) =
+            test_utils::run_default_processor(None, SinkMode::PublishOnly, transactions).await;
+        assert_eq!((result.start_version, result.end_version), (691595, 691596));
+        assert_eq!(result.counts.transactions, 2);
+        let versions = sink
+            .transactions()
+            .iter()
+            .filter_map(|txn| txn.version())
+            .collect::<Vec<_>>();
+        assert_eq!(versions, [691595, 691596]);
+        let events = sink.events();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|event| event.transaction_version == 691595));
+        assert_eq!(sink.write_set_changes().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_publish_failures() {
+        let Some(conn_pool) = test_utils::test_pool() else {
+            return;
+        };
+        let mut driver_config = test_utils::driver_config(json!({ "transaction_topic": "txns" }));
+        driver_config.publish_retry = PublishRetryConfig {
+            max_attempts: 3,
+            base_delay_millis: 1,
+            max_delay_millis: 1,
+        };
+        let (publisher, messages) = Publisher::in_memory(driver_config, Envelope::new(4, NAME));
+        let processor = processor(conn_pool, publisher);
+        let process = |version: i64| {
+            processor.process_versions_with_status(
+                vec![user_transaction(version)],
+                version as u64,
+                version as u64,
+            )
+        };
+
+        // Retried within the batch
+        let version = VERSION + 20;
+        messages.fail_next(2, RDKafkaErrorCode::AllBrokersDown);
+        let result = process(version).await.unwrap();
+        assert_eq!(
+            (result.start_version, result.end_version),
+            (version as u64, version as u64)
+        );
+        assert_eq!(messages.take().len(), 1);
+
+        // Failing past the retries fails the batch, retryable, and it goes through once Kafka is
+        // back
+        let version = VERSION + 21;
+        messages.fail_next(3, RDKafkaErrorCode::AllBrokersDown);
+        let error = process(version).await.unwrap_err();
+        assert!(error.is_retryable());
+        assert!(messages.is_empty());
+        let result = process(version).await.unwrap();
+        assert_eq!(
+            (result.start_version, result.end_version),
+            (version as u64, version as u64)
+        );
+        let published = messages.on_topic("txns");
+        assert_eq!(published.len(), 1);
+        assert_eq!(
+            published[0].printed().payload["version"],
+            version.to_string()
+        );
     }
 }
@@ -0,0 +1,387 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    custom::driver::{
+        backfill_guard::{self, OverwritePolicy},
+        change_feed, column_stats,
+        shadow::{ShadowOutput, ShadowRunner, ShadowTable},
+        validation::{Policy, Rule, Validator, Violation},
+    },
+    database::{
+        clean_data_for_db, execute_with_better_error, get_chunks, write_transaction, GuardedUpsert,
+        PgDbPool, PgPoolConnection,
+    },
+    indexer::{
+        errors::TransactionProcessingError, processing_result::ProcessingResult,
+        transaction_processor::TransactionProcessor,
+    },
+    models::{
+        change_feed::Operation,
+        dex_models::{
+            dex_pools::{DexPool, DexPoolMap},
+            dex_swaps::DexSwap,
+            protocols::DexProtocol,
+        },
+    },
+    schema,
+};
+use aptos_api_types::Transaction as APITransaction;
+use async_trait::async_trait;
+use bigdecimal::Signed;
+use diesel::{pg::upsert::excluded, result::Error, ExpressionMethods, PgConnection};
+use field_count::FieldCount;
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    sync::Mutex,
+};
+
+pub const NAME: &str = "custom_dex_processor";
+pub struct CDexTransactionProcessor {
+    connection_pool: PgDbPool,
+    protocols: Vec<DexProtocol>,
+    /// Pools already in `dex_pools`, so their resource isn't looked up again
+    known_pools: Mutex<HashSet<String>>,
+    validator: Validator<DexOutput>,
+    shadow: ShadowRunner<DexOutput>,
+}
+
+/// What a batch is about to commit, as checked by the validation rules
+pub struct DexOutput {
+    pub dex_swaps: Vec<DexSwap>,
+    /// Pools swapped for the first time
+    pub dex_pools: Vec<DexPool>,
+}
+
+impl ShadowOutput for DexOutput {
+    /// Only the swaps: which pools a batch registers depends on the pools the processor already
+    /// knows, which a shadow doesn't share
+    fn tables(&self) -> Vec<ShadowTable> {
+        vec![ShadowTable::new(
+            "dex_swaps",
+            &["transaction_version", "event_index"],
+            &self.dex_swaps,
+        )]
+    }
+}
+
+pub fn default_rules() -> Vec<Rule<DexOutput>> {
+    vec![
+        Rule::new(
+            "swap_amounts_non_negative",
+            Policy::Fail,
+            |output: &DexOutput| {
+                output
+                    .dex_swaps
+                    .iter()
+                    .filter(|swap| swap.amount_in.is_negative() || swap.amount_out.is_negative())
+                    .map(|swap| {
+                        Violation::new(
+                            swap.transaction_version,
+                            format!("Negative amount swapped in {}", swap.pool),
+                            json!(swap),
+                        )
+                    })
+                    .collect()
+            },
+        ),
+        Rule::new("swap_coins_differ", Policy::Warn, |output: &DexOutput| {
+            output
+                .dex_swaps
+                .iter()
+                .filter(|swap| swap.coin_in == swap.coin_out)
+                .map(|swap| {
+                    Violation::new(
+                        swap.transaction_version,
+                        format!("{} swapped for itself in {}", swap.coin_in, swap.pool),
+                        json!(swap),
+                    )
+                })
+                .collect()
+        }),
+    ]
+}
+
+impl CDexTransactionProcessor {
+    pub fn new(
+        connection_pool: PgDbPool,
+        protocols: Vec<DexProtocol>,
+        validator: Validator<DexOutput>,
+        shadow: ShadowRunner<DexOutput>,
+    ) -> Self {
+        Self {
+            connection_pool,
+            protocols,
+            known_pools: Mutex::new(HashSet::new()),
+            validator,
+            shadow,
+        }
+    }
+
+    /// Finds the pool resource of a swap whose pool isn't registered yet, first in the write sets
+    /// of the batch up to the swap, then in `move_resources`
+    fn find_pool(
+        &self,
+        conn: &mut PgPoolConnection,
+        swap: &DexSwap,
+        batch_pools: &DexPoolMap,
+        start_version: u64,
+    ) -> Option<DexPool> {
+        if let Some(pool) = batch_pools.get(&swap.pool) {
+            return Some(pool.clone());
+        }
+        let protocol = self.protocols.iter().find(|p| p.name == swap.protocol)?;
+        match DexPool::lookup(conn, protocol, &swap.pool, start_version as i64) {
+            Ok(pool) => pool,
+            Err(err) => {
+                aptos_logger::warn!(
+                    pool = swap.pool,
+                    transaction_version = swap.transaction_version,
+                    error = ?err,
+                    "Failed to look up dex pool resource",
+                );
+                None
+            },
+        }
+    }
+}
+
+impl Debug for CDexTransactionProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = &self.connection_pool.state();
+        write!(
+            f,
+            "DexTransactionProcessor {{ connections: {:?}  idle_connections: {:?}  \
+             max_size: {:?}  connection_timeout: {:?} }}",
+            state.connections,
+            state.idle_connections,
+            self.connection_pool.max_size(),
+            self.connection_pool.connection_timeout()
+        )
+    }
+}
+
+fn insert_to_db_impl(
+    conn: &mut PgConnection,
+    policy: OverwritePolicy,
+    dex_swaps: &[DexSwap],
+    dex_pools: &[DexPool],
+) -> Result<(), diesel::result::Error> {
+    insert_dex_swaps(conn, dex_swaps)?;
+    insert_dex_pools(conn, policy, dex_pools)?;
+
+    change_feed::record(
+        conn,
+        "dex_swaps",
+        &["transaction_version", "event_index"],
+        Operation::Insert,
+        dex_swaps,
+    )?;
+    change_feed::record(conn, "dex_pools", &["pool"], Operation::Upsert, dex_pools)?;
+    Ok(())
+}
+
+fn insert_to_db(
+    conn: &mut PgPoolConnection,
+    name: &'static str,
+    start_version: u64,
+    end_version: u64,
+    policy: OverwritePolicy,
+    dex_swaps: Vec<DexSwap>,
+    dex_pools: Vec<DexPool>,
+) -> Result<(), diesel::result::Error> {
+    aptos_logger::trace!(
+        name = name,
+        start_version = start_version,
+        end_version = end_version,
+        "Inserting to db",
+    );
+    column_stats::observe("dex_swaps", &dex_swaps);
+    column_stats::observe("dex_pools", &dex_pools);
+    match write_transaction::<_, Error, _>(conn, |pg_conn| {
+        insert_to_db_impl(pg_conn, policy, &dex_swaps, &dex_pools)
+    }) {
+        Ok(_) => Ok(()),
+        Err(_) => write_transaction::<_, Error, _>(conn, |pg_conn| {
+            let dex_swaps = clean_data_for_db(dex_swaps, true);
+            let dex_pools = clean_data_for_db(dex_pools, true);
+
+            insert_to_db_impl(pg_conn, policy, &dex_swaps, &dex_pools)
+        }),
+    }
+}
+
+fn insert_dex_swaps(
+    conn: &mut PgConnection,
+    item_to_insert: &[DexSwap],
+) -> Result<(), diesel::result::Error> {
+    use schema::dex_swaps::dsl::*;
+
+    let chunks = get_chunks(item_to_insert.len(), DexSwap::field_count());
+    for (start_ind, end_ind) in chunks {
+        execute_with_better_error(
+            conn,
+            diesel::insert_into(schema::dex_swaps::table)
+                .values(&item_to_insert[start_ind..end_ind])
+                .on_conflict((transaction_version, event_index))
+                .do_nothing(),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_dex_pools(
+    conn: &mut PgConnection,
+    policy: OverwritePolicy,
+    item_to_insert: &[DexPool],
+) -> Result<(), diesel::result::Error> {
+    use schema::dex_pools::dsl::*;
+
+    let chunks = get_chunks(item_to_insert.len(), DexPool::field_count());
+    for (start_ind, end_ind) in chunks {
+        GuardedUpsert::new("dex_pools", policy)
+            .skip_unless(" WHERE dex_pools.first_seen_version > EXCLUDED.first_seen_version ")
+            .execute(
+                conn,
+                diesel::insert_into(schema::dex_pools::table)
+                    .values(&item_to_insert[start_ind..end_ind])
+                    .on_conflict(pool)
+                    .do_update()
+                    .set((
+                        pool_address.eq(excluded(pool_address)),
+                        first_seen_version.eq(excluded(first_seen_version)),
+                        inserted_at.eq(excluded(inserted_at)),
+                    )),
+                Some(" WHERE dex_pools.first_seen_version >= EXCLUDED.first_seen_version "),
+                &item_to_insert[start_ind..end_ind],
+            )?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl TransactionProcessor for CDexTransactionProcessor {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn process_transactions(
+        &self,
+        transactions: Vec<APITransaction>,
+        start_version: u64,
+        end_version: u64,
+    ) -> Result<ProcessingResult, TransactionProcessingError> {
+        let mut conn = self.get_conn().map_err(|err| {
+            TransactionProcessingError::ConnectionPoolError((
+                err,
+                start_version,
+                end_version,
+                self.name(),
+            ))
+        })?;
+
+        let mut all_dex_swaps = vec![];
+        // Pool resources written so far in the batch
+        let mut batch_pools: DexPoolMap = HashMap::new();
+        // Unregistered pools referenced by the batch's swaps
+        let mut new_pools: DexPoolMap = HashMap::new();
+        let mut missing_pools = HashSet::new();
+
+        for txn in &transactions {
+            // A swap event of a configured protocol that doesn't decode fails the batch, rather
+            // than the swap going missing
+            let (swaps, pools) =
+                DexSwap::from_transaction(txn, &self.protocols).map_err(|err| {
+                    TransactionProcessingError::TransactionCommitError((
+                        err,
+                        start_version,
+                        end_version,
+                        self.name(),
+                    ))
+                })?;
+            for (pool, dex_pool) in pools {
+                batch_pools.entry(pool).or_insert(dex_pool);
+            }
+            for swap in &swaps {
+                if new_pools.contains_key(&swap.pool)
+                    || missing_pools.contains(&swap.pool)
+                    || self.known_pools.lock().unwrap().contains(&swap.pool)
+                {
+                    continue;
+                }
+                match self.find_pool(&mut conn, swap, &batch_pools, start_version) {
+                    Some(dex_pool) => {
+                        new_pools.insert(swap.pool.clone(), dex_pool);
+                    },
+                    None => {
+                        // Tried again on the pool's next swap
+                        aptos_logger::warn!(
+                            pool = swap.pool,
+                            transaction_version = swap.transaction_version,
+                            "Could not find the resource of a swapped dex pool",
+                        );
+                        missing_pools.insert(swap.pool.clone());
+                    },
+                }
+            }
+            all_dex_swaps.extend(swaps);
+        }
+
+        // Sort by PK
+        let mut all_dex_pools = new_pools.into_values().collect::<Vec<DexPool>>();
+        all_dex_pools.sort_by(|a, b| a.pool.cmp(&b.pool));
+        let pool_ids = all_dex_pools
+            .iter()
+            .map(|dex_pool| dex_pool.pool.clone())
+            .collect::<Vec<_>>();
+
+        let output = DexOutput {
+            dex_swaps: all_dex_swaps,
+            dex_pools: all_dex_pools,
+        };
+        self.shadow.run(&mut conn, &transactions, &output, start_version, end_version);
+        self.validator
+            .validate(Some(&mut conn), &output, start_version, end_version)
+            .map_err(|err| {
+                TransactionProcessingError::TransactionCommitError((
+                    err,
+                    start_version,
+                    end_version,
+                    self.name(),
+                ))
+            })?;
+
+        let tx_result = insert_to_db(
+            &mut conn,
+            self.name(),
+            start_version,
+            end_version,
+            backfill_guard::policy(self.name(), start_version, end_version),
+            output.dex_swaps,
+            output.dex_pools,
+        );
+        match tx_result {
+            Ok(_) => {
+                self.known_pools.lock().unwrap().extend(pool_ids);
+                Ok(ProcessingResult::new(
+                    self.name(),
+                    start_version,
+                    end_version,
+                ))
+            },
+            Err(err) => Err(TransactionProcessingError::TransactionCommitError((
+                anyhow::Error::from(err),
+                start_version,
+                end_version,
+                self.name(),
+            ))),
+        }
+    }
+
+    fn connection_pool(&self) -> Option<&PgDbPool> {
+        Some(&self.connection_pool)
+    }
+}
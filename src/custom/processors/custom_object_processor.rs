@@ -0,0 +1,632 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    counters::{
+        OBJECT_OWNERSHIP_PROPAGATED, OBJECT_OWNERSHIP_PROPAGATION_TRUNCATED,
+        OBJECT_OWNERSHIP_UNRESOLVED,
+    },
+    custom::driver::{
+        backfill_guard::{self, OverwritePolicy},
+        change_feed,
+        config::ObjectOwnershipConfig,
+        publish_retry::PublishError,
+        publisher::Publisher,
+        row_limits,
+    },
+    database::{
+        clean_data_for_db, execute_with_better_error, get_chunks, write_transaction,
+        CurrentRowUpsert, PgDbPool, PgPoolConnection,
+    },
+    indexer::{
+        errors::TransactionProcessingError, processing_result::ProcessingResult,
+        transaction_processor::TransactionProcessor,
+    },
+    models::{
+        account_derivations::AccountDerivation,
+        asset_stores::{AssetStoreChange, CurrentAssetStore, CurrentAssetStoreQuery},
+        change_feed::Operation,
+        object_ownership::{self, DbOwnershipEdges, ObjectOwnershipEdge, Resolution},
+        v2_objects::{CurrentObject, CurrentObjectQuery, Object},
+    },
+    schema,
+};
+use aptos_api_types::{Transaction, WriteSetChange};
+use async_trait::async_trait;
+use diesel::{
+    dsl::sql,
+    pg::upsert::excluded,
+    result::Error,
+    sql_types::{BigInt, Nullable, Text},
+    ExpressionMethods, PgConnection, QueryDsl,
+};
+use field_count::FieldCount;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+};
+
+pub const NAME: &str = "custom_object_processor";
+/// Model of the published asset store changes, see `client::MODEL_TOPIC_KEYS`
+const ASSET_STORE_MODEL: &str = "CurrentAssetStore";
+pub struct CObjectTransactionProcessor {
+    connection_pool: PgDbPool,
+    config: ObjectOwnershipConfig,
+    publisher: Publisher,
+}
+
+impl CObjectTransactionProcessor {
+    pub fn new(
+        connection_pool: PgDbPool,
+        config: ObjectOwnershipConfig,
+        publisher: Publisher,
+    ) -> Self {
+        Self {
+            connection_pool,
+            config,
+            publisher,
+        }
+    }
+}
+
+impl Debug for CObjectTransactionProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = &self.connection_pool.state();
+        write!(
+            f,
+            "ObjectTransactionProcessor {{ connections: {:?}  idle_connections: {:?}  \
+             max_size: {:?}  connection_timeout: {:?} }}",
+            state.connections,
+            state.idle_connections,
+            self.connection_pool.max_size(),
+            self.connection_pool.connection_timeout()
+        )
+    }
+}
+
+fn insert_to_db_impl(
+    conn: &mut PgConnection,
+    policy: OverwritePolicy,
+    objects: &[Object],
+    current_objects: &[CurrentObject],
+    edges: &[ObjectOwnershipEdge],
+    descendants: &[CurrentObject],
+    derivations: &[AccountDerivation],
+    asset_stores: &[CurrentAssetStore],
+) -> Result<(), diesel::result::Error> {
+    // Shadowed so the change feed records the rows that are inserted
+    let asset_stores = row_limits::enforce(conn, asset_stores)?;
+    let asset_stores = asset_stores.as_ref();
+    insert_objects(conn, objects)?;
+    insert_current_objects(conn, policy, current_objects)?;
+    insert_object_ownership_edges(conn, policy, edges)?;
+    update_ultimate_owners(conn, descendants)?;
+    insert_account_derivations(conn, derivations)?;
+    insert_current_asset_stores(conn, policy, asset_stores)?;
+
+    let by_wsc = &["transaction_version", "write_set_change_index"];
+    change_feed::record(conn, "objects", by_wsc, Operation::Insert, objects)?;
+    change_feed::record(
+        conn,
+        "current_objects",
+        &["object_address"],
+        Operation::Upsert,
+        current_objects,
+    )?;
+    change_feed::record(
+        conn,
+        "object_ownership_edges",
+        &["object_address"],
+        Operation::Upsert,
+        edges,
+    )?;
+    change_feed::record(
+        conn,
+        "current_objects",
+        &["object_address"],
+        Operation::Update,
+        descendants,
+    )?;
+    change_feed::record(
+        conn,
+        "account_derivations",
+        &["derived_address"],
+        Operation::Upsert,
+        derivations,
+    )?;
+    change_feed::record(
+        conn,
+        "current_asset_stores",
+        &["store_address", "asset_type"],
+        Operation::Upsert,
+        asset_stores,
+    )?;
+    Ok(())
+}
+
+fn insert_to_db(
+    conn: &mut PgPoolConnection,
+    name: &'static str,
+    start_version: u64,
+    end_version: u64,
+    policy: OverwritePolicy,
+    object_core: (Vec<Object>, Vec<CurrentObject>),
+    ownership: (Vec<ObjectOwnershipEdge>, Vec<CurrentObject>),
+    accounts: (Vec<AccountDerivation>, Vec<CurrentAssetStore>),
+) -> Result<(), diesel::result::Error> {
+    aptos_logger::trace!(
+        name = name,
+        start_version = start_version,
+        end_version = end_version,
+        "Inserting to db",
+    );
+    let (objects, current_objects) = object_core;
+    let (edges, descendants) = ownership;
+    let (derivations, asset_stores) = accounts;
+    match write_transaction::<_, Error, _>(conn, |pg_conn| {
+        insert_to_db_impl(
+            pg_conn,
+            policy,
+            &objects,
+            &current_objects,
+            &edges,
+            &descendants,
+            &derivations,
+            &asset_stores,
+        )
+    }) {
+        Ok(_) => Ok(()),
+        Err(_) => {
+            let objects = clean_data_for_db(objects, true);
+            let current_objects = clean_data_for_db(current_objects, true);
+            let edges = clean_data_for_db(edges, true);
+            let derivations = clean_data_for_db(derivations, true);
+            let asset_stores = clean_data_for_db(asset_stores, true);
+            write_transaction::<_, Error, _>(conn, |pg_conn| {
+                insert_to_db_impl(
+                    pg_conn,
+                    policy,
+                    &objects,
+                    &current_objects,
+                    &edges,
+                    &descendants,
+                    &derivations,
+                    &asset_stores,
+                )
+            })
+        },
+    }
+}
+
+fn insert_objects(
+    conn: &mut PgConnection,
+    items_to_insert: &[Object],
+) -> Result<(), diesel::result::Error> {
+    use schema::objects::dsl::*;
+    let chunks = get_chunks(items_to_insert.len(), Object::field_count());
+    for (start_ind, end_ind) in chunks {
+        execute_with_better_error(
+            conn,
+            diesel::insert_into(schema::objects::table)
+                .values(&items_to_insert[start_ind..end_ind])
+                .on_conflict((transaction_version, write_set_change_index))
+                .do_nothing(),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_current_objects(
+    conn: &mut PgConnection,
+    policy: OverwritePolicy,
+    items_to_insert: &[CurrentObject],
+) -> Result<(), diesel::result::Error> {
+    use schema::current_objects::dsl::*;
+    let chunks = get_chunks(items_to_insert.len(), CurrentObject::field_count());
+    for (start_ind, end_ind) in chunks {
+        CurrentRowUpsert::new("current_objects", policy).execute(
+            conn,
+            diesel::insert_into(schema::current_objects::table)
+                .values(&items_to_insert[start_ind..end_ind])
+                .on_conflict(object_address)
+                .do_update()
+                .set((
+                    owner_address.eq(excluded(owner_address)),
+                    state_key_hash.eq(excluded(state_key_hash)),
+                    allow_ungated_transfer.eq(excluded(allow_ungated_transfer)),
+                    last_guid_creation_num.eq(excluded(last_guid_creation_num)),
+                    last_transaction_version.eq(excluded(last_transaction_version)),
+                    is_deleted.eq(excluded(is_deleted)),
+                    inserted_at.eq(excluded(inserted_at)),
+                    ultimate_owner.eq(excluded(ultimate_owner)),
+                    ownership_depth.eq(excluded(ownership_depth)),
+                )),
+            &items_to_insert[start_ind..end_ind],
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_object_ownership_edges(
+    conn: &mut PgConnection,
+    policy: OverwritePolicy,
+    items_to_insert: &[ObjectOwnershipEdge],
+) -> Result<(), diesel::result::Error> {
+    use schema::object_ownership_edges::dsl::*;
+    let chunks = get_chunks(items_to_insert.len(), ObjectOwnershipEdge::field_count());
+    for (start_ind, end_ind) in chunks {
+        CurrentRowUpsert::new("object_ownership_edges", policy).execute(
+            conn,
+            diesel::insert_into(schema::object_ownership_edges::table)
+                .values(&items_to_insert[start_ind..end_ind])
+                .on_conflict(object_address)
+                .do_update()
+                .set((
+                    owner_address.eq(excluded(owner_address)),
+                    last_transaction_version.eq(excluded(last_transaction_version)),
+                    is_deleted.eq(excluded(is_deleted)),
+                    inserted_at.eq(excluded(inserted_at)),
+                )),
+            &items_to_insert[start_ind..end_ind],
+        )?;
+    }
+    Ok(())
+}
+
+/// Only the resolved columns of descendants change. Rows rewritten since they were selected were
+/// resolved by the batch that rewrote them.
+fn update_ultimate_owners(
+    conn: &mut PgConnection,
+    descendants: &[CurrentObject],
+) -> Result<(), diesel::result::Error> {
+    use schema::current_objects::dsl::*;
+    for descendant in descendants {
+        execute_with_better_error(
+            conn,
+            diesel::update(
+                current_objects
+                    .filter(object_address.eq(&descendant.object_address))
+                    .filter(last_transaction_version.eq(descendant.last_transaction_version)),
+            )
+            .set((
+                ultimate_owner.eq(&descendant.ultimate_owner),
+                ownership_depth.eq(descendant.ownership_depth),
+            )),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+/// What a later transaction reveals about a derivation fills in the stored row, what's stored
+/// already is kept, so it isn't guarded by the backfill policy
+fn insert_account_derivations(
+    conn: &mut PgConnection,
+    items_to_insert: &[AccountDerivation],
+) -> Result<(), diesel::result::Error> {
+    use schema::account_derivations::dsl::*;
+    let chunks = get_chunks(items_to_insert.len(), AccountDerivation::field_count());
+    for (start_ind, end_ind) in chunks {
+        execute_with_better_error(
+            conn,
+            diesel::insert_into(schema::account_derivations::table)
+                .values(&items_to_insert[start_ind..end_ind])
+                .on_conflict(derived_address)
+                .do_update()
+                .set((
+                    source_address.eq(sql::<Nullable<Text>>(
+                        "COALESCE(account_derivations.source_address, excluded.source_address)",
+                    )),
+                    seed.eq(sql::<Nullable<Text>>(
+                        "COALESCE(account_derivations.seed, excluded.seed)",
+                    )),
+                    transaction_version.eq(sql::<BigInt>(
+                        "LEAST(account_derivations.transaction_version, excluded.transaction_version)",
+                    )),
+                )),
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+fn insert_current_asset_stores(
+    conn: &mut PgConnection,
+    policy: OverwritePolicy,
+    items_to_insert: &[CurrentAssetStore],
+) -> Result<(), diesel::result::Error> {
+    use schema::current_asset_stores::dsl::*;
+    let chunks = get_chunks(items_to_insert.len(), CurrentAssetStore::field_count());
+    for (start_ind, end_ind) in chunks {
+        CurrentRowUpsert::new("current_asset_stores", policy).execute(
+            conn,
+            diesel::insert_into(schema::current_asset_stores::table)
+                .values(&items_to_insert[start_ind..end_ind])
+                .on_conflict((store_address, asset_type))
+                .do_update()
+                .set((
+                    owner_address.eq(excluded(owner_address)),
+                    is_frozen.eq(excluded(is_frozen)),
+                    created_transaction_version.eq(excluded(created_transaction_version)),
+                    deleted_transaction_version.eq(excluded(deleted_transaction_version)),
+                    last_transaction_version.eq(excluded(last_transaction_version)),
+                    inserted_at.eq(excluded(inserted_at)),
+                )),
+            &items_to_insert[start_ind..end_ind],
+        )?;
+    }
+    Ok(())
+}
+
+/// The stores `changes` change. Fungible stores written without their object core, e.g. by a
+/// deposit, keep their owner, or take the object's current one when they weren't listed yet.
+fn resolve_asset_stores(
+    conn: &mut PgPoolConnection,
+    mut changes: Vec<AssetStoreChange>,
+) -> Result<Vec<CurrentAssetStore>, diesel::result::Error> {
+    if changes.is_empty() {
+        return Ok(vec![]);
+    }
+    let store_addresses = changes
+        .iter()
+        .map(|change| change.store_address.clone())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    let existing = CurrentAssetStoreQuery::get_by_store_addresses(&store_addresses, conn)?
+        .into_iter()
+        .map(CurrentAssetStore::from)
+        .collect::<Vec<_>>();
+    let listed = existing
+        .iter()
+        .map(|store| store.store_address.clone())
+        .collect::<HashSet<_>>();
+    let unowned = changes
+        .iter()
+        .filter(|change| change.owner_address.is_none() && change.asset_type.is_some())
+        .filter(|change| !listed.contains(&change.store_address))
+        .map(|change| change.store_address.clone())
+        .collect::<Vec<_>>();
+    if !unowned.is_empty() {
+        let owners = CurrentObjectQuery::get_by_addresses(&unowned, conn)?
+            .into_iter()
+            .filter(|object| !object.is_deleted)
+            .map(|object| (object.object_address, object.owner_address))
+            .collect::<HashMap<_, _>>();
+        changes = changes
+            .into_iter()
+            .map(|change| {
+                let owner_address = owners.get(&change.store_address).cloned();
+                change.with_owner(owner_address)
+            })
+            .collect();
+    }
+    Ok(CurrentAssetStore::apply_all(existing, changes))
+}
+
+fn observe(resolution: &Resolution) {
+    match resolution {
+        Resolution::Owner { .. } => {},
+        Resolution::Cycle => OBJECT_OWNERSHIP_UNRESOLVED
+            .with_label_values(&["cycle"])
+            .inc(),
+        Resolution::TooDeep => OBJECT_OWNERSHIP_UNRESOLVED
+            .with_label_values(&["too_deep"])
+            .inc(),
+    }
+}
+
+#[async_trait]
+impl TransactionProcessor for CObjectTransactionProcessor {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn process_transactions(
+        &self,
+        transactions: Vec<Transaction>,
+        start_version: u64,
+        end_version: u64,
+    ) -> Result<ProcessingResult, TransactionProcessingError> {
+        let mut conn = self.get_conn().map_err(|err| {
+            TransactionProcessingError::ConnectionPoolError((
+                err,
+                start_version,
+                end_version,
+                self.name(),
+            ))
+        })?;
+        let commit_error = |err: diesel::result::Error| {
+            TransactionProcessingError::TransactionCommitError((
+                anyhow::Error::from(err),
+                start_version,
+                end_version,
+                NAME,
+            ))
+        };
+        // Published once the rows are written, a failure has the batch processed again
+        let publish_error = |err: PublishError| {
+            TransactionProcessingError::TransactionCommitError((
+                anyhow::Error::from(err),
+                start_version,
+                end_version,
+                NAME,
+            ))
+        };
+
+        let mut all_objects = vec![];
+        let mut all_current_objects = HashMap::new();
+        let mut derivations = vec![];
+        let mut asset_store_changes = vec![];
+        for txn in &transactions {
+            derivations.extend(AccountDerivation::from_transaction(txn));
+            let (changes, txn_version) = match txn {
+                Transaction::UserTransaction(user_txn) => (
+                    user_txn.info.changes.clone(),
+                    user_txn.info.version.0 as i64,
+                ),
+                Transaction::BlockMetadataTransaction(bmt_txn) => {
+                    (bmt_txn.info.changes.clone(), bmt_txn.info.version.0 as i64)
+                },
+                _ => continue,
+            };
+
+            // A fungible store's owner is the one of its object core, once the transaction is read
+            let mut fungible_stores = vec![];
+            for (index, wsc) in changes.iter().enumerate() {
+                let index = index as i64;
+                match wsc {
+                    WriteSetChange::WriteResource(inner) => {
+                        derivations
+                            .extend(AccountDerivation::from_write_resource(inner, txn_version));
+                        asset_store_changes.extend(
+                            AssetStoreChange::from_coin_store_write(inner, txn_version).unwrap(),
+                        );
+                        fungible_stores.extend(
+                            AssetStoreChange::from_fungible_store_write(inner, txn_version)
+                                .unwrap(),
+                        );
+                        if let Some((object, current_object)) =
+                            &Object::from_write_resource(inner, txn_version, index).unwrap()
+                        {
+                            derivations.extend(AccountDerivation::from_object(object));
+                            all_objects.push(object.clone());
+                            all_current_objects
+                                .insert(object.object_address.clone(), current_object.clone());
+                        }
+                    },
+                    WriteSetChange::DeleteResource(inner) => {
+                        asset_store_changes.extend(
+                            AssetStoreChange::from_coin_store_delete(inner, txn_version).unwrap(),
+                        );
+                        asset_store_changes
+                            .extend(AssetStoreChange::from_object_delete(inner, txn_version));
+                        if let Some((object, current_object)) = Object::from_delete_resource(
+                            inner,
+                            txn_version,
+                            index,
+                            &all_current_objects,
+                            &mut conn,
+                        )
+                        .unwrap()
+                        {
+                            all_objects.push(object.clone());
+                            all_current_objects
+                                .insert(object.object_address.clone(), current_object.clone());
+                        }
+                    },
+                    _ => {},
+                }
+            }
+            for change in fungible_stores {
+                let owner_address = all_current_objects
+                    .get(&change.store_address)
+                    .filter(|object| !object.is_deleted)
+                    .map(|object| object.owner_address.clone());
+                asset_store_changes.push(change.with_owner(owner_address));
+            }
+        }
+
+        let batch_edges = all_current_objects
+            .iter()
+            .map(|(address, current_object)| {
+                (
+                    address.clone(),
+                    ObjectOwnershipEdge::from_current_object(current_object),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        let resolved = object_ownership::resolve(
+            &batch_edges,
+            &mut DbOwnershipEdges { conn: &mut conn },
+            self.config.max_depth,
+            self.config.max_propagation,
+        )
+        .map_err(commit_error)?;
+        if resolved.truncated {
+            OBJECT_OWNERSHIP_PROPAGATION_TRUNCATED.inc();
+            aptos_logger::warn!(
+                name = NAME,
+                start_version = start_version,
+                end_version = end_version,
+                max_propagation = self.config.max_propagation,
+                "Ownership changes have more descendants than max_propagation, the rest keep a stale ultimate owner"
+            );
+        }
+        OBJECT_OWNERSHIP_PROPAGATED.inc_by(resolved.descendants.len() as u64);
+
+        for current_object in all_current_objects.values_mut() {
+            // Deleted objects have no owner to resolve
+            if let Some(resolution) = resolved.objects.get(&current_object.object_address) {
+                observe(resolution);
+                (current_object.ultimate_owner, current_object.ownership_depth) =
+                    resolution.columns();
+            }
+        }
+        let descendant_addresses = resolved.descendants.keys().cloned().collect::<Vec<_>>();
+        let mut descendants = CurrentObjectQuery::get_by_addresses(&descendant_addresses, &mut conn)
+            .map_err(commit_error)?
+            .into_iter()
+            .map(|res| {
+                let mut descendant = CurrentObject::from(res);
+                let resolution = &resolved.descendants[&descendant.object_address];
+                observe(resolution);
+                (descendant.ultimate_owner, descendant.ownership_depth) = resolution.columns();
+                descendant
+            })
+            .collect::<Vec<_>>();
+
+        // Sort by PK
+        let mut all_current_objects = all_current_objects
+            .into_values()
+            .collect::<Vec<CurrentObject>>();
+        all_current_objects.sort_by(|a, b| a.object_address.cmp(&b.object_address));
+        let mut edges = batch_edges.into_values().collect::<Vec<_>>();
+        edges.sort_by(|a, b| a.object_address.cmp(&b.object_address));
+        descendants.sort_by(|a, b| a.object_address.cmp(&b.object_address));
+        let derivations = AccountDerivation::merge_all(derivations);
+        let asset_stores =
+            resolve_asset_stores(&mut conn, asset_store_changes).map_err(commit_error)?;
+
+        let tx_result = insert_to_db(
+            &mut conn,
+            self.name(),
+            start_version,
+            end_version,
+            backfill_guard::policy(self.name(), start_version, end_version),
+            (all_objects, all_current_objects.clone()),
+            (edges, descendants.clone()),
+            (derivations, asset_stores.clone()),
+        );
+        match tx_result {
+            Ok(_) => {
+                if !asset_stores.is_empty() && self.publisher.publishes(ASSET_STORE_MODEL) {
+                    self.publisher
+                        .try_send_keyed(
+                            ASSET_STORE_MODEL,
+                            &asset_stores,
+                            CurrentAssetStore::topic_key,
+                        )
+                        .map_err(publish_error)?;
+                }
+                all_current_objects.extend(descendants);
+                if !all_current_objects.is_empty() {
+                    self.publisher
+                        .try_send("CurrentObject", &all_current_objects)
+                        .map_err(publish_error)?;
+                }
+                Ok(ProcessingResult::new(
+                    self.name(),
+                    start_version,
+                    end_version,
+                ))
+            },
+            Err(err) => Err(commit_error(err)),
+        }
+    }
+
+    fn connection_pool(&self) -> Option<&PgDbPool> {
+        Some(&self.connection_pool)
+    }
+}
@@ -0,0 +1,231 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    custom::driver::{
+        alerts::{self, Alert},
+        change_feed, column_stats,
+        publisher::Publisher,
+    },
+    database::{
+        clean_data_for_db, execute_with_better_error, get_chunks, write_transaction, PgDbPool,
+        PgPoolConnection,
+    },
+    indexer::{
+        errors::TransactionProcessingError, processing_result::ProcessingResult,
+        transaction_processor::TransactionProcessor,
+    },
+    models::{
+        change_feed::Operation,
+        onchain_config_changes::{OnchainConfigChange, OnchainConfigWrite},
+    },
+    schema,
+};
+use aptos_api_types::Transaction as APITransaction;
+use async_trait::async_trait;
+use diesel::{result::Error, PgConnection};
+use field_count::FieldCount;
+use serde_json::{json, Value};
+use std::{collections::HashMap, fmt::Debug};
+
+pub const NAME: &str = "custom_onchain_config_processor";
+pub struct COnchainConfigTransactionProcessor {
+    connection_pool: PgDbPool,
+    publisher: Publisher,
+}
+
+impl COnchainConfigTransactionProcessor {
+    pub fn new(connection_pool: PgDbPool, publisher: Publisher) -> Self {
+        Self {
+            connection_pool,
+            publisher,
+        }
+    }
+}
+
+impl Debug for COnchainConfigTransactionProcessor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = &self.connection_pool.state();
+        write!(
+            f,
+            "OnchainConfigTransactionProcessor {{ connections: {:?}  idle_connections: {:?}  \
+             max_size: {:?}  connection_timeout: {:?} }}",
+            state.connections,
+            state.idle_connections,
+            self.connection_pool.max_size(),
+            self.connection_pool.connection_timeout()
+        )
+    }
+}
+
+fn insert_to_db(
+    conn: &mut PgPoolConnection,
+    name: &'static str,
+    start_version: u64,
+    end_version: u64,
+    changes: Vec<OnchainConfigChange>,
+) -> Result<(), diesel::result::Error> {
+    aptos_logger::trace!(
+        name = name,
+        start_version = start_version,
+        end_version = end_version,
+        "Inserting to db",
+    );
+    column_stats::observe("onchain_config_changes", &changes);
+    match write_transaction::<_, Error, _>(conn, |pg_conn| {
+        insert_onchain_config_changes(pg_conn, &changes)
+    }) {
+        Ok(_) => Ok(()),
+        Err(_) => write_transaction::<_, Error, _>(conn, |pg_conn| {
+            let changes = clean_data_for_db(changes, true);
+            insert_onchain_config_changes(pg_conn, &changes)
+        }),
+    }
+}
+
+fn insert_onchain_config_changes(
+    conn: &mut PgConnection,
+    item_to_insert: &[OnchainConfigChange],
+) -> Result<(), diesel::result::Error> {
+    use schema::onchain_config_changes::dsl::*;
+
+    let chunks = get_chunks(item_to_insert.len(), OnchainConfigChange::field_count());
+    for (start_ind, end_ind) in chunks {
+        execute_with_better_error(
+            conn,
+            diesel::insert_into(schema::onchain_config_changes::table)
+                .values(&item_to_insert[start_ind..end_ind])
+                .on_conflict((transaction_version, config_type))
+                .do_nothing(),
+            None,
+        )?;
+    }
+    change_feed::record(
+        conn,
+        "onchain_config_changes",
+        &["transaction_version", "config_type"],
+        Operation::Insert,
+        item_to_insert,
+    )?;
+    Ok(())
+}
+
+fn alert(change: &OnchainConfigChange) -> Alert {
+    let count = |section: &str| {
+        change.diff[section]
+            .as_object()
+            .map(|paths| paths.len())
+            .unwrap_or_default()
+    };
+    Alert {
+        kind: "onchain_config_change",
+        summary: format!(
+            "{} changed at version {}: {} added, {} removed, {} changed",
+            change.config_type,
+            change.transaction_version,
+            count("added"),
+            count("removed"),
+            count("changed"),
+        ),
+        details: json!({
+            "config_type": change.config_type,
+            "transaction_version": change.transaction_version,
+            "diff": change.diff,
+        }),
+    }
+}
+
+#[async_trait]
+impl TransactionProcessor for COnchainConfigTransactionProcessor {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn process_transactions(
+        &self,
+        transactions: Vec<APITransaction>,
+        start_version: u64,
+        end_version: u64,
+    ) -> Result<ProcessingResult, TransactionProcessingError> {
+        let mut conn = self.get_conn().map_err(|err| {
+            TransactionProcessingError::ConnectionPoolError((
+                err,
+                start_version,
+                end_version,
+                self.name(),
+            ))
+        })?;
+
+        // Value of each config type as of the transaction being looked at
+        let mut latest: HashMap<&'static str, Option<Value>> = HashMap::new();
+        let mut all_changes = vec![];
+        for txn in &transactions {
+            for write in OnchainConfigWrite::from_transaction(txn) {
+                if !latest.contains_key(write.config_type) {
+                    let previous = OnchainConfigChange::latest_value(
+                        &mut conn,
+                        write.config_type,
+                        write.transaction_version,
+                    )
+                    .map_err(|err| {
+                        TransactionProcessingError::TransactionCommitError((
+                            anyhow::Error::from(err),
+                            start_version,
+                            end_version,
+                            self.name(),
+                        ))
+                    })?;
+                    latest.insert(write.config_type, previous);
+                }
+                let config_type = write.config_type;
+                let value = write.value.clone();
+                let previous = latest.get(config_type).and_then(|value| value.as_ref());
+                if let Some(change) = OnchainConfigChange::from_write(write, previous) {
+                    all_changes.push(change);
+                    latest.insert(config_type, Some(value));
+                }
+            }
+        }
+        // Sort by PK
+        all_changes.sort_by(|a, b| {
+            (a.transaction_version, &a.config_type).cmp(&(b.transaction_version, &b.config_type))
+        });
+
+        let tx_result = insert_to_db(
+            &mut conn,
+            self.name(),
+            start_version,
+            end_version,
+            all_changes.clone(),
+        );
+        // A change that can't be published fails the batch before it's alerted on. Changes are
+        // found against the values before the batch, so processing it again finds them again.
+        tx_result
+            .map_err(anyhow::Error::from)
+            .and_then(|_| {
+                if !all_changes.is_empty() {
+                    self.publisher
+                        .try_send("OnchainConfigChange", &all_changes)?;
+                }
+                Ok(())
+            })
+            .map(|_| {
+                for change in &all_changes {
+                    alerts::fire(alert(change));
+                }
+                ProcessingResult::new(self.name(), start_version, end_version)
+            })
+            .map_err(|err| {
+                TransactionProcessingError::TransactionCommitError((
+                    err,
+                    start_version,
+                    end_version,
+                    self.name(),
+                ))
+            })
+    }
+
+    fn connection_pool(&self) -> Option<&PgDbPool> {
+        Some(&self.connection_pool)
+    }
+}
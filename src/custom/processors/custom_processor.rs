@@ -8,7 +8,8 @@ use aptos_api_types::Transaction;
 
 use crate::{
     database::{
-        clean_data_for_db, execute_with_better_error, get_chunks, PgDbPool, PgPoolConnection,
+        clean_data_for_db, execute_with_better_error, get_chunks, write_transaction, PgDbPool,
+        PgPoolConnection,
     },
     indexer::{
         errors::TransactionProcessingError, processing_result::ProcessingResult,
@@ -50,8 +51,12 @@ impl Debug for CustomTransactionProcessor {
         let state = &self.connection_pool.state();
         write!(
             f,
-            "DefaultTransactionProcessor {{ connections: {:?}  idle_connections: {:?} }}",
-            state.connections, state.idle_connections
+            "DefaultTransactionProcessor {{ connections: {:?}  idle_connections: {:?}  \
+             max_size: {:?}  connection_timeout: {:?} }}",
+            state.connections,
+            state.idle_connections,
+            self.connection_pool.max_size(),
+            self.connection_pool.connection_timeout()
         )
     }
 }
@@ -83,6 +88,7 @@ impl TransactionProcessor for CustomTransactionProcessor {
                 TransactionDetail::BlockMetadata(bmt) => {
                     block_metadata_transactions.push(bmt.clone())
                 }
+                TransactionDetail::Genesis => {}
             }
         }
         let mut move_modules = vec![];
@@ -94,7 +100,7 @@ impl TransactionProcessor for CustomTransactionProcessor {
             match detail {
                 WriteSetChangeDetail::Module(module) => move_modules.push(module.clone()),
                 WriteSetChangeDetail::Resource(resource) => move_resources.push(resource.clone()),
-                WriteSetChangeDetail::Table(item, current_item, metadata) => {
+                WriteSetChangeDetail::Table(item, current_item, metadata, _) => {
                     table_items.push(item.clone());
                     current_table_items.insert(
                         (
@@ -119,7 +125,14 @@ impl TransactionProcessor for CustomTransactionProcessor {
             .sort_by(|a, b| (&a.table_handle, &a.key_hash).cmp(&(&b.table_handle, &b.key_hash)));
         table_metadata.sort_by(|a, b| a.handle.cmp(&b.handle));
 
-        let mut conn = self.get_conn();
+        let mut conn = self.get_conn().map_err(|err| {
+            TransactionProcessingError::ConnectionPoolError((
+                err,
+                start_version,
+                end_version,
+                self.name(),
+            ))
+        })?;
         let tx_result = insert_to_db(
             &self.publisher,
             &mut conn,
@@ -153,8 +166,8 @@ impl TransactionProcessor for CustomTransactionProcessor {
         }
     }
 
-    fn connection_pool(&self) -> &PgDbPool {
-        &self.connection_pool
+    fn connection_pool(&self) -> Option<&PgDbPool> {
+        Some(&self.connection_pool)
     }
 }
 
@@ -189,30 +202,27 @@ fn insert_to_db(
     let (user_transactions, signatures, block_metadata_transactions) = txn_details;
     let (move_modules, move_resources, table_items, current_table_items, table_metadata) =
         wsc_details;
-    match conn
-        .build_transaction()
-        .read_write()
-        .run::<_, Error, _>(|pg_conn| {
-            insert_to_db_impl(
-                publisher,
-                pg_conn,
-                &txns,
-                (
-                    &user_transactions,
-                    &signatures,
-                    &block_metadata_transactions,
-                ),
-                &events,
-                &wscs,
-                (
-                    &move_modules,
-                    &move_resources,
-                    &table_items,
-                    &current_table_items,
-                    &table_metadata,
-                ),
-            )
-        }) {
+    match write_transaction::<_, Error, _>(conn, |pg_conn| {
+        insert_to_db_impl(
+            publisher,
+            pg_conn,
+            &txns,
+            (
+                &user_transactions,
+                &signatures,
+                &block_metadata_transactions,
+            ),
+            &events,
+            &wscs,
+            (
+                &move_modules,
+                &move_resources,
+                &table_items,
+                &current_table_items,
+                &table_metadata,
+            ),
+        )
+    }) {
         Ok(_) => Ok(()),
         Err(_) => {
             let txns = clean_data_for_db(txns, true);
@@ -227,29 +237,27 @@ fn insert_to_db(
             let current_table_items = clean_data_for_db(current_table_items, true);
             let table_metadata = clean_data_for_db(table_metadata, true);
 
-            conn.build_transaction()
-                .read_write()
-                .run::<_, Error, _>(|pg_conn| {
-                    insert_to_db_impl(
-                        publisher,
-                        pg_conn,
-                        &txns,
-                        (
-                            &user_transactions,
-                            &signatures,
-                            &block_metadata_transactions,
-                        ),
-                        &events,
-                        &wscs,
-                        (
-                            &move_modules,
-                            &move_resources,
-                            &table_items,
-                            &current_table_items,
-                            &table_metadata,
-                        ),
-                    )
-                })
+            write_transaction::<_, Error, _>(conn, |pg_conn| {
+                insert_to_db_impl(
+                    publisher,
+                    pg_conn,
+                    &txns,
+                    (
+                        &user_transactions,
+                        &signatures,
+                        &block_metadata_transactions,
+                    ),
+                    &events,
+                    &wscs,
+                    (
+                        &move_modules,
+                        &move_resources,
+                        &table_items,
+                        &current_table_items,
+                        &table_metadata,
+                    ),
+                )
+            })
         }
     }
 }
@@ -2,21 +2,31 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
+    custom::driver::{
+        backfill_guard::{self, OverwritePolicy},
+        change_feed, column_stats,
+        publish_retry::PublishError,
+        publisher::Publisher,
+    },
     database::{
-        clean_data_for_db, execute_with_better_error, get_chunks, PgDbPool, PgPoolConnection,
+        clean_data_for_db, execute_with_better_error, get_chunks, write_transaction,
+        CurrentRowUpsert, GuardedUpsert, PgDbPool, PgPoolConnection,
     },
     indexer::{
         errors::TransactionProcessingError, processing_result::ProcessingResult,
         transaction_processor::TransactionProcessor,
     },
-    models::stake_models::{
-        delegator_activities::DelegatedStakingActivity,
-        delegator_balances::{CurrentDelegatorBalance, CurrentDelegatorBalanceMap},
-        delegator_pools::{
-            CurrentDelegatorPoolBalance, DelegatorPool, DelegatorPoolBalance, DelegatorPoolMap,
+    models::{
+        change_feed::Operation,
+        stake_models::{
+            delegator_activities::DelegatedStakingActivity,
+            delegator_balances::{CurrentDelegatorBalance, CurrentDelegatorBalanceMap},
+            delegator_pools::{
+                CurrentDelegatorPoolBalance, DelegatorPool, DelegatorPoolBalance, DelegatorPoolMap,
+            },
+            proposal_votes::ProposalVote,
+            staking_pool_voter::{CurrentStakingPoolVoter, StakingPoolVoterMap},
         },
-        proposal_votes::ProposalVote,
-        staking_pool_voter::{CurrentStakingPoolVoter, StakingPoolVoterMap},
     },
     schema,
 };
@@ -27,13 +37,59 @@ use field_count::FieldCount;
 use std::{collections::HashMap, fmt::Debug};
 
 pub const NAME: &str = "custom_stake_processor";
+/// Models of the published activities, voters and delegator balances, see
+/// `client::MODEL_TOPIC_KEYS`
+const ACTIVITY_MODEL: &str = "DelegatedStakingActivity";
+const VOTER_MODEL: &str = "CurrentStakingPoolVoter";
+const DELEGATOR_BALANCE_MODEL: &str = "CurrentDelegatorBalance";
+
 pub struct CStakeTransactionProcessor {
     connection_pool: PgDbPool,
+    publisher: Publisher,
 }
 
 impl CStakeTransactionProcessor {
-    pub fn new(connection_pool: PgDbPool) -> Self {
-        Self { connection_pool }
+    pub fn new(connection_pool: PgDbPool, publisher: Publisher) -> Self {
+        Self {
+            connection_pool,
+            publisher,
+        }
+    }
+
+    /// The rows of a committed batch, each model to its topic if one is configured. Current rows
+    /// are keyed by their primary key without salting, for compacted topics. Stops at the first
+    /// model that can't be published.
+    fn publish(
+        &self,
+        activities: &[DelegatedStakingActivity],
+        voters: &[CurrentStakingPoolVoter],
+        delegator_balances: &[CurrentDelegatorBalance],
+    ) -> Result<(), PublishError> {
+        if !activities.is_empty() && self.publisher.publishes(ACTIVITY_MODEL) {
+            self.publisher.try_send(ACTIVITY_MODEL, activities)?;
+        }
+        if !voters.is_empty() && self.publisher.publishes(VOTER_MODEL) {
+            self.publisher
+                .try_send_keyed(VOTER_MODEL, voters, |voter| {
+                    voter.staking_pool_address.clone()
+                })?;
+        }
+        if !delegator_balances.is_empty() && self.publisher.publishes(DELEGATOR_BALANCE_MODEL) {
+            self.publisher.try_send_keyed(
+                DELEGATOR_BALANCE_MODEL,
+                delegator_balances,
+                |balance| {
+                    format!(
+                        "{}:{}:{}:{}",
+                        balance.delegator_address,
+                        balance.pool_address,
+                        balance.pool_type,
+                        balance.table_handle
+                    )
+                },
+            )?;
+        }
+        Ok(())
     }
 }
 
@@ -42,14 +98,19 @@ impl Debug for CStakeTransactionProcessor {
         let state = &self.connection_pool.state();
         write!(
             f,
-            "StakeTransactionProcessor {{ connections: {:?}  idle_connections: {:?} }}",
-            state.connections, state.idle_connections
+            "StakeTransactionProcessor {{ connections: {:?}  idle_connections: {:?}  \
+             max_size: {:?}  connection_timeout: {:?} }}",
+            state.connections,
+            state.idle_connections,
+            self.connection_pool.max_size(),
+            self.connection_pool.connection_timeout()
         )
     }
 }
 
 fn insert_to_db_impl(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     current_stake_pool_voters: &[CurrentStakingPoolVoter],
     proposal_votes: &[ProposalVote],
     delegator_actvities: &[DelegatedStakingActivity],
@@ -58,13 +119,68 @@ fn insert_to_db_impl(
     delegator_pool_balances: &[DelegatorPoolBalance],
     current_delegator_pool_balances: &[CurrentDelegatorPoolBalance],
 ) -> Result<(), diesel::result::Error> {
-    insert_current_stake_pool_voter(conn, current_stake_pool_voters)?;
+    insert_current_stake_pool_voter(conn, policy, current_stake_pool_voters)?;
     insert_proposal_votes(conn, proposal_votes)?;
     insert_delegator_activities(conn, delegator_actvities)?;
-    insert_delegator_balances(conn, delegator_balances)?;
-    insert_delegator_pools(conn, delegator_pools)?;
+    insert_delegator_balances(conn, policy, delegator_balances)?;
+    insert_delegator_pools(conn, policy, delegator_pools)?;
     insert_delegator_pool_balances(conn, delegator_pool_balances)?;
-    insert_current_delegator_pool_balances(conn, current_delegator_pool_balances)?;
+    insert_current_delegator_pool_balances(conn, policy, current_delegator_pool_balances)?;
+
+    change_feed::record(
+        conn,
+        "current_staking_pool_voter",
+        &["staking_pool_address"],
+        Operation::Upsert,
+        current_stake_pool_voters,
+    )?;
+    change_feed::record(
+        conn,
+        "proposal_votes",
+        &["transaction_version", "proposal_id", "voter_address"],
+        Operation::Insert,
+        proposal_votes,
+    )?;
+    change_feed::record(
+        conn,
+        "delegated_staking_activities",
+        &["transaction_version", "event_index"],
+        Operation::Insert,
+        delegator_actvities,
+    )?;
+    change_feed::record(
+        conn,
+        "current_delegator_balances",
+        &[
+            "delegator_address",
+            "pool_address",
+            "pool_type",
+            "table_handle",
+        ],
+        Operation::Upsert,
+        delegator_balances,
+    )?;
+    change_feed::record(
+        conn,
+        "delegated_staking_pools",
+        &["staking_pool_address"],
+        Operation::Upsert,
+        delegator_pools,
+    )?;
+    change_feed::record(
+        conn,
+        "delegated_staking_pool_balances",
+        &["transaction_version", "staking_pool_address"],
+        Operation::Insert,
+        delegator_pool_balances,
+    )?;
+    change_feed::record(
+        conn,
+        "current_delegated_staking_pool_balances",
+        &["staking_pool_address"],
+        Operation::Upsert,
+        current_delegator_pool_balances,
+    )?;
     Ok(())
 }
 
@@ -73,6 +189,7 @@ fn insert_to_db(
     name: &'static str,
     start_version: u64,
     end_version: u64,
+    policy: OverwritePolicy,
     current_stake_pool_voters: Vec<CurrentStakingPoolVoter>,
     proposal_votes: Vec<ProposalVote>,
     delegator_actvities: Vec<DelegatedStakingActivity>,
@@ -87,12 +204,43 @@ fn insert_to_db(
         end_version = end_version,
         "Inserting to db",
     );
-    match conn
-        .build_transaction()
-        .read_write()
-        .run::<_, Error, _>(|pg_conn| {
+    column_stats::observe("current_staking_pool_voter", &current_stake_pool_voters);
+    column_stats::observe("proposal_votes", &proposal_votes);
+    column_stats::observe("delegated_staking_activities", &delegator_actvities);
+    column_stats::observe("current_delegator_balances", &delegator_balances);
+    column_stats::observe("delegated_staking_pools", &delegator_pools);
+    column_stats::observe("delegated_staking_pool_balances", &delegator_pool_balances);
+    column_stats::observe(
+        "current_delegated_staking_pool_balances",
+        &current_delegator_pool_balances,
+    );
+    match write_transaction::<_, Error, _>(conn, |pg_conn| {
+        insert_to_db_impl(
+            pg_conn,
+            policy,
+            &current_stake_pool_voters,
+            &proposal_votes,
+            &delegator_actvities,
+            &delegator_balances,
+            &delegator_pools,
+            &delegator_pool_balances,
+            &current_delegator_pool_balances,
+        )
+    }) {
+        Ok(_) => Ok(()),
+        Err(_) => write_transaction::<_, Error, _>(conn, |pg_conn| {
+            let current_stake_pool_voters = clean_data_for_db(current_stake_pool_voters, true);
+            let proposal_votes = clean_data_for_db(proposal_votes, true);
+            let delegator_actvities = clean_data_for_db(delegator_actvities, true);
+            let delegator_balances = clean_data_for_db(delegator_balances, true);
+            let delegator_pools = clean_data_for_db(delegator_pools, true);
+            let delegator_pool_balances = clean_data_for_db(delegator_pool_balances, true);
+            let current_delegator_pool_balances =
+                clean_data_for_db(current_delegator_pool_balances, true);
+
             insert_to_db_impl(
                 pg_conn,
+                policy,
                 &current_stake_pool_voters,
                 &proposal_votes,
                 &delegator_actvities,
@@ -101,44 +249,20 @@ fn insert_to_db(
                 &delegator_pool_balances,
                 &current_delegator_pool_balances,
             )
-        }) {
-        Ok(_) => Ok(()),
-        Err(_) => conn
-            .build_transaction()
-            .read_write()
-            .run::<_, Error, _>(|pg_conn| {
-                let current_stake_pool_voters = clean_data_for_db(current_stake_pool_voters, true);
-                let proposal_votes = clean_data_for_db(proposal_votes, true);
-                let delegator_actvities = clean_data_for_db(delegator_actvities, true);
-                let delegator_balances = clean_data_for_db(delegator_balances, true);
-                let delegator_pools = clean_data_for_db(delegator_pools, true);
-                let delegator_pool_balances = clean_data_for_db(delegator_pool_balances, true);
-                let current_delegator_pool_balances =
-                    clean_data_for_db(current_delegator_pool_balances, true);
-
-                insert_to_db_impl(
-                    pg_conn,
-                    &current_stake_pool_voters,
-                    &proposal_votes,
-                    &delegator_actvities,
-                    &delegator_balances,
-                    &delegator_pools,
-                    &delegator_pool_balances,
-                    &current_delegator_pool_balances,
-                )
-            }),
+        }),
     }
 }
 
 fn insert_current_stake_pool_voter(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     item_to_insert: &[CurrentStakingPoolVoter],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_staking_pool_voter::dsl::*;
 
     let chunks = get_chunks(item_to_insert.len(), CurrentStakingPoolVoter::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_staking_pool_voter", policy).execute(
             conn,
             diesel::insert_into(schema::current_staking_pool_voter::table)
                 .values(&item_to_insert[start_ind..end_ind])
@@ -151,9 +275,7 @@ fn insert_current_stake_pool_voter(
                     inserted_at.eq(excluded(inserted_at)),
                     operator_address.eq(excluded(operator_address)),
                 )),
-            Some(
-                " WHERE current_staking_pool_voter.last_transaction_version <= EXCLUDED.last_transaction_version ",
-            ),
+            &item_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -204,13 +326,14 @@ fn insert_delegator_activities(
 
 fn insert_delegator_balances(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     item_to_insert: &[CurrentDelegatorBalance],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_delegator_balances::dsl::*;
 
     let chunks = get_chunks(item_to_insert.len(), CurrentDelegatorBalance::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_delegator_balances", policy).execute(
             conn,
             diesel::insert_into(schema::current_delegator_balances::table)
                 .values(&item_to_insert[start_ind..end_ind])
@@ -222,9 +345,7 @@ fn insert_delegator_balances(
                     shares.eq(excluded(shares)),
                     parent_table_handle.eq(excluded(parent_table_handle)),
                 )),
-            Some(
-                " WHERE current_delegator_balances.last_transaction_version <= EXCLUDED.last_transaction_version ",
-            ),
+            &item_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -232,26 +353,32 @@ fn insert_delegator_balances(
 
 fn insert_delegator_pools(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     item_to_insert: &[DelegatorPool],
 ) -> Result<(), diesel::result::Error> {
     use schema::delegated_staking_pools::dsl::*;
 
     let chunks = get_chunks(item_to_insert.len(), DelegatorPool::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
-            conn,
-            diesel::insert_into(schema::delegated_staking_pools::table)
-                .values(&item_to_insert[start_ind..end_ind])
-                .on_conflict(staking_pool_address)
-                .do_update()
-                .set((
-                    first_transaction_version.eq(excluded(first_transaction_version)),
-                    inserted_at.eq(excluded(inserted_at)),
-                )),
-            Some(
-                " WHERE delegated_staking_pools.first_transaction_version >= EXCLUDED.first_transaction_version ",
-            ),
-        )?;
+        GuardedUpsert::new("delegated_staking_pools", policy)
+            .skip_unless(
+                " WHERE delegated_staking_pools.first_transaction_version > EXCLUDED.first_transaction_version ",
+            )
+            .execute(
+                conn,
+                diesel::insert_into(schema::delegated_staking_pools::table)
+                    .values(&item_to_insert[start_ind..end_ind])
+                    .on_conflict(staking_pool_address)
+                    .do_update()
+                    .set((
+                        first_transaction_version.eq(excluded(first_transaction_version)),
+                        inserted_at.eq(excluded(inserted_at)),
+                    )),
+                Some(
+                    " WHERE delegated_staking_pools.first_transaction_version >= EXCLUDED.first_transaction_version ",
+                ),
+                &item_to_insert[start_ind..end_ind],
+            )?;
     }
     Ok(())
 }
@@ -278,6 +405,7 @@ fn insert_delegator_pool_balances(
 
 fn insert_current_delegator_pool_balances(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     item_to_insert: &[CurrentDelegatorPoolBalance],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_delegated_staking_pool_balances::dsl::*;
@@ -287,7 +415,7 @@ fn insert_current_delegator_pool_balances(
         CurrentDelegatorPoolBalance::field_count(),
     );
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_delegated_staking_pool_balances", policy).execute(
             conn,
             diesel::insert_into(schema::current_delegated_staking_pool_balances::table)
                 .values(&item_to_insert[start_ind..end_ind])
@@ -302,9 +430,7 @@ fn insert_current_delegator_pool_balances(
                     inactive_table_handle.eq(excluded(inactive_table_handle)),
                     active_table_handle.eq(excluded(active_table_handle)),
                 )),
-            Some(
-                " WHERE current_delegated_staking_pool_balances.last_transaction_version <= EXCLUDED.last_transaction_version ",
-            ),
+            &item_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -322,7 +448,14 @@ impl TransactionProcessor for CStakeTransactionProcessor {
         start_version: u64,
         end_version: u64,
     ) -> Result<ProcessingResult, TransactionProcessingError> {
-        let mut conn = self.get_conn();
+        let mut conn = self.get_conn().map_err(|err| {
+            TransactionProcessingError::ConnectionPoolError((
+                err,
+                start_version,
+                end_version,
+                self.name(),
+            ))
+        })?;
 
         let mut all_current_stake_pool_voters: StakingPoolVoterMap = HashMap::new();
         let mut all_proposal_votes = vec![];
@@ -345,7 +478,8 @@ impl TransactionProcessor for CStakeTransactionProcessor {
 
             // Add delegator balances
             let delegator_balances =
-                CurrentDelegatorBalance::from_transaction(txn, &mut conn).unwrap();
+                CurrentDelegatorBalance::from_transaction(txn, &all_delegator_balances, &mut conn)
+                    .unwrap();
             all_delegator_balances.extend(delegator_balances);
 
             // Add delegator pools
@@ -374,11 +508,18 @@ impl TransactionProcessor for CStakeTransactionProcessor {
         all_current_stake_pool_voters
             .sort_by(|a, b| a.staking_pool_address.cmp(&b.staking_pool_address));
         all_delegator_balances.sort_by(|a, b| {
-            (&a.delegator_address, &a.pool_address, &a.pool_type).cmp(&(
-                &b.delegator_address,
-                &b.pool_address,
-                &b.pool_type,
-            ))
+            (
+                &a.delegator_address,
+                &a.pool_address,
+                &a.pool_type,
+                &a.table_handle,
+            )
+                .cmp(&(
+                    &b.delegator_address,
+                    &b.pool_address,
+                    &b.pool_type,
+                    &b.table_handle,
+                ))
         });
         all_delegator_pools.sort_by(|a, b| a.staking_pool_address.cmp(&b.staking_pool_address));
         all_current_delegator_pool_balances
@@ -389,30 +530,186 @@ impl TransactionProcessor for CStakeTransactionProcessor {
             self.name(),
             start_version,
             end_version,
-            all_current_stake_pool_voters,
+            backfill_guard::policy(self.name(), start_version, end_version),
+            all_current_stake_pool_voters.clone(),
             all_proposal_votes,
-            all_delegator_activities,
-            all_delegator_balances,
+            all_delegator_activities.clone(),
+            all_delegator_balances.clone(),
             all_delegator_pools,
             all_delegator_pool_balances,
             all_current_delegator_pool_balances,
         );
-        match tx_result {
-            Ok(_) => Ok(ProcessingResult::new(
-                self.name(),
-                start_version,
-                end_version,
-            )),
-            Err(err) => Err(TransactionProcessingError::TransactionCommitError((
-                anyhow::Error::from(err),
-                start_version,
-                end_version,
-                self.name(),
-            ))),
+        // Published once the rows are written, a failure has the batch processed again
+        tx_result
+            .map_err(anyhow::Error::from)
+            .and_then(|_| {
+                self.publish(
+                    &all_delegator_activities,
+                    &all_current_stake_pool_voters,
+                    &all_delegator_balances,
+                )
+                .map_err(anyhow::Error::from)
+            })
+            .map(|_| ProcessingResult::new(self.name(), start_version, end_version))
+            .map_err(|err| {
+                TransactionProcessingError::TransactionCommitError((
+                    err,
+                    start_version,
+                    end_version,
+                    self.name(),
+                ))
+            })
+    }
+
+    fn connection_pool(&self) -> Option<&PgDbPool> {
+        Some(&self.connection_pool)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::decode_model,
+        custom::{
+            driver::{config::PublishRetryConfig, replay},
+            test_utils,
+        },
+    };
+    use bigdecimal::BigDecimal;
+    use rdkafka::types::RDKafkaErrorCode;
+    use serde_json::json;
+
+    const POOL: &str = "0x00000000000000000000000000000000000000000000000000000000de1e6a7e";
+    const DELEGATOR: &str = "0x00000000000000000000000000000000000000000000000000000000000000d1";
+    const VERSION: i64 = 5_200_000_001;
+
+    fn apt(amount: u64) -> BigDecimal {
+        BigDecimal::from(amount * 100_000_000)
+    }
+
+    #[tokio::test]
+    async fn test_delegation_flow() {
+        let Some(conn_pool) = test_utils::test_pool() else {
+            return;
+        };
+        // In one batch, and a batch per transaction so that the withdrawn share is found in
+        // Postgres
+        for batch_size in [usize::MAX, 1] {
+            let replay = test_utils::run_processor(
+                NAME,
+                conn_pool.clone(),
+                test_utils::fixture("delegation_flow.json"),
+                batch_size,
+            )
+            .await;
+            let decoded = |topic_key: &str| {
+                replay
+                    .messages
+                    .on_topic(topic_key)
+                    .iter()
+                    .map(|message| message.payload.clone())
+                    .collect::<Vec<_>>()
+            };
+
+            let activities = decoded("staking_activity_topic")
+                .iter()
+                .map(|payload| decode_model::<DelegatedStakingActivity>(payload).unwrap())
+                .map(|activity| {
+                    let name = activity.event_type.rsplit("::").next().unwrap().to_string();
+                    (activity.transaction_version, name, activity.amount)
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(activities, [
+                (VERSION, "AddStakeEvent".to_string(), apt(100)),
+                (VERSION + 1, "UnlockStakeEvent".to_string(), apt(40)),
+                (VERSION + 2, "WithdrawStakeEvent".to_string(), apt(40)),
+                (VERSION + 2, "UnlockStakeEvent".to_string(), apt(10)),
+            ]);
+
+            // The latest balance of every share, from the batches' last messages
+            let mut balances = HashMap::new();
+            for payload in decoded("delegator_balance_topic") {
+                let balance = decode_model::<CurrentDelegatorBalance>(&payload).unwrap();
+                assert_eq!(
+                    (
+                        balance.delegator_address.as_str(),
+                        balance.pool_address.as_str()
+                    ),
+                    (DELEGATOR, POOL)
+                );
+                balances.insert(balance.table_handle.clone(), balance);
+            }
+            let mut balances = balances
+                .into_values()
+                .map(|balance| {
+                    (
+                        balance.pool_type,
+                        balance.shares,
+                        balance.last_transaction_version,
+                    )
+                })
+                .collect::<Vec<_>>();
+            balances.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+            // Cycle 1's 40 withdrawn, cycle 2's 10 pending inactive
+            assert_eq!(balances, [
+                ("active_shares".to_string(), apt(50), VERSION + 2),
+                (
+                    "inactive_shares".to_string(),
+                    BigDecimal::from(0),
+                    VERSION + 2
+                ),
+                ("inactive_shares".to_string(), apt(10), VERSION + 2),
+            ]);
+
+            let voters = decoded("staking_pool_voter_topic");
+            let voter = decode_model::<CurrentStakingPoolVoter>(voters.last().unwrap()).unwrap();
+            assert_eq!(voter.staking_pool_address, POOL);
+            assert_eq!(voter.last_transaction_version, VERSION + 2);
         }
     }
 
-    fn connection_pool(&self) -> &PgDbPool {
-        &self.connection_pool
+    #[tokio::test]
+    async fn test_publish_failures() {
+        let Some(conn_pool) = test_utils::test_pool() else {
+            return;
+        };
+        let mut driver_config = test_utils::driver_config(json!({}));
+        driver_config.publish_retry = PublishRetryConfig {
+            max_attempts: 2,
+            base_delay_millis: 1,
+            max_delay_millis: 1,
+        };
+        let (processor, messages) = replay::in_memory_processor(
+            NAME,
+            Some(conn_pool),
+            &driver_config,
+            test_utils::CHAIN_ID,
+        )
+        .unwrap();
+        let transactions = test_utils::fixture("delegation_flow.json");
+        let (start_version, end_version) = (VERSION as u64, VERSION as u64 + 2);
+
+        // A broker restart outlasting the retries fails the batch, retryable, after its rows are
+        // written
+        messages.fail_next(2, RDKafkaErrorCode::AllBrokersDown);
+        let error = processor
+            .process_versions_with_status(transactions.clone(), start_version, end_version)
+            .await
+            .unwrap_err();
+        assert!(error.is_retryable());
+        assert!(messages.is_empty());
+
+        // Processed again once Kafka is back
+        let result = processor
+            .process_versions_with_status(transactions, start_version, end_version)
+            .await
+            .unwrap();
+        assert_eq!(
+            (result.start_version, result.end_version),
+            (start_version, end_version)
+        );
+        assert_eq!(messages.on_topic("staking_activity_topic").len(), 4);
+        assert!(!messages.on_topic("delegator_balance_topic").is_empty());
     }
 }
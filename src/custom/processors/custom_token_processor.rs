@@ -3,7 +3,8 @@
 
 use crate::{
     database::{
-        clean_data_for_db, execute_with_better_error, get_chunks, PgDbPool, PgPoolConnection,
+        clean_data_for_db, execute_with_better_error, get_chunks, write_transaction,
+        CurrentRowUpsert, GuardedUpsert, PgDbPool, PgPoolConnection,
     },
     indexer::{
         errors::TransactionProcessingError, processing_result::ProcessingResult,
@@ -48,13 +49,20 @@ use crate::{
 };
 use aptos_api_types::{Transaction, TransactionPayload, WriteSetChange};
 use async_trait::async_trait;
-use diesel::{pg::upsert::excluded, result::Error, ExpressionMethods, PgConnection};
+use diesel::{pg::upsert::excluded, ExpressionMethods, PgConnection};
 use field_count::FieldCount;
+use serde::Serialize;
 use std::{
     collections::{HashMap, HashSet},
     fmt::Debug,
 };
-use crate::custom::driver::publisher::Publisher;
+use crate::custom::driver::{
+    backfill_guard::{self, OverwritePolicy},
+    column_stats,
+    ordering::Ordered,
+    publish_retry::PublishError,
+    publisher::Publisher,
+};
 
 pub const NAME: &str = "custom_token_processor";
 
@@ -90,8 +98,12 @@ impl Debug for CTokenTransactionProcessor {
         let state = &self.connection_pool.state();
         write!(
             f,
-            "TokenTransactionProcessor {{ connections: {:?}  idle_connections: {:?} }}",
-            state.connections, state.idle_connections
+            "TokenTransactionProcessor {{ connections: {:?}  idle_connections: {:?}  \
+             max_size: {:?}  connection_timeout: {:?} }}",
+            state.connections,
+            state.idle_connections,
+            self.connection_pool.max_size(),
+            self.connection_pool.connection_timeout()
         )
     }
 }
@@ -99,6 +111,7 @@ impl Debug for CTokenTransactionProcessor {
 fn insert_to_db_impl(
     publisher: &Publisher,
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     basic_token_transaction_lists: (&[Token], &[TokenOwnership], &[TokenData], &[CollectionData]),
     basic_token_current_lists: (
         &[CurrentTokenOwnership],
@@ -128,35 +141,37 @@ fn insert_to_db_impl(
         &[TokenActivityV2],
         &[CurrentTokenV2Metadata],
     ),
-) -> Result<(), diesel::result::Error> {
+) -> anyhow::Result<()> {
     let (tokens, token_ownerships, token_datas, collection_datas) = basic_token_transaction_lists;
     let (current_token_ownerships, current_token_datas, current_collection_datas) =
         basic_token_current_lists;
-    // insert_tokens(conn, tokens)?;
-    // insert_token_datas(conn, token_datas)?;
-    // insert_token_ownerships(conn, token_ownerships)?;
+    // Token v1 models go to the topics configured for them
+    insert_tokens(publisher, tokens)?;
+    insert_token_datas(publisher, token_datas)?;
+    insert_token_ownerships(publisher, token_ownerships)?;
     // insert_collection_datas(conn, collection_datas)?;
-    // insert_current_token_ownerships(conn, current_token_ownerships)?;
-    // insert_current_token_datas(conn, current_token_datas)?;
-    // insert_current_collection_datas(conn, current_collection_datas)?;
-    // insert_token_activities(conn, token_activities)?;
-    // insert_current_token_claims(conn, current_token_claims)?;
-    // insert_current_ans_lookups(conn, current_ans_lookups)?;
+    insert_current_token_ownerships(publisher, current_token_ownerships)?;
+    insert_current_token_datas(publisher, current_token_datas)?;
+    insert_current_collection_datas(publisher, conn, policy, current_collection_datas)?;
+    insert_token_activities(publisher, token_activities)?;
+    // insert_current_token_claims(conn, policy, current_token_claims)?;
+    // insert_current_ans_lookups(conn, policy, current_ans_lookups)?;
     // insert_nft_points(conn, nft_points)?;
     // insert_collections_v2(conn, collections_v2)?;
-    // insert_token_datas_v2(conn, token_datas_v2)?;
-    // insert_token_ownerships_v2(conn, token_ownerships_v2)?;
-    // insert_current_collections_v2(conn, current_collections_v2)?;
-    // insert_current_token_datas_v2(conn, current_token_datas_v2)?;
-    // insert_current_token_ownerships_v2(conn, current_token_ownerships_v2)?;
+    // insert_token_datas_v2(conn, policy, token_datas_v2)?;
+    // insert_token_ownerships_v2(conn, policy, token_ownerships_v2)?;
+    // insert_current_collections_v2(conn, policy, current_collections_v2)?;
+    // insert_current_token_datas_v2(conn, policy, current_token_datas_v2)?;
+    // insert_current_token_ownerships_v2(conn, policy, current_token_ownerships_v2)?;
     // insert_token_activities_v2(conn, token_activities_v2)?;
-    // insert_current_token_v2_metadatas(conn, current_token_v2_metadata)?;
+    // insert_current_token_v2_metadatas(conn, policy, current_token_v2_metadata)?;
     Ok(())
 }
 
 fn insert_to_db(
     publisher: &Publisher,
     conn: &mut PgPoolConnection,
+    policy: OverwritePolicy,
     name: &'static str,
     start_version: u64,
     end_version: u64,
@@ -194,7 +209,7 @@ fn insert_to_db(
         Vec<TokenActivityV2>,
         Vec<CurrentTokenV2Metadata>,
     ),
-) -> Result<(), diesel::result::Error> {
+) -> anyhow::Result<()> {
     aptos_logger::trace!(
         name = name,
         start_version = start_version,
@@ -204,13 +219,81 @@ fn insert_to_db(
     let (tokens, token_ownerships, token_datas, collection_datas) = basic_token_transaction_lists;
     let (current_token_ownerships, current_token_datas, current_collection_datas) =
         basic_token_current_lists;
-    match conn
-        .build_transaction()
-        .read_write()
-        .run::<_, Error, _>(|pg_conn| {
+    column_stats::observe("tokens", &tokens);
+    column_stats::observe("token_ownerships", &token_ownerships);
+    column_stats::observe("token_datas", &token_datas);
+    column_stats::observe("collection_datas", &collection_datas);
+    column_stats::observe("current_token_ownerships", &current_token_ownerships);
+    column_stats::observe("current_token_datas", &current_token_datas);
+    column_stats::observe("current_collection_datas", &current_collection_datas);
+    column_stats::observe("token_activities", &token_activities);
+    column_stats::observe("current_token_pending_claims", &current_token_claims);
+    column_stats::observe("current_ans_lookup", &current_ans_lookups);
+    column_stats::observe("nft_points", &nft_points);
+    column_stats::observe("collections_v2", &collections_v2);
+    column_stats::observe("token_datas_v2", &token_datas_v2);
+    column_stats::observe("token_ownerships_v2", &token_ownerships_v2);
+    column_stats::observe("current_collections_v2", &current_collections_v2);
+    column_stats::observe("current_token_datas_v2", &current_token_datas_v2);
+    column_stats::observe("current_token_ownerships_v2", &current_token_ownerships_v2);
+    column_stats::observe("token_activities_v2", &token_activities_v2);
+    column_stats::observe("current_token_v2_metadata", &current_token_v2_metadata);
+    match write_transaction::<_, anyhow::Error, _>(conn, |pg_conn| {
+        insert_to_db_impl(
+            publisher,
+            pg_conn,
+            policy,
+            (&tokens, &token_ownerships, &token_datas, &collection_datas),
+            (
+                &current_token_ownerships,
+                &current_token_datas,
+                &current_collection_datas,
+            ),
+            &token_activities,
+            &current_token_claims,
+            &current_ans_lookups,
+            &nft_points,
+            (
+                &collections_v2,
+                &token_datas_v2,
+                &token_ownerships_v2,
+                &current_collections_v2,
+                &current_token_datas_v2,
+                &current_token_ownerships_v2,
+                &token_activities_v2,
+                &current_token_v2_metadata,
+            ),
+        )
+    }) {
+        Ok(_) => Ok(()),
+        // Cleaning the rows doesn't help a message that couldn't be published
+        Err(err) if err.is::<PublishError>() => Err(err),
+        Err(_) => write_transaction::<_, anyhow::Error, _>(conn, |pg_conn| {
+            let tokens = clean_data_for_db(tokens, true);
+            let token_datas = clean_data_for_db(token_datas, true);
+            let token_ownerships = clean_data_for_db(token_ownerships, true);
+            let collection_datas = clean_data_for_db(collection_datas, true);
+            let current_token_ownerships = clean_data_for_db(current_token_ownerships, true);
+            let current_token_datas = clean_data_for_db(current_token_datas, true);
+            let current_collection_datas = clean_data_for_db(current_collection_datas, true);
+            let token_activities = clean_data_for_db(token_activities, true);
+            let current_token_claims = clean_data_for_db(current_token_claims, true);
+            let current_ans_lookups = clean_data_for_db(current_ans_lookups, true);
+            let nft_points = clean_data_for_db(nft_points, true);
+            let collections_v2 = clean_data_for_db(collections_v2, true);
+            let token_datas_v2 = clean_data_for_db(token_datas_v2, true);
+            let token_ownerships_v2 = clean_data_for_db(token_ownerships_v2, true);
+            let current_collections_v2 = clean_data_for_db(current_collections_v2, true);
+            let current_token_datas_v2 = clean_data_for_db(current_token_datas_v2, true);
+            let current_token_ownerships_v2 =
+                clean_data_for_db(current_token_ownerships_v2, true);
+            let token_activities_v2 = clean_data_for_db(token_activities_v2, true);
+            let current_token_v2_metadata = clean_data_for_db(current_token_v2_metadata, true);
+
             insert_to_db_impl(
                 publisher,
                 pg_conn,
+                policy,
                 (&tokens, &token_ownerships, &token_datas, &collection_datas),
                 (
                     &current_token_ownerships,
@@ -232,103 +315,39 @@ fn insert_to_db(
                     &current_token_v2_metadata,
                 ),
             )
-        }) {
-        Ok(_) => Ok(()),
-        Err(_) => conn
-            .build_transaction()
-            .read_write()
-            .run::<_, Error, _>(|pg_conn| {
-                let tokens = clean_data_for_db(tokens, true);
-                let token_datas = clean_data_for_db(token_datas, true);
-                let token_ownerships = clean_data_for_db(token_ownerships, true);
-                let collection_datas = clean_data_for_db(collection_datas, true);
-                let current_token_ownerships = clean_data_for_db(current_token_ownerships, true);
-                let current_token_datas = clean_data_for_db(current_token_datas, true);
-                let current_collection_datas = clean_data_for_db(current_collection_datas, true);
-                let token_activities = clean_data_for_db(token_activities, true);
-                let current_token_claims = clean_data_for_db(current_token_claims, true);
-                let current_ans_lookups = clean_data_for_db(current_ans_lookups, true);
-                let nft_points = clean_data_for_db(nft_points, true);
-                let collections_v2 = clean_data_for_db(collections_v2, true);
-                let token_datas_v2 = clean_data_for_db(token_datas_v2, true);
-                let token_ownerships_v2 = clean_data_for_db(token_ownerships_v2, true);
-                let current_collections_v2 = clean_data_for_db(current_collections_v2, true);
-                let current_token_datas_v2 = clean_data_for_db(current_token_datas_v2, true);
-                let current_token_ownerships_v2 =
-                    clean_data_for_db(current_token_ownerships_v2, true);
-                let token_activities_v2 = clean_data_for_db(token_activities_v2, true);
-                let current_token_v2_metadata = clean_data_for_db(current_token_v2_metadata, true);
-
-                insert_to_db_impl(
-                    publisher,
-                    pg_conn,
-                    (&tokens, &token_ownerships, &token_datas, &collection_datas),
-                    (
-                        &current_token_ownerships,
-                        &current_token_datas,
-                        &current_collection_datas,
-                    ),
-                    &token_activities,
-                    &current_token_claims,
-                    &current_ans_lookups,
-                    &nft_points,
-                    (
-                        &collections_v2,
-                        &token_datas_v2,
-                        &token_ownerships_v2,
-                        &current_collections_v2,
-                        &current_token_datas_v2,
-                        &current_token_ownerships_v2,
-                        &token_activities_v2,
-                        &current_token_v2_metadata,
-                    ),
-                )
-            }),
+        }),
     }
 }
 
-fn insert_tokens(
+/// Sends `items` to the topic of `model`, if one is configured, failing the batch if they can't
+/// be published
+fn publish_if_configured<T: Serialize + Sync + Ordered>(
     publisher: &Publisher,
-    tokens_to_insert: &[Token],
-) -> Result<(), diesel::result::Error> {
-    publisher.send("Token", tokens_to_insert);
+    model: &str,
+    items: &[T],
+) -> Result<(), PublishError> {
+    if publisher.publishes(model) {
+        publisher.try_send(model, items)?;
+    }
     Ok(())
 }
 
+fn insert_tokens(publisher: &Publisher, tokens_to_insert: &[Token]) -> Result<(), PublishError> {
+    publish_if_configured(publisher, "Token", tokens_to_insert)
+}
+
 fn insert_token_ownerships(
-    conn: &mut PgConnection,
+    publisher: &Publisher,
     token_ownerships_to_insert: &[TokenOwnership],
-) -> Result<(), diesel::result::Error> {
-    use schema::token_ownerships::dsl::*;
-
-    let chunks = get_chunks(
-        token_ownerships_to_insert.len(),
-        TokenOwnership::field_count(),
-    );
-    for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
-            conn,
-            diesel::insert_into(schema::token_ownerships::table)
-                .values(&token_ownerships_to_insert[start_ind..end_ind])
-                .on_conflict((
-                    token_data_id_hash,
-                    property_version,
-                    transaction_version,
-                    table_handle,
-                ))
-                .do_nothing(),
-            None,
-        )?;
-    }
-    Ok(())
+) -> Result<(), PublishError> {
+    publish_if_configured(publisher, "TokenOwnership", token_ownerships_to_insert)
 }
 
 fn insert_token_datas(
     publisher: &Publisher,
     token_datas_to_insert: &[TokenData],
-) -> Result<(), diesel::result::Error> {
-    publisher.send("TokenData", token_datas_to_insert);
-    Ok(())
+) -> Result<(), PublishError> {
+    publish_if_configured(publisher, "TokenData", token_datas_to_insert)
 }
 
 fn insert_collection_datas(
@@ -357,37 +376,67 @@ fn insert_collection_datas(
 fn insert_current_token_ownerships(
     publisher: &Publisher,
     items_to_insert: &[CurrentTokenOwnership],
-) -> Result<(), diesel::result::Error> {
-    publisher.send("CurrentTokenOwnership", items_to_insert);
-    Ok(())
+) -> Result<(), PublishError> {
+    publish_if_configured(publisher, "CurrentTokenOwnership", items_to_insert)
 }
 
 fn insert_current_token_datas(
     publisher: &Publisher,
     items_to_insert: &[CurrentTokenData],
-) -> Result<(), diesel::result::Error> {
-    publisher.send("CurrentTokenData", items_to_insert);
-    Ok(())
+) -> Result<(), PublishError> {
+    publish_if_configured(publisher, "CurrentTokenData", items_to_insert)
 }
 
+/// Also kept in Postgres: a collection data written without its `Collections` resource in the
+/// batch gets its creator from there, see `CollectionData::get_collection_creator`
 fn insert_current_collection_datas(
     publisher: &Publisher,
+    conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentCollectionData],
-) -> Result<(), diesel::result::Error> {
-    publisher.send("CurrentCollectionData", items_to_insert);
+) -> anyhow::Result<()> {
+    use schema::current_collection_datas::dsl::*;
+
+    let chunks = get_chunks(items_to_insert.len(), CurrentCollectionData::field_count());
+
+    for (start_ind, end_ind) in chunks {
+        CurrentRowUpsert::new("current_collection_datas", policy).execute(
+            conn,
+            diesel::insert_into(schema::current_collection_datas::table)
+                .values(&items_to_insert[start_ind..end_ind])
+                .on_conflict(collection_data_id_hash)
+                .do_update()
+                .set((
+                    creator_address.eq(excluded(creator_address)),
+                    collection_name.eq(excluded(collection_name)),
+                    description.eq(excluded(description)),
+                    metadata_uri.eq(excluded(metadata_uri)),
+                    supply.eq(excluded(supply)),
+                    maximum.eq(excluded(maximum)),
+                    maximum_mutable.eq(excluded(maximum_mutable)),
+                    uri_mutable.eq(excluded(uri_mutable)),
+                    description_mutable.eq(excluded(description_mutable)),
+                    last_transaction_version.eq(excluded(last_transaction_version)),
+                    table_handle.eq(excluded(table_handle)),
+                    inserted_at.eq(excluded(inserted_at)),
+                )),
+            &items_to_insert[start_ind..end_ind],
+        )?;
+    }
+    publish_if_configured(publisher, "CurrentCollectionData", items_to_insert)?;
     Ok(())
 }
 
 fn insert_token_activities(
     publisher: &Publisher,
     items_to_insert: &[TokenActivity],
-) -> Result<(), diesel::result::Error> {
-    publisher.send("TokenActivity", items_to_insert);
-    Ok(())
+) -> Result<(), PublishError> {
+    publish_if_configured(publisher, "TokenActivity", items_to_insert)
 }
 
 fn insert_current_token_claims(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentTokenPendingClaim],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_token_pending_claims::dsl::*;
@@ -398,7 +447,7 @@ fn insert_current_token_claims(
     );
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_token_pending_claims", policy).execute(
             conn,
             diesel::insert_into(schema::current_token_pending_claims::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -418,7 +467,7 @@ fn insert_current_token_claims(
                     token_data_id.eq(excluded(token_data_id)),
                     collection_id.eq(excluded(collection_id)),
                 )),
-            Some(" WHERE current_token_pending_claims.last_transaction_version <= excluded.last_transaction_version "),
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -426,6 +475,7 @@ fn insert_current_token_claims(
 
 fn insert_current_ans_lookups(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentAnsLookup],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_ans_lookup::dsl::*;
@@ -433,7 +483,7 @@ fn insert_current_ans_lookups(
     let chunks = get_chunks(items_to_insert.len(), CurrentAnsLookup::field_count());
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_ans_lookup", policy).execute(
             conn,
             diesel::insert_into(schema::current_ans_lookup::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -446,7 +496,7 @@ fn insert_current_ans_lookups(
                     inserted_at.eq(excluded(inserted_at)),
                     token_name.eq(excluded(token_name)),
                 )),
-                Some(" WHERE current_ans_lookup.last_transaction_version <= excluded.last_transaction_version "),
+            &items_to_insert[start_ind..end_ind],
             )?;
     }
     Ok(())
@@ -496,6 +546,7 @@ fn insert_collections_v2(
 
 fn insert_token_datas_v2(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[TokenDataV2],
 ) -> Result<(), diesel::result::Error> {
     use schema::token_datas_v2::dsl::*;
@@ -503,7 +554,7 @@ fn insert_token_datas_v2(
     let chunks = get_chunks(items_to_insert.len(), TokenDataV2::field_count());
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        GuardedUpsert::new("token_datas_v2", policy).execute(
             conn,
             diesel::insert_into(schema::token_datas_v2::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -514,6 +565,7 @@ fn insert_token_datas_v2(
                     decimals.eq(excluded(decimals)),
                 )),
             None,
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -521,6 +573,7 @@ fn insert_token_datas_v2(
 
 fn insert_token_ownerships_v2(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[TokenOwnershipV2],
 ) -> Result<(), diesel::result::Error> {
     use schema::token_ownerships_v2::dsl::*;
@@ -528,7 +581,7 @@ fn insert_token_ownerships_v2(
     let chunks = get_chunks(items_to_insert.len(), TokenOwnershipV2::field_count());
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        GuardedUpsert::new("token_ownerships_v2", policy).execute(
             conn,
             diesel::insert_into(schema::token_ownerships_v2::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -550,6 +603,7 @@ fn insert_token_ownerships_v2(
                     non_transferrable_by_owner.eq(excluded(non_transferrable_by_owner)),
                 )),
             None,
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -557,6 +611,7 @@ fn insert_token_ownerships_v2(
 
 fn insert_current_collections_v2(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentCollectionV2],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_collections_v2::dsl::*;
@@ -564,7 +619,7 @@ fn insert_current_collections_v2(
     let chunks = get_chunks(items_to_insert.len(), CurrentCollectionV2::field_count());
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_collections_v2", policy).execute(
             conn,
             diesel::insert_into(schema::current_collections_v2::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -586,7 +641,7 @@ fn insert_current_collections_v2(
                     last_transaction_timestamp.eq(excluded(last_transaction_timestamp)),
                     inserted_at.eq(excluded(inserted_at)),
                 )),
-            Some(" WHERE current_collections_v2.last_transaction_version <= excluded.last_transaction_version "),
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -594,6 +649,7 @@ fn insert_current_collections_v2(
 
 fn insert_current_token_datas_v2(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentTokenDataV2],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_token_datas_v2::dsl::*;
@@ -601,7 +657,7 @@ fn insert_current_token_datas_v2(
     let chunks = get_chunks(items_to_insert.len(), CurrentTokenDataV2::field_count());
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_token_datas_v2", policy).execute(
             conn,
             diesel::insert_into(schema::current_token_datas_v2::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -623,7 +679,7 @@ fn insert_current_token_datas_v2(
                     inserted_at.eq(excluded(inserted_at)),
                     decimals.eq(excluded(decimals)),
                 )),
-            Some(" WHERE current_token_datas_v2.last_transaction_version <= excluded.last_transaction_version "),
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -631,6 +687,7 @@ fn insert_current_token_datas_v2(
 
 fn insert_current_token_ownerships_v2(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentTokenOwnershipV2],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_token_ownerships_v2::dsl::*;
@@ -641,7 +698,7 @@ fn insert_current_token_ownerships_v2(
     );
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_token_ownerships_v2", policy).execute(
             conn,
             diesel::insert_into(schema::current_token_ownerships_v2::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -659,7 +716,7 @@ fn insert_current_token_ownerships_v2(
                     inserted_at.eq(excluded(inserted_at)),
                     non_transferrable_by_owner.eq(excluded(non_transferrable_by_owner)),
                 )),
-            Some(" WHERE current_token_ownerships_v2.last_transaction_version <= excluded.last_transaction_version "),
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -688,6 +745,7 @@ fn insert_token_activities_v2(
 
 fn insert_current_token_v2_metadatas(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentTokenV2Metadata],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_token_v2_metadata::dsl::*;
@@ -695,7 +753,7 @@ fn insert_current_token_v2_metadatas(
     let chunks = get_chunks(items_to_insert.len(), CurrentTokenV2Metadata::field_count());
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_token_v2_metadata", policy).execute(
             conn,
             diesel::insert_into(schema::current_token_v2_metadata::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -707,7 +765,7 @@ fn insert_current_token_v2_metadatas(
                     last_transaction_version.eq(excluded(last_transaction_version)),
                     inserted_at.eq(excluded(inserted_at)),
                 )),
-            Some(" WHERE current_token_v2_metadata.last_transaction_version <= excluded.last_transaction_version "),
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -725,7 +783,14 @@ impl TransactionProcessor for CTokenTransactionProcessor {
         start_version: u64,
         end_version: u64,
     ) -> Result<ProcessingResult, TransactionProcessingError> {
-        let mut conn = self.get_conn();
+        let mut conn = self.get_conn().map_err(|err| {
+            TransactionProcessingError::ConnectionPoolError((
+                err,
+                start_version,
+                end_version,
+                self.name(),
+            ))
+        })?;
 
         // First get all token related table metadata from the batch of transactions. This is in case
         // an earlier transaction has metadata (in resources) that's missing from a later transaction.
@@ -733,25 +798,18 @@ impl TransactionProcessor for CTokenTransactionProcessor {
             TableMetadataForToken::get_table_handle_to_owner_from_transactions(&transactions);
 
         // Token V1 only, this section will be deprecated soon
-        let mut all_tokens = vec![];
-        let mut all_token_ownerships = vec![];
-        let mut all_token_datas = vec![];
-        let mut all_collection_datas = vec![];
-        let mut all_token_activities = vec![];
-
-        // Hashmap key will be the PK of the table, we do not want to send duplicates writes to the db within a batch
-        let mut all_current_token_ownerships: HashMap<
-            CurrentTokenOwnershipPK,
-            CurrentTokenOwnership,
-        > = HashMap::new();
-        let mut all_current_token_datas: HashMap<TokenDataIdHash, CurrentTokenData> =
-            HashMap::new();
-        let mut all_current_collection_datas: HashMap<TokenDataIdHash, CurrentCollectionData> =
-            HashMap::new();
-        let mut all_current_token_claims: HashMap<
-            CurrentTokenPendingClaimPK,
-            CurrentTokenPendingClaim,
-        > = HashMap::new();
+        let (
+            all_tokens,
+            all_token_ownerships,
+            all_token_datas,
+            all_collection_datas,
+            all_current_token_ownerships,
+            all_current_token_datas,
+            all_current_collection_datas,
+            all_token_activities,
+            all_current_token_claims,
+        ) = parse_v1_token(&transactions, &table_handle_to_owner, &mut conn);
+
         let mut all_current_ans_lookups: HashMap<CurrentAnsLookupPK, CurrentAnsLookup> =
             HashMap::new();
 
@@ -759,32 +817,6 @@ impl TransactionProcessor for CTokenTransactionProcessor {
         let mut all_nft_points = vec![];
 
         for txn in &transactions {
-            let (
-                mut tokens,
-                mut token_ownerships,
-                mut token_datas,
-                mut collection_datas,
-                current_token_ownerships,
-                current_token_datas,
-                current_collection_datas,
-                current_token_claims,
-            ) = Token::from_transaction(txn, &table_handle_to_owner, &mut conn);
-            all_tokens.append(&mut tokens);
-            all_token_ownerships.append(&mut token_ownerships);
-            all_token_datas.append(&mut token_datas);
-            all_collection_datas.append(&mut collection_datas);
-            // Given versions will always be increasing here (within a single batch), we can just override current values
-            all_current_token_ownerships.extend(current_token_ownerships);
-            all_current_token_datas.extend(current_token_datas);
-            all_current_collection_datas.extend(current_collection_datas);
-
-            // Track token activities
-            let mut activities = TokenActivity::from_transaction(txn);
-            all_token_activities.append(&mut activities);
-
-            // claims
-            all_current_token_claims.extend(current_token_claims);
-
             // ANS lookups
             let current_ans_lookups =
                 CurrentAnsLookup::from_transaction(txn, self.ans_contract_address.clone());
@@ -797,45 +829,6 @@ impl TransactionProcessor for CTokenTransactionProcessor {
             }
         }
 
-        // Getting list of values and sorting by pk in order to avoid postgres deadlock since we're doing multi threaded db writes
-        let mut all_current_token_ownerships = all_current_token_ownerships
-            .into_values()
-            .collect::<Vec<CurrentTokenOwnership>>();
-        let mut all_current_token_datas = all_current_token_datas
-            .into_values()
-            .collect::<Vec<CurrentTokenData>>();
-        let mut all_current_collection_datas = all_current_collection_datas
-            .into_values()
-            .collect::<Vec<CurrentCollectionData>>();
-        let mut all_current_token_claims = all_current_token_claims
-            .into_values()
-            .collect::<Vec<CurrentTokenPendingClaim>>();
-
-        // Sort by PK
-        all_current_token_ownerships.sort_by(|a, b| {
-            (&a.token_data_id_hash, &a.property_version, &a.owner_address).cmp(&(
-                &b.token_data_id_hash,
-                &b.property_version,
-                &b.owner_address,
-            ))
-        });
-        all_current_token_datas.sort_by(|a, b| a.token_data_id_hash.cmp(&b.token_data_id_hash));
-        all_current_collection_datas
-            .sort_by(|a, b| a.collection_data_id_hash.cmp(&b.collection_data_id_hash));
-        all_current_token_claims.sort_by(|a, b| {
-            (
-                &a.token_data_id_hash,
-                &a.property_version,
-                &a.from_address,
-                &a.to_address,
-            )
-                .cmp(&(
-                    &b.token_data_id_hash,
-                    &b.property_version,
-                    &b.from_address,
-                    &a.to_address,
-                ))
-        });
         // Sort ans lookup values for postgres insert
         let mut all_current_ans_lookups = all_current_ans_lookups
             .into_values()
@@ -858,6 +851,7 @@ impl TransactionProcessor for CTokenTransactionProcessor {
         let tx_result = insert_to_db(
             &self.publisher,
             &mut conn,
+            backfill_guard::policy(self.name(), start_version, end_version),
             self.name(),
             start_version,
             end_version,
@@ -895,7 +889,7 @@ impl TransactionProcessor for CTokenTransactionProcessor {
                 end_version,
             )),
             Err(err) => Err(TransactionProcessingError::TransactionCommitError((
-                anyhow::Error::from(err),
+                err,
                 start_version,
                 end_version,
                 self.name(),
@@ -903,11 +897,127 @@ impl TransactionProcessor for CTokenTransactionProcessor {
         }
     }
 
-    fn connection_pool(&self) -> &PgDbPool {
-        &self.connection_pool
+    fn connection_pool(&self) -> Option<&PgDbPool> {
+        Some(&self.connection_pool)
     }
 }
 
+/// Token v1 models of a batch. Ownerships only need the token id, so a token is tracked even
+/// when its collection data is written in a later version or batch.
+#[allow(clippy::type_complexity)]
+fn parse_v1_token(
+    transactions: &[Transaction],
+    table_handle_to_owner: &TableHandleToOwner,
+    conn: &mut PgPoolConnection,
+) -> (
+    Vec<Token>,
+    Vec<TokenOwnership>,
+    Vec<TokenData>,
+    Vec<CollectionData>,
+    Vec<CurrentTokenOwnership>,
+    Vec<CurrentTokenData>,
+    Vec<CurrentCollectionData>,
+    Vec<TokenActivity>,
+    Vec<CurrentTokenPendingClaim>,
+) {
+    let mut all_tokens = vec![];
+    let mut all_token_ownerships = vec![];
+    let mut all_token_datas = vec![];
+    let mut all_collection_datas = vec![];
+    let mut all_token_activities = vec![];
+
+    // Hashmap key will be the PK of the table, we do not want to send duplicates writes to the db within a batch
+    let mut all_current_token_ownerships: HashMap<CurrentTokenOwnershipPK, CurrentTokenOwnership> =
+        HashMap::new();
+    let mut all_current_token_datas: HashMap<TokenDataIdHash, CurrentTokenData> = HashMap::new();
+    let mut all_current_collection_datas: HashMap<TokenDataIdHash, CurrentCollectionData> =
+        HashMap::new();
+    let mut all_current_token_claims: HashMap<
+        CurrentTokenPendingClaimPK,
+        CurrentTokenPendingClaim,
+    > = HashMap::new();
+
+    for txn in transactions {
+        let (
+            mut tokens,
+            mut token_ownerships,
+            mut token_datas,
+            mut collection_datas,
+            current_token_ownerships,
+            current_token_datas,
+            current_collection_datas,
+            current_token_claims,
+        ) = Token::from_transaction(txn, table_handle_to_owner, conn);
+        all_tokens.append(&mut tokens);
+        all_token_ownerships.append(&mut token_ownerships);
+        all_token_datas.append(&mut token_datas);
+        all_collection_datas.append(&mut collection_datas);
+        // Given versions will always be increasing here (within a single batch), we can just override current values
+        all_current_token_ownerships.extend(current_token_ownerships);
+        all_current_token_datas.extend(current_token_datas);
+        all_current_collection_datas.extend(current_collection_datas);
+
+        // Track token activities
+        let mut activities = TokenActivity::from_transaction(txn);
+        all_token_activities.append(&mut activities);
+
+        // claims
+        all_current_token_claims.extend(current_token_claims);
+    }
+
+    // Getting list of values and sorting by pk in order to avoid postgres deadlock since we're doing multi threaded db writes
+    let mut all_current_token_ownerships = all_current_token_ownerships
+        .into_values()
+        .collect::<Vec<CurrentTokenOwnership>>();
+    let mut all_current_token_datas = all_current_token_datas
+        .into_values()
+        .collect::<Vec<CurrentTokenData>>();
+    let mut all_current_collection_datas = all_current_collection_datas
+        .into_values()
+        .collect::<Vec<CurrentCollectionData>>();
+    let mut all_current_token_claims = all_current_token_claims
+        .into_values()
+        .collect::<Vec<CurrentTokenPendingClaim>>();
+
+    // Sort by PK
+    all_current_token_ownerships.sort_by(|a, b| {
+        (&a.token_data_id_hash, &a.property_version, &a.owner_address).cmp(&(
+            &b.token_data_id_hash,
+            &b.property_version,
+            &b.owner_address,
+        ))
+    });
+    all_current_token_datas.sort_by(|a, b| a.token_data_id_hash.cmp(&b.token_data_id_hash));
+    all_current_collection_datas
+        .sort_by(|a, b| a.collection_data_id_hash.cmp(&b.collection_data_id_hash));
+    all_current_token_claims.sort_by(|a, b| {
+        (
+            &a.token_data_id_hash,
+            &a.property_version,
+            &a.from_address,
+            &a.to_address,
+        )
+            .cmp(&(
+                &b.token_data_id_hash,
+                &b.property_version,
+                &b.from_address,
+                &a.to_address,
+            ))
+    });
+
+    (
+        all_tokens,
+        all_token_ownerships,
+        all_token_datas,
+        all_collection_datas,
+        all_current_token_ownerships,
+        all_current_token_datas,
+        all_current_collection_datas,
+        all_token_activities,
+        all_current_token_claims,
+    )
+}
+
 fn parse_v2_token(
     transactions: &[Transaction],
     table_handle_to_owner: &TableHandleToOwner,
@@ -1435,3 +1545,354 @@ fn parse_v2_token(
         current_token_v2_metadata,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::new_db_pool;
+    use serde_json::{json, Value};
+
+    const CREATOR: u64 = 0xc;
+    const RECEIVER: u64 = 0xd;
+    const CREATOR_STORE: &str = "0xc1";
+    const RECEIVER_STORE: &str = "0xd1";
+    const TOKEN_DATA_TABLE: &str = "0xc2";
+    const COLLECTION_DATA_TABLE: &str = "0xc3";
+
+    fn address(account: u64) -> String {
+        format!("0x{:064x}", account)
+    }
+
+    fn token_data_id() -> Value {
+        json!({ "creator": address(CREATOR), "collection": "Gems", "name": "Ruby" })
+    }
+
+    fn token_id() -> Value {
+        json!({ "token_data_id": token_data_id(), "property_version": "0" })
+    }
+
+    fn user_transaction(version: i64, sender: u64, changes: Value, events: Value) -> Transaction {
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": version.to_string(),
+            "hash": format!("0x{:064x}", version),
+            "state_change_hash": format!("0x{:064x}", 0),
+            "event_root_hash": format!("0x{:064x}", 0),
+            "gas_used": "100",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": format!("0x{:064x}", 0),
+            "sender": address(sender),
+            "sequence_number": "0",
+            "max_gas_amount": "1000",
+            "gas_unit_price": "100",
+            "expiration_timestamp_secs": "1649713172",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0x3::token::direct_transfer_script",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "signature": {
+                "type": "ed25519_signature",
+                "public_key": "0x14ff6646855dad4a2dab30db773cdd4b22d6f9e6813f3e50142adf4f3efcf9f8",
+                "signature": "0x70781112e78cc8b54b86805c016cef2478bccdef21b721542af0323276ab906c989172adffed5bf2f475f2ec3a5b284a0ac46a6aef0d79f0dbb6b85bfca0080a"
+            },
+            "events": events,
+            "timestamp": "1649713141723410",
+            "changes": changes
+        }))
+        .unwrap()
+    }
+
+    fn resource(owner: u64, type_: &str, data: Value) -> Value {
+        json!({
+            "type": "write_resource",
+            "address": address(owner),
+            "state_key_hash": format!("0x{:064x}", 1),
+            "data": { "type": type_, "data": data }
+        })
+    }
+
+    fn token_store(owner: u64, handle: &str) -> Value {
+        resource(
+            owner,
+            "0x3::token::TokenStore",
+            json!({ "tokens": { "handle": handle } }),
+        )
+    }
+
+    fn table_item(
+        handle: &str,
+        key_type: &str,
+        key: Value,
+        value_type: &str,
+        value: Value,
+    ) -> Value {
+        json!({
+            "type": "write_table_item",
+            "state_key_hash": format!("0x{:064x}", 2),
+            "handle": handle,
+            "key": "0x00",
+            "value": "0x00",
+            "data": { "key": key, "key_type": key_type, "value": value, "value_type": value_type }
+        })
+    }
+
+    fn token_item(handle: &str) -> Value {
+        table_item(
+            handle,
+            "0x3::token::TokenId",
+            token_id(),
+            "0x3::token::Token",
+            json!({
+                "amount": "1",
+                "id": token_id(),
+                "token_properties": { "map": { "data": [] } }
+            }),
+        )
+    }
+
+    fn deleted_token_item(handle: &str) -> Value {
+        json!({
+            "type": "delete_table_item",
+            "state_key_hash": format!("0x{:064x}", 2),
+            "handle": handle,
+            "key": "0x00",
+            "data": { "key": token_id(), "key_type": "0x3::token::TokenId" }
+        })
+    }
+
+    fn token_data_item(supply: u64) -> Value {
+        table_item(
+            TOKEN_DATA_TABLE,
+            "0x3::token::TokenDataId",
+            token_data_id(),
+            "0x3::token::TokenData",
+            json!({
+                // level: 5u64, BCS encoded
+                "default_properties": { "map": { "data": [{
+                    "key": "level",
+                    "value": { "type": "u64", "value": "0x0500000000000000" }
+                }] } },
+                "description": "A gem",
+                "largest_property_version": "0",
+                "maximum": "1",
+                "mutability_config": {
+                    "description": false,
+                    "maximum": false,
+                    "properties": true,
+                    "royalty": false,
+                    "uri": false
+                },
+                "name": "Ruby",
+                "royalty": {
+                    "payee_address": address(CREATOR),
+                    "royalty_points_denominator": "100",
+                    "royalty_points_numerator": "5"
+                },
+                "supply": supply.to_string(),
+                "uri": "https://gems.example/ruby"
+            }),
+        )
+    }
+
+    fn event(account: u64, type_: &str, data: Value) -> Value {
+        json!({
+            "guid": { "account_address": address(account), "creation_number": "4" },
+            "sequence_number": "0",
+            "type": type_,
+            "data": data
+        })
+    }
+
+    /// The creator mints a token, transfers it and the receiver burns it. The collection data is
+    /// written afterwards.
+    fn mint_transfer_burn() -> (Vec<Transaction>, Transaction) {
+        let mint = user_transaction(
+            100,
+            CREATOR,
+            json!([
+                token_store(CREATOR, CREATOR_STORE),
+                token_data_item(1),
+                token_item(CREATOR_STORE),
+            ]),
+            json!([
+                event(
+                    CREATOR,
+                    "0x3::token::MintTokenEvent",
+                    json!({
+                        "amount": "1",
+                        "id": token_data_id()
+                    })
+                ),
+                event(
+                    CREATOR,
+                    "0x3::token::DepositEvent",
+                    json!({ "amount": "1", "id": token_id() })
+                ),
+            ]),
+        );
+        let transfer = user_transaction(
+            101,
+            CREATOR,
+            json!([
+                token_store(CREATOR, CREATOR_STORE),
+                token_store(RECEIVER, RECEIVER_STORE),
+                deleted_token_item(CREATOR_STORE),
+                token_item(RECEIVER_STORE),
+            ]),
+            json!([
+                event(
+                    CREATOR,
+                    "0x3::token::WithdrawEvent",
+                    json!({ "amount": "1", "id": token_id() })
+                ),
+                event(
+                    RECEIVER,
+                    "0x3::token::DepositEvent",
+                    json!({ "amount": "1", "id": token_id() })
+                ),
+            ]),
+        );
+        let burn = user_transaction(
+            102,
+            RECEIVER,
+            json!([
+                token_store(RECEIVER, RECEIVER_STORE),
+                deleted_token_item(RECEIVER_STORE),
+                token_data_item(0),
+            ]),
+            json!([event(
+                RECEIVER,
+                "0x3::token::BurnTokenEvent",
+                json!({
+                    "amount": "1",
+                    "id": token_id()
+                })
+            )]),
+        );
+        let collection = user_transaction(
+            103,
+            CREATOR,
+            json!([
+                resource(
+                    CREATOR,
+                    "0x3::token::Collections",
+                    json!({
+                        "collection_data": { "handle": COLLECTION_DATA_TABLE },
+                        "token_data": { "handle": TOKEN_DATA_TABLE }
+                    })
+                ),
+                table_item(
+                    COLLECTION_DATA_TABLE,
+                    "0x1::string::String",
+                    json!("Gems"),
+                    "0x3::token::CollectionData",
+                    json!({
+                        "description": "Precious stones",
+                        "maximum": "100",
+                        "mutability_config": { "description": false, "maximum": false, "uri": false },
+                        "name": "Gems",
+                        "supply": "1",
+                        "uri": "https://gems.example"
+                    }),
+                ),
+            ]),
+            json!([]),
+        );
+        (vec![mint, transfer, burn], collection)
+    }
+
+    #[test]
+    fn test_mint_transfer_burn() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(&database_url).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        let (batch, collection) = mint_transfer_burn();
+
+        let table_handle_to_owner =
+            TableMetadataForToken::get_table_handle_to_owner_from_transactions(&batch);
+        let (
+            tokens,
+            token_ownerships,
+            token_datas,
+            collection_datas,
+            current_token_ownerships,
+            _,
+            _,
+            token_activities,
+            _,
+        ) = parse_v1_token(&batch, &table_handle_to_owner, &mut conn);
+        assert_eq!(tokens.len(), 3);
+        assert!(collection_datas.is_empty());
+
+        // Tracked without the collection data
+        let ownerships = token_ownerships
+            .iter()
+            .map(|o| {
+                (
+                    o.transaction_version,
+                    o.owner_address.clone().unwrap(),
+                    o.amount.to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ownerships, vec![
+            (100, address(CREATOR), "1".to_string()),
+            (101, address(CREATOR), "0".to_string()),
+            (101, address(RECEIVER), "1".to_string()),
+            (102, address(RECEIVER), "0".to_string()),
+        ]);
+        let current = current_token_ownerships
+            .iter()
+            .map(|o| {
+                (
+                    o.owner_address.clone(),
+                    o.amount.to_string(),
+                    o.last_transaction_version,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(current, vec![
+            (address(CREATOR), "0".to_string(), 101),
+            (address(RECEIVER), "0".to_string(), 102),
+        ]);
+
+        // The property map is decoded from BCS
+        assert_eq!(token_datas.len(), 2);
+        assert_eq!(token_datas[0].default_properties, json!({ "level": "5" }));
+        assert_eq!(token_datas[1].supply.to_string(), "0");
+
+        let transfer_types = token_activities
+            .iter()
+            .map(|a| a.transfer_type.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(transfer_types, vec![
+            "0x3::token::MintTokenEvent",
+            "0x3::token::DepositEvent",
+            "0x3::token::WithdrawEvent",
+            "0x3::token::DepositEvent",
+            "0x3::token::BurnTokenEvent",
+        ]);
+
+        // The collection data of a later batch matches the ownerships already tracked
+        let collection = vec![collection];
+        let table_handle_to_owner =
+            TableMetadataForToken::get_table_handle_to_owner_from_transactions(&collection);
+        let (_, _, _, collection_datas, _, _, current_collection_datas, _, _) =
+            parse_v1_token(&collection, &table_handle_to_owner, &mut conn);
+        assert_eq!(collection_datas.len(), 1);
+        assert_eq!(
+            current_collection_datas[0].creator_address,
+            address(CREATOR)
+        );
+        assert_eq!(
+            current_collection_datas[0].collection_data_id_hash,
+            token_ownerships[0].collection_data_id_hash
+        );
+    }
+}
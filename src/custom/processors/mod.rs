@@ -1,29 +1,29 @@
+pub mod custom_ans_processor;
 pub mod custom_coin_processor;
 pub mod custom_default_processor;
+pub mod custom_dex_processor;
+pub mod custom_object_processor;
+pub mod custom_onchain_config_processor;
 pub mod custom_token_processor;
 pub mod custom_stake_processor;
+pub mod processor_registry;
 
 
 use self::{
-    custom_coin_processor::NAME as COIN_PROCESSOR_NAME, custom_default_processor::NAME as DEFAULT_PROCESSOR_NAME,
-    custom_stake_processor::NAME as STAKE_PROCESSOR_NAME, custom_token_processor::NAME as TOKEN_PROCESSOR_NAME
+    custom_coin_processor::CoinOutput, custom_default_processor::DefaultOutput,
+    custom_dex_processor::DexOutput,
 };
+use crate::custom::driver::{shadow::Shadow, validation::Rule};
 
-pub enum CProcessor {
-    CoinProcessor,
-    DefaultProcessor,
-    TokenProcessor,
-    StakeProcessor
-}
-
-impl CProcessor {
-    pub fn from_string(input_str: &String) -> Self {
-        match input_str.as_str() {
-            DEFAULT_PROCESSOR_NAME => Self::DefaultProcessor,
-            TOKEN_PROCESSOR_NAME => Self::TokenProcessor,
-            COIN_PROCESSOR_NAME => Self::CoinProcessor,
-            STAKE_PROCESSOR_NAME => Self::StakeProcessor,
-            _ => panic!("Processor unsupported {}", input_str),
-        }
-    }
+/// What the processors are built with besides the driver config, see
+/// `processor_registry::build_processor`
+#[derive(Clone, Default)]
+pub struct ProcessorOptions {
+    /// Validation rules run after each processor's defaults, see `driver::validation`
+    pub coin_rules: Vec<Rule<CoinOutput>>,
+    pub default_rules: Vec<Rule<DefaultOutput>>,
+    pub dex_rules: Vec<Rule<DexOutput>>,
+    /// Alternate transforms diffed against the processors', see `driver::shadow`
+    pub coin_shadow: Option<Shadow<CoinOutput>>,
+    pub dex_shadow: Option<Shadow<DexOutput>>,
 }
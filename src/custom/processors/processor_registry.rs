@@ -0,0 +1,349 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Builds the processors by name, so that which ones run comes from config rather than code. The
+//! driver config's `processors` lists them, and `runtime::bootstrap` runs each concurrently with
+//! its own pipeline and its own watermark in `processor_status`, keyed by its name.
+
+use crate::{
+    custom::{
+        driver::{
+            asset_transfers::AssetTransfers,
+            config::{DbPoolsConfig, DriverConfig},
+            db_pools,
+            duplicate_transactions::DuplicateDetector,
+            entry_function_stats::EntryFunctionStats,
+            envelope::Envelope,
+            publish_filter::PublishFilter,
+            publisher::Publisher,
+            shadow::ShadowRunner,
+            sink::{self, Sink},
+            storage_usage::StorageUsage,
+            validation::Validator,
+        },
+        processors::{
+            custom_ans_processor::{self, CAnsTransactionProcessor},
+            custom_coin_processor::{self, CCoinTransactionProcessor},
+            custom_default_processor::{self, CDefaultTransactionProcessor},
+            custom_dex_processor::{self, CDexTransactionProcessor},
+            custom_object_processor::{self, CObjectTransactionProcessor},
+            custom_onchain_config_processor::{self, COnchainConfigTransactionProcessor},
+            custom_stake_processor::{self, CStakeTransactionProcessor},
+            custom_token_processor::{self, CTokenTransactionProcessor},
+            ProcessorOptions,
+        },
+    },
+    database::PgDbPool,
+    indexer::transaction_processor::TransactionProcessor,
+};
+use aptos_config::config::IndexerConfig;
+use std::{
+    collections::HashSet,
+    fmt::{self, Display},
+    sync::Arc,
+};
+
+/// Every processor `build_processor` knows
+pub const NAMES: [&str; 8] = [
+    custom_default_processor::NAME,
+    custom_coin_processor::NAME,
+    custom_token_processor::NAME,
+    custom_stake_processor::NAME,
+    custom_dex_processor::NAME,
+    custom_onchain_config_processor::NAME,
+    custom_object_processor::NAME,
+    custom_ans_processor::NAME,
+];
+
+/// What the processors are configured with
+#[derive(Clone, Copy)]
+pub struct ProcessorConfig<'a> {
+    pub indexer: &'a IndexerConfig,
+    pub driver: &'a DriverConfig,
+    pub options: &'a ProcessorOptions,
+}
+
+/// A processor name that isn't one of `NAMES`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownProcessor {
+    pub name: String,
+}
+
+impl Display for UnknownProcessor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unknown processor {:?}, expected one of: {}",
+            self.name,
+            NAMES.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownProcessor {}
+
+/// The processor named `name`. Processors that don't publish drop `publisher`, and so does the
+/// default processor when its sink isn't Kafka.
+///
+/// Panics if the sink config is invalid.
+pub fn build_processor(
+    name: &str,
+    conn_pool: PgDbPool,
+    publisher: Publisher,
+    config: &ProcessorConfig,
+) -> Result<Arc<dyn TransactionProcessor>, UnknownProcessor> {
+    let driver_config = config.driver;
+    let options = config.options;
+    let validation = &driver_config.validation;
+    let processor: Arc<dyn TransactionProcessor> = match name {
+        custom_default_processor::NAME => Arc::new(default_processor(
+            Some(conn_pool),
+            sink::from_config(&driver_config.sink, publisher),
+            config,
+        )),
+        custom_token_processor::NAME => Arc::new(CTokenTransactionProcessor::new(
+            conn_pool,
+            config.indexer.ans_contract_address.clone(),
+            config.indexer.nft_points_contract.clone(),
+            publisher,
+        )),
+        custom_coin_processor::NAME => Arc::new(CCoinTransactionProcessor::new(
+            conn_pool,
+            publisher,
+            Validator::new(
+                custom_coin_processor::NAME,
+                [
+                    custom_coin_processor::default_rules(),
+                    options.coin_rules.clone(),
+                ]
+                .concat(),
+                validation,
+            ),
+            ShadowRunner::new(
+                custom_coin_processor::NAME,
+                options.coin_shadow.clone(),
+                &driver_config.shadow,
+            ),
+            AssetTransfers::new(&driver_config.asset_transfers),
+            driver_config.sink.mode,
+        )),
+        custom_stake_processor::NAME => {
+            Arc::new(CStakeTransactionProcessor::new(conn_pool, publisher))
+        },
+        custom_dex_processor::NAME => Arc::new(CDexTransactionProcessor::new(
+            conn_pool,
+            driver_config.dex.protocols(),
+            Validator::new(
+                custom_dex_processor::NAME,
+                [
+                    custom_dex_processor::default_rules(),
+                    options.dex_rules.clone(),
+                ]
+                .concat(),
+                validation,
+            ),
+            ShadowRunner::new(
+                custom_dex_processor::NAME,
+                options.dex_shadow.clone(),
+                &driver_config.shadow,
+            ),
+        )),
+        custom_onchain_config_processor::NAME => Arc::new(COnchainConfigTransactionProcessor::new(
+            conn_pool, publisher,
+        )),
+        custom_object_processor::NAME => Arc::new(CObjectTransactionProcessor::new(
+            conn_pool,
+            driver_config.object_ownership.clone(),
+            publisher,
+        )),
+        custom_ans_processor::NAME => Arc::new(CAnsTransactionProcessor::new(
+            conn_pool,
+            driver_config
+                .ans
+                .contract_address
+                .clone()
+                .or_else(|| config.indexer.ans_contract_address.clone()),
+            publisher,
+        )),
+        _ => {
+            return Err(UnknownProcessor {
+                name: name.to_string(),
+            })
+        },
+    };
+    Ok(processor)
+}
+
+/// `custom_default_processor` sending its batches to `sink` rather than to the one the sink
+/// config sets up, e.g. a `RecordingSink` in tests. Without `conn_pool` it publishes without
+/// Postgres, see `CDefaultTransactionProcessor`.
+pub fn default_processor(
+    conn_pool: Option<PgDbPool>,
+    sink: Box<dyn Sink>,
+    config: &ProcessorConfig,
+) -> CDefaultTransactionProcessor {
+    let driver_config = config.driver;
+    // An app-scoped batch leaves out the versions outside of the scope
+    let mut default_rules = [
+        custom_default_processor::default_rules(),
+        config.options.default_rules.clone(),
+    ]
+    .concat();
+    if driver_config.app_scope.enabled {
+        default_rules.retain(|rule| rule.name != "versions_contiguous");
+    }
+    CDefaultTransactionProcessor::new(
+        conn_pool,
+        sink,
+        Validator::new(
+            custom_default_processor::NAME,
+            default_rules,
+            &driver_config.validation,
+        ),
+        DuplicateDetector::new(
+            custom_default_processor::NAME,
+            &driver_config.duplicate_transactions,
+        ),
+        EntryFunctionStats::new(&driver_config.entry_function_stats),
+        StorageUsage::new(custom_default_processor::NAME, &driver_config.storage_usage),
+        driver_config.sink.mode,
+        PublishFilter::new(
+            custom_default_processor::NAME,
+            &driver_config.publish_filter,
+        ),
+    )
+}
+
+/// The connection pool of the processor named `name`, of its own or shared, see
+/// `driver::db_pools`
+pub fn connection_pool(
+    name: &str,
+    database_url: &str,
+    config: &DbPoolsConfig,
+) -> anyhow::Result<PgDbPool> {
+    if !NAMES.contains(&name) {
+        return Err(UnknownProcessor {
+            name: name.to_string(),
+        }
+        .into());
+    }
+    config.validate()?;
+    Ok(db_pools::connection_pool(config, name, database_url)?)
+}
+
+/// Checks the `processors` of the driver config before any is started: at least one, each known
+/// and listed once, since processors of the same name would share a watermark
+pub fn validate_names(names: &[String]) -> anyhow::Result<()> {
+    if names.is_empty() {
+        anyhow::bail!(
+            "No processors configured, expected some of: {}",
+            NAMES.join(", ")
+        );
+    }
+    let mut seen = HashSet::new();
+    for name in names {
+        if !NAMES.contains(&name.as_str()) {
+            return Err(UnknownProcessor { name: name.clone() }.into());
+        }
+        if !seen.insert(name.as_str()) {
+            anyhow::bail!("Processor {:?} is configured more than once", name);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{custom::driver::config::SinkBackend, database::new_db_pool};
+    use serde_json::json;
+
+    fn driver_config() -> DriverConfig {
+        serde_json::from_value(json!({
+            "kafka": {},
+            "topics": {},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_validate_names() {
+        let names = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+        validate_names(&names(&NAMES)).unwrap();
+        validate_names(&names(&[custom_coin_processor::NAME])).unwrap();
+
+        let error =
+            validate_names(&names(&[custom_coin_processor::NAME, "coin_processor"])).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<UnknownProcessor>(),
+            Some(&UnknownProcessor {
+                name: "coin_processor".to_string(),
+            })
+        );
+        assert_eq!(
+            error.to_string(),
+            "Unknown processor \"coin_processor\", expected one of: custom_default_processor, \
+             custom_coin_processor, custom_token_processor, custom_stake_processor, \
+             custom_dex_processor, custom_onchain_config_processor, custom_object_processor, \
+             custom_ans_processor"
+        );
+        assert!(validate_names(&names(&[])).is_err());
+        assert!(validate_names(&names(&[
+            custom_dex_processor::NAME,
+            custom_dex_processor::NAME
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_build_processor() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(&database_url).unwrap();
+        let driver_config = driver_config();
+        let config = ProcessorConfig {
+            indexer: &IndexerConfig::default(),
+            driver: &driver_config,
+            options: &ProcessorOptions::default(),
+        };
+        let publisher =
+            |name| Publisher::from_config(driver_config.clone(), Envelope::new(4, name));
+        for name in NAMES {
+            let processor =
+                build_processor(name, conn_pool.clone(), publisher(name), &config).unwrap();
+            assert_eq!(processor.name(), name);
+        }
+        let publisher = publisher("default_processor");
+        let error = build_processor("default_processor", conn_pool.clone(), publisher, &config)
+            .err()
+            .unwrap();
+        assert_eq!(error.name, "default_processor");
+        assert!(error.to_string().contains(custom_default_processor::NAME));
+
+        // Publishing to the HTTP sink
+        let mut http_config = driver_config.clone();
+        http_config.sink.backend = SinkBackend::Http;
+        http_config.sink.http.url = "http://localhost:8080/batches".to_string();
+        let processor = build_processor(
+            custom_default_processor::NAME,
+            conn_pool,
+            Publisher::from_config(
+                http_config.clone(),
+                Envelope::new(4, custom_default_processor::NAME),
+            ),
+            &ProcessorConfig {
+                driver: &http_config,
+                ..config
+            },
+        )
+        .unwrap();
+        assert_eq!(processor.name(), custom_default_processor::NAME);
+    }
+}
@@ -0,0 +1,131 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Helpers for the processors' tests: fixtures of real transactions from `tests/fixtures`, a
+//! migrated test database, and running a processor over a fixture with what it publishes
+//! recorded, see `driver::memory_publisher`, so that a parsing fix comes with a regression test.
+//! The processors write `processor_status` and their tables whatever they publish, so their runs
+//! need the test database and are skipped without `INDEXER_DATABASE_URL`, except
+//! `custom_default_processor` publishing only, which `run_default_processor` runs without one.
+
+use crate::{
+    custom::{
+        driver::{
+            config::{DriverConfig, SinkMode},
+            memory_publisher::RecordingSink,
+            replay::{self, Replay},
+        },
+        processors::{
+            processor_registry::{self, ProcessorConfig},
+            ProcessorOptions,
+        },
+    },
+    database::{new_db_pool, PgDbPool},
+    indexer::{
+        processing_result::ProcessingResult, tailer::MIGRATIONS,
+        transaction_processor::TransactionProcessor,
+    },
+};
+use aptos_api_types::Transaction;
+use aptos_config::config::IndexerConfig;
+use diesel_migrations::MigrationHarness;
+use serde_json::json;
+use std::path::PathBuf;
+
+/// Chain id the messages of the runs are stamped with
+pub const CHAIN_ID: u8 = 4;
+
+/// The transactions of `tests/fixtures/<name>`, in version order
+pub fn fixture(name: &str) -> Vec<Transaction> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    replay::read_transactions(&[path]).unwrap()
+}
+
+/// The migrated test database, `None` to skip the test without one
+pub fn test_pool() -> Option<PgDbPool> {
+    if crate::should_skip_pg_tests() {
+        return None;
+    }
+    let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+    let conn_pool = new_db_pool(&database_url).unwrap();
+    conn_pool
+        .get()
+        .unwrap()
+        .run_pending_migrations(MIGRATIONS)
+        .unwrap();
+    Some(conn_pool)
+}
+
+/// A driver config with `topics` and the defaults otherwise
+pub fn driver_config(topics: serde_json::Value) -> DriverConfig {
+    serde_json::from_value(json!({ "kafka": {}, "topics": topics })).unwrap()
+}
+
+/// Runs the processor named `name` over `transactions`, in batches of up to `batch_size`
+/// consecutive versions, every model it publishes recorded under its topic key
+pub async fn run_processor(
+    name: &str,
+    conn_pool: PgDbPool,
+    transactions: Vec<Transaction>,
+    batch_size: usize,
+) -> Replay {
+    run_processor_with_config(
+        name,
+        conn_pool,
+        &driver_config(json!({})),
+        transactions,
+        batch_size,
+    )
+    .await
+}
+
+/// `run_processor` with `driver_config`, e.g. for the contract addresses of a processor
+pub async fn run_processor_with_config(
+    name: &str,
+    conn_pool: PgDbPool,
+    driver_config: &DriverConfig,
+    transactions: Vec<Transaction>,
+    batch_size: usize,
+) -> Replay {
+    replay::replay(
+        name,
+        Some(conn_pool),
+        driver_config,
+        CHAIN_ID,
+        transactions,
+        batch_size,
+    )
+    .await
+    .unwrap()
+}
+
+/// Runs `custom_default_processor` in `sink_mode` over `transactions`, which must be consecutive
+/// versions, as a single batch sent to a `RecordingSink`. Without `conn_pool` the sink mode must
+/// be `publish_only`.
+pub async fn run_default_processor(
+    conn_pool: Option<PgDbPool>,
+    sink_mode: SinkMode,
+    transactions: Vec<Transaction>,
+) -> (ProcessingResult, RecordingSink) {
+    let mut driver_config = driver_config(json!({}));
+    driver_config.sink.mode = sink_mode;
+    let sink = RecordingSink::default();
+    let processor = processor_registry::default_processor(
+        conn_pool,
+        Box::new(sink.clone()),
+        &ProcessorConfig {
+            indexer: &IndexerConfig::default(),
+            driver: &driver_config,
+            options: &ProcessorOptions::default(),
+        },
+    );
+    let start_version = transactions.first().unwrap().version().unwrap();
+    let end_version = transactions.last().unwrap().version().unwrap();
+    let result = processor
+        .process_versions_with_status(transactions, start_version, end_version)
+        .await
+        .unwrap();
+    (result, sink)
+}
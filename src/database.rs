@@ -3,14 +3,27 @@
 
 //! Database-related functions
 #![allow(clippy::extra_unused_lifetimes)]
-use crate::util::remove_null_bytes;
+use crate::{
+    counters::{BACKFILL_GUARD_SKIPPED_ROWS, CURRENT_ROW_REGRESSIONS_REJECTED},
+    custom::driver::backfill_guard::OverwritePolicy,
+    util::remove_null_bytes,
+};
 use diesel::{
-    pg::{Pg, PgConnection},
-    query_builder::{AstPass, Query, QueryFragment},
-    r2d2::{ConnectionManager, PoolError, PooledConnection},
-    QueryResult, RunQueryDsl,
+    connection::{AnsiTransactionManager, TransactionManager},
+    pg::{Pg, PgConnection, PgQueryBuilder},
+    query_builder::{AstPass, Query, QueryBuilder, QueryFragment},
+    r2d2::{ConnectionManager, CustomizeConnection, PoolError, PooledConnection},
+    result::Error as DieselError,
+    Connection, QueryResult, RunQueryDsl,
+};
+use std::{
+    cmp::min,
+    fmt::{self, Display},
+    sync::Arc,
+    time::Duration,
 };
-use std::{cmp::min, sync::Arc};
+
+pub mod read_cache;
 
 pub type PgPool = diesel::r2d2::Pool<ConnectionManager<PgConnection>>;
 pub type PgDbPool = Arc<PgPool>;
@@ -22,14 +35,169 @@ pub type PgPoolConnection = PooledConnection<ConnectionManager<PgConnection>>;
 pub struct UpsertFilterLatestTransactionQuery<T> {
     query: T,
     where_clause: Option<&'static str>,
+    /// Table whose `last_transaction_version` must not go backwards, see `CurrentRowUpsert`
+    guarded_table: Option<&'static str>,
+    /// Leave conflicting rows as they are, unless `where_clause` says otherwise (or only take
+    /// strictly newer ones into a guarded table), see `custom::driver::backfill_guard`
+    skip_overwrites: bool,
+}
+
+/// Number of candidate rows logged when a current table upsert rejects stale rows
+const REJECTED_ROWS_SAMPLE_SIZE: usize = 5;
+
+/// Upsert for tables that only keep the latest state per key (current_*). The update only applies
+/// if the incoming row is at least as new as the stored one, i.e.
+/// INSERT INTO ... ON CONFLICT DO UPDATE SET ... WHERE current_x.last_transaction_version <= excluded.last_transaction_version
+/// so a backfill running behind live processing can never regress state. Rows the guard rejects
+/// are counted in `CURRENT_ROW_REGRESSIONS_REJECTED`. With `OverwritePolicy::Skip` only strictly
+/// newer rows are taken, see `custom::driver::backfill_guard`.
+pub struct CurrentRowUpsert {
+    table: &'static str,
+    policy: OverwritePolicy,
+}
+
+impl CurrentRowUpsert {
+    pub const fn new(table: &'static str, policy: OverwritePolicy) -> Self {
+        Self { table, policy }
+    }
+
+    /// Like `execute`, failing with the versions of `rows`
+    pub fn execute_with_context<U, T>(
+        &self,
+        conn: &mut PgConnection,
+        query: U,
+        rows: &[T],
+        versions: impl IntoIterator<Item = i64>,
+    ) -> Result<usize, InsertError>
+    where
+        U: QueryFragment<Pg> + diesel::query_builder::QueryId,
+        T: serde::Serialize,
+    {
+        self.execute(conn, query, rows)
+            .map_err(|error| InsertError::new(error, Some(ChunkContext::new(self.table, versions))))
+    }
+
+    /// `query` must be the `insert_into(..).values(rows).on_conflict(..).do_update()` for `rows`
+    pub fn execute<U, T>(&self, conn: &mut PgConnection, query: U, rows: &[T]) -> QueryResult<usize>
+    where
+        U: QueryFragment<Pg> + diesel::query_builder::QueryId,
+        T: serde::Serialize,
+    {
+        let skip_overwrites = self.policy == OverwritePolicy::Skip;
+        let affected = execute_upsert(conn, query, None, Some(self.table), skip_overwrites)?;
+        // Both inserted and updated rows count as affected, so whatever is missing was rejected
+        let rejected = rows.len().saturating_sub(affected);
+        if rejected > 0 {
+            CURRENT_ROW_REGRESSIONS_REJECTED
+                .with_label_values(&[self.table])
+                .inc_by(rejected as u64);
+            if skip_overwrites {
+                BACKFILL_GUARD_SKIPPED_ROWS
+                    .with_label_values(&[self.table])
+                    .inc_by(rejected as u64);
+            }
+            let sample = rows
+                .iter()
+                .take(REJECTED_ROWS_SAMPLE_SIZE)
+                .map(|row| serde_json::to_string(row).unwrap_or_default())
+                .collect::<Vec<_>>();
+            aptos_logger::debug!(
+                table = self.table,
+                rejected = rejected,
+                batch_size = rows.len(),
+                sample = ?sample,
+                "Rejected out of order upserts on current table"
+            );
+        }
+        Ok(affected)
+    }
+}
+
+/// Upsert for tables without a `last_transaction_version` to order rows by, updating conflicting
+/// rows as the query says with `OverwritePolicy::Overwrite` and leaving them as they are with
+/// `OverwritePolicy::Skip`, see `custom::driver::backfill_guard`. Skipped rows are counted in
+/// `BACKFILL_GUARD_SKIPPED_ROWS`. Inserts that don't update conflicting rows aren't guarded and
+/// go through `execute_with_better_error`.
+pub struct GuardedUpsert {
+    table: &'static str,
+    policy: OverwritePolicy,
+    skip_where_clause: Option<&'static str>,
+}
+
+impl GuardedUpsert {
+    pub const fn new(table: &'static str, policy: OverwritePolicy) -> Self {
+        Self {
+            table,
+            policy,
+            skip_where_clause: None,
+        }
+    }
+
+    /// Replaces the query's where clause with `OverwritePolicy::Skip`, for tables that still take
+    /// some rows then, e.g. `dex_pools` an earlier sighting of a pool
+    pub const fn skip_unless(mut self, where_clause: &'static str) -> Self {
+        self.skip_where_clause = Some(where_clause);
+        self
+    }
+
+    /// `query` must be the `insert_into(..).values(rows).on_conflict(..).do_update()` for `rows`
+    pub fn execute<U, T>(
+        &self,
+        conn: &mut PgConnection,
+        query: U,
+        additional_where_clause: Option<&'static str>,
+        rows: &[T],
+    ) -> QueryResult<usize>
+    where
+        U: QueryFragment<Pg> + diesel::query_builder::QueryId,
+    {
+        if self.policy == OverwritePolicy::Overwrite {
+            return execute_upsert(conn, query, additional_where_clause, None, false);
+        }
+        let affected = execute_upsert(conn, query, self.skip_where_clause, None, true)?;
+        // Inserted rows count as affected, so whatever is missing was a skipped conflict
+        let skipped = rows.len().saturating_sub(affected);
+        if skipped > 0 {
+            BACKFILL_GUARD_SKIPPED_ROWS
+                .with_label_values(&[self.table])
+                .inc_by(skipped as u64);
+        }
+        Ok(affected)
+    }
+
+    /// Like `execute`, failing with the versions of `rows`
+    pub fn execute_with_context<U, T>(
+        &self,
+        conn: &mut PgConnection,
+        query: U,
+        additional_where_clause: Option<&'static str>,
+        rows: &[T],
+        versions: impl IntoIterator<Item = i64>,
+    ) -> Result<usize, InsertError>
+    where
+        U: QueryFragment<Pg> + diesel::query_builder::QueryId,
+    {
+        self.execute(conn, query, additional_where_clause, rows)
+            .map_err(|error| InsertError::new(error, Some(ChunkContext::new(self.table, versions))))
+    }
 }
 
 pub const MAX_DIESEL_PARAM_SIZE: u16 = u16::MAX;
 
 /// Given diesel has a limit of how many parameters can be inserted in a single operation (u16::MAX)
 /// we may need to chunk an array of items based on how many columns are in the table.
-/// This function returns boundaries of chunks in the form of (start_index, end_index)
+/// This function returns boundaries of chunks in the form of (start_index, end_index), each
+/// binding at most `MAX_DIESEL_PARAM_SIZE` parameters.
+///
+/// Panics if `column_count` is 0 or a single row binds more than `MAX_DIESEL_PARAM_SIZE`
+/// parameters, before anything is sent to Postgres
 pub fn get_chunks(num_items_to_insert: usize, column_count: usize) -> Vec<(usize, usize)> {
+    assert!(
+        (1..=MAX_DIESEL_PARAM_SIZE as usize).contains(&column_count),
+        "Can't chunk rows of {} columns, a statement binds 1 to {} parameters",
+        column_count,
+        MAX_DIESEL_PARAM_SIZE
+    );
     let max_item_size = MAX_DIESEL_PARAM_SIZE as usize / column_count;
     let mut chunk: (usize, usize) = (0, min(num_items_to_insert, max_item_size));
     let mut chunks = vec![chunk];
@@ -44,7 +212,8 @@ pub fn get_chunks(num_items_to_insert: usize, column_count: usize) -> Vec<(usize
 }
 
 /// This function will clean the data for postgres. Currently it has support for removing
-/// null bytes from strings but in the future we will add more functionality.
+/// null bytes from strings, at any depth of JSON values and in their keys, but in the future we
+/// will add more functionality.
 pub fn clean_data_for_db<T: serde::Serialize + for<'de> serde::Deserialize<'de>>(
     items: Vec<T>,
     should_remove_null_bytes: bool,
@@ -61,23 +230,262 @@ pub fn new_db_pool(database_url: &str) -> Result<PgDbPool, PoolError> {
     PgPool::builder().build(manager).map(Arc::new)
 }
 
+/// A pool of at most `max_size` connections, waiting up to `connection_timeout` for one, with
+/// Postgres' `statement_timeout` set on every connection it opens
+pub fn new_db_pool_with(
+    database_url: &str,
+    max_size: u32,
+    connection_timeout: Duration,
+    statement_timeout: Option<Duration>,
+) -> Result<PgDbPool, PoolError> {
+    let manager = ConnectionManager::<PgConnection>::new(database_url);
+    let mut builder = PgPool::builder()
+        .max_size(max_size)
+        .connection_timeout(connection_timeout);
+    if let Some(statement_timeout) = statement_timeout {
+        builder = builder.connection_customizer(Box::new(StatementTimeout(statement_timeout)));
+    }
+    builder.build(manager).map(Arc::new)
+}
+
+/// Sets `statement_timeout` on every connection as it's opened, connections are reused with it
+#[derive(Debug)]
+struct StatementTimeout(Duration);
+
+impl CustomizeConnection<PgConnection, diesel::r2d2::Error> for StatementTimeout {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), diesel::r2d2::Error> {
+        diesel::sql_query(format!("SET statement_timeout = {}", self.0.as_millis()))
+            .execute(conn)
+            .map(|_| ())
+            .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+/// A pool of a single connection in a transaction that's never committed, for a dry run to write
+/// as usual and leave nothing behind. Every transaction of `write_transaction` is a savepoint of
+/// it, and every write is rolled back when the pool is dropped. The connection is never replaced,
+/// so what a batch wrote is there for the next, and taking a second connection while the first
+/// is held waits for `connection_timeout` and fails.
+pub fn new_rollback_db_pool(
+    database_url: &str,
+    connection_timeout: Duration,
+) -> Result<PgDbPool, PoolError> {
+    let manager = ConnectionManager::<PgConnection>::new(database_url);
+    PgPool::builder()
+        .max_size(1)
+        .connection_timeout(connection_timeout)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connection_customizer(Box::new(NeverCommitted))
+        .build(manager)
+        .map(Arc::new)
+}
+
+#[derive(Debug)]
+struct NeverCommitted;
+
+impl CustomizeConnection<PgConnection, diesel::r2d2::Error> for NeverCommitted {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), diesel::r2d2::Error> {
+        conn.begin_test_transaction()
+            .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+/// Runs `f` in a read write transaction, or in a savepoint if `conn` is in a transaction already,
+/// e.g. the one of `new_rollback_db_pool`'s connection, which a transaction can't be started in
+pub fn write_transaction<T, E, F>(conn: &mut PgConnection, f: F) -> Result<T, E>
+where
+    F: FnOnce(&mut PgConnection) -> Result<T, E>,
+    E: From<DieselError>,
+{
+    if in_transaction(conn)? {
+        conn.transaction(f)
+    } else {
+        conn.build_transaction().read_write().run(f)
+    }
+}
+
+/// `write_transaction` for a read only transaction. A savepoint can't be read only, so within a
+/// transaction `f` isn't kept from writing.
+pub fn read_only_transaction<T, E, F>(conn: &mut PgConnection, f: F) -> Result<T, E>
+where
+    F: FnOnce(&mut PgConnection) -> Result<T, E>,
+    E: From<DieselError>,
+{
+    if in_transaction(conn)? {
+        conn.transaction(f)
+    } else {
+        conn.build_transaction().read_only().run(f)
+    }
+}
+
+fn in_transaction(conn: &mut PgConnection) -> QueryResult<bool> {
+    Ok(AnsiTransactionManager::transaction_manager_status_mut(conn)
+        .transaction_depth()?
+        .is_some())
+}
+
+/// No connection freed up in `processor`'s pool within its connection timeout
+#[derive(Debug)]
+pub struct PoolTimeout {
+    pub processor: String,
+    pub max_size: u32,
+    pub connection_timeout: Duration,
+    pub error: PoolError,
+}
+
+impl Display for PoolTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} got no connection within {:?} from its pool of {}: {}",
+            self.processor, self.connection_timeout, self.max_size, self.error
+        )
+    }
+}
+
+impl std::error::Error for PoolTimeout {}
+
+/// A connection from `pool` for `processor`, failing with an error naming the processor and the
+/// pool's limits
+pub fn get_connection(pool: &PgPool, processor: &str) -> Result<PgPoolConnection, PoolTimeout> {
+    pool.get().map_err(|error| PoolTimeout {
+        processor: processor.to_string(),
+        max_size: pool.max_size(),
+        connection_timeout: pool.connection_timeout(),
+        error,
+    })
+}
+
 pub fn execute_with_better_error<U>(
+    conn: &mut PgConnection,
+    query: U,
+    additional_where_clause: Option<&'static str>,
+) -> QueryResult<usize>
+where
+    U: QueryFragment<Pg> + diesel::query_builder::QueryId,
+{
+    execute_upsert(conn, query, additional_where_clause, None, false)
+}
+
+/// Like `execute_with_better_error`, failing with the table and versions of the chunk inserted
+pub fn execute_with_context<U>(
+    conn: &mut PgConnection,
+    query: U,
+    additional_where_clause: Option<&'static str>,
+    context: ChunkContext,
+) -> Result<usize, InsertError>
+where
+    U: QueryFragment<Pg> + diesel::query_builder::QueryId,
+{
+    execute_with_better_error(conn, query, additional_where_clause)
+        .map_err(|error| InsertError::new(error, Some(context)))
+}
+
+/// The rows of a chunked insert, to tell which ones a failed statement was about
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkContext {
+    pub table: &'static str,
+    /// Lowest and highest transaction version of the rows, `None` for tables without one
+    pub versions: Option<(i64, i64)>,
+}
+
+impl ChunkContext {
+    pub fn new(table: &'static str, versions: impl IntoIterator<Item = i64>) -> Self {
+        let versions = versions
+            .into_iter()
+            .fold(None, |range, version| match range {
+                None => Some((version, version)),
+                Some((first, last)) => Some((first.min(version), last.max(version))),
+            });
+        Self { table, versions }
+    }
+}
+
+/// A statement Postgres rejected, with the rows it was inserting when known and the constraint
+/// and detail Postgres reported, if any
+#[derive(Debug)]
+pub struct InsertError {
+    pub context: Option<ChunkContext>,
+    pub constraint: Option<String>,
+    pub detail: Option<String>,
+    pub error: DieselError,
+}
+
+impl InsertError {
+    pub fn new(error: DieselError, context: Option<ChunkContext>) -> Self {
+        let (constraint, detail) = match &error {
+            DieselError::DatabaseError(_, info) => (
+                info.constraint_name().map(str::to_string),
+                info.details().map(str::to_string),
+            ),
+            _ => (None, None),
+        };
+        Self {
+            context,
+            constraint,
+            detail,
+            error,
+        }
+    }
+}
+
+impl From<DieselError> for InsertError {
+    fn from(error: DieselError) -> Self {
+        Self::new(error, None)
+    }
+}
+
+impl Display for InsertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.context {
+            Some(ChunkContext {
+                table,
+                versions: Some((first, last)),
+            }) => write!(
+                f,
+                "Failed to insert into {}, versions {}-{}: ",
+                table, first, last
+            )?,
+            Some(ChunkContext { table, .. }) => write!(f, "Failed to insert into {}: ", table)?,
+            None => {},
+        }
+        write!(f, "{}", self.error)?;
+        if let Some(constraint) = &self.constraint {
+            write!(f, " (constraint {})", constraint)?;
+        }
+        if let Some(detail) = &self.detail {
+            write!(f, ", {}", detail)?;
+        }
+        Ok(())
+    }
+}
+
+// The diesel error is part of the message, so it isn't a source too
+impl std::error::Error for InsertError {}
+
+fn execute_upsert<U>(
     conn: &mut PgConnection,
     query: U,
     mut additional_where_clause: Option<&'static str>,
+    mut guarded_table: Option<&'static str>,
+    mut skip_overwrites: bool,
 ) -> QueryResult<usize>
 where
     U: QueryFragment<Pg> + diesel::query_builder::QueryId,
 {
-    let original_query = diesel::debug_query::<diesel::pg::Pg, _>(&query).to_string();
     // This is needed because if we don't insert any row, then diesel makes a call like this
     // SELECT 1 FROM TABLE WHERE 1=0
-    if original_query.to_lowercase().contains("where") {
+    if is_empty_insert(&query)? {
         additional_where_clause = None;
+        guarded_table = None;
+        skip_overwrites = false;
     }
     let final_query = UpsertFilterLatestTransactionQuery {
         query,
         where_clause: additional_where_clause,
+        guarded_table,
+        skip_overwrites,
     };
     let debug = diesel::debug_query::<diesel::pg::Pg, _>(&final_query).to_string();
     aptos_logger::debug!("Executing query: {:?}", debug);
@@ -88,6 +496,14 @@ where
     res
 }
 
+/// Whether diesel turned `query` into the `SELECT 1 FROM table WHERE 1=0` of an insert without
+/// rows. Only the SQL is looked at, the bound values may say anything.
+fn is_empty_insert<U: QueryFragment<Pg>>(query: &U) -> QueryResult<bool> {
+    let mut sql = PgQueryBuilder::default();
+    query.to_sql(&mut sql, &Pg)?;
+    Ok(sql.finish().ends_with(" WHERE 1=0"))
+}
+
 /// Section below is required to modify the query.
 impl<T: Query> Query for UpsertFilterLatestTransactionQuery<T> {
     type SqlType = T::SqlType;
@@ -104,6 +520,17 @@ where
         if let Some(w) = self.where_clause {
             out.push_sql(w);
         }
+        if let Some(table) = self.guarded_table {
+            out.push_sql(" WHERE ");
+            out.push_sql(table);
+            if self.skip_overwrites {
+                out.push_sql(".last_transaction_version < excluded.last_transaction_version ");
+            } else {
+                out.push_sql(".last_transaction_version <= excluded.last_transaction_version ");
+            }
+        } else if self.where_clause.is_none() && self.skip_overwrites {
+            out.push_sql(" WHERE FALSE ");
+        }
         Ok(())
     }
 }
@@ -111,6 +538,54 @@ where
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{
+        indexer::tailer::MIGRATIONS,
+        models::{
+            dex_models::dex_pools::DexPool, events::EventModel, move_tables::CurrentTableItem,
+        },
+        schema,
+    };
+    use diesel::{pg::upsert::excluded, ExpressionMethods, OptionalExtension, QueryDsl};
+    use diesel_migrations::MigrationHarness;
+
+    fn upsert_current_table_items(
+        conn: &mut PgConnection,
+        items_to_insert: &[CurrentTableItem],
+    ) -> QueryResult<usize> {
+        upsert_current_table_items_with(conn, items_to_insert, OverwritePolicy::Overwrite)
+    }
+
+    fn upsert_current_table_items_with(
+        conn: &mut PgConnection,
+        items_to_insert: &[CurrentTableItem],
+        policy: OverwritePolicy,
+    ) -> QueryResult<usize> {
+        use schema::current_table_items::dsl::*;
+        CurrentRowUpsert::new("current_table_items", policy).execute(
+            conn,
+            diesel::insert_into(schema::current_table_items::table)
+                .values(items_to_insert)
+                .on_conflict((table_handle, key_hash))
+                .do_update()
+                .set((
+                    decoded_value.eq(excluded(decoded_value)),
+                    is_deleted.eq(excluded(is_deleted)),
+                    last_transaction_version.eq(excluded(last_transaction_version)),
+                )),
+            items_to_insert,
+        )
+    }
+
+    fn stored_version(conn: &mut PgConnection, handle: &str, hash: &str) -> Option<i64> {
+        use schema::current_table_items::dsl::*;
+        current_table_items
+            .filter(table_handle.eq(handle))
+            .filter(key_hash.eq(hash))
+            .select(last_transaction_version)
+            .first::<i64>(conn)
+            .optional()
+            .unwrap()
+    }
 
     #[tokio::test]
     async fn test_get_chunks_logic() {
@@ -134,4 +609,420 @@ mod test {
             (43690, 65535)
         ]);
     }
+
+    #[test]
+    fn test_get_chunks_parameter_limit() {
+        let max_params = MAX_DIESEL_PARAM_SIZE as usize;
+        for column_count in [1, 2, 3, 7, 20, 64, 255, 4096, max_params - 1, max_params] {
+            let rows_per_chunk = max_params / column_count;
+            // Around the parameters of one, two and three full chunks, e.g. 65535 and 65536
+            let item_counts = [1, rows_per_chunk, 2 * rows_per_chunk, 3 * rows_per_chunk]
+                .into_iter()
+                .flat_map(|count| [count.saturating_sub(1), count, count + 1])
+                .chain([max_params, max_params + 1].map(|params| params / column_count));
+            for num_items in item_counts.filter(|&count| count > 0) {
+                let chunks = get_chunks(num_items, column_count);
+                // Contiguous, covering every item once
+                assert_eq!(chunks.first().unwrap().0, 0);
+                assert_eq!(chunks.last().unwrap().1, num_items);
+                assert!(chunks.windows(2).all(|pair| pair[0].1 == pair[1].0));
+                for (index, &(start, end)) in chunks.iter().enumerate() {
+                    assert!(start < end, "{} rows of {}", num_items, column_count);
+                    assert!((end - start) * column_count <= max_params);
+                    // Only the last one isn't full
+                    if index + 1 < chunks.len() {
+                        assert_eq!(end - start, rows_per_chunk);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't chunk rows of 0 columns")]
+    fn test_get_chunks_no_columns() {
+        get_chunks(10, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Can't chunk rows of 65536 columns")]
+    fn test_get_chunks_too_many_columns() {
+        get_chunks(1, MAX_DIESEL_PARAM_SIZE as usize + 1);
+    }
+
+    #[test]
+    fn test_chunk_context() {
+        assert_eq!(ChunkContext::new("events", [7, 3, 9, 3]), ChunkContext {
+            table: "events",
+            versions: Some((3, 9)),
+        });
+        assert_eq!(
+            ChunkContext::new("table_metadatas", std::iter::empty()).versions,
+            None
+        );
+        let error = InsertError::new(
+            DieselError::NotFound,
+            Some(ChunkContext::new("events", [5, 6])),
+        );
+        assert_eq!(
+            error.to_string(),
+            "Failed to insert into events, versions 5-6: Record not found"
+        );
+    }
+
+    #[test]
+    fn test_insert_error_constraint() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(database_url.as_str()).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+
+        // Far past any ledger the tests index, so there's no transaction for it
+        let version = 1 << 50;
+        let event = |sequence_number: i64| EventModel {
+            sequence_number,
+            creation_number: 0,
+            account_address: format!("0x{:064x}", 0xe7),
+            transaction_version: version + sequence_number,
+            transaction_block_height: 0,
+            type_: "0x1::test::Event".to_string(),
+            data: serde_json::json!({}),
+            event_index: Some(0),
+            event_account_address: None,
+            event_module: None,
+            event_name: None,
+            event_type_params: None,
+            block_timestamp: None,
+        };
+        let events = [event(0), event(1)];
+        let error = execute_with_context(
+            &mut conn,
+            diesel::insert_into(schema::events::table).values(&events[..]),
+            None,
+            ChunkContext::new(
+                "events",
+                events.iter().map(|event| event.transaction_version),
+            ),
+        )
+        .unwrap_err();
+        assert_eq!(error.constraint.as_deref(), Some("fk_transaction_versions"));
+        assert!(error
+            .detail
+            .as_deref()
+            .unwrap()
+            .contains("is not present in table"));
+        assert!(error.to_string().starts_with(&format!(
+            "Failed to insert into events, versions {}-{}: ",
+            version,
+            version + 1
+        )));
+    }
+
+    /// Keeping the earliest sighting of a pool, as `custom_dex_processor` does
+    const EARLIEST: &str = " WHERE dex_pools.first_seen_version >= EXCLUDED.first_seen_version ";
+    const STRICTLY_EARLIER: &str =
+        " WHERE dex_pools.first_seen_version > EXCLUDED.first_seen_version ";
+
+    fn upsert_dex_pools(
+        conn: &mut PgConnection,
+        pools: &[DexPool],
+        upsert: GuardedUpsert,
+        where_clause: Option<&'static str>,
+    ) -> QueryResult<usize> {
+        use schema::dex_pools::dsl::*;
+        upsert.execute(
+            conn,
+            diesel::insert_into(schema::dex_pools::table)
+                .values(pools)
+                .on_conflict(pool)
+                .do_update()
+                .set((
+                    pool_address.eq(excluded(pool_address)),
+                    first_seen_version.eq(excluded(first_seen_version)),
+                )),
+            where_clause,
+            pools,
+        )
+    }
+
+    #[test]
+    fn test_guarded_upsert_skips_overwrites() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(database_url.as_str()).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        conn.begin_test_transaction().unwrap();
+
+        let dex_pool = |address: &str, version: i64| DexPool {
+            pool: "guarded_upsert_test::Pool".to_string(),
+            protocol: "test".to_string(),
+            pool_address: address.to_string(),
+            coin_x: "0x1::aptos_coin::AptosCoin".to_string(),
+            coin_y: "0xcafe::gem::Gem".to_string(),
+            first_seen_version: version,
+        };
+        let stored = |conn: &mut PgConnection| {
+            schema::dex_pools::table
+                .find("guarded_upsert_test::Pool")
+                .select((
+                    schema::dex_pools::pool_address,
+                    schema::dex_pools::first_seen_version,
+                ))
+                .first::<(String, i64)>(conn)
+                .unwrap()
+        };
+        let earliest =
+            |policy| GuardedUpsert::new("dex_pools", policy).skip_unless(STRICTLY_EARLIER);
+        let skipped = || {
+            BACKFILL_GUARD_SKIPPED_ROWS
+                .with_label_values(&["dex_pools"])
+                .get()
+        };
+        let upsert = |conn: &mut PgConnection, pool, guarded| {
+            upsert_dex_pools(conn, &[pool], guarded, Some(EARLIEST)).unwrap()
+        };
+        assert_eq!(
+            upsert(
+                &mut conn,
+                dex_pool("0xa", 100),
+                earliest(OverwritePolicy::Skip)
+            ),
+            1
+        );
+
+        // Skipped at the same version, only an earlier sighting is taken
+        let before = skipped();
+        assert_eq!(
+            upsert(
+                &mut conn,
+                dex_pool("0xb", 100),
+                earliest(OverwritePolicy::Skip)
+            ),
+            0
+        );
+        assert!(skipped() > before);
+        assert_eq!(stored(&mut conn), ("0xa".to_string(), 100));
+        assert_eq!(
+            upsert(
+                &mut conn,
+                dex_pool("0xc", 90),
+                earliest(OverwritePolicy::Skip)
+            ),
+            1
+        );
+        assert_eq!(stored(&mut conn), ("0xc".to_string(), 90));
+        // Inside a window the query's own where clause applies
+        assert_eq!(
+            upsert(
+                &mut conn,
+                dex_pool("0xd", 90),
+                earliest(OverwritePolicy::Overwrite)
+            ),
+            1
+        );
+        assert_eq!(stored(&mut conn), ("0xd".to_string(), 90));
+
+        // Without a where clause, nothing conflicting is updated when skipping
+        let unconditional = |conn: &mut PgConnection, pool, policy| {
+            upsert_dex_pools(conn, &[pool], GuardedUpsert::new("dex_pools", policy), None).unwrap()
+        };
+        assert_eq!(
+            unconditional(&mut conn, dex_pool("0xe", 50), OverwritePolicy::Skip),
+            0
+        );
+        assert_eq!(stored(&mut conn), ("0xd".to_string(), 90));
+        assert_eq!(
+            unconditional(&mut conn, dex_pool("0xe", 95), OverwritePolicy::Overwrite),
+            1
+        );
+        assert_eq!(stored(&mut conn), ("0xe".to_string(), 95));
+    }
+
+    #[test]
+    fn test_current_row_upsert_skips_replays() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(database_url.as_str()).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        conn.begin_test_transaction().unwrap();
+
+        let handle = "0xcurrent_row_upsert_skip_test";
+        let item = |version: i64| CurrentTableItem {
+            table_handle: handle.to_string(),
+            key_hash: "a".to_string(),
+            key: "0x1".to_string(),
+            decoded_key: serde_json::json!("key"),
+            decoded_value: Some(serde_json::json!(version)),
+            last_transaction_version: version,
+            is_deleted: false,
+        };
+        let skip = |conn: &mut PgConnection, version| {
+            upsert_current_table_items_with(conn, &[item(version)], OverwritePolicy::Skip).unwrap()
+        };
+        assert_eq!(skip(&mut conn, 100), 1);
+        // A replay of the same version is skipped, live processing moving on isn't
+        assert_eq!(skip(&mut conn, 100), 0);
+        assert_eq!(skip(&mut conn, 101), 1);
+        assert_eq!(stored_version(&mut conn, handle, "a"), Some(101));
+        // Inside a window the replay overwrites
+        assert_eq!(
+            upsert_current_table_items_with(&mut conn, &[item(101)], OverwritePolicy::Overwrite)
+                .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_current_row_upsert_rejects_backfill_regressions() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(database_url.as_str()).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+
+        let handle = "0xcurrent_row_upsert_test";
+        let item = |hash: &str, version: i64| CurrentTableItem {
+            table_handle: handle.to_string(),
+            key_hash: hash.to_string(),
+            key: "0x1".to_string(),
+            decoded_key: serde_json::json!("key"),
+            decoded_value: Some(serde_json::json!(version)),
+            last_transaction_version: version,
+            is_deleted: false,
+        };
+        diesel::delete(
+            schema::current_table_items::table
+                .filter(schema::current_table_items::table_handle.eq(handle)),
+        )
+        .execute(&mut conn)
+        .unwrap();
+
+        // Live processing is ahead at version 100
+        let live = vec![item("a", 100), item("b", 100)];
+        assert_eq!(upsert_current_table_items(&mut conn, &live).unwrap(), 2);
+
+        // A backfill over versions 10..20 must only touch keys live hasn't seen
+        let backfill = vec![item("a", 10), item("b", 20), item("c", 15)];
+        assert_eq!(upsert_current_table_items(&mut conn, &backfill).unwrap(), 1);
+        assert_eq!(stored_version(&mut conn, handle, "a"), Some(100));
+        assert_eq!(stored_version(&mut conn, handle, "b"), Some(100));
+        assert_eq!(stored_version(&mut conn, handle, "c"), Some(15));
+        assert!(
+            CURRENT_ROW_REGRESSIONS_REJECTED
+                .with_label_values(&["current_table_items"])
+                .get()
+                >= 2
+        );
+
+        // Replaying the same version is not a regression
+        assert_eq!(
+            upsert_current_table_items(&mut conn, &[item("a", 100)]).unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_current_row_upsert_guards_values_mentioning_where() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(database_url.as_str()).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        conn.begin_test_transaction().unwrap();
+
+        let handle = "0xcurrent_row_upsert_where_test";
+        let item = |version: i64, value: &str| CurrentTableItem {
+            table_handle: handle.to_string(),
+            key_hash: "a".to_string(),
+            key: "0x1".to_string(),
+            decoded_key: serde_json::json!("key"),
+            decoded_value: Some(serde_json::json!({ "name": value })),
+            last_transaction_version: version,
+            is_deleted: false,
+        };
+        let stored_value = |conn: &mut PgConnection| {
+            schema::current_table_items::table
+                .filter(schema::current_table_items::table_handle.eq(handle))
+                .select(schema::current_table_items::decoded_value)
+                .first::<Option<serde_json::Value>>(conn)
+                .unwrap()
+        };
+        assert_eq!(
+            upsert_current_table_items(&mut conn, &[item(100, "here")]).unwrap(),
+            1
+        );
+
+        // An older row is rejected whatever its values say
+        assert_eq!(
+            upsert_current_table_items(&mut conn, &[item(10, "somewhere")]).unwrap(),
+            0
+        );
+        assert_eq!(stored_version(&mut conn, handle, "a"), Some(100));
+        // So is a replay outside a backfill window
+        assert_eq!(
+            upsert_current_table_items_with(
+                &mut conn,
+                &[item(100, "WHERE 1=0")],
+                OverwritePolicy::Skip
+            )
+            .unwrap(),
+            0
+        );
+        assert_eq!(
+            stored_value(&mut conn),
+            Some(serde_json::json!({ "name": "here" }))
+        );
+    }
+
+    #[test]
+    fn test_pool_timeout_names_processor() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let pool = new_db_pool_with(
+            database_url.as_str(),
+            1,
+            Duration::from_millis(200),
+            Some(Duration::from_secs(5)),
+        )
+        .unwrap();
+
+        // Two batches at once, the second waits for the first's connection
+        let mut first = get_connection(&pool, "custom_token_processor").unwrap();
+        let second = std::thread::scope(|scope| {
+            scope
+                .spawn(|| get_connection(&pool, "custom_token_processor"))
+                .join()
+                .unwrap()
+        });
+        let error = second.unwrap_err();
+        assert_eq!(error.processor, "custom_token_processor");
+        assert!(error.to_string().starts_with(
+            "custom_token_processor got no connection within 200ms from its pool of 1: "
+        ));
+
+        #[derive(QueryableByName)]
+        struct Setting {
+            #[diesel(sql_type = diesel::sql_types::Text)]
+            statement_timeout: String,
+        }
+        let setting = diesel::sql_query("SHOW statement_timeout")
+            .get_result::<Setting>(&mut first)
+            .unwrap();
+        assert_eq!(setting.statement_timeout, "5s");
+    }
 }
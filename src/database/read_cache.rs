@@ -0,0 +1,360 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Read-through caches of hot current rows, so that the same lookups repeated batch after batch
+//! don't each take a connection: current table items by table handle and key hash, current
+//! account resources by address and type, and coin infos by coin type, e.g. the APT coin info
+//! `custom_coin_processor` reads for every batch. Rows that don't exist are cached too.
+//!
+//! An entry is kept for `ttl_millis` and at most `max_entries` entries per cache, the least
+//! recently used going first. A processor invalidates the keys it writes once its batch is
+//! committed, so a lookup after the commit reads the new row. A lookup that was already reading
+//! from Postgres when a key of its cache was invalidated doesn't cache what it read, which may be
+//! from before the commit. Writes from other processes are only seen once the entry expires.
+//!
+//! Lookups are counted in `indexer_read_cache_lookups_count` by cache and hit or miss, and
+//! invalidated entries in `indexer_read_cache_invalidations_count`.
+
+use crate::{
+    counters::{READ_CACHE_INVALIDATIONS, READ_CACHE_LOOKUPS},
+    custom::driver::config::ReadCacheConfig,
+    database::PgPoolConnection,
+    models::coin_models::coin_infos::CoinInfoQuery,
+    queries::as_of::{
+        get_account_resource_as_of, get_table_item_as_of, AccountResourceAsOf, TableItemAsOf,
+    },
+    util::standardize_address,
+};
+use diesel::PgConnection;
+use once_cell::sync::Lazy;
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+pub static CURRENT_TABLE_ITEMS: Lazy<ReadCache<(String, String), TableItemAsOf>> =
+    Lazy::new(|| ReadCache::new("current_table_items"));
+pub static CURRENT_RESOURCES: Lazy<ReadCache<(String, String), AccountResourceAsOf>> =
+    Lazy::new(|| ReadCache::new("current_resources"));
+pub static COIN_INFOS: Lazy<ReadCache<String, CoinInfoQuery>> =
+    Lazy::new(|| ReadCache::new("coin_infos"));
+
+pub fn init(config: &ReadCacheConfig) {
+    CURRENT_TABLE_ITEMS.configure(config);
+    CURRENT_RESOURCES.configure(config);
+    COIN_INFOS.configure(config);
+}
+
+/// The current item of the table at `table_handle` with the key hashing to `key_hash`
+pub fn current_table_item(
+    conn: &mut PgPoolConnection,
+    table_handle: &str,
+    key_hash: &str,
+) -> anyhow::Result<Option<TableItemAsOf>> {
+    let key = (standardize_address(table_handle), key_hash.to_string());
+    CURRENT_TABLE_ITEMS.get_or_load(&key, || {
+        get_table_item_as_of(conn, &key.0, &key.1, i64::MAX)
+    })
+}
+
+/// The current resource of type `type_` at `address`
+pub fn current_resource(
+    conn: &mut PgPoolConnection,
+    address: &str,
+    type_: &str,
+) -> anyhow::Result<Option<AccountResourceAsOf>> {
+    let key = (standardize_address(address), type_.to_string());
+    CURRENT_RESOURCES.get_or_load(&key, || {
+        get_account_resource_as_of(conn, &key.0, &key.1, i64::MAX)
+    })
+}
+
+/// The coin info of `coin_type`. Coin infos don't change once written, so only a coin info
+/// cached as missing can be stale, until it expires.
+pub fn coin_info(
+    conn: &mut PgConnection,
+    coin_type: &str,
+) -> diesel::QueryResult<Option<CoinInfoQuery>> {
+    COIN_INFOS.get_or_load(&coin_type.to_string(), || {
+        CoinInfoQuery::get_by_coin_type(coin_type.to_string(), conn)
+    })
+}
+
+pub struct ReadCache<K, V> {
+    name: &'static str,
+    state: Mutex<State<K, V>>,
+}
+
+struct State<K, V> {
+    enabled: bool,
+    ttl: Duration,
+    max_entries: usize,
+    entries: HashMap<K, Entry<V>>,
+    /// Keys by the tick of their last use, the least recently used first
+    recency: BTreeMap<u64, K>,
+    tick: u64,
+    /// Bumped by every invalidation, a load that started before one isn't cached
+    epoch: u64,
+}
+
+struct Entry<V> {
+    value: Option<V>,
+    loaded_at: Instant,
+    tick: u64,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> ReadCache<K, V> {
+    /// A cache that loads every lookup until it's configured
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            state: Mutex::new(State {
+                enabled: false,
+                ttl: Duration::ZERO,
+                max_entries: 0,
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+                tick: 0,
+                epoch: 0,
+            }),
+        }
+    }
+
+    pub fn configure(&self, config: &ReadCacheConfig) {
+        let mut state = self.state.lock().unwrap();
+        state.enabled = config.enabled;
+        state.ttl = Duration::from_millis(config.ttl_millis);
+        state.max_entries = config.max_entries;
+        state.epoch += 1;
+        state.entries.clear();
+        state.recency.clear();
+    }
+
+    /// The cached value of `key`, or the one `load` reads, which is then cached unless `key`'s
+    /// cache was invalidated meanwhile
+    pub fn get_or_load<E>(
+        &self,
+        key: &K,
+        load: impl FnOnce() -> Result<Option<V>, E>,
+    ) -> Result<Option<V>, E> {
+        let epoch = {
+            let mut state = self.state.lock().unwrap();
+            if !state.enabled {
+                drop(state);
+                return load();
+            }
+            if let Some(value) = state.get(key) {
+                READ_CACHE_LOOKUPS
+                    .with_label_values(&[self.name, "hit"])
+                    .inc();
+                return Ok(value);
+            }
+            state.epoch
+        };
+        READ_CACHE_LOOKUPS
+            .with_label_values(&[self.name, "miss"])
+            .inc();
+        let value = load()?;
+        let mut state = self.state.lock().unwrap();
+        if state.epoch == epoch {
+            state.insert(key.clone(), value.clone());
+        }
+        Ok(value)
+    }
+
+    /// Drops the entries of `keys`, to be called once the rows are committed
+    pub fn invalidate(&self, keys: impl IntoIterator<Item = K>) {
+        let mut state = self.state.lock().unwrap();
+        if !state.enabled {
+            return;
+        }
+        state.epoch += 1;
+        let mut invalidated = 0;
+        for key in keys {
+            if let Some(entry) = state.entries.remove(&key) {
+                state.recency.remove(&entry.tick);
+                invalidated += 1;
+            }
+        }
+        READ_CACHE_INVALIDATIONS
+            .with_label_values(&[self.name])
+            .inc_by(invalidated);
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> State<K, V> {
+    fn get(&mut self, key: &K) -> Option<Option<V>> {
+        let entry = self.entries.get(key)?;
+        if entry.loaded_at.elapsed() >= self.ttl {
+            let tick = entry.tick;
+            self.entries.remove(key);
+            self.recency.remove(&tick);
+            return None;
+        }
+        let value = entry.value.clone();
+        let previous_tick = entry.tick;
+        self.tick += 1;
+        let tick = self.tick;
+        self.entries.get_mut(key).unwrap().tick = tick;
+        self.recency.remove(&previous_tick);
+        self.recency.insert(tick, key.clone());
+        Some(value)
+    }
+
+    fn insert(&mut self, key: K, value: Option<V>) {
+        if self.max_entries == 0 {
+            return;
+        }
+        self.tick += 1;
+        let entry = Entry {
+            value,
+            loaded_at: Instant::now(),
+            tick: self.tick,
+        };
+        if let Some(previous) = self.entries.insert(key.clone(), entry) {
+            self.recency.remove(&previous.tick);
+        }
+        self.recency.insert(self.tick, key);
+        while self.entries.len() > self.max_entries {
+            let Some(&oldest) = self.recency.keys().next() else {
+                break;
+            };
+            if let Some(key) = self.recency.remove(&oldest) {
+                self.entries.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        convert::Infallible,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            mpsc, Arc,
+        },
+        thread,
+    };
+
+    fn cache(ttl_millis: u64, max_entries: usize) -> ReadCache<u64, u64> {
+        let cache = ReadCache::new("test");
+        cache.configure(&ReadCacheConfig {
+            enabled: true,
+            ttl_millis,
+            max_entries,
+        });
+        cache
+    }
+
+    fn load(value: u64) -> impl FnOnce() -> Result<Option<u64>, Infallible> {
+        move || Ok(Some(value))
+    }
+
+    #[test]
+    fn test_read_through() {
+        let cache = cache(60_000, 2);
+        assert_eq!(cache.get_or_load(&1, load(10)), Ok(Some(10)));
+        // Served from the cache
+        assert_eq!(cache.get_or_load(&1, load(11)), Ok(Some(10)));
+        // Missing rows are cached as missing
+        assert_eq!(
+            cache.get_or_load(&2, || Ok::<_, Infallible>(None)),
+            Ok(None)
+        );
+        assert_eq!(cache.get_or_load(&2, load(20)), Ok(None));
+
+        // 1 was used after 2, so 2 goes first
+        cache.get_or_load(&1, load(12)).unwrap();
+        cache.get_or_load(&3, load(30)).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get_or_load(&1, load(13)), Ok(Some(10)));
+        assert_eq!(cache.get_or_load(&2, load(21)), Ok(Some(21)));
+
+        cache.invalidate([1]);
+        assert_eq!(cache.get_or_load(&1, load(14)), Ok(Some(14)));
+        // Errors aren't cached
+        assert_eq!(cache.get_or_load(&4, || Err("down")), Err("down"));
+        assert_eq!(cache.get_or_load(&4, load(40)), Ok(Some(40)));
+
+        let expiring = self::cache(0, 2);
+        expiring.get_or_load(&1, load(10)).unwrap();
+        assert_eq!(expiring.get_or_load(&1, load(11)), Ok(Some(11)));
+
+        let disabled = ReadCache::new("test");
+        disabled.get_or_load(&1, load(10)).unwrap();
+        assert_eq!(disabled.get_or_load(&1, load(11)), Ok(Some(11)));
+        assert!(disabled.is_empty());
+    }
+
+    #[test]
+    fn test_load_racing_a_write() {
+        let cache = Arc::new(cache(60_000, 100));
+        let (loading_tx, loading_rx) = mpsc::channel();
+        let (written_tx, written_rx) = mpsc::channel();
+        // A lookup reads the row before the write commits and finishes after it's invalidated
+        let reader = {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                cache.get_or_load(&1, || {
+                    loading_tx.send(()).unwrap();
+                    written_rx.recv().unwrap();
+                    Ok::<_, Infallible>(Some(1))
+                })
+            })
+        };
+        loading_rx.recv().unwrap();
+        cache.invalidate([1]);
+        written_tx.send(()).unwrap();
+        assert_eq!(reader.join().unwrap(), Ok(Some(1)));
+        assert_eq!(cache.get_or_load(&1, load(2)), Ok(Some(2)));
+    }
+
+    #[test]
+    fn test_read_after_write() {
+        let cache = Arc::new(cache(60_000, 8));
+        // Rows of 4 keys, each written by its own thread
+        let rows = Arc::new((0..4).map(|_| AtomicU64::new(0)).collect::<Vec<_>>());
+        let threads = (0..4u64)
+            .map(|key| {
+                let (cache, rows) = (cache.clone(), rows.clone());
+                thread::spawn(move || {
+                    for value in 1..=500 {
+                        rows[key as usize].store(value, Ordering::SeqCst);
+                        cache.invalidate([key]);
+                        for other in 0..4u64 {
+                            let read = cache
+                                .get_or_load(&other, || {
+                                    Ok::<_, Infallible>(Some(
+                                        rows[other as usize].load(Ordering::SeqCst),
+                                    ))
+                                })
+                                .unwrap()
+                                .unwrap();
+                            if other == key {
+                                assert_eq!(read, value);
+                            }
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        for key in 0..4u64 {
+            assert_eq!(cache.get_or_load(&key, load(0)), Ok(Some(500)));
+        }
+    }
+}
@@ -1,7 +1,21 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::{
+    custom::driver::{
+        http_sink::HttpSinkError,
+        ordered_commit::EarlierBatchFailed,
+        publish_retry::{self, PublishError},
+        retry_budget::RetryBudgetExhausted,
+    },
+    database::InsertError,
+};
 use anyhow::Error;
+use diesel::{
+    r2d2::PoolError,
+    result::{DatabaseErrorKind, Error as DieselError},
+};
+use rdkafka::error::KafkaError;
 
 // Error, start_version, end_version, name
 type ErrorWithVersionAndName = (Error, u64, u64, &'static str);
@@ -15,6 +29,18 @@ pub enum TransactionProcessingError {
     TransactionCommitError(ErrorWithVersionAndName),
 }
 
+/// What the driver does about a failed batch, see `TransactionProcessingError::kind`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Likely to pass when processed again, e.g. a pool timeout or a broker that's down. The
+    /// driver retries the batch with backoff, see `driver::batch_retry`.
+    Retryable,
+    /// The batch's data couldn't be parsed, and would fail the same way again
+    Parse,
+    /// Needs someone to look into it, e.g. a schema mismatch or a constraint violation
+    Fatal,
+}
+
 impl TransactionProcessingError {
     pub fn inner(&self) -> &ErrorWithVersionAndName {
         match self {
@@ -22,4 +48,279 @@ impl TransactionProcessingError {
             TransactionProcessingError::TransactionCommitError(ewv) => ewv,
         }
     }
+
+    /// Classifies the error by the first of its causes that's known, `Fatal` if none is
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            TransactionProcessingError::ConnectionPoolError(_) => ErrorKind::Retryable,
+            TransactionProcessingError::TransactionCommitError((err, ..)) => classify(err),
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.kind() == ErrorKind::Retryable
+    }
+}
+
+impl ErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::Retryable => "retryable",
+            ErrorKind::Parse => "parse",
+            ErrorKind::Fatal => "fatal",
+        }
+    }
+}
+
+/// Kind of a batch's error, see `TransactionProcessingError::kind`
+pub fn classify(err: &Error) -> ErrorKind {
+    err.chain()
+        .find_map(classify_cause)
+        .unwrap_or(ErrorKind::Fatal)
+}
+
+fn classify_cause(cause: &(dyn std::error::Error + 'static)) -> Option<ErrorKind> {
+    if let Some(err) = cause.downcast_ref::<DieselError>() {
+        return Some(classify_diesel(err));
+    }
+    if let Some(err) = cause.downcast_ref::<InsertError>() {
+        return Some(classify_diesel(&err.error));
+    }
+    if let Some(err) = cause.downcast_ref::<PublishError>() {
+        return Some(match err {
+            PublishError::Failed { error, .. } => classify_kafka(error),
+            PublishError::BudgetExhausted(_) | PublishError::QueueFull { .. } => {
+                ErrorKind::Retryable
+            },
+            PublishError::Serialization { .. } => ErrorKind::Parse,
+        });
+    }
+    if let Some(err) = cause.downcast_ref::<KafkaError>() {
+        return Some(classify_kafka(err));
+    }
+    if let Some(err) = cause.downcast_ref::<HttpSinkError>() {
+        return Some(if err.is_transient() {
+            ErrorKind::Retryable
+        } else {
+            ErrorKind::Fatal
+        });
+    }
+    // Retries of transient errors that ran out, and batches stopped by an earlier one's error
+    if cause.is::<PoolError>()
+        || cause.is::<RetryBudgetExhausted>()
+        || cause.is::<EarlierBatchFailed>()
+    {
+        return Some(ErrorKind::Retryable);
+    }
+    if cause.is::<serde_json::Error>()
+        || cause.is::<bigdecimal::ParseBigDecimalError>()
+        || cause.is::<std::num::ParseIntError>()
+        || cause.is::<hex::FromHexError>()
+    {
+        return Some(ErrorKind::Parse);
+    }
+    None
+}
+
+fn classify_diesel(err: &DieselError) -> ErrorKind {
+    match err {
+        DieselError::DatabaseError(kind, info) => match kind {
+            DatabaseErrorKind::SerializationFailure
+            | DatabaseErrorKind::UnableToSendCommand
+            | DatabaseErrorKind::ClosedConnection => ErrorKind::Retryable,
+            // Postgres errors diesel has no kind for, by their message
+            DatabaseErrorKind::Unknown if is_transient_message(info.message()) => {
+                ErrorKind::Retryable
+            },
+            _ => ErrorKind::Fatal,
+        },
+        _ => ErrorKind::Fatal,
+    }
+}
+
+/// Deadlocks, lock and statement timeouts, and connections dropped or refused by the server
+fn is_transient_message(message: &str) -> bool {
+    [
+        "deadlock detected",
+        "canceling statement due to",
+        "could not obtain lock",
+        "terminating connection",
+        "too many clients",
+        "the database system is",
+        "server closed the connection",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+fn classify_kafka(err: &KafkaError) -> ErrorKind {
+    if publish_retry::is_transient(err) {
+        ErrorKind::Retryable
+    } else {
+        ErrorKind::Fatal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::PgPool;
+    use anyhow::Context;
+    use diesel::{r2d2::ConnectionManager, PgConnection};
+    use rdkafka::types::RDKafkaErrorCode;
+    use std::time::Duration;
+
+    fn database_error(kind: DatabaseErrorKind, message: &str) -> DieselError {
+        DieselError::DatabaseError(kind, Box::new(message.to_string()))
+    }
+
+    fn commit_error(err: Error) -> TransactionProcessingError {
+        TransactionProcessingError::TransactionCommitError((err, 100, 199, "errors_test"))
+    }
+
+    #[test]
+    fn test_classify_database_errors() {
+        let kind = |err: DieselError| classify(&Error::new(err));
+        assert_eq!(
+            kind(database_error(
+                DatabaseErrorKind::SerializationFailure,
+                "could not serialize access due to concurrent update"
+            )),
+            ErrorKind::Retryable
+        );
+        assert_eq!(
+            kind(database_error(
+                DatabaseErrorKind::ClosedConnection,
+                "server closed the connection unexpectedly"
+            )),
+            ErrorKind::Retryable
+        );
+        assert_eq!(
+            kind(database_error(
+                DatabaseErrorKind::Unknown,
+                "deadlock detected"
+            )),
+            ErrorKind::Retryable
+        );
+        // A schema mismatch
+        assert_eq!(
+            kind(database_error(
+                DatabaseErrorKind::Unknown,
+                "column \"entry_function_id_str\" of relation \"user_transactions\" does not exist"
+            )),
+            ErrorKind::Fatal
+        );
+        assert_eq!(
+            kind(database_error(
+                DatabaseErrorKind::NotNullViolation,
+                "null value in column \"hash\" violates not-null constraint"
+            )),
+            ErrorKind::Fatal
+        );
+        assert_eq!(kind(DieselError::NotFound), ErrorKind::Fatal);
+
+        // Wrapped as the inserts wrap them, and with context
+        let insert_error = InsertError::new(
+            database_error(
+                DatabaseErrorKind::SerializationFailure,
+                "could not serialize",
+            ),
+            None,
+        );
+        assert_eq!(classify(&Error::new(insert_error)), ErrorKind::Retryable);
+        let with_context = Err::<(), _>(database_error(
+            DatabaseErrorKind::UniqueViolation,
+            "duplicate key value violates unique constraint \"transactions_pkey\"",
+        ))
+        .context("Failed to insert transactions")
+        .unwrap_err();
+        assert!(!commit_error(with_context).is_retryable());
+    }
+
+    #[test]
+    fn test_classify_pool_timeout() {
+        let pool = PgPool::builder()
+            .connection_timeout(Duration::from_millis(10))
+            .build_unchecked(ConnectionManager::<PgConnection>::new(
+                "postgres://localhost:1/errors_test",
+            ));
+        let timeout = pool.get().map(|_| ()).unwrap_err();
+        assert_eq!(classify(&Error::new(timeout)), ErrorKind::Retryable);
+        let error = TransactionProcessingError::ConnectionPoolError((
+            anyhow::anyhow!("Could not get a connection"),
+            100,
+            199,
+            "errors_test",
+        ));
+        assert!(error.is_retryable());
+    }
+
+    #[test]
+    fn test_classify_publish_errors() {
+        let failed = |code| {
+            Error::new(PublishError::Failed {
+                operation: "transactions".to_string(),
+                attempts: 5,
+                error: KafkaError::MessageProduction(code),
+            })
+        };
+        assert_eq!(
+            classify(&failed(RDKafkaErrorCode::AllBrokersDown)),
+            ErrorKind::Retryable
+        );
+        assert_eq!(
+            classify(&failed(RDKafkaErrorCode::TopicAuthorizationFailed)),
+            ErrorKind::Fatal
+        );
+        assert_eq!(
+            classify(&failed(RDKafkaErrorCode::MessageSizeTooLarge)),
+            ErrorKind::Fatal
+        );
+        assert_eq!(
+            classify(&Error::new(KafkaError::Flush(
+                RDKafkaErrorCode::OperationTimedOut
+            ))),
+            ErrorKind::Retryable
+        );
+        assert_eq!(
+            classify(&Error::new(PublishError::QueueFull {
+                depth: 100_000,
+                waited: std::time::Duration::from_secs(60),
+            })),
+            ErrorKind::Retryable
+        );
+        // Not worth retrying, whatever wraps it
+        let unserializable = PublishError::Serialization {
+            model: "EventModel".to_string(),
+            version: Some(691595),
+            error: "key must be a string".to_string(),
+        };
+        assert!(!unserializable.is_transient());
+        assert_eq!(
+            classify(&Error::new(unserializable).context("Failed to publish the batch")),
+            ErrorKind::Parse
+        );
+    }
+
+    #[test]
+    fn test_classify_other_errors() {
+        let parse_error = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        assert_eq!(classify(&Error::new(parse_error)), ErrorKind::Parse);
+        let parse_error = "0x12".parse::<u64>().unwrap_err();
+        assert_eq!(
+            commit_error(Error::new(parse_error).context("Failed to parse the amount")).kind(),
+            ErrorKind::Parse
+        );
+        let stopped = EarlierBatchFailed {
+            failed_version: 0,
+            start_version: 100,
+            end_version: 199,
+        };
+        assert!(commit_error(Error::new(stopped)).is_retryable());
+        // Unknown errors need a look
+        assert_eq!(
+            commit_error(anyhow::anyhow!("Validation failed")).kind(),
+            ErrorKind::Fatal
+        );
+    }
 }
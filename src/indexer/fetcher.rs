@@ -1,12 +1,21 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::counters::{FETCHED_TRANSACTION, UNABLE_TO_FETCH_TRANSACTION};
+use crate::{
+    counters::{
+        FETCHED_TRANSACTION, FETCH_BYTES_PER_VERSION, FETCH_SPLITS, UNABLE_TO_FETCH_TRANSACTION,
+    },
+    custom::driver::{
+        replication_lag,
+        retry_budget::{self, ErrorClass},
+        sharding::ShardSpec,
+    },
+};
 use aptos_api::Context;
 use aptos_api_types::{AsConverter, LedgerInfo, Transaction, TransactionOnChainData};
 use aptos_logger::prelude::*;
 use futures::{channel::mpsc, SinkExt};
-use std::{sync::Arc, time::Duration};
+use std::{collections::VecDeque, sync::Arc, time::Duration};
 use tokio::task::JoinHandle;
 
 // Default Values
@@ -23,6 +32,7 @@ pub struct Fetcher {
     current_version: u64,
     highest_known_version: u64,
     transactions_sender: mpsc::Sender<Vec<Transaction>>,
+    bytes_per_version: BytesPerVersion,
 }
 
 impl Fetcher {
@@ -34,26 +44,67 @@ impl Fetcher {
     ) -> Self {
         Self {
             context,
-            options,
             chain_id: 0,
             current_version: starting_version,
             highest_known_version: 0,
             transactions_sender,
+            bytes_per_version: BytesPerVersion::new(
+                options
+                    .fetch_budget
+                    .map(|budget| budget.window)
+                    .unwrap_or(0),
+            ),
+            options,
         }
     }
 
     pub fn set_highest_known_version(&mut self) -> anyhow::Result<()> {
         let info = self.context.get_latest_ledger_info_wrapped()?;
         self.highest_known_version = info.ledger_version.0;
+        // A draining shard treats the drain version as the end of the ledger
+        if let Some(drain_at_version) = self.options.shard.and_then(|shard| shard.drain_at_version)
+        {
+            self.highest_known_version = self
+                .highest_known_version
+                .min(drain_at_version.saturating_sub(1));
+        }
+        // So does a backfill its end version
+        if let Some(end_version) = self.options.end_version {
+            self.highest_known_version = self.highest_known_version.min(end_version);
+        }
         self.chain_id = info.chain_id;
         Ok(())
     }
 
+    /// First version from `version` on to fetch, the next one of the shard's slices with sharding
+    fn next_to_fetch(&self, version: u64) -> u64 {
+        match &self.options.shard {
+            Some(shard) => shard.next_owned(version),
+            None => version,
+        }
+    }
+
     /// Will keep looping and checking the latest ledger info to see if there are new transactions
     /// If there are, it will set the highest known version
     async fn ensure_highest_known_version(&mut self) {
         let mut empty_loops = 0;
-        while self.highest_known_version == 0 || self.current_version > self.highest_known_version {
+        while self.highest_known_version == 0
+            || self.next_to_fetch(self.current_version) > self.highest_known_version
+        {
+            if let Some(shard) = self
+                .options
+                .shard
+                .filter(|shard| shard.is_drained(self.current_version))
+            {
+                sample!(
+                    SampleRate::Duration(Duration::from_secs(60)),
+                    aptos_logger::info!(
+                        shard_index = shard.shard_index,
+                        drain_at_version = shard.drain_at_version,
+                        "Shard drained, waiting for a restart with the new shard count",
+                    )
+                );
+            }
             if empty_loops > 0 {
                 tokio::time::sleep(self.options.starting_retry_time).await;
             }
@@ -78,16 +129,28 @@ impl Fetcher {
 
     /// Main loop for fetching transactions
     /// Fetches transactions in batches of `options.transaction_fetch_batch_size` and sends them to the processor channel.
+    /// With a `fetch_budget`, batches shrink so that a fetch of the recent bytes per version stays within its target.
+    /// While `replication_lag` pauses, no new fetches start.
     /// If the processor channel is full, it will wait for the processor to catch up.
     /// 1. Get the latest ledger info, and set the highest known version (if we've caught up)
     /// 2. Determine how many batches of size `options.transaction_fetch_batch_size` we need to catch up
     /// 3. Spawn tasks which fetch 'raw' `OnChainTransactions` from storage, and convert them to `Transaction`s. We spawn at most `options.max_tasks` tasks.
     /// 4. We wait for all the tasks to complete, then send the `Transaction`s to the processor, via the `transactions_sender` channel.
     pub async fn run(&mut self) {
-        let transaction_fetch_batch_size = self.options.transaction_fetch_batch_size;
+        let fetch_budget = self.options.fetch_budget;
         loop {
+            // Nothing new is fetched while the replicas catch up
+            if replication_lag::wait_if_paused().await {
+                continue;
+            }
             self.ensure_highest_known_version().await;
 
+            let transaction_fetch_batch_size = fetch_size(
+                self.options.transaction_fetch_batch_size,
+                fetch_budget.as_ref(),
+                self.bytes_per_version.average(),
+            );
+
             info!(
                 current_version = self.current_version,
                 highest_known_version = self.highest_known_version,
@@ -97,37 +160,57 @@ impl Fetcher {
 
             let fetch_start = chrono::Utc::now().naive_utc();
             let mut tasks = vec![];
-            let mut starting_version = self.current_version;
+            let mut starting_version = self.next_to_fetch(self.current_version);
             let mut num_fetches = 0;
 
             while num_fetches < self.options.max_tasks
                 && starting_version <= self.highest_known_version
             {
-                let num_transactions_to_fetch = std::cmp::min(
+                let mut num_transactions_to_fetch = std::cmp::min(
                     transaction_fetch_batch_size as u64,
                     self.highest_known_version - starting_version + 1,
                 ) as u16;
+                // A fetch never runs into another shard's slice
+                if let Some(shard) = &self.options.shard {
+                    num_transactions_to_fetch = std::cmp::min(
+                        num_transactions_to_fetch as u64,
+                        shard.slice_end(starting_version) - starting_version + 1,
+                    ) as u16;
+                }
 
                 let context = self.context.clone();
                 let highest_known_version = self.highest_known_version;
                 let task = tokio::spawn(async move {
-                    fetch_nexts(
+                    fetch_nexts_within_budget(
                         context,
                         starting_version,
                         highest_known_version,
                         num_transactions_to_fetch,
+                        fetch_budget,
                     )
                     .await
                 });
                 tasks.push(task);
-                starting_version += num_transactions_to_fetch as u64;
+                starting_version =
+                    self.next_to_fetch(starting_version + num_transactions_to_fetch as u64);
                 num_fetches += 1;
             }
 
-            let batches = match futures::future::try_join_all(tasks).await {
+            let fetched = match futures::future::try_join_all(tasks).await {
                 Ok(res) => res,
                 Err(err) => panic!("Error fetching transaction batches: {:?}", err),
             };
+            if fetch_budget.is_some() {
+                for range in &fetched {
+                    let versions = range.batches.iter().map(|batch| batch.len() as u64).sum();
+                    self.bytes_per_version.record(versions, range.bytes);
+                }
+                FETCH_BYTES_PER_VERSION.set(self.bytes_per_version.average().unwrap_or(0) as i64);
+            }
+            let batches = fetched
+                .into_iter()
+                .flat_map(|range| range.batches)
+                .collect::<Vec<_>>();
 
             let versions_fetched = batches.iter().fold(0, |acc, v| acc + v.len());
             let fetch_millis = (chrono::Utc::now().naive_utc() - fetch_start).num_milliseconds();
@@ -176,6 +259,7 @@ async fn fetch_raw_txns_with_retries(
     num_transactions_to_fetch: u16,
     max_retries: u8,
 ) -> Vec<TransactionOnChainData> {
+    let budget = retry_budget::for_fetch(starting_version, num_transactions_to_fetch);
     let mut retries = 0;
     loop {
         match context.get_transactions(starting_version, num_transactions_to_fetch, ledger_version)
@@ -184,17 +268,27 @@ async fn fetch_raw_txns_with_retries(
             Err(err) => {
                 UNABLE_TO_FETCH_TRANSACTION.inc();
                 retries += 1;
-                if retries >= max_retries {
+                // With a budget, it decides when to give up instead of `max_retries`
+                let exhausted = match &budget {
+                    Some(budget) => budget
+                        .retry(ErrorClass::Fetch, "get_transactions", &err)
+                        .err()
+                        .map(|exhausted| exhausted.to_string()),
+                    None => (retries >= max_retries).then(|| {
+                        format!(
+                            "Could not fetch {} transactions after {} retries, starting at {}: {:?}",
+                            num_transactions_to_fetch, retries, starting_version, err
+                        )
+                    }),
+                };
+                if let Some(exhausted) = exhausted {
                     error!(
                         starting_version = starting_version,
                         num_transactions = num_transactions_to_fetch,
                         error = format!("{:?}", err),
                         "Could not fetch transactions: retries exhausted",
                     );
-                    panic!(
-                        "Could not fetch {} transactions after {} retries, starting at {}: {:?}",
-                        num_transactions_to_fetch, retries, starting_version, err
-                    );
+                    panic!("{}", exhausted);
                 } else {
                     error!(
                         starting_version = starting_version,
@@ -209,7 +303,7 @@ async fn fetch_raw_txns_with_retries(
     }
 }
 
-async fn fetch_nexts(
+pub(crate) async fn fetch_nexts(
     context: Arc<Context>,
     starting_version: u64,
     ledger_version: u64,
@@ -226,6 +320,93 @@ async fn fetch_nexts(
     )
     .await;
 
+    convert_raw_txns(&context, starting_version, raw_txns, start_millis)
+}
+
+/// Transactions of a range fetched by `fetch_nexts_within_budget`
+pub(crate) struct FetchedRange {
+    /// One batch, or consecutive pieces of the range when it went over the hard cap
+    pub batches: Vec<Vec<Transaction>>,
+    /// Size of the raw transactions, only measured with a budget
+    pub bytes: u64,
+}
+
+/// `fetch_nexts`, except that a range whose raw transactions add up to more than the budget's
+/// `hard_cap_bytes` is dropped and fetched again in halves, down to single versions. Each piece
+/// is converted before the next one is fetched and becomes a batch of its own.
+pub(crate) async fn fetch_nexts_within_budget(
+    context: Arc<Context>,
+    starting_version: u64,
+    ledger_version: u64,
+    num_transactions_to_fetch: u16,
+    fetch_budget: Option<FetchBudget>,
+) -> FetchedRange {
+    let Some(fetch_budget) = fetch_budget else {
+        let batch = fetch_nexts(
+            context,
+            starting_version,
+            ledger_version,
+            num_transactions_to_fetch,
+        )
+        .await;
+        return FetchedRange {
+            batches: vec![batch],
+            bytes: 0,
+        };
+    };
+    let mut fetched = FetchedRange {
+        batches: vec![],
+        bytes: 0,
+    };
+    // Ranges left to fetch, the next one last
+    let mut pending = vec![(starting_version, num_transactions_to_fetch)];
+    while let Some((start_version, num_transactions)) = pending.pop() {
+        let start_millis = chrono::Utc::now().naive_utc();
+        let raw_txns = fetch_raw_txns_with_retries(
+            context.clone(),
+            start_version,
+            ledger_version,
+            num_transactions,
+            3,
+        )
+        .await;
+        let bytes = raw_txns
+            .iter()
+            .map(|raw_txn| bcs::serialized_size(raw_txn).unwrap_or_default() as u64)
+            .sum::<u64>();
+        if bytes > fetch_budget.hard_cap_bytes && num_transactions > 1 {
+            FETCH_SPLITS.inc();
+            warn!(
+                starting_version = start_version,
+                num_transactions = num_transactions,
+                bytes = bytes,
+                hard_cap_bytes = fetch_budget.hard_cap_bytes,
+                "Fetched transactions over the hard cap, fetching them again in halves",
+            );
+            drop(raw_txns);
+            let half = num_transactions / 2;
+            pending.push((start_version + half as u64, num_transactions - half));
+            pending.push((start_version, half));
+            continue;
+        }
+        fetched.bytes += bytes;
+        fetched.batches.push(convert_raw_txns(
+            &context,
+            start_version,
+            raw_txns,
+            start_millis,
+        ));
+    }
+    fetched
+}
+
+/// Converts the raw transactions fetched from `starting_version` on to API transactions
+fn convert_raw_txns(
+    context: &Context,
+    starting_version: u64,
+    raw_txns: Vec<TransactionOnChainData>,
+    start_millis: chrono::NaiveDateTime,
+) -> Vec<Transaction> {
     let (_, _, block_event) = context
         .db
         .get_block_info_by_version(starting_version)
@@ -340,6 +521,69 @@ async fn fetch_nexts(
     transactions
 }
 
+/// Bytes per version of the last `window` fetches, for sizing the next one
+#[derive(Debug)]
+struct BytesPerVersion {
+    window: usize,
+    /// Versions and bytes of each fetch, oldest first
+    fetches: VecDeque<(u64, u64)>,
+}
+
+impl BytesPerVersion {
+    fn new(window: usize) -> Self {
+        Self {
+            window: window.max(1),
+            fetches: VecDeque::new(),
+        }
+    }
+
+    fn record(&mut self, versions: u64, bytes: u64) {
+        if versions == 0 {
+            return;
+        }
+        self.fetches.push_back((versions, bytes));
+        while self.fetches.len() > self.window {
+            self.fetches.pop_front();
+        }
+    }
+
+    fn average(&self) -> Option<u64> {
+        let (versions, bytes) = self
+            .fetches
+            .iter()
+            .fold((0, 0), |(versions, bytes), fetch| {
+                (versions + fetch.0, bytes + fetch.1)
+            });
+        (versions > 0).then(|| (bytes / versions).max(1))
+    }
+}
+
+/// Versions to request per fetch: the batch size, or fewer if that many versions of the recent
+/// size would go over the budget's target
+fn fetch_size(
+    batch_size: u16,
+    fetch_budget: Option<&FetchBudget>,
+    bytes_per_version: Option<u64>,
+) -> u16 {
+    match (fetch_budget, bytes_per_version) {
+        (Some(fetch_budget), Some(bytes_per_version)) => {
+            (fetch_budget.target_bytes / bytes_per_version).clamp(1, batch_size as u64) as u16
+        },
+        _ => batch_size,
+    }
+}
+
+/// Limits on the size of fetches, measured as the BCS size of the raw transactions
+#[derive(Clone, Copy, Debug)]
+pub struct FetchBudget {
+    /// Bytes a fetch aims for, given the bytes per version of recent fetches
+    pub target_bytes: u64,
+    /// A fetch over this is fetched again in halves
+    pub hard_cap_bytes: u64,
+    /// Fetches the bytes per version is averaged over
+    pub window: usize,
+}
+
 #[derive(Clone, Debug)]
 pub struct TransactionFetcherOptions {
     pub starting_retry_time_millis: u64,
@@ -349,6 +593,11 @@ pub struct TransactionFetcherOptions {
     pub transaction_fetch_batch_size: u16,
     pub max_pending_batches: usize,
     pub max_tasks: usize,
+    pub fetch_budget: Option<FetchBudget>,
+    /// Only fetch the versions of this shard, see `custom::driver::sharding`
+    pub shard: Option<ShardSpec>,
+    /// Fetch nothing past this version, see `custom::driver::backfill`
+    pub end_version: Option<u64>,
 }
 
 fn default_if_zero<T>(value: Option<T>, default: T) -> T
@@ -393,8 +642,27 @@ impl TransactionFetcherOptions {
             transaction_fetch_batch_size,
             max_pending_batches,
             max_tasks: std::cmp::max(max_tasks, 1),
+            fetch_budget: None,
+            shard: None,
+            end_version: None,
         }
     }
+
+    /// Sizes fetches by bytes, see `FetchBudget`
+    pub fn with_fetch_budget(mut self, fetch_budget: FetchBudget) -> Self {
+        self.fetch_budget = Some(fetch_budget);
+        self
+    }
+
+    pub fn with_shard(mut self, shard: ShardSpec) -> Self {
+        self.shard = Some(shard);
+        self
+    }
+
+    pub fn with_end_version(mut self, end_version: u64) -> Self {
+        self.end_version = Some(end_version);
+        self
+    }
 }
 
 impl Default for TransactionFetcherOptions {
@@ -479,6 +747,15 @@ impl TransactionFetcherTrait for TransactionFetcher {
     }
 }
 
+impl Drop for TransactionFetcher {
+    /// Stops fetching when the fetcher is dropped, e.g. when a processor is reloaded
+    fn drop(&mut self) {
+        if let Some(fetcher_handle) = self.fetcher_handle.take() {
+            fetcher_handle.abort();
+        }
+    }
+}
+
 /// For mocking TransactionFetcher in tests
 #[async_trait::async_trait]
 pub trait TransactionFetcherTrait: Send + Sync {
@@ -490,3 +767,37 @@ pub trait TransactionFetcherTrait: Send + Sync {
 
     async fn start(&mut self);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fetch_size_follows_bytes_per_version() {
+        let fetch_budget = FetchBudget {
+            target_bytes: 1_000_000,
+            hard_cap_bytes: 4_000_000,
+            window: 2,
+        };
+        let mut bytes_per_version = BytesPerVersion::new(fetch_budget.window);
+        assert_eq!(bytes_per_version.average(), None);
+        assert_eq!(fetch_size(500, Some(&fetch_budget), None), 500);
+
+        bytes_per_version.record(100, 100_000);
+        assert_eq!(bytes_per_version.average(), Some(1_000));
+        assert_eq!(
+            fetch_size(500, Some(&fetch_budget), bytes_per_version.average()),
+            500
+        );
+
+        // The small fetch falls out of the window
+        bytes_per_version.record(10, 100_000_000);
+        bytes_per_version.record(10, 100_000_000);
+        assert_eq!(bytes_per_version.average(), Some(10_000_000));
+        assert_eq!(
+            fetch_size(500, Some(&fetch_budget), bytes_per_version.average()),
+            1
+        );
+        assert_eq!(fetch_size(500, None, bytes_per_version.average()), 500);
+    }
+}
@@ -4,5 +4,6 @@
 pub mod errors;
 pub mod fetcher;
 pub mod processing_result;
+pub mod recording;
 pub mod tailer;
 pub mod transaction_processor;
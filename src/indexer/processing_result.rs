@@ -1,11 +1,20 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
+use serde::{Deserialize, Serialize};
+use std::ops::AddAssign;
+
 #[derive(Debug)]
 pub struct ProcessingResult {
     pub name: &'static str,
     pub start_version: u64,
     pub end_version: u64,
+    /// What the batch produced, all zero for processors that don't count it
+    pub counts: RowCounts,
+    /// Messages sent to a dead letter topic instead of their own, see `driver::publisher`
+    pub dead_lettered: usize,
+    /// Transactions not published again, see `driver::publish_dedupe`
+    pub republish_skipped: usize,
 }
 
 impl ProcessingResult {
@@ -14,6 +23,60 @@ impl ProcessingResult {
             name,
             start_version,
             end_version,
+            counts: RowCounts::default(),
+            dead_lettered: 0,
+            republish_skipped: 0,
         }
     }
+
+    pub fn with_counts(mut self, counts: RowCounts) -> Self {
+        self.counts = counts;
+        self
+    }
+
+    pub fn with_dead_lettered(mut self, dead_lettered: usize) -> Self {
+        self.dead_lettered = dead_lettered;
+        self
+    }
+
+    pub fn with_republish_skipped(mut self, republish_skipped: usize) -> Self {
+        self.republish_skipped = republish_skipped;
+        self
+    }
+}
+
+/// Rows of each entity a batch produced, to tell an empty batch from one whose rows were dropped
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RowCounts {
+    pub transactions: usize,
+    pub user_transactions: usize,
+    pub events: usize,
+    pub write_set_changes: usize,
+    pub move_resources: usize,
+    pub table_items: usize,
+}
+
+impl RowCounts {
+    /// By entity, as labelled in `indexer_processed_rows_total`
+    pub fn entries(&self) -> [(&'static str, usize); 6] {
+        [
+            ("transactions", self.transactions),
+            ("user_transactions", self.user_transactions),
+            ("events", self.events),
+            ("write_set_changes", self.write_set_changes),
+            ("move_resources", self.move_resources),
+            ("table_items", self.table_items),
+        ]
+    }
+}
+
+impl AddAssign for RowCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.transactions += other.transactions;
+        self.user_transactions += other.user_transactions;
+        self.events += other.events;
+        self.write_set_changes += other.write_set_changes;
+        self.move_resources += other.move_resources;
+        self.table_items += other.table_items;
+    }
 }
@@ -0,0 +1,452 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Record fetched batches to a file once and replay them without a fullnode, e.g. for tests.
+//!
+//! File format (all integers little endian):
+//! - header: the magic `APTIDXRC` followed by the format version as u16
+//! - frames until EOF: kind (u8), payload length (u32), payload
+//!   - `FRAME_LEDGER_INFO`: ledger info as JSON
+//!   - `FRAME_BATCH`: first and last version (u64 each), then the batch as a JSON array of API
+//!     transactions, as the fetcher produced them, redacted if redaction is enabled
+//!
+//! Readers reject recordings with a newer format version than they know.
+
+use crate::{
+    custom::driver::redaction::Redactor,
+    indexer::fetcher::{TransactionFetcherOptions, TransactionFetcherTrait},
+};
+use anyhow::{bail, ensure, Context, Result};
+use aptos_api_types::{LedgerInfo, Transaction, U64};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+pub const RECORDING_MAGIC: &[u8; 8] = b"APTIDXRC";
+pub const RECORDING_FORMAT_VERSION: u16 = 1;
+
+/// The transactions of the tailer tests, see `tests/recordings/README.md`
+#[cfg(test)]
+pub(crate) const TAILER_FIXTURES_RECORDING: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/recordings/tailer_fixtures.aptrec"
+);
+
+const FRAME_LEDGER_INFO: u8 = 1;
+const FRAME_BATCH: u8 = 2;
+
+#[derive(Debug)]
+pub enum RecordingFrame {
+    LedgerInfo(LedgerInfo),
+    Batch {
+        start_version: u64,
+        end_version: u64,
+        transactions: Vec<Transaction>,
+    },
+}
+
+pub struct RecordingWriter {
+    writer: BufWriter<File>,
+}
+
+impl RecordingWriter {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create recording {}", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(RECORDING_MAGIC)?;
+        writer.write_all(&RECORDING_FORMAT_VERSION.to_le_bytes())?;
+        writer.flush()?;
+        Ok(Self { writer })
+    }
+
+    pub fn write_ledger_info(&mut self, ledger_info: &LedgerInfo) -> Result<()> {
+        self.write_frame(FRAME_LEDGER_INFO, &serde_json::to_vec(ledger_info)?)
+    }
+
+    pub fn write_batch(&mut self, transactions: &[Transaction]) -> Result<()> {
+        let (start_version, end_version) = match (transactions.first(), transactions.last()) {
+            (Some(first), Some(last)) => (
+                first.version().unwrap_or_default(),
+                last.version().unwrap_or_default(),
+            ),
+            _ => return Ok(()),
+        };
+        let mut payload = Vec::with_capacity(16);
+        payload.extend_from_slice(&start_version.to_le_bytes());
+        payload.extend_from_slice(&end_version.to_le_bytes());
+        serde_json::to_writer(&mut payload, transactions)?;
+        self.write_frame(FRAME_BATCH, &payload)
+    }
+
+    /// Flushed per frame so that a killed recorder still leaves a readable file
+    fn write_frame(&mut self, kind: u8, payload: &[u8]) -> Result<()> {
+        let len = u32::try_from(payload.len()).context("Recording frame too large")?;
+        self.writer.write_all(&[kind])?;
+        self.writer.write_all(&len.to_le_bytes())?;
+        self.writer.write_all(payload)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+pub struct RecordingReader {
+    reader: BufReader<File>,
+    pub format_version: u16,
+}
+
+impl RecordingReader {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open recording {}", path.display()))?;
+        let mut reader = BufReader::new(file);
+        let mut magic = [0u8; 8];
+        reader
+            .read_exact(&mut magic)
+            .context("Recording is missing its header")?;
+        ensure!(
+            &magic == RECORDING_MAGIC,
+            "{} is not an indexer recording",
+            path.display()
+        );
+        let mut format_version = [0u8; 2];
+        reader.read_exact(&mut format_version)?;
+        let format_version = u16::from_le_bytes(format_version);
+        ensure!(
+            format_version <= RECORDING_FORMAT_VERSION,
+            "Recording {} has format version {}, this build reads up to {}",
+            path.display(),
+            format_version,
+            RECORDING_FORMAT_VERSION
+        );
+        Ok(Self {
+            reader,
+            format_version,
+        })
+    }
+
+    pub fn next_frame(&mut self) -> Result<Option<RecordingFrame>> {
+        let mut kind = [0u8; 1];
+        match self.reader.read_exact(&mut kind) {
+            Ok(()) => {},
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        let mut len = [0u8; 4];
+        self.reader.read_exact(&mut len)?;
+        let mut payload = vec![0u8; u32::from_le_bytes(len) as usize];
+        self.reader
+            .read_exact(&mut payload)
+            .context("Recording ends in the middle of a frame")?;
+        match kind[0] {
+            FRAME_LEDGER_INFO => Ok(Some(RecordingFrame::LedgerInfo(serde_json::from_slice(
+                &payload,
+            )?))),
+            FRAME_BATCH => {
+                ensure!(payload.len() >= 16, "Batch frame is too short");
+                let start_version = u64::from_le_bytes(payload[0..8].try_into().unwrap());
+                let end_version = u64::from_le_bytes(payload[8..16].try_into().unwrap());
+                let raw_txns: Vec<serde_json::Value> = serde_json::from_slice(&payload[16..])?;
+                let transactions = raw_txns
+                    .iter()
+                    .map(|raw_txn| {
+                        let mut txn: Transaction = serde_json::from_value(raw_txn.clone())?;
+                        restore_block_info(&mut txn, raw_txn);
+                        Ok(txn)
+                    })
+                    .collect::<Result<Vec<_>>>()
+                    .with_context(|| {
+                        format!(
+                            "Failed to decode recorded batch {}-{}",
+                            start_version, end_version
+                        )
+                    })?;
+                Ok(Some(RecordingFrame::Batch {
+                    start_version,
+                    end_version,
+                    transactions,
+                }))
+            },
+            other => bail!("Unknown recording frame kind {}", other),
+        }
+    }
+}
+
+/// The fetcher sets `block_height` and `epoch` on the transaction info, but deserializing only
+/// fills them in for some transaction types, so take them from the raw JSON again
+pub(crate) fn restore_block_info(txn: &mut Transaction, raw_txn: &serde_json::Value) {
+    let parse = |field: &str| {
+        raw_txn
+            .get(field)
+            .and_then(|value| value.as_str())
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(U64::from)
+    };
+    let (block_height, epoch) = (parse("block_height"), parse("epoch"));
+    let info = match txn {
+        Transaction::UserTransaction(txn) => &mut txn.info,
+        Transaction::GenesisTransaction(txn) => &mut txn.info,
+        Transaction::BlockMetadataTransaction(txn) => &mut txn.info,
+        Transaction::StateCheckpointTransaction(txn) => &mut txn.info,
+        Transaction::BlockEpilogueTransaction(txn) => &mut txn.info,
+        Transaction::ValidatorTransaction(txn) => txn.transaction_info_mut(),
+        Transaction::PendingTransaction(_) => return,
+    };
+    if info.block_height.is_none() {
+        info.block_height = block_height;
+    }
+    if info.epoch.is_none() {
+        info.epoch = epoch;
+    }
+}
+
+/// Wraps a fetcher and writes everything it returns to a recording
+pub struct RecordingFetcher<F> {
+    inner: F,
+    writer: RecordingWriter,
+    /// Redacts batches before they're written, instead of the tailer after they're returned
+    redactor: Option<Arc<Redactor>>,
+}
+
+impl<F: TransactionFetcherTrait> RecordingFetcher<F> {
+    pub fn new(inner: F, path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            inner,
+            writer: RecordingWriter::create(path)?,
+            redactor: None,
+        })
+    }
+
+    /// Records, and returns, the batches redacted. The tailer must not redact them again, as a
+    /// target would replace the markers themselves.
+    pub fn with_redactor(mut self, redactor: Arc<Redactor>) -> Self {
+        self.redactor = Some(redactor);
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl<F: TransactionFetcherTrait> TransactionFetcherTrait for RecordingFetcher<F> {
+    async fn fetch_next_batch(&mut self) -> Vec<Transaction> {
+        let mut transactions = self.inner.fetch_next_batch().await;
+        if let Some(redactor) = &self.redactor {
+            redactor.redact(&mut transactions);
+        }
+        self.writer
+            .write_batch(&transactions)
+            .unwrap_or_else(|err| panic!("Failed to record batch: {:?}", err));
+        transactions
+    }
+
+    fn fetch_ledger_info(&mut self) -> LedgerInfo {
+        let ledger_info = self.inner.fetch_ledger_info();
+        self.writer
+            .write_ledger_info(&ledger_info)
+            .unwrap_or_else(|err| panic!("Failed to record ledger info: {:?}", err));
+        ledger_info
+    }
+
+    async fn set_version(&mut self, version: u64) {
+        self.inner.set_version(version).await;
+    }
+
+    async fn start(&mut self) {
+        // Snapshot the ledger the recording starts from
+        self.fetch_ledger_info();
+        self.inner.start().await;
+    }
+}
+
+/// Serves the transactions of a recording in batches of `transaction_fetch_batch_size`, starting
+/// at the version set with `set_version`, waiting `latency` before each batch. Once served or
+/// skipped, transactions aren't served again.
+pub struct ReplayFetcher {
+    transactions: VecDeque<Transaction>,
+    ledger_info: Option<LedgerInfo>,
+    batch_size: usize,
+    latency: Duration,
+    started: bool,
+}
+
+impl ReplayFetcher {
+    pub fn open(
+        path: impl AsRef<Path>,
+        options: TransactionFetcherOptions,
+        latency: Duration,
+    ) -> Result<Self> {
+        let mut reader = RecordingReader::open(path)?;
+        let mut transactions = vec![];
+        let mut ledger_info = None;
+        while let Some(frame) = reader.next_frame()? {
+            match frame {
+                RecordingFrame::LedgerInfo(info) => ledger_info = Some(info),
+                RecordingFrame::Batch {
+                    transactions: batch,
+                    ..
+                } => transactions.extend(batch),
+            }
+        }
+        // Batches are recorded in the order the processor tasks fetched them
+        transactions.sort_by_key(|txn| txn.version().unwrap_or_default());
+        transactions.dedup_by_key(|txn| txn.version());
+        Ok(Self {
+            transactions: transactions.into(),
+            ledger_info,
+            batch_size: options.transaction_fetch_batch_size as usize,
+            latency,
+            started: false,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl TransactionFetcherTrait for ReplayFetcher {
+    async fn fetch_next_batch(&mut self) -> Vec<Transaction> {
+        if !self.started || self.transactions.is_empty() {
+            return vec![];
+        }
+        if !self.latency.is_zero() {
+            tokio::time::sleep(self.latency).await;
+        }
+        let batch_size = self.batch_size.min(self.transactions.len());
+        self.transactions.drain(..batch_size).collect()
+    }
+
+    fn fetch_ledger_info(&mut self) -> LedgerInfo {
+        self.ledger_info
+            .clone()
+            .unwrap_or_else(|| panic!("Recording has no ledger info"))
+    }
+
+    async fn set_version(&mut self, version: u64) {
+        if self.started {
+            panic!("ReplayFetcher already started!");
+        }
+        while self
+            .transactions
+            .front()
+            .map_or(false, |txn| txn.version().unwrap_or_default() < version)
+        {
+            self.transactions.pop_front();
+        }
+    }
+
+    async fn start(&mut self) {
+        if self.started {
+            panic!("ReplayFetcher already started!");
+        }
+        self.started = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_replay_committed_recording() {
+        let options = TransactionFetcherOptions::new(None, None, Some(2), None, 1);
+        let mut fetcher =
+            ReplayFetcher::open(TAILER_FIXTURES_RECORDING, options, Duration::ZERO).unwrap();
+        assert_eq!(fetcher.fetch_ledger_info().chain_id, 4);
+        // Nothing is served before the fetcher is started
+        assert!(fetcher.fetch_next_batch().await.is_empty());
+
+        fetcher.set_version(1).await;
+        fetcher.start().await;
+        let mut batches = vec![];
+        loop {
+            let batch = fetcher.fetch_next_batch().await;
+            if batch.is_empty() {
+                break;
+            }
+            batches.push(
+                batch
+                    .iter()
+                    .map(|txn| (txn.version().unwrap(), txn.type_str()))
+                    .collect::<Vec<_>>(),
+            );
+        }
+        assert_eq!(batches, vec![
+            vec![
+                (69158, "block_metadata_transaction"),
+                (260885, "user_transaction")
+            ],
+            vec![
+                (691595, "user_transaction"),
+                (691596, "state_checkpoint_transaction")
+            ],
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_records_redacted_batches() {
+        use crate::custom::driver::{
+            config::RedactionConfig,
+            redaction::{RedactionPattern, RedactionRules, REDACTION_MARKER_PREFIX},
+        };
+
+        let options = TransactionFetcherOptions::new(None, None, Some(10), None, 1);
+        let replay =
+            ReplayFetcher::open(TAILER_FIXTURES_RECORDING, options, Duration::ZERO).unwrap();
+        let path = std::env::temp_dir().join(format!("redacted_{}.aptrec", std::process::id()));
+        let redactor = Redactor::new(RedactionConfig {
+            enabled: true,
+            rules: RedactionRules {
+                patterns: vec![RedactionPattern {
+                    name: "digits".to_string(),
+                    regex: "[0-9]+".to_string(),
+                }],
+                targets: vec![],
+            },
+            ..Default::default()
+        })
+        .unwrap();
+        let mut fetcher = RecordingFetcher::new(replay, &path)
+            .unwrap()
+            .with_redactor(Arc::new(redactor));
+        fetcher.start().await;
+        let mut returned = vec![];
+        loop {
+            let batch = fetcher.fetch_next_batch().await;
+            if batch.is_empty() {
+                break;
+            }
+            returned.extend(batch);
+        }
+        let returned = serde_json::to_string(&returned).unwrap();
+        assert!(returned.contains(REDACTION_MARKER_PREFIX));
+
+        // What's recorded is what the tailer was given
+        let mut recorded = vec![];
+        let mut reader = RecordingReader::open(&path).unwrap();
+        while let Some(frame) = reader.next_frame().unwrap() {
+            if let RecordingFrame::Batch { transactions, .. } = frame {
+                recorded.extend(transactions);
+            }
+        }
+        assert_eq!(serde_json::to_string(&recorded).unwrap(), returned);
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_restores_block_info() {
+        let mut reader = RecordingReader::open(TAILER_FIXTURES_RECORDING).unwrap();
+        assert_eq!(reader.format_version, RECORDING_FORMAT_VERSION);
+        while let Some(frame) = reader.next_frame().unwrap() {
+            if let RecordingFrame::Batch { transactions, .. } = frame {
+                for txn in transactions {
+                    let info = txn.transaction_info().unwrap();
+                    assert!(info.block_height.is_some() && info.epoch.is_some());
+                }
+            }
+        }
+    }
+}
@@ -1,6 +1,15 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 use crate::{
+    custom::driver::{
+        app_scope::AppScope,
+        batch_manifest::{BatchManifest, BatchManifests},
+        ordered_commit::{self, CommitOrder, Ticket},
+        priority::{observe_latency, PriorityLane, MAIN_LANE},
+        publisher::FlushHandle,
+        redaction::Redactor,
+        sharding::{self, ShardSpec},
+    },
     database::{execute_with_better_error, PgDbPool},
     indexer::{
         errors::TransactionProcessingError,
@@ -16,7 +25,8 @@ use crate::{
 };
 use anyhow::{ensure, Context, Result};
 use aptos_api::context::Context as ApiContext;
-use aptos_logger::{debug, info};
+use aptos_api_types::Transaction;
+use aptos_logger::{debug, info, warn};
 use chrono::ParseError;
 use diesel::{
     pg::upsert::excluded,
@@ -30,11 +40,29 @@ use tokio::{sync::Mutex, task::JoinHandle};
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
+/// A fetched batch, see `Tailer::fetch_next_scoped_batch`
+struct ScopedBatch {
+    transactions: Result<Vec<Transaction>>,
+    /// Fetched, before narrowing to the app scope
+    num_txns: u64,
+    start_version: u64,
+    end_version: u64,
+    end_timestamp: u64,
+    /// With ordered commits
+    ticket: Option<Ticket>,
+}
+
 #[derive(Clone)]
 pub struct Tailer {
     pub transaction_fetcher: Arc<Mutex<dyn TransactionFetcherTrait>>,
     processor: Arc<dyn TransactionProcessor>,
     connection_pool: PgDbPool,
+    priority_lane: Option<Arc<PriorityLane>>,
+    redactor: Option<Arc<Redactor>>,
+    app_scope: Option<Arc<AppScope>>,
+    publisher_flush: Option<FlushHandle>,
+    commit_order: Option<Arc<CommitOrder>>,
+    batch_manifests: Option<Arc<BatchManifests>>,
 }
 
 impl Tailer {
@@ -50,9 +78,107 @@ impl Tailer {
             transaction_fetcher: Arc::new(Mutex::new(transaction_fetcher)),
             connection_pool,
             processor,
+            priority_lane: None,
+            redactor: None,
+            app_scope: None,
+            publisher_flush: None,
+            commit_order: None,
+            batch_manifests: None,
         })
     }
 
+    /// Keep the priority lane alive for as long as the tailer, see `PriorityLane::spawn_tail`
+    pub fn with_priority_lane(mut self, priority_lane: Arc<PriorityLane>) -> Self {
+        self.priority_lane = Some(priority_lane);
+        self
+    }
+
+    /// Redact every fetched batch before anything else sees it
+    pub fn with_redactor(mut self, redactor: Arc<Redactor>) -> Self {
+        self.redactor = Some(redactor);
+        self
+    }
+
+    /// Narrow every fetched batch to the app scope, after redacting it
+    pub fn with_app_scope(mut self, app_scope: Arc<AppScope>) -> Self {
+        self.app_scope = Some(app_scope);
+        self
+    }
+
+    /// Lets `flush_publisher` flush the processor's publisher
+    pub fn with_publisher_flush(mut self, publisher_flush: FlushHandle) -> Self {
+        self.publisher_flush = Some(publisher_flush);
+        self
+    }
+
+    /// Commit the batches processed at once in the order they were fetched, see
+    /// `driver::ordered_commit`
+    pub fn with_ordered_commit(mut self) -> Self {
+        self.commit_order = Some(Arc::new(CommitOrder::new()));
+        self
+    }
+
+    /// Publish a manifest of every committed batch, see `driver::batch_manifest`
+    pub fn with_batch_manifests(mut self, batch_manifests: Arc<BatchManifests>) -> Self {
+        self.batch_manifests = Some(batch_manifests);
+        self
+    }
+
+    /// The manifest of a batch that just committed, if manifests are published
+    pub fn batch_manifest(&self, result: &ProcessingResult) -> Option<BatchManifest> {
+        self.batch_manifests
+            .as_ref()
+            .map(|batch_manifests| batch_manifests.manifest(result))
+    }
+
+    /// Publishes the manifests of a round once everything the processor published is delivered,
+    /// and waits for them to be delivered
+    pub fn publish_batch_manifests(&self, manifests: &[BatchManifest]) -> Result<()> {
+        if let Some(batch_manifests) = &self.batch_manifests {
+            batch_manifests.publish(self.publisher_flush.as_ref(), manifests)?;
+        }
+        Ok(())
+    }
+
+    /// Waits up to `timeout` for what the processor published to be delivered
+    pub fn flush_publisher(&self, timeout: std::time::Duration) -> Result<()> {
+        if let Some(publisher_flush) = &self.publisher_flush {
+            publisher_flush.flush(timeout)?;
+        }
+        Ok(())
+    }
+
+    /// Keeps the fingerprints of what was published up to `end_version` in the publisher's replay
+    /// cache, once it's delivered. A batch that isn't kept is published again after a restart.
+    fn commit_replay_cache(&self, end_version: u64) {
+        if let Some(publisher_flush) = &self.publisher_flush {
+            if let Err(e) = publisher_flush.commit_replay_cache(end_version) {
+                warn!(
+                    end_version = end_version,
+                    error = format!("{:#}", e),
+                    "Failed to commit the replay cache"
+                );
+            }
+        }
+    }
+
+    /// Records `end_version` as the last version the processor published, once it's delivered,
+    /// see `driver::publish_dedupe`
+    pub fn commit_publish_checkpoint(&self, end_version: u64) -> Result<()> {
+        if let Some(publisher_flush) = &self.publisher_flush {
+            publisher_flush.commit_publish_checkpoint(end_version)?;
+        }
+        Ok(())
+    }
+
+    /// Lets the versions from `version` on be published again when reprocessed
+    pub fn forget_replayed_from(&self, version: u64) -> Result<()> {
+        if let Some(publisher_flush) = &self.publisher_flush {
+            publisher_flush.forget_replayed_from(version)?;
+        }
+        Ok(())
+    }
+
     pub fn run_migrations(&self) {
         let _ = &self
             .connection_pool
@@ -99,6 +225,7 @@ impl Tailer {
                     &mut conn,
                     diesel::insert_into(ledger_infos::table).values(LedgerInfo {
                         chain_id: new_chain_id,
+                        genesis_hash: None,
                     }),
                     None,
                 )
@@ -117,26 +244,82 @@ impl Tailer {
         info!(version = version, "Will start fetching from version");
     }
 
-    pub async fn process_next_batch(
-        &self,
-    ) -> (
-        u64,
-        Option<Result<ProcessingResult, TransactionProcessingError>>,
-    ) {
-        let transactions = self
+    /// The next fetched batch, redacted, empty if none is ready
+    pub async fn fetch_next_batch(&self) -> Vec<Transaction> {
+        let mut transactions = self
             .transaction_fetcher
             .lock()
             .await
             .fetch_next_batch()
             .await;
+        if let Some(redactor) = &self.redactor {
+            redactor.redact(&mut transactions);
+        }
+        transactions
+    }
 
+    /// The next fetched batch, redacted and narrowed to the app scope, with the versions it
+    /// spanned before narrowing. `None` if no batch is ready.
+    async fn fetch_next_scoped_batch(&self) -> Option<ScopedBatch> {
+        let mut fetcher = self.transaction_fetcher.lock().await;
+        let mut transactions = fetcher.fetch_next_batch().await;
+        if let Some(redactor) = &self.redactor {
+            redactor.redact(&mut transactions);
+        }
         let num_txns = transactions.len() as u64;
+        let start_version = transactions.first()?.version().unwrap();
+        let end_version = transactions.last()?.version().unwrap();
+        let end_timestamp = transactions.last()?.timestamp();
+        // Under the fetcher lock, so that tickets are in fetch order
+        let ticket = self
+            .commit_order
+            .as_ref()
+            .map(|order| order.ticket(start_version, end_version));
+        let transactions = match &self.app_scope {
+            // Under the fetcher lock, so that table handles are found in version order
+            Some(app_scope) => app_scope.scope(transactions),
+            None => Ok(transactions),
+        };
+        Some(ScopedBatch {
+            transactions,
+            num_txns,
+            start_version,
+            end_version,
+            end_timestamp,
+            ticket,
+        })
+    }
+
+    pub async fn process_next_batch(
+        &self,
+    ) -> (
+        u64,
+        Option<Result<ProcessingResult, TransactionProcessingError>>,
+    ) {
         // When the batch is empty b/c we're caught up
-        if num_txns == 0 {
+        let Some(ScopedBatch {
+            transactions,
+            num_txns,
+            start_version,
+            end_version,
+            end_timestamp,
+            ticket,
+        }) = self.fetch_next_scoped_batch().await
+        else {
             return (0, None);
-        }
-        let start_version = transactions.first().unwrap().version();
-        let end_version = transactions.last().unwrap().version();
+        };
+        let transactions = match transactions {
+            Ok(transactions) => transactions,
+            Err(err) => {
+                let error = TransactionProcessingError::TransactionCommitError((
+                    err,
+                    start_version,
+                    end_version,
+                    self.processor.name(),
+                ));
+                return (num_txns, Some(Err(error)));
+            },
+        };
 
         debug!(
             num_txns = num_txns,
@@ -147,12 +330,40 @@ impl Tailer {
 
         let batch_start = chrono::Utc::now().naive_utc();
 
-        let results = self
-            .processor
-            .process_transactions_with_status(transactions)
-            .await;
+        let results = match ticket {
+            Some(ticket) => {
+                let results = ordered_commit::scope(ticket.turn(), async {
+                    if !self.processor.takes_commit_turn() {
+                        ordered_commit::turn().await.map_err(|err| {
+                            TransactionProcessingError::TransactionCommitError((
+                                err.into(),
+                                start_version,
+                                end_version,
+                                self.processor.name(),
+                            ))
+                        })?;
+                    }
+                    self.processor
+                        .process_versions_with_status(transactions, start_version, end_version)
+                        .await
+                })
+                .await;
+                ticket.finish(results.is_ok()).await;
+                results
+            },
+            None => {
+                self.processor
+                    .process_versions_with_status(transactions, start_version, end_version)
+                    .await
+            },
+        };
 
         let batch_millis = (chrono::Utc::now().naive_utc() - batch_start).num_milliseconds();
+        if results.is_ok() {
+            observe_latency(MAIN_LANE, end_timestamp);
+            // Before the round's watermark is committed
+            self.commit_replay_cache(end_version);
+        }
 
         info!(
             num_txns = num_txns,
@@ -190,6 +401,21 @@ impl Tailer {
         Ok(())
     }
 
+    /// With sharding, moves the processor's own watermark up to the last version every shard
+    /// committed, and returns that version
+    pub fn update_low_watermark(
+        &self,
+        processor_name: &str,
+        shard: &ShardSpec,
+    ) -> Result<Option<u64>> {
+        let mut conn = self.connection_pool.get()?;
+        let low_watermark = sharding::low_watermark(&mut conn, shard, processor_name)?;
+        if let Some(version) = low_watermark {
+            self.update_last_processed_version(processor_name, version)?;
+        }
+        Ok(low_watermark)
+    }
+
     /// Get last version processed successfully from databse
     pub fn get_start_version(&self, processor_name: &String) -> Result<Option<i64>> {
         let mut conn = self.connection_pool.get()?;
@@ -306,19 +532,36 @@ pub async fn await_tasks<T: Debug>(tasks: Vec<JoinHandle<T>>) -> Vec<T> {
 mod test {
     use super::*;
     use crate::{
+        custom::{
+            driver::{
+                app_scope::AppScope,
+                config::{AppScopeConfig, StandbyConfig},
+                memory_publisher::RecordedMessages,
+                replay,
+                standby::Lease,
+            },
+            processors::custom_default_processor,
+            test_utils,
+        },
         database::{new_db_pool, PgPoolConnection},
+        indexer::recording::{ReplayFetcher, TAILER_FIXTURES_RECORDING},
         models::transactions::TransactionQuery,
         processors::default_processor::DefaultTransactionProcessor,
+        queries::proof_anchors::{get_proof_anchors, ProofAnchors},
+        schema,
+        util::standardize_address,
     };
     use aptos_api_test_context::new_test_context;
     use aptos_api_types::{LedgerInfo as APILedgerInfo, Transaction, U64};
     use aptos_config::config::NodeConfig;
-    use diesel::RunQueryDsl;
-    use serde_json::json;
+    use diesel::{QueryDsl, RunQueryDsl};
+    use serde_json::{json, Value};
 
     struct FakeFetcher {
         version: u64,
         chain_id: u8,
+        /// Served in order, then empty batches
+        batches: Vec<Vec<Transaction>>,
     }
 
     impl FakeFetcher {
@@ -326,6 +569,7 @@ mod test {
             Self {
                 version: 0,
                 chain_id: 0,
+                batches: vec![],
             }
         }
     }
@@ -333,7 +577,10 @@ mod test {
     #[async_trait::async_trait]
     impl TransactionFetcherTrait for FakeFetcher {
         async fn fetch_next_batch(&mut self) -> Vec<Transaction> {
-            unimplemented!();
+            if self.batches.is_empty() {
+                return vec![];
+            }
+            self.batches.remove(0)
         }
 
         fn fetch_ledger_info(&mut self) -> APILedgerInfo {
@@ -703,6 +950,25 @@ mod test {
         assert!(bmt1.is_some());
         assert_eq!(events1.len(), 1);
         assert_eq!(wsc1.len(), 2);
+        assert_eq!(
+            get_proof_anchors(&mut conn_pool.get().unwrap(), 69158).unwrap(),
+            Some(ProofAnchors {
+                version: 69158,
+                hash: "0x2b7c58ed8524d228f9d0543a82e2793d04e8871df322f976b0e7bb8c5ced4ff5"
+                    .to_string(),
+                state_change_hash:
+                    "0x3ead9eb40582fbc7df5e02f72280931dc3e6f1aae45dc832966b4cd972dac4b8".to_string(),
+                event_root_hash:
+                    "0x2e481956dea9c59b6fc9f823fe5f4c45efce173e42c551c1fe073b5d76a65504".to_string(),
+                state_checkpoint_hash: None,
+                accumulator_root_hash:
+                    "0xb0ad602f805eb20c398f0f29a3504a9ef38bcc52c9c451deb9ec4a2d18807b49".to_string(),
+            })
+        );
+        assert_eq!(
+            get_proof_anchors(&mut conn_pool.get().unwrap(), 1).unwrap(),
+            None
+        );
 
         // This is the genesis transaction
         let (tx0, ut0, bmt0, events0, wsc0) =
@@ -919,4 +1185,406 @@ mod test {
         tailer.set_fetcher_version(4).await;
         assert!(tailer.check_or_update_chain_id().await.is_ok());
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replay_recording() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let (conn_pool, mut tailer) = setup_indexer().unwrap();
+        tailer.transaction_fetcher = Arc::new(Mutex::new(
+            ReplayFetcher::open(
+                TAILER_FIXTURES_RECORDING,
+                TransactionFetcherOptions::default(),
+                std::time::Duration::ZERO,
+            )
+            .unwrap(),
+        ));
+        assert_eq!(tailer.check_or_update_chain_id().await.unwrap(), 4);
+        tailer.set_fetcher_version(0).await;
+        tailer.transaction_fetcher.lock().await.start().await;
+        loop {
+            match tailer.process_next_batch().await {
+                (0, _) => break,
+                (_, Some(result)) => {
+                    result.unwrap();
+                },
+                (_, None) => unreachable!(),
+            }
+        }
+
+        for (version, type_) in [
+            (0, "genesis_transaction"),
+            (69158, "block_metadata_transaction"),
+            (260885, "user_transaction"),
+            (691595, "user_transaction"),
+            (691596, "state_checkpoint_transaction"),
+        ] {
+            let (txn, ..) =
+                TransactionQuery::get_by_version(version, &mut conn_pool.get().unwrap()).unwrap();
+            assert_eq!(txn.type_, type_);
+        }
+        let (_, _, bmt, events, _) =
+            TransactionQuery::get_by_version(69158, &mut conn_pool.get().unwrap()).unwrap();
+        assert!(bmt.is_some());
+        assert_eq!(events.len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_standby_failover() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let (conn_pool, mut leader) = setup_indexer().unwrap();
+        let mut standby = leader.clone();
+        let driver_config = test_utils::driver_config(json!({ "transaction_topic": "txns" }));
+        // What each instance published, through a producer of its own
+        let mut published = vec![];
+        for tailer in [&mut leader, &mut standby] {
+            tailer.transaction_fetcher = Arc::new(Mutex::new(
+                ReplayFetcher::open(
+                    TAILER_FIXTURES_RECORDING,
+                    TransactionFetcherOptions::new(None, None, Some(2), None, 1),
+                    std::time::Duration::ZERO,
+                )
+                .unwrap(),
+            ));
+            let (processor, messages) = replay::in_memory_processor(
+                custom_default_processor::NAME,
+                Some(conn_pool.clone()),
+                &driver_config,
+                test_utils::CHAIN_ID,
+            )
+            .unwrap();
+            tailer.processor = processor;
+            published.push(messages);
+        }
+        let processor_name = leader.processor.name().to_string();
+        let config = StandbyConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        let leader_lease = Lease::new(
+            &processor_name,
+            &StandbyConfig {
+                instance_id: Some("leader".to_string()),
+                ..config.clone()
+            },
+            conn_pool.clone(),
+        );
+        let standby_lease = Lease::new(
+            &processor_name,
+            &StandbyConfig {
+                instance_id: Some("standby".to_string()),
+                ..config
+            },
+            conn_pool.clone(),
+        );
+
+        // The leader processes a batch while the standby is kept off the lease
+        assert!(leader_lease.try_acquire().unwrap());
+        assert!(!standby_lease.try_acquire().unwrap());
+        assert_eq!(leader.check_or_update_chain_id().await.unwrap(), 4);
+        leader.set_fetcher_version(0).await;
+        leader.transaction_fetcher.lock().await.start().await;
+        let (_, result) = leader.process_next_batch().await;
+        let leader_end = result.unwrap().unwrap().end_version;
+        leader
+            .update_last_processed_version(&processor_name, leader_end)
+            .unwrap();
+
+        // A controlled failover: the standby takes over at its next poll, not after the ttl
+        leader_lease.release().unwrap();
+        let released_at = std::time::Instant::now();
+        assert!(standby_lease.try_acquire().unwrap());
+        assert!(released_at.elapsed() < std::time::Duration::from_secs(1));
+
+        // and resumes right after the leader's watermark
+        let start_version = standby.get_start_version(&processor_name).unwrap().unwrap() as u64;
+        assert_eq!(start_version, leader_end + 1);
+        standby.set_fetcher_version(start_version).await;
+        standby.transaction_fetcher.lock().await.start().await;
+        let mut standby_start = None;
+        let mut first_published_after = None;
+        loop {
+            match standby.process_next_batch().await {
+                (0, _) => break,
+                (_, Some(result)) => {
+                    let result = result.unwrap();
+                    standby_start.get_or_insert(result.start_version);
+                    if !published[1].is_empty() {
+                        first_published_after.get_or_insert_with(|| released_at.elapsed());
+                    }
+                    standby
+                        .update_last_processed_version(&processor_name, result.end_version)
+                        .unwrap();
+                },
+                (_, None) => unreachable!(),
+            }
+        }
+        // No version is skipped or processed twice over the handover
+        assert!(standby_start.unwrap() > leader_end);
+        for version in [0, 69158, 260885, 691595, 691596] {
+            TransactionQuery::get_by_version(version, &mut conn_pool.get().unwrap()).unwrap();
+        }
+        assert_eq!(
+            standby.get_start_version(&processor_name).unwrap(),
+            Some(691597)
+        );
+
+        // The gap in published versions: the standby publishes from the version after the
+        // leader's last one, shortly after the lease is released
+        let published_versions = |messages: &RecordedMessages| {
+            messages
+                .on_topic("txns")
+                .iter()
+                .map(|message| {
+                    message.printed().payload["version"]
+                        .as_str()
+                        .unwrap()
+                        .parse::<u64>()
+                        .unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+        let leader_published = published_versions(&published[0]);
+        let standby_published = published_versions(&published[1]);
+        assert_eq!(leader_published.last(), Some(&leader_end));
+        assert_eq!(standby_published.first(), standby_start.as_ref());
+        assert!(first_published_after.unwrap() < std::time::Duration::from_secs(1));
+        // Together every version, each once
+        assert_eq!([leader_published, standby_published].concat(), [
+            0, 69158, 260885, 691595, 691596
+        ]);
+    }
+
+    /// A user transaction calling `function`
+    fn user_transaction(
+        version: u64,
+        sender: &str,
+        function: &str,
+        events: Vec<Value>,
+        changes: Vec<Value>,
+    ) -> Transaction {
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": version.to_string(),
+            "block_height": "100",
+            "epoch": "1",
+            "hash": format!("0x{:064x}", version),
+            "state_change_hash": "0xebfe1eb7aa5321e7a7d741d927487163c34c821eaab60646ae0efd02b286c97c",
+            "event_root_hash": "0x414343554d554c41544f525f504c414345484f4c4445525f4841534800000000",
+            "gas_used": "43",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": "0x97bfd5949d32f6c9a9efad93411924bfda658a8829de384d531ee73c2f740971",
+            "sender": sender,
+            "sequence_number": version.to_string(),
+            "max_gas_amount": "1000",
+            "gas_unit_price": "1",
+            "expiration_timestamp_secs": "1649713172",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": function,
+                "type_arguments": [],
+                "arguments": []
+            },
+            "signature": {
+                "type": "ed25519_signature",
+                "public_key": "0x14ff6646855dad4a2dab30db773cdd4b22d6f9e6813f3e50142adf4f3efcf9f8",
+                "signature": "0x70781112e78cc8b54b86805c016cef2478bccdef21b721542af0323276ab906c989172adffed5bf2f475f2ec3a5b284a0ac46a6aef0d79f0dbb6b85bfca0080a"
+            },
+            "events": events,
+            "timestamp": "1649713141723410",
+            "changes": changes
+        }))
+        .unwrap()
+    }
+
+    fn event(type_: &str, creation_number: u64) -> Value {
+        json!({
+            "guid": {
+                "account_address": "0xfefefefe",
+                "creation_number": creation_number.to_string()
+            },
+            "sequence_number": "0",
+            "type": type_,
+            "data": { "amount": "1" }
+        })
+    }
+
+    fn resource(address: &str, type_: &str, data: Value) -> Value {
+        json!({
+            "type": "write_resource",
+            "address": address,
+            "state_key_hash": format!("0x{:064x}", 1),
+            "data": { "type": type_, "data": data }
+        })
+    }
+
+    fn table_item(handle: &str, key: u8) -> Value {
+        json!({
+            "type": "write_table_item",
+            "state_key_hash": format!("0x{:064x}", 2),
+            "handle": handle,
+            "key": format!("0x{:02x}", key),
+            "value": "0x01",
+            "data": {
+                "key": key.to_string(),
+                "key_type": "u8",
+                "value": "1",
+                "value_type": "u64"
+            }
+        })
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_app_scope() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let (conn_pool, mut tailer) = setup_indexer().unwrap();
+        let app = "0xa11ce";
+        let (app_handle, other_handle) = ("0x1a2b", "0x3c4d");
+        let batch = vec![
+            // Joins the app: its event, its resource and an item of the table in that resource,
+            // written before the resource that makes it reachable
+            user_transaction(
+                100,
+                "0xb0b",
+                "0xa11ce::game::join",
+                vec![
+                    event("0xa11ce::game::JoinEvent", 1),
+                    event("0x1::coin::WithdrawEvent", 2),
+                ],
+                vec![
+                    table_item(app_handle, 1),
+                    resource(
+                        app,
+                        "0xa11ce::game::Game",
+                        json!({ "players": { "handle": app_handle }, "count": "1" }),
+                    ),
+                    resource(
+                        "0xb0b",
+                        "0x1::account::Account",
+                        json!({ "sequence_number": "1" }),
+                    ),
+                ],
+            ),
+            // Nothing of the app
+            user_transaction(
+                101,
+                "0xca1",
+                "0x1::coin::transfer",
+                vec![event("0x1::coin::WithdrawEvent", 2)],
+                vec![
+                    resource(
+                        "0xca1",
+                        "0x1::coin::CoinStore<0x1::aptos_coin::AptosCoin>",
+                        json!({ "frozen": false }),
+                    ),
+                    table_item(other_handle, 1),
+                ],
+            ),
+            // Outside of the app, but holds a coin of the app and writes to its table
+            user_transaction(
+                102,
+                "0xca1",
+                "0x1::aptos_account::transfer",
+                vec![event("0x1::coin::DepositEvent", 3)],
+                vec![
+                    resource(
+                        "0xca1",
+                        "0x1::coin::CoinStore<0xa11ce::token::Gem>",
+                        json!({ "frozen": false }),
+                    ),
+                    table_item(app_handle, 2),
+                    resource(
+                        "0xca1",
+                        "0x1::account::Account",
+                        json!({ "sequence_number": "2" }),
+                    ),
+                ],
+            ),
+        ];
+        tailer.transaction_fetcher = Arc::new(Mutex::new(FakeFetcher {
+            batches: vec![batch],
+            ..FakeFetcher::new(None)
+        }));
+        let config = AppScopeConfig {
+            enabled: true,
+            addresses: vec![app.to_string()],
+            backfill_from_version: 0,
+        };
+        let tailer = tailer.with_app_scope(Arc::new(AppScope::new(&config, conn_pool.clone())));
+
+        // The batch keeps the versions it spanned before narrowing
+        let (num_txns, result) = tailer.process_next_batch().await;
+        let result = result.unwrap().unwrap();
+        assert_eq!(
+            (num_txns, result.start_version, result.end_version),
+            (3, 100, 102)
+        );
+        assert_eq!(tailer.process_next_batch().await.0, 0);
+
+        let mut conn = conn_pool.get().unwrap();
+        let versions = schema::transactions::table
+            .select(schema::transactions::version)
+            .order(schema::transactions::version)
+            .load::<i64>(&mut conn)
+            .unwrap();
+        assert_eq!(versions, vec![100, 102]);
+        let user_versions = schema::user_transactions::table
+            .select(schema::user_transactions::version)
+            .order(schema::user_transactions::version)
+            .load::<i64>(&mut conn)
+            .unwrap();
+        assert_eq!(user_versions, vec![100, 102]);
+        let events = schema::events::table
+            .select(schema::events::type_)
+            .load::<String>(&mut conn)
+            .unwrap();
+        assert_eq!(events, vec!["0xa11ce::game::JoinEvent"]);
+        let changes = schema::write_set_changes::table
+            .select((
+                schema::write_set_changes::transaction_version,
+                schema::write_set_changes::type_,
+            ))
+            .order((
+                schema::write_set_changes::transaction_version,
+                schema::write_set_changes::index,
+            ))
+            .load::<(i64, String)>(&mut conn)
+            .unwrap();
+        assert_eq!(changes, vec![
+            (100, "write_table_item".to_string()),
+            (100, "write_resource".to_string()),
+            (102, "write_resource".to_string()),
+            (102, "write_table_item".to_string()),
+        ]);
+        let mut resources = schema::move_resources::table
+            .select(schema::move_resources::type_)
+            .load::<String>(&mut conn)
+            .unwrap();
+        resources.sort();
+        assert_eq!(resources, vec![
+            "0x1::coin::CoinStore<0xa11ce::token::Gem>",
+            "0xa11ce::game::Game",
+        ]);
+        let table_handles = schema::table_items::table
+            .select(schema::table_items::table_handle)
+            .load::<String>(&mut conn)
+            .unwrap();
+        assert_eq!(table_handles, vec![standardize_address(app_handle); 2]);
+        let current_items = schema::current_table_items::table
+            .count()
+            .get_result::<i64>(&mut conn)
+            .unwrap();
+        assert_eq!(current_items, 2);
+        let registered = schema::app_scope_table_handles::table
+            .select(schema::app_scope_table_handles::handle)
+            .load::<String>(&mut conn)
+            .unwrap();
+        assert_eq!(registered, vec![standardize_address(app_handle)]);
+    }
 }
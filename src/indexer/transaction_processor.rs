@@ -3,10 +3,14 @@
 
 use crate::{
     counters::{
-        GOT_CONNECTION, LATEST_PROCESSED_VERSION, PROCESSOR_ERRORS, PROCESSOR_INVOCATIONS,
-        PROCESSOR_SUCCESSES, UNABLE_TO_GET_CONNECTION,
+        BATCH_DURATION_SECONDS, GOT_CONNECTION, LATEST_PROCESSED_VERSION, PROCESSOR_ERRORS,
+        PROCESSED_ROWS, PROCESSOR_INVOCATIONS, PROCESSOR_SUCCESSES, TRANSACTIONS_PROCESSED,
+        UNABLE_TO_GET_CONNECTION,
+    },
+    custom::driver::retry_budget::{self, ErrorClass},
+    database::{
+        execute_with_better_error, get_chunks, get_connection, PgDbPool, PgPoolConnection,
     },
-    database::{execute_with_better_error, get_chunks, PgDbPool, PgPoolConnection},
     indexer::{errors::TransactionProcessingError, processing_result::ProcessingResult},
     models::processor_statuses::ProcessorStatusModel,
     schema,
@@ -16,7 +20,7 @@ use async_trait::async_trait;
 use diesel::{pg::upsert::excluded, prelude::*};
 use field_count::FieldCount;
 use schema::processor_statuses::{self, dsl};
-use std::fmt::Debug;
+use std::{fmt::Debug, time::Instant};
 
 /// The `TransactionProcessor` is used by an instance of a `Tailer` to process transactions
 #[async_trait]
@@ -36,27 +40,43 @@ pub trait TransactionProcessor: Send + Sync + Debug {
 
     /// Gets a reference to the connection pool
     /// This is used by the `get_conn()` helper below
-    fn connection_pool(&self) -> &PgDbPool;
+    /// `None` for a processor run without Postgres, which doesn't record its statuses, see
+    /// `driver::replay`
+    fn connection_pool(&self) -> Option<&PgDbPool>;
+
+    /// Whether `process_transactions` waits for `ordered_commit::turn` before it writes or
+    /// publishes anything, so that with ordered commits it can parse its batch in parallel with
+    /// the others. Batches of processors that don't wait for their turn before processing.
+    fn takes_commit_turn(&self) -> bool {
+        false
+    }
 
     //* Below are helper methods that don't need to be implemented *//
 
     /// Gets the connection.
-    /// If it was unable to do so (default timeout: 30s), it will keep retrying until it can.
-    fn get_conn(&self) -> PgPoolConnection {
-        let pool = self.connection_pool();
+    /// If it was unable to do so (default timeout: 30s, see `driver::db_pools`), it will keep
+    /// retrying until it can, or until the batch's retry budget is spent, which is returned as
+    /// the error so that the batch fails as retryable, see `indexer::errors`.
+    fn get_conn(&self) -> anyhow::Result<PgPoolConnection> {
+        let Some(pool) = self.connection_pool() else {
+            anyhow::bail!("{} runs without a database", self.name());
+        };
         loop {
-            match pool.get() {
+            match get_connection(pool, self.name()) {
                 Ok(conn) => {
                     GOT_CONNECTION.inc();
-                    return conn;
+                    return Ok(conn);
                 },
                 Err(err) => {
                     UNABLE_TO_GET_CONNECTION.inc();
                     aptos_logger::error!(
-                        "Could not get DB connection from pool, will retry in {:?}. Err: {:?}",
+                        processor_name = self.name(),
+                        connections = pool.state().connections,
+                        "Could not get DB connection from pool, will retry in {:?}. Err: {}",
                         pool.connection_timeout(),
                         err
                     );
+                    retry_budget::retry(ErrorClass::DbConnection, "get connection", &err)?;
                 },
             };
         }
@@ -71,17 +91,45 @@ pub trait TransactionProcessor: Send + Sync + Debug {
             !txns.is_empty(),
             "Must provide at least one transaction to this function"
         );
+        let start_version = txns.first().unwrap().version().unwrap();
+        let end_version = txns.last().unwrap().version().unwrap();
+        self.process_versions_with_status(txns, start_version, end_version)
+            .await
+    }
+
+    /// Like `process_transactions_with_status`, for a batch spanning `start_version` to
+    /// `end_version` whatever its transactions, which can be fewer or none, see `driver::app_scope`
+    async fn process_versions_with_status(
+        &self,
+        txns: Vec<Transaction>,
+        start_version: u64,
+        end_version: u64,
+    ) -> Result<ProcessingResult, TransactionProcessingError> {
         PROCESSOR_INVOCATIONS
             .with_label_values(&[self.name()])
             .inc();
 
-        let start_version = txns.first().unwrap().version().unwrap();
-        let end_version = txns.last().unwrap().version().unwrap();
-
         self.mark_versions_started(start_version, end_version);
-        let res = self
-            .process_transactions(txns, start_version, end_version)
-            .await;
+        let started = Instant::now();
+        let budget = retry_budget::for_batch(self.name(), start_version, end_version);
+        let res = retry_budget::scope(
+            budget.clone(),
+            self.process_transactions(txns, start_version, end_version),
+        )
+        .await;
+        // A spent budget fails the batch, whatever gave up on the retry that spent it
+        let res = match budget.and_then(|budget| budget.exhausted()) {
+            Some(exhausted) => Err(TransactionProcessingError::TransactionCommitError((
+                anyhow::Error::new(exhausted),
+                start_version,
+                end_version,
+                self.name(),
+            ))),
+            None => res,
+        };
+        BATCH_DURATION_SECONDS
+            .with_label_values(&[self.name(), "process"])
+            .observe(started.elapsed().as_secs_f64());
         // Handle block success/failure
         match res.as_ref() {
             Ok(processing_result) => self.update_status_success(processing_result),
@@ -117,6 +165,14 @@ pub trait TransactionProcessor: Send + Sync + Debug {
             processing_result.end_version
         );
         PROCESSOR_SUCCESSES.with_label_values(&[self.name()]).inc();
+        TRANSACTIONS_PROCESSED
+            .with_label_values(&[self.name()])
+            .inc_by(processing_result.end_version - processing_result.start_version + 1);
+        for (entity, count) in processing_result.counts.entries() {
+            PROCESSED_ROWS
+                .with_label_values(&[self.name(), entity])
+                .inc_by(count as u64);
+        }
         LATEST_PROCESSED_VERSION
             .with_label_values(&[self.name()])
             .set(processing_result.end_version as i64);
@@ -143,8 +199,23 @@ pub trait TransactionProcessor: Send + Sync + Debug {
     }
 
     /// Actually performs the write for a `ProcessorStatusModel` changeset
+    /// Nothing is written without a database.
     fn apply_processor_status(&self, psms: &[ProcessorStatusModel]) {
-        let mut conn = self.get_conn();
+        if self.connection_pool().is_none() {
+            return;
+        }
+        // Outside of a batch's retry budget, so a connection is waited for however long it takes
+        let mut conn = match self.get_conn() {
+            Ok(conn) => conn,
+            Err(err) => {
+                aptos_logger::error!(
+                    processor_name = self.name(),
+                    error = ?err,
+                    "Could not record the processor status"
+                );
+                return;
+            },
+        };
         let chunks = get_chunks(psms.len(), ProcessorStatusModel::field_count());
         for (start_ind, end_ind) in chunks {
             execute_with_better_error(
@@ -164,3 +235,91 @@ pub trait TransactionProcessor: Send + Sync + Debug {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        custom::driver::{
+            config::RetryBudgetConfig,
+            retry_budget::{RetryBudget, RetryBudgetExhausted},
+        },
+        database::PgPool,
+        indexer::errors::ErrorKind,
+    };
+    use diesel::r2d2::ConnectionManager;
+    use std::{collections::HashMap, sync::Arc, time::Duration};
+
+    /// A processor whose database never answers
+    #[derive(Debug)]
+    struct Unreachable {
+        connection_pool: PgDbPool,
+    }
+
+    #[async_trait]
+    impl TransactionProcessor for Unreachable {
+        fn name(&self) -> &'static str {
+            "unreachable_processor"
+        }
+
+        async fn process_transactions(
+            &self,
+            _transactions: Vec<Transaction>,
+            start_version: u64,
+            end_version: u64,
+        ) -> Result<ProcessingResult, TransactionProcessingError> {
+            self.get_conn().map_err(|err| {
+                TransactionProcessingError::ConnectionPoolError((
+                    err,
+                    start_version,
+                    end_version,
+                    self.name(),
+                ))
+            })?;
+            Ok(ProcessingResult::new(
+                self.name(),
+                start_version,
+                end_version,
+            ))
+        }
+
+        fn connection_pool(&self) -> Option<&PgDbPool> {
+            Some(&self.connection_pool)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_conn_fails_the_batch() {
+        let manager = ConnectionManager::new("postgres://127.0.0.1:1/indexer");
+        let processor = Unreachable {
+            connection_pool: Arc::new(
+                PgPool::builder()
+                    .connection_timeout(Duration::from_millis(10))
+                    .build_unchecked(manager),
+            ),
+        };
+        let budget = RetryBudget::new(
+            &RetryBudgetConfig {
+                enabled: true,
+                max_attempts: 10,
+                max_wall_clock_secs: 60,
+                max_attempts_per_class: HashMap::from([("db_connection".to_string(), 2)]),
+            },
+            "unreachable_processor batch 1..=1".to_string(),
+        );
+        let error = retry_budget::scope(
+            Some(Arc::new(budget)),
+            processor.process_transactions(vec![], 1, 1),
+        )
+        .await
+        .unwrap_err();
+        // Rather than a panic, a retryable error of the batch
+        let (err, start_version, end_version, name) = error.inner();
+        assert!(err.is::<RetryBudgetExhausted>());
+        assert_eq!(
+            (*start_version, *end_version, *name),
+            (1, 1, "unreachable_processor")
+        );
+        assert_eq!(error.kind(), ErrorKind::Retryable);
+    }
+}
@@ -4,25 +4,42 @@
 // Increase recursion limit for `serde_json::json!` macro parsing
 #![recursion_limit = "256"]
 
+#[cfg(feature = "indexer")]
 #[macro_use]
 extern crate diesel_migrations;
 
 // Need to use this for because src/schema.rs uses the macros and is autogenerated
+#[cfg(feature = "indexer")]
 #[macro_use]
 extern crate diesel;
 
+pub mod client;
+pub mod models;
+
+#[cfg(feature = "indexer")]
 pub mod counters;
+#[cfg(feature = "indexer")]
 pub mod database;
+#[cfg(feature = "indexer")]
 pub mod indexer;
-pub mod models;
+#[cfg(feature = "indexer")]
 pub mod processors;
+#[cfg(feature = "indexer")]
+pub mod queries;
+#[cfg(feature = "indexer")]
 pub mod runtime;
+#[cfg(feature = "indexer")]
 pub mod schema;
-mod util;
+#[cfg(feature = "indexer")]
+pub mod strictness;
+#[cfg(feature = "indexer")]
+pub mod util;
+#[cfg(feature = "indexer")]
 pub mod custom;
 
 /// By default, skips test unless `INDEXER_DATABASE_URL` is set.
 /// In CI, will explode if `INDEXER_DATABASE_URL` is NOT set.
+#[cfg(feature = "indexer")]
 pub fn should_skip_pg_tests() -> bool {
     if std::env::var("CIRCLECI").is_ok() {
         std::env::var("INDEXER_DATABASE_URL").expect("must set 'INDEXER_DATABASE_URL' in CI!");
@@ -0,0 +1,28 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Commands run outside of a node, see `custom::driver::replay` and `custom::driver::debug`
+
+use aptos_indexer::custom::driver::{
+    debug::{self, DebugArgs},
+    replay::{self, ReplayArgs},
+};
+use clap::Parser;
+
+#[derive(Parser)]
+#[clap(name = "aptos-indexer")]
+enum Command {
+    /// Runs a processor over transactions read from files
+    Replay(ReplayArgs),
+    /// Prints what the processors derive from a transaction read from files
+    Debug(DebugArgs),
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    aptos_logger::Logger::new().init();
+    match Command::parse() {
+        Command::Replay(args) => replay::run(&args).await,
+        Command::Debug(args) => debug::run(&args),
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use crate::{models::v2_objects::Object, schema::account_derivations, util::standardize_address};
+use aptos_api_types::{Transaction as APITransaction, TransactionPayload, WriteResource};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::collections::HashMap;
+
+pub const RESOURCE_ACCOUNT: &str = "resource_account";
+pub const OBJECT: &str = "object";
+
+/// Entry functions of `0x1::resource_account` whose first argument is the seed
+const RESOURCE_ACCOUNT_FUNCTIONS: &[&str] = &[
+    "0x1::resource_account::create_resource_account",
+    "0x1::resource_account::create_resource_account_and_fund",
+    "0x1::resource_account::create_resource_account_and_publish_package",
+];
+const RESOURCE_ACCOUNT_CONTAINER: &str = "0x1::resource_account::Container";
+/// Appended to the source address and seed to derive a resource account address
+const DERIVE_RESOURCE_ACCOUNT_SCHEME: u8 = 0xFF;
+
+/// An address derived from another one. Derivations of the same address seen in different
+/// transactions are merged, so what a later transaction reveals fills in the earlier row.
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(derived_address))]
+#[diesel(table_name = account_derivations)]
+pub struct AccountDerivation {
+    pub derived_address: String,
+    /// `resource_account` or `object`
+    pub derivation_kind: String,
+    pub source_address: Option<String>,
+    /// Hex, 0x prefixed
+    pub seed: Option<String>,
+    pub transaction_version: i64,
+}
+
+impl AccountDerivation {
+    /// A resource account created by calling one of the `0x1::resource_account` entry functions,
+    /// the only place its seed shows
+    pub fn from_transaction(transaction: &APITransaction) -> Option<Self> {
+        let APITransaction::UserTransaction(user_txn) = transaction else {
+            return None;
+        };
+        if !user_txn.info.success {
+            return None;
+        }
+        let TransactionPayload::EntryFunctionPayload(payload) = &user_txn.request.payload else {
+            return None;
+        };
+        if !RESOURCE_ACCOUNT_FUNCTIONS.contains(&payload.function.to_string().as_str()) {
+            return None;
+        }
+        let seed = payload.arguments.first()?.as_str()?;
+        let source_address = standardize_address(&user_txn.request.sender.to_string());
+        Some(Self {
+            derived_address: resource_account_address(&source_address, seed)?,
+            derivation_kind: RESOURCE_ACCOUNT.to_string(),
+            source_address: Some(source_address),
+            seed: Some(seed.to_lowercase()),
+            transaction_version: user_txn.info.version.0 as i64,
+        })
+    }
+
+    /// The resource accounts in the `Container` of the account that created them, however they
+    /// were created
+    pub fn from_write_resource(write_resource: &WriteResource, txn_version: i64) -> Vec<Self> {
+        if write_resource.data.typ.to_string() != RESOURCE_ACCOUNT_CONTAINER {
+            return vec![];
+        }
+        let source_address = standardize_address(&write_resource.address.to_string());
+        let data = serde_json::to_value(&write_resource.data.data).unwrap_or_default();
+        data["store"]["data"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry["key"].as_str())
+            .map(|derived_address| Self {
+                derived_address: standardize_address(derived_address),
+                derivation_kind: RESOURCE_ACCOUNT.to_string(),
+                source_address: Some(source_address.clone()),
+                seed: None,
+                transaction_version: txn_version,
+            })
+            .collect()
+    }
+
+    /// An object, derived from the owner it's first seen with, which is usually its creator
+    pub fn from_object(object: &Object) -> Option<Self> {
+        if object.is_deleted {
+            return None;
+        }
+        Some(Self {
+            derived_address: object.object_address.clone(),
+            derivation_kind: OBJECT.to_string(),
+            source_address: Some(object.owner_address.clone()),
+            seed: None,
+            transaction_version: object.transaction_version,
+        })
+    }
+
+    /// Fills in what `self` doesn't know from `other`, a derivation of the same address
+    pub fn merge(&mut self, other: Self) {
+        if other.transaction_version < self.transaction_version {
+            self.source_address = other.source_address.or(self.source_address.take());
+            self.transaction_version = other.transaction_version;
+        } else {
+            self.source_address = self.source_address.take().or(other.source_address);
+        }
+        self.seed = self.seed.take().or(other.seed);
+    }
+
+    /// One derivation per address, sorted by PK
+    pub fn merge_all(derivations: Vec<Self>) -> Vec<Self> {
+        let mut merged: HashMap<String, Self> = HashMap::new();
+        for derivation in derivations {
+            match merged.get_mut(&derivation.derived_address) {
+                Some(existing) => existing.merge(derivation),
+                None => {
+                    merged.insert(derivation.derived_address.clone(), derivation);
+                },
+            }
+        }
+        let mut merged = merged.into_values().collect::<Vec<_>>();
+        merged.sort_by(|a, b| a.derived_address.cmp(&b.derived_address));
+        merged
+    }
+}
+
+/// sha3-256 of the source address, the seed and the resource account scheme, as
+/// `0x1::account::create_resource_address` computes it
+pub fn resource_account_address(source_address: &str, seed: &str) -> Option<String> {
+    let source = hex::decode(standardize_address(source_address).trim_start_matches("0x")).ok()?;
+    let seed = hex::decode(seed.trim_start_matches("0x")).ok()?;
+    let mut hasher = Sha3_256::new();
+    hasher.update(&source);
+    hasher.update(&seed);
+    hasher.update([DERIVE_RESOURCE_ACCOUNT_SCHEME]);
+    Some(format!("0x{}", hex::encode(hasher.finalize())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn derivation(version: i64, source: Option<&str>, seed: Option<&str>) -> AccountDerivation {
+        AccountDerivation {
+            derived_address: "0xa".to_string(),
+            derivation_kind: RESOURCE_ACCOUNT.to_string(),
+            source_address: source.map(str::to_string),
+            seed: seed.map(str::to_string),
+            transaction_version: version,
+        }
+    }
+
+    #[test]
+    fn test_resource_account_address() {
+        // Liquidswap's resource account on mainnet, created with the seed
+        // `b"liquidswap_account_seed"`
+        assert_eq!(
+            resource_account_address(
+                "0x190d44266241744264b964a37b8f09863167a12d3e70cda39376cfb4e3561e12",
+                "0x6c6971756964737761705f6163636f756e745f73656564",
+            )
+            .as_deref(),
+            Some("0x05a97986a9d031c4567e15b797be516910cfcb4156312482efc6a19c0a30c948")
+        );
+
+        let address = resource_account_address("0x1", "0x").unwrap();
+        assert_eq!(address.len(), 66);
+        assert_eq!(resource_account_address("0x01", ""), Some(address.clone()));
+        assert_ne!(resource_account_address("0x1", "0x00"), Some(address));
+        assert_eq!(resource_account_address("0x1", "not hex"), None);
+    }
+
+    #[test]
+    fn test_merge_fills_in_the_seed() {
+        let merged = AccountDerivation::merge_all(vec![
+            derivation(10, Some("0x1"), None),
+            derivation(20, None, Some("0xbeef")),
+        ]);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].transaction_version, 10);
+        assert_eq!(merged[0].source_address.as_deref(), Some("0x1"));
+        assert_eq!(merged[0].seed.as_deref(), Some("0xbeef"));
+    }
+}
@@ -0,0 +1,37 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use crate::schema::admin_audit_log;
+use serde::{Deserialize, Serialize};
+
+/// A request to the admin server that launched or cancelled an operation, or was rejected, see
+/// `custom::driver::admin`
+#[derive(Clone, Debug, Deserialize, Insertable, Serialize)]
+#[diesel(table_name = admin_audit_log)]
+pub struct AdminAuditEntry {
+    pub caller: String,
+    /// The operation kind launched, or `cancel`
+    pub action: String,
+    /// The request's body, or the id of the operation to cancel
+    pub parameters: serde_json::Value,
+    pub operation_id: Option<String>,
+    /// `accepted` or `rejected`
+    pub outcome: String,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Queryable, Serialize)]
+#[diesel(table_name = admin_audit_log)]
+pub struct AdminAuditEntryQuery {
+    pub id: i64,
+    pub caller: String,
+    pub action: String,
+    pub parameters: serde_json::Value,
+    pub operation_id: Option<String>,
+    pub outcome: String,
+    pub error: Option<String>,
+    pub requested_at: chrono::NaiveDateTime,
+}
@@ -0,0 +1,22 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use crate::schema::anomalies;
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+
+/// A transaction that looks wrong in a way no single row shows, see
+/// `custom::driver::duplicate_transactions`
+#[derive(Clone, Debug, Deserialize, FieldCount, Insertable, Serialize)]
+#[diesel(table_name = anomalies)]
+pub struct Anomaly {
+    pub kind: String,
+    pub transaction_version: i64,
+    pub processor: String,
+    pub policy: String,
+    pub message: String,
+    pub details: serde_json::Value,
+}
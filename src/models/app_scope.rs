@@ -0,0 +1,42 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use crate::schema::{app_scope_addresses, app_scope_table_handles};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+
+/// A module address a processor is scoped to, see `custom::driver::app_scope`
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(processor, address))]
+#[diesel(table_name = app_scope_addresses)]
+pub struct AppScopeAddress {
+    pub processor: String,
+    pub address: String,
+    pub backfilled: bool,
+    pub added_at_version: i64,
+}
+
+#[derive(Clone, Debug, Deserialize, Identifiable, Queryable, Serialize)]
+#[diesel(primary_key(processor, address))]
+#[diesel(table_name = app_scope_addresses)]
+pub struct AppScopeAddressQuery {
+    pub processor: String,
+    pub address: String,
+    pub backfilled: bool,
+    pub added_at_version: i64,
+    pub inserted_at: chrono::NaiveDateTime,
+}
+
+/// A table handle reachable from a scoped app's resources, see `custom::driver::app_scope`
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, PartialEq, Serialize)]
+#[diesel(primary_key(handle))]
+#[diesel(table_name = app_scope_table_handles)]
+pub struct AppScopeTableHandle {
+    pub handle: String,
+    /// The type of the resource, or the handle of the table item, it was found in
+    pub discovered_from: String,
+    pub transaction_version: i64,
+}
@@ -0,0 +1,390 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use super::coin_models::{
+    coin_utils::{CoinInfoType, CoinResource},
+    v2_fungible_asset_utils::FungibleAssetStore,
+};
+use crate::{
+    custom::driver::{
+        config::OversizeAction,
+        row_limits::{ColumnLimit, RowLimits, INDEXED_COLUMN_MAX_BYTES},
+    },
+    database::PgPoolConnection,
+    schema::current_asset_stores,
+    util::standardize_address,
+};
+use aptos_api_types::{DeleteResource, WriteResource};
+use diesel::prelude::*;
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const COIN: &str = "coin";
+pub const FUNGIBLE_ASSET: &str = "fungible_asset";
+
+const COIN_STORE: &str = "0x1::coin::CoinStore";
+const OBJECT_GROUP: &str = "0x1::object::ObjectGroup";
+
+// PK of current_asset_stores, i.e. (store_address, asset_type)
+pub type CurrentAssetStorePK = (String, String);
+
+/// A coin store or fungible store and when it was created and deleted. The row only changes with
+/// the store's lifecycle, i.e. creation, deletion, owner and frozen flag, not with its balance.
+#[derive(
+    Clone, Debug, Deserialize, Eq, FieldCount, Identifiable, Insertable, PartialEq, Serialize,
+)]
+#[diesel(primary_key(store_address, asset_type))]
+#[diesel(table_name = current_asset_stores)]
+pub struct CurrentAssetStore {
+    /// The owner's address for a coin store, the store object's for a fungible store
+    pub store_address: String,
+    /// Coin type for a coin store, metadata address for a fungible store
+    pub asset_type: String,
+    pub owner_address: String,
+    /// `coin` or `fungible_asset`
+    pub store_kind: String,
+    pub is_frozen: bool,
+    /// Version the store was last created at
+    pub created_transaction_version: i64,
+    /// `None` while the store exists
+    pub deleted_transaction_version: Option<i64>,
+    pub last_transaction_version: i64,
+}
+
+#[derive(Debug, Deserialize, Identifiable, Queryable, Serialize)]
+#[diesel(primary_key(store_address, asset_type))]
+#[diesel(table_name = current_asset_stores)]
+pub struct CurrentAssetStoreQuery {
+    pub store_address: String,
+    pub asset_type: String,
+    pub owner_address: String,
+    pub store_kind: String,
+    pub is_frozen: bool,
+    pub created_transaction_version: i64,
+    pub deleted_transaction_version: Option<i64>,
+    pub last_transaction_version: i64,
+    pub inserted_at: chrono::NaiveDateTime,
+}
+
+/// What a write set change shows of a store
+#[derive(Clone, Debug)]
+pub struct AssetStoreChange {
+    pub store_address: String,
+    /// Unknown for a deleted object, which doesn't show which fungible store it was
+    pub asset_type: Option<String>,
+    /// Unknown for a fungible store written without its object core
+    pub owner_address: Option<String>,
+    pub store_kind: &'static str,
+    pub is_frozen: bool,
+    pub is_deleted: bool,
+    pub transaction_version: i64,
+}
+
+impl AssetStoreChange {
+    pub fn from_coin_store_write(
+        write_resource: &WriteResource,
+        txn_version: i64,
+    ) -> anyhow::Result<Option<Self>> {
+        let Some(CoinResource::CoinStoreResource(inner)) =
+            CoinResource::from_write_resource(write_resource, txn_version)?
+        else {
+            return Ok(None);
+        };
+        let coin_info_type = CoinInfoType::from_move_type(
+            &write_resource.data.typ.generic_type_params[0],
+            txn_version,
+        )?;
+        let owner_address = standardize_address(&write_resource.address.to_string());
+        Ok(Some(Self {
+            store_address: owner_address.clone(),
+            asset_type: Some(coin_info_type.get_coin_type_trunc()),
+            owner_address: Some(owner_address),
+            store_kind: COIN,
+            is_frozen: inner.frozen,
+            is_deleted: false,
+            transaction_version: txn_version,
+        }))
+    }
+
+    /// A coin store removed from its account, e.g. once the coin is migrated to a fungible store
+    pub fn from_coin_store_delete(
+        delete_resource: &DeleteResource,
+        txn_version: i64,
+    ) -> anyhow::Result<Option<Self>> {
+        let resource = &delete_resource.resource;
+        let type_str = format!(
+            "{}::{}::{}",
+            resource.address, resource.module, resource.name
+        );
+        if type_str != COIN_STORE {
+            return Ok(None);
+        }
+        let coin_info_type =
+            CoinInfoType::from_move_type(&resource.generic_type_params[0], txn_version)?;
+        let owner_address = standardize_address(&delete_resource.address.to_string());
+        Ok(Some(Self {
+            store_address: owner_address.clone(),
+            asset_type: Some(coin_info_type.get_coin_type_trunc()),
+            owner_address: Some(owner_address),
+            store_kind: COIN,
+            is_frozen: false,
+            is_deleted: true,
+            transaction_version: txn_version,
+        }))
+    }
+
+    /// The owner is the one of the store object, see `with_owner`
+    pub fn from_fungible_store_write(
+        write_resource: &WriteResource,
+        txn_version: i64,
+    ) -> anyhow::Result<Option<Self>> {
+        let Some(inner) = FungibleAssetStore::from_write_resource(write_resource, txn_version)?
+        else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            store_address: standardize_address(&write_resource.address.to_string()),
+            asset_type: Some(inner.metadata.get_reference_address()),
+            owner_address: None,
+            store_kind: FUNGIBLE_ASSET,
+            is_frozen: inner.frozen,
+            is_deleted: false,
+            transaction_version: txn_version,
+        }))
+    }
+
+    /// Fungible stores are deleted with their object. Any deleted object might have been one, the
+    /// object's stores are looked up when the changes are applied.
+    pub fn from_object_delete(delete_resource: &DeleteResource, txn_version: i64) -> Option<Self> {
+        if delete_resource.resource.to_string() != OBJECT_GROUP {
+            return None;
+        }
+        Some(Self {
+            store_address: standardize_address(&delete_resource.address.to_string()),
+            asset_type: None,
+            owner_address: None,
+            store_kind: FUNGIBLE_ASSET,
+            is_frozen: false,
+            is_deleted: true,
+            transaction_version: txn_version,
+        })
+    }
+
+    pub fn with_owner(mut self, owner_address: Option<String>) -> Self {
+        self.owner_address = self.owner_address.or(owner_address);
+        self
+    }
+
+    /// The store once the change is applied to `previous`, `None` if there's nothing to list
+    fn apply(
+        &self,
+        asset_type: &str,
+        previous: Option<&CurrentAssetStore>,
+    ) -> Option<CurrentAssetStore> {
+        let version = self.transaction_version;
+        match previous {
+            Some(previous) if previous.deleted_transaction_version.is_none() => {
+                if self.is_deleted {
+                    return Some(CurrentAssetStore {
+                        deleted_transaction_version: Some(version),
+                        last_transaction_version: version,
+                        ..previous.clone()
+                    });
+                }
+                let owner_address = self
+                    .owner_address
+                    .clone()
+                    .unwrap_or_else(|| previous.owner_address.clone());
+                if owner_address == previous.owner_address && self.is_frozen == previous.is_frozen {
+                    // Only the balance changed
+                    return Some(previous.clone());
+                }
+                Some(CurrentAssetStore {
+                    owner_address,
+                    is_frozen: self.is_frozen,
+                    last_transaction_version: version,
+                    ..previous.clone()
+                })
+            },
+            // A store that was never seen live has nothing to list
+            _ if self.is_deleted => previous.cloned(),
+            _ => Some(CurrentAssetStore {
+                store_address: self.store_address.clone(),
+                asset_type: asset_type.to_string(),
+                owner_address: self.owner_address.clone()?,
+                store_kind: self.store_kind.to_string(),
+                is_frozen: self.is_frozen,
+                created_transaction_version: version,
+                deleted_transaction_version: None,
+                last_transaction_version: version,
+            }),
+        }
+    }
+}
+
+impl CurrentAssetStore {
+    pub fn pk(&self) -> CurrentAssetStorePK {
+        (self.store_address.clone(), self.asset_type.clone())
+    }
+
+    /// Key of the store's messages, the owner and the asset type
+    pub fn topic_key(&self) -> String {
+        format!("{}:{}", self.owner_address, self.asset_type)
+    }
+
+    /// Applies `changes`, in version order, to the stored rows of their stores and returns the rows
+    /// that changed, sorted by PK
+    pub fn apply_all(existing: Vec<Self>, changes: Vec<AssetStoreChange>) -> Vec<Self> {
+        let before = existing
+            .into_iter()
+            .map(|store| (store.pk(), store))
+            .collect::<HashMap<_, _>>();
+        let mut stores = before.clone();
+        for change in changes {
+            match &change.asset_type {
+                Some(asset_type) => {
+                    let pk = (change.store_address.clone(), asset_type.clone());
+                    if let Some(store) = change.apply(asset_type, stores.get(&pk)) {
+                        stores.insert(pk, store);
+                    }
+                },
+                None => {
+                    for (pk, store) in stores.iter_mut() {
+                        if pk.0 == change.store_address && store.store_kind == FUNGIBLE_ASSET {
+                            if let Some(deleted) = change.apply(&pk.1, Some(store)) {
+                                *store = deleted;
+                            }
+                        }
+                    }
+                },
+            }
+        }
+        let mut changed = stores
+            .into_iter()
+            .filter(|(pk, store)| before.get(pk) != Some(store))
+            .map(|(_, store)| store)
+            .collect::<Vec<_>>();
+        changed.sort_by(|a, b| a.pk().cmp(&b.pk()));
+        changed
+    }
+}
+
+impl From<CurrentAssetStoreQuery> for CurrentAssetStore {
+    fn from(res: CurrentAssetStoreQuery) -> Self {
+        Self {
+            store_address: res.store_address,
+            asset_type: res.asset_type,
+            owner_address: res.owner_address,
+            store_kind: res.store_kind,
+            is_frozen: res.is_frozen,
+            created_transaction_version: res.created_transaction_version,
+            deleted_transaction_version: res.deleted_transaction_version,
+            last_transaction_version: res.last_transaction_version,
+        }
+    }
+}
+
+impl CurrentAssetStoreQuery {
+    pub fn get_by_store_addresses(
+        store_addresses: &[String],
+        conn: &mut PgPoolConnection,
+    ) -> diesel::QueryResult<Vec<Self>> {
+        current_asset_stores::table
+            .filter(current_asset_stores::store_address.eq_any(store_addresses))
+            .load::<Self>(conn)
+    }
+}
+
+/// Coin types are only truncated to the column's 5000 chars, over what the primary key can index
+impl RowLimits for CurrentAssetStore {
+    const LIMITS: &'static [ColumnLimit] = &[ColumnLimit::new(
+        "asset_type",
+        INDEXED_COLUMN_MAX_BYTES,
+        OversizeAction::DeadLetter,
+    )];
+    const TABLE: &'static str = "current_asset_stores";
+
+    fn transaction_version(&self) -> i64 {
+        self.last_transaction_version
+    }
+
+    fn column(&self, _index: usize) -> &str {
+        &self.asset_type
+    }
+
+    fn column_mut(&mut self, _index: usize) -> &mut String {
+        &mut self.asset_type
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(version: i64, asset_type: Option<&str>, owner: Option<&str>) -> AssetStoreChange {
+        AssetStoreChange {
+            store_address: "0xa".to_string(),
+            asset_type: asset_type.map(str::to_string),
+            owner_address: owner.map(str::to_string),
+            store_kind: FUNGIBLE_ASSET,
+            is_frozen: false,
+            is_deleted: false,
+            transaction_version: version,
+        }
+    }
+
+    fn deleted(change: AssetStoreChange) -> AssetStoreChange {
+        AssetStoreChange {
+            is_deleted: true,
+            ..change
+        }
+    }
+
+    #[test]
+    fn test_create_delete_recreate() {
+        let created = CurrentAssetStore::apply_all(vec![], vec![
+            change(10, Some("0xfa"), Some("0x1")),
+            // Balance changes don't touch the row
+            change(11, Some("0xfa"), None),
+        ]);
+        assert_eq!(created.len(), 1);
+        assert_eq!(created[0].created_transaction_version, 10);
+        assert_eq!(created[0].last_transaction_version, 10);
+        assert_eq!(created[0].owner_address, "0x1");
+
+        // The deleted object is looked up among the stored rows
+        let removed = CurrentAssetStore::apply_all(created.clone(), vec![
+            change(20, Some("0xfa"), None),
+            deleted(change(21, None, None)),
+        ]);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].created_transaction_version, 10);
+        assert_eq!(removed[0].deleted_transaction_version, Some(21));
+
+        let recreated =
+            CurrentAssetStore::apply_all(removed, vec![change(30, Some("0xfa"), Some("0x2"))]);
+        assert_eq!(recreated[0].created_transaction_version, 30);
+        assert_eq!(recreated[0].deleted_transaction_version, None);
+        assert_eq!(recreated[0].owner_address, "0x2");
+
+        // Nothing but the balance changed
+        assert!(
+            CurrentAssetStore::apply_all(created, vec![change(40, Some("0xfa"), None)]).is_empty()
+        );
+    }
+
+    #[test]
+    fn test_unknown_stores() {
+        // Neither an object that wasn't a store nor a store that was never seen live is listed
+        assert!(CurrentAssetStore::apply_all(vec![], vec![
+            deleted(change(10, None, None)),
+            deleted(change(11, Some("0xfa"), Some("0x1"))),
+            change(12, Some("0xfb"), None),
+        ])
+        .is_empty());
+    }
+}
@@ -0,0 +1,697 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use super::{
+    coin_models::{
+        coin_activities::CoinActivity,
+        coin_infos::CoinInfo,
+        v2_fungible_asset_utils::{FungibleAssetEvent, FungibleAssetStore, FungibleAssetSupply},
+    },
+    token_models::{
+        token_activities::TokenActivity,
+        v2_token_utils::{ObjectWithMetadata, TokenV2, V2TokenEvent},
+    },
+};
+use crate::{
+    schema::asset_transfers,
+    util::{parse_timestamp, standardize_address},
+};
+use aptos_api_types::{Transaction as APITransaction, WriteSetChange};
+use bigdecimal::{BigDecimal, One, Zero};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+pub const COIN_V1: &str = "coin_v1";
+pub const FUNGIBLE_ASSET: &str = "fungible_asset";
+pub const TOKEN_V1: &str = "token_v1";
+pub const TOKEN_V2: &str = "token_v2";
+
+pub const DIRECT: &str = "direct";
+pub const MINT: &str = "mint";
+pub const BURN: &str = "burn";
+pub const AIRDROP_CLAIM: &str = "airdrop_claim";
+/// A deposit no withdrawal in the transaction pays for, e.g. out of a contract's custody
+pub const UNMATCHED_DEPOSIT: &str = "unmatched_deposit";
+/// A withdrawal no deposit in the transaction receives, e.g. into a contract's custody
+pub const UNMATCHED_WITHDRAWAL: &str = "unmatched_withdrawal";
+
+const COIN_WITHDRAW_EVENT: &str = "0x1::coin::WithdrawEvent";
+const COIN_DEPOSIT_EVENT: &str = "0x1::coin::DepositEvent";
+const TOKEN_WITHDRAW_EVENT: &str = "0x3::token::WithdrawEvent";
+const TOKEN_DEPOSIT_EVENT: &str = "0x3::token::DepositEvent";
+const TOKEN_MINT_EVENT: &str = "0x3::token::MintTokenEvent";
+const TOKEN_BURN_EVENT: &str = "0x3::token::BurnTokenEvent";
+const TOKEN_OFFER_EVENT: &str = "0x3::token_transfers::TokenOfferEvent";
+const TOKEN_CANCEL_OFFER_EVENT: &str = "0x3::token_transfers::TokenCancelOfferEvent";
+const TOKEN_CLAIM_EVENT: &str = "0x3::token_transfers::TokenClaimEvent";
+
+/// An asset moving between two accounts, or in or out of existence, see
+/// `custom::driver::asset_transfers`
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, PartialEq, Serialize)]
+#[diesel(primary_key(transaction_version, transfer_index))]
+#[diesel(table_name = asset_transfers)]
+pub struct AssetTransfer {
+    pub transaction_version: i64,
+    pub transfer_index: i64,
+    pub standard: String,
+    /// Coin type, fungible asset metadata address, token v1 data id hash or token v2 address
+    pub asset_id: String,
+    pub property_version_v1: BigDecimal,
+    pub from_address: Option<String>,
+    pub to_address: Option<String>,
+    pub amount: BigDecimal,
+    pub kind: String,
+    /// Event completing the transfer, i.e. the later of its withdrawal and deposit
+    pub event_index: i64,
+    pub transaction_timestamp: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Withdrawal,
+    Deposit,
+}
+
+/// One side of a transfer: an amount of an asset leaving or entering an account
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransferLeg {
+    pub event_index: i64,
+    pub standard: &'static str,
+    pub asset_id: String,
+    pub property_version_v1: BigDecimal,
+    pub owner_address: String,
+    pub amount: BigDecimal,
+    pub side: Side,
+}
+
+/// A transfer before it's numbered
+#[derive(Clone, Debug, PartialEq)]
+struct Movement {
+    event_index: i64,
+    standard: &'static str,
+    asset_id: String,
+    property_version_v1: BigDecimal,
+    from_address: Option<String>,
+    to_address: Option<String>,
+    amount: BigDecimal,
+    kind: &'static str,
+}
+
+impl TransferLeg {
+    /// The coin processor's withdrawals and deposits, leaving out gas fees
+    pub fn from_coin_activity(activity: &CoinActivity) -> Option<Self> {
+        let side = match activity.activity_type.as_str() {
+            COIN_WITHDRAW_EVENT => Side::Withdrawal,
+            COIN_DEPOSIT_EVENT => Side::Deposit,
+            _ => return None,
+        };
+        if activity.is_gas_fee {
+            return None;
+        }
+        Some(Self {
+            event_index: activity.event_index?,
+            standard: COIN_V1,
+            asset_id: activity.coin_type.clone(),
+            property_version_v1: BigDecimal::zero(),
+            owner_address: activity.owner_address.clone(),
+            amount: activity.amount.clone(),
+            side,
+        })
+    }
+
+    /// The token processor's v1 withdrawals and deposits
+    pub fn from_token_activity(activity: &TokenActivity) -> Option<Self> {
+        let (side, owner_address) = match activity.transfer_type.as_str() {
+            TOKEN_WITHDRAW_EVENT => (Side::Withdrawal, activity.from_address.clone()?),
+            TOKEN_DEPOSIT_EVENT => (Side::Deposit, activity.to_address.clone()?),
+            _ => return None,
+        };
+        Some(Self {
+            event_index: activity.event_index?,
+            standard: TOKEN_V1,
+            asset_id: activity.token_data_id_hash.clone(),
+            property_version_v1: activity.property_version.clone(),
+            owner_address,
+            amount: activity.token_amount.clone(),
+            side,
+        })
+    }
+
+    fn is_of(&self, activity: &TokenActivity, side: Side, owner_address: &Option<String>) -> bool {
+        self.standard == TOKEN_V1
+            && self.side == side
+            && self.asset_id == activity.token_data_id_hash
+            && self.property_version_v1 == activity.property_version
+            && Some(&self.owner_address) == owner_address.as_ref()
+    }
+}
+
+impl AssetTransfer {
+    /// The transfers of a successful user transaction, from its coin legs (see
+    /// `TransferLeg::from_coin_activity`), its token activities and its fungible asset and token
+    /// v2 events. Within a transaction:
+    /// - a token v1 claim is the transfer from the offerer to the claimer, in place of the
+    ///   claimer's deposit; the withdrawal of an offer and the deposit of a cancelled offer only
+    ///   move the token in and out of escrow and aren't transfers
+    /// - withdrawals and deposits of the same asset are paired in event order, a withdrawal paying
+    ///   for the following deposits until it's used up, so a batch transfer gives one transfer per
+    ///   recipient and a self-transfer one from and to the same account
+    /// - what's left over is a mint or burn if the asset's supply changed in the transaction (its
+    ///   `CoinInfo` or fungible asset `Supply` was written, a token v1 was minted or burned), an
+    ///   unmatched deposit or withdrawal otherwise
+    /// - token v2 transfers, mints and burns come from their events, a mint going to the first
+    ///   owner the token is transferred from in the transaction
+    /// - a fungible asset transfer with the same sender, recipient, amount and kind as a coin
+    ///   transfer is the same movement seen through the coin's paired fungible asset, only the
+    ///   coin transfer is kept
+    pub fn from_transaction(
+        transaction: &APITransaction,
+        coin_legs: Vec<TransferLeg>,
+    ) -> Vec<Self> {
+        let APITransaction::UserTransaction(user_txn) = transaction else {
+            return vec![];
+        };
+        if !user_txn.info.success {
+            return vec![];
+        }
+        let txn_version = user_txn.info.version.0 as i64;
+        let txn_timestamp = parse_timestamp(user_txn.timestamp.0, txn_version);
+
+        let mut supply_changed = HashSet::new();
+        // Fungible asset metadata by store address, owners and token v2s by object address
+        let mut fungible_stores = HashMap::new();
+        let mut owners = HashMap::new();
+        let mut tokens_v2 = HashSet::new();
+        for wsc in &user_txn.info.changes {
+            let WriteSetChange::WriteResource(write_resource) = wsc else {
+                continue;
+            };
+            let address = standardize_address(&write_resource.address.to_string());
+            if let Some(coin_info) =
+                CoinInfo::from_write_resource(write_resource, txn_version, txn_timestamp).unwrap()
+            {
+                supply_changed.insert((COIN_V1, coin_info.coin_type));
+            }
+            if let Some(store) =
+                FungibleAssetStore::from_write_resource(write_resource, txn_version).unwrap()
+            {
+                fungible_stores.insert(address.clone(), store.metadata.get_reference_address());
+            }
+            if FungibleAssetSupply::from_write_resource(write_resource, txn_version)
+                .unwrap()
+                .is_some()
+            {
+                supply_changed.insert((FUNGIBLE_ASSET, address.clone()));
+            }
+            if let Some(object) =
+                ObjectWithMetadata::from_write_resource(write_resource, txn_version).unwrap()
+            {
+                owners.insert(address.clone(), object.object_core.get_owner_address());
+            }
+            if TokenV2::from_write_resource(write_resource, txn_version)
+                .unwrap()
+                .is_some()
+            {
+                tokens_v2.insert(address);
+            }
+        }
+
+        let mut legs = coin_legs;
+        let mut movements = vec![];
+        let token_activities = TokenActivity::from_transaction(transaction);
+        for activity in &token_activities {
+            match activity.transfer_type.as_str() {
+                TOKEN_MINT_EVENT | TOKEN_BURN_EVENT => {
+                    supply_changed.insert((TOKEN_V1, activity.token_data_id_hash.clone()));
+                },
+                _ => legs.extend(TransferLeg::from_token_activity(activity)),
+            }
+        }
+        for activity in &token_activities {
+            // The offer events are the offerer's, `from_address`
+            let (side, owner_address) = match activity.transfer_type.as_str() {
+                TOKEN_OFFER_EVENT => (Side::Withdrawal, &activity.from_address),
+                TOKEN_CANCEL_OFFER_EVENT => (Side::Deposit, &activity.from_address),
+                TOKEN_CLAIM_EVENT => (Side::Deposit, &activity.to_address),
+                _ => continue,
+            };
+            let leg = legs
+                .iter()
+                .position(|leg| leg.is_of(activity, side, owner_address))
+                .map(|position| legs.remove(position));
+            if activity.transfer_type == TOKEN_CLAIM_EVENT {
+                movements.push(Movement {
+                    event_index: leg
+                        .map(|leg| leg.event_index)
+                        .or(activity.event_index)
+                        .unwrap_or_default(),
+                    standard: TOKEN_V1,
+                    asset_id: activity.token_data_id_hash.clone(),
+                    property_version_v1: activity.property_version.clone(),
+                    from_address: activity.from_address.clone(),
+                    to_address: activity.to_address.clone(),
+                    amount: activity.token_amount.clone(),
+                    kind: AIRDROP_CLAIM,
+                });
+            }
+        }
+
+        let mut token_v2_events = vec![];
+        for (index, event) in user_txn.events.iter().enumerate() {
+            let event_type = event.typ.to_string();
+            let event_index = index as i64;
+            if let Some(fa_event) =
+                FungibleAssetEvent::from_event(event_type.as_str(), &event.data, txn_version)
+                    .unwrap()
+            {
+                let store_address = standardize_address(&event.guid.account_address.to_string());
+                // A store written without its object core has no known owner
+                if let (Some(metadata), Some(owner_address)) = (
+                    fungible_stores.get(&store_address),
+                    owners.get(&store_address),
+                ) {
+                    let (side, amount) = match fa_event {
+                        FungibleAssetEvent::WithdrawEvent(inner) => {
+                            (Side::Withdrawal, inner.amount)
+                        },
+                        FungibleAssetEvent::DepositEvent(inner) => (Side::Deposit, inner.amount),
+                    };
+                    legs.push(TransferLeg {
+                        event_index,
+                        standard: FUNGIBLE_ASSET,
+                        asset_id: metadata.clone(),
+                        property_version_v1: BigDecimal::zero(),
+                        owner_address: owner_address.clone(),
+                        amount,
+                        side,
+                    });
+                }
+            } else if let Some(token_event) =
+                V2TokenEvent::from_event(event_type.as_str(), &event.data, txn_version).unwrap()
+            {
+                token_v2_events.push((event_index, token_event));
+            }
+        }
+        movements.extend(Self::token_v2_movements(
+            &token_v2_events,
+            &tokens_v2,
+            &owners,
+        ));
+
+        legs.sort_by_key(|leg| leg.event_index);
+        movements.extend(Self::pair(legs, &supply_changed));
+        let coin_movements = movements
+            .iter()
+            .filter(|movement| movement.standard == COIN_V1)
+            .map(|movement| {
+                (
+                    movement.from_address.clone(),
+                    movement.to_address.clone(),
+                    movement.amount.clone(),
+                    movement.kind,
+                )
+            })
+            .collect::<Vec<_>>();
+        movements.retain(|movement| {
+            movement.standard != FUNGIBLE_ASSET
+                || !coin_movements.contains(&(
+                    movement.from_address.clone(),
+                    movement.to_address.clone(),
+                    movement.amount.clone(),
+                    movement.kind,
+                ))
+        });
+        // Stable, movements completed by the same event keep the order they were found in
+        movements.sort_by_key(|movement| movement.event_index);
+        movements
+            .into_iter()
+            .enumerate()
+            .map(|(index, movement)| Self {
+                transaction_version: txn_version,
+                transfer_index: index as i64,
+                standard: movement.standard.to_string(),
+                asset_id: movement.asset_id,
+                property_version_v1: movement.property_version_v1,
+                from_address: movement.from_address,
+                to_address: movement.to_address,
+                amount: movement.amount,
+                kind: movement.kind.to_string(),
+                event_index: movement.event_index,
+                transaction_timestamp: txn_timestamp,
+            })
+            .collect()
+    }
+
+    /// Pairs the withdrawals and deposits of each asset in event order
+    fn pair(legs: Vec<TransferLeg>, supply_changed: &HashSet<(&str, String)>) -> Vec<Movement> {
+        let mut assets: Vec<(&'static str, String, BigDecimal)> = vec![];
+        let mut pending: HashMap<_, (VecDeque<TransferLeg>, VecDeque<TransferLeg>)> =
+            HashMap::new();
+        let mut movements = vec![];
+        for mut leg in legs {
+            let asset = (
+                leg.standard,
+                leg.asset_id.clone(),
+                leg.property_version_v1.clone(),
+            );
+            if !pending.contains_key(&asset) {
+                assets.push(asset.clone());
+            }
+            let (withdrawals, deposits) = pending.entry(asset).or_default();
+            let (same, other) = match leg.side {
+                Side::Withdrawal => (withdrawals, deposits),
+                Side::Deposit => (deposits, withdrawals),
+            };
+            while leg.amount > BigDecimal::zero() {
+                let Some(counterpart) = other.front_mut() else {
+                    break;
+                };
+                let amount = (&leg.amount).min(&counterpart.amount).clone();
+                let (from, to) = match leg.side {
+                    Side::Withdrawal => (&leg, &*counterpart),
+                    Side::Deposit => (&*counterpart, &leg),
+                };
+                movements.push(Movement {
+                    event_index: leg.event_index,
+                    standard: leg.standard,
+                    asset_id: leg.asset_id.clone(),
+                    property_version_v1: leg.property_version_v1.clone(),
+                    from_address: Some(from.owner_address.clone()),
+                    to_address: Some(to.owner_address.clone()),
+                    amount: amount.clone(),
+                    kind: DIRECT,
+                });
+                leg.amount -= &amount;
+                counterpart.amount -= &amount;
+                if counterpart.amount <= BigDecimal::zero() {
+                    other.pop_front();
+                }
+            }
+            if leg.amount > BigDecimal::zero() {
+                same.push_back(leg);
+            }
+        }
+        for asset in assets {
+            let (withdrawals, deposits) = pending.remove(&asset).unwrap();
+            let supply_changed = supply_changed.contains(&(asset.0, asset.1));
+            for leg in withdrawals.into_iter().chain(deposits) {
+                let (from_address, to_address, kind) = match (leg.side, supply_changed) {
+                    (Side::Withdrawal, true) => (Some(leg.owner_address), None, BURN),
+                    (Side::Withdrawal, false) => {
+                        (Some(leg.owner_address), None, UNMATCHED_WITHDRAWAL)
+                    },
+                    (Side::Deposit, true) => (None, Some(leg.owner_address), MINT),
+                    (Side::Deposit, false) => (None, Some(leg.owner_address), UNMATCHED_DEPOSIT),
+                };
+                movements.push(Movement {
+                    event_index: leg.event_index,
+                    standard: leg.standard,
+                    asset_id: leg.asset_id,
+                    property_version_v1: leg.property_version_v1,
+                    from_address,
+                    to_address,
+                    amount: leg.amount,
+                    kind,
+                });
+            }
+        }
+        movements
+    }
+
+    fn token_v2_movements(
+        events: &[(i64, V2TokenEvent)],
+        tokens_v2: &HashSet<String>,
+        owners: &HashMap<String, String>,
+    ) -> Vec<Movement> {
+        let movement = |event_index, token: String, from_address, to_address, kind| Movement {
+            event_index,
+            standard: TOKEN_V2,
+            asset_id: token,
+            property_version_v1: BigDecimal::zero(),
+            from_address,
+            to_address,
+            amount: BigDecimal::one(),
+            kind,
+        };
+        events
+            .iter()
+            .filter_map(|(event_index, event)| match event {
+                V2TokenEvent::TransferEvent(inner) => {
+                    let token = inner.get_object_address();
+                    tokens_v2.contains(&token).then(|| {
+                        movement(
+                            *event_index,
+                            token,
+                            Some(inner.get_from_address()),
+                            Some(inner.get_to_address()),
+                            DIRECT,
+                        )
+                    })
+                },
+                V2TokenEvent::MintEvent(inner) => {
+                    let token = inner.get_token_address();
+                    let first_owner = events
+                        .iter()
+                        .find_map(|(_, event)| match event {
+                            V2TokenEvent::TransferEvent(transfer)
+                                if transfer.get_object_address() == token =>
+                            {
+                                Some(transfer.get_from_address())
+                            },
+                            _ => None,
+                        })
+                        .or_else(|| owners.get(&token).cloned());
+                    Some(movement(*event_index, token, None, first_owner, MINT))
+                },
+                // The object is usually deleted with the token, leaving its owner unknown
+                V2TokenEvent::BurnEvent(inner) => {
+                    let token = inner.get_token_address();
+                    let owner = owners.get(&token).cloned();
+                    Some(movement(*event_index, token, owner, None, BURN))
+                },
+                V2TokenEvent::TokenMutationEvent(_) => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    const COIN: &str = "0x1::aptos_coin::AptosCoin";
+
+    fn leg(event_index: i64, owner: &str, amount: u64, side: Side) -> TransferLeg {
+        TransferLeg {
+            event_index,
+            standard: COIN_V1,
+            asset_id: COIN.to_string(),
+            property_version_v1: BigDecimal::zero(),
+            owner_address: owner.to_string(),
+            amount: BigDecimal::from(amount),
+            side,
+        }
+    }
+
+    fn summary(movements: &[Movement]) -> Vec<(Option<&str>, Option<&str>, u64, &str)> {
+        movements
+            .iter()
+            .map(|movement| {
+                (
+                    movement.from_address.as_deref(),
+                    movement.to_address.as_deref(),
+                    movement.amount.to_string().parse().unwrap(),
+                    movement.kind,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pair() {
+        let no_supply_change = HashSet::new();
+        // A batch transfer, one withdrawal paying for several deposits
+        let movements = AssetTransfer::pair(
+            vec![
+                leg(0, "0xa", 100, Side::Withdrawal),
+                leg(1, "0xb", 30, Side::Deposit),
+                leg(2, "0xc", 70, Side::Deposit),
+            ],
+            &no_supply_change,
+        );
+        assert_eq!(summary(&movements), vec![
+            (Some("0xa"), Some("0xb"), 30, DIRECT),
+            (Some("0xa"), Some("0xc"), 70, DIRECT),
+        ]);
+        // One withdrawal per recipient
+        let movements = AssetTransfer::pair(
+            vec![
+                leg(0, "0xa", 30, Side::Withdrawal),
+                leg(1, "0xb", 30, Side::Deposit),
+                leg(2, "0xa", 70, Side::Withdrawal),
+                leg(3, "0xc", 70, Side::Deposit),
+            ],
+            &no_supply_change,
+        );
+        assert_eq!(summary(&movements), vec![
+            (Some("0xa"), Some("0xb"), 30, DIRECT),
+            (Some("0xa"), Some("0xc"), 70, DIRECT),
+        ]);
+        // A self-transfer
+        let movements = AssetTransfer::pair(
+            vec![
+                leg(0, "0xa", 5, Side::Withdrawal),
+                leg(1, "0xa", 5, Side::Deposit),
+            ],
+            &no_supply_change,
+        );
+        assert_eq!(summary(&movements), vec![(
+            Some("0xa"),
+            Some("0xa"),
+            5,
+            DIRECT
+        )]);
+        // A swap into and out of a pool's custody
+        let mut other = leg(1, "0xa", 40, Side::Deposit);
+        other.asset_id = "0x1::other::Coin".to_string();
+        let movements = AssetTransfer::pair(
+            vec![leg(0, "0xa", 50, Side::Withdrawal), other],
+            &no_supply_change,
+        );
+        assert_eq!(summary(&movements), vec![
+            (Some("0xa"), None, 50, UNMATCHED_WITHDRAWAL),
+            (None, Some("0xa"), 40, UNMATCHED_DEPOSIT),
+        ]);
+        // What's left over changed the supply
+        let supply_changed = HashSet::from([(COIN_V1, COIN.to_string())]);
+        let movements = AssetTransfer::pair(
+            vec![
+                leg(0, "0xa", 10, Side::Deposit),
+                leg(1, "0xb", 4, Side::Withdrawal),
+            ],
+            &supply_changed,
+        );
+        assert_eq!(summary(&movements), vec![
+            (Some("0xb"), Some("0xa"), 4, DIRECT),
+            (None, Some("0xa"), 6, MINT),
+        ]);
+    }
+
+    fn user_transaction(success: bool, events: Vec<Value>, changes: Vec<Value>) -> APITransaction {
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": "100",
+            "block_height": "10",
+            "epoch": "1",
+            "hash": format!("0x{:064x}", 100),
+            "state_change_hash": format!("0x{:064x}", 0),
+            "event_root_hash": format!("0x{:064x}", 0),
+            "gas_used": "43",
+            "success": success,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": format!("0x{:064x}", 0),
+            "sender": "0xa",
+            "sequence_number": "3",
+            "max_gas_amount": "1000",
+            "gas_unit_price": "100",
+            "expiration_timestamp_secs": "1649713172",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0x1::aptos_account::batch_transfer",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "signature": {
+                "type": "ed25519_signature",
+                "public_key": "0x14ff6646855dad4a2dab30db773cdd4b22d6f9e6813f3e50142adf4f3efcf9f8",
+                "signature": "0x70781112e78cc8b54b86805c016cef2478bccdef21b721542af0323276ab906c989172adffed5bf2f475f2ec3a5b284a0ac46a6aef0d79f0dbb6b85bfca0080a"
+            },
+            "events": events,
+            "timestamp": "1649713141723410",
+            "changes": changes
+        }))
+        .unwrap()
+    }
+
+    fn coin_store(owner: &str) -> Value {
+        let handle = |creation_num: u64| json!({ "counter": "1", "guid": { "id": { "addr": owner, "creation_num": creation_num.to_string() } } });
+        json!({
+            "type": "write_resource",
+            "address": owner,
+            "state_key_hash": format!("0x{:064x}", 1),
+            "data": {
+                "type": format!("0x1::coin::CoinStore<{}>", COIN),
+                "data": {
+                    "coin": { "value": "1000" },
+                    "deposit_events": handle(2),
+                    "withdraw_events": handle(3),
+                    "frozen": false
+                }
+            }
+        })
+    }
+
+    fn coin_event(owner: &str, withdraw: bool, amount: u64) -> Value {
+        let (type_, creation_number) = if withdraw {
+            (COIN_WITHDRAW_EVENT, "3")
+        } else {
+            (COIN_DEPOSIT_EVENT, "2")
+        };
+        json!({
+            "guid": { "account_address": owner, "creation_number": creation_number },
+            "sequence_number": "0",
+            "type": type_,
+            "data": { "amount": amount.to_string() }
+        })
+    }
+
+    fn transfers(transaction: &APITransaction) -> Vec<AssetTransfer> {
+        let coin_legs = CoinActivity::from_transaction(transaction, &None)
+            .0
+            .iter()
+            .filter_map(TransferLeg::from_coin_activity)
+            .collect();
+        AssetTransfer::from_transaction(transaction, coin_legs)
+    }
+
+    #[test]
+    fn test_coin_batch_transfer() {
+        let events = vec![
+            coin_event("0xa", true, 100),
+            coin_event("0xb", false, 30),
+            coin_event("0xc", false, 70),
+        ];
+        let changes = vec![coin_store("0xa"), coin_store("0xb"), coin_store("0xc")];
+        let transfers = transfers(&user_transaction(true, events.clone(), changes.clone()));
+        // The gas fee isn't a transfer
+        assert_eq!(
+            transfers
+                .iter()
+                .map(|transfer| (
+                    transfer.transfer_index,
+                    transfer.to_address.clone().unwrap(),
+                    transfer.amount.clone(),
+                    transfer.event_index
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, standardize_address("0xb"), BigDecimal::from(30), 1),
+                (1, standardize_address("0xc"), BigDecimal::from(70), 2),
+            ]
+        );
+        assert!(transfers.iter().all(|transfer| {
+            transfer.standard == COIN_V1
+                && transfer.kind == DIRECT
+                && transfer.from_address == Some(standardize_address("0xa"))
+                && transfer.asset_id == COIN
+        }));
+
+        // A failed transaction only pays gas
+        assert!(self::transfers(&user_transaction(false, events, changes)).is_empty());
+    }
+}
@@ -0,0 +1,46 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+#![allow(clippy::extra_unused_lifetimes)]
+use crate::{database::PgPoolConnection, schema::backfill_windows};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = backfill_windows)]
+/// Versions a processor may overwrite rows in until `expires_at`, see
+/// `custom::driver::backfill_guard`
+pub struct BackfillWindow {
+    pub processor: String,
+    pub start_version: i64,
+    /// Inclusive
+    pub end_version: i64,
+    pub created_by: String,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Queryable)]
+#[diesel(table_name = backfill_windows)]
+pub struct BackfillWindowQuery {
+    pub id: i64,
+    pub processor: String,
+    pub start_version: i64,
+    pub end_version: i64,
+    pub created_by: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+impl BackfillWindowQuery {
+    /// Windows that haven't expired yet, of every processor
+    pub fn get_unexpired(conn: &mut PgPoolConnection) -> diesel::QueryResult<Vec<Self>> {
+        backfill_windows::table
+            .filter(backfill_windows::expires_at.gt(chrono::Utc::now().naive_utc()))
+            .load::<Self>(conn)
+    }
+
+    pub fn contains(&self, processor: &str, start_version: i64, end_version: i64) -> bool {
+        self.processor == processor
+            && self.start_version <= start_version
+            && end_version <= self.end_version
+            && self.expires_at > chrono::Utc::now().naive_utc()
+    }
+}
@@ -0,0 +1,40 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use crate::schema::balance_anomalies;
+use bigdecimal::BigDecimal;
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+
+/// A delta that would have taken a balance below zero, see `custom::driver::balance_guard`
+#[derive(Clone, Debug, Deserialize, FieldCount, Insertable, PartialEq, Serialize)]
+#[diesel(table_name = balance_anomalies)]
+pub struct BalanceAnomaly {
+    pub balance_table: String,
+    pub account_address: String,
+    pub asset: String,
+    pub transaction_version: i64,
+    pub processor: String,
+    pub expected_delta: BigDecimal,
+    /// The balance before the delta
+    pub stored_balance: BigDecimal,
+}
+
+#[derive(Clone, Debug, Queryable)]
+#[diesel(table_name = balance_anomalies)]
+pub struct BalanceAnomalyQuery {
+    pub balance_table: String,
+    pub account_address: String,
+    pub asset: String,
+    pub transaction_version: i64,
+    pub processor: String,
+    pub expected_delta: BigDecimal,
+    pub stored_balance: BigDecimal,
+    /// The balance derived again from the history, once healed
+    pub rederived_balance: Option<BigDecimal>,
+    pub healed_at: Option<chrono::NaiveDateTime>,
+    pub inserted_at: chrono::NaiveDateTime,
+}
@@ -0,0 +1,95 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use crate::schema::change_feed;
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    /// A new row, or one that was already there and left as is
+    Insert,
+    /// A row of a current table inserted or replaced
+    Upsert,
+    /// Columns of an existing row changed
+    Update,
+}
+
+impl Operation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operation::Insert => "insert",
+            Operation::Upsert => "upsert",
+            Operation::Update => "update",
+        }
+    }
+
+    pub fn parse(operation: &str) -> Option<Self> {
+        match operation {
+            "insert" => Some(Operation::Insert),
+            "upsert" => Some(Operation::Upsert),
+            "update" => Some(Operation::Update),
+            _ => None,
+        }
+    }
+}
+
+/// A row written by a processor, see `custom::driver::change_feed`
+#[derive(Clone, Debug, FieldCount, Insertable)]
+#[diesel(table_name = change_feed)]
+pub struct ChangeFeedRow {
+    pub model: String,
+    pub transaction_version: Option<i64>,
+    pub pk: Value,
+    pub operation: String,
+}
+
+#[derive(Debug, Queryable)]
+#[diesel(table_name = change_feed)]
+pub struct ChangeFeedEntryQuery {
+    pub id: i64,
+    pub model: String,
+    pub transaction_version: Option<i64>,
+    pub pk: Value,
+    pub operation: String,
+    pub inserted_at: chrono::NaiveDateTime,
+}
+
+/// An entry of the change feed as returned by `queries::poll_change_feed`
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ChangeFeedEntry {
+    pub id: i64,
+    pub model: String,
+    pub transaction_version: Option<i64>,
+    /// Primary key columns of the row, by name
+    pub pk: Value,
+    pub operation: Operation,
+    pub inserted_at: chrono::NaiveDateTime,
+}
+
+impl TryFrom<ChangeFeedEntryQuery> for ChangeFeedEntry {
+    type Error = anyhow::Error;
+
+    fn try_from(query: ChangeFeedEntryQuery) -> anyhow::Result<Self> {
+        let operation = Operation::parse(&query.operation).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Unknown operation {} in change feed entry {}",
+                query.operation,
+                query.id
+            )
+        })?;
+        Ok(Self {
+            id: query.id,
+            model: query.model,
+            transaction_version: query.transaction_version,
+            pk: query.pk,
+            operation,
+            inserted_at: query.inserted_at,
+        })
+    }
+}
@@ -10,12 +10,15 @@ use crate::{
         token_models::v2_token_utils::ObjectWithMetadata, user_transactions::UserTransaction,
     },
     schema::account_transactions,
-    util::standardize_address,
+    util::{
+        sort_key::{hex_key, sort_by_pk, SortKey},
+        standardize_address,
+    },
 };
-use aptos_api_types::{DeleteResource, Event, Transaction, WriteResource, WriteSetChange};
+use aptos_api_types::{Address, DeleteResource, Event, Transaction, WriteResource, WriteSetChange};
 use field_count::FieldCount;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{cmp::Ordering, collections::HashMap};
 
 pub type AccountTransactionPK = (String, i64);
 
@@ -32,29 +35,33 @@ impl AccountTransaction {
     /// a user account, an object, or a resource account.
     /// We will consider all transactions that modify a resource or event associated with a particular account.
     /// We will do 1 level of redirection for now (e.g. if it's an object, we will record the owner as account address).
-    /// We will also consider transactions that the account signed or is part of a multi sig / multi agent.
+    /// We will also consider transactions that the account sent, signed or is part of a multi sig / multi agent,
+    /// and the accounts whose modules the transaction published or removed.
     /// TODO: recursively find the parent account of an object
     /// TODO: include table items in the detection path
     pub fn from_transaction(
         transaction: &Transaction,
     ) -> anyhow::Result<HashMap<AccountTransactionPK, Self>> {
-        let (events, wscs, signatures, txn_version) = match transaction {
+        let (events, wscs, signatures, sender, txn_version) = match transaction {
             Transaction::UserTransaction(inner) => (
                 &inner.events,
                 &inner.info.changes,
                 UserTransaction::get_signatures(inner, inner.info.version.0 as i64, 0),
+                Some(&inner.request.sender),
                 inner.info.version.0 as i64,
             ),
             Transaction::GenesisTransaction(inner) => (
                 &inner.events,
                 &inner.info.changes,
                 vec![],
+                None,
                 inner.info.version.0 as i64,
             ),
             Transaction::BlockMetadataTransaction(inner) => (
                 &inner.events,
                 &inner.info.changes,
                 vec![],
+                None,
                 inner.info.version.0 as i64,
             ),
             _ => {
@@ -62,6 +69,10 @@ impl AccountTransaction {
             },
         };
         let mut account_transactions = HashMap::new();
+        // Also recorded without a signature, e.g. for simulated transactions
+        if let Some(sender) = sender {
+            account_transactions.extend(Self::from_address(sender, txn_version));
+        }
         for sig in &signatures {
             account_transactions.insert((sig.signer.clone(), txn_version), Self {
                 transaction_version: txn_version,
@@ -79,12 +90,38 @@ impl AccountTransaction {
                 WriteSetChange::WriteResource(res) => {
                     account_transactions.extend(Self::from_write_resource(res, txn_version)?);
                 },
+                WriteSetChange::DeleteModule(module) => {
+                    account_transactions.extend(Self::from_address(&module.address, txn_version));
+                },
+                WriteSetChange::WriteModule(module) => {
+                    account_transactions.extend(Self::from_address(&module.address, txn_version));
+                },
                 _ => {},
             }
         }
         Ok(account_transactions)
     }
 
+    /// Every account the transactions of a batch touched, once per transaction, sorted by PK
+    pub fn from_transactions(transactions: &[Transaction]) -> anyhow::Result<Vec<Self>> {
+        let mut account_transactions = HashMap::new();
+        for transaction in transactions {
+            account_transactions.extend(Self::from_transaction(transaction)?);
+        }
+        let mut account_transactions = account_transactions.into_values().collect::<Vec<_>>();
+        sort_by_pk(&mut account_transactions);
+        Ok(account_transactions)
+    }
+
+    /// Base case, record the address itself, e.g. the sender or a module's account
+    fn from_address(address: &Address, txn_version: i64) -> HashMap<AccountTransactionPK, Self> {
+        let account_address = standardize_address(&address.to_string());
+        HashMap::from([((account_address.clone(), txn_version), Self {
+            transaction_version: txn_version,
+            account_address,
+        })])
+    }
+
     /// Base case, record event account address. We don't really have to worry about
     /// objects here because it'll be taken care of in the resource section
     fn from_event(event: &Event, txn_version: i64) -> HashMap<AccountTransactionPK, Self> {
@@ -134,3 +171,114 @@ impl AccountTransaction {
         Ok(result)
     }
 }
+
+/// The 32 bytes of the standardized account address, then the version
+impl SortKey for AccountTransaction {
+    type Key = ([u8; 32], i64);
+
+    fn sort_key(&self) -> Option<Self::Key> {
+        Some((
+            hex_key::<32>(&self.account_address, "0x")?,
+            self.transaction_version,
+        ))
+    }
+
+    fn cmp_pk(&self, other: &Self) -> Ordering {
+        (&self.account_address, self.transaction_version)
+            .cmp(&(&other.account_address, other.transaction_version))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn address(account: u64) -> String {
+        format!("0x{:064x}", account)
+    }
+
+    fn event(account: u64) -> Value {
+        json!({
+            "guid": { "account_address": address(account), "creation_number": "2" },
+            "sequence_number": "0",
+            "type": "0x1::coin::DepositEvent",
+            "data": { "amount": "1" }
+        })
+    }
+
+    fn user_transaction(version: i64, sender: u64, events: Value) -> Transaction {
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": version.to_string(),
+            "hash": format!("0x{:064x}", version),
+            "state_change_hash": format!("0x{:064x}", 0),
+            "event_root_hash": format!("0x{:064x}", 0),
+            "gas_used": "43",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": format!("0x{:064x}", 0),
+            "sender": address(sender),
+            "sequence_number": "0",
+            "max_gas_amount": "1000",
+            "gas_unit_price": "1",
+            "expiration_timestamp_secs": "1649713172",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0x1::aptos_account::batch_transfer",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "signature": {
+                "type": "ed25519_signature",
+                "public_key": "0x14ff6646855dad4a2dab30db773cdd4b22d6f9e6813f3e50142adf4f3efcf9f8",
+                "signature": "0x70781112e78cc8b54b86805c016cef2478bccdef21b721542af0323276ab906c989172adffed5bf2f475f2ec3a5b284a0ac46a6aef0d79f0dbb6b85bfca0080a"
+            },
+            "events": events,
+            "timestamp": "1649713141723410",
+            "changes": [{
+                "type": "write_resource",
+                "address": address(sender),
+                "state_key_hash": format!("0x{:064x}", 1),
+                "data": {
+                    "type": "0x1::account::Account",
+                    "data": { "sequence_number": "1" }
+                }
+            }]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_from_transactions() {
+        // Deposits to three accounts, one of them twice
+        let transaction = user_transaction(
+            100,
+            0xa,
+            json!([event(0xd), event(0xb), event(0xc), event(0xb)]),
+        );
+        let rows = AccountTransaction::from_transactions(&[transaction]).unwrap();
+        let rows = rows
+            .iter()
+            .map(|row| (row.account_address.clone(), row.transaction_version))
+            .collect::<Vec<_>>();
+        assert_eq!(rows, vec![
+            (address(0xa), 100),
+            (address(0xb), 100),
+            (address(0xc), 100),
+            (address(0xd), 100),
+        ]);
+
+        // Once per transaction
+        let rows = AccountTransaction::from_transactions(&[
+            user_transaction(101, 0xa, json!([event(0xa)])),
+            user_transaction(100, 0xa, json!([])),
+        ])
+        .unwrap();
+        let versions = rows
+            .iter()
+            .map(|row| row.transaction_version)
+            .collect::<Vec<_>>();
+        assert_eq!(versions, vec![100, 101]);
+    }
+}
@@ -85,6 +85,9 @@ impl CoinActivity {
         let mut current_coin_balances: HashMap<CurrentCoinBalancePK, CurrentCoinBalance> =
             HashMap::new();
         let mut all_event_to_coin_type: EventToCoinType = HashMap::new();
+        // Coin types of the coin stores deleted by the transaction, by owner, as their events
+        // can't be mapped through the store
+        let mut deleted_coin_types: HashMap<OwnerAddress, Vec<CoinType>> = HashMap::new();
         let mut all_coin_supply = Vec::new();
 
         let (txn_info, writesets, events, maybe_user_request, txn_timestamp) = match &transaction {
@@ -126,21 +129,35 @@ impl CoinActivity {
         }
 
         for wsc in writesets {
-            let (maybe_coin_info, maybe_coin_balance_data) =
-                if let APIWriteSetChange::WriteResource(write_resource) = wsc {
-                    (
-                        CoinInfo::from_write_resource(write_resource, txn_version, txn_timestamp)
-                            .unwrap(),
-                        CoinBalance::from_write_resource(
-                            write_resource,
-                            txn_version,
-                            txn_timestamp,
-                        )
+            let (maybe_coin_info, maybe_coin_balance_data) = match wsc {
+                APIWriteSetChange::WriteResource(write_resource) => (
+                    CoinInfo::from_write_resource(write_resource, txn_version, txn_timestamp)
+                        .unwrap(),
+                    CoinBalance::from_write_resource(write_resource, txn_version, txn_timestamp)
                         .unwrap(),
+                ),
+                APIWriteSetChange::DeleteResource(delete_resource) => {
+                    let deleted = CoinBalance::from_delete_resource(
+                        delete_resource,
+                        txn_version,
+                        txn_timestamp,
+                    )
+                    .unwrap();
+                    if let Some((coin_balance, _)) = &deleted {
+                        deleted_coin_types
+                            .entry(coin_balance.owner_address.clone())
+                            .or_default()
+                            .push(coin_balance.coin_type.clone());
+                    }
+                    (
+                        None,
+                        deleted.map(|(coin_balance, current_coin_balance)| {
+                            (coin_balance, current_coin_balance, HashMap::new())
+                        }),
                     )
-                } else {
-                    (None, None)
-                };
+                },
+                _ => (None, None),
+            };
 
             let maybe_coin_supply = if let APIWriteSetChange::WriteTableItem(table_item) = &wsc {
                 CoinSupply::from_write_table_item(
@@ -185,7 +202,12 @@ impl CoinActivity {
                     event,
                     &parsed_event,
                     txn_version,
-                    &all_event_to_coin_type,
+                    Self::get_event_coin_type(
+                        event,
+                        txn_version,
+                        &all_event_to_coin_type,
+                        &deleted_coin_types,
+                    ),
                     txn_info.block_height.unwrap().0 as i64,
                     &entry_function_id_str,
                     txn_timestamp,
@@ -207,7 +229,7 @@ impl CoinActivity {
         event: &APIEvent,
         coin_event: &CoinEvent,
         txn_version: i64,
-        event_to_coin_type: &EventToCoinType,
+        coin_type: CoinType,
         block_height: i64,
         entry_function_id_str: &Option<String>,
         transaction_timestamp: chrono::NaiveDateTime,
@@ -217,19 +239,6 @@ impl CoinActivity {
             CoinEvent::WithdrawCoinEvent(inner) => inner.amount.clone(),
             CoinEvent::DepositCoinEvent(inner) => inner.amount.clone(),
         };
-        let event_move_guid = EventGuidResource {
-            addr: event.guid.account_address.to_string(),
-            creation_num: event.guid.creation_number.0 as i64,
-        };
-        let coin_type =
-            event_to_coin_type
-                .get(&event_move_guid)
-                .unwrap_or_else(|| {
-                    panic!(
-                        "Could not find event in resources (CoinStore), version: {}, event guid: {:?}, mapping: {:?}",
-                        txn_version, event_move_guid, event_to_coin_type
-                    )
-                }).clone();
 
         Self {
             transaction_version: txn_version,
@@ -249,14 +258,42 @@ impl CoinActivity {
         }
     }
 
+    /// The coin type of the coin store that emitted `event`, from the store written by the
+    /// transaction, or the only one it deleted at the event's account
+    fn get_event_coin_type(
+        event: &APIEvent,
+        txn_version: i64,
+        event_to_coin_type: &EventToCoinType,
+        deleted_coin_types: &HashMap<OwnerAddress, Vec<CoinType>>,
+    ) -> CoinType {
+        let event_move_guid = EventGuidResource {
+            addr: event.guid.account_address.to_string(),
+            creation_num: event.guid.creation_number.0 as i64,
+        };
+        if let Some(coin_type) = event_to_coin_type.get(&event_move_guid) {
+            return coin_type.clone();
+        }
+        match deleted_coin_types
+            .get(&standardize_address(&event_move_guid.addr))
+            .map(Vec::as_slice)
+        {
+            Some([coin_type]) => coin_type.clone(),
+            _ => panic!(
+                "Could not find event in resources (CoinStore), version: {}, event guid: {:?}, mapping: {:?}",
+                txn_version, event_move_guid, event_to_coin_type
+            ),
+        }
+    }
+
     fn get_gas_event(
         txn_info: &APITransactionInfo,
         user_transaction_request: &UserTransactionRequest,
         entry_function_id_str: &Option<String>,
         transaction_timestamp: chrono::NaiveDateTime,
     ) -> Self {
-        let aptos_coin_burned =
-            BigDecimal::from(txn_info.gas_used.0 * user_transaction_request.gas_unit_price.0);
+        // Multiplied as decimals, the product of two u64s can overflow
+        let aptos_coin_burned = BigDecimal::from(txn_info.gas_used.0)
+            * BigDecimal::from(user_transaction_request.gas_unit_price.0);
 
         Self {
             transaction_version: txn_info.version.0 as i64,
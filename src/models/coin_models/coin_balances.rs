@@ -13,8 +13,8 @@ use crate::{
     schema::{coin_balances, current_coin_balances},
     util::standardize_address,
 };
-use aptos_api_types::WriteResource as APIWriteResource;
-use bigdecimal::BigDecimal;
+use aptos_api_types::{DeleteResource as APIDeleteResource, WriteResource as APIWriteResource};
+use bigdecimal::{BigDecimal, Zero};
 use field_count::FieldCount;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
@@ -56,23 +56,13 @@ impl CoinBalance {
                     &write_resource.data.typ.generic_type_params[0],
                     txn_version,
                 )?;
-                let owner_address = standardize_address(&write_resource.address.to_string());
-                let coin_balance = Self {
-                    transaction_version: txn_version,
-                    owner_address: owner_address.clone(),
-                    coin_type_hash: coin_info_type.to_hash(),
-                    coin_type: coin_info_type.get_coin_type_trunc(),
-                    amount: inner.coin.value.clone(),
-                    transaction_timestamp: txn_timestamp,
-                };
-                let current_coin_balance = CurrentCoinBalance {
-                    owner_address,
-                    coin_type_hash: coin_info_type.to_hash(),
-                    coin_type: coin_info_type.get_coin_type_trunc(),
-                    amount: inner.coin.value.clone(),
-                    last_transaction_version: txn_version,
-                    last_transaction_timestamp: txn_timestamp,
-                };
+                let (coin_balance, current_coin_balance) = Self::with_current(
+                    standardize_address(&write_resource.address.to_string()),
+                    coin_info_type,
+                    inner.coin.value.clone(),
+                    txn_version,
+                    txn_timestamp,
+                );
                 let event_to_coin_mapping: EventToCoinType = HashMap::from([
                     (
                         (inner.withdraw_events.guid.id.clone()),
@@ -93,4 +83,55 @@ impl CoinBalance {
             _ => Ok(None),
         }
     }
+
+    /// A deleted coin store, e.g. one created and destroyed within a batch, holds nothing anymore
+    pub fn from_delete_resource(
+        delete_resource: &APIDeleteResource,
+        txn_version: i64,
+        txn_timestamp: chrono::NaiveDateTime,
+    ) -> anyhow::Result<Option<(Self, CurrentCoinBalance)>> {
+        let resource = &delete_resource.resource;
+        let type_str = format!(
+            "{}::{}::{}",
+            resource.address, resource.module, resource.name
+        );
+        if type_str != "0x1::coin::CoinStore" {
+            return Ok(None);
+        }
+        let coin_info_type =
+            &CoinInfoType::from_move_type(&resource.generic_type_params[0], txn_version)?;
+        Ok(Some(Self::with_current(
+            standardize_address(&delete_resource.address.to_string()),
+            coin_info_type,
+            BigDecimal::zero(),
+            txn_version,
+            txn_timestamp,
+        )))
+    }
+
+    fn with_current(
+        owner_address: String,
+        coin_info_type: &CoinInfoType,
+        amount: BigDecimal,
+        txn_version: i64,
+        txn_timestamp: chrono::NaiveDateTime,
+    ) -> (Self, CurrentCoinBalance) {
+        let current_coin_balance = CurrentCoinBalance {
+            owner_address: owner_address.clone(),
+            coin_type_hash: coin_info_type.to_hash(),
+            coin_type: coin_info_type.get_coin_type_trunc(),
+            amount: amount.clone(),
+            last_transaction_version: txn_version,
+            last_transaction_timestamp: txn_timestamp,
+        };
+        let coin_balance = Self {
+            transaction_version: txn_version,
+            owner_address,
+            coin_type_hash: current_coin_balance.coin_type_hash.clone(),
+            coin_type: current_coin_balance.coin_type.clone(),
+            amount,
+            transaction_timestamp: txn_timestamp,
+        };
+        (coin_balance, current_coin_balance)
+    }
 }
@@ -6,9 +6,9 @@
 #![allow(clippy::unused_unit)]
 
 use super::coin_utils::{CoinInfoType, CoinResource};
-use crate::{database::PgPoolConnection, schema::coin_infos};
+use crate::schema::coin_infos;
 use aptos_api_types::WriteResource as APIWriteResource;
-use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use diesel::{ExpressionMethods, OptionalExtension, PgConnection, QueryDsl, RunQueryDsl};
 use field_count::FieldCount;
 use serde::{Deserialize, Serialize};
 
@@ -28,7 +28,7 @@ pub struct CoinInfo {
     pub supply_aggregator_table_key: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Identifiable, Queryable, Serialize)]
+#[derive(Clone, Debug, Deserialize, Identifiable, Queryable, Serialize)]
 #[diesel(primary_key(coin_type_hash))]
 #[diesel(table_name = coin_infos)]
 pub struct CoinInfoQuery {
@@ -84,7 +84,7 @@ impl CoinInfo {
 impl CoinInfoQuery {
     pub fn get_by_coin_type(
         coin_type: String,
-        conn: &mut PgPoolConnection,
+        conn: &mut PgConnection,
     ) -> diesel::QueryResult<Option<Self>> {
         coin_infos::table
             .filter(coin_infos::coin_type.eq(coin_type))
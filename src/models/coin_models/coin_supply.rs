@@ -6,7 +6,7 @@
 #![allow(clippy::unused_unit)]
 
 use super::coin_infos::CoinInfoQuery;
-use crate::schema::coin_supply;
+use crate::{schema::coin_supply, util::u128_from_json};
 use anyhow::Context;
 use aptos_api_types::WriteTableItem as APIWriteTableItem;
 use bigdecimal::BigDecimal;
@@ -70,18 +70,10 @@ impl CoinSupply {
                     return Ok(None);
                 }
                 // Everything matches. Get the coin supply
-                let supply = data
-                    .value
-                    .as_str()
-                    .map(|s| s.parse::<BigDecimal>())
-                    .context(format!(
-                        "value is not a string: {:?}, table_item {:?}, version {}",
-                        data.value, write_table_item, txn_version
-                    ))?
-                    .context(format!(
-                        "cannot parse string as u128: {:?}, version {}",
-                        data.value, txn_version
-                    ))?;
+                let supply = u128_from_json(&data.value).context(format!(
+                    "cannot parse supply as u128: table_item {:?}, version {}",
+                    write_table_item, txn_version
+                ))?;
                 return Ok(Some(Self {
                     transaction_version: txn_version,
                     coin_type_hash: aptos_coin_info.coin_type_hash.clone(),
@@ -6,6 +6,7 @@
 
 use crate::{
     models::move_resources::MoveResource,
+    strictness,
     util::{hash_str, truncate_str},
 };
 use anyhow::{Context, Result};
@@ -97,6 +98,8 @@ pub struct CoinStoreResource {
     pub coin: Coin,
     pub deposit_events: DepositEventResource,
     pub withdraw_events: WithdrawEventResource,
+    #[serde(default)]
+    pub frozen: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -147,7 +150,7 @@ pub struct CoinInfoType {
 impl CoinInfoType {
     pub fn from_move_type(move_type: &MoveType, txn_version: i64) -> anyhow::Result<Self> {
         let coin_type = move_type.to_string();
-        let (address, _, _) = if let MoveType::Struct(inner) = move_type {
+        let (address, ..) = if let MoveType::Struct(inner) = move_type {
             (
                 inner.address.to_string(),
                 inner.module.to_string(),
@@ -192,9 +195,9 @@ impl CoinResource {
         txn_version: i64,
     ) -> Result<CoinResource> {
         match data_type {
-            "0x1::coin::CoinInfo" => serde_json::from_value(data.clone())
+            "0x1::coin::CoinInfo" => strictness::from_value(data_type, data)
                 .map(|inner| Some(CoinResource::CoinInfoResource(inner))),
-            "0x1::coin::CoinStore" => serde_json::from_value(data.clone())
+            "0x1::coin::CoinStore" => strictness::from_value(data_type, data)
                 .map(|inner| Some(CoinResource::CoinStoreResource(inner))),
             _ => Ok(None),
         }
@@ -248,9 +251,9 @@ impl CoinEvent {
         txn_version: i64,
     ) -> Result<Option<CoinEvent>> {
         match data_type {
-            "0x1::coin::WithdrawEvent" => serde_json::from_value(data.clone())
+            "0x1::coin::WithdrawEvent" => strictness::from_value(data_type, data)
                 .map(|inner| Some(CoinEvent::WithdrawCoinEvent(inner))),
-            "0x1::coin::DepositEvent" => serde_json::from_value(data.clone())
+            "0x1::coin::DepositEvent" => strictness::from_value(data_type, data)
                 .map(|inner| Some(CoinEvent::DepositCoinEvent(inner))),
             _ => Ok(None),
         }
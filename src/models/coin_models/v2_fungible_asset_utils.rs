@@ -9,6 +9,7 @@ use crate::{
         move_resources::MoveResource,
         token_models::{token_utils::URI_LENGTH, v2_token_utils::ResourceReference},
     },
+    strictness,
     util::truncate_str,
 };
 use anyhow::{Context, Result};
@@ -212,11 +213,11 @@ impl V2FungibleAssetResource {
         txn_version: i64,
     ) -> Result<Self> {
         match data_type {
-            "0x1::fungible_asset::Supply" => serde_json::from_value(data.clone())
+            "0x1::fungible_asset::Supply" => strictness::from_value(data_type, data)
                 .map(|inner| Some(Self::FungibleAssetSupply(inner))),
-            "0x1::fungible_asset::Metadata" => serde_json::from_value(data.clone())
+            "0x1::fungible_asset::Metadata" => strictness::from_value(data_type, data)
                 .map(|inner| Some(Self::FungibleAssetMetadata(inner))),
-            "0x1::fungible_asset::FungibleStore" => serde_json::from_value(data.clone())
+            "0x1::fungible_asset::FungibleStore" => strictness::from_value(data_type, data)
                 .map(|inner| Some(Self::FungibleAssetStore(inner))),
             _ => Ok(None),
         }
@@ -244,11 +245,10 @@ impl FungibleAssetEvent {
     ) -> Result<Option<Self>> {
         match data_type {
             "0x1::fungible_asset::DepositEvent" => {
-                serde_json::from_value(data.clone()).map(|inner| Some(Self::DepositEvent(inner)))
-            },
-            "0x1::fungible_asset::WithdrawEvent" => {
-                serde_json::from_value(data.clone()).map(|inner| Some(Self::WithdrawEvent(inner)))
+                strictness::from_value(data_type, data).map(|inner| Some(Self::DepositEvent(inner)))
             },
+            "0x1::fungible_asset::WithdrawEvent" => strictness::from_value(data_type, data)
+                .map(|inner| Some(Self::WithdrawEvent(inner))),
             _ => Ok(None),
         }
         .context(format!(
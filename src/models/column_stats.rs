@@ -0,0 +1,25 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use crate::schema::indexer_column_stats;
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+
+/// Statistics of one column over one flush window, see `custom::driver::column_stats`
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(table_name, column_name, window_end))]
+#[diesel(table_name = indexer_column_stats)]
+pub struct ColumnStat {
+    pub table_name: String,
+    pub column_name: String,
+    pub window_end: chrono::NaiveDateTime,
+    pub window_start: chrono::NaiveDateTime,
+    pub rows_seen: i64,
+    pub rows_sampled: i64,
+    pub null_ratio: f64,
+    pub distinct_estimate: i64,
+    pub top_values: serde_json::Value,
+}
@@ -0,0 +1,25 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use crate::schema::dead_letter_rows;
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+
+/// A row left out of an insert for a value over its column's size limit, see
+/// `custom::driver::row_limits`
+#[derive(Clone, Debug, Deserialize, FieldCount, Insertable, PartialEq, Serialize)]
+#[diesel(table_name = dead_letter_rows)]
+pub struct DeadLetterRow {
+    pub table_name: String,
+    pub transaction_version: i64,
+    /// sha256 of the row as json
+    pub row_hash: String,
+    /// The first column over its limit
+    pub column_name: String,
+    pub column_bytes: i64,
+    pub max_bytes: i64,
+    pub row_data: serde_json::Value,
+}
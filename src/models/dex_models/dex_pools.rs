@@ -0,0 +1,86 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use super::protocols::DexProtocol;
+use crate::{
+    database::PgPoolConnection,
+    models::move_utils::split_type,
+    schema::{dex_pools, move_resources},
+    util::standardize_address,
+};
+use aptos_api_types::WriteResource as APIWriteResource;
+use diesel::{prelude::*, ExpressionMethods};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Key is the pool resource type
+pub type DexPoolMap = HashMap<String, DexPool>;
+
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(pool))]
+#[diesel(table_name = dex_pools)]
+pub struct DexPool {
+    pub pool: String,
+    pub protocol: String,
+    pub pool_address: String,
+    pub coin_x: String,
+    pub coin_y: String,
+    pub first_seen_version: i64,
+}
+
+impl DexPool {
+    fn new(protocol: &DexProtocol, pool: String, pool_address: &str, version: i64) -> Option<Self> {
+        let (_, type_args) = split_type(&pool)?;
+        if type_args.len() < 2 {
+            return None;
+        }
+        Some(Self {
+            protocol: protocol.name.clone(),
+            pool_address: standardize_address(pool_address),
+            coin_x: type_args[0].clone(),
+            coin_y: type_args[1].clone(),
+            first_seen_version: version,
+            pool,
+        })
+    }
+
+    /// Pool resources of any of the protocols
+    pub fn from_write_resource(
+        write_resource: &APIWriteResource,
+        txn_version: i64,
+        protocols: &[DexProtocol],
+    ) -> Option<Self> {
+        let pool = write_resource.data.typ.to_string();
+        let (struct_name, _) = split_type(&pool)?;
+        let protocol = protocols.iter().find(|p| p.is_pool_resource(struct_name))?;
+        Self::new(
+            protocol,
+            pool,
+            &write_resource.address.to_string(),
+            txn_version,
+        )
+    }
+
+    /// First write of the pool resource before `before_version`, for pools created in earlier batches
+    pub fn lookup(
+        conn: &mut PgPoolConnection,
+        protocol: &DexProtocol,
+        pool: &str,
+        before_version: i64,
+    ) -> anyhow::Result<Option<Self>> {
+        let found = move_resources::table
+            .select((move_resources::address, move_resources::transaction_version))
+            .filter(move_resources::type_.eq(pool))
+            .filter(move_resources::transaction_version.lt(before_version))
+            .order(move_resources::transaction_version.asc())
+            .first::<(String, i64)>(conn)
+            .optional()?;
+        Ok(found.and_then(|(pool_address, version)| {
+            Self::new(protocol, pool.to_string(), &pool_address, version)
+        }))
+    }
+}
@@ -0,0 +1,147 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use super::{
+    dex_pools::{DexPool, DexPoolMap},
+    protocols::{parse_amount, DexProtocol},
+};
+use crate::{
+    models::move_utils::split_type,
+    schema::dex_swaps,
+    util::{parse_timestamp, standardize_address},
+};
+use aptos_api_types::{
+    Event as APIEvent, Transaction as APITransaction, WriteSetChange as APIWriteSetChange,
+};
+use bigdecimal::{BigDecimal, Zero};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(transaction_version, event_index))]
+#[diesel(table_name = dex_swaps)]
+pub struct DexSwap {
+    pub transaction_version: i64,
+    pub event_index: i64,
+    pub protocol: String,
+    pub pool: String,
+    pub coin_in: String,
+    pub coin_out: String,
+    pub amount_in: BigDecimal,
+    pub amount_out: BigDecimal,
+    pub sender: String,
+    pub transaction_timestamp: chrono::NaiveDateTime,
+}
+
+impl DexSwap {
+    /// Swaps of the configured protocols, plus the pool resources written by the transaction so
+    /// that pools created in the same transaction can be registered without a db lookup
+    pub fn from_transaction(
+        transaction: &APITransaction,
+        protocols: &[DexProtocol],
+    ) -> anyhow::Result<(Vec<Self>, DexPoolMap)> {
+        let mut swaps = vec![];
+        let mut pools = HashMap::new();
+        let user_txn = match transaction {
+            APITransaction::UserTransaction(txn) => txn,
+            _ => return Ok((swaps, pools)),
+        };
+        let txn_version = user_txn.info.version.0 as i64;
+        let txn_timestamp = parse_timestamp(user_txn.timestamp.0, txn_version);
+        let txn_sender = standardize_address(&user_txn.request.sender.to_string());
+
+        for wsc in &user_txn.info.changes {
+            if let APIWriteSetChange::WriteResource(write_resource) = wsc {
+                if let Some(pool) =
+                    DexPool::from_write_resource(write_resource, txn_version, protocols)
+                {
+                    pools.insert(pool.pool.clone(), pool);
+                }
+            }
+        }
+        for (index, event) in user_txn.events.iter().enumerate() {
+            if let Some(swap) = Self::from_event(
+                event,
+                index as i64,
+                txn_version,
+                txn_timestamp,
+                &txn_sender,
+                protocols,
+            )? {
+                swaps.push(swap);
+            }
+        }
+        Ok((swaps, pools))
+    }
+
+    fn from_event(
+        event: &APIEvent,
+        event_index: i64,
+        txn_version: i64,
+        txn_timestamp: chrono::NaiveDateTime,
+        txn_sender: &str,
+        protocols: &[DexProtocol],
+    ) -> anyhow::Result<Option<Self>> {
+        let event_type = event.typ.to_string();
+        // Not a swap event if it doesn't parse
+        let (struct_name, type_args) = split_type(&event_type).unwrap_or_default();
+        let protocol = match protocols.iter().find(|p| p.is_swap_event(struct_name)) {
+            Some(protocol) => protocol,
+            None => return Ok(None),
+        };
+        if type_args.len() < 2 {
+            anyhow::bail!(
+                "Swap event of {} has less than two coin type args: {}, version {}",
+                protocol.name,
+                event_type,
+                txn_version
+            );
+        }
+        let fields = &protocol.fields;
+        let amount = |field: &str| {
+            parse_amount(&event.data, field).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Swap event of {} is missing amount field {}: {:?}, version {}",
+                    protocol.name,
+                    field,
+                    event.data,
+                    txn_version
+                )
+            })
+        };
+        let (amount_x_in, amount_y_in) =
+            (amount(&fields.amount_x_in)?, amount(&fields.amount_y_in)?);
+        let (amount_x_out, amount_y_out) =
+            (amount(&fields.amount_x_out)?, amount(&fields.amount_y_out)?);
+
+        let (coin_in, amount_in, coin_out, amount_out) = if amount_x_in > BigDecimal::zero() {
+            (&type_args[0], amount_x_in, &type_args[1], amount_y_out)
+        } else {
+            (&type_args[1], amount_y_in, &type_args[0], amount_x_out)
+        };
+        let sender = fields
+            .sender
+            .as_ref()
+            .and_then(|field| event.data.get(field))
+            .and_then(|value| value.as_str())
+            .map(standardize_address)
+            .unwrap_or_else(|| txn_sender.to_string());
+
+        Ok(Some(Self {
+            transaction_version: txn_version,
+            event_index,
+            protocol: protocol.name.clone(),
+            pool: protocol.pool_type(&type_args),
+            coin_in: coin_in.clone(),
+            coin_out: coin_out.clone(),
+            amount_in,
+            amount_out,
+            sender,
+            transaction_timestamp: txn_timestamp,
+        }))
+    }
+}
@@ -0,0 +1,6 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+pub mod dex_pools;
+pub mod dex_swaps;
+pub mod protocols;
@@ -0,0 +1,115 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::util::{standardize_address, u128_from_json};
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+
+pub const LIQUIDSWAP_ADDRESS: &str =
+    "0x190d44266241744264b964a37b8f09863167a12d3e70cda39376cfb4e3561e12";
+pub const PANCAKESWAP_ADDRESS: &str =
+    "0xc7efb4076dbe143cbcd98cfaaa929ecfc8f299203dfff63b95ccb6bfe19850fa";
+
+/// How to read the swaps of one DEX. Can also be given in the driver config (`dex.protocols`),
+/// so new protocols don't need code changes as long as their swap event looks like
+/// `SwapEvent<X, Y, ...>` with separate in/out amounts for both coins.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DexProtocol {
+    /// Stored in `dex_swaps.protocol`
+    pub name: String,
+    /// Event struct without generic args, e.g. `0x...::liquidity_pool::SwapEvent`
+    pub swap_event_type: String,
+    /// Pool struct without generic args. It's instantiated with the swap event's generic args to
+    /// look the pool resource up, e.g. `0x...::liquidity_pool::LiquidityPool`
+    pub pool_resource_type: String,
+    pub fields: SwapFieldMapping,
+}
+
+/// Names of the swap event's data fields. `X` and `Y` are the first two generic args of the event.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SwapFieldMapping {
+    pub amount_x_in: String,
+    pub amount_y_in: String,
+    pub amount_x_out: String,
+    pub amount_y_out: String,
+    /// Falls back to the transaction sender if the event doesn't carry one
+    #[serde(default)]
+    pub sender: Option<String>,
+}
+
+impl DexProtocol {
+    pub fn builtin() -> Vec<Self> {
+        vec![
+            Self {
+                name: "liquidswap".to_string(),
+                swap_event_type: format!("{}::liquidity_pool::SwapEvent", LIQUIDSWAP_ADDRESS),
+                pool_resource_type: format!(
+                    "{}::liquidity_pool::LiquidityPool",
+                    LIQUIDSWAP_ADDRESS
+                ),
+                fields: SwapFieldMapping {
+                    amount_x_in: "x_in".to_string(),
+                    amount_y_in: "y_in".to_string(),
+                    amount_x_out: "x_out".to_string(),
+                    amount_y_out: "y_out".to_string(),
+                    sender: None,
+                },
+            },
+            Self {
+                name: "pancakeswap".to_string(),
+                swap_event_type: format!("{}::swap::SwapEvent", PANCAKESWAP_ADDRESS),
+                pool_resource_type: format!("{}::swap::TokenPairReserve", PANCAKESWAP_ADDRESS),
+                fields: SwapFieldMapping {
+                    amount_x_in: "amount_x_in".to_string(),
+                    amount_y_in: "amount_y_in".to_string(),
+                    amount_x_out: "amount_x_out".to_string(),
+                    amount_y_out: "amount_y_out".to_string(),
+                    sender: Some("user".to_string()),
+                },
+            },
+        ]
+    }
+
+    pub fn is_swap_event(&self, struct_name: &str) -> bool {
+        normalize_struct_name(struct_name) == normalize_struct_name(&self.swap_event_type)
+    }
+
+    pub fn is_pool_resource(&self, struct_name: &str) -> bool {
+        normalize_struct_name(struct_name) == normalize_struct_name(&self.pool_resource_type)
+    }
+
+    /// Pool resource type for the generic args of a swap event
+    pub fn pool_type(&self, type_args: &[String]) -> String {
+        format!("{}<{}>", self.pool_resource_type, type_args.join(", "))
+    }
+}
+
+/// Pads the address so that `0x1::m::S` and `0x0...01::m::S` compare equal
+fn normalize_struct_name(struct_name: &str) -> String {
+    match struct_name.trim().split_once("::") {
+        Some((address, rest)) if address.starts_with("0x") => {
+            format!("{}::{}", standardize_address(address), rest)
+        },
+        _ => struct_name.trim().to_string(),
+    }
+}
+
+/// Amounts are u64 in the events, which the API serializes as strings
+pub fn parse_amount(data: &serde_json::Value, field: &str) -> Option<BigDecimal> {
+    u128_from_json(data.get(field)?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swap_event_matches_short_address() {
+        let protocol = DexProtocol {
+            swap_event_type: "0x0000000000000000000000000000000000000000000000000000000000000abc::pool::SwapEvent".to_string(),
+            ..DexProtocol::builtin().remove(0)
+        };
+        assert!(protocol.is_swap_event("0xabc::pool::SwapEvent"));
+        assert!(!protocol.is_swap_event("0xabc::pool::AddLiquidityEvent"));
+    }
+}
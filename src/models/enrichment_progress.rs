@@ -0,0 +1,39 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+#![allow(clippy::extra_unused_lifetimes)]
+use crate::{database::PgPoolConnection, schema::enrichment_progress};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+
+#[derive(AsChangeset, Debug, Insertable)]
+#[diesel(table_name = enrichment_progress)]
+/// Progress of one enricher after a batch, see `custom::enrichment`
+pub struct EnrichmentProgress {
+    pub enricher_name: String,
+    pub table_name: String,
+    pub last_cursor: Option<serde_json::Value>,
+    pub rows_enriched: i64,
+    pub completed_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug, Queryable)]
+#[diesel(table_name = enrichment_progress)]
+pub struct EnrichmentProgressQuery {
+    pub enricher_name: String,
+    pub table_name: String,
+    pub last_cursor: Option<serde_json::Value>,
+    pub rows_enriched: i64,
+    pub completed_at: Option<chrono::NaiveDateTime>,
+    pub last_updated: chrono::NaiveDateTime,
+}
+
+impl EnrichmentProgressQuery {
+    pub fn get_by_enricher(
+        enricher_name: &str,
+        conn: &mut PgPoolConnection,
+    ) -> diesel::QueryResult<Option<Self>> {
+        enrichment_progress::table
+            .filter(enrichment_progress::enricher_name.eq(enricher_name))
+            .first::<Self>(conn)
+            .optional()
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use crate::{
+    schema::entry_function_daily_stats,
+    util::{hyperloglog::HyperLogLog, parse_timestamp, standardize_address, u64_to_bigdecimal},
+};
+use aptos_api_types::{Transaction as APITransaction, TransactionPayload};
+use bigdecimal::{BigDecimal, Zero};
+use chrono::NaiveDate;
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+pub type DailyStatKey = (NaiveDate, String);
+
+/// Calls of an entry function on a UTC day, see `custom::driver::entry_function_stats`
+#[derive(Clone, Debug, Deserialize, FieldCount, Insertable, PartialEq, Serialize)]
+#[diesel(table_name = entry_function_daily_stats)]
+pub struct EntryFunctionDailyStat {
+    pub date: NaiveDate,
+    pub entry_function_id_str: String,
+    pub call_count: i64,
+    pub success_count: i64,
+    pub distinct_senders_sketch: Vec<u8>,
+    pub distinct_senders: i64,
+    pub total_gas_used: BigDecimal,
+    pub last_transaction_version: i64,
+}
+
+#[derive(Clone, Debug, Deserialize, Queryable, Serialize)]
+#[diesel(table_name = entry_function_daily_stats)]
+pub struct EntryFunctionDailyStatQuery {
+    pub date: NaiveDate,
+    pub entry_function_id_str: String,
+    pub call_count: i64,
+    pub success_count: i64,
+    pub distinct_senders_sketch: Vec<u8>,
+    pub distinct_senders: i64,
+    pub total_gas_used: BigDecimal,
+    pub last_transaction_version: i64,
+    pub inserted_at: chrono::NaiveDateTime,
+}
+
+/// A day's stats of an entry function, as published when the day closes
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct EntryFunctionDailyRollup {
+    pub date: NaiveDate,
+    pub entry_function_id_str: String,
+    pub call_count: i64,
+    pub success_count: i64,
+    /// Estimated, within about 2%
+    pub distinct_senders: i64,
+    pub total_gas_used: BigDecimal,
+}
+
+/// A user transaction calling an entry function
+#[derive(Clone, Debug)]
+pub struct EntryFunctionCall {
+    pub date: NaiveDate,
+    pub entry_function_id_str: String,
+    pub sender: String,
+    pub success: bool,
+    pub gas_used: BigDecimal,
+    pub version: i64,
+}
+
+impl EntryFunctionCall {
+    /// `None` for anything but a user transaction with an entry function payload
+    pub fn from_transaction(transaction: &APITransaction) -> Option<Self> {
+        let APITransaction::UserTransaction(txn) = transaction else {
+            return None;
+        };
+        let TransactionPayload::EntryFunctionPayload(payload) = &txn.request.payload else {
+            return None;
+        };
+        let version = txn.info.version.0 as i64;
+        Some(Self {
+            date: parse_timestamp(txn.timestamp.0, version).date(),
+            entry_function_id_str: payload.function.to_string(),
+            sender: standardize_address(&txn.request.sender.inner().to_hex_literal()),
+            success: txn.info.success,
+            gas_used: u64_to_bigdecimal(txn.info.gas_used.0),
+            version,
+        })
+    }
+}
+
+impl EntryFunctionDailyStat {
+    /// Empty, the senders are filled in from the sketch once all calls are added
+    fn new(key: &DailyStatKey) -> Self {
+        Self {
+            date: key.0,
+            entry_function_id_str: key.1.clone(),
+            call_count: 0,
+            success_count: 0,
+            distinct_senders: 0,
+            distinct_senders_sketch: vec![],
+            total_gas_used: BigDecimal::zero(),
+            last_transaction_version: -1,
+        }
+    }
+
+    /// Adds up `calls` per day and entry function. A bucket in `stored` only gets the calls
+    /// after its last aggregated version, and its rows carry those calls' counts with the stored
+    /// sketch merged in, to be added to the stored counts. Also returns the calls left out.
+    pub fn aggregate(
+        calls: &[EntryFunctionCall],
+        stored: &HashMap<DailyStatKey, (HyperLogLog, i64)>,
+    ) -> (Vec<Self>, usize) {
+        let mut buckets = BTreeMap::<DailyStatKey, (Self, HyperLogLog)>::new();
+        let mut skipped = 0;
+        for call in calls {
+            let key = (call.date, call.entry_function_id_str.clone());
+            let (sketch, last_version) = stored
+                .get(&key)
+                .cloned()
+                .unwrap_or_else(|| (HyperLogLog::default(), -1));
+            if call.version <= last_version {
+                skipped += 1;
+                continue;
+            }
+            let (stat, sketch) = buckets
+                .entry(key)
+                .or_insert_with_key(|key| (Self::new(key), sketch));
+            stat.call_count += 1;
+            stat.success_count += call.success as i64;
+            stat.total_gas_used += &call.gas_used;
+            stat.last_transaction_version = stat.last_transaction_version.max(call.version);
+            sketch.insert(call.sender.as_bytes());
+        }
+        let stats = buckets
+            .into_values()
+            .map(|(stat, sketch)| Self {
+                distinct_senders: sketch.estimate() as i64,
+                distinct_senders_sketch: sketch.to_bytes(),
+                ..stat
+            })
+            .collect();
+        (stats, skipped)
+    }
+}
+
+impl From<EntryFunctionDailyStatQuery> for EntryFunctionDailyRollup {
+    fn from(stat: EntryFunctionDailyStatQuery) -> Self {
+        Self {
+            date: stat.date,
+            entry_function_id_str: stat.entry_function_id_str,
+            call_count: stat.call_count,
+            success_count: stat.success_count,
+            distinct_senders: stat.distinct_senders,
+            total_gas_used: stat.total_gas_used,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(
+        day: u32,
+        function: &str,
+        sender: &str,
+        success: bool,
+        version: i64,
+    ) -> EntryFunctionCall {
+        EntryFunctionCall {
+            date: NaiveDate::from_ymd_opt(2023, 8, day).unwrap(),
+            entry_function_id_str: function.to_string(),
+            sender: sender.to_string(),
+            success,
+            gas_used: BigDecimal::from(10),
+            version,
+        }
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let calls = vec![
+            call(1, "0x1::coin::transfer", "0xa", true, 1),
+            call(1, "0x1::coin::transfer", "0xa", false, 2),
+            call(1, "0x1::coin::transfer", "0xb", true, 3),
+            call(2, "0x1::coin::transfer", "0xa", true, 4),
+        ];
+        let (stats, skipped) = EntryFunctionDailyStat::aggregate(&calls, &HashMap::new());
+        assert_eq!(skipped, 0);
+        assert_eq!(stats.len(), 2);
+        assert_eq!(
+            (
+                stats[0].call_count,
+                stats[0].success_count,
+                stats[0].distinct_senders
+            ),
+            (3, 2, 2)
+        );
+        assert_eq!(stats[0].total_gas_used, BigDecimal::from(30));
+        assert_eq!(stats[0].last_transaction_version, 3);
+        assert_eq!(stats[1].date, NaiveDate::from_ymd_opt(2023, 8, 2).unwrap());
+
+        // Re-applied after the first two calls were stored, only the third one counts
+        let mut sketch = HyperLogLog::default();
+        sketch.insert(b"0xa");
+        let key = (calls[0].date, calls[0].entry_function_id_str.clone());
+        let stored = HashMap::from([(key, (sketch, 2))]);
+        let (stats, skipped) = EntryFunctionDailyStat::aggregate(&calls[..3], &stored);
+        assert_eq!(skipped, 2);
+        assert_eq!((stats[0].call_count, stats[0].distinct_senders), (1, 2));
+    }
+}
@@ -1,16 +1,35 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 #![allow(clippy::extra_unused_lifetimes)]
-use super::transactions::TransactionQuery;
-use crate::{models::transactions::Transaction, schema::events, util::standardize_address};
-use aptos_api_types::Event as APIEvent;
 use field_count::FieldCount;
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "indexer")]
+use {
+    super::transactions::TransactionQuery,
+    crate::{
+        custom::driver::{
+            config::OversizeAction,
+            row_limits::{ColumnLimit, RowLimits, INDEXED_COLUMN_MAX_BYTES},
+        },
+        models::{move_utils::StructTag, transactions::Transaction},
+        schema::events,
+        util::standardize_address,
+    },
+    aptos_api_types::Event as APIEvent,
+};
 
-#[derive(Associations, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
-#[diesel(belongs_to(Transaction, foreign_key = transaction_version))]
-#[diesel(primary_key(account_address, creation_number, sequence_number))]
-#[diesel(table_name = events)]
+/// Also built without the `indexer` feature, for consumers of the published messages
+#[derive(Clone, Debug, Deserialize, FieldCount, Serialize)]
+#[cfg_attr(feature = "indexer", derive(Associations, Identifiable, Insertable))]
+#[cfg_attr(
+    feature = "indexer",
+    diesel(belongs_to(Transaction, foreign_key = transaction_version))
+)]
+#[cfg_attr(
+    feature = "indexer",
+    diesel(primary_key(account_address, creation_number, sequence_number))
+)]
+#[cfg_attr(feature = "indexer", diesel(table_name = events))]
 pub struct Event {
     pub sequence_number: i64,
     pub creation_number: i64,
@@ -20,9 +39,19 @@ pub struct Event {
     pub type_: String,
     pub data: serde_json::Value,
     pub event_index: Option<i64>,
+    /// Where the event's type is declared, null if the type isn't a struct or can't be parsed
+    pub event_account_address: Option<String>,
+    pub event_module: Option<String>,
+    pub event_name: Option<String>,
+    /// The top level generic type params of the type, as strings, e.g.
+    /// `["0x1::aptos_coin::AptosCoin"]`
+    pub event_type_params: Option<serde_json::Value>,
+    /// Of the transaction's block, null for genesis
+    pub block_timestamp: Option<chrono::NaiveDateTime>,
 }
 
 /// Need a separate struct for queryable because we don't want to define the inserted_at column (letting DB fill)
+#[cfg(feature = "indexer")]
 #[derive(Associations, Debug, Deserialize, Identifiable, Queryable, Serialize)]
 #[diesel(belongs_to(TransactionQuery, foreign_key = transaction_version))]
 #[diesel(primary_key(account_address, creation_number, sequence_number))]
@@ -37,24 +66,47 @@ pub struct EventQuery {
     pub data: serde_json::Value,
     pub inserted_at: chrono::NaiveDateTime,
     pub event_index: Option<i64>,
+    pub event_account_address: Option<String>,
+    pub event_module: Option<String>,
+    pub event_name: Option<String>,
+    pub event_type_params: Option<serde_json::Value>,
+    pub block_timestamp: Option<chrono::NaiveDateTime>,
 }
 
+#[cfg(feature = "indexer")]
 impl Event {
     pub fn from_event(
         event: &APIEvent,
         transaction_version: i64,
         transaction_block_height: i64,
+        block_timestamp: Option<chrono::NaiveDateTime>,
         event_index: i64,
     ) -> Self {
+        let type_ = event.typ.to_string();
+        let struct_tag = StructTag::parse(&type_);
+        // Types that aren't structs, e.g. `u64`, have no parts to begin with
+        if struct_tag.is_none() && type_.contains("::") {
+            aptos_logger::warn!(
+                transaction_version = transaction_version,
+                event_index = event_index,
+                type_ = type_.as_str(),
+                "Failed to parse event type, leaving its parts null"
+            );
+        }
         Event {
             account_address: standardize_address(&event.guid.account_address.to_string()),
             creation_number: event.guid.creation_number.0 as i64,
             sequence_number: event.sequence_number.0 as i64,
             transaction_version,
             transaction_block_height,
-            type_: event.typ.to_string(),
+            type_,
             data: event.data.clone(),
             event_index: Some(event_index),
+            event_account_address: struct_tag.as_ref().map(|tag| tag.address.clone()),
+            event_module: struct_tag.as_ref().map(|tag| tag.module.clone()),
+            event_name: struct_tag.as_ref().map(|tag| tag.name.clone()),
+            event_type_params: struct_tag.map(|tag| tag.type_params_json()),
+            block_timestamp,
         }
     }
 
@@ -62,6 +114,7 @@ impl Event {
         events: &[APIEvent],
         transaction_version: i64,
         transaction_block_height: i64,
+        block_timestamp: Option<chrono::NaiveDateTime>,
     ) -> Vec<Self> {
         events
             .iter()
@@ -71,6 +124,7 @@ impl Event {
                     event,
                     transaction_version,
                     transaction_block_height,
+                    block_timestamp,
                     index as i64,
                 )
             })
@@ -78,5 +132,67 @@ impl Event {
     }
 }
 
+#[cfg(feature = "indexer")]
+impl RowLimits for Event {
+    const LIMITS: &'static [ColumnLimit] = &[ColumnLimit::new(
+        "type",
+        INDEXED_COLUMN_MAX_BYTES,
+        OversizeAction::DeadLetter,
+    )];
+    const TABLE: &'static str = "events";
+
+    fn transaction_version(&self) -> i64 {
+        self.transaction_version
+    }
+
+    fn column(&self, _index: usize) -> &str {
+        &self.type_
+    }
+
+    fn column_mut(&mut self, _index: usize) -> &mut String {
+        &mut self.type_
+    }
+}
+
 // Prevent conflicts with other things named `Event`
 pub type EventModel = Event;
+
+#[cfg(all(test, feature = "indexer"))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_from_event() {
+        let api_event: APIEvent = serde_json::from_value(json!({
+            "guid": { "account_address": "0xab", "creation_number": "2" },
+            "sequence_number": "7",
+            "type": "0x1::coin::DepositEvent<0x1::aptos_coin::AptosCoin>",
+            "data": { "amount": "1" }
+        }))
+        .unwrap();
+        let event = EventModel::from_event(&api_event, 10, 2, None, 0);
+        assert_eq!(
+            event.event_account_address,
+            Some(standardize_address("0x1"))
+        );
+        assert_eq!(event.event_module.as_deref(), Some("coin"));
+        assert_eq!(event.event_name.as_deref(), Some("DepositEvent"));
+        assert_eq!(
+            event.event_type_params,
+            Some(json!(["0x1::aptos_coin::AptosCoin"]))
+        );
+
+        let primitive: APIEvent = serde_json::from_value(json!({
+            "guid": { "account_address": "0x0", "creation_number": "0" },
+            "sequence_number": "0",
+            "type": "u64",
+            "data": "1"
+        }))
+        .unwrap();
+        let event = EventModel::from_event(&primitive, 10, 2, None, 1);
+        assert_eq!(event.type_, "u64");
+        assert_eq!(event.event_module, None);
+        assert_eq!(event.event_type_params, None);
+    }
+}
@@ -0,0 +1,43 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use crate::{database::PgPoolConnection, schema::index_advisor_observations};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+
+/// How a query helper filtered a table and was planned, see `queries::index_advisor`
+#[derive(Clone, Debug, Deserialize, Queryable, Serialize)]
+#[diesel(table_name = index_advisor_observations)]
+pub struct IndexAdvisorObservation {
+    pub helper: String,
+    pub table_name: String,
+    /// Comma separated
+    pub filter_columns: String,
+    pub calls: i64,
+    pub total_exec_ms: f64,
+    pub sampled_plans: i64,
+    pub seq_scan_plans: i64,
+    pub max_estimated_rows: i64,
+    pub last_plan: serde_json::Value,
+    pub last_seen: chrono::NaiveDateTime,
+}
+
+impl IndexAdvisorObservation {
+    /// Observations with at least one sampled sequential scan
+    pub fn get_with_seq_scans(conn: &mut PgPoolConnection) -> diesel::QueryResult<Vec<Self>> {
+        index_advisor_observations::table
+            .filter(index_advisor_observations::seq_scan_plans.gt(0))
+            .load::<Self>(conn)
+    }
+
+    pub fn filter_columns(&self) -> Vec<String> {
+        self.filter_columns
+            .split(',')
+            .filter(|column| !column.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+}
@@ -9,6 +9,8 @@ use diesel::{OptionalExtension, QueryDsl, RunQueryDsl};
 #[diesel(primary_key(chain_id))]
 pub struct LedgerInfo {
     pub chain_id: i64,
+    /// Hash of the transaction at version 0, see `custom::driver::ledger_reset`
+    pub genesis_hash: Option<String>,
 }
 
 impl LedgerInfo {
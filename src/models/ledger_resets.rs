@@ -0,0 +1,18 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+#![allow(clippy::extra_unused_lifetimes)]
+use crate::schema::ledger_resets;
+use serde_json::Value;
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = ledger_resets)]
+/// A ledger reset detected at startup, see `custom::driver::ledger_reset`
+pub struct LedgerReset {
+    pub chain_id: i64,
+    pub previous_genesis_hash: String,
+    pub new_genesis_hash: String,
+    /// `halt` or `wipe`
+    pub action: String,
+    pub truncated_tables: Value,
+    pub processor: String,
+}
@@ -1,20 +1,88 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
+// Only `events` and `transactions` are built without the `indexer` feature, see `crate::client`
+#[cfg(feature = "indexer")]
+pub mod account_derivations;
+#[cfg(feature = "indexer")]
+pub mod admin_audit_log;
+#[cfg(feature = "indexer")]
+pub mod anomalies;
+#[cfg(feature = "indexer")]
+pub mod app_scope;
+#[cfg(feature = "indexer")]
+pub mod asset_stores;
+#[cfg(feature = "indexer")]
+pub mod asset_transfers;
+#[cfg(feature = "indexer")]
+pub mod backfill_windows;
+#[cfg(feature = "indexer")]
+pub mod balance_anomalies;
+#[cfg(feature = "indexer")]
 pub mod block_metadata_transactions;
+#[cfg(feature = "indexer")]
+pub mod change_feed;
+#[cfg(feature = "indexer")]
 pub mod coin_models;
+#[cfg(feature = "indexer")]
+pub mod column_stats;
+#[cfg(feature = "indexer")]
+pub mod dead_letter_rows;
+#[cfg(feature = "indexer")]
+pub mod dex_models;
+#[cfg(feature = "indexer")]
+pub mod enrichment_progress;
+#[cfg(feature = "indexer")]
+pub mod entry_function_daily_stats;
 pub mod events;
+#[cfg(feature = "indexer")]
+pub mod index_advisor_observations;
+#[cfg(feature = "indexer")]
 pub mod ledger_info;
+#[cfg(feature = "indexer")]
+pub mod ledger_resets;
+#[cfg(feature = "indexer")]
 pub mod move_modules;
+#[cfg(feature = "indexer")]
 pub mod move_resources;
+#[cfg(feature = "indexer")]
 pub mod move_tables;
+#[cfg(feature = "indexer")]
+pub mod move_utils;
+#[cfg(feature = "indexer")]
+pub mod object_ownership;
+#[cfg(feature = "indexer")]
+pub mod onchain_config_changes;
+#[cfg(feature = "indexer")]
+pub mod operations_log;
+#[cfg(feature = "indexer")]
+pub mod processor_leases;
+#[cfg(feature = "indexer")]
 pub mod processor_status;
+#[cfg(feature = "indexer")]
 pub mod processor_statuses;
+#[cfg(feature = "indexer")]
 pub mod property_map;
+#[cfg(feature = "indexer")]
+pub mod range_hashes;
+#[cfg(feature = "indexer")]
+pub mod scripts;
+#[cfg(feature = "indexer")]
+pub mod shadow_diffs;
+#[cfg(feature = "indexer")]
 pub mod signatures;
+#[cfg(feature = "indexer")]
 pub mod stake_models;
+#[cfg(feature = "indexer")]
+pub mod storage_usage;
+#[cfg(feature = "indexer")]
 pub mod token_models;
 pub mod transactions;
+#[cfg(feature = "indexer")]
 pub mod user_transactions;
+#[cfg(feature = "indexer")]
 pub mod v2_objects;
+#[cfg(feature = "indexer")]
+pub mod validation_violations;
+#[cfg(feature = "indexer")]
 pub mod write_set_changes;
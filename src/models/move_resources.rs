@@ -1,11 +1,23 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 #![allow(clippy::extra_unused_lifetimes)]
-use crate::{models::transactions::Transaction, schema::move_resources, util::standardize_address};
-use anyhow::{Context, Result};
+use crate::{
+    custom::driver::{
+        config::OversizeAction,
+        row_limits::{ColumnLimit, RowLimits, INDEXED_COLUMN_MAX_BYTES},
+    },
+    models::{move_utils::StructTag, transactions::Transaction},
+    schema::{current_move_resources, move_resources},
+    util::{
+        sort_key::{hex_key, sort_by_pk, SortKey},
+        standardize_address,
+    },
+};
 use aptos_api_types::{DeleteResource, MoveStructTag as APIMoveStructTag, WriteResource};
 use field_count::FieldCount;
 use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, collections::HashMap};
+
 #[derive(
     Associations, Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize,
 )]
@@ -18,15 +30,38 @@ pub struct MoveResource {
     pub transaction_block_height: i64,
     pub name: String,
     pub type_: String,
+    /// The account holding the resource
     pub address: String,
     pub module: String,
+    /// The top level generic type params of the type, as strings
     pub generic_type_params: Option<serde_json::Value>,
     pub data: Option<serde_json::Value>,
     pub is_deleted: bool,
     pub state_key_hash: String,
+    /// Where the resource's type is declared, e.g. `0x1` for any `0x1::coin::CoinStore<T>`
+    pub resource_address: String,
+}
+
+/// Latest state of every resource, a deleted resource keeping its last type and a tombstone
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(address, type_))]
+#[diesel(table_name = current_move_resources)]
+pub struct CurrentMoveResource {
+    pub address: String,
+    pub type_: String,
+    pub module: String,
+    pub name: String,
+    pub generic_type_params: Option<serde_json::Value>,
+    pub data: Option<serde_json::Value>,
+    pub state_key_hash: String,
+    pub last_transaction_version: i64,
+    pub is_deleted: bool,
+    /// Where the resource's type is declared
+    pub resource_address: String,
 }
 
 pub struct MoveStructTag {
+    address: String,
     module: String,
     name: String,
     generic_type_params: Option<serde_json::Value>,
@@ -52,6 +87,7 @@ impl MoveResource {
             data: Some(serde_json::to_value(&write_resource.data.data).unwrap()),
             is_deleted: false,
             state_key_hash: standardize_address(write_resource.state_key_hash.as_str()),
+            resource_address: parsed_data.address,
         }
     }
 
@@ -74,23 +110,197 @@ impl MoveResource {
             data: None,
             is_deleted: true,
             state_key_hash: standardize_address(delete_resource.state_key_hash.as_str()),
+            resource_address: parsed_data.address,
         }
     }
 
+    /// The latest resource of every address and type in `resources`, sorted by primary key to
+    /// avoid deadlocks between concurrent batches
+    pub fn current_resources(resources: &[MoveResource]) -> Vec<CurrentMoveResource> {
+        let mut current = HashMap::new();
+        for resource in resources {
+            // In version and write set order, so the last write of a key wins
+            current.insert(
+                (resource.address.clone(), resource.type_.clone()),
+                CurrentMoveResource::from(resource),
+            );
+        }
+        let mut current = current.into_values().collect::<Vec<_>>();
+        sort_by_pk(&mut current);
+        current
+    }
+
+    /// Split like event types, see `models::move_utils`
     pub fn convert_move_struct_tag(struct_tag: &APIMoveStructTag) -> MoveStructTag {
-        MoveStructTag {
-            module: struct_tag.module.to_string(),
-            name: struct_tag.name.to_string(),
-            generic_type_params: struct_tag
-                .generic_type_params
-                .iter()
-                .map(|move_type| -> Result<Option<serde_json::Value>> {
-                    Ok(Some(
-                        serde_json::to_value(move_type).context("Failed to parse move type")?,
-                    ))
-                })
-                .collect::<Result<Option<serde_json::Value>>>()
-                .unwrap_or(None),
+        match StructTag::parse(&struct_tag.to_string()) {
+            Some(tag) => MoveStructTag {
+                generic_type_params: Some(tag.type_params_json()),
+                address: tag.address,
+                module: tag.module,
+                name: tag.name,
+            },
+            // The API only returns well formed tags
+            None => MoveStructTag {
+                address: standardize_address(&struct_tag.address.to_string()),
+                module: struct_tag.module.to_string(),
+                name: struct_tag.name.to_string(),
+                generic_type_params: None,
+            },
+        }
+    }
+}
+
+/// `module` and `name` are in `mr_addr_mod_name_ver_index`, and can't be longer than Move allows
+/// unless something upstream is broken
+impl RowLimits for MoveResource {
+    const LIMITS: &'static [ColumnLimit] = &[
+        ColumnLimit::new("type", INDEXED_COLUMN_MAX_BYTES, OversizeAction::Truncate),
+        ColumnLimit::new("module", INDEXED_COLUMN_MAX_BYTES, OversizeAction::Fail),
+        ColumnLimit::new("name", INDEXED_COLUMN_MAX_BYTES, OversizeAction::Fail),
+    ];
+    const TABLE: &'static str = "move_resources";
+
+    fn transaction_version(&self) -> i64 {
+        self.transaction_version
+    }
+
+    fn column(&self, index: usize) -> &str {
+        match index {
+            0 => &self.type_,
+            1 => &self.module,
+            _ => &self.name,
+        }
+    }
+
+    fn column_mut(&mut self, index: usize) -> &mut String {
+        match index {
+            0 => &mut self.type_,
+            1 => &mut self.module,
+            _ => &mut self.name,
+        }
+    }
+}
+
+impl From<&MoveResource> for CurrentMoveResource {
+    fn from(resource: &MoveResource) -> Self {
+        Self {
+            address: resource.address.clone(),
+            type_: resource.type_.clone(),
+            module: resource.module.clone(),
+            name: resource.name.clone(),
+            generic_type_params: resource.generic_type_params.clone(),
+            data: resource.data.clone(),
+            state_key_hash: resource.state_key_hash.clone(),
+            last_transaction_version: resource.transaction_version,
+            is_deleted: resource.is_deleted,
+            resource_address: resource.resource_address.clone(),
         }
     }
 }
+
+impl CurrentMoveResource {
+    /// Kafka key of the resource, the same for all its versions
+    pub fn topic_key(&self) -> String {
+        format!("{}:{}", self.address, self.type_)
+    }
+}
+
+/// The 32 bytes of the standardized address, then the type, which has no compact form
+impl SortKey for CurrentMoveResource {
+    type Key = ([u8; 32], String);
+
+    fn sort_key(&self) -> Option<Self::Key> {
+        Some((hex_key::<32>(&self.address, "0x")?, self.type_.clone()))
+    }
+
+    fn cmp_pk(&self, other: &Self) -> Ordering {
+        (&self.address, &self.type_).cmp(&(&other.address, &other.type_))
+    }
+}
+
+/// `type` is in the primary key, a row with a type too long to index is dead lettered
+impl RowLimits for CurrentMoveResource {
+    const LIMITS: &'static [ColumnLimit] = &[ColumnLimit::new(
+        "type",
+        INDEXED_COLUMN_MAX_BYTES,
+        OversizeAction::DeadLetter,
+    )];
+    const TABLE: &'static str = "current_move_resources";
+
+    fn transaction_version(&self) -> i64 {
+        self.last_transaction_version
+    }
+
+    fn column(&self, _index: usize) -> &str {
+        &self.type_
+    }
+
+    fn column_mut(&mut self, _index: usize) -> &mut String {
+        &mut self.type_
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resource(version: i64, address: u64, type_: &str) -> MoveResource {
+        MoveResource {
+            transaction_version: version,
+            write_set_change_index: 0,
+            transaction_block_height: 0,
+            name: "Resource".to_string(),
+            type_: type_.to_string(),
+            address: format!("0x{:064x}", address),
+            module: "module".to_string(),
+            generic_type_params: None,
+            data: None,
+            is_deleted: false,
+            state_key_hash: format!("0x{:064x}", version),
+            resource_address: standardize_address("0x1"),
+        }
+    }
+
+    #[test]
+    fn test_current_resources() {
+        let resources = vec![
+            resource(1, 0xb, "0x1::a::A"),
+            resource(2, 0xa, "0x1::b::B"),
+            resource(3, 0xa, "0x1::a::A"),
+            resource(4, 0xb, "0x1::a::A"),
+        ];
+        let current = MoveResource::current_resources(&resources);
+        assert_eq!(
+            current
+                .iter()
+                .map(|resource| (resource.address.as_str(), resource.type_.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (resources[2].address.as_str(), "0x1::a::A"),
+                (resources[1].address.as_str(), "0x1::b::B"),
+                (resources[0].address.as_str(), "0x1::a::A"),
+            ]
+        );
+        // The last write of a key wins
+        assert_eq!(current[2].last_transaction_version, 4);
+    }
+
+    #[test]
+    fn test_convert_move_struct_tag() {
+        let struct_tag = "0x1::smart_table::SmartTable<address, \
+                          vector<0x1::object::Object<0x4::token::Token>>>"
+            .parse::<APIMoveStructTag>()
+            .unwrap();
+        let parsed = MoveResource::convert_move_struct_tag(&struct_tag);
+        assert_eq!(parsed.address, standardize_address("0x1"));
+        assert_eq!(parsed.module, "smart_table");
+        assert_eq!(parsed.name, "SmartTable");
+        assert_eq!(
+            parsed.generic_type_params,
+            Some(serde_json::json!([
+                "address",
+                "vector<0x1::object::Object<0x4::token::Token>>"
+            ]))
+        );
+    }
+}
@@ -2,13 +2,22 @@
 // SPDX-License-Identifier: Apache-2.0
 #![allow(clippy::extra_unused_lifetimes)]
 use crate::{
+    custom::driver::{
+        config::OversizeAction,
+        row_limits::{ColumnLimit, RowLimits, INDEXED_COLUMN_MAX_BYTES},
+    },
     models::transactions::Transaction,
     schema::{current_table_items, table_items, table_metadatas},
-    util::{hash_str, standardize_address},
+    util::{
+        hash_str,
+        sort_key::{concat_keys, hex_key, SortKey},
+        standardize_address,
+    },
 };
 use aptos_api_types::{DeleteTableItem, WriteTableItem};
 use field_count::FieldCount;
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 
 #[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
 #[diesel(primary_key(table_handle, key_hash))]
@@ -120,6 +129,64 @@ impl TableItem {
     }
 }
 
+/// The 32 bytes of the standardized table handle followed by the 32 of the key hash
+impl SortKey for CurrentTableItem {
+    type Key = [u8; 64];
+
+    fn sort_key(&self) -> Option<Self::Key> {
+        Some(concat_keys(
+            hex_key::<32>(&self.table_handle, "0x")?,
+            hex_key::<32>(&self.key_hash, "")?,
+        ))
+    }
+
+    fn cmp_pk(&self, other: &Self) -> Ordering {
+        (&self.table_handle, &self.key_hash).cmp(&(&other.table_handle, &other.key_hash))
+    }
+}
+
+impl RowLimits for TableItem {
+    const LIMITS: &'static [ColumnLimit] = &[ColumnLimit::new(
+        "key",
+        INDEXED_COLUMN_MAX_BYTES,
+        OversizeAction::DeadLetter,
+    )];
+    const TABLE: &'static str = "table_items";
+
+    fn transaction_version(&self) -> i64 {
+        self.transaction_version
+    }
+
+    fn column(&self, _index: usize) -> &str {
+        &self.key
+    }
+
+    fn column_mut(&mut self, _index: usize) -> &mut String {
+        &mut self.key
+    }
+}
+
+impl RowLimits for CurrentTableItem {
+    const LIMITS: &'static [ColumnLimit] = &[ColumnLimit::new(
+        "key",
+        INDEXED_COLUMN_MAX_BYTES,
+        OversizeAction::DeadLetter,
+    )];
+    const TABLE: &'static str = "current_table_items";
+
+    fn transaction_version(&self) -> i64 {
+        self.last_transaction_version
+    }
+
+    fn column(&self, _index: usize) -> &str {
+        &self.key
+    }
+
+    fn column_mut(&mut self, _index: usize) -> &mut String {
+        &mut self.key
+    }
+}
+
 impl TableMetadata {
     pub fn from_write_table_item(table_item: &WriteTableItem) -> Self {
         Self {
@@ -0,0 +1,265 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Splitting Move type strings, e.g. `0x1::coin::CoinStore<0x1::aptos_coin::AptosCoin>`, into
+//! their parts. Every model that splits a type goes through here: resource and event types, and
+//! the coin types taken from the generic params of DEX swaps and pools.
+
+use crate::{custom::driver::app_scope::is_address, util::standardize_address};
+use std::fmt;
+
+/// The parts of a struct type, e.g. `0x1::coin::CoinStore<0x1::aptos_coin::AptosCoin>`
+#[derive(Clone, Debug, PartialEq)]
+pub struct StructTag {
+    /// Standardized
+    pub address: String,
+    pub module: String,
+    pub name: String,
+    /// Top level only, nested generics are kept in their param, e.g. `vector<0x1::m::T<u8>>`
+    pub type_params: Vec<String>,
+}
+
+impl StructTag {
+    /// None if `type_str` isn't a well formed struct type
+    pub fn parse(type_str: &str) -> Option<Self> {
+        let (base, type_params) = split_type(type_str)?;
+        let mut parts = base.split("::");
+        let (address, module, name) = (parts.next()?, parts.next()?, parts.next()?);
+        if parts.next().is_some()
+            || !is_address(address)
+            || !is_identifier(module)
+            || !is_identifier(name)
+        {
+            return None;
+        }
+        Some(Self {
+            address: standardize_address(address),
+            module: module.to_string(),
+            name: name.to_string(),
+            type_params,
+        })
+    }
+
+    /// The top level generic type params, as a JSON array of strings
+    pub fn type_params_json(&self) -> serde_json::Value {
+        serde_json::Value::from(self.type_params.clone())
+    }
+}
+
+/// With the standardized address, so parsing it again gives the same tag
+impl fmt::Display for StructTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}::{}::{}", self.address, self.module, self.name)?;
+        if !self.type_params.is_empty() {
+            write!(f, "<{}>", self.type_params.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits any type, e.g. `vector<u8>` or a struct type, into what's before its generic params and
+/// its top level generic params. None if the brackets don't balance or a param is empty.
+pub fn split_type(type_str: &str) -> Option<(&str, Vec<String>)> {
+    let type_str = type_str.trim();
+    match type_str.find('<') {
+        Some(start) => Some((
+            type_str[..start].trim(),
+            split_type_params(type_str[start + 1..].strip_suffix('>')?)?,
+        )),
+        None => Some((type_str, vec![])),
+    }
+}
+
+/// Splits `A, B<C, D>` at its top level commas, None if the brackets don't balance
+pub fn split_type_params(inner: &str) -> Option<Vec<String>> {
+    let mut params = vec![];
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in inner.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => depth = depth.checked_sub(1)?,
+            ',' if depth == 0 => {
+                params.push(inner[start..i].trim().to_string());
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    params.push(inner[start..].trim().to_string());
+    (depth == 0 && params.iter().all(|param| !param.is_empty())).then_some(params)
+}
+
+fn is_identifier(value: &str) -> bool {
+    let mut chars = value.chars();
+    chars
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIQUIDSWAP: &str = "0x190d44266241744264b964a37b8f09863167a12d3e70cda39376cfb4e3561e12";
+    const USDC: &str =
+        "0xf22bede237a07e121b56d91a491eb7bcdfd1f5907926a9e58338f964a01b17fa::asset::USDC";
+
+    /// Struct tags seen on mainnet, as the API writes them
+    fn corpus() -> Vec<String> {
+        vec![
+            "0x1::account::Account".to_string(),
+            "0x1::coin::CoinStore<0x1::aptos_coin::AptosCoin>".to_string(),
+            format!("0x1::coin::CoinInfo<{}>", USDC),
+            "0x1::event::EventHandle<0x1::coin::DepositEvent>".to_string(),
+            "0x1::object::ObjectCore".to_string(),
+            "0x4::token::Token".to_string(),
+            format!(
+                "{0}::liquidity_pool::LiquidityPool<0x1::aptos_coin::AptosCoin, {1}, \
+                 {0}::curves::Uncorrelated>",
+                LIQUIDSWAP, USDC
+            ),
+            format!(
+                "0x1::coin::CoinStore<{0}::lp_coin::LP<0x1::aptos_coin::AptosCoin, {1}, \
+                 {0}::curves::Uncorrelated>>",
+                LIQUIDSWAP, USDC
+            ),
+            // Three levels
+            format!(
+                "0x1::coin::CoinInfo<{0}::lp_coin::LP<{0}::lp_coin::LP<0x1::aptos_coin::AptosCoin, \
+                 {1}, {0}::curves::Stable>, {1}, {0}::curves::Uncorrelated>>",
+                LIQUIDSWAP, USDC
+            ),
+            "0x1::smart_table::SmartTable<address, vector<0x1::object::Object<0x4::token::Token>>>"
+                .to_string(),
+            "0x1::table::Table<vector<u8>, vector<vector<0x1::option::Option<u64>>>>".to_string(),
+        ]
+    }
+
+    #[test]
+    fn test_parse_struct_tag() {
+        let tag = StructTag::parse("0x1::coin::CoinStore<0x1::aptos_coin::AptosCoin>").unwrap();
+        assert_eq!(tag.address, standardize_address("0x1"));
+        assert_eq!(tag.module, "coin");
+        assert_eq!(tag.name, "CoinStore");
+        assert_eq!(tag.type_params, vec!["0x1::aptos_coin::AptosCoin"]);
+        assert_eq!(
+            tag.type_params_json(),
+            serde_json::json!(["0x1::aptos_coin::AptosCoin"])
+        );
+
+        let nested =
+            StructTag::parse("0xa11ce::pool::Swapped<0x1::coin::Coin<0xb::m::T<u8>>, vector<u64>>")
+                .unwrap();
+        assert_eq!(nested.type_params, vec![
+            "0x1::coin::Coin<0xb::m::T<u8>>",
+            "vector<u64>"
+        ]);
+        assert!(StructTag::parse("0x1::coin::DepositEvent")
+            .unwrap()
+            .type_params
+            .is_empty());
+
+        for malformed in [
+            "u64",
+            "vector<u8>",
+            "0x1::coin",
+            "0x1::coin::Coin::Extra",
+            "coin::Coin::T",
+            "0x1::coin::Coin<0x1::aptos_coin::AptosCoin",
+            "0x1::coin::Coin<A>>",
+            "0x1::coin::Coin<A,>",
+            "0x1::1coin::Coin",
+        ] {
+            assert_eq!(StructTag::parse(malformed), None, "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn test_split_type() {
+        let (name, params) = split_type(&corpus()[6]).unwrap();
+        assert_eq!(
+            name,
+            format!("{}::liquidity_pool::LiquidityPool", LIQUIDSWAP)
+        );
+        assert_eq!(params, vec![
+            "0x1::aptos_coin::AptosCoin".to_string(),
+            USDC.to_string(),
+            format!("{}::curves::Uncorrelated", LIQUIDSWAP),
+        ]);
+        assert_eq!(
+            split_type("vector<vector<u8>>"),
+            Some(("vector", vec!["vector<u8>".to_string()]))
+        );
+        assert_eq!(split_type("0x1::a::B"), Some(("0x1::a::B", vec![])));
+        assert_eq!(split_type("0x1::a::B<C"), None);
+    }
+
+    #[test]
+    fn test_corpus_round_trip() {
+        for type_str in corpus() {
+            let tag = StructTag::parse(&type_str).unwrap_or_else(|| panic!("{}", type_str));
+            // The params are kept as written
+            assert_eq!(
+                tag.to_string(),
+                type_str.replacen(type_str.split("::").next().unwrap(), &tag.address, 1)
+            );
+            assert_eq!(StructTag::parse(&tag.to_string()), Some(tag.clone()));
+            for param in &tag.type_params {
+                if let Some(nested) = StructTag::parse(param) {
+                    assert_eq!(StructTag::parse(&nested.to_string()), Some(nested));
+                }
+            }
+        }
+    }
+
+    /// A random type of at most `depth` levels of generics, from a linear congruential generator
+    fn random_type(seed: &mut u64, depth: u32) -> String {
+        let mut next = |bound: u64| {
+            *seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (*seed >> 33) % bound
+        };
+        match (next(6), depth) {
+            (0, _) => ["u8", "u64", "u128", "bool", "address"][next(5) as usize].to_string(),
+            (1, depth) if depth > 0 => format!("vector<{}>", random_type(seed, depth - 1)),
+            (_, depth) => {
+                let address = format!("0x{:x}", next(u64::MAX));
+                let params = match depth {
+                    0 => 0,
+                    _ => next(4),
+                };
+                let mut type_str = format!("{}::m{}::S{}", address, next(10), next(10));
+                if params > 0 {
+                    let params = (0..params)
+                        .map(|_| random_type(seed, depth - 1))
+                        .collect::<Vec<_>>();
+                    type_str.push_str(&format!("<{}>", params.join(", ")));
+                }
+                type_str
+            },
+        }
+    }
+
+    #[test]
+    fn test_fuzz_round_trip() {
+        let mut seed = 42;
+        for _ in 0..1_000 {
+            let type_str = random_type(&mut seed, 3);
+            let (base, params) = split_type(&type_str).unwrap();
+            let rebuilt = if params.is_empty() {
+                base.to_string()
+            } else {
+                format!("{}<{}>", base, params.join(", "))
+            };
+            assert_eq!(rebuilt, type_str);
+            if let Some(tag) = StructTag::parse(&type_str) {
+                assert_eq!(StructTag::parse(&tag.to_string()), Some(tag));
+            } else {
+                assert!(!type_str.contains("::") || type_str.starts_with("vector<"));
+            }
+        }
+    }
+}
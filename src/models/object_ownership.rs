@@ -0,0 +1,323 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+//! Objects can own objects, so the owner of an object is often another object. The ultimate
+//! owner is the first owner up the chain that isn't a live object, i.e. an account or a deleted
+//! object, and the depth is the number of edges to it.
+
+use super::v2_objects::CurrentObject;
+use crate::{database::PgPoolConnection, schema::object_ownership_edges};
+use diesel::{prelude::*, ExpressionMethods};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(object_address))]
+#[diesel(table_name = object_ownership_edges)]
+pub struct ObjectOwnershipEdge {
+    pub object_address: String,
+    pub owner_address: String,
+    pub last_transaction_version: i64,
+    pub is_deleted: bool,
+}
+
+impl ObjectOwnershipEdge {
+    pub fn from_current_object(current_object: &CurrentObject) -> Self {
+        Self {
+            object_address: current_object.object_address.clone(),
+            owner_address: current_object.owner_address.clone(),
+            last_transaction_version: current_object.last_transaction_version,
+            is_deleted: current_object.is_deleted,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    Owner { ultimate_owner: String, depth: i64 },
+    /// The chain comes back to an object already on it
+    Cycle,
+    /// The chain is longer than the max depth
+    TooDeep,
+}
+
+impl Resolution {
+    /// `(ultimate_owner, ownership_depth)` of `current_objects`
+    pub fn columns(&self) -> (Option<String>, Option<i64>) {
+        match self {
+            Resolution::Owner {
+                ultimate_owner,
+                depth,
+            } => (Some(ultimate_owner.clone()), Some(*depth)),
+            _ => (None, None),
+        }
+    }
+}
+
+/// The stored edges, as of before the batch
+pub trait OwnershipEdges {
+    /// Owners of those of `objects` that are live objects
+    fn owners(&mut self, objects: &[String]) -> QueryResult<HashMap<String, String>>;
+
+    /// `(object, owner)` of up to `limit` live objects owned by any of `owners`
+    fn children(&mut self, owners: &[String], limit: i64) -> QueryResult<Vec<(String, String)>>;
+}
+
+pub struct DbOwnershipEdges<'a> {
+    pub conn: &'a mut PgPoolConnection,
+}
+
+impl OwnershipEdges for DbOwnershipEdges<'_> {
+    fn owners(&mut self, objects: &[String]) -> QueryResult<HashMap<String, String>> {
+        use object_ownership_edges::dsl::*;
+
+        Ok(object_ownership_edges
+            .select((object_address, owner_address))
+            .filter(object_address.eq_any(objects))
+            .filter(is_deleted.eq(false))
+            .load::<(String, String)>(self.conn)?
+            .into_iter()
+            .collect())
+    }
+
+    fn children(&mut self, owners: &[String], limit: i64) -> QueryResult<Vec<(String, String)>> {
+        use object_ownership_edges::dsl::*;
+
+        object_ownership_edges
+            .select((object_address, owner_address))
+            .filter(owner_address.eq_any(owners))
+            .filter(is_deleted.eq(false))
+            .order(object_address.asc())
+            .limit(limit)
+            .load::<(String, String)>(self.conn)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ResolvedOwnership {
+    /// Live objects of the batch
+    pub objects: HashMap<String, Resolution>,
+    /// Stored objects whose chain goes through an object of the batch. Their owner didn't change
+    /// but their ultimate owner may have.
+    pub descendants: HashMap<String, Resolution>,
+    /// Whether descendants were left out because of `max_propagation`
+    pub truncated: bool,
+}
+
+struct Walk {
+    object: String,
+    current: String,
+    depth: i64,
+    visited: HashSet<String>,
+}
+
+/// Resolves the objects of the batch (by address, as of the end of the batch) by walking up
+/// their chains, through the batch's edges first and the stored ones after, then walks down
+/// from them to update at most `max_propagation` stored descendants.
+pub fn resolve(
+    batch: &HashMap<String, ObjectOwnershipEdge>,
+    edges: &mut impl OwnershipEdges,
+    max_depth: i64,
+    max_propagation: usize,
+) -> QueryResult<ResolvedOwnership> {
+    let mut resolved = ResolvedOwnership::default();
+    // Owner of each object looked at, `None` if it isn't a live object
+    let mut known: HashMap<String, Option<String>> = batch
+        .iter()
+        .map(|(address, edge)| {
+            (
+                address.clone(),
+                (!edge.is_deleted).then(|| edge.owner_address.clone()),
+            )
+        })
+        .collect();
+    let mut walks = batch
+        .values()
+        .filter(|edge| !edge.is_deleted)
+        .map(|edge| Walk {
+            object: edge.object_address.clone(),
+            current: edge.owner_address.clone(),
+            depth: 1,
+            visited: HashSet::from([edge.object_address.clone()]),
+        })
+        .collect::<Vec<_>>();
+
+    // One lookup of the stored edges per level
+    while !walks.is_empty() {
+        let unknown = walks
+            .iter()
+            .filter(|walk| !known.contains_key(&walk.current))
+            .map(|walk| walk.current.clone())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        if !unknown.is_empty() {
+            let owners = edges.owners(&unknown)?;
+            for address in unknown {
+                let owner = owners.get(&address).cloned();
+                known.insert(address, owner);
+            }
+        }
+        let mut next_walks = vec![];
+        for mut walk in walks {
+            let resolution = if walk.visited.contains(&walk.current) {
+                Resolution::Cycle
+            } else {
+                match &known[&walk.current] {
+                    None => Resolution::Owner {
+                        ultimate_owner: walk.current.clone(),
+                        depth: walk.depth,
+                    },
+                    Some(_) if walk.depth >= max_depth => Resolution::TooDeep,
+                    Some(owner) => {
+                        let owner = owner.clone();
+                        walk.visited.insert(std::mem::replace(&mut walk.current, owner));
+                        walk.depth += 1;
+                        next_walks.push(walk);
+                        continue;
+                    },
+                }
+            };
+            resolved.objects.insert(walk.object, resolution);
+        }
+        walks = next_walks;
+    }
+
+    // What a direct child of each object inherits: deleted objects are where chains end
+    let mut frontier: HashMap<String, Resolution> = batch
+        .values()
+        .map(|edge| {
+            let resolution = if edge.is_deleted {
+                Resolution::Owner {
+                    ultimate_owner: edge.object_address.clone(),
+                    depth: 0,
+                }
+            } else {
+                resolved.objects[&edge.object_address].clone()
+            };
+            (edge.object_address.clone(), resolution)
+        })
+        .collect();
+    let mut seen = batch.keys().cloned().collect::<HashSet<_>>();
+    let mut budget = max_propagation;
+    while !frontier.is_empty() {
+        let owners = frontier.keys().cloned().collect::<Vec<_>>();
+        let mut children = edges.children(&owners, budget as i64 + 1)?;
+        if children.len() > budget {
+            resolved.truncated = true;
+            children.truncate(budget);
+        }
+        let mut next_frontier = HashMap::new();
+        for (child, owner) in children {
+            // Objects of the batch are resolved already and cycles end here
+            if !seen.insert(child.clone()) {
+                continue;
+            }
+            let resolution = match &frontier[&owner] {
+                Resolution::Owner { depth, .. } if *depth >= max_depth => Resolution::TooDeep,
+                Resolution::Owner {
+                    ultimate_owner,
+                    depth,
+                } => Resolution::Owner {
+                    ultimate_owner: ultimate_owner.clone(),
+                    depth: depth + 1,
+                },
+                unresolved => unresolved.clone(),
+            };
+            budget -= 1;
+            next_frontier.insert(child.clone(), resolution.clone());
+            resolved.descendants.insert(child, resolution);
+        }
+        if resolved.truncated {
+            break;
+        }
+        frontier = next_frontier;
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct StoredEdges(HashMap<String, String>);
+
+    impl OwnershipEdges for StoredEdges {
+        fn owners(&mut self, objects: &[String]) -> QueryResult<HashMap<String, String>> {
+            Ok(objects
+                .iter()
+                .filter_map(|o| self.0.get(o).map(|owner| (o.clone(), owner.clone())))
+                .collect())
+        }
+
+        fn children(
+            &mut self,
+            owners: &[String],
+            limit: i64,
+        ) -> QueryResult<Vec<(String, String)>> {
+            let mut children = self
+                .0
+                .iter()
+                .filter(|(_, owner)| owners.contains(owner))
+                .map(|(o, owner)| (o.clone(), owner.clone()))
+                .collect::<Vec<_>>();
+            children.sort();
+            children.truncate(limit as usize);
+            Ok(children)
+        }
+    }
+
+    fn edge(object: &str, owner: &str) -> (String, ObjectOwnershipEdge) {
+        (object.to_string(), ObjectOwnershipEdge {
+            object_address: object.to_string(),
+            owner_address: owner.to_string(),
+            last_transaction_version: 1,
+            is_deleted: false,
+        })
+    }
+
+    fn owner(ultimate_owner: &str, depth: i64) -> Resolution {
+        Resolution::Owner {
+            ultimate_owner: ultimate_owner.to_string(),
+            depth,
+        }
+    }
+
+    #[test]
+    fn test_resolve_chains_and_descendants() {
+        // Stored: token -> bag -> wallet, and gem -> token
+        let mut stored = StoredEdges(
+            [("token", "bag"), ("bag", "wallet"), ("gem", "token")]
+                .iter()
+                .map(|(o, owner)| (o.to_string(), owner.to_string()))
+                .collect(),
+        );
+        // The bag moves into a chest owned by another wallet, and two objects own each other
+        let batch = HashMap::from([
+            edge("bag", "chest"),
+            edge("chest", "other_wallet"),
+            edge("a", "b"),
+            edge("b", "a"),
+        ]);
+        let resolved = resolve(&batch, &mut stored, 8, 100).unwrap();
+        assert_eq!(resolved.objects["chest"], owner("other_wallet", 1));
+        assert_eq!(resolved.objects["bag"], owner("other_wallet", 2));
+        assert_eq!(resolved.objects["a"], Resolution::Cycle);
+        assert_eq!(resolved.descendants["token"], owner("other_wallet", 3));
+        assert_eq!(resolved.descendants["gem"], owner("other_wallet", 4));
+        assert!(!resolved.truncated);
+
+        let resolved = resolve(&batch, &mut stored, 3, 1).unwrap();
+        assert_eq!(resolved.descendants.len(), 1);
+        assert_eq!(resolved.descendants["token"], owner("other_wallet", 3));
+        assert!(resolved.truncated);
+
+        let resolved = resolve(&batch, &mut stored, 2, 100).unwrap();
+        assert_eq!(resolved.descendants["token"], Resolution::TooDeep);
+    }
+}
@@ -0,0 +1,298 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use crate::{
+    database::PgPoolConnection,
+    schema::onchain_config_changes,
+    util::{parse_timestamp, standardize_address},
+};
+use aptos_api_types::{Transaction as APITransaction, WriteSetChange as APIWriteSetChange};
+use aptos_types::on_chain_config::{OnChainConsensusConfig, OnChainExecutionConfig};
+use diesel::{prelude::*, ExpressionMethods};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+pub const GAS_SCHEDULE: &str = "gas_schedule";
+pub const FEATURES: &str = "features";
+pub const CONSENSUS_CONFIG: &str = "consensus_config";
+pub const EXECUTION_CONFIG: &str = "execution_config";
+
+/// (module, struct, config type) of the config resources stored at 0x1
+const CONFIG_RESOURCES: &[(&str, &str, &str)] = &[
+    ("gas_schedule", "GasScheduleV2", GAS_SCHEDULE),
+    ("features", "Features", FEATURES),
+    ("consensus_config", "ConsensusConfig", CONSENSUS_CONFIG),
+    ("execution_config", "ExecutionConfig", EXECUTION_CONFIG),
+];
+
+/// Names of the feature flags by bit index, from `0x1::features`. Bits missing here are
+/// reported as `unknown_<index>` until they're added.
+const FEATURE_FLAGS: &[(usize, &str)] = &[
+    (1, "CODE_DEPENDENCY_CHECK"),
+    (2, "TREAT_FRIEND_AS_PRIVATE"),
+    (3, "SHA_512_AND_RIPEMD_160_NATIVES"),
+    (4, "APTOS_STD_CHAIN_ID_NATIVES"),
+    (5, "VM_BINARY_FORMAT_V6"),
+    (6, "COLLECT_AND_DISTRIBUTE_GAS_FEES"),
+    (7, "MULTI_ED25519_PK_VALIDATE_V2_NATIVES"),
+    (8, "BLAKE2B_256_NATIVE"),
+    (9, "RESOURCE_GROUPS"),
+    (10, "MULTISIG_ACCOUNTS"),
+    (11, "DELEGATION_POOLS"),
+    (12, "CRYPTOGRAPHY_ALGEBRA_NATIVES"),
+    (13, "BLS12_381_STRUCTURES"),
+    (14, "ED25519_PUBKEY_VALIDATE_RETURN_FALSE_WRONG_LENGTH"),
+    (15, "STRUCT_CONSTRUCTORS"),
+    (16, "PERIODICAL_REWARD_RATE_DECREASE"),
+    (17, "PARTIAL_GOVERNANCE_VOTING"),
+    (18, "SIGNATURE_CHECKER_V2"),
+    (19, "STORAGE_SLOT_METADATA"),
+    (20, "CHARGE_INVARIANT_VIOLATION"),
+    (21, "DELEGATION_POOL_PARTIAL_GOVERNANCE_VOTING"),
+    (22, "GAS_PAYER_ENABLED"),
+    (23, "APTOS_UNIQUE_IDENTIFIERS"),
+    (24, "BULLETPROOFS_NATIVES"),
+    (25, "SIGNER_NATIVE_FORMAT_FIX"),
+    (26, "MODULE_EVENT"),
+    (27, "EMIT_FEE_STATEMENT"),
+    (28, "STORAGE_DELETION_REFUND"),
+];
+
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(transaction_version, config_type))]
+#[diesel(table_name = onchain_config_changes)]
+pub struct OnchainConfigChange {
+    pub transaction_version: i64,
+    pub config_type: String,
+    pub resource_type: String,
+    pub value: Value,
+    pub diff: Value,
+    pub transaction_timestamp: chrono::NaiveDateTime,
+}
+
+/// A decoded write of a config resource, which is a change if its value differs from the
+/// previous one
+#[derive(Clone, Debug)]
+pub struct OnchainConfigWrite {
+    pub transaction_version: i64,
+    pub config_type: &'static str,
+    pub resource_type: String,
+    pub value: Value,
+    pub transaction_timestamp: chrono::NaiveDateTime,
+}
+
+impl OnchainConfigWrite {
+    /// Config resources written by the transaction, whatever its type: they change in
+    /// governance proposals, but also at genesis and on reconfiguration
+    pub fn from_transaction(transaction: &APITransaction) -> Vec<Self> {
+        let info = match transaction.transaction_info() {
+            Ok(info) => info,
+            Err(_) => return vec![],
+        };
+        let txn_version = info.version.0 as i64;
+        let framework = standardize_address("0x1");
+        info.changes
+            .iter()
+            .filter_map(|wsc| match wsc {
+                APIWriteSetChange::WriteResource(write_resource) => Some(write_resource),
+                _ => None,
+            })
+            .filter(|write_resource| {
+                standardize_address(&write_resource.address.to_string()) == framework
+            })
+            .filter_map(|write_resource| {
+                let typ = &write_resource.data.typ;
+                let config_type = CONFIG_RESOURCES
+                    .iter()
+                    .find(|(module, name, _)| {
+                        standardize_address(&typ.address.to_string()) == framework
+                            && typ.module.as_str() == *module
+                            && typ.name.as_str() == *name
+                    })
+                    .map(|(_, _, config_type)| *config_type)?;
+                let data = serde_json::to_value(&write_resource.data.data).ok()?;
+                Some(Self {
+                    transaction_version: txn_version,
+                    config_type,
+                    resource_type: typ.to_string(),
+                    value: decode(config_type, &data),
+                    transaction_timestamp: parse_timestamp(transaction.timestamp(), txn_version),
+                })
+            })
+            .collect()
+    }
+}
+
+impl OnchainConfigChange {
+    /// `None` if the write didn't change the value
+    pub fn from_write(write: OnchainConfigWrite, previous: Option<&Value>) -> Option<Self> {
+        if previous == Some(&write.value) {
+            return None;
+        }
+        Some(Self {
+            diff: diff(previous.unwrap_or(&Value::Null), &write.value),
+            transaction_version: write.transaction_version,
+            config_type: write.config_type.to_string(),
+            resource_type: write.resource_type,
+            value: write.value,
+            transaction_timestamp: write.transaction_timestamp,
+        })
+    }
+
+    /// Value of the config type as of its last change before `before_version`
+    pub fn latest_value(
+        conn: &mut PgPoolConnection,
+        config_type: &str,
+        before_version: i64,
+    ) -> QueryResult<Option<Value>> {
+        onchain_config_changes::table
+            .select(onchain_config_changes::value)
+            .filter(onchain_config_changes::config_type.eq(config_type))
+            .filter(onchain_config_changes::transaction_version.lt(before_version))
+            .order(onchain_config_changes::transaction_version.desc())
+            .first::<Value>(conn)
+            .optional()
+    }
+}
+
+/// Readable form of a config resource's data. Configs that fail to decode are kept as the
+/// node returned them.
+pub fn decode(config_type: &str, data: &Value) -> Value {
+    let decoded = match config_type {
+        GAS_SCHEDULE => decode_gas_schedule(data),
+        FEATURES => data["features"].as_str().and_then(decode_features),
+        CONSENSUS_CONFIG => data["config"]
+            .as_str()
+            .and_then(|config| decode_bcs::<OnChainConsensusConfig>(config)),
+        EXECUTION_CONFIG => data["config"]
+            .as_str()
+            .and_then(|config| decode_bcs::<OnChainExecutionConfig>(config)),
+        _ => None,
+    };
+    decoded.unwrap_or_else(|| data.clone())
+}
+
+/// `{"feature_version": .., "entries": {key: value}}`, the entries being a vector of pairs
+fn decode_gas_schedule(data: &Value) -> Option<Value> {
+    let mut entries = Map::new();
+    for entry in data["entries"].as_array()? {
+        entries.insert(entry["key"].as_str()?.to_string(), entry["val"].clone());
+    }
+    Some(json!({
+        "feature_version": data["feature_version"],
+        "entries": entries,
+    }))
+}
+
+/// `{flag: true}` for every enabled flag. The bitvec is little endian, bit `i` being bit
+/// `i % 8` of byte `i / 8`.
+fn decode_features(features: &str) -> Option<Value> {
+    let bytes = hex::decode(features.strip_prefix("0x").unwrap_or(features)).ok()?;
+    let mut flags = Map::new();
+    for index in 0..bytes.len() * 8 {
+        if bytes[index / 8] & (1 << (index % 8)) == 0 {
+            continue;
+        }
+        let name = FEATURE_FLAGS
+            .iter()
+            .find(|(flag_index, _)| *flag_index == index)
+            .map(|(_, name)| name.to_string())
+            .unwrap_or_else(|| format!("unknown_{}", index));
+        flags.insert(name, Value::Bool(true));
+    }
+    Some(Value::Object(flags))
+}
+
+fn decode_bcs<T: for<'de> Deserialize<'de> + Serialize>(config: &str) -> Option<Value> {
+    let bytes = hex::decode(config.strip_prefix("0x").unwrap_or(config)).ok()?;
+    let decoded = bcs::from_bytes::<T>(&bytes).ok()?;
+    serde_json::to_value(decoded).ok()
+}
+
+/// `{"added": {path: new}, "removed": {path: old}, "changed": {path: {"old": .., "new": ..}}}`
+/// over the dot separated paths of the leaves of both values. Arrays are compared as a whole.
+pub fn diff(old: &Value, new: &Value) -> Value {
+    let (mut old_leaves, mut new_leaves) = (BTreeMap::new(), BTreeMap::new());
+    flatten(old, "", &mut old_leaves);
+    flatten(new, "", &mut new_leaves);
+    let (mut added, mut removed, mut changed) = (Map::new(), Map::new(), Map::new());
+    for (path, new_value) in &new_leaves {
+        match old_leaves.get(path) {
+            None => {
+                added.insert(path.clone(), (*new_value).clone());
+            },
+            Some(old_value) if old_value != new_value => {
+                changed.insert(path.clone(), json!({"old": old_value, "new": new_value}));
+            },
+            _ => {},
+        }
+    }
+    for (path, old_value) in old_leaves {
+        if !new_leaves.contains_key(&path) {
+            removed.insert(path, old_value.clone());
+        }
+    }
+    json!({"added": added, "removed": removed, "changed": changed})
+}
+
+fn flatten<'a>(value: &'a Value, path: &str, leaves: &mut BTreeMap<String, &'a Value>) {
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields {
+                let field_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                flatten(field, &field_path, leaves);
+            }
+        },
+        Value::Null if path.is_empty() => {},
+        _ => {
+            leaves.insert(path.to_string(), value);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_features() {
+        // Bits 1, 2 and 9, and 30 which has no name yet
+        let flags = decode_features("0x06020040").unwrap();
+        assert_eq!(
+            flags,
+            json!({
+                "CODE_DEPENDENCY_CHECK": true,
+                "TREAT_FRIEND_AS_PRIVATE": true,
+                "RESOURCE_GROUPS": true,
+                "unknown_30": true,
+            })
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = json!({"feature_version": "12", "entries": {"instr.add": "4", "instr.sub": "4"}});
+        let new = json!({"feature_version": "12", "entries": {"instr.add": "5", "instr.mul": "6"}});
+        assert_eq!(
+            diff(&old, &new),
+            json!({
+                "added": {"entries.instr.mul": "6"},
+                "removed": {"entries.instr.sub": "4"},
+                "changed": {"entries.instr.add": {"old": "4", "new": "5"}},
+            })
+        );
+        assert_eq!(
+            diff(&Value::Null, &json!({"a": 1}))["added"],
+            json!({"a": 1})
+        );
+    }
+}
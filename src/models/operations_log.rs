@@ -0,0 +1,25 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+#![allow(clippy::extra_unused_lifetimes)]
+use crate::schema::operations_log;
+use serde::{Deserialize, Serialize};
+
+/// A lifecycle event of a long-running driver task, as logged and published on the control
+/// topic, see `custom::driver::operations`
+#[derive(Clone, Debug, Deserialize, Insertable, PartialEq, Queryable, Serialize)]
+#[diesel(table_name = operations_log)]
+pub struct OperationEvent {
+    pub operation_id: String,
+    /// 0 for `started`, then one per event
+    pub event_index: i64,
+    pub kind: String,
+    /// `started`, `progress`, `completed`, `cancelled` or `failed`
+    pub event: String,
+    pub actor: String,
+    pub parameters: serde_json::Value,
+    pub done: i64,
+    pub total: Option<i64>,
+    pub error_code: Option<String>,
+    pub error: Option<String>,
+    pub emitted_at: chrono::NaiveDateTime,
+}
@@ -0,0 +1,19 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use crate::schema::processor_leases;
+use serde::{Deserialize, Serialize};
+
+/// The instance of a processor that writes and publishes, see `custom::driver::standby`
+#[derive(Clone, Debug, Deserialize, Queryable, Serialize)]
+#[diesel(table_name = processor_leases)]
+pub struct ProcessorLease {
+    pub processor: String,
+    pub holder: String,
+    pub acquired_at: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+    pub inserted_at: chrono::NaiveDateTime,
+}
@@ -19,6 +19,10 @@ pub struct ProcessorStatusV2Query {
     pub processor: String,
     pub last_success_version: i64,
     pub last_updated: chrono::NaiveDateTime,
+    pub health_state: String,
+    pub health_state_since: chrono::NaiveDateTime,
+    /// Last version published with `publish_dedupe`, see `custom::driver::publish_dedupe`
+    pub last_published_version: Option<i64>,
 }
 
 impl ProcessorStatusV2Query {
@@ -0,0 +1,22 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use crate::schema::range_hashes;
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+
+/// Hash of the rows of a table in a version range, see `custom::driver::range_hash`
+#[derive(Clone, Debug, Deserialize, FieldCount, Insertable, Serialize)]
+#[diesel(table_name = range_hashes)]
+pub struct RangeHash {
+    pub processor: String,
+    pub table_name: String,
+    pub start_version: i64,
+    /// Inclusive
+    pub end_version: i64,
+    pub row_count: i64,
+    pub hash: String,
+}
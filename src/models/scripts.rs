@@ -0,0 +1,147 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+#![allow(clippy::extra_unused_lifetimes)]
+use crate::schema::scripts;
+use aptos_api_types::{ScriptPayload, Transaction as APITransaction, TransactionPayload};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Number of script hashes remembered as already stored
+pub const DEFAULT_SEEN_SCRIPTS_CAPACITY: usize = 100_000;
+
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[diesel(primary_key(script_hash))]
+#[diesel(table_name = scripts)]
+pub struct Script {
+    pub script_hash: String,
+    pub bytecode: Vec<u8>,
+    pub abi: Option<serde_json::Value>,
+    pub first_seen_version: i64,
+}
+
+impl Script {
+    pub fn from_script_payload(payload: &ScriptPayload, version: i64) -> Self {
+        let bytecode = payload.code.bytecode.0.clone();
+        Self {
+            script_hash: Self::hash_bytecode(&bytecode),
+            bytecode,
+            abi: payload
+                .code
+                .abi
+                .as_ref()
+                .map(|abi| serde_json::to_value(abi).unwrap()),
+            first_seen_version: version,
+        }
+    }
+
+    /// sha3-256 of the raw bytecode, 0x prefixed
+    pub fn hash_bytecode(bytecode: &[u8]) -> String {
+        format!("0x{}", hex::encode(Sha3_256::digest(bytecode)))
+    }
+
+    pub fn get_script_hash(payload: &TransactionPayload) -> Option<String> {
+        match payload {
+            TransactionPayload::ScriptPayload(script) => {
+                Some(Self::hash_bytecode(&script.code.bytecode.0))
+            },
+            _ => None,
+        }
+    }
+
+    /// Distinct scripts in the batch with the lowest version each was seen at
+    pub fn from_transactions(transactions: &[APITransaction]) -> Vec<Self> {
+        let mut scripts: HashMap<String, Self> = HashMap::new();
+        for txn in transactions {
+            if let APITransaction::UserTransaction(user_txn) = txn {
+                if let TransactionPayload::ScriptPayload(payload) = &user_txn.request.payload {
+                    let script = Self::from_script_payload(payload, user_txn.info.version.0 as i64);
+                    scripts.entry(script.script_hash.clone()).or_insert(script);
+                }
+            }
+        }
+        let mut scripts = scripts.into_values().collect::<Vec<_>>();
+        // Sort by PK
+        scripts.sort_by(|a, b| a.script_hash.cmp(&b.script_hash));
+        scripts
+    }
+}
+
+/// Bounded set of script hashes known to be in the `scripts` table, so that the bytecode of
+/// popular scripts isn't sent to the db on every batch. When a hash is evicted the insert's
+/// `ON CONFLICT DO NOTHING` still keeps the table deduplicated.
+pub struct SeenScripts {
+    capacity: usize,
+    hashes: HashSet<String>,
+    insertion_order: VecDeque<String>,
+}
+
+impl SeenScripts {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            hashes: HashSet::new(),
+            insertion_order: VecDeque::new(),
+        }
+    }
+
+    /// Drops scripts that are already stored
+    pub fn filter_unseen(&self, scripts: Vec<Script>) -> Vec<Script> {
+        scripts
+            .into_iter()
+            .filter(|script| !self.hashes.contains(&script.script_hash))
+            .collect()
+    }
+
+    /// Should only be called once the scripts are committed
+    pub fn mark_seen(&mut self, script_hashes: Vec<String>) {
+        for script_hash in script_hashes {
+            if self.capacity == 0 || !self.hashes.insert(script_hash.clone()) {
+                continue;
+            }
+            self.insertion_order.push_back(script_hash);
+            if self.insertion_order.len() > self.capacity {
+                if let Some(evicted) = self.insertion_order.pop_front() {
+                    self.hashes.remove(&evicted);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(script_hash: &str) -> Script {
+        Script {
+            script_hash: script_hash.to_string(),
+            bytecode: vec![],
+            abi: None,
+            first_seen_version: 0,
+        }
+    }
+
+    fn unseen(seen: &SeenScripts, hashes: &[&str]) -> Vec<String> {
+        seen.filter_unseen(hashes.iter().map(|hash| script(hash)).collect())
+            .into_iter()
+            .map(|script| script.script_hash)
+            .collect()
+    }
+
+    #[test]
+    fn test_seen_scripts_evicts_oldest() {
+        let mut seen = SeenScripts::new(2);
+        seen.mark_seen(vec!["0xa".to_string(), "0xb".to_string()]);
+        assert_eq!(unseen(&seen, &["0xa", "0xb", "0xc"]), ["0xc"]);
+
+        // Marking a known hash again doesn't refresh it
+        seen.mark_seen(vec!["0xa".to_string(), "0xc".to_string()]);
+        assert_eq!(unseen(&seen, &["0xa", "0xb", "0xc"]), ["0xa"]);
+
+        let mut disabled = SeenScripts::new(0);
+        disabled.mark_seen(vec!["0xa".to_string()]);
+        assert_eq!(unseen(&disabled, &["0xa"]), ["0xa"]);
+    }
+}
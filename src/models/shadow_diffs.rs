@@ -0,0 +1,29 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use crate::schema::shadow_diffs;
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+
+/// A value where a shadow processor's output differs from the primary's, see
+/// `custom::driver::shadow`
+#[derive(Clone, Debug, Deserialize, FieldCount, Insertable, Serialize)]
+#[diesel(table_name = shadow_diffs)]
+pub struct ShadowDiff {
+    pub processor: String,
+    pub shadow: String,
+    pub start_version: i64,
+    /// Inclusive
+    pub end_version: i64,
+    /// Position among the differences of the batch
+    pub diff_index: i64,
+    pub table_name: String,
+    pub pk: String,
+    /// `*` for a row only one of the two has
+    pub column_name: String,
+    pub primary_value: Option<serde_json::Value>,
+    pub shadow_value: Option<serde_json::Value>,
+}
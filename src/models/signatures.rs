@@ -37,6 +37,7 @@ pub struct Signature {
     pub signature: String,
     pub threshold: i64,
     pub public_key_indices: serde_json::Value,
+    pub is_fee_payer: bool,
 }
 
 impl Signature {
@@ -130,6 +131,7 @@ impl Signature {
             signature: s.signature.to_string(),
             multi_agent_index,
             multi_sig_index: 0,
+            is_fee_payer: false,
         }
     }
 
@@ -171,6 +173,7 @@ impl Signature {
                 ),
                 multi_agent_index,
                 multi_sig_index: index as i64,
+                is_fee_payer: false,
             });
         }
         signatures
@@ -243,15 +246,20 @@ impl Signature {
                 Some(&address.to_string()),
             ));
         }
-        signatures.append(&mut Self::parse_multi_agent_signature_helper(
+        // The fee payer goes after the secondary signers, one key per row if it's a multi key
+        let mut fee_payer_signatures = Self::parse_multi_agent_signature_helper(
             &s.fee_payer_signer,
             sender,
             transaction_version,
             transaction_block_height,
-            true,
+            false,
             (s.secondary_signer_addresses.len() + 1) as i64,
             Some(&s.fee_payer_address.to_string()),
-        ));
+        );
+        for signature in &mut fee_payer_signatures {
+            signature.is_fee_payer = true;
+        }
+        signatures.append(&mut fee_payer_signatures);
         Ok(signatures)
     }
 
@@ -326,6 +334,7 @@ impl Signature {
             signature: "Not implemented".into(),
             multi_agent_index,
             multi_sig_index: 0,
+            is_fee_payer: false,
         }
     }
 
@@ -351,6 +360,79 @@ impl Signature {
             signature: "Not implemented".into(),
             multi_agent_index,
             multi_sig_index: 0,
+            is_fee_payer: false,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn ed25519(key: u8) -> serde_json::Value {
+        json!({
+            "type": "ed25519_signature",
+            "public_key": format!("0x{}", format!("{:02x}", key).repeat(32)),
+            "signature": format!("0x{}", format!("{:02x}", key).repeat(64)),
+        })
+    }
+
+    /// A sponsored transfer whose fee payer is a 2 of 3 multi-ed25519 account. Hand-built, with
+    /// placeholder keys and signatures, until it's replaced with a mainnet capture.
+    fn fee_payer_signature() -> APITransactionSignature {
+        serde_json::from_value(json!({
+            "type": "fee_payer_signature",
+            "sender": ed25519(0x11),
+            "secondary_signer_addresses": ["0x22"],
+            "secondary_signers": [ed25519(0x22)],
+            "fee_payer_address": "0xfee",
+            "fee_payer_signer": {
+                "type": "multi_ed25519_signature",
+                "public_keys": [
+                    format!("0x{}", "a1".repeat(32)),
+                    format!("0x{}", "a2".repeat(32)),
+                    format!("0x{}", "a3".repeat(32)),
+                ],
+                "signatures": [
+                    format!("0x{}", "b1".repeat(64)),
+                    format!("0x{}", "b3".repeat(64)),
+                ],
+                "threshold": 2,
+                "bitmap": "0xa0000000",
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_fee_payer_signature() {
+        let sender = "0x11".to_string();
+        let signatures =
+            Signature::from_user_transaction(&fee_payer_signature(), &sender, 100, 10).unwrap();
+        let rows = signatures
+            .iter()
+            .map(|signature| {
+                (
+                    signature.signer.clone(),
+                    signature.multi_agent_index,
+                    signature.multi_sig_index,
+                    signature.is_sender_primary,
+                    signature.is_fee_payer,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(rows, vec![
+            (standardize_address("0x11"), 0, 0, true, false),
+            (standardize_address("0x22"), 0, 0, false, false),
+            (standardize_address("0xfee"), 2, 0, false, true),
+            (standardize_address("0xfee"), 2, 1, false, true),
+        ]);
+        // The fee payer's keys that signed, out of its 3
+        assert_eq!(signatures[2].public_key, format!("0x{}", "a1".repeat(32)));
+        assert_eq!(signatures[3].public_key, format!("0x{}", "a3".repeat(32)));
+        assert_eq!(signatures[3].type_, "multi_ed25519_signature");
+        assert_eq!(signatures[3].threshold, 2);
+        assert_eq!(signatures[3].public_key_indices, json!([0, 2]));
+    }
+}
@@ -6,10 +6,11 @@
 
 use super::delegator_pools::{DelegatorPool, DelegatorPoolBalanceMetadata, PoolBalanceMetadata};
 use crate::{
+    custom::driver::retry_budget::{self, ErrorClass},
     database::PgPoolConnection,
     models::token_models::collection_datas::{QUERY_RETRIES, QUERY_RETRY_DELAY_MS},
     schema::current_delegator_balances,
-    util::standardize_address,
+    util::{standardize_address, u128_from_json},
 };
 use anyhow::Context;
 use aptos_api_types::{
@@ -27,10 +28,14 @@ pub type TableHandle = String;
 pub type Address = String;
 pub type ShareToStakingPoolMapping = HashMap<TableHandle, DelegatorPoolBalanceMetadata>;
 pub type ShareToPoolMapping = HashMap<TableHandle, PoolBalanceMetadata>;
-pub type CurrentDelegatorBalancePK = (Address, Address, String);
+/// Delegator, pool, pool type and share table, as in the table's primary key. A delegator has an
+/// inactive share in the pool of every lockup cycle they unlocked in, pending inactive in the
+/// current cycle's, and a transaction can write several, e.g. an unlock withdrawing what was
+/// unlocked in a past cycle.
+pub type CurrentDelegatorBalancePK = (Address, Address, String, TableHandle);
 pub type CurrentDelegatorBalanceMap = HashMap<CurrentDelegatorBalancePK, CurrentDelegatorBalance>;
 
-#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
 #[diesel(primary_key(delegator_address, pool_address, pool_type))]
 #[diesel(table_name = current_delegator_balances)]
 pub struct CurrentDelegatorBalance {
@@ -75,18 +80,10 @@ impl CurrentDelegatorBalance {
                     write_table_item, txn_version
                 )
             });
-            let shares = data
-                .value
-                .as_str()
-                .map(|s| s.parse::<BigDecimal>())
-                .context(format!(
-                    "value is not a string: {:?}, table_item {:?}, version {}",
-                    data.value, write_table_item, txn_version
-                ))?
-                .context(format!(
-                    "cannot parse string as u64: {:?}, version {}",
-                    data.value, txn_version
-                ))?;
+            let shares = u128_from_json(&data.value).context(format!(
+                "cannot parse shares as u128: table_item {:?}, version {}",
+                write_table_item, txn_version
+            ))?;
             let shares = shares / &pool_balance.scaling_factor;
             Ok(Some(Self {
                 delegator_address,
@@ -136,18 +133,10 @@ impl CurrentDelegatorBalance {
                     write_table_item, txn_version
                 )
             });
-            let shares = data
-                .value
-                .as_str()
-                .map(|s| s.parse::<BigDecimal>())
-                .context(format!(
-                    "value is not a string: {:?}, table_item {:?}, version {}",
-                    data.value, write_table_item, txn_version
-                ))?
-                .context(format!(
-                    "cannot parse string as u64: {:?}, version {}",
-                    data.value, txn_version
-                ))?;
+            let shares = u128_from_json(&data.value).context(format!(
+                "cannot parse shares as u128: table_item {:?}, version {}",
+                write_table_item, txn_version
+            ))?;
             let shares = shares / &pool_balance.scaling_factor;
             Ok(Some(Self {
                 delegator_address,
@@ -229,15 +218,63 @@ impl CurrentDelegatorBalance {
                 delegator_address,
                 pool_address,
                 pool_type: "inactive_shares".to_string(),
-                table_handle: table_handle.clone(),
+                table_handle,
                 last_transaction_version: txn_version,
                 shares: BigDecimal::zero(),
-                parent_table_handle: table_handle,
+                parent_table_handle: inactive_pool_handle,
             }));
         }
         Ok(None)
     }
 
+    /// Setting amount to 0 for an inactive share withdrawn with the last of its pool, which is
+    /// deleted too rather than written, so the transaction doesn't map the share's table to it.
+    /// The share was written before, earlier in the batch (`batch_balances`) or in Postgres.
+    pub fn get_inactive_share_from_written_share(
+        delete_table_item: &APIDeleteTableItem,
+        txn_version: i64,
+        batch_balances: &CurrentDelegatorBalanceMap,
+        conn: &mut PgPoolConnection,
+    ) -> anyhow::Result<Option<Self>> {
+        let table_handle = standardize_address(&delete_table_item.handle.to_string());
+        let delegator_address = standardize_address(&delete_table_item.key.to_string());
+        let in_batch = batch_balances
+            .values()
+            .find(|balance| {
+                balance.pool_type == "inactive_shares"
+                    && balance.table_handle == table_handle
+                    && balance.delegator_address == delegator_address
+            })
+            .map(|balance| {
+                (
+                    balance.pool_address.clone(),
+                    balance.parent_table_handle.clone(),
+                )
+            });
+        let written = match in_batch {
+            Some(written) => Some(written),
+            None => CurrentDelegatorBalanceQuery::get_inactive_share(
+                conn,
+                &delegator_address,
+                &table_handle,
+            )
+            .context(format!(
+                "Failed to look up inactive share {} of {}, txn version {}",
+                table_handle, delegator_address, txn_version
+            ))?
+            .map(|balance| (balance.pool_address, balance.parent_table_handle)),
+        };
+        Ok(written.map(|(pool_address, parent_table_handle)| Self {
+            delegator_address,
+            pool_address,
+            pool_type: "inactive_shares".to_string(),
+            table_handle,
+            last_transaction_version: txn_version,
+            shares: BigDecimal::zero(),
+            parent_table_handle,
+        }))
+    }
+
     /// Key is the inactive share table handle obtained from 0x1::delegation_pool::DelegationPool
     /// Value is the same metadata although it's not really used
     pub fn get_active_pool_to_staking_pool_mapping(
@@ -304,7 +341,14 @@ impl CurrentDelegatorBalance {
             retried += 1;
             match CurrentDelegatorBalanceQuery::get_by_inactive_share_handle(conn, table_handle) {
                 Ok(current_delegator_balance) => return Ok(current_delegator_balance.pool_address),
-                Err(_) => {
+                Err(err) => {
+                    if let Err(exhausted) = retry_budget::retry(
+                        ErrorClass::DbQuery,
+                        "get staking pool from inactive share handle",
+                        &err,
+                    ) {
+                        return Err(exhausted.into());
+                    }
                     std::thread::sleep(std::time::Duration::from_millis(QUERY_RETRY_DELAY_MS));
                 },
             }
@@ -314,8 +358,10 @@ impl CurrentDelegatorBalance {
         ))
     }
 
+    /// The balances `transaction` changes, after those of the batch so far, `batch_balances`
     pub fn from_transaction(
         transaction: &APITransaction,
+        batch_balances: &CurrentDelegatorBalanceMap,
         conn: &mut PgPoolConnection,
     ) -> anyhow::Result<CurrentDelegatorBalanceMap> {
         let mut active_pool_to_staking_pool: ShareToStakingPoolMapping = HashMap::new();
@@ -361,7 +407,7 @@ impl CurrentDelegatorBalance {
                         .unwrap()
                         {
                             Some(balance)
-                        } else {
+                        } else if let Some(balance) =
                             Self::get_inactive_share_from_delete_table_item(
                                 table_item,
                                 txn_version,
@@ -370,6 +416,19 @@ impl CurrentDelegatorBalance {
                                 conn,
                             )
                             .unwrap()
+                        {
+                            Some(balance)
+                        } else if !inactive_pool_to_staking_pool.is_empty() {
+                            // Only a transaction writing a delegation pool withdraws from it
+                            Self::get_inactive_share_from_written_share(
+                                table_item,
+                                txn_version,
+                                batch_balances,
+                                conn,
+                            )
+                            .unwrap()
+                        } else {
+                            None
                         }
                     },
                     APIWriteSetChange::WriteTableItem(table_item) => {
@@ -400,6 +459,7 @@ impl CurrentDelegatorBalance {
                             delegator_balance.delegator_address.clone(),
                             delegator_balance.pool_address.clone(),
                             delegator_balance.pool_type.clone(),
+                            delegator_balance.table_handle.clone(),
                         ),
                         delegator_balance,
                     );
@@ -419,4 +479,17 @@ impl CurrentDelegatorBalanceQuery {
             .filter(current_delegator_balances::parent_table_handle.eq(table_handle))
             .first::<Self>(conn)
     }
+
+    pub fn get_inactive_share(
+        conn: &mut PgPoolConnection,
+        delegator_address: &str,
+        table_handle: &str,
+    ) -> diesel::QueryResult<Option<Self>> {
+        current_delegator_balances::table
+            .filter(current_delegator_balances::delegator_address.eq(delegator_address))
+            .filter(current_delegator_balances::pool_type.eq("inactive_shares"))
+            .filter(current_delegator_balances::table_handle.eq(table_handle))
+            .first::<Self>(conn)
+            .optional()
+    }
 }
@@ -1,7 +1,10 @@
 // Copyright © Aptos Foundation
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::models::{move_resources::MoveResource, token_models::token_utils::Table};
+use crate::{
+    models::{move_resources::MoveResource, token_models::token_utils::Table},
+    strictness,
+};
 use anyhow::{Context, Result};
 use aptos_api_types::{deserialize_from_string, WriteResource};
 use bigdecimal::BigDecimal;
@@ -99,9 +102,8 @@ impl StakeTableItem {
         txn_version: i64,
     ) -> Result<Option<Self>> {
         match data_type {
-            "0x1::pool_u64_unbound::Pool" => {
-                serde_json::from_value(data.clone()).map(|inner| Some(StakeTableItem::Pool(inner)))
-            },
+            "0x1::pool_u64_unbound::Pool" => strictness::from_value(data_type, data)
+                .map(|inner| Some(StakeTableItem::Pool(inner))),
             _ => Ok(None),
         }
         .context(format!(
@@ -127,9 +129,9 @@ impl StakeResource {
 
     fn from_resource(data_type: &str, data: &serde_json::Value, txn_version: i64) -> Result<Self> {
         match data_type {
-            "0x1::stake::StakePool" => serde_json::from_value(data.clone())
+            "0x1::stake::StakePool" => strictness::from_value(data_type, data)
                 .map(|inner| Some(StakeResource::StakePool(inner))),
-            "0x1::delegation_pool::DelegationPool" => serde_json::from_value(data.clone())
+            "0x1::delegation_pool::DelegationPool" => strictness::from_value(data_type, data)
                 .map(|inner| Some(StakeResource::DelegationPool(inner))),
             _ => Ok(None),
         }
@@ -187,17 +189,17 @@ impl StakeEvent {
         txn_version: i64,
     ) -> Result<Option<Self>> {
         match data_type {
-            "0x1::aptos_governance::VoteEvent" => serde_json::from_value(data.clone())
+            "0x1::aptos_governance::VoteEvent" => strictness::from_value(data_type, data)
                 .map(|inner| Some(StakeEvent::GovernanceVoteEvent(inner))),
-            "0x1::stake::DistributeRewardsEvent" => serde_json::from_value(data.clone())
+            "0x1::stake::DistributeRewardsEvent" => strictness::from_value(data_type, data)
                 .map(|inner| Some(StakeEvent::DistributeRewardsEvent(inner))),
-            "0x1::delegation_pool::AddStakeEvent" => serde_json::from_value(data.clone())
+            "0x1::delegation_pool::AddStakeEvent" => strictness::from_value(data_type, data)
                 .map(|inner| Some(StakeEvent::AddStakeEvent(inner))),
-            "0x1::delegation_pool::UnlockStakeEvent" => serde_json::from_value(data.clone())
+            "0x1::delegation_pool::UnlockStakeEvent" => strictness::from_value(data_type, data)
                 .map(|inner| Some(StakeEvent::UnlockStakeEvent(inner))),
-            "0x1::delegation_pool::WithdrawStakeEvent" => serde_json::from_value(data.clone())
+            "0x1::delegation_pool::WithdrawStakeEvent" => strictness::from_value(data_type, data)
                 .map(|inner| Some(StakeEvent::WithdrawStakeEvent(inner))),
-            "0x1::delegation_pool::ReactivateStakeEvent" => serde_json::from_value(data.clone())
+            "0x1::delegation_pool::ReactivateStakeEvent" => strictness::from_value(data_type, data)
                 .map(|inner| Some(StakeEvent::ReactivateStakeEvent(inner))),
             _ => Ok(None),
         }
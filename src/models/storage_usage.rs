@@ -0,0 +1,257 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use crate::{
+    schema::{account_storage_deposits, transaction_storage_usage},
+    util::standardize_address,
+};
+use aptos_api_types::{
+    deserialize_from_string, Transaction as APITransaction, TransactionSignature, WriteSetChange,
+};
+use bigdecimal::{BigDecimal, Signed, Zero};
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Emitted by every user transaction once the `EMIT_FEE_STATEMENT` feature is on
+pub const FEE_STATEMENT_EVENT_TYPE: &str = "0x1::transaction_fee::FeeStatement";
+
+/// The storage part of a `0x1::transaction_fee::FeeStatement` event
+#[derive(Debug, Deserialize)]
+pub struct FeeStatement {
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub storage_fee_octas: BigDecimal,
+    #[serde(deserialize_with = "deserialize_from_string")]
+    pub storage_fee_refund_octas: BigDecimal,
+}
+
+/// Storage fees and write set sizes of a user transaction, see `custom::driver::storage_usage`
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Queryable, Serialize)]
+#[diesel(primary_key(transaction_version))]
+#[diesel(table_name = transaction_storage_usage)]
+pub struct TransactionStorageUsage {
+    pub transaction_version: i64,
+    pub payer_address: String,
+    pub storage_fee_octas: Option<BigDecimal>,
+    pub storage_fee_refund_octas: Option<BigDecimal>,
+    pub slots_written: i64,
+    pub slots_deleted: i64,
+    pub bytes_written: i64,
+}
+
+/// Storage deposits an account paid and got refunded, see `custom::driver::storage_usage`
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, PartialEq, Serialize)]
+#[diesel(primary_key(account_address))]
+#[diesel(table_name = account_storage_deposits)]
+pub struct AccountStorageDeposit {
+    pub account_address: String,
+    pub total_deposited_octas: BigDecimal,
+    pub total_refunded_octas: BigDecimal,
+    pub deposit_balance_octas: BigDecimal,
+    pub last_transaction_version: i64,
+}
+
+#[derive(Clone, Debug, Deserialize, Identifiable, Queryable, Serialize)]
+#[diesel(primary_key(account_address))]
+#[diesel(table_name = account_storage_deposits)]
+pub struct AccountStorageDepositQuery {
+    pub account_address: String,
+    pub total_deposited_octas: BigDecimal,
+    pub total_refunded_octas: BigDecimal,
+    pub deposit_balance_octas: BigDecimal,
+    pub last_transaction_version: i64,
+    pub inserted_at: chrono::NaiveDateTime,
+}
+
+/// A refund larger than the payer's deposit balance, which is kept at zero instead
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct NegativeDeposit {
+    pub account_address: String,
+    pub transaction_version: i64,
+    pub balance_octas: BigDecimal,
+    pub deposit_octas: BigDecimal,
+    pub refund_octas: BigDecimal,
+}
+
+impl TransactionStorageUsage {
+    /// `None` for anything but a user transaction
+    pub fn from_transaction(transaction: &APITransaction) -> Option<Self> {
+        let APITransaction::UserTransaction(txn) = transaction else {
+            return None;
+        };
+        // A fee statement that doesn't parse is treated like a missing one
+        let fee_statement = txn
+            .events
+            .iter()
+            .find(|event| event.typ.to_string() == FEE_STATEMENT_EVENT_TYPE)
+            .and_then(|event| serde_json::from_value::<FeeStatement>(event.data.clone()).ok());
+        let payer_address = match &txn.request.signature {
+            Some(TransactionSignature::FeePayerSignature(sig)) => sig.fee_payer_address.to_string(),
+            _ => txn.request.sender.to_string(),
+        };
+        let (mut slots_written, mut slots_deleted, mut bytes_written) = (0, 0, 0);
+        for change in &txn.info.changes {
+            match change {
+                WriteSetChange::WriteModule(module) => {
+                    slots_written += 1;
+                    bytes_written += module.data.bytecode.0.len() as i64;
+                },
+                WriteSetChange::WriteResource(_) => slots_written += 1,
+                WriteSetChange::WriteTableItem(item) => {
+                    slots_written += 1;
+                    bytes_written += (item.key.0.len() + item.value.0.len()) as i64;
+                },
+                WriteSetChange::DeleteModule(_)
+                | WriteSetChange::DeleteResource(_)
+                | WriteSetChange::DeleteTableItem(_) => slots_deleted += 1,
+            }
+        }
+        Some(Self {
+            transaction_version: txn.info.version.0 as i64,
+            payer_address: standardize_address(&payer_address),
+            storage_fee_octas: fee_statement
+                .as_ref()
+                .map(|fees| fees.storage_fee_octas.clone()),
+            storage_fee_refund_octas: fee_statement.map(|fees| fees.storage_fee_refund_octas),
+            slots_written,
+            slots_deleted,
+            bytes_written,
+        })
+    }
+}
+
+impl AccountStorageDeposit {
+    fn new(account_address: &str) -> Self {
+        Self {
+            account_address: account_address.to_string(),
+            total_deposited_octas: BigDecimal::zero(),
+            total_refunded_octas: BigDecimal::zero(),
+            deposit_balance_octas: BigDecimal::zero(),
+            last_transaction_version: -1,
+        }
+    }
+
+    /// Applies the fees of `usages`, in version order, to the `stored` deposits of their payers.
+    /// Usages at or below an account's last applied version are left out. Returns the accounts'
+    /// new rows, the refunds that would have taken a balance below zero, which is kept at zero,
+    /// and the number of usages left out.
+    pub fn apply(
+        usages: &[TransactionStorageUsage],
+        stored: &HashMap<String, Self>,
+    ) -> (Vec<Self>, Vec<NegativeDeposit>, usize) {
+        let mut deposits = BTreeMap::<String, Self>::new();
+        let mut negatives = vec![];
+        let mut skipped = 0;
+        for usage in usages {
+            let (Some(fee), Some(refund)) =
+                (&usage.storage_fee_octas, &usage.storage_fee_refund_octas)
+            else {
+                continue;
+            };
+            if fee.is_zero() && refund.is_zero() {
+                continue;
+            }
+            let deposit = deposits
+                .entry(usage.payer_address.clone())
+                .or_insert_with_key(|address| {
+                    stored
+                        .get(address)
+                        .cloned()
+                        .unwrap_or_else(|| Self::new(address))
+                });
+            if usage.transaction_version <= deposit.last_transaction_version {
+                skipped += 1;
+                continue;
+            }
+            let balance = &deposit.deposit_balance_octas + fee - refund;
+            if balance.is_negative() {
+                negatives.push(NegativeDeposit {
+                    account_address: usage.payer_address.clone(),
+                    transaction_version: usage.transaction_version,
+                    balance_octas: deposit.deposit_balance_octas.clone(),
+                    deposit_octas: fee.clone(),
+                    refund_octas: refund.clone(),
+                });
+                deposit.deposit_balance_octas = BigDecimal::zero();
+            } else {
+                deposit.deposit_balance_octas = balance;
+            }
+            deposit.total_deposited_octas += fee;
+            deposit.total_refunded_octas += refund;
+            deposit.last_transaction_version = usage.transaction_version;
+        }
+        // Accounts whose usages were all left out keep their stored row
+        let deposits = deposits
+            .into_values()
+            .filter(|deposit| stored.get(&deposit.account_address) != Some(deposit))
+            .collect();
+        (deposits, negatives, skipped)
+    }
+}
+
+impl From<AccountStorageDepositQuery> for AccountStorageDeposit {
+    fn from(row: AccountStorageDepositQuery) -> Self {
+        Self {
+            account_address: row.account_address,
+            total_deposited_octas: row.total_deposited_octas,
+            total_refunded_octas: row.total_refunded_octas,
+            deposit_balance_octas: row.deposit_balance_octas,
+            last_transaction_version: row.last_transaction_version,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(payer: &str, version: i64, fee: u64, refund: u64) -> TransactionStorageUsage {
+        TransactionStorageUsage {
+            transaction_version: version,
+            payer_address: payer.to_string(),
+            storage_fee_octas: Some(BigDecimal::from(fee)),
+            storage_fee_refund_octas: Some(BigDecimal::from(refund)),
+            slots_written: 1,
+            slots_deleted: 0,
+            bytes_written: 0,
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let usages = vec![
+            usage("0xa", 1, 100, 0),
+            usage("0xa", 2, 0, 40),
+            usage("0xb", 3, 0, 0),
+            usage("0xb", 4, 0, 25),
+        ];
+        let (deposits, negatives, skipped) = AccountStorageDeposit::apply(&usages, &HashMap::new());
+        assert_eq!(skipped, 0);
+        assert_eq!(deposits.len(), 2);
+        assert_eq!(deposits[0].deposit_balance_octas, BigDecimal::from(60));
+        assert_eq!(deposits[0].total_refunded_octas, BigDecimal::from(40));
+        assert_eq!(deposits[0].last_transaction_version, 2);
+        // A refund to an account that paid no deposit stays at zero and is reported
+        assert_eq!(deposits[1].deposit_balance_octas, BigDecimal::zero());
+        assert_eq!(deposits[1].total_refunded_octas, BigDecimal::from(25));
+        assert_eq!(negatives.len(), 1);
+        assert_eq!(
+            (
+                &negatives[0].account_address,
+                negatives[0].transaction_version
+            ),
+            (&"0xb".to_string(), 4)
+        );
+
+        // Re-applied on top of the stored rows, nothing changes
+        let stored = deposits
+            .into_iter()
+            .map(|deposit| (deposit.account_address.clone(), deposit))
+            .collect::<HashMap<_, _>>();
+        let (deposits, negatives, skipped) = AccountStorageDeposit::apply(&usages, &stored);
+        assert_eq!((deposits.len(), negatives.len(), skipped), (0, 0, 3));
+    }
+}
@@ -6,21 +6,33 @@
 #![allow(clippy::unused_unit)]
 
 use crate::{
+    custom::driver::{
+        config::OversizeAction,
+        row_limits::{ColumnLimit, RowLimits},
+    },
+    database::PgPoolConnection,
     schema::current_ans_lookup,
     util::{bigdecimal_to_u64, parse_timestamp_secs, standardize_address},
 };
-use aptos_api_types::{deserialize_from_string, MoveType, Transaction as APITransaction};
+use anyhow::Context;
+use aptos_api_types::{
+    deserialize_from_string, MoveType, Transaction as APITransaction, WriteSetChange,
+};
 use bigdecimal::BigDecimal;
+use diesel::prelude::*;
 use field_count::FieldCount;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 type Domain = String;
 type Subdomain = String;
 // PK of current_ans_lookup, i.e. domain and subdomain name
 pub type CurrentAnsLookupPK = (Domain, Subdomain);
+pub type CurrentAnsLookupMap = HashMap<CurrentAnsLookupPK, CurrentAnsLookup>;
+/// Width of the `domain` and `subdomain` columns, a longer name fails the insert
+const NAME_MAX_BYTES: usize = 64;
 
-#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[derive(Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, PartialEq, Serialize)]
 #[diesel(primary_key(domain, subdomain))]
 #[diesel(table_name = current_ans_lookup)]
 #[diesel(treat_none_as_null = true)]
@@ -29,8 +41,48 @@ pub struct CurrentAnsLookup {
     pub subdomain: String,
     pub registered_address: Option<String>,
     pub last_transaction_version: i64,
+    /// When the name stops resolving: a subdomain's is the earlier of its record's and its
+    /// domain's
+    pub expiration_timestamp: chrono::NaiveDateTime,
+    pub token_name: String,
+    /// Whether the name is the primary name of `registered_address`
+    pub is_primary: bool,
+    /// The expiration in the name's own record
+    pub record_expiration_timestamp: chrono::NaiveDateTime,
+}
+
+/// Need a separate struct for queryable because we don't want to define the inserted_at column (letting DB fill)
+#[derive(Debug, Identifiable, Queryable)]
+#[diesel(primary_key(domain, subdomain))]
+#[diesel(table_name = current_ans_lookup)]
+pub struct CurrentAnsLookupQuery {
+    pub domain: String,
+    pub subdomain: String,
+    pub registered_address: Option<String>,
     pub expiration_timestamp: chrono::NaiveDateTime,
+    pub last_transaction_version: i64,
+    pub inserted_at: chrono::NaiveDateTime,
     pub token_name: String,
+    pub is_primary: bool,
+    pub record_expiration_timestamp: chrono::NaiveDateTime,
+}
+
+/// A change of a name by a transaction of the ANS contract
+#[derive(Clone, Debug, PartialEq)]
+pub enum AnsChange {
+    /// The name's record was written, with where it points and when it expires
+    Record {
+        name: CurrentAnsLookupPK,
+        target_address: Option<String>,
+        expiration_timestamp: chrono::NaiveDateTime,
+        transaction_version: i64,
+    },
+    /// The name became, or stopped being, the primary name of the address it points to
+    Primary {
+        name: CurrentAnsLookupPK,
+        is_primary: bool,
+        transaction_version: i64,
+    },
 }
 
 pub enum ANSEvent {
@@ -55,6 +107,30 @@ pub struct RegisterNameEventV1 {
     expiration_time_secs: BigDecimal,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SetReverseLookupEventV1 {
+    account_addr: String,
+    prev_domain_name: OptionalString,
+    prev_subdomain_name: OptionalString,
+    curr_domain_name: OptionalString,
+    curr_subdomain_name: OptionalString,
+}
+
+/// Key of the `domains::NameRegistryV1` table
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct NameRecordKeyV1 {
+    subdomain_name: OptionalString,
+    domain_name: String,
+}
+
+/// Value of the `domains::NameRegistryV1` table
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct NameRecordV1 {
+    #[serde(deserialize_with = "deserialize_from_string")]
+    expiration_time_sec: BigDecimal,
+    target_address: OptionalString,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct OptionalString {
     vec: Vec<String>,
@@ -131,6 +207,8 @@ impl CurrentAnsLookup {
                                     last_transaction_version: txn_version,
                                     expiration_timestamp,
                                     token_name,
+                                    is_primary: false,
+                                    record_expiration_timestamp: expiration_timestamp,
                                 }
                             },
                             ANSEvent::RegisterNameEventV1(inner) => {
@@ -151,6 +229,8 @@ impl CurrentAnsLookup {
                                     last_transaction_version: txn_version,
                                     expiration_timestamp,
                                     token_name,
+                                    is_primary: false,
+                                    record_expiration_timestamp: expiration_timestamp,
                                 }
                             },
                         };
@@ -169,3 +249,395 @@ impl CurrentAnsLookup {
         current_ans_lookups
     }
 }
+
+impl CurrentAnsLookup {
+    fn new(
+        name: &CurrentAnsLookupPK,
+        expiration_timestamp: chrono::NaiveDateTime,
+        transaction_version: i64,
+    ) -> Self {
+        let (domain, subdomain) = name.clone();
+        let token_name = if subdomain.is_empty() {
+            format!("{}.apt", domain)
+        } else {
+            format!("{}.{}.apt", subdomain, domain)
+        };
+        Self {
+            domain,
+            subdomain,
+            registered_address: None,
+            last_transaction_version: transaction_version,
+            expiration_timestamp,
+            token_name,
+            is_primary: false,
+            record_expiration_timestamp: expiration_timestamp,
+        }
+    }
+
+    /// Applies `changes` in order to `current`, the rows of every domain they change with their
+    /// subdomains, and returns the rows that changed sorted by PK. A new target clears
+    /// `is_primary`, a primary name pointing to its address. Subdomains are capped at their
+    /// domain's expiration whenever both rows are known, also when the subdomain was written in an
+    /// earlier batch than its domain.
+    pub fn apply_changes(mut current: CurrentAnsLookupMap, changes: &[AnsChange]) -> Vec<Self> {
+        let previous = current.clone();
+        for change in changes {
+            match change {
+                AnsChange::Record {
+                    name,
+                    target_address,
+                    expiration_timestamp,
+                    transaction_version,
+                } => {
+                    let row = current.entry(name.clone()).or_insert_with(|| {
+                        Self::new(name, *expiration_timestamp, *transaction_version)
+                    });
+                    if &row.registered_address != target_address {
+                        row.registered_address = target_address.clone();
+                        row.is_primary = false;
+                    }
+                    row.expiration_timestamp = *expiration_timestamp;
+                    row.record_expiration_timestamp = *expiration_timestamp;
+                    row.last_transaction_version = *transaction_version;
+                },
+                AnsChange::Primary {
+                    name,
+                    is_primary,
+                    transaction_version,
+                } => match current.get_mut(name) {
+                    Some(row) => {
+                        row.is_primary = *is_primary;
+                        row.last_transaction_version = *transaction_version;
+                    },
+                    None => aptos_logger::warn!(
+                        domain = name.0,
+                        subdomain = name.1,
+                        transaction_version = transaction_version,
+                        "Primary name change of a name without a record, skipping"
+                    ),
+                },
+            }
+        }
+
+        let domains = current
+            .values()
+            .filter(|row| row.subdomain.is_empty())
+            .map(|row| {
+                (
+                    row.domain.clone(),
+                    (row.expiration_timestamp, row.last_transaction_version),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        for row in current.values_mut().filter(|row| !row.subdomain.is_empty()) {
+            let (expiration_timestamp, version) = match domains.get(&row.domain) {
+                Some((domain_expiration, version)) => (
+                    row.record_expiration_timestamp.min(*domain_expiration),
+                    *version,
+                ),
+                None => (
+                    row.record_expiration_timestamp,
+                    row.last_transaction_version,
+                ),
+            };
+            if expiration_timestamp != row.expiration_timestamp {
+                row.expiration_timestamp = expiration_timestamp;
+                row.last_transaction_version = row.last_transaction_version.max(version);
+            }
+        }
+
+        let mut changed = current
+            .into_iter()
+            .filter(|(name, row)| previous.get(name) != Some(row))
+            .map(|(_, row)| row)
+            .collect::<Vec<_>>();
+        changed.sort_by(|a, b| (&a.domain, &a.subdomain).cmp(&(&b.domain, &b.subdomain)));
+        changed
+    }
+}
+
+impl CurrentAnsLookupQuery {
+    /// The rows of `domains` and of their subdomains
+    pub fn get_by_domains(
+        conn: &mut PgPoolConnection,
+        domains: &[String],
+    ) -> diesel::QueryResult<Vec<Self>> {
+        current_ans_lookup::table
+            .filter(current_ans_lookup::domain.eq_any(domains))
+            .load::<Self>(conn)
+    }
+}
+
+impl From<CurrentAnsLookupQuery> for CurrentAnsLookup {
+    fn from(query: CurrentAnsLookupQuery) -> Self {
+        Self {
+            domain: query.domain,
+            subdomain: query.subdomain,
+            registered_address: query.registered_address,
+            last_transaction_version: query.last_transaction_version,
+            expiration_timestamp: query.expiration_timestamp,
+            token_name: query.token_name,
+            is_primary: query.is_primary,
+            record_expiration_timestamp: query.record_expiration_timestamp,
+        }
+    }
+}
+
+impl RowLimits for CurrentAnsLookup {
+    const LIMITS: &'static [ColumnLimit] = &[
+        ColumnLimit::new("domain", NAME_MAX_BYTES, OversizeAction::DeadLetter),
+        ColumnLimit::new("subdomain", NAME_MAX_BYTES, OversizeAction::DeadLetter),
+    ];
+    const TABLE: &'static str = "current_ans_lookup";
+
+    fn transaction_version(&self) -> i64 {
+        self.last_transaction_version
+    }
+
+    fn column(&self, index: usize) -> &str {
+        match index {
+            0 => &self.domain,
+            _ => &self.subdomain,
+        }
+    }
+
+    fn column_mut(&mut self, index: usize) -> &mut String {
+        match index {
+            0 => &mut self.domain,
+            _ => &mut self.subdomain,
+        }
+    }
+}
+
+impl AnsChange {
+    pub fn name(&self) -> &CurrentAnsLookupPK {
+        match self {
+            Self::Record { name, .. } | Self::Primary { name, .. } => name,
+        }
+    }
+
+    /// The changes of a transaction to names of the ANS contract at `ans_contract_address`,
+    /// standardized: the records written to `domains::NameRegistryV1`, then the primary names
+    /// set and cleared by `domains::SetReverseLookupEventV1`s. A name without a decoded record in
+    /// the write set is taken from its register and set address events instead.
+    pub fn from_transaction(
+        transaction: &APITransaction,
+        ans_contract_address: &str,
+    ) -> anyhow::Result<Vec<Self>> {
+        let user_txn = match transaction {
+            APITransaction::UserTransaction(user_txn) => user_txn,
+            _ => return Ok(vec![]),
+        };
+        let txn_version = user_txn.info.version.0 as i64;
+        let is_contract_type = |type_str: &str, name: &str| {
+            type_str.split_once("::").map_or(false, |(address, rest)| {
+                standardize_address(address) == ans_contract_address && rest == name
+            })
+        };
+
+        let mut changes = vec![];
+        let mut recorded = HashSet::new();
+        for wsc in &user_txn.info.changes {
+            let data = match wsc {
+                WriteSetChange::WriteTableItem(item) => match &item.data {
+                    Some(data) => data,
+                    None => continue,
+                },
+                _ => continue,
+            };
+            if !is_contract_type(&data.value_type, "domains::NameRecordV1") {
+                continue;
+            }
+            let key: NameRecordKeyV1 =
+                serde_json::from_value(data.key.clone()).with_context(|| {
+                    format!("Bad name record key {}, version {}", data.key, txn_version)
+                })?;
+            let record: NameRecordV1 =
+                serde_json::from_value(data.value.clone()).with_context(|| {
+                    format!("Bad name record {}, version {}", data.value, txn_version)
+                })?;
+            let name = (
+                key.domain_name,
+                key.subdomain_name.get_string().unwrap_or_default(),
+            );
+            recorded.insert(name.clone());
+            changes.push(Self::Record {
+                name,
+                target_address: record
+                    .target_address
+                    .get_string()
+                    .map(|address| standardize_address(&address)),
+                expiration_timestamp: parse_timestamp_secs(
+                    bigdecimal_to_u64(&record.expiration_time_sec),
+                    txn_version,
+                ),
+                transaction_version: txn_version,
+            });
+        }
+
+        let mut primary_changes = vec![];
+        for event in &user_txn.events {
+            let event_type = match &event.typ {
+                MoveType::Struct(inner)
+                    if standardize_address(&inner.address.to_string()) == ans_contract_address =>
+                {
+                    format!("{}::{}", inner.module, inner.name)
+                },
+                _ => continue,
+            };
+            let parse_error =
+                || format!("Bad {} {}, version {}", event_type, event.data, txn_version);
+            match event_type.as_str() {
+                "domains::RegisterNameEventV1" => {
+                    let inner: RegisterNameEventV1 =
+                        serde_json::from_value(event.data.clone()).with_context(parse_error)?;
+                    let name = (
+                        inner.domain_name,
+                        inner.subdomain_name.get_string().unwrap_or_default(),
+                    );
+                    if !recorded.contains(&name) {
+                        changes.push(Self::Record {
+                            name,
+                            target_address: None,
+                            expiration_timestamp: parse_timestamp_secs(
+                                bigdecimal_to_u64(&inner.expiration_time_secs),
+                                txn_version,
+                            ),
+                            transaction_version: txn_version,
+                        });
+                    }
+                },
+                "domains::SetNameAddressEventV1" => {
+                    let inner: SetNameAddressEventV1 =
+                        serde_json::from_value(event.data.clone()).with_context(parse_error)?;
+                    let name = (
+                        inner.domain_name,
+                        inner.subdomain_name.get_string().unwrap_or_default(),
+                    );
+                    if !recorded.contains(&name) {
+                        changes.push(Self::Record {
+                            name,
+                            target_address: inner
+                                .new_address
+                                .get_string()
+                                .map(|address| standardize_address(&address)),
+                            expiration_timestamp: parse_timestamp_secs(
+                                bigdecimal_to_u64(&inner.expiration_time_secs),
+                                txn_version,
+                            ),
+                            transaction_version: txn_version,
+                        });
+                    }
+                },
+                "domains::SetReverseLookupEventV1" => {
+                    let inner: SetReverseLookupEventV1 =
+                        serde_json::from_value(event.data.clone()).with_context(parse_error)?;
+                    let names = [
+                        (inner.prev_domain_name, inner.prev_subdomain_name, false),
+                        (inner.curr_domain_name, inner.curr_subdomain_name, true),
+                    ];
+                    for (domain, subdomain, is_primary) in names {
+                        if let Some(domain) = domain.get_string() {
+                            primary_changes.push(Self::Primary {
+                                name: (domain, subdomain.get_string().unwrap_or_default()),
+                                is_primary,
+                                transaction_version: txn_version,
+                            });
+                        }
+                    }
+                },
+                _ => {},
+            }
+        }
+        changes.append(&mut primary_changes);
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(domain: &str, subdomain: &str) -> CurrentAnsLookupPK {
+        (domain.to_string(), subdomain.to_string())
+    }
+
+    fn record(
+        name: &CurrentAnsLookupPK,
+        target_address: &str,
+        expiration_secs: i64,
+        transaction_version: i64,
+    ) -> AnsChange {
+        AnsChange::Record {
+            name: name.clone(),
+            target_address: Some(target_address.to_string()),
+            expiration_timestamp: chrono::NaiveDateTime::from_timestamp_opt(expiration_secs, 0)
+                .unwrap(),
+            transaction_version,
+        }
+    }
+
+    fn primary(name: &CurrentAnsLookupPK, is_primary: bool, transaction_version: i64) -> AnsChange {
+        AnsChange::Primary {
+            name: name.clone(),
+            is_primary,
+            transaction_version,
+        }
+    }
+
+    fn secs(timestamp: chrono::NaiveDateTime) -> i64 {
+        timestamp.timestamp()
+    }
+
+    #[test]
+    fn test_apply_changes() {
+        let alice = name("alice", "");
+        let pay = name("alice", "pay");
+        let rows = CurrentAnsLookup::apply_changes(HashMap::new(), &[
+            record(&alice, "0xa1", 1_000, 1),
+            primary(&alice, true, 2),
+            // A primary name change of a name never recorded is skipped
+            primary(&name("bob", ""), true, 2),
+        ]);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].token_name, "alice.apt");
+        assert!(rows[0].is_primary);
+        assert_eq!(rows[0].last_transaction_version, 2);
+
+        // Writing the record again keeps the primary name, pointing it elsewhere clears it
+        let current = CurrentAnsLookupMap::from([(alice.clone(), rows[0].clone())]);
+        let rows =
+            CurrentAnsLookup::apply_changes(current.clone(), &[record(&alice, "0xa1", 2_000, 3)]);
+        assert!(rows[0].is_primary);
+        let rows = CurrentAnsLookup::apply_changes(current, &[record(&alice, "0xb2", 1_000, 3)]);
+        assert!(!rows[0].is_primary);
+        assert_eq!(rows[0].registered_address.as_deref(), Some("0xb2"));
+
+        // A subdomain without its domain keeps its own expiration, until the domain's row
+        // arrives in a later batch
+        let rows =
+            CurrentAnsLookup::apply_changes(HashMap::new(), &[record(&pay, "0xa1", 5_000, 4)]);
+        assert_eq!(rows[0].token_name, "pay.alice.apt");
+        assert_eq!(secs(rows[0].expiration_timestamp), 5_000);
+        let current = CurrentAnsLookupMap::from([(pay.clone(), rows[0].clone())]);
+        let rows = CurrentAnsLookup::apply_changes(current, &[record(&alice, "0xa1", 1_000, 5)]);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].subdomain, "pay");
+        assert_eq!(secs(rows[1].expiration_timestamp), 1_000);
+        assert_eq!(secs(rows[1].record_expiration_timestamp), 5_000);
+        assert_eq!(rows[1].last_transaction_version, 5);
+
+        // Renewing the domain lets the subdomain run to its own expiration, and the subdomain
+        // isn't returned again when its expiration is unchanged
+        let current = rows
+            .into_iter()
+            .map(|row| ((row.domain.clone(), row.subdomain.clone()), row))
+            .collect::<CurrentAnsLookupMap>();
+        let rows =
+            CurrentAnsLookup::apply_changes(current.clone(), &[record(&alice, "0xa1", 9_000, 6)]);
+        assert_eq!(secs(rows[1].expiration_timestamp), 5_000);
+        let rows = CurrentAnsLookup::apply_changes(current, &[primary(&alice, false, 6)]);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].subdomain, "");
+    }
+}
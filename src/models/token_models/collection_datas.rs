@@ -10,6 +10,7 @@ use super::{
     tokens::TableHandleToOwner,
 };
 use crate::{
+    custom::driver::retry_budget::{self, ErrorClass},
     database::PgPoolConnection,
     schema::{collection_datas, current_collection_datas},
     util::standardize_address,
@@ -174,7 +175,12 @@ impl CollectionData {
             retried += 1;
             match CurrentCollectionDataQuery::get_by_table_handle(conn, table_handle) {
                 Ok(current_collection_data) => return Ok(current_collection_data.creator_address),
-                Err(_) => {
+                Err(err) => {
+                    if let Err(exhausted) =
+                        retry_budget::retry(ErrorClass::DbQuery, "get collection creator", &err)
+                    {
+                        return Err(exhausted.into());
+                    }
                     std::thread::sleep(std::time::Duration::from_millis(QUERY_RETRY_DELAY_MS));
                 },
             }
@@ -4,9 +4,12 @@
 // This is required because a diesel macro makes clippy sad
 #![allow(clippy::extra_unused_lifetimes)]
 
-use crate::util::{
-    deserialize_property_map_from_bcs_hexstring, deserialize_string_from_hexstring, hash_str,
-    standardize_address, truncate_str,
+use crate::{
+    strictness,
+    util::{
+        deserialize_property_map_from_bcs_hexstring, deserialize_string_from_hexstring, hash_str,
+        standardize_address, truncate_str,
+    },
 };
 use anyhow::{Context, Result};
 use aptos_api_types::deserialize_from_string;
@@ -325,18 +328,17 @@ impl TokenWriteSet {
         txn_version: i64,
     ) -> Result<Option<TokenWriteSet>> {
         match data_type {
-            "0x3::token::TokenDataId" => serde_json::from_value(data.clone())
+            "0x3::token::TokenDataId" => strictness::from_value(data_type, data)
                 .map(|inner| Some(TokenWriteSet::TokenDataId(inner))),
-            "0x3::token::TokenId" => serde_json::from_value(data.clone())
+            "0x3::token::TokenId" => strictness::from_value(data_type, data)
                 .map(|inner| Some(TokenWriteSet::TokenId(inner))),
-            "0x3::token::TokenData" => serde_json::from_value(data.clone())
+            "0x3::token::TokenData" => strictness::from_value(data_type, data)
                 .map(|inner| Some(TokenWriteSet::TokenData(inner))),
-            "0x3::token::Token" => {
-                serde_json::from_value(data.clone()).map(|inner| Some(TokenWriteSet::Token(inner)))
-            },
-            "0x3::token::CollectionData" => serde_json::from_value(data.clone())
+            "0x3::token::Token" => strictness::from_value(data_type, data)
+                .map(|inner| Some(TokenWriteSet::Token(inner))),
+            "0x3::token::CollectionData" => strictness::from_value(data_type, data)
                 .map(|inner| Some(TokenWriteSet::CollectionData(inner))),
-            "0x3::token_transfers::TokenOfferId" => serde_json::from_value(data.clone())
+            "0x3::token_transfers::TokenOfferId" => strictness::from_value(data_type, data)
                 .map(|inner| Some(TokenWriteSet::TokenOfferId(inner))),
             _ => Ok(None),
         }
@@ -366,21 +368,23 @@ impl TokenEvent {
         txn_version: i64,
     ) -> Result<Option<TokenEvent>> {
         match data_type {
-            "0x3::token::MintTokenEvent" => serde_json::from_value(data.clone())
+            "0x3::token::MintTokenEvent" => strictness::from_value(data_type, data)
                 .map(|inner| Some(TokenEvent::MintTokenEvent(inner))),
-            "0x3::token::BurnTokenEvent" => serde_json::from_value(data.clone())
+            "0x3::token::BurnTokenEvent" => strictness::from_value(data_type, data)
                 .map(|inner| Some(TokenEvent::BurnTokenEvent(inner))),
-            "0x3::token::MutateTokenPropertyMapEvent" => serde_json::from_value(data.clone())
+            "0x3::token::MutateTokenPropertyMapEvent" => strictness::from_value(data_type, data)
                 .map(|inner| Some(TokenEvent::MutateTokenPropertyMapEvent(inner))),
-            "0x3::token::WithdrawEvent" => serde_json::from_value(data.clone())
+            "0x3::token::WithdrawEvent" => strictness::from_value(data_type, data)
                 .map(|inner| Some(TokenEvent::WithdrawTokenEvent(inner))),
-            "0x3::token::DepositEvent" => serde_json::from_value(data.clone())
+            "0x3::token::DepositEvent" => strictness::from_value(data_type, data)
                 .map(|inner| Some(TokenEvent::DepositTokenEvent(inner))),
-            "0x3::token_transfers::TokenOfferEvent" => serde_json::from_value(data.clone())
+            "0x3::token_transfers::TokenOfferEvent" => strictness::from_value(data_type, data)
                 .map(|inner| Some(TokenEvent::OfferTokenEvent(inner))),
-            "0x3::token_transfers::TokenCancelOfferEvent" => serde_json::from_value(data.clone())
-                .map(|inner| Some(TokenEvent::CancelTokenOfferEvent(inner))),
-            "0x3::token_transfers::TokenClaimEvent" => serde_json::from_value(data.clone())
+            "0x3::token_transfers::TokenCancelOfferEvent" => {
+                strictness::from_value(data_type, data)
+                    .map(|inner| Some(TokenEvent::CancelTokenOfferEvent(inner)))
+            },
+            "0x3::token_transfers::TokenClaimEvent" => strictness::from_value(data_type, data)
                 .map(|inner| Some(TokenEvent::ClaimTokenEvent(inner))),
             _ => Ok(None),
         }
@@ -414,11 +418,11 @@ impl TokenResource {
         txn_version: i64,
     ) -> Result<TokenResource> {
         match data_type {
-            "0x3::token::Collections" => serde_json::from_value(data.clone())
+            "0x3::token::Collections" => strictness::from_value(data_type, data)
                 .map(|inner| Some(TokenResource::CollectionResource(inner))),
-            "0x3::token::TokenStore" => serde_json::from_value(data.clone())
+            "0x3::token::TokenStore" => strictness::from_value(data_type, data)
                 .map(|inner| Some(TokenResource::TokenStoreResource(inner))),
-            "0x3::token_transfers::PendingClaims" => serde_json::from_value(data.clone())
+            "0x3::token_transfers::PendingClaims" => strictness::from_value(data_type, data)
                 .map(|inner| Some(TokenResource::PendingClaimsResource(inner))),
             _ => Ok(None),
         }
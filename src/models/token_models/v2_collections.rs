@@ -12,6 +12,7 @@ use super::{
     v2_token_utils::{TokenStandard, TokenV2AggregatedDataMapping, V2TokenResource},
 };
 use crate::{
+    custom::driver::retry_budget::{self, ErrorClass},
     database::PgPoolConnection,
     models::move_resources::MoveResource,
     schema::{collections_v2, current_collections_v2},
@@ -289,7 +290,14 @@ impl CollectionV2 {
             retried += 1;
             match Self::get_by_table_handle(conn, table_handle) {
                 Ok(creator) => return Ok(creator),
-                Err(_) => {
+                Err(err) => {
+                    if let Err(exhausted) = retry_budget::retry(
+                        ErrorClass::DbQuery,
+                        "get collection creator for v1",
+                        &err,
+                    ) {
+                        return Err(exhausted.into());
+                    }
                     std::thread::sleep(std::time::Duration::from_millis(QUERY_RETRY_DELAY_MS));
                 },
             }
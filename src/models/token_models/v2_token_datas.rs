@@ -11,6 +11,7 @@ use super::{
     v2_token_utils::{TokenStandard, TokenV2, TokenV2AggregatedDataMapping},
 };
 use crate::{
+    custom::driver::retry_budget::{self, ErrorClass},
     database::PgPoolConnection,
     schema::{current_token_datas_v2, token_datas_v2},
     util::standardize_address,
@@ -242,7 +243,12 @@ impl TokenDataV2 {
             retried += 1;
             match Self::get_by_token_data_id(conn, address) {
                 Ok(_) => return true,
-                Err(_) => {
+                Err(err) => {
+                    if let Err(_) =
+                        retry_budget::retry(ErrorClass::DbQuery, "is address token", &err)
+                    {
+                        return false;
+                    }
                     std::thread::sleep(std::time::Duration::from_millis(QUERY_RETRY_DELAY_MS));
                 },
             }
@@ -15,6 +15,7 @@ use super::{
     },
 };
 use crate::{
+    custom::driver::retry_budget::{self, ErrorClass},
     database::PgPoolConnection,
     models::{
         coin_models::v2_fungible_asset_utils::V2FungibleAssetResource, move_resources::MoveResource,
@@ -615,7 +616,12 @@ impl CurrentTokenOwnershipV2Query {
                         is_soulbound: inner.is_soulbound_v2,
                     })
                 },
-                Err(_) => {
+                Err(err) => {
+                    if let Err(exhausted) =
+                        retry_budget::retry(ErrorClass::DbQuery, "get nft by token data id", &err)
+                    {
+                        return Err(exhausted.into());
+                    }
                     std::thread::sleep(std::time::Duration::from_millis(QUERY_RETRY_DELAY_MS));
                 },
             }
@@ -13,6 +13,7 @@ use crate::{
         move_resources::MoveResource,
         v2_objects::CurrentObjectPK,
     },
+    strictness,
     util::{
         deserialize_token_object_property_map_from_bcs_hexstring, standardize_address, truncate_str,
     },
@@ -470,25 +471,23 @@ impl V2TokenResource {
     ) -> Result<Self> {
         match data_type {
             "0x1::object::ObjectCore" => {
-                serde_json::from_value(data.clone()).map(|inner| Some(Self::ObjectCore(inner)))
+                strictness::from_value(data_type, data).map(|inner| Some(Self::ObjectCore(inner)))
             },
             "0x4::collection::Collection" => {
-                serde_json::from_value(data.clone()).map(|inner| Some(Self::Collection(inner)))
+                strictness::from_value(data_type, data).map(|inner| Some(Self::Collection(inner)))
             },
             "0x4::collection::FixedSupply" => {
-                serde_json::from_value(data.clone()).map(|inner| Some(Self::FixedSupply(inner)))
-            },
-            "0x4::collection::UnlimitedSupply" => {
-                serde_json::from_value(data.clone()).map(|inner| Some(Self::UnlimitedSupply(inner)))
-            },
-            "0x4::aptos_token::AptosCollection" => {
-                serde_json::from_value(data.clone()).map(|inner| Some(Self::AptosCollection(inner)))
+                strictness::from_value(data_type, data).map(|inner| Some(Self::FixedSupply(inner)))
             },
+            "0x4::collection::UnlimitedSupply" => strictness::from_value(data_type, data)
+                .map(|inner| Some(Self::UnlimitedSupply(inner))),
+            "0x4::aptos_token::AptosCollection" => strictness::from_value(data_type, data)
+                .map(|inner| Some(Self::AptosCollection(inner))),
             "0x4::token::Token" => {
-                serde_json::from_value(data.clone()).map(|inner| Some(Self::TokenV2(inner)))
+                strictness::from_value(data_type, data).map(|inner| Some(Self::TokenV2(inner)))
             },
             "0x4::property_map::PropertyMap" => {
-                serde_json::from_value(data.clone()).map(|inner| Some(Self::PropertyMap(inner)))
+                strictness::from_value(data_type, data).map(|inner| Some(Self::PropertyMap(inner)))
             },
             _ => Ok(None),
         }
@@ -519,16 +518,15 @@ impl V2TokenEvent {
     ) -> Result<Option<Self>> {
         match data_type {
             "0x4::collection::MintEvent" => {
-                serde_json::from_value(data.clone()).map(|inner| Some(Self::MintEvent(inner)))
+                strictness::from_value(data_type, data).map(|inner| Some(Self::MintEvent(inner)))
             },
-            "0x4::token::MutationEvent" => serde_json::from_value(data.clone())
+            "0x4::token::MutationEvent" => strictness::from_value(data_type, data)
                 .map(|inner| Some(Self::TokenMutationEvent(inner))),
             "0x4::collection::BurnEvent" => {
-                serde_json::from_value(data.clone()).map(|inner| Some(Self::BurnEvent(inner)))
-            },
-            "0x1::object::TransferEvent" => {
-                serde_json::from_value(data.clone()).map(|inner| Some(Self::TransferEvent(inner)))
+                strictness::from_value(data_type, data).map(|inner| Some(Self::BurnEvent(inner)))
             },
+            "0x1::object::TransferEvent" => strictness::from_value(data_type, data)
+                .map(|inner| Some(Self::TransferEvent(inner))),
             _ => Ok(None),
         }
         .context(format!(
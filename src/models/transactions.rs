@@ -5,32 +5,38 @@
 #![allow(clippy::extra_unused_lifetimes)]
 #![allow(clippy::unused_unit)]
 
-use super::{
-    block_metadata_transactions::{BlockMetadataTransaction, BlockMetadataTransactionQuery},
-    events::{EventModel, EventQuery},
-    signatures::Signature,
-    user_transactions::{UserTransaction, UserTransactionQuery},
-    write_set_changes::{WriteSetChangeDetail, WriteSetChangeModel, WriteSetChangeQuery},
-};
-use crate::{
-    database::PgPoolConnection,
-    schema::{block_metadata_transactions, transactions, user_transactions},
-    util::u64_to_bigdecimal,
-};
-use aptos_api_types::{Transaction as APITransaction, TransactionInfo};
 use bigdecimal::BigDecimal;
-use diesel::{
-    BelongingToDsl, ExpressionMethods, GroupedBy, OptionalExtension, QueryDsl, RunQueryDsl,
-};
 use field_count::FieldCount;
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "indexer")]
+use {
+    super::{
+        block_metadata_transactions::{BlockMetadataTransaction, BlockMetadataTransactionQuery},
+        events::{EventModel, EventQuery},
+        signatures::Signature,
+        user_transactions::{UserTransaction, UserTransactionQuery},
+        write_set_changes::{WriteSetChangeDetail, WriteSetChangeModel, WriteSetChangeQuery},
+    },
+    crate::{
+        database::PgPoolConnection,
+        schema::{block_metadata_transactions, transactions, user_transactions},
+        util::{parse_timestamp, u64_to_bigdecimal},
+    },
+    aptos_api_types::{Transaction as APITransaction, TransactionInfo},
+    diesel::{
+        BelongingToDsl, ExpressionMethods, GroupedBy, OptionalExtension, QueryDsl, RunQueryDsl,
+    },
+};
 
+#[cfg(feature = "indexer")]
 const DEFAULT_ACCOUNT_ADDRESS: &str =
     "0x0000000000000000000000000000000000000000000000000000000000000000";
 
-#[derive(Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
-#[diesel(primary_key(version))]
-#[diesel(table_name = transactions)]
+/// Also built without the `indexer` feature, for consumers of the published messages
+#[derive(Debug, Deserialize, FieldCount, Serialize)]
+#[cfg_attr(feature = "indexer", derive(Identifiable, Insertable))]
+#[cfg_attr(feature = "indexer", diesel(primary_key(version)))]
+#[cfg_attr(feature = "indexer", diesel(table_name = transactions))]
 pub struct Transaction {
     pub version: i64,
     pub block_height: i64,
@@ -47,9 +53,12 @@ pub struct Transaction {
     pub num_events: i64,
     pub num_write_set_changes: i64,
     pub epoch: i64,
+    /// Of the transaction's block, null for genesis
+    pub block_timestamp: Option<chrono::NaiveDateTime>,
 }
 
 /// Need a separate struct for queryable because we don't want to define the inserted_at column (letting DB fill)
+#[cfg(feature = "indexer")]
 #[derive(Debug, Deserialize, Identifiable, Queryable, Serialize)]
 #[diesel(primary_key(version))]
 #[diesel(table_name = transactions)]
@@ -70,8 +79,10 @@ pub struct TransactionQuery {
     pub num_write_set_changes: i64,
     pub inserted_at: chrono::NaiveDateTime,
     pub epoch: i64,
+    pub block_timestamp: Option<chrono::NaiveDateTime>,
 }
 
+#[cfg(feature = "indexer")]
 impl Transaction {
     fn from_transaction_info(
         info: &TransactionInfo,
@@ -79,6 +90,7 @@ impl Transaction {
         type_: String,
         num_events: i64,
         block_height: i64,
+        block_timestamp: Option<chrono::NaiveDateTime>,
         epoch: i64,
     ) -> Self {
         Self {
@@ -97,6 +109,19 @@ impl Transaction {
             num_events,
             num_write_set_changes: info.changes.len() as i64,
             epoch,
+            block_timestamp,
+        }
+    }
+
+    /// Every transaction but genesis carries the timestamp of its block, so a transaction at the
+    /// start of a batch has it without the block metadata transaction
+    fn block_timestamp(transaction: &APITransaction) -> Option<chrono::NaiveDateTime> {
+        match transaction {
+            APITransaction::GenesisTransaction(_) => None,
+            _ => Some(parse_timestamp(
+                transaction.timestamp(),
+                transaction.version().unwrap() as i64,
+            )),
         }
     }
 
@@ -116,6 +141,7 @@ impl Transaction {
             .unwrap()
             .0 as i64;
         let epoch = transaction.transaction_info().unwrap().epoch.unwrap().0 as i64;
+        let block_timestamp = Self::block_timestamp(transaction);
         match transaction {
             APITransaction::UserTransaction(user_txn) => {
                 let (user_txn_output, signatures) =
@@ -135,6 +161,7 @@ impl Transaction {
                         transaction.type_str().to_string(),
                         user_txn.events.len() as i64,
                         block_height,
+                        block_timestamp,
                         epoch,
                     ),
                     Some(TransactionDetail::User(user_txn_output, signatures)),
@@ -142,6 +169,7 @@ impl Transaction {
                         &user_txn.events,
                         user_txn.info.version.0 as i64,
                         block_height,
+                        block_timestamp,
                     ),
                     wsc,
                     wsc_detail,
@@ -161,15 +189,17 @@ impl Transaction {
                                 .expect("Unable to deserialize Genesis transaction"),
                         ),
                         transaction.type_str().to_string(),
-                        0,
+                        genesis_txn.events.len() as i64,
                         block_height,
+                        block_timestamp,
                         epoch,
                     ),
-                    None,
+                    Some(TransactionDetail::Genesis),
                     EventModel::from_events(
                         &genesis_txn.events,
                         genesis_txn.info.version.0 as i64,
                         block_height,
+                        block_timestamp,
                     ),
                     wsc,
                     wsc_detail,
@@ -188,6 +218,7 @@ impl Transaction {
                         transaction.type_str().to_string(),
                         0,
                         block_height,
+                        block_timestamp,
                         epoch,
                     ),
                     Some(TransactionDetail::BlockMetadata(
@@ -200,6 +231,7 @@ impl Transaction {
                         &block_metadata_txn.events,
                         block_metadata_txn.info.version.0 as i64,
                         block_height,
+                        block_timestamp,
                     ),
                     wsc,
                     wsc_detail,
@@ -212,6 +244,7 @@ impl Transaction {
                     transaction.type_str().to_string(),
                     0,
                     block_height,
+                    block_timestamp,
                     epoch,
                 ),
                 None,
@@ -226,6 +259,7 @@ impl Transaction {
                     transaction.type_str().to_string(),
                     0,
                     block_height,
+                    block_timestamp,
                     epoch,
                 ),
                 None,
@@ -243,6 +277,7 @@ impl Transaction {
                     transaction.type_str().to_string(),
                     0,
                     block_height,
+                    block_timestamp,
                     epoch,
                 ),
                 None,
@@ -291,6 +326,7 @@ impl Transaction {
     }
 }
 
+#[cfg(feature = "indexer")]
 impl TransactionQuery {
     pub fn get_many_by_version(
         start_version: u64,
@@ -443,11 +479,253 @@ impl TransactionQuery {
     }
 }
 
+#[cfg(feature = "indexer")]
 #[derive(Deserialize, Serialize)]
 pub enum TransactionDetail {
     User(UserTransaction, Vec<Signature>),
     BlockMetadata(BlockMetadataTransaction),
+    /// Genesis has no table of its own, its framework events and initial write set are returned
+    /// with the events and write set changes of every other transaction
+    Genesis,
 }
 
 // Prevent conflicts with other things named `Transaction`
 pub type TransactionModel = Transaction;
+
+#[cfg(all(test, feature = "indexer"))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn genesis_transaction() -> APITransaction {
+        serde_json::from_value(json!({
+            "type": "genesis_transaction",
+            "version": "0",
+            "hash": format!("0x{:064x}", 0xa),
+            "state_change_hash": format!("0x{:064x}", 0),
+            "event_root_hash": format!("0x{:064x}", 0),
+            "gas_used": "0",
+            "block_height": "0",
+            "epoch": "0",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": format!("0x{:064x}", 0),
+            "changes": [{
+                "type": "write_resource",
+                "address": "0x1",
+                "state_key_hash": format!("0x{:064x}", 1),
+                "data": {
+                    "type": "0x1::chain_id::ChainId",
+                    "data": { "id": 4 }
+                }
+            }],
+            "payload": {
+                "type": "write_set_payload",
+                "write_set": {
+                    "type": "direct_write_set",
+                    "changes": [],
+                    "events": []
+                }
+            },
+            "events": [{
+                "guid": { "account_address": "0x1", "creation_number": "2" },
+                "sequence_number": "0",
+                "type": "0x1::reconfiguration::NewEpochEvent",
+                "data": { "epoch": "1" }
+            }]
+        }))
+        .unwrap()
+    }
+
+    fn state_checkpoint_transaction() -> APITransaction {
+        serde_json::from_value(json!({
+            "type": "state_checkpoint_transaction",
+            "version": "1",
+            "hash": format!("0x{:064x}", 0xb),
+            "state_change_hash": format!("0x{:064x}", 0),
+            "event_root_hash": format!("0x{:064x}", 0),
+            "state_checkpoint_hash": format!("0x{:064x}", 0xc),
+            "gas_used": "0",
+            "block_height": "0",
+            "epoch": "1",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": format!("0x{:064x}", 0),
+            "changes": [],
+            "timestamp": "1649713141723410"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_genesis_and_state_checkpoint() {
+        let (txns, details, events, wscs, wsc_details) = TransactionModel::from_transactions(&[
+            genesis_transaction(),
+            state_checkpoint_transaction(),
+        ]);
+
+        let versions = txns
+            .iter()
+            .map(|txn| (txn.version, txn.type_.as_str(), txn.hash.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(versions, vec![
+            (0, "genesis_transaction", format!("0x{:064x}", 0xa)),
+            (1, "state_checkpoint_transaction", format!("0x{:064x}", 0xb)),
+        ]);
+        assert_eq!(txns[0].num_events, 1);
+        assert_eq!(txns[0].num_write_set_changes, 1);
+        assert_eq!(
+            txns[1].state_checkpoint_hash,
+            Some(format!("0x{:064x}", 0xc))
+        );
+
+        assert!(matches!(details.as_slice(), [TransactionDetail::Genesis]));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].transaction_version, 0);
+        assert_eq!(events[0].type_, "0x1::reconfiguration::NewEpochEvent");
+        assert_eq!(wscs.len(), 1);
+        assert_eq!(wscs[0].transaction_version, 0);
+        assert_eq!(wsc_details.len(), 1);
+        assert_eq!(txns[0].block_timestamp, None);
+        assert_eq!(events[0].block_timestamp, None);
+        assert_eq!(
+            txns[1].block_timestamp,
+            Some(parse_timestamp(1_649_713_141_723_410, 1))
+        );
+    }
+
+    fn event(account: &str) -> serde_json::Value {
+        json!({
+            "guid": { "account_address": account, "creation_number": "2" },
+            "sequence_number": "0",
+            "type": "0x1::coin::DepositEvent",
+            "data": { "amount": "1" }
+        })
+    }
+
+    fn block_metadata_transaction(version: u64, block_height: u64) -> APITransaction {
+        serde_json::from_value(json!({
+            "type": "block_metadata_transaction",
+            "version": version.to_string(),
+            "block_height": block_height.to_string(),
+            "epoch": "1",
+            "hash": format!("0x{:064x}", version),
+            "state_change_hash": format!("0x{:064x}", 0),
+            "event_root_hash": format!("0x{:064x}", 0),
+            "gas_used": "0",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": format!("0x{:064x}", 0),
+            "id": format!("0x{:064x}", block_height),
+            "round": block_height.to_string(),
+            "failed_proposer_indices": [],
+            "previous_block_votes_bitvec": [],
+            "proposer": format!("0x{:064x}", 0xb0),
+            "timestamp": (1_649_713_000_000_000 + block_height).to_string(),
+            "events": [event("0x1")],
+            "changes": []
+        }))
+        .unwrap()
+    }
+
+    /// In the block at `block_height`, with its timestamp
+    fn user_transaction(version: u64, block_height: u64) -> APITransaction {
+        let account = format!("0x{:064x}", 0xabcd);
+        serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": version.to_string(),
+            "block_height": block_height.to_string(),
+            "epoch": "1",
+            "hash": format!("0x{:064x}", version),
+            "state_change_hash": format!("0x{:064x}", 0),
+            "event_root_hash": format!("0x{:064x}", 0),
+            "gas_used": "43",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": format!("0x{:064x}", 0),
+            "sender": account,
+            "sequence_number": version.to_string(),
+            "max_gas_amount": "1000",
+            "gas_unit_price": "1",
+            "expiration_timestamp_secs": "1649713172",
+            "payload": {
+                "type": "entry_function_payload",
+                "function": "0x1::aptos_account::transfer",
+                "type_arguments": [],
+                "arguments": []
+            },
+            "signature": {
+                "type": "ed25519_signature",
+                "public_key": "0x14ff6646855dad4a2dab30db773cdd4b22d6f9e6813f3e50142adf4f3efcf9f8",
+                "signature": "0x70781112e78cc8b54b86805c016cef2478bccdef21b721542af0323276ab906c989172adffed5bf2f475f2ec3a5b284a0ac46a6aef0d79f0dbb6b85bfca0080a"
+            },
+            "events": [event(&account), event(&account)],
+            "timestamp": (1_649_713_000_000_000 + block_height).to_string(),
+            "changes": []
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_block_height_and_timestamp() {
+        // The first transaction's block metadata transaction was in the previous batch
+        let (txns, _, events, _, _) = TransactionModel::from_transactions(&[
+            user_transaction(10, 5),
+            block_metadata_transaction(11, 6),
+            user_transaction(12, 6),
+            block_metadata_transaction(13, 7),
+            user_transaction(14, 7),
+        ]);
+        let block = |version: i64| match version {
+            10 => 5,
+            11 | 12 => 6,
+            _ => 7,
+        };
+        let timestamp = |block_height: i64| {
+            Some(parse_timestamp(
+                1_649_713_000_000_000 + block_height as u64,
+                0,
+            ))
+        };
+
+        for txn in &txns {
+            assert_eq!(txn.block_height, block(txn.version));
+            assert_eq!(txn.block_timestamp, timestamp(txn.block_height));
+        }
+        assert_eq!(events.len(), 8);
+        for event in &events {
+            assert_eq!(
+                event.transaction_block_height,
+                block(event.transaction_version)
+            );
+            assert_eq!(
+                event.block_timestamp,
+                timestamp(event.transaction_block_height)
+            );
+        }
+    }
+
+    #[test]
+    fn test_u64_max_gas() {
+        let mut txn = serde_json::to_value(user_transaction(10, 5)).unwrap();
+        for field in ["gas_used", "max_gas_amount", "gas_unit_price"] {
+            txn[field] = json!(u64::MAX.to_string());
+        }
+        let txn: APITransaction = serde_json::from_value(txn).unwrap();
+        let (txns, details, ..) = TransactionModel::from_transactions(&[txn]);
+        let [TransactionDetail::User(user_txn, _)] = details.as_slice() else {
+            unreachable!()
+        };
+        let u64_max = BigDecimal::from(u64::MAX);
+        assert_eq!(txns[0].gas_used, u64_max);
+        assert_eq!(user_txn.max_gas_amount, u64_max);
+        assert_eq!(user_txn.gas_unit_price, u64_max);
+
+        // Published as decimal strings, which floats would round
+        let published = serde_json::to_value(&txns[0]).unwrap();
+        assert_eq!(published["gas_used"], json!("18446744073709551615"));
+        let published = serde_json::to_value(user_txn).unwrap();
+        assert_eq!(published["max_gas_amount"], json!("18446744073709551615"));
+        assert_eq!(published["gas_unit_price"], json!("18446744073709551615"));
+    }
+}
@@ -6,17 +6,31 @@
 #![allow(clippy::unused_unit)]
 
 use super::{
+    scripts::Script,
     signatures::Signature,
     transactions::{Transaction, TransactionQuery},
 };
 use crate::{
+    custom::driver::{
+        config::OversizeAction,
+        row_limits::{ColumnLimit, RowLimits, INDEXED_COLUMN_MAX_BYTES},
+    },
     schema::user_transactions,
     util::{parse_timestamp, parse_timestamp_secs, standardize_address, u64_to_bigdecimal},
 };
-use aptos_api_types::{TransactionPayload, UserTransaction as APIUserTransaction};
+use aptos_api_types::{
+    EntryFunctionPayload, MoveType, MultisigTransactionPayload, TransactionPayload,
+    UserTransaction as APIUserTransaction,
+};
 use bigdecimal::BigDecimal;
 use field_count::FieldCount;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// `entry_function_id_str` of script payloads
+pub const SCRIPT_ENTRY_FUNCTION_ID: &str = "script";
+/// `entry_function_id_str` of multisig payloads, whose entry function is in the other columns
+pub const MULTISIG_ENTRY_FUNCTION_ID: &str = "multisig";
 
 #[derive(
     Associations, Clone, Deserialize, Debug, FieldCount, Identifiable, Insertable, Serialize,
@@ -34,8 +48,22 @@ pub struct UserTransaction {
     pub expiration_timestamp_secs: chrono::NaiveDateTime,
     pub gas_unit_price: BigDecimal,
     pub timestamp: chrono::NaiveDateTime,
+    /// `address::module::function` for entry function payloads, `script` or `multisig` for those
+    /// payloads, empty for the others
     pub entry_function_id_str: String,
     pub epoch: i64,
+    /// Hash into `scripts` for script payloads
+    pub script_hash: Option<String>,
+    /// Module of the entry function called, e.g. `0x1::aptos_account`, also set for the entry
+    /// function a multisig payload executes
+    pub entry_function_module: Option<String>,
+    /// Name of the entry function called, e.g. `transfer`
+    pub entry_function_name: Option<String>,
+    /// Type arguments of the entry function or script as a JSON array of strings, e.g.
+    /// `["0x1::aptos_coin::AptosCoin"]`
+    pub entry_function_type_arguments: Value,
+    /// Arguments of the entry function or script as a JSON array, as the API returns them
+    pub entry_function_arguments: Value,
 }
 
 /// Need a separate struct for queryable because we don't want to define the inserted_at column (letting DB fill)
@@ -56,6 +84,76 @@ pub struct UserTransactionQuery {
     pub entry_function_id_str: String,
     pub inserted_at: chrono::NaiveDateTime,
     pub epoch: i64,
+    pub script_hash: Option<String>,
+    pub entry_function_module: Option<String>,
+    pub entry_function_name: Option<String>,
+    pub entry_function_type_arguments: Value,
+    pub entry_function_arguments: Value,
+}
+
+/// The entry function columns of a payload
+struct EntryFunctionColumns {
+    id_str: String,
+    module: Option<String>,
+    name: Option<String>,
+    type_arguments: Value,
+    arguments: Value,
+}
+
+impl EntryFunctionColumns {
+    fn from_payload(payload: &TransactionPayload) -> Self {
+        match payload {
+            TransactionPayload::EntryFunctionPayload(payload) => Self::from_entry_function(payload),
+            TransactionPayload::ScriptPayload(payload) => Self {
+                id_str: SCRIPT_ENTRY_FUNCTION_ID.to_string(),
+                module: None,
+                name: None,
+                type_arguments: type_arguments(&payload.type_arguments),
+                arguments: Value::from(payload.arguments.clone()),
+            },
+            TransactionPayload::MultisigPayload(payload) => {
+                let call = match &payload.transaction_payload {
+                    Some(MultisigTransactionPayload::EntryFunctionPayload(payload)) => {
+                        Self::from_entry_function(payload)
+                    },
+                    // Executing a transaction proposed earlier, whose payload is only on chain
+                    _ => Self::none(),
+                };
+                Self {
+                    id_str: MULTISIG_ENTRY_FUNCTION_ID.to_string(),
+                    ..call
+                }
+            },
+            _ => Self::none(),
+        }
+    }
+
+    fn from_entry_function(payload: &EntryFunctionPayload) -> Self {
+        Self {
+            id_str: payload.function.to_string(),
+            module: Some(payload.function.module.to_string()),
+            name: Some(payload.function.name.to_string()),
+            type_arguments: type_arguments(&payload.type_arguments),
+            arguments: Value::from(payload.arguments.clone()),
+        }
+    }
+
+    fn none() -> Self {
+        Self {
+            id_str: String::default(),
+            module: None,
+            name: None,
+            type_arguments: Value::Array(vec![]),
+            arguments: Value::Array(vec![]),
+        }
+    }
+}
+
+fn type_arguments(type_arguments: &[MoveType]) -> Value {
+    type_arguments
+        .iter()
+        .map(|type_argument| Value::from(type_argument.to_string()))
+        .collect()
 }
 
 impl UserTransaction {
@@ -65,6 +163,7 @@ impl UserTransaction {
         epoch: i64,
     ) -> (Self, Vec<Signature>) {
         let version = txn.info.version.0 as i64;
+        let call = EntryFunctionColumns::from_payload(&txn.request.payload);
         (
             Self {
                 version,
@@ -84,13 +183,13 @@ impl UserTransaction {
                 ),
                 gas_unit_price: u64_to_bigdecimal(txn.request.gas_unit_price.0),
                 timestamp: parse_timestamp(txn.timestamp.0, version),
-                entry_function_id_str: match &txn.request.payload {
-                    TransactionPayload::EntryFunctionPayload(payload) => {
-                        payload.function.to_string()
-                    },
-                    _ => String::default(),
-                },
+                entry_function_id_str: call.id_str,
                 epoch,
+                script_hash: Script::get_script_hash(&txn.request.payload),
+                entry_function_module: call.module,
+                entry_function_name: call.name,
+                entry_function_type_arguments: call.type_arguments,
+                entry_function_arguments: call.arguments,
             },
             Self::get_signatures(txn, version, block_height),
         )
@@ -118,5 +217,133 @@ impl UserTransaction {
     }
 }
 
+impl RowLimits for UserTransaction {
+    const LIMITS: &'static [ColumnLimit] = &[ColumnLimit::new(
+        "entry_function_id_str",
+        INDEXED_COLUMN_MAX_BYTES,
+        OversizeAction::Truncate,
+    )];
+    const TABLE: &'static str = "user_transactions";
+
+    fn transaction_version(&self) -> i64 {
+        self.version
+    }
+
+    fn column(&self, _index: usize) -> &str {
+        &self.entry_function_id_str
+    }
+
+    fn column_mut(&mut self, _index: usize) -> &mut String {
+        &mut self.entry_function_id_str
+    }
+}
+
 // Prevent conflicts with other things named `Transaction`
 pub type UserTransactionModel = UserTransaction;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::clean_data_for_db;
+    use aptos_api_types::Transaction as APITransaction;
+    use serde_json::json;
+
+    fn user_transaction(payload: Value) -> UserTransaction {
+        let txn: APITransaction = serde_json::from_value(json!({
+            "type": "user_transaction",
+            "version": "42",
+            "hash": format!("0x{:064x}", 42),
+            "state_change_hash": format!("0x{:064x}", 0),
+            "event_root_hash": format!("0x{:064x}", 0),
+            "gas_used": "43",
+            "success": true,
+            "vm_status": "Executed successfully",
+            "accumulator_root_hash": format!("0x{:064x}", 0),
+            "sender": format!("0x{:064x}", 0xabcd),
+            "sequence_number": "7",
+            "max_gas_amount": "1000",
+            "gas_unit_price": "100",
+            "expiration_timestamp_secs": "1649713172",
+            "payload": payload,
+            "signature": {
+                "type": "ed25519_signature",
+                "public_key": "0x14ff6646855dad4a2dab30db773cdd4b22d6f9e6813f3e50142adf4f3efcf9f8",
+                "signature": "0x70781112e78cc8b54b86805c016cef2478bccdef21b721542af0323276ab906c989172adffed5bf2f475f2ec3a5b284a0ac46a6aef0d79f0dbb6b85bfca0080a"
+            },
+            "events": [],
+            "timestamp": "1649713141723410",
+            "changes": []
+        }))
+        .unwrap();
+        let APITransaction::UserTransaction(txn) = txn else {
+            unreachable!()
+        };
+        UserTransaction::from_transaction(&txn, 100, 1).0
+    }
+
+    fn entry_function_payload(function: &str) -> Value {
+        json!({
+            "type": "entry_function_payload",
+            "function": function,
+            "type_arguments": ["0x1::aptos_coin::AptosCoin"],
+            "arguments": ["0xcafe", "10"]
+        })
+    }
+
+    #[test]
+    fn test_entry_function_columns() {
+        let txn = user_transaction(entry_function_payload("0x1::aptos_account::transfer"));
+        assert_eq!(txn.entry_function_id_str, "0x1::aptos_account::transfer");
+        assert_eq!(
+            txn.entry_function_module.as_deref(),
+            Some("0x1::aptos_account")
+        );
+        assert_eq!(txn.entry_function_name.as_deref(), Some("transfer"));
+        assert_eq!(
+            txn.entry_function_type_arguments,
+            json!(["0x1::aptos_coin::AptosCoin"])
+        );
+        assert_eq!(txn.entry_function_arguments, json!(["0xcafe", "10"]));
+
+        let txn = user_transaction(json!({
+            "type": "script_payload",
+            "code": { "bytecode": "0xa11ceb0b" },
+            "type_arguments": [],
+            "arguments": ["1"]
+        }));
+        assert_eq!(txn.entry_function_id_str, SCRIPT_ENTRY_FUNCTION_ID);
+        assert_eq!(txn.entry_function_module, None);
+        assert_eq!(txn.entry_function_type_arguments, json!([]));
+        assert_eq!(txn.entry_function_arguments, json!(["1"]));
+
+        let txn = user_transaction(json!({
+            "type": "multisig_payload",
+            "multisig_address": "0xbeef",
+            "transaction_payload": entry_function_payload("0x1::coin::transfer")
+        }));
+        assert_eq!(txn.entry_function_id_str, MULTISIG_ENTRY_FUNCTION_ID);
+        assert_eq!(txn.entry_function_module.as_deref(), Some("0x1::coin"));
+        assert_eq!(txn.entry_function_arguments, json!(["0xcafe", "10"]));
+        // Executing a transaction proposed earlier
+        let txn = user_transaction(json!({
+            "type": "multisig_payload",
+            "multisig_address": "0xbeef"
+        }));
+        assert_eq!(txn.entry_function_id_str, MULTISIG_ENTRY_FUNCTION_ID);
+        assert_eq!(txn.entry_function_name, None);
+        assert_eq!(txn.entry_function_arguments, json!([]));
+    }
+
+    #[test]
+    fn test_clean_data_for_db() {
+        let mut payload = entry_function_payload("0x1::message::set_message");
+        payload["arguments"] = json!(["hi\u{0000}there"]);
+        let txns = clean_data_for_db(vec![user_transaction(payload)], true);
+        assert_eq!(txns[0].entry_function_arguments, json!(["hithere"]));
+        assert_eq!(txns[0].entry_function_name.as_deref(), Some("set_message"));
+        assert_eq!(
+            txns[0].entry_function_type_arguments,
+            json!(["0x1::aptos_coin::AptosCoin"])
+        );
+    }
+}
@@ -10,6 +10,7 @@ use super::token_models::{
     v2_token_utils::ObjectWithMetadata,
 };
 use crate::{
+    custom::driver::retry_budget::{self, ErrorClass},
     database::PgPoolConnection,
     models::move_resources::MoveResource,
     schema::{current_objects, objects},
@@ -49,6 +50,9 @@ pub struct CurrentObject {
     pub last_guid_creation_num: BigDecimal,
     pub last_transaction_version: i64,
     pub is_deleted: bool,
+    /// Resolved by `custom_object_processor`, see `models::object_ownership`
+    pub ultimate_owner: Option<String>,
+    pub ownership_depth: Option<i64>,
 }
 
 #[derive(Debug, Deserialize, Identifiable, Queryable, Serialize)]
@@ -63,6 +67,8 @@ pub struct CurrentObjectQuery {
     pub last_transaction_version: i64,
     pub is_deleted: bool,
     pub inserted_at: chrono::NaiveDateTime,
+    pub ultimate_owner: Option<String>,
+    pub ownership_depth: Option<i64>,
 }
 
 impl Object {
@@ -98,6 +104,8 @@ impl Object {
                     last_guid_creation_num: object_core.guid_creation_num.clone(),
                     last_transaction_version: txn_version,
                     is_deleted: false,
+                    ultimate_owner: None,
+                    ownership_depth: None,
                 },
             )))
         } else {
@@ -156,6 +164,8 @@ impl Object {
                     allow_ungated_transfer: previous_object.allow_ungated_transfer,
                     last_transaction_version: txn_version,
                     is_deleted: true,
+                    ultimate_owner: None,
+                    ownership_depth: None,
                 },
             )))
         } else {
@@ -172,18 +182,13 @@ impl Object {
         while retried < QUERY_RETRIES {
             retried += 1;
             match CurrentObjectQuery::get_by_address(object_address, conn) {
-                Ok(res) => {
-                    return Ok(CurrentObject {
-                        object_address: res.object_address,
-                        owner_address: res.owner_address,
-                        state_key_hash: res.state_key_hash,
-                        allow_ungated_transfer: res.allow_ungated_transfer,
-                        last_guid_creation_num: res.last_guid_creation_num,
-                        last_transaction_version: res.last_transaction_version,
-                        is_deleted: res.is_deleted,
-                    })
-                },
-                Err(_) => {
+                Ok(res) => return Ok(res.into()),
+                Err(err) => {
+                    if let Err(exhausted) =
+                        retry_budget::retry(ErrorClass::DbQuery, "get object owner", &err)
+                    {
+                        return Err(exhausted.into());
+                    }
                     std::thread::sleep(std::time::Duration::from_millis(QUERY_RETRY_DELAY_MS));
                 },
             }
@@ -192,6 +197,22 @@ impl Object {
     }
 }
 
+impl From<CurrentObjectQuery> for CurrentObject {
+    fn from(res: CurrentObjectQuery) -> Self {
+        Self {
+            object_address: res.object_address,
+            owner_address: res.owner_address,
+            state_key_hash: res.state_key_hash,
+            allow_ungated_transfer: res.allow_ungated_transfer,
+            last_guid_creation_num: res.last_guid_creation_num,
+            last_transaction_version: res.last_transaction_version,
+            is_deleted: res.is_deleted,
+            ultimate_owner: res.ultimate_owner,
+            ownership_depth: res.ownership_depth,
+        }
+    }
+}
+
 impl CurrentObjectQuery {
     /// TODO: Change this to a KV store
     pub fn get_by_address(
@@ -202,4 +223,13 @@ impl CurrentObjectQuery {
             .filter(current_objects::object_address.eq(object_address))
             .first::<Self>(conn)
     }
+
+    pub fn get_by_addresses(
+        object_addresses: &[String],
+        conn: &mut PgPoolConnection,
+    ) -> diesel::QueryResult<Vec<Self>> {
+        current_objects::table
+            .filter(current_objects::object_address.eq_any(object_addresses))
+            .load::<Self>(conn)
+    }
 }
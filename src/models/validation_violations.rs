@@ -0,0 +1,27 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+// This is required because a diesel macro makes clippy sad
+#![allow(clippy::extra_unused_lifetimes)]
+
+use crate::schema::validation_violations;
+use field_count::FieldCount;
+use serde::{Deserialize, Serialize};
+
+/// A row a processor was about to commit that broke one of its rules, see
+/// `custom::driver::validation`
+#[derive(Clone, Debug, Deserialize, FieldCount, Insertable, Serialize)]
+#[diesel(table_name = validation_violations)]
+pub struct ValidationViolation {
+    pub processor: String,
+    pub rule_name: String,
+    pub start_version: i64,
+    /// Inclusive
+    pub end_version: i64,
+    /// Position among the violations of the rule in the batch
+    pub violation_index: i64,
+    pub policy: String,
+    pub transaction_version: Option<i64>,
+    pub message: String,
+    pub details: serde_json::Value,
+}
@@ -14,7 +14,9 @@ use aptos_api_types::WriteSetChange as APIWriteSetChange;
 use field_count::FieldCount;
 use serde::{Deserialize, Serialize};
 
-#[derive(Associations, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize)]
+#[derive(
+    Associations, Clone, Debug, Deserialize, FieldCount, Identifiable, Insertable, Serialize,
+)]
 #[diesel(belongs_to(Transaction, foreign_key = transaction_version))]
 #[diesel(primary_key(transaction_version, index))]
 #[diesel(table_name = write_set_changes)]
@@ -22,7 +24,7 @@ pub struct WriteSetChange {
     pub transaction_version: i64,
     pub index: i64,
     pub hash: String,
-    transaction_block_height: i64,
+    pub transaction_block_height: i64,
     pub type_: String,
     pub address: String,
 }
@@ -135,6 +137,7 @@ impl WriteSetChange {
                         ti,
                         cti,
                         Some(TableMetadata::from_write_table_item(table_item)),
+                        false,
                     ),
                 )
             },
@@ -154,7 +157,7 @@ impl WriteSetChange {
                         address: String::default(),
                         index,
                     },
-                    WriteSetChangeDetail::Table(ti, cti, None),
+                    WriteSetChangeDetail::Table(ti, cti, None, true),
                 )
             },
         }
@@ -197,7 +200,9 @@ impl WriteSetChange {
 pub enum WriteSetChangeDetail {
     Module(MoveModule),
     Resource(MoveResource),
-    Table(TableItem, CurrentTableItem, Option<TableMetadata>),
+    /// Whether the change is a `DeleteTableItem`, whose rows are tombstones: `is_deleted` and
+    /// without a decoded value
+    Table(TableItem, CurrentTableItem, Option<TableMetadata>, bool),
 }
 
 // Prevent conflicts with other things named `WriteSetChange`
@@ -2,8 +2,10 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
+    custom::driver::backfill_guard::{self, OverwritePolicy},
     database::{
-        clean_data_for_db, execute_with_better_error, get_chunks, PgDbPool, PgPoolConnection,
+        clean_data_for_db, execute_with_better_error, get_chunks, CurrentRowUpsert, GuardedUpsert,
+        PgDbPool, PgPoolConnection,
     },
     indexer::{
         errors::TransactionProcessingError, processing_result::ProcessingResult,
@@ -49,6 +51,7 @@ impl Debug for CoinTransactionProcessor {
 
 fn insert_to_db_impl(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     coin_activities: &[CoinActivity],
     coin_infos: &[CoinInfo],
     coin_balances: &[CoinBalance],
@@ -57,9 +60,9 @@ fn insert_to_db_impl(
     account_transactions: &[AccountTransaction],
 ) -> Result<(), diesel::result::Error> {
     insert_coin_activities(conn, coin_activities)?;
-    insert_coin_infos(conn, coin_infos)?;
+    insert_coin_infos(conn, policy, coin_infos)?;
     insert_coin_balances(conn, coin_balances)?;
-    insert_current_coin_balances(conn, current_coin_balances)?;
+    insert_current_coin_balances(conn, policy, current_coin_balances)?;
     insert_coin_supply(conn, coin_supply)?;
     insert_account_transactions(conn, account_transactions)?;
     Ok(())
@@ -70,6 +73,7 @@ fn insert_to_db(
     name: &'static str,
     start_version: u64,
     end_version: u64,
+    policy: OverwritePolicy,
     coin_activities: Vec<CoinActivity>,
     coin_infos: Vec<CoinInfo>,
     coin_balances: Vec<CoinBalance>,
@@ -89,6 +93,7 @@ fn insert_to_db(
         .run::<_, Error, _>(|pg_conn| {
             insert_to_db_impl(
                 pg_conn,
+                policy,
                 &coin_activities,
                 &coin_infos,
                 &coin_balances,
@@ -111,6 +116,7 @@ fn insert_to_db(
 
                 insert_to_db_impl(
                     pg_conn,
+                    policy,
                     &coin_activities,
                     &coin_infos,
                     &coin_balances,
@@ -149,31 +155,37 @@ fn insert_coin_activities(
 
 fn insert_coin_infos(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     item_to_insert: &[CoinInfo],
 ) -> Result<(), diesel::result::Error> {
     use schema::coin_infos::dsl::*;
 
     let chunks = get_chunks(item_to_insert.len(), CoinInfo::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
-            conn,
-            diesel::insert_into(schema::coin_infos::table)
-                .values(&item_to_insert[start_ind..end_ind])
-                .on_conflict(coin_type_hash)
-                .do_update()
-                .set((
-                    transaction_version_created.eq(excluded(transaction_version_created)),
-                    creator_address.eq(excluded(creator_address)),
-                    name.eq(excluded(name)),
-                    symbol.eq(excluded(symbol)),
-                    decimals.eq(excluded(decimals)),
-                    transaction_created_timestamp.eq(excluded(transaction_created_timestamp)),
-                    supply_aggregator_table_handle.eq(excluded(supply_aggregator_table_handle)),
-                    supply_aggregator_table_key.eq(excluded(supply_aggregator_table_key)),
-                    inserted_at.eq(excluded(inserted_at)),
-                )),
-            Some(" WHERE coin_infos.transaction_version_created >= EXCLUDED.transaction_version_created "),
-        )?;
+        GuardedUpsert::new("coin_infos", policy)
+            .skip_unless(
+                " WHERE coin_infos.transaction_version_created > EXCLUDED.transaction_version_created ",
+            )
+            .execute(
+                conn,
+                diesel::insert_into(schema::coin_infos::table)
+                    .values(&item_to_insert[start_ind..end_ind])
+                    .on_conflict(coin_type_hash)
+                    .do_update()
+                    .set((
+                        transaction_version_created.eq(excluded(transaction_version_created)),
+                        creator_address.eq(excluded(creator_address)),
+                        name.eq(excluded(name)),
+                        symbol.eq(excluded(symbol)),
+                        decimals.eq(excluded(decimals)),
+                        transaction_created_timestamp.eq(excluded(transaction_created_timestamp)),
+                        supply_aggregator_table_handle.eq(excluded(supply_aggregator_table_handle)),
+                        supply_aggregator_table_key.eq(excluded(supply_aggregator_table_key)),
+                        inserted_at.eq(excluded(inserted_at)),
+                    )),
+                Some(" WHERE coin_infos.transaction_version_created >= EXCLUDED.transaction_version_created "),
+                &item_to_insert[start_ind..end_ind],
+            )?;
     }
     Ok(())
 }
@@ -200,13 +212,14 @@ fn insert_coin_balances(
 
 fn insert_current_coin_balances(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     item_to_insert: &[CurrentCoinBalance],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_coin_balances::dsl::*;
 
     let chunks = get_chunks(item_to_insert.len(), CurrentCoinBalance::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_coin_balances", policy).execute(
             conn,
             diesel::insert_into(schema::current_coin_balances::table)
                 .values(&item_to_insert[start_ind..end_ind])
@@ -218,7 +231,7 @@ fn insert_current_coin_balances(
                     last_transaction_timestamp.eq(excluded(last_transaction_timestamp)),
                     inserted_at.eq(excluded(inserted_at)),
                 )),
-                Some(" WHERE current_coin_balances.last_transaction_version <= excluded.last_transaction_version "),
+            &item_to_insert[start_ind..end_ind],
             )?;
     }
     Ok(())
@@ -276,7 +289,14 @@ impl TransactionProcessor for CoinTransactionProcessor {
         start_version: u64,
         end_version: u64,
     ) -> Result<ProcessingResult, TransactionProcessingError> {
-        let mut conn = self.get_conn();
+        let mut conn = self.get_conn().map_err(|err| {
+            TransactionProcessingError::ConnectionPoolError((
+                err,
+                start_version,
+                end_version,
+                self.name(),
+            ))
+        })?;
         // get aptos_coin info for supply tracking
         // TODO: This only needs to be fetched once. Need to persist somehow
         let maybe_aptos_coin_info =
@@ -333,6 +353,7 @@ impl TransactionProcessor for CoinTransactionProcessor {
             self.name(),
             start_version,
             end_version,
+            backfill_guard::policy(self.name(), start_version, end_version),
             all_coin_activities,
             all_coin_infos,
             all_coin_balances,
@@ -355,7 +376,7 @@ impl TransactionProcessor for CoinTransactionProcessor {
         }
     }
 
-    fn connection_pool(&self) -> &PgDbPool {
-        &self.connection_pool
+    fn connection_pool(&self) -> Option<&PgDbPool> {
+        Some(&self.connection_pool)
     }
 }
@@ -2,8 +2,10 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
+    custom::driver::backfill_guard::{self, OverwritePolicy},
     database::{
-        clean_data_for_db, execute_with_better_error, get_chunks, PgDbPool, PgPoolConnection,
+        clean_data_for_db, execute_with_better_error, get_chunks, CurrentRowUpsert, GuardedUpsert,
+        PgDbPool, PgPoolConnection,
     },
     indexer::{
         errors::TransactionProcessingError, processing_result::ProcessingResult,
@@ -22,6 +24,7 @@ use crate::{
         write_set_changes::{WriteSetChangeDetail, WriteSetChangeModel},
     },
     schema,
+    util::sort_key::sort_by_pk,
 };
 use aptos_api_types::{Transaction, WriteSetChange};
 use async_trait::async_trait;
@@ -53,6 +56,7 @@ impl Debug for DefaultTransactionProcessor {
 
 fn insert_to_db_impl(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     txns: &[TransactionModel],
     txn_details: (
         &[UserTransactionModel],
@@ -78,15 +82,15 @@ fn insert_to_db_impl(
     insert_user_transactions(conn, user_transactions)?;
     insert_signatures(conn, signatures)?;
     insert_block_metadata_transactions(conn, block_metadata_transactions)?;
-    insert_events(conn, events)?;
+    insert_events(conn, policy, events)?;
     insert_write_set_changes(conn, wscs)?;
     insert_move_modules(conn, move_modules)?;
-    insert_move_resources(conn, move_resources)?;
+    insert_move_resources(conn, policy, move_resources)?;
     insert_table_items(conn, table_items)?;
-    insert_current_table_items(conn, current_table_items)?;
+    insert_current_table_items(conn, policy, current_table_items)?;
     insert_table_metadata(conn, table_metadata)?;
     insert_objects(conn, objects)?;
-    insert_current_objects(conn, current_objects)?;
+    insert_current_objects(conn, policy, current_objects)?;
     Ok(())
 }
 
@@ -95,6 +99,7 @@ fn insert_to_db(
     name: &'static str,
     start_version: u64,
     end_version: u64,
+    policy: OverwritePolicy,
     txns: Vec<TransactionModel>,
     txn_details: (
         Vec<UserTransactionModel>,
@@ -128,6 +133,7 @@ fn insert_to_db(
         .run::<_, Error, _>(|pg_conn| {
             insert_to_db_impl(
                 pg_conn,
+                policy,
                 &txns,
                 (
                     &user_transactions,
@@ -167,6 +173,7 @@ fn insert_to_db(
                 .run::<_, Error, _>(|pg_conn| {
                     insert_to_db_impl(
                         pg_conn,
+                        policy,
                         &txns,
                         (
                             &user_transactions,
@@ -275,12 +282,13 @@ fn insert_block_metadata_transactions(
 
 fn insert_events(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[EventModel],
 ) -> Result<(), diesel::result::Error> {
     use schema::events::dsl::*;
     let chunks = get_chunks(items_to_insert.len(), EventModel::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        GuardedUpsert::new("events", policy).execute(
             conn,
             diesel::insert_into(schema::events::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -289,8 +297,14 @@ fn insert_events(
                 .set((
                     inserted_at.eq(excluded(inserted_at)),
                     event_index.eq(excluded(event_index)),
+                    event_account_address.eq(excluded(event_account_address)),
+                    event_module.eq(excluded(event_module)),
+                    event_name.eq(excluded(event_name)),
+                    event_type_params.eq(excluded(event_type_params)),
+                    block_timestamp.eq(excluded(block_timestamp)),
                 )),
             None,
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -336,12 +350,13 @@ fn insert_move_modules(
 
 fn insert_move_resources(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[MoveResource],
 ) -> Result<(), diesel::result::Error> {
     use schema::move_resources::dsl::*;
     let chunks = get_chunks(items_to_insert.len(), MoveResource::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        GuardedUpsert::new("move_resources", policy).execute(
             conn,
             diesel::insert_into(schema::move_resources::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -352,6 +367,7 @@ fn insert_move_resources(
                     state_key_hash.eq(excluded(state_key_hash)),
                 )),
             None,
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -378,12 +394,13 @@ fn insert_table_items(
 
 fn insert_current_table_items(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentTableItem],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_table_items::dsl::*;
     let chunks = get_chunks(items_to_insert.len(), CurrentTableItem::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_table_items", policy).execute(
             conn,
             diesel::insert_into(schema::current_table_items::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -397,7 +414,7 @@ fn insert_current_table_items(
                     last_transaction_version.eq(excluded(last_transaction_version)),
                     inserted_at.eq(excluded(inserted_at)),
                 )),
-                Some(" WHERE current_table_items.last_transaction_version <= excluded.last_transaction_version "),
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -443,12 +460,13 @@ fn insert_objects(
 
 fn insert_current_objects(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentObject],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_objects::dsl::*;
     let chunks = get_chunks(items_to_insert.len(), CurrentObject::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_objects", policy).execute(
             conn,
             diesel::insert_into(schema::current_objects::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -463,7 +481,7 @@ fn insert_current_objects(
                     is_deleted.eq(excluded(is_deleted)),
                     inserted_at.eq(excluded(inserted_at)),
                 )),
-                Some(" WHERE current_objects.last_transaction_version <= excluded.last_transaction_version "),
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -481,7 +499,14 @@ impl TransactionProcessor for DefaultTransactionProcessor {
         start_version: u64,
         end_version: u64,
     ) -> Result<ProcessingResult, TransactionProcessingError> {
-        let mut conn = self.get_conn();
+        let mut conn = self.get_conn().map_err(|err| {
+            TransactionProcessingError::ConnectionPoolError((
+                err,
+                start_version,
+                end_version,
+                self.name(),
+            ))
+        })?;
 
         let (txns, txn_details, events, write_set_changes, wsc_details) =
             TransactionModel::from_transactions(&transactions);
@@ -498,6 +523,7 @@ impl TransactionProcessor for DefaultTransactionProcessor {
                 TransactionDetail::BlockMetadata(bmt) => {
                     block_metadata_transactions.push(bmt.clone())
                 },
+                TransactionDetail::Genesis => {},
             }
         }
         let mut move_modules = vec![];
@@ -509,7 +535,7 @@ impl TransactionProcessor for DefaultTransactionProcessor {
             match detail {
                 WriteSetChangeDetail::Module(module) => move_modules.push(module.clone()),
                 WriteSetChangeDetail::Resource(resource) => move_resources.push(resource.clone()),
-                WriteSetChangeDetail::Table(item, current_item, metadata) => {
+                WriteSetChangeDetail::Table(item, current_item, metadata, _) => {
                     table_items.push(item.clone());
                     current_table_items.insert(
                         (
@@ -585,8 +611,7 @@ impl TransactionProcessor for DefaultTransactionProcessor {
             .collect::<Vec<CurrentObject>>();
 
         // Sort by PK
-        current_table_items
-            .sort_by(|a, b| (&a.table_handle, &a.key_hash).cmp(&(&b.table_handle, &b.key_hash)));
+        sort_by_pk(&mut current_table_items);
         table_metadata.sort_by(|a, b| a.handle.cmp(&b.handle));
         all_current_objects.sort_by(|a, b| a.object_address.cmp(&b.object_address));
 
@@ -595,6 +620,7 @@ impl TransactionProcessor for DefaultTransactionProcessor {
             self.name(),
             start_version,
             end_version,
+            backfill_guard::policy(self.name(), start_version, end_version),
             txns,
             (user_transactions, signatures, block_metadata_transactions),
             events,
@@ -623,7 +649,7 @@ impl TransactionProcessor for DefaultTransactionProcessor {
         }
     }
 
-    fn connection_pool(&self) -> &PgDbPool {
-        &self.connection_pool
+    fn connection_pool(&self) -> Option<&PgDbPool> {
+        Some(&self.connection_pool)
     }
 }
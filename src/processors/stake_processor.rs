@@ -2,8 +2,10 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
+    custom::driver::backfill_guard::{self, OverwritePolicy},
     database::{
-        clean_data_for_db, execute_with_better_error, get_chunks, PgDbPool, PgPoolConnection,
+        clean_data_for_db, execute_with_better_error, get_chunks, CurrentRowUpsert, GuardedUpsert,
+        PgDbPool, PgPoolConnection,
     },
     indexer::{
         errors::TransactionProcessingError, processing_result::ProcessingResult,
@@ -50,6 +52,7 @@ impl Debug for StakeTransactionProcessor {
 
 fn insert_to_db_impl(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     current_stake_pool_voters: &[CurrentStakingPoolVoter],
     proposal_votes: &[ProposalVote],
     delegator_actvities: &[DelegatedStakingActivity],
@@ -58,13 +61,13 @@ fn insert_to_db_impl(
     delegator_pool_balances: &[DelegatorPoolBalance],
     current_delegator_pool_balances: &[CurrentDelegatorPoolBalance],
 ) -> Result<(), diesel::result::Error> {
-    insert_current_stake_pool_voter(conn, current_stake_pool_voters)?;
+    insert_current_stake_pool_voter(conn, policy, current_stake_pool_voters)?;
     insert_proposal_votes(conn, proposal_votes)?;
     insert_delegator_activities(conn, delegator_actvities)?;
-    insert_delegator_balances(conn, delegator_balances)?;
-    insert_delegator_pools(conn, delegator_pools)?;
+    insert_delegator_balances(conn, policy, delegator_balances)?;
+    insert_delegator_pools(conn, policy, delegator_pools)?;
     insert_delegator_pool_balances(conn, delegator_pool_balances)?;
-    insert_current_delegator_pool_balances(conn, current_delegator_pool_balances)?;
+    insert_current_delegator_pool_balances(conn, policy, current_delegator_pool_balances)?;
     Ok(())
 }
 
@@ -73,6 +76,7 @@ fn insert_to_db(
     name: &'static str,
     start_version: u64,
     end_version: u64,
+    policy: OverwritePolicy,
     current_stake_pool_voters: Vec<CurrentStakingPoolVoter>,
     proposal_votes: Vec<ProposalVote>,
     delegator_actvities: Vec<DelegatedStakingActivity>,
@@ -93,6 +97,7 @@ fn insert_to_db(
         .run::<_, Error, _>(|pg_conn| {
             insert_to_db_impl(
                 pg_conn,
+                policy,
                 &current_stake_pool_voters,
                 &proposal_votes,
                 &delegator_actvities,
@@ -118,6 +123,7 @@ fn insert_to_db(
 
                 insert_to_db_impl(
                     pg_conn,
+                    policy,
                     &current_stake_pool_voters,
                     &proposal_votes,
                     &delegator_actvities,
@@ -132,13 +138,14 @@ fn insert_to_db(
 
 fn insert_current_stake_pool_voter(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     item_to_insert: &[CurrentStakingPoolVoter],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_staking_pool_voter::dsl::*;
 
     let chunks = get_chunks(item_to_insert.len(), CurrentStakingPoolVoter::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_staking_pool_voter", policy).execute(
             conn,
             diesel::insert_into(schema::current_staking_pool_voter::table)
                 .values(&item_to_insert[start_ind..end_ind])
@@ -151,9 +158,7 @@ fn insert_current_stake_pool_voter(
                     inserted_at.eq(excluded(inserted_at)),
                     operator_address.eq(excluded(operator_address)),
                 )),
-            Some(
-                " WHERE current_staking_pool_voter.last_transaction_version <= EXCLUDED.last_transaction_version ",
-            ),
+            &item_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -204,13 +209,14 @@ fn insert_delegator_activities(
 
 fn insert_delegator_balances(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     item_to_insert: &[CurrentDelegatorBalance],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_delegator_balances::dsl::*;
 
     let chunks = get_chunks(item_to_insert.len(), CurrentDelegatorBalance::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_delegator_balances", policy).execute(
             conn,
             diesel::insert_into(schema::current_delegator_balances::table)
                 .values(&item_to_insert[start_ind..end_ind])
@@ -222,9 +228,7 @@ fn insert_delegator_balances(
                     shares.eq(excluded(shares)),
                     parent_table_handle.eq(excluded(parent_table_handle)),
                 )),
-            Some(
-                " WHERE current_delegator_balances.last_transaction_version <= EXCLUDED.last_transaction_version ",
-            ),
+            &item_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -232,26 +236,32 @@ fn insert_delegator_balances(
 
 fn insert_delegator_pools(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     item_to_insert: &[DelegatorPool],
 ) -> Result<(), diesel::result::Error> {
     use schema::delegated_staking_pools::dsl::*;
 
     let chunks = get_chunks(item_to_insert.len(), DelegatorPool::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
-            conn,
-            diesel::insert_into(schema::delegated_staking_pools::table)
-                .values(&item_to_insert[start_ind..end_ind])
-                .on_conflict(staking_pool_address)
-                .do_update()
-                .set((
-                    first_transaction_version.eq(excluded(first_transaction_version)),
-                    inserted_at.eq(excluded(inserted_at)),
-                )),
-            Some(
-                " WHERE delegated_staking_pools.first_transaction_version >= EXCLUDED.first_transaction_version ",
-            ),
-        )?;
+        GuardedUpsert::new("delegated_staking_pools", policy)
+            .skip_unless(
+                " WHERE delegated_staking_pools.first_transaction_version > EXCLUDED.first_transaction_version ",
+            )
+            .execute(
+                conn,
+                diesel::insert_into(schema::delegated_staking_pools::table)
+                    .values(&item_to_insert[start_ind..end_ind])
+                    .on_conflict(staking_pool_address)
+                    .do_update()
+                    .set((
+                        first_transaction_version.eq(excluded(first_transaction_version)),
+                        inserted_at.eq(excluded(inserted_at)),
+                    )),
+                Some(
+                    " WHERE delegated_staking_pools.first_transaction_version >= EXCLUDED.first_transaction_version ",
+                ),
+                &item_to_insert[start_ind..end_ind],
+            )?;
     }
     Ok(())
 }
@@ -278,6 +288,7 @@ fn insert_delegator_pool_balances(
 
 fn insert_current_delegator_pool_balances(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     item_to_insert: &[CurrentDelegatorPoolBalance],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_delegated_staking_pool_balances::dsl::*;
@@ -287,7 +298,7 @@ fn insert_current_delegator_pool_balances(
         CurrentDelegatorPoolBalance::field_count(),
     );
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_delegated_staking_pool_balances", policy).execute(
             conn,
             diesel::insert_into(schema::current_delegated_staking_pool_balances::table)
                 .values(&item_to_insert[start_ind..end_ind])
@@ -302,9 +313,7 @@ fn insert_current_delegator_pool_balances(
                     inactive_table_handle.eq(excluded(inactive_table_handle)),
                     active_table_handle.eq(excluded(active_table_handle)),
                 )),
-            Some(
-                " WHERE current_delegated_staking_pool_balances.last_transaction_version <= EXCLUDED.last_transaction_version ",
-            ),
+            &item_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -322,7 +331,14 @@ impl TransactionProcessor for StakeTransactionProcessor {
         start_version: u64,
         end_version: u64,
     ) -> Result<ProcessingResult, TransactionProcessingError> {
-        let mut conn = self.get_conn();
+        let mut conn = self.get_conn().map_err(|err| {
+            TransactionProcessingError::ConnectionPoolError((
+                err,
+                start_version,
+                end_version,
+                self.name(),
+            ))
+        })?;
 
         let mut all_current_stake_pool_voters: StakingPoolVoterMap = HashMap::new();
         let mut all_proposal_votes = vec![];
@@ -345,7 +361,8 @@ impl TransactionProcessor for StakeTransactionProcessor {
 
             // Add delegator balances
             let delegator_balances =
-                CurrentDelegatorBalance::from_transaction(txn, &mut conn).unwrap();
+                CurrentDelegatorBalance::from_transaction(txn, &all_delegator_balances, &mut conn)
+                    .unwrap();
             all_delegator_balances.extend(delegator_balances);
 
             // Add delegator pools
@@ -374,11 +391,18 @@ impl TransactionProcessor for StakeTransactionProcessor {
         all_current_stake_pool_voters
             .sort_by(|a, b| a.staking_pool_address.cmp(&b.staking_pool_address));
         all_delegator_balances.sort_by(|a, b| {
-            (&a.delegator_address, &a.pool_address, &a.pool_type).cmp(&(
-                &b.delegator_address,
-                &b.pool_address,
-                &b.pool_type,
-            ))
+            (
+                &a.delegator_address,
+                &a.pool_address,
+                &a.pool_type,
+                &a.table_handle,
+            )
+                .cmp(&(
+                    &b.delegator_address,
+                    &b.pool_address,
+                    &b.pool_type,
+                    &b.table_handle,
+                ))
         });
         all_delegator_pools.sort_by(|a, b| a.staking_pool_address.cmp(&b.staking_pool_address));
         all_current_delegator_pool_balances
@@ -389,6 +413,7 @@ impl TransactionProcessor for StakeTransactionProcessor {
             self.name(),
             start_version,
             end_version,
+            backfill_guard::policy(self.name(), start_version, end_version),
             all_current_stake_pool_voters,
             all_proposal_votes,
             all_delegator_activities,
@@ -412,7 +437,7 @@ impl TransactionProcessor for StakeTransactionProcessor {
         }
     }
 
-    fn connection_pool(&self) -> &PgDbPool {
-        &self.connection_pool
+    fn connection_pool(&self) -> Option<&PgDbPool> {
+        Some(&self.connection_pool)
     }
 }
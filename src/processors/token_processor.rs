@@ -2,8 +2,10 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
+    custom::driver::backfill_guard::{self, OverwritePolicy},
     database::{
-        clean_data_for_db, execute_with_better_error, get_chunks, PgDbPool, PgPoolConnection,
+        clean_data_for_db, execute_with_better_error, get_chunks, CurrentRowUpsert, GuardedUpsert,
+        PgDbPool, PgPoolConnection,
     },
     indexer::{
         errors::TransactionProcessingError, processing_result::ProcessingResult,
@@ -93,6 +95,7 @@ impl Debug for TokenTransactionProcessor {
 
 fn insert_to_db_impl(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     basic_token_transaction_lists: (&[Token], &[TokenOwnership], &[TokenData], &[CollectionData]),
     basic_token_current_lists: (
         &[CurrentTokenOwnership],
@@ -126,25 +129,25 @@ fn insert_to_db_impl(
     let (tokens, token_ownerships, token_datas, collection_datas) = basic_token_transaction_lists;
     let (current_token_ownerships, current_token_datas, current_collection_datas) =
         basic_token_current_lists;
-    insert_tokens(conn, tokens)?;
-    insert_token_datas(conn, token_datas)?;
+    insert_tokens(conn, policy, tokens)?;
+    insert_token_datas(conn, policy, token_datas)?;
     insert_token_ownerships(conn, token_ownerships)?;
     insert_collection_datas(conn, collection_datas)?;
-    insert_current_token_ownerships(conn, current_token_ownerships)?;
-    insert_current_token_datas(conn, current_token_datas)?;
-    insert_current_collection_datas(conn, current_collection_datas)?;
-    insert_token_activities(conn, token_activities)?;
-    insert_current_token_claims(conn, current_token_claims)?;
-    insert_current_ans_lookups(conn, current_ans_lookups)?;
+    insert_current_token_ownerships(conn, policy, current_token_ownerships)?;
+    insert_current_token_datas(conn, policy, current_token_datas)?;
+    insert_current_collection_datas(conn, policy, current_collection_datas)?;
+    insert_token_activities(conn, policy, token_activities)?;
+    insert_current_token_claims(conn, policy, current_token_claims)?;
+    insert_current_ans_lookups(conn, policy, current_ans_lookups)?;
     insert_nft_points(conn, nft_points)?;
     insert_collections_v2(conn, collections_v2)?;
-    insert_token_datas_v2(conn, token_datas_v2)?;
-    insert_token_ownerships_v2(conn, token_ownerships_v2)?;
-    insert_current_collections_v2(conn, current_collections_v2)?;
-    insert_current_token_datas_v2(conn, current_token_datas_v2)?;
-    insert_current_token_ownerships_v2(conn, current_token_ownerships_v2)?;
+    insert_token_datas_v2(conn, policy, token_datas_v2)?;
+    insert_token_ownerships_v2(conn, policy, token_ownerships_v2)?;
+    insert_current_collections_v2(conn, policy, current_collections_v2)?;
+    insert_current_token_datas_v2(conn, policy, current_token_datas_v2)?;
+    insert_current_token_ownerships_v2(conn, policy, current_token_ownerships_v2)?;
     insert_token_activities_v2(conn, token_activities_v2)?;
-    insert_current_token_v2_metadatas(conn, current_token_v2_metadata)?;
+    insert_current_token_v2_metadatas(conn, policy, current_token_v2_metadata)?;
     Ok(())
 }
 
@@ -153,6 +156,7 @@ fn insert_to_db(
     name: &'static str,
     start_version: u64,
     end_version: u64,
+    policy: OverwritePolicy,
     basic_token_transaction_lists: (
         Vec<Token>,
         Vec<TokenOwnership>,
@@ -203,6 +207,7 @@ fn insert_to_db(
         .run::<_, Error, _>(|pg_conn| {
             insert_to_db_impl(
                 pg_conn,
+                policy,
                 (&tokens, &token_ownerships, &token_datas, &collection_datas),
                 (
                     &current_token_ownerships,
@@ -253,6 +258,7 @@ fn insert_to_db(
 
                 insert_to_db_impl(
                     pg_conn,
+                    policy,
                     (&tokens, &token_ownerships, &token_datas, &collection_datas),
                     (
                         &current_token_ownerships,
@@ -280,13 +286,14 @@ fn insert_to_db(
 
 fn insert_tokens(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     tokens_to_insert: &[Token],
 ) -> Result<(), diesel::result::Error> {
     use schema::tokens::dsl::*;
 
     let chunks = get_chunks(tokens_to_insert.len(), Token::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        GuardedUpsert::new("tokens", policy).execute(
             conn,
             diesel::insert_into(schema::tokens::table)
                 .values(&tokens_to_insert[start_ind..end_ind])
@@ -297,6 +304,7 @@ fn insert_tokens(
                     inserted_at.eq(excluded(inserted_at)),
                 )),
             None,
+            &tokens_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -332,13 +340,14 @@ fn insert_token_ownerships(
 
 fn insert_token_datas(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     token_datas_to_insert: &[TokenData],
 ) -> Result<(), diesel::result::Error> {
     use schema::token_datas::dsl::*;
 
     let chunks = get_chunks(token_datas_to_insert.len(), TokenData::field_count());
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        GuardedUpsert::new("token_datas", policy).execute(
             conn,
             diesel::insert_into(schema::token_datas::table)
                 .values(&token_datas_to_insert[start_ind..end_ind])
@@ -349,6 +358,7 @@ fn insert_token_datas(
                     inserted_at.eq(excluded(inserted_at)),
                 )),
             None,
+            &token_datas_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -379,6 +389,7 @@ fn insert_collection_datas(
 
 fn insert_current_token_ownerships(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentTokenOwnership],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_token_ownerships::dsl::*;
@@ -386,7 +397,7 @@ fn insert_current_token_ownerships(
     let chunks = get_chunks(items_to_insert.len(), CurrentTokenOwnership::field_count());
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_token_ownerships", policy).execute(
             conn,
             diesel::insert_into(schema::current_token_ownerships::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -403,7 +414,7 @@ fn insert_current_token_ownerships(
                     table_type.eq(excluded(table_type)),
                     inserted_at.eq(excluded(inserted_at)),
                 )),
-            Some(" WHERE current_token_ownerships.last_transaction_version <= excluded.last_transaction_version "),
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -411,6 +422,7 @@ fn insert_current_token_ownerships(
 
 fn insert_current_token_datas(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentTokenData],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_token_datas::dsl::*;
@@ -418,7 +430,7 @@ fn insert_current_token_datas(
     let chunks = get_chunks(items_to_insert.len(), CurrentTokenData::field_count());
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_token_datas", policy).execute(
             conn,
             diesel::insert_into(schema::current_token_datas::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -446,7 +458,7 @@ fn insert_current_token_datas(
                     description.eq(excluded(description)),
                     inserted_at.eq(excluded(inserted_at)),
                 )),
-            Some(" WHERE current_token_datas.last_transaction_version <= excluded.last_transaction_version "),
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -454,6 +466,7 @@ fn insert_current_token_datas(
 
 fn insert_current_collection_datas(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentCollectionData],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_collection_datas::dsl::*;
@@ -461,7 +474,7 @@ fn insert_current_collection_datas(
     let chunks = get_chunks(items_to_insert.len(), CurrentCollectionData::field_count());
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_collection_datas", policy).execute(
             conn,
             diesel::insert_into(schema::current_collection_datas::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -481,7 +494,7 @@ fn insert_current_collection_datas(
                     table_handle.eq(excluded(table_handle)),
                     inserted_at.eq(excluded(inserted_at)),
                 )),
-            Some(" WHERE current_collection_datas.last_transaction_version <= excluded.last_transaction_version "),
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -489,6 +502,7 @@ fn insert_current_collection_datas(
 
 fn insert_token_activities(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[TokenActivity],
 ) -> Result<(), diesel::result::Error> {
     use schema::token_activities::dsl::*;
@@ -496,7 +510,7 @@ fn insert_token_activities(
     let chunks = get_chunks(items_to_insert.len(), TokenActivity::field_count());
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        GuardedUpsert::new("token_activities", policy).execute(
             conn,
             diesel::insert_into(schema::token_activities::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -512,12 +526,14 @@ fn insert_token_activities(
                     event_index.eq(excluded(event_index)),
                 )),
             None,
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
 }
 fn insert_current_token_claims(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentTokenPendingClaim],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_token_pending_claims::dsl::*;
@@ -528,7 +544,7 @@ fn insert_current_token_claims(
     );
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_token_pending_claims", policy).execute(
             conn,
             diesel::insert_into(schema::current_token_pending_claims::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -548,7 +564,7 @@ fn insert_current_token_claims(
                     token_data_id.eq(excluded(token_data_id)),
                     collection_id.eq(excluded(collection_id)),
                 )),
-            Some(" WHERE current_token_pending_claims.last_transaction_version <= excluded.last_transaction_version "),
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -556,6 +572,7 @@ fn insert_current_token_claims(
 
 fn insert_current_ans_lookups(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentAnsLookup],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_ans_lookup::dsl::*;
@@ -563,7 +580,7 @@ fn insert_current_ans_lookups(
     let chunks = get_chunks(items_to_insert.len(), CurrentAnsLookup::field_count());
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_ans_lookup", policy).execute(
             conn,
             diesel::insert_into(schema::current_ans_lookup::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -576,7 +593,7 @@ fn insert_current_ans_lookups(
                     inserted_at.eq(excluded(inserted_at)),
                     token_name.eq(excluded(token_name)),
                 )),
-                Some(" WHERE current_ans_lookup.last_transaction_version <= excluded.last_transaction_version "),
+            &items_to_insert[start_ind..end_ind],
             )?;
     }
     Ok(())
@@ -626,6 +643,7 @@ fn insert_collections_v2(
 
 fn insert_token_datas_v2(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[TokenDataV2],
 ) -> Result<(), diesel::result::Error> {
     use schema::token_datas_v2::dsl::*;
@@ -633,7 +651,7 @@ fn insert_token_datas_v2(
     let chunks = get_chunks(items_to_insert.len(), TokenDataV2::field_count());
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        GuardedUpsert::new("token_datas_v2", policy).execute(
             conn,
             diesel::insert_into(schema::token_datas_v2::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -644,6 +662,7 @@ fn insert_token_datas_v2(
                     decimals.eq(excluded(decimals)),
                 )),
             None,
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -651,6 +670,7 @@ fn insert_token_datas_v2(
 
 fn insert_token_ownerships_v2(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[TokenOwnershipV2],
 ) -> Result<(), diesel::result::Error> {
     use schema::token_ownerships_v2::dsl::*;
@@ -658,7 +678,7 @@ fn insert_token_ownerships_v2(
     let chunks = get_chunks(items_to_insert.len(), TokenOwnershipV2::field_count());
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        GuardedUpsert::new("token_ownerships_v2", policy).execute(
             conn,
             diesel::insert_into(schema::token_ownerships_v2::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -680,6 +700,7 @@ fn insert_token_ownerships_v2(
                     non_transferrable_by_owner.eq(excluded(non_transferrable_by_owner)),
                 )),
             None,
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -687,6 +708,7 @@ fn insert_token_ownerships_v2(
 
 fn insert_current_collections_v2(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentCollectionV2],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_collections_v2::dsl::*;
@@ -694,7 +716,7 @@ fn insert_current_collections_v2(
     let chunks = get_chunks(items_to_insert.len(), CurrentCollectionV2::field_count());
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_collections_v2", policy).execute(
             conn,
             diesel::insert_into(schema::current_collections_v2::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -716,7 +738,7 @@ fn insert_current_collections_v2(
                     last_transaction_timestamp.eq(excluded(last_transaction_timestamp)),
                     inserted_at.eq(excluded(inserted_at)),
                 )),
-            Some(" WHERE current_collections_v2.last_transaction_version <= excluded.last_transaction_version "),
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -724,6 +746,7 @@ fn insert_current_collections_v2(
 
 fn insert_current_token_datas_v2(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentTokenDataV2],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_token_datas_v2::dsl::*;
@@ -731,7 +754,7 @@ fn insert_current_token_datas_v2(
     let chunks = get_chunks(items_to_insert.len(), CurrentTokenDataV2::field_count());
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_token_datas_v2", policy).execute(
             conn,
             diesel::insert_into(schema::current_token_datas_v2::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -753,7 +776,7 @@ fn insert_current_token_datas_v2(
                     inserted_at.eq(excluded(inserted_at)),
                     decimals.eq(excluded(decimals)),
                 )),
-            Some(" WHERE current_token_datas_v2.last_transaction_version <= excluded.last_transaction_version "),
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -761,6 +784,7 @@ fn insert_current_token_datas_v2(
 
 fn insert_current_token_ownerships_v2(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentTokenOwnershipV2],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_token_ownerships_v2::dsl::*;
@@ -771,7 +795,7 @@ fn insert_current_token_ownerships_v2(
     );
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_token_ownerships_v2", policy).execute(
             conn,
             diesel::insert_into(schema::current_token_ownerships_v2::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -789,7 +813,7 @@ fn insert_current_token_ownerships_v2(
                     inserted_at.eq(excluded(inserted_at)),
                     non_transferrable_by_owner.eq(excluded(non_transferrable_by_owner)),
                 )),
-            Some(" WHERE current_token_ownerships_v2.last_transaction_version <= excluded.last_transaction_version "),
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -818,6 +842,7 @@ fn insert_token_activities_v2(
 
 fn insert_current_token_v2_metadatas(
     conn: &mut PgConnection,
+    policy: OverwritePolicy,
     items_to_insert: &[CurrentTokenV2Metadata],
 ) -> Result<(), diesel::result::Error> {
     use schema::current_token_v2_metadata::dsl::*;
@@ -825,7 +850,7 @@ fn insert_current_token_v2_metadatas(
     let chunks = get_chunks(items_to_insert.len(), CurrentTokenV2Metadata::field_count());
 
     for (start_ind, end_ind) in chunks {
-        execute_with_better_error(
+        CurrentRowUpsert::new("current_token_v2_metadata", policy).execute(
             conn,
             diesel::insert_into(schema::current_token_v2_metadata::table)
                 .values(&items_to_insert[start_ind..end_ind])
@@ -837,7 +862,7 @@ fn insert_current_token_v2_metadatas(
                     last_transaction_version.eq(excluded(last_transaction_version)),
                     inserted_at.eq(excluded(inserted_at)),
                 )),
-            Some(" WHERE current_token_v2_metadata.last_transaction_version <= excluded.last_transaction_version "),
+            &items_to_insert[start_ind..end_ind],
         )?;
     }
     Ok(())
@@ -855,7 +880,14 @@ impl TransactionProcessor for TokenTransactionProcessor {
         start_version: u64,
         end_version: u64,
     ) -> Result<ProcessingResult, TransactionProcessingError> {
-        let mut conn = self.get_conn();
+        let mut conn = self.get_conn().map_err(|err| {
+            TransactionProcessingError::ConnectionPoolError((
+                err,
+                start_version,
+                end_version,
+                self.name(),
+            ))
+        })?;
 
         // First get all token related table metadata from the batch of transactions. This is in case
         // an earlier transaction has metadata (in resources) that's missing from a later transaction.
@@ -990,6 +1022,7 @@ impl TransactionProcessor for TokenTransactionProcessor {
             self.name(),
             start_version,
             end_version,
+            backfill_guard::policy(self.name(), start_version, end_version),
             (
                 all_tokens,
                 all_token_ownerships,
@@ -1032,8 +1065,8 @@ impl TransactionProcessor for TokenTransactionProcessor {
         }
     }
 
-    fn connection_pool(&self) -> &PgDbPool {
-        &self.connection_pool
+    fn connection_pool(&self) -> Option<&PgDbPool> {
+        Some(&self.connection_pool)
     }
 }
 
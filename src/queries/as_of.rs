@@ -0,0 +1,533 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Reads of state as of an earlier version, e.g. to reconcile against a snapshot taken at that
+//! version, which the current tables can't answer once a row has been overwritten.
+//!
+//! A table item is looked up in `current_table_items` first: if its last write is at or before
+//! the version, that's the answer, in one primary key lookup. Otherwise the last write at or
+//! before the version is read from `table_items`, through `ti_hand_keymd5_ver_index` on the table
+//! handle, the md5 of the key and the version, which costs one more index lookup however many
+//! times the item was written. A key without a current row was never written.
+//!
+//! Resources have no current table, so they're always read from `move_resources`, through
+//! `mr_addr_mod_name_ver_index`. That lookup reads the writes of every instantiation of the
+//! resource's generic struct at the account until the version, e.g. every `CoinStore<T>`, so it's
+//! slower for accounts with many of them. Types truncated by `custom::driver::row_limits` aren't
+//! found.
+//!
+//! The batch variants resolve their keys in a single query, a lateral join per key doing the same
+//! lookups, which beats a round trip per key from a few keys on. Deleted rows read as missing.
+
+use crate::{
+    database::PgPoolConnection,
+    queries::index_advisor::instrument,
+    schema::{current_table_items, move_resources, table_items},
+    util::standardize_address,
+};
+use diesel::{
+    sql_query,
+    sql_types::{Array, BigInt, Jsonb, Nullable, Text},
+    ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl,
+};
+use serde::{Deserialize, Serialize};
+
+sql_function!(fn md5(x: Text) -> Text);
+
+/// A table item as it was at a version
+#[derive(Clone, Debug, Deserialize, PartialEq, QueryableByName, Serialize)]
+pub struct TableItemAsOf {
+    #[diesel(sql_type = Text)]
+    pub table_handle: String,
+    #[diesel(sql_type = Text)]
+    pub key_hash: String,
+    #[diesel(sql_type = Text)]
+    pub key: String,
+    #[diesel(sql_type = Jsonb)]
+    pub decoded_key: serde_json::Value,
+    #[diesel(sql_type = Nullable<Jsonb>)]
+    pub decoded_value: Option<serde_json::Value>,
+    /// The version that wrote the value
+    #[diesel(sql_type = BigInt)]
+    pub transaction_version: i64,
+}
+
+/// A resource of an account as it was at a version
+#[derive(Clone, Debug, Deserialize, PartialEq, QueryableByName, Serialize)]
+pub struct AccountResourceAsOf {
+    #[diesel(sql_type = Text)]
+    pub address: String,
+    #[diesel(sql_type = Text)]
+    pub type_: String,
+    #[diesel(sql_type = Nullable<Jsonb>)]
+    pub data: Option<serde_json::Value>,
+    /// The version that wrote the value
+    #[diesel(sql_type = BigInt)]
+    pub transaction_version: i64,
+}
+
+const TABLE_ITEMS_AS_OF_SQL: &str = "
+SELECT c.table_handle, c.key_hash, c.key, s.decoded_key, s.decoded_value, s.transaction_version
+FROM unnest($1::text[], $2::text[]) WITH ORDINALITY AS k (table_handle, key_hash, ord)
+JOIN current_table_items c ON c.table_handle = k.table_handle AND c.key_hash = k.key_hash
+CROSS JOIN LATERAL (
+    SELECT c.decoded_key, c.decoded_value, c.is_deleted,
+        c.last_transaction_version AS transaction_version
+    WHERE c.last_transaction_version <= $3
+    UNION ALL
+    (
+        SELECT t.decoded_key, t.decoded_value, t.is_deleted, t.transaction_version
+        FROM table_items t
+        WHERE c.last_transaction_version > $3
+            AND t.table_handle = c.table_handle
+            AND md5(t.key) = md5(c.key)
+            AND t.key = c.key
+            AND t.transaction_version <= $3
+        ORDER BY t.transaction_version DESC, t.write_set_change_index DESC
+        LIMIT 1
+    )
+) s
+WHERE NOT s.is_deleted
+ORDER BY k.ord";
+
+const ACCOUNT_RESOURCES_AS_OF_SQL: &str = "
+SELECT k.address, k.type AS type_, s.data, s.transaction_version
+FROM unnest($1::text[], $2::text[], $3::text[], $4::text[])
+    WITH ORDINALITY AS k (address, type, module, name, ord)
+CROSS JOIN LATERAL (
+    SELECT r.data, r.is_deleted, r.transaction_version
+    FROM move_resources r
+    WHERE r.address = k.address
+        AND r.module = k.module
+        AND r.name = k.name
+        AND r.type = k.type
+        AND r.transaction_version <= $5
+    ORDER BY r.transaction_version DESC, r.write_set_change_index DESC
+    LIMIT 1
+) s
+WHERE NOT s.is_deleted
+ORDER BY k.ord";
+
+/// The item of the table at `table_handle` with the key hashing to `key_hash` as it was after
+/// `version`, `None` if it didn't exist then
+pub fn get_table_item_as_of(
+    conn: &mut PgPoolConnection,
+    table_handle: &str,
+    key_hash: &str,
+    version: i64,
+) -> anyhow::Result<Option<TableItemAsOf>> {
+    let table_handle = standardize_address(table_handle);
+    let current_query = current_table_items::table
+        .filter(current_table_items::table_handle.eq(table_handle.clone()))
+        .filter(current_table_items::key_hash.eq(key_hash.to_string()))
+        .select((
+            current_table_items::key,
+            current_table_items::decoded_key,
+            current_table_items::decoded_value,
+            current_table_items::is_deleted,
+            current_table_items::last_transaction_version,
+        ));
+    let current = instrument(
+        conn,
+        "get_table_item_as_of",
+        "current_table_items",
+        &["table_handle", "key_hash"],
+        current_query,
+        |conn, query| query.first::<ItemState>(conn).optional(),
+    )?;
+    let Some((key, decoded_key, decoded_value, is_deleted, last_version)) = current else {
+        return Ok(None);
+    };
+    let (decoded_key, decoded_value, is_deleted, transaction_version) = if last_version <= version {
+        (decoded_key, decoded_value, is_deleted, last_version)
+    } else {
+        let history_query = table_items::table
+            .filter(table_items::table_handle.eq(table_handle.clone()))
+            .filter(md5(table_items::key).eq(md5(key.clone())))
+            .filter(table_items::key.eq(key.clone()))
+            .filter(table_items::transaction_version.le(version))
+            .order((
+                table_items::transaction_version.desc(),
+                table_items::write_set_change_index.desc(),
+            ))
+            .select((
+                table_items::decoded_key,
+                table_items::decoded_value,
+                table_items::is_deleted,
+                table_items::transaction_version,
+            ));
+        let history = instrument(
+            conn,
+            "get_table_item_as_of",
+            "table_items",
+            &["table_handle", "key", "transaction_version"],
+            history_query,
+            |conn, query| {
+                query
+                    .first::<(serde_json::Value, Option<serde_json::Value>, bool, i64)>(conn)
+                    .optional()
+            },
+        )?;
+        match history {
+            Some(history) => history,
+            None => return Ok(None),
+        }
+    };
+    Ok((!is_deleted).then(|| TableItemAsOf {
+        table_handle,
+        key_hash: key_hash.to_string(),
+        key,
+        decoded_key,
+        decoded_value,
+        transaction_version,
+    }))
+}
+
+/// The items of `keys`, (table handle, key hash) pairs, as they were after `version`, in the
+/// order of `keys`, leaving out the ones that didn't exist then
+pub fn get_table_items_as_of(
+    conn: &mut PgPoolConnection,
+    keys: &[(String, String)],
+    version: i64,
+) -> anyhow::Result<Vec<TableItemAsOf>> {
+    let (table_handles, key_hashes): (Vec<_>, Vec<_>) = keys
+        .iter()
+        .map(|(table_handle, key_hash)| (standardize_address(table_handle), key_hash.clone()))
+        .unzip();
+    Ok(sql_query(TABLE_ITEMS_AS_OF_SQL)
+        .bind::<Array<Text>, _>(table_handles)
+        .bind::<Array<Text>, _>(key_hashes)
+        .bind::<BigInt, _>(version)
+        .load::<TableItemAsOf>(conn)?)
+}
+
+/// The resource of type `type_` at `address` as it was after `version`, `None` if it didn't
+/// exist then
+pub fn get_account_resource_as_of(
+    conn: &mut PgPoolConnection,
+    address: &str,
+    type_: &str,
+    version: i64,
+) -> anyhow::Result<Option<AccountResourceAsOf>> {
+    let address = standardize_address(address);
+    let (module, name) = module_and_name(type_)?;
+    let query = move_resources::table
+        .filter(move_resources::address.eq(address.clone()))
+        .filter(move_resources::module.eq(module))
+        .filter(move_resources::name.eq(name))
+        .filter(move_resources::type_.eq(type_.to_string()))
+        .filter(move_resources::transaction_version.le(version))
+        .order((
+            move_resources::transaction_version.desc(),
+            move_resources::write_set_change_index.desc(),
+        ))
+        .select((
+            move_resources::data,
+            move_resources::is_deleted,
+            move_resources::transaction_version,
+        ));
+    let resource = instrument(
+        conn,
+        "get_account_resource_as_of",
+        "move_resources",
+        &["address", "module", "name", "type", "transaction_version"],
+        query,
+        |conn, query| {
+            query
+                .first::<(Option<serde_json::Value>, bool, i64)>(conn)
+                .optional()
+        },
+    )?;
+    Ok(match resource {
+        Some((data, false, transaction_version)) => Some(AccountResourceAsOf {
+            address,
+            type_: type_.to_string(),
+            data,
+            transaction_version,
+        }),
+        _ => None,
+    })
+}
+
+/// The resources of `keys`, (address, type) pairs, as they were after `version`, in the order of
+/// `keys`, leaving out the ones that didn't exist then
+pub fn get_account_resources_as_of(
+    conn: &mut PgPoolConnection,
+    keys: &[(String, String)],
+    version: i64,
+) -> anyhow::Result<Vec<AccountResourceAsOf>> {
+    let (mut addresses, mut types, mut modules, mut names) = (vec![], vec![], vec![], vec![]);
+    for (address, type_) in keys {
+        let (module, name) = module_and_name(type_)?;
+        addresses.push(standardize_address(address));
+        types.push(type_.clone());
+        modules.push(module);
+        names.push(name);
+    }
+    Ok(sql_query(ACCOUNT_RESOURCES_AS_OF_SQL)
+        .bind::<Array<Text>, _>(addresses)
+        .bind::<Array<Text>, _>(types)
+        .bind::<Array<Text>, _>(modules)
+        .bind::<Array<Text>, _>(names)
+        .bind::<BigInt, _>(version)
+        .load::<AccountResourceAsOf>(conn)?)
+}
+
+/// Key, decoded key, decoded value, is deleted and last version of a current table item
+type ItemState = (
+    String,
+    serde_json::Value,
+    Option<serde_json::Value>,
+    bool,
+    i64,
+);
+
+/// The module and name of a struct type, as stored in `move_resources`
+fn module_and_name(type_: &str) -> anyhow::Result<(String, String)> {
+    let base = type_.split('<').next().unwrap_or_default();
+    match base.split("::").collect::<Vec<_>>()[..] {
+        [_, module, name] if !module.is_empty() && !name.is_empty() => {
+            Ok((module.to_string(), name.to_string()))
+        },
+        _ => anyhow::bail!("Not a struct type: {}", type_),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::new_db_pool, indexer::tailer::MIGRATIONS};
+    use diesel_migrations::MigrationHarness;
+    use serde_json::json;
+
+    const HANDLE: &str = "0x00000000000000000000000000000000000000000000000000000000000a5f01";
+    const ADDRESS: &str = "0x00000000000000000000000000000000000000000000000000000000000a5f02";
+    const BASE_VERSION: i64 = 9_100_000_000;
+
+    #[test]
+    fn test_module_and_name() {
+        assert_eq!(
+            module_and_name("0x1::coin::CoinStore<0x1::aptos_coin::AptosCoin>").unwrap(),
+            ("coin".to_string(), "CoinStore".to_string())
+        );
+        assert!(module_and_name("0x1::coin").is_err());
+        assert!(module_and_name("u64").is_err());
+    }
+
+    /// Writes of an item at offsets from `BASE_VERSION`, a `None` value deleting it. `index` is
+    /// the write set change index of the writes, distinct per item.
+    fn write_item(
+        conn: &mut PgPoolConnection,
+        key: &str,
+        index: i64,
+        writes: &[(i64, Option<i64>)],
+    ) {
+        let key_hash = crate::util::hash_str(key);
+        for (offset, value) in writes {
+            diesel::insert_into(table_items::table)
+                .values((
+                    table_items::key.eq(key),
+                    table_items::transaction_version.eq(BASE_VERSION + offset),
+                    table_items::write_set_change_index.eq(index),
+                    table_items::transaction_block_height.eq(0),
+                    table_items::table_handle.eq(HANDLE),
+                    table_items::decoded_key.eq(json!(key)),
+                    table_items::decoded_value.eq(value.map(|value| json!(value))),
+                    table_items::is_deleted.eq(value.is_none()),
+                ))
+                .execute(conn)
+                .unwrap();
+        }
+        let (offset, value) = writes.last().unwrap();
+        diesel::insert_into(current_table_items::table)
+            .values((
+                current_table_items::table_handle.eq(HANDLE),
+                current_table_items::key_hash.eq(key_hash),
+                current_table_items::key.eq(key),
+                current_table_items::decoded_key.eq(json!(key)),
+                current_table_items::decoded_value.eq(value.map(|value| json!(value))),
+                current_table_items::last_transaction_version.eq(BASE_VERSION + offset),
+                current_table_items::is_deleted.eq(value.is_none()),
+            ))
+            .execute(conn)
+            .unwrap();
+    }
+
+    fn write_resource(
+        conn: &mut PgPoolConnection,
+        type_: &str,
+        index: i64,
+        writes: &[(i64, Option<i64>)],
+    ) {
+        let (module, name) = module_and_name(type_).unwrap();
+        for (offset, value) in writes {
+            diesel::insert_into(move_resources::table)
+                .values((
+                    move_resources::transaction_version.eq(BASE_VERSION + offset),
+                    move_resources::write_set_change_index.eq(index),
+                    move_resources::transaction_block_height.eq(0),
+                    move_resources::name.eq(&name),
+                    move_resources::address.eq(ADDRESS),
+                    move_resources::type_.eq(type_),
+                    move_resources::module.eq(&module),
+                    move_resources::data.eq(value.map(|value| json!({ "value": value }))),
+                    move_resources::is_deleted.eq(value.is_none()),
+                    move_resources::state_key_hash.eq(ADDRESS),
+                ))
+                .execute(conn)
+                .unwrap();
+        }
+    }
+
+    fn item_value(item: Option<&TableItemAsOf>) -> Option<(i64, serde_json::Value)> {
+        item.map(|item| {
+            (
+                item.transaction_version - BASE_VERSION,
+                item.decoded_value.clone().unwrap(),
+            )
+        })
+    }
+
+    #[test]
+    fn test_table_item_as_of() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(&database_url).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        diesel::delete(table_items::table.filter(table_items::table_handle.eq(HANDLE)))
+            .execute(&mut conn)
+            .unwrap();
+        diesel::delete(
+            current_table_items::table.filter(current_table_items::table_handle.eq(HANDLE)),
+        )
+        .execute(&mut conn)
+        .unwrap();
+        // Unchanged since version 1
+        write_item(&mut conn, "stable", 0, &[(1, Some(1))]);
+        // Overwritten at 20
+        write_item(&mut conn, "overwritten", 1, &[(1, Some(1)), (20, Some(2))]);
+        // Deleted at 5
+        write_item(&mut conn, "deleted", 2, &[(1, Some(1)), (5, None)]);
+        // Deleted at 5 and recreated at 20
+        write_item(&mut conn, "recreated", 3, &[
+            (1, Some(1)),
+            (5, None),
+            (20, Some(2)),
+        ]);
+        // Created at 20
+        write_item(&mut conn, "created", 4, &[(20, Some(2))]);
+
+        let cases = [
+            ("stable", Some((1, json!(1))), Some((1, json!(1)))),
+            ("overwritten", Some((1, json!(1))), Some((20, json!(2)))),
+            ("deleted", None, None),
+            ("recreated", None, Some((20, json!(2)))),
+            ("created", None, Some((20, json!(2)))),
+            ("missing", None, None),
+        ];
+        for (key, at_10, at_30) in &cases {
+            let key_hash = crate::util::hash_str(key);
+            for (offset, expected) in [(10, at_10), (30, at_30)] {
+                let item =
+                    get_table_item_as_of(&mut conn, HANDLE, &key_hash, BASE_VERSION + offset)
+                        .unwrap();
+                assert_eq!(
+                    &item_value(item.as_ref()),
+                    expected,
+                    "{} at {}",
+                    key,
+                    offset
+                );
+            }
+        }
+        // Before the first write
+        let key_hash = crate::util::hash_str("stable");
+        assert_eq!(
+            get_table_item_as_of(&mut conn, HANDLE, &key_hash, BASE_VERSION).unwrap(),
+            None
+        );
+
+        let keys = cases
+            .iter()
+            .rev()
+            .map(|(key, ..)| (HANDLE.to_string(), crate::util::hash_str(key)))
+            .collect::<Vec<_>>();
+        let items = get_table_items_as_of(&mut conn, &keys, BASE_VERSION + 10).unwrap();
+        assert_eq!(
+            items
+                .iter()
+                .map(|item| item.key.as_str())
+                .collect::<Vec<_>>(),
+            vec!["overwritten", "stable"]
+        );
+        assert_eq!(item_value(items.first()), Some((1, json!(1))));
+        let items = get_table_items_as_of(&mut conn, &keys, BASE_VERSION + 30).unwrap();
+        assert_eq!(
+            items
+                .iter()
+                .map(|item| item_value(Some(item)).unwrap().0)
+                .collect::<Vec<_>>(),
+            vec![20, 20, 20, 1]
+        );
+    }
+
+    #[test]
+    fn test_account_resource_as_of() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(&database_url).unwrap();
+        let mut conn = conn_pool.get().unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        diesel::delete(move_resources::table.filter(move_resources::address.eq(ADDRESS)))
+            .execute(&mut conn)
+            .unwrap();
+        let coin_a = "0x1::coin::CoinStore<0xa::a::A>";
+        let coin_b = "0x1::coin::CoinStore<0xb::b::B>";
+        let deleted = "0xa::a::Deleted";
+        let recreated = "0xa::a::Recreated";
+        write_resource(&mut conn, coin_a, 0, &[(1, Some(1)), (20, Some(2))]);
+        write_resource(&mut conn, coin_b, 1, &[(2, Some(1))]);
+        write_resource(&mut conn, deleted, 2, &[(1, Some(1)), (5, None)]);
+        write_resource(&mut conn, recreated, 3, &[
+            (1, Some(1)),
+            (5, None),
+            (20, Some(2)),
+        ]);
+
+        let value = |type_: &str, offset: i64| {
+            get_account_resource_as_of(&mut conn, ADDRESS, type_, BASE_VERSION + offset)
+                .unwrap()
+                .map(|resource| {
+                    (
+                        resource.transaction_version - BASE_VERSION,
+                        resource.data.unwrap()["value"].clone(),
+                    )
+                })
+        };
+        assert_eq!(value(coin_a, 10), Some((1, json!(1))));
+        assert_eq!(value(coin_a, 30), Some((20, json!(2))));
+        assert_eq!(value(coin_b, 10), Some((2, json!(1))));
+        assert_eq!(value(deleted, 10), None);
+        assert_eq!(value(recreated, 10), None);
+        assert_eq!(value(recreated, 30), Some((20, json!(2))));
+        assert_eq!(value("0xa::a::Missing", 30), None);
+
+        let keys = [coin_b, deleted, recreated, coin_a]
+            .iter()
+            .map(|type_| (ADDRESS.to_string(), type_.to_string()))
+            .collect::<Vec<_>>();
+        let resources = get_account_resources_as_of(&mut conn, &keys, BASE_VERSION + 10).unwrap();
+        assert_eq!(
+            resources
+                .iter()
+                .map(|resource| (resource.type_.as_str(), resource.transaction_version))
+                .collect::<Vec<_>>(),
+            vec![(coin_b, BASE_VERSION + 2), (coin_a, BASE_VERSION + 1)]
+        );
+    }
+}
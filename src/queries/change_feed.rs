@@ -0,0 +1,35 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    database::PgPoolConnection,
+    models::change_feed::{ChangeFeedEntry, ChangeFeedEntryQuery},
+    queries::index_advisor::instrument,
+    schema::change_feed,
+};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+
+/// Up to `limit` entries of the change feed after `after_id`, oldest first. Start from 0 and pass
+/// the id of the last entry returned to get the next ones; entries are never skipped, but the
+/// ones older than the feed's retention are gone.
+pub fn poll_change_feed(
+    conn: &mut PgPoolConnection,
+    after_id: i64,
+    limit: i64,
+) -> anyhow::Result<Vec<ChangeFeedEntry>> {
+    let query = change_feed::table
+        .filter(change_feed::id.gt(after_id))
+        .order(change_feed::id.asc())
+        .limit(limit);
+    instrument(
+        conn,
+        "poll_change_feed",
+        "change_feed",
+        &["id"],
+        query,
+        |conn, query| query.load::<ChangeFeedEntryQuery>(conn),
+    )?
+    .into_iter()
+    .map(ChangeFeedEntry::try_from)
+    .collect()
+}
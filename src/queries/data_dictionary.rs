@@ -0,0 +1,262 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Column level documentation of every indexed table, for data catalogs to ingest. The columns,
+//! their types, nullability and the primary keys are read from the diesel schema the crate is
+//! built with, so they can't drift from the tables; descriptions, sources and the processors
+//! writing each table are registered in `table_docs`. A column without a description of its own
+//! falls back to the shared description of its name, e.g. `inserted_at`.
+
+use super::table_docs::{ColumnDoc, TableDoc, COMMON_COLUMNS, TABLES};
+use serde::{Deserialize, Serialize};
+
+const SCHEMA: &str = include_str!("../schema.rs");
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct TableEntry {
+    pub table: String,
+    pub description: String,
+    /// Processors, or driver modules, that write the table
+    pub written_by: Vec<String>,
+    pub primary_key: Vec<String>,
+    pub columns: Vec<ColumnEntry>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ColumnEntry {
+    pub name: String,
+    /// Diesel SQL type without `Nullable`, e.g. `Varchar`
+    pub sql_type: String,
+    /// Type of the column in the model structs, e.g. `Option<String>`
+    pub rust_type: String,
+    pub nullable: bool,
+    pub max_length: Option<u32>,
+    pub primary_key: bool,
+    pub description: String,
+    /// The API field the column is read from, or how it's derived
+    pub source: Option<String>,
+}
+
+/// A table of the diesel schema
+#[derive(Debug)]
+struct SchemaTable {
+    name: String,
+    primary_key: Vec<String>,
+    columns: Vec<SchemaColumn>,
+}
+
+#[derive(Debug)]
+struct SchemaColumn {
+    name: String,
+    sql_type: String,
+    nullable: bool,
+    max_length: Option<u32>,
+}
+
+/// Every indexed table, sorted by name. Tables or columns without a registered description show
+/// an empty one rather than being left out.
+pub fn data_dictionary() -> Vec<TableEntry> {
+    parse_schema(SCHEMA)
+        .into_iter()
+        .map(|table| {
+            let doc = TABLES.iter().find(|doc| doc.table == table.name);
+            TableEntry {
+                description: doc.map_or("", |doc| doc.description).to_string(),
+                written_by: doc
+                    .map(|doc| doc.written_by.iter().map(|name| name.to_string()).collect())
+                    .unwrap_or_default(),
+                columns: table
+                    .columns
+                    .iter()
+                    .map(|column| {
+                        let column_doc = column_doc(doc, &column.name);
+                        ColumnEntry {
+                            name: column.name.clone(),
+                            sql_type: column.sql_type.clone(),
+                            rust_type: rust_type(&column.sql_type, column.nullable),
+                            nullable: column.nullable,
+                            max_length: column.max_length,
+                            primary_key: table.primary_key.contains(&column.name),
+                            description: column_doc
+                                .map_or("", |column_doc| column_doc.description)
+                                .to_string(),
+                            source: column_doc
+                                .and_then(|column_doc| column_doc.source)
+                                .map(str::to_string),
+                        }
+                    })
+                    .collect(),
+                table: table.name,
+                primary_key: table.primary_key,
+            }
+        })
+        .collect()
+}
+
+/// The data dictionary as the JSON document a health endpoint serves
+pub fn data_dictionary_json() -> serde_json::Value {
+    serde_json::json!({ "tables": data_dictionary() })
+}
+
+/// Names of the tables of the diesel schema, i.e. the indexer's own tables
+pub fn indexed_tables() -> Vec<String> {
+    parse_schema(SCHEMA)
+        .into_iter()
+        .map(|table| table.name)
+        .collect()
+}
+
+fn column_doc(table: Option<&TableDoc>, column: &str) -> Option<&'static ColumnDoc> {
+    table
+        .and_then(|table| table.columns.iter().find(|doc| doc.name == column))
+        .or_else(|| COMMON_COLUMNS.iter().find(|doc| doc.name == column))
+}
+
+fn rust_type(sql_type: &str, nullable: bool) -> String {
+    let rust_type = match sql_type {
+        "Bool" => "bool",
+        "Int2" => "i16",
+        "Int4" => "i32",
+        "Int8" => "i64",
+        "Float8" => "f64",
+        "Numeric" => "BigDecimal",
+        "Varchar" | "Text" => "String",
+        "Bytea" => "Vec<u8>",
+        "Jsonb" => "serde_json::Value",
+        "Timestamp" => "chrono::NaiveDateTime",
+        other => other,
+    };
+    if nullable {
+        format!("Option<{}>", rust_type)
+    } else {
+        rust_type.to_string()
+    }
+}
+
+/// The `diesel::table!` blocks of `schema`, sorted by name
+fn parse_schema(schema: &str) -> Vec<SchemaTable> {
+    let mut tables = vec![];
+    let mut current: Option<SchemaTable> = None;
+    let mut max_length = None;
+    let mut sql_name = None;
+    for line in schema.lines().map(str::trim) {
+        if line.starts_with("diesel::table!") {
+            continue;
+        }
+        if let Some(table) = current.as_mut() {
+            if line == "}" {
+                tables.extend(current.take());
+            } else if let Some(length) = attribute(line, "max_length") {
+                max_length = length.parse().ok();
+            } else if let Some(name) = attribute(line, "sql_name") {
+                sql_name = Some(name.trim_matches('"').to_string());
+            } else if let Some((name, sql_type)) = line.trim_end_matches(',').split_once(" -> ") {
+                let (sql_type, nullable) = match sql_type
+                    .strip_prefix("Nullable<")
+                    .and_then(|inner| inner.strip_suffix('>'))
+                {
+                    Some(inner) => (inner, true),
+                    None => (sql_type, false),
+                };
+                table.columns.push(SchemaColumn {
+                    name: sql_name.take().unwrap_or_else(|| name.to_string()),
+                    sql_type: sql_type.to_string(),
+                    nullable,
+                    max_length: max_length.take(),
+                });
+            }
+        } else if let Some((name, primary_key)) = line
+            .strip_suffix('{')
+            .and_then(|header| header.trim().strip_suffix(')'))
+            .and_then(|header| header.split_once(" ("))
+        {
+            current = Some(SchemaTable {
+                name: name.to_string(),
+                primary_key: primary_key.split(", ").map(str::to_string).collect(),
+                columns: vec![],
+            });
+        }
+    }
+    tables.sort_by(|a, b| a.name.cmp(&b.name));
+    tables
+}
+
+/// Value of `#[name = value]`
+fn attribute<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    line.strip_prefix("#[")?
+        .strip_suffix(']')?
+        .strip_prefix(name)?
+        .trim_start()
+        .strip_prefix('=')
+        .map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_schema() {
+        let tables = parse_schema(SCHEMA);
+        let transactions = tables
+            .iter()
+            .find(|table| table.name == "transactions")
+            .unwrap();
+        assert_eq!(transactions.primary_key, vec!["version"]);
+        let type_column = transactions
+            .columns
+            .iter()
+            .find(|column| column.name == "type")
+            .unwrap();
+        assert_eq!(type_column.sql_type, "Varchar");
+        assert_eq!(type_column.max_length, None);
+        assert_eq!(transactions.columns[2].name, "hash");
+        assert_eq!(transactions.columns[2].max_length, Some(66));
+        let checkpoint = transactions
+            .columns
+            .iter()
+            .find(|column| column.name == "state_checkpoint_hash")
+            .unwrap();
+        assert!(checkpoint.nullable);
+        assert_eq!(rust_type(&checkpoint.sql_type, true), "Option<String>");
+    }
+
+    /// Fails when a table ships without descriptions, register them in `table_docs`
+    #[test]
+    fn test_every_column_is_described() {
+        let mut missing = vec![];
+        for table in data_dictionary() {
+            if table.description.is_empty() {
+                missing.push(table.table.clone());
+            }
+            for column in &table.columns {
+                if column.description.is_empty() {
+                    missing.push(format!("{}.{}", table.table, column.name));
+                }
+            }
+        }
+        assert!(missing.is_empty(), "Undocumented: {:?}", missing);
+    }
+
+    #[test]
+    fn test_no_stale_docs() {
+        let tables = parse_schema(SCHEMA);
+        let mut stale = vec![];
+        for doc in TABLES {
+            match tables.iter().find(|table| table.name == doc.table) {
+                Some(table) => stale.extend(
+                    doc.columns
+                        .iter()
+                        .filter(|column| !table.columns.iter().any(|c| c.name == column.name))
+                        .map(|column| format!("{}.{}", doc.table, column.name)),
+                ),
+                None => stale.push(doc.table.to_string()),
+            }
+        }
+        assert!(
+            stale.is_empty(),
+            "Documented but not in the schema: {:?}",
+            stale
+        );
+    }
+}
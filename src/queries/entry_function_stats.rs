@@ -0,0 +1,89 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    database::PgPoolConnection,
+    models::{
+        entry_function_daily_stats::{EntryFunctionCall, EntryFunctionDailyStat},
+        user_transactions::{MULTISIG_ENTRY_FUNCTION_ID, SCRIPT_ENTRY_FUNCTION_ID},
+    },
+    queries::index_advisor::instrument,
+    schema::{transactions, user_transactions},
+};
+use bigdecimal::BigDecimal;
+use chrono::{Duration, NaiveDate};
+use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use std::collections::HashMap;
+
+/// The stats of a UTC day derived again from `user_transactions` and `transactions`, to compare
+/// with or replace the rows of `entry_function_daily_stats`. Both tables are filled by
+/// `default_processor`, so the day has to be indexed by it.
+pub fn rederive_entry_function_stats(
+    conn: &mut PgPoolConnection,
+    day: NaiveDate,
+) -> anyhow::Result<Vec<EntryFunctionDailyStat>> {
+    let start = day.and_hms_opt(0, 0, 0).unwrap();
+    let calls_query = user_transactions::table
+        .filter(user_transactions::timestamp.ge(start))
+        .filter(user_transactions::timestamp.lt(start + Duration::days(1)))
+        .filter(user_transactions::entry_function_id_str.ne_all([
+            "",
+            SCRIPT_ENTRY_FUNCTION_ID,
+            MULTISIG_ENTRY_FUNCTION_ID,
+        ]))
+        .order(user_transactions::version)
+        .select((
+            user_transactions::version,
+            user_transactions::entry_function_id_str,
+            user_transactions::sender,
+        ));
+    let calls = instrument(
+        conn,
+        "rederive_entry_function_stats",
+        "user_transactions",
+        &["timestamp", "entry_function_id_str"],
+        calls_query,
+        |conn, query| query.load::<(i64, String, String)>(conn),
+    )?;
+    let (Some(first), Some(last)) = (calls.first(), calls.last()) else {
+        return Ok(vec![]);
+    };
+
+    let outcomes_query = transactions::table
+        .filter(transactions::version.between(first.0, last.0))
+        .filter(transactions::type_.eq("user_transaction"))
+        .select((
+            transactions::version,
+            transactions::success,
+            transactions::gas_used,
+        ));
+    let outcomes = instrument(
+        conn,
+        "rederive_entry_function_stats",
+        "transactions",
+        &["version", "type"],
+        outcomes_query,
+        |conn, query| query.load::<(i64, bool, BigDecimal)>(conn),
+    )?
+    .into_iter()
+    .map(|(version, success, gas_used)| (version, (success, gas_used)))
+    .collect::<HashMap<_, _>>();
+
+    let calls = calls
+        .into_iter()
+        .map(|(version, entry_function_id_str, sender)| {
+            let (success, gas_used) = outcomes.get(&version).cloned().ok_or_else(|| {
+                anyhow::anyhow!("User transaction {} is missing from transactions", version)
+            })?;
+            Ok(EntryFunctionCall {
+                date: day,
+                entry_function_id_str,
+                sender,
+                success,
+                gas_used,
+                version,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(EntryFunctionDailyStat::aggregate(&calls, &HashMap::new()).0)
+}
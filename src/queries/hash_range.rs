@@ -0,0 +1,327 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Deterministic hashes of the rows of a version range, to check that two deployments indexing
+//! the same chain stored the same data. The rows of each table are read in primary key order and
+//! fed to an xxh3 hasher as canonical JSON: object keys sorted, addresses in their long lowercase
+//! form, and the columns that depend on when a row was written, like `inserted_at`, left out.
+
+use crate::{
+    database::PgPoolConnection, queries::index_advisor::instrument, util::standardize_address,
+};
+use anyhow::{bail, Context};
+use diesel::{
+    sql_query,
+    sql_types::{Jsonb, Text},
+    Connection, RunQueryDsl,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use xxhash_rust::xxh3::Xxh3;
+
+/// Columns that differ between deployments for the same data
+pub const EXCLUDED_COLUMNS: &[&str] = &["inserted_at"];
+/// Columns a table's rows are assigned to a range by, the first one present wins. Only columns
+/// that never change once a row is written: a `current_*` row's `last_transaction_version` moves
+/// past its range when the row is updated, so those tables can't be hashed by range.
+pub const VERSION_COLUMNS: &[&str] = &[
+    "transaction_version",
+    "version",
+    "first_transaction_version",
+];
+/// Rows fetched from the cursor at a time
+const FETCH_SIZE: usize = 1000;
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct TableHash {
+    pub rows: u64,
+    /// xxh3 of the canonicalized rows in primary key order, hex
+    pub hash: String,
+}
+
+/// Per table hashes of a version range, comparable across deployments
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct RangeManifest {
+    pub start_version: i64,
+    /// Inclusive
+    pub end_version: i64,
+    pub tables: BTreeMap<String, TableHash>,
+}
+
+impl RangeManifest {
+    /// Tables whose rows differ from `other`'s, including tables only one of them has
+    pub fn diff(&self, other: &RangeManifest) -> Vec<String> {
+        let mut tables = self
+            .tables
+            .keys()
+            .chain(other.tables.keys())
+            .filter(|table| self.tables.get(*table) != other.tables.get(*table))
+            .cloned()
+            .collect::<Vec<_>>();
+        tables.sort();
+        tables.dedup();
+        tables
+    }
+}
+
+#[derive(Debug, QueryableByName)]
+struct Column {
+    #[diesel(sql_type = Text)]
+    column_name: String,
+    #[diesel(sql_type = Text)]
+    data_type: String,
+}
+
+#[derive(Debug, QueryableByName)]
+struct PkColumn {
+    #[diesel(sql_type = Text)]
+    column_name: String,
+}
+
+#[derive(Debug, QueryableByName)]
+struct Row {
+    #[diesel(sql_type = Jsonb)]
+    row: Value,
+}
+
+/// Hashes the rows of `tables` with a version in `start_version..=end_version`
+pub fn hash_range<S: AsRef<str>>(
+    conn: &mut PgPoolConnection,
+    tables: &[S],
+    start_version: i64,
+    end_version: i64,
+) -> anyhow::Result<RangeManifest> {
+    let mut manifest = RangeManifest {
+        start_version,
+        end_version,
+        tables: BTreeMap::new(),
+    };
+    for table in tables {
+        let table = table.as_ref();
+        let hash = hash_table(conn, table, start_version, end_version)
+            .with_context(|| format!("Failed to hash {}", table))?;
+        manifest.tables.insert(table.to_string(), hash);
+    }
+    Ok(manifest)
+}
+
+/// The column of `VERSION_COLUMNS` `table`'s rows are assigned to a range by, `None` if it has
+/// none and can't be hashed
+pub fn version_column(
+    conn: &mut PgPoolConnection,
+    table: &str,
+) -> anyhow::Result<Option<&'static str>> {
+    Ok(find_version_column(&table_columns(conn, table)?))
+}
+
+fn table_columns(conn: &mut PgPoolConnection, table: &str) -> anyhow::Result<Vec<Column>> {
+    Ok(sql_query(
+        "SELECT column_name::text AS column_name, data_type::text AS data_type \
+        FROM information_schema.columns WHERE table_schema = current_schema() AND table_name = $1",
+    )
+    .bind::<Text, _>(table)
+    .load::<Column>(conn)?)
+}
+
+fn find_version_column(columns: &[Column]) -> Option<&'static str> {
+    VERSION_COLUMNS
+        .iter()
+        .find(|name| columns.iter().any(|column| column.column_name == **name))
+        .copied()
+}
+
+fn hash_table(
+    conn: &mut PgPoolConnection,
+    table: &str,
+    start_version: i64,
+    end_version: i64,
+) -> anyhow::Result<TableHash> {
+    let columns = table_columns(conn, table)?;
+    let Some(version_column) = find_version_column(&columns) else {
+        bail!("{} has no immutable version column", table);
+    };
+    let pk = sql_query(
+        "SELECT a.attname::text AS column_name FROM pg_index i \
+        JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey) \
+        WHERE i.indrelid = $1::regclass AND i.indisprimary \
+        ORDER BY array_position(i.indkey::int2[], a.attnum)",
+    )
+    .bind::<Text, _>(table)
+    .load::<PkColumn>(conn)?;
+    if pk.is_empty() {
+        bail!("{} has no primary key", table);
+    }
+    // Text keys are ordered bytewise, whatever the collation of either database
+    let order_by = pk
+        .iter()
+        .map(|key| {
+            let is_text = columns.iter().any(|column| {
+                column.column_name == key.column_name
+                    && matches!(
+                        column.data_type.as_str(),
+                        "character varying" | "text" | "character"
+                    )
+            });
+            if is_text {
+                format!("{} COLLATE \"C\"", quote_ident(&key.column_name))
+            } else {
+                quote_ident(&key.column_name)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let excluded = EXCLUDED_COLUMNS
+        .iter()
+        .map(|column| format!("'{}'", column))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let select = format!(
+        "SELECT to_jsonb(t) - ARRAY[{}]::text[] AS row FROM {} t \
+        WHERE {} BETWEEN {} AND {} ORDER BY {}",
+        excluded,
+        quote_ident(table),
+        quote_ident(version_column),
+        start_version,
+        end_version,
+        order_by
+    );
+    // A cursor only lives in its transaction
+    conn.transaction::<_, anyhow::Error, _>(|conn| {
+        instrument(
+            conn,
+            "hash_range",
+            table,
+            &[version_column],
+            sql_query(select.clone()),
+            |conn, _| hash_rows(conn, &select),
+        )
+    })
+}
+
+fn hash_rows(conn: &mut PgPoolConnection, select: &str) -> anyhow::Result<TableHash> {
+    sql_query(format!(
+        "DECLARE range_hash_rows NO SCROLL CURSOR FOR {}",
+        select
+    ))
+    .execute(conn)?;
+    let mut hasher = Xxh3::new();
+    let mut rows = 0;
+    loop {
+        let batch =
+            sql_query(format!("FETCH {} FROM range_hash_rows", FETCH_SIZE)).load::<Row>(conn)?;
+        for row in &batch {
+            let bytes = serde_json::to_vec(&canonicalize(&row.row))?;
+            // Length prefixed, so rows can't run into each other
+            hasher.update(&(bytes.len() as u64).to_le_bytes());
+            hasher.update(&bytes);
+        }
+        rows += batch.len() as u64;
+        if batch.len() < FETCH_SIZE {
+            break;
+        }
+    }
+    Ok(TableHash {
+        rows,
+        hash: format!("{:016x}", hasher.digest()),
+    })
+}
+
+/// `value` with object keys in sorted order and addresses in their long form, so that rows
+/// that mean the same serialize the same
+pub fn canonicalize(value: &Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let mut keys = object.keys().collect::<Vec<_>>();
+            keys.sort();
+            Value::Object(
+                keys.into_iter()
+                    .map(|key| (key.clone(), canonicalize(&object[key])))
+                    .collect::<Map<_, _>>(),
+            )
+        },
+        Value::Array(values) => Value::Array(values.iter().map(canonicalize).collect()),
+        Value::String(string) if is_address(string) => {
+            Value::String(standardize_address(&string.to_lowercase()))
+        },
+        _ => value.clone(),
+    }
+}
+
+/// "0x" followed by at most 64 hex digits. Short hex byte strings match as well, which is
+/// harmless as long as both sides canonicalize the same way.
+fn is_address(string: &str) -> bool {
+    string.len() > 2
+        && string.len() <= 66
+        && string.starts_with("0x")
+        && string[2..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_canonicalize() {
+        let a = json!({
+            "owner_address": "0x1",
+            "data": {"b": 1, "a": ["0xA"]},
+            "name": "0xcafe toast",
+        });
+        let b = json!({
+            "name": "0xcafe toast",
+            "data": {"a": ["0x000000000000000000000000000000000000000000000000000000000000000a"], "b": 1},
+            "owner_address": "0x0000000000000000000000000000000000000000000000000000000000000001",
+        });
+        assert_eq!(
+            serde_json::to_string(&canonicalize(&a)).unwrap(),
+            serde_json::to_string(&canonicalize(&b)).unwrap()
+        );
+        assert_eq!(canonicalize(&json!("0xcafe toast")), json!("0xcafe toast"));
+        assert_eq!(canonicalize(&json!("0x")), json!("0x"));
+    }
+
+    #[test]
+    fn test_manifest_diff() {
+        let manifest = |tables: &[(&str, &str)]| RangeManifest {
+            start_version: 0,
+            end_version: 99,
+            tables: tables
+                .iter()
+                .map(|(table, hash)| {
+                    (table.to_string(), TableHash {
+                        rows: 1,
+                        hash: hash.to_string(),
+                    })
+                })
+                .collect(),
+        };
+        let ours = manifest(&[("events", "aa"), ("transactions", "bb")]);
+        let theirs = manifest(&[("events", "aa"), ("transactions", "cc"), ("objects", "dd")]);
+        assert!(ours.diff(&ours).is_empty());
+        assert_eq!(ours.diff(&theirs), vec!["objects", "transactions"]);
+    }
+
+    #[test]
+    fn test_version_column() {
+        let Some(conn_pool) = crate::custom::test_utils::test_pool() else {
+            return;
+        };
+        let mut conn = conn_pool.get().unwrap();
+        let mut column = |table| version_column(&mut conn, table).unwrap();
+        assert_eq!(column("events"), Some("transaction_version"));
+        assert_eq!(column("transactions"), Some("version"));
+        assert_eq!(
+            column("delegated_staking_pools"),
+            Some("first_transaction_version")
+        );
+        // Updated in place
+        assert_eq!(column("current_coin_balances"), None);
+        assert_eq!(column("missing_table"), None);
+    }
+}
@@ -0,0 +1,418 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Index suggestions from how the query helpers are used. Every helper runs its query through
+//! `instrument`, naming the table and the columns it filters on. Calls and their execution time
+//! are aggregated in memory; one call in `sample_every` also has its query planned with `EXPLAIN`
+//! and flushes the aggregate, with the plan's sequential scans, into
+//! `index_advisor_observations`. Nothing is ever created: `advise_indexes` turns the helpers whose
+//! sampled plans scan a large table into `CREATE INDEX` statements for a human to review.
+//!
+//! Recording never fails a helper, errors are logged. Call `configure` with a `sample_every` of
+//! 0 to turn it off, e.g. against a read-only replica.
+
+use crate::{
+    database::PgPoolConnection, models::index_advisor_observations::IndexAdvisorObservation,
+};
+use aptos_logger::warn;
+use diesel::{
+    pg::{Pg, PgConnection},
+    query_builder::{AstPass, Query, QueryFragment, QueryId},
+    sql_query,
+    sql_types::{BigInt, Double, Jsonb, Text},
+    Connection, QueryResult, RunQueryDsl,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+const DEFAULT_SAMPLE_EVERY: u64 = 100;
+
+static SAMPLE_EVERY: AtomicU64 = AtomicU64::new(DEFAULT_SAMPLE_EVERY);
+static CALLS: AtomicU64 = AtomicU64::new(0);
+/// Calls since the last flush, by helper, table and filter columns
+static PENDING: Lazy<Mutex<HashMap<(String, String, String), Pending>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Clone, Copy, Default)]
+struct Pending {
+    calls: i64,
+    exec_ms: f64,
+}
+
+/// Plans one call in `sample_every` of every helper, 0 turns recording off
+pub fn configure(sample_every: u64) {
+    SAMPLE_EVERY.store(sample_every, Ordering::Relaxed);
+}
+
+/// Runs `run` with `query`, recording that `helper` filtered `table` on `filter_columns`
+pub fn instrument<Q, R, F>(
+    conn: &mut PgPoolConnection,
+    helper: &str,
+    table: &str,
+    filter_columns: &[&str],
+    query: Q,
+    run: F,
+) -> R
+where
+    Q: QueryFragment<Pg> + QueryId + Clone,
+    F: FnOnce(&mut PgPoolConnection, Q) -> R,
+{
+    let sample_every = SAMPLE_EVERY.load(Ordering::Relaxed);
+    if sample_every == 0 {
+        return run(conn, query);
+    }
+    let sampled = CALLS.fetch_add(1, Ordering::Relaxed) % sample_every == 0;
+    let explained = sampled.then(|| query.clone());
+    let started = Instant::now();
+    let result = run(conn, query);
+    let key = (
+        helper.to_string(),
+        table.to_string(),
+        filter_columns.join(","),
+    );
+    let pending = {
+        let mut pending = PENDING.lock().unwrap();
+        let entry = pending.entry(key.clone()).or_default();
+        entry.calls += 1;
+        entry.exec_ms += started.elapsed().as_secs_f64() * 1000.0;
+        if sampled {
+            pending.remove(&key)
+        } else {
+            None
+        }
+    };
+    if let (Some(query), Some(pending)) = (explained, pending) {
+        // A savepoint inside a helper's transaction, so a failure doesn't abort it
+        let recorded = conn
+            .transaction::<_, anyhow::Error, _>(|conn| record_sample(conn, &key, pending, query));
+        if let Err(err) = recorded {
+            warn!(
+                helper = helper,
+                table_name = table,
+                error = ?err,
+                "Failed to record an index advisor observation"
+            );
+        }
+    }
+    result
+}
+
+/// `EXPLAIN (FORMAT JSON)` of a query, which plans it without running it
+#[derive(QueryId)]
+struct Explain<Q> {
+    query: Q,
+}
+
+impl<Q> Query for Explain<Q> {
+    type SqlType = diesel::sql_types::Json;
+}
+
+impl<Q> RunQueryDsl<PgConnection> for Explain<Q> {}
+
+impl<Q: QueryFragment<Pg>> QueryFragment<Pg> for Explain<Q> {
+    fn walk_ast<'b>(&'b self, mut out: AstPass<'_, 'b, Pg>) -> QueryResult<()> {
+        out.push_sql("EXPLAIN (FORMAT JSON) ");
+        self.query.walk_ast(out.reborrow())
+    }
+}
+
+/// A sequential scan node of a plan
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SeqScan {
+    pub relation: String,
+    /// Rows the planner expects the scan to return
+    pub plan_rows: i64,
+    pub filter: Option<String>,
+}
+
+/// The sequential scans anywhere in an `EXPLAIN (FORMAT JSON)` output
+pub fn seq_scans(plan: &Value) -> Vec<SeqScan> {
+    fn walk(node: &Value, scans: &mut Vec<SeqScan>) {
+        if node["Node Type"] == "Seq Scan" {
+            if let Some(relation) = node["Relation Name"].as_str() {
+                scans.push(SeqScan {
+                    relation: relation.to_string(),
+                    plan_rows: node["Plan Rows"].as_f64().unwrap_or_default() as i64,
+                    filter: node["Filter"].as_str().map(str::to_string),
+                });
+            }
+        }
+        for child in node["Plans"].as_array().into_iter().flatten() {
+            walk(child, scans);
+        }
+    }
+    let mut scans = vec![];
+    for statement in plan.as_array().into_iter().flatten() {
+        walk(&statement["Plan"], &mut scans);
+    }
+    scans
+}
+
+fn record_sample<Q>(
+    conn: &mut PgPoolConnection,
+    (helper, table, filter_columns): &(String, String, String),
+    pending: Pending,
+    query: Q,
+) -> anyhow::Result<()>
+where
+    Q: QueryFragment<Pg> + QueryId,
+{
+    let plan = Explain { query }.get_result::<Value>(conn)?;
+    let scans = seq_scans(&plan)
+        .into_iter()
+        .filter(|scan| &scan.relation == table)
+        .collect::<Vec<_>>();
+    let max_estimated_rows = scans.iter().map(|scan| scan.plan_rows).max().unwrap_or(0);
+    sql_query(
+        "INSERT INTO index_advisor_observations AS o (helper, table_name, filter_columns, calls, \
+        total_exec_ms, sampled_plans, seq_scan_plans, max_estimated_rows, last_plan) \
+        VALUES ($1, $2, $3, $4, $5, 1, $6, $7, $8) \
+        ON CONFLICT (helper, table_name, filter_columns) DO UPDATE SET \
+        calls = o.calls + EXCLUDED.calls, \
+        total_exec_ms = o.total_exec_ms + EXCLUDED.total_exec_ms, \
+        sampled_plans = o.sampled_plans + 1, \
+        seq_scan_plans = o.seq_scan_plans + EXCLUDED.seq_scan_plans, \
+        max_estimated_rows = GREATEST(o.max_estimated_rows, EXCLUDED.max_estimated_rows), \
+        last_plan = EXCLUDED.last_plan, \
+        last_seen = NOW()",
+    )
+    .bind::<Text, _>(helper)
+    .bind::<Text, _>(table)
+    .bind::<Text, _>(filter_columns)
+    .bind::<BigInt, _>(pending.calls)
+    .bind::<Double, _>(pending.exec_ms)
+    .bind::<BigInt, _>(i64::from(!scans.is_empty()))
+    .bind::<BigInt, _>(max_estimated_rows)
+    .bind::<Jsonb, _>(json!({ "seq_scans": scans }))
+    .execute(conn)?;
+    Ok(())
+}
+
+/// An index that would spare a helper its sequential scans
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct IndexSuggestion {
+    pub helper: String,
+    pub table_name: String,
+    pub columns: Vec<String>,
+    pub statement: String,
+    /// Rows in the table, as estimated by Postgres
+    pub table_rows: i64,
+    /// Share of the sampled plans that scanned the table
+    pub seq_scan_share: f64,
+    pub calls: i64,
+    pub avg_exec_ms: f64,
+    /// Rows scanned for nothing over the observed calls, which an index would skip
+    pub estimated_rows_avoided: i64,
+}
+
+#[derive(Debug, QueryableByName)]
+struct TableRows {
+    #[diesel(sql_type = BigInt)]
+    rows: i64,
+}
+
+#[derive(Debug, QueryableByName)]
+struct IndexColumns {
+    #[diesel(sql_type = diesel::sql_types::Array<Text>)]
+    columns: Vec<String>,
+}
+
+/// `CREATE INDEX` suggestions for the helpers whose sampled plans scan a table of at least
+/// `min_table_rows` rows, most beneficial first. Filters an existing index already leads with are
+/// left out.
+pub fn advise_indexes(
+    conn: &mut PgPoolConnection,
+    min_table_rows: i64,
+) -> anyhow::Result<Vec<IndexSuggestion>> {
+    let mut suggestions = vec![];
+    for observation in IndexAdvisorObservation::get_with_seq_scans(conn)? {
+        let table_rows = sql_query(
+            "SELECT GREATEST(reltuples, 0)::bigint AS rows FROM pg_class WHERE oid = $1::regclass",
+        )
+        .bind::<Text, _>(&observation.table_name)
+        .get_result::<TableRows>(conn)?
+        .rows;
+        let indexes = sql_query(
+            "SELECT ARRAY(SELECT a.attname::text FROM unnest(i.indkey) WITH ORDINALITY AS k(attnum, n) \
+            JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = k.attnum ORDER BY k.n) \
+            AS columns FROM pg_index i WHERE i.indrelid = $1::regclass",
+        )
+        .bind::<Text, _>(&observation.table_name)
+        .load::<IndexColumns>(conn)?
+        .into_iter()
+        .map(|index| index.columns)
+        .collect::<Vec<_>>();
+        suggestions.extend(suggest(&observation, table_rows, &indexes, min_table_rows));
+    }
+    suggestions.sort_by(|a, b| b.estimated_rows_avoided.cmp(&a.estimated_rows_avoided));
+    Ok(suggestions)
+}
+
+/// The suggestion for one observation, given the table's size and the columns of its indexes
+fn suggest(
+    observation: &IndexAdvisorObservation,
+    table_rows: i64,
+    indexes: &[Vec<String>],
+    min_table_rows: i64,
+) -> Option<IndexSuggestion> {
+    let columns = observation.filter_columns();
+    if columns.is_empty() || observation.seq_scan_plans == 0 || table_rows < min_table_rows {
+        return None;
+    }
+    // An index on the same leading columns exists, the planner chose not to use it
+    if indexes.iter().any(|index| index.starts_with(&columns)) {
+        return None;
+    }
+    let seq_scan_share =
+        observation.seq_scan_plans as f64 / observation.sampled_plans.max(1) as f64;
+    let rows_per_call = (table_rows - observation.max_estimated_rows).max(0);
+    let statement = format!(
+        "CREATE INDEX CONCURRENTLY IF NOT EXISTS {}_{}_advised_index ON {} ({});",
+        observation.table_name,
+        columns.join("_"),
+        observation.table_name,
+        columns.join(", ")
+    );
+    Some(IndexSuggestion {
+        helper: observation.helper.clone(),
+        table_name: observation.table_name.clone(),
+        columns,
+        statement,
+        table_rows,
+        seq_scan_share,
+        calls: observation.calls,
+        avg_exec_ms: observation.total_exec_ms / observation.calls.max(1) as f64,
+        estimated_rows_avoided: (rows_per_call as f64 * observation.calls as f64 * seq_scan_share)
+            as i64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::new_db_pool, indexer::tailer::MIGRATIONS};
+    use diesel_migrations::MigrationHarness;
+
+    fn observation(filter_columns: &str, seq_scan_plans: i64) -> IndexAdvisorObservation {
+        IndexAdvisorObservation {
+            helper: "helper".to_string(),
+            table_name: "items".to_string(),
+            filter_columns: filter_columns.to_string(),
+            calls: 100,
+            total_exec_ms: 500.0,
+            sampled_plans: 2,
+            seq_scan_plans,
+            max_estimated_rows: 10,
+            last_plan: json!({}),
+            last_seen: chrono::Utc::now().naive_utc(),
+        }
+    }
+
+    #[test]
+    fn test_seq_scans() {
+        let plan = json!([{
+            "Plan": {
+                "Node Type": "Limit",
+                "Plan Rows": 10,
+                "Plans": [{
+                    "Node Type": "Seq Scan",
+                    "Relation Name": "items",
+                    "Plan Rows": 42.0,
+                    "Filter": "((owner)::text = 'a'::text)",
+                }, {
+                    "Node Type": "Index Scan",
+                    "Relation Name": "owners",
+                    "Plan Rows": 1,
+                }],
+            },
+        }]);
+        assert_eq!(seq_scans(&plan), vec![SeqScan {
+            relation: "items".to_string(),
+            plan_rows: 42,
+            filter: Some("((owner)::text = 'a'::text)".to_string()),
+        }]);
+    }
+
+    #[test]
+    fn test_suggest() {
+        let suggestion = suggest(&observation("owner,kind", 1), 1_000_000, &[], 10_000).unwrap();
+        assert_eq!(suggestion.columns, vec!["owner", "kind"]);
+        assert_eq!(
+            suggestion.statement,
+            "CREATE INDEX CONCURRENTLY IF NOT EXISTS items_owner_kind_advised_index ON items (owner, kind);"
+        );
+        assert_eq!(suggestion.seq_scan_share, 0.5);
+        assert_eq!(suggestion.avg_exec_ms, 5.0);
+        assert_eq!(suggestion.estimated_rows_avoided, 49_999_500);
+
+        // Small table, no scan, or an index that already leads with the columns
+        assert!(suggest(&observation("owner", 1), 100, &[], 10_000).is_none());
+        assert!(suggest(&observation("owner", 0), 1_000_000, &[], 10_000).is_none());
+        let indexes = vec![vec!["owner".to_string(), "kind".to_string()]];
+        assert!(suggest(&observation("owner", 1), 1_000_000, &indexes, 10_000).is_none());
+        assert!(suggest(&observation("kind", 1), 1_000_000, &indexes, 10_000).is_some());
+    }
+
+    #[test]
+    fn test_advise_indexes_on_seeded_table() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let pool = new_db_pool(&database_url).unwrap();
+        let mut conn = pool.get().unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        for command in [
+            "DROP TABLE IF EXISTS index_advisor_test_items",
+            "CREATE TABLE index_advisor_test_items (id BIGINT PRIMARY KEY, owner VARCHAR(66) NOT NULL)",
+            "INSERT INTO index_advisor_test_items SELECT n, '0x' || (n % 1000) FROM generate_series(1, 50000) n",
+            "ANALYZE index_advisor_test_items",
+            "DELETE FROM index_advisor_observations WHERE helper LIKE 'test_%'",
+        ] {
+            sql_query(command).execute(&mut conn).unwrap();
+        }
+        configure(1);
+        for helper in ["test_by_owner", "test_by_id"] {
+            let (column, filter) = if helper == "test_by_owner" {
+                ("owner", "owner = '0x7'")
+            } else {
+                ("id", "id = 7")
+            };
+            let query = sql_query(format!(
+                "SELECT id FROM index_advisor_test_items WHERE {}",
+                filter
+            ));
+            let rows = instrument(
+                &mut conn,
+                helper,
+                "index_advisor_test_items",
+                &[column],
+                query,
+                |conn, query| query.execute(conn),
+            )
+            .unwrap();
+            assert!(rows > 0);
+        }
+        let suggestions = advise_indexes(&mut conn, 10_000)
+            .unwrap()
+            .into_iter()
+            .filter(|suggestion| suggestion.helper.starts_with("test_"))
+            .collect::<Vec<_>>();
+        // The primary key serves the lookup by id
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].helper, "test_by_owner");
+        assert_eq!(suggestions[0].columns, vec!["owner"]);
+        sql_query("DROP TABLE index_advisor_test_items")
+            .execute(&mut conn)
+            .unwrap();
+    }
+}
@@ -0,0 +1,31 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Read helpers over the indexed tables for tools that query Postgres directly
+
+pub mod as_of;
+pub mod change_feed;
+pub mod data_dictionary;
+pub mod entry_function_stats;
+pub mod hash_range;
+pub mod index_advisor;
+pub mod proof_anchors;
+pub mod stream;
+mod table_docs;
+
+pub use as_of::{
+    get_account_resource_as_of, get_account_resources_as_of, get_table_item_as_of,
+    get_table_items_as_of, AccountResourceAsOf, TableItemAsOf,
+};
+pub use change_feed::poll_change_feed;
+pub use data_dictionary::{
+    data_dictionary, data_dictionary_json, indexed_tables, ColumnEntry, TableEntry,
+};
+pub use entry_function_stats::rederive_entry_function_stats;
+pub use hash_range::{hash_range, RangeManifest};
+pub use index_advisor::{advise_indexes, IndexSuggestion};
+pub use proof_anchors::{get_proof_anchors, ProofAnchors};
+pub use stream::{
+    stream_events, stream_transactions, stream_write_set_changes, EventFilter, StreamOptions,
+    TransactionFilter, WriteSetChangeFilter,
+};
@@ -0,0 +1,43 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{database::PgPoolConnection, queries::index_advisor::instrument, schema::transactions};
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, RunQueryDsl};
+use serde::{Deserialize, Serialize};
+
+/// The hashes of a transaction's info that an accumulator proof of it commits to, hex, 0x prefixed
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Queryable, Serialize)]
+pub struct ProofAnchors {
+    pub version: i64,
+    pub hash: String,
+    pub state_change_hash: String,
+    pub event_root_hash: String,
+    /// Only set for the transactions that end a block with a state checkpoint
+    pub state_checkpoint_hash: Option<String>,
+    pub accumulator_root_hash: String,
+}
+
+/// The proof anchors of the transaction at `version`, `None` if it isn't indexed
+pub fn get_proof_anchors(
+    conn: &mut PgPoolConnection,
+    version: i64,
+) -> anyhow::Result<Option<ProofAnchors>> {
+    let query = transactions::table
+        .filter(transactions::version.eq(version))
+        .select((
+            transactions::version,
+            transactions::hash,
+            transactions::state_change_hash,
+            transactions::event_root_hash,
+            transactions::state_checkpoint_hash,
+            transactions::accumulator_root_hash,
+        ));
+    Ok(instrument(
+        conn,
+        "get_proof_anchors",
+        "transactions",
+        &["version"],
+        query,
+        |conn, query| query.first::<ProofAnchors>(conn).optional(),
+    )?)
+}
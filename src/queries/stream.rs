@@ -0,0 +1,499 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Streams over the history tables for bulk consumers, e.g. a pipeline reading every event in
+//! version order, without pagination SQL of their own. Rows are read `fetch_size` at a time with
+//! keyset pagination on the order of the stream, so memory stays bounded however large the range;
+//! a page failing on a transient connection error is retried from the last row yielded. With
+//! `parallelism` above 1 the range is split into that many contiguous version shards, each read
+//! on its own connection: the rows of a shard come in order, the shards interleave.
+
+use crate::{
+    database::PgDbPool,
+    models::{
+        events::EventQuery, transactions::TransactionQuery, write_set_changes::WriteSetChangeQuery,
+    },
+    schema::{events, transactions, write_set_changes},
+    util::standardize_address,
+};
+use diesel::{
+    dsl::sql,
+    r2d2::PoolError,
+    result::{DatabaseErrorKind, Error as DieselError},
+    sql_types::{BigInt, Bool, Text},
+    ExpressionMethods, PgConnection, QueryDsl, QueryResult, RunQueryDsl,
+};
+use futures::{
+    stream::{self, BoxStream},
+    Stream, StreamExt,
+};
+use std::{collections::VecDeque, ops::Range, sync::Arc, time::Duration};
+
+#[derive(Clone, Debug)]
+pub struct StreamOptions {
+    /// Rows read per query
+    pub fetch_size: i64,
+    /// Version shards read at once, each on its own connection
+    pub parallelism: usize,
+    /// Retries of a page failing on a transient connection error, backing off from
+    /// `retry_backoff` and doubling
+    pub max_retries: u32,
+    pub retry_backoff: Duration,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        Self {
+            fetch_size: 1000,
+            parallelism: 1,
+            max_retries: 5,
+            retry_backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    pub account_address: Option<String>,
+    pub type_: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TransactionFilter {
+    pub type_: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct WriteSetChangeFilter {
+    pub address: Option<String>,
+    pub type_: Option<String>,
+}
+
+/// Version, index in the transaction (-1 for rows written before the column existed), then the
+/// primary key
+type EventKey = (i64, i64, String, i64, i64);
+
+/// Events of the versions in `range`, by version and then in the order they were emitted
+pub fn stream_events(
+    conn_pool: PgDbPool,
+    range: Range<i64>,
+    filter: EventFilter,
+    options: StreamOptions,
+) -> impl Stream<Item = anyhow::Result<EventQuery>> {
+    let filter = Arc::new(filter);
+    sharded(range, &options, |shard| {
+        let filter = filter.clone();
+        keyset(
+            conn_pool.clone(),
+            &options,
+            move |conn, after, limit| events_page(conn, &shard, &filter, after, limit),
+            |event: &EventQuery| {
+                (
+                    event.transaction_version,
+                    event.event_index.unwrap_or(-1),
+                    event.account_address.clone(),
+                    event.creation_number,
+                    event.sequence_number,
+                )
+            },
+        )
+    })
+}
+
+/// Transactions of the versions in `range`, by version
+pub fn stream_transactions(
+    conn_pool: PgDbPool,
+    range: Range<i64>,
+    filter: TransactionFilter,
+    options: StreamOptions,
+) -> impl Stream<Item = anyhow::Result<TransactionQuery>> {
+    let filter = Arc::new(filter);
+    sharded(range, &options, |shard| {
+        let filter = filter.clone();
+        keyset(
+            conn_pool.clone(),
+            &options,
+            move |conn, after, limit| transactions_page(conn, &shard, &filter, after, limit),
+            |txn: &TransactionQuery| txn.version,
+        )
+    })
+}
+
+/// Write set changes of the versions in `range`, by version and index
+pub fn stream_write_set_changes(
+    conn_pool: PgDbPool,
+    range: Range<i64>,
+    filter: WriteSetChangeFilter,
+    options: StreamOptions,
+) -> impl Stream<Item = anyhow::Result<WriteSetChangeQuery>> {
+    let filter = Arc::new(filter);
+    sharded(range, &options, |shard| {
+        let filter = filter.clone();
+        keyset(
+            conn_pool.clone(),
+            &options,
+            move |conn, after, limit| write_set_changes_page(conn, &shard, &filter, after, limit),
+            |wsc: &WriteSetChangeQuery| (wsc.transaction_version, wsc.index),
+        )
+    })
+}
+
+fn events_page(
+    conn: &mut PgConnection,
+    range: &Range<i64>,
+    filter: &EventFilter,
+    after: Option<&EventKey>,
+    limit: i64,
+) -> QueryResult<Vec<EventQuery>> {
+    let mut query = events::table
+        .filter(events::transaction_version.ge(range.start))
+        .filter(events::transaction_version.lt(range.end))
+        .into_boxed();
+    if let Some(account_address) = &filter.account_address {
+        query = query.filter(events::account_address.eq(standardize_address(account_address)));
+    }
+    if let Some(type_) = &filter.type_ {
+        query = query.filter(events::type_.eq(type_.clone()));
+    }
+    if let Some((version, index, account_address, creation_number, sequence_number)) = after {
+        // The version bound alone lets the planner use the version index
+        query = query
+            .filter(events::transaction_version.ge(*version))
+            .filter(
+                sql::<Bool>(
+                    "(transaction_version, COALESCE(event_index, -1), account_address, \
+                     creation_number, sequence_number) > (",
+                )
+                .bind::<BigInt, _>(*version)
+                .sql(", ")
+                .bind::<BigInt, _>(*index)
+                .sql(", ")
+                .bind::<Text, _>(account_address.clone())
+                .sql(", ")
+                .bind::<BigInt, _>(*creation_number)
+                .sql(", ")
+                .bind::<BigInt, _>(*sequence_number)
+                .sql(")"),
+            );
+    }
+    query
+        .order((
+            events::transaction_version,
+            sql::<BigInt>("COALESCE(event_index, -1)"),
+            events::account_address,
+            events::creation_number,
+            events::sequence_number,
+        ))
+        .limit(limit)
+        .load::<EventQuery>(conn)
+}
+
+fn transactions_page(
+    conn: &mut PgConnection,
+    range: &Range<i64>,
+    filter: &TransactionFilter,
+    after: Option<&i64>,
+    limit: i64,
+) -> QueryResult<Vec<TransactionQuery>> {
+    let mut query = transactions::table
+        .filter(transactions::version.ge(range.start))
+        .filter(transactions::version.lt(range.end))
+        .into_boxed();
+    if let Some(type_) = &filter.type_ {
+        query = query.filter(transactions::type_.eq(type_.clone()));
+    }
+    if let Some(version) = after {
+        query = query.filter(transactions::version.gt(*version));
+    }
+    query
+        .order(transactions::version)
+        .limit(limit)
+        .load::<TransactionQuery>(conn)
+}
+
+fn write_set_changes_page(
+    conn: &mut PgConnection,
+    range: &Range<i64>,
+    filter: &WriteSetChangeFilter,
+    after: Option<&(i64, i64)>,
+    limit: i64,
+) -> QueryResult<Vec<WriteSetChangeQuery>> {
+    let mut query = write_set_changes::table
+        .filter(write_set_changes::transaction_version.ge(range.start))
+        .filter(write_set_changes::transaction_version.lt(range.end))
+        .into_boxed();
+    if let Some(address) = &filter.address {
+        query = query.filter(write_set_changes::address.eq(standardize_address(address)));
+    }
+    if let Some(type_) = &filter.type_ {
+        query = query.filter(write_set_changes::type_.eq(type_.clone()));
+    }
+    if let Some((version, index)) = after {
+        query = query.filter(
+            sql::<Bool>("(transaction_version, index) > (")
+                .bind::<BigInt, _>(*version)
+                .sql(", ")
+                .bind::<BigInt, _>(*index)
+                .sql(")"),
+        );
+    }
+    query
+        .order((
+            write_set_changes::transaction_version,
+            write_set_changes::index,
+        ))
+        .limit(limit)
+        .load::<WriteSetChangeQuery>(conn)
+}
+
+/// One stream per shard of `range`, merged
+fn sharded<T, F>(
+    range: Range<i64>,
+    options: &StreamOptions,
+    shard: F,
+) -> BoxStream<'static, anyhow::Result<T>>
+where
+    T: Send + 'static,
+    F: Fn(Range<i64>) -> BoxStream<'static, anyhow::Result<T>>,
+{
+    let mut shards = split(range, options.parallelism);
+    if shards.len() == 1 {
+        return shard(shards.remove(0));
+    }
+    stream::select_all(shards.into_iter().map(shard)).boxed()
+}
+
+/// `range` in up to `parts` contiguous ranges of about the same length
+fn split(range: Range<i64>, parts: usize) -> Vec<Range<i64>> {
+    let len = range.end.saturating_sub(range.start).max(0);
+    let parts = (parts.max(1) as i64).min(len.max(1));
+    let size = len / parts + (len % parts != 0) as i64;
+    if size == 0 {
+        return vec![range];
+    }
+    (0..parts)
+        .map(|part| {
+            let start = range.start.saturating_add(part.saturating_mul(size));
+            start..start.saturating_add(size).min(range.end)
+        })
+        .filter(|shard| !shard.is_empty())
+        .collect()
+}
+
+/// Where a keyset stream is: the key of the last row yielded and the rest of the page
+struct Keyset<T, K> {
+    after: Option<K>,
+    rows: VecDeque<T>,
+    done: bool,
+}
+
+/// Reads pages of `fetch` after the key of the last row yielded until a page comes back short
+fn keyset<T, K, F>(
+    conn_pool: PgDbPool,
+    options: &StreamOptions,
+    fetch: F,
+    key: fn(&T) -> K,
+) -> BoxStream<'static, anyhow::Result<T>>
+where
+    T: Send + 'static,
+    K: Clone + Send + 'static,
+    F: Fn(&mut PgConnection, Option<&K>, i64) -> QueryResult<Vec<T>> + Send + Sync + 'static,
+{
+    let options = options.clone();
+    let fetch = Arc::new(fetch);
+    let state = Keyset {
+        after: None,
+        rows: VecDeque::new(),
+        done: false,
+    };
+    stream::unfold(state, move |mut state| {
+        let (conn_pool, options, fetch) = (conn_pool.clone(), options.clone(), fetch.clone());
+        async move {
+            loop {
+                if let Some(row) = state.rows.pop_front() {
+                    state.after = Some(key(&row));
+                    return Some((Ok(row), state));
+                }
+                if state.done {
+                    return None;
+                }
+                match fetch_page(&conn_pool, &options, &fetch, state.after.clone()).await {
+                    Ok(rows) => {
+                        state.done = (rows.len() as i64) < options.fetch_size;
+                        state.rows = rows.into();
+                    },
+                    Err(err) => {
+                        state.done = true;
+                        return Some((Err(err), state));
+                    },
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+/// The page after `after`, retried on transient connection errors
+async fn fetch_page<T, K, F>(
+    conn_pool: &PgDbPool,
+    options: &StreamOptions,
+    fetch: &Arc<F>,
+    after: Option<K>,
+) -> anyhow::Result<Vec<T>>
+where
+    T: Send + 'static,
+    K: Clone + Send + 'static,
+    F: Fn(&mut PgConnection, Option<&K>, i64) -> QueryResult<Vec<T>> + Send + Sync + 'static,
+{
+    let mut retries = 0;
+    loop {
+        let (conn_pool, fetch, after) = (conn_pool.clone(), fetch.clone(), after.clone());
+        let limit = options.fetch_size.max(1);
+        let result = tokio::task::spawn_blocking(move || -> anyhow::Result<Vec<T>> {
+            let mut conn = conn_pool.get()?;
+            Ok(fetch(&mut conn, after.as_ref(), limit)?)
+        })
+        .await?;
+        match result {
+            Err(err) if retries < options.max_retries && is_transient(&err) => {
+                tokio::time::sleep(
+                    options
+                        .retry_backoff
+                        .saturating_mul(2u32.saturating_pow(retries)),
+                )
+                .await;
+                retries += 1;
+            },
+            result => return result,
+        }
+    }
+}
+
+/// Errors a retry on another connection may not run into: no connection to be had, or one that
+/// broke
+fn is_transient(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<PoolError>().is_some()
+        || matches!(
+            err.downcast_ref::<DieselError>(),
+            Some(DieselError::DatabaseError(
+                DatabaseErrorKind::ClosedConnection | DatabaseErrorKind::UnableToSendCommand,
+                _
+            ))
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::new_db_pool, indexer::tailer::MIGRATIONS};
+    use diesel_migrations::MigrationHarness;
+
+    const BASE_VERSION: i64 = 9_000_000_000;
+    const VERSIONS: i64 = 250_000;
+    const EVENTS_PER_VERSION: i64 = 4;
+    const ADDRESS: &str = "0x00000000000000000000000000000000000000000000000000000000005743a4";
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split(0..10, 1), vec![0..10]);
+        assert_eq!(split(0..10, 3), vec![0..4, 4..8, 8..10]);
+        assert_eq!(split(5..7, 4), vec![5..6, 6..7]);
+        assert_eq!(split(3..3, 4), vec![3..3]);
+        assert_eq!(split(0..i64::MAX, 2).len(), 2);
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(!is_transient(&anyhow::Error::from(DieselError::NotFound)));
+        assert!(is_transient(&anyhow::Error::from(
+            DieselError::DatabaseError(
+                DatabaseErrorKind::ClosedConnection,
+                Box::new("server closed the connection".to_string()),
+            )
+        )));
+    }
+
+    /// A million events, four per version
+    fn seed_events(conn_pool: &PgDbPool) {
+        let mut conn = conn_pool.get().unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        diesel::delete(events::table.filter(events::account_address.eq(ADDRESS)))
+            .execute(&mut conn)
+            .unwrap();
+        diesel::sql_query(format!(
+            "INSERT INTO events (sequence_number, creation_number, account_address, \
+             transaction_version, transaction_block_height, type, data, event_index) \
+             SELECT v * {per} + i, 0, '{address}', {base} + v, 0, '0x1::stream::TestEvent', \
+             '{{}}'::jsonb, i FROM generate_series(0, {last}) v, generate_series(0, {per} - 1) i",
+            per = EVENTS_PER_VERSION,
+            address = ADDRESS,
+            base = BASE_VERSION,
+            last = VERSIONS - 1,
+        ))
+        .execute(&mut conn)
+        .unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_stream_events() {
+        if crate::should_skip_pg_tests() {
+            return;
+        }
+        let database_url = std::env::var("INDEXER_DATABASE_URL").unwrap();
+        let conn_pool = new_db_pool(&database_url).unwrap();
+        seed_events(&conn_pool);
+        let range = BASE_VERSION..BASE_VERSION + VERSIONS;
+        let filter = EventFilter {
+            account_address: Some(ADDRESS.to_string()),
+            ..Default::default()
+        };
+        let total = (VERSIONS * EVENTS_PER_VERSION) as usize;
+
+        // In order, and every event once
+        let mut events = Box::pin(stream_events(
+            conn_pool.clone(),
+            range.clone(),
+            filter.clone(),
+            StreamOptions {
+                fetch_size: 10_000,
+                ..Default::default()
+            },
+        ));
+        let mut previous = None;
+        let mut count = 0;
+        while let Some(event) = events.next().await {
+            let event = event.unwrap();
+            let key = (event.transaction_version, event.event_index.unwrap());
+            assert!(previous < Some(key));
+            previous = Some(key);
+            count += 1;
+        }
+        assert_eq!(count, total);
+        assert_eq!(
+            previous,
+            Some((BASE_VERSION + VERSIONS - 1, EVENTS_PER_VERSION - 1))
+        );
+
+        // Split across connections, every shard in order
+        let shards = 4;
+        let shard_len = VERSIONS / shards;
+        let mut events = Box::pin(stream_events(conn_pool, range, filter, StreamOptions {
+            fetch_size: 10_000,
+            parallelism: shards as usize,
+            ..Default::default()
+        }));
+        let mut previous = vec![None; shards as usize];
+        let mut seen = vec![false; total];
+        while let Some(event) = events.next().await {
+            let event = event.unwrap();
+            let (version, index) = (event.transaction_version, event.event_index.unwrap());
+            let shard = ((version - BASE_VERSION) / shard_len) as usize;
+            assert!(previous[shard] < Some((version, index)));
+            previous[shard] = Some((version, index));
+            let position = ((version - BASE_VERSION) * EVENTS_PER_VERSION + index) as usize;
+            assert!(!seen[position]);
+            seen[position] = true;
+        }
+        assert!(seen.iter().all(|seen| *seen));
+    }
+}
@@ -0,0 +1,1487 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Descriptions of the indexed tables for `data_dictionary`. Register a table here when adding
+//! it to the schema; `data_dictionary`'s tests fail for tables and columns without a description.
+//! Columns only need an entry of their own when `COMMON_COLUMNS` doesn't describe them well.
+
+pub struct TableDoc {
+    pub table: &'static str,
+    pub description: &'static str,
+    pub written_by: &'static [&'static str],
+    pub columns: &'static [ColumnDoc],
+}
+
+pub struct ColumnDoc {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// The API field the column is read from, or how it's derived
+    pub source: Option<&'static str>,
+}
+
+const fn col(name: &'static str, description: &'static str) -> ColumnDoc {
+    ColumnDoc {
+        name,
+        description,
+        source: None,
+    }
+}
+
+const fn api(name: &'static str, source: &'static str, description: &'static str) -> ColumnDoc {
+    ColumnDoc {
+        name,
+        description,
+        source: Some(source),
+    }
+}
+
+const DEFAULT: &[&str] = &["default_processor", "custom_default_processor"];
+const COIN: &[&str] = &["coin_processor"];
+const TOKEN: &[&str] = &["token_processor"];
+const STAKE: &[&str] = &["stake_processor", "custom_stake_processor"];
+const OBJECT: &[&str] = &["custom_object_processor"];
+const ANS: &[&str] = &["token_processor", "custom_ans_processor"];
+
+/// Descriptions of columns that mean the same in every table they're in
+pub const COMMON_COLUMNS: &[ColumnDoc] = &[
+    col("inserted_at", "When the row was written"),
+    api(
+        "transaction_version",
+        "transaction.version",
+        "Version of the transaction the row comes from",
+    ),
+    col(
+        "last_transaction_version",
+        "Version of the last transaction that changed the row",
+    ),
+    api(
+        "transaction_timestamp",
+        "transaction.timestamp",
+        "Timestamp of the transaction the row comes from",
+    ),
+    col(
+        "last_transaction_timestamp",
+        "Timestamp of the last transaction that changed the row",
+    ),
+    api(
+        "transaction_block_height",
+        "derived: block of the transaction",
+        "Height of the block the transaction is in",
+    ),
+    api(
+        "block_height",
+        "derived: block of the transaction",
+        "Height of the block the transaction is in",
+    ),
+    api(
+        "epoch",
+        "derived: epoch of the transaction's block",
+        "Epoch the transaction is in",
+    ),
+    api(
+        "write_set_change_index",
+        "transaction.changes[index]",
+        "Position of the change in the transaction's write set",
+    ),
+    api(
+        "event_index",
+        "transaction.events[index]",
+        "Position of the event in the transaction's events",
+    ),
+    api(
+        "event_account_address",
+        "event.guid.account_address",
+        "Account of the event's handle",
+    ),
+    api(
+        "event_creation_number",
+        "event.guid.creation_number",
+        "Creation number of the event's handle",
+    ),
+    api(
+        "event_sequence_number",
+        "event.sequence_number",
+        "Sequence number of the event in its handle",
+    ),
+    api(
+        "entry_function_id_str",
+        "transaction.payload.function",
+        "Entry function the transaction called, as address::module::function",
+    ),
+    col("owner_address", "Account or object that owns it"),
+    col("creator_address", "Account that created the collection"),
+    col("collection_name", "Name of the collection"),
+    col("token_name", "Name of the token"),
+    col("description", "Description set by the creator"),
+    col(
+        "collection_data_id_hash",
+        "sha256 of the collection's creator and name, identifies a v1 collection",
+    ),
+    col(
+        "token_data_id_hash",
+        "sha256 of the token's creator, collection and name, identifies a v1 token",
+    ),
+    col(
+        "property_version",
+        "Property version of a v1 token, 0 unless its properties were mutated",
+    ),
+    col(
+        "property_version_v1",
+        "Property version of a v1 token, 0 for a v2 token",
+    ),
+    col(
+        "token_data_id",
+        "Address of a v2 token object, 0x prefixed token_data_id_hash of a v1 token",
+    ),
+    col(
+        "collection_id",
+        "Address of a v2 collection object, 0x prefixed collection_data_id_hash of a v1 collection",
+    ),
+    col("token_standard", "v1 or v2"),
+    col(
+        "is_fungible_v2",
+        "Whether a v2 token is fungible, NULL for a v1 token",
+    ),
+    col(
+        "token_properties",
+        "Property map of the token, decoded to JSON",
+    ),
+    col(
+        "coin_type",
+        "Move type of the coin, e.g. 0x1::aptos_coin::AptosCoin",
+    ),
+    col(
+        "coin_type_hash",
+        "sha256 of coin_type, which can be too long to index",
+    ),
+    col(
+        "table_handle",
+        "Handle of the Move table the row was read from",
+    ),
+    col("is_deleted", "Whether the last change deleted it"),
+    api(
+        "state_key_hash",
+        "write_set_change.state_key_hash",
+        "Hash of the state key the resource is stored under",
+    ),
+    col("staking_pool_address", "Address of the stake pool"),
+    col("pool_address", "Address of the delegation pool"),
+    col("delegator_address", "Account that delegated to the pool"),
+    col(
+        "processor",
+        "Name of the processor, see the processor setting of the config",
+    ),
+    col("start_version", "First version of the range"),
+    col("end_version", "Last version of the range"),
+    col("table_name", "Name of an indexed table"),
+    col("decimals", "Number of decimals the amounts are shown with"),
+];
+
+pub const TABLES: &[TableDoc] = &[
+    TableDoc {
+        table: "account_derivations",
+        description: "Addresses derived from another address rather than from a key: resource accounts and objects",
+        written_by: OBJECT,
+        columns: &[
+            col("derived_address", "The resource account or object"),
+            col("derivation_kind", "resource_account or object"),
+            api(
+                "source_address",
+                "derived: creator of the resource account, first owner of the object",
+                "The account or object the address was derived from, when known",
+            ),
+            api(
+                "seed",
+                "transaction.payload.arguments[0] of 0x1::resource_account entry functions",
+                "Seed of a resource account, hex, when the transaction that created it shows it",
+            ),
+            col(
+                "transaction_version",
+                "First version the derivation was seen at",
+            ),
+        ],
+    },
+    TableDoc {
+        table: "account_storage_deposits",
+        description: "Running storage deposits per payer, see custom::driver::storage_usage",
+        written_by: &["custom_default_processor"],
+        columns: &[
+            col("account_address", "The fee payer, or the sender of transactions without one"),
+            api(
+                "total_deposited_octas",
+                "sum of FeeStatement.storage_fee_octas",
+                "Storage fees the account paid",
+            ),
+            api(
+                "total_refunded_octas",
+                "sum of FeeStatement.storage_fee_refund_octas",
+                "Storage refunds the account got",
+            ),
+            col(
+                "deposit_balance_octas",
+                "Fees less refunds, derived again from transaction_storage_usage when a refund exceeds it; an upper bound of the refundable deposit",
+            ),
+        ],
+    },
+    TableDoc {
+        table: "account_transactions",
+        description: "Accounts each transaction touched: as its sender or a signer, or through a resource, module or event of the account or of an object it owns",
+        written_by: &["coin_processor", "custom_default_processor"],
+        columns: &[col("account_address", "An account the transaction touched")],
+    },
+    TableDoc {
+        table: "admin_audit_log",
+        description: "Requests to the admin server that launched or cancelled an operation, or were rejected, see custom::driver::admin",
+        written_by: &["custom::driver::admin"],
+        columns: &[
+            col("id", "Sequence id of the request"),
+            col("caller", "Name of the caller's token"),
+            col("action", "Kind of the operation launched, or cancel"),
+            col("parameters", "Body of the request, or the operation to cancel"),
+            col("operation_id", "Operation launched or cancelled"),
+            col("outcome", "accepted or rejected"),
+            col("error", "Why the request was rejected"),
+            col("requested_at", "When the request was made"),
+        ],
+    },
+    TableDoc {
+        table: "anomalies",
+        description: "Transactions that look wrong in a way no single row shows, see custom::driver::duplicate_transactions",
+        written_by: &["custom::driver::duplicate_transactions"],
+        columns: &[
+            col("kind", "What's wrong: duplicate_sequence_number"),
+            col("policy", "What was done about it: keep_both, keep_lower or fail"),
+            col("message", "What's wrong with the transaction"),
+            col("details", "The transaction and what it conflicts with, e.g. another version"),
+        ],
+    },
+    TableDoc {
+        table: "app_scope_addresses",
+        description: "Module addresses each app-scoped processor indexes, see custom::driver::app_scope",
+        written_by: &["custom::driver::app_scope"],
+        columns: &[
+            col("processor", "Processor scoped to the address"),
+            col("address", "Module address of an app, from the app_scope config"),
+            col("backfilled", "False while the versions before the address was added are still to be backfilled"),
+            col("added_at_version", "Watermark of the processor when the address was added"),
+        ],
+    },
+    TableDoc {
+        table: "app_scope_table_handles",
+        description: "Table handles reachable from the scoped apps' resources, whose items are in scope, see custom::driver::app_scope",
+        written_by: &["custom::driver::app_scope"],
+        columns: &[
+            col("handle", "The table handle"),
+            col("discovered_from", "Type of the resource, or handle of the table item, the handle was found in"),
+        ],
+    },
+    TableDoc {
+        table: "asset_transfers",
+        description: "Who sent what to whom in successful user transactions, across coin v1, fungible assets and tokens v1 and v2, see custom::driver::asset_transfers",
+        written_by: COIN,
+        columns: &[
+            col("transfer_index", "Position of the transfer in the transaction, in the order of the events completing them"),
+            col("standard", "coin_v1, fungible_asset, token_v1 or token_v2"),
+            col("asset_id", "Coin type, fungible asset metadata address, token v1 data id hash or token v2 object address"),
+            col("property_version_v1", "Property version of a token v1, 0 otherwise"),
+            col("from_address", "Sender, null for mints and unmatched deposits"),
+            col("to_address", "Recipient, null for burns and unmatched withdrawals"),
+            col("amount", "Amount of a fungible asset, number of tokens"),
+            col("kind", "direct, mint, burn, airdrop_claim, unmatched_deposit or unmatched_withdrawal"),
+            col("event_index", "Event completing the transfer: the later of its withdrawal and deposit"),
+        ],
+    },
+    TableDoc {
+        table: "backfill_windows",
+        description: "Version ranges a backfill may overwrite current rows in, see custom::driver::backfill_guard",
+        written_by: &["custom::driver::backfill_guard", "custom::driver::ledger_reset"],
+        columns: &[
+            col("id", "Sequence id of the window"),
+            col("processor", "Processor the window applies to"),
+            col("created_by", "Who opened the window"),
+            col("created_at", "When the window was opened"),
+            col("expires_at", "When the window stops applying"),
+        ],
+    },
+    TableDoc {
+        table: "balance_anomalies",
+        description: "Deltas that would have taken a balance the indexer keeps below zero, see custom::driver::balance_guard",
+        written_by: &["custom::driver::storage_usage"],
+        columns: &[
+            col("balance_table", "Table of the balance, e.g. account_storage_deposits"),
+            col("account_address", "Account of the balance"),
+            col("asset", "Asset of the balance, 0x1::aptos_coin::AptosCoin for the storage deposits"),
+            col("transaction_version", "Version of the delta"),
+            col("processor", "Processor that applied the delta"),
+            col("expected_delta", "The delta, negative"),
+            col("stored_balance", "The balance before the delta"),
+            col("rederived_balance", "The balance derived again from its history, once healed"),
+            col("healed_at", "When the balance was derived again, null until then"),
+        ],
+    },
+    TableDoc {
+        table: "block_metadata_transactions",
+        description: "Block metadata transactions, one per block",
+        written_by: DEFAULT,
+        columns: &[
+            api("version", "transaction.version", "Version of the transaction"),
+            api("id", "transaction.id", "Hash of the block"),
+            api("round", "transaction.round", "Consensus round of the block"),
+            api("epoch", "transaction.epoch", "Epoch of the block"),
+            api(
+                "previous_block_votes_bitvec",
+                "transaction.previous_block_votes_bitvec",
+                "Validators that voted for the previous block",
+            ),
+            api("proposer", "transaction.proposer", "Validator that proposed the block"),
+            api(
+                "failed_proposer_indices",
+                "transaction.failed_proposer_indices",
+                "Validators that failed to propose in the rounds before",
+            ),
+            api("timestamp", "transaction.timestamp", "Timestamp of the block"),
+        ],
+    },
+    TableDoc {
+        table: "change_feed",
+        description: "Rows written by the processors, in commit order, for consumers that poll Postgres",
+        written_by: &["custom::driver::change_feed"],
+        columns: &[
+            col("id", "Sequence id, the cursor of the feed"),
+            col("model", "Table the row was written to"),
+            col(
+                "transaction_version",
+                "Version of the transaction the row comes from, if it has one",
+            ),
+            col("pk", "Primary key of the written row, as a JSON object"),
+            col("operation", "insert, upsert or update"),
+        ],
+    },
+    TableDoc {
+        table: "coin_activities",
+        description: "Coin deposits, withdrawals and gas fees, one row per event",
+        written_by: COIN,
+        columns: &[
+            col("owner_address", "Account the coins moved in or out of"),
+            api("amount", "event.data.amount", "Amount moved, in the coin's smallest unit"),
+            api(
+                "activity_type",
+                "event.type",
+                "Type of the event, or 0x1::aptos_coin::GasFeeEvent for the gas fee",
+            ),
+            col("is_gas_fee", "Whether the row is the gas fee of the transaction"),
+            api(
+                "is_transaction_success",
+                "transaction.success",
+                "Whether the transaction succeeded",
+            ),
+        ],
+    },
+    TableDoc {
+        table: "coin_balances",
+        description: "Coin balance of an account after each transaction that changed it",
+        written_by: COIN,
+        columns: &[api(
+            "amount",
+            "CoinStore.coin.value",
+            "Balance, in the coin's smallest unit",
+        )],
+    },
+    TableDoc {
+        table: "coin_infos",
+        description: "Coin types and their metadata, from 0x1::coin::CoinInfo",
+        written_by: COIN,
+        columns: &[
+            col(
+                "transaction_version_created",
+                "Version the coin was first seen at",
+            ),
+            col("creator_address", "Account that published the coin's module"),
+            api("name", "CoinInfo.name", "Name of the coin"),
+            api("symbol", "CoinInfo.symbol", "Symbol of the coin"),
+            api("decimals", "CoinInfo.decimals", "Number of decimals of the coin"),
+            col(
+                "transaction_created_timestamp",
+                "Timestamp of the version the coin was first seen at",
+            ),
+            api(
+                "supply_aggregator_table_handle",
+                "CoinInfo.supply",
+                "Table of the aggregator that tracks the supply, if any",
+            ),
+            api(
+                "supply_aggregator_table_key",
+                "CoinInfo.supply",
+                "Key of the supply in that table",
+            ),
+        ],
+    },
+    TableDoc {
+        table: "coin_supply",
+        description: "Supply of APT after each transaction that changed it",
+        written_by: COIN,
+        columns: &[
+            api(
+                "supply",
+                "aggregator table item of CoinInfo.supply",
+                "Total supply, in the coin's smallest unit",
+            ),
+            col("transaction_epoch", "Epoch of the transaction"),
+        ],
+    },
+    TableDoc {
+        table: "collection_datas",
+        description: "v1 collections after each transaction that changed them",
+        written_by: TOKEN,
+        columns: &[
+            api("metadata_uri", "CollectionData.uri", "URI of the collection's metadata"),
+            api("supply", "CollectionData.supply", "Number of token datas in the collection"),
+            api("maximum", "CollectionData.maximum", "Maximum supply, 0 for unlimited"),
+            api(
+                "maximum_mutable",
+                "CollectionData.mutability_config",
+                "Whether the maximum can change",
+            ),
+            api(
+                "uri_mutable",
+                "CollectionData.mutability_config",
+                "Whether the URI can change",
+            ),
+            api(
+                "description_mutable",
+                "CollectionData.mutability_config",
+                "Whether the description can change",
+            ),
+        ],
+    },
+    TableDoc {
+        table: "collections_v2",
+        description: "v1 and v2 collections after each write of their resource",
+        written_by: TOKEN,
+        columns: &[
+            col("uri", "URI of the collection's metadata"),
+            col("current_supply", "Number of tokens in the collection"),
+            col("max_supply", "Maximum supply, NULL for unlimited"),
+            col("total_minted_v2", "Number of tokens ever minted in a v2 collection"),
+            col("mutable_description", "Whether the description can change"),
+            col("mutable_uri", "Whether the URI can change"),
+            col("table_handle_v1", "Collections table of a v1 collection"),
+        ],
+    },
+    TableDoc {
+        table: "current_ans_lookup",
+        description: "Latest target of every Aptos Names domain and subdomain, expired ones included",
+        written_by: ANS,
+        columns: &[
+            col("domain", "Domain, without .apt"),
+            col("subdomain", "Subdomain, empty for the domain itself"),
+            col("registered_address", "Address the name points to, if any"),
+            col(
+                "expiration_timestamp",
+                "When the name stops resolving, for a subdomain the earlier of its own and its domain's",
+            ),
+            col("token_name", "Name of the token of the name"),
+            col("is_primary", "Whether the name is the primary name of registered_address"),
+            col("record_expiration_timestamp", "When the name's own registration expires"),
+        ],
+    },
+    TableDoc {
+        table: "current_asset_stores",
+        description: "Every coin store and fungible store, live or deleted, for listings of the assets an account holds",
+        written_by: OBJECT,
+        columns: &[
+            col(
+                "store_address",
+                "The owner's address for a coin store, the store object's for a fungible store",
+            ),
+            api(
+                "asset_type",
+                "CoinStore type argument, FungibleStore.metadata",
+                "Coin type for a coin store, metadata address for a fungible store",
+            ),
+            col("store_kind", "coin or fungible_asset"),
+            api(
+                "is_frozen",
+                "CoinStore.frozen, FungibleStore.frozen",
+                "Whether the store is frozen",
+            ),
+            col(
+                "created_transaction_version",
+                "Version the store was last created at",
+            ),
+            col(
+                "deleted_transaction_version",
+                "Version the store was deleted at, NULL while it exists",
+            ),
+        ],
+    },
+    TableDoc {
+        table: "current_coin_balances",
+        description: "Latest coin balance of every account and coin",
+        written_by: COIN,
+        columns: &[api(
+            "amount",
+            "CoinStore.coin.value",
+            "Balance, in the coin's smallest unit",
+        )],
+    },
+    TableDoc {
+        table: "current_collection_datas",
+        description: "Latest state of every v1 collection",
+        written_by: TOKEN,
+        columns: &[
+            api("metadata_uri", "CollectionData.uri", "URI of the collection's metadata"),
+            api("supply", "CollectionData.supply", "Number of token datas in the collection"),
+            api("maximum", "CollectionData.maximum", "Maximum supply, 0 for unlimited"),
+            api(
+                "maximum_mutable",
+                "CollectionData.mutability_config",
+                "Whether the maximum can change",
+            ),
+            api(
+                "uri_mutable",
+                "CollectionData.mutability_config",
+                "Whether the URI can change",
+            ),
+            api(
+                "description_mutable",
+                "CollectionData.mutability_config",
+                "Whether the description can change",
+            ),
+        ],
+    },
+    TableDoc {
+        table: "current_collections_v2",
+        description: "Latest state of every v1 and v2 collection",
+        written_by: TOKEN,
+        columns: &[
+            col("uri", "URI of the collection's metadata"),
+            col("current_supply", "Number of tokens in the collection"),
+            col("max_supply", "Maximum supply, NULL for unlimited"),
+            col("total_minted_v2", "Number of tokens ever minted in a v2 collection"),
+            col("mutable_description", "Whether the description can change"),
+            col("mutable_uri", "Whether the URI can change"),
+            col("table_handle_v1", "Collections table of a v1 collection"),
+        ],
+    },
+    TableDoc {
+        table: "current_delegated_staking_pool_balances",
+        description: "Latest balance of every delegation pool",
+        written_by: STAKE,
+        columns: &[
+            api(
+                "total_coins",
+                "DelegationPool.active_shares.total_coins",
+                "Active stake of the pool",
+            ),
+            api(
+                "total_shares",
+                "DelegationPool.active_shares.total_shares",
+                "Active shares of the pool",
+            ),
+            api(
+                "operator_commission_percentage",
+                "DelegationPool.operator_commission_percentage",
+                "Commission of the operator, in hundredths of a percent",
+            ),
+            col("inactive_table_handle", "Table of the inactive shares"),
+            col("active_table_handle", "Table of the active shares"),
+        ],
+    },
+    TableDoc {
+        table: "current_delegator_balances",
+        description: "Latest shares of every delegator in every delegation pool",
+        written_by: STAKE,
+        columns: &[
+            col("pool_type", "active_shares or inactive_shares"),
+            col("table_handle", "Table the shares are stored in"),
+            col("shares", "Shares of the delegator"),
+            col(
+                "parent_table_handle",
+                "Active shares table of the pool, the same for every pool_type",
+            ),
+        ],
+    },
+    TableDoc {
+        table: "current_move_resources",
+        description: "Latest state of every resource, by address and type",
+        written_by: &["custom_default_processor"],
+        columns: &[
+            api("address", "write_resource.address", "Account or object the resource is stored at"),
+            api("type", "write_resource.data.type", "Move type of the resource"),
+            col("module", "Module the type is declared in"),
+            col("name", "Name of the type, without its generic params"),
+            col("generic_type_params", "Generic type params of the type"),
+            api("data", "write_resource.data.data", "Value of the resource, decoded, NULL once deleted"),
+            col("state_key_hash", "Hash of the resource's state key"),
+            col("is_deleted", "Whether the latest change deleted the resource"),
+        ],
+    },
+    TableDoc {
+        table: "current_objects",
+        description: "Latest state of every object",
+        written_by: &[
+            "default_processor",
+            "custom_default_processor",
+            "custom_object_processor",
+        ],
+        columns: &[
+            col("object_address", "Address of the object"),
+            api("owner_address", "ObjectCore.owner", "Direct owner of the object"),
+            api(
+                "allow_ungated_transfer",
+                "ObjectCore.allow_ungated_transfer",
+                "Whether the owner can transfer the object without a transfer ref",
+            ),
+            api(
+                "last_guid_creation_num",
+                "ObjectCore.guid_creation_num",
+                "Next creation number of the object's event handles",
+            ),
+            col(
+                "ultimate_owner",
+                "First owner up the ownership chain that isn't a live object, see models::object_ownership",
+            ),
+            col(
+                "ownership_depth",
+                "Number of ownership edges up to the ultimate owner",
+            ),
+        ],
+    },
+    TableDoc {
+        table: "current_staking_pool_voter",
+        description: "Latest voter and operator of every stake pool",
+        written_by: STAKE,
+        columns: &[
+            api("voter_address", "StakePool.delegated_voter", "Delegated voter of the pool"),
+            api("operator_address", "StakePool.operator_address", "Operator of the pool"),
+        ],
+    },
+    TableDoc {
+        table: "current_table_items",
+        description: "Latest value of every Move table item",
+        written_by: DEFAULT,
+        columns: &[
+            api("key_hash", "derived: sha256 of key", "Hash of the key, which can be too long to index"),
+            api("key", "write_table_item.key", "Key of the item, hex encoded BCS"),
+            api("decoded_key", "write_table_item.data.key", "Key of the item, decoded"),
+            api(
+                "decoded_value",
+                "write_table_item.data.value",
+                "Value of the item, decoded, NULL once deleted",
+            ),
+        ],
+    },
+    TableDoc {
+        table: "current_token_datas",
+        description: "Latest state of every v1 token data",
+        written_by: TOKEN,
+        columns: &[
+            api("name", "TokenData.name", "Name of the token"),
+            api("maximum", "TokenData.maximum", "Maximum supply, 0 for unlimited"),
+            api("supply", "TokenData.supply", "Current supply"),
+            api(
+                "largest_property_version",
+                "TokenData.largest_property_version",
+                "Largest property version minted",
+            ),
+            api("metadata_uri", "TokenData.uri", "URI of the token's metadata"),
+            api("payee_address", "TokenData.royalty.payee_address", "Receiver of the royalties"),
+            api(
+                "royalty_points_numerator",
+                "TokenData.royalty.royalty_points_numerator",
+                "Numerator of the royalty share",
+            ),
+            api(
+                "royalty_points_denominator",
+                "TokenData.royalty.royalty_points_denominator",
+                "Denominator of the royalty share",
+            ),
+            api("maximum_mutable", "TokenData.mutability_config", "Whether the maximum can change"),
+            api("uri_mutable", "TokenData.mutability_config", "Whether the URI can change"),
+            api(
+                "description_mutable",
+                "TokenData.mutability_config",
+                "Whether the description can change",
+            ),
+            api(
+                "properties_mutable",
+                "TokenData.mutability_config",
+                "Whether the properties can change",
+            ),
+            api("royalty_mutable", "TokenData.mutability_config", "Whether the royalty can change"),
+            api(
+                "default_properties",
+                "TokenData.default_properties",
+                "Default property map of the token, decoded to JSON",
+            ),
+        ],
+    },
+    TableDoc {
+        table: "current_token_datas_v2",
+        description: "Latest state of every v1 and v2 token",
+        written_by: TOKEN,
+        columns: &[
+            col("maximum", "Maximum supply, NULL for unlimited"),
+            col("supply", "Current supply"),
+            col(
+                "largest_property_version_v1",
+                "Largest property version minted of a v1 token",
+            ),
+            col("token_uri", "URI of the token's metadata"),
+        ],
+    },
+    TableDoc {
+        table: "current_token_ownerships",
+        description: "Latest amount of every v1 token held by every owner",
+        written_by: TOKEN,
+        columns: &[
+            col("name", "Name of the token"),
+            col("amount", "Number of tokens held"),
+            col("table_type", "Type of the table the tokens are held in"),
+        ],
+    },
+    TableDoc {
+        table: "current_token_ownerships_v2",
+        description: "Latest amount of every v1 and v2 token held by every owner",
+        written_by: TOKEN,
+        columns: &[
+            col(
+                "storage_id",
+                "Token store table of a v1 token, token object address of a v2 token",
+            ),
+            col("amount", "Number of tokens held"),
+            col("table_type_v1", "Type of the table a v1 token is held in"),
+            col(
+                "token_properties_mutated_v1",
+                "Properties of a v1 token that differ from its token data's",
+            ),
+            col("is_soulbound_v2", "Whether a v2 token can't be transferred"),
+            col(
+                "non_transferrable_by_owner",
+                "Whether the owner can't transfer the v2 token object",
+            ),
+        ],
+    },
+    TableDoc {
+        table: "current_token_pending_claims",
+        description: "Latest v1 token offers waiting to be claimed",
+        written_by: TOKEN,
+        columns: &[
+            col("from_address", "Account that offered the token"),
+            col("to_address", "Account the token is offered to"),
+            col("name", "Name of the token"),
+            col("amount", "Number of tokens offered, 0 once claimed or cancelled"),
+            col("table_handle", "Pending claims table of the offering account"),
+        ],
+    },
+    TableDoc {
+        table: "current_token_v2_metadata",
+        description: "Latest value of the non framework resources of every v2 token object",
+        written_by: TOKEN,
+        columns: &[
+            col("object_address", "Address of the token object"),
+            api("resource_type", "write_resource.data.type", "Type of the resource"),
+            api("data", "write_resource.data.data", "Value of the resource"),
+        ],
+    },
+    TableDoc {
+        table: "dead_letter_rows",
+        description: "Rows left out of an insert for a value over its column's size limit, see custom::driver::row_limits",
+        written_by: &["custom::driver::row_limits"],
+        columns: &[
+            col("table_name", "Table the row was for"),
+            col("row_hash", "sha256 of the row as json"),
+            col("column_name", "First column whose value is over its limit"),
+            col("column_bytes", "Size of the value in bytes"),
+            col("max_bytes", "Limit of the column in bytes"),
+            col("row_data", "The row as json"),
+        ],
+    },
+    TableDoc {
+        table: "delegated_staking_activities",
+        description: "Delegation pool events: adding, unlocking, reactivating and withdrawing stake",
+        written_by: STAKE,
+        columns: &[
+            api("event_type", "event.type", "Type of the event"),
+            api("amount", "event.data", "Amount of stake the event moved"),
+        ],
+    },
+    TableDoc {
+        table: "delegated_staking_pool_balances",
+        description: "Balance of a delegation pool after each transaction that changed it",
+        written_by: STAKE,
+        columns: &[
+            api(
+                "total_coins",
+                "DelegationPool.active_shares.total_coins",
+                "Active stake of the pool",
+            ),
+            api(
+                "total_shares",
+                "DelegationPool.active_shares.total_shares",
+                "Active shares of the pool",
+            ),
+            api(
+                "operator_commission_percentage",
+                "DelegationPool.operator_commission_percentage",
+                "Commission of the operator, in hundredths of a percent",
+            ),
+            col("inactive_table_handle", "Table of the inactive shares"),
+            col("active_table_handle", "Table of the active shares"),
+        ],
+    },
+    TableDoc {
+        table: "delegated_staking_pools",
+        description: "Every delegation pool",
+        written_by: STAKE,
+        columns: &[col(
+            "first_transaction_version",
+            "Version the pool was first seen at",
+        )],
+    },
+    TableDoc {
+        table: "dex_pools",
+        description: "Pools of the supported DEXes, see the dex setting of the config",
+        written_by: &["custom_dex_processor"],
+        columns: &[
+            api("pool", "write_resource.data.type", "Type of the pool resource"),
+            col("protocol", "Name of the DEX"),
+            col("pool_address", "Account the pool resource is stored at"),
+            col("coin_x", "First coin type of the pool"),
+            col("coin_y", "Second coin type of the pool"),
+            col("first_seen_version", "Version the pool was first seen at"),
+        ],
+    },
+    TableDoc {
+        table: "dex_swaps",
+        description: "Swaps on the supported DEXes, one row per swap event",
+        written_by: &["custom_dex_processor"],
+        columns: &[
+            col("protocol", "Name of the DEX"),
+            col("pool", "Type of the pool resource"),
+            col("coin_in", "Coin type sold"),
+            col("coin_out", "Coin type bought"),
+            api("amount_in", "event.data", "Amount sold, in the coin's smallest unit"),
+            api("amount_out", "event.data", "Amount bought, in the coin's smallest unit"),
+            api("sender", "transaction.sender", "Sender of the transaction"),
+        ],
+    },
+    TableDoc {
+        table: "enrichment_progress",
+        description: "Progress of the enrichers that fill in columns of rows already indexed",
+        written_by: &["custom::enrichment"],
+        columns: &[
+            col("enricher_name", "Name of the enricher"),
+            col("table_name", "Table the enricher fills in"),
+            col("last_cursor", "Position of the last enriched row"),
+            col("rows_enriched", "Number of rows enriched so far"),
+            col("completed_at", "When the enricher reached the end, NULL while running"),
+            col("last_updated", "When the progress was last written"),
+        ],
+    },
+    TableDoc {
+        table: "entry_function_daily_stats",
+        description: "Calls of every entry function per UTC day, see custom::driver::entry_function_stats",
+        written_by: &["custom_default_processor"],
+        columns: &[
+            api("date", "derived: UTC date of transaction.timestamp", "UTC day of the calls' blocks"),
+            col("call_count", "User transactions calling the function that day"),
+            col("success_count", "Calls that succeeded"),
+            col("distinct_senders_sketch", "HyperLogLog registers of the senders, see util::hyperloglog"),
+            col("distinct_senders", "Estimated number of distinct senders, within about 2%"),
+            api("total_gas_used", "transaction.gas_used", "Gas units used by the calls"),
+        ],
+    },
+    TableDoc {
+        table: "events",
+        description: "Every event emitted by a transaction",
+        written_by: DEFAULT,
+        columns: &[
+            api("sequence_number", "event.sequence_number", "Sequence number in the event's handle"),
+            api("creation_number", "event.guid.creation_number", "Creation number of the event's handle"),
+            api("account_address", "event.guid.account_address", "Account of the event's handle"),
+            api("type", "event.type", "Move type of the event"),
+            api("data", "event.data", "Payload of the event, decoded"),
+            col("event_account_address", "Address the event's type is declared at, NULL if it can't be parsed"),
+            col("event_module", "Module the event's type is declared in"),
+            col("event_name", "Name of the event's type, without its generic params"),
+            col("event_type_params", "Top level generic type params of the event's type, as a JSON array"),
+        ],
+    },
+    TableDoc {
+        table: "index_advisor_observations",
+        description: "How the query helpers filter the tables, for index suggestions, see queries::index_advisor",
+        written_by: &["queries::index_advisor"],
+        columns: &[
+            col("helper", "Name of the query helper"),
+            col("filter_columns", "Columns the helper filters on, comma separated"),
+            col("calls", "Number of calls recorded"),
+            col("total_exec_ms", "Execution time of the recorded calls"),
+            col("sampled_plans", "Number of calls planned with EXPLAIN"),
+            col("seq_scan_plans", "Number of sampled plans with a sequential scan"),
+            col("max_estimated_rows", "Largest row estimate of a sampled plan"),
+            col("last_plan", "Last sampled plan"),
+            col("last_seen", "When the helper was last recorded"),
+        ],
+    },
+    TableDoc {
+        table: "indexer_column_stats",
+        description: "Statistics of the values written to each column, per window, see custom::driver::column_stats",
+        written_by: &["custom::driver::column_stats"],
+        columns: &[
+            col("column_name", "Name of the column"),
+            col("window_end", "End of the window"),
+            col("window_start", "Start of the window"),
+            col("rows_seen", "Number of rows written in the window"),
+            col("rows_sampled", "Number of rows the statistics are computed from"),
+            col("null_ratio", "Share of the sampled values that are NULL"),
+            col("distinct_estimate", "Estimated number of distinct values"),
+            col("top_values", "Most frequent sampled values and their counts"),
+        ],
+    },
+    TableDoc {
+        table: "indexer_status",
+        description: "Whether the indexer of each database is up. Kept from the upstream schema, nothing writes it",
+        written_by: &[],
+        columns: &[
+            col("db", "Name of the database"),
+            col("is_indexer_up", "Whether the indexer is up"),
+        ],
+    },
+    TableDoc {
+        table: "ledger_infos",
+        description: "Chain the database was indexed from",
+        written_by: &["indexer::tailer", "custom::driver::ledger_reset"],
+        columns: &[
+            api("chain_id", "ledger_info.chain_id", "Id of the chain"),
+            col("genesis_hash", "Hash of the chain's genesis transaction"),
+        ],
+    },
+    TableDoc {
+        table: "ledger_resets",
+        description: "Resets of the chain detected at startup and what was done about them, see custom::driver::ledger_reset",
+        written_by: &["custom::driver::ledger_reset"],
+        columns: &[
+            col("id", "Sequence id of the reset"),
+            col("chain_id", "Id of the chain"),
+            col("previous_genesis_hash", "Genesis hash the database was indexed from"),
+            col("new_genesis_hash", "Genesis hash of the chain after the reset"),
+            col("action", "halt or wipe"),
+            col("truncated_tables", "Tables emptied because of the reset"),
+            col("processor", "Processor that detected the reset"),
+            col("created_at", "When the reset was detected"),
+        ],
+    },
+    TableDoc {
+        table: "move_modules",
+        description: "Move modules after each write or delete",
+        written_by: DEFAULT,
+        columns: &[
+            api("name", "write_module.data.abi.name", "Name of the module"),
+            api("address", "write_module.address", "Account the module is published at"),
+            api("bytecode", "write_module.data.bytecode", "Bytecode of the module"),
+            api("friends", "write_module.data.abi.friends", "Friend modules"),
+            api(
+                "exposed_functions",
+                "write_module.data.abi.exposed_functions",
+                "Functions of the module's ABI",
+            ),
+            api("structs", "write_module.data.abi.structs", "Structs of the module's ABI"),
+        ],
+    },
+    TableDoc {
+        table: "move_resources",
+        description: "Move resources after each write or delete",
+        written_by: DEFAULT,
+        columns: &[
+            api("name", "write_resource.data.type.name", "Name of the resource's struct"),
+            api("address", "write_resource.address", "Account the resource is stored at"),
+            api("type", "write_resource.data.type", "Move type of the resource"),
+            api("module", "write_resource.data.type.module", "Module of the resource's struct"),
+            api(
+                "generic_type_params",
+                "write_resource.data.type.generic_type_params",
+                "Type arguments of the resource",
+            ),
+            api("data", "write_resource.data.data", "Value of the resource, NULL once deleted"),
+        ],
+    },
+    TableDoc {
+        table: "nft_points",
+        description: "Points of the NFT points contract, see the nft_points_contract setting",
+        written_by: TOKEN,
+        columns: &[
+            col("token_name", "Name of the token the points are for"),
+            api(
+                "point_type",
+                "transaction.payload.arguments[3]",
+                "Type of the points",
+            ),
+            api("amount", "transaction.payload.arguments[2]", "Number of points"),
+        ],
+    },
+    TableDoc {
+        table: "object_ownership_edges",
+        description: "Latest direct owner of every object, see models::object_ownership",
+        written_by: OBJECT,
+        columns: &[
+            col("object_address", "Address of the object"),
+            api("owner_address", "ObjectCore.owner", "Direct owner of the object"),
+        ],
+    },
+    TableDoc {
+        table: "objects",
+        description: "Objects after each write or delete",
+        written_by: &[
+            "default_processor",
+            "custom_default_processor",
+            "custom_object_processor",
+        ],
+        columns: &[
+            col("object_address", "Address of the object"),
+            api("owner_address", "ObjectCore.owner", "Direct owner of the object"),
+            api(
+                "guid_creation_num",
+                "ObjectCore.guid_creation_num",
+                "Next creation number of the object's event handles",
+            ),
+            api(
+                "allow_ungated_transfer",
+                "ObjectCore.allow_ungated_transfer",
+                "Whether the owner can transfer the object without a transfer ref",
+            ),
+        ],
+    },
+    TableDoc {
+        table: "onchain_config_changes",
+        description: "Changes to the on-chain configs stored at 0x1",
+        written_by: &["custom_onchain_config_processor"],
+        columns: &[
+            col(
+                "config_type",
+                "gas_schedule, features, consensus_config or execution_config",
+            ),
+            api("resource_type", "write_resource.data.type", "Type of the config resource"),
+            col("value", "Value of the config, decoded"),
+            col("diff", "Leaves added, removed and changed since the previous value"),
+        ],
+    },
+    TableDoc {
+        table: "operations_log",
+        description: "Lifecycle events of long-running driver tasks, as published on the control topic, see custom::driver::operations",
+        written_by: &["custom::driver::operations"],
+        columns: &[
+            col("operation_id", "Id generated when the operation started"),
+            col("event_index", "Position of the event in the operation, 0 for started"),
+            col("kind", "backfill, enrichment or prune"),
+            col("event", "started, progress, completed or failed"),
+            col("actor", "Who or what started the operation"),
+            col("parameters", "What the operation works on, e.g. a version range"),
+            col("done", "Units of work done so far, e.g. versions or rows"),
+            col("total", "Units of work of the whole operation, if known"),
+            col("error_code", "Class of the failure: database, connection, processing or internal"),
+            col("error", "Message of the failure"),
+            col("emitted_at", "When the event happened"),
+        ],
+    },
+    TableDoc {
+        table: "processor_leases",
+        description: "Which instance of a processor writes and publishes, see custom::driver::standby",
+        written_by: &["custom::driver::standby"],
+        columns: &[
+            col(
+                "processor",
+                "Name of the processor, with @index/count for a shard's own lease",
+            ),
+            col("holder", "Instance holding the lease"),
+            col("acquired_at", "When the holder acquired the lease"),
+            col("expires_at", "When another instance may take the lease over"),
+        ],
+    },
+    TableDoc {
+        table: "processor_status",
+        description: "Watermark and health state of every processor",
+        written_by: &["indexer::tailer", "custom::driver::circuit_breaker"],
+        columns: &[
+            col(
+                "processor",
+                "Name of the processor, with @index/count for a shard, @start-end for a backfill",
+            ),
+            col("last_success_version", "Version up to which everything was processed"),
+            col("last_updated", "When the watermark was last written"),
+            col("health_state", "healthy, degraded or critical"),
+            col("health_state_since", "When the processor entered its health state"),
+        ],
+    },
+    TableDoc {
+        table: "processor_statuses",
+        description: "Per version processing status of the upstream indexer",
+        written_by: &["indexer::transaction_processor"],
+        columns: &[
+            col("name", "Name of the processor"),
+            col("version", "Version processed"),
+            col("success", "Whether the version was processed"),
+            col("details", "Error of a failed version"),
+            col("last_updated", "When the status was written"),
+        ],
+    },
+    TableDoc {
+        table: "proposal_votes",
+        description: "Governance votes, one row per vote event",
+        written_by: STAKE,
+        columns: &[
+            api("proposal_id", "event.data.proposal_id", "Id of the proposal"),
+            api("voter_address", "event.data.voter", "Voter of the stake pool"),
+            api("staking_pool_address", "event.data.stake_pool", "Stake pool that voted"),
+            api("num_votes", "event.data.num_votes", "Voting power used"),
+            api("should_pass", "event.data.should_pass", "Whether the vote is for the proposal"),
+        ],
+    },
+    TableDoc {
+        table: "scripts",
+        description: "Scripts run by transactions, once per script",
+        written_by: &["default_processor"],
+        columns: &[
+            col("script_hash", "sha3-256 of the script's bytecode"),
+            api("bytecode", "transaction.payload.code.bytecode", "Bytecode of the script"),
+            api("abi", "transaction.payload.code.abi", "ABI of the script"),
+            col("first_seen_version", "Version the script was first run at"),
+        ],
+    },
+    TableDoc {
+        table: "range_hashes",
+        description: "Hashes of the rows written for version ranges, to compare deployments, see custom::driver::range_hash",
+        written_by: &["custom::driver::range_hash"],
+        columns: &[
+            col("row_count", "Number of rows written for the range"),
+            col("hash", "Hash of the rows written for the range"),
+            col("computed_at", "When the hash was computed"),
+        ],
+    },
+    TableDoc {
+        table: "shadow_diffs",
+        description: "Differences between a shadow processor's output and the primary's, see custom::driver::shadow",
+        written_by: &["custom::driver::shadow"],
+        columns: &[
+            col("shadow", "Name of the shadow"),
+            col("diff_index", "Position of the difference in the batch"),
+            col("pk", "Primary key values of the row, as a JSON array"),
+            col("column_name", "Column that differs, * for a row only one of the two has"),
+            col("primary_value", "Value in the primary's output, NULL if the row is missing"),
+            col("shadow_value", "Value in the shadow's output, NULL if the row is missing"),
+        ],
+    },
+    TableDoc {
+        table: "signatures",
+        description: "Signatures of user transactions, one row per signer and key",
+        written_by: DEFAULT,
+        columns: &[
+            col("multi_agent_index", "Position of the signer among the transaction's signers"),
+            col("multi_sig_index", "Position of the key in a multi key signature"),
+            api("signer", "transaction.signature", "Account that signed"),
+            col("is_sender_primary", "Whether the signer is the sender"),
+            api("type", "transaction.signature.type", "Type of the signature"),
+            api("public_key", "transaction.signature.public_key", "Public key of the signature"),
+            api("signature", "transaction.signature.signature", "The signature"),
+            col("threshold", "Number of keys a multi key signature needs"),
+            col("public_key_indices", "Keys that signed a multi key signature"),
+            col("is_fee_payer", "Whether the signer paid the gas of a sponsored transaction"),
+        ],
+    },
+    TableDoc {
+        table: "table_items",
+        description: "Move table items after each write or delete",
+        written_by: DEFAULT,
+        columns: &[
+            api("key", "write_table_item.key", "Key of the item, hex encoded BCS"),
+            api("decoded_key", "write_table_item.data.key", "Key of the item, decoded"),
+            api(
+                "decoded_value",
+                "write_table_item.data.value",
+                "Value of the item, decoded, NULL once deleted",
+            ),
+        ],
+    },
+    TableDoc {
+        table: "table_metadatas",
+        description: "Key and value types of every Move table",
+        written_by: DEFAULT,
+        columns: &[
+            api("handle", "write_table_item.handle", "Handle of the table"),
+            api("key_type", "write_table_item.data.key_type", "Move type of the keys"),
+            api("value_type", "write_table_item.data.value_type", "Move type of the values"),
+        ],
+    },
+    TableDoc {
+        table: "token_activities",
+        description: "v1 token events: mints, transfers, offers, claims and burns",
+        written_by: TOKEN,
+        columns: &[
+            col("name", "Name of the token"),
+            api("transfer_type", "event.type", "Type of the event"),
+            col("from_address", "Account the tokens left, if any"),
+            col("to_address", "Account the tokens went to, if any"),
+            col("token_amount", "Number of tokens"),
+            col("coin_type", "Coin paid for the tokens, not set by any event yet"),
+            col("coin_amount", "Amount paid for the tokens, not set by any event yet"),
+        ],
+    },
+    TableDoc {
+        table: "token_activities_v2",
+        description: "v1 and v2 token events",
+        written_by: TOKEN,
+        columns: &[
+            api("type", "event.type", "Type of the event"),
+            col("from_address", "Account or object the tokens left, if any"),
+            col("to_address", "Account or object the tokens went to, if any"),
+            col("token_amount", "Number of tokens"),
+            col("before_value", "Value before a mutation event"),
+            col("after_value", "Value after a mutation event"),
+        ],
+    },
+    TableDoc {
+        table: "token_datas",
+        description: "v1 token datas after each transaction that changed them",
+        written_by: TOKEN,
+        columns: &[
+            api("name", "TokenData.name", "Name of the token"),
+            api("maximum", "TokenData.maximum", "Maximum supply, 0 for unlimited"),
+            api("supply", "TokenData.supply", "Current supply"),
+            api(
+                "largest_property_version",
+                "TokenData.largest_property_version",
+                "Largest property version minted",
+            ),
+            api("metadata_uri", "TokenData.uri", "URI of the token's metadata"),
+            api("payee_address", "TokenData.royalty.payee_address", "Receiver of the royalties"),
+            api(
+                "royalty_points_numerator",
+                "TokenData.royalty.royalty_points_numerator",
+                "Numerator of the royalty share",
+            ),
+            api(
+                "royalty_points_denominator",
+                "TokenData.royalty.royalty_points_denominator",
+                "Denominator of the royalty share",
+            ),
+            api("maximum_mutable", "TokenData.mutability_config", "Whether the maximum can change"),
+            api("uri_mutable", "TokenData.mutability_config", "Whether the URI can change"),
+            api(
+                "description_mutable",
+                "TokenData.mutability_config",
+                "Whether the description can change",
+            ),
+            api(
+                "properties_mutable",
+                "TokenData.mutability_config",
+                "Whether the properties can change",
+            ),
+            api("royalty_mutable", "TokenData.mutability_config", "Whether the royalty can change"),
+            api(
+                "default_properties",
+                "TokenData.default_properties",
+                "Default property map of the token, decoded to JSON",
+            ),
+        ],
+    },
+    TableDoc {
+        table: "token_datas_v2",
+        description: "v1 and v2 tokens after each write of their resource",
+        written_by: TOKEN,
+        columns: &[
+            col("maximum", "Maximum supply, NULL for unlimited"),
+            col("supply", "Current supply"),
+            col(
+                "largest_property_version_v1",
+                "Largest property version minted of a v1 token",
+            ),
+            col("token_uri", "URI of the token's metadata"),
+        ],
+    },
+    TableDoc {
+        table: "token_ownerships",
+        description: "Amount of a v1 token held in a token store after each transaction that changed it",
+        written_by: TOKEN,
+        columns: &[
+            col("name", "Name of the token"),
+            col("owner_address", "Owner of the token store, NULL when it's unknown"),
+            col("amount", "Number of tokens held"),
+            col("table_type", "Type of the table the tokens are held in"),
+        ],
+    },
+    TableDoc {
+        table: "token_ownerships_v2",
+        description: "Amount of a v1 or v2 token held by an owner after each write",
+        written_by: TOKEN,
+        columns: &[
+            col("owner_address", "Owner of the token, NULL when it's unknown"),
+            col(
+                "storage_id",
+                "Token store table of a v1 token, token object address of a v2 token",
+            ),
+            col("amount", "Number of tokens held"),
+            col("table_type_v1", "Type of the table a v1 token is held in"),
+            col(
+                "token_properties_mutated_v1",
+                "Properties of a v1 token that differ from its token data's",
+            ),
+            col("is_soulbound_v2", "Whether a v2 token can't be transferred"),
+            col(
+                "non_transferrable_by_owner",
+                "Whether the owner can't transfer the v2 token object",
+            ),
+        ],
+    },
+    TableDoc {
+        table: "tokens",
+        description: "v1 tokens and their properties after each transaction that changed them",
+        written_by: TOKEN,
+        columns: &[col("name", "Name of the token")],
+    },
+    TableDoc {
+        table: "transaction_storage_usage",
+        description: "Storage fees and write set sizes of user transactions, see custom::driver::storage_usage",
+        written_by: &["custom_default_processor"],
+        columns: &[
+            col("payer_address", "The fee payer, or the sender of transactions without one"),
+            api(
+                "storage_fee_octas",
+                "FeeStatement.storage_fee_octas",
+                "Storage fee charged, null before the chain emitted fee statements",
+            ),
+            api(
+                "storage_fee_refund_octas",
+                "FeeStatement.storage_fee_refund_octas",
+                "Storage fee refunded for deleted slots, null before the chain emitted fee statements",
+            ),
+            api("slots_written", "transaction.changes", "Modules, resources and table items written"),
+            api("slots_deleted", "transaction.changes", "Modules, resources and table items deleted"),
+            api(
+                "bytes_written",
+                "transaction.changes",
+                "Bytes of the written table items (key and value) and modules; resources aren't sized by the API",
+            ),
+        ],
+    },
+    TableDoc {
+        table: "transactions",
+        description: "Every transaction",
+        written_by: DEFAULT,
+        columns: &[
+            api("version", "transaction.version", "Version of the transaction"),
+            api("hash", "transaction.hash", "Hash of the transaction"),
+            api(
+                "type",
+                "transaction.type",
+                "user_transaction, block_metadata_transaction, state_checkpoint_transaction or genesis_transaction",
+            ),
+            api("payload", "transaction.payload", "Payload of a user transaction"),
+            api("state_change_hash", "transaction.state_change_hash", "Hash of the write set"),
+            api("event_root_hash", "transaction.event_root_hash", "Root hash of the events"),
+            api(
+                "state_checkpoint_hash",
+                "transaction.state_checkpoint_hash",
+                "State root hash, for the transactions that end a block",
+            ),
+            api("gas_used", "transaction.gas_used", "Gas units used"),
+            api("success", "transaction.success", "Whether the transaction succeeded"),
+            api("vm_status", "transaction.vm_status", "Status of the VM after the transaction"),
+            api(
+                "accumulator_root_hash",
+                "transaction.accumulator_root_hash",
+                "Root hash of the transaction accumulator after the transaction",
+            ),
+            api("num_events", "transaction.events", "Number of events"),
+            api(
+                "num_write_set_changes",
+                "transaction.changes",
+                "Number of write set changes",
+            ),
+        ],
+    },
+    TableDoc {
+        table: "user_transactions",
+        description: "User transactions",
+        written_by: DEFAULT,
+        columns: &[
+            api("version", "transaction.version", "Version of the transaction"),
+            api(
+                "parent_signature_type",
+                "transaction.signature.type",
+                "Type of the transaction's signature",
+            ),
+            api("sender", "transaction.sender", "Sender of the transaction"),
+            api(
+                "sequence_number",
+                "transaction.sequence_number",
+                "Sequence number of the sender",
+            ),
+            api("max_gas_amount", "transaction.max_gas_amount", "Gas limit of the transaction"),
+            api(
+                "expiration_timestamp_secs",
+                "transaction.expiration_timestamp_secs",
+                "When the transaction expires",
+            ),
+            api("gas_unit_price", "transaction.gas_unit_price", "Price of a gas unit, in octas"),
+            api("timestamp", "transaction.timestamp", "Timestamp of the transaction"),
+            api(
+                "script_hash",
+                "derived: sha3-256 of transaction.payload.code.bytecode",
+                "Script the transaction ran, see scripts",
+            ),
+            api(
+                "entry_function_id_str",
+                "transaction.payload.function",
+                "Entry function called as address::module::function, script or multisig for those",
+            ),
+            api(
+                "entry_function_module",
+                "derived: transaction.payload.function",
+                "Module of the entry function called, as address::module, also set for multisig",
+            ),
+            api(
+                "entry_function_name",
+                "derived: transaction.payload.function",
+                "Name of the entry function called",
+            ),
+            api(
+                "entry_function_type_arguments",
+                "transaction.payload.type_arguments",
+                "Type arguments of the entry function or script, as a JSON array of strings",
+            ),
+            api(
+                "entry_function_arguments",
+                "transaction.payload.arguments",
+                "Arguments of the entry function or script, as a JSON array",
+            ),
+        ],
+    },
+    TableDoc {
+        table: "validation_violations",
+        description: "Rows that broke a validation rule, see custom::driver::validation",
+        written_by: &["custom::driver::validation"],
+        columns: &[
+            col("rule_name", "Name of the rule"),
+            col("violation_index", "Position of the violation in the batch"),
+            col("policy", "warn, or fail for a rule that fails the batch"),
+            col(
+                "transaction_version",
+                "Version of the transaction of the row, if it has one",
+            ),
+            col("message", "What's wrong with the row"),
+            col("details", "The row and the values the rule checked"),
+        ],
+    },
+    TableDoc {
+        table: "write_set_changes",
+        description: "Every write set change of every transaction",
+        written_by: DEFAULT,
+        columns: &[
+            col("index", "Position of the change in the transaction's write set"),
+            api("hash", "write_set_change.state_key_hash", "Hash of the state key"),
+            api("type", "write_set_change.type", "Type of the change, e.g. write_resource"),
+            api("address", "write_set_change.address", "Account the change is at"),
+        ],
+    },
+];
@@ -2,33 +2,90 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::{
-    database::new_db_pool,
+    database::{new_rollback_db_pool, read_cache, PgDbPool},
     indexer::{
-        fetcher::TransactionFetcherOptions, processing_result::ProcessingResult, tailer::Tailer,
-        transaction_processor::TransactionProcessor,
+        fetcher::{FetchBudget, TransactionFetcher, TransactionFetcherOptions},
+        processing_result::{ProcessingResult, RowCounts},
+        recording::{RecordingFetcher, ReplayFetcher},
+        tailer::Tailer,
     },
+    queries::index_advisor,
+    strictness,
     custom::{
+        enrichment,
         processors::{
-            CProcessor,
-            custom_coin_processor::CCoinTransactionProcessor,
-            custom_default_processor::CDefaultTransactionProcessor,
-            custom_token_processor::CTokenTransactionProcessor,
-            custom_stake_processor::CStakeTransactionProcessor,
+            ProcessorOptions,
+            custom_default_processor,
+            processor_registry::{self, ProcessorConfig},
         }
     },
 };
 use aptos_api::context::Context;
 use aptos_config::config::{IndexerConfig, NodeConfig};
-use aptos_logger::{error, info};
+use aptos_logger::{debug, error, info, warn};
 use aptos_mempool::MempoolClientSender;
 use aptos_storage_interface::DbReader;
 use aptos_types::chain_id::ChainId;
-use std::{collections::VecDeque, sync::Arc};
-use tokio::runtime::Runtime;
+use futures::FutureExt;
+use once_cell::sync::OnceCell;
+use std::{
+    collections::VecDeque,
+    panic::AssertUnwindSafe,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{runtime::Runtime, sync::Mutex};
 use crate::custom::driver::{
+    admin,
+    alerts,
+    app_scope::{AppScope, ScopeExpansion},
+    backfill::{BackfillArgs, DryRunSummary},
+    backfill_guard,
+    batch_manifest::BatchManifests,
+    batch_retry::BatchRetry,
+    change_feed,
+    circuit_breaker,
+    column_stats,
+    consumer_lag,
+    db_pools::PoolSettings,
+    config::{DriverConfig, RecordingMode, DEFAULT_CONFIG_PATH},
+    debug::{self, DebugArgs},
+    envelope::{self, Envelope},
+    health::{self, PauseReason},
+    ledger_reset::{self, Fence},
+    lifecycle::{BackfillCommand, Indexer, ProcessorControl},
+    metrics,
+    operations::{self, Operation},
+    preflight::Preflight,
+    priority::PriorityLane,
+    publish_dedupe::PublishCheckpoint,
     publisher::Publisher,
+    range_hash,
+    rate_limit,
+    redaction::Redactor,
+    replay,
+    replay_cache::ReplayCache,
+    replication_lag,
+    retry_budget,
+    row_limits,
+    sharding,
+    shutdown,
+    standby::{Lease, Role},
+    version_guard::VersionGuard,
 };
 
+/// How long a demoted leader waits for what it published to be delivered
+const DEMOTION_FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a standby waits for its fetcher when no batch is ready
+const STANDBY_IDLE_WAIT: Duration = Duration::from_millis(100);
+/// How long a backfill waits for its fetcher when no batch is ready
+const BACKFILL_IDLE_WAIT: Duration = Duration::from_millis(100);
+/// How long a backfill waits for what a round published to be acked before saving its progress
+const BACKFILL_FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Set once the migrations ran, the processors of a process would otherwise race to run them
+static MIGRATED: OnceCell<()> = OnceCell::new();
+
 pub struct MovingAverage {
     window_millis: u64,
     // (timestamp_millis, value)
@@ -93,57 +150,130 @@ pub fn bootstrap(
 
     let runtime = aptos_runtimes::spawn_named_runtime("indexer".into(), None);
 
-    let mut indexer_config = config.indexer.clone();
-    let node_config = config.clone();
-
     // custom
-    indexer_config.processor = Option::from(String::from("custom_default_processor"));
-    let processors: [Option<String>; 0] = [
-        // Option::from(String::from("custom_coin_processor")),
-        // Option::from(String::from("custom_token_processor"))
-    ];
-    let n = processors.len();
-
-    for i in 0..n {
-        let mut new_indexer_config = config.indexer.clone();
-        new_indexer_config.processor = processors[i].clone();
-        let new_db = db.clone();
-        let new_mp_sender = mp_sender.clone();
-        let new_node_config = node_config.clone();
+    // Every processor of the driver config runs its own pipeline, from its own watermark
+    let driver_config = DriverConfig::read_from(DEFAULT_CONFIG_PATH);
+    if let Err(e) = processor_registry::validate_names(&driver_config.processors) {
+        return Some(Err(e));
+    }
+    if let Err(e) = driver_config.db_pools.validate() {
+        return Some(Err(e));
+    }
+    if let Err(e) = envelope::chain_id(driver_config.chain_id, chain_id.id()) {
+        return Some(Err(e.into()));
+    }
+    for processor in driver_config.processors {
+        let mut indexer_config = config.indexer.clone();
+        indexer_config.processor = Some(processor);
+        let db = db.clone();
+        let mp_sender = mp_sender.clone();
+        let node_config = config.clone();
 
         runtime.spawn(async move {
-            let context = Arc::new(Context::new(chain_id, new_db, new_mp_sender, new_node_config, None));
-            run_forever(new_indexer_config, context).await;
+            let context = Arc::new(Context::new(chain_id, db, mp_sender, node_config, None));
+            run_forever(indexer_config, context).await;
         });
     }
 
+    Some(Ok(runtime))
+}
+
+/// Like `bootstrap`, for a process that runs one processor over the range of `args` instead of
+/// following the ledger, see `driver::backfill`. Refuses an invalid range right away, and exits the
+/// process once the range is done, with 0, or with 1 if it failed.
+pub fn bootstrap_backfill(
+    config: &NodeConfig,
+    chain_id: ChainId,
+    db: Arc<dyn DbReader>,
+    mp_sender: MempoolClientSender,
+    args: BackfillArgs,
+) -> anyhow::Result<Runtime> {
+    args.validate()?;
+    envelope::chain_id(
+        DriverConfig::read_from(DEFAULT_CONFIG_PATH).chain_id,
+        chain_id.id(),
+    )?;
+    let runtime = aptos_runtimes::spawn_named_runtime("backfill".into(), None);
+
+    let indexer_config = config.indexer.clone();
+    let node_config = config.clone();
     runtime.spawn(async move {
         let context = Arc::new(Context::new(chain_id, db, mp_sender, node_config, None));
-        run_forever(indexer_config, context).await;
+        match run_backfill(indexer_config, context, &args, ProcessorOptions::default()).await {
+            Ok(()) => std::process::exit(0),
+            Err(e) => {
+                error!(processor_name = args.processor, error = ?e, "Backfill failed");
+                std::process::exit(1);
+            },
+        }
     });
 
-    Some(Ok(runtime))
+    Ok(runtime)
+}
+
+/// Like `bootstrap`, for a process that prints what the processors derive from the transaction of
+/// `args` instead of indexing, see `driver::debug`. The transaction is read from the node's db,
+/// or from the `input` files if there are any. Exits the process once it's printed, with 0, or
+/// with 1 if it couldn't be read.
+pub fn bootstrap_debug(
+    config: &NodeConfig,
+    chain_id: ChainId,
+    db: Arc<dyn DbReader>,
+    mp_sender: MempoolClientSender,
+    args: DebugArgs,
+) -> anyhow::Result<Runtime> {
+    let target = args.target()?;
+    let driver_config = args.driver_config()?;
+    let runtime = aptos_runtimes::spawn_named_runtime("debug".into(), None);
+
+    let node_config = config.clone();
+    runtime.spawn(async move {
+        let result = if args.input.is_empty() {
+            let context = Arc::new(Context::new(chain_id, db, mp_sender, node_config, None));
+            debug::debug_version(context, &driver_config, target)
+                .await
+                .and_then(|report| args.print(&report))
+        } else {
+            debug::run(&args)
+        };
+        match result {
+            Ok(()) => std::process::exit(0),
+            Err(e) => {
+                error!(error = ?e, "Debug failed");
+                std::process::exit(1);
+            },
+        }
+    });
+
+    Ok(runtime)
 }
 
 pub async fn run_forever(config: IndexerConfig, context: Arc<Context>) {
+    run_forever_with_options(config, context, ProcessorOptions::default()).await
+}
+
+/// `run_forever` with processors built from `options`, e.g. with custom validation rules
+pub async fn run_forever_with_options(
+    config: IndexerConfig,
+    context: Arc<Context>,
+    options: ProcessorOptions,
+) {
     // All of these options should be filled already with defaults
     let processor_name = config.processor.clone().unwrap();
     let check_chain_id = config.check_chain_id.unwrap();
     let skip_migrations = config.skip_migrations.unwrap();
-    let fetch_tasks = config.fetch_tasks.unwrap();
     let processor_tasks = config.processor_tasks.unwrap();
     let emit_every = config.emit_every.unwrap();
-    let batch_size = config.batch_size.unwrap();
     let lookback_versions = config.gap_lookback_versions.unwrap() as i64;
 
     info!(processor_name = processor_name, "Starting indexer...");
 
-    let db_uri = &config.postgres_uri.unwrap();
-    info!(
-        processor_name = processor_name,
-        "Creating connection pool..."
-    );
-    let conn_pool = new_db_pool(db_uri).expect("Failed to create connection pool");
+    // custom
+    let mut driver_config = DriverConfig::read_from(DEFAULT_CONFIG_PATH);
+
+    let db_uri = &config.postgres_uri.clone().unwrap();
+    let conn_pool = processor_registry::connection_pool(&processor_name, db_uri, &driver_config.db_pools)
+        .unwrap_or_else(|e| panic!("Failed to create connection pool: {:?}", e));
     info!(
         processor_name = processor_name,
         "Created the connection pool... "
@@ -151,54 +281,147 @@ pub async fn run_forever(config: IndexerConfig, context: Arc<Context>) {
 
     info!(processor_name = processor_name, "Instantiating tailer... ");
 
-    // custom
-    let publisher = Publisher::new();
-    let processor_enum = CProcessor::from_string(&processor_name);
-    let processor: Arc<dyn TransactionProcessor> = match processor_enum {
-        CProcessor::DefaultProcessor => {
-            Arc::new(CDefaultTransactionProcessor::new(conn_pool.clone(), publisher))
-        }
-        CProcessor::TokenProcessor => Arc::new(CTokenTransactionProcessor::new(
-            conn_pool.clone(),
-            config.ans_contract_address,
-            config.nft_points_contract,
-            publisher,
-        )),
-        CProcessor::CoinProcessor => Arc::new(CCoinTransactionProcessor::new(conn_pool.clone(), publisher)),
-        CProcessor::StakeProcessor => Arc::new(CStakeTransactionProcessor::new(conn_pool.clone()))
-    };
-
-    let options =
-        TransactionFetcherOptions::new(None, None, Some(batch_size), None, fetch_tasks as usize);
-
-    let tailer = Tailer::new(context, conn_pool.clone(), processor, options)
-        .expect("Failed to instantiate tailer");
+    let chain_id = envelope::chain_id(driver_config.chain_id, context.chain_id().id())
+        .unwrap_or_else(|e| panic!("{}", e));
+    // Before the tailer, whose fetcher only fetches the shard's versions
+    sharding::init(&driver_config.sharding);
+    // Before the publishers, which frame Avro messages with the ids of the schemas
+    #[cfg(feature = "avro")]
+    crate::custom::driver::schema_registry::init(&driver_config)
+        .await
+        .unwrap_or_else(|e| panic!("{:#}", e));
+    let mut tailer = build_tailer(&config, &driver_config, &options, context.clone(), conn_pool.clone());
 
     if !skip_migrations {
         info!(processor_name = processor_name, "Running migrations...");
-        tailer.run_migrations();
+        MIGRATED.get_or_init(|| tailer.run_migrations());
     }
 
+    alerts::init(&driver_config.alerts);
+    operations::init(&driver_config, chain_id, conn_pool.clone());
+    admin::init(&driver_config, conn_pool.clone());
+    metrics::init(&driver_config.metrics);
+    health::init(&driver_config.health, conn_pool.clone(), context.clone());
+    health::register(&processor_name);
+    shutdown::init(&driver_config.shutdown);
+    shutdown::register(&processor_name);
+    consumer_lag::init(&driver_config);
+    column_stats::init(&driver_config.column_stats, conn_pool.clone());
+    backfill_guard::init(&driver_config.backfill_guard, conn_pool.clone());
+    change_feed::init(&driver_config.change_feed, conn_pool.clone());
+    range_hash::init(&driver_config.range_hash, conn_pool.clone());
+    index_advisor::configure(driver_config.index_advisor.sample_every);
+    retry_budget::init(&driver_config.retry_budget);
+    row_limits::init(&driver_config.row_limits);
+    rate_limit::init(&driver_config.rate_limit);
+    read_cache::init(&driver_config.read_cache);
+    circuit_breaker::init(&driver_config, chain_id, conn_pool.clone());
+    replication_lag::init(&driver_config.replication_lag, conn_pool.clone());
+    strictness::init(
+        driver_config.api_strictness.level,
+        driver_config.api_strictness.sample_every,
+        &driver_config.api_strictness.ignored_paths,
+    );
+    // After the migrations, which create the progress table
+    enrichment::spawn_configured(&driver_config.enrichment, conn_pool.clone())
+        .unwrap_or_else(|e| panic!("Invalid enrichment config: {:#}", e));
+
+    Preflight::new(&processor_name, &driver_config, conn_pool.clone(), context.clone())
+        .run()
+        .await
+        .unwrap_or_else(|e| panic!("{}", e));
+
+    // A wipe drops the watermarks, so this goes before they're read
+    ledger_reset::check(
+        &processor_name,
+        &driver_config.ledger_reset,
+        &conn_pool,
+        context.clone(),
+    )
+    .unwrap_or_else(|e| panic!("{:?}", e));
+    let mut fence = Fence::new(&processor_name, &driver_config.ledger_reset, &conn_pool)
+        .unwrap_or_else(|e| panic!("Failed to set up the ledger reset fence: {:?}", e));
+
     info!(
         processor_name = processor_name,
         lookback_versions = lookback_versions,
         "Fetching starting version from db..."
     );
     // For now this is not being used but we'd want to track it anyway
-    let starting_version_from_db_short = tailer
-        .get_start_version(&processor_name)
-        .unwrap_or_else(|e| panic!("Failed to get starting version: {:?}", e))
-        .unwrap_or_else(|| {
+    let starting_version_from_db_short = get_watermark(&tailer, &processor_name);
+    // Addresses added to the app scope since the last run are backfilled, see `driver::app_scope`
+    let scope_expansion = if driver_config.app_scope.enabled {
+        ScopeExpansion::detect(&processor_name, &driver_config.app_scope, starting_version_from_db_short, conn_pool.clone())
+            .unwrap_or_else(|e| panic!("Failed to record the app scope: {:?}", e))
+    } else {
+        None
+    };
+    let backfill_from_version = driver_config.app_scope.backfill_from_version;
+    let mut start_version = match (config.starting_version, &scope_expansion) {
+        (Some(version), _) => version,
+        (None, Some(_)) => backfill_from_version.min(starting_version_from_db_short),
+        (None, None) => starting_version_from_db_short,
+    };
+    let mut scope_expansion = scope_expansion.and_then(|expansion| {
+        if start_version > backfill_from_version {
+            warn!(
+                processor_name = processor_name,
+                start_version = start_version,
+                backfill_from_version = backfill_from_version,
+                addresses = ?expansion.addresses(),
+                "Starting above backfill_from_version, the addresses added to the app scope stay pending"
+            );
+            None
+        } else if start_version >= starting_version_from_db_short {
+            // Nothing below the watermark to backfill
+            expansion
+                .complete()
+                .unwrap_or_else(|e| panic!("Failed to record the app scope: {:?}", e));
+            None
+        } else {
             info!(
                 processor_name = processor_name,
-                "No starting version from db so starting from version 0"
+                start_version = start_version,
+                addresses = ?expansion.addresses(),
+                "Backfilling the addresses added to the app scope"
             );
-            0
-        }) as u64;
-    let start_version = match config.starting_version {
-        None => starting_version_from_db_short,
-        Some(version) => version,
-    };
+            Some(expansion)
+        }
+    });
+
+    // Starting below the watermark reprocesses versions that were indexed already, which is a
+    // backfill and needs a window to overwrite their rows
+    let mut backfill = None;
+    if start_version < starting_version_from_db_short {
+        let window_hours = driver_config.backfill_guard.starting_version_window_hours;
+        let actor = format!("starting_version of {}", processor_name);
+        let end_version = starting_version_from_db_short - 1;
+        backfill_guard::register_window(
+            &conn_pool,
+            &processor_name,
+            start_version as i64,
+            end_version as i64,
+            &actor,
+            Duration::from_secs(window_hours * 3600),
+        )
+        .unwrap_or_else(|e| panic!("Failed to register backfill window: {:?}", e));
+        backfill = Some(Backfill {
+            operation: operations::start(
+                "backfill",
+                &actor,
+                serde_json::json!({
+                    "processor": processor_name,
+                    "start_version": start_version,
+                    "end_version": end_version,
+                }),
+                0,
+                Some(end_version - start_version + 1),
+            ),
+            start_version,
+            end_version,
+            app_scope: scope_expansion.take(),
+        });
+    }
 
     info!(
         processor_name = processor_name,
@@ -207,45 +430,882 @@ pub async fn run_forever(config: IndexerConfig, context: Arc<Context>) {
         starting_version_from_db = starting_version_from_db_short,
         "Setting starting version..."
     );
-    tailer.set_fetcher_version(start_version).await;
 
-    info!(processor_name = processor_name, "Starting fetcher...");
-    tailer.transaction_fetcher.lock().await.start().await;
+    // Check once here to avoid a boolean check every iteration
+    if check_chain_id {
+        tailer
+            .check_or_update_chain_id()
+            .await
+            .expect("Failed to get chain ID");
+    }
+
+    let mut control = Indexer::handle().register(&processor_name);
+    let mut batch_retry = BatchRetry::new(&driver_config.batch_retry);
+    let lease = driver_config.standby.enabled.then(|| {
+        Lease::new(
+            &sharding::watermark_key(&processor_name),
+            &driver_config.standby,
+            conn_pool.clone(),
+        )
+    });
+    loop {
+        if let Some(lease) = &lease {
+            let acquired = lease.try_acquire().unwrap_or_else(|e| {
+                warn!(processor_name = processor_name, error = ?e, "Failed to acquire the lease");
+                false
+            });
+            if !acquired {
+                follow_until_leader(&config, &driver_config, &options, context.clone(), conn_pool.clone(), lease).await;
+                // The leader was writing meanwhile, so a fresh processor starts at its watermark
+                tailer = build_tailer(&config, &driver_config, &options, context.clone(), conn_pool.clone());
+                start_version = get_watermark(&tailer, &processor_name);
+                if let Some(backfill) = backfill.take() {
+                    backfill.operation.fail("standby", "Another instance was leading");
+                }
+            }
+            lease.set_role(Role::Leader);
+            health::on_role(&processor_name, Role::Leader);
+        }
+        tailer.set_fetcher_version(start_version).await;
+
+        info!(processor_name = processor_name, "Starting fetcher...");
+        tailer.transaction_fetcher.lock().await.start().await;
+
+        info!(
+            processor_name = processor_name,
+            start_version = start_version,
+            "Indexing loop started!"
+        );
 
+        let mut version_guard = VersionGuard::new(&processor_name, &driver_config.version_guard, start_version, sharding::spec());
+        match process_until_interrupted(
+            &tailer,
+            &processor_name,
+            driver_config.ordered_commit.round_tasks(processor_tasks),
+            emit_every,
+            &mut control,
+            &mut backfill,
+            lease.as_ref(),
+            fence.as_ref(),
+            &mut version_guard,
+            &mut batch_retry,
+        )
+        .await
+        {
+            Interrupt::Reload(new_config) => {
+                // So that a running backfill can be slowed down or sped up
+                rate_limit::init(&new_config.rate_limit);
+                driver_config = new_config;
+            },
+            Interrupt::Retry(committed_version) => {
+                // A new fetcher, since the old one is past the round
+                tailer = build_tailer(&config, &driver_config, &options, context.clone(), conn_pool.clone());
+                if let Some(version) = committed_version {
+                    start_version = version + 1;
+                }
+                continue;
+            },
+            Interrupt::Backfill(command) => {
+                if let Some(previous) = backfill.take() {
+                    previous.operation.fail("superseded", "Another backfill was started");
+                }
+                // The in-flight batches are committed, so the processor can restart below them. A
+                // forced republish lasts until the next reload.
+                let mut backfill_config = driver_config.clone();
+                backfill_config.publish_dedupe.force_republish |= command.force_republish;
+                tailer = build_tailer(&config, &backfill_config, &options, context.clone(), conn_pool.clone());
+                start_version = get_watermark(&tailer, &processor_name);
+                backfill = start_backfill(&conn_pool, &processor_name, command, start_version);
+                if let Some(backfill) = &backfill {
+                    start_version = backfill.start_version;
+                    if let Err(e) = tailer.forget_replayed_from(start_version) {
+                        error!(processor_name = processor_name, error = ?e, "Failed to reset the replay cache");
+                    }
+                }
+                continue;
+            },
+            Interrupt::LedgerReset => {
+                // Another processor wiped the tables, watermarks included
+                if let Some(backfill) = backfill.take() {
+                    backfill.operation.fail("ledger_reset", "The ledger was reset");
+                }
+                fence = Fence::new(&processor_name, &driver_config.ledger_reset, &conn_pool)
+                    .unwrap_or_else(|e| panic!("Failed to set up the ledger reset fence: {:?}", e));
+                tailer = build_tailer(&config, &driver_config, &options, context.clone(), conn_pool.clone());
+                start_version = get_watermark(&tailer, &processor_name);
+                continue;
+            },
+            Interrupt::BackfillCancelled => {
+                let cancelled = backfill.take().unwrap();
+                tailer = build_tailer(&config, &driver_config, &options, context.clone(), conn_pool.clone());
+                // Back to the watermark the backfill started at
+                start_version = get_watermark(&tailer, &processor_name).max(cancelled.end_version + 1);
+                info!(
+                    processor_name = processor_name,
+                    start_version = start_version,
+                    "Backfill cancelled"
+                );
+                cancelled.operation.cancelled();
+                continue;
+            },
+            Interrupt::Demote | Interrupt::LeaseLost => {
+                if let Err(e) = tailer.flush_publisher(DEMOTION_FLUSH_TIMEOUT) {
+                    error!(processor_name = processor_name, error = ?e, "Failed to flush the publisher");
+                }
+                // Only expires the lease if it's still ours, and keeps this process a standby for
+                // a ttl either way
+                if let Err(e) = lease.as_ref().unwrap().release() {
+                    error!(processor_name = processor_name, error = ?e, "Failed to release the lease");
+                }
+                continue;
+            },
+            Interrupt::Shutdown => {
+                if let Err(e) = tailer.flush_publisher(shutdown::flush_timeout()) {
+                    error!(processor_name = processor_name, error = ?e, "Failed to flush the publisher");
+                }
+                if let Some(lease) = &lease {
+                    if let Err(e) = lease.release() {
+                        error!(processor_name = processor_name, error = ?e, "Failed to release the lease");
+                    }
+                }
+                info!(
+                    processor_name = processor_name,
+                    start_version = get_watermark(&tailer, &processor_name),
+                    "Stopping processor, it will resume at its watermark"
+                );
+                shutdown::stopped(&processor_name);
+                return;
+            },
+        }
+
+        // The in-flight batches are committed, so the old processor and its fetcher can go and
+        // the new one picks up at the watermark
+        tailer = build_tailer(&config, &driver_config, &options, context.clone(), conn_pool.clone());
+        start_version = get_watermark(&tailer, &processor_name);
+        info!(
+            processor_name = processor_name,
+            start_version = start_version,
+            "Reloaded processor"
+        );
+    }
+}
+
+/// Runs `args.processor` over the range of `args`, rolling its writes back with `dry_run`.
+/// Resolves once every version of the range is done.
+pub async fn run_backfill(
+    mut config: IndexerConfig,
+    context: Arc<Context>,
+    args: &BackfillArgs,
+    options: ProcessorOptions,
+) -> anyhow::Result<()> {
+    args.validate()?;
+    config.processor = Some(args.processor.clone());
+    let processor_name = args.processor.as_str();
+    let check_chain_id = config.check_chain_id.unwrap();
+    let skip_migrations = config.skip_migrations.unwrap();
+    let mut driver_config = DriverConfig::read_from(DEFAULT_CONFIG_PATH);
+    let conn_pool = processor_registry::connection_pool(
+        processor_name,
+        &config.postgres_uri.clone().unwrap(),
+        &driver_config.db_pools,
+    )?;
+    let chain_id = envelope::chain_id(driver_config.chain_id, context.chain_id().id())?;
+    // Those are the process following the ledger's
+    driver_config.priority_lane.enabled = false;
+    driver_config.replay_cache.enabled = false;
+    driver_config.standby.enabled = false;
+    driver_config.fetcher_recording.mode = RecordingMode::Off;
+    driver_config.publish_dedupe.force_republish |= args.force_republish;
+    strictness::init(
+        driver_config.api_strictness.level,
+        driver_config.api_strictness.sample_every,
+        &driver_config.api_strictness.ignored_paths,
+    );
+    // Before the publishers, which frame Avro messages with the ids of the schemas
+    #[cfg(feature = "avro")]
+    crate::custom::driver::schema_registry::init(&driver_config).await?;
+    let new_tailer = || {
+        let mut tailer = build_tailer(
+            &config,
+            &driver_config,
+            &options,
+            context.clone(),
+            conn_pool.clone(),
+        );
+        tailer.transaction_fetcher = Arc::new(Mutex::new(TransactionFetcher::new(
+            context.clone(),
+            0,
+            fetcher_options(&config, &driver_config).with_end_version(args.end_version),
+        )));
+        tailer
+    };
+    let mut tailer = new_tailer();
+    if args.dry_run {
+        return dry_run_backfill(
+            &tailer,
+            &driver_config,
+            args,
+            &config.postgres_uri.clone().unwrap(),
+            chain_id,
+        )
+        .await;
+    }
+
+    if !skip_migrations {
+        info!(processor_name = processor_name, "Running migrations...");
+        MIGRATED.get_or_init(|| tailer.run_migrations());
+    }
+    alerts::init(&driver_config.alerts);
+    operations::init(&driver_config, chain_id, conn_pool.clone());
+    backfill_guard::init(&driver_config.backfill_guard, conn_pool.clone());
+    change_feed::init(&driver_config.change_feed, conn_pool.clone());
+    index_advisor::configure(driver_config.index_advisor.sample_every);
+    retry_budget::init(&driver_config.retry_budget);
+    row_limits::init(&driver_config.row_limits);
+    rate_limit::init(&driver_config.rate_limit);
+    read_cache::init(&driver_config.read_cache);
+    if check_chain_id {
+        tailer.check_or_update_chain_id().await?;
+    }
+
+    let watermark_key = args.watermark_key();
+    let watermark = tailer
+        .get_start_version(&watermark_key)?
+        .map(|version| version as u64);
+    let Some(start_version) = args.resume_version(watermark) else {
+        info!(
+            processor_name = processor_name,
+            range = watermark_key,
+            "The range was backfilled already"
+        );
+        return Ok(());
+    };
+    let actor = format!("backfill {}", watermark_key);
+    backfill_guard::register_window(
+        &conn_pool,
+        processor_name,
+        start_version as i64,
+        args.end_version as i64,
+        &actor,
+        Duration::from_secs(driver_config.backfill_guard.starting_version_window_hours * 3600),
+    )?;
+    let operation = operations::start(
+        "backfill",
+        &actor,
+        serde_json::json!({
+            "processor": processor_name,
+            "start_version": args.start_version,
+            "end_version": args.end_version,
+        }),
+        start_version - args.start_version,
+        Some(args.versions()),
+    );
     info!(
         processor_name = processor_name,
         start_version = start_version,
-        "Indexing loop started!"
+        end_version = args.end_version,
+        "Backfilling"
     );
 
-    let mut versions_processed: u64 = 0;
-    let mut base: u64 = 0;
+    tailer.set_fetcher_version(start_version).await;
+    tailer.transaction_fetcher.lock().await.start().await;
+    let processor_tasks = driver_config
+        .ordered_commit
+        .round_tasks(config.processor_tasks.unwrap());
+    let mut version_guard = VersionGuard::new(
+        &watermark_key,
+        &driver_config.version_guard,
+        start_version,
+        None,
+    );
+    let mut batch_retry = BatchRetry::new(&driver_config.batch_retry);
+    let mut next_version = start_version;
+    let result = async {
+        while next_version <= args.end_version {
+            let mut tasks = vec![];
+            for _ in 0..processor_tasks {
+                let other_tailer = tailer.clone();
+                tasks.push(tokio::spawn(async move {
+                    other_tailer.process_next_batch().await
+                }));
+            }
+            let mut round_ranges = vec![];
+            let mut round_manifests = vec![];
+            let mut failed = None;
+            for (_, res) in futures::future::try_join_all(tasks).await? {
+                match res {
+                    None => {},
+                    Some(Ok(processed_result)) => {
+                        round_ranges
+                            .push((processed_result.start_version, processed_result.end_version));
+                        round_manifests.extend(tailer.batch_manifest(&processed_result));
+                    },
+                    Some(Err(tpe)) => failed = failed.or(Some(tpe)),
+                }
+            }
+            if let Some(tpe) = failed {
+                let (err, start_version, end_version, _) = tpe.inner();
+                let delay = match batch_retry.on_failure(processor_name, &tpe) {
+                    Ok(delay) => delay,
+                    Err(give_up) => anyhow::bail!(
+                        "Error in '{}' while processing batch {}-{}, {}: {:?}",
+                        processor_name,
+                        start_version,
+                        end_version,
+                        give_up,
+                        err
+                    ),
+                };
+                warn!(
+                    processor_name = processor_name,
+                    start_version = start_version,
+                    end_version = end_version,
+                    delay_millis = delay.as_millis() as u64,
+                    error = ?err,
+                    "Error processing batch, will process the round again"
+                );
+                tokio::time::sleep(delay).await;
+                // A new fetcher, since the old one is past the round
+                tailer = new_tailer();
+                tailer.set_fetcher_version(next_version).await;
+                tailer.transaction_fetcher.lock().await.start().await;
+                version_guard = VersionGuard::new(
+                    &watermark_key,
+                    &driver_config.version_guard,
+                    next_version,
+                    None,
+                );
+                continue;
+            }
+            let Some(round_end_version) = round_ranges
+                .iter()
+                .map(|(_, end_version)| *end_version)
+                .max()
+            else {
+                tokio::time::sleep(BACKFILL_IDLE_WAIT).await;
+                continue;
+            };
+            version_guard.accept_round(&mut round_ranges)?;
+            // Once the progress is past the round, whatever of it Kafka didn't ack is lost
+            tailer.flush_publisher(BACKFILL_FLUSH_TIMEOUT)?;
+            tailer.publish_batch_manifests(&round_manifests)?;
+            tailer.update_last_processed_version(&watermark_key, round_end_version)?;
+            batch_retry.on_success();
+            operation.progress(round_end_version - args.start_version + 1);
+            rate_limit::pace(processor_name, round_end_version - next_version + 1, true).await;
+            next_version = round_end_version + 1;
+        }
+        Ok::<_, anyhow::Error>(())
+    }
+    .await;
+    match result {
+        Ok(()) => {
+            operation.complete(args.versions());
+            info!(
+                processor_name = processor_name,
+                start_version = args.start_version,
+                end_version = args.end_version,
+                "Backfill done"
+            );
+            Ok(())
+        },
+        Err(e) => {
+            operation.fail(operations::error_code(&e), format!("{:#}", e));
+            Err(e)
+        },
+    }
+}
 
-    // Check once here to avoid a boolean check every iteration
-    if check_chain_id {
+/// Runs `args.processor` over the range of `args` on a connection whose writes are rolled back,
+/// publishing to memory and saving no progress. Fails if the processor failed on any batch.
+async fn dry_run_backfill(
+    tailer: &Tailer,
+    driver_config: &DriverConfig,
+    args: &BackfillArgs,
+    database_url: &str,
+    chain_id: u8,
+) -> anyhow::Result<()> {
+    let mut driver_config = driver_config.clone();
+    // The dedupe reads its checkpoints from a connection of its own, which the single connection
+    // of the pool held by the processor would keep it waiting for
+    driver_config.publish_dedupe.force_republish = true;
+    let conn_pool = new_rollback_db_pool(
+        database_url,
+        PoolSettings::for_processor(&driver_config.db_pools, &args.processor).connection_timeout,
+    )?;
+    let (processor, messages) =
+        replay::in_memory_processor(&args.processor, Some(conn_pool), &driver_config, chain_id)?;
+    info!(
+        processor_name = args.processor,
+        start_version = args.start_version,
+        end_version = args.end_version,
+        "Dry running the backfill"
+    );
+    tailer.set_fetcher_version(args.start_version).await;
+    tailer.transaction_fetcher.lock().await.start().await;
+    let mut summary = DryRunSummary::default();
+    let mut next_version = args.start_version;
+    while next_version <= args.end_version {
+        let transactions = tailer.fetch_next_batch().await;
+        let Some(last_version) = transactions.last().and_then(|txn| txn.version()) else {
+            tokio::time::sleep(BACKFILL_IDLE_WAIT).await;
+            continue;
+        };
+        let start_version = next_version;
+        let versions = last_version - start_version + 1;
+        let result = AssertUnwindSafe(processor.process_versions_with_status(
+            transactions,
+            start_version,
+            last_version,
+        ))
+        .catch_unwind()
+        .await;
+        match result {
+            Ok(Ok(result)) => summary.add(versions, &result, messages.take().len()),
+            Ok(Err(tpe)) => {
+                let (err, ..) = tpe.inner();
+                warn!(
+                    processor_name = args.processor,
+                    start_version = start_version,
+                    end_version = last_version,
+                    error = ?err,
+                    "Failed to process a batch of the backfill"
+                );
+                messages.take();
+                summary.add_failed(versions);
+            },
+            // The panic hook logged the panic already
+            Err(_) => {
+                warn!(
+                    processor_name = args.processor,
+                    start_version = start_version,
+                    end_version = last_version,
+                    "Panicked processing a batch of the backfill"
+                );
+                messages.take();
+                summary.add_failed(versions);
+            },
+        }
+        next_version = last_version + 1;
+    }
+    info!(
+        processor_name = args.processor,
+        summary = serde_json::to_string(&summary).unwrap_or_default(),
+        "Dry run done"
+    );
+    if summary.failed_batches > 0 {
+        anyhow::bail!(
+            "The processor failed on {} batch(es) of the range",
+            summary.failed_batches
+        );
+    }
+    Ok(())
+}
+
+/// Builds the processor named in `config` and a tailer running it
+fn build_tailer(
+    config: &IndexerConfig,
+    driver_config: &DriverConfig,
+    options: &ProcessorOptions,
+    context: Arc<Context>,
+    conn_pool: PgDbPool,
+) -> Tailer {
+    let processor_name = config.processor.clone().unwrap();
+
+    // The chain id was checked against the config at startup
+    let envelope = Envelope::new(context.chain_id().id(), &processor_name);
+    let mut publisher = Publisher::from_config(driver_config.clone(), envelope.clone());
+    if driver_config.replay_cache.enabled {
+        publisher = publisher.with_replay_cache(Arc::new(ReplayCache::open(
+            &driver_config.replay_cache,
+            &sharding::watermark_key(&processor_name),
+        )));
+    }
+    if driver_config.publish_dedupe.enabled {
+        publisher = publisher.with_publish_checkpoint(Arc::new(PublishCheckpoint::new(
+            &driver_config.publish_dedupe,
+            conn_pool.clone(),
+            &sharding::watermark_key(&processor_name),
+        )));
+    }
+    let publisher_flush = publisher.flush_handle();
+    let batch_manifests = BatchManifests::from_config(driver_config, envelope.clone());
+    // Only the default processor publishes transactions, so only it runs the priority lane
+    let runs_priority_lane =
+        driver_config.priority_lane.enabled && processor_name == custom_default_processor::NAME;
+    let processor_config = ProcessorConfig {
+        indexer: config,
+        driver: driver_config,
+        options,
+    };
+    let processor = processor_registry::build_processor(
+        &processor_name,
+        conn_pool.clone(),
+        publisher,
+        &processor_config,
+    )
+    .unwrap_or_else(|e| panic!("{}", e));
+
+    let options = fetcher_options(config, driver_config);
+    let mut tailer = Tailer::new(context.clone(), conn_pool.clone(), processor, options.clone())
+        .expect("Failed to instantiate tailer")
+        .with_publisher_flush(publisher_flush);
+    if let Some(batch_manifests) = batch_manifests {
+        tailer = tailer.with_batch_manifests(Arc::new(batch_manifests));
+    }
+    let redactor = driver_config.redaction.enabled.then(|| {
+        let redactor = Arc::new(
+            Redactor::new(driver_config.redaction.clone()).unwrap_or_else(|e| panic!("{:?}", e)),
+        );
+        Redactor::spawn_reloader(redactor.clone());
+        redactor
+    });
+    let recording = &driver_config.fetcher_recording;
+    match recording.mode {
+        RecordingMode::Off => {},
+        RecordingMode::Record => {
+            info!(processor_name = processor_name, path = recording.path, "Recording fetched batches...");
+            let mut fetcher = RecordingFetcher::new(
+                TransactionFetcher::new(context.clone(), 0, options.clone()),
+                &recording.path,
+            )
+            .unwrap_or_else(|e| panic!("{:?}", e));
+            // Redacted before they're written, and only once
+            if let Some(redactor) = &redactor {
+                fetcher = fetcher.with_redactor(redactor.clone());
+            }
+            tailer.transaction_fetcher = Arc::new(Mutex::new(fetcher));
+        },
+        RecordingMode::Replay => {
+            info!(processor_name = processor_name, path = recording.path, "Replaying recorded batches...");
+            let fetcher = ReplayFetcher::open(
+                &recording.path,
+                options.clone(),
+                Duration::from_millis(recording.replay_latency_millis),
+            )
+            .unwrap_or_else(|e| panic!("{:?}", e));
+            tailer.transaction_fetcher = Arc::new(Mutex::new(fetcher));
+        },
+    }
+    // A replay has no chain to tail
+    if runs_priority_lane && recording.mode != RecordingMode::Replay {
+        info!(processor_name = processor_name, "Starting priority lane...");
+        let priority_lane = Arc::new(PriorityLane::new(
+            driver_config.priority_lane.clone(),
+            Publisher::from_config(driver_config.clone(), envelope),
+        ));
+        PriorityLane::spawn_reloader(priority_lane.clone());
+        // Its own fetcher, from the chain's tip rather than the processor's watermark
+        PriorityLane::spawn_tail(
+            priority_lane.clone(),
+            TransactionFetcher::new(context.clone(), 0, options.clone()),
+            redactor.clone(),
+        );
+        tailer = tailer.with_priority_lane(priority_lane);
+    }
+    if let Some(redactor) = redactor {
+        if recording.mode != RecordingMode::Record {
+            tailer = tailer.with_redactor(redactor);
+        }
+    }
+    if driver_config.app_scope.enabled {
+        tailer = tailer.with_app_scope(Arc::new(AppScope::new(&driver_config.app_scope, conn_pool)));
+    }
+    if driver_config.ordered_commit.enabled {
+        if let Err(err) = driver_config.ordered_commit.validate() {
+            panic!("Invalid ordered_commit config: {:#}", err);
+        }
+        tailer = tailer.with_ordered_commit();
+    }
+    tailer
+}
+
+/// How the processor named in `config` fetches
+fn fetcher_options(
+    config: &IndexerConfig,
+    driver_config: &DriverConfig,
+) -> TransactionFetcherOptions {
+    let fetch_tasks = config.fetch_tasks.unwrap();
+    let batch_size = config.batch_size.unwrap();
+    let mut options =
+        TransactionFetcherOptions::new(None, None, Some(batch_size), None, fetch_tasks as usize);
+    let fetch_budget = &driver_config.fetch_budget;
+    if fetch_budget.enabled {
+        options = options.with_fetch_budget(FetchBudget {
+            target_bytes: fetch_budget.target_bytes,
+            hard_cap_bytes: fetch_budget.hard_cap_bytes,
+            window: fetch_budget.window,
+        });
+    }
+    if let Some(shard) = sharding::spec() {
+        options = options.with_shard(shard);
+    }
+    options
+}
+
+/// Version after the last one the processor committed. A shard without a watermark of its own
+/// starts after the combined one.
+fn get_watermark(tailer: &Tailer, processor_name: &str) -> u64 {
+    let get_start_version = |key: String| {
         tailer
-            .check_or_update_chain_id()
-            .await
-            .expect("Failed to get chain ID");
+            .get_start_version(&key)
+            .unwrap_or_else(|e| panic!("Failed to get starting version: {:?}", e))
+    };
+    get_start_version(sharding::watermark_key(processor_name))
+        .or_else(|| sharding::spec().and_then(|_| get_start_version(processor_name.to_string())))
+        .unwrap_or_else(|| {
+            info!(
+                processor_name = processor_name,
+                "No starting version from db so starting from version 0"
+            );
+            0
+        }) as u64
+}
+
+/// Runs as a standby until the lease is acquired: fetches and parses near the leader's watermark
+/// without writing, publishing or moving the watermark. See `driver::standby`.
+async fn follow_until_leader(
+    config: &IndexerConfig,
+    driver_config: &DriverConfig,
+    options: &ProcessorOptions,
+    context: Arc<Context>,
+    conn_pool: PgDbPool,
+    lease: &Lease,
+) {
+    lease.set_role(Role::Standby);
+    let processor_name = config.processor.clone().unwrap();
+    health::on_role(&processor_name, Role::Standby);
+    let standby = &driver_config.standby;
+    let poll_interval = Duration::from_millis(standby.poll_interval_millis.max(1));
+    let build_follower = || build_tailer(config, driver_config, options, context.clone(), conn_pool.clone());
+
+    let mut follower = build_follower();
+    let mut leader_watermark = get_watermark(&follower, &processor_name);
+    let mut next_version = leader_watermark;
+    follower.set_fetcher_version(next_version).await;
+    follower.transaction_fetcher.lock().await.start().await;
+    let mut polled_at = Instant::now();
+    loop {
+        if polled_at.elapsed() >= poll_interval {
+            polled_at = Instant::now();
+            match lease.try_acquire() {
+                Ok(true) => return,
+                Ok(false) => {},
+                Err(e) => warn!(processor_name = processor_name, error = ?e, "Failed to poll the lease"),
+            }
+            leader_watermark = get_watermark(&follower, &processor_name);
+        }
+        if next_version.saturating_add(standby.max_lead_versions) < leader_watermark {
+            info!(
+                processor_name = processor_name,
+                next_version = next_version,
+                leader_watermark = leader_watermark,
+                "Standby fell behind the leader, restarting at its watermark"
+            );
+            follower = build_follower();
+            next_version = leader_watermark;
+            follower.set_fetcher_version(next_version).await;
+            follower.transaction_fetcher.lock().await.start().await;
+        }
+        if next_version > leader_watermark.saturating_add(standby.max_lead_versions) {
+            tokio::time::sleep(poll_interval).await;
+            continue;
+        }
+        let transactions = follower.fetch_next_batch().await;
+        let Some(last_version) = transactions.last().and_then(|txn| txn.version()) else {
+            tokio::time::sleep(STANDBY_IDLE_WAIT.min(poll_interval)).await;
+            continue;
+        };
+        next_version = last_version + 1;
+        let report = debug::derive_batch(driver_config, &transactions);
+        for panic in &report.panics {
+            warn!(
+                processor_name = processor_name,
+                start_version = report.version,
+                end_version = last_version,
+                stage = panic.stage,
+                message = panic.message,
+                "Standby failed to parse a batch"
+            );
+        }
+        lease.on_parsed(last_version, leader_watermark, report.panics.len());
+    }
+}
+
+/// The backfill `command` asks for, for a processor at `watermark`. `None` if there's nothing
+/// below the watermark or its window can't be registered, failing its operation.
+fn start_backfill(
+    conn_pool: &PgDbPool,
+    processor_name: &str,
+    command: BackfillCommand,
+    watermark: u64,
+) -> Option<Backfill> {
+    let operation = operations::attach(command.operation_id);
+    if command.start_version >= watermark {
+        operation.fail(
+            "invalid",
+            format!("Nothing to reprocess below the watermark {}", watermark),
+        );
+        return None;
+    }
+    let end_version = command
+        .end_version
+        .unwrap_or(watermark - 1)
+        .min(watermark - 1);
+    if let Some(window) = command.window {
+        if let Err(e) = backfill_guard::register_window(
+            conn_pool,
+            processor_name,
+            command.start_version as i64,
+            end_version as i64,
+            &command.actor,
+            window,
+        ) {
+            operation.fail(operations::error_code(&e), format!("{:#}", e));
+            return None;
+        }
     }
+    operation.set_work(0, Some(end_version - command.start_version + 1));
+    info!(
+        processor_name = processor_name,
+        start_version = command.start_version,
+        end_version = end_version,
+        actor = command.actor,
+        "Backfilling"
+    );
+    Some(Backfill {
+        operation,
+        start_version: command.start_version,
+        end_version,
+        app_scope: None,
+    })
+}
+
+/// The versions below the watermark a processor was started at or asked to backfill, reprocessed
+/// as an operation
+struct Backfill {
+    operation: Operation,
+    start_version: u64,
+    /// Inclusive
+    end_version: u64,
+    /// The addresses added to the app scope that the backfill is for
+    app_scope: Option<ScopeExpansion>,
+}
+
+/// Why a processor stopped processing rounds of batches
+enum Interrupt {
+    /// Its lifecycle control asked for a reload with this config
+    Reload(DriverConfig),
+    /// Its lifecycle control asked it to hand the lease over
+    Demote,
+    /// Another instance holds the lease
+    LeaseLost,
+    /// Its lifecycle control asked for a backfill
+    Backfill(BackfillCommand),
+    /// The backfill it was running was cancelled
+    BackfillCancelled,
+    /// The process is shutting down, see `driver::shutdown`
+    Shutdown,
+    /// Another processor wiped the tables after a ledger reset, see `driver::ledger_reset`
+    LedgerReset,
+    /// A batch failed with a retryable error and the round is to be processed again, after the
+    /// last version committed if any, see `driver::batch_retry`
+    Retry(Option<u64>),
+}
+
+/// Processes rounds of batches until the processor's lifecycle control asks for a reload or a
+/// demotion, the process loses the lease or shuts down
+#[allow(clippy::too_many_arguments)]
+async fn process_until_interrupted(
+    tailer: &Tailer,
+    processor_name: &str,
+    processor_tasks: u8,
+    emit_every: u64,
+    control: &mut ProcessorControl,
+    backfill: &mut Option<Backfill>,
+    lease: Option<&Lease>,
+    fence: Option<&Fence>,
+    version_guard: &mut VersionGuard,
+    batch_retry: &mut BatchRetry,
+) -> Interrupt {
+    let mut versions_processed: u64 = 0;
+    let mut base: u64 = 0;
+    // The end of the last round committed
+    let mut committed_version = None;
 
     let mut ma = MovingAverage::new(10_000);
+    // With sharding, the last combined watermark seen
+    let mut low_watermark = None;
 
     loop {
+        // Between rounds nothing is in flight, which is where pausing and reloading happen
+        if shutdown::requested() {
+            return Interrupt::Shutdown;
+        }
+        let reload = tokio::select! {
+            reload = control.checkpoint() => reload,
+            // Also while paused
+            _ = shutdown::wait() => return Interrupt::Shutdown,
+        };
+        if let Some(driver_config) = reload {
+            return Interrupt::Reload(driver_config);
+        }
+        if let Some(command) = control.take_backfill() {
+            return Interrupt::Backfill(command);
+        }
+        if backfill.as_ref().map_or(false, |backfill| backfill.operation.is_cancelled()) {
+            return Interrupt::BackfillCancelled;
+        }
+        if let Some(lease) = lease {
+            if control.take_demotion() {
+                info!(processor_name = processor_name, "Handing the lease over...");
+                return Interrupt::Demote;
+            }
+            if !lease.renew_if_due() {
+                warn!(processor_name = processor_name, "Lost the lease, stopping");
+                return Interrupt::LeaseLost;
+            }
+        }
+        if replication_lag::wait_if_paused().await {
+            health::on_paused(processor_name, PauseReason::ReplicationLag);
+            continue;
+        }
+        // Held until the round is committed, so that a wipe waits for it
+        let fenced_round = match fence.map(Fence::enter) {
+            None => None,
+            Some(Ok(Some(round))) => Some(round),
+            Some(Ok(None)) => return Interrupt::LedgerReset,
+            Some(Err(e)) => {
+                warn!(processor_name = processor_name, error = ?e, "Failed to enter the ledger reset fence");
+                None
+            },
+        };
+
+        let round_start = std::time::Instant::now();
         let mut tasks = vec![];
         for _ in 0..processor_tasks {
             let other_tailer = tailer.clone();
             let task = tokio::spawn(async move { other_tailer.process_next_batch().await });
             tasks.push(task);
         }
-        let batches = match futures::future::try_join_all(tasks).await {
-            Ok(res) => res,
-            Err(err) => panic!("Error processing transaction batches: {:?}", err),
+        let batches = match shutdown::within_drain(futures::future::try_join_all(tasks)).await {
+            Some(Ok(res)) => res,
+            Some(Err(err)) => panic!("Error processing transaction batches: {:?}", err),
+            None => {
+                warn!(
+                    processor_name = processor_name,
+                    "The batches in flight didn't finish in time, they'll be processed again"
+                );
+                return Interrupt::Shutdown;
+            },
         };
 
         let mut batch_start_version = u64::MAX;
         let mut batch_end_version = 0;
         let mut num_res = 0;
+        let mut round_counts = RowCounts::default();
+        let mut round_ranges = vec![];
+        let mut round_manifests = vec![];
 
         for (num_txn, res) in batches {
             let processed_result: ProcessingResult = match res {
@@ -254,27 +1314,128 @@ pub async fn run_forever(config: IndexerConfig, context: Arc<Context>) {
                 Some(Ok(res)) => res,
                 Some(Err(tpe)) => {
                     let (err, start_version, end_version, _) = tpe.inner();
+                    let give_up = match batch_retry.on_failure(processor_name, &tpe) {
+                        Ok(delay) => {
+                            warn!(
+                                processor_name = processor_name,
+                                start_version = start_version,
+                                end_version = end_version,
+                                delay_millis = delay.as_millis() as u64,
+                                error = ?err,
+                                "Error processing batch, will process the round again"
+                            );
+                            tokio::select! {
+                                _ = tokio::time::sleep(delay) => {},
+                                _ = shutdown::wait() => return Interrupt::Shutdown,
+                            }
+                            return Interrupt::Retry(committed_version);
+                        },
+                        Err(give_up) => give_up,
+                    };
+                    if let Some(backfill) = backfill.take() {
+                        backfill.operation.fail("processing", format!("{:#}", err));
+                    }
                     error!(
                         processor_name = processor_name,
                         start_version = start_version,
                         end_version = end_version,
+                        error_kind = tpe.kind().as_str(),
                         error =? err,
                         "Error processing batch!"
                     );
                     panic!(
-                        "Error in '{}' while processing batch: {:?}",
-                        processor_name, err
+                        "Error in '{}' while processing batch {}-{}, {}: {:?}",
+                        processor_name, start_version, end_version, give_up, err
                     );
                 },
             };
             batch_start_version =
                 std::cmp::min(batch_start_version, processed_result.start_version);
             batch_end_version = std::cmp::max(batch_end_version, processed_result.end_version);
+            round_ranges.push((processed_result.start_version, processed_result.end_version));
+            round_manifests.extend(tailer.batch_manifest(&processed_result));
             num_res += num_txn;
+            round_counts += processed_result.counts;
+            debug!(
+                processor_name = processor_name,
+                start_version = processed_result.start_version,
+                end_version = processed_result.end_version,
+                row_counts = serde_json::to_string(&processed_result.counts).unwrap_or_default(),
+                "Processed batch"
+            );
+            if processed_result.dead_lettered > 0 {
+                warn!(
+                    processor_name = processor_name,
+                    start_version = processed_result.start_version,
+                    end_version = processed_result.end_version,
+                    dead_lettered = processed_result.dead_lettered,
+                    "Dead lettered messages of the batch that couldn't be published"
+                );
+            }
+            if processed_result.republish_skipped > 0 {
+                info!(
+                    processor_name = processor_name,
+                    start_version = processed_result.start_version,
+                    end_version = processed_result.end_version,
+                    republish_skipped = processed_result.republish_skipped,
+                    "Skipped transactions of the batch that were published already"
+                );
+            }
         }
 
+        // Once the watermark is past a gap, nothing would process the versions missing
+        if let Err(gap) = version_guard.accept_round(&mut round_ranges) {
+            if let Some(backfill) = backfill.take() {
+                backfill.operation.fail("version_gap", gap.to_string());
+            }
+            error!(
+                processor_name = processor_name,
+                expected_version = gap.expected_version,
+                start_version = gap.start_version,
+                end_version = gap.end_version,
+                "Batch doesn't start right after the last one!"
+            );
+            panic!("Version gap in '{}': {}", processor_name, gap);
+        }
+
+        // Once the watermark is past the round, whatever of it Kafka didn't ack is lost
+        if shutdown::requested() {
+            if let Err(e) = tailer.flush_publisher(shutdown::flush_timeout()) {
+                error!(
+                    processor_name = processor_name,
+                    end_version = batch_end_version,
+                    error = ?e,
+                    "Failed to flush the publisher, the last round will be processed again"
+                );
+                return Interrupt::Shutdown;
+            }
+        }
+        // Once the watermark is past the round, a manifest that wasn't delivered is lost
+        if let Err(e) = tailer.publish_batch_manifests(&round_manifests) {
+            warn!(
+                processor_name = processor_name,
+                end_version = batch_end_version,
+                error = ?e,
+                "Failed to publish the batch manifests, will process the round again"
+            );
+            return Interrupt::Retry(committed_version);
+        }
+        // Before the watermark, so that a restart in between doesn't publish the round again
+        if num_res > 0 {
+            if let Err(e) = tailer.commit_publish_checkpoint(batch_end_version) {
+                warn!(
+                    processor_name = processor_name,
+                    end_version = batch_end_version,
+                    error = ?e,
+                    "Failed to record the last published version"
+                );
+            }
+        }
         tailer
-            .update_last_processed_version(&processor_name, batch_end_version)
+            .update_last_processed_version(
+                &sharding::watermark_key(processor_name),
+                batch_end_version,
+            )
             .unwrap_or_else(|e| {
                 error!(
                     processor_name = processor_name,
@@ -284,20 +1445,110 @@ pub async fn run_forever(config: IndexerConfig, context: Arc<Context>) {
                 );
                 panic!("Failed to update last processed version: {:?}", e);
             });
+        if num_res > 0 {
+            committed_version = Some(batch_end_version);
+            batch_retry.on_success();
+        }
+        if let Some(current) = backfill.as_ref().filter(|_| num_res > 0) {
+            if batch_end_version >= current.end_version {
+                let current = backfill.take().unwrap();
+                current
+                    .operation
+                    .complete(current.end_version - current.start_version + 1);
+                if let Some(expansion) = &current.app_scope {
+                    match expansion.complete() {
+                        Ok(()) => info!(
+                            processor_name = processor_name,
+                            addresses = ?expansion.addresses(),
+                            "Backfilled the addresses added to the app scope"
+                        ),
+                        Err(e) => error!(
+                            processor_name = processor_name,
+                            error = ?e,
+                            "Failed to mark the app scope's addresses backfilled"
+                        ),
+                    }
+                }
+            } else if batch_end_version >= current.start_version {
+                current
+                    .operation
+                    .progress(batch_end_version - current.start_version + 1);
+            }
+        }
+        match sharding::spec() {
+            Some(shard) => {
+                let previous = low_watermark;
+                match tailer.update_low_watermark(processor_name, &shard) {
+                    Ok(version) => low_watermark = version.max(previous),
+                    Err(e) => error!(
+                        processor_name = processor_name,
+                        error = format!("{:?}", e),
+                        "Failed to update the combined watermark"
+                    ),
+                }
+                // Every shard sees the combined watermark move, the first one hashes what it covers
+                if let (Some(previous), Some(current), 0) =
+                    (previous, low_watermark, shard.shard_index)
+                {
+                    range_hash::on_progress(processor_name, previous + 1, current);
+                }
+            },
+            None => range_hash::on_progress(processor_name, batch_start_version, batch_end_version),
+        }
+        if circuit_breaker::enabled() || metrics::enabled() {
+            // Only empty batches, the processor caught up with the ledger
+            let lag = if num_res == 0 {
+                0
+            } else {
+                let ledger_info = tailer.transaction_fetcher.lock().await.fetch_ledger_info();
+                ledger_info
+                    .ledger_version
+                    .0
+                    .saturating_sub(batch_end_version)
+            };
+            metrics::on_round(processor_name, lag);
+            circuit_breaker::on_round(processor_name, lag);
+        }
+        health::on_round(processor_name, (num_res > 0).then_some(batch_end_version));
+        drop(fenced_round);
 
         ma.tick_now(num_res);
+        consumer_lag::pace(round_start.elapsed()).await;
+        tokio::select! {
+            _ = rate_limit::pace(processor_name, num_res, backfill.is_some()) => {},
+            _ = shutdown::wait() => return Interrupt::Shutdown,
+        }
 
         versions_processed += num_res;
         if emit_every != 0 {
             let new_base: u64 = versions_processed / emit_every;
             if base != new_base {
                 base = new_base;
+                let lag_status = consumer_lag::status();
+                let replication_status = replication_lag::status();
+                let running_operations = operations::status();
+                let last_range = range_hash::status()
+                    .into_iter()
+                    .rev()
+                    .find(|status| status.processor == processor_name);
                 info!(
                     processor_name = processor_name,
                     batch_start_version = batch_start_version,
                     batch_end_version = batch_end_version,
                     versions_processed = versions_processed,
+                    row_counts = serde_json::to_string(&round_counts).unwrap_or_default(),
                     tps = (ma.avg() * 1000.0) as u64,
+                    consumer_lag = lag_status.as_ref().and_then(|s| s.observed_lag),
+                    throttle_factor = lag_status.as_ref().map(|s| s.throttle_factor),
+                    replication_lag_secs =
+                        replication_status.as_ref().and_then(|s| s.observed_lag_secs),
+                    replication_lag_pauses = replication_status.as_ref().map(|s| s.pauses),
+                    running_operations = (!running_operations.is_empty()).then(|| {
+                        serde_json::to_string(&running_operations).unwrap_or_default()
+                    }),
+                    last_hashed_range = last_range.map(|s| {
+                        serde_json::to_string(&s.manifest).unwrap_or_default()
+                    }),
                     "Processed batch version"
                 );
             }
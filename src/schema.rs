@@ -2,6 +2,32 @@
 
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    account_derivations (derived_address) {
+        #[max_length = 66]
+        derived_address -> Varchar,
+        #[max_length = 50]
+        derivation_kind -> Varchar,
+        #[max_length = 66]
+        source_address -> Nullable<Varchar>,
+        seed -> Nullable<Text>,
+        transaction_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    account_storage_deposits (account_address) {
+        #[max_length = 66]
+        account_address -> Varchar,
+        total_deposited_octas -> Numeric,
+        total_refunded_octas -> Numeric,
+        deposit_balance_octas -> Numeric,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     account_transactions (account_address, transaction_version) {
         transaction_version -> Int8,
@@ -11,6 +37,115 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    admin_audit_log (id) {
+        id -> Int8,
+        #[max_length = 100]
+        caller -> Varchar,
+        #[max_length = 50]
+        action -> Varchar,
+        parameters -> Jsonb,
+        #[max_length = 100]
+        operation_id -> Nullable<Varchar>,
+        #[max_length = 20]
+        outcome -> Varchar,
+        error -> Nullable<Text>,
+        requested_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    anomalies (kind, transaction_version) {
+        #[max_length = 50]
+        kind -> Varchar,
+        transaction_version -> Int8,
+        #[max_length = 50]
+        processor -> Varchar,
+        #[max_length = 20]
+        policy -> Varchar,
+        message -> Text,
+        details -> Jsonb,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    app_scope_addresses (processor, address) {
+        #[max_length = 50]
+        processor -> Varchar,
+        #[max_length = 66]
+        address -> Varchar,
+        backfilled -> Bool,
+        added_at_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    app_scope_table_handles (handle) {
+        #[max_length = 66]
+        handle -> Varchar,
+        discovered_from -> Text,
+        transaction_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    asset_transfers (transaction_version, transfer_index) {
+        transaction_version -> Int8,
+        transfer_index -> Int8,
+        #[max_length = 20]
+        standard -> Varchar,
+        #[max_length = 5000]
+        asset_id -> Varchar,
+        property_version_v1 -> Numeric,
+        #[max_length = 66]
+        from_address -> Nullable<Varchar>,
+        #[max_length = 66]
+        to_address -> Nullable<Varchar>,
+        amount -> Numeric,
+        #[max_length = 30]
+        kind -> Varchar,
+        event_index -> Int8,
+        transaction_timestamp -> Timestamp,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    backfill_windows (id) {
+        id -> Int8,
+        #[max_length = 50]
+        processor -> Varchar,
+        start_version -> Int8,
+        end_version -> Int8,
+        #[max_length = 100]
+        created_by -> Varchar,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    balance_anomalies (balance_table, account_address, asset, transaction_version) {
+        #[max_length = 50]
+        balance_table -> Varchar,
+        #[max_length = 66]
+        account_address -> Varchar,
+        #[max_length = 5000]
+        asset -> Varchar,
+        transaction_version -> Int8,
+        #[max_length = 50]
+        processor -> Varchar,
+        expected_delta -> Numeric,
+        stored_balance -> Numeric,
+        rederived_balance -> Nullable<Numeric>,
+        healed_at -> Nullable<Timestamp>,
+        inserted_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     block_metadata_transactions (version) {
         version -> Int8,
@@ -28,6 +163,19 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    change_feed (id) {
+        id -> Int8,
+        #[max_length = 100]
+        model -> Varchar,
+        transaction_version -> Nullable<Int8>,
+        pk -> Jsonb,
+        #[max_length = 10]
+        operation -> Varchar,
+        inserted_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     coin_activities (transaction_version, event_account_address, event_creation_number, event_sequence_number) {
         transaction_version -> Int8,
@@ -168,6 +316,26 @@ diesel::table! {
         inserted_at -> Timestamp,
         #[max_length = 140]
         token_name -> Varchar,
+        is_primary -> Bool,
+        record_expiration_timestamp -> Timestamp,
+    }
+}
+
+diesel::table! {
+    current_asset_stores (store_address, asset_type) {
+        #[max_length = 66]
+        store_address -> Varchar,
+        #[max_length = 5000]
+        asset_type -> Varchar,
+        #[max_length = 66]
+        owner_address -> Varchar,
+        #[max_length = 50]
+        store_kind -> Varchar,
+        is_frozen -> Bool,
+        created_transaction_version -> Int8,
+        deleted_transaction_version -> Nullable<Int8>,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
     }
 }
 
@@ -270,6 +438,26 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    current_move_resources (address, type_) {
+        #[max_length = 66]
+        address -> Varchar,
+        #[sql_name = "type"]
+        type_ -> Text,
+        module -> Text,
+        name -> Text,
+        generic_type_params -> Nullable<Jsonb>,
+        data -> Nullable<Jsonb>,
+        #[max_length = 66]
+        state_key_hash -> Varchar,
+        last_transaction_version -> Int8,
+        is_deleted -> Bool,
+        inserted_at -> Timestamp,
+        #[max_length = 66]
+        resource_address -> Nullable<Varchar>,
+    }
+}
+
 diesel::table! {
     current_objects (object_address) {
         #[max_length = 66]
@@ -283,6 +471,9 @@ diesel::table! {
         last_transaction_version -> Int8,
         is_deleted -> Bool,
         inserted_at -> Timestamp,
+        #[max_length = 66]
+        ultimate_owner -> Nullable<Varchar>,
+        ownership_depth -> Nullable<Int8>,
     }
 }
 
@@ -465,6 +656,22 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    dead_letter_rows (table_name, transaction_version, row_hash) {
+        #[max_length = 100]
+        table_name -> Varchar,
+        transaction_version -> Int8,
+        #[max_length = 64]
+        row_hash -> Varchar,
+        #[max_length = 100]
+        column_name -> Varchar,
+        column_bytes -> Int8,
+        max_bytes -> Int8,
+        row_data -> Jsonb,
+        inserted_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     delegated_staking_activities (transaction_version, event_index) {
         transaction_version -> Int8,
@@ -504,6 +711,65 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    dex_pools (pool) {
+        pool -> Text,
+        #[max_length = 100]
+        protocol -> Varchar,
+        #[max_length = 66]
+        pool_address -> Varchar,
+        coin_x -> Text,
+        coin_y -> Text,
+        first_seen_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    dex_swaps (transaction_version, event_index) {
+        transaction_version -> Int8,
+        event_index -> Int8,
+        #[max_length = 100]
+        protocol -> Varchar,
+        pool -> Text,
+        coin_in -> Text,
+        coin_out -> Text,
+        amount_in -> Numeric,
+        amount_out -> Numeric,
+        #[max_length = 66]
+        sender -> Varchar,
+        transaction_timestamp -> Timestamp,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    enrichment_progress (enricher_name) {
+        #[max_length = 50]
+        enricher_name -> Varchar,
+        #[max_length = 100]
+        table_name -> Varchar,
+        last_cursor -> Nullable<Jsonb>,
+        rows_enriched -> Int8,
+        completed_at -> Nullable<Timestamp>,
+        last_updated -> Timestamp,
+    }
+}
+
+diesel::table! {
+    entry_function_daily_stats (date, entry_function_id_str) {
+        date -> Date,
+        entry_function_id_str -> Text,
+        call_count -> Int8,
+        success_count -> Int8,
+        distinct_senders_sketch -> Bytea,
+        distinct_senders -> Int8,
+        total_gas_used -> Numeric,
+        last_transaction_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     events (account_address, creation_number, sequence_number) {
         sequence_number -> Int8,
@@ -517,6 +783,47 @@ diesel::table! {
         data -> Jsonb,
         inserted_at -> Timestamp,
         event_index -> Nullable<Int8>,
+        #[max_length = 66]
+        event_account_address -> Nullable<Varchar>,
+        event_module -> Nullable<Text>,
+        event_name -> Nullable<Text>,
+        event_type_params -> Nullable<Jsonb>,
+        block_timestamp -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    index_advisor_observations (helper, table_name, filter_columns) {
+        #[max_length = 100]
+        helper -> Varchar,
+        #[max_length = 100]
+        table_name -> Varchar,
+        #[max_length = 300]
+        filter_columns -> Varchar,
+        calls -> Int8,
+        total_exec_ms -> Float8,
+        sampled_plans -> Int8,
+        seq_scan_plans -> Int8,
+        max_estimated_rows -> Int8,
+        last_plan -> Jsonb,
+        last_seen -> Timestamp,
+    }
+}
+
+diesel::table! {
+    indexer_column_stats (table_name, column_name, window_end) {
+        #[max_length = 100]
+        table_name -> Varchar,
+        #[max_length = 100]
+        column_name -> Varchar,
+        window_end -> Timestamp,
+        window_start -> Timestamp,
+        rows_seen -> Int8,
+        rows_sampled -> Int8,
+        null_ratio -> Float8,
+        distinct_estimate -> Int8,
+        top_values -> Jsonb,
+        inserted_at -> Timestamp,
     }
 }
 
@@ -532,6 +839,25 @@ diesel::table! {
 diesel::table! {
     ledger_infos (chain_id) {
         chain_id -> Int8,
+        #[max_length = 66]
+        genesis_hash -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    ledger_resets (id) {
+        id -> Int8,
+        chain_id -> Int8,
+        #[max_length = 66]
+        previous_genesis_hash -> Varchar,
+        #[max_length = 66]
+        new_genesis_hash -> Varchar,
+        #[max_length = 10]
+        action -> Varchar,
+        truncated_tables -> Jsonb,
+        #[max_length = 50]
+        processor -> Varchar,
+        created_at -> Timestamp,
     }
 }
 
@@ -569,6 +895,8 @@ diesel::table! {
         inserted_at -> Timestamp,
         #[max_length = 66]
         state_key_hash -> Varchar,
+        #[max_length = 66]
+        resource_address -> Nullable<Varchar>,
     }
 }
 
@@ -585,6 +913,18 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    object_ownership_edges (object_address) {
+        #[max_length = 66]
+        object_address -> Varchar,
+        #[max_length = 66]
+        owner_address -> Varchar,
+        last_transaction_version -> Int8,
+        is_deleted -> Bool,
+        inserted_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     objects (transaction_version, write_set_change_index) {
         transaction_version -> Int8,
@@ -603,11 +943,63 @@ diesel::table! {
 }
 
 diesel::table! {
-    processor_status (processor) {
+    onchain_config_changes (transaction_version, config_type) {
+        transaction_version -> Int8,
+        #[max_length = 50]
+        config_type -> Varchar,
+        #[max_length = 512]
+        resource_type -> Varchar,
+        value -> Jsonb,
+        diff -> Jsonb,
+        transaction_timestamp -> Timestamp,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    operations_log (operation_id, event_index) {
+        #[max_length = 100]
+        operation_id -> Varchar,
+        event_index -> Int8,
+        #[max_length = 50]
+        kind -> Varchar,
+        #[max_length = 20]
+        event -> Varchar,
+        #[max_length = 200]
+        actor -> Varchar,
+        parameters -> Jsonb,
+        done -> Int8,
+        total -> Nullable<Int8>,
+        #[max_length = 50]
+        error_code -> Nullable<Varchar>,
+        error -> Nullable<Text>,
+        emitted_at -> Timestamp,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    processor_leases (processor) {
         #[max_length = 50]
         processor -> Varchar,
+        #[max_length = 200]
+        holder -> Varchar,
+        acquired_at -> Timestamp,
+        expires_at -> Timestamp,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    processor_status (processor) {
+        #[max_length = 100]
+        processor -> Varchar,
         last_success_version -> Int8,
         last_updated -> Timestamp,
+        #[max_length = 10]
+        health_state -> Varchar,
+        health_state_since -> Timestamp,
+        last_published_version -> Nullable<Int8>,
     }
 }
 
@@ -637,6 +1029,52 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    scripts (script_hash) {
+        #[max_length = 66]
+        script_hash -> Varchar,
+        bytecode -> Bytea,
+        abi -> Nullable<Jsonb>,
+        first_seen_version -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    range_hashes (processor, table_name, start_version, end_version) {
+        #[max_length = 50]
+        processor -> Varchar,
+        #[max_length = 100]
+        table_name -> Varchar,
+        start_version -> Int8,
+        end_version -> Int8,
+        row_count -> Int8,
+        #[max_length = 16]
+        hash -> Varchar,
+        computed_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    shadow_diffs (processor, shadow, start_version, end_version, diff_index) {
+        #[max_length = 50]
+        processor -> Varchar,
+        #[max_length = 100]
+        shadow -> Varchar,
+        start_version -> Int8,
+        end_version -> Int8,
+        diff_index -> Int8,
+        #[max_length = 100]
+        table_name -> Varchar,
+        pk -> Text,
+        #[max_length = 100]
+        column_name -> Varchar,
+        primary_value -> Nullable<Jsonb>,
+        shadow_value -> Nullable<Jsonb>,
+        inserted_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     signatures (transaction_version, multi_agent_index, multi_sig_index, is_sender_primary) {
         transaction_version -> Int8,
@@ -655,6 +1093,7 @@ diesel::table! {
         threshold -> Int8,
         public_key_indices -> Jsonb,
         inserted_at -> Timestamp,
+        is_fee_payer -> Bool,
     }
 }
 
@@ -874,6 +1313,20 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    transaction_storage_usage (transaction_version) {
+        transaction_version -> Int8,
+        #[max_length = 66]
+        payer_address -> Varchar,
+        storage_fee_octas -> Nullable<Numeric>,
+        storage_fee_refund_octas -> Nullable<Numeric>,
+        slots_written -> Int8,
+        slots_deleted -> Int8,
+        bytes_written -> Int8,
+        inserted_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     transactions (version) {
         version -> Int8,
@@ -898,6 +1351,7 @@ diesel::table! {
         num_write_set_changes -> Int8,
         inserted_at -> Timestamp,
         epoch -> Int8,
+        block_timestamp -> Nullable<Timestamp>,
     }
 }
 
@@ -917,6 +1371,30 @@ diesel::table! {
         entry_function_id_str -> Text,
         inserted_at -> Timestamp,
         epoch -> Int8,
+        #[max_length = 66]
+        script_hash -> Nullable<Varchar>,
+        entry_function_module -> Nullable<Text>,
+        entry_function_name -> Nullable<Text>,
+        entry_function_type_arguments -> Jsonb,
+        entry_function_arguments -> Jsonb,
+    }
+}
+
+diesel::table! {
+    validation_violations (processor, rule_name, start_version, end_version, violation_index) {
+        #[max_length = 50]
+        processor -> Varchar,
+        #[max_length = 100]
+        rule_name -> Varchar,
+        start_version -> Int8,
+        end_version -> Int8,
+        violation_index -> Int8,
+        #[max_length = 10]
+        policy -> Varchar,
+        transaction_version -> Nullable<Int8>,
+        message -> Text,
+        details -> Jsonb,
+        inserted_at -> Timestamp,
     }
 }
 
@@ -936,8 +1414,18 @@ diesel::table! {
 }
 
 diesel::allow_tables_to_appear_in_same_query!(
+    account_derivations,
+    account_storage_deposits,
     account_transactions,
+    admin_audit_log,
+    anomalies,
+    app_scope_addresses,
+    app_scope_table_handles,
+    asset_transfers,
+    backfill_windows,
+    balance_anomalies,
     block_metadata_transactions,
+    change_feed,
     coin_activities,
     coin_balances,
     coin_infos,
@@ -945,11 +1433,13 @@ diesel::allow_tables_to_appear_in_same_query!(
     collection_datas,
     collections_v2,
     current_ans_lookup,
+    current_asset_stores,
     current_coin_balances,
     current_collection_datas,
     current_collections_v2,
     current_delegated_staking_pool_balances,
     current_delegator_balances,
+    current_move_resources,
     current_objects,
     current_staking_pool_voter,
     current_table_items,
@@ -959,19 +1449,34 @@ diesel::allow_tables_to_appear_in_same_query!(
     current_token_ownerships_v2,
     current_token_pending_claims,
     current_token_v2_metadata,
+    dead_letter_rows,
     delegated_staking_activities,
     delegated_staking_pool_balances,
     delegated_staking_pools,
+    dex_pools,
+    dex_swaps,
+    enrichment_progress,
+    entry_function_daily_stats,
     events,
+    index_advisor_observations,
+    indexer_column_stats,
     indexer_status,
     ledger_infos,
+    ledger_resets,
     move_modules,
     move_resources,
     nft_points,
+    object_ownership_edges,
     objects,
+    onchain_config_changes,
+    operations_log,
+    processor_leases,
     processor_status,
     processor_statuses,
     proposal_votes,
+    range_hashes,
+    scripts,
+    shadow_diffs,
     signatures,
     table_items,
     table_metadatas,
@@ -982,7 +1487,9 @@ diesel::allow_tables_to_appear_in_same_query!(
     token_ownerships,
     token_ownerships_v2,
     tokens,
+    transaction_storage_usage,
     transactions,
     user_transactions,
+    validation_violations,
     write_set_changes,
 );
@@ -0,0 +1,243 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Field drift between the Move data the node returns and the types the models parse it into.
+//! Serde silently ignores fields our types don't have, and an `Option` field that was renamed on
+//! chain silently stays `None`. `from_value` replaces `serde_json::from_value` for Move
+//! resources, events and table items: with `Warn` or `Strict` it serializes the parsed value back
+//! and diffs its field names against the input. Fields only in the input are `unknown`, fields
+//! only in our type are `missing`. Both are logged once and counted per type and field path.
+
+use crate::counters::{API_FIELD_DRIFT, API_FIELD_DRIFT_CHECKS};
+use aptos_logger::{info, warn};
+use once_cell::sync::OnceCell;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    sync::Mutex,
+};
+
+/// Fields holding free form data (property maps) whose keys aren't part of any schema. The
+/// typed side also transforms them, so their contents aren't diffed.
+const OPAQUE_FIELDS: &[&str] = &["default_properties", "token_properties"];
+/// Types that are free form as a whole
+const OPAQUE_TYPES: &[&str] = &["0x4::property_map::PropertyMap"];
+
+static CHECKER: OnceCell<DriftChecker> = OnceCell::new();
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Strictness {
+    /// Unknown fields are ignored and missing optional fields stay empty, as serde does
+    Lenient,
+    /// Every `sample_every`th parse of a type is diffed, drift is logged and counted
+    Warn,
+    /// Every parse is diffed and drift fails it, and with it the batch
+    Strict,
+}
+
+/// Sets the strictness of the process. Only the first call has an effect.
+pub fn init(strictness: Strictness, sample_every: u64, ignored_paths: &[String]) {
+    if strictness == Strictness::Lenient {
+        return;
+    }
+    let checker = DriftChecker {
+        strictness,
+        sample_every: sample_every.max(1),
+        ignored_paths: ignored_paths.iter().cloned().collect(),
+        parses: Mutex::new(HashMap::new()),
+        reported: Mutex::new(HashSet::new()),
+    };
+    if CHECKER.set(checker).is_ok() {
+        info!(
+            strictness = format!("{:?}", strictness),
+            sample_every = sample_every,
+            "Checking Move data for field drift"
+        );
+    }
+}
+
+/// `serde_json::from_value(data.clone())`, checked for drift against `data_type` (the Move
+/// struct without generic args) according to the strictness
+pub fn from_value<T: Serialize + DeserializeOwned>(
+    data_type: &str,
+    data: &Value,
+) -> serde_json::Result<T> {
+    let parsed = serde_json::from_value(data.clone())?;
+    let checker = match CHECKER.get() {
+        Some(checker) if checker.should_check(data_type) => checker,
+        _ => return Ok(parsed),
+    };
+    API_FIELD_DRIFT_CHECKS.inc();
+    let drift = checker.drift(data_type, data, &serde_json::to_value(&parsed)?);
+    if drift.is_empty() {
+        return Ok(parsed);
+    }
+    checker.report(data_type, &drift);
+    if checker.strictness == Strictness::Strict {
+        return Err(serde::de::Error::custom(format!(
+            "{} drifted from the indexer's type: {}",
+            data_type,
+            drift
+                .iter()
+                .map(|d| format!("{} {}", d.kind, d.field_path))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+    Ok(parsed)
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FieldDrift {
+    /// `unknown` or `missing`
+    pub kind: &'static str,
+    /// Dot separated, with `[]` for array elements, e.g. `supply.vec[].aggregator`
+    pub field_path: String,
+}
+
+struct DriftChecker {
+    strictness: Strictness,
+    sample_every: u64,
+    /// `data_type.field_path` entries not to report
+    ignored_paths: HashSet<String>,
+    parses: Mutex<HashMap<String, u64>>,
+    reported: Mutex<HashSet<(String, String)>>,
+}
+
+impl DriftChecker {
+    fn should_check(&self, data_type: &str) -> bool {
+        if OPAQUE_TYPES.contains(&data_type) {
+            return false;
+        }
+        if self.strictness == Strictness::Strict {
+            return true;
+        }
+        let mut parses = self.parses.lock().unwrap();
+        let count = match parses.get_mut(data_type) {
+            Some(count) => count,
+            None => parses.entry(data_type.to_string()).or_insert(0),
+        };
+        *count += 1;
+        // The first parse of a type is always checked, so rare types get checked too
+        (*count - 1) % self.sample_every == 0
+    }
+
+    fn drift(&self, data_type: &str, input: &Value, consumed: &Value) -> Vec<FieldDrift> {
+        let mut drift = BTreeSet::new();
+        diff_fields(input, consumed, "", &mut drift);
+        drift
+            .into_iter()
+            .filter(|d| {
+                !self
+                    .ignored_paths
+                    .contains(&format!("{}.{}", data_type, d.field_path))
+            })
+            .collect()
+    }
+
+    fn report(&self, data_type: &str, drift: &[FieldDrift]) {
+        let mut reported = self.reported.lock().unwrap();
+        for d in drift {
+            API_FIELD_DRIFT
+                .with_label_values(&[data_type, &d.field_path, d.kind])
+                .inc();
+            if reported.insert((data_type.to_string(), d.field_path.clone())) {
+                warn!(
+                    data_type = data_type,
+                    field_path = d.field_path,
+                    kind = d.kind,
+                    "Move data drifted from the indexer's type"
+                );
+            }
+        }
+    }
+}
+
+/// Field names in `input` but not in `consumed` and the other way round, recursing into objects
+/// and arrays present on both sides
+pub fn diff_fields(input: &Value, consumed: &Value, path: &str, drift: &mut BTreeSet<FieldDrift>) {
+    let join = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+    match (input, consumed) {
+        (Value::Object(input), Value::Object(consumed)) => {
+            for (key, value) in input {
+                if OPAQUE_FIELDS.contains(&key.as_str()) {
+                    continue;
+                }
+                match consumed.get(key) {
+                    Some(consumed_value) => diff_fields(value, consumed_value, &join(key), drift),
+                    None => {
+                        drift.insert(FieldDrift {
+                            kind: "unknown",
+                            field_path: join(key),
+                        });
+                    },
+                }
+            }
+            for key in consumed.keys() {
+                if !input.contains_key(key) && !OPAQUE_FIELDS.contains(&key.as_str()) {
+                    drift.insert(FieldDrift {
+                        kind: "missing",
+                        field_path: join(key),
+                    });
+                }
+            }
+        },
+        (Value::Array(input), Value::Array(consumed)) => {
+            // No indices in the path, so that it stays a small set of metric labels
+            let path = format!("{}[]", path);
+            for (value, consumed_value) in input.iter().zip(consumed) {
+                diff_fields(value, consumed_value, &path, drift);
+            }
+        },
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff_fields_unknown_and_missing() {
+        let input = json!({
+            "coin": {"value": "1", "frozen_at": "2"},
+            "events": [{"counter": "0", "guid": {"id": {"addr": "0x1", "creation_num": "2"}}}],
+            "default_properties": {"map": {"data": []}},
+        });
+        let consumed = json!({
+            "coin": {"value": "1"},
+            "events": [{"counter": "0", "guid": {"id": {"addr": "0x1", "creation_number": null}}}],
+            "default_properties": {},
+            "frozen": null,
+        });
+        let mut drift = BTreeSet::new();
+        diff_fields(&input, &consumed, "", &mut drift);
+        assert_eq!(drift.into_iter().collect::<Vec<_>>(), vec![
+            FieldDrift {
+                kind: "missing",
+                field_path: "events[].guid.id.creation_number".to_string(),
+            },
+            FieldDrift {
+                kind: "missing",
+                field_path: "frozen".to_string(),
+            },
+            FieldDrift {
+                kind: "unknown",
+                field_path: "coin.frozen_at".to_string(),
+            },
+            FieldDrift {
+                kind: "unknown",
+                field_path: "events[].guid.id.creation_num".to_string(),
+            },
+        ]);
+    }
+}
@@ -2,11 +2,16 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::models::property_map::{PropertyMap, TokenObjectPropertyMap};
+use anyhow::Context;
 use aptos_api_types::Address;
 use bigdecimal::{BigDecimal, Signed, ToPrimitive, Zero};
 use serde::{Deserialize, Deserializer};
 use serde_json::Value;
 use sha2::Digest;
+use std::str::FromStr;
+
+pub mod hyperloglog;
+pub mod sort_key;
 
 // 9999-12-31 23:59:59, this is the max supported by Google BigQuery
 pub const MAX_TIMESTAMP_SECS: i64 = 253_402_300_799;
@@ -35,6 +40,19 @@ pub fn bigdecimal_to_u64(val: &BigDecimal) -> u64 {
     val.to_u64().expect("Unable to convert big decimal to u64")
 }
 
+/// A u64 or u128 from a move value, which the API serializes as a decimal string so that it stays
+/// exact. A JSON number is only taken while it fits a u64, larger ones were parsed as floats.
+pub fn u128_from_json(value: &Value) -> anyhow::Result<BigDecimal> {
+    let amount = match value {
+        Value::String(s) => s
+            .parse::<u128>()
+            .with_context(|| format!("{:?} isn't a u128", s))?,
+        Value::Number(n) => n.as_u64().with_context(|| format!("{} isn't a u64", n))? as u128,
+        _ => anyhow::bail!("{} isn't an amount", value),
+    };
+    Ok(BigDecimal::from_str(&amount.to_string())?)
+}
+
 pub fn ensure_not_negative(val: BigDecimal) -> BigDecimal {
     if val.is_negative() {
         return BigDecimal::zero();
@@ -68,7 +86,9 @@ pub fn remove_null_bytes<T: serde::Serialize + for<'de> serde::Deserialize<'de>>
     serde_json::from_value::<T>(txn_json).unwrap()
 }
 
-fn recurse_remove_null_bytes_from_json(sub_json: &mut Value) {
+/// Removes the null bytes Postgres rejects from every string of `sub_json`, however deeply nested,
+/// object keys included
+pub fn recurse_remove_null_bytes_from_json(sub_json: &mut Value) {
     match sub_json {
         Value::Array(array) => {
             for item in array {
@@ -76,9 +96,14 @@ fn recurse_remove_null_bytes_from_json(sub_json: &mut Value) {
             }
         },
         Value::Object(object) => {
-            for (_key, value) in object {
-                recurse_remove_null_bytes_from_json(value);
-            }
+            // Keys can't be changed in place, e.g. those of a decoded property map
+            *object = std::mem::take(object)
+                .into_iter()
+                .map(|(mut key, mut value)| {
+                    recurse_remove_null_bytes_from_json(&mut value);
+                    (string_null_byte_replacement(&mut key), value)
+                })
+                .collect();
         },
         Value::String(str) => {
             if !str.is_empty() {
@@ -135,10 +160,27 @@ pub fn convert_bcs_hex(typ: String, value: String) -> Option<String> {
     let decoded = hex::decode(value.strip_prefix("0x").unwrap_or(&*value)).ok()?;
 
     match typ.as_str() {
-        "0x1::string::String" => bcs::from_bytes::<String>(decoded.as_slice()),
+        // Token v1 property types are free form, and many collections spelled strings this way
+        "0x1::string::String" | "String" | "string" => {
+            bcs::from_bytes::<String>(decoded.as_slice())
+        },
         "u8" => bcs::from_bytes::<u8>(decoded.as_slice()).map(|e| e.to_string()),
+        "u16" => bcs::from_bytes::<u16>(decoded.as_slice()).map(|e| e.to_string()),
+        "u32" => bcs::from_bytes::<u32>(decoded.as_slice()).map(|e| e.to_string()),
         "u64" => bcs::from_bytes::<u64>(decoded.as_slice()).map(|e| e.to_string()),
         "u128" => bcs::from_bytes::<u128>(decoded.as_slice()).map(|e| e.to_string()),
+        "u256" => bcs::from_bytes::<[u8; 32]>(decoded.as_slice()).map(|bytes| {
+            // Little endian
+            bytes
+                .iter()
+                .rev()
+                .fold(BigDecimal::zero(), |acc, byte| {
+                    acc * BigDecimal::from(256) + BigDecimal::from(*byte)
+                })
+                .to_string()
+        }),
+        "vector<u8>" => bcs::from_bytes::<Vec<u8>>(decoded.as_slice())
+            .map(|bytes| format!("0x{}", hex::encode(bytes))),
         "bool" => bcs::from_bytes::<bool>(decoded.as_slice()).map(|e| e.to_string()),
         "address" => bcs::from_bytes::<Address>(decoded.as_slice()).map(|e| e.to_string()),
         _ => Ok(value),
@@ -300,6 +342,21 @@ mod tests {
         assert_eq!(d.default_properties, Value::Object(serde_json::Map::new()));
     }
 
+    #[test]
+    fn test_convert_bcs_hex() {
+        let convert = |typ: &str, value: &str| convert_bcs_hex(typ.to_string(), value.to_string());
+        assert_eq!(convert("u16", "0x0a00").unwrap(), "10");
+        assert_eq!(convert("u32", "0x40420f00").unwrap(), "1000000");
+        let u256 = format!("0x0001{}", "00".repeat(30));
+        assert_eq!(convert("u256", &u256).unwrap(), "256");
+        assert_eq!(convert("vector<u8>", "0x02beef").unwrap(), "0xbeef");
+        assert_eq!(convert("String", "0x0465706963").unwrap(), "epic");
+        // Free form types stay as they are
+        assert_eq!(convert("integer", "0x05").unwrap(), "0x05");
+        // Values that don't decode as their type fail, and the caller keeps the hex
+        assert_eq!(convert("u16", "0x0a"), None);
+    }
+
     #[test]
     fn test_deserialize_token_object_property_map() {
         let test_property_json = r#"
@@ -363,4 +420,50 @@ mod tests {
         let d: TokenObjectDataMock = serde_json::from_str(val.as_str()).unwrap();
         assert_eq!(d.default_properties, Value::Object(serde_json::Map::new()));
     }
+
+    #[test]
+    fn test_remove_null_bytes_nested() {
+        let mut value = serde_json::json!({
+            "a": [{ "b": { "c\u{0000}": "x\u{0000}y", "d": "\\u0000z" } }],
+            "e": 1
+        });
+        recurse_remove_null_bytes_from_json(&mut value);
+        assert_eq!(
+            value,
+            serde_json::json!({ "a": [{ "b": { "c": "xy", "d": "z" } }], "e": 1 })
+        );
+    }
+
+    #[test]
+    fn test_u128_from_json() {
+        let u64_max = BigDecimal::from(u64::MAX);
+        assert_eq!(
+            u128_from_json(&serde_json::json!("18446744073709551615")).unwrap(),
+            u64_max
+        );
+        assert_eq!(
+            u128_from_json(&serde_json::json!(u64::MAX)).unwrap(),
+            u64_max
+        );
+
+        // A coin supply aggregator's value, as the API returns it
+        let supply: Value = serde_json::from_str(
+            r#"{ "key": "0x619dc29a0aac8fa146714058e8dd6d2d0f3bdf5f6331907bf91f3acd81e6935", "value": "340282366920938463463374607431768211455" }"#,
+        )
+        .unwrap();
+        let supply = u128_from_json(&supply["value"]).unwrap();
+        assert_eq!(supply.to_string(), u128::MAX.to_string());
+        assert!(supply > u64_max);
+
+        for invalid in [
+            serde_json::json!("340282366920938463463374607431768211456"),
+            serde_json::json!("-1"),
+            serde_json::json!("1.5"),
+            serde_json::json!(1.5),
+            serde_json::json!(-1),
+            serde_json::json!(true),
+        ] {
+            assert!(u128_from_json(&invalid).is_err(), "{}", invalid);
+        }
+    }
 }
@@ -0,0 +1,113 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! HyperLogLog sketches for distinct counts that are stored and merged across batches. A sketch
+//! is its registers as bytes; merging takes the maximum of every register, so merging the same
+//! values twice changes nothing. Items are hashed with sha256, which keeps a sketch built from the
+//! same items identical across processes and releases.
+
+use sha2::{Digest, Sha256};
+
+/// Bits of the hash choosing the register, 4096 registers with a standard error of about 1.6%
+const PRECISION: u32 = 12;
+const REGISTERS: usize = 1 << PRECISION;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self {
+            registers: vec![0; REGISTERS],
+        }
+    }
+}
+
+impl HyperLogLog {
+    /// `None` if `bytes` isn't a sketch of this precision
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        (bytes.len() == REGISTERS).then(|| Self {
+            registers: bytes.to_vec(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.registers.clone()
+    }
+
+    pub fn insert(&mut self, item: &[u8]) {
+        let digest = Sha256::digest(item);
+        let hash = u64::from_be_bytes(digest[..8].try_into().unwrap());
+        let index = (hash >> (64 - PRECISION)) as usize;
+        // Position of the first set bit of the rest, capped for an all-zero rest
+        let rank = ((hash << PRECISION).leading_zeros() + 1).min(64 - PRECISION + 1) as u8;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        for (register, other) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*other);
+        }
+    }
+
+    /// Estimated number of distinct items inserted, with linear counting for small counts
+    pub fn estimate(&self) -> u64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum = self
+            .registers
+            .iter()
+            .map(|register| 2f64.powi(-(*register as i32)))
+            .sum::<f64>();
+        let raw = alpha * m * m / sum;
+        let zeros = self
+            .registers
+            .iter()
+            .filter(|register| **register == 0)
+            .count();
+        if raw <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            raw.round() as u64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sketch(items: impl Iterator<Item = u64>) -> HyperLogLog {
+        let mut sketch = HyperLogLog::default();
+        for item in items {
+            sketch.insert(format!("0x{:x}", item).as_bytes());
+        }
+        sketch
+    }
+
+    #[test]
+    fn test_estimate() {
+        assert_eq!(HyperLogLog::default().estimate(), 0);
+        assert_eq!(sketch([1, 2, 3, 1, 2].into_iter()).estimate(), 3);
+        for count in [1_000u64, 100_000] {
+            let estimate = sketch(0..count).estimate() as f64;
+            let error = (estimate - count as f64).abs() / count as f64;
+            assert!(error < 0.05, "{} estimated as {}", count, estimate);
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut merged = sketch(0..500);
+        merged.merge(&sketch(250..1_000));
+        assert_eq!(merged, sketch(0..1_000));
+        // Merging again is a no-op, which keeps re-applied batches from inflating the count
+        let before = merged.clone();
+        merged.merge(&sketch(250..1_000));
+        assert_eq!(merged, before);
+        assert_eq!(HyperLogLog::from_bytes(&merged.to_bytes()), Some(merged));
+        assert_eq!(HyperLogLog::from_bytes(&[0; 16]), None);
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Primary key sorts over precomputed compact keys. Rows are sorted by primary key before
+//! they're written so that concurrent writers lock rows in the same order and can't deadlock.
+//! Comparing the keys as strings is slow for large batches, each comparison walking two long hex
+//! strings, so rows whose keys are canonical hex are compared as the decoded bytes instead, which
+//! order exactly like the strings.
+
+use std::cmp::Ordering;
+
+/// A row with a compact key ordering the same as its primary key
+pub trait SortKey {
+    type Key: Ord;
+
+    /// `None` if the primary key doesn't decode into the compact key
+    fn sort_key(&self) -> Option<Self::Key>;
+
+    /// Order of the primary key itself
+    fn cmp_pk(&self, other: &Self) -> Ordering;
+}
+
+/// Sorts `items` by primary key, in the same stable order as `sort_by(SortKey::cmp_pk)`. If a
+/// single key doesn't decode the whole batch is sorted by `cmp_pk`, as mixing both would change
+/// the order.
+pub fn sort_by_pk<T: SortKey>(items: &mut Vec<T>) {
+    let keys = items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| item.sort_key().map(|key| (key, index)))
+        .collect::<Option<Vec<_>>>();
+    let mut keys = match keys {
+        Some(keys) => keys,
+        None => {
+            items.sort_by(T::cmp_pk);
+            return;
+        },
+    };
+    // The index breaks ties, keeping the sort stable
+    keys.sort_unstable();
+    let mut slots = items.drain(..).map(Some).collect::<Vec<_>>();
+    items.extend(
+        keys.into_iter()
+            .map(|(_, index)| slots[index].take().unwrap()),
+    );
+}
+
+/// The `N` bytes of a key written as `prefix` and exactly `2 * N` lowercase hex digits. Keys of
+/// that shape order like their strings, since every byte is two digits and `0-9` sort before `a-f`.
+pub fn hex_key<const N: usize>(value: &str, prefix: &str) -> Option<[u8; N]> {
+    let digits = value.strip_prefix(prefix)?.as_bytes();
+    if digits.len() != 2 * N {
+        return None;
+    }
+    let mut key = [0; N];
+    for (byte, pair) in key.iter_mut().zip(digits.chunks_exact(2)) {
+        *byte = (hex_digit(pair[0])? << 4) | hex_digit(pair[1])?;
+    }
+    Some(key)
+}
+
+fn hex_digit(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        _ => None,
+    }
+}
+
+/// Concatenation of two keys, for primary keys of two columns
+pub fn concat_keys<const A: usize, const B: usize, const N: usize>(
+    a: [u8; A],
+    b: [u8; B],
+) -> [u8; N] {
+    assert_eq!(A + B, N);
+    let mut key = [0; N];
+    key[..A].copy_from_slice(&a);
+    key[A..].copy_from_slice(&b);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Row {
+        handle: String,
+        key_hash: String,
+        /// Tells apart rows of equal keys, to check the sort is stable
+        position: usize,
+    }
+
+    impl SortKey for Row {
+        type Key = [u8; 64];
+
+        fn sort_key(&self) -> Option<Self::Key> {
+            Some(concat_keys(
+                hex_key::<32>(&self.handle, "0x")?,
+                hex_key::<32>(&self.key_hash, "")?,
+            ))
+        }
+
+        fn cmp_pk(&self, other: &Self) -> Ordering {
+            (&self.handle, &self.key_hash).cmp(&(&other.handle, &other.key_hash))
+        }
+    }
+
+    /// Deterministic xorshift, so failures reproduce
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        /// 64 hex digits out of few values, so that keys collide and share prefixes
+        fn hex(&mut self) -> String {
+            format!("{:x}{:0>63x}", self.next() % 16, self.next() % 20)
+        }
+    }
+
+    fn rows(rng: &mut Rng, count: usize) -> Vec<Row> {
+        (0..count)
+            .map(|position| Row {
+                handle: format!("0x{}", rng.hex()),
+                key_hash: rng.hex(),
+                position,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_hex_key() {
+        assert_eq!(hex_key::<2>("0x0aff", "0x"), Some([0x0a, 0xff]));
+        assert_eq!(hex_key::<2>("0aff", "0x"), None);
+        assert_eq!(hex_key::<2>("0x0AFF", "0x"), None);
+        assert_eq!(hex_key::<2>("0x0af", "0x"), None);
+        assert_eq!(hex_key::<2>("0x0afff", "0x"), None);
+        assert_eq!(concat_keys::<1, 2, 3>([1], [2, 3]), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_same_order_as_strings() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for count in [0, 1, 2, 10, 100, 1000] {
+            let mut by_key = rows(&mut rng, count);
+            let mut by_string = by_key.clone();
+            sort_by_pk(&mut by_key);
+            by_string.sort_by(Row::cmp_pk);
+            assert_eq!(by_key, by_string);
+        }
+    }
+
+    #[test]
+    fn test_fallback() {
+        let mut rng = Rng(42);
+        let mut by_key = rows(&mut rng, 100);
+        // Uppercase hex doesn't decode, so the batch falls back to strings
+        by_key[50].key_hash = "F".repeat(64);
+        let mut by_string = by_key.clone();
+        sort_by_pk(&mut by_key);
+        by_string.sort_by(Row::cmp_pk);
+        assert_eq!(by_key, by_string);
+    }
+}
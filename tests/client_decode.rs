@@ -0,0 +1,125 @@
+// Copyright © Aptos Foundation
+// SPDX-License-Identifier: Apache-2.0
+
+//! Only uses `aptos_indexer::client`, so it also has to build with the minimal feature set:
+//! `cargo test -p aptos-indexer --no-default-features --test client_decode`
+
+use aptos_indexer::client::{
+    dead_letter_topic, decode_model, decode_transaction, is_priority, logical_key,
+    topic_key_for_model, APITransaction, DeadLetterMessage, EventModel, TransactionModel,
+    LOGICAL_KEY_HEADER, PRIORITY_HEADER,
+};
+use serde_json::json;
+
+#[test]
+fn test_decode_published_models() {
+    let txn_payload = json!({
+        "version": 10,
+        "block_height": 2,
+        "hash": "0x1234",
+        "type_": "user_transaction",
+        "payload": { "type": "entry_function_payload" },
+        "state_change_hash": "0xab",
+        "event_root_hash": "0xcd",
+        "state_checkpoint_hash": null,
+        "gas_used": "12",
+        "success": true,
+        "vm_status": "Executed successfully",
+        "accumulator_root_hash": "0xef",
+        "num_events": 1,
+        "num_write_set_changes": 3,
+        "epoch": 1,
+    })
+    .to_string();
+    let txn: TransactionModel = decode_model(txn_payload.as_bytes()).unwrap();
+    assert_eq!(txn.version, 10);
+    assert_eq!(txn.num_write_set_changes, 3);
+
+    let event_payload = json!({
+        "sequence_number": 0,
+        "creation_number": 2,
+        "account_address": "0x1",
+        "transaction_version": 10,
+        "transaction_block_height": 2,
+        "type_": "0x1::coin::DepositEvent",
+        "data": { "amount": "100" },
+        "event_index": 0,
+    })
+    .to_string();
+    let event: EventModel = decode_model(event_payload.as_bytes()).unwrap();
+    assert_eq!(event.type_, "0x1::coin::DepositEvent");
+    // Payloads from before the type's parts were published decode with them empty
+    assert_eq!(event.event_module, None);
+
+    assert!(decode_model::<EventModel>(b"not json").is_err());
+}
+
+#[test]
+fn test_decode_published_transaction() {
+    let payload = json!({
+        "type": "state_checkpoint_transaction",
+        "version": "10",
+        "hash": "0xa4d0d270d71cf031476dd2674d1e4a247489dfc3521c871ee37f42bd71a0a234",
+        "state_change_hash": "0x27b382a98a32256a9e6403ca1f6e26998273d77afa9e8666e7ee13679af40a7a",
+        "event_root_hash": "0xcbdbb1b830d1016d45a828bb3171ea81826e8315f14140acfbd7886f49fbcb40",
+        "state_checkpoint_hash": null,
+        "gas_used": "0",
+        "success": true,
+        "vm_status": "Executed successfully",
+        "accumulator_root_hash": "0x6a527d06063dfd42c6b3a862574d5f3ec1660afb8058135edda5072712bfdb51",
+        "changes": [],
+        "timestamp": "1660000000000000",
+    })
+    .to_string();
+    let txn = decode_transaction(payload.as_bytes()).unwrap();
+    assert!(matches!(txn, APITransaction::StateCheckpointTransaction(_)));
+    assert_eq!(
+        topic_key_for_model("TransactionModel"),
+        Some("transaction_topic")
+    );
+}
+
+#[test]
+fn test_logical_key() {
+    let headers = [(LOGICAL_KEY_HEADER, "0x1".as_bytes())];
+    assert_eq!(
+        logical_key(headers, Some("0x1#3".as_bytes())),
+        Some("0x1".as_bytes())
+    );
+    assert_eq!(
+        logical_key([], Some("0x2".as_bytes())),
+        Some("0x2".as_bytes())
+    );
+}
+
+#[test]
+fn test_is_priority() {
+    assert!(is_priority([
+        (LOGICAL_KEY_HEADER, "0x1".as_bytes()),
+        (PRIORITY_HEADER, "true".as_bytes()),
+    ]));
+    assert!(!is_priority([(LOGICAL_KEY_HEADER, "0x1".as_bytes())]));
+}
+
+#[test]
+fn test_decode_dead_letter() {
+    assert_eq!(
+        dead_letter_topic("apscan.indexer.transaction"),
+        "apscan.indexer.transaction.dlq"
+    );
+    let payload = json!({
+        "model": "TransactionModel",
+        "topic": "apscan.indexer.transaction",
+        "version": 10,
+        "hash": "0x1234",
+        "key": "0x1",
+        "error": "Failed to publish to apscan.indexer.transaction after 1 attempt(s): too large",
+        "payload": null,
+        "payload_bytes": 2_000_000,
+    })
+    .to_string();
+    let message: DeadLetterMessage = decode_model(payload.as_bytes()).unwrap();
+    assert_eq!(message.version, Some(10));
+    assert_eq!(message.payload, None);
+    assert_eq!(message.payload_bytes, Some(2_000_000));
+}